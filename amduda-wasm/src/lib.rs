@@ -0,0 +1,8 @@
+//! JavaScript bindings of `amduda` for browsers, see `amduda::wasm`.
+//!
+//! ```text
+//! wasm-pack build amduda-wasm --target web -- --features webgpu
+//! ```
+
+#[cfg(target_arch = "wasm32")]
+pub use amduda::wasm::*;
@@ -1,4 +1,6 @@
 use amduda::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use amduda::aurex_lm::layout::WeightLayout;
+use amduda::aurex_lm::quantizer::{dequantize_int4, quantize_int4};
 use amduda::hal_backends::cpu_simd::CpuSimdBackend;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
@@ -78,5 +80,50 @@ fn layer_norm_bench(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, matmul_bench, conv2d_bench, attention_bench, layer_norm_bench);
+fn int4_matmul_bench(c: &mut Criterion) {
+    // A decode step: one token through a 256 x 256 projection.
+    let m = 1;
+    let n = 256;
+    let k = 256;
+    let a: Vec<f32> = (0..m * k).map(|x| (x % 7) as f32).collect();
+    let w: Vec<f32> = (0..k * n).map(|x| (x % 15) as f32 - 7.0).collect();
+    let (packed, scale) = quantize_int4(&w);
+    let simd = CpuSimdBackend;
+    c.bench_function("matmul_int4_dequantize", |bench| {
+        bench.iter(|| {
+            let b = dequantize_int4(black_box(&packed), scale, k * n);
+            simd.matmul(black_box(&a), &b, m, n, k)
+        })
+    });
+    c.bench_function("matmul_int4_packed", |bench| {
+        bench.iter(|| simd.matmul_int4(black_box(&a), black_box(&packed), scale, m, n, k))
+    });
+}
+
+fn blocked_matmul_bench(c: &mut Criterion) {
+    // Weights too large for L1, read column block by column block.
+    let m = 4;
+    let n = 512;
+    let k = 512;
+    let a: Vec<f32> = (0..m * k).map(|x| (x % 7) as f32).collect();
+    let b: Vec<f32> = (0..k * n).map(|x| (x % 11) as f32).collect();
+    let blocked = WeightLayout::Blocked { cols: 8 }.pack(&b, k, n);
+    let simd = CpuSimdBackend;
+    c.bench_function("matmul_row_major", |bench| {
+        bench.iter(|| simd.matmul(black_box(&a), black_box(&b), m, n, k))
+    });
+    c.bench_function("matmul_blocked", |bench| {
+        bench.iter(|| simd.matmul_blocked(black_box(&a), black_box(&blocked), m, n, k))
+    });
+}
+
+criterion_group!(
+    benches,
+    matmul_bench,
+    conv2d_bench,
+    attention_bench,
+    layer_norm_bench,
+    int4_matmul_bench,
+    blocked_matmul_bench
+);
 criterion_main!(benches);
@@ -0,0 +1,52 @@
+//! Builds the SYCL shim when the `sycl` feature is enabled.
+//!
+//! The shim is compiled into a shared library with a DPC++ compiler, `icpx`
+//! unless `SYCL_CXX` names another one, since the device code of SYCL
+//! objects is only linked by the SYCL compiler driver.
+
+fn main() {
+    #[cfg(feature = "sycl")]
+    sycl::build();
+}
+
+#[cfg(feature = "sycl")]
+mod sycl {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    const SOURCES: [&str; 5] = [
+        "src/hal_backends/sycl/shim.cpp",
+        "src/hal_backends/sycl/matmul.cpp",
+        "src/hal_backends/sycl/conv2d.cpp",
+        "src/hal_backends/sycl/attention.cpp",
+        "src/hal_backends/sycl/layer_norm.cpp",
+    ];
+
+    pub fn build() {
+        for source in SOURCES {
+            println!("cargo:rerun-if-changed={source}");
+        }
+        println!("cargo:rerun-if-env-changed=SYCL_CXX");
+
+        let compiler = std::env::var("SYCL_CXX").unwrap_or_else(|_| "icpx".to_string());
+        let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+        let library = out_dir.join("libaurex_sycl_shim.so");
+        let status = Command::new(&compiler)
+            .args(["-fsycl", "-shared", "-fPIC", "-O2", "-fvisibility=hidden"])
+            .arg(SOURCES[0])
+            .arg("-o")
+            .arg(&library)
+            .status()
+            .unwrap_or_else(|err| panic!("failed to run SYCL compiler '{compiler}': {err}"));
+        assert!(
+            status.success(),
+            "SYCL compiler '{compiler}' failed with {status}"
+        );
+
+        println!("cargo:rustc-link-search=native={}", out_dir.display());
+        println!("cargo:rustc-link-lib=dylib=aurex_sycl_shim");
+        // Tests and examples of this crate find the shim without
+        // LD_LIBRARY_PATH.
+        println!("cargo:rustc-link-arg=-Wl,-rpath,{}", out_dir.display());
+    }
+}
@@ -0,0 +1,67 @@
+//! Whisper-style speech front-end on the CPU SIMD backend.
+//!
+//! Reads the WAV file given as the first argument, or synthesizes two
+//! seconds of a rising tone, converts it to the 80-band log-mel spectrogram
+//! Whisper expects and runs it through a convolutional front-end with
+//! deterministic weights.
+//!
+//! ```text
+//! cargo run --release --example asr_frontend -- speech.wav
+//! ```
+
+use std::time::Instant;
+
+use amduda::amduda_core::audio::{Audio, MelSpectrogram, WHISPER_SAMPLE_RATE};
+use amduda::aurex_lm::speech::SpeechFrontEnd;
+use amduda::hal_backends::cpu_simd::CpuSimdBackend;
+
+const HIDDEN: usize = 384;
+
+fn chirp(sample_rate: u32, seconds: f32) -> Audio {
+    let len = (sample_rate as f32 * seconds) as usize;
+    let samples = (0..len)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            // Sweep from 200 Hz to 2 kHz.
+            let phase = 2.0 * std::f32::consts::PI * (200.0 * t + 450.0 * t * t);
+            0.5 * phase.sin()
+        })
+        .collect();
+    Audio {
+        sample_rate,
+        samples,
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let audio = match std::env::args().nth(1) {
+        Some(path) => Audio::open(path)?,
+        None => chirp(44_100, 2.0),
+    };
+    println!(
+        "input: {:.2} s at {} Hz",
+        audio.duration(),
+        audio.sample_rate
+    );
+    let audio = audio.resample(WHISPER_SAMPLE_RATE);
+
+    let mel = MelSpectrogram::whisper();
+    let n_mels = mel.config().n_mels;
+    let start = Instant::now();
+    let features = mel.compute(&CpuSimdBackend, &audio.samples);
+    let frames = features.len() / n_mels;
+    println!("log-mel: {n_mels} x {frames} in {:?}", start.elapsed());
+
+    let weights: Vec<f32> = (0..SpeechFrontEnd::weight_count(n_mels, HIDDEN))
+        .map(|i| ((i * 31 % 97) as f32 - 48.0) / 480.0)
+        .collect();
+    let front_end = SpeechFrontEnd::new(n_mels, HIDDEN, &weights)?;
+    let start = Instant::now();
+    let encoded = front_end.forward(&features);
+    println!(
+        "front-end: {} x {HIDDEN} in {:?}",
+        encoded.len() / HIDDEN,
+        start.elapsed()
+    );
+    Ok(())
+}
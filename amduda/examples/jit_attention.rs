@@ -1,8 +1,8 @@
-#[cfg(feature = "jit")]
+#[cfg(any(feature = "jit", feature = "jit-cranelift"))]
 use amduda::amduda_core::jit_compiler::{compile_kernel, Device};
 
 fn main() {
-    #[cfg(feature = "jit")]
+    #[cfg(any(feature = "jit", feature = "jit-cranelift"))]
     {
         // Compile a simple "add" kernel and execute it.
         let kernel = compile_kernel("add", Device::CPU).expect("compile kernel");
@@ -10,9 +10,11 @@ fn main() {
         println!("2 + 3 = {}", result);
     }
 
-    #[cfg(not(feature = "jit"))]
+    #[cfg(not(any(feature = "jit", feature = "jit-cranelift")))]
     {
         // Example available only when JIT feature is enabled.
-        eprintln!("The `jit` feature is not enabled. Enable it to run this example.");
+        eprintln!(
+            "No JIT feature is enabled. Enable `jit` or `jit-cranelift` to run this example."
+        );
     }
 }
@@ -0,0 +1,324 @@
+//! Audio feature extraction for speech models.
+//!
+//! Whisper-style speech models read 16 kHz mono audio as a log-mel
+//! spectrogram.  [`Audio`] decodes PCM WAV files, mixes them down to mono and
+//! resamples them; [`MelSpectrogram`] then computes the features:
+//!
+//! 1. the signal is reflect-padded by half a window and cut into frames of
+//!    `n_fft` samples every `hop_length` samples, each weighted by a periodic
+//!    Hann window;
+//! 2. the power spectrum of every frame is computed as a matmul of the
+//!    frames with the real and imaginary DFT basis;
+//! 3. a matmul with a bank of Slaney-normalised triangular filters maps the
+//!    spectrum onto `n_mels` mel bands;
+//! 4. the bands are log-compressed, clamped to 80 dB below the loudest one
+//!    and scaled to roughly `[-1, 1]`.
+//!
+//! Both matmuls run through [`TensorOps`], so the SIMD kernels of
+//! [`CpuSimdBackend`](crate::hal_backends::cpu_simd::CpuSimdBackend) apply.
+
+use std::f32::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::tensor_ops::TensorOps;
+
+/// Sample rate Whisper models expect.
+pub const WHISPER_SAMPLE_RATE: u32 = 16_000;
+
+/// Mono audio with samples in `[-1, 1]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+impl Audio {
+    /// Decode the WAV file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::decode_wav(&fs::read(path)?)
+    }
+
+    /// Decode a RIFF WAV file holding 8, 16, 24 or 32-bit integer PCM or
+    /// 32-bit float samples.  Channels are averaged into one.
+    pub fn decode_wav(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("not a RIFF WAVE file"));
+        }
+        let mut format = None;
+        let mut data = None;
+        let mut chunks = &bytes[12..];
+        while chunks.len() >= 8 {
+            let id = &chunks[..4];
+            let len = u32::from_le_bytes([chunks[4], chunks[5], chunks[6], chunks[7]]) as usize;
+            let body = &chunks[8..(8 + len).min(chunks.len())];
+            match id {
+                b"fmt " if body.len() >= 16 => format = Some(body),
+                b"data" => data = Some(body),
+                _ => {}
+            }
+            // Chunks are padded to an even length.
+            chunks = &chunks[(8 + len + len % 2).min(chunks.len())..];
+        }
+        let (Some(format), Some(data)) = (format, data) else {
+            return Err(invalid("WAV file without fmt or data chunk"));
+        };
+        let u16_at = |i: usize| u16::from_le_bytes([format[i], format[i + 1]]);
+        let mut tag = u16_at(0);
+        let channels = usize::from(u16_at(2));
+        let sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+        let bits = u16_at(14);
+        if tag == 0xFFFE && format.len() >= 26 {
+            // WAVE_FORMAT_EXTENSIBLE keeps the format in its sub-format GUID.
+            tag = u16_at(24);
+        }
+        let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+            (1, 8) => |s| (f32::from(s[0]) - 128.0) / 128.0,
+            (1, 16) => |s| f32::from(i16::from_le_bytes([s[0], s[1]])) / 32768.0,
+            (1, 24) => |s| (i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) as f32 / 8_388_608.0,
+            (1, 32) => |s| i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f32 / 2_147_483_648.0,
+            (3, 32) => |s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]),
+            _ => {
+                return Err(invalid(format!(
+                    "unsupported WAV format {tag} with {bits} bits"
+                )))
+            }
+        };
+        if channels == 0 {
+            return Err(invalid("WAV file without channels"));
+        }
+        let width = usize::from(bits / 8);
+        let samples = data
+            .chunks_exact(width * channels)
+            .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / channels as f32)
+            .collect();
+        Ok(Self {
+            sample_rate,
+            samples,
+        })
+    }
+
+    /// Linearly interpolate the samples to `sample_rate`.
+    pub fn resample(&self, sample_rate: u32) -> Audio {
+        if sample_rate == self.sample_rate || self.samples.is_empty() {
+            return Audio {
+                sample_rate,
+                samples: self.samples.clone(),
+            };
+        }
+        let ratio = f64::from(self.sample_rate) / f64::from(sample_rate);
+        let len = (self.samples.len() as f64 / ratio).round() as usize;
+        let last = self.samples.len() - 1;
+        let samples = (0..len)
+            .map(|i| {
+                let pos = i as f64 * ratio;
+                let lo = (pos as usize).min(last);
+                let hi = (lo + 1).min(last);
+                let frac = (pos - lo as f64) as f32;
+                self.samples[lo] * (1.0 - frac) + self.samples[hi] * frac
+            })
+            .collect();
+        Audio {
+            sample_rate,
+            samples,
+        }
+    }
+
+    /// Duration in seconds.
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32 / self.sample_rate.max(1) as f32
+    }
+}
+
+/// Parameters of a [`MelSpectrogram`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MelConfig {
+    pub sample_rate: u32,
+    /// Samples per frame and DFT size.
+    pub n_fft: usize,
+    /// Samples between the starts of consecutive frames.
+    pub hop_length: usize,
+    pub n_mels: usize,
+}
+
+impl MelConfig {
+    /// 80 mel bands of 25 ms frames every 10 ms at 16 kHz, as in Whisper.
+    pub fn whisper() -> Self {
+        Self {
+            sample_rate: WHISPER_SAMPLE_RATE,
+            n_fft: 400,
+            hop_length: 160,
+            n_mels: 80,
+        }
+    }
+}
+
+impl Default for MelConfig {
+    fn default() -> Self {
+        Self::whisper()
+    }
+}
+
+/// Log-mel spectrogram extractor, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct MelSpectrogram {
+    config: MelConfig,
+    window: Vec<f32>,
+    /// `n_fft x 2 * bins`: cosine then sine basis of every frequency bin.
+    basis: Vec<f32>,
+    /// `bins x n_mels`.
+    filters: Vec<f32>,
+}
+
+impl MelSpectrogram {
+    pub fn new(config: MelConfig) -> Self {
+        let n = config.n_fft;
+        let bins = n / 2 + 1;
+        let window = (0..n)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos())
+            .collect();
+        let mut basis = vec![0.0; n * 2 * bins];
+        for (t, row) in basis.chunks_exact_mut(2 * bins).enumerate() {
+            for k in 0..bins {
+                // Reduce the phase first to keep it exact for long windows.
+                let phase = 2.0 * PI * ((t * k) % n) as f32 / n as f32;
+                row[k] = phase.cos();
+                row[bins + k] = -phase.sin();
+            }
+        }
+        Self {
+            config,
+            window,
+            basis,
+            filters: mel_filters(config.sample_rate, n, config.n_mels),
+        }
+    }
+
+    pub fn whisper() -> Self {
+        Self::new(MelConfig::whisper())
+    }
+
+    pub fn config(&self) -> &MelConfig {
+        &self.config
+    }
+
+    /// Frequency bins of the power spectrum.
+    pub fn bins(&self) -> usize {
+        self.config.n_fft / 2 + 1
+    }
+
+    /// Mel filter bank, `bins x n_mels`.
+    pub fn filters(&self) -> &[f32] {
+        &self.filters
+    }
+
+    /// Frames computed for `samples` samples.
+    pub fn frames(&self, samples: usize) -> usize {
+        samples / self.config.hop_length.max(1)
+    }
+
+    /// Log-mel spectrogram of `samples`, recorded at the configured sample
+    /// rate, as `n_mels x frames` values.
+    pub fn compute(&self, ops: &dyn TensorOps, samples: &[f32]) -> Vec<f32> {
+        let MelConfig {
+            n_fft,
+            hop_length,
+            n_mels,
+            ..
+        } = self.config;
+        let bins = self.bins();
+        let frames = self.frames(samples.len());
+        if frames == 0 {
+            return Vec::new();
+        }
+        let pad = n_fft / 2;
+        let mut windowed = Vec::with_capacity(frames * n_fft);
+        for f in 0..frames {
+            let start = f * hop_length;
+            windowed.extend(
+                self.window
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| w * reflect(samples, (start + i) as isize - pad as isize)),
+            );
+        }
+
+        let spectrum = ops.matmul(&windowed, &self.basis, frames, 2 * bins, n_fft);
+        let power: Vec<f32> = spectrum
+            .chunks_exact(2 * bins)
+            .flat_map(|row| {
+                let (re, im) = row.split_at(bins);
+                re.iter().zip(im).map(|(r, i)| r * r + i * i)
+            })
+            .collect();
+        let mel = ops.matmul(&power, &self.filters, frames, n_mels, bins);
+
+        let log: Vec<f32> = mel.iter().map(|&m| m.max(1e-10).log10()).collect();
+        let floor = log.iter().copied().fold(f32::NEG_INFINITY, f32::max) - 8.0;
+        let mut out = vec![0.0; n_mels * frames];
+        for (f, row) in log.chunks_exact(n_mels).enumerate() {
+            for (m, &v) in row.iter().enumerate() {
+                out[m * frames + f] = (v.max(floor) + 4.0) / 4.0;
+            }
+        }
+        out
+    }
+}
+
+/// Sample `i` of `samples` mirrored at both ends, as by reflect padding.
+fn reflect(samples: &[f32], i: isize) -> f32 {
+    let len = samples.len() as isize;
+    if len < 2 {
+        return samples.first().copied().unwrap_or(0.0);
+    }
+    let period = 2 * (len - 1);
+    let i = i.rem_euclid(period);
+    samples[(if i < len { i } else { period - i }) as usize]
+}
+
+/// Mel scale of Slaney's Auditory Toolbox: linear below 1 kHz, logarithmic
+/// above.
+fn hz_to_mel(hz: f32) -> f32 {
+    let log_step = 6.4f32.ln() / 27.0;
+    if hz < 1000.0 {
+        3.0 * hz / 200.0
+    } else {
+        15.0 + (hz / 1000.0).ln() / log_step
+    }
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    let log_step = 6.4f32.ln() / 27.0;
+    if mel < 15.0 {
+        200.0 * mel / 3.0
+    } else {
+        1000.0 * ((mel - 15.0) * log_step).exp()
+    }
+}
+
+/// `bins x n_mels` triangular filters spaced evenly on the mel scale up to
+/// the Nyquist frequency, each scaled to unit area.
+fn mel_filters(sample_rate: u32, n_fft: usize, n_mels: usize) -> Vec<f32> {
+    let bins = n_fft / 2 + 1;
+    let nyquist = sample_rate as f32 / 2.0;
+    let top = hz_to_mel(nyquist);
+    let edges: Vec<f32> = (0..n_mels + 2)
+        .map(|i| mel_to_hz(top * i as f32 / (n_mels + 1) as f32))
+        .collect();
+    let mut filters = vec![0.0; bins * n_mels];
+    for (k, row) in filters.chunks_exact_mut(n_mels).enumerate() {
+        let freq = k as f32 * sample_rate as f32 / n_fft as f32;
+        for (m, w) in row.iter_mut().enumerate() {
+            let (lo, mid, hi) = (edges[m], edges[m + 1], edges[m + 2]);
+            let rising = (freq - lo) / (mid - lo);
+            let falling = (hi - freq) / (hi - mid);
+            *w = rising.min(falling).max(0.0) * 2.0 / (hi - lo);
+        }
+    }
+    filters
+}
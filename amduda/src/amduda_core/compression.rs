@@ -0,0 +1,107 @@
+//! Compression of cold allocations demoted to the CPU or NVMe tier.
+//!
+//! A [`MemoryManager`](crate::amduda_core::memory_tiering::MemoryManager)
+//! with a [`Compressor`] attached compresses the bytes of allocations it
+//! moves to a slower tier and charges the tier only for the compressed size.
+//! Data is decompressed transparently when read or promoted again.  Data
+//! that does not shrink is kept as is.
+
+use std::io;
+
+use aurex_runtime::config::Compression;
+
+/// Level used when none is configured; zstd's own default.
+pub const DEFAULT_LEVEL: i32 = 3;
+
+/// Totals of the compression work done by a memory manager.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Allocations stored compressed.
+    pub compressed: u64,
+    /// Demotions that left the data uncompressed because it did not shrink.
+    pub incompressible: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub decompressed: u64,
+}
+
+impl CompressionStats {
+    /// Compressed size relative to the input, 1.0 before any compression.
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            return 1.0;
+        }
+        self.bytes_out as f64 / self.bytes_in as f64
+    }
+}
+
+/// Codec and level applied to demoted allocations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compressor {
+    codec: Compression,
+    level: i32,
+}
+
+impl Compressor {
+    /// Compressor for `codec` at [`DEFAULT_LEVEL`].
+    pub fn new(codec: Compression) -> Self {
+        Self {
+            codec,
+            level: DEFAULT_LEVEL,
+        }
+    }
+
+    /// Trade speed for ratio: zstd accepts levels 1 to 22, LZ4 has a single
+    /// level and ignores it.
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn codec(&self) -> Compression {
+        self.codec
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self.codec {
+            Compression::Zstd => zstd::bulk::compress(data, self.level),
+            Compression::Lz4 => Ok(lz4_flex::block::compress(data)),
+        }
+    }
+
+    /// Most bytes one compressed byte can expand to.  LZ4 extends a match
+    /// by at most 255 bytes per byte; a zstd RLE block of 4 bytes covers at
+    /// most 128 KiB.
+    pub fn max_ratio(&self) -> usize {
+        match self.codec {
+            Compression::Zstd => 1 << 15,
+            Compression::Lz4 => 255,
+        }
+    }
+
+    /// Restore `original_len` bytes from `data` produced by [`compress`].
+    /// Lengths `data` cannot expand to are rejected before anything is
+    /// allocated for them.
+    ///
+    /// [`compress`]: Self::compress
+    pub fn decompress(&self, data: &[u8], original_len: usize) -> io::Result<Vec<u8>> {
+        if original_len > data.len().saturating_mul(self.max_ratio()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} compressed bytes cannot hold {original_len} bytes",
+                    data.len()
+                ),
+            ));
+        }
+        match self.codec {
+            Compression::Zstd => zstd::bulk::decompress(data, original_len),
+            Compression::Lz4 => lz4_flex::block::decompress(data, original_len)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+}
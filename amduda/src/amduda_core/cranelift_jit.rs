@@ -0,0 +1,287 @@
+//! Cranelift code generation for the JIT compiler.
+//!
+//! Used when the crate is built with `jit-cranelift` but not `jit`.  Cranelift
+//! is pure Rust, so no LLVM has to be installed on the host.  It does not
+//! vectorize loops, so tensor kernels run scalar, and compiling is cheap
+//! enough that objects are not kept in the on-disk kernel cache.  Loops over
+//! fixed dimensions of at most [`UNROLL_LIMIT`] iterations are unrolled here,
+//! as Cranelift does not unroll loops itself.
+
+use cranelift_codegen::{
+    ir::{condcodes::IntCC, types, AbiParam, InstBuilder, MemFlags, Type, Value},
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use super::jit_compiler::TensorKernel;
+
+/// Longest loop over a fixed dimension that is unrolled.
+const UNROLL_LIMIT: usize = 16;
+
+/// Compile the `i32` kernel `source` and return the address of its entry
+/// point.
+pub(super) unsafe fn jit_kernel(source: &str) -> Result<u64, String> {
+    let mul = match source {
+        "add" => false,
+        "mul" => true,
+        _ => return Err(format!("unsupported kernel: {}", source)),
+    };
+    compile(|ir| {
+        let [a, b] = ir.function(&[types::I32, types::I32], Some(types::I32));
+        let result = if mul {
+            ir.b.ins().imul(a, b)
+        } else {
+            ir.b.ins().iadd(a, b)
+        };
+        ir.b.ins().return_(&[result]);
+    })
+}
+
+/// Compile the `f32` kernel `source` and return the address of its entry
+/// point.
+pub(super) unsafe fn jit_kernel_f32(source: &str) -> Result<u64, String> {
+    let mul = match source {
+        "add_f32" => false,
+        "mul_f32" => true,
+        _ => return Err(format!("unsupported kernel: {}", source)),
+    };
+    compile(|ir| {
+        let [a, b] = ir.function(&[types::F32, types::F32], Some(types::F32));
+        let result = if mul {
+            ir.b.ins().fmul(a, b)
+        } else {
+            ir.b.ins().fadd(a, b)
+        };
+        ir.b.ins().return_(&[result]);
+    })
+}
+
+/// Compile the tensor kernel `kernel` and return the address of its entry
+/// point.
+pub(super) unsafe fn jit_tensor_kernel(kernel: TensorKernel) -> Result<u64, String> {
+    match kernel {
+        TensorKernel::Matmul(dims) => compile(|ir| ir.matmul(dims)),
+        TensorKernel::LayerNorm(len) => compile(|ir| ir.layer_norm(len)),
+    }
+}
+
+/// Define `kernel` with `build`, compile it for the host CPU and return its
+/// address.
+fn compile(build: impl FnOnce(&mut KernelIr)) -> Result<u64, String> {
+    let mut flags = settings::builder();
+    flags
+        .set("opt_level", "speed")
+        .map_err(|err| err.to_string())?;
+    let isa = cranelift_native::builder()?
+        .finish(settings::Flags::new(flags))
+        .map_err(|err| err.to_string())?;
+    let size_type = isa.pointer_type();
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let mut ctx = module.make_context();
+    let mut builder_ctx = FunctionBuilderContext::new();
+    let mut ir = KernelIr {
+        b: FunctionBuilder::new(&mut ctx.func, &mut builder_ctx),
+        size_type,
+    };
+    build(&mut ir);
+    ir.b.finalize();
+
+    let id = module
+        .declare_function("kernel", Linkage::Export, &ctx.func.signature)
+        .map_err(|err| err.to_string())?;
+    module
+        .define_function(id, &mut ctx)
+        .map_err(|err| err.to_string())?;
+    module
+        .finalize_definitions()
+        .map_err(|err| err.to_string())?;
+    let addr = module.get_finalized_function(id) as u64;
+    // Note: the module is intentionally leaked, the kernel lives as long as
+    // the process.
+    std::mem::forget(module);
+    Ok(addr)
+}
+
+/// IR builder for a kernel named `kernel`.
+struct KernelIr<'a> {
+    b: FunctionBuilder<'a>,
+    /// Type of `usize` and pointers on the host.
+    size_type: Type,
+}
+
+impl KernelIr<'_> {
+    /// Declare `kernel(params...) -> ret`, position the builder in its entry
+    /// block and return the parameters.
+    fn function<const N: usize>(&mut self, params: &[Type; N], ret: Option<Type>) -> [Value; N] {
+        let signature = &mut self.b.func.signature;
+        signature
+            .params
+            .extend(params.iter().map(|&ty| AbiParam::new(ty)));
+        signature.returns.extend(ret.map(AbiParam::new));
+        let entry = self.b.create_block();
+        self.b.append_block_params_for_function_params(entry);
+        self.b.switch_to_block(entry);
+        self.b.seal_block(entry);
+        let values = self.b.block_params(entry);
+        std::array::from_fn(|i| values[i])
+    }
+
+    /// `out[i*n + j] += a[i*k + p] * b[p*n + j]` for all `i < m, p < k, j < n`.
+    /// `n` and `k` are constants when `dims` is set.
+    fn matmul(&mut self, dims: Option<(usize, usize)>) {
+        let s = self.size_type;
+        let [a, b, out, m, n, k] = self.function(&[s; 6], None);
+        let (fixed_n, fixed_k) = (dims.map(|(n, _)| n), dims.map(|(_, k)| k));
+        let n = self.dim(n, fixed_n);
+        let k = self.dim(k, fixed_k);
+        self.for_range(m, None, |ir, i, _| {
+            ir.for_dim(k, fixed_k, None, |ir, p, _| {
+                let ik = ir.b.ins().imul(i, k);
+                let a_idx = ir.b.ins().iadd(ik, p);
+                let a_ip = ir.load(a, a_idx);
+                ir.for_dim(n, fixed_n, None, |ir, j, _| {
+                    let i_n = ir.b.ins().imul(i, n);
+                    let idx = ir.b.ins().iadd(i_n, j);
+                    let pn = ir.b.ins().imul(p, n);
+                    let b_idx = ir.b.ins().iadd(pn, j);
+                    let b_pj = ir.load(b, b_idx);
+                    let product = ir.b.ins().fmul(a_ip, b_pj);
+                    let current = ir.load(out, idx);
+                    let sum = ir.b.ins().fadd(current, product);
+                    ir.store(out, idx, sum);
+                    None
+                });
+                None
+            });
+            None
+        });
+        self.b.ins().return_(&[]);
+    }
+
+    /// `out[i] = (x[i] - mean) / sqrt(var + eps) * gamma[i] + beta[i]`.
+    /// `len` is a constant when `fixed_len` is set.
+    fn layer_norm(&mut self, fixed_len: Option<usize>) {
+        let s = self.size_type;
+        let [x, gamma, beta, out, len, eps] = self.function(&[s, s, s, s, s, types::F32], None);
+        let len = self.dim(len, fixed_len);
+        let count = self.b.ins().fcvt_from_uint(types::F32, len);
+        let zero = self.b.ins().f32const(0.0);
+
+        let total = self.for_dim(len, fixed_len, Some(zero), |ir, i, acc| {
+            let value = ir.load(x, i);
+            Some(ir.b.ins().fadd(acc?, value))
+        });
+        let mean = self.b.ins().fdiv(total.unwrap(), count);
+        let squares = self.for_dim(len, fixed_len, Some(zero), |ir, i, acc| {
+            let value = ir.load(x, i);
+            let d = ir.b.ins().fsub(value, mean);
+            let sq = ir.b.ins().fmul(d, d);
+            Some(ir.b.ins().fadd(acc?, sq))
+        });
+        let var = self.b.ins().fdiv(squares.unwrap(), count);
+        let var_eps = self.b.ins().fadd(var, eps);
+        let denom = self.b.ins().sqrt(var_eps);
+
+        self.for_dim(len, fixed_len, None, |ir, i, _| {
+            let value = ir.load(x, i);
+            let d = ir.b.ins().fsub(value, mean);
+            let norm = ir.b.ins().fdiv(d, denom);
+            let g = ir.load(gamma, i);
+            let scaled = ir.b.ins().fmul(norm, g);
+            let shift = ir.load(beta, i);
+            let shifted = ir.b.ins().fadd(scaled, shift);
+            ir.store(out, i, shifted);
+            None
+        });
+        self.b.ins().return_(&[]);
+    }
+
+    /// The dimension passed as `param`, or the constant `fixed`.
+    fn dim(&mut self, param: Value, fixed: Option<usize>) -> Value {
+        match fixed {
+            Some(value) => self.b.ins().iconst(self.size_type, value as i64),
+            None => param,
+        }
+    }
+
+    /// Like [`KernelIr::for_range`], but fully unrolled when the trip count
+    /// is `fixed` and at most [`UNROLL_LIMIT`].
+    fn for_dim(
+        &mut self,
+        end: Value,
+        fixed: Option<usize>,
+        init: Option<Value>,
+        mut body: impl FnMut(&mut Self, Value, Option<Value>) -> Option<Value>,
+    ) -> Option<Value> {
+        match fixed {
+            Some(count) if count <= UNROLL_LIMIT => {
+                let mut acc = init;
+                for i in 0..count {
+                    let index = self.b.ins().iconst(self.size_type, i as i64);
+                    acc = body(self, index, acc);
+                }
+                acc
+            }
+            _ => self.for_range(end, init, body),
+        }
+    }
+
+    /// Emit `for i in 0..end { acc = body(i, acc) }` and return the final
+    /// `acc`, if the loop carries one.
+    fn for_range(
+        &mut self,
+        end: Value,
+        init: Option<Value>,
+        body: impl FnOnce(&mut Self, Value, Option<Value>) -> Option<Value>,
+    ) -> Option<Value> {
+        let (header, body_block, exit) = (
+            self.b.create_block(),
+            self.b.create_block(),
+            self.b.create_block(),
+        );
+        self.b.append_block_param(header, self.size_type);
+        if init.is_some() {
+            self.b.append_block_param(header, types::F32);
+        }
+        let zero = self.b.ins().iconst(self.size_type, 0);
+        let args: Vec<Value> = std::iter::once(zero).chain(init).collect();
+        self.b.ins().jump(header, &args);
+
+        self.b.switch_to_block(header);
+        let index = self.b.block_params(header)[0];
+        let acc = self.b.block_params(header).get(1).copied();
+        let more = self.b.ins().icmp(IntCC::UnsignedLessThan, index, end);
+        self.b.ins().brif(more, body_block, &[], exit, &[]);
+
+        self.b.switch_to_block(body_block);
+        self.b.seal_block(body_block);
+        let next_acc = body(self, index, acc);
+        let next = self.b.ins().iadd_imm(index, 1);
+        let args: Vec<Value> = std::iter::once(next).chain(next_acc).collect();
+        self.b.ins().jump(header, &args);
+        self.b.seal_block(header);
+
+        self.b.switch_to_block(exit);
+        self.b.seal_block(exit);
+        acc
+    }
+
+    /// Address of the `f32` at `index` of `base`.
+    fn element(&mut self, base: Value, index: Value) -> Value {
+        let offset = self.b.ins().imul_imm(index, 4);
+        self.b.ins().iadd(base, offset)
+    }
+
+    fn load(&mut self, base: Value, index: Value) -> Value {
+        let addr = self.element(base, index);
+        self.b.ins().load(types::F32, MemFlags::trusted(), addr, 0)
+    }
+
+    fn store(&mut self, base: Value, index: Value, value: Value) {
+        let addr = self.element(base, index);
+        self.b.ins().store(MemFlags::trusted(), value, addr, 0);
+    }
+}
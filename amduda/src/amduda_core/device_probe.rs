@@ -0,0 +1,111 @@
+//! Probing of the memory available to each tier on this machine.
+//!
+//! * GPU memory is the size of the device-local heaps reported by HIP (with
+//!   the `rocm` feature) or else by the first Vulkan GPU found.
+//! * CPU memory is the RAM currently available according to `sysinfo`.
+//! * NVMe is present when the kernel lists an `nvme*` block device; its space
+//!   is the free space of the filesystem holding the spill directory.
+//!
+//! Every probe returns `None` when the value cannot be determined, leaving it
+//! to [`DeviceCapabilities::probe`] to fall back to its defaults.
+//!
+//! [`DeviceCapabilities::probe`]: crate::amduda_core::memory_tiering::DeviceCapabilities::probe
+
+use std::path::Path;
+
+use ash::{vk, Entry};
+use sysinfo::System;
+
+/// Bytes of device memory of the first GPU found.
+pub fn gpu_memory() -> Option<usize> {
+    rocm_memory().or_else(vulkan_memory)
+}
+
+#[cfg(feature = "rocm")]
+fn rocm_memory() -> Option<usize> {
+    use hip_runtime_sys as hip;
+
+    let mut free = 0usize;
+    let mut total = 0usize;
+    unsafe {
+        let _ = hip::hipInit(0);
+        if hip::hipMemGetInfo(&mut free, &mut total) != hip::hipError_t::hipSuccess as i32 {
+            return None;
+        }
+    }
+    (total > 0).then_some(total)
+}
+
+#[cfg(not(feature = "rocm"))]
+fn rocm_memory() -> Option<usize> {
+    None
+}
+
+/// Device-local heap size of the largest Vulkan GPU.  Software rasterizers
+/// report themselves as CPU devices and are skipped.
+fn vulkan_memory() -> Option<usize> {
+    let entry = unsafe { Entry::load() }.ok()?;
+    let app_info = vk::ApplicationInfo::builder().api_version(vk::API_VERSION_1_0);
+    let create_info = vk::InstanceCreateInfo::builder().application_info(&app_info);
+    let instance = unsafe { entry.create_instance(&create_info, None) }.ok()?;
+    let devices = unsafe { instance.enumerate_physical_devices() }.unwrap_or_default();
+    let largest = devices
+        .into_iter()
+        .filter(|&device| {
+            let props = unsafe { instance.get_physical_device_properties(device) };
+            props.device_type != vk::PhysicalDeviceType::CPU
+        })
+        .map(|device| {
+            let props = unsafe { instance.get_physical_device_memory_properties(device) };
+            props.memory_heaps[..props.memory_heap_count as usize]
+                .iter()
+                .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+                .map(|heap| heap.size)
+                .sum::<u64>()
+        })
+        .max();
+    unsafe { instance.destroy_instance(None) };
+    largest.filter(|&bytes| bytes > 0).map(|bytes| bytes as usize)
+}
+
+/// Bytes of RAM available without swapping.
+pub fn system_memory() -> Option<usize> {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    let available = sys.available_memory();
+    (available > 0).then_some(available as usize)
+}
+
+/// Whether the kernel exposes an NVMe block device.
+pub fn has_nvme() -> bool {
+    std::fs::read_dir("/sys/block")
+        .map(|entries| {
+            entries
+                .flatten()
+                .any(|e| e.file_name().to_string_lossy().starts_with("nvme"))
+        })
+        .unwrap_or(false)
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `dir`.
+#[cfg(unix)]
+pub fn free_space(dir: &Path) -> Option<usize> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    // The field widths differ between platforms.
+    #[allow(clippy::useless_conversion)]
+    let bytes = u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize));
+    Some(usize::try_from(bytes).unwrap_or(usize::MAX))
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_dir: &Path) -> Option<usize> {
+    None
+}
@@ -0,0 +1,137 @@
+//! Eviction policies deciding which allocation leaves a full memory tier.
+//!
+//! A [`MemoryManager`](crate::amduda_core::memory_tiering::MemoryManager)
+//! reports every allocation, access and release to its [`EvictionPolicy`] and
+//! asks it for a victim whenever a tier runs out of room.  Accesses are
+//! recorded automatically when the manager is attached to a
+//! [`Dispatcher`](aurex_backend::Dispatcher) as an access observer, so callers
+//! no longer tag data as hot or cold by hand.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use aurex_runtime::config::Eviction;
+
+use crate::amduda_core::memory_tiering::AllocationId;
+
+/// Strategy choosing eviction victims among live allocations.
+pub trait EvictionPolicy: Send + std::fmt::Debug {
+    /// A new allocation was made; it counts as accessed.
+    fn on_insert(&mut self, id: AllocationId);
+    /// `id` was read or written.
+    fn on_access(&mut self, id: AllocationId);
+    /// `id` was freed or dropped.
+    fn on_remove(&mut self, id: AllocationId);
+    /// Pick the allocation to evict among those accepted by `eligible`.
+    fn victim(&mut self, eligible: &dyn Fn(AllocationId) -> bool) -> Option<AllocationId>;
+}
+
+/// Policy selected by the `eviction` setting of the `[memory]` section.
+pub fn from_kind(kind: Eviction) -> Box<dyn EvictionPolicy> {
+    match kind {
+        Eviction::Lru => Box::new(Lru::default()),
+        Eviction::Lfu => Box::new(Lfu::default()),
+        Eviction::Clock => Box::new(Clock::default()),
+    }
+}
+
+/// Evicts the least recently used allocation.
+#[derive(Debug, Default)]
+pub struct Lru {
+    last_use: BTreeMap<AllocationId, u64>,
+    tick: u64,
+}
+
+impl EvictionPolicy for Lru {
+    fn on_insert(&mut self, id: AllocationId) {
+        self.on_access(id);
+    }
+
+    fn on_access(&mut self, id: AllocationId) {
+        self.tick += 1;
+        self.last_use.insert(id, self.tick);
+    }
+
+    fn on_remove(&mut self, id: AllocationId) {
+        self.last_use.remove(&id);
+    }
+
+    fn victim(&mut self, eligible: &dyn Fn(AllocationId) -> bool) -> Option<AllocationId> {
+        self.last_use
+            .iter()
+            .filter(|(id, _)| eligible(**id))
+            .min_by_key(|(_, tick)| **tick)
+            .map(|(id, _)| *id)
+    }
+}
+
+/// Evicts the least frequently used allocation, the oldest one on ties.
+#[derive(Debug, Default)]
+pub struct Lfu {
+    uses: BTreeMap<AllocationId, u64>,
+}
+
+impl EvictionPolicy for Lfu {
+    fn on_insert(&mut self, id: AllocationId) {
+        self.uses.insert(id, 1);
+    }
+
+    fn on_access(&mut self, id: AllocationId) {
+        if let Some(uses) = self.uses.get_mut(&id) {
+            *uses += 1;
+        }
+    }
+
+    fn on_remove(&mut self, id: AllocationId) {
+        self.uses.remove(&id);
+    }
+
+    fn victim(&mut self, eligible: &dyn Fn(AllocationId) -> bool) -> Option<AllocationId> {
+        self.uses
+            .iter()
+            .filter(|(id, _)| eligible(**id))
+            .min_by_key(|(id, uses)| (**uses, **id))
+            .map(|(id, _)| *id)
+    }
+}
+
+/// Second-chance approximation of LRU: a hand sweeps over the allocations,
+/// clearing reference bits, and evicts the first eligible one found without
+/// its bit set.
+#[derive(Debug, Default)]
+pub struct Clock {
+    ring: VecDeque<(AllocationId, bool)>,
+}
+
+impl EvictionPolicy for Clock {
+    fn on_insert(&mut self, id: AllocationId) {
+        // The hand sits at the front, so new entries are examined last.
+        self.ring.push_back((id, true));
+    }
+
+    fn on_access(&mut self, id: AllocationId) {
+        if let Some(entry) = self.ring.iter_mut().find(|(e, _)| *e == id) {
+            entry.1 = true;
+        }
+    }
+
+    fn on_remove(&mut self, id: AllocationId) {
+        self.ring.retain(|(e, _)| *e != id);
+    }
+
+    fn victim(&mut self, eligible: &dyn Fn(AllocationId) -> bool) -> Option<AllocationId> {
+        // Two sweeps clear every reference bit, so the second one finds a
+        // victim if any allocation is eligible.
+        for _ in 0..2 * self.ring.len() {
+            let (id, referenced) = self.ring.front_mut()?;
+            let id = *id;
+            if eligible(id) {
+                if !*referenced {
+                    return Some(id);
+                }
+                *referenced = false;
+            }
+            self.ring.rotate_left(1);
+        }
+        None
+    }
+}
@@ -1,15 +1,29 @@
-//! JIT compilation support using LLVM.
+//! JIT compilation of kernels for the host CPU.
+//!
+//! Besides scalar toy kernels, whole tensor kernels are compiled: matmul and
+//! layer norm loops over raw slices (pointer + length ABI).  Code is generated
+//! by LLVM with the `jit` feature, which vectorizes the inner loops and keeps
+//! the objects in the on-disk kernel cache.  The `jit-cranelift` feature
+//! generates the same kernels with Cranelift instead, which needs no system
+//! LLVM and compiles faster but emits scalar loops.  LLVM wins when both
+//! features are enabled.
+//!
+//! Tensor kernels can be specialized for the fixed dimensions of a model, see
+//! [`compile_matmul_for`] and [`compile_layer_norm_for`].  Their loops then
+//! have constant trip counts: LLVM unrolls and vectorizes them without
+//! remainder handling, and Cranelift unrolls short ones itself.
+
+use std::{collections::HashMap, sync::Mutex};
 
-use std::{
-    collections::HashMap,
-    ffi::{CStr, CString},
-    ptr,
-    sync::Mutex,
-};
+use once_cell::sync::Lazy;
 
-use llvm_sys::{core::*, execution_engine::*, target::*};
+#[cfg(not(feature = "jit"))]
+use super::cranelift_jit as codegen;
+#[cfg(feature = "jit")]
+use super::llvm_jit as codegen;
 
-use once_cell::sync::Lazy;
+#[cfg(feature = "jit")]
+pub use super::llvm_jit::emit_kernel_ir_f32;
 
 /// Target device for kernel execution.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -42,10 +56,6 @@ pub struct CompiledKernelF32 {
 static KERNEL_CACHE_F32: Lazy<Mutex<HashMap<String, CompiledKernelF32>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
-fn c_string(s: &str) -> CString {
-    CString::new(s).expect("CString conversion failed")
-}
-
 /// Compile a simple kernel described by `source` for the given `device`.
 ///
 /// Supported `source` strings: "add" and "mul".
@@ -55,60 +65,11 @@ pub fn compile_kernel(source: &str, device: Device) -> Result<CompiledKernel, St
         return Ok(cached);
     }
 
-    unsafe {
-        // Initialise LLVM for JIT usage.
-        LLVMLinkInMCJIT();
-        LLVM_InitializeNativeTarget();
-        LLVM_InitializeNativeAsmPrinter();
-
-        let context = LLVMContextCreate();
-        let module_name = c_string("kernel_module");
-        let module = LLVMModuleCreateWithName(module_name.as_ptr());
-        let builder = LLVMCreateBuilderInContext(context);
-
-        // i32 function: (i32, i32) -> i32
-        let i32_type = LLVMInt32TypeInContext(context);
-        let mut arg_types = [i32_type, i32_type];
-        let fn_type = LLVMFunctionType(i32_type, arg_types.as_mut_ptr(), 2, 0);
-        let fn_name = c_string("kernel");
-        let function = LLVMAddFunction(module, fn_name.as_ptr(), fn_type);
-        let entry_name = c_string("entry");
-        let entry = LLVMAppendBasicBlockInContext(context, function, entry_name.as_ptr());
-        LLVMPositionBuilderAtEnd(builder, entry);
-
-        let a = LLVMGetParam(function, 0);
-        let b = LLVMGetParam(function, 1);
-        let tmp_name = c_string("tmp");
-        let result = match source {
-            "add" => LLVMBuildAdd(builder, a, b, tmp_name.as_ptr()),
-            "mul" => LLVMBuildMul(builder, a, b, tmp_name.as_ptr()),
-            _ => {
-                LLVMDisposeBuilder(builder);
-                LLVMContextDispose(context);
-                return Err(format!("unsupported kernel: {}", source));
-            }
-        };
-
-        LLVMBuildRet(builder, result);
-
-        let mut engine: LLVMExecutionEngineRef = ptr::null_mut();
-        let mut error = ptr::null_mut();
-        if LLVMCreateExecutionEngineForModule(&mut engine, module, &mut error) != 0 {
-            let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
-            LLVMDisposeMessage(error);
-            return Err(msg);
-        }
-
-        let addr = LLVMGetFunctionAddress(engine, fn_name.as_ptr());
-        let func = std::mem::transmute::<u64, extern "C" fn(i32, i32) -> i32>(addr);
-
-        LLVMDisposeBuilder(builder);
-        // Note: module and context intentionally leaked for simplicity.
-
-        let compiled = CompiledKernel { func };
-        KERNEL_CACHE.lock().unwrap().insert(key, compiled);
-        Ok(compiled)
-    }
+    let addr = unsafe { codegen::jit_kernel(source)? };
+    let func = unsafe { std::mem::transmute::<u64, extern "C" fn(i32, i32) -> i32>(addr) };
+    let compiled = CompiledKernel { func };
+    KERNEL_CACHE.lock().unwrap().insert(key, compiled);
+    Ok(compiled)
 }
 
 /// Compile a simple floating point kernel described by `source` for the given `device`.
@@ -120,58 +81,167 @@ pub fn compile_kernel_f32(source: &str, device: Device) -> Result<CompiledKernel
         return Ok(cached);
     }
 
-    unsafe {
-        // Initialise LLVM for JIT usage.
-        LLVMLinkInMCJIT();
-        LLVM_InitializeNativeTarget();
-        LLVM_InitializeNativeAsmPrinter();
-
-        let context = LLVMContextCreate();
-        let module_name = c_string("kernel_module_f32");
-        let module = LLVMModuleCreateWithName(module_name.as_ptr());
-        let builder = LLVMCreateBuilderInContext(context);
-
-        // f32 function: (f32, f32) -> f32
-        let f32_type = LLVMFloatTypeInContext(context);
-        let mut arg_types = [f32_type, f32_type];
-        let fn_type = LLVMFunctionType(f32_type, arg_types.as_mut_ptr(), 2, 0);
-        let fn_name = c_string("kernel");
-        let function = LLVMAddFunction(module, fn_name.as_ptr(), fn_type);
-        let entry_name = c_string("entry");
-        let entry = LLVMAppendBasicBlockInContext(context, function, entry_name.as_ptr());
-        LLVMPositionBuilderAtEnd(builder, entry);
-
-        let a = LLVMGetParam(function, 0);
-        let b = LLVMGetParam(function, 1);
-        let tmp_name = c_string("tmp");
-        let result = match source {
-            "add_f32" => LLVMBuildFAdd(builder, a, b, tmp_name.as_ptr()),
-            "mul_f32" => LLVMBuildFMul(builder, a, b, tmp_name.as_ptr()),
-            _ => {
-                LLVMDisposeBuilder(builder);
-                LLVMContextDispose(context);
-                return Err(format!("unsupported kernel: {}", source));
-            }
+    let addr = unsafe { codegen::jit_kernel_f32(source)? };
+    let func = unsafe { std::mem::transmute::<u64, extern "C" fn(f32, f32) -> f32>(addr) };
+    let compiled = CompiledKernelF32 { func };
+    KERNEL_CACHE_F32.lock().unwrap().insert(key, compiled);
+    Ok(compiled)
+}
+
+/// Signature of compiled matmul kernels: `(a, b, out, m, n, k)` adds the
+/// row-major product of the `m×k` matrix `a` and the `k×n` matrix `b` to the
+/// `m×n` matrix `out`.
+pub type MatmulFn = unsafe extern "C" fn(*const f32, *const f32, *mut f32, usize, usize, usize);
+
+/// Signature of compiled layer norm kernels: `(x, gamma, beta, out, len, eps)`
+/// normalizes the `len` values of `x` into `out`.
+pub type LayerNormFn =
+    unsafe extern "C" fn(*const f32, *const f32, *const f32, *mut f32, usize, f32);
+
+/// Handle to a compiled matmul kernel.
+#[derive(Clone, Copy)]
+pub struct MatmulKernel {
+    /// Function pointer to the JIT-compiled kernel.
+    pub func: MatmulFn,
+    /// `(n, k)` the kernel is specialized for.  A specialized kernel ignores
+    /// the `n` and `k` it is called with.
+    pub dims: Option<(usize, usize)>,
+}
+
+impl MatmulKernel {
+    /// Multiply the row-major `m×k` matrix `a` by the `k×n` matrix `b`.
+    pub fn run(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        assert!(
+            a.len() >= m * k && b.len() >= k * n,
+            "matmul operands too short"
+        );
+        assert!(
+            self.dims.unwrap_or((n, k)) == (n, k),
+            "matmul kernel specialized for other dimensions"
+        );
+        let mut out = vec![0.0; m * n];
+        unsafe { (self.func)(a.as_ptr(), b.as_ptr(), out.as_mut_ptr(), m, n, k) };
+        out
+    }
+}
+
+/// Handle to a compiled layer norm kernel.
+#[derive(Clone, Copy)]
+pub struct LayerNormKernel {
+    /// Function pointer to the JIT-compiled kernel.
+    pub func: LayerNormFn,
+    /// Length the kernel is specialized for.  A specialized kernel ignores
+    /// the length it is called with.
+    pub len: Option<usize>,
+}
+
+impl LayerNormKernel {
+    /// Normalize `x` and scale and shift it by `gamma` and `beta`.
+    pub fn run(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        let len = x.len();
+        assert!(
+            gamma.len() >= len && beta.len() >= len,
+            "layer norm parameters too short"
+        );
+        assert!(
+            self.len.unwrap_or(len) == len,
+            "layer norm kernel specialized for another length"
+        );
+        let mut out = vec![0.0; len];
+        unsafe {
+            (self.func)(
+                x.as_ptr(),
+                gamma.as_ptr(),
+                beta.as_ptr(),
+                out.as_mut_ptr(),
+                len,
+                eps,
+            )
         };
+        out
+    }
+}
 
-        LLVMBuildRet(builder, result);
+/// Tensor kernel to generate.  Dimensions that are set become constants of
+/// the generated code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(super) enum TensorKernel {
+    /// Matmul, specialized for `(n, k)`.
+    Matmul(Option<(usize, usize)>),
+    /// Layer norm, specialized for rows of the given length.
+    LayerNorm(Option<usize>),
+}
 
-        let mut engine: LLVMExecutionEngineRef = ptr::null_mut();
-        let mut error = ptr::null_mut();
-        if LLVMCreateExecutionEngineForModule(&mut engine, module, &mut error) != 0 {
-            let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
-            LLVMDisposeMessage(error);
-            return Err(msg);
+impl TensorKernel {
+    pub(super) fn op(self) -> &'static str {
+        match self {
+            TensorKernel::Matmul(_) => "matmul",
+            TensorKernel::LayerNorm(_) => "layer_norm",
         }
+    }
 
-        let addr = LLVMGetFunctionAddress(engine, fn_name.as_ptr());
-        let func = std::mem::transmute::<u64, extern "C" fn(f32, f32) -> f32>(addr);
+    /// Dimensions baked into the kernel, empty for generic kernels.
+    pub(super) fn shapes(self) -> Vec<usize> {
+        match self {
+            TensorKernel::Matmul(dims) => dims.map_or(Vec::new(), |(n, k)| vec![n, k]),
+            TensorKernel::LayerNorm(len) => len.into_iter().collect(),
+        }
+    }
+}
 
-        LLVMDisposeBuilder(builder);
-        // Note: module and context intentionally leaked for simplicity.
+/// Addresses of compiled tensor kernels keyed by op and device.
+static TENSOR_KERNEL_CACHE: Lazy<Mutex<HashMap<String, u64>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Compile a matmul kernel for the given `device`.
+///
+/// The loops run in `i, p, j` order so the innermost one streams over rows of
+/// `b` and `out` and is vectorized without reassociating sums; results are
+/// bit-identical to the scalar CPU backend.
+pub fn compile_matmul(device: Device) -> Result<MatmulKernel, String> {
+    let addr = compile_tensor_kernel(TensorKernel::Matmul(None), device)?;
+    let func = unsafe { std::mem::transmute::<u64, MatmulFn>(addr) };
+    Ok(MatmulKernel { func, dims: None })
+}
+
+/// Compile a matmul kernel for a right-hand side of `k×n` values, as used
+/// with the fixed weights of a model.  Only `m` may vary between calls.
+pub fn compile_matmul_for(n: usize, k: usize, device: Device) -> Result<MatmulKernel, String> {
+    let addr = compile_tensor_kernel(TensorKernel::Matmul(Some((n, k))), device)?;
+    let func = unsafe { std::mem::transmute::<u64, MatmulFn>(addr) };
+    Ok(MatmulKernel {
+        func,
+        dims: Some((n, k)),
+    })
+}
+
+/// Compile a layer norm kernel for the given `device`.
+///
+/// Mean and variance are summed in order, so only the normalization loop is
+/// vectorized.
+pub fn compile_layer_norm(device: Device) -> Result<LayerNormKernel, String> {
+    let addr = compile_tensor_kernel(TensorKernel::LayerNorm(None), device)?;
+    let func = unsafe { std::mem::transmute::<u64, LayerNormFn>(addr) };
+    Ok(LayerNormKernel { func, len: None })
+}
+
+/// Compile a layer norm kernel for rows of `len` values, e.g. the hidden
+/// size of a model.
+pub fn compile_layer_norm_for(len: usize, device: Device) -> Result<LayerNormKernel, String> {
+    let addr = compile_tensor_kernel(TensorKernel::LayerNorm(Some(len)), device)?;
+    let func = unsafe { std::mem::transmute::<u64, LayerNormFn>(addr) };
+    Ok(LayerNormKernel {
+        func,
+        len: Some(len),
+    })
+}
 
-        let compiled = CompiledKernelF32 { func };
-        KERNEL_CACHE_F32.lock().unwrap().insert(key, compiled);
-        Ok(compiled)
+fn compile_tensor_kernel(kernel: TensorKernel, device: Device) -> Result<u64, String> {
+    let key = format!("{}{:?}::{:?}", kernel.op(), kernel.shapes(), device);
+    if let Some(&addr) = TENSOR_KERNEL_CACHE.lock().unwrap().get(&key) {
+        return Ok(addr);
     }
+    let addr = unsafe { codegen::jit_tensor_kernel(kernel)? };
+    TENSOR_KERNEL_CACHE.lock().unwrap().insert(key, addr);
+    Ok(addr)
 }
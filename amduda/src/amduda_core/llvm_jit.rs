@@ -0,0 +1,605 @@
+//! LLVM code generation for the JIT compiler.
+//!
+//! Tensor kernels are optimized with the `default<O3>` pipeline for the host
+//! CPU so the inner loops are vectorized.  Their object code is kept in the
+//! on-disk [`KernelCache`] and linked with ORC, so later runs on the same host
+//! skip compilation.
+
+use std::{
+    ffi::{CStr, CString},
+    ptr,
+};
+
+use aurex_utils::kernel_cache::{KernelCache, KernelKey};
+use llvm_sys::{
+    analysis::*,
+    core::*,
+    error::*,
+    execution_engine::*,
+    orc2::{lljit::*, *},
+    prelude::*,
+    target::*,
+    target_machine::*,
+    transforms::pass_builder::*,
+    LLVMIntPredicate,
+};
+
+use super::jit_compiler::TensorKernel;
+
+fn c_string(s: &str) -> CString {
+    CString::new(s).expect("CString conversion failed")
+}
+
+/// Compile the `i32` kernel `source` and return the address of its entry
+/// point.
+pub(super) unsafe fn jit_kernel(source: &str) -> Result<u64, String> {
+    // Initialise LLVM for JIT usage.
+    LLVMLinkInMCJIT();
+    LLVM_InitializeNativeTarget();
+    LLVM_InitializeNativeAsmPrinter();
+
+    let context = LLVMContextCreate();
+    let module_name = c_string("kernel_module");
+    let module = LLVMModuleCreateWithName(module_name.as_ptr());
+    let builder = LLVMCreateBuilderInContext(context);
+
+    // i32 function: (i32, i32) -> i32
+    let i32_type = LLVMInt32TypeInContext(context);
+    let mut arg_types = [i32_type, i32_type];
+    let fn_type = LLVMFunctionType(i32_type, arg_types.as_mut_ptr(), 2, 0);
+    let fn_name = c_string("kernel");
+    let function = LLVMAddFunction(module, fn_name.as_ptr(), fn_type);
+    let entry_name = c_string("entry");
+    let entry = LLVMAppendBasicBlockInContext(context, function, entry_name.as_ptr());
+    LLVMPositionBuilderAtEnd(builder, entry);
+
+    let a = LLVMGetParam(function, 0);
+    let b = LLVMGetParam(function, 1);
+    let tmp_name = c_string("tmp");
+    let result = match source {
+        "add" => LLVMBuildAdd(builder, a, b, tmp_name.as_ptr()),
+        "mul" => LLVMBuildMul(builder, a, b, tmp_name.as_ptr()),
+        _ => {
+            LLVMDisposeBuilder(builder);
+            LLVMContextDispose(context);
+            return Err(format!("unsupported kernel: {}", source));
+        }
+    };
+
+    LLVMBuildRet(builder, result);
+
+    let mut engine: LLVMExecutionEngineRef = ptr::null_mut();
+    let mut error = ptr::null_mut();
+    if LLVMCreateExecutionEngineForModule(&mut engine, module, &mut error) != 0 {
+        let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
+        LLVMDisposeMessage(error);
+        return Err(msg);
+    }
+
+    let addr = LLVMGetFunctionAddress(engine, fn_name.as_ptr());
+    LLVMDisposeBuilder(builder);
+    // Note: module and context intentionally leaked for simplicity.
+    Ok(addr)
+}
+
+/// Compile the `f32` kernel `source` and return the address of its entry
+/// point.
+pub(super) unsafe fn jit_kernel_f32(source: &str) -> Result<u64, String> {
+    // Initialise LLVM for JIT usage.
+    LLVMLinkInMCJIT();
+    LLVM_InitializeNativeTarget();
+    LLVM_InitializeNativeAsmPrinter();
+
+    let context = LLVMContextCreate();
+    let module_name = c_string("kernel_module_f32");
+    let module = LLVMModuleCreateWithName(module_name.as_ptr());
+    let builder = LLVMCreateBuilderInContext(context);
+
+    // f32 function: (f32, f32) -> f32
+    let f32_type = LLVMFloatTypeInContext(context);
+    let mut arg_types = [f32_type, f32_type];
+    let fn_type = LLVMFunctionType(f32_type, arg_types.as_mut_ptr(), 2, 0);
+    let fn_name = c_string("kernel");
+    let function = LLVMAddFunction(module, fn_name.as_ptr(), fn_type);
+    let entry_name = c_string("entry");
+    let entry = LLVMAppendBasicBlockInContext(context, function, entry_name.as_ptr());
+    LLVMPositionBuilderAtEnd(builder, entry);
+
+    let a = LLVMGetParam(function, 0);
+    let b = LLVMGetParam(function, 1);
+    let tmp_name = c_string("tmp");
+    let result = match source {
+        "add_f32" => LLVMBuildFAdd(builder, a, b, tmp_name.as_ptr()),
+        "mul_f32" => LLVMBuildFMul(builder, a, b, tmp_name.as_ptr()),
+        _ => {
+            LLVMDisposeBuilder(builder);
+            LLVMContextDispose(context);
+            return Err(format!("unsupported kernel: {}", source));
+        }
+    };
+
+    LLVMBuildRet(builder, result);
+
+    let mut engine: LLVMExecutionEngineRef = ptr::null_mut();
+    let mut error = ptr::null_mut();
+    if LLVMCreateExecutionEngineForModule(&mut engine, module, &mut error) != 0 {
+        let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
+        LLVMDisposeMessage(error);
+        return Err(msg);
+    }
+
+    let addr = LLVMGetFunctionAddress(engine, fn_name.as_ptr());
+    LLVMDisposeBuilder(builder);
+    // Note: module and context intentionally leaked for simplicity.
+    Ok(addr)
+}
+
+/// Build the `f32` kernel described by `source` and return its textual LLVM
+/// IR instead of JIT compiling it.  Used to ship kernels inside compiled
+/// bundles.
+///
+/// Supported `source` strings: "add_f32" and "mul_f32".
+pub fn emit_kernel_ir_f32(source: &str) -> Result<String, String> {
+    unsafe {
+        let context = LLVMContextCreate();
+        let module_name = c_string("kernel_module_f32");
+        let module = LLVMModuleCreateWithNameInContext(module_name.as_ptr(), context);
+        let builder = LLVMCreateBuilderInContext(context);
+
+        let f32_type = LLVMFloatTypeInContext(context);
+        let mut arg_types = [f32_type, f32_type];
+        let fn_type = LLVMFunctionType(f32_type, arg_types.as_mut_ptr(), 2, 0);
+        let fn_name = c_string("kernel");
+        let function = LLVMAddFunction(module, fn_name.as_ptr(), fn_type);
+        let entry_name = c_string("entry");
+        let entry = LLVMAppendBasicBlockInContext(context, function, entry_name.as_ptr());
+        LLVMPositionBuilderAtEnd(builder, entry);
+
+        let a = LLVMGetParam(function, 0);
+        let b = LLVMGetParam(function, 1);
+        let tmp_name = c_string("tmp");
+        let result = match source {
+            "add_f32" => LLVMBuildFAdd(builder, a, b, tmp_name.as_ptr()),
+            "mul_f32" => LLVMBuildFMul(builder, a, b, tmp_name.as_ptr()),
+            _ => {
+                LLVMDisposeBuilder(builder);
+                LLVMDisposeModule(module);
+                LLVMContextDispose(context);
+                return Err(format!("unsupported kernel: {}", source));
+            }
+        };
+        LLVMBuildRet(builder, result);
+
+        let ir = LLVMPrintModuleToString(module);
+        let text = CStr::from_ptr(ir).to_string_lossy().into_owned();
+        LLVMDisposeMessage(ir);
+        LLVMDisposeBuilder(builder);
+        LLVMDisposeModule(module);
+        LLVMContextDispose(context);
+        Ok(text)
+    }
+}
+/// Load the tensor kernel `kernel` from the on-disk kernel cache, compiling
+/// it first on a miss, and return the address of its entry point.
+pub(super) unsafe fn jit_tensor_kernel(kernel: TensorKernel) -> Result<u64, String> {
+    LLVM_InitializeNativeTarget();
+    LLVM_InitializeNativeAsmPrinter();
+
+    let machine = host_target_machine()?;
+    let key =
+        KernelKey::new(kernel.op(), "cpu", &host_driver(machine)).with_shapes(&kernel.shapes());
+    let object = KernelCache::global().get_or_compile(&key, || emit_tensor_kernel(kernel, machine));
+    LLVMDisposeTargetMachine(machine);
+    load_object(&object?)
+}
+
+/// Compiler and CPU the kernels are generated for.  Objects are only reused
+/// on identical hosts built from the same version of this crate.
+unsafe fn host_driver(machine: LLVMTargetMachineRef) -> String {
+    let take = |msg: *mut libc::c_char| {
+        let text = CStr::from_ptr(msg).to_string_lossy().into_owned();
+        LLVMDisposeMessage(msg);
+        text
+    };
+    format!(
+        "amduda-{} llvm-15 {} {} {}",
+        env!("CARGO_PKG_VERSION"),
+        take(LLVMGetTargetMachineTriple(machine)),
+        take(LLVMGetTargetMachineCPU(machine)),
+        take(LLVMGetTargetMachineFeatureString(machine)),
+    )
+}
+
+/// Build and optimize the tensor kernel `kernel` and emit it as an object
+/// file.
+unsafe fn emit_tensor_kernel(
+    kernel: TensorKernel,
+    machine: LLVMTargetMachineRef,
+) -> Result<Vec<u8>, String> {
+    let context = LLVMContextCreate();
+    let module_name = c_string("tensor_kernel_module");
+    let module = LLVMModuleCreateWithNameInContext(module_name.as_ptr(), context);
+    let result = optimize_tensor_kernel(kernel, module, machine).and_then(|()| {
+        let mut buffer = ptr::null_mut();
+        let mut error = ptr::null_mut();
+        if LLVMTargetMachineEmitToMemoryBuffer(
+            machine,
+            module,
+            LLVMCodeGenFileType::LLVMObjectFile,
+            &mut error,
+            &mut buffer,
+        ) != 0
+        {
+            let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
+            LLVMDisposeMessage(error);
+            return Err(msg);
+        }
+        let start = LLVMGetBufferStart(buffer).cast::<u8>();
+        let object = std::slice::from_raw_parts(start, LLVMGetBufferSize(buffer)).to_vec();
+        LLVMDisposeMemoryBuffer(buffer);
+        Ok(object)
+    });
+    LLVMDisposeModule(module);
+    LLVMContextDispose(context);
+    result
+}
+
+/// Define `kernel` for `tensor_kernel` in `module`, verify it and run the
+/// `default<O3>` pipeline for `machine`.
+unsafe fn optimize_tensor_kernel(
+    tensor_kernel: TensorKernel,
+    module: LLVMModuleRef,
+    machine: LLVMTargetMachineRef,
+) -> Result<(), String> {
+    let triple = LLVMGetTargetMachineTriple(machine);
+    LLVMSetTarget(module, triple);
+    LLVMDisposeMessage(triple);
+    let layout = LLVMCreateTargetDataLayout(machine);
+    LLVMSetModuleDataLayout(module, layout);
+    let size_type = LLVMIntPtrTypeInContext(LLVMGetModuleContext(module), layout);
+    LLVMDisposeTargetData(layout);
+
+    let ir = KernelIr::new(module, size_type);
+    match tensor_kernel {
+        TensorKernel::Matmul(dims) => ir.matmul(dims),
+        TensorKernel::LayerNorm(len) => ir.layer_norm(len),
+    }
+    LLVMDisposeBuilder(ir.builder);
+
+    let mut error = ptr::null_mut();
+    let invalid = LLVMVerifyModule(
+        module,
+        LLVMVerifierFailureAction::LLVMReturnStatusAction,
+        &mut error,
+    );
+    let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
+    LLVMDisposeMessage(error);
+    if invalid != 0 {
+        return Err(msg);
+    }
+
+    let options = LLVMCreatePassBuilderOptions();
+    LLVMPassBuilderOptionsSetLoopVectorization(options, 1);
+    LLVMPassBuilderOptionsSetSLPVectorization(options, 1);
+    LLVMPassBuilderOptionsSetLoopUnrolling(options, 1);
+    let passes = c_string("default<O3>");
+    let failed = LLVMRunPasses(module, passes.as_ptr(), machine, options);
+    LLVMDisposePassBuilderOptions(options);
+    error_message(failed)
+}
+
+/// Link `object` into a new ORC JIT and return the address of its `kernel`.
+unsafe fn load_object(object: &[u8]) -> Result<u64, String> {
+    let mut jit = ptr::null_mut();
+    error_message(LLVMOrcCreateLLJIT(&mut jit, ptr::null_mut()))?;
+    let dylib = LLVMOrcLLJITGetMainJITDylib(jit);
+    // Lets the object call into the C runtime, e.g. `memset`.
+    let mut generator = ptr::null_mut();
+    error_message(LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess(
+        &mut generator,
+        LLVMOrcLLJITGetGlobalPrefix(jit),
+        None,
+        ptr::null_mut(),
+    ))?;
+    LLVMOrcJITDylibAddGenerator(dylib, generator);
+
+    let buffer_name = c_string("tensor_kernel");
+    let buffer = LLVMCreateMemoryBufferWithMemoryRangeCopy(
+        object.as_ptr().cast(),
+        object.len(),
+        buffer_name.as_ptr(),
+    );
+    error_message(LLVMOrcLLJITAddObjectFile(jit, dylib, buffer))?;
+    let mut addr = 0;
+    let fn_name = c_string("kernel");
+    error_message(LLVMOrcLLJITLookup(jit, &mut addr, fn_name.as_ptr()))?;
+    // Note: the JIT is intentionally leaked, the kernel lives as long as the
+    // process.
+    Ok(addr)
+}
+
+/// Turn an `LLVMErrorRef` into a `Result`, consuming the error.
+unsafe fn error_message(error: LLVMErrorRef) -> Result<(), String> {
+    if error.is_null() {
+        return Ok(());
+    }
+    let msg = LLVMGetErrorMessage(error);
+    let text = CStr::from_ptr(msg).to_string_lossy().into_owned();
+    LLVMDisposeErrorMessage(msg);
+    Err(text)
+}
+
+/// Target machine for the host CPU, including its vector extensions.
+unsafe fn host_target_machine() -> Result<LLVMTargetMachineRef, String> {
+    let triple = LLVMGetDefaultTargetTriple();
+    let mut target = ptr::null_mut();
+    let mut error = ptr::null_mut();
+    if LLVMGetTargetFromTriple(triple, &mut target, &mut error) != 0 {
+        let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
+        LLVMDisposeMessage(error);
+        LLVMDisposeMessage(triple);
+        return Err(msg);
+    }
+    let cpu = LLVMGetHostCPUName();
+    let features = LLVMGetHostCPUFeatures();
+    let machine = LLVMCreateTargetMachine(
+        target,
+        triple,
+        cpu,
+        features,
+        LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        LLVMRelocMode::LLVMRelocDefault,
+        LLVMCodeModel::LLVMCodeModelJITDefault,
+    );
+    LLVMDisposeMessage(triple);
+    LLVMDisposeMessage(cpu);
+    LLVMDisposeMessage(features);
+    Ok(machine)
+}
+
+/// IR builder for a tensor kernel named `kernel`.
+struct KernelIr {
+    context: LLVMContextRef,
+    module: LLVMModuleRef,
+    builder: LLVMBuilderRef,
+    f32_type: LLVMTypeRef,
+    size_type: LLVMTypeRef,
+    ptr_type: LLVMTypeRef,
+}
+
+impl KernelIr {
+    unsafe fn new(module: LLVMModuleRef, size_type: LLVMTypeRef) -> Self {
+        let context = LLVMGetModuleContext(module);
+        let f32_type = LLVMFloatTypeInContext(context);
+        Self {
+            context,
+            module,
+            builder: LLVMCreateBuilderInContext(context),
+            f32_type,
+            size_type,
+            ptr_type: LLVMPointerType(f32_type, 0),
+        }
+    }
+
+    /// Declare `void kernel(params...)` and position the builder in its entry
+    /// block.  Pointer parameters never alias, which lets the vectorizer skip
+    /// runtime overlap checks.
+    unsafe fn function(&self, params: &mut [LLVMTypeRef]) -> LLVMValueRef {
+        let void = LLVMVoidTypeInContext(self.context);
+        let fn_type = LLVMFunctionType(void, params.as_mut_ptr(), params.len() as u32, 0);
+        let fn_name = c_string("kernel");
+        let function = LLVMAddFunction(self.module, fn_name.as_ptr(), fn_type);
+        let noalias = self.enum_attribute("noalias");
+        for (i, &param) in params.iter().enumerate() {
+            if param == self.ptr_type {
+                LLVMAddAttributeAtIndex(function, i as u32 + 1, noalias);
+            }
+        }
+        let entry_name = c_string("entry");
+        let entry = LLVMAppendBasicBlockInContext(self.context, function, entry_name.as_ptr());
+        LLVMPositionBuilderAtEnd(self.builder, entry);
+        function
+    }
+
+    unsafe fn enum_attribute(&self, name: &str) -> LLVMAttributeRef {
+        let kind = LLVMGetEnumAttributeKindForName(name.as_ptr().cast(), name.len());
+        LLVMCreateEnumAttribute(self.context, kind, 0)
+    }
+
+    /// `out[i*n + j] += a[i*k + p] * b[p*n + j]` for all `i < m, p < k, j < n`.
+    /// `n` and `k` are constants when `dims` is set.
+    unsafe fn matmul(&self, dims: Option<(usize, usize)>) {
+        let (f, s) = (self.ptr_type, self.size_type);
+        let function = self.function(&mut [f, f, f, s, s, s]);
+        let [a, b, out, m, n, k] = [0, 1, 2, 3, 4, 5].map(|i| LLVMGetParam(function, i));
+        let n = self.dim(n, dims.map(|(n, _)| n));
+        let k = self.dim(k, dims.map(|(_, k)| k));
+        self.for_range(function, m, None, |i, _| {
+            self.for_range(function, k, None, |p, _| {
+                let a_ip = self.load(a, self.add(self.mul(i, k), p));
+                self.for_range(function, n, None, |j, _| {
+                    let idx = self.add(self.mul(i, n), j);
+                    let b_pj = self.load(b, self.add(self.mul(p, n), j));
+                    let product =
+                        LLVMBuildFMul(self.builder, a_ip, b_pj, c_string("prod").as_ptr());
+                    let sum = LLVMBuildFAdd(
+                        self.builder,
+                        self.load(out, idx),
+                        product,
+                        c_string("sum").as_ptr(),
+                    );
+                    self.store(out, idx, sum);
+                    None
+                });
+                None
+            });
+            None
+        });
+        LLVMBuildRetVoid(self.builder);
+    }
+
+    /// `out[i] = (x[i] - mean) / sqrt(var + eps) * gamma[i] + beta[i]`.
+    /// `len` is a constant when `fixed_len` is set.
+    unsafe fn layer_norm(&self, fixed_len: Option<usize>) {
+        let (f, s) = (self.ptr_type, self.size_type);
+        let function = self.function(&mut [f, f, f, f, s, self.f32_type]);
+        let [x, gamma, beta, out, len, eps] = [0, 1, 2, 3, 4, 5].map(|i| LLVMGetParam(function, i));
+        let len = self.dim(len, fixed_len);
+        let count = LLVMBuildUIToFP(self.builder, len, self.f32_type, c_string("count").as_ptr());
+        let zero = LLVMConstReal(self.f32_type, 0.0);
+
+        let total = self.for_range(function, len, Some(zero), |i, acc| {
+            Some(LLVMBuildFAdd(
+                self.builder,
+                acc?,
+                self.load(x, i),
+                c_string("total").as_ptr(),
+            ))
+        });
+        let mean = LLVMBuildFDiv(
+            self.builder,
+            total.unwrap(),
+            count,
+            c_string("mean").as_ptr(),
+        );
+        let squares = self.for_range(function, len, Some(zero), |i, acc| {
+            let d = LLVMBuildFSub(self.builder, self.load(x, i), mean, c_string("d").as_ptr());
+            let sq = LLVMBuildFMul(self.builder, d, d, c_string("sq").as_ptr());
+            Some(LLVMBuildFAdd(
+                self.builder,
+                acc?,
+                sq,
+                c_string("squares").as_ptr(),
+            ))
+        });
+        let var = LLVMBuildFDiv(
+            self.builder,
+            squares.unwrap(),
+            count,
+            c_string("var").as_ptr(),
+        );
+        let var_eps = LLVMBuildFAdd(self.builder, var, eps, c_string("var_eps").as_ptr());
+        let denom = self.sqrt(var_eps);
+
+        self.for_range(function, len, None, |i, _| {
+            let d = LLVMBuildFSub(self.builder, self.load(x, i), mean, c_string("d").as_ptr());
+            let norm = LLVMBuildFDiv(self.builder, d, denom, c_string("norm").as_ptr());
+            let scaled = LLVMBuildFMul(
+                self.builder,
+                norm,
+                self.load(gamma, i),
+                c_string("scaled").as_ptr(),
+            );
+            let shifted = LLVMBuildFAdd(
+                self.builder,
+                scaled,
+                self.load(beta, i),
+                c_string("shifted").as_ptr(),
+            );
+            self.store(out, i, shifted);
+            None
+        });
+        LLVMBuildRetVoid(self.builder);
+    }
+
+    /// Emit `for i in 0..end { acc = body(i, acc) }` and return the final
+    /// `acc`, if the loop carries one.
+    unsafe fn for_range(
+        &self,
+        function: LLVMValueRef,
+        end: LLVMValueRef,
+        init: Option<LLVMValueRef>,
+        body: impl FnOnce(LLVMValueRef, Option<LLVMValueRef>) -> Option<LLVMValueRef>,
+    ) -> Option<LLVMValueRef> {
+        let block = |name: &str| {
+            let name = c_string(name);
+            LLVMAppendBasicBlockInContext(self.context, function, name.as_ptr())
+        };
+        let (header, body_block, exit) = (block("loop"), block("body"), block("exit"));
+        let preheader = LLVMGetInsertBlock(self.builder);
+        LLVMBuildBr(self.builder, header);
+
+        LLVMPositionBuilderAtEnd(self.builder, header);
+        let index = LLVMBuildPhi(self.builder, self.size_type, c_string("i").as_ptr());
+        let acc = init.map(|_| LLVMBuildPhi(self.builder, self.f32_type, c_string("acc").as_ptr()));
+        let more = LLVMBuildICmp(
+            self.builder,
+            LLVMIntPredicate::LLVMIntULT,
+            index,
+            end,
+            c_string("more").as_ptr(),
+        );
+        LLVMBuildCondBr(self.builder, more, body_block, exit);
+
+        LLVMPositionBuilderAtEnd(self.builder, body_block);
+        let next_acc = body(index, acc);
+        let next = LLVMBuildNUWAdd(self.builder, index, self.size(1), c_string("next").as_ptr());
+        let latch = LLVMGetInsertBlock(self.builder);
+        LLVMBuildBr(self.builder, header);
+
+        let mut blocks = [preheader, latch];
+        let mut indices = [self.size(0), next];
+        LLVMAddIncoming(index, indices.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+        if let (Some(acc), Some(init), Some(next_acc)) = (acc, init, next_acc) {
+            let mut values = [init, next_acc];
+            LLVMAddIncoming(acc, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+        }
+        LLVMPositionBuilderAtEnd(self.builder, exit);
+        acc
+    }
+
+    /// The dimension passed as `param`, or the constant `fixed`.
+    unsafe fn dim(&self, param: LLVMValueRef, fixed: Option<usize>) -> LLVMValueRef {
+        fixed.map_or(param, |value| self.size(value as u64))
+    }
+
+    unsafe fn size(&self, value: u64) -> LLVMValueRef {
+        LLVMConstInt(self.size_type, value, 0)
+    }
+
+    unsafe fn add(&self, a: LLVMValueRef, b: LLVMValueRef) -> LLVMValueRef {
+        LLVMBuildNUWAdd(self.builder, a, b, c_string("idx").as_ptr())
+    }
+
+    unsafe fn mul(&self, a: LLVMValueRef, b: LLVMValueRef) -> LLVMValueRef {
+        LLVMBuildNUWMul(self.builder, a, b, c_string("idx").as_ptr())
+    }
+
+    unsafe fn element(&self, base: LLVMValueRef, index: LLVMValueRef) -> LLVMValueRef {
+        let mut indices = [index];
+        LLVMBuildInBoundsGEP2(
+            self.builder,
+            self.f32_type,
+            base,
+            indices.as_mut_ptr(),
+            1,
+            c_string("elem").as_ptr(),
+        )
+    }
+
+    unsafe fn load(&self, base: LLVMValueRef, index: LLVMValueRef) -> LLVMValueRef {
+        let elem = self.element(base, index);
+        LLVMBuildLoad2(self.builder, self.f32_type, elem, c_string("val").as_ptr())
+    }
+
+    unsafe fn store(&self, base: LLVMValueRef, index: LLVMValueRef, value: LLVMValueRef) {
+        LLVMBuildStore(self.builder, value, self.element(base, index));
+    }
+
+    unsafe fn sqrt(&self, value: LLVMValueRef) -> LLVMValueRef {
+        let name = "llvm.sqrt";
+        let id = LLVMLookupIntrinsicID(name.as_ptr().cast(), name.len());
+        let mut types = [self.f32_type];
+        let decl = LLVMGetIntrinsicDeclaration(self.module, id, types.as_mut_ptr(), 1);
+        let fn_type = LLVMIntrinsicGetType(self.context, id, types.as_mut_ptr(), 1);
+        let mut args = [value];
+        LLVMBuildCall2(
+            self.builder,
+            fn_type,
+            decl,
+            args.as_mut_ptr(),
+            1,
+            c_string("sqrt").as_ptr(),
+        )
+    }
+}
@@ -0,0 +1,115 @@
+//! Structured records of what a memory manager does with allocations.
+//!
+//! A [`MemoryManager`] emits a [`MemoryEvent`] for every allocation,
+//! migration, eviction and release, naming the tiers involved, the bytes
+//! moved, why it acted and how long it took.  Events go to the listeners
+//! registered with [`MemoryManager::add_event_listener`], are counted in the
+//! `aurex_memory_events_total` metrics, and with
+//! [`MemoryManager::with_profiler`] land in a [`Profiler`] as
+//! [`MemoryRecord`]s, so the tiering of a real workload can be replayed when
+//! debugging it.
+//!
+//! [`MemoryManager`]: super::memory_tiering::MemoryManager
+//! [`MemoryManager::add_event_listener`]: super::memory_tiering::MemoryManager::add_event_listener
+//! [`MemoryManager::with_profiler`]: super::memory_tiering::MemoryManager::with_profiler
+//! [`Profiler`]: aurex_utils::profiler::Profiler
+
+use std::time::Duration;
+
+use aurex_utils::profiler::MemoryRecord;
+
+use crate::amduda_core::memory_tiering::{AllocationId, MemoryTier};
+
+/// What happened to an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryEventKind {
+    /// The allocation was placed in a tier.
+    Allocate,
+    /// The allocation was moved on request, by a prefetch or on demand.
+    Migrate,
+    /// The allocation was demoted to make room in a full tier.
+    Evict,
+    /// The allocation was discarded because no slower tier had room.
+    Drop,
+    /// The allocation was released.
+    Free,
+}
+
+impl MemoryEventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryEventKind::Allocate => "allocate",
+            MemoryEventKind::Migrate => "migrate",
+            MemoryEventKind::Evict => "evict",
+            MemoryEventKind::Drop => "drop",
+            MemoryEventKind::Free => "free",
+        }
+    }
+}
+
+/// Why the manager acted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventReason {
+    /// A caller allocated, migrated or freed the allocation.
+    Request,
+    /// A prefetch hint promoted the allocation ahead of use.
+    Prefetch,
+    /// The allocation was acquired while on a slow tier.
+    Demand,
+    /// Room had to be made for another allocation.
+    Capacity,
+    /// A pressure handler gave the allocation up.
+    Pressure,
+}
+
+impl EventReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventReason::Request => "request",
+            EventReason::Prefetch => "prefetch",
+            EventReason::Demand => "demand",
+            EventReason::Capacity => "capacity",
+            EventReason::Pressure => "pressure",
+        }
+    }
+}
+
+/// One allocation, migration, eviction or release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEvent {
+    pub id: AllocationId,
+    pub kind: MemoryEventKind,
+    /// Tier the allocation left, `None` for new allocations.
+    pub from: Option<MemoryTier>,
+    /// Tier the allocation moved to, `None` once it is gone.
+    pub to: Option<MemoryTier>,
+    /// Bytes charged to the tiers, compressed if the data was compressed.
+    pub bytes: usize,
+    pub reason: EventReason,
+    /// Time taken, including copies, compression and spilling.
+    pub latency: Duration,
+}
+
+impl MemoryEvent {
+    /// Tier the event is accounted to: the destination, or the source of
+    /// releases.
+    pub fn tier(&self) -> Option<MemoryTier> {
+        self.to.or(self.from)
+    }
+
+    /// The event as recorded by a profiler.
+    pub fn record(&self) -> MemoryRecord {
+        MemoryRecord {
+            kind: self.kind.name(),
+            from: self.from.map(MemoryTier::name),
+            to: self.to.map(MemoryTier::name),
+            bytes: self.bytes as u64,
+            reason: self.reason.name(),
+            latency: self.latency,
+        }
+    }
+}
+
+/// Receives every event of a memory manager.  Listeners run while the
+/// manager is borrowed and must not call back into it.
+pub type MemoryEventListener = Box<dyn FnMut(&MemoryEvent) + Send>;
@@ -1,16 +1,98 @@
 //! Simulated multi-tier memory manager.
 //!
-//! The manager detects device capabilities and allocates memory across GPU,
+//! The manager probes device capabilities and allocates memory across GPU,
 //! CPU and NVMe tiers. When a tier is exhausted, data is migrated to the next
-//! slower tier to act as a simple cache hierarchy.
+//! slower tier to act as a simple cache hierarchy.  Migrations can be run
+//! asynchronously on a [`TransferEngine`] so copies overlap with compute.
+//!
+//! Every allocation is tracked in a table and identified by an
+//! [`AllocationId`], so migrations and eviction always act on whole tensors.
+//! Victims are chosen by a pluggable [`EvictionPolicy`] (LRU by default).
+//! Tensor buffers can be bound to their allocation with
+//! [`MemoryManager::bind`]; a manager attached to a [`Dispatcher`] then sees
+//! every op reading those buffers as an access.
+//!
+//!
+//! Allocations made with [`MemoryManager::allocate_data`] carry their bytes.
+//! With a [`SpillStore`] attached, those bytes are written to a spill file
+//! when the allocation is evicted to NVMe and restored when it is promoted
+//! again; without one the NVMe tier only does the accounting.  A
+//! [`Compressor`] additionally compresses those bytes whenever an allocation
+//! is demoted to a slower tier, and the tier is charged for the compressed
+//! size only.
+//!
+//! Components that know what comes next, such as the
+//! [`ProceduralFsm`](crate::amduda_core::procedural_fsm::ProceduralFsm) or
+//! the generation loop, call [`MemoryManager::prefetch`] so promotions from
+//! NVMe run on the transfer engine ahead of time, and
+//! [`MemoryManager::acquire`] right before the data is used.
+//!
+//! Tiers can be given [`Watermarks`].  When usage crosses the high mark, or
+//! an allocation would otherwise be dropped for lack of room, the registered
+//! pressure handlers are asked in turn to give memory back (shrink a KV
+//! cache, drop a prefix cache, reduce the batch size, ...) until usage is
+//! back at the low mark.
+//!
+//! A manager given a device with [`MemoryManager::with_device`] keeps the
+//! bytes of allocations on the GPU tier in [`DeviceBuffer`]s allocated by
+//! that backend, downloading them when the allocation is demoted and
+//! uploading them again when it is promoted.
+//!
+//! Every allocation, migration, eviction and release is reported as a
+//! [`MemoryEvent`] to the registered listeners, the metrics registry and an
+//! attached profiler, see [`memory_events`](crate::amduda_core::memory_events).
+//!
+//! [`Dispatcher`]: aurex_backend::Dispatcher
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use aurex_backend::dispatch::AccessObserver;
+use aurex_runtime::config::{MemoryConfig, Watermarks};
+use aurex_utils::metrics::Metrics;
+use aurex_utils::profiler::Profiler;
+
+use crate::amduda_core::compression::{CompressionStats, Compressor, DEFAULT_LEVEL};
+use crate::amduda_core::device_probe;
+use crate::amduda_core::eviction::{self, EvictionPolicy, Lru};
+use crate::amduda_core::memory_events::{
+    EventReason, MemoryEvent, MemoryEventKind, MemoryEventListener,
+};
+use crate::amduda_core::spill::SpillStore;
+use crate::amduda_core::transfer::{TransferEngine, TransferHandle, TransferKind};
+use crate::hal_backends::device_buffer::{BufferAllocator, DeviceBuffer};
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// Memory tiers ordered from fastest to slowest.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum MemoryTier {
     Gpu,
     Cpu,
     Nvme,
 }
 
+impl MemoryTier {
+    /// Parse a tier name as used in the `[memory]` section.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "gpu" => Some(MemoryTier::Gpu),
+            "cpu" => Some(MemoryTier::Cpu),
+            "nvme" => Some(MemoryTier::Nvme),
+            _ => None,
+        }
+    }
+
+    /// Name of the tier as used in the `[memory]` section and in metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryTier::Gpu => "gpu",
+            MemoryTier::Cpu => "cpu",
+            MemoryTier::Nvme => "nvme",
+        }
+    }
+}
+
 /// Runtime capabilities of the system.
 #[derive(Debug, Clone, Copy)]
 pub struct DeviceCapabilities {
@@ -25,36 +107,145 @@ pub struct DeviceCapabilities {
 }
 
 impl DeviceCapabilities {
-    /// Detects capabilities from environment variables.
+    /// Probes the capabilities of this machine, with the `AMDUDA_*`
+    /// environment variables overriding the probed values.
     pub fn detect() -> Self {
-        let has_gpu = std::env::var("AMDUDA_HAS_GPU")
-            .map(|v| v == "1")
-            .unwrap_or(false);
-        let has_nvme = std::env::var("AMDUDA_HAS_NVME")
-            .map(|v| v == "1")
-            .unwrap_or(false);
-        let gpu_mem = std::env::var("AMDUDA_GPU_MEM")
-            .ok()
-            .and_then(|v| v.parse::<usize>().ok())
-            .unwrap_or(1024);
-        let cpu_mem = std::env::var("AMDUDA_CPU_MEM")
-            .ok()
-            .and_then(|v| v.parse::<usize>().ok())
-            .unwrap_or(8 * 1024);
-        let nvme_mem = std::env::var("AMDUDA_NVME_MEM")
-            .ok()
-            .and_then(|v| v.parse::<usize>().ok())
-            .unwrap_or(usize::MAX);
-        Self {
-            has_gpu,
-            has_nvme,
-            gpu_mem,
-            cpu_mem,
+        Self::probe(&MemoryConfig::from_env())
+    }
+
+    /// Probes the values left unset in `config`: GPU memory through
+    /// ROCm or Vulkan, available RAM, NVMe presence and the free space of the
+    /// spill directory (the temp directory when unset).  Values that cannot
+    /// be probed use the defaults of [`from_config`](Self::from_config).
+    pub fn probe(config: &MemoryConfig) -> Self {
+        let gpu_mem = match (config.has_gpu, config.gpu_mem) {
+            (Some(false), _) => None,
+            (_, Some(bytes)) => Some(bytes),
+            _ => device_probe::gpu_memory(),
+        };
+        let nvme_mem = config.nvme_mem.or_else(|| {
+            let dir = config.spill_dir.clone().unwrap_or_else(std::env::temp_dir);
+            device_probe::free_space(&dir)
+        });
+        let probed = MemoryConfig {
+            has_gpu: config.has_gpu.or(Some(gpu_mem.is_some())),
+            has_nvme: config.has_nvme.or_else(|| Some(device_probe::has_nvme())),
+            gpu_mem: gpu_mem.or(config.gpu_mem),
+            cpu_mem: config.cpu_mem.or_else(device_probe::system_memory),
             nvme_mem,
+            ..config.clone()
+        };
+        let caps = Self::from_config(&probed);
+        tracing::debug!(?caps, "probed device capabilities");
+        caps
+    }
+
+    /// Capabilities described by the `[memory]` section of an
+    /// [`AurexConfig`](aurex_runtime::AurexConfig) without probing; unset
+    /// values use the defaults of a CPU-only machine with 8 KiB of memory.
+    pub fn from_config(config: &MemoryConfig) -> Self {
+        Self {
+            has_gpu: config.has_gpu.unwrap_or(false),
+            has_nvme: config.has_nvme.unwrap_or(false),
+            gpu_mem: config.gpu_mem.unwrap_or(1024),
+            cpu_mem: config.cpu_mem.unwrap_or(8 * 1024),
+            nvme_mem: config.nvme_mem.unwrap_or(usize::MAX),
         }
     }
 }
 
+/// Handle of an allocation made by a [`MemoryManager`].  Handles are never
+/// reused, so a freed or dropped allocation stays unknown to the manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AllocationId(pub(crate) u64);
+
+/// Table entry describing a live allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    pub bytes: usize,
+    pub tier: MemoryTier,
+    /// Bytes charged to `tier`, fewer than `bytes` while compressed.
+    pub stored: usize,
+}
+
+impl Allocation {
+    pub fn is_compressed(&self) -> bool {
+        self.stored < self.bytes
+    }
+}
+
+/// Counters of the prefetch hints handled by a [`MemoryManager`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefetchStats {
+    /// Allocations named in prefetch hints.
+    pub hints: u64,
+    /// Single-tier promotions started by hints.
+    pub migrations: u64,
+    /// Acquired allocations that had been prefetched.
+    pub hits: u64,
+    /// Acquired allocations that had to be promoted on demand.
+    pub misses: u64,
+}
+
+/// Memory pressure reported to pressure handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryPressure {
+    pub tier: MemoryTier,
+    pub used: usize,
+    pub limit: usize,
+    /// Bytes still to be released to get back to the low watermark.
+    pub excess: usize,
+    /// Set when an allocation is about to be dropped unless memory is
+    /// released.
+    pub critical: bool,
+}
+
+/// Reacts to memory pressure and returns the allocations it gave up, which
+/// the manager then frees.  Handlers that shed memory the manager does not
+/// track (e.g. by reducing the batch size) return nothing.  They run while
+/// the manager is borrowed and must not call back into it.
+pub type PressureHandler = Box<dyn FnMut(&MemoryPressure) -> Vec<AllocationId> + Send>;
+
+/// Counters of the memory pressure handled by a [`MemoryManager`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PressureStats {
+    /// Times the handlers were run.
+    pub events: u64,
+    /// Runs triggered by an allocation about to be dropped.
+    pub critical: u64,
+    /// Bytes freed from allocations returned by the handlers.
+    pub released: u64,
+}
+
+/// Pressure handlers in the order they are asked to release memory.
+#[derive(Default)]
+struct PressureHandlers(Vec<PressureHandler>);
+
+impl std::fmt::Debug for PressureHandlers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} pressure handlers", self.0.len())
+    }
+}
+
+/// Listeners notified of every memory event.
+#[derive(Default)]
+struct EventListeners(Vec<MemoryEventListener>);
+
+impl std::fmt::Debug for EventListeners {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} event listeners", self.0.len())
+    }
+}
+
+/// Backend allocating the buffers of GPU tier data.
+struct Device(Arc<dyn BufferAllocator>);
+
+impl std::fmt::Debug for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("buffer allocator")
+    }
+}
+
 /// Simple hierarchical memory manager.
 #[derive(Debug)]
 pub struct MemoryManager {
@@ -65,9 +256,31 @@ pub struct MemoryManager {
     gpu_used: usize,
     cpu_used: usize,
     nvme_used: usize,
-    gpu_cold: usize,
-    cpu_cold: usize,
-    nvme_cold: usize,
+    allocations: BTreeMap<AllocationId, Allocation>,
+    next_id: u64,
+    /// Buffers bound to allocations, keyed by address and length.
+    buffers: HashMap<(usize, usize), AllocationId>,
+    policy: Box<dyn EvictionPolicy>,
+    /// Bytes of resident allocations made with `allocate_data`, compressed
+    /// for allocations whose `stored` size is below their `bytes`.
+    contents: HashMap<AllocationId, Vec<u8>>,
+    /// Bytes of allocations with data on the GPU tier, when a device is set.
+    device_buffers: HashMap<AllocationId, DeviceBuffer>,
+    device: Option<Device>,
+    spill: Option<SpillStore>,
+    compressor: Option<Compressor>,
+    compression: CompressionStats,
+    /// Promotions started by `prefetch` and not yet acquired.
+    pending: HashMap<AllocationId, Vec<TransferHandle>>,
+    prefetch: PrefetchStats,
+    watermarks: HashMap<MemoryTier, Watermarks>,
+    handlers: PressureHandlers,
+    pressure: PressureStats,
+    listeners: EventListeners,
+    /// Reason reported for migrations, set while prefetching or acquiring.
+    reason: EventReason,
+    publish: bool,
+    transfers: Option<Arc<TransferEngine>>,
 }
 
 impl MemoryManager {
@@ -76,6 +289,39 @@ impl MemoryManager {
         Self::new_with_limits(caps, caps.gpu_mem, caps.cpu_mem, caps.nvme_mem)
     }
 
+    /// Create a manager for the capabilities, eviction policy, watermarks and
+    /// spill directory described by `config`, probing the capabilities it
+    /// leaves unset.  A spill directory that cannot be created is reported and
+    /// the NVMe tier stays simulated.
+    pub fn from_config(config: &MemoryConfig) -> Self {
+        let mut mgr = Self::new(DeviceCapabilities::probe(config));
+        if let Some(kind) = config.eviction {
+            mgr = mgr.with_eviction_policy(eviction::from_kind(kind));
+        }
+        for (name, &marks) in &config.watermarks {
+            match MemoryTier::from_name(name) {
+                Some(tier) => mgr = mgr.with_watermarks(tier, marks),
+                None => tracing::warn!(tier = %name, "ignoring watermarks of unknown tier"),
+            }
+        }
+        if let Some(codec) = config.compression {
+            let level = config.compression_level.unwrap_or(DEFAULT_LEVEL);
+            mgr = mgr.with_compressor(Compressor::new(codec).with_level(level));
+        }
+        if let Some(dir) = &config.spill_dir {
+            match SpillStore::new(dir) {
+                Ok(store) => {
+                    let direct = config.spill_direct.unwrap_or(false);
+                    mgr = mgr.with_spill_store(store.with_direct_io(direct));
+                }
+                Err(err) => {
+                    tracing::warn!(dir = %dir.display(), %err, "cannot use spill directory");
+                }
+            }
+        }
+        mgr
+    }
+
     /// Create a manager with explicit limits for each tier.
     pub fn new_with_limits(
         caps: DeviceCapabilities,
@@ -91,74 +337,417 @@ impl MemoryManager {
             gpu_used: 0,
             cpu_used: 0,
             nvme_used: 0,
-            gpu_cold: 0,
-            cpu_cold: 0,
-            nvme_cold: 0,
+            allocations: BTreeMap::new(),
+            next_id: 0,
+            buffers: HashMap::new(),
+            policy: Box::new(Lru::default()),
+            contents: HashMap::new(),
+            device_buffers: HashMap::new(),
+            device: None,
+            spill: None,
+            compressor: None,
+            compression: CompressionStats::default(),
+            pending: HashMap::new(),
+            prefetch: PrefetchStats::default(),
+            watermarks: HashMap::new(),
+            handlers: PressureHandlers::default(),
+            pressure: PressureStats::default(),
+            listeners: EventListeners::default(),
+            reason: EventReason::Request,
+            publish: true,
+            transfers: None,
         }
     }
 
     /// Allocates memory using a caching hierarchy. New allocations prefer the
-    /// fastest tier (GPU) and trigger migrations when space is required.
-    pub fn allocate(&mut self, bytes: usize) -> MemoryTier {
+    /// fastest tier (GPU) and evict other allocations when space is required.
+    pub fn allocate(&mut self, bytes: usize) -> AllocationId {
+        let start = Instant::now();
+        let id = AllocationId(self.next_id);
+        self.next_id += 1;
+        let tier = self.place(bytes);
+        *self.used_mut(tier) += bytes;
+        self.allocations.insert(
+            id,
+            Allocation {
+                bytes,
+                tier,
+                stored: bytes,
+            },
+        );
+        self.policy.on_insert(id);
+        tracing::trace!(?id, bytes, ?tier, "allocated");
+        self.emit(MemoryEvent {
+            id,
+            kind: MemoryEventKind::Allocate,
+            from: None,
+            to: Some(tier),
+            bytes,
+            reason: EventReason::Request,
+            latency: start.elapsed(),
+        });
+        self.check_watermarks();
+        self.publish_metrics();
+        id
+    }
+
+    /// Allocate room for `data` and keep it with the allocation, compressing
+    /// and spilling it right away if it only fits on NVMe.
+    pub fn allocate_data(&mut self, data: Vec<u8>) -> AllocationId {
+        let id = self.allocate(data.len());
+        self.contents.insert(id, data);
+        self.upload(id);
+        if self.tier(id) == Some(MemoryTier::Nvme) {
+            self.compress(id);
+            self.spill_out(id);
+            self.publish_metrics();
+        }
+        id
+    }
+
+    /// Bytes stored with `id`, downloaded from its device buffer or read
+    /// back from its spill file if it lives on NVMe, and decompressed if
+    /// needed.  `None` for unknown allocations and those made without data.
+    pub fn read(&mut self, id: AllocationId) -> io::Result<Option<Vec<u8>>> {
+        let Some(alloc) = self.allocation(id) else {
+            return Ok(None);
+        };
+        let buffer = self.device_buffers.get(&id);
+        let stored = match (self.contents.get(&id), buffer, &mut self.spill) {
+            (Some(data), _, _) => data.clone(),
+            (None, Some(buffer), _) => buffer.to_vec().map_err(io::Error::other)?,
+            (None, None, Some(spill)) if spill.contains(id) => spill.read(id)?,
+            _ => return Ok(None),
+        };
+        if !alloc.is_compressed() {
+            return Ok(Some(stored));
+        }
+        let compressor = self.compressor.expect("compressed allocations need a compressor");
+        self.compression.decompressed += 1;
+        compressor.decompress(&stored, alloc.bytes).map(Some)
+    }
+
+    /// Back the NVMe tier with spill files in `store`.
+    pub fn with_spill_store(mut self, store: SpillStore) -> Self {
+        self.spill = Some(store);
+        self
+    }
+
+    pub fn spill_store(&self) -> Option<&SpillStore> {
+        self.spill.as_ref()
+    }
+
+    /// Keep the data of allocations on the GPU tier in buffers allocated by
+    /// `device`.  Must be set before the first allocation.
+    pub fn with_device(mut self, device: Arc<dyn BufferAllocator>) -> Self {
+        debug_assert!(self.allocations.is_empty());
+        self.device = Some(Device(device));
+        self
+    }
+
+    /// Device buffer holding the data of `id` while it is on the GPU tier.
+    pub fn device_buffer(&self, id: AllocationId) -> Option<&DeviceBuffer> {
+        self.device_buffers.get(&id)
+    }
+
+    /// Compress the data of allocations demoted to a slower tier.  Must be
+    /// set before the first allocation.
+    pub fn with_compressor(mut self, compressor: Compressor) -> Self {
+        debug_assert!(self.allocations.is_empty());
+        self.compressor = Some(compressor);
+        self
+    }
+
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.compression
+    }
+
+    fn place(&mut self, bytes: usize) -> MemoryTier {
         if self.caps.has_gpu && bytes <= self.gpu_limit {
-            self.ensure_gpu_space(bytes);
-            self.gpu_used += bytes;
+            self.make_room(MemoryTier::Gpu, bytes, None);
             MemoryTier::Gpu
+        } else if bytes <= self.cpu_limit {
+            self.make_room(MemoryTier::Cpu, bytes, None);
+            MemoryTier::Cpu
         } else {
-            self.ensure_cpu_space(bytes);
-            if self.cpu_used + bytes <= self.cpu_limit {
-                self.cpu_used += bytes;
-                MemoryTier::Cpu
-            } else {
-                self.nvme_used += bytes;
-                MemoryTier::Nvme
-            }
+            MemoryTier::Nvme
         }
     }
 
-    /// Manually migrate data between tiers.
-    pub fn migrate(&mut self, from: MemoryTier, to: MemoryTier, bytes: usize) {
-        match (from, to) {
-            (MemoryTier::Gpu, MemoryTier::Cpu) => {
-                self.ensure_cpu_space(bytes);
-                let moved = bytes.min(self.gpu_used);
-                self.gpu_used -= moved;
-                self.cpu_used += moved;
-                let cold = moved.min(self.gpu_cold);
-                self.gpu_cold -= cold;
-                self.cpu_cold += cold;
+    /// Choose eviction victims with `policy` instead of LRU.  Must be set
+    /// before the first allocation.
+    pub fn with_eviction_policy(mut self, policy: Box<dyn EvictionPolicy>) -> Self {
+        debug_assert!(self.allocations.is_empty());
+        self.policy = policy;
+        self
+    }
+
+    /// Release an allocation.  Returns `false` if `id` is not live.
+    pub fn free(&mut self, id: AllocationId) -> bool {
+        let freed = self.release_for(id, EventReason::Request);
+        self.publish_metrics();
+        freed
+    }
+
+    /// Tier currently holding `id`, `None` once it was freed or dropped.
+    pub fn tier(&self, id: AllocationId) -> Option<MemoryTier> {
+        self.allocations.get(&id).map(|a| a.tier)
+    }
+
+    pub fn allocation(&self, id: AllocationId) -> Option<Allocation> {
+        self.allocations.get(&id).copied()
+    }
+
+    /// Live allocations, oldest first.
+    pub fn allocations(&self) -> impl Iterator<Item = (AllocationId, Allocation)> + '_ {
+        self.allocations.iter().map(|(&id, &a)| (id, a))
+    }
+
+    /// Move an allocation to `to`, evicting others from that tier as needed.
+    /// Migrations into a missing tier, or one the allocation cannot fit, are
+    /// ignored, as are promotions out of a spill file that cannot be read.
+    /// Returns the tier holding the allocation afterwards.
+    pub fn migrate(&mut self, id: AllocationId, to: MemoryTier) -> Option<MemoryTier> {
+        tracing::debug!(?id, ?to, "memory migration requested");
+        self.move_allocation(id, to);
+        self.check_watermarks();
+        self.publish_metrics();
+        self.tier(id)
+    }
+
+    /// Run migrations on `engine` instead of copying synchronously.
+    pub fn with_transfer_engine(mut self, engine: Arc<TransferEngine>) -> Self {
+        self.transfers = Some(engine);
+        self
+    }
+
+    /// Migrate like [`migrate`](Self::migrate) but copy the data on the
+    /// transfer engine.  The allocation table is updated immediately; the
+    /// returned handle completes once the copy has finished.  Without an
+    /// engine the handle is already complete.
+    pub fn migrate_async(&mut self, id: AllocationId, to: MemoryTier) -> TransferHandle {
+        tracing::debug!(?id, ?to, "async memory migration requested");
+        let kind = self
+            .tier(id)
+            .and_then(|from| TransferKind::between(from, to));
+        let spilled = self.contents.get(&id).cloned();
+        let moved = self.move_allocation(id, to);
+        self.check_watermarks();
+        self.publish_metrics();
+        match (&self.transfers, kind) {
+            (Some(engine), Some(kind)) if moved > 0 => {
+                let payload = spilled
+                    .or_else(|| self.contents.get(&id).cloned())
+                    .unwrap_or_else(|| vec![0; moved]);
+                engine.submit(kind, payload)
             }
-            (MemoryTier::Cpu, MemoryTier::Gpu) => {
-                if self.caps.has_gpu {
-                    self.ensure_gpu_space(bytes);
-                    let moved = bytes.min(self.cpu_used);
-                    self.cpu_used -= moved;
-                    self.gpu_used += moved;
-                    let cold = moved.min(self.cpu_cold);
-                    self.cpu_cold -= cold;
-                    self.gpu_cold += cold;
+            _ => TransferHandle::completed(kind, moved),
+        }
+    }
+
+    /// Start promoting `ids` to the fastest tier in the background, one tier
+    /// at a time (NVMe to CPU, then CPU to GPU), and mark them as used.
+    /// Returns the number of single-tier migrations started.
+    pub fn prefetch(&mut self, ids: &[AllocationId]) -> usize {
+        let target = self.fastest_tier();
+        let mut started = 0;
+        self.reason = EventReason::Prefetch;
+        for &id in ids {
+            self.prefetch.hints += 1;
+            self.touch(id);
+            while let Some(tier) = self.tier(id).filter(|&tier| tier > target) {
+                let next = if tier == MemoryTier::Nvme {
+                    MemoryTier::Cpu
+                } else {
+                    MemoryTier::Gpu
+                };
+                let handle = self.migrate_async(id, next);
+                if self.tier(id) != Some(next) {
+                    break;
                 }
+                self.pending.entry(id).or_default().push(handle);
+                started += 1;
+            }
+        }
+        self.reason = EventReason::Request;
+        self.prefetch.migrations += started as u64;
+        tracing::trace!(hints = ids.len(), started, "prefetch");
+        started
+    }
+
+    /// Make `id` ready for use: wait for its prefetch to finish or, without
+    /// one, promote it to the fastest tier now.  Returns its tier afterwards.
+    pub fn acquire(&mut self, id: AllocationId) -> Option<MemoryTier> {
+        let handles = self.pending.remove(&id);
+        let prefetched = handles.is_some();
+        for handle in handles.into_iter().flatten() {
+            handle.wait();
+        }
+        // A prefetched allocation may have been evicted again before use.
+        if self.tier(id).is_some_and(|tier| tier > self.fastest_tier()) {
+            tracing::debug!(?id, prefetched, "promoting allocation on demand");
+            self.prefetch.misses += 1;
+            self.reason = EventReason::Demand;
+            self.migrate(id, self.fastest_tier());
+            self.reason = EventReason::Request;
+        } else if prefetched {
+            self.prefetch.hits += 1;
+        }
+        self.touch(id);
+        self.tier(id)
+    }
+
+    pub fn prefetch_stats(&self) -> PrefetchStats {
+        self.prefetch
+    }
+
+    /// Run the pressure handlers when usage of `tier` exceeds
+    /// `marks.high` of its capacity, until it is back at `marks.low`.
+    pub fn with_watermarks(mut self, tier: MemoryTier, marks: Watermarks) -> Self {
+        debug_assert!(
+            marks.low <= marks.high,
+            "low watermark above high watermark"
+        );
+        self.watermarks.insert(tier, marks);
+        self
+    }
+
+    /// Register a pressure handler.  Handlers are asked in registration
+    /// order, so cheap ones (dropping caches) should come before those
+    /// degrading service (reducing the batch size).
+    pub fn add_pressure_handler(&mut self, handler: PressureHandler) {
+        self.handlers.0.push(handler);
+    }
+
+    /// Builder form of [`add_pressure_handler`](Self::add_pressure_handler).
+    pub fn with_pressure_handler(mut self, handler: PressureHandler) -> Self {
+        self.add_pressure_handler(handler);
+        self
+    }
+
+    pub fn pressure_stats(&self) -> PressureStats {
+        self.pressure
+    }
+
+    /// Relieve every tier used beyond its high watermark.
+    fn check_watermarks(&mut self) {
+        for tier in [MemoryTier::Gpu, MemoryTier::Cpu, MemoryTier::Nvme] {
+            let Some(marks) = self.watermarks.get(&tier).copied() else {
+                continue;
+            };
+            let limit = self.limit(tier) as f64;
+            let used = self.used(tier);
+            if used as f64 > marks.high * limit {
+                let excess = used.saturating_sub((marks.low * limit) as usize);
+                self.relieve(tier, excess, false, None);
+            }
+        }
+    }
+
+    /// Ask the pressure handlers in turn to release `excess` bytes of `tier`,
+    /// never freeing `keep`.
+    fn relieve(
+        &mut self,
+        tier: MemoryTier,
+        excess: usize,
+        critical: bool,
+        keep: Option<AllocationId>,
+    ) {
+        if self.handlers.0.is_empty() {
+            return;
+        }
+        self.pressure.events += 1;
+        self.pressure.critical += u64::from(critical);
+        let target = self.used(tier).saturating_sub(excess);
+        let mut handlers = std::mem::take(&mut self.handlers.0);
+        for handler in &mut handlers {
+            let used = self.used(tier);
+            if used <= target {
+                break;
             }
-            (MemoryTier::Cpu, MemoryTier::Nvme) => {
-                if self.caps.has_nvme {
-                    let moved = bytes.min(self.cpu_used);
-                    self.cpu_used -= moved;
-                    self.nvme_used += moved;
-                    let cold = moved.min(self.cpu_cold);
-                    self.cpu_cold -= cold;
-                    self.nvme_cold += cold;
+            let pressure = MemoryPressure {
+                tier,
+                used,
+                limit: self.limit(tier),
+                excess: used - target,
+                critical,
+            };
+            for id in handler(&pressure) {
+                let stored = self
+                    .allocation(id)
+                    .filter(|_| Some(id) != keep)
+                    .map(|a| a.stored);
+                if let Some(stored) = stored {
+                    self.release_for(id, EventReason::Pressure);
+                    self.pressure.released += stored as u64;
                 }
             }
-            (MemoryTier::Nvme, MemoryTier::Cpu) => {
-                let moved = bytes.min(self.nvme_used);
-                self.ensure_cpu_space(moved);
-                self.nvme_used -= moved;
-                self.cpu_used += moved;
-                 let cold = moved.min(self.nvme_cold);
-                 self.nvme_cold -= cold;
-                 self.cpu_cold += cold;
+        }
+        self.handlers.0 = handlers;
+        tracing::debug!(
+            ?tier,
+            critical,
+            used = self.used(tier),
+            target,
+            "relieved memory pressure"
+        );
+    }
+
+    fn fastest_tier(&self) -> MemoryTier {
+        if self.caps.has_gpu {
+            MemoryTier::Gpu
+        } else {
+            MemoryTier::Cpu
+        }
+    }
+
+    /// Move `id` to `to` if possible and return the number of bytes moved.
+    fn move_allocation(&mut self, id: AllocationId, to: MemoryTier) -> usize {
+        let start = Instant::now();
+        let Some(Allocation { bytes, tier, .. }) = self.allocation(id) else {
+            return 0;
+        };
+        let possible = match to {
+            MemoryTier::Gpu => self.caps.has_gpu && bytes <= self.gpu_limit,
+            MemoryTier::Cpu => bytes <= self.cpu_limit,
+            MemoryTier::Nvme => self.caps.has_nvme && self.nvme_used + bytes <= self.nvme_limit,
+        };
+        if tier == to || !possible {
+            return 0;
+        }
+        if tier == MemoryTier::Nvme {
+            if let Err(err) = self.spill_in(id) {
+                tracing::error!(?id, %err, "failed to restore spilled allocation, keeping it on NVMe");
+                return 0;
             }
-            _ => {}
+        }
+        let needed = self.size_in(id, to);
+        if to != MemoryTier::Nvme {
+            self.make_room(to, needed, Some(id));
+        }
+        self.relocate(id, to);
+        self.emit(MemoryEvent {
+            id,
+            kind: MemoryEventKind::Migrate,
+            from: Some(tier),
+            to: Some(to),
+            bytes: self.allocations[&id].stored,
+            reason: self.reason,
+            latency: start.elapsed(),
+        });
+        bytes
+    }
+
+    /// Bytes `id` will occupy once moved to `to`.  Demotions compress the
+    /// data up front so only the compressed size has to be made room for.
+    fn size_in(&mut self, id: AllocationId, to: MemoryTier) -> usize {
+        let alloc = self.allocations[&id];
+        if to > alloc.tier {
+            self.download(id);
+            self.compress(id);
+            self.allocations[&id].stored
+        } else {
+            alloc.bytes
         }
     }
 
@@ -167,100 +756,392 @@ impl MemoryManager {
         (self.gpu_used, self.cpu_used, self.nvme_used)
     }
 
-    fn ensure_gpu_space(&mut self, bytes: usize) {
-        if self.gpu_used + bytes <= self.gpu_limit {
-            return;
+    /// Detach the manager from the global metrics registry, e.g. for what-if
+    /// simulations that must not show up on dashboards.
+    pub fn without_metrics(mut self) -> Self {
+        self.publish = false;
+        self
+    }
+
+    /// Register a listener for the events of this manager.
+    pub fn add_event_listener(&mut self, listener: MemoryEventListener) {
+        self.listeners.0.push(listener);
+    }
+
+    /// Builder form of [`add_event_listener`](Self::add_event_listener).
+    pub fn with_event_listener(mut self, listener: MemoryEventListener) -> Self {
+        self.add_event_listener(listener);
+        self
+    }
+
+    /// Record every event of this manager into `profiler`.
+    pub fn with_profiler(self, profiler: Arc<Mutex<Profiler>>) -> Self {
+        self.with_event_listener(Box::new(move |event| {
+            profiler.lock().unwrap().record_memory_event(event.record());
+        }))
+    }
+
+    fn emit(&mut self, event: MemoryEvent) {
+        tracing::trace!(?event, "memory event");
+        if self.publish {
+            if let Some(tier) = event.tier() {
+                Metrics::global().record_memory_event(
+                    event.kind.name(),
+                    tier.name(),
+                    event.bytes as u64,
+                );
+            }
+        }
+        for listener in &mut self.listeners.0 {
+            listener(&event);
+        }
+    }
+
+    fn publish_metrics(&self) {
+        if self.publish {
+            self.report_metrics(Metrics::global());
+        }
+    }
+
+    /// Publish the current tier usage as `aurex_memory_tier_bytes` gauges.
+    /// Called automatically after allocations and migrations.
+    pub fn report_metrics(&self, metrics: &Metrics) {
+        metrics.set_tier_usage("gpu", self.gpu_used as u64);
+        metrics.set_tier_usage("cpu", self.cpu_used as u64);
+        metrics.set_tier_usage("nvme", self.nvme_used as u64);
+    }
+
+    fn limit(&self, tier: MemoryTier) -> usize {
+        match tier {
+            MemoryTier::Gpu => self.gpu_limit,
+            MemoryTier::Cpu => self.cpu_limit,
+            MemoryTier::Nvme => self.nvme_limit,
+        }
+    }
+
+    fn used(&self, tier: MemoryTier) -> usize {
+        match tier {
+            MemoryTier::Gpu => self.gpu_used,
+            MemoryTier::Cpu => self.cpu_used,
+            MemoryTier::Nvme => self.nvme_used,
+        }
+    }
+
+    fn used_mut(&mut self, tier: MemoryTier) -> &mut usize {
+        match tier {
+            MemoryTier::Gpu => &mut self.gpu_used,
+            MemoryTier::Cpu => &mut self.cpu_used,
+            MemoryTier::Nvme => &mut self.nvme_used,
+        }
+    }
+
+    /// Evict allocations other than `keep` from `tier` until `bytes` more fit.
+    /// The pressure handlers get one chance to release memory before a victim
+    /// would be dropped.
+    fn make_room(&mut self, tier: MemoryTier, bytes: usize, keep: Option<AllocationId>) {
+        let mut relieved = false;
+        while self.used(tier) + bytes > self.limit(tier) {
+            let allocations = &self.allocations;
+            let victim = self.policy.victim(&|id| {
+                Some(id) != keep && allocations.get(&id).is_some_and(|a| a.tier == tier)
+            });
+            match victim {
+                Some(victim) if !relieved && self.would_drop(victim) => {
+                    relieved = true;
+                    let excess = self.used(tier) + bytes - self.limit(tier);
+                    self.relieve(tier, excess, true, keep);
+                }
+                Some(victim) => self.evict(victim),
+                None => break,
+            }
         }
-        let needed = self.gpu_used + bytes - self.gpu_limit;
-        // migrate cold data first
-        if self.gpu_cold > 0 {
-            let cold_migrate = needed.min(self.gpu_cold);
-            if cold_migrate > 0 {
-                self.ensure_cpu_space(cold_migrate);
-                self.gpu_cold -= cold_migrate;
-                self.gpu_used -= cold_migrate;
-                self.cpu_used += cold_migrate;
-                self.cpu_cold += cold_migrate;
+    }
+
+    /// Whether evicting `id` would drop it because no slower tier has room.
+    /// Ignores compression, so it may report a drop that compressing would
+    /// have avoided.
+    fn would_drop(&self, id: AllocationId) -> bool {
+        let alloc = self.allocations[&id];
+        let nvme_room = self.caps.has_nvme && self.nvme_used + alloc.stored <= self.nvme_limit;
+        match alloc.tier {
+            MemoryTier::Gpu => alloc.stored > self.cpu_limit && !nvme_room,
+            MemoryTier::Cpu => !nvme_room,
+            MemoryTier::Nvme => true,
+        }
+    }
+
+    /// Move `id` to the next slower tier with room, or drop it when there is
+    /// none.
+    fn evict(&mut self, id: AllocationId) {
+        let start = Instant::now();
+        let tier = self.allocations[&id].tier;
+        let bytes = self.size_in(id, MemoryTier::Nvme);
+        let nvme_room = self.caps.has_nvme && self.nvme_used + bytes <= self.nvme_limit;
+        let target = match tier {
+            MemoryTier::Gpu if bytes <= self.cpu_limit => Some(MemoryTier::Cpu),
+            MemoryTier::Gpu | MemoryTier::Cpu if nvme_room => Some(MemoryTier::Nvme),
+            _ => None,
+        };
+        match target {
+            Some(to) => {
+                tracing::debug!(?id, bytes, from = ?tier, ?to, "evicting allocation");
+                if to == MemoryTier::Cpu {
+                    self.make_room(to, bytes, Some(id));
+                }
+                self.relocate(id, to);
+                self.emit(MemoryEvent {
+                    id,
+                    kind: MemoryEventKind::Evict,
+                    from: Some(tier),
+                    to: Some(to),
+                    bytes: self.allocations[&id].stored,
+                    reason: EventReason::Capacity,
+                    latency: start.elapsed(),
+                });
+            }
+            None => {
+                tracing::warn!(?id, bytes, ?tier, "dropping allocation, no slower tier");
+                let stored = self.allocations[&id].stored;
+                self.release(id);
+                self.emit(MemoryEvent {
+                    id,
+                    kind: MemoryEventKind::Drop,
+                    from: Some(tier),
+                    to: None,
+                    bytes: stored,
+                    reason: EventReason::Capacity,
+                    latency: start.elapsed(),
+                });
             }
         }
-        if self.gpu_used + bytes <= self.gpu_limit {
+    }
+
+    fn relocate(&mut self, id: AllocationId, to: MemoryTier) {
+        let Some(alloc) = self.allocations.get_mut(&id) else {
             return;
+        };
+        let (from, stored) = (alloc.tier, alloc.stored);
+        alloc.tier = to;
+        *self.used_mut(from) -= stored;
+        *self.used_mut(to) += stored;
+        // Spilled bytes were already restored by `move_allocation`.
+        if from == MemoryTier::Gpu {
+            self.download(id);
+        }
+        if to < from {
+            self.decompress(id);
+        }
+        if to == MemoryTier::Gpu {
+            self.upload(id);
+        }
+        if to == MemoryTier::Nvme {
+            self.spill_out(id);
         }
-        let remaining = self.gpu_used + bytes - self.gpu_limit;
-        self.ensure_cpu_space(remaining);
-        let migrated = remaining.min(self.gpu_used);
-        self.gpu_used -= migrated;
-        self.cpu_used += migrated;
     }
 
-    fn ensure_cpu_space(&mut self, bytes: usize) {
-        if self.cpu_used + bytes <= self.cpu_limit {
+    /// Move the resident bytes of `id` into a buffer on the device while it
+    /// is on the GPU tier.  They stay in host memory if the upload fails.
+    fn upload(&mut self, id: AllocationId) {
+        let (Some(device), Some(data)) = (&self.device, self.contents.get(&id)) else {
+            return;
+        };
+        let alloc = self.allocations[&id];
+        if alloc.tier != MemoryTier::Gpu || alloc.is_compressed() {
             return;
         }
-        let needed = self.cpu_used + bytes - self.cpu_limit;
-        if self.caps.has_nvme && self.nvme_used + needed <= self.nvme_limit {
-            // migrate cold bytes first
-            if self.cpu_cold > 0 {
-                let cold_migrate = needed.min(self.cpu_cold);
-                self.cpu_cold -= cold_migrate;
-                self.cpu_used -= cold_migrate;
-                self.nvme_used += cold_migrate;
-                self.nvme_cold += cold_migrate;
+        let uploaded = device.0.alloc_buffer(data.len()).and_then(|mut buffer| {
+            buffer.upload(0, data)?;
+            Ok(buffer)
+        });
+        match uploaded {
+            Ok(buffer) => {
+                self.contents.remove(&id);
+                self.device_buffers.insert(id, buffer);
             }
-            if self.cpu_used + bytes > self.cpu_limit {
-                let remaining = self.cpu_used + bytes - self.cpu_limit;
-                let migrated = remaining.min(self.cpu_used);
-                self.cpu_used -= migrated;
-                self.nvme_used += migrated;
+            Err(err) => {
+                tracing::warn!(?id, %err, "failed to upload allocation, keeping it in host memory");
             }
-        } else {
-            // drop cold bytes first
-            if self.cpu_cold > 0 {
-                let dropped = needed.min(self.cpu_cold);
-                self.cpu_cold -= dropped;
-                self.cpu_used -= dropped;
+        }
+    }
+
+    /// Move the bytes of `id` from its device buffer back to host memory.
+    fn download(&mut self, id: AllocationId) {
+        let Some(buffer) = self.device_buffers.get(&id) else {
+            return;
+        };
+        match buffer.to_vec() {
+            Ok(data) => {
+                self.device_buffers.remove(&id);
+                self.contents.insert(id, data);
+            }
+            Err(err) => tracing::error!(?id, %err, "failed to download allocation"),
+        }
+    }
+
+    /// Compress the resident bytes of `id`, charging its tier for the
+    /// compressed size.  Data that does not shrink is left alone.
+    fn compress(&mut self, id: AllocationId) {
+        let (Some(compressor), Some(data)) = (self.compressor, self.contents.get_mut(&id)) else {
+            return;
+        };
+        let alloc = self.allocations.get_mut(&id).expect("contents belong to live allocations");
+        if alloc.is_compressed() {
+            return;
+        }
+        let packed = match compressor.compress(data) {
+            Ok(packed) if packed.len() < data.len() => packed,
+            Ok(_) => {
+                self.compression.incompressible += 1;
+                return;
             }
-            if self.cpu_used + bytes > self.cpu_limit {
-                let remaining = self.cpu_used + bytes - self.cpu_limit;
-                let dropped = remaining.min(self.cpu_used);
-                self.cpu_used -= dropped;
+            Err(err) => {
+                tracing::warn!(?id, %err, "failed to compress allocation");
+                return;
+            }
+        };
+        tracing::trace!(
+            ?id,
+            bytes = data.len(),
+            compressed = packed.len(),
+            "compressed allocation"
+        );
+        self.compression.compressed += 1;
+        self.compression.bytes_in += data.len() as u64;
+        self.compression.bytes_out += packed.len() as u64;
+        let (tier, saved) = (alloc.tier, alloc.stored - packed.len());
+        alloc.stored = packed.len();
+        *data = packed;
+        *self.used_mut(tier) -= saved;
+    }
+
+    /// Undo [`compress`](Self::compress) for resident data.
+    fn decompress(&mut self, id: AllocationId) {
+        let (Some(compressor), Some(data)) = (self.compressor, self.contents.get_mut(&id)) else {
+            return;
+        };
+        let alloc = self.allocations.get_mut(&id).expect("contents belong to live allocations");
+        if !alloc.is_compressed() {
+            return;
+        }
+        match compressor.decompress(data, alloc.bytes) {
+            Ok(unpacked) => *data = unpacked,
+            Err(err) => {
+                tracing::error!(?id, %err, "failed to decompress allocation");
+                return;
             }
         }
+        self.compression.decompressed += 1;
+        let (tier, grown) = (alloc.tier, alloc.bytes - alloc.stored);
+        alloc.stored = alloc.bytes;
+        *self.used_mut(tier) += grown;
     }
-}
 
-impl MemoryManager {
-    /// Mark bytes in a tier as cold, making them candidates for eviction.
-    pub fn mark_cold(&mut self, tier: MemoryTier, bytes: usize) {
-        match tier {
-            MemoryTier::Gpu => {
-                let add = bytes.min(self.gpu_used - self.gpu_cold);
-                self.gpu_cold += add;
+    /// Move the bytes of `id` into its spill file.  They stay in memory if
+    /// the write fails.
+    fn spill_out(&mut self, id: AllocationId) {
+        let (Some(spill), Some(data)) = (&mut self.spill, self.contents.get(&id)) else {
+            return;
+        };
+        match spill.write(id, data) {
+            Ok(()) => {
+                self.contents.remove(&id);
             }
-            MemoryTier::Cpu => {
-                let add = bytes.min(self.cpu_used - self.cpu_cold);
-                self.cpu_cold += add;
+            Err(err) => {
+                tracing::warn!(?id, %err, "failed to spill allocation, keeping it in memory");
             }
-            MemoryTier::Nvme => {
-                let add = bytes.min(self.nvme_used - self.nvme_cold);
-                self.nvme_cold += add;
+        }
+    }
+
+    /// Restore the bytes of `id` from its spill file.  The file is kept if
+    /// it cannot be read, so the data is not lost with it.
+    fn spill_in(&mut self, id: AllocationId) -> io::Result<()> {
+        let Some(spill) = &mut self.spill else {
+            return Ok(());
+        };
+        if !spill.contains(id) {
+            return Ok(());
+        }
+        let data = spill.read(id)?;
+        spill.remove(id);
+        self.contents.insert(id, data);
+        Ok(())
+    }
+
+    /// Release `id` and report it freed for `reason`.
+    fn release_for(&mut self, id: AllocationId, reason: EventReason) -> bool {
+        let start = Instant::now();
+        let Some(alloc) = self.allocation(id) else {
+            return false;
+        };
+        self.release(id);
+        self.emit(MemoryEvent {
+            id,
+            kind: MemoryEventKind::Free,
+            from: Some(alloc.tier),
+            to: None,
+            bytes: alloc.stored,
+            reason,
+            latency: start.elapsed(),
+        });
+        true
+    }
+
+    fn release(&mut self, id: AllocationId) -> bool {
+        match self.allocations.remove(&id) {
+            Some(alloc) => {
+                *self.used_mut(alloc.tier) -= alloc.stored;
+                self.policy.on_remove(id);
+                self.buffers.retain(|_, bound| *bound != id);
+                self.contents.remove(&id);
+                self.device_buffers.remove(&id);
+                self.pending.remove(&id);
+                if let Some(spill) = &mut self.spill {
+                    spill.remove(id);
+                }
+                true
             }
+            None => false,
         }
     }
+}
 
-    /// Mark bytes in a tier as recently used (hot).
-    pub fn mark_hot(&mut self, tier: MemoryTier, bytes: usize) {
-        match tier {
-            MemoryTier::Gpu => self.gpu_cold = self.gpu_cold.saturating_sub(bytes),
-            MemoryTier::Cpu => self.cpu_cold = self.cpu_cold.saturating_sub(bytes),
-            MemoryTier::Nvme => self.nvme_cold = self.nvme_cold.saturating_sub(bytes),
+impl MemoryManager {
+    /// Record an access to `id` with the eviction policy.
+    pub fn touch(&mut self, id: AllocationId) {
+        if self.allocations.contains_key(&id) {
+            self.policy.on_access(id);
+        }
+    }
+
+    /// Associate the tensor stored in `data` with `id` so ops reading it
+    /// through a [`Dispatcher`](aurex_backend::Dispatcher) count as accesses.
+    pub fn bind(&mut self, id: AllocationId, data: &[f32]) {
+        if self.allocations.contains_key(&id) && !data.is_empty() {
+            self.buffers.insert((data.as_ptr() as usize, data.len()), id);
+        }
+    }
+
+    /// Allocation bound to `data` with [`bind`](Self::bind).
+    pub fn bound(&self, data: &[f32]) -> Option<AllocationId> {
+        self.buffers
+            .get(&(data.as_ptr() as usize, data.len()))
+            .copied()
+    }
+}
+
+impl AccessObserver for MemoryManager {
+    fn on_access(&mut self, data: &[f32]) {
+        if let Some(id) = self.bound(data) {
+            self.policy.on_access(id);
         }
     }
 }
 
-/// Convenience allocation using detected capabilities with default limits.
+/// Convenience placement query using detected capabilities with default
+/// limits.
 pub fn allocate(bytes: usize) -> MemoryTier {
     let caps = DeviceCapabilities::detect();
     let mut mgr = MemoryManager::new(caps);
-    mgr.allocate(bytes)
+    let id = mgr.allocate(bytes);
+    mgr.tier(id).expect("a new allocation is never evicted by its own placement")
 }
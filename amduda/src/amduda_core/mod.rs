@@ -1,7 +1,33 @@
-//! Core runtime components: tensor ops, procedural FSM, and memory tiering.
+//! Core runtime components: tensor ops, audio features, procedural FSM,
+//! memory tiering, device probing, NUMA placement and eviction policies.
+//!
+//! Only the tensor ops, audio features and procedural FSM are built for
+//! `wasm32`; the other components drive host memory, devices and threads.
+//! Without the `std` feature the tensor ops and the procedural FSM remain.
 
-#[cfg(feature = "jit")]
+#[cfg(feature = "std")]
+pub mod audio;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod compression;
+#[cfg(all(feature = "jit-cranelift", not(feature = "jit")))]
+mod cranelift_jit;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod device_probe;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod eviction;
+#[cfg(any(feature = "jit", feature = "jit-cranelift"))]
 pub mod jit_compiler;
+#[cfg(feature = "jit")]
+mod llvm_jit;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod memory_events;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
 pub mod memory_tiering;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod numa;
 pub mod procedural_fsm;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod spill;
 pub mod tensor_ops;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod transfer;
@@ -0,0 +1,162 @@
+//! NUMA placement of large CPU-tier buffers.
+//!
+//! The kernel places a page on the node of the thread touching it first.  A
+//! [`NumaBuffer`] is an anonymous mapping filled by worker threads pinned to
+//! the target nodes: either entirely by the CPUs of one node, which is also
+//! bound to it with `mbind` where permitted, or in one contiguous chunk per
+//! node so the memory bandwidth of every socket is used.  Buffers smaller than
+//! [`NUMA_MIN_BYTES`] are not worth the threads and stay ordinary vectors.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Deref;
+use std::path::Path;
+
+use aurex_utils::numa::{self, NumaNode, NumaTopology};
+use memmap2::MmapMut;
+
+/// Size from which weight buffers are placed explicitly; one huge page.
+pub const NUMA_MIN_BYTES: usize = 2 << 20;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Buffer whose pages were first touched on chosen NUMA nodes.
+pub struct NumaBuffer {
+    map: MmapMut,
+    len: usize,
+    /// Node of each chunk, in buffer order.
+    nodes: Vec<usize>,
+}
+
+impl NumaBuffer {
+    /// Copy `data` onto `node`, or across all nodes of `topology` when none
+    /// is given.
+    pub fn copy(data: &[u8], topology: &NumaTopology, node: Option<usize>) -> io::Result<Self> {
+        Self::fill(data.len(), topology, node, |offset, chunk| {
+            chunk.copy_from_slice(&data[offset..offset + chunk.len()]);
+            Ok(())
+        })
+    }
+
+    /// Read the file at `path` onto `node`, or across all nodes of
+    /// `topology` when none is given.  Every worker reads its own chunk, so
+    /// the file is never staged in memory of another node.
+    pub fn read_file(
+        path: impl AsRef<Path>,
+        topology: &NumaTopology,
+        node: Option<usize>,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let len = std::fs::metadata(path)?.len() as usize;
+        Self::fill(len, topology, node, |offset, chunk| {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(offset as u64))?;
+            file.read_exact(chunk)
+        })
+    }
+
+    /// NUMA node of each chunk of the buffer, in order.
+    pub fn nodes(&self) -> &[usize] {
+        &self.nodes
+    }
+
+    fn fill<F>(
+        len: usize,
+        topology: &NumaTopology,
+        node: Option<usize>,
+        fill: F,
+    ) -> io::Result<Self>
+    where
+        F: Fn(usize, &mut [u8]) -> io::Result<()> + Sync,
+    {
+        let targets: Vec<&NumaNode> = match node {
+            Some(id) => vec![topology.node(id).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown NUMA node {id}"),
+                )
+            })?],
+            None => topology.nodes().iter().collect(),
+        };
+        // Anonymous mappings cannot be empty.
+        let mut map = MmapMut::map_anon(len.max(1))?;
+        if let [target] = targets[..] {
+            bind(&mut map, target.id);
+        }
+        let chunk = len
+            .div_ceil(targets.len())
+            .next_multiple_of(PAGE_SIZE)
+            .max(PAGE_SIZE);
+        let mut nodes = Vec::new();
+        std::thread::scope(|scope| {
+            let fill = &fill;
+            let workers: Vec<_> = map[..len]
+                .chunks_mut(chunk)
+                .zip(&targets)
+                .enumerate()
+                .map(|(i, (part, target))| {
+                    nodes.push(target.id);
+                    scope.spawn(move || {
+                        if let Err(err) = numa::pin_current_thread(&target.cpus) {
+                            tracing::debug!(node = target.id, %err, "cannot pin worker");
+                        }
+                        fill(i * chunk, part)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|w| w.join().expect("placement worker panicked"))
+        })?;
+        tracing::debug!(bytes = len, ?nodes, "placed buffer on NUMA nodes");
+        Ok(Self { map, len, nodes })
+    }
+}
+
+impl Deref for NumaBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map[..self.len]
+    }
+}
+
+impl std::fmt::Debug for NumaBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NumaBuffer")
+            .field("len", &self.len)
+            .field("nodes", &self.nodes)
+            .finish()
+    }
+}
+
+/// Bind the pages of `map` to `node` so they cannot be placed elsewhere even
+/// under memory pressure.  Containers often forbid `mbind`; first touch by
+/// the pinned workers still places the pages then.
+#[cfg(target_os = "linux")]
+fn bind(map: &mut MmapMut, node: usize) {
+    const MPOL_BIND: libc::c_long = 2;
+    let bits = libc::c_ulong::BITS as usize;
+    let mut mask: Vec<libc::c_ulong> = vec![0; node / bits + 1];
+    mask[node / bits] |= 1 << (node % bits);
+    // The kernel ignores the last bit of `maxnode`.
+    let max_node = mask.len() * bits + 1;
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            map.as_mut_ptr(),
+            map.len(),
+            MPOL_BIND,
+            mask.as_ptr(),
+            max_node,
+            0,
+        )
+    };
+    if rc != 0 {
+        let err = io::Error::last_os_error();
+        tracing::debug!(node, %err, "mbind failed, relying on first touch");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind(_map: &mut MmapMut, _node: usize) {}
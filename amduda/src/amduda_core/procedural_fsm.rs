@@ -1,9 +1,59 @@
 //! Runtime-driven finite state machine coordinating token processing.
+//!
+//! The FSM knows which state comes next, so it can drive memory tiering:
+//! allocations declared with [`ProceduralFsm::needs`] are prefetched by the
+//! attached [`MemoryManager`] as soon as a state that may precede their user
+//! is entered, and acquired when their state is reached.
+//!
+//! Every transition is recorded in a journal together with the length of the
+//! KV cache after it.  [`ProceduralFsm::replay`] re-runs a journal to verify
+//! that a session is reproducible, and [`ProceduralFsm::rollback`] undoes the
+//! last steps and reports how far the KV cache has to be truncated, e.g. with
+//! [`LanguageModel::truncate`](crate::aurex_lm::tiny_lm::LanguageModel::truncate).
+//!
+//! The transitions, journal, replay and rollback only need `alloc`.  Without
+//! the `std` feature, and on `wasm32`, the FSM has no memory manager, no
+//! runtime stepping and no journal timestamps, and is driven by its own
+//! [`RuntimeEvent`] with the variants of `aurex_runtime::RuntimeEvent`.
 
-use aurex_runtime::{Runtime, RuntimeEvent};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::collections::HashMap;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::sync::{Arc, Mutex};
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::time::SystemTime;
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use aurex_runtime::Runtime;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub use aurex_runtime::RuntimeEvent;
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use crate::amduda_core::memory_tiering::{AllocationId, MemoryManager};
+
+/// Events driving the FSM in builds without `aurex_runtime`.
+#[cfg(not(all(feature = "std", not(target_arch = "wasm32"))))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeEvent {
+    /// A token was fetched; `cache_hit` skips the KV cache update.
+    TokenFetched { cache_hit: bool },
+    /// The KV cache has been updated.
+    CacheUpdated,
+    /// Attention computation has completed.
+    AttentionComputed,
+    /// A token has been emitted as output.
+    TokenEmitted,
+    /// Roll back to retry processing the current token.
+    Rollback,
+    /// An unrecoverable error occurred.
+    Error,
+}
 
 /// Possible states in the token processing pipeline.
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum State {
     /// Fetch the next token from the model.
     FetchToken,
@@ -17,9 +67,64 @@ pub enum State {
     Error,
 }
 
+impl State {
+    /// States that can directly follow this one.
+    pub fn successors(self) -> &'static [State] {
+        match self {
+            State::FetchToken => &[State::KVCacheUpdate, State::ComputeAttention],
+            State::KVCacheUpdate => &[State::ComputeAttention],
+            State::ComputeAttention => &[State::OutputToken],
+            State::OutputToken => &[State::FetchToken],
+            State::Error => &[],
+        }
+    }
+}
+
+/// A transition recorded by [`ProceduralFsm::on_event`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub from: State,
+    pub event: RuntimeEvent,
+    pub to: State,
+    /// Number of tokens in the KV cache after the transition.
+    pub kv_len: usize,
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    pub timestamp: SystemTime,
+}
+
+/// Error raised when a replayed journal does not reproduce its transitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayError {
+    /// Index of the first diverging entry.
+    pub index: usize,
+    pub expected: State,
+    pub actual: State,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "replay diverged at entry {}: expected {:?}, got {:?}",
+            self.index, self.expected, self.actual
+        )
+    }
+}
+
+impl core::error::Error for ReplayError {}
+
 /// A simple procedural state machine driven by [`RuntimeEvent`]s.
 pub struct ProceduralFsm {
     state: State,
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    memory: Option<Arc<Mutex<MemoryManager>>>,
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    needs: HashMap<State, Vec<AllocationId>>,
+    journal: Vec<JournalEntry>,
+    kv_len: usize,
+    /// KV cache length when the current token started, restored by
+    /// [`RuntimeEvent::Rollback`].
+    token_start: usize,
 }
 
 impl ProceduralFsm {
@@ -27,6 +132,13 @@ impl ProceduralFsm {
     pub fn new() -> Self {
         Self {
             state: State::FetchToken,
+            #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+            memory: None,
+            #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+            needs: HashMap::new(),
+            journal: Vec::new(),
+            kv_len: 0,
+            token_start: 0,
         }
     }
 
@@ -35,9 +147,21 @@ impl ProceduralFsm {
         self.state
     }
 
+    /// Number of tokens in the KV cache, counting one per
+    /// [`RuntimeEvent::CacheUpdated`] of a completed or in-flight token.
+    pub fn kv_len(&self) -> usize {
+        self.kv_len
+    }
+
+    /// Transitions recorded so far, oldest first.
+    pub fn journal(&self) -> &[JournalEntry] {
+        &self.journal
+    }
+
     /// Advance the FSM based on a runtime event.
     pub fn on_event(&mut self, event: RuntimeEvent) -> State {
-        self.state = match (self.state, event) {
+        let previous = self.state;
+        self.state = match (self.state, event.clone()) {
             (State::FetchToken, RuntimeEvent::TokenFetched { cache_hit }) => {
                 if cache_hit {
                     State::ComputeAttention
@@ -53,9 +177,104 @@ impl ProceduralFsm {
             // Unexpected events leave the state unchanged.
             (s, _) => s,
         };
+        match (previous, &event, self.state) {
+            (State::KVCacheUpdate, RuntimeEvent::CacheUpdated, _) => self.kv_len += 1,
+            // The retried token's cache entries are discarded.
+            (_, RuntimeEvent::Rollback, _) => self.kv_len = self.token_start,
+            _ => {}
+        }
+        if self.state == State::FetchToken {
+            self.token_start = self.kv_len;
+        }
+        self.journal.push(JournalEntry {
+            from: previous,
+            event,
+            to: self.state,
+            kv_len: self.kv_len,
+            #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+            timestamp: SystemTime::now(),
+        });
+        if self.state != previous {
+            self.on_enter(self.state);
+        }
         self.state
     }
 
+    /// Apply the events of `journal` to a new FSM, checking that every
+    /// transition reaches the recorded state.
+    pub fn replay(journal: &[JournalEntry]) -> Result<Self, ReplayError> {
+        let mut fsm = Self::new();
+        for (index, entry) in journal.iter().enumerate() {
+            let actual = fsm.on_event(entry.event.clone());
+            if actual != entry.to {
+                return Err(ReplayError {
+                    index,
+                    expected: entry.to,
+                    actual,
+                });
+            }
+        }
+        Ok(fsm)
+    }
+
+    /// Undo the last `steps` journal entries (all of them if there are
+    /// fewer), restoring the state before them.  Returns the KV cache length
+    /// at that point, to which the model's cache must be truncated.
+    pub fn rollback(&mut self, steps: usize) -> usize {
+        let keep = self.journal.len().saturating_sub(steps);
+        let Some(first_undone) = self.journal.get(keep) else {
+            return self.kv_len;
+        };
+        let state = first_undone.from;
+        self.journal.truncate(keep);
+        self.kv_len = self.journal.last().map_or(0, |entry| entry.kv_len);
+        // Recompute where the current token started from the kept entries.
+        self.token_start = self
+            .journal
+            .iter()
+            .rev()
+            .find(|entry| entry.to == State::FetchToken)
+            .map_or(0, |entry| entry.kv_len);
+        if state != self.state {
+            self.state = state;
+            self.on_enter(state);
+        }
+        self.kv_len
+    }
+}
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+impl ProceduralFsm {
+    /// Send prefetch hints for the allocations declared with
+    /// [`needs`](Self::needs) to `memory`.
+    pub fn with_memory_manager(mut self, memory: Arc<Mutex<MemoryManager>>) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    /// Declare that `state` works on `ids`, e.g. the KV blocks read by
+    /// [`State::ComputeAttention`].  Replaces earlier declarations.
+    pub fn needs(&mut self, state: State, ids: impl IntoIterator<Item = AllocationId>) {
+        self.needs.insert(state, ids.into_iter().collect());
+    }
+
+    /// Make what `state` needs ready, then prefetch what the possible next
+    /// states need.
+    fn on_enter(&self, state: State) {
+        let Some(memory) = &self.memory else {
+            return;
+        };
+        let mut memory = memory.lock().unwrap();
+        for &id in self.needs.get(&state).into_iter().flatten() {
+            memory.acquire(id);
+        }
+        for next in state.successors() {
+            if let Some(ids) = self.needs.get(next) {
+                memory.prefetch(ids);
+            }
+        }
+    }
+
     /// Convenience helper that applies an event to the FSM and returns the next
     /// runtime event scheduled by [`Runtime::step`].
     pub async fn step_with_runtime(
@@ -115,3 +334,9 @@ impl ProceduralFsm {
             .await
     }
 }
+
+#[cfg(not(all(feature = "std", not(target_arch = "wasm32"))))]
+impl ProceduralFsm {
+    /// Without a memory manager there is nothing to prepare.
+    fn on_enter(&self, _state: State) {}
+}
@@ -0,0 +1,225 @@
+//! Spill files backing the NVMe memory tier.
+//!
+//! A [`SpillStore`] owns a directory in which allocations evicted to NVMe are
+//! written, one file per allocation, and read back when they are accessed or
+//! promoted again.  Writes can bypass the page cache
+//! with `O_DIRECT` on Linux; filesystems rejecting it (e.g. tmpfs) silently
+//! fall back to buffered IO.  Bytes and time spent on spill IO are counted in
+//! [`SpillStats`] and, when attached, in a [`Profiler`].
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use aurex_utils::profiler::{Profiler, SpillStats};
+
+use crate::amduda_core::memory_tiering::AllocationId;
+
+/// Alignment of buffers and lengths written with `O_DIRECT`.
+pub const DIRECT_IO_ALIGN: usize = 4096;
+
+static NEXT_STORE: AtomicU64 = AtomicU64::new(0);
+
+/// Directory of spill files, one per allocation on the NVMe tier.
+pub struct SpillStore {
+    dir: PathBuf,
+    /// Distinguishes stores of one process sharing a directory.
+    prefix: String,
+    direct: bool,
+    files: HashMap<AllocationId, usize>,
+    stats: SpillStats,
+    profiler: Option<Arc<Mutex<Profiler>>>,
+}
+
+impl SpillStore {
+    /// Create a store writing into `dir`, creating the directory if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let prefix = format!(
+            "amduda-{}-{}",
+            std::process::id(),
+            NEXT_STORE.fetch_add(1, Ordering::Relaxed)
+        );
+        Ok(Self {
+            dir,
+            prefix,
+            direct: false,
+            files: HashMap::new(),
+            stats: SpillStats::default(),
+            profiler: None,
+        })
+    }
+
+    /// Write spill files with `O_DIRECT` where supported.
+    pub fn with_direct_io(mut self, direct: bool) -> Self {
+        self.direct = direct;
+        self
+    }
+
+    /// Also record spill traffic into `profiler`.
+    pub fn with_profiler(mut self, profiler: Arc<Mutex<Profiler>>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether writes currently bypass the page cache.
+    pub fn direct_io(&self) -> bool {
+        self.direct
+    }
+
+    /// Spill file of `id`, whether or not it exists.
+    pub fn path(&self, id: AllocationId) -> PathBuf {
+        self.dir.join(format!("{}-{}.spill", self.prefix, id.0))
+    }
+
+    pub fn contains(&self, id: AllocationId) -> bool {
+        self.files.contains_key(&id)
+    }
+
+    /// Bytes and time spent on spill IO by this store.
+    pub fn stats(&self) -> SpillStats {
+        self.stats
+    }
+
+    /// Write `data` as the spill file of `id`, replacing an earlier one.
+    pub fn write(&mut self, id: AllocationId, data: &[u8]) -> io::Result<()> {
+        let path = self.path(id);
+        let start = Instant::now();
+        if self.direct {
+            match write_direct(&path, data) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                    tracing::debug!(
+                        dir = %self.dir.display(),
+                        "O_DIRECT unsupported, using buffered spill IO"
+                    );
+                    self.direct = false;
+                    write_buffered(&path, data)?;
+                }
+                Err(err) => return Err(err),
+            }
+        } else {
+            write_buffered(&path, data)?;
+        }
+        let elapsed = start.elapsed();
+        self.files.insert(id, data.len());
+        self.stats.bytes_written += data.len() as u64;
+        self.stats.write_time += elapsed;
+        if let Some(profiler) = &self.profiler {
+            profiler
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .record_spill_write(data.len() as u64, elapsed);
+        }
+        tracing::trace!(?id, bytes = data.len(), "spilled allocation");
+        Ok(())
+    }
+
+    /// Read the spill file of `id`.
+    pub fn read(&mut self, id: AllocationId) -> io::Result<Vec<u8>> {
+        let Some(&len) = self.files.get(&id) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("allocation {id:?} is not spilled"),
+            ));
+        };
+        let start = Instant::now();
+        let mut data = Vec::with_capacity(len);
+        File::open(self.path(id))?
+            .take(len as u64)
+            .read_to_end(&mut data)?;
+        if data.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("spill file of {id:?} is shorter than {len} bytes"),
+            ));
+        }
+        let elapsed = start.elapsed();
+        self.stats.bytes_read += len as u64;
+        self.stats.read_time += elapsed;
+        if let Some(profiler) = &self.profiler {
+            profiler
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .record_spill_read(len as u64, elapsed);
+        }
+        Ok(data)
+    }
+
+    /// Delete the spill file of `id`.  Returns `false` if there was none.
+    pub fn remove(&mut self, id: AllocationId) -> bool {
+        if self.files.remove(&id).is_none() {
+            return false;
+        }
+        if let Err(err) = fs::remove_file(self.path(id)) {
+            tracing::warn!(?id, %err, "failed to remove spill file");
+        }
+        true
+    }
+}
+
+impl std::fmt::Debug for SpillStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpillStore")
+            .field("dir", &self.dir)
+            .field("direct", &self.direct)
+            .field("files", &self.files.len())
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl Drop for SpillStore {
+    fn drop(&mut self) {
+        let ids: Vec<AllocationId> = self.files.keys().copied().collect();
+        for id in ids {
+            self.remove(id);
+        }
+    }
+}
+
+fn write_buffered(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)
+}
+
+/// Write through an aligned bounce buffer padded to [`DIRECT_IO_ALIGN`], then
+/// trim the file to the real length.
+#[cfg(target_os = "linux")]
+fn write_direct(path: &Path, data: &[u8]) -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)?;
+    let padded = data.len().next_multiple_of(DIRECT_IO_ALIGN);
+    if padded > 0 {
+        let mut bounce = vec![0u8; padded + DIRECT_IO_ALIGN];
+        let offset = bounce.as_ptr().align_offset(DIRECT_IO_ALIGN);
+        let aligned = &mut bounce[offset..offset + padded];
+        aligned[..data.len()].copy_from_slice(data);
+        file.write_all(aligned)?;
+    }
+    file.set_len(data.len() as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_direct(_path: &Path, _data: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "O_DIRECT is only supported on Linux",
+    ))
+}
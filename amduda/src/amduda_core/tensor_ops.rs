@@ -1,4 +1,39 @@
 //! Trait-based tensor operations with a CPU fallback implementation.
+//!
+//! The 1D convolution and GELU used by the convolutional front-ends of
+//! speech models are provided methods with host implementations, so every
+//! backend supports them; backends with faster kernels override them.
+//!
+//! Only `alloc` is used, so the ops and [`CpuFallback`] are also built
+//! without the `std` feature.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// Shape of a 1D convolution over `in_channels x length` inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conv1dShape {
+    pub in_channels: usize,
+    pub out_channels: usize,
+    pub kernel: usize,
+    pub stride: usize,
+    /// Zeros added before and after every input channel.
+    pub padding: usize,
+}
+
+impl Conv1dShape {
+    /// Output length for inputs of `length` samples per channel.
+    pub fn output_len(&self, length: usize) -> usize {
+        let padded = length + 2 * self.padding;
+        if padded < self.kernel {
+            return 0;
+        }
+        (padded - self.kernel) / self.stride.max(1) + 1
+    }
+}
 
 /// Common tensor operations used across backends.
 pub trait TensorOps {
@@ -19,6 +54,60 @@ pub trait TensorOps {
 
     /// Layer normalization applied to a 1D tensor.
     fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32>;
+
+    /// 1D convolution of `input` (`in_channels x length`) with `weight`
+    /// (`out_channels x in_channels x kernel`), adding `bias` to every output
+    /// channel.  Returns `out_channels x shape.output_len(length)` values.
+    fn conv1d(&self, input: &[f32], weight: &[f32], bias: &[f32], shape: Conv1dShape) -> Vec<f32> {
+        let (cols, out_len) = im2col(input, shape);
+        let k = shape.in_channels * shape.kernel;
+        let mut out = vec![0.0; shape.out_channels * out_len];
+        for (o, row) in out.chunks_exact_mut(out_len.max(1)).enumerate() {
+            let filter = &weight[o * k..(o + 1) * k];
+            for (t, v) in row.iter_mut().enumerate() {
+                let dot: f32 = filter
+                    .iter()
+                    .enumerate()
+                    .map(|(p, w)| w * cols[p * out_len + t])
+                    .sum();
+                *v = bias[o] + dot;
+            }
+        }
+        out
+    }
+
+    /// GELU activation, using the tanh approximation.
+    fn gelu(&self, x: &[f32]) -> Vec<f32> {
+        x.iter().map(|&v| gelu(v)).collect()
+    }
+}
+
+/// GELU of a single value, see [`TensorOps::gelu`].
+pub fn gelu(x: f32) -> f32 {
+    const SQRT_2_OVER_PI: f32 = 0.797_884_6;
+    0.5 * x * (1.0 + (SQRT_2_OVER_PI * (x + 0.044_715 * x * x * x)).tanh())
+}
+
+/// Unfold `input` (`in_channels x length`) into the
+/// `(in_channels * kernel) x output_len` matrix whose column `t` holds the
+/// values the kernel covers at output `t`, so a convolution becomes a matmul
+/// with the `out_channels x (in_channels * kernel)` weights.  Returns the
+/// matrix and the output length.
+pub fn im2col(input: &[f32], shape: Conv1dShape) -> (Vec<f32>, usize) {
+    let length = input.len() / shape.in_channels.max(1);
+    let out_len = shape.output_len(length);
+    let stride = shape.stride.max(1);
+    let mut cols = vec![0.0; shape.in_channels * shape.kernel * out_len];
+    for (row, values) in cols.chunks_exact_mut(out_len.max(1)).enumerate() {
+        let (c, tap) = (row / shape.kernel, row % shape.kernel);
+        let channel = &input[c * length..(c + 1) * length];
+        for (t, v) in values.iter_mut().enumerate() {
+            // Position in the unpadded input; padding reads as zero.
+            let pos = (t * stride + tap).wrapping_sub(shape.padding);
+            *v = channel.get(pos).copied().unwrap_or(0.0);
+        }
+    }
+    (cols, out_len)
 }
 
 /// Software fallback used by CPU and emulated by other backends in tests.
@@ -66,7 +155,7 @@ impl TensorOps for CpuFallback {
     }
 
     fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
-        #[cfg(feature = "jit")]
+        #[cfg(any(feature = "jit", feature = "jit-cranelift"))]
         {
             use super::jit_compiler::{compile_kernel_f32, Device};
             if let Ok(kern) = compile_kernel_f32("mul_f32", Device::CPU) {
@@ -0,0 +1,272 @@
+//! Asynchronous host/device transfers through pinned staging buffers.
+//!
+//! A [`TransferEngine`] owns a copy thread that plays the role of the
+//! backend's copy queue: callers submit a transfer, keep computing and only
+//! block in [`TransferHandle::wait`] once they need the data.  On device
+//! backends (ROCm, Vulkan, OpenCL, SYCL) copies are staged through a
+//! page-locked host buffer in chunks of [`STAGING_CHUNK`] bytes, which is what
+//! allows the DMA engine to run concurrently with kernels.  With the `rocm`
+//! feature the staging buffer is registered with HIP; Vulkan staging buffers
+//! are host visible so a plain copy into them suffices.  Host-only backends
+//! copy directly without staging.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+use once_cell::sync::Lazy;
+
+#[cfg(feature = "rocm")]
+use hip_runtime_sys as hip;
+
+use crate::amduda_core::memory_tiering::MemoryTier;
+use crate::hal_backends::{self, BackendKind};
+
+/// Size of the pinned staging buffer used for device copies.
+pub const STAGING_CHUNK: usize = 1 << 20;
+
+static SHARED: Lazy<Arc<TransferEngine>> =
+    Lazy::new(|| Arc::new(TransferEngine::for_backend(hal_backends::select_backend())));
+
+/// Direction of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferKind {
+    HostToDevice,
+    DeviceToHost,
+    NvmeToHost,
+    HostToNvme,
+}
+
+impl TransferKind {
+    /// Direction of a migration between two tiers, `None` if the memory
+    /// manager does not copy between them.
+    pub fn between(from: MemoryTier, to: MemoryTier) -> Option<Self> {
+        match (from, to) {
+            (MemoryTier::Cpu, MemoryTier::Gpu) => Some(TransferKind::HostToDevice),
+            (MemoryTier::Gpu, MemoryTier::Cpu) => Some(TransferKind::DeviceToHost),
+            (MemoryTier::Nvme, MemoryTier::Cpu) => Some(TransferKind::NvmeToHost),
+            (MemoryTier::Cpu, MemoryTier::Nvme) => Some(TransferKind::HostToNvme),
+            _ => None,
+        }
+    }
+}
+
+/// Totals of the transfers completed by an engine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub transfers: u64,
+    pub bytes: u64,
+    /// Bytes that went through the pinned staging buffer.
+    pub staged_bytes: u64,
+}
+
+/// Page-locked host memory.
+pub(crate) struct PinnedBuffer {
+    pub(crate) data: Vec<u8>,
+}
+
+impl PinnedBuffer {
+    pub(crate) fn new(len: usize) -> Self {
+        #[allow(unused_mut)]
+        let mut data = vec![0u8; len];
+        #[cfg(feature = "rocm")]
+        unsafe {
+            let _ = hip::hipHostRegister(data.as_mut_ptr() as *mut std::ffi::c_void, len, 0);
+        }
+        Self { data }
+    }
+}
+
+#[cfg(feature = "rocm")]
+impl Drop for PinnedBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = hip::hipHostUnregister(self.data.as_mut_ptr() as *mut std::ffi::c_void);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Completion {
+    result: Mutex<Option<Vec<u8>>>,
+    ready: Condvar,
+}
+
+impl Completion {
+    fn finish(&self, data: Vec<u8>) {
+        *self.result.lock().unwrap() = Some(data);
+        self.ready.notify_all();
+    }
+}
+
+/// Pending transfer returned by [`TransferEngine::submit`].
+pub struct TransferHandle {
+    kind: Option<TransferKind>,
+    bytes: usize,
+    completion: Arc<Completion>,
+}
+
+impl TransferHandle {
+    /// Handle of a transfer that needed no copy, e.g. a migration into a tier
+    /// that is not present.
+    pub fn completed(kind: Option<TransferKind>, bytes: usize) -> Self {
+        let completion = Arc::new(Completion::default());
+        completion.finish(Vec::new());
+        Self {
+            kind,
+            bytes,
+            completion,
+        }
+    }
+
+    pub fn kind(&self) -> Option<TransferKind> {
+        self.kind
+    }
+
+    /// Number of bytes moved by the transfer.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completion.result.lock().unwrap().is_some()
+    }
+
+    /// Block until the copy has finished and return the destination buffer.
+    pub fn wait(self) -> Vec<u8> {
+        let mut result = self.completion.result.lock().unwrap();
+        loop {
+            if let Some(data) = result.take() {
+                return data;
+            }
+            result = self.completion.ready.wait(result).unwrap();
+        }
+    }
+}
+
+impl std::fmt::Debug for TransferHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransferHandle")
+            .field("kind", &self.kind)
+            .field("bytes", &self.bytes)
+            .field("complete", &self.is_complete())
+            .finish()
+    }
+}
+
+struct Job {
+    src: Vec<u8>,
+    completion: Arc<Completion>,
+}
+
+/// Copy queue running transfers concurrently with compute.
+pub struct TransferEngine {
+    backend: BackendKind,
+    jobs: Mutex<Option<Sender<Job>>>,
+    worker: Option<JoinHandle<()>>,
+    stats: Arc<Mutex<TransferStats>>,
+}
+
+impl TransferEngine {
+    /// Create an engine for `backend`; device backends stage copies through
+    /// pinned memory.
+    pub fn for_backend(backend: BackendKind) -> Self {
+        let staged = !matches!(
+            backend,
+            BackendKind::CpuSimd | BackendKind::Riscv | BackendKind::Jit
+        );
+        let stats = Arc::new(Mutex::new(TransferStats::default()));
+        let (tx, rx) = channel();
+        let worker_stats = stats.clone();
+        let worker = std::thread::Builder::new()
+            .name("amduda-transfer".into())
+            .spawn(move || copy_loop(rx, staged, worker_stats))
+            .expect("failed to spawn transfer thread");
+        Self {
+            backend,
+            jobs: Mutex::new(Some(tx)),
+            worker: Some(worker),
+            stats,
+        }
+    }
+
+    /// Engine shared by all memory managers of the process, created for the
+    /// backend chosen by `AUREX_BACKEND`.
+    pub fn shared() -> Arc<TransferEngine> {
+        SHARED.clone()
+    }
+
+    pub fn backend(&self) -> BackendKind {
+        self.backend
+    }
+
+    /// Queue a copy of `src` and return immediately.
+    pub fn submit(&self, kind: TransferKind, src: Vec<u8>) -> TransferHandle {
+        let bytes = src.len();
+        let completion = Arc::new(Completion::default());
+        tracing::trace!(?kind, bytes, "transfer submitted");
+        let job = Job {
+            src,
+            completion: completion.clone(),
+        };
+        let jobs = self.jobs.lock().unwrap();
+        if let Err(err) = jobs.as_ref().expect("transfer engine shut down").send(job) {
+            // The copy thread is gone; perform the copy inline.
+            let job = err.0;
+            completion.finish(job.src);
+        }
+        TransferHandle {
+            kind: Some(kind),
+            bytes,
+            completion,
+        }
+    }
+
+    pub fn stats(&self) -> TransferStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+impl std::fmt::Debug for TransferEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransferEngine")
+            .field("backend", &self.backend)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl Drop for TransferEngine {
+    fn drop(&mut self) {
+        self.jobs.lock().unwrap().take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn copy_loop(jobs: Receiver<Job>, staged: bool, stats: Arc<Mutex<TransferStats>>) {
+    let mut staging: Option<PinnedBuffer> = None;
+    for job in jobs {
+        let mut dst = vec![0u8; job.src.len()];
+        if staged {
+            let staging = staging.get_or_insert_with(|| PinnedBuffer::new(STAGING_CHUNK));
+            for (src, dst) in job.src.chunks(STAGING_CHUNK).zip(dst.chunks_mut(STAGING_CHUNK)) {
+                let chunk = &mut staging.data[..src.len()];
+                chunk.copy_from_slice(src);
+                dst.copy_from_slice(chunk);
+            }
+        } else {
+            dst.copy_from_slice(&job.src);
+        }
+        {
+            let mut stats = stats.lock().unwrap();
+            stats.transfers += 1;
+            stats.bytes += dst.len() as u64;
+            if staged {
+                stats.staged_bytes += dst.len() as u64;
+            }
+        }
+        job.completion.finish(dst);
+    }
+}
@@ -0,0 +1,213 @@
+//! Continuous batching with chunked prefill.
+//!
+//! A [`BatchScheduler`] serves several generation requests at once.  Every
+//! iteration advances each decoding request by one token and then spends a
+//! budget of [`prefill_chunk`](BatchScheduler::with_prefill_chunk) prompt
+//! tokens on requests still prefilling, those of the highest
+//! [`GenerationConfig::priority`] first and oldest first among equals; a
+//! config built with [`GenerationConfig::from_options`] carries the
+//! priority of the request's [`GenerationOptions`].  A long prompt is
+//! therefore processed over several iterations instead of stalling the
+//! requests already streaming tokens, and no iteration runs more than the
+//! chunk plus one token per decoding request.
+//!
+//! Requests run on their own [`GenerationEngine`] over a clone of the
+//! scheduler's model, so models whose clones share weights, such as
+//! [`TinyLm`](super::tiny_lm::TinyLm), are cheap to batch.  The engines do
+//! not critique their outputs; use
+//! [`GenerationEngine::with_reflexion`] directly for that.
+//!
+//! [`GenerationOptions`]: aurex_runtime::GenerationOptions
+
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+
+use aurex_runtime::config::AurexConfig;
+use aurex_runtime::Priority;
+
+use super::generation::{
+    Decoding, GenerationConfig, GenerationEngine, GenerationOutput, Prefill, StreamEvent,
+};
+use super::tiny_lm::LanguageModel;
+
+/// Prompt tokens prefilled per iteration unless configured otherwise.
+pub const DEFAULT_PREFILL_CHUNK: usize = 256;
+
+/// Identifies a request submitted to a [`BatchScheduler`].
+pub type RequestId = u64;
+
+/// Work done by a [`BatchScheduler`] so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    pub iterations: u64,
+    /// Prompt tokens run by the model.
+    pub prefill_tokens: u64,
+    /// Tokens sampled by decode steps.
+    pub decode_tokens: u64,
+    /// Most tokens run by a single iteration.
+    pub max_iteration_tokens: usize,
+}
+
+enum Phase<'c> {
+    Prefilling {
+        prefill: Prefill,
+        config: &'c GenerationConfig,
+    },
+    Decoding(Box<Decoding<'c>>),
+}
+
+struct Request<'c, M: LanguageModel> {
+    id: RequestId,
+    priority: Priority,
+    engine: GenerationEngine<M>,
+    phase: Phase<'c>,
+}
+
+/// Interleaves the prefill and decode steps of concurrent requests.
+pub struct BatchScheduler<'c, M: LanguageModel + Clone> {
+    model: M,
+    prefill_chunk: usize,
+    /// Requests in submission order.
+    requests: VecDeque<Request<'c, M>>,
+    next_id: RequestId,
+    stats: BatchStats,
+}
+
+impl<'c, M: LanguageModel + Clone> BatchScheduler<'c, M> {
+    pub fn new(model: M) -> Self {
+        Self {
+            model,
+            prefill_chunk: DEFAULT_PREFILL_CHUNK,
+            requests: VecDeque::new(),
+            next_id: 0,
+            stats: BatchStats::default(),
+        }
+    }
+
+    /// Build a scheduler with the prefill chunk of `config.scheduler`.
+    pub fn from_config(model: M, config: &AurexConfig) -> Self {
+        let scheduler = Self::new(model);
+        match config.scheduler.prefill_chunk {
+            Some(chunk) => scheduler.with_prefill_chunk(chunk),
+            None => scheduler,
+        }
+    }
+
+    /// Prefill at most `chunk` prompt tokens per iteration.  A smaller
+    /// chunk bounds the latency of decoding requests more tightly but
+    /// takes more iterations to start long prompts.
+    pub fn with_prefill_chunk(mut self, chunk: usize) -> Self {
+        self.prefill_chunk = chunk.max(1);
+        self
+    }
+
+    pub fn prefill_chunk(&self) -> usize {
+        self.prefill_chunk
+    }
+
+    /// Queue a request for `prompt`.  It starts prefilling on the next
+    /// [`step`](Self::step).
+    pub fn submit(&mut self, prompt: &str, config: &'c GenerationConfig) -> RequestId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut engine = GenerationEngine::new(self.model.clone());
+        let prefill = engine.begin(prompt, config);
+        self.requests.push_back(Request {
+            id,
+            priority: config.priority,
+            engine,
+            phase: Phase::Prefilling { prefill, config },
+        });
+        id
+    }
+
+    /// Requests not finished yet.
+    pub fn pending(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    pub fn stats(&self) -> BatchStats {
+        self.stats
+    }
+
+    /// Run one iteration: a decode step of every decoding request, then up
+    /// to one chunk of prefill.  `on_event` receives the streamed tokens
+    /// with the request they belong to.  Returns the requests that
+    /// finished.
+    pub fn step(
+        &mut self,
+        mut on_event: impl FnMut(RequestId, &StreamEvent),
+    ) -> Vec<(RequestId, GenerationOutput)> {
+        let mut ran = 0;
+        for request in &mut self.requests {
+            if let Phase::Decoding(decoding) = &mut request.phase {
+                let id = request.id;
+                let before = decoding.sampled();
+                request
+                    .engine
+                    .decode_step(decoding, &mut |event| on_event(id, event));
+                ran += decoding.sampled() - before;
+            }
+        }
+        self.stats.decode_tokens += ran as u64;
+
+        let mut budget = self.prefill_chunk;
+        let mut prefilling: Vec<_> = self
+            .requests
+            .iter_mut()
+            .filter(|r| matches!(r.phase, Phase::Prefilling { .. }))
+            .collect();
+        // Stable, so submission order breaks ties.
+        prefilling.sort_by_key(|r| Reverse(r.priority));
+        for request in prefilling {
+            if budget == 0 {
+                break;
+            }
+            if let Phase::Prefilling { prefill, .. } = &mut request.phase {
+                let run = request.engine.prefill(prefill, budget);
+                budget -= run;
+                ran += run;
+                self.stats.prefill_tokens += run as u64;
+            }
+        }
+
+        // Prefilled requests decode from the next iteration on.
+        let mut finished = Vec::new();
+        let mut pending = VecDeque::with_capacity(self.requests.len());
+        for mut request in self.requests.drain(..) {
+            request.phase = match request.phase {
+                Phase::Prefilling { prefill, config } if prefill.is_done() => {
+                    Phase::Decoding(Box::new(request.engine.start_decode(prefill, config)))
+                }
+                Phase::Decoding(decoding) if decoding.is_finished() => {
+                    finished.push((request.id, decoding.output()));
+                    continue;
+                }
+                phase => phase,
+            };
+            pending.push_back(request);
+        }
+        self.requests = pending;
+
+        self.stats.iterations += 1;
+        self.stats.max_iteration_tokens = self.stats.max_iteration_tokens.max(ran);
+        finished
+    }
+
+    /// Step until every request has finished, returning the outputs in
+    /// the order the requests finished.
+    pub fn run(
+        &mut self,
+        mut on_event: impl FnMut(RequestId, &StreamEvent),
+    ) -> Vec<(RequestId, GenerationOutput)> {
+        let mut outputs = Vec::new();
+        while !self.is_idle() {
+            outputs.extend(self.step(&mut on_event));
+        }
+        outputs
+    }
+}
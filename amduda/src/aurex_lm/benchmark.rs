@@ -0,0 +1,200 @@
+//! Comparison of quantization levels on one backend.
+//!
+//! [`run`] quantizes the same model to every requested [`Precision`] in turn
+//! and measures, on the backend selected by the [`AurexConfig`]:
+//!
+//! - decode throughput in generated tokens per second, prefill included,
+//! - the bytes taken by the stored weights,
+//! - the perplexity over an evaluation text, see [`evaluation`].
+//!
+//! The [`BenchReport`] prints as a table with each precision's perplexity
+//! relative to `f32`, and serializes to JSON for tracking results over time.
+//!
+//! [`evaluation`]: super::evaluation
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use aurex_backend::{Backend, Precision, Workload};
+use aurex_runtime::AurexConfig;
+use serde::Serialize;
+
+use super::evaluation;
+use super::generation::{GenerationConfig, GenerationEngine};
+use super::model_loader::{LoadedModel, Quantization};
+use super::sampler::SamplingParams;
+use super::tiny_lm::TinyLm;
+use super::tokenizer::ByteTokenizer;
+
+/// Evaluation text used when none is given.
+pub const SAMPLE_TEXT: &str = "The quick brown fox jumps over the lazy dog. \
+A journey of a thousand miles begins with a single step. \
+All that glitters is not gold, and not all those who wander are lost. \
+The early bird catches the worm, but the second mouse gets the cheese.";
+
+/// Settings of a benchmark [`run`].
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub precisions: Vec<Precision>,
+    /// Prompt the timed generations continue.
+    pub prompt: String,
+    /// Tokens generated per timed generation.
+    pub tokens: usize,
+    /// Timed generations per precision; the fastest counts.
+    pub repeats: usize,
+    /// Text the perplexity is measured on.
+    pub text: String,
+    /// Tokens per perplexity window.
+    pub window: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            precisions: vec![
+                Precision::F32,
+                Precision::Bf16,
+                Precision::Int8,
+                Precision::Int4,
+            ],
+            prompt: "Once upon a time".to_string(),
+            tokens: 64,
+            repeats: 3,
+            text: SAMPLE_TEXT.to_string(),
+            window: 256,
+        }
+    }
+}
+
+/// Measurements of the model at one precision.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchResult {
+    pub precision: Precision,
+    /// Encoding of the weights, `None` for `f32`.
+    pub quantization: Option<Quantization>,
+    pub tokens_per_sec: f64,
+    pub weight_bytes: usize,
+    pub perplexity: f64,
+    /// Tokens the perplexity was measured over.
+    pub scored_tokens: usize,
+}
+
+/// Results of every precision of a benchmark [`run`].
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub model: String,
+    pub backend: Backend,
+    pub results: Vec<BenchResult>,
+}
+
+impl BenchReport {
+    fn baseline(&self) -> Option<&BenchResult> {
+        self.results.iter().find(|r| r.precision == Precision::F32)
+    }
+
+    /// Relative change of the perplexity of `result` from the `f32` result,
+    /// when `f32` was measured.
+    pub fn perplexity_delta(&self, result: &BenchResult) -> Option<f64> {
+        Some(result.perplexity / self.baseline()?.perplexity - 1.0)
+    }
+
+    /// Throughput of `result` relative to the `f32` result, when `f32` was
+    /// measured.
+    pub fn speedup(&self, result: &BenchResult) -> Option<f64> {
+        Some(result.tokens_per_sec / self.baseline()?.tokens_per_sec)
+    }
+}
+
+/// Benchmark `model` at every precision of `config` on the backend and
+/// routes selected by `runtime`.  The weights are decoded to `f32` once and
+/// quantized again for each precision.
+pub fn run(
+    mut model: LoadedModel,
+    runtime: &AurexConfig,
+    config: &BenchConfig,
+) -> Result<BenchReport> {
+    let master = model.weights_f32()?;
+    let text = ByteTokenizer.encode(&config.text);
+    let mut backend = Backend::Cpu;
+    let mut results = Vec::with_capacity(config.precisions.len());
+    for &precision in &config.precisions {
+        model.set_precision(&master, precision);
+        let mut dispatcher = runtime
+            .dispatcher(Workload::Heavy)
+            .with_shapes(model.kernel_shapes());
+        dispatcher.set_precision(precision);
+        backend = dispatcher.backend();
+        let mut lm = TinyLm::from_model(&model, Arc::new(dispatcher))?;
+
+        let perplexity = evaluation::perplexity(&mut lm, &text, config.window);
+        let tokens_per_sec = throughput(&lm, config);
+        tracing::info!(?precision, tokens_per_sec, "benchmarked precision");
+        results.push(BenchResult {
+            precision,
+            quantization: model.config.quantization,
+            tokens_per_sec,
+            weight_bytes: model.weights.as_bytes().len(),
+            perplexity: perplexity.perplexity(),
+            scored_tokens: perplexity.tokens,
+        });
+    }
+    Ok(BenchReport {
+        model: model.config.name.clone(),
+        backend,
+        results,
+    })
+}
+
+/// Best generated tokens per second of `config.repeats` greedy generations,
+/// each from an empty cache.
+fn throughput(lm: &TinyLm, config: &BenchConfig) -> f64 {
+    let generation = GenerationConfig {
+        max_tokens: config.tokens,
+        sampling: SamplingParams {
+            temperature: 0.0,
+            ..SamplingParams::default()
+        },
+        stop_at_eos: false,
+        ..GenerationConfig::default()
+    };
+    let mut best = 0.0_f64;
+    for _ in 0..config.repeats.max(1) {
+        let mut engine = GenerationEngine::new(lm.clone());
+        let start = Instant::now();
+        let out = engine.generate(&config.prompt, &generation, |_| {});
+        let secs = start.elapsed().as_secs_f64().max(f64::EPSILON);
+        best = best.max(out.tokens.len() as f64 / secs);
+    }
+    best
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Model: {}", self.model)?;
+        writeln!(f, "Backend: {}", self.backend)?;
+        write!(
+            f,
+            "{:<10} {:>10} {:>8} {:>12} {:>12} {:>10}",
+            "precision", "tok/s", "speedup", "weights", "perplexity", "delta"
+        )?;
+        for result in &self.results {
+            let speedup = self.speedup(result).map(|s| format!("{s:.2}x"));
+            let delta = self
+                .perplexity_delta(result)
+                .map(|d| format!("{:+.2}%", d * 100.0));
+            write!(
+                f,
+                "\n{:<10} {:>10.1} {:>8} {:>10} B {:>12.4} {:>10}",
+                format!("{:?}", result.precision).to_lowercase(),
+                result.tokens_per_sec,
+                speedup.as_deref().unwrap_or("-"),
+                result.weight_bytes,
+                result.perplexity,
+                delta.as_deref().unwrap_or("-"),
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Compiled model bundles (`.aurexc`).
+//!
+//! `aurex compile` writes a single file holding the (optionally quantized)
+//! weights, the fused op graph and kernels pre-compiled for the target
+//! backend so `aurex run` can start without repeating that work.
+//!
+//! Layout:
+//!
+//! ```text
+//! magic "AUREXC\0\x01" | u32 manifest length | manifest JSON
+//! | u64 weight length | weights | kernel blobs in manifest order
+//! ```
+//!
+//! All integers are little endian.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::model_loader::{LoadedModel, ModelConfig, Quantization, Weights};
+use crate::amduda_core::memory_tiering;
+
+/// File magic identifying a compiled bundle.
+pub const BUNDLE_MAGIC: &[u8; 8] = b"AUREXC\0\x01";
+
+/// Manifest format version written by this build.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Conventional file extension of compiled bundles.
+pub const BUNDLE_EXTENSION: &str = "aurexc";
+
+/// Encoding of a pre-compiled kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KernelKind {
+    /// SPIR-V words for the Vulkan backend.
+    SpirV,
+    /// Textual LLVM IR for the JIT compiler.
+    LlvmIr,
+}
+
+/// Manifest entry describing a kernel blob.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KernelEntry {
+    pub name: String,
+    pub kind: KernelKind,
+    pub len: u64,
+}
+
+/// A pre-compiled kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelBlob {
+    pub name: String,
+    pub kind: KernelKind,
+    pub data: Vec<u8>,
+}
+
+/// Metadata stored at the start of a bundle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub version: u32,
+    /// Model configuration after compilation; `quantization` and `scale`
+    /// describe the stored weights.
+    pub config: ModelConfig,
+    /// Backend the kernels were compiled for.
+    pub target: String,
+    /// Number of logical weight values (needed to unpack INT4).
+    pub weight_count: usize,
+    /// Op graph after fusion passes.
+    pub graph: Vec<String>,
+    pub kernels: Vec<KernelEntry>,
+}
+
+/// In-memory representation of a `.aurexc` file.
+#[derive(Debug, Clone)]
+pub struct CompiledBundle {
+    pub manifest: BundleManifest,
+    pub weights: Vec<u8>,
+    pub kernels: Vec<KernelBlob>,
+}
+
+impl CompiledBundle {
+    /// Serialize the bundle to `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut manifest = self.manifest.clone();
+        manifest.kernels = self
+            .kernels
+            .iter()
+            .map(|k| KernelEntry {
+                name: k.name.clone(),
+                kind: k.kind,
+                len: k.data.len() as u64,
+            })
+            .collect();
+        let json = serde_json::to_vec(&manifest)?;
+
+        writer.write_all(BUNDLE_MAGIC)?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(&json)?;
+        writer.write_all(&(self.weights.len() as u64).to_le_bytes())?;
+        writer.write_all(&self.weights)?;
+        for kernel in &self.kernels {
+            writer.write_all(&kernel.data)?;
+        }
+        Ok(())
+    }
+
+    /// Deserialize a bundle from `reader`.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("reading bundle header")?;
+        if &magic != BUNDLE_MAGIC {
+            bail!("not an aurexc bundle");
+        }
+        let mut len4 = [0u8; 4];
+        reader.read_exact(&mut len4)?;
+        let json = read_bytes(&mut reader, u32::from_le_bytes(len4).into())
+            .context("reading bundle manifest")?;
+        let manifest: BundleManifest = serde_json::from_slice(&json)?;
+        if manifest.version != BUNDLE_VERSION {
+            bail!(
+                "unsupported bundle version {} (expected {})",
+                manifest.version,
+                BUNDLE_VERSION
+            );
+        }
+
+        let mut len8 = [0u8; 8];
+        reader.read_exact(&mut len8)?;
+        let weights =
+            read_bytes(&mut reader, u64::from_le_bytes(len8)).context("reading bundle weights")?;
+
+        let mut kernels = Vec::with_capacity(manifest.kernels.len());
+        for entry in &manifest.kernels {
+            let data = read_bytes(&mut reader, entry.len)
+                .with_context(|| format!("reading kernel '{}'", entry.name))?;
+            kernels.push(KernelBlob {
+                name: entry.name.clone(),
+                kind: entry.kind,
+                data,
+            });
+        }
+        Ok(Self {
+            manifest,
+            weights,
+            kernels,
+        })
+    }
+
+    /// Write the bundle to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = fs::File::create(path.as_ref())
+            .with_context(|| format!("creating {}", path.as_ref().display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a bundle from `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = fs::File::open(path.as_ref())
+            .with_context(|| format!("opening {}", path.as_ref().display()))?;
+        Self::read_from(std::io::BufReader::new(file))
+    }
+
+    /// Look up a pre-compiled kernel by name.
+    pub fn kernel(&self, name: &str) -> Option<&KernelBlob> {
+        self.kernels.iter().find(|k| k.name == name)
+    }
+
+    /// Turn the bundle into a [`LoadedModel`], placing the weights on a tier
+    /// chosen by the memory manager.
+    pub fn into_model(self) -> LoadedModel {
+        let tier = memory_tiering::allocate(self.weights.len());
+        LoadedModel {
+            scale: self.manifest.config.scale,
+            config: self.manifest.config,
+            weights: Weights::Memory(self.weights),
+            tier,
+        }
+    }
+}
+
+/// Read exactly `len` bytes from `reader`.  The buffer grows with the data
+/// actually read, so a corrupt length fails as a short read instead of
+/// allocating `len` bytes up front.
+pub(super) fn read_bytes<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        bail!("unexpected end of file: read {} of {len} bytes", data.len());
+    }
+    Ok(data)
+}
+
+/// Whether `path` starts with the bundle magic.
+pub fn is_bundle(path: impl AsRef<Path>) -> bool {
+    let mut magic = [0u8; 8];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| &magic == BUNDLE_MAGIC)
+        .unwrap_or(false)
+}
+
+/// Number of logical values stored in `bytes` encoded as `quantization`
+/// (`None` meaning raw little endian `f32`).
+pub fn weight_count(bytes: usize, quantization: Option<Quantization>) -> usize {
+    match quantization {
+        None => bytes / 4,
+        Some(Quantization::Bf16) => bytes / 2,
+        Some(Quantization::Int8) => bytes,
+        Some(Quantization::Int4) => bytes * 2,
+    }
+}
@@ -0,0 +1,184 @@
+//! Confidence estimation from token log-probabilities.
+//!
+//! A [`ConfidenceMonitor`], attached with
+//! [`GenerationEngine::with_confidence`], scores every sampled token by its
+//! log-probability and the entropy of the distribution it was drawn from, and
+//! keeps the running perplexity over a window of recent tokens.  Once the
+//! perplexity rises above [`ConfidencePolicy::downgrade_perplexity`] the
+//! confidence is [`ConfidenceLevel::Low`]; above
+//! [`ConfidencePolicy::abort_perplexity`] it has collapsed and the engine ends
+//! the generation with [`FinishReason::LowConfidence`].
+//!
+//! Every token's [`ConfidenceRecord`] is kept in the monitor's trace, passed
+//! to streaming callers of [`GenerationEngine::generate_streaming`] and, when
+//! attached, recorded in a [`Profiler`].
+//!
+//! As a [`ConfidenceRegulator`] the monitor asks the runtime for `F32`
+//! precision while confidence is low and turns events into
+//! [`RuntimeEvent::Error`] once it has collapsed.
+//!
+//! [`GenerationEngine::with_confidence`]: super::generation::GenerationEngine::with_confidence
+//! [`GenerationEngine::generate_streaming`]: super::generation::GenerationEngine::generate_streaming
+//! [`FinishReason::LowConfidence`]: super::generation::FinishReason::LowConfidence
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use aurex_runtime::{ConfidenceRegulator, Precision, RuntimeEvent};
+use aurex_utils::profiler::{ConfidenceRecord, Profiler};
+
+/// Thresholds of a [`ConfidenceMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidencePolicy {
+    /// Number of recent tokens the running perplexity is computed over.
+    pub window: usize,
+    /// Tokens observed before the confidence level may drop.
+    pub warmup: usize,
+    /// Running perplexity above which confidence is low.
+    pub downgrade_perplexity: f32,
+    /// Running perplexity above which confidence has collapsed.
+    pub abort_perplexity: f32,
+}
+
+impl Default for ConfidencePolicy {
+    fn default() -> Self {
+        Self {
+            window: 8,
+            warmup: 4,
+            downgrade_perplexity: 16.0,
+            abort_perplexity: 64.0,
+        }
+    }
+}
+
+/// How confident the model currently is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfidenceLevel {
+    #[default]
+    High,
+    /// Running perplexity above the downgrade threshold.
+    Low,
+    /// Running perplexity above the abort threshold.
+    Collapsed,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    window: VecDeque<f32>,
+    trace: Vec<ConfidenceRecord>,
+    level: ConfidenceLevel,
+}
+
+/// Running confidence estimate, see the [module documentation](self).
+#[derive(Default)]
+pub struct ConfidenceMonitor {
+    policy: ConfidencePolicy,
+    state: Mutex<State>,
+    profiler: Option<Arc<Mutex<Profiler>>>,
+}
+
+impl ConfidenceMonitor {
+    pub fn new(policy: ConfidencePolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Also record every token's confidence into `profiler`.
+    pub fn with_profiler(mut self, profiler: Arc<Mutex<Profiler>>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    pub fn policy(&self) -> ConfidencePolicy {
+        self.policy
+    }
+
+    /// Confidence after the most recent token.
+    pub fn level(&self) -> ConfidenceLevel {
+        self.state.lock().unwrap().level
+    }
+
+    /// Confidence of the tokens observed since the last reset.
+    pub fn trace(&self) -> Vec<ConfidenceRecord> {
+        self.state.lock().unwrap().trace.clone()
+    }
+
+    /// Forget all observed tokens, e.g. before a new generation.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+
+    /// Log-probability of `token` and entropy in nats of the distribution
+    /// given by `logits`.
+    pub fn token_stats(logits: &[f32], token: u32) -> (f32, f32) {
+        let max = logits.iter().fold(f32::NEG_INFINITY, |m, &l| m.max(l));
+        if !max.is_finite() {
+            return (f32::NEG_INFINITY, 0.0);
+        }
+        let total: f32 = logits.iter().map(|l| (l - max).exp()).sum();
+        let log_total = total.ln();
+        let entropy = logits
+            .iter()
+            .filter(|l| l.is_finite())
+            .map(|l| {
+                let logprob = l - max - log_total;
+                -logprob.exp() * logprob
+            })
+            .sum();
+        let logprob = logits
+            .get(token as usize)
+            .map_or(f32::NEG_INFINITY, |l| l - max - log_total);
+        (logprob, entropy)
+    }
+
+    /// Record that `token` was sampled from `logits` and return its
+    /// confidence.  [`level`](Self::level) reflects the token afterwards.
+    pub fn observe(&self, logits: &[f32], token: u32) -> ConfidenceRecord {
+        let (logprob, entropy) = Self::token_stats(logits, token);
+        let mut state = self.state.lock().unwrap();
+        state.window.push_back(logprob);
+        while state.window.len() > self.policy.window.max(1) {
+            state.window.pop_front();
+        }
+        let mean = state.window.iter().sum::<f32>() / state.window.len() as f32;
+        let record = ConfidenceRecord {
+            position: state.trace.len(),
+            logprob,
+            entropy,
+            perplexity: (-mean).exp(),
+        };
+        state.trace.push(record);
+        if state.trace.len() >= self.policy.warmup {
+            state.level = if record.perplexity > self.policy.abort_perplexity {
+                ConfidenceLevel::Collapsed
+            } else if record.perplexity > self.policy.downgrade_perplexity {
+                ConfidenceLevel::Low
+            } else {
+                ConfidenceLevel::High
+            };
+        }
+        drop(state);
+        if let Some(profiler) = &self.profiler {
+            profiler.lock().unwrap().record_confidence(record);
+        }
+        record
+    }
+}
+
+#[async_trait]
+impl ConfidenceRegulator for ConfidenceMonitor {
+    async fn regulate(&self, event: &RuntimeEvent) -> RuntimeEvent {
+        if self.level() == ConfidenceLevel::Collapsed {
+            RuntimeEvent::Error
+        } else {
+            event.clone()
+        }
+    }
+
+    async fn requested_precision(&self, _event: &RuntimeEvent) -> Option<Precision> {
+        (self.level() == ConfidenceLevel::Low).then_some(Precision::F32)
+    }
+}
@@ -0,0 +1,194 @@
+//! Context compression of long conversations.
+//!
+//! A [`ContextCompressor`] is the stage in front of a session's next turn:
+//! once the turn's prompt plus the tokens it may generate would fill
+//! [`CompressionPolicy::compress_at`] of the model's context window, the
+//! model itself summarizes the older turns and [`Session::summarize`]
+//! replaces them in the history.  The most recent
+//! [`CompressionPolicy::keep_messages`] messages stay verbatim.
+//!
+//! The summary prompt is the transcript up to the last summarized message
+//! followed by [`CompressionPolicy::instruction`], so it continues the
+//! session's KV cache instead of prefilling the old turns again.  Once the
+//! summary is in the history only the system prompt still matches the
+//! cache, and the cache is truncated to it so the pages of the summarized
+//! turns are released before the next turn prefills the summary.
+//!
+//! When the model writes an empty summary the history stays as it is, the
+//! cache is cut back to the transcript the summary prompt shared with it,
+//! and the session is not compressed again until it has grown by
+//! [`RETRY_AFTER_MESSAGES`] messages.
+
+use aurex_runtime::config::SessionConfig;
+use aurex_runtime::{Role, Session};
+
+use super::generation::{GenerationConfig, GenerationEngine};
+use super::sampler::SamplingParams;
+use super::tiny_lm::LanguageModel;
+use super::tokenizer::ByteTokenizer;
+
+/// Messages a session must grow by after an empty summary before it is
+/// summarized again.
+pub const RETRY_AFTER_MESSAGES: usize = 4;
+
+/// When and how a [`ContextCompressor`] summarizes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionPolicy {
+    /// Tokens the model attends to.
+    pub context_window: usize,
+    /// Fraction of the window a turn may fill before the history is
+    /// compressed.
+    pub compress_at: f32,
+    /// Most recent messages kept verbatim.
+    pub keep_messages: usize,
+    /// Longest summary in tokens.
+    pub summary_tokens: usize,
+    /// System message asking the model for the summary.
+    pub instruction: String,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            context_window: 4096,
+            compress_at: 0.75,
+            keep_messages: 4,
+            summary_tokens: 128,
+            instruction: "Summarize the conversation so far in a few sentences.".into(),
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Policy of the `[sessions]` section, `None` without a context window.
+    pub fn from_config(config: &SessionConfig) -> Option<Self> {
+        let default = Self::default();
+        Some(Self {
+            context_window: config.context_window?,
+            compress_at: config
+                .compress_at
+                .map_or(default.compress_at, |fraction| fraction as f32),
+            keep_messages: config.keep_messages.unwrap_or(default.keep_messages),
+            summary_tokens: config.summary_tokens.unwrap_or(default.summary_tokens),
+            ..default
+        })
+    }
+
+    /// Tokens a turn may use before the history is compressed.
+    pub fn limit(&self) -> usize {
+        (self.context_window as f32 * self.compress_at.clamp(0.0, 1.0)) as usize
+    }
+}
+
+/// Outcome of [`ContextCompressor::compress`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compression {
+    /// Messages replaced by the summary, none if the model wrote an empty
+    /// one.
+    pub summarized: usize,
+    pub summary: String,
+    /// Tokens of the turn's prompt before and after compression.
+    pub prompt_tokens_before: usize,
+    pub prompt_tokens_after: usize,
+    /// Tokens computed for the summary, prefilled and generated.
+    pub tokens: usize,
+    /// Of these, the tokens of the summary itself.
+    pub generated_tokens: usize,
+}
+
+/// Summarizes the older turns of sessions, see the
+/// [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct ContextCompressor {
+    policy: CompressionPolicy,
+}
+
+impl ContextCompressor {
+    pub fn new(policy: CompressionPolicy) -> Self {
+        Self { policy }
+    }
+
+    pub fn policy(&self) -> &CompressionPolicy {
+        &self.policy
+    }
+
+    /// Whether the next turn of `session`, generating up to `max_tokens`,
+    /// would exceed the policy's limit.
+    pub fn needs_compression<K>(&self, session: &Session<K>, max_tokens: usize) -> bool {
+        prompt_tokens(session) + max_tokens > self.policy.limit()
+    }
+
+    /// Summarize the older messages of `session` with `engine` if its next
+    /// turn needs the room.  `engine` should hold the session's KV cache;
+    /// it is left holding the cache of the system prompt.  Returns `None`,
+    /// leaving the history and cache as they are, when the session fits,
+    /// has no messages old enough to summarize or is backing off after an
+    /// empty summary.  An empty summary is returned with nothing summarized
+    /// and the tokens it cost.
+    pub fn compress<M: LanguageModel, K>(
+        &self,
+        engine: &mut GenerationEngine<M>,
+        session: &mut Session<K>,
+        max_tokens: usize,
+    ) -> Option<Compression> {
+        if !self.needs_compression(session, max_tokens) {
+            return None;
+        }
+        let count = session
+            .messages()
+            .len()
+            .saturating_sub(self.policy.keep_messages);
+        if count == 0 {
+            return None;
+        }
+        if session
+            .messages_since_failed_summary()
+            .is_some_and(|added| added < RETRY_AFTER_MESSAGES)
+        {
+            return None;
+        }
+
+        let prompt = format!(
+            "{}{}: {}\n{}: ",
+            session.transcript_prefix(count),
+            Role::System,
+            self.policy.instruction,
+            Role::Assistant
+        );
+        let config = GenerationConfig {
+            max_tokens: self.policy.summary_tokens,
+            sampling: SamplingParams {
+                temperature: 0.0,
+                ..SamplingParams::default()
+            },
+            stop: vec!["\n".into()],
+            ..GenerationConfig::default()
+        };
+        let output = engine.generate(&prompt, &config, |_| {});
+        let tokens = output.prompt_tokens - output.cached_tokens + output.tokens.len();
+        let summary = output.text.trim().to_string();
+        let before = prompt_tokens(session);
+        let (summarized, unchanged) = if summary.is_empty() {
+            session.summary_failed();
+            // The transcript before the instruction still matches the cache.
+            (0, session.transcript_prefix(count))
+        } else {
+            session.summarize(count, &summary);
+            (count, session.transcript_prefix(0))
+        };
+        engine.truncate_kv_cache(ByteTokenizer.encode(&unchanged).len());
+        Some(Compression {
+            summarized,
+            summary,
+            prompt_tokens_before: before,
+            prompt_tokens_after: prompt_tokens(session),
+            tokens,
+            generated_tokens: output.tokens.len(),
+        })
+    }
+}
+
+/// Tokens of the prompt of `session`'s next turn.
+fn prompt_tokens<K>(session: &Session<K>) -> usize {
+    ByteTokenizer.encode(&session.prompt()).len()
+}
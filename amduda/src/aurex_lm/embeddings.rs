@@ -0,0 +1,189 @@
+//! Sentence embeddings.
+//!
+//! [`EmbeddingModel`] reads the loaded weights as the same tied
+//! `vocab_size x hidden_size` embedding table as [`TinyLm`], but runs it as
+//! an encoder: every token attends to all tokens of the input, not only the
+//! preceding ones.  The input starts with the end-of-sequence token, which
+//! serves as the `[CLS]` position.  The token states are pooled into a
+//! single L2-normalised vector per text, by averaging them ([`Pooling::Mean`])
+//! or by taking the `[CLS]` state ([`Pooling::Cls`]).
+//!
+//! The attention products and layer norm run through a [`TensorOps`]
+//! implementation, typically the backend [`Dispatcher`].
+//!
+//! [`TinyLm`]: super::tiny_lm::TinyLm
+//! [`Dispatcher`]: aurex_backend::Dispatcher
+
+use anyhow::{bail, Result};
+use aurex_backend::TensorOps;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::model_loader::LoadedModel;
+use super::tokenizer::{ByteTokenizer, EOS_TOKEN};
+
+/// How token states are combined into one embedding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pooling {
+    /// Average of the states of the text tokens.
+    #[default]
+    Mean,
+    /// State of the leading `[CLS]` token.
+    Cls,
+}
+
+impl std::str::FromStr for Pooling {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mean" => Ok(Pooling::Mean),
+            "cls" => Ok(Pooling::Cls),
+            other => Err(format!("unknown pooling '{other}'")),
+        }
+    }
+}
+
+/// Tied-embedding encoder with a single bidirectional attention layer.
+pub struct EmbeddingModel {
+    vocab: usize,
+    hidden: usize,
+    embedding: Vec<f32>,
+    gamma: Vec<f32>,
+    beta: Vec<f32>,
+    tokenizer: ByteTokenizer,
+    ops: Arc<dyn TensorOps + Send + Sync>,
+}
+
+impl EmbeddingModel {
+    /// Build a model from raw `f32` weights.
+    pub fn new(
+        weights: &[f32],
+        vocab: usize,
+        hidden: usize,
+        ops: Arc<dyn TensorOps + Send + Sync>,
+    ) -> Result<Self> {
+        if vocab == 0 || hidden == 0 {
+            bail!("vocab and hidden size must be non-zero");
+        }
+        let needed = vocab * hidden;
+        if weights.len() < needed {
+            bail!(
+                "model needs {needed} weights for vocab {vocab} x hidden {hidden}, found {}",
+                weights.len()
+            );
+        }
+        Ok(Self {
+            vocab,
+            hidden,
+            embedding: weights[..needed].to_vec(),
+            gamma: vec![1.0; hidden],
+            beta: vec![0.0; hidden],
+            tokenizer: ByteTokenizer,
+            ops,
+        })
+    }
+
+    /// Build a model from a loaded checkpoint, with the shape rules of
+    /// [`TinyLm::from_model`](super::tiny_lm::TinyLm::from_model).
+    pub fn from_model(model: &LoadedModel, ops: Arc<dyn TensorOps + Send + Sync>) -> Result<Self> {
+        let weights = model.weights_f32()?;
+        let (vocab, hidden) = model.config.embedding_shape(weights.len());
+        Self::new(&weights, vocab, hidden, ops)
+    }
+
+    /// Length of the produced embeddings.
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    /// Number of tokens `text` is encoded as, including `[CLS]`.
+    pub fn token_count(&self, text: &str) -> usize {
+        self.tokenizer.encode(text).len() + 1
+    }
+
+    /// Run the encoder over `tokens` and return the `tokens.len() x hidden`
+    /// token states.
+    pub fn encode(&self, tokens: &[u32]) -> Vec<f32> {
+        let (n, d) = (tokens.len(), self.hidden);
+        if n == 0 {
+            return Vec::new();
+        }
+        let mut x = Vec::with_capacity(n * d);
+        for &token in tokens {
+            let t = (token as usize).min(self.vocab - 1);
+            x.extend_from_slice(&self.embedding[t * d..(t + 1) * d]);
+        }
+        let mut xt = vec![0.0; d * n];
+        for i in 0..n {
+            for h in 0..d {
+                xt[h * n + i] = x[i * d + h];
+            }
+        }
+
+        let scale = 1.0 / (d as f32).sqrt();
+        let mut probs = self.ops.matmul(&x, &xt, n, n, d);
+        for row in probs.chunks_mut(n) {
+            let max = row.iter().fold(f32::NEG_INFINITY, |m, &s| m.max(s * scale));
+            let mut total = 0.0;
+            for p in row.iter_mut() {
+                *p = (*p * scale - max).exp();
+                total += *p;
+            }
+            row.iter_mut().for_each(|p| *p /= total);
+        }
+        let context = self.ops.matmul(&probs, &x, n, d, n);
+
+        let mut states = Vec::with_capacity(n * d);
+        for (xi, ci) in x.chunks(d).zip(context.chunks(d)) {
+            let residual: Vec<f32> = xi.iter().zip(ci).map(|(a, b)| a + b).collect();
+            states.extend(
+                self.ops
+                    .layer_norm(&residual, &self.gamma, &self.beta, 1e-5),
+            );
+        }
+        states
+    }
+
+    /// Embed `text` into a unit-length vector of [`hidden_size`] values.
+    ///
+    /// [`hidden_size`]: Self::hidden_size
+    pub fn embed(&self, text: &str, pooling: Pooling) -> Vec<f32> {
+        let mut tokens = vec![EOS_TOKEN];
+        tokens.extend(self.tokenizer.encode(text));
+        let states = self.encode(&tokens);
+        let d = self.hidden;
+        let mut pooled = match pooling {
+            Pooling::Cls => states[..d].to_vec(),
+            // An empty text only has the [CLS] state to average.
+            Pooling::Mean => {
+                let text = if tokens.len() > 1 {
+                    &states[d..]
+                } else {
+                    &states[..]
+                };
+                let rows = (text.len() / d) as f32;
+                let mut sum = vec![0.0; d];
+                for row in text.chunks(d) {
+                    sum.iter_mut().zip(row).for_each(|(s, v)| *s += v);
+                }
+                sum.iter_mut().for_each(|s| *s /= rows);
+                sum
+            }
+        };
+        let norm = pooled.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            pooled.iter_mut().for_each(|v| *v /= norm);
+        }
+        pooled
+    }
+
+    /// Embed every text of `texts`.
+    pub fn embed_batch<S: AsRef<str>>(&self, texts: &[S], pooling: Pooling) -> Vec<Vec<f32>> {
+        texts
+            .iter()
+            .map(|text| self.embed(text.as_ref(), pooling))
+            .collect()
+    }
+}
@@ -0,0 +1,55 @@
+//! Perplexity of a language model over a text.
+//!
+//! The text is tokenized and split into windows of at most `window` tokens.
+//! Each window starts from an empty context, and every token after its first
+//! is scored by the log-probability the model assigned to it given the
+//! tokens before it.  Comparing the perplexity of the same model at
+//! different precisions shows how much accuracy quantization costs, without
+//! any gradients or labels beyond the text itself.
+
+use super::sampler::log_softmax;
+use super::tiny_lm::LanguageModel;
+
+/// Negative log-likelihood accumulated over the scored tokens of a text.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Perplexity {
+    /// Tokens scored; the first token of every window is only context.
+    pub tokens: usize,
+    /// Sum of the negative log-probabilities of the scored tokens, in nats.
+    pub nll: f64,
+}
+
+impl Perplexity {
+    /// Mean negative log-likelihood per scored token, `0` when nothing was
+    /// scored.
+    pub fn mean_nll(&self) -> f64 {
+        if self.tokens == 0 {
+            0.0
+        } else {
+            self.nll / self.tokens as f64
+        }
+    }
+
+    /// Exponential of the mean negative log-likelihood.
+    pub fn perplexity(&self) -> f64 {
+        self.mean_nll().exp()
+    }
+}
+
+/// Perplexity of `model` over `tokens`, split into windows of at most
+/// `window` tokens (at least 2).  The model is reset before every window
+/// and left reset afterwards.
+pub fn perplexity<M: LanguageModel>(model: &mut M, tokens: &[u32], window: usize) -> Perplexity {
+    let mut total = Perplexity::default();
+    for chunk in tokens.chunks(window.max(2)) {
+        model.reset();
+        let mut logits = model.forward(chunk[0]);
+        for &token in &chunk[1..] {
+            total.nll -= f64::from(log_softmax(&logits, token));
+            total.tokens += 1;
+            logits = model.forward(token);
+        }
+    }
+    model.reset();
+    total
+}
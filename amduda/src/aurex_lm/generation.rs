@@ -0,0 +1,863 @@
+//! Autoregressive text generation.
+//!
+//! [`GenerationEngine`] tokenizes a prompt, prefills the model with it and
+//! then samples tokens one at a time, streaming decoded text to a callback as
+//! soon as it forms complete characters.  When a prompt starts with tokens
+//! already in the model's KV cache, e.g. the earlier turns of a
+//! conversation, only the remaining tokens are prefilled; a [`KvCache`]
+//! snapshot lets a conversation's cache be set aside and restored later, or
+//! saved to disk, see [`kv_snapshot`](super::kv_snapshot).  A
+//! [`BatchScheduler`](super::batching::BatchScheduler) serves several
+//! requests at once, prefilling long prompts in chunks between their decode
+//! steps.
+//!
+//! Each request can bias the logits of chosen tokens, stop at any of a set of
+//! stop strings, and restrict the output to a [`Grammar`] or a
+//! [`JsonSchema`] so that, for example, tool calls are always valid JSON.
+//! Text that might be the start of a stop string is held back until it is
+//! known not to be one.
+//!
+//! With [`GenerationConfig::token_healing`] the last prompt token is backed
+//! off and regenerated under the constraint that its text comes first, so a
+//! prompt ending inside what the tokenizer would merge into one token, e.g.
+//! half a word, is continued without an artifact at the seam.
+//!
+//! With [`GenerationConfig::prompt_lookup`] the engine drafts the tokens that
+//! followed the last occurrence of the context's final n-gram, runs them in
+//! one batched forward pass and keeps those it samples anyway, see
+//! [`prompt_lookup`](super::prompt_lookup).
+//!
+//! With [`GenerationEngine::with_prefetch`] the engine also tells a
+//! [`MemoryManager`] which allocations (KV blocks, weight tensors) the next
+//! position will use, so their promotion from slower tiers overlaps with the
+//! current forward pass.  [`GenerationEngine::with_reflexion`] makes it
+//! critique and retry its own outputs, and
+//! [`GenerationEngine::with_confidence`] tracks how confident the model is in
+//! every token and aborts once that confidence collapses.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use aurex_runtime::{GenerationOptions, Priority};
+use aurex_utils::metrics::Metrics;
+use aurex_utils::profiler::ConfidenceRecord;
+
+use crate::amduda_core::memory_tiering::{AllocationId, MemoryManager};
+
+use super::confidence::{ConfidenceLevel, ConfidenceMonitor};
+use super::grammar::{Grammar, GrammarMatcher};
+use super::json_schema::JsonSchema;
+use super::prompt_lookup::PromptLookup;
+use super::reflexion::{Attempt, Reflexion};
+use super::sampler::{apply_logit_bias, Sampler, SamplingParams};
+use super::tiny_lm::LanguageModel;
+use super::tokenizer::{ByteTokenizer, StreamDecoder, EOS_TOKEN};
+
+/// Settings of a single generation request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationConfig {
+    /// Maximum number of new tokens.
+    pub max_tokens: usize,
+    pub sampling: SamplingParams,
+    /// Stop when the model emits the end-of-sequence token.
+    pub stop_at_eos: bool,
+    /// Added to the logits of these tokens before sampling, see
+    /// [`apply_logit_bias`].
+    pub logit_bias: HashMap<u32, f32>,
+    /// Stop before the first occurrence of any of these strings.  The stop
+    /// string itself is not part of the output text.
+    pub stop: Vec<String>,
+    /// Only sample tokens that keep the output a prefix of a sentence of
+    /// this grammar.
+    pub grammar: Option<Grammar>,
+    /// Only sample tokens that keep the output a prefix of a JSON document
+    /// matching this schema.  Applies in addition to `grammar`.
+    pub schema: Option<JsonSchema>,
+    /// Back off the last prompt token and only sample tokens that start
+    /// with its text until that text is covered again.  The covered prompt
+    /// text is not part of the output.
+    pub token_healing: bool,
+    /// Speculatively decode tokens drafted from the context.  The output is
+    /// the same as without.
+    pub prompt_lookup: Option<PromptLookup>,
+    /// Requests of higher priority are prefilled first by a
+    /// [`BatchScheduler`](super::batching::BatchScheduler).
+    pub priority: Priority,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 128,
+            sampling: SamplingParams::default(),
+            stop_at_eos: true,
+            logit_bias: HashMap::new(),
+            stop: Vec::new(),
+            grammar: None,
+            schema: None,
+            token_healing: false,
+            prompt_lookup: None,
+            priority: Priority::Normal,
+        }
+    }
+}
+
+impl GenerationConfig {
+    /// Settings of a request made with `options`, defaults where they leave
+    /// a setting open.
+    pub fn from_options(options: &GenerationOptions) -> Self {
+        Self::default().with_options(options)
+    }
+
+    /// Override the settings `options` choose.  Stop strings are replaced
+    /// only when `options` has some.
+    pub fn with_options(mut self, options: &GenerationOptions) -> Self {
+        if let Some(max_tokens) = options.max_tokens {
+            self.max_tokens = max_tokens;
+        }
+        let sampler = options.sampler;
+        if let Some(temperature) = sampler.temperature {
+            self.sampling.temperature = temperature;
+        }
+        if let Some(top_p) = sampler.top_p {
+            self.sampling.top_p = top_p;
+        }
+        if let Some(seed) = sampler.seed {
+            self.sampling.seed = seed;
+        }
+        if !options.stop.is_empty() {
+            self.stop = options.stop.clone();
+        }
+        self.priority = options.priority;
+        self
+    }
+}
+
+/// Why generation ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    /// `max_tokens` was reached.
+    Length,
+    /// The model produced the end-of-sequence token, or the grammar or
+    /// schema allows no further output.
+    Eos,
+    /// The output reached one of the stop strings.
+    Stop,
+    /// The model's confidence collapsed, see [`ConfidenceMonitor`].  The
+    /// token that collapsed it is not part of the output.
+    LowConfidence,
+}
+
+/// Result of [`GenerationEngine::generate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationOutput {
+    pub text: String,
+    /// Generated token ids, excluding the prompt.
+    pub tokens: Vec<u32>,
+    pub prompt_tokens: usize,
+    /// Prompt tokens reused from the KV cache instead of being prefilled.
+    pub cached_tokens: usize,
+    pub finish_reason: FinishReason,
+    /// Tokens drafted by [`GenerationConfig::prompt_lookup`].
+    pub draft_tokens: usize,
+    /// Drafted tokens that were sampled and so needed no forward pass of
+    /// their own.
+    pub accepted_draft_tokens: usize,
+}
+
+/// Progress reported by [`GenerationEngine::generate_streaming`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamEvent<'a> {
+    /// Newly decoded text, possibly empty while a character or a potential
+    /// stop string is incomplete.
+    pub text: &'a str,
+    /// Token just generated, `None` for text flushed at the end.
+    pub token: Option<u32>,
+    /// Confidence of `token` when a [`ConfidenceMonitor`] is attached.
+    pub confidence: Option<ConfidenceRecord>,
+}
+
+/// Prompt of a request, prefilled in one go or in chunks.
+#[derive(Clone)]
+pub(super) struct Prefill {
+    /// Tokens to run, without a token backed off for healing.
+    tokens: Vec<u32>,
+    /// Tokens of the prompt as given.
+    prompt_len: usize,
+    /// Text of the token backed off for healing.
+    healed: &'static [u8],
+    cached_tokens: usize,
+    /// Tokens in the KV cache so far.
+    done: usize,
+    /// Logits following the last token run.
+    logits: Vec<f32>,
+}
+
+impl Prefill {
+    /// Whether every prompt token has been run.
+    pub(super) fn is_done(&self) -> bool {
+        self.done == self.tokens.len()
+    }
+}
+
+/// Sampling state of a request, advanced one token at a time.
+pub(super) struct Decoding<'c> {
+    config: &'c GenerationConfig,
+    sampler: Sampler,
+    constraints: Vec<GrammarMatcher<'c>>,
+    stop: StopFilter<'c>,
+    decoder: StreamDecoder,
+    /// Backed-off prompt text not regenerated yet.
+    heal: &'static [u8],
+    logits: Vec<f32>,
+    /// Tokens regenerating the backed-off prompt text.
+    healing: Vec<u32>,
+    tokens: Vec<u32>,
+    text: String,
+    prompt_len: usize,
+    cached_tokens: usize,
+    /// Drafted tokens already in the KV cache with the logits following
+    /// them, to be kept while they are what gets sampled.
+    drafts: VecDeque<(u32, Vec<f32>)>,
+    drafted: usize,
+    accepted: usize,
+    /// Set once decoding has ended.
+    finish_reason: Option<FinishReason>,
+}
+
+impl Decoding<'_> {
+    /// Whether decoding has ended.
+    pub(super) fn is_finished(&self) -> bool {
+        self.finish_reason.is_some()
+    }
+
+    /// Tokens sampled so far, healing tokens included.
+    pub(super) fn sampled(&self) -> usize {
+        self.healing.len() + self.tokens.len()
+    }
+
+    /// Output of the finished request.
+    pub(super) fn output(self) -> GenerationOutput {
+        GenerationOutput {
+            text: self.text,
+            tokens: self.tokens,
+            prompt_tokens: self.prompt_len,
+            cached_tokens: self.cached_tokens,
+            finish_reason: self.finish_reason.unwrap_or(FinishReason::Length),
+            draft_tokens: self.drafted,
+            accepted_draft_tokens: self.accepted,
+        }
+    }
+}
+
+/// Holds back streamed text that may turn out to be the start of a stop
+/// string.
+struct StopFilter<'a> {
+    stop: &'a [String],
+    pending: String,
+}
+
+impl<'a> StopFilter<'a> {
+    fn new(stop: &'a [String]) -> Self {
+        Self {
+            stop,
+            pending: String::new(),
+        }
+    }
+
+    /// Add `piece` and return the text that is safe to emit, and whether a
+    /// stop string was reached.
+    fn push(&mut self, piece: &str) -> (String, bool) {
+        self.pending.push_str(piece);
+        let found = self
+            .stop
+            .iter()
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| self.pending.find(s.as_str()))
+            .min();
+        if let Some(at) = found {
+            let text = self.pending[..at].to_string();
+            self.pending.clear();
+            return (text, true);
+        }
+        // Longest suffix of the pending text that starts a stop string.
+        let held = self
+            .stop
+            .iter()
+            .filter_map(|s| {
+                (1..s.len())
+                    .rev()
+                    .find(|&k| s.is_char_boundary(k) && self.pending.ends_with(&s[..k]))
+            })
+            .max()
+            .unwrap_or(0);
+        let text = self.pending[..self.pending.len() - held].to_string();
+        self.pending.drain(..text.len());
+        (text, false)
+    }
+
+    /// Text still held back when generation ends without a stop string.
+    fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Snapshot of the KV cache of a [`GenerationEngine`]: the model and the
+/// tokens it has processed.  With copy-on-write caches such as
+/// [`TinyLm`](super::tiny_lm::TinyLm)'s, snapshots share their pages.
+#[derive(Clone)]
+pub struct KvCache<M> {
+    model: M,
+    context: Vec<u32>,
+}
+
+impl<M> KvCache<M> {
+    pub(super) fn from_parts(model: M, context: Vec<u32>) -> Self {
+        Self { model, context }
+    }
+
+    /// Model holding the cached keys and values.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// Tokens held in the cache.
+    pub fn tokens(&self) -> &[u32] {
+        &self.context
+    }
+
+    pub fn len(&self) -> usize {
+        self.context.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.context.is_empty()
+    }
+}
+
+/// Allocations used by the forward pass at a given context position.
+pub type PrefetchPlanner = Box<dyn FnMut(usize) -> Vec<AllocationId> + Send>;
+
+/// Prefetch state of a generation run.
+struct Prefetch {
+    memory: Arc<Mutex<MemoryManager>>,
+    planner: PrefetchPlanner,
+    /// Allocations hinted for the upcoming position.
+    upcoming: Option<Vec<AllocationId>>,
+}
+
+impl Prefetch {
+    /// Make the allocations of `position` ready for its forward pass and
+    /// start fetching those of `position + 1`.
+    fn step(&mut self, position: usize) {
+        let current = match self.upcoming.take() {
+            Some(ids) => ids,
+            None => (self.planner)(position),
+        };
+        let next = (self.planner)(position + 1);
+        let mut memory = self.memory.lock().unwrap();
+        for id in current {
+            memory.acquire(id);
+        }
+        memory.prefetch(&next);
+        self.upcoming = Some(next);
+    }
+}
+
+/// Drives a [`LanguageModel`] to produce text.
+pub struct GenerationEngine<M: LanguageModel> {
+    model: M,
+    /// Tokens in the model's KV cache.
+    context: Vec<u32>,
+    tokenizer: ByteTokenizer,
+    prefetch: Option<Prefetch>,
+    reflexion: Option<Arc<Reflexion>>,
+    confidence: Option<Arc<ConfidenceMonitor>>,
+}
+
+impl<M: LanguageModel> GenerationEngine<M> {
+    pub fn new(model: M) -> Self {
+        Self {
+            model,
+            context: Vec::new(),
+            tokenizer: ByteTokenizer,
+            prefetch: None,
+            reflexion: None,
+            confidence: None,
+        }
+    }
+
+    /// Prefetch the allocations `planner` names for the next context
+    /// position from `memory` while the current position is computed.
+    pub fn with_prefetch(
+        mut self,
+        memory: Arc<Mutex<MemoryManager>>,
+        planner: PrefetchPlanner,
+    ) -> Self {
+        self.prefetch = Some(Prefetch {
+            memory,
+            planner,
+            upcoming: None,
+        });
+        self
+    }
+
+    /// Critique every generation and retry low-scoring ones as configured
+    /// by `reflexion`.  The text of the returned attempt is passed to
+    /// `on_text` once it has been chosen instead of being streamed.
+    pub fn with_reflexion(mut self, reflexion: Arc<Reflexion>) -> Self {
+        self.reflexion = Some(reflexion);
+        self
+    }
+
+    /// Score every sampled token with `confidence` and stop with
+    /// [`FinishReason::LowConfidence`] once its confidence collapses.  The
+    /// monitor is reset at the start of every generation.
+    pub fn with_confidence(mut self, confidence: Arc<ConfidenceMonitor>) -> Self {
+        self.confidence = Some(confidence);
+        self
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// Mutable access to the model.  The engine no longer trusts the
+    /// model's KV cache afterwards and prefills the next prompt in full.
+    pub fn model_mut(&mut self) -> &mut M {
+        self.context.clear();
+        &mut self.model
+    }
+
+    /// Snapshot of the current KV cache.
+    pub fn kv_cache(&self) -> KvCache<M>
+    where
+        M: Clone,
+    {
+        KvCache {
+            model: self.model.clone(),
+            context: self.context.clone(),
+        }
+    }
+
+    /// Continue from `cache`, returning the cache it replaces.
+    pub fn restore_kv_cache(&mut self, cache: KvCache<M>) -> KvCache<M> {
+        KvCache {
+            model: std::mem::replace(&mut self.model, cache.model),
+            context: std::mem::replace(&mut self.context, cache.context),
+        }
+    }
+
+    /// Keep only the first `len` tokens of the KV cache, e.g. once the
+    /// prompt after them is about to change.
+    pub fn truncate_kv_cache(&mut self, len: usize) {
+        if len < self.context.len() {
+            self.truncate(len);
+        }
+    }
+
+    pub fn tokenizer(&self) -> &ByteTokenizer {
+        &self.tokenizer
+    }
+
+    /// Generate a continuation of `prompt`, calling `on_text` with every
+    /// newly decoded piece of text.
+    pub fn generate(
+        &mut self,
+        prompt: &str,
+        config: &GenerationConfig,
+        mut on_text: impl FnMut(&str),
+    ) -> GenerationOutput {
+        self.generate_streaming(prompt, config, |event| {
+            if !event.text.is_empty() {
+                on_text(event.text);
+            }
+        })
+    }
+
+    /// Like [`generate`](Self::generate), but call `on_event` for every
+    /// generated token with its confidence, even when it decodes to no text
+    /// yet.
+    pub fn generate_streaming(
+        &mut self,
+        prompt: &str,
+        config: &GenerationConfig,
+        mut on_event: impl FnMut(&StreamEvent),
+    ) -> GenerationOutput {
+        let mut prefill = self.begin(prompt, config);
+        self.prefill(&mut prefill, usize::MAX);
+        let Some(reflexion) = self.reflexion.clone() else {
+            let mut decoding = self.start_decode(prefill, config);
+            while self.decode_step(&mut decoding, &mut on_event) {}
+            return decoding.output();
+        };
+
+        let mut config = config.clone();
+        let mut best: Option<(f32, GenerationOutput)> = None;
+        for retry in 0.. {
+            if retry > 0 {
+                // Retry from the cached prompt.
+                self.truncate(prefill.tokens.len());
+                config.sampling = reflexion.adjust(config.sampling, retry);
+            }
+            let mut decoding = self.start_decode(prefill.clone(), &config);
+            while self.decode_step(&mut decoding, &mut |_| {}) {}
+            let generated: Vec<u32> = decoding
+                .healing
+                .iter()
+                .chain(&decoding.tokens)
+                .copied()
+                .collect();
+            let score = self.critique(
+                prefill.tokens.len(),
+                &generated,
+                &reflexion.policy().critique,
+            );
+            let output = decoding.output();
+            let again = reflexion.record(
+                Attempt {
+                    prompt: prompt.to_string(),
+                    text: output.text.clone(),
+                    score,
+                    sampling: config.sampling,
+                },
+                retry,
+            );
+            if best.as_ref().is_none_or(|(s, _)| score > *s) {
+                best = Some((score, output));
+            }
+            if !again {
+                break;
+            }
+        }
+        let (_, output) = best.expect("at least one attempt");
+        if !output.text.is_empty() {
+            on_event(&StreamEvent {
+                text: &output.text,
+                token: None,
+                confidence: None,
+            });
+        }
+        output
+    }
+
+    /// Score the generated `tokens` following a prompt of `prompt_len`
+    /// tokens with the `critique` prompt, see [`Reflexion::score`].
+    fn critique(&mut self, prompt_len: usize, tokens: &[u32], critique: &str) -> f32 {
+        self.truncate(prompt_len);
+        let mut logits = Vec::new();
+        for token in tokens
+            .iter()
+            .copied()
+            .chain(self.tokenizer.encode(critique))
+        {
+            logits = self.forward(token);
+        }
+        Reflexion::score(&logits)
+    }
+
+    /// Tokenize `prompt` and drop the part of the KV cache it does not
+    /// share, ready to be [`prefill`](Self::prefill)ed.
+    pub(super) fn begin(&mut self, prompt: &str, config: &GenerationConfig) -> Prefill {
+        if let Some(prefetch) = &mut self.prefetch {
+            prefetch.upcoming = None;
+        }
+        let mut tokens = self.tokenizer.encode(prompt);
+        if tokens.is_empty() {
+            // Start an empty prompt from the sequence boundary.
+            tokens.push(EOS_TOKEN);
+        }
+        let prompt_len = tokens.len();
+        let healed: &[u8] = match tokens.last() {
+            Some(&last) if config.token_healing && prompt_len > 1 => {
+                tokens.pop();
+                self.tokenizer.token_bytes(last)
+            }
+            _ => &[],
+        };
+        // Reuse the cached prefix of the prompt, but always run its last
+        // token to get the logits of the first new one.
+        let cached_tokens = self
+            .context
+            .iter()
+            .zip(&tokens)
+            .take_while(|(cached, token)| cached == token)
+            .count()
+            .min(tokens.len() - 1);
+        self.truncate(cached_tokens);
+        Prefill {
+            tokens,
+            prompt_len,
+            healed,
+            cached_tokens,
+            done: cached_tokens,
+            logits: Vec::new(),
+        }
+    }
+
+    /// Run up to `budget` more tokens of `prefill`.  Returns the number of
+    /// tokens run.
+    pub(super) fn prefill(&mut self, prefill: &mut Prefill, budget: usize) -> usize {
+        let start = prefill.done;
+        let end = prefill.tokens.len().min(start.saturating_add(budget));
+        for &token in &prefill.tokens[start..end] {
+            prefill.logits = self.forward(token);
+        }
+        prefill.done = end;
+        end - start
+    }
+
+    /// Start sampling after the prefilled prompt of `prefill`.
+    pub(super) fn start_decode<'c>(
+        &self,
+        prefill: Prefill,
+        config: &'c GenerationConfig,
+    ) -> Decoding<'c> {
+        if let Some(confidence) = &self.confidence {
+            confidence.reset();
+        }
+        Decoding {
+            config,
+            sampler: Sampler::new(config.sampling),
+            constraints: config
+                .grammar
+                .iter()
+                .chain(config.schema.as_ref().map(JsonSchema::grammar))
+                .map(GrammarMatcher::new)
+                .collect(),
+            stop: StopFilter::new(&config.stop),
+            decoder: StreamDecoder::new(),
+            heal: prefill.healed,
+            logits: prefill.logits,
+            healing: Vec::new(),
+            tokens: Vec::new(),
+            text: String::new(),
+            prompt_len: prefill.prompt_len,
+            cached_tokens: prefill.cached_tokens,
+            drafts: VecDeque::new(),
+            drafted: 0,
+            accepted: 0,
+            finish_reason: None,
+        }
+    }
+
+    /// Sample the next token of `state`, first regenerating its backed-off
+    /// prompt text.  Returns `false` once decoding has ended.
+    pub(super) fn decode_step(
+        &mut self,
+        state: &mut Decoding,
+        on_event: &mut dyn FnMut(&StreamEvent),
+    ) -> bool {
+        if state.finish_reason.is_some() {
+            return false;
+        }
+        let config = state.config;
+        if state.tokens.len() >= config.max_tokens {
+            self.finish_decode(state, FinishReason::Length, on_event);
+            return false;
+        }
+        let started = Instant::now();
+        let logits = &mut state.logits;
+        apply_logit_bias(logits, &config.logit_bias);
+        if !state.heal.is_empty() {
+            heal_mask(logits, state.heal, |t| self.tokenizer.token_bytes(t));
+            let next = state.sampler.sample(logits);
+            let bytes = self.tokenizer.token_bytes(next);
+            let covered = bytes.len().min(state.heal.len());
+            state.heal = &state.heal[covered..];
+            if covered == bytes.len() {
+                // The token only restores prompt text.
+                state.healing.push(next);
+                state.logits = self.forward(next);
+                return true;
+            }
+            // The token extends past the prompt; only its remainder is
+            // output and seen by the constraints.
+            let rest = &bytes[covered..];
+            for constraint in &mut state.constraints {
+                for &byte in rest {
+                    constraint.accept(byte);
+                }
+            }
+            state.tokens.push(next);
+            let (piece, stopped) = state.stop.push(&state.decoder.push_bytes(rest));
+            let event = StreamEvent {
+                text: &piece,
+                token: Some(next),
+                confidence: None,
+            };
+            emit(on_event, &mut state.text, event);
+            if stopped {
+                self.finish_decode(state, FinishReason::Stop, on_event);
+                return false;
+            }
+            if state.tokens.len() < config.max_tokens {
+                state.logits = self.forward(next);
+            }
+            return true;
+        }
+        if state.constraints.iter().any(|c| !c.can_continue()) {
+            self.finish_decode(state, FinishReason::Eos, on_event);
+            return false;
+        }
+        for constraint in &state.constraints {
+            constraint.mask(logits);
+        }
+        let next = state.sampler.sample(logits);
+        if config.stop_at_eos && next == EOS_TOKEN {
+            self.finish_decode(state, FinishReason::Eos, on_event);
+            return false;
+        }
+        let confidence = self.confidence.as_ref().map(|monitor| {
+            let record = monitor.observe(logits, next);
+            (monitor.level(), record)
+        });
+        if let Some((ConfidenceLevel::Collapsed, _)) = confidence {
+            self.finish_decode(state, FinishReason::LowConfidence, on_event);
+            return false;
+        }
+        for constraint in &mut state.constraints {
+            constraint.accept_token(next);
+        }
+        state.tokens.push(next);
+        let (piece, stopped) = state.stop.push(&state.decoder.push(next));
+        let event = StreamEvent {
+            text: &piece,
+            token: Some(next),
+            confidence: confidence.map(|(_, record)| record),
+        };
+        emit(on_event, &mut state.text, event);
+        if !stopped && state.tokens.len() < config.max_tokens {
+            state.logits = self.advance(state, next);
+        }
+        let metrics = Metrics::global();
+        metrics.record_tokens(1);
+        metrics.observe_latency(started.elapsed());
+        if stopped {
+            self.finish_decode(state, FinishReason::Stop, on_event);
+            return false;
+        }
+        true
+    }
+
+    /// Logits following the sampled `next`: those of the first draft of
+    /// `state` if it matches, else of a forward pass of `next` followed by
+    /// a new draft.
+    fn advance(&mut self, state: &mut Decoding, next: u32) -> Vec<f32> {
+        if let Some((draft, logits)) = state.drafts.pop_front() {
+            if draft == next {
+                state.accepted += 1;
+                return logits;
+            }
+            self.truncate(self.context.len() - state.drafts.len() - 1);
+            state.drafts.clear();
+        }
+        let Some(lookup) = state.config.prompt_lookup else {
+            return self.forward(next);
+        };
+        // Drafts are only worth running while tokens remain to sample
+        // after them.
+        let limit = state.config.max_tokens - state.tokens.len() - 1;
+        let history = [self.context.as_slice(), &[next]].concat();
+        let draft = lookup.draft(&history, limit);
+        if draft.is_empty() {
+            return self.forward(next);
+        }
+        let batch: Vec<u32> = std::iter::once(next).chain(draft.iter().copied()).collect();
+        let mut logits = self.forward_batch(&batch).into_iter();
+        let first = logits.next().expect("logits of the sampled token");
+        state.drafted += draft.len();
+        state.drafts = batch[1..].iter().copied().zip(logits).collect();
+        first
+    }
+
+    /// End decoding of `state` for `reason`, flushing text held back.
+    fn finish_decode(
+        &mut self,
+        state: &mut Decoding,
+        mut reason: FinishReason,
+        on_event: &mut dyn FnMut(&StreamEvent),
+    ) {
+        if reason != FinishReason::Stop {
+            let (piece, stopped) = state.stop.push(&state.decoder.finish());
+            emit(on_event, &mut state.text, flushed(&piece));
+            if stopped {
+                reason = FinishReason::Stop;
+            } else {
+                let rest = state.stop.finish();
+                emit(on_event, &mut state.text, flushed(&rest));
+            }
+        }
+        // Drop drafts that were never sampled from the KV cache.
+        if !state.drafts.is_empty() {
+            self.truncate(self.context.len() - state.drafts.len());
+            state.drafts.clear();
+        }
+        state.finish_reason = Some(reason);
+    }
+
+    fn forward(&mut self, token: u32) -> Vec<f32> {
+        if let Some(prefetch) = &mut self.prefetch {
+            prefetch.step(self.model.context_len());
+        }
+        self.context.push(token);
+        self.model.forward(token)
+    }
+
+    /// Run `tokens` in one batch, see [`LanguageModel::forward_batch`].
+    fn forward_batch(&mut self, tokens: &[u32]) -> Vec<Vec<f32>> {
+        if let Some(prefetch) = &mut self.prefetch {
+            let position = self.model.context_len();
+            for i in 0..tokens.len() {
+                prefetch.step(position + i);
+            }
+        }
+        self.context.extend_from_slice(tokens);
+        self.model.forward_batch(tokens)
+    }
+
+    /// Drop all but the first `len` tokens from the KV cache.
+    fn truncate(&mut self, len: usize) {
+        if let Some(prefetch) = &mut self.prefetch {
+            // Hints for the dropped positions no longer apply.
+            prefetch.upcoming = None;
+        }
+        if len == 0 {
+            self.model.reset();
+        } else {
+            self.model.truncate(len);
+        }
+        self.context.truncate(len);
+    }
+}
+
+/// Pass `event` on unless it carries nothing, and append its text to
+/// `text`.
+fn emit(on_event: &mut dyn FnMut(&StreamEvent), text: &mut String, event: StreamEvent) {
+    if !event.text.is_empty() || event.token.is_some() {
+        on_event(&event);
+        text.push_str(event.text);
+    }
+}
+
+/// Event of text flushed when decoding ends.
+fn flushed(text: &str) -> StreamEvent<'_> {
+    StreamEvent {
+        text,
+        token: None,
+        confidence: None,
+    }
+}
+
+/// Set the logits of all tokens whose text neither starts `heal` nor
+/// starts with it to negative infinity.
+fn heal_mask<'a>(logits: &mut [f32], heal: &[u8], token_bytes: impl Fn(u32) -> &'a [u8]) {
+    for (token, logit) in logits.iter_mut().enumerate() {
+        let bytes = token_bytes(token as u32);
+        let consistent = !bytes.is_empty() && (heal.starts_with(bytes) || bytes.starts_with(heal));
+        if !consistent {
+            *logit = f32::NEG_INFINITY;
+        }
+    }
+}
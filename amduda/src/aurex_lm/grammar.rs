@@ -0,0 +1,596 @@
+//! Grammar-constrained decoding.
+//!
+//! [`Grammar`] parses a subset of the GBNF notation used by llama.cpp:
+//!
+//! ```text
+//! root   ::= "{" ws "\"name\":" ws string ws "}"
+//! string ::= "\"" [^"\\]* "\""
+//! ws     ::= [ \t\n]*
+//! ```
+//!
+//! Rules are `name ::= alternatives`, alternatives are separated by `|` and
+//! consist of string literals, character classes (`[a-z]`, `[^"]`), `.` for
+//! any byte, rule references and parenthesised groups, each optionally
+//! followed by `*`, `+` or `?`.  `#` starts a comment.  Generation starts at
+//! the rule named `root`.  Left-recursive rules are rejected.
+//!
+//! Literals and classes match bytes, which lines up with the byte tokenizer:
+//! a [`GrammarMatcher`] tracks the positions the output so far can be in and
+//! masks every token that would leave the grammar.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::tokenizer::{BYTE_TOKENS, EOS_TOKEN};
+
+/// Error raised when parsing a [`Grammar`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    /// Malformed grammar text at byte `offset`.
+    Syntax { offset: usize, message: String },
+    /// A rule is referenced but never defined.
+    UndefinedRule(String),
+    /// A rule is defined twice.
+    DuplicateRule(String),
+    /// The grammar has no `root` rule.
+    MissingRoot,
+    /// The rule can reach itself without consuming input.
+    LeftRecursion(String),
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrammarError::Syntax { offset, message } => {
+                write!(f, "grammar syntax error at byte {offset}: {message}")
+            }
+            GrammarError::UndefinedRule(name) => write!(f, "undefined grammar rule '{name}'"),
+            GrammarError::DuplicateRule(name) => write!(f, "grammar rule '{name}' defined twice"),
+            GrammarError::MissingRoot => write!(f, "grammar has no 'root' rule"),
+            GrammarError::LeftRecursion(name) => {
+                write!(f, "grammar rule '{name}' is left-recursive")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Element {
+    /// One byte within `ranges`, or outside them when `negated`.
+    Bytes {
+        ranges: Vec<(u8, u8)>,
+        negated: bool,
+    },
+    Rule(usize),
+}
+
+impl Element {
+    fn byte(b: u8) -> Self {
+        Element::Bytes {
+            ranges: vec![(b, b)],
+            negated: false,
+        }
+    }
+
+    fn matches(ranges: &[(u8, u8)], negated: bool, b: u8) -> bool {
+        ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&b)) != negated
+    }
+}
+
+type Alternative = Vec<Element>;
+
+/// Parsed grammar, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grammar {
+    rules: Vec<Vec<Alternative>>,
+    names: Vec<String>,
+    root: usize,
+}
+
+impl Grammar {
+    /// Parse GBNF `text`.
+    pub fn parse(text: &str) -> Result<Self, GrammarError> {
+        Parser::new(text).parse()
+    }
+
+    /// Number of rules, including those generated for groups and
+    /// repetitions.
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    fn check_left_recursion(&self) -> Result<(), GrammarError> {
+        let mut nullable = vec![false; self.rules.len()];
+        loop {
+            let mut changed = false;
+            for (rule, alts) in self.rules.iter().enumerate() {
+                if nullable[rule] {
+                    continue;
+                }
+                let empty = alts.iter().any(|alt| {
+                    alt.iter()
+                        .all(|e| matches!(e, Element::Rule(r) if nullable[*r]))
+                });
+                if empty {
+                    nullable[rule] = true;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        // Rules each rule can start with before consuming a byte.
+        let starts: Vec<Vec<usize>> = self
+            .rules
+            .iter()
+            .map(|alts| {
+                let mut out = Vec::new();
+                for alt in alts {
+                    for element in alt {
+                        match element {
+                            Element::Rule(r) => {
+                                out.push(*r);
+                                if !nullable[*r] {
+                                    break;
+                                }
+                            }
+                            Element::Bytes { .. } => break,
+                        }
+                    }
+                }
+                out
+            })
+            .collect();
+
+        // 0 = unvisited, 1 = on the DFS path, 2 = done.
+        let mut state = vec![0u8; self.rules.len()];
+        fn visit(rule: usize, starts: &[Vec<usize>], state: &mut [u8]) -> Option<usize> {
+            match state[rule] {
+                1 => return Some(rule),
+                2 => return None,
+                _ => {}
+            }
+            state[rule] = 1;
+            for &next in &starts[rule] {
+                if let Some(cycle) = visit(next, starts, state) {
+                    return Some(cycle);
+                }
+            }
+            state[rule] = 2;
+            None
+        }
+        for rule in 0..self.rules.len() {
+            if let Some(cycle) = visit(rule, &starts, &mut state) {
+                return Err(GrammarError::LeftRecursion(self.names[cycle].clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    rules: Vec<Option<Vec<Alternative>>>,
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            src: text.as_bytes(),
+            pos: 0,
+            rules: Vec::new(),
+            names: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    fn parse(mut self) -> Result<Grammar, GrammarError> {
+        self.skip_space();
+        while self.pos < self.src.len() {
+            let name = self.ident()?;
+            self.skip_space();
+            if !self.src[self.pos..].starts_with(b"::=") {
+                return Err(self.error("expected '::='"));
+            }
+            self.pos += 3;
+            let alts = self.alternatives()?;
+            let id = self.rule_id(&name);
+            if self.rules[id].is_some() {
+                return Err(GrammarError::DuplicateRule(name));
+            }
+            self.rules[id] = Some(alts);
+            self.skip_space();
+        }
+
+        let root = *self.ids.get("root").ok_or(GrammarError::MissingRoot)?;
+        let mut rules = Vec::with_capacity(self.rules.len());
+        for (id, rule) in self.rules.into_iter().enumerate() {
+            rules.push(rule.ok_or_else(|| GrammarError::UndefinedRule(self.names[id].clone()))?);
+        }
+        let grammar = Grammar {
+            rules,
+            names: self.names,
+            root,
+        };
+        grammar.check_left_recursion()?;
+        Ok(grammar)
+    }
+
+    fn error(&self, message: &str) -> GrammarError {
+        GrammarError::Syntax {
+            offset: self.pos,
+            message: message.to_string(),
+        }
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        self.add_rule(name.to_string(), None)
+    }
+
+    fn add_rule(&mut self, name: String, alts: Option<Vec<Alternative>>) -> usize {
+        let id = self.rules.len();
+        self.rules.push(alts);
+        self.ids.insert(name.clone(), id);
+        self.names.push(name);
+        id
+    }
+
+    /// Rule for a group or repetition.
+    fn generated_rule(&mut self, alts: Vec<Alternative>) -> usize {
+        let name = format!("<{}>", self.rules.len());
+        self.add_rule(name, Some(alts))
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(&c) = self.src.get(self.pos) {
+            if c == b'#' {
+                while self.pos < self.src.len() && self.src[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn is_ident_byte(c: u8) -> bool {
+        c.is_ascii_alphanumeric() || c == b'_' || c == b'-'
+    }
+
+    fn ident(&mut self) -> Result<String, GrammarError> {
+        let start = self.pos;
+        while self.pos < self.src.len() && Self::is_ident_byte(self.src[self.pos]) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("expected a rule name"));
+        }
+        Ok(String::from_utf8_lossy(&self.src[start..self.pos]).into_owned())
+    }
+
+    /// Whether the next token starts a new rule definition.
+    fn at_rule_start(&self) -> bool {
+        let mut pos = self.pos;
+        while pos < self.src.len() && Self::is_ident_byte(self.src[pos]) {
+            pos += 1;
+        }
+        if pos == self.pos {
+            return false;
+        }
+        while pos < self.src.len() && self.src[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        self.src[pos..].starts_with(b"::=")
+    }
+
+    fn alternatives(&mut self) -> Result<Vec<Alternative>, GrammarError> {
+        let mut alts = vec![self.sequence()?];
+        while self.src.get(self.pos) == Some(&b'|') {
+            self.pos += 1;
+            alts.push(self.sequence()?);
+        }
+        Ok(alts)
+    }
+
+    fn sequence(&mut self) -> Result<Alternative, GrammarError> {
+        let mut seq = Vec::new();
+        loop {
+            self.skip_space();
+            let Some(&c) = self.src.get(self.pos) else {
+                break;
+            };
+            let mut elements = match c {
+                b'|' | b')' => break,
+                _ if self.at_rule_start() => break,
+                b'"' => self.literal()?,
+                b'[' => vec![self.class()?],
+                b'.' => {
+                    self.pos += 1;
+                    vec![Element::Bytes {
+                        ranges: Vec::new(),
+                        negated: true,
+                    }]
+                }
+                b'(' => {
+                    self.pos += 1;
+                    let alts = self.alternatives()?;
+                    if self.src.get(self.pos) != Some(&b')') {
+                        return Err(self.error("expected ')'"));
+                    }
+                    self.pos += 1;
+                    vec![Element::Rule(self.generated_rule(alts))]
+                }
+                _ if Self::is_ident_byte(c) => {
+                    let name = self.ident()?;
+                    vec![Element::Rule(self.rule_id(&name))]
+                }
+                _ => return Err(self.error("unexpected character")),
+            };
+            if let Some(&op @ (b'*' | b'+' | b'?')) = self.src.get(self.pos) {
+                self.pos += 1;
+                let item = if elements.len() == 1 {
+                    elements.pop().unwrap()
+                } else {
+                    Element::Rule(self.generated_rule(vec![elements]))
+                };
+                elements = vec![self.repeat(item, op)];
+            }
+            seq.extend(elements);
+        }
+        Ok(seq)
+    }
+
+    /// Rule matching `item` repeated as `op` (`*`, `+` or `?`) says.
+    fn repeat(&mut self, item: Element, op: u8) -> Element {
+        let id = self.generated_rule(Vec::new());
+        let alts = match op {
+            b'*' => vec![vec![item, Element::Rule(id)], Vec::new()],
+            b'+' => vec![vec![item.clone(), Element::Rule(id)], vec![item]],
+            _ => vec![vec![item], Vec::new()],
+        };
+        self.rules[id] = Some(alts);
+        Element::Rule(id)
+    }
+
+    fn literal(&mut self) -> Result<Vec<Element>, GrammarError> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            match self.src.get(self.pos) {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(_) => out.push(Element::byte(self.byte()?)),
+            }
+        }
+    }
+
+    fn class(&mut self) -> Result<Element, GrammarError> {
+        self.pos += 1;
+        let negated = self.src.get(self.pos) == Some(&b'^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        loop {
+            match self.src.get(self.pos) {
+                None => return Err(self.error("unterminated character class")),
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Element::Bytes { ranges, negated });
+                }
+                Some(c) if !c.is_ascii() => {
+                    return Err(
+                        self.error("character classes match single bytes, use '\\x' escapes")
+                    );
+                }
+                Some(_) => {
+                    let lo = self.byte()?;
+                    let hi = if self.src.get(self.pos) == Some(&b'-')
+                        && self.src.get(self.pos + 1).is_some_and(|&c| c != b']')
+                    {
+                        self.pos += 1;
+                        self.byte()?
+                    } else {
+                        lo
+                    };
+                    if hi < lo {
+                        return Err(self.error("empty character range"));
+                    }
+                    ranges.push((lo, hi));
+                }
+            }
+        }
+    }
+
+    /// One possibly escaped byte of a literal or class.
+    fn byte(&mut self) -> Result<u8, GrammarError> {
+        let c = self.src[self.pos];
+        self.pos += 1;
+        if c != b'\\' {
+            return Ok(c);
+        }
+        let Some(&e) = self.src.get(self.pos) else {
+            return Err(self.error("unterminated escape"));
+        };
+        self.pos += 1;
+        Ok(match e {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'x' => {
+                let hex = self
+                    .src
+                    .get(self.pos..self.pos + 2)
+                    .and_then(|h| std::str::from_utf8(h).ok())
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| self.error("expected two hex digits after '\\x'"))?;
+                self.pos += 2;
+                hex
+            }
+            other => other,
+        })
+    }
+}
+
+/// Position within an alternative of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Pos {
+    rule: usize,
+    alt: usize,
+    idx: usize,
+}
+
+/// Tracks a partial output against a [`Grammar`].
+///
+/// Every stack lists the positions still to complete, innermost rule last;
+/// the top of a non-empty stack always waits for a byte, and an empty stack
+/// means the output is a complete sentence of the grammar.
+#[derive(Debug, Clone)]
+pub struct GrammarMatcher<'g> {
+    grammar: &'g Grammar,
+    stacks: Vec<Vec<Pos>>,
+}
+
+impl<'g> GrammarMatcher<'g> {
+    pub fn new(grammar: &'g Grammar) -> Self {
+        let mut stacks = Vec::new();
+        for alt in 0..grammar.rules[grammar.root].len() {
+            let start = Pos {
+                rule: grammar.root,
+                alt,
+                idx: 0,
+            };
+            expand(grammar, vec![start], &mut stacks);
+        }
+        stacks.sort_unstable();
+        stacks.dedup();
+        Self { grammar, stacks }
+    }
+
+    /// Whether the output so far is a complete sentence of the grammar.
+    pub fn is_complete(&self) -> bool {
+        self.stacks.iter().any(Vec::is_empty)
+    }
+
+    /// Whether any byte can extend the output.
+    pub fn can_continue(&self) -> bool {
+        self.stacks.iter().any(|s| !s.is_empty())
+    }
+
+    /// Whether `byte` can extend the output.
+    pub fn allows(&self, byte: u8) -> bool {
+        self.stacks
+            .iter()
+            .any(|stack| self.top_matches(stack, byte))
+    }
+
+    /// Extend the output by `byte`.  Returns `false` and leaves the state
+    /// unchanged if the grammar does not allow it.
+    pub fn accept(&mut self, byte: u8) -> bool {
+        let mut next = Vec::new();
+        for stack in &self.stacks {
+            if self.top_matches(stack, byte) {
+                let mut stack = stack.clone();
+                stack.last_mut().unwrap().idx += 1;
+                expand(self.grammar, stack, &mut next);
+            }
+        }
+        if next.is_empty() {
+            return false;
+        }
+        next.sort_unstable();
+        next.dedup();
+        self.stacks = next;
+        true
+    }
+
+    /// Extend the output by a token of the byte tokenizer.  The
+    /// end-of-sequence token is accepted once the output is complete.
+    pub fn accept_token(&mut self, token: u32) -> bool {
+        match token {
+            t if t < BYTE_TOKENS => self.accept(t as u8),
+            EOS_TOKEN => self.is_complete(),
+            _ => false,
+        }
+    }
+
+    /// Set the logits of all tokens the grammar does not allow next to
+    /// negative infinity.
+    pub fn mask(&self, logits: &mut [f32]) {
+        let mut allowed = [false; BYTE_TOKENS as usize];
+        for stack in &self.stacks {
+            if let Some(Element::Bytes { ranges, negated }) = self.top(stack) {
+                for (b, ok) in allowed.iter_mut().enumerate() {
+                    *ok = *ok || Element::matches(ranges, *negated, b as u8);
+                }
+            }
+        }
+        let complete = self.is_complete();
+        for (token, logit) in logits.iter_mut().enumerate() {
+            let ok = match token {
+                t if t < allowed.len() => allowed[t],
+                t if t == EOS_TOKEN as usize => complete,
+                _ => false,
+            };
+            if !ok {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+    }
+
+    fn top(&self, stack: &[Pos]) -> Option<&'g Element> {
+        let pos = stack.last()?;
+        Some(&self.grammar.rules[pos.rule][pos.alt][pos.idx])
+    }
+
+    fn top_matches(&self, stack: &[Pos], byte: u8) -> bool {
+        match self.top(stack) {
+            Some(Element::Bytes { ranges, negated }) => Element::matches(ranges, *negated, byte),
+            _ => false,
+        }
+    }
+}
+
+/// Resolve the top of `stack` until it waits for a byte or is empty and add
+/// the resulting stacks to `out`.
+fn expand(grammar: &Grammar, mut stack: Vec<Pos>, out: &mut Vec<Vec<Pos>>) {
+    let finished = |pos: &Pos| pos.idx == grammar.rules[pos.rule][pos.alt].len();
+    while stack.last().is_some_and(finished) {
+        stack.pop();
+    }
+    let Some(&top) = stack.last() else {
+        out.push(stack);
+        return;
+    };
+    match grammar.rules[top.rule][top.alt][top.idx] {
+        Element::Bytes { .. } => out.push(stack),
+        Element::Rule(rule) => {
+            stack.last_mut().unwrap().idx += 1;
+            // Dropping finished callers first keeps repetitions from growing
+            // the stack.
+            while stack.last().is_some_and(finished) {
+                stack.pop();
+            }
+            for alt in 0..grammar.rules[rule].len() {
+                let mut next = stack.clone();
+                next.push(Pos { rule, alt, idx: 0 });
+                expand(grammar, next, out);
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! Branching generation (tree of thought).
+//!
+//! [`BranchSearch`] forks a generation into several candidate branches,
+//! extends each by a segment of tokens, scores them by their mean token
+//! log-probability, prunes all but the best few and repeats until the
+//! branches end or `max_tokens` is reached.  The best branch is committed:
+//! its context becomes the model's and its text the output.
+//!
+//! Branches are clones of the model, so with [`TinyLm`] they see the prompt
+//! through shared copy-on-write KV cache pages.  How many branches may be
+//! explored is bounded by [`EffortBudget::branch_limit`].
+//!
+//! Every branch keeps the KV cache of the tokens it generated.  With
+//! [`Recompute`] active, surviving branches drop that state after each
+//! pruning round and keep only their tokens; the state is recomputed from
+//! the prompt checkpoint when the branch is forked again or committed.  This
+//! trades a replay of the branch tokens for the memory of the branch caches,
+//! and can be switched on by a [`MemoryManager`] pressure handler.
+//!
+//! As a [`HypothesisManager`], `BranchSearch` turns the next event after a
+//! commit that abandoned the previously leading branch into
+//! [`RuntimeEvent::Rollback`], since tokens streamed from that branch are no
+//! longer part of the output.
+//!
+//! [`TinyLm`]: super::tiny_lm::TinyLm
+//! [`MemoryManager`]: crate::amduda_core::memory_tiering::MemoryManager
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use aurex_runtime::{EffortBudget, HypothesisManager, RuntimeEvent};
+
+use crate::amduda_core::memory_tiering::PressureHandler;
+
+use super::generation::{FinishReason, GenerationConfig, GenerationOutput};
+use super::sampler::{apply_logit_bias, log_softmax, Sampler, SamplingParams};
+use super::tiny_lm::LanguageModel;
+use super::tokenizer::{ByteTokenizer, EOS_TOKEN};
+
+/// Shape of the search tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BranchPolicy {
+    /// Children forked from every surviving branch per round.
+    pub width: usize,
+    /// Branches kept after pruning.
+    pub beam: usize,
+    /// Tokens generated per branch and round.
+    pub segment: usize,
+}
+
+impl Default for BranchPolicy {
+    fn default() -> Self {
+        Self {
+            width: 3,
+            beam: 2,
+            segment: 8,
+        }
+    }
+}
+
+/// Counters of a [`BranchSearch`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    /// Branches forked.
+    pub forks: usize,
+    /// Branches dropped by pruning.
+    pub pruned: usize,
+    /// Completed pruning rounds.
+    pub rounds: usize,
+    /// Branch states dropped for recomputation.
+    pub discarded: usize,
+    /// Tokens replayed to recompute dropped branch states.
+    pub recomputed: usize,
+}
+
+/// Switch making branches drop their model state between rounds, see the
+/// [module documentation](self).  Clones share the switch.
+#[derive(Debug, Clone, Default)]
+pub struct Recompute {
+    active: Arc<AtomicBool>,
+}
+
+impl Recompute {
+    /// Recomputation that is off until activated.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recomputation that is on from the start.
+    pub fn always() -> Self {
+        let recompute = Self::new();
+        recompute.set_active(true);
+        recompute
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn set_active(&self, active: bool) {
+        self.active.store(active, Ordering::Relaxed);
+    }
+
+    /// Pressure handler turning recomputation on.  The branch caches are
+    /// not tracked by the manager, so it returns no allocations.
+    pub fn pressure_handler(&self) -> PressureHandler {
+        let recompute = self.clone();
+        Box::new(move |pressure| {
+            if !recompute.active.swap(true, Ordering::Relaxed) {
+                tracing::debug!(tier = ?pressure.tier, "recomputing branch states");
+            }
+            Vec::new()
+        })
+    }
+}
+
+struct Branch<M> {
+    /// `None` once dropped for recomputation.
+    model: Option<M>,
+    logits: Vec<f32>,
+    tokens: Vec<u32>,
+    logprob: f32,
+    finish: Option<FinishReason>,
+}
+
+impl<M> Branch<M> {
+    fn score(&self) -> f32 {
+        if self.tokens.is_empty() {
+            0.0
+        } else {
+            self.logprob / self.tokens.len() as f32
+        }
+    }
+}
+
+/// Branching generation policy, see the [module documentation](self).
+#[derive(Debug, Default)]
+pub struct BranchSearch {
+    policy: BranchPolicy,
+    stats: Mutex<BranchStats>,
+    switched: AtomicBool,
+    recompute: Recompute,
+}
+
+impl BranchSearch {
+    pub fn new(policy: BranchPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Drop and recompute branch states while `recompute` is active.
+    pub fn with_recompute(mut self, recompute: Recompute) -> Self {
+        self.recompute = recompute;
+        self
+    }
+
+    pub fn policy(&self) -> BranchPolicy {
+        self.policy
+    }
+
+    pub fn recompute(&self) -> &Recompute {
+        &self.recompute
+    }
+
+    /// Counters accumulated over all searches.
+    pub fn stats(&self) -> BranchStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Generate a continuation of `prompt` by branching search and commit
+    /// the best branch to `model`.  Sampling, `max_tokens`, `stop_at_eos`
+    /// and `logit_bias` of `config` apply; stop strings and grammar
+    /// constraints are not supported here.
+    pub fn search<M: LanguageModel + Clone>(
+        &self,
+        model: &mut M,
+        prompt: &str,
+        config: &GenerationConfig,
+        budget: &EffortBudget,
+    ) -> GenerationOutput {
+        let tokenizer = ByteTokenizer;
+        model.reset();
+        let mut prompt_tokens = tokenizer.encode(prompt);
+        if prompt_tokens.is_empty() {
+            prompt_tokens.push(EOS_TOKEN);
+        }
+        let mut logits = Vec::new();
+        for &token in &prompt_tokens {
+            logits = model.forward(token);
+        }
+
+        let width = budget.branch_limit(self.policy.width.max(1));
+        let beam = self.policy.beam.clamp(1, width);
+        let segment = self.policy.segment.max(1);
+        let checkpoint = model.clone();
+        let mut branches = vec![Branch {
+            model: Some(model.clone()),
+            logits,
+            tokens: Vec::new(),
+            logprob: 0.0,
+            finish: None,
+        }];
+        let mut leader: Vec<u32> = Vec::new();
+        let mut seed = config.sampling.seed;
+        while branches.iter().any(|b| b.finish.is_none()) {
+            let mut children = Vec::new();
+            for branch in branches {
+                if branch.finish.is_some() {
+                    children.push(branch);
+                    continue;
+                }
+                let parent = match branch.model {
+                    Some(model) => model,
+                    None => self.replay(&checkpoint, &branch.tokens),
+                };
+                for _ in 0..width {
+                    let sampling = SamplingParams {
+                        seed,
+                        ..config.sampling
+                    };
+                    seed = seed.wrapping_add(1);
+                    let mut child = Branch {
+                        model: Some(parent.clone()),
+                        logits: branch.logits.clone(),
+                        tokens: branch.tokens.clone(),
+                        logprob: branch.logprob,
+                        finish: None,
+                    };
+                    extend(&mut child, config, sampling, segment);
+                    children.push(child);
+                }
+            }
+
+            children.sort_by(|a, b| b.score().total_cmp(&a.score()));
+            let mut stats = self.stats.lock().unwrap();
+            stats.forks += children.len();
+            stats.pruned += children.len().saturating_sub(beam);
+            stats.rounds += 1;
+            children.truncate(beam);
+            if self.recompute.is_active() {
+                for child in &mut children {
+                    stats.discarded += usize::from(child.model.take().is_some());
+                }
+            }
+            if !children[0].tokens.starts_with(&leader) {
+                self.switched.store(true, Ordering::SeqCst);
+            }
+            leader = children[0].tokens.clone();
+            branches = children;
+        }
+
+        let winner = branches.swap_remove(0);
+        *model = match winner.model {
+            Some(model) => model,
+            None => self.replay(&checkpoint, &winner.tokens),
+        };
+        GenerationOutput {
+            text: tokenizer.decode(&winner.tokens),
+            tokens: winner.tokens,
+            prompt_tokens: prompt_tokens.len(),
+            cached_tokens: 0,
+            finish_reason: winner.finish.unwrap_or(FinishReason::Length),
+            draft_tokens: 0,
+            accepted_draft_tokens: 0,
+        }
+    }
+
+    /// State of a branch that generated `tokens` after `checkpoint`.
+    fn replay<M: LanguageModel + Clone>(&self, checkpoint: &M, tokens: &[u32]) -> M {
+        let mut model = checkpoint.clone();
+        if !tokens.is_empty() {
+            model.forward_batch(tokens);
+        }
+        self.stats.lock().unwrap().recomputed += tokens.len();
+        model
+    }
+}
+
+/// Sample up to `segment` tokens on `branch`.
+fn extend<M: LanguageModel>(
+    branch: &mut Branch<M>,
+    config: &GenerationConfig,
+    sampling: SamplingParams,
+    segment: usize,
+) {
+    let mut sampler = Sampler::new(sampling);
+    for _ in 0..segment {
+        if branch.tokens.len() >= config.max_tokens {
+            branch.finish = Some(FinishReason::Length);
+            return;
+        }
+        apply_logit_bias(&mut branch.logits, &config.logit_bias);
+        let next = sampler.sample(&branch.logits);
+        branch.logprob += log_softmax(&branch.logits, next);
+        if config.stop_at_eos && next == EOS_TOKEN {
+            branch.finish = Some(FinishReason::Eos);
+            return;
+        }
+        branch.tokens.push(next);
+        let model = branch
+            .model
+            .as_mut()
+            .expect("extended branches have a model");
+        branch.logits = model.forward(next);
+    }
+    if branch.tokens.len() >= config.max_tokens {
+        branch.finish = Some(FinishReason::Length);
+    }
+}
+
+#[async_trait]
+impl HypothesisManager for BranchSearch {
+    async fn manage(&self, event: &RuntimeEvent) -> RuntimeEvent {
+        if self.switched.swap(false, Ordering::SeqCst) {
+            RuntimeEvent::Rollback
+        } else {
+            event.clone()
+        }
+    }
+}
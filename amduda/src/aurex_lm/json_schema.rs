@@ -0,0 +1,340 @@
+//! JSON-schema constrained output.
+//!
+//! [`JsonSchema::compile`] translates a JSON schema into a [`Grammar`] whose
+//! sentences are compact JSON documents matching the schema, so generation
+//! with [`GenerationConfig::schema`] always yields parseable output.
+//!
+//! Supported keywords are `type` (a name or a list of names), `properties`
+//! and `required` for objects, `items`, `minItems` and `maxItems` for arrays,
+//! `enum`, `const`, `anyOf`, `oneOf`, a single-entry `allOf` and local
+//! `$ref`s such as `#/$defs/node`.  Properties are emitted in key order and
+//! objects without `properties` accept any members.  Other keywords
+//! (`pattern`, `minimum`, `format`, ...) are not enforced.
+//!
+//! [`GenerationConfig::schema`]: super::generation::GenerationConfig::schema
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde_json::Value;
+
+use super::grammar::{Grammar, GrammarError};
+
+/// Rules shared by all compiled schemas.
+const JSON_RULES: &str = r#"
+ws      ::= " "?
+value   ::= object | array | string | number | boolean | null
+object  ::= "{" ws ( string ws ":" ws value ( "," ws string ws ":" ws value )* )? ws "}"
+array   ::= "[" ws ( value ( "," ws value )* )? ws "]"
+string  ::= "\"" char* "\""
+char    ::= [^"\\\x00-\x1f] | "\\" ( ["\\/bfnrt] | "u" hex hex hex hex )
+hex     ::= [0-9a-fA-F]
+number  ::= integer ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )?
+integer ::= "-"? ( "0" | [1-9] [0-9]* )
+boolean ::= "true" | "false"
+null    ::= "null"
+"#;
+
+/// Error raised when compiling a [`JsonSchema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    /// The schema uses a construct that cannot be compiled.
+    Unsupported(String),
+    /// A `$ref` does not point into the schema.
+    InvalidRef(String),
+    /// The generated grammar was rejected.
+    Grammar(GrammarError),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Unsupported(what) => write!(f, "unsupported JSON schema: {what}"),
+            SchemaError::InvalidRef(r) => write!(f, "cannot resolve JSON schema $ref '{r}'"),
+            SchemaError::Grammar(err) => write!(f, "invalid schema grammar: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// JSON schema compiled into a grammar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonSchema {
+    schema: Value,
+    gbnf: String,
+    grammar: Grammar,
+}
+
+impl JsonSchema {
+    /// Compile `schema`, see the [module documentation](self).
+    pub fn compile(schema: &Value) -> Result<Self, SchemaError> {
+        let mut compiler = Compiler::new(schema);
+        let root = compiler.visit(schema, "root")?;
+        compiler.rules.insert(0, format!("root ::= {root}"));
+        let gbnf = compiler.rules.join("\n") + JSON_RULES;
+        let grammar = Grammar::parse(&gbnf).map_err(SchemaError::Grammar)?;
+        Ok(Self {
+            schema: schema.clone(),
+            gbnf,
+            grammar,
+        })
+    }
+
+    /// The schema this was compiled from.
+    pub fn schema(&self) -> &Value {
+        &self.schema
+    }
+
+    /// GBNF text of the compiled grammar.
+    pub fn gbnf(&self) -> &str {
+        &self.gbnf
+    }
+
+    /// Grammar to constrain generation with.
+    pub fn grammar(&self) -> &Grammar {
+        &self.grammar
+    }
+}
+
+struct Compiler<'a> {
+    root: &'a Value,
+    rules: Vec<String>,
+    names: HashSet<String>,
+    refs: HashMap<String, String>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(root: &'a Value) -> Self {
+        let names = [
+            "root", "ws", "value", "object", "array", "string", "char", "hex", "number", "integer",
+            "boolean", "null",
+        ];
+        Self {
+            root,
+            rules: Vec::new(),
+            names: names.iter().map(|n| n.to_string()).collect(),
+            refs: HashMap::new(),
+        }
+    }
+
+    /// Reserve a rule name derived from `hint`.
+    fn name(&mut self, hint: &str) -> String {
+        let base: String = hint
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        let mut name = base.clone();
+        let mut n = 1;
+        while !self.names.insert(name.clone()) {
+            n += 1;
+            name = format!("{base}-{n}");
+        }
+        name
+    }
+
+    fn rule(&mut self, hint: &str, body: String) -> String {
+        let name = self.name(hint);
+        self.rules.push(format!("{name} ::= {body}"));
+        name
+    }
+
+    /// GBNF expression matching the values allowed by `schema`.
+    fn visit(&mut self, schema: &Value, hint: &str) -> Result<String, SchemaError> {
+        let obj = match schema {
+            Value::Bool(true) => return Ok("value".into()),
+            Value::Object(obj) => obj,
+            other => return Err(SchemaError::Unsupported(format!("schema {other}"))),
+        };
+        if let Some(r) = obj.get("$ref") {
+            let r = r.as_str().unwrap_or_default();
+            return self.reference(r);
+        }
+        if let Some(value) = obj.get("const") {
+            return Ok(literal(value));
+        }
+        if let Some(values) = obj.get("enum") {
+            let values = values
+                .as_array()
+                .ok_or_else(|| SchemaError::Unsupported("non-array enum".into()))?;
+            let alts: Vec<String> = values.iter().map(literal).collect();
+            return Ok(self.rule(hint, alts.join(" | ")));
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(options) = obj.get(key) {
+                let options = options
+                    .as_array()
+                    .ok_or_else(|| SchemaError::Unsupported(format!("non-array {key}")))?;
+                let mut alts = Vec::new();
+                for (i, option) in options.iter().enumerate() {
+                    alts.push(self.visit(option, &format!("{hint}-{i}"))?);
+                }
+                return Ok(self.rule(hint, alts.join(" | ")));
+            }
+        }
+        if let Some(all) = obj.get("allOf") {
+            return match all.as_array().map(Vec::as_slice) {
+                Some([single]) => self.visit(single, hint),
+                _ => Err(SchemaError::Unsupported(
+                    "allOf with several schemas".into(),
+                )),
+            };
+        }
+
+        let types: Vec<&str> = match obj.get("type") {
+            Some(Value::String(t)) => vec![t.as_str()],
+            Some(Value::Array(ts)) => ts.iter().filter_map(Value::as_str).collect(),
+            Some(other) => return Err(SchemaError::Unsupported(format!("type {other}"))),
+            None if obj.contains_key("properties") => vec!["object"],
+            None if obj.contains_key("items") => vec!["array"],
+            None => return Ok("value".into()),
+        };
+        if types.is_empty() {
+            return Err(SchemaError::Unsupported("empty type list".into()));
+        }
+        let mut alts = Vec::new();
+        for t in types {
+            alts.push(self.typed(obj, t, hint)?);
+        }
+        Ok(match alts.as_slice() {
+            [single] => single.clone(),
+            _ => self.rule(hint, alts.join(" | ")),
+        })
+    }
+
+    fn typed(
+        &mut self,
+        obj: &serde_json::Map<String, Value>,
+        t: &str,
+        hint: &str,
+    ) -> Result<String, SchemaError> {
+        match t {
+            "string" | "number" | "integer" | "boolean" | "null" => Ok(t.to_string()),
+            "object" => match obj.get("properties").and_then(Value::as_object) {
+                Some(properties) => self.object(obj, properties, hint),
+                None => Ok("object".into()),
+            },
+            "array" => self.array(obj, hint),
+            other => Err(SchemaError::Unsupported(format!("type \"{other}\""))),
+        }
+    }
+
+    fn object(
+        &mut self,
+        obj: &serde_json::Map<String, Value>,
+        properties: &serde_json::Map<String, Value>,
+        hint: &str,
+    ) -> Result<String, SchemaError> {
+        let required: HashSet<&str> = obj
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|r| r.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let mut members = Vec::new();
+        for (key, schema) in properties {
+            let value = self.visit(schema, &format!("{hint}-{key}"))?;
+            let member = format!(
+                "{} ws \":\" ws {value}",
+                literal(&Value::String(key.clone()))
+            );
+            members.push((member, required.contains(key.as_str())));
+        }
+
+        // `first` rules list the members from index i on before any was
+        // written, `after` rules once a comma is needed.  Built back to front.
+        let mut first = String::from("\"\"");
+        let mut after = String::from("\"\"");
+        for (i, (member, required)) in members.iter().enumerate().rev() {
+            let next_after = after.clone();
+            let with = format!("{member} {next_after}");
+            let with_comma = format!("\",\" ws {member} {next_after}");
+            let (f, a) = if *required {
+                (with, with_comma)
+            } else {
+                (
+                    format!("{with} | {first}"),
+                    format!("{with_comma} | {next_after}"),
+                )
+            };
+            first = self.rule(&format!("{hint}-first-{i}"), f);
+            after = self.rule(&format!("{hint}-after-{i}"), a);
+        }
+        Ok(self.rule(hint, format!("\"{{\" ws {first} ws \"}}\"")))
+    }
+
+    fn array(
+        &mut self,
+        obj: &serde_json::Map<String, Value>,
+        hint: &str,
+    ) -> Result<String, SchemaError> {
+        let item = match obj.get("items") {
+            Some(items) => self.visit(items, &format!("{hint}-item"))?,
+            None => "value".into(),
+        };
+        let min = obj.get("minItems").and_then(Value::as_u64).unwrap_or(0) as usize;
+        let max = obj
+            .get("maxItems")
+            .and_then(Value::as_u64)
+            .map(|m| m as usize);
+        if max.is_some_and(|max| max < min) {
+            return Err(SchemaError::Unsupported("maxItems below minItems".into()));
+        }
+        if max == Some(0) {
+            return Ok(self.rule(hint, "\"[\" ws \"]\"".into()));
+        }
+
+        let next = format!("\",\" ws {item}");
+        let mut items = item.clone();
+        for _ in 1..min.max(1) {
+            items = format!("{items} {next}");
+        }
+        let tail = match max {
+            None => format!("( {next} )*"),
+            Some(max) => {
+                let mut tail = String::new();
+                for _ in min.max(1)..max {
+                    tail = format!("( {next} {tail} )?");
+                }
+                tail
+            }
+        };
+        let items = if min == 0 {
+            format!("( {items} {tail} )?")
+        } else {
+            format!("{items} {tail}")
+        };
+        Ok(self.rule(hint, format!("\"[\" ws {items} ws \"]\"")))
+    }
+
+    fn reference(&mut self, r: &str) -> Result<String, SchemaError> {
+        if let Some(name) = self.refs.get(r) {
+            return Ok(name.clone());
+        }
+        let target = r
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+            .ok_or_else(|| SchemaError::InvalidRef(r.to_string()))?;
+        let hint = r.rsplit('/').next().unwrap_or("ref");
+        // Register the rule first so recursive references resolve to it.
+        let name = self.name(hint);
+        self.refs.insert(r.to_string(), name.clone());
+        let body = self.visit(target, &format!("{name}-def"))?;
+        self.rules.push(format!("{name} ::= {body}"));
+        Ok(name)
+    }
+}
+
+/// GBNF literal matching the compact JSON encoding of `value`.
+fn literal(value: &Value) -> String {
+    let json = value.to_string();
+    let mut out = String::with_capacity(json.len() + 2);
+    out.push('"');
+    for c in json.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,306 @@
+//! KV cache snapshots on disk (`.aurexkv`).
+//!
+//! [`KvCache::save`] writes the cached positions and tokens of a generation
+//! so a long-context session can be resumed later, possibly in another
+//! process, with [`KvCache::load`] instead of prefilling it again.  The file
+//! holds no weights: it records a hash of the model that computed the cache,
+//! and loading it into any other model fails.
+//!
+//! Layout:
+//!
+//! ```text
+//! magic "AUREXKV\x01" | u32 header length | header JSON
+//! | u64 payload length | payload
+//! ```
+//!
+//! The payload holds the context tokens as `u32` followed by the cached
+//! positions in the header's [`KvEncoding`], and is compressed as a whole
+//! when the header names a codec.  All integers are little endian.
+
+use anyhow::{bail, Context, Result};
+use aurex_runtime::config::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::bundle::read_bytes;
+use super::generation::KvCache;
+use super::quantizer::{dequantize_bf16, dequantize_int8, quantize_bf16, quantize_int8};
+use crate::amduda_core::compression::Compressor;
+
+/// File magic identifying a KV cache snapshot.
+pub const KV_MAGIC: &[u8; 8] = b"AUREXKV\x01";
+
+/// Header format version written by this build.
+pub const KV_VERSION: u32 = 1;
+
+/// Conventional file extension of KV cache snapshots.
+pub const KV_EXTENSION: &str = "aurexkv";
+
+/// Model whose key/value cache can be exported and imported again.
+pub trait KvState {
+    /// Hash identifying the weights the cache is computed from.
+    fn model_hash(&self) -> u64;
+    /// Values cached per position.
+    fn kv_width(&self) -> usize;
+    /// Cached positions, `positions x kv_width` values, and the number of
+    /// leading positions that are a prefix rather than context tokens.
+    fn export_kv(&self) -> (Vec<f32>, usize);
+    /// Replace the cache by `values` exported by [`export_kv`] with
+    /// `prefix` leading prefix positions.
+    ///
+    /// [`export_kv`]: KvState::export_kv
+    fn import_kv(&mut self, values: &[f32], prefix: usize) -> Result<()>;
+}
+
+/// Storage of the cached values in a snapshot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KvEncoding {
+    /// Exact `f32` values.
+    #[default]
+    F32,
+    /// Half the size, keeping 8 significant bits.
+    Bf16,
+    /// A quarter of the size, with one scale per position.
+    Int8,
+}
+
+/// How [`KvCache::save_with`] stores a snapshot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KvSaveOptions {
+    pub encoding: KvEncoding,
+    /// Codec compressing the payload, `None` to store it as is.
+    pub compression: Option<Compression>,
+}
+
+/// Metadata stored at the start of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvHeader {
+    pub version: u32,
+    /// [`KvState::model_hash`] of the model that computed the cache.
+    pub model_hash: u64,
+    /// Values per cached position.
+    pub width: usize,
+    /// Cached positions, prefix included.
+    pub positions: usize,
+    /// Leading positions holding prefix embeddings.
+    pub prefix: usize,
+    /// Context tokens, one per position after the prefix.
+    pub tokens: usize,
+    pub encoding: KvEncoding,
+    pub compression: Option<Compression>,
+    /// Length of the payload before compression.
+    pub payload_len: u64,
+}
+
+/// 64-bit FNV-1a hash of `bytes`, continuing from `hash`.  Start from
+/// [`FNV_OFFSET`].
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Initial value of [`fnv1a`].
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+impl<M: KvState> KvCache<M> {
+    /// Serialize the snapshot to `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W, options: &KvSaveOptions) -> Result<()> {
+        let model = self.model();
+        let width = model.kv_width();
+        let (values, prefix) = model.export_kv();
+        let positions = values.len() / width.max(1);
+        if positions < prefix || positions - prefix != self.len() {
+            bail!(
+                "KV cache holds {positions} positions with a prefix of {prefix} \
+                 but {} tokens",
+                self.len()
+            );
+        }
+
+        let mut payload: Vec<u8> = self.tokens().iter().flat_map(|t| t.to_le_bytes()).collect();
+        encode(&mut payload, &values, width, options.encoding);
+        let header = KvHeader {
+            version: KV_VERSION,
+            model_hash: model.model_hash(),
+            width,
+            positions,
+            prefix,
+            tokens: self.len(),
+            encoding: options.encoding,
+            compression: options.compression,
+            payload_len: payload.len() as u64,
+        };
+        if let Some(codec) = options.compression {
+            payload = Compressor::new(codec)
+                .compress(&payload)
+                .context("compressing KV cache")?;
+        }
+        let json = serde_json::to_vec(&header)?;
+
+        writer.write_all(KV_MAGIC)?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(&json)?;
+        writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+        writer.write_all(&payload)?;
+        Ok(())
+    }
+
+    /// Deserialize a snapshot from `reader` into `model`, which must be the
+    /// model the snapshot was saved from.
+    pub fn read_from<R: Read>(mut reader: R, mut model: M) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("reading KV cache header")?;
+        if &magic != KV_MAGIC {
+            bail!("not an aurexkv snapshot");
+        }
+        let mut len4 = [0u8; 4];
+        reader.read_exact(&mut len4)?;
+        let json = read_bytes(&mut reader, u32::from_le_bytes(len4).into())
+            .context("reading KV cache header")?;
+        let header: KvHeader = serde_json::from_slice(&json)?;
+        if header.version != KV_VERSION {
+            bail!(
+                "unsupported KV cache version {} (expected {})",
+                header.version,
+                KV_VERSION
+            );
+        }
+        if header.model_hash != model.model_hash() {
+            bail!(
+                "KV cache was saved from model {:016x}, not {:016x}",
+                header.model_hash,
+                model.model_hash()
+            );
+        }
+        if header.width != model.kv_width() {
+            bail!(
+                "KV cache has {} values per position, the model {}",
+                header.width,
+                model.kv_width()
+            );
+        }
+        if header.positions < header.prefix || header.positions - header.prefix != header.tokens {
+            bail!(
+                "KV cache holds {} positions with a prefix of {} but {} tokens",
+                header.positions,
+                header.prefix,
+                header.tokens
+            );
+        }
+
+        // Sizes are checked against the header before buffers are allocated
+        // for them.
+        let expected = encoded_len(header.positions, header.width, header.encoding)
+            .and_then(|len| len.checked_add(header.tokens.checked_mul(4)?))
+            .context("KV cache header describes an impossibly large payload")?;
+        if header.payload_len != expected as u64 {
+            bail!(
+                "KV cache payload is {} bytes uncompressed, expected {expected}",
+                header.payload_len
+            );
+        }
+        let mut len8 = [0u8; 8];
+        reader.read_exact(&mut len8)?;
+        let mut payload = read_bytes(&mut reader, u64::from_le_bytes(len8))
+            .context("reading KV cache payload")?;
+        if let Some(codec) = header.compression {
+            payload = Compressor::new(codec)
+                .decompress(&payload, expected)
+                .context("decompressing KV cache")?;
+        }
+        if payload.len() != expected {
+            bail!(
+                "KV cache payload has {} bytes, expected {expected}",
+                payload.len()
+            );
+        }
+
+        let (tokens, values) = payload.split_at(4 * header.tokens);
+        let tokens = tokens
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let values = decode(values, header.width, header.encoding);
+        model.import_kv(&values, header.prefix)?;
+        Ok(KvCache::from_parts(model, tokens))
+    }
+
+    /// Write the snapshot to `path` as exact `f32` values.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.save_with(path, &KvSaveOptions::default())
+    }
+
+    /// Write the snapshot to `path`, encoded and compressed as `options`
+    /// selects.
+    pub fn save_with(&self, path: impl AsRef<Path>, options: &KvSaveOptions) -> Result<()> {
+        let file = fs::File::create(path.as_ref())
+            .with_context(|| format!("creating {}", path.as_ref().display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.write_to(&mut writer, options)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read the snapshot at `path` into `model`, see
+    /// [`read_from`](Self::read_from).
+    pub fn load(path: impl AsRef<Path>, model: M) -> Result<Self> {
+        let file = fs::File::open(path.as_ref())
+            .with_context(|| format!("opening {}", path.as_ref().display()))?;
+        Self::read_from(std::io::BufReader::new(file), model)
+            .with_context(|| format!("loading {}", path.as_ref().display()))
+    }
+}
+
+/// Bytes of `positions` encoded positions, `None` on overflow.
+fn encoded_len(positions: usize, width: usize, encoding: KvEncoding) -> Option<usize> {
+    match encoding {
+        KvEncoding::F32 => positions.checked_mul(width)?.checked_mul(4),
+        KvEncoding::Bf16 => positions.checked_mul(width)?.checked_mul(2),
+        KvEncoding::Int8 => positions.checked_mul(width.checked_add(4)?),
+    }
+}
+
+fn encode(out: &mut Vec<u8>, values: &[f32], width: usize, encoding: KvEncoding) {
+    match encoding {
+        KvEncoding::F32 => out.extend(values.iter().flat_map(|v| v.to_le_bytes())),
+        KvEncoding::Bf16 => out.extend(quantize_bf16(values).iter().flat_map(|v| v.to_le_bytes())),
+        KvEncoding::Int8 => {
+            for position in values.chunks(width) {
+                let (q, scale) = quantize_int8(position);
+                out.extend_from_slice(&scale.to_le_bytes());
+                out.extend(q.into_iter().map(|v| v as u8));
+            }
+        }
+    }
+}
+
+fn decode(bytes: &[u8], width: usize, encoding: KvEncoding) -> Vec<f32> {
+    match encoding {
+        KvEncoding::F32 => bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        KvEncoding::Bf16 => {
+            let q: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            dequantize_bf16(&q)
+        }
+        KvEncoding::Int8 => bytes
+            .chunks_exact(4 + width)
+            .flat_map(|position| {
+                let (scale, q) = position.split_at(4);
+                let scale = f32::from_le_bytes([scale[0], scale[1], scale[2], scale[3]]);
+                let q: Vec<i8> = q.iter().map(|&b| b as i8).collect();
+                dequantize_int8(&q, scale)
+            })
+            .collect(),
+    }
+}
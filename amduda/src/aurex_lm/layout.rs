@@ -0,0 +1,200 @@
+//! Weight layouts chosen per backend when weights are loaded or compiled.
+//!
+//! A matmul kernel walking a row-major `rows x cols` weight matrix column
+//! block by column block jumps a full row ahead for every value it reads.
+//! A [`WeightLayout`] reorders the stored values so each kernel reads them
+//! sequentially:
+//!
+//! * [`WeightLayout::Blocked`] stores panels of `cols` columns one after
+//!   another, each panel row-major, so the CPU SIMD kernels load one vector
+//!   per row from consecutive addresses.
+//! * [`WeightLayout::Tiled`] stores `rows x cols` tiles one after another, so
+//!   a GPU workgroup stages its tile in shared memory with a single
+//!   contiguous read.
+//! * [`WeightLayout::Interleaved`] stores groups of `rows` rows column by
+//!   column, so dot product units on NPUs and FPGAs read the values of one
+//!   column across the group together.
+//!
+//! Layouts only permute the values of the leading `rows x cols` block; edge
+//! panels and tiles are narrower instead of padded, and the encoding of the
+//! values is kept, so repacking is lossless for every [`Quantization`].
+//! Compiled `.aurexc` bundles record the layout of their weights in the
+//! model configuration.
+
+use std::fmt;
+
+use aurex_backend::Backend;
+use serde::{Deserialize, Serialize};
+
+use super::model_loader::Quantization;
+use crate::hal_backends::cpu_simd::SIMD_WIDTH;
+
+/// Edge length of the tiles used by GPU backends.
+pub const GPU_TILE: usize = 16;
+
+/// Rows interleaved for plugin accelerators.
+pub const PLUGIN_INTERLEAVE: usize = 4;
+
+/// Order in which the values of a weight matrix are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WeightLayout {
+    #[default]
+    RowMajor,
+    /// Column panels of `cols` columns.
+    Blocked { cols: usize },
+    /// Tiles of `rows x cols` values, in row-major tile order.
+    Tiled { rows: usize, cols: usize },
+    /// Groups of `rows` rows stored column by column.
+    Interleaved { rows: usize },
+}
+
+impl WeightLayout {
+    /// Layout the matmul kernels of `backend` read sequentially.
+    pub fn for_backend(backend: Backend) -> Self {
+        match backend {
+            Backend::Cpu => WeightLayout::Blocked { cols: SIMD_WIDTH },
+            Backend::Rocm | Backend::Sycl | Backend::OpenCl | Backend::Vulkan => {
+                WeightLayout::Tiled {
+                    rows: GPU_TILE,
+                    cols: GPU_TILE,
+                }
+            }
+            Backend::Plugin(_) => WeightLayout::Interleaved {
+                rows: PLUGIN_INTERLEAVE,
+            },
+        }
+    }
+
+    pub fn is_row_major(&self) -> bool {
+        *self == WeightLayout::RowMajor
+    }
+
+    /// Position of value `(r, c)` of a `rows x cols` matrix in storage.
+    pub fn offset(&self, rows: usize, cols: usize, r: usize, c: usize) -> usize {
+        match *self {
+            WeightLayout::RowMajor => r * cols + c,
+            WeightLayout::Blocked { cols: block } => {
+                let start = c - c % block.max(1);
+                let width = block.max(1).min(cols - start);
+                start * rows + r * width + (c - start)
+            }
+            WeightLayout::Tiled {
+                rows: tile_rows,
+                cols: tile_cols,
+            } => {
+                let top = r - r % tile_rows.max(1);
+                let left = c - c % tile_cols.max(1);
+                let height = tile_rows.max(1).min(rows - top);
+                let width = tile_cols.max(1).min(cols - left);
+                top * cols + left * height + (r - top) * width + (c - left)
+            }
+            WeightLayout::Interleaved { rows: group } => {
+                let top = r - r % group.max(1);
+                let height = group.max(1).min(rows - top);
+                top * cols + c * height + (r - top)
+            }
+        }
+    }
+
+    /// Reorder the row-major `rows x cols` matrix at the start of `data` into
+    /// this layout.  Values beyond the matrix are kept in place; `data` is
+    /// returned unchanged if it holds fewer than `rows * cols` values.
+    pub fn pack<T: Copy>(&self, data: &[T], rows: usize, cols: usize) -> Vec<T> {
+        let mut out = data.to_vec();
+        if self.is_row_major() || rows * cols > data.len() {
+            return out;
+        }
+        for r in 0..rows {
+            for c in 0..cols {
+                out[self.offset(rows, cols, r, c)] = data[r * cols + c];
+            }
+        }
+        out
+    }
+
+    /// Inverse of [`pack`](Self::pack).
+    pub fn unpack<T: Copy>(&self, data: &[T], rows: usize, cols: usize) -> Vec<T> {
+        let mut out = data.to_vec();
+        if self.is_row_major() || rows * cols > data.len() {
+            return out;
+        }
+        for r in 0..rows {
+            for c in 0..cols {
+                out[r * cols + c] = data[self.offset(rows, cols, r, c)];
+            }
+        }
+        out
+    }
+
+    /// [`pack`](Self::pack) weights stored as `quantization`, moving encoded
+    /// values without decoding them.
+    pub fn pack_encoded(
+        &self,
+        bytes: &[u8],
+        quantization: Option<Quantization>,
+        rows: usize,
+        cols: usize,
+    ) -> Vec<u8> {
+        let values = decode_units(bytes, quantization);
+        encode_units(&self.pack(&values, rows, cols), quantization)
+    }
+
+    /// [`unpack`](Self::unpack) weights stored as `quantization`.
+    pub fn unpack_encoded(
+        &self,
+        bytes: &[u8],
+        quantization: Option<Quantization>,
+        rows: usize,
+        cols: usize,
+    ) -> Vec<u8> {
+        let values = decode_units(bytes, quantization);
+        encode_units(&self.unpack(&values, rows, cols), quantization)
+    }
+}
+
+impl fmt::Display for WeightLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WeightLayout::RowMajor => write!(f, "row-major"),
+            WeightLayout::Blocked { cols } => write!(f, "blocked ({cols} columns)"),
+            WeightLayout::Tiled { rows, cols } => write!(f, "tiled ({rows}x{cols})"),
+            WeightLayout::Interleaved { rows } => write!(f, "interleaved ({rows} rows)"),
+        }
+    }
+}
+
+/// Split `bytes` into its encoded values: little endian words for `f32` and
+/// BF16, bytes for INT8 and nibbles, low first, for INT4.
+fn decode_units(bytes: &[u8], quantization: Option<Quantization>) -> Vec<u32> {
+    match quantization {
+        None => bytes
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect(),
+        Some(Quantization::Bf16) => bytes
+            .chunks_exact(2)
+            .map(|c| u32::from(u16::from_le_bytes([c[0], c[1]])))
+            .collect(),
+        Some(Quantization::Int8) => bytes.iter().map(|&b| u32::from(b)).collect(),
+        Some(Quantization::Int4) => bytes
+            .iter()
+            .flat_map(|&b| [u32::from(b & 0x0F), u32::from(b >> 4)])
+            .collect(),
+    }
+}
+
+fn encode_units(values: &[u32], quantization: Option<Quantization>) -> Vec<u8> {
+    match quantization {
+        None => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        Some(Quantization::Bf16) => values
+            .iter()
+            .flat_map(|&v| (v as u16).to_le_bytes())
+            .collect(),
+        Some(Quantization::Int8) => values.iter().map(|&v| v as u8).collect(),
+        Some(Quantization::Int4) => values
+            .chunks(2)
+            .map(|pair| pair[0] as u8 | (pair.get(1).copied().unwrap_or(0) as u8) << 4)
+            .collect(),
+    }
+}
@@ -1,5 +1,60 @@
 //! Aurex-LM core modules
+//!
+//! `wasm32` builds keep the modules without native dependencies: the
+//! quantizer, tokenizer, sampler, grammars, the speech front-end and the
+//! portable [`web_runtime`].  Without the `std` feature only the quantizer
+//! remains.
 
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod batching;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod benchmark;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod bundle;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod confidence;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod context_compression;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod embeddings;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod evaluation;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod generation;
+#[cfg(feature = "std")]
+pub mod grammar;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod hypothesis;
+#[cfg(feature = "std")]
+pub mod json_schema;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod kv_snapshot;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod layout;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
 pub mod model_loader;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod moe;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
 pub mod paged_attention;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod prompt_lookup;
 pub mod quantizer;
+#[cfg(feature = "proptest")]
+pub mod quantizer_props;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod reflexion;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod retrieval;
+#[cfg(feature = "std")]
+pub mod sampler;
+#[cfg(feature = "std")]
+pub mod speech;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod tiny_lm;
+#[cfg(feature = "std")]
+pub mod tokenizer;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod vision;
+#[cfg(feature = "std")]
+pub mod web_runtime;
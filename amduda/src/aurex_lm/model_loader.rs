@@ -3,26 +3,35 @@
 //! The loader reads a JSON configuration describing the location of model
 //! weights and optional quantization parameters.  Weights are placed on a
 //! memory tier using the simulated [`MemoryManager`] and are either loaded
-//! into memory or memory‑mapped when they overflow CPU memory limits.
+//! into memory or memory‑mapped when they overflow CPU memory limits.  On
+//! NUMA machines large CPU-tier weights are placed on the node set with
+//! `AMDUDA_NUMA_NODE`, or spread over all nodes.
+//!
+//! [`load_model_to_device`] instead maps the weights and uploads them to a
+//! device buffer chunk by chunk, so they are never copied into a host
+//! allocation of their full size.
 
-use crate::amduda_core::memory_tiering::{self, MemoryTier};
+use crate::amduda_core::memory_tiering::{self, MemoryManager, MemoryTier};
+use crate::amduda_core::numa::{NumaBuffer, NUMA_MIN_BYTES};
+use crate::amduda_core::transfer::PinnedBuffer;
+use crate::amduda_core::tensor_ops::TensorOps;
+use crate::hal_backends::cpu_simd::CpuSimdBackend;
+use crate::hal_backends::device_buffer::{BufferAllocator, BufferError, DeviceBuffer};
 use anyhow::Result;
-use aurex_runtime::{Precision, Runtime};
+use aurex_backend::tensor_parallel::{ShardStrategy, TensorParallelDispatcher, WeightShard};
+use aurex_backend::KernelShapes;
+use aurex_runtime::config::MemoryConfig;
+use aurex_runtime::{Precision, PrecisionObserver, Runtime};
+use aurex_utils::numa::NumaTopology;
 use memmap2::{Mmap, MmapOptions};
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
+use std::sync::{Arc, Mutex};
 
-/// Supported on-disk quantized weight formats.
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
-#[serde(rename_all = "lowercase")]
-pub enum Quantization {
-    Int4,
-    Int8,
-    Bf16,
-}
+pub use super::quantizer::Quantization;
 
 /// Configuration for loading a model from disk.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub name: String,
     pub weight_path: String,
@@ -31,6 +40,52 @@ pub struct ModelConfig {
     /// Optional scale factor for pre-quantized INT4/INT8 weights.
     #[serde(default)]
     pub scale: Option<f32>,
+    /// Number of transformer blocks, used to build the op graph at compile
+    /// time.  Defaults to a single block.
+    #[serde(default)]
+    pub layers: Option<usize>,
+    /// Vocabulary size; defaults to the byte tokenizer vocabulary.
+    #[serde(default)]
+    pub vocab_size: Option<usize>,
+    /// Hidden dimension; derived from the weight count when absent.
+    #[serde(default)]
+    pub hidden_size: Option<usize>,
+    /// Order of the stored embedding table, see [`WeightLayout`].
+    #[serde(default)]
+    pub layout: WeightLayout,
+    /// Mixture-of-experts layer stored after the embedding table.
+    #[serde(default)]
+    pub moe: Option<MoeConfig>,
+    /// Patch embedding for image inputs, stored after the mixture-of-experts
+    /// layer.
+    #[serde(default)]
+    pub vision: Option<VisionConfig>,
+}
+
+impl ModelConfig {
+    /// `(vocab, hidden)` shape of the embedding table for a checkpoint with
+    /// `weight_count` values.  The vocabulary defaults to the byte tokenizer
+    /// and the hidden size is derived from the weight count when absent.
+    pub fn embedding_shape(&self, weight_count: usize) -> (usize, usize) {
+        let vocab = self.vocab_size.unwrap_or(super::tokenizer::BYTE_VOCAB_SIZE);
+        // The table, a mixture-of-experts layer and a patch embedding all
+        // scale with the hidden size.
+        let per_hidden = vocab
+            + self.moe.map_or(0, |moe| moe.weight_count(1))
+            + self.vision.map_or(0, |vision| vision.weight_count(1));
+        let hidden = self.hidden_size.unwrap_or(weight_count / per_hidden.max(1));
+        (vocab, hidden)
+    }
+}
+
+/// Name, shape and storage type of a weight tensor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorInfo {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub dtype: &'static str,
+    /// Storage size in bytes.
+    pub bytes: usize,
 }
 
 /// Concrete representation of loaded weights.
@@ -40,8 +95,92 @@ pub enum Weights {
     Memory(Vec<u8>),
     /// Weights memory mapped from NVMe storage.
     Mmap(Mmap),
+    /// Large CPU-resident weights placed on NUMA nodes.
+    Numa(NumaBuffer),
+}
+
+/// Bytes uploaded per step by [`load_model_to_device`].
+pub const UPLOAD_CHUNK: usize = 4 << 20;
+
+/// Progress of a weight upload, reported after every chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadProgress {
+    pub uploaded: usize,
+    pub total: usize,
+}
+
+impl Weights {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Weights::Memory(data) => data,
+            Weights::Mmap(map) => map,
+            Weights::Numa(buffer) => buffer,
+        }
+    }
+
+    /// Copy the weights into a new buffer from `device`, `chunk` bytes at a
+    /// time, calling `progress` after each chunk.
+    ///
+    /// Mapped weights are read sequentially into a pinned staging chunk and
+    /// uploaded from there; the pages of every uploaded chunk are released
+    /// from the mapping again, so only about one chunk of the file is
+    /// resident at any time.  Weights already in memory are uploaded
+    /// directly.
+    pub fn upload(
+        &self,
+        device: &dyn BufferAllocator,
+        chunk: usize,
+        mut progress: impl FnMut(UploadProgress),
+    ) -> Result<DeviceBuffer, BufferError> {
+        let bytes = self.as_bytes();
+        let total = bytes.len();
+        let chunk = chunk.max(1);
+        let mut buffer = device.alloc_buffer(total)?;
+        let mut staging = match self {
+            Weights::Mmap(map) => {
+                advise_sequential(map);
+                Some(PinnedBuffer::new(chunk.min(total)))
+            }
+            _ => None,
+        };
+        let mut uploaded = 0;
+        while uploaded < total {
+            let len = chunk.min(total - uploaded);
+            let src = &bytes[uploaded..uploaded + len];
+            match (self, &mut staging) {
+                (Weights::Mmap(map), Some(staging)) => {
+                    let staged = &mut staging.data[..len];
+                    staged.copy_from_slice(src);
+                    buffer.upload(uploaded, staged)?;
+                    release_pages(map, uploaded, len);
+                }
+                _ => buffer.upload(uploaded, src)?,
+            }
+            uploaded += len;
+            progress(UploadProgress { uploaded, total });
+        }
+        Ok(buffer)
+    }
+}
+
+#[cfg(unix)]
+fn advise_sequential(map: &Mmap) {
+    let _ = map.advise(memmap2::Advice::Sequential);
 }
 
+/// Drop `len` bytes at `offset` of `map` from memory; the pages are read back
+/// from the file if they are touched again.
+#[cfg(unix)]
+fn release_pages(map: &Mmap, offset: usize, len: usize) {
+    let _ = map.advise_range(memmap2::Advice::DontNeed, offset, len);
+}
+
+#[cfg(not(unix))]
+fn advise_sequential(_map: &Mmap) {}
+
+#[cfg(not(unix))]
+fn release_pages(_map: &Mmap, _offset: usize, _len: usize) {}
+
 /// Fully loaded model including configuration and weights.
 #[derive(Debug)]
 pub struct LoadedModel {
@@ -70,6 +209,15 @@ pub fn load_model(path: &str) -> Result<LoadedModel> {
             let mmap = unsafe { MmapOptions::new().map(&file)? };
             Weights::Mmap(mmap)
         }
+        MemoryTier::Cpu if size >= NUMA_MIN_BYTES => {
+            let topology = NumaTopology::detect();
+            if topology.is_numa() {
+                let node = MemoryConfig::from_env().numa_node;
+                Weights::Numa(NumaBuffer::read_file(&config.weight_path, &topology, node)?)
+            } else {
+                Weights::Memory(fs::read(&config.weight_path)?)
+            }
+        }
         _ => {
             let data = fs::read(&config.weight_path)?;
             Weights::Memory(data)
@@ -84,15 +232,56 @@ pub fn load_model(path: &str) -> Result<LoadedModel> {
     })
 }
 
+/// Map the weights of the model at `path` and upload them to `device` in
+/// chunks of [`UPLOAD_CHUNK`] bytes, see [`Weights::upload`].  The returned
+/// model keeps the mapping for host-side uses such as dequantization and is
+/// placed on the GPU tier.
+pub fn load_model_to_device(
+    path: &str,
+    device: &dyn BufferAllocator,
+    progress: impl FnMut(UploadProgress),
+) -> Result<(LoadedModel, DeviceBuffer)> {
+    let cfg = fs::read_to_string(path)?;
+    let config: ModelConfig = serde_json::from_str(&cfg)?;
+    let file = File::open(&config.weight_path)?;
+    // An empty file cannot be mapped.
+    let weights = if file.metadata()?.len() == 0 {
+        Weights::Memory(Vec::new())
+    } else {
+        Weights::Mmap(unsafe { MmapOptions::new().map(&file)? })
+    };
+    let buffer = weights.upload(device, UPLOAD_CHUNK, progress)?;
+    tracing::debug!(
+        model = %config.name,
+        bytes = buffer.len(),
+        location = ?buffer.location(),
+        "uploaded weights"
+    );
+    let model = LoadedModel {
+        scale: config.scale,
+        config,
+        weights,
+        tier: MemoryTier::Gpu,
+    };
+    Ok((model, buffer))
+}
+
+use super::layout::WeightLayout;
+use super::moe::{MoeConfig, MoeLayer};
+use super::vision::{PatchEmbedding, VisionConfig};
 use super::quantizer::{
     dequantize_bf16, dequantize_int4, dequantize_int8, quantize_bf16, quantize_int4, quantize_int8,
 };
+use crate::hal_backends::cpu_simd::SIMD_WIDTH;
 
 impl LoadedModel {
     /// Change the precision of the provided `data` slice and update the stored
     /// weights and configuration accordingly. `data` should contain the original
-    /// floating point weights.
+    /// floating point weights in row-major order; they are stored in the
+    /// model's layout.
     pub fn change_precision(&mut self, data: &[f32], target: Quantization) {
+        let (rows, cols) = self.config.embedding_shape(data.len());
+        let data = &self.config.layout.pack(data, rows, cols)[..];
         let (bytes, scale) = match target {
             Quantization::Int8 => {
                 let (q, s) = quantize_int8(data);
@@ -113,52 +302,252 @@ impl LoadedModel {
         self.scale = scale;
     }
 
-    /// Dequantize the currently stored weights back into `f32` values using the
-    /// recorded quantization metadata.
+    /// Dequantize the currently stored weights back into row-major `f32`
+    /// values using the recorded quantization metadata.
     pub fn dequantized_weights(&self, len: usize) -> Option<Vec<f32>> {
-        match (&self.weights, self.config.quantization) {
-            (Weights::Memory(bytes), Some(Quantization::Int8)) => {
+        let values = self.dequantized_stored(len)?;
+        let (rows, cols) = self.config.embedding_shape(self.weight_count());
+        Some(self.config.layout.unpack(&values, rows, cols))
+    }
+
+    /// Dequantized weights in storage order.
+    fn dequantized_stored(&self, len: usize) -> Option<Vec<f32>> {
+        let bytes = self.weights.as_bytes();
+        match self.config.quantization {
+            Some(Quantization::Int8) => {
                 let data: Vec<i8> = bytes.iter().map(|&b| b as i8).collect();
                 self.scale.map(|s| dequantize_int8(&data, s))
             }
-            (Weights::Mmap(mmap), Some(Quantization::Int8)) => {
-                let data: Vec<i8> = mmap.iter().map(|&b| b as i8).collect();
-                self.scale.map(|s| dequantize_int8(&data, s))
-            }
-            (Weights::Memory(bytes), Some(Quantization::Int4)) => {
-                self.scale.map(|s| dequantize_int4(bytes, s, len))
-            }
-            (Weights::Mmap(mmap), Some(Quantization::Int4)) => {
-                self.scale.map(|s| dequantize_int4(&mmap[..], s, len))
-            }
-            (Weights::Memory(bytes), Some(Quantization::Bf16)) => {
+            Some(Quantization::Int4) => self.scale.map(|s| dequantize_int4(bytes, s, len)),
+            Some(Quantization::Bf16) => {
                 let mut u16s = Vec::with_capacity(bytes.len() / 2);
                 for chunk in bytes.chunks(2) {
                     u16s.push(u16::from_le_bytes([chunk[0], chunk[1]]));
                 }
                 Some(dequantize_bf16(&u16s))
             }
-            (Weights::Mmap(mmap), Some(Quantization::Bf16)) => {
-                let mut u16s = Vec::with_capacity(mmap.len() / 2);
-                for chunk in mmap.chunks(2) {
-                    u16s.push(u16::from_le_bytes([chunk[0], chunk[1]]));
+            None => None,
+        }
+    }
+
+    /// Number of logical weight values stored.
+    pub fn weight_count(&self) -> usize {
+        super::bundle::weight_count(self.weights.as_bytes().len(), self.config.quantization)
+    }
+
+    /// Tensors stored in the checkpoint.  The weights hold a
+    /// `tok_embeddings` table, followed by the `moe.gate` and `moe.experts`
+    /// of a mixture-of-experts layer and the `vision.patch_proj`,
+    /// `vision.patch_bias` and `vision.pos_embed` of a patch embedding if
+    /// configured; values beyond them are reported as `unused`.
+    pub fn tensors(&self) -> Vec<TensorInfo> {
+        let (dtype, bits) = match self.config.quantization {
+            None => ("f32", 32),
+            Some(Quantization::Bf16) => ("bf16", 16),
+            Some(Quantization::Int8) => ("int8", 8),
+            Some(Quantization::Int4) => ("int4", 4),
+        };
+        let tensor = |name: &str, shape: Vec<usize>| {
+            let elems: usize = shape.iter().product();
+            TensorInfo {
+                name: name.to_string(),
+                bytes: (elems * bits).div_ceil(8),
+                shape,
+                dtype,
+            }
+        };
+        let count = self.weight_count();
+        let (vocab, hidden) = self.config.embedding_shape(count);
+        let table = vocab * hidden;
+        if hidden == 0 || table > count {
+            return vec![tensor("weights", vec![count])];
+        }
+        let mut tensors = vec![tensor("tok_embeddings", vec![vocab, hidden])];
+        let mut used = table;
+        if let Some(moe) = self
+            .config
+            .moe
+            .filter(|moe| table + moe.weight_count(hidden) <= count)
+        {
+            let inter = moe.intermediate_size;
+            tensors.push(tensor("moe.gate", vec![hidden, moe.experts]));
+            tensors.push(tensor("moe.experts", vec![moe.experts, 3, hidden, inter]));
+            used += moe.weight_count(hidden);
+        }
+        if let Some(vision) = self
+            .config
+            .vision
+            .filter(|vision| used + vision.weight_count(hidden) <= count)
+        {
+            let patch = vision.patch_size;
+            tensors.push(tensor("vision.patch_proj", vec![hidden, 3, patch, patch]));
+            tensors.push(tensor("vision.patch_bias", vec![hidden]));
+            tensors.push(tensor("vision.pos_embed", vec![vision.patches(), hidden]));
+            used += vision.weight_count(hidden);
+        }
+        if count > used {
+            tensors.push(tensor("unused", vec![count - used]));
+        }
+        tensors
+    }
+
+    /// Fixed shapes of the kernels the model runs, to specialize backends for
+    /// at load time: the output projection onto the vocabulary and the layer
+    /// norm over the hidden size.
+    pub fn kernel_shapes(&self) -> KernelShapes {
+        let (vocab, hidden) = self.config.embedding_shape(self.weight_count());
+        if vocab == 0 || hidden == 0 {
+            return KernelShapes::new();
+        }
+        KernelShapes::new()
+            .with_matmul(vocab, hidden)
+            .with_norm(hidden)
+    }
+
+    /// Decode the stored weights into row-major `f32` values, dequantizing
+    /// if needed.
+    pub fn weights_f32(&self) -> Result<Vec<f32>> {
+        let bytes = self.weights.as_bytes();
+        match self.config.quantization {
+            None => {
+                let (rows, cols) = self.config.embedding_shape(self.weight_count());
+                Ok(self.config.layout.unpack(&f32_values(bytes), rows, cols))
+            }
+            quant => {
+                let count = super::bundle::weight_count(bytes.len(), quant);
+                match self.dequantized_weights(count) {
+                    Some(w) => Ok(w),
+                    None => anyhow::bail!("missing scale for {:?} weights", quant),
                 }
-                Some(dequantize_bf16(&u16s))
             }
-            _ => None,
         }
     }
 
+    /// Multiply `a` (`m x k`) by the leading `k x n` block of the stored
+    /// weights on the CPU.  int4 weights are multiplied straight from their
+    /// packed nibbles, and a whole embedding table repacked for the CPU is
+    /// read in its blocked layout; other weights are decoded first.
+    pub fn matmul_weights(&self, a: &[f32], m: usize, n: usize, k: usize) -> Result<Vec<f32>> {
+        let count = self.weight_count();
+        if k * n > count {
+            anyhow::bail!("cannot multiply by {k} x {n} weights, only {count} are stored");
+        }
+        let backend = CpuSimdBackend;
+        let bytes = self.weights.as_bytes();
+        let blocked = self.config.layout == WeightLayout::Blocked { cols: SIMD_WIDTH }
+            && self.config.embedding_shape(count) == (k, n);
+        match (self.config.quantization, self.scale) {
+            (None, _) if blocked => Ok(backend.matmul_blocked(a, &f32_values(bytes), m, n, k)),
+            (Some(Quantization::Int4), Some(scale)) if blocked => {
+                Ok(backend.matmul_int4_blocked(a, bytes, scale, m, n, k))
+            }
+            (Some(Quantization::Int4), Some(scale)) if self.config.layout.is_row_major() => {
+                Ok(backend.matmul_int4(a, bytes, scale, m, n, k))
+            }
+            _ => Ok(backend.matmul(a, &self.weights_f32()?[..k * n], m, n, k)),
+        }
+    }
+
+    /// Reorder the stored weights into `layout` without changing their
+    /// encoding.  The weights are copied into memory.
+    pub fn repack(&mut self, layout: WeightLayout) {
+        if layout == self.config.layout {
+            return;
+        }
+        let (rows, cols) = self.config.embedding_shape(self.weight_count());
+        let quant = self.config.quantization;
+        let bytes = self.weights.as_bytes();
+        let row_major = self.config.layout.unpack_encoded(bytes, quant, rows, cols);
+        self.weights = Weights::Memory(layout.pack_encoded(&row_major, quant, rows, cols));
+        self.config.layout = layout;
+        tracing::debug!(model = %self.config.name, %layout, rows, cols, "repacked weights");
+    }
+
+    /// Build the mixture-of-experts layer stored after the embedding table,
+    /// placing its experts in `memory`.  Quantized weights are dequantized
+    /// first.
+    pub fn moe_layer(&self, memory: Arc<Mutex<MemoryManager>>) -> Result<MoeLayer> {
+        let Some(moe) = self.config.moe else {
+            anyhow::bail!("model {} has no mixture-of-experts layer", self.config.name);
+        };
+        let weights = self.weights_f32()?;
+        let (vocab, hidden) = self.config.embedding_shape(weights.len());
+        let start = (vocab * hidden).min(weights.len());
+        let layer = MoeLayer::new(moe, hidden, &weights[start..], memory)?;
+        tracing::debug!(
+            model = %self.config.name,
+            experts = moe.experts,
+            per_token = moe.experts_per_token,
+            "placed mixture-of-experts layer"
+        );
+        Ok(layer)
+    }
+
+    /// Build the patch embedding for image inputs stored after the embedding
+    /// table and mixture-of-experts layer, running on `ops`.
+    pub fn patch_embedding(
+        &self,
+        ops: Arc<dyn aurex_backend::TensorOps + Send + Sync>,
+    ) -> Result<PatchEmbedding> {
+        let Some(vision) = self.config.vision else {
+            anyhow::bail!("model {} does not accept images", self.config.name);
+        };
+        let weights = self.weights_f32()?;
+        let (vocab, hidden) = self.config.embedding_shape(weights.len());
+        let moe = self.config.moe.map_or(0, |moe| moe.weight_count(hidden));
+        let start = (vocab * hidden + moe).min(weights.len());
+        PatchEmbedding::new(vision, hidden, &weights[start..], ops)
+    }
+
+    /// Split the `tok_embeddings` table across the devices of `tp` for tensor
+    /// parallel execution.  Fails if the table is missing or a shard exceeds
+    /// `device_mem` bytes, so models larger than one device can be placed as
+    /// long as each shard fits.
+    pub fn shard_weights(
+        &self,
+        tp: &TensorParallelDispatcher,
+        strategy: ShardStrategy,
+        device_mem: Option<usize>,
+    ) -> Result<Vec<WeightShard>> {
+        let weights = self.weights_f32()?;
+        let (vocab, hidden) = self.config.embedding_shape(weights.len());
+        if hidden == 0 || vocab * hidden > weights.len() {
+            anyhow::bail!(
+                "cannot shard {} weights as a {vocab} x {hidden} table",
+                weights.len()
+            );
+        }
+        let shards = tp.shard_weights(&weights[..vocab * hidden], vocab, hidden, strategy);
+        if let Some(limit) = device_mem {
+            if let Some(shard) = shards.iter().find(|s| s.bytes() > limit) {
+                anyhow::bail!(
+                    "shard {} needs {} bytes but devices only have {limit}; use more devices",
+                    shard.rank,
+                    shard.bytes()
+                );
+            }
+        }
+        Ok(shards)
+    }
+
     /// Change the runtime precision and re-encode the provided floating point
     /// `data` slice accordingly.  This enables dynamic precision scaling by
     /// keeping the runtime and loaded weights in sync.
     pub fn apply_precision(&mut self, runtime: &mut Runtime, data: &[f32], precision: Precision) {
         runtime.set_precision(precision);
+        self.set_precision(data, precision);
+    }
+
+    /// Re-encode the floating point `data` slice for `precision` without
+    /// touching the runtime.
+    pub fn set_precision(&mut self, data: &[f32], precision: Precision) {
         match precision {
             Precision::Int8 => self.change_precision(data, Quantization::Int8),
             Precision::Int4 => self.change_precision(data, Quantization::Int4),
             Precision::Bf16 => self.change_precision(data, Quantization::Bf16),
             Precision::F32 => {
+                let (rows, cols) = self.config.embedding_shape(data.len());
+                let data = self.config.layout.pack(data, rows, cols);
                 let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
                 self.weights = Weights::Memory(bytes);
                 self.config.quantization = None;
@@ -167,3 +556,43 @@ impl LoadedModel {
         }
     }
 }
+
+/// Keeps a [`LoadedModel`] in sync with runtime precision changes.  A master
+/// copy of the `f32` weights is retained so every switch re-encodes from the
+/// original values instead of compounding quantization error.
+pub struct PrecisionSync {
+    model: Mutex<LoadedModel>,
+    master: Vec<f32>,
+}
+
+impl PrecisionSync {
+    /// Wrap `model` together with its original floating point weights.
+    pub fn new(model: LoadedModel, master: Vec<f32>) -> Self {
+        Self {
+            model: Mutex::new(model),
+            master,
+        }
+    }
+
+    /// Run `f` with the wrapped model.
+    pub fn with_model<R>(&self, f: impl FnOnce(&LoadedModel) -> R) -> R {
+        f(&self.model.lock().unwrap())
+    }
+}
+
+impl PrecisionObserver for PrecisionSync {
+    fn on_precision_change(&self, precision: Precision) {
+        self.model
+            .lock()
+            .unwrap()
+            .set_precision(&self.master, precision);
+    }
+}
+
+/// Little endian `f32` values of `bytes`.
+pub(crate) fn f32_values(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
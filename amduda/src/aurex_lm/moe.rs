@@ -0,0 +1,288 @@
+//! Mixture-of-experts (MoE) layers.
+//!
+//! A [`MoeLayer`] replaces the feed-forward block of a transformer layer with
+//! `experts` SwiGLU feed-forward networks, as in Mixtral.  A linear gate
+//! scores every expert for every token; only the `experts_per_token` best
+//! scoring experts run, and their outputs are summed weighted by the softmax
+//! of their gate logits.
+//!
+//! Only the chosen experts run, but all of them have to be stored.  The
+//! weights of each expert are kept in one allocation of a shared
+//! [`MemoryManager`], so experts that do not fit in VRAM are placed on the
+//! CPU or NVMe tier and the least recently used ones are evicted there as
+//! others are needed.  [`MoeLayer::forward`] groups the tokens of a batch by
+//! expert and runs the active experts one after another: each is promoted to
+//! the fastest tier with [`MemoryManager::acquire`] right before it runs
+//! while the next one is prefetched, and its three weight matrices are each
+//! applied to all tokens routed to it with a single matmul.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::amduda_core::memory_tiering::{AllocationId, MemoryManager, MemoryTier};
+use crate::amduda_core::tensor_ops::TensorOps;
+use crate::hal_backends::cpu_simd::CpuSimdBackend;
+
+use super::model_loader::f32_values;
+
+/// Experts run per token when the configuration does not say.
+pub const DEFAULT_EXPERTS_PER_TOKEN: usize = 2;
+
+fn default_experts_per_token() -> usize {
+    DEFAULT_EXPERTS_PER_TOKEN
+}
+
+/// Shape of a mixture-of-experts layer.
+///
+/// A layer with `hidden` inputs stores the `hidden x experts` gate followed
+/// by the experts, each as its row-major `hidden x intermediate_size` `w1`
+/// and `w3` projections and its `intermediate_size x hidden` `w2` projection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoeConfig {
+    pub experts: usize,
+    #[serde(default = "default_experts_per_token")]
+    pub experts_per_token: usize,
+    pub intermediate_size: usize,
+}
+
+impl MoeConfig {
+    /// Weights of one expert of a layer with `hidden` inputs.
+    pub fn expert_len(&self, hidden: usize) -> usize {
+        3 * hidden * self.intermediate_size
+    }
+
+    /// Weights of the gate and all experts of a layer with `hidden` inputs.
+    pub fn weight_count(&self, hidden: usize) -> usize {
+        hidden * self.experts + self.experts * self.expert_len(hidden)
+    }
+}
+
+/// An expert chosen for a token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Route {
+    pub expert: usize,
+    /// Weight of the expert's output, the weights of a token sum to one.
+    pub weight: f32,
+}
+
+/// Counters of a [`MoeLayer`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MoeStats {
+    /// Tokens passed through the layer.
+    pub tokens: u64,
+    /// Tokens routed to each expert.
+    pub expert_tokens: Vec<u64>,
+    /// Experts that had to be promoted from a slower tier before running.
+    pub promotions: u64,
+}
+
+/// Mixture-of-experts feed-forward layer, see the
+/// [module documentation](self).
+pub struct MoeLayer {
+    config: MoeConfig,
+    hidden: usize,
+    gate: Vec<f32>,
+    experts: Vec<AllocationId>,
+    memory: Arc<Mutex<MemoryManager>>,
+    backend: Arc<dyn TensorOps + Send + Sync>,
+    stats: MoeStats,
+}
+
+impl MoeLayer {
+    /// Build a layer with `hidden` inputs from its stored `weights`, see
+    /// [`MoeConfig`], placing every expert as one allocation of `memory`.
+    pub fn new(
+        config: MoeConfig,
+        hidden: usize,
+        weights: &[f32],
+        memory: Arc<Mutex<MemoryManager>>,
+    ) -> Result<Self> {
+        if config.experts == 0 || config.experts_per_token == 0 {
+            anyhow::bail!("a mixture-of-experts layer needs at least one expert per token");
+        }
+        let needed = config.weight_count(hidden);
+        if weights.len() < needed {
+            anyhow::bail!(
+                "{} experts of {hidden} x {} need {needed} weights, only {} are stored",
+                config.experts,
+                config.intermediate_size,
+                weights.len()
+            );
+        }
+        let (gate, rest) = weights.split_at(hidden * config.experts);
+        let experts = {
+            let mut memory = memory.lock().unwrap();
+            rest.chunks_exact(config.expert_len(hidden))
+                .take(config.experts)
+                .map(|expert| {
+                    memory.allocate_data(expert.iter().flat_map(|v| v.to_le_bytes()).collect())
+                })
+                .collect()
+        };
+        Ok(Self {
+            config,
+            hidden,
+            gate: gate.to_vec(),
+            experts,
+            memory,
+            backend: Arc::new(CpuSimdBackend),
+            stats: MoeStats {
+                expert_tokens: vec![0; config.experts],
+                ..MoeStats::default()
+            },
+        })
+    }
+
+    /// Run the gate and expert matmuls on `backend` instead of the CPU SIMD
+    /// backend.
+    pub fn with_backend(mut self, backend: Arc<dyn TensorOps + Send + Sync>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn config(&self) -> &MoeConfig {
+        &self.config
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    pub fn stats(&self) -> &MoeStats {
+        &self.stats
+    }
+
+    /// Allocation holding the weights of `expert`.
+    pub fn expert_allocation(&self, expert: usize) -> Option<AllocationId> {
+        self.experts.get(expert).copied()
+    }
+
+    /// Tier currently holding the weights of `expert`.
+    pub fn expert_tier(&self, expert: usize) -> Option<MemoryTier> {
+        let id = self.expert_allocation(expert)?;
+        self.memory.lock().unwrap().tier(id)
+    }
+
+    /// Choose the experts of every token of `x`, `tokens x hidden`.
+    pub fn route(&self, x: &[f32]) -> Vec<Vec<Route>> {
+        let tokens = x.len() / self.hidden.max(1);
+        let experts = self.config.experts;
+        let top_k = self.config.experts_per_token.min(experts);
+        let logits = self
+            .backend
+            .matmul(x, &self.gate, tokens, experts, self.hidden);
+        logits
+            .chunks_exact(experts)
+            .map(|logits| {
+                let mut order: Vec<usize> = (0..experts).collect();
+                order.sort_by(|&a, &b| logits[b].total_cmp(&logits[a]));
+                order.truncate(top_k);
+                // Softmax over the chosen logits only.
+                let max = logits[order[0]];
+                let exps: Vec<f32> = order.iter().map(|&e| (logits[e] - max).exp()).collect();
+                let sum: f32 = exps.iter().sum();
+                order
+                    .into_iter()
+                    .zip(exps)
+                    .map(|(expert, exp)| Route {
+                        expert,
+                        weight: exp / sum,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Apply the layer to `x`, `tokens x hidden`.
+    pub fn forward(&mut self, x: &[f32]) -> Result<Vec<f32>> {
+        let hidden = self.hidden;
+        if hidden == 0 || !x.len().is_multiple_of(hidden) {
+            anyhow::bail!("input of {} values is not a batch of {hidden}", x.len());
+        }
+        let routes = self.route(x);
+        let mut assigned: Vec<Vec<(usize, f32)>> = vec![Vec::new(); self.config.experts];
+        for (token, token_routes) in routes.iter().enumerate() {
+            for route in token_routes {
+                assigned[route.expert].push((token, route.weight));
+            }
+        }
+        let active: Vec<usize> = (0..self.config.experts)
+            .filter(|&e| !assigned[e].is_empty())
+            .collect();
+
+        let mut out = vec![0f32; x.len()];
+        for (i, &expert) in active.iter().enumerate() {
+            let weights = self.load_expert(expert, active.get(i + 1).copied())?;
+            let rows = &assigned[expert];
+            let input: Vec<f32> = rows
+                .iter()
+                .flat_map(|&(token, _)| &x[token * hidden..(token + 1) * hidden])
+                .copied()
+                .collect();
+            let y = self.run_expert(&weights, &input, rows.len());
+            for (row, &(token, weight)) in y.chunks_exact(hidden).zip(rows) {
+                for (o, v) in out[token * hidden..(token + 1) * hidden]
+                    .iter_mut()
+                    .zip(row)
+                {
+                    *o += weight * v;
+                }
+            }
+            self.stats.expert_tokens[expert] += rows.len() as u64;
+        }
+        self.stats.tokens += routes.len() as u64;
+        tracing::trace!(
+            tokens = routes.len(),
+            experts = active.len(),
+            "mixture-of-experts forward"
+        );
+        Ok(out)
+    }
+
+    /// Promote `expert` to the fastest tier and read its weights, then start
+    /// prefetching the `next` expert.
+    fn load_expert(&mut self, expert: usize, next: Option<usize>) -> Result<Vec<f32>> {
+        let id = self.experts[expert];
+        let mut memory = self.memory.lock().unwrap();
+        let before = memory.tier(id);
+        if memory.acquire(id) < before {
+            self.stats.promotions += 1;
+        }
+        let Some(bytes) = memory.read(id)? else {
+            anyhow::bail!("weights of expert {expert} are no longer stored");
+        };
+        if let Some(next) = next {
+            memory.prefetch(&[self.experts[next]]);
+        }
+        Ok(f32_values(&bytes))
+    }
+
+    /// SwiGLU feed-forward of one expert over `tokens` rows of `input`.
+    fn run_expert(&self, weights: &[f32], input: &[f32], tokens: usize) -> Vec<f32> {
+        let hidden = self.hidden;
+        let inter = self.config.intermediate_size;
+        let (w1, rest) = weights.split_at(hidden * inter);
+        let (w3, w2) = rest.split_at(hidden * inter);
+        let gate = self.backend.matmul(input, w1, tokens, inter, hidden);
+        let up = self.backend.matmul(input, w3, tokens, inter, hidden);
+        let act: Vec<f32> = gate
+            .iter()
+            .zip(&up)
+            .map(|(g, u)| g / (1.0 + (-g).exp()) * u)
+            .collect();
+        self.backend.matmul(&act, w2, tokens, hidden, inter)
+    }
+}
+
+impl std::fmt::Debug for MoeLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MoeLayer")
+            .field("config", &self.config)
+            .field("hidden", &self.hidden)
+            .field("experts", &self.experts)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
@@ -5,32 +5,57 @@
 //! [`MemoryManager`] and exposes two reference implementations:
 //! `compute` implements a classic sliding window, while
 //! `compute_block_sparse` operates on blocks of tokens.  Both methods
-//! demonstrate how key and value pages may be allocated across memory tiers
-//! and migrated between NVMe and CPU memory to efficiently handle large
-//! contexts.
+//! allocate the key and value caches as pages across memory tiers and migrate
+//! the pages in use between NVMe and CPU memory to efficiently handle large
+//! contexts.  Migrations run on the shared [`TransferEngine`], so the pages of
+//! the next block are prefetched while the current one is being computed.
+//!
+//! With [`PagedAttention::with_attention_sinks`] the sliding window runs in
+//! StreamingLLM mode: every query also attends to the first tokens of the
+//! sequence, the attention sinks that softmax attention relies on to stay
+//! stable, and the pages between the sinks and the window are freed once
+//! the window has passed them.  Memory then stays bounded by the sinks and
+//! the window, so sequences longer than the training context keep working
+//! without re-encoding the history.
 
-use crate::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use crate::amduda_core::memory_tiering::{
+    AllocationId, DeviceCapabilities, MemoryManager, MemoryTier,
+};
+use crate::amduda_core::transfer::{TransferEngine, TransferHandle};
 use std::mem::size_of;
 
 /// Result of a paged attention invocation.
 #[derive(Debug)]
 pub struct AttentionResult {
     pub output: Vec<f32>,
+    /// Slowest tier any key page was placed on.
     pub k_tier: MemoryTier,
+    /// Slowest tier any value page was placed on.
     pub v_tier: MemoryTier,
+    /// Key/value page pairs freed after leaving the attention window.
+    pub evicted_pages: usize,
+}
+
+/// Key and value pages of one attention call, one allocation per page.
+struct KvPages {
+    k: Vec<AllocationId>,
+    v: Vec<AllocationId>,
 }
 
 /// Paged attention engine owning a [`MemoryManager`].
 #[derive(Debug)]
 pub struct PagedAttention {
     mgr: MemoryManager,
+    /// Leading tokens every query attends to in attention-sink mode.
+    sinks: Option<usize>,
 }
 
 impl PagedAttention {
     /// Create a new engine with default tier limits.
     pub fn new(caps: DeviceCapabilities) -> Self {
         Self {
-            mgr: MemoryManager::new(caps),
+            mgr: MemoryManager::new(caps).with_transfer_engine(TransferEngine::shared()),
+            sinks: None,
         }
     }
 
@@ -42,10 +67,25 @@ impl PagedAttention {
         nvme_limit: usize,
     ) -> Self {
         Self {
-            mgr: MemoryManager::new_with_limits(caps, gpu_limit, cpu_limit, nvme_limit),
+            mgr: MemoryManager::new_with_limits(caps, gpu_limit, cpu_limit, nvme_limit)
+                .with_transfer_engine(TransferEngine::shared()),
+            sinks: None,
         }
     }
 
+    /// Run [`compute`](Self::compute) in attention-sink mode: queries also
+    /// attend to the first `sinks` tokens, and key/value pages that are
+    /// neither sinks nor in the window are freed.
+    pub fn with_attention_sinks(mut self, sinks: usize) -> Self {
+        self.sinks = Some(sinks);
+        self
+    }
+
+    /// Number of attention sinks, `None` outside attention-sink mode.
+    pub fn attention_sinks(&self) -> Option<usize> {
+        self.sinks
+    }
+
     /// Expose tier usage for tests.
     pub fn usage(&self) -> (usize, usize, usize) {
         self.mgr.usage()
@@ -58,6 +98,9 @@ impl PagedAttention {
     /// * `v` – flattened value vectors of length `n_k * d`.
     /// * `d` – feature dimension.
     /// * `window` – number of previous tokens each query attends to.
+    ///
+    /// In attention-sink mode each query additionally attends to the sink
+    /// tokens before its window.
     pub fn compute(
         &mut self,
         q: &[f32],
@@ -74,63 +117,59 @@ impl PagedAttention {
         let n_k = k.len() / d;
         assert_eq!(n_q, n_k, "q, k and v must have the same number of tokens");
 
-        // Allocate key/value buffers using tiering.
-        let byte_len = k.len() * size_of::<f32>();
-        let k_tier = self.mgr.allocate(byte_len);
-        let v_tier = self.mgr.allocate(byte_len);
-
-        // Pull the working set for the sliding window into CPU memory if it
-        // resides on NVMe.  This models a simple cache for large contexts.
-        if matches!(k_tier, MemoryTier::Nvme) {
-            let window_bytes = window * d * size_of::<f32>();
-            self.mgr
-                .migrate(MemoryTier::Nvme, MemoryTier::Cpu, window_bytes);
-        }
-        if matches!(v_tier, MemoryTier::Nvme) {
-            let window_bytes = window * d * size_of::<f32>();
-            self.mgr
-                .migrate(MemoryTier::Nvme, MemoryTier::Cpu, window_bytes);
-        }
+        // Pages hold one window of tokens, so every query only needs its own
+        // page and the previous one to be resident, plus the sink pages.
+        let page_tokens = window.max(1);
+        let sinks = self.sinks;
+        let sink_pages = sinks.map(|sinks| sinks.div_ceil(page_tokens));
+        let pages = self.allocate_pages(n_k.div_ceil(page_tokens), page_tokens * d);
+        let k_tier = self.slowest_tier(&pages.k);
+        let v_tier = self.slowest_tier(&pages.v);
 
         let mut output = vec![0f32; n_q * d];
-        for i in 0..n_q {
-            let q_i = &q[i * d..(i + 1) * d];
-            let start = if i >= window { i + 1 - window } else { 0 };
-
-            // Compute unnormalised scores.
-            let mut scores = Vec::new();
-            for j in start..=i {
-                let k_j = &k[j * d..(j + 1) * d];
-                let dot: f32 = q_i.iter().zip(k_j.iter()).map(|(a, b)| a * b).sum();
-                scores.push((j, dot));
-            }
+        let evicted_pages = self.stream_pages(&pages, 1, sink_pages, |page| {
+            let end = ((page + 1) * page_tokens).min(n_q);
+            for i in page * page_tokens..end {
+                let q_i = &q[i * d..(i + 1) * d];
+                let start = if i >= window { i + 1 - window } else { 0 };
+                let sink_end = sinks.unwrap_or(0).min(start);
 
-            // Softmax normalisation.
-            let max_score = scores
-                .iter()
-                .map(|(_, s)| *s)
-                .fold(f32::NEG_INFINITY, f32::max);
-            let mut denom = 0f32;
-            let mut weights = Vec::new();
-            for &(_, s) in &scores {
-                let w = (s - max_score).exp();
-                denom += w;
-                weights.push(w);
-            }
+                // Compute unnormalised scores.
+                let mut scores = Vec::new();
+                for j in (0..sink_end).chain(start..=i) {
+                    let k_j = &k[j * d..(j + 1) * d];
+                    let dot: f32 = q_i.iter().zip(k_j.iter()).map(|(a, b)| a * b).sum();
+                    scores.push((j, dot));
+                }
+
+                // Softmax normalisation.
+                let max_score = scores
+                    .iter()
+                    .map(|(_, s)| *s)
+                    .fold(f32::NEG_INFINITY, f32::max);
+                let mut denom = 0f32;
+                let mut weights = Vec::new();
+                for &(_, s) in &scores {
+                    let w = (s - max_score).exp();
+                    denom += w;
+                    weights.push(w);
+                }
 
-            for ((j, _), w) in scores.iter().zip(weights.iter()) {
-                let weight = *w / denom;
-                let v_j = &v[*j * d..(*j + 1) * d];
-                for (out, val) in output[i * d..(i + 1) * d].iter_mut().zip(v_j.iter()) {
-                    *out += weight * val;
+                for ((j, _), w) in scores.iter().zip(weights.iter()) {
+                    let weight = *w / denom;
+                    let v_j = &v[*j * d..(*j + 1) * d];
+                    for (out, val) in output[i * d..(i + 1) * d].iter_mut().zip(v_j.iter()) {
+                        *out += weight * val;
+                    }
                 }
             }
-        }
+        });
 
         AttentionResult {
             output,
             k_tier,
             v_tier,
+            evicted_pages,
         }
     }
 
@@ -156,31 +195,15 @@ impl PagedAttention {
         let n = q.len() / d;
         assert_eq!(n, k.len() / d, "q, k and v must have the same number of tokens");
         assert!(block_size > 0);
-        let n_blocks = (n + block_size - 1) / block_size;
-
-        // Allocate key/value buffers using tiering.
-        let byte_len = k.len() * size_of::<f32>();
-        let k_tier = self.mgr.allocate(byte_len);
-        let v_tier = self.mgr.allocate(byte_len);
-
-        // Bring the initial window of blocks into CPU memory if backing store
-        // is NVMe.  This models a simple cache of hot blocks.
-        let block_bytes = block_size * d * size_of::<f32>();
-        let init_blocks = block_window.min(n_blocks);
-        let window_bytes = init_blocks * block_bytes;
-        if matches!(k_tier, MemoryTier::Nvme) {
-            self.mgr
-                .migrate(MemoryTier::Nvme, MemoryTier::Cpu, window_bytes);
-            self.mgr.mark_hot(MemoryTier::Cpu, window_bytes);
-        }
-        if matches!(v_tier, MemoryTier::Nvme) {
-            self.mgr
-                .migrate(MemoryTier::Nvme, MemoryTier::Cpu, window_bytes);
-            self.mgr.mark_hot(MemoryTier::Cpu, window_bytes);
-        }
+        let n_blocks = n.div_ceil(block_size);
+
+        // One page per block of keys and values.
+        let pages = self.allocate_pages(n_blocks, block_size * d);
+        let k_tier = self.slowest_tier(&pages.k);
+        let v_tier = self.slowest_tier(&pages.v);
 
         let mut output = vec![0f32; n * d];
-        for b in 0..n_blocks {
+        self.stream_pages(&pages, block_window.saturating_sub(1), None, |b| {
             let q_start = b * block_size;
             let q_end = (q_start + block_size).min(n);
             let first_block = b.saturating_sub(block_window - 1);
@@ -215,36 +238,89 @@ impl PagedAttention {
                     }
                 }
             }
-
-            // Update cache for next block.
-            if b + 1 < n_blocks {
-                if matches!(k_tier, MemoryTier::Nvme) {
-                    if b + 1 >= block_window {
-                        self.mgr.mark_cold(MemoryTier::Cpu, block_bytes);
-                        self.mgr
-                            .migrate(MemoryTier::Cpu, MemoryTier::Nvme, block_bytes);
-                    }
-                    self.mgr
-                        .migrate(MemoryTier::Nvme, MemoryTier::Cpu, block_bytes);
-                    self.mgr.mark_hot(MemoryTier::Cpu, block_bytes);
-                }
-                if matches!(v_tier, MemoryTier::Nvme) {
-                    if b + 1 >= block_window {
-                        self.mgr.mark_cold(MemoryTier::Cpu, block_bytes);
-                        self.mgr
-                            .migrate(MemoryTier::Cpu, MemoryTier::Nvme, block_bytes);
-                    }
-                    self.mgr
-                        .migrate(MemoryTier::Nvme, MemoryTier::Cpu, block_bytes);
-                    self.mgr.mark_hot(MemoryTier::Cpu, block_bytes);
-                }
-            }
-        }
+        });
 
         AttentionResult {
             output,
             k_tier,
             v_tier,
+            evicted_pages: 0,
+        }
+    }
+
+    /// Allocate `n_pages` key pages followed by as many value pages of
+    /// `page_elems` floats each.
+    fn allocate_pages(&mut self, n_pages: usize, page_elems: usize) -> KvPages {
+        let page_bytes = page_elems * size_of::<f32>();
+        let k = (0..n_pages).map(|_| self.mgr.allocate(page_bytes)).collect();
+        let v = (0..n_pages).map(|_| self.mgr.allocate(page_bytes)).collect();
+        KvPages { k, v }
+    }
+
+    fn slowest_tier(&self, pages: &[AllocationId]) -> MemoryTier {
+        pages
+            .iter()
+            .filter_map(|&page| self.mgr.tier(page))
+            .max()
+            .unwrap_or(MemoryTier::Cpu)
+    }
+
+    /// Run `compute_page` for every page in order while keeping the page and
+    /// its `lookback` predecessors resident.  The working set of the next page
+    /// is touched and prefetched while the current page is computed, so pages
+    /// leaving the window are the least recently used and evicted first.
+    ///
+    /// With `sink_pages`, those leading pages stay in the working set of
+    /// every page and the pages after them are freed once they leave the
+    /// window.  Returns the number of freed pages.
+    fn stream_pages(
+        &mut self,
+        pages: &KvPages,
+        lookback: usize,
+        sink_pages: Option<usize>,
+        mut compute_page: impl FnMut(usize),
+    ) -> usize {
+        let n_pages = pages.k.len();
+        let window = |page: usize| {
+            let start = page.saturating_sub(lookback);
+            (0..sink_pages.unwrap_or(0).min(start)).chain(start..page + 1)
+        };
+        let mut pending = self.fetch(pages, window(0));
+        let mut evicted = 0;
+        for page in 0..n_pages {
+            for transfer in pending.drain(..) {
+                transfer.wait();
+            }
+            if page + 1 < n_pages {
+                pending = self.fetch(pages, window(page + 1));
+            }
+            compute_page(page);
+            if let Some(old) = page.checked_sub(lookback) {
+                if sink_pages.is_some_and(|sinks| old >= sinks) {
+                    self.mgr.free(pages.k[old]);
+                    self.mgr.free(pages.v[old]);
+                    evicted += 1;
+                }
+            }
+        }
+        evicted
+    }
+
+    /// Start loading the NVMe-resident pages in `range` into CPU memory.
+    fn fetch(
+        &mut self,
+        pages: &KvPages,
+        range: impl IntoIterator<Item = usize>,
+    ) -> Vec<TransferHandle> {
+        let mut pending = Vec::new();
+        for i in range {
+            for page in [pages.k[i], pages.v[i]] {
+                self.mgr.touch(page);
+                if self.mgr.tier(page) == Some(MemoryTier::Nvme) {
+                    pending.push(self.mgr.migrate_async(page, MemoryTier::Cpu));
+                }
+            }
         }
+        pending
     }
 }
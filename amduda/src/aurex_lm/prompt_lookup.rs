@@ -0,0 +1,54 @@
+//! Speculative decoding by prompt lookup.
+//!
+//! Summaries, extractions and edits copy long spans of their input.
+//! [`PromptLookup`] exploits that without a draft model: it finds the last
+//! earlier occurrence of the n-gram the context ends with and proposes the
+//! tokens that followed it.  The generation engine runs the proposed tokens
+//! through the model in one [`forward_batch`] call and keeps them as long as
+//! they are what it samples anyway, so the output is unchanged and every
+//! accepted token saves a forward pass.
+//!
+//! [`forward_batch`]: super::tiny_lm::LanguageModel::forward_batch
+
+/// Settings of prompt-lookup drafting, see
+/// [`GenerationConfig::prompt_lookup`](super::generation::GenerationConfig::prompt_lookup).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptLookup {
+    /// Longest suffix of the context searched for; shorter suffixes are
+    /// tried when it does not occur earlier.
+    pub max_ngram: usize,
+    /// Most tokens proposed at once.
+    pub max_draft: usize,
+}
+
+impl Default for PromptLookup {
+    fn default() -> Self {
+        Self {
+            max_ngram: 3,
+            max_draft: 8,
+        }
+    }
+}
+
+impl PromptLookup {
+    /// Tokens that followed the last earlier occurrence of the longest
+    /// matching suffix of `context`, at most `limit` of them and
+    /// [`max_draft`](Self::max_draft).  Empty when no suffix recurs.
+    pub fn draft<'a>(&self, context: &'a [u32], limit: usize) -> &'a [u32] {
+        let limit = limit.min(self.max_draft);
+        if limit == 0 || context.len() < 2 {
+            return &[];
+        }
+        for n in (1..=self.max_ngram.min(context.len() - 1)).rev() {
+            let suffix = &context[context.len() - n..];
+            let found = context[..context.len() - 1]
+                .windows(n)
+                .rposition(|window| window == suffix);
+            if let Some(start) = found {
+                let from = start + n;
+                return &context[from..(from + limit).min(context.len())];
+            }
+        }
+        &[]
+    }
+}
@@ -1,21 +1,69 @@
 //! Quantization and dequantization utilities for Aurex-LM.
 //!
 //! The routines implemented here perform simple per-tensor symmetric
-//! quantization.  INT8 and INT4 quantization return a scale factor that is
-//! required for dequantization while BF16 quantization simply truncates the
-//! mantissa of `f32` values.
+//! quantization.  INT8, INT4 and NF4 quantization return a scale factor that
+//! is required for dequantization while BF16 quantization simply truncates
+//! the mantissa of `f32` values.
+//!
+//! Non-finite values do not affect the scale: NaN quantizes to zero and
+//! infinities saturate to the largest level of their sign.
+//!
+//! Embedded targets built without the `std` feature keep this module.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 use half::bf16;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+use serde::{Deserialize, Serialize};
+
+/// Supported on-disk quantized weight formats.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    Int4,
+    Int8,
+    Bf16,
+}
+
+impl core::str::FromStr for Quantization {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "int4" => Ok(Quantization::Int4),
+            "int8" => Ok(Quantization::Int8),
+            "bf16" => Ok(Quantization::Bf16),
+            other => Err(format!("unknown quantization '{other}'")),
+        }
+    }
+}
+
+/// Largest magnitude among the finite values of `data`.
+fn finite_max(data: &[f32]) -> f32 {
+    data.iter()
+        .filter(|v| v.is_finite())
+        .fold(0.0_f32, |m, &v| m.max(v.abs()))
+}
+
+/// Scale mapping the magnitude `max` to `levels`: 1 for all-zero data, and
+/// at least the smallest positive `f32` when tiny magnitudes underflow.
+fn scale_for(max: f32, levels: f32) -> f32 {
+    if max == 0.0 {
+        1.0
+    } else {
+        (max / levels).max(f32::from_bits(1))
+    }
+}
 
 /// Quantize a slice of `f32` values into INT8 representation.
 ///
 /// Returns the quantized values and the scaling factor used during
 /// quantization.
 pub fn quantize_int8(data: &[f32]) -> (Vec<i8>, f32) {
-    let max = data
-        .iter()
-        .fold(0.0_f32, |m, &v| m.max(v.abs()));
-    let scale = if max == 0.0 { 1.0 } else { max / 127.0 };
+    let scale = scale_for(finite_max(data), 127.0);
     let quantized = data
         .iter()
         .map(|&v| {
@@ -37,10 +85,7 @@ pub fn dequantize_int8(data: &[i8], scale: f32) -> Vec<f32> {
 /// expected to track the original length of the data for correct
 /// dequantization.
 pub fn quantize_int4(data: &[f32]) -> (Vec<u8>, f32) {
-    let max = data
-        .iter()
-        .fold(0.0_f32, |m, &v| m.max(v.abs()));
-    let scale = if max == 0.0 { 1.0 } else { max / 7.0 };
+    let scale = scale_for(finite_max(data), 7.0);
     let mut out = Vec::with_capacity((data.len() + 1) / 2);
     for chunk in data.chunks(2) {
         let mut byte = 0u8;
@@ -85,3 +130,67 @@ pub fn dequantize_bf16(data: &[u16]) -> Vec<f32> {
     data.iter().map(|&v| bf16::from_bits(v).to_f32()).collect()
 }
 
+/// The 16 NormalFloat4 levels: quantiles of a standard normal distribution
+/// scaled to `[-1, 1]`, with an exact zero.
+pub const NF4_LEVELS: [f32; 16] = [
+    -1.0,
+    -0.696_192_8,
+    -0.525_073_05,
+    -0.394_917_5,
+    -0.284_441_38,
+    -0.184_773_43,
+    -0.091_050_036,
+    0.0,
+    0.079_580_3,
+    0.160_930_2,
+    0.246_112_3,
+    0.337_915_24,
+    0.440_709_83,
+    0.562_617,
+    0.722_956_84,
+    1.0,
+];
+
+/// Index of the [`NF4_LEVELS`] entry nearest to `v`.
+fn nf4_index(v: f32) -> u8 {
+    if v.is_nan() {
+        return 7;
+    }
+    let v = v.clamp(-1.0, 1.0);
+    let mut best = 0;
+    for (i, level) in NF4_LEVELS.iter().enumerate() {
+        if (v - level).abs() < (v - NF4_LEVELS[best]).abs() {
+            best = i;
+        }
+    }
+    best as u8
+}
+
+/// Quantize a slice of `f32` values into NF4 representation, which suits
+/// normally distributed weights better than INT4.
+///
+/// Values are divided by the largest magnitude and replaced by the index of
+/// the nearest of the [`NF4_LEVELS`], packed two per byte like INT4.
+pub fn quantize_nf4(data: &[f32]) -> (Vec<u8>, f32) {
+    let scale = scale_for(finite_max(data), 1.0);
+    let out = data
+        .chunks(2)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &v)| byte | nf4_index(v / scale) << (4 * i))
+        })
+        .collect();
+    (out, scale)
+}
+
+/// Dequantize NF4 values back into `f32` using the provided scale and
+/// original length of the data.
+pub fn dequantize_nf4(data: &[u8], scale: f32, len: usize) -> Vec<f32> {
+    data.iter()
+        .flat_map(|&byte| [byte & 0x0F, byte >> 4])
+        .take(len)
+        .map(|index| NF4_LEVELS[index as usize] * scale)
+        .collect()
+}
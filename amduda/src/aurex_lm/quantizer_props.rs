@@ -0,0 +1,229 @@
+//! Property-based checks of the [`quantizer`](super::quantizer).
+//!
+//! The strategies generate tensors of random length and magnitude, including
+//! the cases that break naive quantizers: all-zero tensors, subnormal and
+//! huge values, and NaN or infinite entries.  The `check_*` functions state
+//! the properties every [`Format`] must keep, for use in `proptest!` blocks:
+//!
+//! - dequantized finite values stay within half a quantization step of the
+//!   input, or within the rounding error of BF16,
+//! - the scale never shrinks when the input grows,
+//! - quantizing dequantized values reproduces the packed bytes,
+//! - non-finite inputs leave the finite values and the scale untouched.
+//!
+//! Available with the `proptest` feature; `cargo test -p amduda --features
+//! proptest` runs them against the quantizer.
+
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+
+use super::quantizer::{
+    dequantize_bf16, dequantize_int4, dequantize_int8, dequantize_nf4, quantize_bf16,
+    quantize_int4, quantize_int8, quantize_nf4, NF4_LEVELS,
+};
+
+/// Quantized representation under test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Int4,
+    Int8,
+    Bf16,
+    Nf4,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Int4, Format::Int8, Format::Bf16, Format::Nf4];
+
+    /// Packed bytes and scale of `data`; BF16 has a scale of 1.
+    pub fn quantize(self, data: &[f32]) -> (Vec<u8>, f32) {
+        match self {
+            Format::Int4 => quantize_int4(data),
+            Format::Int8 => {
+                let (q, scale) = quantize_int8(data);
+                (q.into_iter().map(|v| v as u8).collect(), scale)
+            }
+            Format::Bf16 => {
+                let q = quantize_bf16(data);
+                (q.iter().flat_map(|v| v.to_le_bytes()).collect(), 1.0)
+            }
+            Format::Nf4 => quantize_nf4(data),
+        }
+    }
+
+    /// Values of `len` elements packed by [`Format::quantize`].
+    pub fn dequantize(self, bytes: &[u8], scale: f32, len: usize) -> Vec<f32> {
+        match self {
+            Format::Int4 => dequantize_int4(bytes, scale, len),
+            Format::Int8 => {
+                let q: Vec<i8> = bytes.iter().map(|&b| b as i8).collect();
+                dequantize_int8(&q, scale)
+            }
+            Format::Bf16 => {
+                let q: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                dequantize_bf16(&q)
+            }
+            Format::Nf4 => dequantize_nf4(bytes, scale, len),
+        }
+    }
+
+    /// Bytes taken by `len` quantized values.
+    pub fn packed_len(self, len: usize) -> usize {
+        match self {
+            Format::Int4 | Format::Nf4 => len.div_ceil(2),
+            Format::Int8 => len,
+            Format::Bf16 => 2 * len,
+        }
+    }
+
+    /// Largest error of a dequantized finite value `x` quantized with
+    /// `scale`.
+    pub fn error_bound(self, x: f32, scale: f32) -> f32 {
+        // Slack for the rounding of the scale and the division by it, which
+        // is imprecise for subnormal scales.
+        let slack = 1e-5 * scale + f32::MIN_POSITIVE;
+        match self {
+            Format::Int4 | Format::Int8 => 0.5 * scale + slack,
+            Format::Nf4 => 0.5 * max_nf4_gap() * scale + slack,
+            // BF16 keeps 8 significant bits, rounding to nearest, and its
+            // smallest subnormal is 2^-133.
+            Format::Bf16 => x.abs() / 256.0 + f32::from_bits(1 << 16),
+        }
+    }
+}
+
+fn max_nf4_gap() -> f32 {
+    NF4_LEVELS
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .fold(0.0, f32::max)
+}
+
+/// Finite values from subnormal to huge magnitudes, zeros included.
+pub fn finite_value() -> impl Strategy<Value = f32> {
+    prop_oneof![
+        4 => -1.0e3_f32..1.0e3,
+        1 => Just(0.0_f32),
+        1 => -1.0e-3_f32..1.0e-3,
+        1 => prop::num::f32::NORMAL | prop::num::f32::SUBNORMAL | prop::num::f32::ZERO,
+    ]
+    // Values near f32::MAX overflow when BF16 rounds them up.
+    .prop_filter("representable in bf16", |v| v.abs() < 3.0e38)
+}
+
+/// Any value, NaN and infinities included.
+pub fn any_value() -> impl Strategy<Value = f32> {
+    prop_oneof![
+        8 => finite_value(),
+        1 => Just(f32::NAN),
+        1 => Just(f32::INFINITY),
+        1 => Just(f32::NEG_INFINITY),
+    ]
+}
+
+/// Tensors of finite values with up to `max_len` elements, or all zeros.
+pub fn tensor(max_len: usize) -> impl Strategy<Value = Vec<f32>> {
+    prop_oneof![
+        4 => prop::collection::vec(finite_value(), 0..=max_len),
+        1 => (0..=max_len).prop_map(|len| vec![0.0; len]),
+    ]
+}
+
+/// Tensors with up to `max_len` elements of [`any_value`].
+pub fn tensor_with_non_finite(max_len: usize) -> impl Strategy<Value = Vec<f32>> {
+    prop::collection::vec(any_value(), 0..=max_len)
+}
+
+/// Finite values of `data` come back within [`Format::error_bound`], and
+/// the packed size matches [`Format::packed_len`].
+pub fn check_error_bound(format: Format, data: &[f32]) -> Result<(), TestCaseError> {
+    let (bytes, scale) = format.quantize(data);
+    prop_assert_eq!(bytes.len(), format.packed_len(data.len()));
+    prop_assert!(scale.is_finite() && scale > 0.0, "scale {}", scale);
+    let values = format.dequantize(&bytes, scale, data.len());
+    prop_assert_eq!(values.len(), data.len());
+    for (i, (&x, &y)) in data.iter().zip(&values).enumerate() {
+        if !x.is_finite() {
+            continue;
+        }
+        let bound = format.error_bound(x, scale);
+        prop_assert!(
+            (x - y).abs() <= bound,
+            "{:?}: value {} at {} dequantized to {} with scale {}, bound {}",
+            format,
+            x,
+            i,
+            y,
+            scale,
+            bound
+        );
+    }
+    Ok(())
+}
+
+/// Multiplying the input by `factor >= 1` never shrinks the scale.
+pub fn check_scale_monotonic(
+    format: Format,
+    data: &[f32],
+    factor: f32,
+) -> Result<(), TestCaseError> {
+    let grown: Vec<f32> = data.iter().map(|v| v * factor).collect();
+    if grown.iter().any(|v| v.is_infinite()) || data.iter().all(|&v| v == 0.0) {
+        return Ok(());
+    }
+    let (_, scale) = format.quantize(data);
+    let (_, grown_scale) = format.quantize(&grown);
+    prop_assert!(
+        grown_scale >= scale,
+        "{:?}: scale {} shrank to {} when growing by {}",
+        format,
+        scale,
+        grown_scale,
+        factor
+    );
+    Ok(())
+}
+
+/// Quantizing the dequantized values reproduces the packed bytes.
+pub fn check_packing_round_trip(format: Format, data: &[f32]) -> Result<(), TestCaseError> {
+    let (bytes, scale) = format.quantize(data);
+    let values = format.dequantize(&bytes, scale, data.len());
+    let (again, _) = format.quantize(&values);
+    prop_assert_eq!(again, bytes, "{:?} repacked differently", format);
+    Ok(())
+}
+
+/// NaN and infinities neither change the scale nor the quantization of the
+/// finite values; NaN dequantizes to zero in the integer formats and stays
+/// NaN in BF16.
+pub fn check_non_finite(format: Format, data: &[f32]) -> Result<(), TestCaseError> {
+    let finite: Vec<f32> = data
+        .iter()
+        .map(|&v| if v.is_finite() { v } else { 0.0 })
+        .collect();
+    let (bytes, scale) = format.quantize(data);
+    let (_, finite_scale) = format.quantize(&finite);
+    prop_assert_eq!(scale, finite_scale);
+    let values = format.dequantize(&bytes, scale, data.len());
+    for (&x, &y) in data.iter().zip(&values) {
+        if x.is_nan() {
+            let expected = if format == Format::Bf16 {
+                y.is_nan()
+            } else {
+                y == 0.0
+            };
+            prop_assert!(expected, "{:?}: NaN dequantized to {}", format, y);
+        } else if x.is_infinite() && format != Format::Bf16 {
+            prop_assert!(
+                y.is_finite() && y.signum() == x.signum(),
+                "{:?}: {} dequantized to {}",
+                format,
+                x,
+                y
+            );
+        }
+    }
+    check_error_bound(format, data)
+}
@@ -0,0 +1,143 @@
+//! Self-critique and retry of generations.
+//!
+//! A [`Reflexion`] policy, attached with
+//! [`GenerationEngine::with_reflexion`], makes the engine critique each
+//! generation with the model itself: the output is followed by the
+//! [`ReflexionPolicy::critique`] prompt and the model's distribution over the
+//! digits `0`-`9` gives a score between 0 and 1.  Outputs scoring below the
+//! threshold are retried from the cached prompt with a higher temperature and
+//! a new seed, and the best attempt is returned.
+//!
+//! `Reflexion` also implements the runtime's [`ReflexionLoop`]: after a low
+//! score it turns the next reflected event into
+//! `RuntimeEvent::TokenFetched { cache_hit: true }`, which takes the
+//! [`ProceduralFsm`] from `FetchToken` straight back to `ComputeAttention`
+//! with the prompt still in the KV cache.
+//!
+//! [`GenerationEngine::with_reflexion`]: super::generation::GenerationEngine::with_reflexion
+//! [`ProceduralFsm`]: crate::amduda_core::procedural_fsm::ProceduralFsm
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use aurex_runtime::{ReflexionLoop, RuntimeEvent};
+
+use super::sampler::SamplingParams;
+
+/// Settings of a [`Reflexion`] policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflexionPolicy {
+    /// Attempts scoring below this are retried.
+    pub threshold: f32,
+    /// Retries after the first attempt.
+    pub max_retries: usize,
+    /// Added to the temperature on every retry.
+    pub temperature_step: f32,
+    /// Number of recent attempts kept in the history.
+    pub history: usize,
+    /// Text appended to the output to ask the model for a score digit.
+    pub critique: String,
+}
+
+impl Default for ReflexionPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            max_retries: 2,
+            temperature_step: 0.3,
+            history: 16,
+            critique: "\nRate the answer above from 0 (wrong) to 9 (excellent): ".into(),
+        }
+    }
+}
+
+/// A critiqued generation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    pub prompt: String,
+    pub text: String,
+    /// Critique score between 0 and 1.
+    pub score: f32,
+    /// Sampling parameters the text was generated with.
+    pub sampling: SamplingParams,
+}
+
+/// Opt-in self-critique policy, see the [module documentation](self).
+#[derive(Debug, Default)]
+pub struct Reflexion {
+    policy: ReflexionPolicy,
+    history: Mutex<VecDeque<Attempt>>,
+    retry_pending: AtomicBool,
+}
+
+impl Reflexion {
+    pub fn new(policy: ReflexionPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    pub fn policy(&self) -> &ReflexionPolicy {
+        &self.policy
+    }
+
+    /// Recent attempts, oldest first.
+    pub fn history(&self) -> Vec<Attempt> {
+        self.history.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Score in `[0, 1]` read from the next-token `logits` after the
+    /// critique prompt: the expected value of the digit the model would
+    /// write, divided by 9.
+    pub fn score(logits: &[f32]) -> f32 {
+        let digits: Vec<f32> = (b'0'..=b'9')
+            .map(|d| logits.get(d as usize).copied().unwrap_or(f32::NEG_INFINITY))
+            .collect();
+        let max = digits.iter().fold(f32::NEG_INFINITY, |m, &l| m.max(l));
+        if !max.is_finite() {
+            return 0.0;
+        }
+        let weights: Vec<f32> = digits.iter().map(|l| (l - max).exp()).collect();
+        let total: f32 = weights.iter().sum();
+        let expected: f32 = weights.iter().enumerate().map(|(d, w)| d as f32 * w).sum();
+        expected / total / 9.0
+    }
+
+    /// Record `attempt` and decide whether to retry: the score is below the
+    /// threshold and `retries` retries have been made so far.
+    pub fn record(&self, attempt: Attempt, retries: usize) -> bool {
+        let retry = attempt.score < self.policy.threshold && retries < self.policy.max_retries;
+        let mut history = self.history.lock().unwrap();
+        history.push_back(attempt);
+        while history.len() > self.policy.history {
+            history.pop_front();
+        }
+        if retry {
+            self.retry_pending.store(true, Ordering::SeqCst);
+        }
+        retry
+    }
+
+    /// Sampling parameters for retry number `retry` (starting at 1).
+    pub fn adjust(&self, sampling: SamplingParams, retry: usize) -> SamplingParams {
+        SamplingParams {
+            temperature: sampling.temperature + self.policy.temperature_step * retry as f32,
+            seed: sampling.seed.wrapping_add(retry as u64),
+            ..sampling
+        }
+    }
+}
+
+#[async_trait]
+impl ReflexionLoop for Reflexion {
+    async fn reflect(&self, event: &RuntimeEvent) -> RuntimeEvent {
+        if self.retry_pending.swap(false, Ordering::SeqCst) {
+            RuntimeEvent::TokenFetched { cache_hit: true }
+        } else {
+            event.clone()
+        }
+    }
+}
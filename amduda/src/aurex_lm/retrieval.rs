@@ -0,0 +1,449 @@
+//! Retrieval for retrieval-augmented generation (`.aurexri` indexes).
+//!
+//! Documents are split by [`chunk_text`] into overlapping passages that end
+//! at paragraph, sentence or word boundaries where possible.  A
+//! [`VectorIndex`] embeds the passages with an [`EmbeddingModel`] and keeps
+//! their unit-length vectors in a flat array searched exhaustively, so
+//! results are exact; the index is saved to and loaded from disk.  A
+//! [`Retriever`] is the retrieval stage in front of generation: it embeds a
+//! query, looks up the closest chunks and injects them into the prompt.
+//!
+//! Layout:
+//!
+//! ```text
+//! magic "AUREXRI\x01" | u32 header length | header JSON
+//! | u64 payload length | payload
+//! ```
+//!
+//! The header holds the model name, pooling and chunks; the payload holds
+//! one vector of `dimension` `f32` values per chunk.  All integers are little
+//! endian.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::embeddings::{EmbeddingModel, Pooling};
+
+/// File magic identifying a retrieval index.
+pub const INDEX_MAGIC: &[u8; 8] = b"AUREXRI\x01";
+
+/// Header format version written by this build.
+pub const INDEX_VERSION: u32 = 1;
+
+/// Conventional file extension of retrieval indexes.
+pub const INDEX_EXTENSION: &str = "aurexri";
+
+/// How documents are split into chunks, in bytes, which the byte-level
+/// tokenizer counts as tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkConfig {
+    /// Longest chunk.
+    pub size: usize,
+    /// Bytes the start of a chunk repeats from the end of the previous one;
+    /// at most half the size.
+    pub overlap: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            size: 512,
+            overlap: 64,
+        }
+    }
+}
+
+/// Split `text` into chunks of at most `config.size` bytes, returned with
+/// their byte offsets.  Chunks are trimmed of surrounding whitespace; a
+/// word longer than a chunk is cut.
+pub fn chunk_text<'a>(text: &'a str, config: &ChunkConfig) -> Vec<(usize, &'a str)> {
+    let size = config.size.max(1);
+    let overlap = config.overlap.min(size / 2);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let mut end = floor_char_boundary(text, (start + size).min(text.len()));
+        if end < text.len() {
+            let window = &text[start..end];
+            // Prefer ending after a paragraph, then a sentence, then a word,
+            // unless that would leave less than half a chunk.
+            let cut = window
+                .rfind("\n\n")
+                .map(|i| i + 2)
+                .or_else(|| window.rfind(". ").map(|i| i + 2))
+                .or_else(|| after_last_whitespace(window));
+            if let Some(cut) = cut.filter(|&cut| cut > window.len() / 2) {
+                end = start + cut;
+            }
+        }
+        if end <= start {
+            // A chunk smaller than the character at `start`.
+            end = start + text[start..].chars().next().map_or(1, char::len_utf8);
+        }
+        let piece = &text[start..end];
+        let trimmed = piece.trim_start();
+        let offset = start + piece.len() - trimmed.len();
+        let trimmed = trimmed.trim_end();
+        if !trimmed.is_empty() {
+            chunks.push((offset, trimmed));
+        }
+        if end == text.len() {
+            break;
+        }
+        // Step back by the overlap, then forward past the word cut into.
+        let back = floor_char_boundary(text, end.saturating_sub(overlap));
+        let back = if back > start { back } else { end };
+        start = text[back..end]
+            .char_indices()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(end, |(i, c)| back + i + c.len_utf8());
+    }
+    chunks
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn after_last_whitespace(text: &str) -> Option<usize> {
+    text.char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map(|(i, c)| i + c.len_utf8())
+}
+
+/// Passage of a document stored in a [`VectorIndex`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Name of the document, e.g. its path.
+    pub document: String,
+    /// Byte offset of the chunk in the document.
+    pub start: usize,
+    pub text: String,
+}
+
+/// Chunk found for a query, with the cosine similarity of its vector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit<'a> {
+    pub chunk: &'a Chunk,
+    pub score: f32,
+}
+
+/// Metadata stored at the start of an index file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct IndexHeader {
+    version: u32,
+    model: String,
+    pooling: Pooling,
+    dimension: usize,
+    chunks: Vec<Chunk>,
+}
+
+/// Exact nearest neighbour index over chunk embeddings, see the
+/// [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorIndex {
+    model: String,
+    pooling: Pooling,
+    dimension: usize,
+    chunks: Vec<Chunk>,
+    /// `chunks.len() x dimension` unit-length vectors.
+    vectors: Vec<f32>,
+}
+
+impl VectorIndex {
+    /// Empty index for `dimension`-sized embeddings that `model` computes
+    /// with `pooling`.
+    pub fn new(model: impl Into<String>, dimension: usize, pooling: Pooling) -> Self {
+        Self {
+            model: model.into(),
+            pooling,
+            dimension,
+            chunks: Vec::new(),
+            vectors: Vec::new(),
+        }
+    }
+
+    /// Name of the model the vectors are computed with.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn pooling(&self) -> Pooling {
+        self.pooling
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Add `chunk` with its embedding `vector`, which is normalised.
+    pub fn insert(&mut self, chunk: Chunk, mut vector: Vec<f32>) -> Result<()> {
+        if vector.len() != self.dimension {
+            bail!(
+                "embedding has {} values, the index {}",
+                vector.len(),
+                self.dimension
+            );
+        }
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+        self.chunks.push(chunk);
+        self.vectors.extend(vector);
+        Ok(())
+    }
+
+    /// Remove the chunks of `document` and return how many there were.
+    pub fn remove_document(&mut self, document: &str) -> usize {
+        let d = self.dimension;
+        let before = self.chunks.len();
+        let mut kept = 0;
+        for i in 0..before {
+            if self.chunks[i].document != document {
+                self.chunks.swap(kept, i);
+                self.vectors.copy_within(i * d..(i + 1) * d, kept * d);
+                kept += 1;
+            }
+        }
+        self.chunks.truncate(kept);
+        self.vectors.truncate(kept * d);
+        before - kept
+    }
+
+    /// Chunk `text`, embed the chunks with `embeddings` and store them as
+    /// `document`, replacing its previous chunks.  Returns the number of
+    /// chunks stored.
+    pub fn ingest(
+        &mut self,
+        embeddings: &EmbeddingModel,
+        document: &str,
+        text: &str,
+        config: &ChunkConfig,
+    ) -> Result<usize> {
+        self.check_model(embeddings)?;
+        self.remove_document(document);
+        let chunks = chunk_text(text, config);
+        let texts: Vec<&str> = chunks.iter().map(|&(_, text)| text).collect();
+        let vectors = embeddings.embed_batch(&texts, self.pooling);
+        for ((start, text), vector) in chunks.iter().zip(vectors) {
+            let chunk = Chunk {
+                document: document.to_string(),
+                start: *start,
+                text: text.to_string(),
+            };
+            self.insert(chunk, vector)?;
+        }
+        Ok(chunks.len())
+    }
+
+    /// The `k` chunks whose vectors are closest to `query`, best first.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<Hit<'_>> {
+        if query.len() != self.dimension || self.dimension == 0 {
+            return Vec::new();
+        }
+        let norm = query
+            .iter()
+            .map(|v| v * v)
+            .sum::<f32>()
+            .sqrt()
+            .max(f32::MIN_POSITIVE);
+        let mut hits: Vec<Hit> = self
+            .chunks
+            .iter()
+            .zip(self.vectors.chunks_exact(self.dimension))
+            .map(|(chunk, vector)| Hit {
+                chunk,
+                score: vector.iter().zip(query).map(|(a, b)| a * b).sum::<f32>() / norm,
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(k);
+        hits
+    }
+
+    fn check_model(&self, embeddings: &EmbeddingModel) -> Result<()> {
+        if embeddings.hidden_size() != self.dimension {
+            bail!(
+                "index holds {}-dimensional embeddings of model {}, the model computes {}",
+                self.dimension,
+                self.model,
+                embeddings.hidden_size()
+            );
+        }
+        Ok(())
+    }
+
+    /// Serialize the index to `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let header = IndexHeader {
+            version: INDEX_VERSION,
+            model: self.model.clone(),
+            pooling: self.pooling,
+            dimension: self.dimension,
+            chunks: self.chunks.clone(),
+        };
+        let json = serde_json::to_vec(&header)?;
+        let payload: Vec<u8> = self.vectors.iter().flat_map(|v| v.to_le_bytes()).collect();
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(&json)?;
+        writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+        writer.write_all(&payload)?;
+        Ok(())
+    }
+
+    /// Deserialize an index from `reader`.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("reading index header")?;
+        if &magic != INDEX_MAGIC {
+            bail!("not an aurexri index");
+        }
+        let mut len4 = [0u8; 4];
+        reader.read_exact(&mut len4)?;
+        let mut json = vec![0u8; u32::from_le_bytes(len4) as usize];
+        reader.read_exact(&mut json)?;
+        let header: IndexHeader = serde_json::from_slice(&json)?;
+        if header.version != INDEX_VERSION {
+            bail!(
+                "unsupported index version {} (expected {})",
+                header.version,
+                INDEX_VERSION
+            );
+        }
+        let mut len8 = [0u8; 8];
+        reader.read_exact(&mut len8)?;
+        let len = u64::from_le_bytes(len8) as usize;
+        let expected = 4 * header.chunks.len() * header.dimension;
+        if len != expected {
+            bail!("index payload has {len} bytes, expected {expected}");
+        }
+        let mut payload = vec![0u8; len];
+        reader
+            .read_exact(&mut payload)
+            .context("reading index vectors")?;
+        let vectors = payload
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Ok(Self {
+            model: header.model,
+            pooling: header.pooling,
+            dimension: header.dimension,
+            chunks: header.chunks,
+            vectors,
+        })
+    }
+
+    /// Write the index to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = fs::File::create(path.as_ref())
+            .with_context(|| format!("creating {}", path.as_ref().display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read the index at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = fs::File::open(path.as_ref())
+            .with_context(|| format!("opening {}", path.as_ref().display()))?;
+        Self::read_from(std::io::BufReader::new(file))
+            .with_context(|| format!("loading {}", path.as_ref().display()))
+    }
+}
+
+/// Retrieval stage in front of generation: finds the chunks of an index
+/// closest to a query and injects them into the prompt.
+pub struct Retriever<'a> {
+    index: &'a VectorIndex,
+    embeddings: &'a EmbeddingModel,
+    top_k: usize,
+    min_score: Option<f32>,
+}
+
+impl<'a> Retriever<'a> {
+    /// Retriever embedding queries with `embeddings`, which must be the
+    /// model the index was built with.
+    pub fn new(index: &'a VectorIndex, embeddings: &'a EmbeddingModel) -> Result<Self> {
+        index.check_model(embeddings)?;
+        Ok(Self {
+            index,
+            embeddings,
+            top_k: 4,
+            min_score: None,
+        })
+    }
+
+    /// Chunks injected per query.
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Leave out chunks less similar to the query than `min_score`.
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    /// The chunks closest to `query`, best first.
+    pub fn retrieve(&self, query: &str) -> Vec<Hit<'a>> {
+        let vector = self.embeddings.embed(query, self.index.pooling);
+        let mut hits = self.index.search(&vector, self.top_k);
+        if let Some(min_score) = self.min_score {
+            hits.retain(|hit| hit.score >= min_score);
+        }
+        hits
+    }
+
+    /// Prompt answering `query` from the chunks closest to it, and those
+    /// chunks.
+    pub fn augment(&self, query: &str) -> (String, Vec<Hit<'a>>) {
+        let hits = self.retrieve(query);
+        (augment_prompt(query, &hits), hits)
+    }
+}
+
+/// Prompt asking to answer `query` from the numbered chunks of `hits`.
+pub fn augment_prompt(query: &str, hits: &[Hit]) -> String {
+    if hits.is_empty() {
+        return format!("Question: {query}\nAnswer:");
+    }
+    let mut prompt = String::from("Answer the question using the context.\n\nContext:\n");
+    for (i, hit) in hits.iter().enumerate() {
+        let _ = writeln!(
+            prompt,
+            "[{}] {}: {}",
+            i + 1,
+            hit.chunk.document,
+            hit.chunk.text
+        );
+    }
+    let _ = write!(prompt, "\nQuestion: {query}\nAnswer:");
+    prompt
+}
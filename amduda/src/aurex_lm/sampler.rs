@@ -0,0 +1,125 @@
+//! Token sampling from model logits.
+//!
+//! Supports greedy decoding (temperature `0`), temperature scaling and
+//! nucleus (top-p) filtering.  A small deterministic PRNG keeps generations
+//! reproducible for a given seed.  [`apply_logit_bias`] shifts the logits of
+//! chosen tokens before sampling.
+
+use std::collections::HashMap;
+
+/// Parameters controlling token selection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplingParams {
+    /// Softmax temperature; `0` selects the most likely token.
+    pub temperature: f32,
+    /// Nucleus threshold in `(0, 1]`; `1` disables filtering.
+    pub top_p: f32,
+    /// Seed of the random number generator.
+    pub seed: u64,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            temperature: 0.8,
+            top_p: 0.95,
+            seed: 0x5eed,
+        }
+    }
+}
+
+/// Stateful sampler.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    params: SamplingParams,
+    state: u64,
+}
+
+impl Sampler {
+    pub fn new(params: SamplingParams) -> Self {
+        Self {
+            params,
+            // xorshift must not start at zero
+            state: params.seed | 1,
+        }
+    }
+
+    pub fn params(&self) -> SamplingParams {
+        self.params
+    }
+
+    /// Select the next token from `logits`.
+    pub fn sample(&mut self, logits: &[f32]) -> u32 {
+        if self.params.temperature <= 0.0 {
+            return argmax(logits);
+        }
+        let probs = softmax(logits, self.params.temperature);
+        let mut order: Vec<usize> = (0..probs.len()).collect();
+        order.sort_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+
+        let top_p = self.params.top_p.clamp(f32::MIN_POSITIVE, 1.0);
+        let mut kept = Vec::new();
+        let mut mass = 0.0;
+        for idx in order {
+            kept.push(idx);
+            mass += probs[idx];
+            if mass >= top_p {
+                break;
+            }
+        }
+
+        let mut target = self.next_f32() * mass;
+        for &idx in &kept {
+            target -= probs[idx];
+            if target <= 0.0 {
+                return idx as u32;
+            }
+        }
+        *kept.last().unwrap_or(&0) as u32
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let v = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (v >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Index of the largest logit.
+pub fn argmax(logits: &[f32]) -> u32 {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i as u32)
+        .unwrap_or(0)
+}
+
+/// Add the bias of every token in `bias` to its logit.  A bias of
+/// `f32::NEG_INFINITY` bans the token; tokens outside `logits` are ignored.
+pub fn apply_logit_bias(logits: &mut [f32], bias: &HashMap<u32, f32>) {
+    for (&token, &b) in bias {
+        if let Some(logit) = logits.get_mut(token as usize) {
+            *logit += b;
+        }
+    }
+}
+
+/// Numerically stable softmax of `logits / temperature`.
+pub fn softmax(logits: &[f32], temperature: f32) -> Vec<f32> {
+    let t = if temperature > 0.0 { temperature } else { 1.0 };
+    let max = logits.iter().fold(f32::NEG_INFINITY, |m, &v| m.max(v));
+    let exps: Vec<f32> = logits.iter().map(|&v| ((v - max) / t).exp()).collect();
+    let sum: f32 = exps.iter().sum();
+    exps.into_iter().map(|e| e / sum).collect()
+}
+
+/// Log-probability of `token` under the softmax of `logits`.
+pub fn log_softmax(logits: &[f32], token: u32) -> f32 {
+    let max = logits.iter().fold(f32::NEG_INFINITY, |m, &l| m.max(l));
+    let total: f32 = logits.iter().map(|l| (l - max).exp()).sum();
+    logits[token as usize] - max - total.ln()
+}
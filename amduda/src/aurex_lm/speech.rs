@@ -0,0 +1,141 @@
+//! Convolutional front-end of Whisper-style speech encoders.
+//!
+//! [`SpeechFrontEnd`] turns the `n_mels x frames` log-mel spectrogram of a
+//! [`MelSpectrogram`](crate::amduda_core::audio::MelSpectrogram) into the
+//! input sequence of the encoder's transformer blocks:
+//!
+//! 1. a 3-tap convolution from the mel bands to the hidden size, then GELU;
+//! 2. a 3-tap convolution with stride 2, halving the frame rate, then GELU;
+//! 3. the result is transposed to `positions x hidden` and the fixed
+//!    sinusoidal position embedding is added.
+//!
+//! Both convolutions and activations run through [`TensorOps`], so a
+//! backend's [`conv1d`](TensorOps::conv1d) kernel applies.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::amduda_core::tensor_ops::{Conv1dShape, TensorOps};
+use crate::hal_backends::cpu_simd::CpuSimdBackend;
+
+/// Taps of both front-end convolutions.
+pub const FRONT_END_KERNEL: usize = 3;
+
+/// Two-layer convolutional stem of a speech encoder, see the
+/// [module documentation](self).
+#[derive(Clone)]
+pub struct SpeechFrontEnd {
+    n_mels: usize,
+    hidden: usize,
+    conv1: Vec<f32>,
+    bias1: Vec<f32>,
+    conv2: Vec<f32>,
+    bias2: Vec<f32>,
+    backend: Arc<dyn TensorOps + Send + Sync>,
+}
+
+impl SpeechFrontEnd {
+    /// Weights of a front-end from `n_mels` bands to `hidden` dimensions.
+    pub fn weight_count(n_mels: usize, hidden: usize) -> usize {
+        (n_mels + hidden) * hidden * FRONT_END_KERNEL + 2 * hidden
+    }
+
+    /// Build a front-end from `weights` holding the `hidden x n_mels x 3`
+    /// kernel and `hidden` biases of the first convolution, then the
+    /// `hidden x hidden x 3` kernel and `hidden` biases of the second.
+    pub fn new(n_mels: usize, hidden: usize, weights: &[f32]) -> Result<Self> {
+        if n_mels == 0 || hidden == 0 {
+            bail!("a speech front-end needs mel bands and hidden dimensions");
+        }
+        let needed = Self::weight_count(n_mels, hidden);
+        if weights.len() < needed {
+            bail!(
+                "speech front-end needs {needed} weights, only {} are stored",
+                weights.len()
+            );
+        }
+        let (conv1, rest) = weights.split_at(hidden * n_mels * FRONT_END_KERNEL);
+        let (bias1, rest) = rest.split_at(hidden);
+        let (conv2, rest) = rest.split_at(hidden * hidden * FRONT_END_KERNEL);
+        Ok(Self {
+            n_mels,
+            hidden,
+            conv1: conv1.to_vec(),
+            bias1: bias1.to_vec(),
+            conv2: conv2.to_vec(),
+            bias2: rest[..hidden].to_vec(),
+            backend: Arc::new(CpuSimdBackend),
+        })
+    }
+
+    /// Run the convolutions on `backend` instead of the CPU SIMD backend.
+    pub fn with_backend(mut self, backend: Arc<dyn TensorOps + Send + Sync>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    /// Encoder positions produced for `frames` spectrogram frames.
+    pub fn positions(frames: usize) -> usize {
+        frames.div_ceil(2)
+    }
+
+    /// Embed the `n_mels x frames` spectrogram `mel` as
+    /// `positions x hidden` values.
+    pub fn forward(&self, mel: &[f32]) -> Vec<f32> {
+        let hidden = self.hidden;
+        let shape = |in_channels, stride| Conv1dShape {
+            in_channels,
+            out_channels: hidden,
+            kernel: FRONT_END_KERNEL,
+            stride,
+            padding: 1,
+        };
+        let x = self
+            .backend
+            .conv1d(mel, &self.conv1, &self.bias1, shape(self.n_mels, 1));
+        let x = self.backend.gelu(&x);
+        let x = self
+            .backend
+            .conv1d(&x, &self.conv2, &self.bias2, shape(hidden, 2));
+        let x = self.backend.gelu(&x);
+
+        let positions = x.len() / hidden;
+        let mut out = sinusoids(positions, hidden);
+        for (h, channel) in x.chunks_exact(positions.max(1)).enumerate() {
+            for (t, v) in channel.iter().enumerate() {
+                out[t * hidden + h] += v;
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Debug for SpeechFrontEnd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpeechFrontEnd")
+            .field("n_mels", &self.n_mels)
+            .field("hidden", &self.hidden)
+            .finish()
+    }
+}
+
+/// Whisper's sinusoidal position embedding, `positions x channels`: sines
+/// of geometrically spaced timescales up to 10000 followed by their cosines.
+pub fn sinusoids(positions: usize, channels: usize) -> Vec<f32> {
+    let half = channels / 2;
+    let increment = 10_000f32.ln() / (half.max(2) - 1) as f32;
+    let mut out = vec![0.0; positions * channels];
+    for (t, row) in out.chunks_exact_mut(channels.max(1)).enumerate() {
+        for i in 0..half {
+            let angle = t as f32 * (-increment * i as f32).exp();
+            row[i] = angle.sin();
+            row[half + i] = angle.cos();
+        }
+    }
+    out
+}
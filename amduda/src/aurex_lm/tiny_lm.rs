@@ -0,0 +1,320 @@
+//! Minimal decoder-only language model.
+//!
+//! [`TinyLm`] interprets the loaded weights as a `vocab_size x hidden_size`
+//! embedding table tied with the output projection.  Each step embeds the
+//! input token, attends over the embeddings of all previous tokens (kept as a
+//! key/value cache), normalises the residual sum and projects back onto the
+//! vocabulary.  Layer norm and the output projection run through a
+//! [`TensorOps`] implementation, typically the backend [`Dispatcher`], so the
+//! selected backend and precision apply.  [`LanguageModel::forward_batch`]
+//! projects all positions of a batch with one matmul.
+//!
+//! The key/value cache is split into pages of [`KV_PAGE_TOKENS`] tokens.
+//! Cloning a model shares its weights and cache pages; a clone only copies a
+//! page when it writes to it, so forked generation branches are cheap.
+//!
+//! Embeddings of non-text inputs, such as the image patches of a
+//! [`PatchEmbedding`](super::vision::PatchEmbedding), can be placed in front
+//! of the context with [`TinyLm::set_prefix_embeddings`].  The prefix stays
+//! cached when the context is reset or truncated, and context positions
+//! count the tokens after it.
+//!
+//! The cache can be exported and imported through [`KvState`], which
+//! [`KvCache`](super::generation::KvCache) snapshots use to persist it.
+//!
+//! [`Dispatcher`]: aurex_backend::Dispatcher
+
+use anyhow::{bail, Result};
+use aurex_backend::TensorOps;
+use std::sync::Arc;
+
+use super::kv_snapshot::{fnv1a, KvState, FNV_OFFSET};
+use super::model_loader::LoadedModel;
+
+/// Autoregressive model producing next-token logits one token at a time.
+pub trait LanguageModel {
+    /// Number of entries in the logits vector.
+    fn vocab_size(&self) -> usize;
+    /// Feed `token` and return the logits for the following token.
+    fn forward(&mut self, token: u32) -> Vec<f32>;
+    /// Feed `tokens` in order and return the logits following each of
+    /// them.  Models that can run the tokens as one batch override this.
+    fn forward_batch(&mut self, tokens: &[u32]) -> Vec<Vec<f32>> {
+        tokens.iter().map(|&token| self.forward(token)).collect()
+    }
+    /// Forget all cached context.
+    fn reset(&mut self);
+    /// Keep only the first `len` tokens of the cached context.
+    fn truncate(&mut self, len: usize);
+    /// Number of tokens currently in the context.
+    fn context_len(&self) -> usize;
+}
+
+/// Tokens per key/value cache page of [`TinyLm`].
+pub const KV_PAGE_TOKENS: usize = 16;
+
+/// Tied-embedding model with a single attention layer.
+#[derive(Clone)]
+pub struct TinyLm {
+    vocab: usize,
+    hidden: usize,
+    embedding: Arc<[f32]>,
+    /// Transposed embedding (`hidden x vocab`) used as output projection.
+    unembedding: Arc<[f32]>,
+    gamma: Vec<f32>,
+    beta: Vec<f32>,
+    /// Copy-on-write pages of cached keys.
+    pages: Vec<Arc<Vec<f32>>>,
+    /// Cached positions, including the prefix.
+    len: usize,
+    /// Leading positions holding prefix embeddings.
+    prefix: usize,
+    ops: Arc<dyn TensorOps + Send + Sync>,
+}
+
+impl TinyLm {
+    /// Build a model from raw `f32` weights.
+    pub fn new(
+        weights: &[f32],
+        vocab: usize,
+        hidden: usize,
+        ops: Arc<dyn TensorOps + Send + Sync>,
+    ) -> Result<Self> {
+        if vocab == 0 || hidden == 0 {
+            bail!("vocab and hidden size must be non-zero");
+        }
+        let needed = vocab * hidden;
+        if weights.len() < needed {
+            bail!(
+                "model needs {needed} weights for vocab {vocab} x hidden {hidden}, found {}",
+                weights.len()
+            );
+        }
+        let embedding = &weights[..needed];
+        let mut unembedding = vec![0.0; needed];
+        for v in 0..vocab {
+            for h in 0..hidden {
+                unembedding[h * vocab + v] = embedding[v * hidden + h];
+            }
+        }
+        Ok(Self {
+            vocab,
+            hidden,
+            embedding: embedding.into(),
+            unembedding: unembedding.into(),
+            gamma: vec![1.0; hidden],
+            beta: vec![0.0; hidden],
+            pages: Vec::new(),
+            len: 0,
+            prefix: 0,
+            ops,
+        })
+    }
+
+    /// Build a model from a loaded checkpoint.  The vocabulary defaults to
+    /// the byte tokenizer and the hidden size is derived from the weight
+    /// count when not configured.
+    pub fn from_model(model: &LoadedModel, ops: Arc<dyn TensorOps + Send + Sync>) -> Result<Self> {
+        let weights = model.weights_f32()?;
+        let (vocab, hidden) = model.config.embedding_shape(weights.len());
+        Self::new(&weights, vocab, hidden, ops)
+    }
+
+    /// Hidden dimension of the model.
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    /// Approximate floating point operations of the next [`forward`] call.
+    ///
+    /// [`forward`]: LanguageModel::forward
+    pub fn flops_per_token(&self) -> u64 {
+        let ctx = (self.len + 1) as u64;
+        let (v, h) = (self.vocab as u64, self.hidden as u64);
+        4 * ctx * h + 8 * h + 2 * v * h
+    }
+
+    /// Number of key/value cache pages shared with `other`, e.g. a clone
+    /// made before either model extended its context.
+    pub fn shared_kv_pages(&self, other: &TinyLm) -> usize {
+        self.pages
+            .iter()
+            .zip(&other.pages)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count()
+    }
+
+    /// Replace the context with `embeddings`, `positions x hidden` values,
+    /// kept in front of every following token.
+    pub fn set_prefix_embeddings(&mut self, embeddings: &[f32]) -> Result<()> {
+        if !embeddings.len().is_multiple_of(self.hidden) {
+            bail!(
+                "{} values are not a sequence of {}-dimensional embeddings",
+                embeddings.len(),
+                self.hidden
+            );
+        }
+        self.pages.clear();
+        self.len = 0;
+        for embedding in embeddings.chunks_exact(self.hidden) {
+            self.cache(embedding);
+        }
+        self.prefix = self.len;
+        Ok(())
+    }
+
+    /// Number of cached prefix embeddings.
+    pub fn prefix_len(&self) -> usize {
+        self.prefix
+    }
+
+    /// Append `x` to the key/value cache.
+    fn cache(&mut self, x: &[f32]) {
+        if self.len.is_multiple_of(KV_PAGE_TOKENS) {
+            self.pages
+                .push(Arc::new(Vec::with_capacity(KV_PAGE_TOKENS * self.hidden)));
+        }
+        let page = self.pages.last_mut().expect("page for the new token");
+        Arc::make_mut(page).extend_from_slice(x);
+        self.len += 1;
+    }
+
+    /// Keep the first `len` cached positions, prefix included.
+    fn truncate_positions(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.pages.truncate(len.div_ceil(KV_PAGE_TOKENS));
+        let tail = len % KV_PAGE_TOKENS;
+        if tail > 0 {
+            let page = self.pages.last_mut().expect("page holding the tail");
+            Arc::make_mut(page).truncate(tail * self.hidden);
+        }
+        self.len = len;
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &[f32]> {
+        self.pages.iter().flat_map(|page| page.chunks(self.hidden))
+    }
+
+    /// Cache `token` and return the residual stream attending over the
+    /// cache.
+    fn attend(&mut self, token: u32) -> Vec<f32> {
+        let x = self.embed(token).to_vec();
+        let d = self.hidden;
+        self.cache(&x);
+
+        let scale = 1.0 / (d as f32).sqrt();
+        let scores: Vec<f32> = self
+            .keys()
+            .map(|k| k.iter().zip(&x).map(|(a, b)| a * b).sum::<f32>() * scale)
+            .collect();
+        let max = scores.iter().fold(f32::NEG_INFINITY, |m, &s| m.max(s));
+        let weights: Vec<f32> = scores.iter().map(|s| (s - max).exp()).collect();
+        let total: f32 = weights.iter().sum();
+
+        let mut residual = x;
+        for (k, w) in self.keys().zip(&weights) {
+            for (r, kv) in residual.iter_mut().zip(k) {
+                *r += kv * w / total;
+            }
+        }
+        residual
+    }
+
+    /// Logits of `residuals`, one row of `hidden` values per position,
+    /// projected in a single matmul.
+    fn project(&self, residuals: &[f32]) -> Vec<f32> {
+        let rows = residuals.len() / self.hidden;
+        self.ops.set_layer(Some("final_norm"));
+        let h: Vec<f32> = residuals
+            .chunks_exact(self.hidden)
+            .flat_map(|r| self.ops.layer_norm(r, &self.gamma, &self.beta, 1e-5))
+            .collect();
+        self.ops.set_layer(Some("lm_head"));
+        let logits = self.ops.matmul(&h, &self.unembedding, rows, self.vocab, self.hidden);
+        self.ops.set_layer(None);
+        logits
+    }
+
+    fn embed(&self, token: u32) -> &[f32] {
+        let t = (token as usize).min(self.vocab - 1);
+        &self.embedding[t * self.hidden..(t + 1) * self.hidden]
+    }
+}
+
+impl LanguageModel for TinyLm {
+    fn vocab_size(&self) -> usize {
+        self.vocab
+    }
+
+    fn forward(&mut self, token: u32) -> Vec<f32> {
+        let residual = self.attend(token);
+        self.project(&residual)
+    }
+
+    /// Attends over the cache per token, then normalises and projects all
+    /// positions together.
+    fn forward_batch(&mut self, tokens: &[u32]) -> Vec<Vec<f32>> {
+        let residuals: Vec<f32> = tokens.iter().flat_map(|&t| self.attend(t)).collect();
+        self.project(&residuals)
+            .chunks_exact(self.vocab)
+            .map(<[f32]>::to_vec)
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        self.truncate_positions(self.prefix);
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.truncate_positions(self.prefix + len);
+    }
+
+    fn context_len(&self) -> usize {
+        self.len - self.prefix
+    }
+}
+
+impl KvState for TinyLm {
+    fn model_hash(&self) -> u64 {
+        let mut hash = fnv1a(FNV_OFFSET, &(self.vocab as u64).to_le_bytes());
+        hash = fnv1a(hash, &(self.hidden as u64).to_le_bytes());
+        for v in self.embedding.iter().chain(&self.gamma).chain(&self.beta) {
+            hash = fnv1a(hash, &v.to_le_bytes());
+        }
+        hash
+    }
+
+    fn kv_width(&self) -> usize {
+        self.hidden
+    }
+
+    fn export_kv(&self) -> (Vec<f32>, usize) {
+        (
+            self.pages
+                .iter()
+                .flat_map(|page| page.iter().copied())
+                .collect(),
+            self.prefix,
+        )
+    }
+
+    fn import_kv(&mut self, values: &[f32], prefix: usize) -> Result<()> {
+        let positions = values.len() / self.hidden;
+        if !values.len().is_multiple_of(self.hidden) || prefix > positions {
+            bail!(
+                "{} values with a prefix of {prefix} are not a cache of {}-dimensional positions",
+                values.len(),
+                self.hidden
+            );
+        }
+        self.pages.clear();
+        self.len = 0;
+        for position in values.chunks_exact(self.hidden) {
+            self.cache(position);
+        }
+        self.prefix = prefix;
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+//! Byte level tokenizer.
+//!
+//! Every UTF-8 byte maps to the token with the same id and a single
+//! end-of-sequence token follows the byte range.  This keeps small models
+//! usable without shipping a vocabulary file.
+
+/// Number of byte tokens.
+pub const BYTE_TOKENS: u32 = 256;
+
+/// Id of the end-of-sequence token.
+pub const EOS_TOKEN: u32 = BYTE_TOKENS;
+
+/// Vocabulary size of the byte tokenizer including EOS.
+pub const BYTE_VOCAB_SIZE: usize = BYTE_TOKENS as usize + 1;
+
+/// Byte of every byte token, indexed by token id.
+static BYTES: [u8; BYTE_TOKENS as usize] = {
+    let mut bytes = [0; BYTE_TOKENS as usize];
+    let mut i = 0;
+    while i < bytes.len() {
+        bytes[i] = i as u8;
+        i += 1;
+    }
+    bytes
+};
+
+/// Encodes text as UTF-8 bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByteTokenizer;
+
+impl ByteTokenizer {
+    /// Encode `text` into token ids.
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        text.bytes().map(u32::from).collect()
+    }
+
+    /// Decode token ids, skipping special tokens and replacing invalid UTF-8.
+    pub fn decode(&self, tokens: &[u32]) -> String {
+        let bytes: Vec<u8> = tokens
+            .iter()
+            .filter(|&&t| t < BYTE_TOKENS)
+            .map(|&t| t as u8)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Bytes of the text `token` stands for, empty for special tokens.
+    pub fn token_bytes(&self, token: u32) -> &'static [u8] {
+        match BYTES.get(token as usize) {
+            Some(byte) => std::slice::from_ref(byte),
+            None => &[],
+        }
+    }
+
+    /// Vocabulary size including special tokens.
+    pub fn vocab_size(&self) -> usize {
+        BYTE_VOCAB_SIZE
+    }
+}
+
+/// Incremental decoder for streaming output.  Bytes of multi-byte characters
+/// are buffered until the character is complete.
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    pending: Vec<u8>,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one token and return the text that became printable.
+    pub fn push(&mut self, token: u32) -> String {
+        self.push_bytes(ByteTokenizer.token_bytes(token))
+    }
+
+    /// Feed the bytes of part of a token and return the text that became
+    /// printable.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> String {
+        if bytes.is_empty() {
+            return String::new();
+        }
+        self.pending.extend_from_slice(bytes);
+        match std::str::from_utf8(&self.pending) {
+            Ok(text) => {
+                let text = text.to_string();
+                self.pending.clear();
+                text
+            }
+            // Incomplete character: wait for more bytes.
+            Err(e) if e.error_len().is_none() => String::new(),
+            Err(_) => {
+                let text = String::from_utf8_lossy(&self.pending).into_owned();
+                self.pending.clear();
+                text
+            }
+        }
+    }
+
+    /// Flush any buffered bytes, replacing incomplete characters.
+    pub fn finish(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        text
+    }
+}
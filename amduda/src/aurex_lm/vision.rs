@@ -0,0 +1,351 @@
+//! Image inputs for multimodal models.
+//!
+//! Images reach a model through the preprocessing of ViT-style encoders:
+//!
+//! 1. [`Image::resize`] scales the RGB image to the square input size of the
+//!    encoder with bilinear filtering;
+//! 2. [`Image::normalize`] standardizes every channel with the mean and
+//!    standard deviation the encoder was trained with;
+//! 3. [`Image::patchify`] cuts the image into `patch x patch` squares and
+//!    flattens each channel by channel, row by row.
+//!
+//! [`PatchEmbedding`] then projects every patch onto the hidden size of the
+//! language model and adds a learned position embedding.  The projection is
+//! the strided `patch x patch` convolution of ViT: because the stride equals
+//! the kernel size, it runs as a single matmul of the flattened patches with
+//! the flattened kernel through [`TensorOps`], so the selected backend and
+//! precision apply.  The resulting `patches x hidden` embeddings are placed
+//! in front of the prompt with
+//! [`TinyLm::set_prefix_embeddings`](super::tiny_lm::TinyLm::set_prefix_embeddings).
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use aurex_backend::TensorOps;
+use serde::{Deserialize, Serialize};
+
+/// Per channel mean of the ImageNet training images.
+pub const IMAGENET_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+
+/// Per channel standard deviation of the ImageNet training images.
+pub const IMAGENET_STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+fn default_image_size() -> usize {
+    224
+}
+
+fn default_patch_size() -> usize {
+    16
+}
+
+fn default_mean() -> [f32; 3] {
+    IMAGENET_MEAN
+}
+
+fn default_std() -> [f32; 3] {
+    IMAGENET_STD
+}
+
+/// Preprocessing and shape of a ViT-style patch embedding.
+///
+/// A model with `hidden` dimensions stores the `hidden x 3 x patch_size x
+/// patch_size` convolution kernel, its `hidden` biases and the
+/// `patches x hidden` position embedding.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VisionConfig {
+    /// Edge length images are resized to.
+    #[serde(default = "default_image_size")]
+    pub image_size: usize,
+    #[serde(default = "default_patch_size")]
+    pub patch_size: usize,
+    #[serde(default = "default_mean")]
+    pub mean: [f32; 3],
+    #[serde(default = "default_std")]
+    pub std: [f32; 3],
+}
+
+impl Default for VisionConfig {
+    fn default() -> Self {
+        Self {
+            image_size: default_image_size(),
+            patch_size: default_patch_size(),
+            mean: IMAGENET_MEAN,
+            std: IMAGENET_STD,
+        }
+    }
+}
+
+impl VisionConfig {
+    /// Patches per image.
+    pub fn patches(&self) -> usize {
+        let side = self.image_size / self.patch_size.max(1);
+        side * side
+    }
+
+    /// Values per flattened RGB patch.
+    pub fn patch_len(&self) -> usize {
+        3 * self.patch_size * self.patch_size
+    }
+
+    /// Weights of the patch embedding of a model with `hidden` dimensions.
+    pub fn weight_count(&self, hidden: usize) -> usize {
+        (self.patch_len() + 1 + self.patches()) * hidden
+    }
+}
+
+/// Decoded image with `channels` interleaved values in `[0, 1]` per pixel,
+/// row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub data: Vec<f32>,
+}
+
+impl Image {
+    /// Image of 8-bit `pixels` with `channels` values each.
+    pub fn from_u8(width: usize, height: usize, channels: usize, pixels: &[u8]) -> Result<Self> {
+        if channels == 0 || pixels.len() != width * height * channels {
+            bail!(
+                "{} bytes are not a {width}x{height} image with {channels} channels",
+                pixels.len()
+            );
+        }
+        Ok(Self {
+            width,
+            height,
+            channels,
+            data: pixels.iter().map(|&p| f32::from(p) / 255.0).collect(),
+        })
+    }
+
+    /// Decode the PNG file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+        Self::decode_png(BufReader::new(file))
+            .with_context(|| format!("cannot decode {}", path.display()))
+    }
+
+    /// Decode a PNG image.  Palette images are expanded and 16-bit samples
+    /// reduced to 8 bits.
+    pub fn decode_png(reader: impl std::io::BufRead + std::io::Seek) -> Result<Self> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let size = reader
+            .output_buffer_size()
+            .context("image too large to decode")?;
+        let mut pixels = vec![0; size];
+        let info = reader.next_frame(&mut pixels)?;
+        pixels.truncate(info.line_size * info.height as usize);
+        let channels = info.color_type.samples();
+        Self::from_u8(info.width as usize, info.height as usize, channels, &pixels)
+    }
+
+    /// The image as RGB: gray values are replicated and alpha is dropped.
+    pub fn to_rgb(&self) -> Image {
+        let data = match self.channels {
+            3 => self.data.clone(),
+            1 | 2 => self
+                .data
+                .chunks_exact(self.channels)
+                .flat_map(|p| [p[0]; 3])
+                .collect(),
+            _ => self
+                .data
+                .chunks_exact(self.channels)
+                .flat_map(|p| [p[0], p[1], p[2]])
+                .collect(),
+        };
+        Image {
+            width: self.width,
+            height: self.height,
+            channels: 3,
+            data,
+        }
+    }
+
+    /// Bilinear resize to `width x height`, sampling at pixel centres.
+    pub fn resize(&self, width: usize, height: usize) -> Image {
+        let c = self.channels;
+        let mut data = vec![0.0; width * height * c];
+        if [self.width, self.height, width, height].contains(&0) {
+            return Image {
+                width,
+                height,
+                channels: c,
+                data,
+            };
+        }
+        // Source coordinate and weight of the next pixel along one axis.
+        let taps = |src: usize, dst: usize, i: usize| {
+            let pos = ((i as f32 + 0.5) * src as f32 / dst as f32 - 0.5).max(0.0);
+            let lo = (pos as usize).min(src - 1);
+            let hi = (lo + 1).min(src - 1);
+            (lo, hi, pos - lo as f32)
+        };
+        for (y, row) in data.chunks_exact_mut(width * c).enumerate() {
+            let (y0, y1, fy) = taps(self.height, height, y);
+            for (x, pixel) in row.chunks_exact_mut(c).enumerate() {
+                let (x0, x1, fx) = taps(self.width, width, x);
+                let at =
+                    |yy: usize, xx: usize, ch: usize| self.data[(yy * self.width + xx) * c + ch];
+                for (ch, out) in pixel.iter_mut().enumerate() {
+                    let top = at(y0, x0, ch) * (1.0 - fx) + at(y0, x1, ch) * fx;
+                    let bottom = at(y1, x0, ch) * (1.0 - fx) + at(y1, x1, ch) * fx;
+                    *out = top * (1.0 - fy) + bottom * fy;
+                }
+            }
+        }
+        Image {
+            width,
+            height,
+            channels: c,
+            data,
+        }
+    }
+
+    /// Subtract `mean` from and divide by `std` every value of each channel.
+    pub fn normalize(&mut self, mean: &[f32], std: &[f32]) {
+        for pixel in self.data.chunks_exact_mut(self.channels) {
+            for ((v, m), s) in pixel.iter_mut().zip(mean).zip(std) {
+                *v = (*v - m) / s;
+            }
+        }
+    }
+
+    /// Cut the image into `patch x patch` squares, row by row, and flatten
+    /// each channel by channel, then row by row.  Edge pixels that do not
+    /// fill a whole patch are dropped.
+    pub fn patchify(&self, patch: usize) -> Vec<f32> {
+        let c = self.channels;
+        let (rows, cols) = (self.height / patch.max(1), self.width / patch.max(1));
+        let mut out = Vec::with_capacity(rows * cols * c * patch * patch);
+        for py in 0..rows {
+            for px in 0..cols {
+                for ch in 0..c {
+                    for y in py * patch..(py + 1) * patch {
+                        let row = y * self.width;
+                        out.extend(
+                            (px * patch..(px + 1) * patch).map(|x| self.data[(row + x) * c + ch]),
+                        );
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// ViT-style patch embedding, see the [module documentation](self).
+#[derive(Clone)]
+pub struct PatchEmbedding {
+    config: VisionConfig,
+    hidden: usize,
+    /// Flattened convolution kernel, transposed to `patch_len x hidden`.
+    projection: Vec<f32>,
+    bias: Vec<f32>,
+    /// `patches x hidden`.
+    position: Vec<f32>,
+    ops: Arc<dyn TensorOps + Send + Sync>,
+}
+
+impl PatchEmbedding {
+    /// Build the embedding from its stored `weights`, see [`VisionConfig`].
+    pub fn new(
+        config: VisionConfig,
+        hidden: usize,
+        weights: &[f32],
+        ops: Arc<dyn TensorOps + Send + Sync>,
+    ) -> Result<Self> {
+        if hidden == 0 || config.patches() == 0 {
+            bail!(
+                "cannot embed {0}x{0} images in {1}x{1} patches of {hidden} values",
+                config.image_size,
+                config.patch_size
+            );
+        }
+        let needed = config.weight_count(hidden);
+        if weights.len() < needed {
+            bail!(
+                "patch embedding needs {needed} weights, only {} are stored",
+                weights.len()
+            );
+        }
+        let patch_len = config.patch_len();
+        let (kernel, rest) = weights.split_at(hidden * patch_len);
+        let (bias, rest) = rest.split_at(hidden);
+        let mut projection = vec![0.0; kernel.len()];
+        for (h, filter) in kernel.chunks_exact(patch_len).enumerate() {
+            for (p, &w) in filter.iter().enumerate() {
+                projection[p * hidden + h] = w;
+            }
+        }
+        Ok(Self {
+            config,
+            hidden,
+            projection,
+            bias: bias.to_vec(),
+            position: rest[..config.patches() * hidden].to_vec(),
+            ops,
+        })
+    }
+
+    pub fn config(&self) -> &VisionConfig {
+        &self.config
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    /// Resize, normalize and patchify `image` as configured.
+    pub fn preprocess(&self, image: &Image) -> Vec<f32> {
+        let size = self.config.image_size;
+        let mut image = image.to_rgb().resize(size, size);
+        image.normalize(&self.config.mean, &self.config.std);
+        image.patchify(self.config.patch_size)
+    }
+
+    /// Embed `image` as `patches x hidden` values.
+    pub fn forward(&self, image: &Image) -> Vec<f32> {
+        let patches = self.preprocess(image);
+        let n = self.config.patches();
+        let mut out = self.ops.matmul(
+            &patches,
+            &self.projection,
+            n,
+            self.hidden,
+            self.config.patch_len(),
+        );
+        for (row, position) in out
+            .chunks_exact_mut(self.hidden)
+            .zip(self.position.chunks_exact(self.hidden))
+        {
+            for ((v, b), p) in row.iter_mut().zip(&self.bias).zip(position) {
+                *v += b + p;
+            }
+        }
+        tracing::debug!(
+            width = image.width,
+            height = image.height,
+            patches = n,
+            "embedded image"
+        );
+        out
+    }
+}
+
+impl std::fmt::Debug for PatchEmbedding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PatchEmbedding")
+            .field("config", &self.config)
+            .field("hidden", &self.hidden)
+            .finish()
+    }
+}
@@ -0,0 +1,243 @@
+//! Portable runtime for small models, including in browsers.
+//!
+//! [`WebRuntime`] runs the tied-embedding model of
+//! [`TinyLm`](super::tiny_lm::TinyLm) using only portable dependencies, so it
+//! builds for `wasm32-unknown-unknown` as well as natively. There is no
+//! memory tiering, no backend dispatcher and no threads: the weights are
+//! decoded from bytes the caller already holds, e.g. a `fetch` response, and
+//! every op runs through an amduda [`TensorOps`] implementation, by default
+//! the [`CpuSimdBackend`].
+//!
+//! The configuration is read from the JSON of a native
+//! [`ModelConfig`](super::model_loader::ModelConfig); keys the runtime does
+//! not use, such as `weight_path`, are ignored.
+//!
+//! [`WebRuntime::forward`] splits into [`WebRuntime::hidden_state`] and the
+//! projection onto the vocabulary, so callers with an asynchronous backend,
+//! like WebGPU in a browser, can run the projection themselves.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::quantizer::{dequantize_bf16, dequantize_int4, dequantize_int8, Quantization};
+use super::sampler::{Sampler, SamplingParams};
+use super::tokenizer::{ByteTokenizer, BYTE_VOCAB_SIZE, EOS_TOKEN};
+use crate::amduda_core::tensor_ops::TensorOps;
+use crate::hal_backends::cpu_simd::CpuSimdBackend;
+
+/// Subset of the model configuration the [`WebRuntime`] reads.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WebModelConfig {
+    #[serde(default)]
+    pub quantization: Option<Quantization>,
+    /// Scale factor of INT4/INT8 weights.
+    #[serde(default)]
+    pub scale: Option<f32>,
+    /// Vocabulary size; defaults to the byte tokenizer vocabulary.
+    #[serde(default)]
+    pub vocab_size: Option<usize>,
+    /// Hidden dimension; derived from the weight count when absent.
+    #[serde(default)]
+    pub hidden_size: Option<usize>,
+    /// Stored weight order; only row-major tables can be loaded.
+    #[serde(default)]
+    pub layout: Option<serde_json::Value>,
+}
+
+/// Tied-embedding model with a single attention layer, see the
+/// [module documentation](self).
+#[derive(Clone)]
+pub struct WebRuntime {
+    vocab: usize,
+    hidden: usize,
+    embedding: Vec<f32>,
+    /// Transposed embedding (`hidden x vocab`) used as output projection.
+    unembedding: Vec<f32>,
+    gamma: Vec<f32>,
+    beta: Vec<f32>,
+    /// Embeddings of the cached context, `context_len x hidden`.
+    keys: Vec<f32>,
+    backend: Arc<dyn TensorOps + Send + Sync>,
+}
+
+impl WebRuntime {
+    /// Build a runtime from the `vocab x hidden` embedding table at the
+    /// start of `weights`.
+    pub fn new(weights: &[f32], vocab: usize, hidden: usize) -> Result<Self> {
+        if vocab == 0 || hidden == 0 {
+            bail!("vocab and hidden size must be non-zero");
+        }
+        let needed = vocab * hidden;
+        if weights.len() < needed {
+            bail!(
+                "model needs {needed} weights for vocab {vocab} x hidden {hidden}, found {}",
+                weights.len()
+            );
+        }
+        let embedding = weights[..needed].to_vec();
+        let mut unembedding = vec![0.0; needed];
+        for (v, row) in embedding.chunks_exact(hidden).enumerate() {
+            for (h, &w) in row.iter().enumerate() {
+                unembedding[h * vocab + v] = w;
+            }
+        }
+        Ok(Self {
+            vocab,
+            hidden,
+            embedding,
+            unembedding,
+            gamma: vec![1.0; hidden],
+            beta: vec![0.0; hidden],
+            keys: Vec::new(),
+            backend: Arc::new(CpuSimdBackend),
+        })
+    }
+
+    /// Build a runtime from stored weight `bytes` described by `config`.
+    pub fn from_bytes(config: &WebModelConfig, bytes: &[u8]) -> Result<Self> {
+        if let Some(layout) = config
+            .layout
+            .as_ref()
+            .filter(|l| l.as_str() != Some("row-major"))
+        {
+            bail!("weights are stored in {layout} order; export a row-major table");
+        }
+        let weights = decode_weights(bytes, config.quantization, config.scale)?;
+        let vocab = config.vocab_size.unwrap_or(BYTE_VOCAB_SIZE);
+        let hidden = config.hidden_size.unwrap_or(weights.len() / vocab.max(1));
+        Self::new(&weights, vocab, hidden)
+    }
+
+    /// Build a runtime from the JSON model configuration `config` and the
+    /// stored weight `bytes`.
+    pub fn from_json(config: &str, bytes: &[u8]) -> Result<Self> {
+        let config: WebModelConfig =
+            serde_json::from_str(config).context("invalid model configuration")?;
+        Self::from_bytes(&config, bytes)
+    }
+
+    /// Run the ops on `backend` instead of the CPU SIMD backend.
+    pub fn with_backend(mut self, backend: Arc<dyn TensorOps + Send + Sync>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.vocab
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    /// Output projection, `hidden x vocab`.
+    pub fn unembedding(&self) -> &[f32] {
+        &self.unembedding
+    }
+
+    /// Number of tokens in the context.
+    pub fn context_len(&self) -> usize {
+        self.keys.len() / self.hidden
+    }
+
+    /// Forget the context.
+    pub fn reset(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Feed `token` and return the normalised hidden state the logits of the
+    /// following token are projected from.
+    pub fn hidden_state(&mut self, token: u32) -> Vec<f32> {
+        let d = self.hidden;
+        let t = (token as usize).min(self.vocab - 1);
+        let x = self.embedding[t * d..(t + 1) * d].to_vec();
+        self.keys.extend_from_slice(&x);
+
+        let scale = 1.0 / (d as f32).sqrt();
+        let scores: Vec<f32> = self
+            .keys
+            .chunks_exact(d)
+            .map(|k| k.iter().zip(&x).map(|(a, b)| a * b).sum::<f32>() * scale)
+            .collect();
+        let max = scores.iter().fold(f32::NEG_INFINITY, |m, &s| m.max(s));
+        let weights: Vec<f32> = scores.iter().map(|s| (s - max).exp()).collect();
+        let total: f32 = weights.iter().sum();
+
+        let mut residual = x;
+        for (k, w) in self.keys.chunks_exact(d).zip(&weights) {
+            for (r, kv) in residual.iter_mut().zip(k) {
+                *r += kv * w / total;
+            }
+        }
+        self.backend
+            .layer_norm(&residual, &self.gamma, &self.beta, 1e-5)
+    }
+
+    /// Feed `token` and return the logits for the following token.
+    pub fn forward(&mut self, token: u32) -> Vec<f32> {
+        let h = self.hidden_state(token);
+        self.backend
+            .matmul(&h, &self.unembedding, 1, self.vocab, self.hidden)
+    }
+
+    /// Continue `prompt` by up to `max_tokens` tokens, stopping at the end of
+    /// sequence token, and return the generated text.
+    pub fn generate(&mut self, prompt: &str, max_tokens: usize, params: SamplingParams) -> String {
+        let tokenizer = ByteTokenizer;
+        let mut sampler = Sampler::new(params);
+        let mut logits = Vec::new();
+        for token in tokenizer.encode(prompt) {
+            logits = self.forward(token);
+        }
+        let mut tokens = Vec::new();
+        while tokens.len() < max_tokens && !logits.is_empty() {
+            let token = sampler.sample(&logits);
+            if token == EOS_TOKEN {
+                break;
+            }
+            tokens.push(token);
+            logits = self.forward(token);
+        }
+        tokenizer.decode(&tokens)
+    }
+}
+
+impl std::fmt::Debug for WebRuntime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebRuntime")
+            .field("vocab", &self.vocab)
+            .field("hidden", &self.hidden)
+            .field("context_len", &self.context_len())
+            .finish()
+    }
+}
+
+/// Decode stored weights: little-endian `f32` values, or `quantization`
+/// values with their `scale`.
+pub fn decode_weights(
+    bytes: &[u8],
+    quantization: Option<Quantization>,
+    scale: Option<f32>,
+) -> Result<Vec<f32>> {
+    let scale = || scale.with_context(|| format!("missing scale for {quantization:?} weights"));
+    Ok(match quantization {
+        None => bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        Some(Quantization::Int8) => {
+            let values: Vec<i8> = bytes.iter().map(|&b| b as i8).collect();
+            dequantize_int8(&values, scale()?)
+        }
+        Some(Quantization::Int4) => dequantize_int4(bytes, scale()?, bytes.len() * 2),
+        Some(Quantization::Bf16) => {
+            let values: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            dequantize_bf16(&values)
+        }
+    })
+}
@@ -1,20 +1,48 @@
 //! CPU backend implementing [`TensorOps`] with x86 AVX intrinsics.
+//!
+//! On other architectures, `wasm32` included, every op runs the portable
+//! [`CpuFallback`] kernels.
+//!
+//! [`CpuSimdBackend::matmul_int4`] multiplies by int4 weights packed with
+//! [`quantize_int4`] directly, unpacking nibbles in AVX2 registers instead of
+//! dequantizing the weights into an `f32` copy first.
+//!
+//! 1D convolutions unfold their input with [`im2col`] and run as one SIMD
+//! matmul.
+//!
+//! The `_blocked` variants read weights repacked into
+//! [`WeightLayout::Blocked`] panels of [`SIMD_WIDTH`] columns, so every
+//! vector load reads the next consecutive bytes of the weights.
+//!
+//! [`quantize_int4`]: crate::aurex_lm::quantizer::quantize_int4
+//! [`WeightLayout::Blocked`]: crate::aurex_lm::layout::WeightLayout::Blocked
 
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
-use crate::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use crate::amduda_core::tensor_ops::{im2col, Conv1dShape, CpuFallback, TensorOps};
+use crate::hal_backends::device_buffer::{BufferAllocator, BufferError, DeviceBuffer};
+
+/// `f32` lanes of an AVX register.
+pub const SIMD_WIDTH: usize = 8;
 
 /// Represents the host CPU using SIMD operations when available.
 pub struct CpuSimdBackend;
 
+impl BufferAllocator for CpuSimdBackend {
+    fn alloc_buffer(&self, len: usize) -> Result<DeviceBuffer, BufferError> {
+        Ok(DeviceBuffer::host(len))
+    }
+}
+
 impl TensorOps for CpuSimdBackend {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        #[cfg(target_arch = "x86_64")]
         if is_x86_feature_detected!("avx") {
-            unsafe { matmul_avx(a, b, m, n, k) }
-        } else {
-            let cpu = CpuFallback;
-            cpu.matmul(a, b, m, n, k)
+            return unsafe { matmul_avx(a, b, m, n, k) };
         }
+        let cpu = CpuFallback;
+        cpu.matmul(a, b, m, n, k)
     }
 
     fn conv2d(
@@ -24,30 +52,126 @@ impl TensorOps for CpuSimdBackend {
         input_shape: (usize, usize),
         kernel_shape: (usize, usize),
     ) -> Vec<f32> {
+        #[cfg(target_arch = "x86_64")]
         if is_x86_feature_detected!("avx") {
-            unsafe { conv2d_avx(input, kernel, input_shape, kernel_shape) }
-        } else {
-            let cpu = CpuFallback;
-            cpu.conv2d(input, kernel, input_shape, kernel_shape)
+            return unsafe { conv2d_avx(input, kernel, input_shape, kernel_shape) };
         }
+        let cpu = CpuFallback;
+        cpu.conv2d(input, kernel, input_shape, kernel_shape)
     }
 
     fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        #[cfg(target_arch = "x86_64")]
         if is_x86_feature_detected!("avx") {
-            unsafe { attention_avx(q, k, v, dim) }
-        } else {
-            let cpu = CpuFallback;
-            cpu.attention(q, k, v, dim)
+            return unsafe { attention_avx(q, k, v, dim) };
         }
+        let cpu = CpuFallback;
+        cpu.attention(q, k, v, dim)
     }
 
     fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        #[cfg(target_arch = "x86_64")]
         if is_x86_feature_detected!("avx") {
-            unsafe { layer_norm_avx(x, gamma, beta, eps) }
-        } else {
-            let cpu = CpuFallback;
-            cpu.layer_norm(x, gamma, beta, eps)
+            return unsafe { layer_norm_avx(x, gamma, beta, eps) };
+        }
+        let cpu = CpuFallback;
+        cpu.layer_norm(x, gamma, beta, eps)
+    }
+
+    fn conv1d(&self, input: &[f32], weight: &[f32], bias: &[f32], shape: Conv1dShape) -> Vec<f32> {
+        // Unfolding the input turns the convolution into a single matmul.
+        let (cols, out_len) = im2col(input, shape);
+        let k = shape.in_channels * shape.kernel;
+        let mut out = self.matmul(weight, &cols, shape.out_channels, out_len, k);
+        for (row, b) in out.chunks_exact_mut(out_len.max(1)).zip(bias) {
+            for v in row {
+                *v += b;
+            }
         }
+        out
+    }
+}
+
+impl CpuSimdBackend {
+    /// Multiply `a` (`m x k`) by the int4 weights `b` (`k x n`, row-major,
+    /// two values per byte, low nibble first) and their quantization
+    /// `scale`.
+    pub fn matmul_int4(
+        &self,
+        a: &[f32],
+        b: &[u8],
+        scale: f32,
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Vec<f32> {
+        assert_int4_len(b, n, k);
+        let at = |p: usize, j: usize| p * n + j;
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { matmul_int4_avx2(a, b, scale, m, n, k, at) };
+        }
+        matmul_int4_scalar(a, b, scale, m, n, k, at)
+    }
+
+    /// [`matmul_int4`](Self::matmul_int4) with `b` in panels of
+    /// [`SIMD_WIDTH`] columns.
+    pub fn matmul_int4_blocked(
+        &self,
+        a: &[f32],
+        b: &[u8],
+        scale: f32,
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Vec<f32> {
+        assert_int4_len(b, n, k);
+        let at = move |p: usize, j: usize| panel_offset(p, j, n, k);
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { matmul_int4_avx2(a, b, scale, m, n, k, at) };
+        }
+        matmul_int4_scalar(a, b, scale, m, n, k, at)
+    }
+
+    /// Multiply `a` (`m x k`) by `b` (`k x n`) stored in panels of
+    /// [`SIMD_WIDTH`] columns.
+    pub fn matmul_blocked(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        assert!(b.len() >= k * n, "{} weights hold fewer than {k} x {n} values", b.len());
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx") {
+            return unsafe { matmul_blocked_avx(a, b, m, n, k) };
+        }
+        let mut out = vec![0.0; m * n];
+        for i in 0..m {
+            for j in 0..n {
+                out[i * n + j] = (0..k)
+                    .map(|p| a[i * k + p] * b[panel_offset(p, j, n, k)])
+                    .sum();
+            }
+        }
+        out
+    }
+}
+
+fn assert_int4_len(b: &[u8], n: usize, k: usize) {
+    assert!(
+        b.len() * 2 >= k * n,
+        "{} packed bytes hold fewer than {k} x {n} int4 values",
+        b.len()
+    );
+}
+
+/// Position of value `(p, j)` of a `k x n` matrix stored in panels of
+/// [`SIMD_WIDTH`] columns; the last panel holds the remaining columns.
+#[inline(always)]
+fn panel_offset(p: usize, j: usize, n: usize, k: usize) -> usize {
+    let full = n - n % SIMD_WIDTH;
+    if j < full {
+        let start = j - j % SIMD_WIDTH;
+        start * k + p * SIMD_WIDTH + (j - start)
+    } else {
+        full * k + p * (n - full) + (j - full)
     }
 }
 
@@ -56,6 +180,7 @@ pub fn init() {
     // No-op for the stubbed backend.
 }
 
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx")]
 unsafe fn matmul_avx(a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
     let mut out = vec![0.0; m * n];
@@ -85,6 +210,7 @@ unsafe fn matmul_avx(a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<
     out
 }
 
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx")]
 unsafe fn conv2d_avx(
     input: &[f32],
@@ -125,6 +251,7 @@ unsafe fn conv2d_avx(
     out
 }
 
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx")]
 unsafe fn attention_avx(q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
     let mut acc = _mm256_setzero_ps();
@@ -160,6 +287,7 @@ unsafe fn attention_avx(q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32>
     out
 }
 
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx")]
 unsafe fn layer_norm_avx(x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
     let len = x.len();
@@ -217,3 +345,126 @@ unsafe fn layer_norm_avx(x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Ve
     out
 }
 
+/// Sign extended int4 value at nibble `idx` of `packed`.
+fn int4_at(packed: &[u8], idx: usize) -> i8 {
+    let byte = packed[idx / 2];
+    let nibble = if idx.is_multiple_of(2) { byte & 0x0F } else { byte >> 4 };
+    ((nibble << 4) as i8) >> 4
+}
+
+/// Eight nibbles of `packed` starting at nibble `idx`, nibble `l` in bits
+/// `4 * l..4 * l + 4`.
+///
+/// # Safety
+///
+/// `packed` must hold at least `idx + 8` nibbles.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn nibbles8(packed: &[u8], idx: usize) -> u32 {
+    let ptr = packed.as_ptr().add(idx / 2);
+    let word = u32::from_le(ptr.cast::<u32>().read_unaligned());
+    if idx.is_multiple_of(2) {
+        word
+    } else {
+        (word >> 4) | (u32::from(*ptr.add(4)) << 28)
+    }
+}
+
+fn matmul_int4_scalar(
+    a: &[f32],
+    b: &[u8],
+    scale: f32,
+    m: usize,
+    n: usize,
+    k: usize,
+    at: impl Fn(usize, usize) -> usize,
+) -> Vec<f32> {
+    let mut out = vec![0.0; m * n];
+    for i in 0..m {
+        for j in 0..n {
+            let mut s = 0.0;
+            for p in 0..k {
+                s += a[i * k + p] * int4_at(b, at(p, j)) as f32;
+            }
+            out[i * n + j] = s * scale;
+        }
+    }
+    out
+}
+
+/// Int4 matmul reading value `(p, j)` of the weights at nibble `at(p, j)`.
+/// The eight values `(p, j..j + 8)` must be consecutive for every `j`
+/// divisible by eight.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn matmul_int4_avx2(
+    a: &[f32],
+    b: &[u8],
+    scale: f32,
+    m: usize,
+    n: usize,
+    k: usize,
+    at: impl Fn(usize, usize) -> usize,
+) -> Vec<f32> {
+    // Shifting a broadcast word by these moves nibble `l` into lane `l`.
+    let shifts = _mm256_setr_epi32(0, 4, 8, 12, 16, 20, 24, 28);
+    let mask = _mm256_set1_epi32(0x0F);
+    let eight = _mm256_set1_epi32(8);
+    let scale_v = _mm256_set1_ps(scale);
+    let mut out = vec![0.0; m * n];
+    for i in 0..m {
+        let row_a = &a[i * k..(i + 1) * k];
+        let mut j = 0;
+        while j + 8 <= n {
+            let mut sum = _mm256_setzero_ps();
+            for (p, &a_p) in row_a.iter().enumerate() {
+                let word = _mm256_set1_epi32(nibbles8(b, at(p, j)) as i32);
+                let q = _mm256_and_si256(_mm256_srlv_epi32(word, shifts), mask);
+                // (q ^ 8) - 8 sign extends the 4-bit values.
+                let q = _mm256_sub_epi32(_mm256_xor_si256(q, eight), eight);
+                let a_val = _mm256_set1_ps(a_p);
+                sum = _mm256_add_ps(sum, _mm256_mul_ps(a_val, _mm256_cvtepi32_ps(q)));
+            }
+            let res = _mm256_mul_ps(sum, scale_v);
+            _mm256_storeu_ps(out.as_mut_ptr().add(i * n + j), res);
+            j += 8;
+        }
+        while j < n {
+            let mut s = 0.0;
+            for (p, &a_p) in row_a.iter().enumerate() {
+                s += a_p * int4_at(b, at(p, j)) as f32;
+            }
+            out[i * n + j] = s * scale;
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn matmul_blocked_avx(a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+    let mut out = vec![0.0; m * n];
+    let full = n - n % SIMD_WIDTH;
+    for i in 0..m {
+        let row_a = &a[i * k..(i + 1) * k];
+        for j in (0..full).step_by(SIMD_WIDTH) {
+            // The panel holds the `k` rows of columns `j..j + 8` back to back.
+            let panel = b.as_ptr().add(j * k);
+            let mut sum = _mm256_setzero_ps();
+            for (p, &a_p) in row_a.iter().enumerate() {
+                let b_vec = _mm256_loadu_ps(panel.add(p * SIMD_WIDTH));
+                sum = _mm256_add_ps(sum, _mm256_mul_ps(_mm256_set1_ps(a_p), b_vec));
+            }
+            _mm256_storeu_ps(out.as_mut_ptr().add(i * n + j), sum);
+        }
+        for j in full..n {
+            out[i * n + j] = row_a
+                .iter()
+                .enumerate()
+                .map(|(p, &a_p)| a_p * b[panel_offset(p, j, n, k)])
+                .sum();
+        }
+    }
+    out
+}
@@ -0,0 +1,237 @@
+//! Device memory shared by the backends and the memory manager.
+//!
+//! A [`DeviceBuffer`] owns a fixed number of bytes on one device: a host
+//! visible Vulkan buffer, a `hipMalloc` allocation with the `rocm` feature, an
+//! OpenCL buffer with the `opencl` feature, or plain host memory for the CPU
+//! backends and emulated devices.  Backends hand out buffers through
+//! [`BufferAllocator`] and implement [`DeviceMemory`] for their allocations;
+//! callers only see [`upload`](DeviceBuffer::upload),
+//! [`download`](DeviceBuffer::download) and
+//! [`copy_from`](DeviceBuffer::copy_from), which check bounds before the
+//! backend is involved.
+//!
+//! A [`MemoryManager`] given an allocator keeps the data of allocations on
+//! the GPU tier in device buffers and moves it to host memory when they are
+//! demoted.
+//!
+//! [`MemoryManager`]: crate::amduda_core::memory_tiering::MemoryManager
+
+use std::fmt;
+
+/// Bytes copied per step when a copy is staged through host memory.
+pub const COPY_CHUNK: usize = 1 << 20;
+
+/// Memory a [`DeviceBuffer`] lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BufferLocation {
+    Host,
+    Vulkan,
+    Hip,
+    OpenCl,
+}
+
+/// Error raised by buffer allocation and transfers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferError {
+    /// An access of `len` bytes at `offset` exceeds a buffer of `size` bytes.
+    OutOfBounds {
+        offset: usize,
+        len: usize,
+        size: usize,
+    },
+    /// The device could not allocate `bytes` bytes.
+    Alloc {
+        location: BufferLocation,
+        bytes: usize,
+        reason: String,
+    },
+    /// A transfer to or from the device failed.
+    Transfer {
+        location: BufferLocation,
+        reason: String,
+    },
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::OutOfBounds { offset, len, size } => write!(
+                f,
+                "access of {len} bytes at offset {offset} exceeds buffer of {size} bytes"
+            ),
+            BufferError::Alloc {
+                location,
+                bytes,
+                reason,
+            } => write!(
+                f,
+                "cannot allocate {bytes} bytes of {location:?} memory: {reason}"
+            ),
+            BufferError::Transfer { location, reason } => {
+                write!(f, "{location:?} transfer failed: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+/// Backend allocation behind a [`DeviceBuffer`].  Offsets and lengths are
+/// checked by the buffer, so implementations only see in-bounds accesses.
+pub trait DeviceMemory: Send + Sync {
+    fn location(&self) -> BufferLocation;
+    /// Copy `data` to `offset` bytes into the allocation.
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferError>;
+    /// Copy `out.len()` bytes at `offset` of the allocation into `out`.
+    fn read(&self, offset: usize, out: &mut [u8]) -> Result<(), BufferError>;
+    /// Contents of host allocations, which lets copies skip staging.
+    fn as_host(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Source of device buffers, implemented by the backends.
+pub trait BufferAllocator: Send + Sync {
+    /// Allocate a zeroed buffer of `len` bytes.
+    fn alloc_buffer(&self, len: usize) -> Result<DeviceBuffer, BufferError>;
+}
+
+/// Allocator of host buffers, used by CPU backends.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostAllocator;
+
+impl BufferAllocator for HostAllocator {
+    fn alloc_buffer(&self, len: usize) -> Result<DeviceBuffer, BufferError> {
+        Ok(DeviceBuffer::host(len))
+    }
+}
+
+/// Host memory.
+struct HostMemory(Vec<u8>);
+
+impl DeviceMemory for HostMemory {
+    fn location(&self) -> BufferLocation {
+        BufferLocation::Host
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferError> {
+        self.0[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&self, offset: usize, out: &mut [u8]) -> Result<(), BufferError> {
+        out.copy_from_slice(&self.0[offset..offset + out.len()]);
+        Ok(())
+    }
+
+    fn as_host(&self) -> Option<&[u8]> {
+        Some(&self.0)
+    }
+}
+
+/// Fixed size buffer on a device, released when dropped.
+pub struct DeviceBuffer {
+    memory: Box<dyn DeviceMemory>,
+    len: usize,
+}
+
+impl fmt::Debug for DeviceBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceBuffer")
+            .field("location", &self.location())
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl DeviceBuffer {
+    /// Wrap a backend allocation of `len` bytes.
+    pub fn new(memory: Box<dyn DeviceMemory>, len: usize) -> Self {
+        Self { memory, len }
+    }
+
+    /// Zeroed host buffer of `len` bytes.
+    pub fn host(len: usize) -> Self {
+        Self::new(Box::new(HostMemory(vec![0; len])), len)
+    }
+
+    /// Host buffer holding `data`.
+    pub fn from_host(data: Vec<u8>) -> Self {
+        let len = data.len();
+        Self::new(Box::new(HostMemory(data)), len)
+    }
+
+    pub fn location(&self) -> BufferLocation {
+        self.memory.location()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy `data` to `offset` bytes into the buffer.
+    pub fn upload(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferError> {
+        self.check(offset, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.memory.write(offset, data)
+    }
+
+    /// Copy `out.len()` bytes at `offset` of the buffer into `out`.
+    pub fn download(&self, offset: usize, out: &mut [u8]) -> Result<(), BufferError> {
+        self.check(offset, out.len())?;
+        if out.is_empty() {
+            return Ok(());
+        }
+        self.memory.read(offset, out)
+    }
+
+    /// Whole contents of the buffer.
+    pub fn to_vec(&self) -> Result<Vec<u8>, BufferError> {
+        let mut out = vec![0; self.len];
+        self.download(0, &mut out)?;
+        Ok(out)
+    }
+
+    /// Copy `len` bytes at `src_offset` of `src` to `dst_offset` of this
+    /// buffer.  Copies from host buffers go straight to the device; others
+    /// are staged through host memory in chunks of [`COPY_CHUNK`] bytes.
+    pub fn copy_from(
+        &mut self,
+        dst_offset: usize,
+        src: &DeviceBuffer,
+        src_offset: usize,
+        len: usize,
+    ) -> Result<(), BufferError> {
+        src.check(src_offset, len)?;
+        self.check(dst_offset, len)?;
+        if let Some(host) = src.memory.as_host() {
+            return self.upload(dst_offset, &host[src_offset..src_offset + len]);
+        }
+        let mut staging = vec![0; len.min(COPY_CHUNK)];
+        let mut done = 0;
+        while done < len {
+            let chunk = &mut staging[..(len - done).min(COPY_CHUNK)];
+            src.download(src_offset + done, chunk)?;
+            self.upload(dst_offset + done, chunk)?;
+            done += chunk.len();
+        }
+        Ok(())
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result<(), BufferError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(()),
+            _ => Err(BufferError::OutOfBounds {
+                offset,
+                len,
+                size: self.len,
+            }),
+        }
+    }
+}
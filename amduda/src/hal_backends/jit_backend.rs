@@ -0,0 +1,90 @@
+//! Host CPU backend running JIT-compiled tensor kernels.
+//!
+//! Matmul and layer norm are compiled once, by LLVM into loops vectorized for
+//! the instruction set of the host or by Cranelift into scalar loops;
+//! convolution and attention have no kernels yet and run on the
+//! [`CpuFallback`].  Kernels specialized for the shapes of a loaded model are
+//! used for ops of exactly those shapes.
+
+use std::collections::HashMap;
+
+use aurex_backend::KernelShapes;
+
+use crate::amduda_core::jit_compiler::{
+    compile_layer_norm, compile_layer_norm_for, compile_matmul, compile_matmul_for, Device,
+    LayerNormKernel, MatmulKernel,
+};
+use crate::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+
+/// Backend executing tensor ops through kernels compiled at startup.
+#[derive(Clone)]
+pub struct JitBackend {
+    matmul: MatmulKernel,
+    layer_norm: LayerNormKernel,
+    /// Matmul kernels keyed by the `(n, k)` they are specialized for.
+    matmuls: HashMap<(usize, usize), MatmulKernel>,
+    /// Layer norm kernels keyed by the row length they are specialized for.
+    layer_norms: HashMap<usize, LayerNormKernel>,
+}
+
+impl JitBackend {
+    /// Check whether the JIT can compile kernels for the host.
+    pub fn is_available() -> bool {
+        Self::new().is_ok()
+    }
+
+    /// Compile the kernels of the backend.  Kernels are cached, so creating
+    /// further backends is cheap.
+    pub fn new() -> Result<Self, String> {
+        Ok(Self {
+            matmul: compile_matmul(Device::CPU)?,
+            layer_norm: compile_layer_norm(Device::CPU)?,
+            matmuls: HashMap::new(),
+            layer_norms: HashMap::new(),
+        })
+    }
+
+    /// Compile kernels specialized for `shapes`, typically the
+    /// `kernel_shapes` of the [`LoadedModel`] about to run.
+    ///
+    /// [`LoadedModel`]: crate::aurex_lm::model_loader::LoadedModel
+    pub fn with_shapes(mut self, shapes: &KernelShapes) -> Result<Self, String> {
+        for &(n, k) in &shapes.matmuls {
+            let kernel = compile_matmul_for(n, k, Device::CPU)?;
+            self.matmuls.insert((n, k), kernel);
+        }
+        for &len in &shapes.norms {
+            let kernel = compile_layer_norm_for(len, Device::CPU)?;
+            self.layer_norms.insert(len, kernel);
+        }
+        Ok(self)
+    }
+}
+
+impl TensorOps for JitBackend {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        let kernel = self.matmuls.get(&(n, k)).unwrap_or(&self.matmul);
+        kernel.run(a, b, m, n, k)
+    }
+
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        let cpu = CpuFallback;
+        cpu.conv2d(input, kernel, input_shape, kernel_shape)
+    }
+
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        let cpu = CpuFallback;
+        cpu.attention(q, k, v, dim)
+    }
+
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        let kernel = self.layer_norms.get(&x.len()).unwrap_or(&self.layer_norm);
+        kernel.run(x, gamma, beta, eps)
+    }
+}
@@ -1,11 +1,26 @@
 //! Hardware abstraction layer backends.
+//!
+//! `wasm32` builds only include the CPU backend and, with the `webgpu`
+//! feature, the WebGPU backend.
 
 pub mod cpu_simd;
+pub mod device_buffer;
+#[cfg(any(feature = "jit", feature = "jit-cranelift"))]
+pub mod jit_backend;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod opencl_backend;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod rocm_backend;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod vulkan_backend;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod sycl_backend;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod riscv_backend;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod verification;
+#[cfg(feature = "webgpu")]
+pub mod webgpu_backend;
 
 /// Enumeration of the available backend types.  This is used by tests to ensure
 /// that the correct backend is selected from environment configuration.
@@ -23,6 +38,12 @@ pub enum BackendKind {
     Sycl,
     /// RISC-V backend.
     Riscv,
+    /// JIT-compiled kernels on the host CPU, selectable with the `jit` or
+    /// `jit-cranelift` feature.
+    Jit,
+    /// WGSL compute shaders through wgpu, selectable with the `webgpu`
+    /// feature.
+    WebGpu,
 }
 
 /// Select a backend based on the `AUREX_BACKEND` environment variable.  If the
@@ -34,11 +55,20 @@ pub fn select_backend() -> BackendKind {
         .to_lowercase()
         .as_str()
     {
+        #[cfg(not(target_arch = "wasm32"))]
         "rocm" if rocm_backend::RocmBackend::is_available() => BackendKind::Rocm,
+        #[cfg(not(target_arch = "wasm32"))]
         "vulkan" if vulkan_backend::VulkanBackend::is_available() => BackendKind::Vulkan,
+        #[cfg(not(target_arch = "wasm32"))]
         "opencl" if opencl_backend::OpenClBackend::is_available() => BackendKind::OpenCl,
+        #[cfg(not(target_arch = "wasm32"))]
         "sycl" if sycl_backend::SyclBackend::is_available() => BackendKind::Sycl,
+        #[cfg(not(target_arch = "wasm32"))]
         "riscv" if riscv_backend::RiscvBackend::is_available() => BackendKind::Riscv,
+        #[cfg(any(feature = "jit", feature = "jit-cranelift"))]
+        "jit" if jit_backend::JitBackend::is_available() => BackendKind::Jit,
+        #[cfg(all(feature = "webgpu", not(target_arch = "wasm32")))]
+        "webgpu" if webgpu_backend::WebGpuBackend::is_available() => BackendKind::WebGpu,
         _ => BackendKind::CpuSimd,
     }
 }
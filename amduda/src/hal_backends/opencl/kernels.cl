@@ -0,0 +1,54 @@
+// OpenCL C kernels of the OpenCL backend.
+//
+// Built once per device when the `opencl` feature is enabled.  Sizes are
+// passed as `ulong` and every kernel writes `float` results.
+
+__kernel void matmul(__global const float* a, __global const float* b,
+                     __global float* c, ulong m, ulong n, ulong k) {
+    size_t i = get_global_id(0);
+    size_t j = get_global_id(1);
+    float sum = 0.0f;
+    for (ulong p = 0; p < k; ++p)
+        sum += a[i * k + p] * b[p * n + j];
+    c[i * n + j] = sum;
+}
+
+__kernel void conv2d(__global const float* input, __global const float* filter,
+                     __global float* out, ulong ih, ulong iw, ulong kh, ulong kw) {
+    size_t i = get_global_id(0);
+    size_t j = get_global_id(1);
+    ulong ow = iw - kw + 1;
+    float sum = 0.0f;
+    for (ulong ki = 0; ki < kh; ++ki)
+        for (ulong kj = 0; kj < kw; ++kj)
+            sum += input[(i + ki) * iw + (j + kj)] * filter[ki * kw + kj];
+    out[i * ow + j] = sum;
+}
+
+__kernel void attention(__global const float* q, __global const float* k,
+                        __global const float* v, __global float* out, ulong dim) {
+    float score = 0.0f;
+    for (ulong i = 0; i < dim; ++i)
+        score += q[i] * k[i];
+    score /= dim;
+    for (ulong i = 0; i < dim; ++i)
+        out[i] = v[i] * score;
+}
+
+__kernel void layer_norm(__global const float* x, __global const float* gamma,
+                         __global const float* beta, __global float* out,
+                         ulong len, float eps) {
+    float mean = 0.0f;
+    for (ulong i = 0; i < len; ++i)
+        mean += x[i];
+    mean /= len;
+    float var = 0.0f;
+    for (ulong i = 0; i < len; ++i) {
+        float d = x[i] - mean;
+        var += d * d;
+    }
+    var /= len;
+    float denom = sqrt(var + eps);
+    for (ulong i = 0; i < len; ++i)
+        out[i] = ((x[i] - mean) / denom) * gamma[i] + beta[i];
+}
@@ -1,26 +1,283 @@
-//! OpenCL backend providing a very small emulation of OpenCL style device
-//! management.  The real project would use the `opencl3` crate (or similar) to
-//! interface with the OpenCL runtime.  The containers used for the unit tests do
-//! not ship with an OpenCL implementation, therefore this module provides a
-//! lightweight stub that models platform and device enumeration, basic context
-//! management and kernel compilation/launch paths.  Tensor operations simply
-//! fall back to the [`CpuFallback`] implementation which allows higher level
-//! code to exercise the dispatch and backend selection logic without requiring a
-//! functional OpenCL stack.
+//! OpenCL backend.
+//!
+//! With the `opencl` feature the backend enumerates the platforms and devices
+//! of the installed ICDs through `opencl3`, builds the OpenCL C kernels in
+//! `opencl/kernels.cl` once per device and runs ops on the selected device,
+//! copying inputs into device buffers and reading the result back.
+//!
+//! Without the feature, or when no ICD reports a usable device, the backend
+//! keeps a lightweight stub that models one CPU and one GPU device.  Tensor
+//! operations then fall back to the [`CpuFallback`] implementation which
+//! allows higher level code to exercise the dispatch and backend selection
+//! logic without requiring a functional OpenCL stack.
 
 use crate::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use crate::hal_backends::device_buffer::{BufferAllocator, BufferError, DeviceBuffer};
 
-/// Type of an emulated OpenCL device.
+/// Type of an OpenCL device.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DeviceKind {
     /// A device executing on the host CPU.
     Cpu,
     /// A device representing a discrete GPU.
     Gpu,
+    /// Any other device type, such as an FPGA or DSP.
+    Accelerator,
 }
 
-/// Representation of an OpenCL device.  Only an identifier and the device kind
-/// are tracked which is sufficient for tests that validate dispatch paths.
+#[cfg(feature = "opencl")]
+mod native {
+    use std::ffi::c_void;
+    use std::ptr;
+    use std::sync::OnceLock;
+
+    use opencl3::command_queue::CommandQueue;
+    use opencl3::context::Context;
+    use opencl3::device::{Device, CL_DEVICE_TYPE_ALL, CL_DEVICE_TYPE_CPU, CL_DEVICE_TYPE_GPU};
+    use opencl3::kernel::Kernel;
+    use opencl3::memory::{
+        Buffer, CL_MEM_COPY_HOST_PTR, CL_MEM_READ_ONLY, CL_MEM_READ_WRITE, CL_MEM_WRITE_ONLY,
+    };
+    use opencl3::platform::get_platforms;
+    use opencl3::program::Program;
+    use opencl3::types::{cl_device_id, cl_float, cl_uchar, cl_ulong, CL_BLOCKING};
+
+    use super::{DeviceKind, KERNELS};
+    use crate::hal_backends::device_buffer::{
+        BufferError, BufferLocation, DeviceBuffer, DeviceMemory,
+    };
+
+    /// Scalar kernel argument following the buffers.
+    pub enum Scalar {
+        Size(usize),
+        Float(f32),
+    }
+
+    /// Device with a context, an in-order queue and the built kernels.
+    pub struct NativeDevice {
+        pub kind: DeviceKind,
+        pub name: String,
+        context: Context,
+        queue: CommandQueue,
+        program: Program,
+    }
+
+    // SAFETY: OpenCL objects may be used from any thread.  The only call that
+    // is not thread safe, `clSetKernelArg`, is made on kernels created per
+    // launch.
+    unsafe impl Send for NativeDevice {}
+    unsafe impl Sync for NativeDevice {}
+
+    impl std::fmt::Debug for NativeDevice {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("NativeDevice")
+                .field("kind", &self.kind)
+                .field("name", &self.name)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl NativeDevice {
+        /// Devices of every platform that built the kernels, probed once per
+        /// process.  Empty when no ICD is installed.
+        pub fn all() -> &'static [NativeDevice] {
+            static DEVICES: OnceLock<Vec<NativeDevice>> = OnceLock::new();
+            DEVICES.get_or_init(|| {
+                let platforms = match get_platforms() {
+                    Ok(platforms) => platforms,
+                    Err(err) => {
+                        tracing::warn!(%err, "no OpenCL platform available, using the emulated backend");
+                        return Vec::new();
+                    }
+                };
+                let ids = platforms
+                    .iter()
+                    .flat_map(|platform| platform.get_devices(CL_DEVICE_TYPE_ALL).unwrap_or_default());
+                let devices = ids
+                    .filter_map(|id| match Self::open(id) {
+                        Ok(device) => {
+                            tracing::debug!(device = %device.name, "OpenCL device opened");
+                            Some(device)
+                        }
+                        Err(err) => {
+                            tracing::warn!(%err, "skipping OpenCL device");
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if devices.is_empty() {
+                    tracing::warn!("no OpenCL device available, using the emulated backend");
+                }
+                devices
+            })
+        }
+
+        fn open(id: cl_device_id) -> Result<Self, String> {
+            let device = Device::new(id);
+            let name = device.name().map_err(|e| e.to_string())?;
+            let dev_type = device.dev_type().map_err(|e| e.to_string())?;
+            let kind = if dev_type & CL_DEVICE_TYPE_GPU != 0 {
+                DeviceKind::Gpu
+            } else if dev_type & CL_DEVICE_TYPE_CPU != 0 {
+                DeviceKind::Cpu
+            } else {
+                DeviceKind::Accelerator
+            };
+            let context = Context::from_device(&device).map_err(|e| format!("{name}: {e}"))?;
+            let queue =
+                CommandQueue::create(&context, id, 0).map_err(|e| format!("{name}: {e}"))?;
+            let program = Program::create_and_build_from_source(&context, KERNELS, "")
+                .map_err(|e| format!("{name}: building kernels failed: {e}"))?;
+            Ok(NativeDevice {
+                kind,
+                name,
+                context,
+                queue,
+                program,
+            })
+        }
+
+        /// Allocate a zeroed buffer of `len` bytes in the device's context.
+        pub fn alloc(&'static self, len: usize) -> Result<DeviceBuffer, BufferError> {
+            let mut zeros = vec![0u8; len.max(1)];
+            let buffer = Buffer::<cl_uchar>::create(
+                &self.context,
+                CL_MEM_READ_WRITE | CL_MEM_COPY_HOST_PTR,
+                zeros.len(),
+                zeros.as_mut_ptr() as *mut c_void,
+            )
+            .map_err(|e| BufferError::Alloc {
+                location: BufferLocation::OpenCl,
+                bytes: len,
+                reason: e.to_string(),
+            })?;
+            let memory = ClMemory {
+                buffer,
+                device: self,
+            };
+            Ok(DeviceBuffer::new(Box::new(memory), len))
+        }
+
+        /// Run kernel `op` over `global` work items with `inputs` as its
+        /// leading buffer arguments, an output buffer of `out_len` values and
+        /// `scalars`.  `None` when the launch failed.
+        pub fn run(
+            &self,
+            op: &str,
+            inputs: &[&[f32]],
+            out_len: usize,
+            scalars: &[Scalar],
+            global: &[usize],
+        ) -> Option<Vec<f32>> {
+            // Zero sized buffers and ranges are invalid in OpenCL.
+            if out_len == 0 || inputs.iter().any(|data| data.is_empty()) {
+                return None;
+            }
+            match self.launch(op, inputs, out_len, scalars, global) {
+                Ok(out) => Some(out),
+                Err(err) => {
+                    tracing::warn!(op, %err, "OpenCL kernel failed, running on the CPU");
+                    None
+                }
+            }
+        }
+
+        fn launch(
+            &self,
+            op: &str,
+            inputs: &[&[f32]],
+            out_len: usize,
+            scalars: &[Scalar],
+            global: &[usize],
+        ) -> opencl3::Result<Vec<f32>> {
+            let kernel = Kernel::create(&self.program, op)?;
+            let buffers = inputs
+                .iter()
+                .map(|data| {
+                    // The buffer only reads from the host pointer.
+                    Buffer::<cl_float>::create(
+                        &self.context,
+                        CL_MEM_READ_ONLY | CL_MEM_COPY_HOST_PTR,
+                        data.len(),
+                        data.as_ptr() as *mut c_void,
+                    )
+                })
+                .collect::<opencl3::Result<Vec<_>>>()?;
+            let out = Buffer::<cl_float>::create(
+                &self.context,
+                CL_MEM_WRITE_ONLY,
+                out_len,
+                ptr::null_mut(),
+            )?;
+            let mut index = 0;
+            for buffer in buffers.iter().chain([&out]) {
+                kernel.set_arg(index, buffer)?;
+                index += 1;
+            }
+            for scalar in scalars {
+                match *scalar {
+                    Scalar::Size(value) => kernel.set_arg(index, &(value as cl_ulong))?,
+                    Scalar::Float(value) => kernel.set_arg(index, &value)?,
+                }
+                index += 1;
+            }
+            self.queue.enqueue_nd_range_kernel(
+                kernel.get(),
+                global.len() as u32,
+                ptr::null(),
+                global.as_ptr(),
+                ptr::null(),
+                &[],
+            )?;
+            let mut result = vec![0.0; out_len];
+            self.queue
+                .enqueue_read_buffer(&out, CL_BLOCKING, 0, &mut result, &[])?;
+            Ok(result)
+        }
+    }
+
+    /// OpenCL buffer behind a [`DeviceBuffer`], transferred through the
+    /// owning device's queue.
+    struct ClMemory {
+        buffer: Buffer<cl_uchar>,
+        device: &'static NativeDevice,
+    }
+
+    // SAFETY: see `NativeDevice`; writes go through `&mut self`.
+    unsafe impl Sync for ClMemory {}
+
+    fn transfer_error(e: opencl3::error_codes::ClError) -> BufferError {
+        BufferError::Transfer {
+            location: BufferLocation::OpenCl,
+            reason: e.to_string(),
+        }
+    }
+
+    impl DeviceMemory for ClMemory {
+        fn location(&self) -> BufferLocation {
+            BufferLocation::OpenCl
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferError> {
+            self.device
+                .queue
+                .enqueue_write_buffer(&mut self.buffer, CL_BLOCKING, offset, data, &[])
+                .map_err(transfer_error)?;
+            Ok(())
+        }
+
+        fn read(&self, offset: usize, out: &mut [u8]) -> Result<(), BufferError> {
+            self.device
+                .queue
+                .enqueue_read_buffer(&self.buffer, CL_BLOCKING, offset, out, &[])
+                .map_err(transfer_error)?;
+            Ok(())
+        }
+    }
+}
+
+/// Representation of an OpenCL device.  `id` indexes the list returned by
+/// [`OpenClBackend::enumerate`].
 #[derive(Clone, Copy, Debug)]
 pub struct OpenClDevice {
     pub id: usize,
@@ -34,11 +291,13 @@ pub struct OpenClContext {
     device: OpenClDevice,
 }
 
-/// Backend instance used by tests.  In a fully fledged implementation this
-/// would manage command queues, compiled kernels and device memory.
+/// Backend instance used by higher level code.  Ops run on an OpenCL device
+/// when one was opened and are emulated on the host otherwise.
 #[derive(Clone, Copy, Debug)]
 pub struct OpenClBackend {
     ctx: OpenClContext,
+    #[cfg(feature = "opencl")]
+    native: Option<&'static native::NativeDevice>,
 }
 
 impl OpenClBackend {
@@ -46,6 +305,21 @@ impl OpenClBackend {
     /// available we emulate a single CPU and GPU device so that the rest of the
     /// stack can exercise device selection and dispatch logic.
     pub fn enumerate() -> Vec<OpenClDevice> {
+        #[cfg(feature = "opencl")]
+        {
+            let devices = native::NativeDevice::all();
+            if !devices.is_empty() {
+                return devices
+                    .iter()
+                    .enumerate()
+                    .map(|(id, device)| OpenClDevice {
+                        id,
+                        kind: device.kind,
+                        name: &device.name,
+                    })
+                    .collect();
+            }
+        }
         vec![
             OpenClDevice {
                 id: 0,
@@ -66,23 +340,40 @@ impl OpenClBackend {
     }
 
     /// Create a new backend for the requested device kind.  When the requested
-    /// device is not present we fall back to the CPU device.
+    /// device is not present we fall back to the first enumerated device.
     pub fn new(kind: DeviceKind) -> Self {
-        let device = Self::enumerate()
-            .into_iter()
+        let devices = Self::enumerate();
+        let device = devices
+            .iter()
+            .copied()
             .find(|d| d.kind == kind)
-            .unwrap_or(OpenClDevice {
-                id: 0,
-                kind: DeviceKind::Cpu,
-                name: "OpenCL CPU",
-            });
+            .unwrap_or(devices[0]);
         OpenClBackend {
             ctx: OpenClContext { device },
+            #[cfg(feature = "opencl")]
+            native: native::NativeDevice::all().get(device.id),
         }
     }
 
-    /// Simulate kernel compilation.  In the stub implementation this is a
-    /// no-op, but it models the call that a real backend would have to perform.
+    /// Whether ops run on an OpenCL device rather than the emulation layer.
+    pub fn is_native(&self) -> bool {
+        #[cfg(feature = "opencl")]
+        {
+            self.native.is_some()
+        }
+        #[cfg(not(feature = "opencl"))]
+        {
+            false
+        }
+    }
+
+    /// Device ops run on.
+    pub fn device(&self) -> OpenClDevice {
+        self.ctx.device
+    }
+
+    /// Simulate kernel compilation.  Native devices build [`KERNELS`] when
+    /// they are opened, so this is a no-op in both modes.
     fn compile_kernel(&self, _src: &str, _name: &str) {
         // No-op for the emulated backend.
     }
@@ -93,16 +384,37 @@ impl OpenClBackend {
     where
         F: FnOnce() -> R,
     {
-        // A real implementation would enqueue the kernel on a command queue and
-        // wait for completion.  For the stub we directly execute the closure.
         f()
     }
 }
 
+/// OpenCL C sources of the four ops, built with the `opencl` feature.
+const KERNELS: &str = include_str!("opencl/kernels.cl");
+
+impl BufferAllocator for OpenClBackend {
+    /// Allocate in the context of the backend's device.  Emulated devices
+    /// allocate host memory.
+    fn alloc_buffer(&self, len: usize) -> Result<DeviceBuffer, BufferError> {
+        #[cfg(feature = "opencl")]
+        if let Some(device) = self.native {
+            return device.alloc(len);
+        }
+        Ok(DeviceBuffer::host(len))
+    }
+}
+
 impl TensorOps for OpenClBackend {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
         let cpu = CpuFallback;
-        self.compile_kernel("", "matmul");
+        self.compile_kernel(KERNELS, "matmul");
+        #[cfg(feature = "opencl")]
+        if let Some(device) = self.native.filter(|_| a.len() == m * k && b.len() == k * n) {
+            use native::Scalar::Size;
+            let scalars = [Size(m), Size(n), Size(k)];
+            if let Some(out) = device.run("matmul", &[a, b], m * n, &scalars, &[m, n]) {
+                return out;
+            }
+        }
         self.launch(|| cpu.matmul(a, b, m, n, k))
     }
 
@@ -114,19 +426,55 @@ impl TensorOps for OpenClBackend {
         kernel_shape: (usize, usize),
     ) -> Vec<f32> {
         let cpu = CpuFallback;
-        self.compile_kernel("", "conv2d");
+        self.compile_kernel(KERNELS, "conv2d");
+        #[cfg(feature = "opencl")]
+        {
+            let ((ih, iw), (kh, kw)) = (input_shape, kernel_shape);
+            let valid = input.len() == ih * iw && kernel.len() == kh * kw && kh <= ih && kw <= iw;
+            if let Some(device) = self.native.filter(|_| valid) {
+                use native::Scalar::Size;
+                let (oh, ow) = (ih - kh + 1, iw - kw + 1);
+                let scalars = [Size(ih), Size(iw), Size(kh), Size(kw)];
+                let out = device.run("conv2d", &[input, kernel], oh * ow, &scalars, &[oh, ow]);
+                if let Some(out) = out {
+                    return out;
+                }
+            }
+        }
         self.launch(|| cpu.conv2d(input, kernel, input_shape, kernel_shape))
     }
 
     fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
         let cpu = CpuFallback;
-        self.compile_kernel("", "attention");
+        self.compile_kernel(KERNELS, "attention");
+        #[cfg(feature = "opencl")]
+        if let Some(device) = self
+            .native
+            .filter(|_| q.len() == dim && k.len() == dim && v.len() == dim)
+        {
+            let scalars = [native::Scalar::Size(dim)];
+            if let Some(out) = device.run("attention", &[q, k, v], dim, &scalars, &[1]) {
+                return out;
+            }
+        }
         self.launch(|| cpu.attention(q, k, v, dim))
     }
 
     fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
         let cpu = CpuFallback;
-        self.compile_kernel("", "layer_norm");
+        self.compile_kernel(KERNELS, "layer_norm");
+        #[cfg(feature = "opencl")]
+        if let Some(device) = self
+            .native
+            .filter(|_| gamma.len() == x.len() && beta.len() == x.len())
+        {
+            use native::Scalar::{Float, Size};
+            let scalars = [Size(x.len()), Float(eps)];
+            let out = device.run("layer_norm", &[x, gamma, beta], x.len(), &scalars, &[1]);
+            if let Some(out) = out {
+                return out;
+            }
+        }
         self.launch(|| cpu.layer_norm(x, gamma, beta, eps))
     }
 }
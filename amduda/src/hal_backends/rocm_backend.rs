@@ -1,19 +1,361 @@
 //! ROCm backend exposing a small subset of the HIP runtime API.
 //!
-//! The real project will offload tensor operations to AMD GPUs via HIP.  The
-//! container used for the unit tests does not ship with ROCm, therefore the
-//! implementation below emulates the public API when the `rocm` feature is not
-//! enabled.  This allows higher level code and tests to exercise the device
+//! With the `rocm` feature the four tensor ops run as the HIP kernels in
+//! `rocm/kernels.hip`.  They are compiled with hiprtc for the `gfx`
+//! architecture of each device the first time the device is used, and the
+//! code objects are kept in the on-disk kernel cache under
+//! [`code_object_key`], so later runs only load them.  Ops whose kernel
+//! cannot be compiled or launched run on the CPU.
+//!
+//! [`RocmStream`]s and [`RocmEvent`]s let callers queue copies on several
+//! streams and order them against each other, so transfers can overlap with
+//! kernels.
+//!
+//! The container used for the unit tests does not ship with ROCm, therefore
+//! the implementation below emulates the public API when the `rocm` feature is
+//! not enabled.  This allows higher level code and tests to exercise the device
 //! discovery, memory allocation and kernel launch pathways without requiring a
 //! GPU.
 
 use crate::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use crate::hal_backends::device_buffer::{BufferAllocator, BufferError, DeviceBuffer};
+use aurex_utils::kernel_cache::KernelKey;
 use std::ffi::c_void;
 use std::ptr;
 
+#[cfg(feature = "rocm")]
+use crate::hal_backends::device_buffer::{BufferLocation, DeviceMemory};
 #[cfg(feature = "rocm")]
 use hip_runtime_sys as hip;
 
+/// HIP C++ sources of the four ops, compiled with hiprtc with the `rocm`
+/// feature.
+pub const KERNELS: &str = include_str!("rocm/kernels.hip");
+
+/// Entry points of [`KERNELS`].
+pub const KERNEL_NAMES: [&str; 4] = ["matmul", "conv2d", "attention", "layer_norm"];
+
+/// Key of the code object of [`KERNELS`] compiled for the `gfx` architecture
+/// `arch`, e.g. `gfx90a:sramecc+:xnack-`, by HIP `hip_version`.  Code objects
+/// are only reused by the same version of this crate.
+pub fn code_object_key(arch: &str, hip_version: &str) -> KernelKey {
+    let driver = format!(
+        "amduda-{} hip-{hip_version} {arch}",
+        env!("CARGO_PKG_VERSION")
+    );
+    KernelKey::new("tensor_ops", "rocm", &driver)
+}
+
+#[cfg(feature = "rocm")]
+mod native {
+    use std::collections::HashMap;
+    use std::ffi::{c_char, c_int, c_void, CStr, CString};
+    use std::ptr;
+    use std::sync::OnceLock;
+
+    use aurex_utils::kernel_cache::KernelCache;
+    use hip_runtime_sys as hip;
+
+    use super::{code_object_key, HipMemory, KERNELS, KERNEL_NAMES};
+
+    /// Side of the thread blocks of `matmul` and `conv2d`, `TILE` in
+    /// `kernels.hip`.
+    pub const TILE: u32 = 16;
+    /// Threads of the single block of `attention` and `layer_norm`, `BLOCK`
+    /// in `kernels.hip`.
+    pub const BLOCK: u32 = 256;
+
+    #[allow(non_camel_case_types)]
+    type hiprtcProgram = *mut c_void;
+
+    const HIPRTC_SUCCESS: c_int = 0;
+
+    #[link(name = "hiprtc")]
+    extern "C" {
+        fn hiprtcCreateProgram(
+            prog: *mut hiprtcProgram,
+            src: *const c_char,
+            name: *const c_char,
+            num_headers: c_int,
+            headers: *const *const c_char,
+            include_names: *const *const c_char,
+        ) -> c_int;
+        fn hiprtcCompileProgram(
+            prog: hiprtcProgram,
+            num_options: c_int,
+            options: *const *const c_char,
+        ) -> c_int;
+        fn hiprtcGetProgramLogSize(prog: hiprtcProgram, size: *mut usize) -> c_int;
+        fn hiprtcGetProgramLog(prog: hiprtcProgram, log: *mut c_char) -> c_int;
+        fn hiprtcGetCodeSize(prog: hiprtcProgram, size: *mut usize) -> c_int;
+        fn hiprtcGetCode(prog: hiprtcProgram, code: *mut c_char) -> c_int;
+        fn hiprtcDestroyProgram(prog: *mut hiprtcProgram) -> c_int;
+    }
+
+    pub fn check(status: i32, what: &str) -> Result<(), String> {
+        if status == hip::hipError_t::hipSuccess as i32 {
+            Ok(())
+        } else {
+            Err(format!("{what} returned {status}"))
+        }
+    }
+
+    /// Scalar kernel argument following the buffers.
+    pub enum Scalar {
+        Size(u32),
+        Float(f32),
+    }
+
+    /// Module of [`KERNELS`] loaded on one device.
+    pub struct DeviceKernels {
+        device: i32,
+        pub arch: String,
+        functions: HashMap<&'static str, hip::hipFunction_t>,
+    }
+
+    // SAFETY: HIP modules and functions may be used from any host thread;
+    // every launch selects the device first.
+    unsafe impl Send for DeviceKernels {}
+    unsafe impl Sync for DeviceKernels {}
+
+    impl std::fmt::Debug for DeviceKernels {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("DeviceKernels")
+                .field("device", &self.device)
+                .field("arch", &self.arch)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl DeviceKernels {
+        /// Kernels of device `id`, compiled or loaded from the kernel cache
+        /// once per process.  `None` when they could not be built.
+        pub fn get(id: i32) -> Option<&'static DeviceKernels> {
+            static DEVICES: OnceLock<Vec<Option<DeviceKernels>>> = OnceLock::new();
+            let devices = DEVICES.get_or_init(|| {
+                let ids = super::RocmBackend::enumerate().into_iter().map(|d| d.id);
+                ids.map(|device| match Self::load(device) {
+                    Ok(kernels) => {
+                        tracing::debug!(device, arch = %kernels.arch, "HIP kernels loaded");
+                        Some(kernels)
+                    }
+                    Err(err) => {
+                        tracing::warn!(device, %err, "no HIP kernels, running ops on the CPU");
+                        None
+                    }
+                })
+                .collect()
+            });
+            devices.get(usize::try_from(id).ok()?)?.as_ref()
+        }
+
+        fn load(device: i32) -> Result<Self, String> {
+            // SAFETY: out pointers are valid and the code object outlives
+            // `hipModuleLoadData`, which copies it.
+            unsafe {
+                check(hip::hipSetDevice(device), "hipSetDevice")?;
+                let mut props: hip::hipDeviceProp_t = std::mem::zeroed();
+                check(
+                    hip::hipGetDeviceProperties(&mut props, device),
+                    "hipGetDeviceProperties",
+                )?;
+                let arch = CStr::from_ptr(props.gcnArchName.as_ptr())
+                    .to_string_lossy()
+                    .into_owned();
+                let mut version = 0;
+                check(
+                    hip::hipRuntimeGetVersion(&mut version),
+                    "hipRuntimeGetVersion",
+                )?;
+
+                let key = code_object_key(&arch, &version.to_string());
+                let code = KernelCache::global().get_or_compile(&key, || compile(&arch))?;
+                let mut module: hip::hipModule_t = ptr::null_mut();
+                check(
+                    hip::hipModuleLoadData(&mut module, code.as_ptr() as *const c_void),
+                    "hipModuleLoadData",
+                )?;
+                let mut functions = HashMap::new();
+                for name in KERNEL_NAMES {
+                    let symbol = CString::new(name).expect("kernel names have no NUL");
+                    let mut function: hip::hipFunction_t = ptr::null_mut();
+                    check(
+                        hip::hipModuleGetFunction(&mut function, module, symbol.as_ptr()),
+                        "hipModuleGetFunction",
+                    )?;
+                    functions.insert(name, function);
+                }
+                Ok(DeviceKernels {
+                    device,
+                    arch,
+                    functions,
+                })
+            }
+        }
+
+        /// Run kernel `op` over a grid of `grid` blocks of `block` threads
+        /// with `inputs` as its leading arguments, an output of `out_len`
+        /// values and `scalars`.  `None` when the launch failed.
+        pub fn run(
+            &self,
+            op: &str,
+            inputs: &[&[f32]],
+            out_len: usize,
+            scalars: &[Scalar],
+            grid: (u32, u32),
+            block: (u32, u32),
+        ) -> Option<Vec<f32>> {
+            if out_len == 0 || inputs.iter().any(|data| data.is_empty()) {
+                return None;
+            }
+            match self.launch(op, inputs, out_len, scalars, grid, block) {
+                Ok(out) => Some(out),
+                Err(err) => {
+                    tracing::warn!(op, %err, "HIP kernel failed, running on the CPU");
+                    None
+                }
+            }
+        }
+
+        fn launch(
+            &self,
+            op: &str,
+            inputs: &[&[f32]],
+            out_len: usize,
+            scalars: &[Scalar],
+            grid: (u32, u32),
+            block: (u32, u32),
+        ) -> Result<Vec<f32>, String> {
+            let function = *self
+                .functions
+                .get(op)
+                .ok_or_else(|| format!("no kernel named {op}"))?;
+            // SAFETY: every buffer holds the values the kernel reads or
+            // writes, and the argument array points at live locals.
+            unsafe {
+                check(hip::hipSetDevice(self.device), "hipSetDevice")?;
+                let mut buffers = Vec::with_capacity(inputs.len() + 1);
+                for data in inputs {
+                    let buffer = upload(data)?;
+                    buffers.push(buffer);
+                }
+                buffers.push(allocate(out_len * 4)?);
+
+                let mut pointers: Vec<*mut c_void> = buffers.iter().map(|b| b.0).collect();
+                let mut values: Vec<[u8; 4]> = scalars
+                    .iter()
+                    .map(|scalar| match *scalar {
+                        Scalar::Size(value) => value.to_ne_bytes(),
+                        Scalar::Float(value) => value.to_ne_bytes(),
+                    })
+                    .collect();
+                let mut args: Vec<*mut c_void> = pointers
+                    .iter_mut()
+                    .map(|p| p as *mut *mut c_void as *mut c_void)
+                    .chain(values.iter_mut().map(|v| v.as_mut_ptr() as *mut c_void))
+                    .collect();
+                check(
+                    hip::hipModuleLaunchKernel(
+                        function,
+                        grid.0,
+                        grid.1,
+                        1,
+                        block.0,
+                        block.1,
+                        1,
+                        0,
+                        ptr::null_mut(),
+                        args.as_mut_ptr(),
+                        ptr::null_mut(),
+                    ),
+                    "hipModuleLaunchKernel",
+                )?;
+                let mut out = vec![0.0f32; out_len];
+                check(
+                    hip::hipMemcpy(
+                        out.as_mut_ptr() as *mut c_void,
+                        buffers[inputs.len()].0,
+                        out_len * 4,
+                        hip::hipMemcpyKind::hipMemcpyDeviceToHost as u32,
+                    ),
+                    "hipMemcpy",
+                )?;
+                Ok(out)
+            }
+        }
+    }
+
+    unsafe fn allocate(bytes: usize) -> Result<HipMemory, String> {
+        let mut ptr: *mut c_void = ptr::null_mut();
+        check(hip::hipMalloc(&mut ptr, bytes), "hipMalloc")?;
+        Ok(HipMemory(ptr))
+    }
+
+    unsafe fn upload(data: &[f32]) -> Result<HipMemory, String> {
+        let buffer = allocate(std::mem::size_of_val(data))?;
+        check(
+            hip::hipMemcpy(
+                buffer.0,
+                data.as_ptr() as *const c_void,
+                std::mem::size_of_val(data),
+                hip::hipMemcpyKind::hipMemcpyHostToDevice as u32,
+            ),
+            "hipMemcpy",
+        )?;
+        Ok(buffer)
+    }
+
+    /// Compile [`KERNELS`] to a code object for `arch` with hiprtc.
+    fn compile(arch: &str) -> Result<Vec<u8>, String> {
+        let source = CString::new(KERNELS).expect("kernel source has no NUL");
+        let name = c"kernels.hip";
+        let options = [
+            CString::new(format!("--gpu-architecture={arch}")).map_err(|e| e.to_string())?,
+            CString::new("-O3").expect("no NUL"),
+        ];
+        let option_ptrs: Vec<*const c_char> = options.iter().map(|o| o.as_ptr()).collect();
+        // SAFETY: every pointer passed to hiprtc is valid for the call and
+        // the program is destroyed on all paths.
+        unsafe {
+            let mut prog: hiprtcProgram = ptr::null_mut();
+            let status = hiprtcCreateProgram(
+                &mut prog,
+                source.as_ptr(),
+                name.as_ptr(),
+                0,
+                ptr::null(),
+                ptr::null(),
+            );
+            if status != HIPRTC_SUCCESS {
+                return Err(format!("hiprtcCreateProgram returned {status}"));
+            }
+            let status =
+                hiprtcCompileProgram(prog, option_ptrs.len() as c_int, option_ptrs.as_ptr());
+            let result = if status != HIPRTC_SUCCESS {
+                let mut size = 0;
+                hiprtcGetProgramLogSize(prog, &mut size);
+                let mut log = vec![0u8; size.max(1)];
+                hiprtcGetProgramLog(prog, log.as_mut_ptr() as *mut c_char);
+                let log = String::from_utf8_lossy(&log);
+                Err(format!(
+                    "hiprtc failed for {arch} with {status}: {}",
+                    log.trim_end_matches('\0').trim()
+                ))
+            } else {
+                let mut size = 0;
+                hiprtcGetCodeSize(prog, &mut size);
+                let mut code = vec![0u8; size];
+                match hiprtcGetCode(prog, code.as_mut_ptr() as *mut c_char) {
+                    HIPRTC_SUCCESS => Ok(code),
+                    status => Err(format!("hiprtcGetCode returned {status}")),
+                }
+            };
+            hiprtcDestroyProgram(&mut prog);
+            result
+        }
+    }
+}
+
 /// Representation of a ROCm device.  Only the device identifier is tracked for
 /// now.
 #[derive(Clone, Copy, Debug, Default)]
@@ -21,11 +363,13 @@ pub struct RocmDevice {
     pub id: i32,
 }
 
-/// Backend instance.  In a full implementation this would manage HIP streams,
-/// modules and other runtime state.
+/// Backend instance.  Ops run as HIP kernels on the selected device when its
+/// kernels were built and are emulated on the host otherwise.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct RocmBackend {
     device: RocmDevice,
+    #[cfg(feature = "rocm")]
+    kernels: Option<&'static native::DeviceKernels>,
 }
 
 impl RocmBackend {
@@ -55,7 +399,36 @@ impl RocmBackend {
             let _ = hip::hipInit(0);
         }
         let device = Self::enumerate().into_iter().next().unwrap_or_default();
-        RocmBackend { device }
+        RocmBackend {
+            device,
+            #[cfg(feature = "rocm")]
+            kernels: native::DeviceKernels::get(device.id),
+        }
+    }
+
+    /// Whether ops run as HIP kernels rather than on the host.
+    pub fn is_native(&self) -> bool {
+        #[cfg(feature = "rocm")]
+        {
+            self.kernels.is_some()
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            false
+        }
+    }
+
+    /// `gfx` architecture the kernels were compiled for, `None` when ops are
+    /// emulated.
+    pub fn arch(&self) -> Option<&str> {
+        #[cfg(feature = "rocm")]
+        {
+            self.kernels.map(|kernels| kernels.arch.as_str())
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            None
+        }
     }
 
     /// Return the number of ROCm devices visible to the process.
@@ -122,22 +495,357 @@ impl RocmBackend {
         }
     }
 
-    /// Launch a kernel.  For the emulated path we simply execute the provided
-    /// closure on the host.  The ROCm enabled build would load and launch a HIP
-    /// kernel using `hipModuleLaunchKernel` or similar APIs.
+    /// Run `f` on the host once the work queued on the device has finished.
+    /// Ops without a usable HIP kernel run their CPU implementation through
+    /// this.
     pub fn launch<F>(&self, f: F)
     where
         F: FnOnce(),
     {
         #[cfg(feature = "rocm")]
         unsafe {
-            // A real implementation would actually launch a compiled kernel.
-            // We simply synchronise to validate the call chain.
             let _ = hip::hipDeviceSynchronize();
         }
+        f();
+    }
+}
+
+impl RocmBackend {
+    /// Create a stream on the backend's device.
+    pub fn create_stream(&self) -> Result<RocmStream, String> {
+        RocmStream::new(self.device)
+    }
+}
+
+/// In-order queue of device work, a `hipStream_t`.  Copies and launches on
+/// different streams may overlap; [`RocmEvent`]s order them where needed.
+///
+/// Without the `rocm` feature work runs on the host as it is enqueued, so a
+/// stream is always idle and its events have always completed.
+#[derive(Debug)]
+pub struct RocmStream {
+    device: RocmDevice,
+    #[cfg(feature = "rocm")]
+    raw: hip::hipStream_t,
+}
+
+// SAFETY: HIP streams may be used from any host thread; every call selects
+// the stream's device first.
+#[cfg(feature = "rocm")]
+unsafe impl Send for RocmStream {}
+#[cfg(feature = "rocm")]
+unsafe impl Sync for RocmStream {}
+
+impl RocmStream {
+    fn new(device: RocmDevice) -> Result<Self, String> {
+        #[cfg(feature = "rocm")]
+        {
+            let mut raw: hip::hipStream_t = ptr::null_mut();
+            // SAFETY: `raw` is a valid out pointer.
+            unsafe {
+                native::check(hip::hipSetDevice(device.id), "hipSetDevice")?;
+                native::check(hip::hipStreamCreate(&mut raw), "hipStreamCreate")?;
+            }
+            Ok(RocmStream { device, raw })
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            Ok(RocmStream { device })
+        }
+    }
+
+    /// Device the stream's work runs on.
+    pub fn device(&self) -> RocmDevice {
+        self.device
+    }
+
+    /// Record an event that completes once the work enqueued so far has
+    /// finished.
+    pub fn record(&self) -> Result<RocmEvent, String> {
+        #[cfg(feature = "rocm")]
+        {
+            let mut raw: hip::hipEvent_t = ptr::null_mut();
+            // SAFETY: `raw` is a valid out pointer and the event is destroyed
+            // by `RocmEvent` or here when recording fails.
+            unsafe {
+                native::check(hip::hipSetDevice(self.device.id), "hipSetDevice")?;
+                native::check(hip::hipEventCreate(&mut raw), "hipEventCreate")?;
+                let event = RocmEvent { raw };
+                native::check(hip::hipEventRecord(raw, self.raw), "hipEventRecord")?;
+                Ok(event)
+            }
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            Ok(RocmEvent {})
+        }
+    }
+
+    /// Make work enqueued after this call wait for `event`, which may have
+    /// been recorded on another stream.  The host does not block.
+    pub fn wait(&self, event: &RocmEvent) -> Result<(), String> {
+        #[cfg(feature = "rocm")]
+        {
+            // SAFETY: both handles are live.
+            let status = unsafe { hip::hipStreamWaitEvent(self.raw, event.raw, 0) };
+            native::check(status, "hipStreamWaitEvent")
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            let _ = event;
+            Ok(())
+        }
+    }
+
+    /// Whether all work enqueued so far has finished.
+    pub fn query(&self) -> Result<bool, String> {
+        #[cfg(feature = "rocm")]
+        {
+            // SAFETY: the stream is live.
+            ready(unsafe { hip::hipStreamQuery(self.raw) }, "hipStreamQuery")
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            Ok(true)
+        }
+    }
+
+    /// Block the host until all work enqueued so far has finished.
+    pub fn synchronize(&self) -> Result<(), String> {
+        #[cfg(feature = "rocm")]
+        {
+            // SAFETY: the stream is live.
+            let status = unsafe { hip::hipStreamSynchronize(self.raw) };
+            native::check(status, "hipStreamSynchronize")
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            Ok(())
+        }
+    }
+
+    /// Enqueue a copy of `bytes` from host memory to the device.
+    ///
+    /// # Safety
+    ///
+    /// `dst` must be a device allocation of at least `bytes`, and `src` must
+    /// stay valid and unchanged until the copy has finished, e.g. until an
+    /// event recorded after it completes.
+    pub unsafe fn memcpy_htod_async(
+        &self,
+        dst: *mut c_void,
+        src: *const c_void,
+        bytes: usize,
+    ) -> Result<(), String> {
+        #[cfg(feature = "rocm")]
+        {
+            let kind = hip::hipMemcpyKind::hipMemcpyHostToDevice as u32;
+            let status = hip::hipMemcpyAsync(dst, src, bytes, kind, self.raw);
+            native::check(status, "hipMemcpyAsync")
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            ptr::copy_nonoverlapping(src as *const u8, dst as *mut u8, bytes);
+            Ok(())
+        }
+    }
+
+    /// Enqueue a copy of `bytes` from the device to host memory.
+    ///
+    /// # Safety
+    ///
+    /// `src` must be a device allocation of at least `bytes`, and `dst` must
+    /// stay valid and must not be read until the copy has finished.
+    pub unsafe fn memcpy_dtoh_async(
+        &self,
+        dst: *mut c_void,
+        src: *const c_void,
+        bytes: usize,
+    ) -> Result<(), String> {
+        #[cfg(feature = "rocm")]
+        {
+            let kind = hip::hipMemcpyKind::hipMemcpyDeviceToHost as u32;
+            let status = hip::hipMemcpyAsync(dst, src, bytes, kind, self.raw);
+            native::check(status, "hipMemcpyAsync")
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            ptr::copy_nonoverlapping(src as *const u8, dst as *mut u8, bytes);
+            Ok(())
+        }
+    }
+
+    /// Run `f` on the host once the work enqueued so far has finished.
+    pub fn launch<F>(&self, f: F) -> Result<(), String>
+    where
+        F: FnOnce(),
+    {
+        self.synchronize()?;
+        f();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rocm")]
+impl Drop for RocmStream {
+    fn drop(&mut self) {
+        // SAFETY: the stream is destroyed once; HIP finishes its pending
+        // work first.
+        let _ = unsafe { hip::hipStreamDestroy(self.raw) };
+    }
+}
+
+/// Point in a [`RocmStream`], a `hipEvent_t`.  Other streams can
+/// [wait](RocmStream::wait) for it and the host can poll or block on it.
+#[derive(Debug)]
+pub struct RocmEvent {
+    #[cfg(feature = "rocm")]
+    raw: hip::hipEvent_t,
+}
+
+// SAFETY: HIP events may be used from any host thread.
+#[cfg(feature = "rocm")]
+unsafe impl Send for RocmEvent {}
+#[cfg(feature = "rocm")]
+unsafe impl Sync for RocmEvent {}
+
+impl RocmEvent {
+    /// Whether the work before the event has finished.
+    pub fn query(&self) -> Result<bool, String> {
+        #[cfg(feature = "rocm")]
+        {
+            // SAFETY: the event is live.
+            ready(unsafe { hip::hipEventQuery(self.raw) }, "hipEventQuery")
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            Ok(true)
+        }
+    }
+
+    /// Block the host until the work before the event has finished.
+    pub fn synchronize(&self) -> Result<(), String> {
+        #[cfg(feature = "rocm")]
+        {
+            // SAFETY: the event is live.
+            let status = unsafe { hip::hipEventSynchronize(self.raw) };
+            native::check(status, "hipEventSynchronize")
+        }
         #[cfg(not(feature = "rocm"))]
         {
-            f();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "rocm")]
+impl Drop for RocmEvent {
+    fn drop(&mut self) {
+        // SAFETY: the event is destroyed once.
+        let _ = unsafe { hip::hipEventDestroy(self.raw) };
+    }
+}
+
+/// `Ok(false)` for `hipErrorNotReady`, the status of a query on pending work.
+#[cfg(feature = "rocm")]
+fn ready(status: i32, what: &str) -> Result<bool, String> {
+    if status == hip::hipError_t::hipErrorNotReady as i32 {
+        return Ok(false);
+    }
+    native::check(status, what).map(|()| true)
+}
+
+/// `hipMalloc` allocation behind a [`DeviceBuffer`].
+#[cfg(feature = "rocm")]
+struct HipMemory(*mut c_void);
+
+// SAFETY: device pointers may be used from any host thread, and writes go
+// through `&mut self`.
+#[cfg(feature = "rocm")]
+unsafe impl Send for HipMemory {}
+#[cfg(feature = "rocm")]
+unsafe impl Sync for HipMemory {}
+
+#[cfg(feature = "rocm")]
+fn hip_check(status: i32, what: &str) -> Result<(), BufferError> {
+    if status == hip::hipError_t::hipSuccess as i32 {
+        return Ok(());
+    }
+    Err(BufferError::Transfer {
+        location: BufferLocation::Hip,
+        reason: format!("{what} returned {status}"),
+    })
+}
+
+#[cfg(feature = "rocm")]
+impl DeviceMemory for HipMemory {
+    fn location(&self) -> BufferLocation {
+        BufferLocation::Hip
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferError> {
+        // SAFETY: the buffer checked that the range lies in the allocation.
+        let status = unsafe {
+            hip::hipMemcpy(
+                self.0.add(offset),
+                data.as_ptr() as *const c_void,
+                data.len(),
+                hip::hipMemcpyKind::hipMemcpyHostToDevice as u32,
+            )
+        };
+        hip_check(status, "hipMemcpy")
+    }
+
+    fn read(&self, offset: usize, out: &mut [u8]) -> Result<(), BufferError> {
+        // SAFETY: the buffer checked that the range lies in the allocation.
+        let status = unsafe {
+            hip::hipMemcpy(
+                out.as_mut_ptr() as *mut c_void,
+                self.0.add(offset),
+                out.len(),
+                hip::hipMemcpyKind::hipMemcpyDeviceToHost as u32,
+            )
+        };
+        hip_check(status, "hipMemcpy")
+    }
+}
+
+#[cfg(feature = "rocm")]
+impl Drop for HipMemory {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from `hipMalloc` and is freed once.
+        let _ = unsafe { hip::hipFree(self.0) };
+    }
+}
+
+impl BufferAllocator for RocmBackend {
+    /// Allocate with `hipMalloc` on the backend's device.  Without the `rocm`
+    /// feature the buffer lives in host memory.
+    fn alloc_buffer(&self, len: usize) -> Result<DeviceBuffer, BufferError> {
+        #[cfg(feature = "rocm")]
+        {
+            let mut ptr: *mut c_void = ptr::null_mut();
+            // SAFETY: `ptr` is a valid out pointer; a zero length request
+            // still yields a distinct allocation.
+            let status = unsafe {
+                let _ = hip::hipSetDevice(self.device.id);
+                hip::hipMalloc(&mut ptr, len.max(1))
+            };
+            if status != hip::hipError_t::hipSuccess as i32 || ptr.is_null() {
+                return Err(BufferError::Alloc {
+                    location: BufferLocation::Hip,
+                    bytes: len,
+                    reason: format!("hipMalloc returned {status}"),
+                });
+            }
+            let memory = HipMemory(ptr);
+            // SAFETY: `ptr` holds at least `len` bytes.
+            hip_check(unsafe { hip::hipMemset(ptr, 0, len) }, "hipMemset")?;
+            Ok(DeviceBuffer::new(Box::new(memory), len))
+        }
+        #[cfg(not(feature = "rocm"))]
+        {
+            Ok(DeviceBuffer::host(len))
         }
     }
 }
@@ -145,6 +853,18 @@ impl RocmBackend {
 impl TensorOps for RocmBackend {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
         let cpu = CpuFallback;
+        #[cfg(feature = "rocm")]
+        if let (Some(kernels), Some([m32, n32, k32])) = (self.kernels, sizes([m, n, k])) {
+            if a.len() == m * k && b.len() == k * n {
+                use native::{Scalar::Size, TILE};
+                let scalars = [Size(m32), Size(n32), Size(k32)];
+                let grid = (n32.div_ceil(TILE), m32.div_ceil(TILE));
+                let out = kernels.run("matmul", &[a, b], m * n, &scalars, grid, (TILE, TILE));
+                if let Some(out) = out {
+                    return out;
+                }
+            }
+        }
         let mut out = Vec::new();
         self.launch(|| {
             out = cpu.matmul(a, b, m, n, k);
@@ -160,6 +880,31 @@ impl TensorOps for RocmBackend {
         kernel_shape: (usize, usize),
     ) -> Vec<f32> {
         let cpu = CpuFallback;
+        #[cfg(feature = "rocm")]
+        {
+            let ((ih, iw), (kh, kw)) = (input_shape, kernel_shape);
+            let valid = input.len() == ih * iw && kernel.len() == kh * kw && kh <= ih && kw <= iw;
+            if let (Some(kernels), Some(dims), true) =
+                (self.kernels, sizes([ih, iw, kh, kw]), valid)
+            {
+                use native::{Scalar::Size, TILE};
+                let (oh, ow) = (dims[0] - dims[2] + 1, dims[1] - dims[3] + 1);
+                let scalars = dims.map(Size);
+                let grid = (ow.div_ceil(TILE), oh.div_ceil(TILE));
+                let out_len = (oh * ow) as usize;
+                let out = kernels.run(
+                    "conv2d",
+                    &[input, kernel],
+                    out_len,
+                    &scalars,
+                    grid,
+                    (TILE, TILE),
+                );
+                if let Some(out) = out {
+                    return out;
+                }
+            }
+        }
         let mut out = Vec::new();
         self.launch(|| {
             out = cpu.conv2d(input, kernel, input_shape, kernel_shape);
@@ -169,6 +914,23 @@ impl TensorOps for RocmBackend {
 
     fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
         let cpu = CpuFallback;
+        #[cfg(feature = "rocm")]
+        if let (Some(kernels), Some([dim32])) = (self.kernels, sizes([dim])) {
+            if q.len() == dim && k.len() == dim && v.len() == dim {
+                use native::{Scalar::Size, BLOCK};
+                let out = kernels.run(
+                    "attention",
+                    &[q, k, v],
+                    dim,
+                    &[Size(dim32)],
+                    (1, 1),
+                    (BLOCK, 1),
+                );
+                if let Some(out) = out {
+                    return out;
+                }
+            }
+        }
         let mut out = Vec::new();
         self.launch(|| {
             out = cpu.attention(q, k, v, dim);
@@ -178,6 +940,19 @@ impl TensorOps for RocmBackend {
 
     fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
         let cpu = CpuFallback;
+        #[cfg(feature = "rocm")]
+        if let (Some(kernels), Some([len])) = (self.kernels, sizes([x.len()])) {
+            if gamma.len() == x.len() && beta.len() == x.len() {
+                use native::Scalar::{Float, Size};
+                use native::BLOCK;
+                let scalars = [Size(len), Float(eps)];
+                let inputs = [x, gamma, beta];
+                let out = kernels.run("layer_norm", &inputs, x.len(), &scalars, (1, 1), (BLOCK, 1));
+                if let Some(out) = out {
+                    return out;
+                }
+            }
+        }
         let mut out = Vec::new();
         self.launch(|| {
             out = cpu.layer_norm(x, gamma, beta, eps);
@@ -186,6 +961,17 @@ impl TensorOps for RocmBackend {
     }
 }
 
+/// `dims` as the `unsigned int` sizes of the kernels, `None` when one does
+/// not fit.
+#[cfg(feature = "rocm")]
+fn sizes<const N: usize>(dims: [usize; N]) -> Option<[u32; N]> {
+    let mut out = [0; N];
+    for (size, dim) in out.iter_mut().zip(dims) {
+        *size = u32::try_from(dim).ok()?;
+    }
+    Some(out)
+}
+
 /// Initialize the ROCm backend by probing devices.
 pub fn init() {
     let _ = RocmBackend::new();
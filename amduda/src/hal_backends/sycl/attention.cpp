@@ -0,0 +1,13 @@
+#include <sycl/sycl.hpp>
+using namespace sycl;
+extern "C" void attention(queue q, const float* qv, const float* kv,
+                           const float* vv, float* out, size_t dim) {
+    q.single_task([=]() {
+        float score = 0.0f;
+        for (size_t i = 0; i < dim; ++i)
+            score += qv[i] * kv[i];
+        score /= dim;
+        for (size_t i = 0; i < dim; ++i)
+            out[i] = vv[i] * score;
+    }).wait();
+}
@@ -0,0 +1,17 @@
+#include <sycl/sycl.hpp>
+using namespace sycl;
+extern "C" void conv2d(queue q, const float* input, const float* kernel,
+                        float* out, size_t ih, size_t iw,
+                        size_t kh, size_t kw) {
+    size_t oh = ih - kh + 1;
+    size_t ow = iw - kw + 1;
+    q.parallel_for(range<2>(oh, ow), [=](id<2> idx) {
+        size_t i = idx[0];
+        size_t j = idx[1];
+        float sum = 0.0f;
+        for (size_t ki = 0; ki < kh; ++ki)
+            for (size_t kj = 0; kj < kw; ++kj)
+                sum += input[(i+ki)*iw + (j+kj)] * kernel[ki*kw + kj];
+        out[i*ow + j] = sum;
+    }).wait();
+}
@@ -0,0 +1,21 @@
+#include <sycl/sycl.hpp>
+using namespace sycl;
+extern "C" void layer_norm(queue q, const float* x, const float* gamma,
+                             const float* beta, float* out, size_t len,
+                             float eps) {
+    q.single_task([=]() {
+        float mean = 0.0f;
+        for (size_t i = 0; i < len; ++i)
+            mean += x[i];
+        mean /= len;
+        float var = 0.0f;
+        for (size_t i = 0; i < len; ++i) {
+            float d = x[i] - mean;
+            var += d * d;
+        }
+        var /= len;
+        float denom = sycl::sqrt(var + eps);
+        for (size_t i = 0; i < len; ++i)
+            out[i] = ((x[i] - mean) / denom) * gamma[i] + beta[i];
+    }).wait();
+}
@@ -0,0 +1,13 @@
+#include <sycl/sycl.hpp>
+using namespace sycl;
+extern "C" void matmul(queue q, const float* a, const float* b, float* c,
+                        size_t M, size_t N, size_t K) {
+    q.parallel_for(range<2>(M, N), [=](id<2> idx) {
+        size_t i = idx[0];
+        size_t j = idx[1];
+        float sum = 0.0f;
+        for (size_t kk = 0; kk < K; ++kk)
+            sum += a[i*K + kk] * b[kk*N + j];
+        c[i*N + j] = sum;
+    }).wait();
+}
@@ -0,0 +1,131 @@
+// C interface to the DPC++ kernels of the SYCL backend.
+//
+// Built by amduda's build.rs into a shared library when the `sycl` feature is
+// enabled.  Each entry point copies its host inputs into device USM, runs the
+// matching kernel on the queue and copies the result back.  Entry points
+// return 0 on success and -1 when SYCL raised an exception.
+
+#include <sycl/sycl.hpp>
+
+#include <cstring>
+#include <exception>
+#include <new>
+#include <string>
+
+#include "attention.cpp"
+#include "conv2d.cpp"
+#include "layer_norm.cpp"
+#include "matmul.cpp"
+
+#define AUREX_EXPORT extern "C" __attribute__((visibility("default")))
+
+struct aurex_sycl_queue {
+    sycl::queue q;
+};
+
+namespace {
+
+// Device copy of a host buffer, released when it goes out of scope.
+class DeviceBuffer {
+  public:
+    DeviceBuffer(sycl::queue& q, const float* host, size_t len)
+        : q_(q), ptr_(sycl::malloc_device<float>(len == 0 ? 1 : len, q)) {
+        if (ptr_ == nullptr) {
+            throw std::bad_alloc();
+        }
+        if (host != nullptr && len > 0) {
+            q_.memcpy(ptr_, host, len * sizeof(float)).wait();
+        }
+    }
+    DeviceBuffer(const DeviceBuffer&) = delete;
+    DeviceBuffer& operator=(const DeviceBuffer&) = delete;
+    ~DeviceBuffer() { sycl::free(ptr_, q_); }
+
+    float* get() const { return ptr_; }
+
+    void read(float* host, size_t len) {
+        if (len > 0) {
+            q_.memcpy(host, ptr_, len * sizeof(float)).wait();
+        }
+    }
+
+  private:
+    sycl::queue& q_;
+    float* ptr_;
+};
+
+template <typename F>
+int guarded(F&& f) {
+    try {
+        f();
+        return 0;
+    } catch (const std::exception&) {
+        return -1;
+    }
+}
+
+}  // namespace
+
+// Create an in-order queue on the default device and copy its name,
+// truncated and NUL terminated, into `name`.  Returns null when no device is
+// available.
+AUREX_EXPORT aurex_sycl_queue* aurex_sycl_queue_create(char* name, size_t name_len) {
+    try {
+        auto* queue = new aurex_sycl_queue{sycl::queue{sycl::default_selector_v,
+                                                       sycl::property::queue::in_order{}}};
+        if (name != nullptr && name_len > 0) {
+            std::string device = queue->q.get_device().get_info<sycl::info::device::name>();
+            size_t len = device.size() < name_len - 1 ? device.size() : name_len - 1;
+            std::memcpy(name, device.data(), len);
+            name[len] = '\0';
+        }
+        return queue;
+    } catch (const std::exception&) {
+        return nullptr;
+    }
+}
+
+AUREX_EXPORT void aurex_sycl_queue_destroy(aurex_sycl_queue* queue) { delete queue; }
+
+AUREX_EXPORT int aurex_sycl_matmul(aurex_sycl_queue* queue, const float* a, const float* b,
+                                   float* c, size_t m, size_t n, size_t k) {
+    return guarded([&] {
+        sycl::queue& q = queue->q;
+        DeviceBuffer da(q, a, m * k), db(q, b, k * n), dc(q, nullptr, m * n);
+        matmul(q, da.get(), db.get(), dc.get(), m, n, k);
+        dc.read(c, m * n);
+    });
+}
+
+AUREX_EXPORT int aurex_sycl_conv2d(aurex_sycl_queue* queue, const float* input,
+                                   const float* kernel, float* out, size_t ih, size_t iw,
+                                   size_t kh, size_t kw) {
+    return guarded([&] {
+        sycl::queue& q = queue->q;
+        size_t out_len = (ih - kh + 1) * (iw - kw + 1);
+        DeviceBuffer di(q, input, ih * iw), dk(q, kernel, kh * kw), dout(q, nullptr, out_len);
+        conv2d(q, di.get(), dk.get(), dout.get(), ih, iw, kh, kw);
+        dout.read(out, out_len);
+    });
+}
+
+AUREX_EXPORT int aurex_sycl_attention(aurex_sycl_queue* queue, const float* qv, const float* kv,
+                                      const float* vv, float* out, size_t dim) {
+    return guarded([&] {
+        sycl::queue& q = queue->q;
+        DeviceBuffer dq(q, qv, dim), dk(q, kv, dim), dv(q, vv, dim), dout(q, nullptr, dim);
+        attention(q, dq.get(), dk.get(), dv.get(), dout.get(), dim);
+        dout.read(out, dim);
+    });
+}
+
+AUREX_EXPORT int aurex_sycl_layer_norm(aurex_sycl_queue* queue, const float* x,
+                                       const float* gamma, const float* beta, float* out,
+                                       size_t len, float eps) {
+    return guarded([&] {
+        sycl::queue& q = queue->q;
+        DeviceBuffer dx(q, x, len), dg(q, gamma, len), db(q, beta, len), dout(q, nullptr, len);
+        layer_norm(q, dx.get(), dg.get(), db.get(), dout.get(), len, eps);
+        dout.read(out, len);
+    });
+}
@@ -1,18 +1,132 @@
 //! SYCL backend implemented via oneAPI (DPC++).
 //!
-//! The real project will interface with oneAPI to JIT and launch kernels on a
-//! variety of accelerators.  The containers used for the unit tests do not ship
-//! with the oneAPI runtime.  To keep the build portable this module provides a
-//! small emulation layer: we model device enumeration, kernel compilation and
-//! launch but ultimately fall back to the [`CpuFallback`] implementation.
+//! With the `sycl` feature the DPC++ kernels in `sycl/` are compiled by
+//! `build.rs` into a small C++ shim that owns a `sycl::queue` on the default
+//! device, and ops run on that device through the shim's C interface.
 //!
-//! Sample DPC++ kernels are embedded as string constants.  They illustrate how
-//! tensor operations could be expressed in SYCL.  During tests the kernels are
-//! not compiled; instead we simply record that the compile/launch path was
-//! exercised and delegate the actual computation to the CPU fallback.
+//! Without the feature, or when no SYCL device can be opened, the backend
+//! keeps the portable emulation layer: we model device enumeration, kernel
+//! compilation and launch but ultimately fall back to the [`CpuFallback`]
+//! implementation.  The kernel sources stay embedded as string constants so
+//! the compile path is exercised either way.
 
 use crate::amduda_core::tensor_ops::{CpuFallback, TensorOps};
 
+#[cfg(feature = "sycl")]
+mod ffi {
+    use std::ffi::{c_char, c_int};
+
+    /// Opaque `sycl::queue` owned by the shim.
+    #[repr(C)]
+    pub struct Queue {
+        _private: [u8; 0],
+    }
+
+    extern "C" {
+        pub fn aurex_sycl_queue_create(name: *mut c_char, name_len: usize) -> *mut Queue;
+        pub fn aurex_sycl_queue_destroy(queue: *mut Queue);
+        pub fn aurex_sycl_matmul(
+            queue: *mut Queue,
+            a: *const f32,
+            b: *const f32,
+            c: *mut f32,
+            m: usize,
+            n: usize,
+            k: usize,
+        ) -> c_int;
+        pub fn aurex_sycl_conv2d(
+            queue: *mut Queue,
+            input: *const f32,
+            kernel: *const f32,
+            out: *mut f32,
+            ih: usize,
+            iw: usize,
+            kh: usize,
+            kw: usize,
+        ) -> c_int;
+        pub fn aurex_sycl_attention(
+            queue: *mut Queue,
+            q: *const f32,
+            k: *const f32,
+            v: *const f32,
+            out: *mut f32,
+            dim: usize,
+        ) -> c_int;
+        pub fn aurex_sycl_layer_norm(
+            queue: *mut Queue,
+            x: *const f32,
+            gamma: *const f32,
+            beta: *const f32,
+            out: *mut f32,
+            len: usize,
+            eps: f32,
+        ) -> c_int;
+    }
+}
+
+/// Queue on the default SYCL device, opened once per process.
+#[cfg(feature = "sycl")]
+#[derive(Debug)]
+struct NativeQueue {
+    queue: *mut ffi::Queue,
+    name: String,
+}
+
+// SAFETY: `sycl::queue` is thread safe and the shim never mutates the
+// wrapper after creation.
+#[cfg(feature = "sycl")]
+unsafe impl Send for NativeQueue {}
+#[cfg(feature = "sycl")]
+unsafe impl Sync for NativeQueue {}
+
+#[cfg(feature = "sycl")]
+impl NativeQueue {
+    fn get() -> Option<&'static NativeQueue> {
+        static QUEUE: std::sync::OnceLock<Option<NativeQueue>> = std::sync::OnceLock::new();
+        QUEUE
+            .get_or_init(|| {
+                let mut name = [0 as std::ffi::c_char; 256];
+                // SAFETY: the shim writes at most `name.len()` bytes,
+                // including the terminating NUL.
+                let queue = unsafe { ffi::aurex_sycl_queue_create(name.as_mut_ptr(), name.len()) };
+                if queue.is_null() {
+                    tracing::warn!("no SYCL device available, using the emulated backend");
+                    return None;
+                }
+                // SAFETY: the shim NUL terminated the name.
+                let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned();
+                tracing::debug!(device = %name, "SYCL queue created");
+                Some(NativeQueue { queue, name })
+            })
+            .as_ref()
+    }
+
+    /// Run a shim entry point writing `len` values, `None` when it failed.
+    fn run(
+        &self,
+        op: &str,
+        len: usize,
+        f: impl FnOnce(*mut ffi::Queue, *mut f32) -> std::ffi::c_int,
+    ) -> Option<Vec<f32>> {
+        let mut out = vec![0.0; len];
+        if f(self.queue, out.as_mut_ptr()) == 0 {
+            return Some(out);
+        }
+        tracing::warn!(op, "SYCL kernel failed, running on the CPU");
+        None
+    }
+}
+
+#[cfg(feature = "sycl")]
+impl Drop for NativeQueue {
+    fn drop(&mut self) {
+        // SAFETY: the queue was created by the shim and is destroyed once.
+        unsafe { ffi::aurex_sycl_queue_destroy(self.queue) }
+    }
+}
+
 /// Representation of a SYCL device.  In the emulated implementation only an id
 /// and name are tracked.
 #[derive(Clone, Copy, Debug)]
@@ -27,23 +141,49 @@ pub struct SyclContext {
     device: SyclDevice,
 }
 
-/// Backend instance used by higher level code.  In a full implementation this
-/// would own a `sycl::queue` and compiled kernels.
+/// Backend instance used by higher level code.  Ops run on the shim's queue
+/// when one was opened and are emulated on the host otherwise.
 #[derive(Clone, Copy, Debug)]
 pub struct SyclBackend {
     ctx: SyclContext,
+    #[cfg(feature = "sycl")]
+    native: Option<&'static NativeQueue>,
 }
 
 impl SyclBackend {
     /// Enumerate available SYCL devices.  When the runtime is unavailable we
     /// emulate a single CPU device so that dispatch logic can still be tested.
     pub fn enumerate() -> Vec<SyclDevice> {
+        #[cfg(feature = "sycl")]
+        if let Some(native) = NativeQueue::get() {
+            return vec![SyclDevice {
+                id: 0,
+                name: &native.name,
+            }];
+        }
         vec![SyclDevice {
             id: 0,
             name: "SYCL CPU",
         }]
     }
 
+    /// Whether ops run on a SYCL device rather than the emulation layer.
+    pub fn is_native(&self) -> bool {
+        #[cfg(feature = "sycl")]
+        {
+            self.native.is_some()
+        }
+        #[cfg(not(feature = "sycl"))]
+        {
+            false
+        }
+    }
+
+    /// Name of the device ops run on.
+    pub fn device_name(&self) -> &'static str {
+        self.ctx.device.name
+    }
+
     /// Convenience helper mirroring other backends.
     pub fn is_available() -> bool {
         !Self::enumerate().is_empty()
@@ -54,6 +194,8 @@ impl SyclBackend {
         let device = Self::enumerate().remove(0);
         SyclBackend {
             ctx: SyclContext { device },
+            #[cfg(feature = "sycl")]
+            native: NativeQueue::get(),
         }
     }
 
@@ -74,88 +216,20 @@ impl SyclBackend {
 }
 
 // -----------------------------------------------------------------------------
-// Sample DPC++ kernels (not executed during tests)
+// DPC++ kernels, run through the shim with the `sycl` feature
 // -----------------------------------------------------------------------------
 
 /// Matrix multiplication kernel expressed in SYCL/DPC++.
-const MATMUL_KERNEL: &str = r#"
-#include <sycl/sycl.hpp>
-using namespace sycl;
-extern "C" void matmul(queue q, const float* a, const float* b, float* c,
-                        size_t M, size_t N, size_t K) {
-    q.parallel_for(range<2>(M, N), [=](id<2> idx) {
-        size_t i = idx[0];
-        size_t j = idx[1];
-        float sum = 0.0f;
-        for (size_t kk = 0; kk < K; ++kk)
-            sum += a[i*K + kk] * b[kk*N + j];
-        c[i*N + j] = sum;
-    }).wait();
-}
-"#;
+const MATMUL_KERNEL: &str = include_str!("sycl/matmul.cpp");
 
 /// 2D convolution for single channel inputs.
-const CONV2D_KERNEL: &str = r#"
-#include <sycl/sycl.hpp>
-using namespace sycl;
-extern "C" void conv2d(queue q, const float* input, const float* kernel,
-                        float* out, size_t ih, size_t iw,
-                        size_t kh, size_t kw) {
-    size_t oh = ih - kh + 1;
-    size_t ow = iw - kw + 1;
-    q.parallel_for(range<2>(oh, ow), [=](id<2> idx) {
-        size_t i = idx[0];
-        size_t j = idx[1];
-        float sum = 0.0f;
-        for (size_t ki = 0; ki < kh; ++ki)
-            for (size_t kj = 0; kj < kw; ++kj)
-                sum += input[(i+ki)*iw + (j+kj)] * kernel[ki*kw + kj];
-        out[i*ow + j] = sum;
-    }).wait();
-}
-"#;
+const CONV2D_KERNEL: &str = include_str!("sycl/conv2d.cpp");
 
 /// Simple attention primitive.
-const ATTENTION_KERNEL: &str = r#"
-#include <sycl/sycl.hpp>
-using namespace sycl;
-extern "C" void attention(queue q, const float* qv, const float* kv,
-                           const float* vv, float* out, size_t dim) {
-    q.single_task([=]() {
-        float score = 0.0f;
-        for (size_t i = 0; i < dim; ++i)
-            score += qv[i] * kv[i];
-        score /= dim;
-        for (size_t i = 0; i < dim; ++i)
-            out[i] = vv[i] * score;
-    }).wait();
-}
-"#;
+const ATTENTION_KERNEL: &str = include_str!("sycl/attention.cpp");
 
 /// Layer normalization kernel.
-const LAYERNORM_KERNEL: &str = r#"
-#include <sycl/sycl.hpp>
-using namespace sycl;
-extern "C" void layer_norm(queue q, const float* x, const float* gamma,
-                             const float* beta, float* out, size_t len,
-                             float eps) {
-    q.single_task([=]() {
-        float mean = 0.0f;
-        for (size_t i = 0; i < len; ++i)
-            mean += x[i];
-        mean /= len;
-        float var = 0.0f;
-        for (size_t i = 0; i < len; ++i) {
-            float d = x[i] - mean;
-            var += d * d;
-        }
-        var /= len;
-        float denom = sycl::sqrt(var + eps);
-        for (size_t i = 0; i < len; ++i)
-            out[i] = ((x[i] - mean) / denom) * gamma[i] + beta[i];
-    }).wait();
-}
-"#;
+const LAYERNORM_KERNEL: &str = include_str!("sycl/layer_norm.cpp");
 
 // -----------------------------------------------------------------------------
 // TensorOps implementation
@@ -165,6 +239,15 @@ impl TensorOps for SyclBackend {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
         let cpu = CpuFallback;
         self.compile_kernel(MATMUL_KERNEL, "matmul");
+        #[cfg(feature = "sycl")]
+        if let Some(native) = self.native.filter(|_| a.len() == m * k && b.len() == k * n) {
+            let out = native.run("matmul", m * n, |queue, out| unsafe {
+                ffi::aurex_sycl_matmul(queue, a.as_ptr(), b.as_ptr(), out, m, n, k)
+            });
+            if let Some(out) = out {
+                return out;
+            }
+        }
         self.launch(|| {});
         cpu.matmul(a, b, m, n, k)
     }
@@ -178,6 +261,29 @@ impl TensorOps for SyclBackend {
     ) -> Vec<f32> {
         let cpu = CpuFallback;
         self.compile_kernel(CONV2D_KERNEL, "conv2d");
+        #[cfg(feature = "sycl")]
+        {
+            let ((ih, iw), (kh, kw)) = (input_shape, kernel_shape);
+            let valid = input.len() == ih * iw && kernel.len() == kh * kw && kh <= ih && kw <= iw;
+            if let Some(native) = self.native.filter(|_| valid) {
+                let len = (ih - kh + 1) * (iw - kw + 1);
+                let out = native.run("conv2d", len, |queue, out| unsafe {
+                    ffi::aurex_sycl_conv2d(
+                        queue,
+                        input.as_ptr(),
+                        kernel.as_ptr(),
+                        out,
+                        ih,
+                        iw,
+                        kh,
+                        kw,
+                    )
+                });
+                if let Some(out) = out {
+                    return out;
+                }
+            }
+        }
         self.launch(|| {});
         cpu.conv2d(input, kernel, input_shape, kernel_shape)
     }
@@ -185,6 +291,18 @@ impl TensorOps for SyclBackend {
     fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
         let cpu = CpuFallback;
         self.compile_kernel(ATTENTION_KERNEL, "attention");
+        #[cfg(feature = "sycl")]
+        if let Some(native) = self
+            .native
+            .filter(|_| q.len() == dim && k.len() == dim && v.len() == dim)
+        {
+            let out = native.run("attention", dim, |queue, out| unsafe {
+                ffi::aurex_sycl_attention(queue, q.as_ptr(), k.as_ptr(), v.as_ptr(), out, dim)
+            });
+            if let Some(out) = out {
+                return out;
+            }
+        }
         self.launch(|| {});
         cpu.attention(q, k, v, dim)
     }
@@ -192,6 +310,26 @@ impl TensorOps for SyclBackend {
     fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
         let cpu = CpuFallback;
         self.compile_kernel(LAYERNORM_KERNEL, "layer_norm");
+        #[cfg(feature = "sycl")]
+        if let Some(native) = self
+            .native
+            .filter(|_| gamma.len() == x.len() && beta.len() == x.len())
+        {
+            let out = native.run("layer_norm", x.len(), |queue, out| unsafe {
+                ffi::aurex_sycl_layer_norm(
+                    queue,
+                    x.as_ptr(),
+                    gamma.as_ptr(),
+                    beta.as_ptr(),
+                    out,
+                    x.len(),
+                    eps,
+                )
+            });
+            if let Some(out) = out {
+                return out;
+            }
+        }
         self.launch(|| {});
         cpu.layer_norm(x, gamma, beta, eps)
     }
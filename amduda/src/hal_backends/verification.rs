@@ -0,0 +1,427 @@
+//! Numerical conformance of backends against [`CpuFallback`].
+//!
+//! Every op of a backend's [`TensorOps`] is run on randomized shapes and
+//! values and compared element by element with the reference results of
+//! [`CpuFallback`].  Inputs come from a seeded generator, so a failing case
+//! reproduces with the same [`VerifyConfig`].  A backend that panics or
+//! returns the wrong number of elements fails the case.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+
+use super::{opencl_backend, riscv_backend, rocm_backend, sycl_backend, vulkan_backend};
+
+/// Ops checked by [`verify_backend`].
+pub const OPS: [&str; 4] = ["matmul", "conv2d", "attention", "layer_norm"];
+
+/// Accepted deviation of an element: `|got - want| <= abs + rel * |want|`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    pub abs: f32,
+    pub rel: f32,
+}
+
+impl Tolerance {
+    pub fn new(abs: f32, rel: f32) -> Self {
+        Self { abs, rel }
+    }
+
+    fn accepts(&self, want: f32, got: f32) -> bool {
+        (got - want).abs() <= self.abs + self.rel * want.abs()
+    }
+}
+
+impl Default for Tolerance {
+    /// Loose enough for sums reordered by vectorized kernels.
+    fn default() -> Self {
+        Self::new(1e-4, 1e-4)
+    }
+}
+
+/// Parameters of a verification run.
+#[derive(Debug, Clone)]
+pub struct VerifyConfig {
+    /// Random cases per op.
+    pub cases: usize,
+    /// Upper bound of every generated dimension.
+    pub max_dim: usize,
+    pub seed: u64,
+    /// Tolerance of ops without an override.
+    pub tolerance: Tolerance,
+    /// Per-op tolerances, keyed by op name.
+    pub op_tolerances: HashMap<String, Tolerance>,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        Self {
+            cases: 32,
+            max_dim: 64,
+            seed: 0x5eed,
+            tolerance: Tolerance::default(),
+            op_tolerances: HashMap::new(),
+        }
+    }
+}
+
+impl VerifyConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_cases(mut self, cases: usize) -> Self {
+        self.cases = cases;
+        self
+    }
+
+    pub fn with_max_dim(mut self, max_dim: usize) -> Self {
+        self.max_dim = max_dim.max(1);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Use `tolerance` for `op` instead of the default one.
+    pub fn with_op_tolerance(mut self, op: &str, tolerance: Tolerance) -> Self {
+        self.op_tolerances.insert(op.to_string(), tolerance);
+        self
+    }
+
+    /// Tolerance applied to `op`.
+    pub fn tolerance(&self, op: &str) -> Tolerance {
+        self.op_tolerances
+            .get(op)
+            .copied()
+            .unwrap_or(self.tolerance)
+    }
+}
+
+/// Results of one op.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpReport {
+    pub op: &'static str,
+    pub cases: usize,
+    pub failures: usize,
+    /// Largest absolute error over all elements of all cases.
+    pub max_abs_error: f32,
+    /// Largest error relative to the reference value.
+    pub max_rel_error: f32,
+    /// Shapes and reason of the first failing case.
+    pub first_failure: Option<String>,
+}
+
+impl OpReport {
+    pub fn passed(&self) -> bool {
+        self.failures == 0
+    }
+
+    /// Record the errors of `got` and describe why it fails, if it does.
+    fn compare(&mut self, want: &[f32], got: &[f32], tolerance: Tolerance) -> Option<String> {
+        if want.len() != got.len() {
+            return Some(format!(
+                "expected {} elements, got {}",
+                want.len(),
+                got.len()
+            ));
+        }
+        let mut failure = None;
+        for (i, (&w, &g)) in want.iter().zip(got).enumerate() {
+            let abs = (g - w).abs();
+            // NaN compares false, so it is caught by `accepts` below.
+            self.max_abs_error = self.max_abs_error.max(abs);
+            if w != 0.0 {
+                self.max_rel_error = self.max_rel_error.max(abs / w.abs());
+            }
+            if failure.is_none() && !tolerance.accepts(w, g) {
+                failure = Some(format!("element {i}: expected {w}, got {g}"));
+            }
+        }
+        failure
+    }
+}
+
+/// Results of all ops of one backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConformanceReport {
+    pub backend: String,
+    pub ops: Vec<OpReport>,
+}
+
+impl ConformanceReport {
+    pub fn passed(&self) -> bool {
+        self.ops.iter().all(OpReport::passed)
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.passed() { "PASS" } else { "FAIL" };
+        writeln!(f, "Backend {}: {verdict}", self.backend)?;
+        for op in &self.ops {
+            write!(
+                f,
+                "  {:<12} {:>4}/{:<4} passed  max abs {:.3e}  max rel {:.3e}",
+                op.op,
+                op.cases - op.failures,
+                op.cases,
+                op.max_abs_error,
+                op.max_rel_error
+            )?;
+            if let Some(failure) = &op.first_failure {
+                write!(f, "  first failure: {failure}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Names accepted by [`open_backend`], in the order [`verify_all`] checks
+/// them.
+pub fn backend_names() -> Vec<&'static str> {
+    let mut names = vec!["cpu", "rocm", "vulkan", "opencl", "sycl", "riscv"];
+    if cfg!(any(feature = "jit", feature = "jit-cranelift")) {
+        names.push("jit");
+    }
+    names
+}
+
+/// Instantiate the backend called `name` (as in `AUREX_BACKEND`).
+pub fn open_backend(name: &str) -> Result<Box<dyn TensorOps>, String> {
+    match name.to_lowercase().as_str() {
+        "cpu" | "cpu_simd" => Ok(Box::new(super::cpu_simd::CpuSimdBackend)),
+        "rocm" if rocm_backend::RocmBackend::is_available() => {
+            Ok(Box::new(rocm_backend::RocmBackend::new()))
+        }
+        "vulkan" if vulkan_backend::VulkanBackend::is_available() => {
+            let backend = vulkan_backend::VulkanBackend::new().map_err(|err| err.to_string())?;
+            Ok(Box::new(backend))
+        }
+        "opencl" if opencl_backend::OpenClBackend::is_available() => Ok(Box::new(
+            opencl_backend::OpenClBackend::new(opencl_backend::DeviceKind::Gpu),
+        )),
+        "sycl" if sycl_backend::SyclBackend::is_available() => {
+            Ok(Box::new(sycl_backend::SyclBackend::new()))
+        }
+        "riscv" if riscv_backend::RiscvBackend::is_available() => {
+            Ok(Box::new(riscv_backend::RiscvBackend::new()))
+        }
+        #[cfg(any(feature = "jit", feature = "jit-cranelift"))]
+        "jit" if super::jit_backend::JitBackend::is_available() => {
+            Ok(Box::new(super::jit_backend::JitBackend::new()?))
+        }
+        "rocm" | "vulkan" | "opencl" | "sycl" | "riscv" | "jit" => {
+            Err(format!("{name} backend is not available"))
+        }
+        _ => Err(format!("unknown backend '{name}'")),
+    }
+}
+
+/// Open the backend called `name` and verify it.
+pub fn verify(name: &str, config: &VerifyConfig) -> Result<ConformanceReport, String> {
+    let ops = open_backend(name)?;
+    Ok(verify_backend(name, ops.as_ref(), config))
+}
+
+/// Verify every backend available on this host.
+pub fn verify_all(config: &VerifyConfig) -> Vec<ConformanceReport> {
+    backend_names()
+        .into_iter()
+        .filter_map(|name| verify(name, config).ok())
+        .collect()
+}
+
+/// Compare every op of `ops` with [`CpuFallback`] on `config.cases` random
+/// cases each.
+pub fn verify_backend(name: &str, ops: &dyn TensorOps, config: &VerifyConfig) -> ConformanceReport {
+    let mut rng = Rng::new(config.seed);
+    let reference = CpuFallback;
+    let ops = OPS
+        .iter()
+        .map(|&op| {
+            let tolerance = config.tolerance(op);
+            let mut report = OpReport {
+                op,
+                cases: config.cases,
+                failures: 0,
+                max_abs_error: 0.0,
+                max_rel_error: 0.0,
+                first_failure: None,
+            };
+            for _ in 0..config.cases {
+                let case = Case::random(op, &mut rng, config.max_dim);
+                let want = case.run(&reference);
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| case.run(ops)));
+                let error = match outcome {
+                    Ok(got) => report.compare(&want, &got, tolerance),
+                    Err(payload) => Some(format!("panicked: {}", panic_message(&*payload))),
+                };
+                if let Some(error) = error {
+                    report.failures += 1;
+                    report
+                        .first_failure
+                        .get_or_insert_with(|| format!("{}: {error}", case.describe()));
+                }
+            }
+            report
+        })
+        .collect();
+    ConformanceReport {
+        backend: name.to_string(),
+        ops,
+    }
+}
+
+/// Randomized inputs of one op.
+enum Case {
+    Matmul {
+        a: Vec<f32>,
+        b: Vec<f32>,
+        m: usize,
+        n: usize,
+        k: usize,
+    },
+    Conv2d {
+        input: Vec<f32>,
+        kernel: Vec<f32>,
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    },
+    Attention {
+        q: Vec<f32>,
+        k: Vec<f32>,
+        v: Vec<f32>,
+        dim: usize,
+    },
+    LayerNorm {
+        x: Vec<f32>,
+        gamma: Vec<f32>,
+        beta: Vec<f32>,
+        eps: f32,
+    },
+}
+
+impl Case {
+    fn random(op: &str, rng: &mut Rng, max_dim: usize) -> Self {
+        match op {
+            "matmul" => {
+                let (m, n, k) = (rng.dim(max_dim), rng.dim(max_dim), rng.dim(max_dim));
+                Case::Matmul {
+                    a: rng.values(m * k),
+                    b: rng.values(k * n),
+                    m,
+                    n,
+                    k,
+                }
+            }
+            "conv2d" => {
+                let (ih, iw) = (rng.dim(max_dim), rng.dim(max_dim));
+                let (kh, kw) = (rng.dim(ih), rng.dim(iw));
+                Case::Conv2d {
+                    input: rng.values(ih * iw),
+                    kernel: rng.values(kh * kw),
+                    input_shape: (ih, iw),
+                    kernel_shape: (kh, kw),
+                }
+            }
+            "attention" => {
+                let dim = rng.dim(max_dim);
+                Case::Attention {
+                    q: rng.values(dim),
+                    k: rng.values(dim),
+                    v: rng.values(dim),
+                    dim,
+                }
+            }
+            _ => {
+                let len = rng.dim(max_dim);
+                Case::LayerNorm {
+                    x: rng.values(len),
+                    gamma: rng.values(len),
+                    beta: rng.values(len),
+                    eps: 1e-5,
+                }
+            }
+        }
+    }
+
+    fn run(&self, ops: &dyn TensorOps) -> Vec<f32> {
+        match self {
+            Case::Matmul { a, b, m, n, k } => ops.matmul(a, b, *m, *n, *k),
+            Case::Conv2d {
+                input,
+                kernel,
+                input_shape,
+                kernel_shape,
+            } => ops.conv2d(input, kernel, *input_shape, *kernel_shape),
+            Case::Attention { q, k, v, dim } => ops.attention(q, k, v, *dim),
+            Case::LayerNorm {
+                x,
+                gamma,
+                beta,
+                eps,
+            } => ops.layer_norm(x, gamma, beta, *eps),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Case::Matmul { m, n, k, .. } => format!("{m}x{k} * {k}x{n}"),
+            Case::Conv2d {
+                input_shape: (ih, iw),
+                kernel_shape: (kh, kw),
+                ..
+            } => format!("{ih}x{iw} input, {kh}x{kw} kernel"),
+            Case::Attention { dim, .. } => format!("dim {dim}"),
+            Case::LayerNorm { x, .. } => format!("len {}", x.len()),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// xorshift64* generator of case shapes and values.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift must not start at zero
+        Rng(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Dimension in `1..=max`.
+    fn dim(&mut self, max: usize) -> usize {
+        1 + (self.next_u64() % max.max(1) as u64) as usize
+    }
+
+    /// `len` values in `[-1, 1)`.
+    fn values(&mut self, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|_| (self.next_u64() >> 40) as f32 / (1u64 << 23) as f32 - 1.0)
+            .collect()
+    }
+}
@@ -8,6 +8,9 @@ use ash::util::read_spv;
 use ash::{vk, Device, Entry, Instance};
 
 use crate::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use crate::hal_backends::device_buffer::{
+    BufferAllocator, BufferError, BufferLocation, DeviceBuffer, DeviceMemory,
+};
 
 // Embedded SPIR-V shader used for all TensorOps kernels. The bytes are generated
 // from a minimal compute shader that defines an empty `main` with a local size of
@@ -43,6 +46,7 @@ const LAYERNORM_SPV: &[u8] = PLACEHOLDER_SPV;
 pub struct VulkanContext {
     entry: Entry,
     instance: Instance,
+    physical: vk::PhysicalDevice,
     device: Device,
     queue: vk::Queue,
     queue_family_index: u32,
@@ -85,6 +89,7 @@ impl VulkanContext {
         Ok(Self {
             entry,
             instance,
+            physical,
             device,
             queue,
             queue_family_index,
@@ -129,6 +134,123 @@ impl VulkanContext {
     }
 }
 
+/// Storage buffer in host visible, coherent memory behind a [`DeviceBuffer`].
+/// The memory stays mapped for its whole lifetime, so transfers are plain
+/// copies.
+struct VulkanMemory {
+    device: Device,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+}
+
+// SAFETY: the mapping is only written through `&mut self` and Vulkan handles
+// may be used from any thread.
+unsafe impl Send for VulkanMemory {}
+unsafe impl Sync for VulkanMemory {}
+
+impl DeviceMemory for VulkanMemory {
+    fn location(&self) -> BufferLocation {
+        BufferLocation::Vulkan
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferError> {
+        // SAFETY: the buffer checked that the range lies in the mapping.
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), self.mapped.add(offset), data.len()) };
+        Ok(())
+    }
+
+    fn read(&self, offset: usize, out: &mut [u8]) -> Result<(), BufferError> {
+        // SAFETY: the buffer checked that the range lies in the mapping.
+        unsafe { std::ptr::copy_nonoverlapping(self.mapped.add(offset), out.as_mut_ptr(), out.len()) };
+        Ok(())
+    }
+}
+
+impl Drop for VulkanMemory {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.unmap_memory(self.memory);
+            self.device.destroy_buffer(self.buffer, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+impl VulkanContext {
+    /// Allocate a zeroed storage buffer of `len` bytes in host visible,
+    /// coherent memory.
+    pub fn alloc_buffer(&self, len: usize) -> Result<DeviceBuffer, BufferError> {
+        let alloc_error = |reason: String| BufferError::Alloc {
+            location: BufferLocation::Vulkan,
+            bytes: len,
+            reason,
+        };
+        let info = vk::BufferCreateInfo::builder()
+            .size(len.max(1) as vk::DeviceSize)
+            .usage(
+                vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { self.device.create_buffer(&info, None) }
+            .map_err(|e| alloc_error(e.to_string()))?;
+        let requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.physical)
+        };
+        let wanted = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let memory_type = (0..properties.memory_type_count).find(|&i| {
+            requirements.memory_type_bits & (1 << i) != 0
+                && properties.memory_types[i as usize]
+                    .property_flags
+                    .contains(wanted)
+        });
+        let Some(memory_type) = memory_type else {
+            unsafe { self.device.destroy_buffer(buffer, None) };
+            return Err(alloc_error("no host visible memory type".into()));
+        };
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type);
+        let memory = match unsafe { self.device.allocate_memory(&alloc_info, None) } {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { self.device.destroy_buffer(buffer, None) };
+                return Err(alloc_error(e.to_string()));
+            }
+        };
+        let mapped = unsafe {
+            self.device
+                .bind_buffer_memory(buffer, memory, 0)
+                .and_then(|()| {
+                    self.device
+                        .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                })
+        };
+        let mapped = match mapped {
+            Ok(mapped) => mapped as *mut u8,
+            Err(e) => {
+                unsafe {
+                    self.device.destroy_buffer(buffer, None);
+                    self.device.free_memory(memory, None);
+                }
+                return Err(alloc_error(e.to_string()));
+            }
+        };
+        unsafe { std::ptr::write_bytes(mapped, 0, len) };
+        let memory = VulkanMemory {
+            device: self.device.clone(),
+            buffer,
+            memory,
+            mapped,
+        };
+        Ok(DeviceBuffer::new(Box::new(memory), len))
+    }
+}
+
 /// Vulkan backend implementing `TensorOps` by dispatching SPIR-V shaders.
 pub struct VulkanBackend {
     ctx: VulkanContext,
@@ -167,6 +289,12 @@ impl VulkanBackend {
     }
 }
 
+impl BufferAllocator for VulkanBackend {
+    fn alloc_buffer(&self, len: usize) -> Result<DeviceBuffer, BufferError> {
+        self.ctx.alloc_buffer(len)
+    }
+}
+
 impl TensorOps for VulkanBackend {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
         self.dispatch(MATMUL_SPV);
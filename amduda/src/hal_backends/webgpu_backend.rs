@@ -0,0 +1,333 @@
+//! WebGPU backend running matmuls as WGSL compute shaders through `wgpu`.
+//!
+//! In browsers the backend drives WebGPU; natively `wgpu` picks Vulkan,
+//! Metal, DX12 or GL.  Results are read back asynchronously, since a browser
+//! only completes buffer mappings once control returns to its event loop:
+//! [`WebGpuBackend::matmul_async`] is the portable entry point, and the
+//! [`TensorOps`] implementation, which blocks until the GPU finishes, is only
+//! available natively.
+//!
+//! Weights that are multiplied repeatedly, like an output projection, are
+//! uploaded once with [`WebGpuBackend::upload`] and stay resident in a
+//! [`GpuTensor`].
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll, Waker};
+
+use anyhow::{anyhow, Context, Result};
+use wgpu::util::DeviceExt;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+
+/// Invocations per workgroup along each output dimension.
+const WORKGROUP: u32 = 8;
+
+const MATMUL_WGSL: &str = r#"
+struct Dims {
+    m: u32,
+    n: u32,
+    k: u32,
+    pad: u32,
+}
+
+@group(0) @binding(0) var<storage, read> a: array<f32>;
+@group(0) @binding(1) var<storage, read> b: array<f32>;
+@group(0) @binding(2) var<storage, read_write> result: array<f32>;
+@group(0) @binding(3) var<uniform> dims: Dims;
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let row = id.y;
+    let col = id.x;
+    if (row >= dims.m || col >= dims.n) {
+        return;
+    }
+    var sum = 0.0;
+    for (var p = 0u; p < dims.k; p = p + 1u) {
+        sum = sum + a[row * dims.k + p] * b[p * dims.n + col];
+    }
+    result[row * dims.n + col] = sum;
+}
+"#;
+
+/// `f32` values resident in GPU memory.
+#[derive(Debug)]
+pub struct GpuTensor {
+    buffer: wgpu::Buffer,
+    len: usize,
+}
+
+impl GpuTensor {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// GPU device and the compiled matmul pipeline.
+#[derive(Debug)]
+pub struct WebGpuBackend {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    matmul: wgpu::ComputePipeline,
+    info: wgpu::AdapterInfo,
+}
+
+impl WebGpuBackend {
+    /// Open the default adapter and compile the kernels.
+    pub async fn request() -> Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .ok_or_else(|| anyhow!("no WebGPU adapter available"))?;
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("amduda"),
+                    required_limits: adapter.limits(),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .context("cannot open the WebGPU device")?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("matmul"),
+            source: wgpu::ShaderSource::Wgsl(MATMUL_WGSL.into()),
+        });
+        let matmul = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("matmul"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Ok(Self {
+            device,
+            queue,
+            matmul,
+            info: adapter.get_info(),
+        })
+    }
+
+    /// Blocking [`request`](Self::request).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new() -> Result<Self> {
+        pollster::block_on(Self::request())
+    }
+
+    /// Whether a WebGPU adapter can run the kernels.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn is_available() -> bool {
+        Self::new().is_ok()
+    }
+
+    /// Adapter the backend runs on.
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.info
+    }
+
+    /// Copy `values` to the GPU.
+    pub fn upload(&self, values: &[f32]) -> GpuTensor {
+        // Empty bindings are invalid; keep one value of padding.
+        let bytes: Vec<u8> = values
+            .iter()
+            .chain(values.is_empty().then_some(&0.0))
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("tensor"),
+                contents: &bytes,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            });
+        GpuTensor {
+            buffer,
+            len: values.len(),
+        }
+    }
+
+    /// Multiply `a` (`m x k`) by `b` (`k x n`).
+    pub async fn matmul_async(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Result<Vec<f32>> {
+        self.matmul_tensor(a, &self.upload(&b[..k * n]), m, n, k)
+            .await
+    }
+
+    /// Multiply `a` (`m x k`) by the resident `b` (`k x n`).
+    pub async fn matmul_tensor(
+        &self,
+        a: &[f32],
+        b: &GpuTensor,
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Result<Vec<f32>> {
+        anyhow::ensure!(
+            b.len >= k * n,
+            "cannot multiply by {k} x {n} weights, only {} are resident",
+            b.len
+        );
+        if m * n == 0 {
+            return Ok(Vec::new());
+        }
+        let a = self.upload(&a[..m * k]);
+        let size = (m * n * 4) as wgpu::BufferAddress;
+        let result = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("matmul result"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("matmul readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let dims: Vec<u8> = [m, n, k, 0]
+            .iter()
+            .flat_map(|&d| (d as u32).to_le_bytes())
+            .collect();
+        let dims = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("matmul dims"),
+                contents: &dims,
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let bindings = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("matmul"),
+            layout: &self.matmul.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: a.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: b.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: result.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: dims.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("matmul"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("matmul"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.matmul);
+            pass.set_bind_group(0, &bindings, &[]);
+            pass.dispatch_workgroups(
+                (n as u32).div_ceil(WORKGROUP),
+                (m as u32).div_ceil(WORKGROUP),
+                1,
+            );
+        }
+        encoder.copy_buffer_to_buffer(&result, 0, &staging, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let slice = staging.slice(..);
+        let mapping = Mapping::default();
+        let state = mapping.0.clone();
+        slice.map_async(wgpu::MapMode::Read, move |outcome| {
+            let mut state = state.lock().expect("mapping state poisoned");
+            state.0 = Some(outcome);
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        });
+        #[cfg(not(target_arch = "wasm32"))]
+        self.device.poll(wgpu::Maintain::Wait);
+        mapping.await.context("cannot read the matmul result")?;
+
+        let values = slice
+            .get_mapped_range()
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        staging.unmap();
+        Ok(values)
+    }
+}
+
+/// Outcome of a `map_async` request and the task waiting for it.
+type MappingState = (Option<Result<(), wgpu::BufferAsyncError>>, Option<Waker>);
+
+/// Future resolving when a buffer mapping completes.
+#[derive(Default)]
+struct Mapping(Arc<Mutex<MappingState>>);
+
+impl Future for Mapping {
+    type Output = Result<(), wgpu::BufferAsyncError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.lock().expect("mapping state poisoned");
+        match state.0.take() {
+            Some(outcome) => Poll::Ready(outcome),
+            None => {
+                state.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TensorOps for WebGpuBackend {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        match pollster::block_on(self.matmul_async(a, b, m, n, k)) {
+            Ok(out) => out,
+            Err(err) => {
+                tracing::warn!("WebGPU matmul failed, running on the CPU: {err:#}");
+                CpuFallback.matmul(a, b, m, n, k)
+            }
+        }
+    }
+
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        CpuFallback.conv2d(input, kernel, input_shape, kernel_shape)
+    }
+
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        CpuFallback.attention(q, k, v, dim)
+    }
+
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        CpuFallback.layer_norm(x, gamma, beta, eps)
+    }
+}
@@ -1,3 +1,12 @@
+//! Without the default `std` feature the crate is `no_std + alloc` and only
+//! contains the tensor ops, the quantizer and the procedural FSM.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 pub mod aurex_lm;
 pub mod amduda_core;
+#[cfg(feature = "std")]
 pub mod hal_backends;
+#[cfg(all(target_arch = "wasm32", feature = "std"))]
+pub mod wasm;
@@ -0,0 +1,158 @@
+//! JavaScript bindings of the [`WebRuntime`] for browser builds.
+//!
+//! ```text
+//! wasm-pack build amduda-wasm --target web -- --features webgpu
+//! ```
+//!
+//! ```js
+//! import init, { Model } from "./pkg/amduda_wasm.js";
+//! await init();
+//! const config = await (await fetch("model.json")).text();
+//! const weights = new Uint8Array(await (await fetch("weights.bin")).arrayBuffer());
+//! const model = new Model(config, weights);
+//! await model.useWebGpu(); // optional, with the `webgpu` feature
+//! console.log(await model.generate("Hello", 32, 0.8, 7));
+//! ```
+//!
+//! Without WebGPU every op runs on the CPU backend, compiled to WebAssembly.
+//! With it, the output projection is uploaded to the GPU once and each token
+//! projects its hidden state there.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::Promise;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
+
+use crate::aurex_lm::sampler::{Sampler, SamplingParams};
+use crate::aurex_lm::tokenizer::{ByteTokenizer, EOS_TOKEN};
+use crate::aurex_lm::web_runtime::WebRuntime;
+#[cfg(feature = "webgpu")]
+use crate::hal_backends::webgpu_backend::{GpuTensor, WebGpuBackend};
+
+fn js_error(err: anyhow::Error) -> JsValue {
+    JsError::new(&format!("{err:#}")).into()
+}
+
+/// Output projection resident on the GPU.
+#[cfg(feature = "webgpu")]
+type Gpu = Rc<(WebGpuBackend, GpuTensor)>;
+
+/// A loaded model.
+#[wasm_bindgen]
+pub struct Model {
+    runtime: Rc<RefCell<WebRuntime>>,
+    #[cfg(feature = "webgpu")]
+    gpu: Rc<RefCell<Option<Gpu>>>,
+}
+
+#[wasm_bindgen]
+impl Model {
+    /// Load a model from its JSON configuration and stored weights.
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: &str, weights: &[u8]) -> Result<Model, JsValue> {
+        let runtime = WebRuntime::from_json(config, weights).map_err(js_error)?;
+        Ok(Model {
+            runtime: Rc::new(RefCell::new(runtime)),
+            #[cfg(feature = "webgpu")]
+            gpu: Rc::default(),
+        })
+    }
+
+    #[wasm_bindgen(getter, js_name = vocabSize)]
+    pub fn vocab_size(&self) -> usize {
+        self.runtime.borrow().vocab_size()
+    }
+
+    #[wasm_bindgen(getter, js_name = contextLength)]
+    pub fn context_len(&self) -> usize {
+        self.runtime.borrow().context_len()
+    }
+
+    /// Forget the context.
+    pub fn reset(&self) {
+        self.runtime.borrow_mut().reset();
+    }
+
+    /// Run the output projection on WebGPU.  Resolves once the weights are
+    /// uploaded.
+    #[cfg(feature = "webgpu")]
+    #[wasm_bindgen(js_name = useWebGpu)]
+    pub fn use_webgpu(&self) -> Promise {
+        let runtime = self.runtime.clone();
+        let gpu = self.gpu.clone();
+        future_to_promise(async move {
+            let backend = WebGpuBackend::request().await.map_err(js_error)?;
+            let projection = backend.upload(runtime.borrow().unembedding());
+            *gpu.borrow_mut() = Some(Rc::new((backend, projection)));
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Continue `prompt` by up to `max_tokens` tokens sampled at
+    /// `temperature` and resolve to the generated text.
+    pub fn generate(
+        &self,
+        prompt: String,
+        max_tokens: usize,
+        temperature: f32,
+        seed: u32,
+    ) -> Promise {
+        let session = Session {
+            runtime: self.runtime.clone(),
+            #[cfg(feature = "webgpu")]
+            gpu: self.gpu.borrow().clone(),
+        };
+        future_to_promise(async move {
+            let params = SamplingParams {
+                temperature,
+                seed: seed.into(),
+                ..SamplingParams::default()
+            };
+            let tokenizer = ByteTokenizer;
+            let mut sampler = Sampler::new(params);
+            let mut logits = Vec::new();
+            for token in tokenizer.encode(&prompt) {
+                logits = session.forward(token).await?;
+            }
+            let mut tokens = Vec::new();
+            while tokens.len() < max_tokens && !logits.is_empty() {
+                let token = sampler.sample(&logits);
+                if token == EOS_TOKEN {
+                    break;
+                }
+                tokens.push(token);
+                logits = session.forward(token).await?;
+            }
+            Ok(JsValue::from_str(&tokenizer.decode(&tokens)))
+        })
+    }
+}
+
+/// Model state shared with a pending generation.
+struct Session {
+    runtime: Rc<RefCell<WebRuntime>>,
+    #[cfg(feature = "webgpu")]
+    gpu: Option<Gpu>,
+}
+
+impl Session {
+    /// Feed `token` and return the logits for the following token.
+    async fn forward(&self, token: u32) -> Result<Vec<f32>, JsValue> {
+        #[cfg(feature = "webgpu")]
+        if let Some(gpu) = &self.gpu {
+            let (backend, projection) = &**gpu;
+            let (h, vocab, hidden) = {
+                let mut runtime = self.runtime.borrow_mut();
+                let h = runtime.hidden_state(token);
+                (h, runtime.vocab_size(), runtime.hidden_size())
+            };
+            return backend
+                .matmul_tensor(&h, projection, 1, vocab, hidden)
+                .await
+                .map_err(js_error);
+        }
+        Ok(self.runtime.borrow_mut().forward(token))
+    }
+}
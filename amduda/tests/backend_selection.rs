@@ -68,3 +68,29 @@ fn selects_riscv_when_requested() {
         assert_eq!(hal_backends::select_backend(), BackendKind::Riscv);
     });
 }
+
+#[cfg(any(feature = "jit", feature = "jit-cranelift"))]
+#[test]
+#[serial]
+fn selects_jit_when_requested() {
+    with_backend_var(Some("jit"), || {
+        if hal_backends::jit_backend::JitBackend::is_available() {
+            assert_eq!(hal_backends::select_backend(), BackendKind::Jit);
+        } else {
+            assert_eq!(hal_backends::select_backend(), BackendKind::CpuSimd);
+        }
+    });
+}
+
+#[cfg(feature = "webgpu")]
+#[test]
+#[serial]
+fn selects_webgpu_when_requested() {
+    with_backend_var(Some("webgpu"), || {
+        if hal_backends::webgpu_backend::WebGpuBackend::is_available() {
+            assert_eq!(hal_backends::select_backend(), BackendKind::WebGpu);
+        } else {
+            assert_eq!(hal_backends::select_backend(), BackendKind::CpuSimd);
+        }
+    });
+}
@@ -0,0 +1,63 @@
+//! Models and generation settings shared by the tests.
+
+// Each test binary uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use amduda::aurex_lm::embeddings::EmbeddingModel;
+use amduda::aurex_lm::generation::GenerationConfig;
+use amduda::aurex_lm::sampler::SamplingParams;
+use amduda::aurex_lm::tiny_lm::TinyLm;
+use aurex_backend::dispatch::CpuBackend;
+
+/// Vocabulary of the byte tokenizer: 256 bytes and EOS.
+pub const VOCAB: usize = 257;
+
+/// Fixed pseudo-random weights of a [`TinyLm`] with `hidden` values per
+/// token; other `seed`s give other models.
+pub fn tiny_lm_weights(hidden: usize, seed: usize) -> Vec<f32> {
+    (0..VOCAB * hidden)
+        .map(|i| (((i + seed) * 37 % 101) as f32 / 50.0) - 1.0)
+        .collect()
+}
+
+/// [`TinyLm`] with `hidden` values per token and the weights of seed 0.
+pub fn tiny_lm(hidden: usize) -> TinyLm {
+    TinyLm::new(
+        &tiny_lm_weights(hidden, 0),
+        VOCAB,
+        hidden,
+        Arc::new(CpuBackend),
+    )
+    .unwrap()
+}
+
+/// [`EmbeddingModel`] with `hidden` values per token and fixed
+/// pseudo-random weights.
+pub fn embedding_model(hidden: usize) -> EmbeddingModel {
+    let weights: Vec<f32> = (0..VOCAB * hidden)
+        .map(|i| ((i * 31 % 97) as f32 / 48.0) - 1.0)
+        .collect();
+    EmbeddingModel::new(&weights, VOCAB, hidden, Arc::new(CpuBackend)).unwrap()
+}
+
+/// Greedy decoding of up to `max_tokens` tokens.
+pub fn greedy(max_tokens: usize) -> GenerationConfig {
+    GenerationConfig {
+        max_tokens,
+        sampling: SamplingParams {
+            temperature: 0.0,
+            ..SamplingParams::default()
+        },
+        ..GenerationConfig::default()
+    }
+}
+
+/// Greedy decoding of exactly `max_tokens` tokens, going on past EOS.
+pub fn greedy_ignoring_eos(max_tokens: usize) -> GenerationConfig {
+    GenerationConfig {
+        stop_at_eos: false,
+        ..greedy(max_tokens)
+    }
+}
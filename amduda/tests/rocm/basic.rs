@@ -1,4 +1,5 @@
-use amduda::hal_backends::rocm_backend::RocmBackend;
+use amduda::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use amduda::hal_backends::rocm_backend::{code_object_key, RocmBackend, KERNELS, KERNEL_NAMES};
 use std::ffi::c_void;
 
 #[test]
@@ -30,3 +31,86 @@ fn kernel_launch_executes_closure() {
     });
     assert!(executed);
 }
+
+#[test]
+fn kernel_source_defines_every_op() {
+    for name in KERNEL_NAMES {
+        let entry = format!("extern \"C\" __global__ void {name}(");
+        assert!(KERNELS.contains(&entry), "{name}");
+    }
+}
+
+#[test]
+fn code_objects_are_cached_per_architecture() {
+    let key = code_object_key("gfx90a:sramecc+:xnack-", "60032830");
+    assert_eq!(key.backend, "rocm");
+    assert_eq!(key, code_object_key("gfx90a:sramecc+:xnack-", "60032830"));
+    assert_ne!(key, code_object_key("gfx1100", "60032830"));
+    assert_ne!(key, code_object_key("gfx90a:sramecc+:xnack-", "60140091"));
+}
+
+#[test]
+fn ops_match_the_cpu() {
+    let backend = RocmBackend::new();
+    assert_eq!(backend.is_native(), backend.arch().is_some());
+    let close = |a: Vec<f32>, b: Vec<f32>| {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(&b) {
+            assert!((x - y).abs() < 1e-4, "{a:?} != {b:?}");
+        }
+    };
+
+    let a: Vec<f32> = (0..20 * 3).map(|i| i as f32 / 7.0).collect();
+    let b: Vec<f32> = (0..3 * 17).map(|i| 1.0 - i as f32 / 11.0).collect();
+    close(
+        backend.matmul(&a, &b, 20, 17, 3),
+        CpuFallback.matmul(&a, &b, 20, 17, 3),
+    );
+    close(
+        backend.conv2d(&a, &b[..4], (6, 10), (2, 2)),
+        CpuFallback.conv2d(&a, &b[..4], (6, 10), (2, 2)),
+    );
+    let x = &a[..];
+    close(
+        backend.attention(x, x, x, x.len()),
+        CpuFallback.attention(x, x, x, x.len()),
+    );
+    let gamma = vec![1.5; x.len()];
+    let beta = vec![0.25; x.len()];
+    close(
+        backend.layer_norm(x, &gamma, &beta, 1e-5),
+        CpuFallback.layer_norm(x, &gamma, &beta, 1e-5),
+    );
+}
+
+#[test]
+fn streams_order_copies_through_events() {
+    let backend = RocmBackend::new();
+    let upload = backend.create_stream().unwrap();
+    let download = backend.create_stream().unwrap();
+    assert_eq!(upload.device().id, download.device().id);
+    let host = [5u32, 6, 7, 8];
+    let mut out = [0u32; 4];
+    unsafe {
+        let ptr = backend.alloc(16);
+        upload
+            .memcpy_htod_async(ptr, host.as_ptr() as *const c_void, 16)
+            .unwrap();
+        let uploaded = upload.record().unwrap();
+        download.wait(&uploaded).unwrap();
+        download
+            .memcpy_dtoh_async(out.as_mut_ptr() as *mut c_void, ptr, 16)
+            .unwrap();
+        let done = download.record().unwrap();
+        done.synchronize().unwrap();
+        assert!(done.query().unwrap());
+        assert!(uploaded.query().unwrap());
+        backend.free(ptr);
+    }
+    assert_eq!(host, out);
+    assert!(download.query().unwrap());
+
+    let mut ran = false;
+    upload.launch(|| ran = true).unwrap();
+    assert!(ran);
+}
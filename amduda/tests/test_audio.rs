@@ -0,0 +1,218 @@
+use std::f32::consts::PI;
+
+use amduda::amduda_core::audio::{Audio, MelConfig, MelSpectrogram};
+use amduda::amduda_core::tensor_ops::{gelu, Conv1dShape, CpuFallback, TensorOps};
+use amduda::aurex_lm::speech::{sinusoids, SpeechFrontEnd};
+use amduda::hal_backends::cpu_simd::CpuSimdBackend;
+
+/// RIFF WAV file of interleaved 16-bit samples.
+fn wav_i16(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let mut out = Vec::new();
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&channels.to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * u32::from(channels) * 2).to_le_bytes());
+    out.extend_from_slice(&(channels * 2).to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    // An unknown chunk of odd length with its pad byte is skipped.
+    out.extend_from_slice(b"LIST");
+    out.extend_from_slice(&3u32.to_le_bytes());
+    out.extend_from_slice(&[1, 2, 3, 0]);
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(&data);
+    out
+}
+
+fn sine(freq: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| (2.0 * PI * freq * i as f32 / sample_rate as f32).sin())
+        .collect()
+}
+
+/// Direct convolution of `in_channels x length` inputs.
+fn naive_conv1d(input: &[f32], weight: &[f32], bias: &[f32], shape: Conv1dShape) -> Vec<f32> {
+    let length = input.len() / shape.in_channels;
+    let out_len = shape.output_len(length);
+    let mut out = Vec::new();
+    for (o, b) in bias.iter().enumerate() {
+        for t in 0..out_len {
+            let mut sum = *b;
+            for c in 0..shape.in_channels {
+                for k in 0..shape.kernel {
+                    let pos = (t * shape.stride + k) as isize - shape.padding as isize;
+                    if pos >= 0 && (pos as usize) < length {
+                        let w = weight[(o * shape.in_channels + c) * shape.kernel + k];
+                        sum += w * input[c * length + pos as usize];
+                    }
+                }
+            }
+            out.push(sum);
+        }
+    }
+    out
+}
+
+fn values(count: usize) -> Vec<f32> {
+    (0..count)
+        .map(|i| ((i * 17 % 23) as f32 - 11.0) / 10.0)
+        .collect()
+}
+
+#[test]
+fn wav_files_decode_to_mono() {
+    let bytes = wav_i16(8000, 2, &[16384, -16384, 32767, 32767, -32768, 0]);
+    let audio = Audio::decode_wav(&bytes).unwrap();
+    assert_eq!(audio.sample_rate, 8000);
+    assert_eq!(audio.samples.len(), 3);
+    assert_eq!(audio.samples[0], 0.0);
+    assert!((audio.samples[1] - 1.0).abs() < 1e-4);
+    assert_eq!(audio.samples[2], -0.5);
+    assert!((audio.duration() - 3.0 / 8000.0).abs() < 1e-9);
+
+    assert!(Audio::decode_wav(b"RIFF\0\0\0\0WAVE").is_err());
+    assert!(Audio::decode_wav(b"not audio").is_err());
+}
+
+#[test]
+fn resampling_interpolates_linearly() {
+    let audio = Audio {
+        sample_rate: 4,
+        samples: vec![0.0, 1.0, 0.0, -1.0],
+    };
+    let up = audio.resample(8);
+    assert_eq!(up.sample_rate, 8);
+    assert_eq!(up.samples, vec![0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]);
+    let down = up.resample(4);
+    assert_eq!(down.samples, audio.samples);
+}
+
+#[test]
+fn log_mel_peaks_at_the_tone_frequency() {
+    let mel = MelSpectrogram::whisper();
+    let samples = sine(1000.0, 16_000, 16_000);
+    let features = mel.compute(&CpuSimdBackend, &samples);
+    let frames = mel.frames(samples.len());
+    assert_eq!(frames, 100);
+    assert_eq!(features.len(), 80 * frames);
+    // Bands are clamped to 80 dB, that is 2.0 after scaling, below the peak.
+    let max = features.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let min = features.iter().copied().fold(f32::INFINITY, f32::min);
+    assert!(max - min <= 2.0 + 1e-6, "{min}..{max}");
+
+    // The loudest band of a middle frame holds 1 kHz.
+    let frame = frames / 2;
+    let band = (0..80)
+        .max_by(|&a, &b| features[a * frames + frame].total_cmp(&features[b * frames + frame]))
+        .unwrap();
+    let bins = mel.bins();
+    let filters = mel.filters();
+    let peak_bin = (0..bins)
+        .max_by(|&a, &b| filters[a * 80 + band].total_cmp(&filters[b * 80 + band]))
+        .unwrap();
+    let peak_hz = peak_bin as f32 * 16_000.0 / 400.0;
+    assert!(
+        (peak_hz - 1000.0).abs() <= 80.0,
+        "band {band} peaks at {peak_hz} Hz"
+    );
+
+    // Every backend computes the same features.
+    let fallback = mel.compute(&CpuFallback, &samples);
+    for (a, b) in features.iter().zip(&fallback) {
+        assert!((a - b).abs() < 1e-3, "{a} != {b}");
+    }
+    let short = MelSpectrogram::new(MelConfig {
+        n_mels: 8,
+        ..MelConfig::whisper()
+    });
+    assert!(short.compute(&CpuSimdBackend, &samples[..100]).is_empty());
+}
+
+#[test]
+fn conv1d_matches_the_direct_convolution() {
+    let shape = Conv1dShape {
+        in_channels: 3,
+        out_channels: 4,
+        kernel: 3,
+        stride: 2,
+        padding: 1,
+    };
+    assert_eq!(shape.output_len(9), 5);
+    assert_eq!(shape.output_len(0), 0);
+    let input = values(3 * 9);
+    let weight = values(4 * 3 * 3);
+    let bias = [0.1, -0.2, 0.3, 0.0];
+    let expected = naive_conv1d(&input, &weight, &bias, shape);
+    for got in [
+        CpuFallback.conv1d(&input, &weight, &bias, shape),
+        CpuSimdBackend.conv1d(&input, &weight, &bias, shape),
+    ] {
+        assert_eq!(got.len(), expected.len());
+        for (g, e) in got.iter().zip(&expected) {
+            assert!((g - e).abs() < 1e-5, "{g} != {e}");
+        }
+    }
+}
+
+#[test]
+fn gelu_uses_the_tanh_approximation() {
+    assert_eq!(gelu(0.0), 0.0);
+    assert!((gelu(1.0) - 0.8412).abs() < 1e-4);
+    assert!((gelu(-1.0) + 0.1588).abs() < 1e-4);
+    assert_eq!(CpuSimdBackend.gelu(&[0.0, 1.0]), vec![0.0, gelu(1.0)]);
+}
+
+#[test]
+fn front_end_halves_the_frame_rate() {
+    let (n_mels, hidden, frames) = (8, 6, 11);
+    let weights = values(SpeechFrontEnd::weight_count(n_mels, hidden));
+    let front_end = SpeechFrontEnd::new(n_mels, hidden, &weights).unwrap();
+    let mel = values(n_mels * frames);
+    let out = front_end.forward(&mel);
+    assert_eq!(SpeechFrontEnd::positions(frames), 6);
+    assert_eq!(out.len(), 6 * hidden);
+
+    // Reference: both convolutions, transposed, plus the positions.
+    let w1 = &weights[..hidden * n_mels * 3];
+    let b1 = &weights[w1.len()..w1.len() + hidden];
+    let w2 = &weights[w1.len() + hidden..w1.len() + hidden + hidden * hidden * 3];
+    let b2 = &weights[w1.len() + hidden + w2.len()..];
+    let conv = |in_channels, stride| Conv1dShape {
+        in_channels,
+        out_channels: hidden,
+        kernel: 3,
+        stride,
+        padding: 1,
+    };
+    let x: Vec<f32> = naive_conv1d(&mel, w1, b1, conv(n_mels, 1))
+        .into_iter()
+        .map(gelu)
+        .collect();
+    let x: Vec<f32> = naive_conv1d(&x, w2, b2, conv(hidden, 2))
+        .into_iter()
+        .map(gelu)
+        .collect();
+    let positions = sinusoids(6, hidden);
+    for t in 0..6 {
+        for h in 0..hidden {
+            let expected = x[h * 6 + t] + positions[t * hidden + h];
+            let got = out[t * hidden + h];
+            assert!((got - expected).abs() < 1e-4, "{got} != {expected}");
+        }
+    }
+    assert_eq!(&positions[..hidden], [0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+
+    let fallback = front_end
+        .clone()
+        .with_backend(std::sync::Arc::new(CpuFallback));
+    for (a, b) in fallback.forward(&mel).iter().zip(&out) {
+        assert!((a - b).abs() < 1e-4);
+    }
+    assert!(SpeechFrontEnd::new(n_mels, hidden, &weights[1..]).is_err());
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use amduda::aurex_lm::batching::{BatchScheduler, DEFAULT_PREFILL_CHUNK};
+use amduda::aurex_lm::generation::{GenerationConfig, GenerationEngine};
+use aurex_runtime::config::AurexConfig;
+use aurex_runtime::{GenerationOptions, Priority, SamplerOptions};
+use common::{greedy_ignoring_eos, tiny_lm};
+use std::collections::HashMap;
+
+#[test]
+fn batched_requests_match_standalone_generation() {
+    let long = "summarise: ".repeat(20);
+    let prompts = ["hi", long.as_str(), "tell me about foxes"];
+    let configs = [
+        greedy_ignoring_eos(12),
+        greedy_ignoring_eos(6),
+        greedy_ignoring_eos(9),
+    ];
+    let mut scheduler = BatchScheduler::new(tiny_lm(8)).with_prefill_chunk(16);
+    let ids: Vec<_> = prompts
+        .iter()
+        .zip(&configs)
+        .map(|(prompt, config)| scheduler.submit(prompt, config))
+        .collect();
+
+    let mut streamed: HashMap<u64, String> = HashMap::new();
+    let outputs: HashMap<_, _> = scheduler
+        .run(|id, event| streamed.entry(id).or_default().push_str(event.text))
+        .into_iter()
+        .collect();
+    assert!(scheduler.is_idle());
+
+    for ((id, prompt), config) in ids.iter().zip(prompts).zip(&configs) {
+        let expected = GenerationEngine::new(tiny_lm(8)).generate(prompt, config, |_| {});
+        let output = &outputs[id];
+        assert_eq!(output.tokens, expected.tokens, "{prompt}");
+        assert_eq!(output.text, expected.text);
+        assert_eq!(output.finish_reason, expected.finish_reason);
+        assert_eq!(output.prompt_tokens, expected.prompt_tokens);
+        assert_eq!(streamed[id], output.text);
+    }
+}
+
+#[test]
+fn long_prompts_are_prefilled_in_chunks_between_decode_steps() {
+    let long = "x".repeat(200);
+    let (short_config, long_config) = (greedy_ignoring_eos(40), greedy_ignoring_eos(4));
+    let mut scheduler = BatchScheduler::new(tiny_lm(8)).with_prefill_chunk(32);
+    let short = scheduler.submit("hello", &short_config);
+    scheduler.step(|_, _| {});
+    let long = scheduler.submit(&long, &long_config);
+
+    // The short request keeps decoding while the long prompt is prefilled.
+    let mut short_tokens = Vec::new();
+    let mut finished = Vec::new();
+    while finished.len() < 2 {
+        for (id, _) in scheduler.step(|id, event| {
+            if id == short && event.token.is_some() {
+                short_tokens.push(event.token);
+            }
+        }) {
+            finished.push(id);
+        }
+    }
+    assert_eq!(finished, [long, short]);
+    assert_eq!(short_tokens.len(), 40);
+
+    let stats = scheduler.stats();
+    assert_eq!(stats.prefill_tokens, 5 + 200);
+    assert_eq!(stats.decode_tokens, 40 + 4);
+    assert!(stats.max_iteration_tokens <= 32 + 2, "{stats:?}");
+    assert!(stats.iterations >= 200 / 32, "{stats:?}");
+}
+
+#[test]
+fn prefill_chunk_comes_from_the_scheduler_config() {
+    let mut config = AurexConfig::default();
+    config.scheduler.prefill_chunk = Some(48);
+    let scheduler = BatchScheduler::from_config(tiny_lm(8), &config);
+    assert_eq!(scheduler.prefill_chunk(), 48);
+
+    let scheduler = BatchScheduler::from_config(tiny_lm(8), &AurexConfig::default());
+    assert_eq!(scheduler.prefill_chunk(), DEFAULT_PREFILL_CHUNK);
+}
+
+#[test]
+fn higher_priority_requests_are_prefilled_first() {
+    let options = GenerationOptions {
+        max_tokens: Some(3),
+        sampler: SamplerOptions {
+            temperature: Some(0.0),
+            ..SamplerOptions::default()
+        },
+        ..GenerationOptions::default()
+    };
+    let low_config = GenerationConfig::from_options(&options.clone().with_priority(Priority::Low));
+    let high_config = GenerationConfig::from_options(&options.with_priority(Priority::High));
+    assert_eq!(high_config.max_tokens, 3);
+    assert_eq!(high_config.sampling.temperature, 0.0);
+    assert_eq!(high_config.priority, Priority::High);
+
+    let mut scheduler = BatchScheduler::new(tiny_lm(8)).with_prefill_chunk(32);
+    let (low_prompt, high_prompt) = ("x".repeat(100), "y".repeat(100));
+    let low = scheduler.submit(&low_prompt, &low_config);
+    let high = scheduler.submit(&high_prompt, &high_config);
+
+    let mut first_tokens = Vec::new();
+    let outputs = scheduler.run(|id, event| {
+        if event.token.is_some() && !first_tokens.contains(&id) {
+            first_tokens.push(id);
+        }
+    });
+    // The more urgent prompt finishes prefilling first although it was
+    // submitted later.
+    assert_eq!(first_tokens, [high, low]);
+    assert_eq!(outputs.len(), 2);
+    assert!(outputs.iter().all(|(_, output)| output.tokens.len() <= 3));
+}
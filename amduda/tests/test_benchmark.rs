@@ -0,0 +1,36 @@
+use amduda::aurex_lm::benchmark::{self, BenchConfig};
+use amduda::aurex_lm::model_loader::load_model;
+use aurex_backend::{Backend, Precision};
+use aurex_runtime::AurexConfig;
+use serial_test::serial;
+
+#[test]
+#[serial]
+fn quantization_shrinks_weights_and_keeps_perplexity_close() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let model = load_model("tests/golden/tiny/config.json").unwrap();
+    let mut runtime = AurexConfig::default();
+    runtime.backend.preferred = Some(Backend::Cpu);
+    let config = BenchConfig {
+        tokens: 4,
+        repeats: 1,
+        ..BenchConfig::default()
+    };
+    let report = benchmark::run(model, &runtime, &config).unwrap();
+    std::env::remove_var("AMDUDA_HAS_GPU");
+
+    assert_eq!(report.backend, Backend::Cpu);
+    let precisions: Vec<Precision> = report.results.iter().map(|r| r.precision).collect();
+    assert_eq!(precisions, config.precisions);
+    for pair in report.results.windows(2) {
+        assert!(pair[1].weight_bytes < pair[0].weight_bytes);
+    }
+    for result in &report.results {
+        let delta = report.perplexity_delta(result).unwrap();
+        assert!(
+            delta.abs() < 0.1,
+            "{:?} changed perplexity by {delta}",
+            result.precision
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use amduda::aurex_lm::bundle::{
+    is_bundle, BundleManifest, CompiledBundle, KernelBlob, KernelKind, BUNDLE_VERSION,
+};
+use amduda::aurex_lm::model_loader::{ModelConfig, Quantization};
+use tempfile::tempdir;
+
+#[test]
+fn bundle_round_trips_through_disk() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("model.aurexc");
+    let bundle = CompiledBundle {
+        manifest: BundleManifest {
+            version: BUNDLE_VERSION,
+            config: ModelConfig {
+                name: "tiny".into(),
+                quantization: Some(Quantization::Int4),
+                scale: Some(0.5),
+                ..ModelConfig::default()
+            },
+            target: "vulkan".into(),
+            weight_count: 6,
+            graph: vec!["matmul_layernorm".into(), "attention".into()],
+            kernels: Vec::new(),
+        },
+        weights: vec![1, 2, 3],
+        kernels: vec![
+            KernelBlob {
+                name: "attention".into(),
+                kind: KernelKind::SpirV,
+                data: vec![3, 2, 0x23, 7],
+            },
+            KernelBlob {
+                name: "matmul_layernorm".into(),
+                kind: KernelKind::SpirV,
+                data: vec![9; 8],
+            },
+        ],
+    };
+    bundle.save(&path).unwrap();
+    assert!(is_bundle(&path));
+
+    let loaded = CompiledBundle::load(&path).unwrap();
+    assert_eq!(loaded.manifest.graph, bundle.manifest.graph);
+    assert_eq!(loaded.manifest.kernels.len(), 2);
+    assert_eq!(loaded.weights, vec![1, 2, 3]);
+    assert_eq!(loaded.kernel("matmul_layernorm").unwrap().data, vec![9; 8]);
+    assert_eq!(loaded.manifest.config.scale, Some(0.5));
+
+    // Truncated files and corrupt lengths are errors, not huge allocations.
+    let mut bytes = std::fs::read(&path).unwrap();
+    assert!(CompiledBundle::read_from(&bytes[..bytes.len() - 3]).is_err());
+    let json_len = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+    let weights_len = 12 + json_len;
+    bytes[weights_len..weights_len + 8].copy_from_slice(&(1u64 << 60).to_le_bytes());
+    assert!(CompiledBundle::read_from(&bytes[..]).is_err());
+}
+
+#[test]
+fn rejects_foreign_files() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("weights.bin");
+    std::fs::write(&path, b"not a bundle at all").unwrap();
+    assert!(!is_bundle(&path));
+    assert!(CompiledBundle::load(&path).is_err());
+}
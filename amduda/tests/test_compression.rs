@@ -0,0 +1,100 @@
+use amduda::amduda_core::compression::Compressor;
+use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use amduda::amduda_core::spill::SpillStore;
+use aurex_runtime::config::{Compression, MemoryConfig};
+use serial_test::serial;
+use tempfile::tempdir;
+
+fn gpu_and_nvme() -> DeviceCapabilities {
+    std::env::set_var("AMDUDA_HAS_GPU", "1");
+    std::env::set_var("AMDUDA_HAS_NVME", "1");
+    DeviceCapabilities::detect()
+}
+
+/// Highly compressible stand-in for a weight tensor.
+fn weights(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i / 64) as u8).collect()
+}
+
+#[test]
+#[serial]
+fn demoted_allocations_are_compressed_and_restored() {
+    for codec in [Compression::Zstd, Compression::Lz4] {
+        let mut mgr = MemoryManager::new_with_limits(gpu_and_nvme(), 4096, 4096, 0)
+            .without_metrics()
+            .with_compressor(Compressor::new(codec));
+        let a = mgr.allocate_data(weights(4096));
+        assert!(!mgr.allocation(a).unwrap().is_compressed());
+
+        // Evicting `a` to the CPU tier compresses it.
+        let b = mgr.allocate_data(weights(4096));
+        let evicted = mgr.allocation(a).unwrap();
+        assert_eq!(evicted.tier, MemoryTier::Cpu);
+        assert!(evicted.is_compressed(), "{codec:?}");
+        assert_eq!(mgr.usage(), (4096, evicted.stored, 0));
+        assert_eq!(mgr.read(a).unwrap(), Some(weights(4096)));
+
+        // Promoting it back decompresses it in place.
+        assert_eq!(mgr.migrate(a, MemoryTier::Gpu), Some(MemoryTier::Gpu));
+        assert!(!mgr.allocation(a).unwrap().is_compressed());
+        assert!(mgr.allocation(b).unwrap().is_compressed());
+        assert_eq!(mgr.read(a).unwrap(), Some(weights(4096)));
+
+        let stats = mgr.compression_stats();
+        assert_eq!(stats.compressed, 2);
+        assert!(stats.ratio() < 0.5, "{codec:?} ratio {}", stats.ratio());
+    }
+}
+
+#[test]
+#[serial]
+fn incompressible_data_is_stored_raw() {
+    let mut mgr = MemoryManager::new_with_limits(gpu_and_nvme(), 256, 1024, 0)
+        .without_metrics()
+        .with_compressor(Compressor::new(Compression::Zstd));
+    // A xorshift sequence does not compress.
+    let mut x = 0x2545_f491_u32;
+    let noise: Vec<u8> = (0..256)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        })
+        .collect();
+    let a = mgr.allocate_data(noise.clone());
+    mgr.allocate(256);
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Cpu));
+    assert!(!mgr.allocation(a).unwrap().is_compressed());
+    assert_eq!(mgr.compression_stats().incompressible, 1);
+    assert_eq!(mgr.read(a).unwrap(), Some(noise));
+}
+
+#[test]
+#[serial]
+fn spill_files_hold_compressed_bytes() {
+    let dir = tempdir().unwrap();
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_HAS_NVME", "1");
+    std::env::remove_var("AMDUDA_COMPRESSION");
+    std::env::remove_var("AMDUDA_COMPRESSION_LEVEL");
+    let config = MemoryConfig {
+        cpu_mem: Some(64),
+        nvme_mem: Some(1 << 20),
+        compression: Some(Compression::Zstd),
+        compression_level: Some(19),
+        ..MemoryConfig::from_env()
+    };
+    let mut mgr = MemoryManager::from_config(&config)
+        .without_metrics()
+        .with_spill_store(SpillStore::new(dir.path()).unwrap());
+
+    let big = mgr.allocate_data(weights(8192));
+    let alloc = mgr.allocation(big).unwrap();
+    assert_eq!(alloc.tier, MemoryTier::Nvme);
+    assert!(alloc.is_compressed());
+    assert_eq!(mgr.usage(), (0, 0, alloc.stored));
+    let on_disk = std::fs::metadata(mgr.spill_store().unwrap().path(big)).unwrap();
+    assert_eq!(on_disk.len() as usize, alloc.stored);
+    assert_eq!(mgr.read(big).unwrap(), Some(weights(8192)));
+}
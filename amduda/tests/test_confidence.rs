@@ -0,0 +1,143 @@
+mod common;
+
+use amduda::aurex_lm::confidence::{ConfidenceLevel, ConfidenceMonitor, ConfidencePolicy};
+use amduda::aurex_lm::generation::{FinishReason, GenerationEngine};
+use amduda::aurex_lm::tiny_lm::LanguageModel;
+use aurex_runtime::{ConfidenceRegulator, Precision, RuntimeEvent};
+use aurex_utils::profiler::Profiler;
+use common::greedy;
+use std::sync::{Arc, Mutex};
+
+/// Model that confidently writes `a` for the first `sure` positions and has
+/// no idea what comes next afterwards, barely preferring `?`.
+struct Fading {
+    sure: usize,
+    context: Vec<u32>,
+}
+
+impl LanguageModel for Fading {
+    fn vocab_size(&self) -> usize {
+        257
+    }
+    fn forward(&mut self, token: u32) -> Vec<f32> {
+        self.context.push(token);
+        let mut logits = vec![0.0; 257];
+        if self.context.len() <= self.sure {
+            logits[b'a' as usize] = 20.0;
+        } else {
+            logits[b'?' as usize] = 0.5;
+        }
+        logits
+    }
+    fn reset(&mut self) {
+        self.context.clear();
+    }
+    fn truncate(&mut self, len: usize) {
+        self.context.truncate(len);
+    }
+    fn context_len(&self) -> usize {
+        self.context.len()
+    }
+}
+
+#[test]
+fn token_stats_follow_the_distribution() {
+    let uniform = vec![0.0; 4];
+    let (logprob, entropy) = ConfidenceMonitor::token_stats(&uniform, 2);
+    assert!((logprob + 4f32.ln()).abs() < 1e-5);
+    assert!((entropy - 4f32.ln()).abs() < 1e-5);
+
+    let certain = [0.0, 50.0, f32::NEG_INFINITY];
+    let (logprob, entropy) = ConfidenceMonitor::token_stats(&certain, 1);
+    assert!(logprob.abs() < 1e-5);
+    assert!(entropy.abs() < 1e-5);
+}
+
+#[test]
+fn collapsed_confidence_aborts_generation() {
+    let profiler = Arc::new(Mutex::new(Profiler::new()));
+    let monitor = Arc::new(
+        ConfidenceMonitor::new(ConfidencePolicy::default()).with_profiler(profiler.clone()),
+    );
+    let mut engine = GenerationEngine::new(Fading {
+        sure: 6,
+        context: Vec::new(),
+    })
+    .with_confidence(monitor.clone());
+
+    let mut streamed = Vec::new();
+    let out = engine.generate_streaming("Q", &greedy(32), |event| {
+        streamed.push((event.token, event.confidence));
+    });
+    assert_eq!(out.finish_reason, FinishReason::LowConfidence);
+    assert!(out.text.starts_with("aaaaaa"));
+    assert!(out.tokens.len() < 32);
+    assert_eq!(monitor.level(), ConfidenceLevel::Collapsed);
+
+    // Every emitted token streamed its confidence; the one that collapsed it
+    // is only in the trace.
+    let trace = monitor.trace();
+    assert_eq!(trace.len(), out.tokens.len() + 1);
+    assert_eq!(streamed.len(), out.tokens.len());
+    for (index, (token, confidence)) in streamed.iter().enumerate() {
+        assert_eq!(*token, Some(out.tokens[index]));
+        assert_eq!(confidence.unwrap(), trace[index]);
+    }
+    assert!(trace[0].perplexity < 1.01);
+    assert!(trace.last().unwrap().perplexity > 64.0);
+    assert!(trace.last().unwrap().entropy > 5.0);
+    assert_eq!(
+        profiler.lock().unwrap().confidence_trace(),
+        trace.as_slice()
+    );
+}
+
+#[test]
+fn confident_generations_run_to_completion() {
+    let monitor = Arc::new(ConfidenceMonitor::new(ConfidencePolicy::default()));
+    let mut engine = GenerationEngine::new(Fading {
+        sure: usize::MAX,
+        context: Vec::new(),
+    })
+    .with_confidence(monitor.clone());
+    let out = engine.generate("Q", &greedy(8), |_| {});
+    assert_eq!(out.text, "aaaaaaaa");
+    assert_eq!(out.finish_reason, FinishReason::Length);
+    assert_eq!(monitor.trace().len(), 8);
+    assert_eq!(monitor.level(), ConfidenceLevel::High);
+
+    // A new generation starts a new trace.
+    engine.generate("Q", &greedy(2), |_| {});
+    assert_eq!(monitor.trace().len(), 2);
+}
+
+#[test]
+fn low_confidence_requests_full_precision() {
+    let monitor = ConfidenceMonitor::new(ConfidencePolicy {
+        warmup: 1,
+        ..ConfidencePolicy::default()
+    });
+    let rt = tokio::runtime::Runtime::new().expect("tokio runtime");
+    rt.block_on(async {
+        let event = RuntimeEvent::TokenEmitted;
+        assert_eq!(monitor.requested_precision(&event).await, None);
+
+        // Uniform over 32 tokens: perplexity 32 is low but not collapsed.
+        monitor.observe(&[0.0; 32], 0);
+        assert_eq!(monitor.level(), ConfidenceLevel::Low);
+        assert_eq!(
+            monitor.requested_precision(&event).await,
+            Some(Precision::F32)
+        );
+        assert_eq!(monitor.regulate(&event).await, event);
+
+        monitor.observe(&[0.0; 257], 0);
+        monitor.observe(&[0.0; 257], 0);
+        assert_eq!(monitor.level(), ConfidenceLevel::Collapsed);
+        assert_eq!(monitor.regulate(&event).await, RuntimeEvent::Error);
+
+        monitor.reset();
+        assert_eq!(monitor.level(), ConfidenceLevel::High);
+        assert!(monitor.trace().is_empty());
+    });
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use amduda::aurex_lm::context_compression::{
+    CompressionPolicy, ContextCompressor, RETRY_AFTER_MESSAGES,
+};
+use amduda::aurex_lm::generation::GenerationEngine;
+use amduda::aurex_lm::tiny_lm::LanguageModel;
+use amduda::aurex_lm::tokenizer::EOS_TOKEN;
+use aurex_runtime::config::SessionConfig;
+use aurex_runtime::session::SUMMARY_PREFIX;
+use aurex_runtime::{Role, Session};
+use common::{greedy, tiny_lm};
+
+/// Model that ends every generation at once.
+#[derive(Clone)]
+struct Silent {
+    seen: usize,
+}
+
+impl LanguageModel for Silent {
+    fn vocab_size(&self) -> usize {
+        257
+    }
+    fn forward(&mut self, _token: u32) -> Vec<f32> {
+        self.seen += 1;
+        let mut logits = vec![0.0; 257];
+        logits[EOS_TOKEN as usize] = 10.0;
+        logits
+    }
+    fn reset(&mut self) {
+        self.seen = 0;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.seen = self.seen.min(len);
+    }
+    fn context_len(&self) -> usize {
+        self.seen
+    }
+}
+
+fn compressor(context_window: usize) -> ContextCompressor {
+    ContextCompressor::new(CompressionPolicy {
+        context_window,
+        compress_at: 1.0,
+        keep_messages: 2,
+        summary_tokens: 8,
+        ..CompressionPolicy::default()
+    })
+}
+
+#[test]
+fn older_turns_are_replaced_by_a_summary() {
+    let mut engine = GenerationEngine::new(tiny_lm(8));
+    let mut session: Session = Session::new(1, Some("Be brief.".into()));
+    for turn in [
+        "Hi there",
+        "Tell me about paged attention",
+        "And the KV cache?",
+    ] {
+        session.push(Role::User, turn);
+        let reply = engine.generate(&session.prompt(), &greedy(6), |_| {});
+        session.push(Role::Assistant, reply.text);
+    }
+    session.push(Role::User, "Thanks");
+    let prompt_len = session.prompt().len();
+
+    // The next turn would overflow the window by one token.
+    let compressor = compressor(prompt_len + 8);
+    assert!(!compressor.needs_compression(&session, 8));
+    assert!(compressor.needs_compression(&session, 9));
+    let compression = compressor.compress(&mut engine, &mut session, 9).unwrap();
+    assert_eq!(compression.summarized, 5);
+    assert_eq!(compression.prompt_tokens_before, prompt_len);
+    assert_eq!(compression.prompt_tokens_after, session.prompt().len());
+    assert!(compression.summary.chars().count() <= 8);
+
+    let messages = session.messages();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[0].role, Role::System);
+    assert_eq!(
+        messages[0].content,
+        format!("{SUMMARY_PREFIX}{}", compression.summary)
+    );
+    assert_eq!(messages[2].content, "Thanks");
+
+    // The summary continued the cached conversation instead of prefilling
+    // it, and the cache now ends with the system prompt.
+    let instruction = CompressionPolicy::default().instruction;
+    let uncached = format!("system: {instruction}\nassistant: ").len();
+    assert!(compression.tokens <= uncached + 8, "{compression:?}");
+    let system = "system: Be brief.\n".len();
+    assert_eq!(engine.kv_cache().len(), system);
+    let next = engine.generate(&session.prompt(), &greedy(2), |_| {});
+    assert_eq!(next.cached_tokens, system);
+}
+
+#[test]
+fn sessions_within_the_limit_are_left_alone() {
+    let mut engine = GenerationEngine::new(tiny_lm(8));
+    let mut session: Session = Session::new(1, None);
+    session.push(Role::User, "Hi");
+    let compressor = compressor(64);
+    assert!(!compressor.needs_compression(&session, 16));
+    assert!(compressor.compress(&mut engine, &mut session, 16).is_none());
+    // Too long, but every message is recent.
+    assert!(compressor.compress(&mut engine, &mut session, 64).is_none());
+    assert_eq!(session.messages().len(), 1);
+
+    assert_eq!(
+        CompressionPolicy::from_config(&SessionConfig::default()),
+        None
+    );
+    let policy = CompressionPolicy::from_config(&SessionConfig {
+        context_window: Some(1000),
+        compress_at: Some(0.5),
+        ..SessionConfig::default()
+    })
+    .unwrap();
+    assert_eq!(policy.limit(), 500);
+    assert_eq!(
+        policy.keep_messages,
+        CompressionPolicy::default().keep_messages
+    );
+}
+
+#[test]
+fn empty_summaries_keep_the_history_and_back_off() {
+    let mut engine = GenerationEngine::new(Silent { seen: 0 });
+    let mut session: Session = Session::new(1, None);
+    for (role, content) in [
+        (Role::User, "Hi there"),
+        (Role::Assistant, "Hello"),
+        (Role::User, "Tell me more"),
+    ] {
+        session.push(role, content);
+    }
+    let history = session.messages().to_vec();
+    let compressor = compressor(16);
+    assert!(compressor.needs_compression(&session, 8));
+    let attempt = compressor.compress(&mut engine, &mut session, 8).unwrap();
+    assert_eq!(attempt.summarized, 0);
+    assert_eq!(attempt.prompt_tokens_after, attempt.prompt_tokens_before);
+    assert_eq!(session.messages(), history);
+    // The prefill of the summary prompt is charged, and the cache goes back
+    // to the transcript the prompt shared with the session.
+    let instruction = CompressionPolicy::default().instruction;
+    let prefix = "user: Hi there\n".len();
+    let prompt = prefix + format!("system: {instruction}\nassistant: ").len();
+    assert_eq!(attempt.tokens, prompt);
+    assert_eq!(engine.kv_cache().len(), prefix);
+
+    // Further turns are not summarized until the session has grown by
+    // `RETRY_AFTER_MESSAGES`.
+    for _ in 0..RETRY_AFTER_MESSAGES {
+        assert!(compressor.compress(&mut engine, &mut session, 8).is_none());
+        session.push(Role::User, "More");
+    }
+    let retried = compressor.compress(&mut engine, &mut session, 8).unwrap();
+    assert_eq!(retried.summarized, 0);
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use amduda::hal_backends::cpu_simd::CpuSimdBackend;
+use amduda::hal_backends::device_buffer::{
+    BufferAllocator, BufferError, BufferLocation, DeviceBuffer, DeviceMemory, HostAllocator,
+};
+use amduda::hal_backends::opencl_backend::{DeviceKind, OpenClBackend};
+use amduda::hal_backends::rocm_backend::RocmBackend;
+use amduda::hal_backends::vulkan_backend::VulkanBackend;
+
+/// Host memory posing as a device, so copies have to be staged.
+struct FakeDevice(Vec<u8>);
+
+impl DeviceMemory for FakeDevice {
+    fn location(&self) -> BufferLocation {
+        BufferLocation::Vulkan
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferError> {
+        self.0[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&self, offset: usize, out: &mut [u8]) -> Result<(), BufferError> {
+        out.copy_from_slice(&self.0[offset..offset + out.len()]);
+        Ok(())
+    }
+}
+
+/// Allocator of a device without memory.
+struct FullDevice;
+
+impl BufferAllocator for FullDevice {
+    fn alloc_buffer(&self, len: usize) -> Result<DeviceBuffer, BufferError> {
+        Err(BufferError::Alloc {
+            location: BufferLocation::Hip,
+            bytes: len,
+            reason: "out of memory".into(),
+        })
+    }
+}
+
+fn gpu_manager() -> MemoryManager {
+    let caps = DeviceCapabilities {
+        has_gpu: true,
+        has_nvme: false,
+        gpu_mem: 16,
+        cpu_mem: 64,
+        nvme_mem: 0,
+    };
+    MemoryManager::new(caps).without_metrics()
+}
+
+#[test]
+fn uploads_are_bounds_checked() {
+    let mut buffer = DeviceBuffer::host(8);
+    buffer.upload(2, &[1, 2, 3]).unwrap();
+    let mut out = [0; 4];
+    buffer.download(1, &mut out).unwrap();
+    assert_eq!(out, [0, 1, 2, 3]);
+
+    let err = buffer.upload(6, &[0; 3]).unwrap_err();
+    assert_eq!(
+        err,
+        BufferError::OutOfBounds {
+            offset: 6,
+            len: 3,
+            size: 8
+        }
+    );
+    assert!(buffer.download(usize::MAX, &mut out).is_err());
+}
+
+#[test]
+fn copies_between_devices_are_staged() {
+    let src = DeviceBuffer::new(Box::new(FakeDevice((0..16).collect())), 16);
+    let mut dst = DeviceBuffer::host(8);
+    dst.copy_from(2, &src, 10, 6).unwrap();
+    assert_eq!(dst.to_vec().unwrap(), [0, 0, 10, 11, 12, 13, 14, 15]);
+
+    let mut device = DeviceBuffer::new(Box::new(FakeDevice(vec![0; 4])), 4);
+    device.copy_from(0, &dst, 4, 4).unwrap();
+    assert_eq!(device.location(), BufferLocation::Vulkan);
+    assert_eq!(device.to_vec().unwrap(), [12, 13, 14, 15]);
+    assert!(device.copy_from(1, &dst, 0, 4).is_err());
+}
+
+#[test]
+fn backends_allocate_zeroed_buffers() {
+    let mut allocators: Vec<Box<dyn BufferAllocator>> = vec![
+        Box::new(CpuSimdBackend),
+        Box::new(RocmBackend::new()),
+        Box::new(OpenClBackend::new(DeviceKind::Gpu)),
+    ];
+    if let Ok(vulkan) = VulkanBackend::new() {
+        allocators.push(Box::new(vulkan));
+    }
+    for allocator in allocators {
+        let mut buffer = allocator.alloc_buffer(12).unwrap();
+        assert_eq!(buffer.to_vec().unwrap(), [0; 12]);
+        let data = 7.5f32.to_le_bytes();
+        buffer.upload(4, &data).unwrap();
+        let mut out = [0; 4];
+        buffer.download(4, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+}
+
+#[test]
+fn gpu_tier_data_lives_in_device_buffers() {
+    let mut mgr = gpu_manager().with_device(Arc::new(HostAllocator));
+    let first = mgr.allocate_data(vec![1; 12]);
+    assert_eq!(mgr.tier(first), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.device_buffer(first).map(DeviceBuffer::len), Some(12));
+    assert_eq!(mgr.read(first).unwrap(), Some(vec![1; 12]));
+
+    // Demoting the allocation brings its bytes back to host memory.
+    let second = mgr.allocate_data(vec![2; 12]);
+    assert_eq!(mgr.tier(first), Some(MemoryTier::Cpu));
+    assert!(mgr.device_buffer(first).is_none());
+    assert!(mgr.device_buffer(second).is_some());
+    assert_eq!(mgr.read(first).unwrap(), Some(vec![1; 12]));
+
+    mgr.migrate(first, MemoryTier::Gpu);
+    assert!(mgr.device_buffer(first).is_some());
+    assert_eq!(mgr.read(first).unwrap(), Some(vec![1; 12]));
+    assert_eq!(mgr.read(second).unwrap(), Some(vec![2; 12]));
+
+    mgr.free(first);
+    assert!(mgr.device_buffer(first).is_none());
+}
+
+#[test]
+fn failed_uploads_keep_data_on_the_host() {
+    let mut mgr = gpu_manager().with_device(Arc::new(FullDevice));
+    let id = mgr.allocate_data(vec![3; 8]);
+    assert_eq!(mgr.tier(id), Some(MemoryTier::Gpu));
+    assert!(mgr.device_buffer(id).is_none());
+    assert_eq!(mgr.read(id).unwrap(), Some(vec![3; 8]));
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use amduda::aurex_lm::embeddings::{EmbeddingModel, Pooling};
+use aurex_backend::dispatch::CpuBackend;
+use common::embedding_model;
+use std::sync::Arc;
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[test]
+fn embeddings_are_unit_vectors() {
+    let model = embedding_model(8);
+    for pooling in [Pooling::Mean, Pooling::Cls] {
+        for text in ["", "a", "retrieval augmented generation"] {
+            let embedding = model.embed(text, pooling);
+            assert_eq!(embedding.len(), 8);
+            let norm = cosine(&embedding, &embedding).sqrt();
+            assert!((norm - 1.0).abs() < 1e-4, "{pooling:?} {text:?}: {norm}");
+        }
+    }
+    assert_eq!(model.token_count("abc"), 4);
+}
+
+#[test]
+fn encoder_attends_in_both_directions() {
+    let model = embedding_model(8);
+    // The state of the first token depends on the tokens after it.
+    let ab = model.encode(&[1, 2]);
+    let ac = model.encode(&[1, 3]);
+    assert_eq!(ab.len(), 16);
+    assert_ne!(ab[..8], ac[..8]);
+
+    let cls_ab = model.embed("ab", Pooling::Cls);
+    assert_ne!(cls_ab, model.embed("ac", Pooling::Cls));
+    assert_ne!(cls_ab, model.embed("ab", Pooling::Mean));
+}
+
+#[test]
+fn batches_match_single_embeddings() {
+    let model = embedding_model(4);
+    let texts = ["first", "second"];
+    let batch = model.embed_batch(&texts, Pooling::Mean);
+    assert_eq!(batch.len(), 2);
+    assert_eq!(batch[0], model.embed("first", Pooling::Mean));
+    assert_eq!(batch[1], model.embed("second", Pooling::Mean));
+    assert!(cosine(&batch[0], &batch[0]) > cosine(&batch[0], &batch[1]));
+}
+
+#[test]
+fn pooling_parses_from_names() {
+    assert_eq!("mean".parse::<Pooling>(), Ok(Pooling::Mean));
+    assert_eq!("CLS".parse::<Pooling>(), Ok(Pooling::Cls));
+    assert!("max".parse::<Pooling>().is_err());
+    assert!(EmbeddingModel::new(&[0.0; 4], 257, 4, Arc::new(CpuBackend)).is_err());
+}
@@ -0,0 +1,94 @@
+use amduda::aurex_lm::evaluation::{perplexity, Perplexity};
+use amduda::aurex_lm::tiny_lm::{LanguageModel, TinyLm};
+use aurex_backend::dispatch::CpuBackend;
+use std::sync::Arc;
+
+/// Model predicting `token + 1` with logit `confidence` and every other
+/// token with logit 0, recording the windows it saw.
+struct Counter {
+    confidence: f32,
+    context: Vec<u32>,
+    resets: usize,
+}
+
+impl Counter {
+    fn new(confidence: f32) -> Self {
+        Self {
+            confidence,
+            context: Vec::new(),
+            resets: 0,
+        }
+    }
+}
+
+impl LanguageModel for Counter {
+    fn vocab_size(&self) -> usize {
+        8
+    }
+    fn forward(&mut self, token: u32) -> Vec<f32> {
+        self.context.push(token);
+        let mut logits = vec![0.0; 8];
+        logits[(token as usize + 1) % 8] = self.confidence;
+        logits
+    }
+    fn reset(&mut self) {
+        self.context.clear();
+        self.resets += 1;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.context.truncate(len);
+    }
+    fn context_len(&self) -> usize {
+        self.context.len()
+    }
+}
+
+#[test]
+fn uniform_model_has_perplexity_of_its_vocabulary() {
+    let mut model = Counter::new(0.0);
+    let result = perplexity(&mut model, &[0, 3, 5, 1, 2], 16);
+    assert_eq!(result.tokens, 4);
+    assert!((result.perplexity() - 8.0).abs() < 1e-4);
+}
+
+#[test]
+fn confident_correct_model_approaches_one() {
+    let mut model = Counter::new(30.0);
+    let result = perplexity(&mut model, &[0, 1, 2, 3, 4, 5], 16);
+    assert!(result.perplexity() < 1.001);
+
+    let mut wrong = Counter::new(30.0);
+    let result = perplexity(&mut wrong, &[0, 2, 4, 6], 16);
+    assert!(result.perplexity() > 1e6);
+}
+
+#[test]
+fn windows_restart_the_context() {
+    let mut model = Counter::new(0.0);
+    let result = perplexity(&mut model, &[0; 10], 4);
+    // Windows of 4, 4 and 2 tokens score all but their first token.
+    assert_eq!(result.tokens, 3 + 3 + 1);
+    assert_eq!(model.resets, 4);
+    assert_eq!(model.context_len(), 0);
+}
+
+#[test]
+fn empty_and_single_token_texts_score_nothing() {
+    let mut model = Counter::new(0.0);
+    assert_eq!(perplexity(&mut model, &[], 8), Perplexity::default());
+    let result = perplexity(&mut model, &[3], 8);
+    assert_eq!(result.tokens, 0);
+    assert_eq!(result.perplexity(), 1.0);
+}
+
+#[test]
+fn scores_tiny_lm() {
+    let weights: Vec<f32> = (0..257 * 4)
+        .map(|i| ((i % 13) as f32 - 6.0) * 0.1)
+        .collect();
+    let mut lm = TinyLm::new(&weights, 257, 4, Arc::new(CpuBackend)).unwrap();
+    let tokens: Vec<u32> = b"hello world".iter().map(|&b| u32::from(b)).collect();
+    let result = perplexity(&mut lm, &tokens, 8);
+    assert_eq!(result.tokens, 7 + 2);
+    assert!(result.perplexity().is_finite() && result.perplexity() > 1.0);
+}
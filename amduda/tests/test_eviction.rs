@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+
+use amduda::amduda_core::eviction::{Clock, Lfu};
+use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use aurex_backend::{Backend, Dispatcher, TensorOps, Workload};
+use aurex_runtime::config::{Eviction, MemoryConfig};
+use serial_test::serial;
+
+fn gpu_manager() -> MemoryManager {
+    std::env::set_var("AMDUDA_HAS_GPU", "1");
+    std::env::set_var("AMDUDA_HAS_NVME", "1");
+    MemoryManager::new_with_limits(DeviceCapabilities::detect(), 64, 256, 256).without_metrics()
+}
+
+#[test]
+#[serial]
+fn lfu_keeps_frequently_used_allocations() {
+    let mut mgr = gpu_manager().with_eviction_policy(Box::new(Lfu::default()));
+    let a = mgr.allocate(32);
+    let b = mgr.allocate(32);
+    mgr.touch(a);
+    mgr.touch(a);
+    mgr.touch(b);
+
+    // `b` was used last but less often than `a`.
+    mgr.allocate(32);
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Cpu));
+}
+
+#[test]
+#[serial]
+fn clock_gives_referenced_allocations_a_second_chance() {
+    let mut mgr = gpu_manager().with_eviction_policy(Box::new(Clock::default()));
+    let a = mgr.allocate(16);
+    let b = mgr.allocate(16);
+    let c = mgr.allocate(16);
+    let d = mgr.allocate(16);
+
+    // The first sweep clears every bit and evicts `a`.  The next sweep
+    // starts after it, finds `b` referenced again and evicts `c` instead.
+    let e = mgr.allocate(16);
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Cpu));
+    mgr.touch(b);
+    mgr.allocate(16);
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(c), Some(MemoryTier::Cpu));
+    assert_eq!(mgr.tier(d), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(e), Some(MemoryTier::Gpu));
+}
+
+#[test]
+#[serial]
+fn policy_is_taken_from_config() {
+    std::env::remove_var("AMDUDA_EVICTION");
+    let config = MemoryConfig {
+        has_gpu: Some(true),
+        gpu_mem: Some(64),
+        eviction: Some(Eviction::Lfu),
+        ..MemoryConfig::default()
+    };
+    let mut mgr = MemoryManager::from_config(&config).without_metrics();
+    let a = mgr.allocate(32);
+    let b = mgr.allocate(32);
+    mgr.touch(a);
+    mgr.touch(b);
+    mgr.touch(a);
+    mgr.allocate(32);
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Cpu));
+}
+
+#[test]
+#[serial]
+fn dispatcher_accesses_update_the_policy() {
+    std::env::remove_var("AUREX_BACKEND");
+    let mgr = Arc::new(Mutex::new(gpu_manager()));
+    let dispatcher =
+        Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_access_observer(mgr.clone());
+
+    let weights = vec![1.0f32; 4];
+    let activations = vec![2.0f32; 4];
+    let (w, x) = {
+        let mut mgr = mgr.lock().unwrap();
+        let w = mgr.allocate(32);
+        let x = mgr.allocate(32);
+        mgr.bind(w, &weights);
+        mgr.bind(x, &activations);
+        (w, x)
+    };
+
+    // Only the weights are read, so the older allocation stays resident.
+    dispatcher.layer_norm(&weights, &[1.0; 4], &[0.0; 4], 1e-5);
+    let mut mgr = mgr.lock().unwrap();
+    mgr.allocate(32);
+    assert_eq!(mgr.tier(w), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(x), Some(MemoryTier::Cpu));
+
+    assert!(mgr.free(w));
+    assert_eq!(mgr.bound(&weights), None);
+}
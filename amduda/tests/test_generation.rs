@@ -0,0 +1,277 @@
+mod common;
+
+use amduda::aurex_lm::generation::{FinishReason, GenerationConfig, GenerationEngine};
+use amduda::aurex_lm::grammar::Grammar;
+use amduda::aurex_lm::sampler::{Sampler, SamplingParams};
+use amduda::aurex_lm::tiny_lm::{LanguageModel, TinyLm};
+use amduda::aurex_lm::tokenizer::{ByteTokenizer, StreamDecoder, EOS_TOKEN};
+use aurex_backend::dispatch::CpuBackend;
+use common::{greedy, tiny_lm, tiny_lm_weights, VOCAB};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Model that always predicts `next` regardless of input.
+struct Fixed {
+    next: u32,
+    seen: usize,
+}
+
+impl LanguageModel for Fixed {
+    fn vocab_size(&self) -> usize {
+        257
+    }
+    fn forward(&mut self, _token: u32) -> Vec<f32> {
+        self.seen += 1;
+        let mut logits = vec![0.0; 257];
+        logits[self.next as usize] = 10.0;
+        logits
+    }
+    fn reset(&mut self) {
+        self.seen = 0;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.seen = self.seen.min(len);
+    }
+    fn context_len(&self) -> usize {
+        self.seen
+    }
+}
+
+/// Model that cycles through `text`, predicting `text[n % len]` after `n`
+/// tokens of context.
+struct Cycle {
+    text: &'static [u8],
+    seen: usize,
+}
+
+impl LanguageModel for Cycle {
+    fn vocab_size(&self) -> usize {
+        257
+    }
+    fn forward(&mut self, _token: u32) -> Vec<f32> {
+        self.seen += 1;
+        let mut logits = vec![0.0; 257];
+        logits[self.text[self.seen % self.text.len()] as usize] = 10.0;
+        logits
+    }
+    fn reset(&mut self) {
+        self.seen = 0;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.seen = self.seen.min(len);
+    }
+    fn context_len(&self) -> usize {
+        self.seen
+    }
+}
+
+#[test]
+fn test_tokenizer_round_trip_and_streaming() {
+    let tok = ByteTokenizer;
+    let ids = tok.encode("héllo");
+    assert_eq!(ids.len(), 6);
+    assert_eq!(tok.decode(&ids), "héllo");
+
+    let mut decoder = StreamDecoder::new();
+    let pieces: Vec<String> = ids.iter().map(|&t| decoder.push(t)).collect();
+    // The two bytes of 'é' are emitted together.
+    assert_eq!(pieces, vec!["h", "", "é", "l", "l", "o"]);
+    assert_eq!(decoder.push(EOS_TOKEN), "");
+    // Partial tokens are buffered the same way.
+    assert_eq!(tok.token_bytes(ids[1]), &[0xc3]);
+    assert_eq!(tok.token_bytes(EOS_TOKEN), &[] as &[u8]);
+    assert_eq!(decoder.push_bytes(&[0xc3]), "");
+    assert_eq!(decoder.push_bytes(&[0xa9, b'!']), "é!");
+}
+
+#[test]
+fn test_sampler_respects_top_p_and_seed() {
+    let logits = [5.0, 4.0, -10.0, -10.0];
+    let params = SamplingParams {
+        temperature: 1.0,
+        top_p: 0.9,
+        seed: 7,
+    };
+    let mut a = Sampler::new(params);
+    let mut b = Sampler::new(params);
+    for _ in 0..100 {
+        let t = a.sample(&logits);
+        assert!(t < 2, "token outside the nucleus");
+        assert_eq!(t, b.sample(&logits));
+    }
+}
+
+#[test]
+fn test_generation_streams_until_limit_or_eos() {
+    let mut engine = GenerationEngine::new(Fixed { next: b'a' as u32, seen: 0 });
+    let mut streamed = String::new();
+    let out = engine.generate("hi", &greedy(5), |t| streamed.push_str(t));
+    assert_eq!(out.text, "aaaaa");
+    assert_eq!(streamed, out.text);
+    assert_eq!(out.prompt_tokens, 2);
+    assert_eq!(out.finish_reason, FinishReason::Length);
+
+    let mut engine = GenerationEngine::new(Fixed { next: EOS_TOKEN, seen: 0 });
+    let out = engine.generate("hi", &greedy(5), |_| {});
+    assert!(out.tokens.is_empty());
+    assert_eq!(out.finish_reason, FinishReason::Eos);
+}
+
+#[test]
+fn test_tiny_lm_is_deterministic_and_caches_context() {
+    let make = || tiny_lm(4);
+
+    let mut lm = make();
+    let logits = lm.forward(b'x' as u32);
+    assert_eq!(logits.len(), VOCAB);
+    lm.forward(b'y' as u32);
+    assert_eq!(lm.context_len(), 2);
+    lm.reset();
+    assert_eq!(lm.context_len(), 0);
+
+    let a = GenerationEngine::new(make()).generate("ab", &greedy(8), |_| {});
+    let b = GenerationEngine::new(make()).generate("ab", &greedy(8), |_| {});
+    assert_eq!(a, b);
+
+    let weights = tiny_lm_weights(4, 0);
+    assert!(TinyLm::new(&weights[..10], VOCAB, 4, Arc::new(CpuBackend)).is_err());
+}
+
+#[test]
+fn test_generation_stops_before_stop_strings() {
+    let mut engine = GenerationEngine::new(Cycle {
+        text: b"abcd",
+        seen: 0,
+    });
+    let config = GenerationConfig {
+        stop: vec!["xyz".into(), "cd".into()],
+        ..greedy(10)
+    };
+    let mut streamed = String::new();
+    let out = engine.generate("wxyz", &config, |t| streamed.push_str(t));
+    assert_eq!(out.text, "ab");
+    assert_eq!(streamed, "ab");
+    assert_eq!(out.tokens.len(), 4);
+    assert_eq!(out.finish_reason, FinishReason::Stop);
+
+    // Text held back as a possible stop string is flushed at the end.
+    let config = GenerationConfig {
+        stop: vec!["da".into()],
+        ..greedy(4)
+    };
+    let mut streamed = String::new();
+    let out = engine.generate("wxyz", &config, |t| streamed.push_str(t));
+    assert_eq!(out.text, "abcd");
+    assert_eq!(streamed, "abcd");
+    assert_eq!(out.finish_reason, FinishReason::Length);
+}
+
+#[test]
+fn test_logit_bias_shifts_and_bans_tokens() {
+    let mut engine = GenerationEngine::new(Fixed { next: b'a' as u32, seen: 0 });
+    let config = GenerationConfig {
+        logit_bias: HashMap::from([(b'a' as u32, f32::NEG_INFINITY), (b'b' as u32, 5.0)]),
+        ..greedy(3)
+    };
+    assert_eq!(engine.generate("hi", &config, |_| {}).text, "bbb");
+}
+
+#[test]
+fn test_grammar_constrains_the_output() {
+    let grammar = Grammar::parse(r#"root ::= "{" [0-9]+ "}""#).unwrap();
+    let mut engine = GenerationEngine::new(Fixed { next: b'a' as u32, seen: 0 });
+    let config = GenerationConfig {
+        grammar: Some(grammar),
+        stop_at_eos: false,
+        ..greedy(10)
+    };
+    let out = engine.generate("hi", &config, |_| {});
+    // Among the equally likely allowed tokens greedy decoding takes the last.
+    assert_eq!(out.text, "{9}");
+    assert_eq!(out.finish_reason, FinishReason::Eos);
+
+    let lm = tiny_lm(4);
+    let grammar =
+        Grammar::parse(r#"root ::= "[" ("true" | "false") ("," ("true" | "false"))* "]""#).unwrap();
+    let config = GenerationConfig {
+        sampling: SamplingParams {
+            temperature: 1.0,
+            top_p: 1.0,
+            seed: 3,
+        },
+        grammar: Some(grammar),
+        max_tokens: 200,
+        ..GenerationConfig::default()
+    };
+    let out = GenerationEngine::new(lm).generate("", &config, |_| {});
+    assert_eq!(out.finish_reason, FinishReason::Eos);
+    let values: Vec<bool> = serde_json::from_str(&out.text).unwrap();
+    assert!(!values.is_empty());
+}
+
+#[test]
+fn test_prompts_reuse_the_cached_prefix() {
+    let make = || tiny_lm(4);
+
+    let mut engine = GenerationEngine::new(make());
+    let first = engine.generate("user: hi\n", &greedy(4), |_| {});
+    assert_eq!(first.cached_tokens, 0);
+    let conversation = engine.kv_cache();
+    assert_eq!(&conversation.tokens()[..9], b"user: hi\n".map(u32::from).as_slice());
+
+    // The next turn only prefills the tokens after the cached prefix and
+    // generates what a fresh engine would.
+    let turn = format!("user: hi\n{}user: more\n", first.text);
+    let second = engine.generate(&turn, &greedy(4), |_| {});
+    assert!(second.cached_tokens >= 9);
+    let fresh = GenerationEngine::new(make()).generate(&turn, &greedy(4), |_| {});
+    assert_eq!(second.text, fresh.text);
+
+    // A restored snapshot continues the earlier conversation.
+    engine.generate("something else", &greedy(4), |_| {});
+    engine.restore_kv_cache(conversation);
+    let again = engine.generate(&turn, &greedy(4), |_| {});
+    assert_eq!(again.cached_tokens, second.cached_tokens);
+    assert_eq!(again.text, second.text);
+}
+
+#[test]
+fn test_token_healing_regenerates_the_last_prompt_token() {
+    let healing = GenerationConfig {
+        token_healing: true,
+        ..greedy(3)
+    };
+
+    // The model wants 'z' everywhere, but the backed-off 'b' has to come
+    // first; it restores the prompt and is not part of the output.
+    let mut engine = GenerationEngine::new(Fixed {
+        next: b'z' as u32,
+        seen: 0,
+    });
+    let output = engine.generate("ab", &healing, |_| {});
+    assert_eq!(output.text, "zzz");
+    assert_eq!(output.tokens, b"zzz".map(u32::from));
+    assert_eq!(output.prompt_tokens, 2);
+    // "a", the regenerated "b" and all but the last "z" were run.
+    assert_eq!(engine.model().seen, 4);
+
+    // A model continuing the prompt text generates the same output with and
+    // without healing.
+    let cycle = || Cycle {
+        text: b"hello world",
+        seen: 0,
+    };
+    let plain = GenerationEngine::new(cycle()).generate("hel", &greedy(3), |_| {});
+    let healed = GenerationEngine::new(cycle()).generate("hel", &healing, |_| {});
+    assert_eq!(healed.text, plain.text);
+    assert_eq!(healed.tokens.len(), 3);
+
+    // A single-token prompt has nothing to back off to.
+    let single = GenerationEngine::new(cycle()).generate("h", &healing, |_| {});
+    assert_eq!(
+        single.text,
+        GenerationEngine::new(cycle())
+            .generate("h", &greedy(3), |_| {})
+            .text
+    );
+}
@@ -0,0 +1,182 @@
+//! End-to-end generation against stored golden outputs.
+//!
+//! The tiny model in `tests/golden/tiny` is loaded, quantized to each
+//! precision, run on every available dispatcher backend and sampled with a
+//! fixed seed.  The generated tokens and their log-probabilities must match
+//! `tests/golden/generation.json`, so numerical changes in kernels, the
+//! quantizer or the sampler show up as a diff.  After an intended change, run
+//! with `AMDUDA_UPDATE_GOLDEN=1` to rewrite the golden outputs.
+
+use amduda::aurex_lm::confidence::{ConfidenceMonitor, ConfidencePolicy};
+use amduda::aurex_lm::generation::{GenerationConfig, GenerationEngine};
+use amduda::aurex_lm::model_loader::{load_model, Quantization};
+use amduda::aurex_lm::sampler::SamplingParams;
+use amduda::aurex_lm::tiny_lm::TinyLm;
+use aurex_backend::{Backend, Dispatcher, Precision, Workload};
+use serde::{Deserialize, Serialize};
+use serial_test::serial;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+const MODEL: &str = "tests/golden/tiny/config.json";
+const GOLDEN: &str = "tests/golden/generation.json";
+const PROMPT: &str = "The quick brown fox";
+const TOKENS: usize = 16;
+/// Allowed drift of a log-probability, well below the gap between the
+/// probabilities of competing tokens.
+const LOGPROB_TOLERANCE: f32 = 1e-4;
+
+const PRECISIONS: [Precision; 4] = [
+    Precision::F32,
+    Precision::Bf16,
+    Precision::Int8,
+    Precision::Int4,
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Golden {
+    tokens: Vec<u32>,
+    logprobs: Vec<f32>,
+}
+
+fn quantization(precision: Precision) -> Option<Quantization> {
+    match precision {
+        Precision::F32 => None,
+        Precision::Bf16 => Some(Quantization::Bf16),
+        Precision::Int8 => Some(Quantization::Int8),
+        Precision::Int4 => Some(Quantization::Int4),
+    }
+}
+
+fn precision_name(precision: Precision) -> &'static str {
+    match precision {
+        Precision::F32 => "f32",
+        Precision::Bf16 => "bf16",
+        Precision::Int8 => "int8",
+        Precision::Int4 => "int4",
+    }
+}
+
+/// Load, quantize and sample the tiny model on `backend` at `precision`.
+fn generate(backend: Backend, precision: Precision) -> Golden {
+    let mut model = load_model(MODEL).unwrap();
+    if let Some(quant) = quantization(precision) {
+        let weights = model.weights_f32().unwrap();
+        model.change_precision(&weights, quant);
+    }
+    let mut dispatcher = Dispatcher::new(Some(backend), Workload::Light);
+    dispatcher.set_precision(precision);
+    let lm = TinyLm::from_model(&model, Arc::new(dispatcher)).unwrap();
+
+    // Never stop early on low confidence, the trace is part of the output.
+    let monitor = Arc::new(ConfidenceMonitor::new(ConfidencePolicy {
+        abort_perplexity: f32::INFINITY,
+        ..ConfidencePolicy::default()
+    }));
+    let mut engine = GenerationEngine::new(lm).with_confidence(monitor.clone());
+    let config = GenerationConfig {
+        max_tokens: TOKENS,
+        sampling: SamplingParams {
+            temperature: 0.7,
+            top_p: 0.95,
+            seed: 1234,
+        },
+        stop_at_eos: false,
+        ..GenerationConfig::default()
+    };
+    let out = engine.generate(PROMPT, &config, |_| {});
+    Golden {
+        tokens: out.tokens,
+        logprobs: monitor.trace().iter().map(|r| r.logprob).collect(),
+    }
+}
+
+/// Describe how `actual` differs from `expected`, if it does.
+fn compare(expected: &Golden, actual: &Golden) -> Option<String> {
+    if expected.tokens != actual.tokens {
+        return Some(format!(
+            "tokens {:?}, expected {:?}",
+            actual.tokens, expected.tokens
+        ));
+    }
+    let drift = expected
+        .logprobs
+        .iter()
+        .zip(&actual.logprobs)
+        .enumerate()
+        .find(|(_, (e, a))| (*e - *a).abs() > LOGPROB_TOLERANCE);
+    match drift {
+        Some((i, (e, a))) => Some(format!("logprob of token {i} is {a}, expected {e}")),
+        None if expected.logprobs.len() != actual.logprobs.len() => Some(format!(
+            "{} logprobs, expected {}",
+            actual.logprobs.len(),
+            expected.logprobs.len()
+        )),
+        None => None,
+    }
+}
+
+#[test]
+#[serial]
+fn generation_matches_golden_outputs() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let update = std::env::var("AMDUDA_UPDATE_GOLDEN").is_ok_and(|v| v == "1");
+    let mut golden: BTreeMap<String, Golden> = match std::fs::read_to_string(GOLDEN) {
+        Ok(json) => serde_json::from_str(&json).unwrap(),
+        Err(_) if update => BTreeMap::new(),
+        Err(err) => panic!("cannot read {GOLDEN}: {err}"),
+    };
+
+    let mut failures = Vec::new();
+    let mut checked = 0;
+    for backend in Dispatcher::available_backends(&[]) {
+        if matches!(backend, Backend::Plugin(_)) {
+            continue;
+        }
+        for precision in PRECISIONS {
+            let key = format!("{backend}/{}", precision_name(precision));
+            let actual = generate(backend, precision);
+            assert_eq!(actual.tokens.len(), TOKENS, "{key}");
+            if update {
+                golden.insert(key, actual);
+                continue;
+            }
+            match golden.get(&key) {
+                Some(expected) => {
+                    checked += 1;
+                    if let Some(diff) = compare(expected, &actual) {
+                        failures.push(format!("{key}: {diff}"));
+                    }
+                }
+                None => eprintln!("no golden output for {key}, skipped"),
+            }
+        }
+    }
+    std::env::remove_var("AMDUDA_HAS_GPU");
+
+    if update {
+        // One case per line keeps diffs of regenerated outputs readable.
+        let cases: Vec<String> = golden
+            .iter()
+            .map(|(key, case)| format!("  {key:?}: {}", serde_json::to_string(case).unwrap()))
+            .collect();
+        std::fs::write(GOLDEN, format!("{{\n{}\n}}\n", cases.join(",\n"))).unwrap();
+        return;
+    }
+    assert!(checked > 0, "no golden output matches an available backend");
+    assert!(
+        failures.is_empty(),
+        "generation differs from {GOLDEN} (rerun with AMDUDA_UPDATE_GOLDEN=1 if intended):\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+#[serial]
+fn generation_is_reproducible() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let first = generate(Backend::Cpu, Precision::Int8);
+    let second = generate(Backend::Cpu, Precision::Int8);
+    std::env::remove_var("AMDUDA_HAS_GPU");
+    assert_eq!(first, second);
+}
@@ -0,0 +1,107 @@
+use amduda::aurex_lm::grammar::{Grammar, GrammarError, GrammarMatcher};
+use amduda::aurex_lm::tokenizer::EOS_TOKEN;
+
+const JSON_NAME: &str = r#"
+# An object with a single string field.
+root   ::= "{" ws "\"name\":" ws string ws "}"
+string ::= "\"" [^"\\]* "\""
+ws     ::= [ \t\n]*
+"#;
+
+fn accepts<'g>(grammar: &'g Grammar, text: &str) -> Option<GrammarMatcher<'g>> {
+    let mut matcher = GrammarMatcher::new(grammar);
+    text.bytes().all(|b| matcher.accept(b)).then_some(matcher)
+}
+
+#[test]
+fn matches_sentences_of_the_grammar() {
+    let grammar = Grammar::parse(JSON_NAME).unwrap();
+    let done = accepts(&grammar, r#"{ "name": "aurex" }"#).unwrap();
+    assert!(done.is_complete());
+    assert!(!done.can_continue());
+
+    let partial = accepts(&grammar, r#"{"name":"au"#).unwrap();
+    assert!(!partial.is_complete());
+    assert!(partial.allows(b'r') && partial.allows(b'"'));
+    assert!(!partial.allows(b'\\'));
+
+    assert!(accepts(&grammar, r#"{"nam"#).is_some());
+    assert!(accepts(&grammar, r#"{"nope"#).is_none());
+    assert!(accepts(&grammar, "[").is_none());
+}
+
+#[test]
+fn repetitions_groups_and_alternatives() {
+    let grammar = Grammar::parse(
+        r#"
+        root  ::= item ("," item)* "."?
+        item  ::= digit+ | "x"
+        digit ::= [0-9]
+        "#,
+    )
+    .unwrap();
+    for text in ["1", "12,x,345", "x."] {
+        assert!(accepts(&grammar, text).unwrap().is_complete(), "{text}");
+    }
+    assert!(!accepts(&grammar, "1,").unwrap().is_complete());
+    assert!(accepts(&grammar, ",").is_none());
+    assert!(accepts(&grammar, "x.x").is_none());
+
+    // Long repetitions keep a bounded number of stacks.
+    let long = "1".repeat(10_000);
+    assert!(accepts(&grammar, &long).unwrap().is_complete());
+}
+
+#[test]
+fn rejected_bytes_leave_the_state_unchanged() {
+    let grammar = Grammar::parse(r#"root ::= "ab" | "ac""#).unwrap();
+    let mut matcher = GrammarMatcher::new(&grammar);
+    assert!(matcher.accept(b'a'));
+    assert!(!matcher.accept(b'd'));
+    assert!(matcher.accept_token(b'c' as u32));
+    assert!(matcher.accept_token(EOS_TOKEN));
+}
+
+#[test]
+fn masks_disallowed_tokens() {
+    let grammar = Grammar::parse(r#"root ::= [ab] "c"?"#).unwrap();
+    let mut matcher = GrammarMatcher::new(&grammar);
+    let mut logits = vec![0.0; 258];
+    matcher.mask(&mut logits);
+    let allowed: Vec<usize> = (0..logits.len()).filter(|&t| logits[t] == 0.0).collect();
+    assert_eq!(allowed, [b'a' as usize, b'b' as usize]);
+
+    matcher.accept(b'b');
+    let mut logits = vec![0.0; 258];
+    matcher.mask(&mut logits);
+    let allowed: Vec<usize> = (0..logits.len()).filter(|&t| logits[t] == 0.0).collect();
+    assert_eq!(allowed, [b'c' as usize, EOS_TOKEN as usize]);
+}
+
+#[test]
+fn invalid_grammars_are_rejected() {
+    assert_eq!(
+        Grammar::parse(r#"start ::= "a""#),
+        Err(GrammarError::MissingRoot)
+    );
+    assert_eq!(
+        Grammar::parse("root ::= value"),
+        Err(GrammarError::UndefinedRule("value".into()))
+    );
+    assert_eq!(
+        Grammar::parse("root ::= root \"a\" | \"b\""),
+        Err(GrammarError::LeftRecursion("root".into()))
+    );
+    assert_eq!(
+        Grammar::parse("root ::= \"a\"\nroot ::= \"b\""),
+        Err(GrammarError::DuplicateRule("root".into()))
+    );
+    assert!(matches!(
+        Grammar::parse(r#"root ::= "a"#),
+        Err(GrammarError::Syntax { .. })
+    ));
+    assert!(matches!(
+        Grammar::parse("root ::= [z-a]"),
+        Err(GrammarError::Syntax { .. })
+    ));
+}
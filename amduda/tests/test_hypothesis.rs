@@ -0,0 +1,186 @@
+use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use amduda::aurex_lm::generation::{FinishReason, GenerationConfig};
+use amduda::aurex_lm::hypothesis::{BranchPolicy, BranchSearch, Recompute};
+use amduda::aurex_lm::sampler::SamplingParams;
+use amduda::aurex_lm::tiny_lm::{LanguageModel, TinyLm, KV_PAGE_TOKENS};
+use aurex_backend::dispatch::CpuBackend;
+use aurex_runtime::config::Watermarks;
+use aurex_runtime::{EffortBudget, EffortCaps, HypothesisManager, RuntimeEvent};
+use std::sync::Arc;
+
+/// Model slightly preferring `a` after the prompt, but only confident in how
+/// to continue after `b`.
+#[derive(Clone)]
+struct Trap {
+    context: Vec<u32>,
+    prompt_len: usize,
+}
+
+impl LanguageModel for Trap {
+    fn vocab_size(&self) -> usize {
+        257
+    }
+    fn forward(&mut self, token: u32) -> Vec<f32> {
+        self.context.push(token);
+        let mut logits = vec![-30.0; 257];
+        match self.context.get(self.prompt_len).map(|&t| t as u8) {
+            None => {
+                logits[b'a' as usize] = 2.0;
+                logits[b'b' as usize] = 1.8;
+            }
+            Some(b'b') => logits[b'b' as usize] = 10.0,
+            _ => (b'c'..=b'z').for_each(|t| logits[t as usize] = 0.0),
+        }
+        logits
+    }
+    fn reset(&mut self) {
+        self.context.clear();
+    }
+    fn truncate(&mut self, len: usize) {
+        self.context.truncate(len);
+    }
+    fn context_len(&self) -> usize {
+        self.context.len()
+    }
+}
+
+fn sampled(max_tokens: usize, seed: u64) -> GenerationConfig {
+    GenerationConfig {
+        max_tokens,
+        sampling: SamplingParams {
+            temperature: 1.0,
+            top_p: 1.0,
+            seed,
+        },
+        ..GenerationConfig::default()
+    }
+}
+
+#[test]
+fn clones_share_kv_pages_until_written() {
+    let weights: Vec<f32> = (0..257 * 4).map(|i| (i % 11) as f32 / 11.0).collect();
+    let mut model = TinyLm::new(&weights, 257, 4, Arc::new(CpuBackend)).unwrap();
+    for token in 0..(KV_PAGE_TOKENS + 2) as u32 {
+        model.forward(token);
+    }
+    let mut fork = model.clone();
+    assert_eq!(fork.shared_kv_pages(&model), 2);
+
+    // Writing to the partially filled page copies it, the full one stays shared.
+    let a = fork.forward(7);
+    assert_eq!(fork.shared_kv_pages(&model), 1);
+    assert_eq!((model.context_len(), fork.context_len()), (18, 19));
+    assert_eq!(model.forward(7), a);
+
+    model.truncate(3);
+    assert_eq!(model.context_len(), 3);
+    assert_eq!(fork.shared_kv_pages(&model), 0);
+}
+
+#[test]
+fn search_commits_the_most_confident_branch() {
+    let search = BranchSearch::new(BranchPolicy {
+        width: 4,
+        beam: 2,
+        segment: 3,
+    });
+    let mut model = Trap {
+        context: Vec::new(),
+        prompt_len: 2,
+    };
+    let out = search.search(&mut model, "Q:", &sampled(6, 1), &EffortBudget::default());
+    assert_eq!(out.text, "bbbbbb");
+    assert_eq!(out.finish_reason, FinishReason::Length);
+    assert_eq!(out.prompt_tokens, 2);
+    assert_eq!(model.context_len(), 2 + 6);
+
+    // 4 children in the first round, 2 x 4 in the second.
+    let stats = search.stats();
+    assert_eq!(stats.rounds, 2);
+    assert_eq!(stats.forks, 4 + 8);
+    assert_eq!(stats.pruned, 2 + 6);
+}
+
+#[test]
+fn effort_budget_limits_branches() {
+    let search = BranchSearch::new(BranchPolicy::default());
+    let budget = EffortBudget::new(EffortCaps {
+        max_branches: Some(1),
+        ..EffortCaps::default()
+    });
+    let mut model = Trap {
+        context: Vec::new(),
+        prompt_len: 2,
+    };
+    let out = search.search(&mut model, "Q:", &sampled(16, 0), &budget);
+    assert_eq!(out.tokens.len(), 16);
+    let stats = search.stats();
+    assert_eq!(stats.forks, stats.rounds);
+    assert_eq!(stats.pruned, 0);
+
+    let rt = tokio::runtime::Runtime::new().expect("tokio runtime");
+    let event = RuntimeEvent::TokenEmitted;
+    assert_eq!(rt.block_on(search.manage(&event)), event);
+}
+
+#[test]
+fn recomputed_branches_match_kept_ones() {
+    let weights: Vec<f32> = (0..257 * 4).map(|i| (i % 13) as f32 / 13.0).collect();
+    let model = TinyLm::new(&weights, 257, 4, Arc::new(CpuBackend)).unwrap();
+    let policy = BranchPolicy {
+        width: 3,
+        beam: 2,
+        segment: 4,
+    };
+    let config = sampled(12, 5);
+
+    let mut kept = model.clone();
+    let search = BranchSearch::new(policy);
+    let expected = search.search(&mut kept, "hi", &config, &EffortBudget::default());
+    assert_eq!(
+        (search.stats().discarded, search.stats().recomputed),
+        (0, 0)
+    );
+
+    let mut recomputed = model.clone();
+    let search = BranchSearch::new(policy).with_recompute(Recompute::always());
+    let out = search.search(&mut recomputed, "hi", &config, &EffortBudget::default());
+    assert_eq!(out.tokens, expected.tokens);
+    assert_eq!(recomputed.context_len(), kept.context_len());
+    assert_eq!(recomputed.forward(1), kept.forward(1));
+    // Both survivors of each of the three rounds are dropped; the second and
+    // third rounds and the commit replay 4, 8 and 12 tokens per branch.
+    let stats = search.stats();
+    assert_eq!(stats.discarded, 2 * 3);
+    assert_eq!(stats.recomputed, 2 * 4 + 2 * 8 + 12);
+}
+
+#[test]
+fn memory_pressure_turns_recomputation_on() {
+    let caps = DeviceCapabilities {
+        has_gpu: false,
+        has_nvme: false,
+        gpu_mem: 0,
+        cpu_mem: 100,
+        nvme_mem: 0,
+    };
+    let recompute = Recompute::new();
+    let search = BranchSearch::default().with_recompute(recompute.clone());
+    let mut mgr = MemoryManager::new(caps)
+        .without_metrics()
+        .with_watermarks(
+            MemoryTier::Cpu,
+            Watermarks {
+                high: 0.8,
+                low: 0.5,
+            },
+        )
+        .with_pressure_handler(recompute.pressure_handler());
+
+    mgr.allocate(60);
+    assert!(!search.recompute().is_active());
+    mgr.allocate(30);
+    assert!(search.recompute().is_active());
+    assert_eq!(mgr.usage(), (0, 90, 0));
+    assert_eq!(mgr.pressure_stats().released, 0);
+}
@@ -1,6 +1,12 @@
-#![cfg(feature = "jit")]
+#![cfg(any(feature = "jit", feature = "jit-cranelift"))]
 
-use amduda::amduda_core::jit_compiler::{compile_kernel, compile_kernel_f32, Device};
+use amduda::amduda_core::jit_compiler::{
+    compile_kernel, compile_kernel_f32, compile_layer_norm, compile_layer_norm_for, compile_matmul,
+    compile_matmul_for, Device,
+};
+use amduda::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use amduda::hal_backends::jit_backend::JitBackend;
+use aurex_backend::KernelShapes;
 
 #[test]
 fn cpu_add_kernel_executes() {
@@ -34,3 +40,82 @@ fn f32_kernel_executes() {
         assert_eq!(result, 6.0);
     }
 }
+
+#[test]
+fn matmul_kernel_matches_cpu_fallback() {
+    if let Ok(k) = compile_matmul(Device::CPU) {
+        // Odd sizes exercise the scalar remainder of the vectorized loop.
+        let (m, n, k_dim) = (3, 37, 5);
+        let a: Vec<f32> = (0..m * k_dim).map(|i| i as f32 * 0.5 - 3.0).collect();
+        let b: Vec<f32> = (0..k_dim * n).map(|i| (i % 7) as f32 - 2.5).collect();
+        assert_eq!(
+            k.run(&a, &b, m, n, k_dim),
+            CpuFallback.matmul(&a, &b, m, n, k_dim)
+        );
+        assert!(k.run(&[], &[], 0, 4, 0).is_empty());
+    }
+}
+
+#[test]
+fn layer_norm_kernel_matches_cpu_fallback() {
+    if let Ok(k) = compile_layer_norm(Device::CPU) {
+        let x: Vec<f32> = (0..19).map(|i| (i * i % 11) as f32).collect();
+        let gamma = vec![1.5; 19];
+        let beta = vec![-0.25; 19];
+        let expected = CpuFallback.layer_norm(&x, &gamma, &beta, 1e-5);
+        for (got, want) in k.run(&x, &gamma, &beta, 1e-5).iter().zip(&expected) {
+            assert!((got - want).abs() < 1e-5, "{got} != {want}");
+        }
+    }
+}
+
+#[test]
+fn jit_backend_runs_compiled_kernels() {
+    if let Ok(backend) = JitBackend::new() {
+        let out = backend.matmul(&[1.0, 2.0, 3.0, 4.0], &[5.0, 6.0, 7.0, 8.0], 2, 2, 2);
+        assert_eq!(out, vec![19.0, 22.0, 43.0, 50.0]);
+        let norm = backend.layer_norm(&[1.0, 3.0], &[1.0, 1.0], &[0.0, 0.0], 0.0);
+        assert_eq!(norm, vec![-1.0, 1.0]);
+    }
+}
+
+#[test]
+fn specialized_kernels_match_generic_ones() {
+    // Small dimensions are unrolled by Cranelift, n = 37 keeps its loop.
+    for (m, n, k_dim) in [(3, 4, 5), (2, 37, 3)] {
+        if let Ok(k) = compile_matmul_for(n, k_dim, Device::CPU) {
+            let a: Vec<f32> = (0..m * k_dim).map(|i| i as f32 * 0.5 - 3.0).collect();
+            let b: Vec<f32> = (0..k_dim * n).map(|i| (i % 7) as f32 - 2.5).collect();
+            assert_eq!(k.dims, Some((n, k_dim)));
+            assert_eq!(
+                k.run(&a, &b, m, n, k_dim),
+                CpuFallback.matmul(&a, &b, m, n, k_dim)
+            );
+        }
+    }
+    if let (Ok(fixed), Ok(generic)) = (
+        compile_layer_norm_for(6, Device::CPU),
+        compile_layer_norm(Device::CPU),
+    ) {
+        let x = [1.0, -2.0, 0.5, 4.0, 3.0, -1.5];
+        let (gamma, beta) = ([2.0; 6], [0.5; 6]);
+        assert_eq!(
+            fixed.run(&x, &gamma, &beta, 1e-5),
+            generic.run(&x, &gamma, &beta, 1e-5)
+        );
+    }
+}
+
+#[test]
+fn jit_backend_uses_kernels_specialized_for_model_shapes() {
+    let shapes = KernelShapes::new().with_matmul(2, 2).with_norm(2);
+    if let Ok(backend) = JitBackend::new().and_then(|b| b.with_shapes(&shapes)) {
+        let out = backend.matmul(&[1.0, 2.0, 3.0, 4.0], &[5.0, 6.0, 7.0, 8.0], 2, 2, 2);
+        assert_eq!(out, vec![19.0, 22.0, 43.0, 50.0]);
+        // Other shapes fall back to the generic kernel.
+        let out = backend.matmul(&[1.0, 2.0], &[3.0, 4.0], 2, 1, 1);
+        assert_eq!(out, vec![3.0, 6.0]);
+        let norm = backend.layer_norm(&[1.0, 3.0], &[1.0, 1.0], &[0.0, 0.0], 0.0);
+        assert_eq!(norm, vec![-1.0, 1.0]);
+    }
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use amduda::aurex_lm::generation::{FinishReason, GenerationConfig, GenerationEngine};
+use amduda::aurex_lm::grammar::GrammarMatcher;
+use amduda::aurex_lm::json_schema::{JsonSchema, SchemaError};
+use amduda::aurex_lm::sampler::SamplingParams;
+use common::tiny_lm;
+use serde_json::{json, Value};
+
+/// Whether `text` is a complete sentence of the compiled schema.
+fn valid(schema: &JsonSchema, text: &str) -> bool {
+    let mut matcher = GrammarMatcher::new(schema.grammar());
+    text.bytes().all(|b| matcher.accept(b)) && matcher.is_complete()
+}
+
+#[test]
+fn objects_require_their_required_properties() {
+    let schema = JsonSchema::compile(&json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string" },
+            "age": { "type": "integer" },
+            "tags": { "type": "array", "items": { "type": "string" } }
+        },
+        "required": ["name"]
+    }))
+    .unwrap();
+    assert!(valid(&schema, r#"{"name":"aurex"}"#));
+    assert!(valid(&schema, r#"{ "age": 3, "name": "a\"b" }"#));
+    assert!(valid(&schema, r#"{"age":-12,"name":"x","tags":["a","b"]}"#));
+    assert!(!valid(&schema, r#"{"age":3}"#));
+    assert!(!valid(&schema, r#"{"name":"x",}"#));
+    assert!(!valid(&schema, r#"{"name":3}"#));
+    assert!(!valid(&schema, r#"{"name":"x","other":1}"#));
+}
+
+#[test]
+fn arrays_enums_and_unions() {
+    let schema = JsonSchema::compile(&json!({
+        "type": "array",
+        "items": { "anyOf": [{ "enum": ["red", "green"] }, { "type": ["number", "null"] }] },
+        "minItems": 1,
+        "maxItems": 3
+    }))
+    .unwrap();
+    assert!(valid(&schema, r#"["red"]"#));
+    assert!(valid(&schema, r#"["green", 1.5e3, null]"#));
+    assert!(!valid(&schema, "[]"));
+    assert!(!valid(&schema, r#"["red","red","red","red"]"#));
+    assert!(!valid(&schema, r#"["blue"]"#));
+
+    let constant = JsonSchema::compile(&json!({ "const": { "kind": "ping" } })).unwrap();
+    assert!(valid(&constant, r#"{"kind":"ping"}"#));
+    assert!(!valid(&constant, r#"{"kind":"pong"}"#));
+}
+
+#[test]
+fn references_may_be_recursive() {
+    let schema = JsonSchema::compile(&json!({
+        "$ref": "#/$defs/node",
+        "$defs": {
+            "node": {
+                "type": "object",
+                "properties": {
+                    "value": { "type": "integer" },
+                    "children": { "type": "array", "items": { "$ref": "#/$defs/node" } }
+                },
+                "required": ["value"]
+            }
+        }
+    }))
+    .unwrap();
+    assert!(valid(
+        &schema,
+        r#"{"children":[{"value":2},{"children":[],"value":3}],"value":1}"#
+    ));
+    assert!(!valid(&schema, r#"{"children":[{}],"value":1}"#));
+    assert!(schema.gbnf().contains("node ::="));
+}
+
+#[test]
+fn unsupported_schemas_are_rejected() {
+    assert!(matches!(
+        JsonSchema::compile(&json!({ "$ref": "#/$defs/missing" })),
+        Err(SchemaError::InvalidRef(_))
+    ));
+    assert!(matches!(
+        JsonSchema::compile(&json!({ "type": "tuple" })),
+        Err(SchemaError::Unsupported(_))
+    ));
+    assert!(matches!(
+        JsonSchema::compile(&json!({ "type": "array", "minItems": 3, "maxItems": 2 })),
+        Err(SchemaError::Unsupported(_))
+    ));
+    // An empty schema accepts any JSON value.
+    let any = JsonSchema::compile(&json!({})).unwrap();
+    assert!(valid(&any, r#"[1, {"a": true}, "x"]"#));
+}
+
+#[test]
+fn generation_follows_the_schema() {
+    let schema = JsonSchema::compile(&json!({
+        "type": "object",
+        "properties": {
+            "ok": { "type": "boolean" },
+            "color": { "enum": ["red", "green"] }
+        },
+        "required": ["ok", "color"]
+    }))
+    .unwrap();
+    for seed in 0..4 {
+        let lm = tiny_lm(4);
+        let config = GenerationConfig {
+            max_tokens: 64,
+            sampling: SamplingParams {
+                temperature: 1.0,
+                top_p: 1.0,
+                seed,
+            },
+            schema: Some(schema.clone()),
+            ..GenerationConfig::default()
+        };
+        let out = GenerationEngine::new(lm).generate("", &config, |_| {});
+        assert_eq!(out.finish_reason, FinishReason::Eos);
+        let value: Value = serde_json::from_str(&out.text).unwrap();
+        assert!(value["ok"].is_boolean(), "{}", out.text);
+        assert!(["red", "green"].contains(&value["color"].as_str().unwrap()));
+    }
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use amduda::aurex_lm::generation::{GenerationEngine, KvCache};
+use amduda::aurex_lm::kv_snapshot::{KvEncoding, KvSaveOptions, KvState};
+use amduda::aurex_lm::tiny_lm::TinyLm;
+use aurex_backend::dispatch::CpuBackend;
+use aurex_runtime::config::Compression;
+use common::{greedy_ignoring_eos, tiny_lm_weights, VOCAB};
+use std::sync::Arc;
+use tempfile::tempdir;
+
+fn model(seed: usize) -> TinyLm {
+    let weights = tiny_lm_weights(8, seed);
+    TinyLm::new(&weights, VOCAB, 8, Arc::new(CpuBackend)).unwrap()
+}
+
+/// Engine that has processed a conversation longer than one cache page.
+fn conversation() -> (GenerationEngine<TinyLm>, String) {
+    let mut engine = GenerationEngine::new(model(0));
+    let prompt = "user: tell me a long story about foxes\n";
+    let first = engine.generate(prompt, &greedy_ignoring_eos(8), |_| {});
+    (engine, format!("{prompt}{}user: go on\n", first.text))
+}
+
+#[test]
+fn saved_cache_resumes_the_session() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("session.aurexkv");
+    let (mut engine, turn) = conversation();
+    let cache = engine.kv_cache();
+    cache.save(&path).unwrap();
+
+    let expected = engine.generate(&turn, &greedy_ignoring_eos(8), |_| {});
+    let loaded = KvCache::load(&path, model(0)).unwrap();
+    assert_eq!(loaded.tokens(), cache.tokens());
+    assert_eq!(loaded.model().export_kv(), cache.model().export_kv());
+
+    let mut resumed = GenerationEngine::new(model(0));
+    resumed.restore_kv_cache(loaded);
+    let output = resumed.generate(&turn, &greedy_ignoring_eos(8), |_| {});
+    assert_eq!(output.cached_tokens, expected.cached_tokens);
+    assert_eq!(output.text, expected.text);
+}
+
+#[test]
+fn quantized_and_compressed_snapshots_are_smaller() {
+    let dir = tempdir().unwrap();
+    let (engine, _) = conversation();
+    let cache = engine.kv_cache();
+    let (exact, _) = cache.model().export_kv();
+    let size = |name: &str, options: KvSaveOptions| {
+        let path = dir.path().join(name);
+        cache.save_with(&path, &options).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        let (values, _) = KvCache::load(&path, model(0)).unwrap().model().export_kv();
+        (len, values)
+    };
+
+    let (f32_len, values) = size("f32", KvSaveOptions::default());
+    assert_eq!(values, exact);
+    for (encoding, tolerance) in [
+        (KvEncoding::Bf16, 1.0 / 128.0),
+        (KvEncoding::Int8, 1.0 / 64.0),
+    ] {
+        for compression in [None, Some(Compression::Zstd), Some(Compression::Lz4)] {
+            let options = KvSaveOptions {
+                encoding,
+                compression,
+            };
+            let (len, values) = size(&format!("{encoding:?}-{compression:?}"), options);
+            assert!(len < f32_len, "{options:?}: {len} >= {f32_len} bytes");
+            for (a, b) in exact.iter().zip(&values) {
+                assert!(
+                    (a - b).abs() <= tolerance,
+                    "{options:?}: {a} restored as {b}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn snapshots_only_load_into_their_model() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("session.aurexkv");
+    let (engine, _) = conversation();
+    engine.kv_cache().save(&path).unwrap();
+
+    let err = KvCache::load(&path, model(1)).err().unwrap();
+    assert!(format!("{err:#}").contains("saved from model"), "{err:#}");
+}
+
+#[test]
+fn corrupt_snapshots_are_rejected() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("session.aurexkv");
+    let (engine, _) = conversation();
+    engine.kv_cache().save(&path).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+
+    std::fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+    assert!(KvCache::load(&path, model(0)).is_err());
+
+    // Lengths are checked against the header instead of being allocated.
+    let json_len = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+    let mut huge = bytes.clone();
+    huge[12 + json_len..20 + json_len].copy_from_slice(&(1u64 << 60).to_le_bytes());
+    std::fs::write(&path, &huge).unwrap();
+    assert!(KvCache::load(&path, model(0)).is_err());
+    let mut header: serde_json::Value = serde_json::from_slice(&bytes[12..12 + json_len]).unwrap();
+    header["positions"] = (1u64 << 62).into();
+    header["tokens"] = ((1u64 << 62) - header["prefix"].as_u64().unwrap()).into();
+    let json = serde_json::to_vec(&header).unwrap();
+    let mut oversized = bytes[..8].to_vec();
+    oversized.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    oversized.extend_from_slice(&json);
+    oversized.extend_from_slice(&bytes[12 + json_len..]);
+    std::fs::write(&path, &oversized).unwrap();
+    let err = KvCache::load(&path, model(0)).err().unwrap();
+    assert!(format!("{err:#}").contains("impossibly large"), "{err:#}");
+
+    std::fs::write(&path, b"not a snapshot").unwrap();
+    let err = KvCache::load(&path, model(0)).err().unwrap();
+    assert!(
+        format!("{err:#}").contains("not an aurexkv snapshot"),
+        "{err:#}"
+    );
+}
+
+#[test]
+fn compressed_snapshots_claiming_huge_payloads_are_rejected() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("session.aurexkv");
+    let (engine, _) = conversation();
+    for compression in [Compression::Zstd, Compression::Lz4] {
+        let options = KvSaveOptions {
+            compression: Some(compression),
+            ..KvSaveOptions::default()
+        };
+        engine.kv_cache().save_with(&path, &options).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        // A header consistent with itself, describing a terabyte of cache.
+        let json_len = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        let mut header: serde_json::Value =
+            serde_json::from_slice(&bytes[12..12 + json_len]).unwrap();
+        let width = header["width"].as_u64().unwrap();
+        let positions = (1u64 << 40) / (4 * width);
+        let tokens = positions - header["prefix"].as_u64().unwrap();
+        header["positions"] = positions.into();
+        header["tokens"] = tokens.into();
+        header["payload_len"] = (positions * width * 4 + tokens * 4).into();
+        let json = serde_json::to_vec(&header).unwrap();
+        let mut forged = bytes[..8].to_vec();
+        forged.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        forged.extend_from_slice(&json);
+        forged.extend_from_slice(&bytes[12 + json_len..]);
+        std::fs::write(&path, &forged).unwrap();
+
+        let err = KvCache::load(&path, model(0)).err().unwrap();
+        assert!(format!("{err:#}").contains("cannot hold"), "{err:#}");
+    }
+}
+
+#[test]
+fn prefix_embeddings_are_kept() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("session.aurexkv");
+    let mut lm = model(0);
+    lm.set_prefix_embeddings(&[0.5; 16]).unwrap();
+    let mut engine = GenerationEngine::new(lm);
+    engine.generate("describe the image", &greedy_ignoring_eos(4), |_| {});
+    engine.kv_cache().save(&path).unwrap();
+
+    let loaded = KvCache::load(&path, model(0)).unwrap();
+    assert_eq!(loaded.model().prefix_len(), 2);
+    assert_eq!(loaded.tokens(), engine.kv_cache().tokens());
+}
@@ -0,0 +1,85 @@
+use amduda::aurex_lm::layout::WeightLayout;
+use amduda::aurex_lm::model_loader::Quantization;
+use amduda::aurex_lm::quantizer::quantize_int4;
+use aurex_backend::Backend;
+
+const LAYOUTS: [WeightLayout; 4] = [
+    WeightLayout::RowMajor,
+    WeightLayout::Blocked { cols: 4 },
+    WeightLayout::Tiled { rows: 2, cols: 3 },
+    WeightLayout::Interleaved { rows: 2 },
+];
+
+#[test]
+fn layouts_permute_and_restore_the_matrix() {
+    // 5 x 7 leaves edge panels, tiles and row groups; two trailing values
+    // lie outside the matrix.
+    let (rows, cols) = (5, 7);
+    let data: Vec<u32> = (0..37).collect();
+    for layout in LAYOUTS {
+        let packed = layout.pack(&data, rows, cols);
+        let mut sorted = packed.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, data, "{layout} is not a permutation");
+        assert_eq!(packed[35..], data[35..]);
+        assert_eq!(layout.unpack(&packed, rows, cols), data, "{layout}");
+    }
+}
+
+#[test]
+fn blocked_panels_store_columns_contiguously() {
+    let data: Vec<u32> = (0..12).collect();
+    let packed = WeightLayout::Blocked { cols: 2 }.pack(&data, 3, 4);
+    assert_eq!(packed, vec![0, 1, 4, 5, 8, 9, 2, 3, 6, 7, 10, 11]);
+    let packed = WeightLayout::Tiled { rows: 2, cols: 2 }.pack(&data, 3, 4);
+    assert_eq!(packed, vec![0, 1, 4, 5, 2, 3, 6, 7, 8, 9, 10, 11]);
+    let packed = WeightLayout::Interleaved { rows: 2 }.pack(&data, 3, 4);
+    assert_eq!(packed, vec![0, 4, 1, 5, 2, 6, 3, 7, 8, 9, 10, 11]);
+    // Matrices larger than the data are left alone.
+    assert_eq!(WeightLayout::Blocked { cols: 2 }.pack(&data, 4, 4), data);
+}
+
+#[test]
+fn encoded_weights_repack_losslessly() {
+    let values: Vec<f32> = (0..15).map(|x| x as f32 - 7.0).collect();
+    let (int4, _) = quantize_int4(&values);
+    let f32_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    for layout in LAYOUTS {
+        for (bytes, quant) in [
+            (&int4, Some(Quantization::Int4)),
+            (&f32_bytes, None),
+            (&f32_bytes, Some(Quantization::Int8)),
+            (&f32_bytes, Some(Quantization::Bf16)),
+        ] {
+            let packed = layout.pack_encoded(bytes, quant, 3, 5);
+            assert_eq!(packed.len(), bytes.len());
+            assert_eq!(
+                &layout.unpack_encoded(&packed, quant, 3, 5),
+                bytes,
+                "{layout}"
+            );
+        }
+    }
+}
+
+#[test]
+fn backends_get_their_layouts() {
+    assert_eq!(
+        WeightLayout::for_backend(Backend::Cpu),
+        WeightLayout::Blocked { cols: 8 }
+    );
+    assert_eq!(
+        WeightLayout::for_backend(Backend::Vulkan),
+        WeightLayout::Tiled { rows: 16, cols: 16 }
+    );
+    let layout = WeightLayout::for_backend(Backend::plugin("fpga_npu"));
+    assert_eq!(layout, WeightLayout::Interleaved { rows: 4 });
+    assert_eq!(
+        serde_json::to_string(&layout).unwrap(),
+        r#"{"interleaved":{"rows":4}}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<WeightLayout>(r#""row-major""#).unwrap(),
+        WeightLayout::RowMajor
+    );
+}
@@ -0,0 +1,147 @@
+use std::sync::{Arc, Mutex};
+
+use amduda::amduda_core::memory_events::{EventReason, MemoryEvent, MemoryEventKind};
+use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use aurex_utils::metrics::Metrics;
+use aurex_utils::profiler::Profiler;
+use serial_test::serial;
+
+type Events = Arc<Mutex<Vec<MemoryEvent>>>;
+
+fn manager(gpu: usize, cpu: usize, nvme: usize) -> (MemoryManager, Events) {
+    std::env::set_var("AMDUDA_HAS_GPU", if gpu > 0 { "1" } else { "0" });
+    std::env::set_var("AMDUDA_HAS_NVME", if nvme > 0 { "1" } else { "0" });
+    let events = Events::default();
+    let sink = events.clone();
+    let mgr = MemoryManager::new_with_limits(DeviceCapabilities::detect(), gpu, cpu, nvme)
+        .without_metrics()
+        .with_event_listener(Box::new(move |event| sink.lock().unwrap().push(*event)));
+    (mgr, events)
+}
+
+fn summary(events: &Events) -> Vec<(MemoryEventKind, Option<MemoryTier>, Option<MemoryTier>)> {
+    events
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|e| (e.kind, e.from, e.to))
+        .collect()
+}
+
+#[test]
+#[serial]
+fn allocations_evictions_and_frees_are_reported() {
+    let (mut mgr, events) = manager(100, 1000, 0);
+    let a = mgr.allocate(60);
+    let b = mgr.allocate(60);
+    assert!(mgr.free(b));
+
+    use MemoryEventKind::*;
+    use MemoryTier::*;
+    assert_eq!(
+        summary(&events),
+        vec![
+            (Allocate, None, Some(Gpu)),
+            (Evict, Some(Gpu), Some(Cpu)),
+            (Allocate, None, Some(Gpu)),
+            (Free, Some(Gpu), None),
+        ]
+    );
+    let events = events.lock().unwrap();
+    assert_eq!(events[1].id, a);
+    assert_eq!(events[1].reason, EventReason::Capacity);
+    assert_eq!(events[3].id, b);
+    assert_eq!(events[3].bytes, 60);
+    assert_eq!(events[3].reason, EventReason::Request);
+}
+
+#[test]
+#[serial]
+fn migrations_carry_their_reason() {
+    let (mut mgr, events) = manager(100, 1000, 1000);
+    let a = mgr.allocate(10);
+    mgr.migrate(a, MemoryTier::Nvme);
+    mgr.prefetch(&[a]);
+    mgr.migrate(a, MemoryTier::Cpu);
+    mgr.acquire(a);
+
+    let reasons: Vec<_> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| e.kind == MemoryEventKind::Migrate)
+        .map(|e| (e.to, e.reason))
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![
+            (Some(MemoryTier::Nvme), EventReason::Request),
+            (Some(MemoryTier::Cpu), EventReason::Prefetch),
+            (Some(MemoryTier::Gpu), EventReason::Prefetch),
+            (Some(MemoryTier::Cpu), EventReason::Request),
+            (Some(MemoryTier::Gpu), EventReason::Demand),
+        ]
+    );
+}
+
+#[test]
+#[serial]
+fn allocations_without_a_slower_tier_are_dropped() {
+    let (mut mgr, events) = manager(0, 100, 0);
+    let a = mgr.allocate(60);
+    mgr.allocate(60);
+    assert_eq!(mgr.tier(a), None);
+
+    let events = events.lock().unwrap();
+    let dropped = events
+        .iter()
+        .find(|e| e.kind == MemoryEventKind::Drop)
+        .unwrap();
+    assert_eq!(dropped.id, a);
+    assert_eq!((dropped.from, dropped.to), (Some(MemoryTier::Cpu), None));
+    assert_eq!(dropped.tier(), Some(MemoryTier::Cpu));
+}
+
+#[test]
+#[serial]
+fn profiler_records_every_event() {
+    let profiler = Arc::new(Mutex::new(Profiler::new()));
+    let (mgr, _) = manager(100, 1000, 0);
+    let mut mgr = mgr.with_profiler(profiler.clone());
+    let a = mgr.allocate(60);
+    mgr.allocate(60);
+    mgr.free(a);
+
+    let profiler = profiler.lock().unwrap();
+    let records = profiler.memory_events();
+    let kinds: Vec<_> = records.iter().map(|r| (r.kind, r.from, r.to)).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            ("allocate", None, Some("gpu")),
+            ("evict", Some("gpu"), Some("cpu")),
+            ("allocate", None, Some("gpu")),
+            ("free", Some("cpu"), None),
+        ]
+    );
+    assert_eq!(records[1].reason, "capacity");
+    assert_eq!(records[1].bytes, 60);
+}
+
+#[test]
+#[serial]
+fn events_are_counted_in_the_metrics_registry() {
+    std::env::set_var("AMDUDA_HAS_GPU", "1");
+    std::env::set_var("AMDUDA_HAS_NVME", "0");
+    let metrics = Metrics::global();
+    let before = metrics.memory_events("allocate", "gpu");
+    let evicted = metrics.memory_events("evict", "cpu");
+    let mut mgr = MemoryManager::new_with_limits(DeviceCapabilities::detect(), 100, 1000, 0);
+    mgr.allocate(60);
+    mgr.allocate(60);
+    assert_eq!(metrics.memory_events("allocate", "gpu"), before + 2);
+    assert_eq!(metrics.memory_events("evict", "cpu"), evicted + 1);
+    assert!(metrics
+        .render()
+        .contains("aurex_memory_events_total{event=\"evict\",tier=\"cpu\"}"));
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use amduda::amduda_core::memory_tiering::{
+    AllocationId, DeviceCapabilities, MemoryManager, MemoryPressure, MemoryTier, PressureHandler,
+};
+use aurex_runtime::config::{MemoryConfig, Watermarks};
+use serial_test::serial;
+
+type Shared<T> = Arc<Mutex<Vec<T>>>;
+
+/// Handler giving up one of `cache` per call, like a prefix cache dropping
+/// its oldest entry.
+fn drop_one(cache: &Shared<AllocationId>) -> PressureHandler {
+    let cache = cache.clone();
+    Box::new(move |_| cache.lock().unwrap().pop().into_iter().collect())
+}
+
+#[test]
+#[serial]
+fn crossing_the_high_watermark_runs_handlers_in_order() {
+    std::env::set_var("AMDUDA_HAS_GPU", "1");
+    std::env::set_var("AMDUDA_HAS_NVME", "0");
+    let cache = Shared::default();
+    let seen: Shared<MemoryPressure> = Shared::default();
+    let record = seen.clone();
+    let mut mgr = MemoryManager::new_with_limits(DeviceCapabilities::detect(), 100, 1000, 0)
+        .without_metrics()
+        .with_watermarks(
+            MemoryTier::Gpu,
+            Watermarks {
+                high: 0.8,
+                low: 0.5,
+            },
+        )
+        .with_pressure_handler(drop_one(&cache))
+        .with_pressure_handler(Box::new(move |pressure| {
+            // Shrinking the batch frees nothing the manager tracks.
+            record.lock().unwrap().push(*pressure);
+            Vec::new()
+        }));
+
+    let a = mgr.allocate(30);
+    let b = mgr.allocate(30);
+    cache.lock().unwrap().extend([b, a]);
+    assert_eq!(mgr.pressure_stats().events, 0);
+
+    let c = mgr.allocate(30);
+    assert_eq!(mgr.tier(a), None);
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(c), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.usage(), (60, 0, 0));
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 1);
+    assert_eq!((seen[0].used, seen[0].excess), (60, 10));
+    assert!(!seen[0].critical);
+    let stats = mgr.pressure_stats();
+    assert_eq!((stats.events, stats.critical, stats.released), (1, 0, 30));
+}
+
+#[test]
+#[serial]
+fn handlers_save_allocations_from_being_dropped() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_HAS_NVME", "0");
+    let caps = DeviceCapabilities::detect();
+
+    // Without handlers the least recently used weights are dropped.
+    let mut mgr = MemoryManager::new_with_limits(caps, 0, 100, 0).without_metrics();
+    let weights = mgr.allocate(40);
+    mgr.allocate(40);
+    mgr.allocate(40);
+    assert_eq!(mgr.tier(weights), None);
+
+    let cache = Shared::default();
+    let mut mgr = MemoryManager::new_with_limits(caps, 0, 100, 0)
+        .without_metrics()
+        .with_pressure_handler(drop_one(&cache));
+    let weights = mgr.allocate(40);
+    let kv = mgr.allocate(40);
+    cache.lock().unwrap().push(kv);
+    let fresh = mgr.allocate(40);
+    assert_eq!(mgr.tier(weights), Some(MemoryTier::Cpu));
+    assert_eq!(mgr.tier(kv), None);
+    assert_eq!(mgr.tier(fresh), Some(MemoryTier::Cpu));
+    let stats = mgr.pressure_stats();
+    assert_eq!((stats.events, stats.critical, stats.released), (1, 1, 40));
+}
+
+#[test]
+#[serial]
+fn watermarks_are_read_from_config() {
+    let config = MemoryConfig {
+        has_gpu: Some(false),
+        has_nvme: Some(false),
+        cpu_mem: Some(100),
+        watermarks: BTreeMap::from([
+            (
+                "cpu".to_string(),
+                Watermarks {
+                    high: 0.5,
+                    low: 0.2,
+                },
+            ),
+            (
+                "tape".to_string(),
+                Watermarks {
+                    high: 0.5,
+                    low: 0.2,
+                },
+            ),
+        ]),
+        ..MemoryConfig::default()
+    };
+    let cache = Shared::default();
+    let mut mgr = MemoryManager::from_config(&config)
+        .without_metrics()
+        .with_pressure_handler(drop_one(&cache));
+
+    let kv = mgr.allocate(40);
+    cache.lock().unwrap().push(kv);
+    let weights = mgr.allocate(20);
+    assert_eq!(mgr.tier(kv), None);
+    assert_eq!(mgr.tier(weights), Some(MemoryTier::Cpu));
+    assert_eq!(mgr.usage(), (0, 20, 0));
+}
@@ -1,5 +1,10 @@
 use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use amduda::amduda_core::transfer::{TransferEngine, TransferKind};
+use amduda::hal_backends::BackendKind;
+use aurex_runtime::config::MemoryConfig;
 use serial_test::serial;
+use std::sync::Arc;
+use tempfile::tempdir;
 
 #[test]
 #[serial]
@@ -9,14 +14,20 @@ fn gpu_caching_and_migration() {
     let caps = DeviceCapabilities::detect();
     let mut mgr = MemoryManager::new_with_limits(caps, 64, 64, 256);
 
-    assert_eq!(mgr.allocate(32), MemoryTier::Gpu);
+    let a = mgr.allocate(32);
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Gpu));
     assert_eq!(mgr.usage(), (32, 0, 0));
 
-    assert_eq!(mgr.allocate(48), MemoryTier::Gpu);
-    assert_eq!(mgr.usage(), (64, 16, 0));
+    let b = mgr.allocate(48);
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Cpu));
+    assert_eq!(mgr.usage(), (48, 32, 0));
 
-    assert_eq!(mgr.allocate(64), MemoryTier::Gpu);
-    assert_eq!(mgr.usage(), (64, 64, 16));
+    let c = mgr.allocate(64);
+    assert_eq!(mgr.tier(c), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Cpu));
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Nvme));
+    assert_eq!(mgr.usage(), (64, 48, 32));
 }
 
 #[test]
@@ -27,14 +38,17 @@ fn cpu_and_nvme_fallback() {
     let caps = DeviceCapabilities::detect();
     let mut mgr = MemoryManager::new_with_limits(caps, 0, 64, 512);
 
-    assert_eq!(mgr.allocate(32), MemoryTier::Cpu);
+    let a = mgr.allocate(32);
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Cpu));
     assert_eq!(mgr.usage(), (0, 32, 0));
 
-    assert_eq!(mgr.allocate(64), MemoryTier::Cpu);
+    let b = mgr.allocate(64);
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Cpu));
     assert_eq!(mgr.usage(), (0, 64, 32));
 
-    assert_eq!(mgr.allocate(128), MemoryTier::Nvme);
-    assert_eq!(mgr.usage(), (0, 0, 224));
+    let c = mgr.allocate(128);
+    assert_eq!(mgr.tier(c), Some(MemoryTier::Nvme));
+    assert_eq!(mgr.usage(), (0, 64, 160));
 }
 
 #[test]
@@ -45,25 +59,30 @@ fn manual_migration() {
     let caps = DeviceCapabilities::detect();
     let mut mgr = MemoryManager::new_with_limits(caps, 64, 64, 256);
 
-    mgr.allocate(32);
-    mgr.migrate(MemoryTier::Gpu, MemoryTier::Cpu, 16);
+    mgr.allocate(16);
+    let b = mgr.allocate(16);
+    assert_eq!(mgr.migrate(b, MemoryTier::Cpu), Some(MemoryTier::Cpu));
     assert_eq!(mgr.usage(), (16, 16, 0));
 
-    mgr.migrate(MemoryTier::Cpu, MemoryTier::Nvme, 8);
-    assert_eq!(mgr.usage(), (16, 8, 8));
+    assert_eq!(mgr.migrate(b, MemoryTier::Nvme), Some(MemoryTier::Nvme));
+    assert_eq!(mgr.usage(), (16, 0, 16));
 }
 
 #[test]
 #[serial]
-fn cold_data_evicted_first() {
+fn least_recently_used_evicted_first() {
     std::env::set_var("AMDUDA_HAS_GPU", "1");
     std::env::set_var("AMDUDA_HAS_NVME", "1");
     let caps = DeviceCapabilities::detect();
     let mut mgr = MemoryManager::new_with_limits(caps, 64, 64, 256);
 
-    mgr.allocate(64);
-    mgr.mark_cold(MemoryTier::Gpu, 32);
-    assert_eq!(mgr.allocate(32), MemoryTier::Gpu);
+    let a = mgr.allocate(32);
+    let b = mgr.allocate(32);
+    mgr.touch(a);
+    let c = mgr.allocate(32);
+    assert_eq!(mgr.tier(c), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Cpu));
     assert_eq!(mgr.usage(), (64, 32, 0));
 }
 
@@ -75,6 +94,86 @@ fn large_allocation_falls_back() {
     let caps = DeviceCapabilities::detect();
     let mut mgr = MemoryManager::new_with_limits(caps, 64, 64, 256);
 
-    assert_eq!(mgr.allocate(200), MemoryTier::Nvme);
+    let id = mgr.allocate(200);
+    assert_eq!(mgr.tier(id), Some(MemoryTier::Nvme));
     assert_eq!(mgr.usage(), (0, 0, 200));
 }
+
+#[test]
+#[serial]
+fn freed_and_dropped_allocations_leave_the_table() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_HAS_NVME", "0");
+    let caps = DeviceCapabilities::detect();
+    let mut mgr = MemoryManager::new_with_limits(caps, 0, 64, 0);
+
+    let a = mgr.allocate(32);
+    let b = mgr.allocate(32);
+    assert!(mgr.free(a));
+    assert!(!mgr.free(a));
+    assert_eq!(mgr.tier(a), None);
+    assert_eq!(mgr.usage(), (0, 32, 0));
+
+    // Without an NVMe tier the oldest allocation is dropped to make room.
+    let c = mgr.allocate(48);
+    assert_eq!(mgr.tier(b), None);
+    assert_eq!(mgr.tier(c), Some(MemoryTier::Cpu));
+    assert_eq!(mgr.allocations().count(), 1);
+    assert_eq!(mgr.usage(), (0, 48, 0));
+}
+
+#[test]
+#[serial]
+fn async_migration_runs_on_transfer_engine() {
+    std::env::set_var("AMDUDA_HAS_GPU", "1");
+    std::env::set_var("AMDUDA_HAS_NVME", "1");
+    let caps = DeviceCapabilities::detect();
+    let engine = Arc::new(TransferEngine::for_backend(BackendKind::Vulkan));
+    let mut mgr =
+        MemoryManager::new_with_limits(caps, 64, 64, 256).with_transfer_engine(engine.clone());
+
+    mgr.allocate(16);
+    let b = mgr.allocate(16);
+    let pending = mgr.migrate_async(b, MemoryTier::Cpu);
+    assert_eq!(mgr.usage(), (16, 16, 0));
+    assert_eq!(pending.kind(), Some(TransferKind::DeviceToHost));
+    assert_eq!(pending.wait().len(), 16);
+    assert_eq!(engine.stats().bytes, 16);
+
+    // Already on the CPU tier: no copy is queued.
+    let noop = mgr.migrate_async(b, MemoryTier::Cpu);
+    assert!(noop.is_complete());
+    assert_eq!(engine.stats().transfers, 1);
+}
+
+#[test]
+#[serial]
+fn probed_capabilities_yield_to_config() {
+    let dir = tempdir().unwrap();
+    let config = MemoryConfig {
+        has_gpu: Some(false),
+        has_nvme: Some(true),
+        spill_dir: Some(dir.path().to_path_buf()),
+        ..MemoryConfig::default()
+    };
+    let caps = DeviceCapabilities::probe(&config);
+    assert!(!caps.has_gpu);
+    assert!(caps.has_nvme);
+    // RAM and free disk space come from the machine, not the defaults.
+    assert!(caps.cpu_mem > 8 * 1024);
+    assert!(caps.nvme_mem > 0 && caps.nvme_mem < usize::MAX);
+
+    let pinned = DeviceCapabilities::probe(&MemoryConfig {
+        cpu_mem: Some(64),
+        nvme_mem: Some(256),
+        ..config
+    });
+    assert_eq!((pinned.cpu_mem, pinned.nvme_mem), (64, 256));
+
+    std::env::set_var("AMDUDA_HAS_GPU", "1");
+    std::env::set_var("AMDUDA_GPU_MEM", "4096");
+    let caps = DeviceCapabilities::detect();
+    std::env::remove_var("AMDUDA_GPU_MEM");
+    assert!(caps.has_gpu);
+    assert_eq!(caps.gpu_mem, 4096);
+}
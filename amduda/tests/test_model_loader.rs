@@ -1,9 +1,14 @@
 use amduda::amduda_core::memory_tiering::MemoryTier;
-use amduda::aurex_lm::model_loader::{load_model, LoadedModel, ModelConfig, Quantization, Weights};
+use amduda::aurex_lm::model_loader::{
+    load_model, load_model_to_device, LoadedModel, ModelConfig, PrecisionSync, Quantization,
+    UploadProgress, Weights,
+};
 use amduda::aurex_lm::quantizer::{quantize_int4, quantize_int8};
+use amduda::hal_backends::device_buffer::HostAllocator;
 use aurex_runtime::{Precision, Runtime};
 use serde_json::json;
 use serial_test::serial;
+use std::sync::Arc;
 use tempfile::tempdir;
 
 fn write_dummy_model(size: usize, quant: &str) -> std::path::PathBuf {
@@ -80,6 +85,7 @@ fn test_load_model_bf16() {
 fn test_load_model_mmap() {
     std::env::set_var("AMDUDA_HAS_GPU", "0");
     std::env::set_var("AMDUDA_HAS_NVME", "1");
+    std::env::set_var("AMDUDA_CPU_MEM", "8192");
     let config = write_dummy_model(9000, "int4");
     let model = load_model(config.to_str().unwrap()).unwrap();
     assert_eq!(model.config.quantization, Some(Quantization::Int4));
@@ -90,6 +96,49 @@ fn test_load_model_mmap() {
     }
 }
 
+#[test]
+fn mapped_weights_upload_in_chunks() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("weights.bin");
+    let bytes: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+    std::fs::write(&path, &bytes).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let weights = Weights::Mmap(unsafe { memmap2::Mmap::map(&file).unwrap() });
+
+    let mut reports = Vec::new();
+    let buffer = weights
+        .upload(&HostAllocator, 4096, |p| reports.push(p))
+        .unwrap();
+    assert_eq!(buffer.to_vec().unwrap(), bytes);
+    let uploaded: Vec<usize> = reports.iter().map(|p| p.uploaded).collect();
+    assert_eq!(uploaded, [4096, 8192, 10_000]);
+    assert!(reports.iter().all(|p| p.total == 10_000));
+    // Released pages are read back from the file.
+    assert_eq!(weights.as_bytes(), &bytes[..]);
+
+    let resident = Weights::Memory(bytes.clone());
+    let buffer = resident.upload(&HostAllocator, 6000, |_| {}).unwrap();
+    assert_eq!(buffer.to_vec().unwrap(), bytes);
+}
+
+#[test]
+fn load_model_to_device_maps_and_uploads() {
+    let config = write_dummy_model(9000, "int8");
+    let mut last = None;
+    let (model, buffer) =
+        load_model_to_device(config.to_str().unwrap(), &HostAllocator, |p| last = Some(p)).unwrap();
+    assert_eq!(model.tier, MemoryTier::Gpu);
+    assert!(matches!(model.weights, Weights::Mmap(_)));
+    assert_eq!(buffer.to_vec().unwrap(), vec![1u8; 9000]);
+    assert_eq!(
+        last,
+        Some(UploadProgress {
+            uploaded: 9000,
+            total: 9000
+        })
+    );
+}
+
 #[test]
 #[serial]
 fn test_load_large_model_gpu_nvme() {
@@ -131,6 +180,67 @@ fn test_dequantize_int4() {
     }
 }
 
+#[test]
+#[serial]
+fn test_int4_matmul_uses_packed_weights() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_HAS_NVME", "0");
+    let data: Vec<f32> = (0..12).map(|x| x as f32 - 6.0).collect();
+    let config = write_quantized_model(&data, Quantization::Int4);
+    let model = load_model(config.to_str().unwrap()).unwrap();
+    // [1, 2] x the leading 2 x 5 block of the weights.
+    let out = model.matmul_weights(&[1.0, 2.0], 1, 5, 2).unwrap();
+    let deq = model.dequantized_weights(10).unwrap();
+    let expected: Vec<f32> = (0..5).map(|j| deq[j] + 2.0 * deq[5 + j]).collect();
+    for (e, g) in expected.iter().zip(&out) {
+        assert!((e - g).abs() < 1e-4);
+    }
+    assert!(model.matmul_weights(&[1.0, 2.0], 1, 7, 2).is_err());
+}
+
+#[test]
+fn repacked_weights_keep_their_values() {
+    use amduda::aurex_lm::layout::WeightLayout;
+
+    // 3 rows of 11 columns: one full panel of 8 and a panel of 3.
+    let data: Vec<f32> = (0..33).map(|x| (x % 13) as f32 - 6.0).collect();
+    let a = [1.0, -2.0, 0.5, 3.0, -1.0, 2.0];
+    for quantization in [None, Some(Quantization::Int4)] {
+        let mut model = LoadedModel {
+            config: ModelConfig {
+                name: "dummy".into(),
+                weight_path: String::new(),
+                vocab_size: Some(3),
+                hidden_size: Some(11),
+                ..ModelConfig::default()
+            },
+            weights: Weights::Memory(data.iter().flat_map(|v| v.to_le_bytes()).collect()),
+            tier: MemoryTier::Cpu,
+            scale: None,
+        };
+        if let Some(q) = quantization {
+            model.change_precision(&data, q);
+        }
+        let expected = model.matmul_weights(&a, 2, 11, 3).unwrap();
+        let stored = model.weights.as_bytes().to_vec();
+
+        model.repack(WeightLayout::Blocked { cols: 8 });
+        assert_eq!(model.config.layout, WeightLayout::Blocked { cols: 8 });
+        assert_ne!(model.weights.as_bytes(), &stored[..]);
+        let restored = model.weights_f32().unwrap();
+        for (orig, got) in data.iter().zip(&restored) {
+            assert!((orig - got).abs() < 1.0);
+        }
+        let got = model.matmul_weights(&a, 2, 11, 3).unwrap();
+        for (e, g) in expected.iter().zip(&got) {
+            assert!((e - g).abs() < 1e-3, "{quantization:?}: {e} != {g}");
+        }
+
+        model.repack(WeightLayout::RowMajor);
+        assert_eq!(model.weights.as_bytes(), &stored[..]);
+    }
+}
+
 #[test]
 #[serial]
 fn test_dynamic_precision_scaling() {
@@ -143,6 +253,7 @@ fn test_dynamic_precision_scaling() {
             weight_path: String::new(),
             quantization: None,
             scale: None,
+            ..ModelConfig::default()
         },
         weights: Weights::Memory(Vec::new()),
         tier: MemoryTier::Cpu,
@@ -156,3 +267,87 @@ fn test_dynamic_precision_scaling() {
         assert!((orig - got).abs() < 0.1);
     }
 }
+
+#[test]
+#[serial]
+fn test_precision_sync_follows_runtime() {
+    let data = vec![0.0_f32, 1.0, -1.0, 0.5];
+    let model = LoadedModel {
+        config: ModelConfig {
+            name: "dummy".into(),
+            weight_path: String::new(),
+            quantization: None,
+            scale: None,
+            ..ModelConfig::default()
+        },
+        weights: Weights::Memory(Vec::new()),
+        tier: MemoryTier::Cpu,
+        scale: None,
+    };
+    let sync = Arc::new(PrecisionSync::new(model, data.clone()));
+    let mut runtime = Runtime::default();
+    runtime.add_precision_observer(sync.clone());
+
+    runtime.set_precision(Precision::Int8);
+    sync.with_model(|m| {
+        assert_eq!(m.config.quantization, Some(Quantization::Int8));
+        let deq = m.dequantized_weights(data.len()).unwrap();
+        for (orig, got) in data.iter().zip(deq.iter()) {
+            assert!((orig - got).abs() < 0.05);
+        }
+    });
+
+    runtime.set_precision(Precision::F32);
+    sync.with_model(|m| assert_eq!(m.config.quantization, None));
+}
+
+#[test]
+fn shards_models_larger_than_one_device() {
+    use aurex_backend::tensor_parallel::ShardStrategy;
+    use aurex_backend::{Backend, TensorParallelDispatcher};
+
+    let data: Vec<f32> = (0..40).map(|i| i as f32).collect();
+    let model = LoadedModel {
+        config: ModelConfig {
+            name: "dummy".into(),
+            weight_path: String::new(),
+            vocab_size: Some(10),
+            hidden_size: Some(4),
+            ..ModelConfig::default()
+        },
+        weights: Weights::Memory(data.iter().flat_map(|v| v.to_le_bytes()).collect()),
+        tier: MemoryTier::Cpu,
+        scale: None,
+    };
+    let single = TensorParallelDispatcher::new(&[Backend::Cpu]);
+    assert!(model.shard_weights(&single, ShardStrategy::Row, Some(100)).is_err());
+
+    let tp = TensorParallelDispatcher::new(&[Backend::Cpu, Backend::Cpu]);
+    let shards = model.shard_weights(&tp, ShardStrategy::Row, Some(100)).unwrap();
+    assert_eq!(shards.len(), 2);
+    assert_eq!(shards[1].range, 5..10);
+    assert_eq!(shards[1].data, data[20..].to_vec());
+    assert!(shards.iter().all(|s| s.bytes() == 80));
+}
+
+#[test]
+fn kernel_shapes_follow_the_embedding_table() {
+    let data = [0.5f32; 48];
+    let mut model = LoadedModel {
+        config: ModelConfig {
+            name: "dummy".into(),
+            weight_path: String::new(),
+            vocab_size: Some(12),
+            ..ModelConfig::default()
+        },
+        weights: Weights::Memory(data.iter().flat_map(|v| v.to_le_bytes()).collect()),
+        tier: MemoryTier::Cpu,
+        scale: None,
+    };
+    let shapes = model.kernel_shapes();
+    assert_eq!(shapes.matmuls, vec![(12, 4)]);
+    assert_eq!(shapes.norms, vec![4]);
+
+    model.config.vocab_size = Some(100);
+    assert!(model.kernel_shapes().is_empty());
+}
@@ -0,0 +1,178 @@
+use std::sync::{Arc, Mutex};
+
+use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use amduda::aurex_lm::model_loader::{LoadedModel, ModelConfig, Weights};
+use amduda::aurex_lm::moe::{MoeConfig, MoeLayer};
+
+const HIDDEN: usize = 4;
+const INTER: usize = 3;
+
+fn config(experts: usize, experts_per_token: usize) -> MoeConfig {
+    MoeConfig {
+        experts,
+        experts_per_token,
+        intermediate_size: INTER,
+    }
+}
+
+/// Gate and expert weights with small, distinct values.
+fn weights(config: &MoeConfig) -> Vec<f32> {
+    (0..config.weight_count(HIDDEN))
+        .map(|i| ((i * 7 % 19) as f32 - 9.0) / 10.0)
+        .collect()
+}
+
+/// Manager with room for `gpu_experts` experts in VRAM and one in RAM.
+fn memory(config: &MoeConfig, gpu_experts: usize) -> Arc<Mutex<MemoryManager>> {
+    let expert_bytes = config.expert_len(HIDDEN) * 4;
+    let caps = DeviceCapabilities {
+        has_gpu: true,
+        has_nvme: true,
+        gpu_mem: gpu_experts * expert_bytes,
+        cpu_mem: expert_bytes,
+        nvme_mem: 1 << 20,
+    };
+    let mgr =
+        MemoryManager::new_with_limits(caps, gpu_experts * expert_bytes, expert_bytes, 1 << 20)
+            .without_metrics();
+    Arc::new(Mutex::new(mgr))
+}
+
+fn matmul(a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+    let mut out = vec![0.0; m * n];
+    for i in 0..m {
+        for j in 0..n {
+            out[i * n + j] = (0..k).map(|p| a[i * k + p] * b[p * n + j]).sum();
+        }
+    }
+    out
+}
+
+/// Dense reference: every expert applied to one token.
+fn naive_expert(config: &MoeConfig, weights: &[f32], expert: usize, x: &[f32]) -> Vec<f32> {
+    let len = config.expert_len(HIDDEN);
+    let start = HIDDEN * config.experts + expert * len;
+    let w = &weights[start..start + len];
+    let gate = matmul(x, &w[..HIDDEN * INTER], 1, INTER, HIDDEN);
+    let up = matmul(x, &w[HIDDEN * INTER..2 * HIDDEN * INTER], 1, INTER, HIDDEN);
+    let act: Vec<f32> = gate
+        .iter()
+        .zip(&up)
+        .map(|(g, u)| g / (1.0 + (-g).exp()) * u)
+        .collect();
+    matmul(&act, &w[2 * HIDDEN * INTER..], 1, HIDDEN, INTER)
+}
+
+fn input(tokens: usize) -> Vec<f32> {
+    (0..tokens * HIDDEN)
+        .map(|i| ((i * 5 % 11) as f32 - 5.0) / 4.0)
+        .collect()
+}
+
+#[test]
+fn gate_routes_each_token_to_its_top_experts() {
+    let config = config(4, 2);
+    let weights = weights(&config);
+    let layer = MoeLayer::new(config, HIDDEN, &weights, memory(&config, 4)).unwrap();
+    let x = input(3);
+    let logits = matmul(&x, &weights[..HIDDEN * 4], 3, 4, HIDDEN);
+    for (token, routes) in layer.route(&x).iter().enumerate() {
+        assert_eq!(routes.len(), 2);
+        let row = &logits[token * 4..(token + 1) * 4];
+        let mut sorted = row.to_vec();
+        sorted.sort_by(|a, b| b.total_cmp(a));
+        assert_eq!(row[routes[0].expert], sorted[0]);
+        assert_eq!(row[routes[1].expert], sorted[1]);
+        assert!(routes[0].weight >= routes[1].weight);
+        assert!((routes[0].weight + routes[1].weight - 1.0).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn forward_matches_the_dense_reference() {
+    let config = config(4, 2);
+    let weights = weights(&config);
+    let mut layer = MoeLayer::new(config, HIDDEN, &weights, memory(&config, 4)).unwrap();
+    let x = input(5);
+    let routes = layer.route(&x);
+    let out = layer.forward(&x).unwrap();
+    for (token, routes) in routes.iter().enumerate() {
+        let x_t = &x[token * HIDDEN..(token + 1) * HIDDEN];
+        let mut expected = vec![0.0; HIDDEN];
+        for route in routes {
+            let y = naive_expert(&config, &weights, route.expert, x_t);
+            for (e, v) in expected.iter_mut().zip(y) {
+                *e += route.weight * v;
+            }
+        }
+        for (o, e) in out[token * HIDDEN..(token + 1) * HIDDEN]
+            .iter()
+            .zip(expected)
+        {
+            assert!((o - e).abs() < 1e-5, "{o} != {e}");
+        }
+    }
+    let stats = layer.stats();
+    assert_eq!(stats.tokens, 5);
+    assert_eq!(stats.expert_tokens.iter().sum::<u64>(), 10);
+}
+
+#[test]
+fn offloaded_experts_migrate_to_vram_on_demand() {
+    // VRAM holds one expert, RAM another; the rest live on NVMe.
+    let config = config(4, 1);
+    let weights = weights(&config);
+    let mut layer = MoeLayer::new(config, HIDDEN, &weights, memory(&config, 1)).unwrap();
+    let tiers: Vec<_> = (0..4).map(|e| layer.expert_tier(e).unwrap()).collect();
+    assert_eq!(tiers.iter().filter(|&&t| t == MemoryTier::Gpu).count(), 1);
+    assert!(tiers.contains(&MemoryTier::Nvme));
+
+    let x = input(8);
+    let mut reference = MoeLayer::new(config, HIDDEN, &weights, memory(&config, 4)).unwrap();
+    let expected = reference.forward(&x).unwrap();
+    assert_eq!(reference.stats().promotions, 0);
+
+    let out = layer.forward(&x).unwrap();
+    assert_eq!(out, expected);
+    let used: Vec<usize> = (0..4)
+        .filter(|&e| layer.stats().expert_tokens[e] > 0)
+        .collect();
+    assert!(used.len() > 1);
+    assert!(layer.stats().promotions > 0);
+    let last = *used.last().unwrap();
+    assert_eq!(layer.expert_tier(last), Some(MemoryTier::Gpu));
+}
+
+#[test]
+fn models_store_their_moe_layer_after_the_embeddings() {
+    let moe = config(2, 1);
+    let table: Vec<f32> = vec![0.5; 3 * HIDDEN];
+    let layer_weights = weights(&moe);
+    let values: Vec<f32> = table.iter().chain(&layer_weights).copied().collect();
+    let model = LoadedModel {
+        config: ModelConfig {
+            name: "mixtral-tiny".into(),
+            vocab_size: Some(3),
+            moe: Some(moe),
+            ..ModelConfig::default()
+        },
+        weights: Weights::Memory(values.iter().flat_map(|v| v.to_le_bytes()).collect()),
+        tier: MemoryTier::Cpu,
+        scale: None,
+    };
+    assert_eq!(model.config.embedding_shape(values.len()), (3, HIDDEN));
+    let tensors = model.tensors();
+    let names: Vec<&str> = tensors.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, ["tok_embeddings", "moe.gate", "moe.experts"]);
+    assert_eq!(tensors[2].shape, [2, 3, HIDDEN, INTER]);
+
+    let mut layer = model.moe_layer(memory(&moe, 2)).unwrap();
+    let mut direct = MoeLayer::new(moe, HIDDEN, &layer_weights, memory(&moe, 2)).unwrap();
+    let x = input(2);
+    assert_eq!(layer.forward(&x).unwrap(), direct.forward(&x).unwrap());
+
+    let json =
+        r#"{"name": "m", "weight_path": "w.bin", "moe": {"experts": 8, "intermediate_size": 16}}"#;
+    let config: ModelConfig = serde_json::from_str(json).unwrap();
+    assert_eq!(config.moe.unwrap().experts_per_token, 2);
+}
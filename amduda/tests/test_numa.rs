@@ -0,0 +1,47 @@
+use amduda::amduda_core::numa::NumaBuffer;
+use aurex_utils::numa::NumaTopology;
+use tempfile::tempdir;
+
+/// Two nodes sharing CPU 0, which exists on every machine.
+fn two_nodes(dir: &std::path::Path) -> NumaTopology {
+    for node in ["node0", "node1"] {
+        std::fs::create_dir_all(dir.join(node)).unwrap();
+        std::fs::write(dir.join(node).join("cpulist"), "0\n").unwrap();
+    }
+    NumaTopology::detect_in(dir).unwrap()
+}
+
+#[test]
+fn buffers_are_spread_or_pinned_across_nodes() {
+    let dir = tempdir().unwrap();
+    let topology = two_nodes(dir.path());
+    let data: Vec<u8> = (0..3 * 4096 + 5).map(|i| (i % 251) as u8).collect();
+
+    let spread = NumaBuffer::copy(&data, &topology, None).unwrap();
+    assert_eq!(&spread[..], &data[..]);
+    assert_eq!(spread.nodes(), &[0, 1]);
+
+    let pinned = NumaBuffer::copy(&data, &topology, Some(1)).unwrap();
+    assert_eq!(&pinned[..], &data[..]);
+    assert_eq!(pinned.nodes(), &[1]);
+
+    let err = NumaBuffer::copy(&data, &topology, Some(7)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn files_are_read_by_the_node_workers() {
+    let dir = tempdir().unwrap();
+    let topology = two_nodes(&dir.path().join("sys"));
+    let path = dir.path().join("weights.bin");
+    let data: Vec<u8> = (0..10_000).map(|i| (i % 13) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+
+    let buffer = NumaBuffer::read_file(&path, &topology, None).unwrap();
+    assert_eq!(&buffer[..], &data[..]);
+    assert_eq!(buffer.nodes(), &[0, 1]);
+
+    let empty = dir.path().join("empty.bin");
+    std::fs::write(&empty, b"").unwrap();
+    assert!(NumaBuffer::read_file(&empty, &topology, None).unwrap().is_empty());
+}
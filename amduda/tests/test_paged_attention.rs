@@ -3,6 +3,18 @@ use amduda::aurex_lm::paged_attention::PagedAttention;
 use serial_test::serial;
 
 fn naive_sliding_window(q: &[f32], k: &[f32], v: &[f32], d: usize, window: usize) -> Vec<f32> {
+    naive_attention_sinks(q, k, v, d, 0, window)
+}
+
+/// Sliding window attention that also attends to the first `sinks` tokens.
+fn naive_attention_sinks(
+    q: &[f32],
+    k: &[f32],
+    v: &[f32],
+    d: usize,
+    sinks: usize,
+    window: usize,
+) -> Vec<f32> {
     assert_eq!(k.len(), v.len());
     let n = q.len() / d;
     let mut out = vec![0f32; n * d];
@@ -11,7 +23,7 @@ fn naive_sliding_window(q: &[f32], k: &[f32], v: &[f32], d: usize, window: usize
         let start = if i >= window { i + 1 - window } else { 0 };
 
         let mut scores = Vec::new();
-        for j in start..=i {
+        for j in (0..sinks.min(start)).chain(start..=i) {
             let k_j = &k[j * d..(j + 1) * d];
             let dot: f32 = q_i.iter().zip(k_j.iter()).map(|(a, b)| a * b).sum();
             scores.push((j, dot));
@@ -51,7 +63,7 @@ fn naive_block_sparse(
     assert_eq!(k.len(), v.len());
     let n = q.len() / d;
     let mut out = vec![0f32; n * d];
-    let n_blocks = (n + block_size - 1) / block_size;
+    let n_blocks = n.div_ceil(block_size);
     for b in 0..n_blocks {
         let q_start = b * block_size;
         let q_end = (q_start + block_size).min(n);
@@ -150,3 +162,65 @@ fn block_sparse_attention_correctness_and_tiering() {
     let usage = attn.usage();
     assert_eq!(usage, (0, 32, 96));
 }
+
+#[test]
+#[serial]
+fn attention_sinks_keep_the_first_tokens_and_free_the_middle() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_HAS_NVME", "0");
+    let caps = DeviceCapabilities::detect();
+    let mut attn = PagedAttention::new_with_limits(caps, 0, 1024, 0).with_attention_sinks(2);
+    assert_eq!(attn.attention_sinks(), Some(2));
+
+    let d = 2;
+    let q: Vec<f32> = (0..16).map(|i| ((i * 7 % 11) as f32 / 5.0) - 1.0).collect();
+    let k: Vec<f32> = (0..16).map(|i| ((i * 5 % 13) as f32 / 6.0) - 1.0).collect();
+    let v: Vec<f32> = (0..16).map(|i| (i % 4) as f32).collect();
+    let window = 2;
+
+    let result = attn.compute(&q, &k, &v, d, window);
+    let expected = naive_attention_sinks(&q, &k, &v, d, 2, window);
+    assert!(result
+        .output
+        .iter()
+        .zip(expected.iter())
+        .all(|(a, b)| (a - b).abs() < 1e-5));
+    assert_ne!(expected, naive_sliding_window(&q, &k, &v, d, window));
+
+    // Four pages of two tokens: the sink page and the last page stay, the
+    // two pages in between are freed.
+    assert_eq!(result.evicted_pages, 2);
+    let page = 2 * d * 4;
+    assert_eq!(attn.usage(), (0, 2 * 2 * page, 0));
+}
+
+#[test]
+#[serial]
+fn attention_sinks_spill_to_nvme_without_losing_the_sinks() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_HAS_NVME", "1");
+    let caps = DeviceCapabilities::detect();
+    let mut attn = PagedAttention::new_with_limits(caps, 0, 64, 4096).with_attention_sinks(1);
+
+    let d = 2;
+    let n = 32;
+    let q: Vec<f32> = (0..n * d)
+        .map(|i| ((i * 7 % 11) as f32 / 5.0) - 1.0)
+        .collect();
+    let k: Vec<f32> = (0..n * d)
+        .map(|i| ((i * 5 % 13) as f32 / 6.0) - 1.0)
+        .collect();
+    let v: Vec<f32> = (0..n * d).map(|i| (i % 4) as f32).collect();
+
+    let result = attn.compute(&q, &k, &v, d, 4);
+    let expected = naive_attention_sinks(&q, &k, &v, d, 1, 4);
+    assert!(result
+        .output
+        .iter()
+        .zip(expected.iter())
+        .all(|(a, b)| (a - b).abs() < 1e-5));
+    assert_eq!(result.k_tier, MemoryTier::Nvme);
+    assert_eq!(result.evicted_pages, 6);
+    let (_, cpu, nvme) = attn.usage();
+    assert_eq!(cpu + nvme, 2 * 2 * 4 * d * 4);
+}
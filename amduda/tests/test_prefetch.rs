@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+
+use amduda::amduda_core::memory_tiering::{
+    AllocationId, DeviceCapabilities, MemoryManager, MemoryTier,
+};
+use amduda::amduda_core::procedural_fsm::{ProceduralFsm, State};
+use amduda::amduda_core::transfer::TransferEngine;
+use amduda::aurex_lm::generation::{GenerationConfig, GenerationEngine};
+use amduda::aurex_lm::sampler::SamplingParams;
+use amduda::aurex_lm::tiny_lm::LanguageModel;
+use amduda::hal_backends::BackendKind;
+use aurex_runtime::RuntimeEvent;
+use serial_test::serial;
+
+fn manager() -> MemoryManager {
+    std::env::set_var("AMDUDA_HAS_GPU", "1");
+    std::env::set_var("AMDUDA_HAS_NVME", "1");
+    let engine = Arc::new(TransferEngine::for_backend(BackendKind::CpuSimd));
+    MemoryManager::new_with_limits(DeviceCapabilities::detect(), 256, 256, 4096)
+        .without_metrics()
+        .with_transfer_engine(engine)
+}
+
+/// Allocate `n` blocks of `bytes` each and push them to NVMe.
+fn cold_blocks(mgr: &mut MemoryManager, n: usize, bytes: usize) -> Vec<AllocationId> {
+    (0..n)
+        .map(|_| {
+            let id = mgr.allocate(bytes);
+            mgr.migrate(id, MemoryTier::Nvme);
+            id
+        })
+        .collect()
+}
+
+#[test]
+#[serial]
+fn prefetch_promotes_through_every_tier() {
+    let mut mgr = manager();
+    let blocks = cold_blocks(&mut mgr, 2, 64);
+
+    assert_eq!(mgr.prefetch(&blocks[..1]), 2);
+    assert_eq!(mgr.tier(blocks[0]), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.acquire(blocks[0]), Some(MemoryTier::Gpu));
+
+    // Not hinted: promoted synchronously on the critical path.
+    assert_eq!(mgr.acquire(blocks[1]), Some(MemoryTier::Gpu));
+    let stats = mgr.prefetch_stats();
+    assert_eq!((stats.hints, stats.migrations), (1, 2));
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+}
+
+#[test]
+#[serial]
+fn fsm_prefetches_kv_blocks_before_attention() {
+    let mut mgr = manager();
+    let kv = cold_blocks(&mut mgr, 2, 64);
+    let mgr = Arc::new(Mutex::new(mgr));
+    let mut fsm = ProceduralFsm::new().with_memory_manager(mgr.clone());
+    fsm.needs(State::ComputeAttention, kv.clone());
+
+    // A cache miss leads to the KV update, after which attention runs, so
+    // the blocks are requested as soon as the update starts.
+    fsm.on_event(RuntimeEvent::TokenFetched { cache_hit: false });
+    assert_eq!(fsm.state(), State::KVCacheUpdate);
+    {
+        let mgr = mgr.lock().unwrap();
+        assert!(kv.iter().all(|&id| mgr.tier(id) == Some(MemoryTier::Gpu)));
+        assert_eq!(mgr.prefetch_stats().hits, 0);
+    }
+
+    fsm.on_event(RuntimeEvent::CacheUpdated);
+    assert_eq!(fsm.state(), State::ComputeAttention);
+    let stats = mgr.lock().unwrap().prefetch_stats();
+    assert_eq!((stats.hits, stats.misses), (2, 0));
+}
+
+/// Model predicting token 1 after any input.
+struct Constant {
+    seen: usize,
+}
+
+impl LanguageModel for Constant {
+    fn vocab_size(&self) -> usize {
+        257
+    }
+    fn forward(&mut self, _token: u32) -> Vec<f32> {
+        self.seen += 1;
+        let mut logits = vec![0.0; 257];
+        logits[1] = 10.0;
+        logits
+    }
+    fn reset(&mut self) {
+        self.seen = 0;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.seen = self.seen.min(len);
+    }
+    fn context_len(&self) -> usize {
+        self.seen
+    }
+}
+
+#[test]
+#[serial]
+fn generation_prefetches_the_next_position() {
+    let mut mgr = manager();
+    // One KV page per position, only a few of which fit on the GPU.
+    let pages = cold_blocks(&mut mgr, 8, 96);
+    let mgr = Arc::new(Mutex::new(mgr));
+    let planner_pages = pages.clone();
+    let mut engine = GenerationEngine::new(Constant { seen: 0 }).with_prefetch(
+        mgr.clone(),
+        Box::new(move |position| vec![planner_pages[position % planner_pages.len()]]),
+    );
+
+    let config = GenerationConfig {
+        max_tokens: 6,
+        sampling: SamplingParams {
+            temperature: 0.0,
+            ..SamplingParams::default()
+        },
+        stop_at_eos: false,
+        ..GenerationConfig::default()
+    };
+    let out = engine.generate("ab", &config, |_| {});
+    let forward_passes = out.prompt_tokens + out.tokens.len() - 1;
+
+    // Only the very first position misses; every later one was hinted
+    // during the previous forward pass.
+    let stats = mgr.lock().unwrap().prefetch_stats();
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.hits as usize, forward_passes - 1);
+}
@@ -1,5 +1,8 @@
 use amduda::amduda_core::procedural_fsm::{ProceduralFsm, State};
+use amduda::aurex_lm::tiny_lm::{LanguageModel, TinyLm};
+use aurex_backend::dispatch::CpuBackend;
 use aurex_runtime::{Runtime, RuntimeEvent};
+use std::sync::Arc;
 
 // Helper to run async code in tests without requiring the tokio macros.
 fn run_async<F: std::future::Future<Output = ()>>(fut: F) {
@@ -55,3 +58,79 @@ fn fsm_handles_errors() {
         assert_eq!(fsm.state(), State::Error);
     });
 }
+
+/// Events of one token with a cache miss, from fetch back to fetch.
+fn miss_token() -> [RuntimeEvent; 4] {
+    [
+        RuntimeEvent::TokenFetched { cache_hit: false },
+        RuntimeEvent::CacheUpdated,
+        RuntimeEvent::AttentionComputed,
+        RuntimeEvent::TokenEmitted,
+    ]
+}
+
+#[test]
+fn journal_replays_deterministically() {
+    let mut fsm = ProceduralFsm::new();
+    for event in miss_token().into_iter().chain(miss_token()) {
+        fsm.on_event(event);
+    }
+    fsm.on_event(RuntimeEvent::TokenFetched { cache_hit: true });
+    assert_eq!(fsm.journal().len(), 9);
+    assert_eq!(fsm.kv_len(), 2);
+
+    let replayed = ProceduralFsm::replay(fsm.journal()).unwrap();
+    assert_eq!(replayed.state(), fsm.state());
+    assert_eq!(replayed.kv_len(), fsm.kv_len());
+    let transitions = |fsm: &ProceduralFsm| -> Vec<(State, State, usize)> {
+        fsm.journal()
+            .iter()
+            .map(|e| (e.from, e.to, e.kv_len))
+            .collect()
+    };
+    assert_eq!(transitions(&replayed), transitions(&fsm));
+
+    let mut tampered = fsm.journal().to_vec();
+    tampered[1].to = State::OutputToken;
+    let Err(err) = ProceduralFsm::replay(&tampered) else {
+        panic!("replay of a tampered journal must diverge");
+    };
+    assert_eq!(err.index, 1);
+    assert_eq!(err.actual, State::ComputeAttention);
+}
+
+#[test]
+fn rollback_truncates_the_kv_cache() {
+    let weights: Vec<f32> = (0..257 * 4).map(|i| (i % 7) as f32 / 7.0).collect();
+    let mut model = TinyLm::new(&weights, 257, 4, Arc::new(CpuBackend)).unwrap();
+    let mut fsm = ProceduralFsm::new();
+    for token in [10, 11, 12] {
+        for event in miss_token() {
+            if event == RuntimeEvent::CacheUpdated {
+                model.forward(token);
+            }
+            fsm.on_event(event);
+        }
+    }
+    assert_eq!((fsm.kv_len(), model.context_len()), (3, 3));
+
+    // Undo the last token and a half: back to the attention of token 2.
+    let kv_len = fsm.rollback(6);
+    model.truncate(kv_len);
+    assert_eq!(fsm.state(), State::ComputeAttention);
+    assert_eq!(model.context_len(), 2);
+    assert_eq!(fsm.journal().len(), 6);
+
+    // A rollback event discards the cache update of the in-flight token.
+    fsm.on_event(RuntimeEvent::AttentionComputed);
+    fsm.on_event(RuntimeEvent::TokenEmitted);
+    fsm.on_event(RuntimeEvent::TokenFetched { cache_hit: false });
+    fsm.on_event(RuntimeEvent::CacheUpdated);
+    assert_eq!(fsm.kv_len(), 3);
+    fsm.on_event(RuntimeEvent::Rollback);
+    assert_eq!((fsm.state(), fsm.kv_len()), (State::FetchToken, 2));
+
+    assert_eq!(fsm.rollback(100), 0);
+    assert_eq!(fsm.state(), State::FetchToken);
+    assert!(fsm.journal().is_empty());
+}
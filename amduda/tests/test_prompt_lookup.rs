@@ -0,0 +1,119 @@
+mod common;
+
+use amduda::aurex_lm::generation::{GenerationConfig, GenerationEngine};
+use amduda::aurex_lm::prompt_lookup::PromptLookup;
+use amduda::aurex_lm::sampler::SamplingParams;
+use amduda::aurex_lm::tiny_lm::LanguageModel;
+use common::tiny_lm;
+
+fn config(temperature: f32, prompt_lookup: Option<PromptLookup>) -> GenerationConfig {
+    GenerationConfig {
+        max_tokens: 24,
+        sampling: SamplingParams {
+            temperature,
+            seed: 7,
+            ..SamplingParams::default()
+        },
+        stop_at_eos: false,
+        prompt_lookup,
+        ..GenerationConfig::default()
+    }
+}
+
+/// Model repeating `text`, counting the forward passes it runs.
+struct Repeat {
+    text: &'static [u8],
+    seen: usize,
+    passes: usize,
+}
+
+impl LanguageModel for Repeat {
+    fn vocab_size(&self) -> usize {
+        257
+    }
+    fn forward(&mut self, _token: u32) -> Vec<f32> {
+        self.passes += 1;
+        self.seen += 1;
+        let mut logits = vec![0.0; 257];
+        logits[self.text[self.seen % self.text.len()] as usize] = 10.0;
+        logits
+    }
+    fn forward_batch(&mut self, tokens: &[u32]) -> Vec<Vec<f32>> {
+        let logits = tokens.iter().map(|&t| self.forward(t)).collect();
+        self.passes -= tokens.len() - 1;
+        logits
+    }
+    fn reset(&mut self) {
+        self.seen = 0;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.seen = self.seen.min(len);
+    }
+    fn context_len(&self) -> usize {
+        self.seen
+    }
+}
+
+#[test]
+fn drafts_continue_the_last_recurring_ngram() {
+    let lookup = PromptLookup {
+        max_ngram: 3,
+        max_draft: 3,
+    };
+    // "1 2" recurs, "4 1 2" does not.
+    assert_eq!(lookup.draft(&[1, 2, 3, 4, 5, 1, 2], 8), [3, 4, 5]);
+    assert_eq!(lookup.draft(&[1, 2, 3, 4, 5, 1, 2], 1), [3]);
+    // The latest occurrence wins, and longer n-grams before shorter ones.
+    assert_eq!(lookup.draft(&[7, 2, 8, 1, 2, 9, 1, 2], 8), [9, 1, 2]);
+    assert_eq!(lookup.draft(&[1, 2, 3, 9, 2, 4, 1, 2], 2), [3, 9]);
+    assert!(lookup.draft(&[1, 2, 3], 8).is_empty());
+    assert!(lookup.draft(&[1], 8).is_empty());
+    assert!(lookup.draft(&[], 8).is_empty());
+}
+
+#[test]
+fn speculation_does_not_change_the_output() {
+    let prompt = "the quick brown fox jumps over the lazy dog. the quick brown";
+    for temperature in [0.0, 0.8] {
+        let mut plain = GenerationEngine::new(tiny_lm(8));
+        let expected = plain.generate(prompt, &config(temperature, None), |_| {});
+        let mut engine = GenerationEngine::new(tiny_lm(8));
+        let lookup = Some(PromptLookup::default());
+        let output = engine.generate(prompt, &config(temperature, lookup), |_| {});
+
+        assert_eq!(output.tokens, expected.tokens, "temperature {temperature}");
+        assert_eq!(output.text, expected.text);
+        assert!(output.draft_tokens > 0);
+        assert_eq!(expected.draft_tokens, 0);
+        // Rejected drafts are gone from the cache.
+        assert_eq!(engine.kv_cache().tokens(), plain.kv_cache().tokens());
+    }
+}
+
+#[test]
+fn accepted_drafts_save_forward_passes() {
+    let text = b"abcdefgh";
+    let prompt = "abcdefghabcdefgh";
+    let run = |lookup| {
+        let model = Repeat {
+            text,
+            seen: 0,
+            passes: 0,
+        };
+        let mut engine = GenerationEngine::new(model);
+        let output = engine.generate(prompt, &config(0.0, lookup), |_| {});
+        (output, engine.model().passes)
+    };
+
+    let (expected, plain_passes) = run(None);
+    let (output, passes) = run(Some(PromptLookup::default()));
+    assert_eq!(output.text, expected.text);
+    assert_eq!(output.text, "abcdefghabcdefghabcdefgh");
+    assert!(output.accepted_draft_tokens > 0);
+    assert!(output.accepted_draft_tokens <= output.draft_tokens);
+    assert_eq!(
+        plain_passes - passes,
+        output.accepted_draft_tokens,
+        "{output:?}"
+    );
+}
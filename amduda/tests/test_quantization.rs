@@ -1,6 +1,9 @@
+use amduda::amduda_core::tensor_ops::{CpuFallback, TensorOps};
 use amduda::aurex_lm::quantizer::{
-    dequantize_bf16, dequantize_int4, dequantize_int8, quantize_bf16, quantize_int4, quantize_int8,
+    dequantize_bf16, dequantize_int4, dequantize_int8, dequantize_nf4, quantize_bf16,
+    quantize_int4, quantize_int8, quantize_nf4,
 };
+use amduda::hal_backends::cpu_simd::CpuSimdBackend;
 
 #[test]
 fn test_int8_round_trip() {
@@ -32,3 +35,48 @@ fn test_bf16_round_trip() {
     }
 }
 
+#[test]
+fn test_int4_matmul_matches_dequantized_matmul() {
+    // Odd widths start rows of the packed weights mid-byte and leave
+    // columns for the scalar tail.
+    for (m, n, k) in [(1, 16, 8), (3, 13, 7), (2, 9, 5)] {
+        let a: Vec<f32> = (0..m * k).map(|x| (x % 5) as f32 - 2.0).collect();
+        let w: Vec<f32> = (0..k * n).map(|x| ((x * 7) % 15) as f32 - 7.0).collect();
+        let (packed, scale) = quantize_int4(&w);
+        let expected = CpuFallback.matmul(&a, &dequantize_int4(&packed, scale, w.len()), m, n, k);
+        let got = CpuSimdBackend.matmul_int4(&a, &packed, scale, m, n, k);
+        assert_eq!(got.len(), m * n);
+        for (e, g) in expected.iter().zip(&got) {
+            assert!((e - g).abs() < 1e-4, "{m}x{n}x{k}: {e} != {g}");
+        }
+    }
+}
+
+#[test]
+fn test_nf4_round_trip() {
+    let data = [0.0_f32, 1.0, -1.0, 0.5, -0.25];
+    let (q, scale) = quantize_nf4(&data);
+    assert_eq!(q.len(), 3);
+    assert_eq!(scale, 1.0);
+    let deq = dequantize_nf4(&q, scale, data.len());
+    assert_eq!(deq.len(), data.len());
+    assert_eq!(&deq[..3], &[0.0, 1.0, -1.0]);
+    for (a, b) in data.iter().zip(deq.iter()) {
+        assert!((a - b).abs() < 0.07);
+    }
+}
+
+#[test]
+fn test_non_finite_values_do_not_change_the_scale() {
+    let data = [1.0_f32, f32::NAN, f32::INFINITY, f32::NEG_INFINITY, -0.5];
+    let (q, scale) = quantize_int8(&data);
+    assert_eq!(scale, 1.0 / 127.0);
+    assert_eq!(q, vec![127, 0, 127, -127, -64]);
+
+    let (q, scale) = quantize_int4(&data);
+    assert_eq!(scale, 1.0 / 7.0);
+    assert_eq!(dequantize_int4(&q, scale, 5)[1..4], [0.0, 1.0, -8.0 / 7.0]);
+
+    let (_, scale) = quantize_nf4(&data);
+    assert_eq!(scale, 1.0);
+}
@@ -0,0 +1,42 @@
+#![cfg(feature = "proptest")]
+
+use amduda::aurex_lm::quantizer_props::{
+    check_error_bound, check_non_finite, check_packing_round_trip, check_scale_monotonic, tensor,
+    tensor_with_non_finite, Format,
+};
+use proptest::prelude::*;
+
+const MAX_LEN: usize = 64;
+
+fn format() -> impl Strategy<Value = Format> {
+    prop::sample::select(Format::ALL.to_vec())
+}
+
+proptest! {
+    #[test]
+    fn dequantized_values_stay_within_bounds(format in format(), data in tensor(MAX_LEN)) {
+        check_error_bound(format, &data)?;
+    }
+
+    #[test]
+    fn scale_grows_with_the_input(
+        format in format(),
+        data in tensor(MAX_LEN),
+        factor in 1.0_f32..1.0e3,
+    ) {
+        check_scale_monotonic(format, &data, factor)?;
+    }
+
+    #[test]
+    fn packing_round_trips(format in format(), data in tensor(MAX_LEN)) {
+        check_packing_round_trip(format, &data)?;
+    }
+
+    #[test]
+    fn non_finite_values_are_contained(
+        format in format(),
+        data in tensor_with_non_finite(MAX_LEN),
+    ) {
+        check_non_finite(format, &data)?;
+    }
+}
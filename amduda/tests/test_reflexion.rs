@@ -0,0 +1,139 @@
+use amduda::amduda_core::procedural_fsm::{ProceduralFsm, State};
+use amduda::aurex_lm::generation::{GenerationConfig, GenerationEngine};
+use amduda::aurex_lm::reflexion::{Attempt, Reflexion, ReflexionPolicy};
+use amduda::aurex_lm::sampler::SamplingParams;
+use amduda::aurex_lm::tiny_lm::LanguageModel;
+use aurex_runtime::{ReflexionLoop, RuntimeEvent};
+use std::sync::Arc;
+
+/// Model that slightly prefers writing `x` over `y`, and when asked for a
+/// rating after a `:` scores answers containing `y` 9 and others 0.
+struct Picky {
+    context: Vec<u32>,
+}
+
+impl LanguageModel for Picky {
+    fn vocab_size(&self) -> usize {
+        257
+    }
+    fn forward(&mut self, token: u32) -> Vec<f32> {
+        self.context.push(token);
+        let mut logits = vec![0.0; 257];
+        if self.context.ends_with(b": ".map(u32::from).as_slice()) {
+            let digit = if self.context.contains(&u32::from(b'y')) {
+                b'9'
+            } else {
+                b'0'
+            };
+            logits[digit as usize] = 20.0;
+        } else {
+            logits[b'x' as usize] = 2.0;
+            logits[b'y' as usize] = 1.9;
+        }
+        logits
+    }
+    fn reset(&mut self) {
+        self.context.clear();
+    }
+    fn truncate(&mut self, len: usize) {
+        self.context.truncate(len);
+    }
+    fn context_len(&self) -> usize {
+        self.context.len()
+    }
+}
+
+fn greedy_token() -> GenerationConfig {
+    GenerationConfig {
+        max_tokens: 1,
+        sampling: SamplingParams {
+            temperature: 0.0,
+            ..SamplingParams::default()
+        },
+        ..GenerationConfig::default()
+    }
+}
+
+#[test]
+fn scores_are_the_expected_digit() {
+    let mut logits = vec![0.0; 257];
+    logits[b'9' as usize] = 50.0;
+    assert!((Reflexion::score(&logits) - 1.0).abs() < 1e-4);
+    let uniform = vec![0.0; 257];
+    assert!((Reflexion::score(&uniform) - 0.5).abs() < 1e-4);
+    assert_eq!(Reflexion::score(&[]), 0.0);
+}
+
+#[test]
+fn low_scores_are_retried_from_the_cached_prompt() {
+    let reflexion = Arc::new(Reflexion::new(ReflexionPolicy {
+        max_retries: 8,
+        temperature_step: 1.0,
+        ..ReflexionPolicy::default()
+    }));
+    let mut engine = GenerationEngine::new(Picky {
+        context: Vec::new(),
+    })
+    .with_reflexion(reflexion.clone());
+    let mut streamed = String::new();
+    let out = engine.generate("Q?", &greedy_token(), |piece| streamed.push_str(piece));
+    assert_eq!(out.text, "y");
+    assert_eq!(streamed, "y");
+    assert_eq!(out.prompt_tokens, 2);
+
+    let history = reflexion.history();
+    assert!(history.len() >= 2);
+    assert_eq!(history[0].text, "x");
+    assert!(history[0].score < 0.01);
+    assert_eq!(history[0].sampling.temperature, 0.0);
+    assert!(history[1].sampling.temperature > 0.0);
+    let last = history.last().unwrap();
+    assert_eq!(last.text, "y");
+    assert!(last.score > 0.99);
+}
+
+#[test]
+fn retries_stop_at_the_limit_and_the_history_is_bounded() {
+    let reflexion = Arc::new(Reflexion::new(ReflexionPolicy {
+        max_retries: 3,
+        temperature_step: 0.0,
+        history: 2,
+        ..ReflexionPolicy::default()
+    }));
+    let mut engine = GenerationEngine::new(Picky {
+        context: Vec::new(),
+    })
+    .with_reflexion(reflexion.clone());
+    // Greedy decoding never writes `y`, so every attempt scores 0.
+    let out = engine.generate("Q?", &greedy_token(), |_| {});
+    assert_eq!(out.text, "x");
+    let history = reflexion.history();
+    assert_eq!(history.len(), 2);
+    assert!(history.iter().all(|a| a.text == "x"));
+}
+
+#[test]
+fn retry_events_reenter_attention() {
+    let reflexion = Reflexion::new(ReflexionPolicy::default());
+    let rt = tokio::runtime::Runtime::new().expect("tokio runtime");
+    rt.block_on(async {
+        let emitted = RuntimeEvent::TokenEmitted;
+        assert_eq!(reflexion.reflect(&emitted).await, emitted);
+
+        let attempt = Attempt {
+            prompt: "Q?".into(),
+            text: "x".into(),
+            score: 0.1,
+            sampling: SamplingParams::default(),
+        };
+        assert!(reflexion.record(attempt, 0));
+        let retry = reflexion.reflect(&emitted).await;
+        assert_eq!(retry, RuntimeEvent::TokenFetched { cache_hit: true });
+        // The retry is emitted once.
+        assert_eq!(reflexion.reflect(&emitted).await, emitted);
+
+        let mut fsm = ProceduralFsm::new();
+        fsm.on_event(retry);
+        assert_eq!(fsm.state(), State::ComputeAttention);
+    });
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use amduda::aurex_lm::embeddings::Pooling;
+use amduda::aurex_lm::retrieval::{
+    augment_prompt, chunk_text, Chunk, ChunkConfig, Retriever, VectorIndex,
+};
+use common::embedding_model;
+
+const TEXT: &str = "Paged attention splits the KV cache into pages. Sequences grow \
+without copying.\n\nThe memory manager moves tensors between GPU memory, CPU memory and \
+disk. It evicts the least recently used tensors first.";
+
+#[test]
+fn chunks_end_at_boundaries_and_overlap() {
+    let config = ChunkConfig {
+        size: 64,
+        overlap: 16,
+    };
+    let chunks = chunk_text(TEXT, &config);
+    assert!(chunks.len() > 2);
+    for &(start, chunk) in &chunks {
+        assert!(chunk.len() <= 64, "{chunk:?}");
+        assert_eq!(&TEXT[start..start + chunk.len()], chunk);
+        assert_eq!(chunk, chunk.trim());
+    }
+    assert_eq!(
+        chunks[0].1,
+        "Paged attention splits the KV cache into pages."
+    );
+    // Consecutive chunks share a few words and never start mid-word.
+    for pair in chunks.windows(2) {
+        let ((a, first), (b, _)) = (pair[0], pair[1]);
+        assert!(b > a && b <= a + first.len(), "{pair:?}");
+        assert!(TEXT[..b].ends_with(char::is_whitespace));
+    }
+    assert_eq!(chunks.last().unwrap().1.rsplit(' ').next(), Some("first."));
+
+    assert_eq!(
+        chunk_text("short text", &ChunkConfig::default()),
+        vec![(0, "short text")]
+    );
+    assert!(chunk_text(" \n ", &ChunkConfig::default()).is_empty());
+    // Words longer than a chunk are cut, on character boundaries.
+    let cut = chunk_text(
+        "ééééé",
+        &ChunkConfig {
+            size: 3,
+            overlap: 0,
+        },
+    );
+    assert_eq!(
+        cut.iter().map(|&(_, c)| c).collect::<Vec<_>>(),
+        ["é", "é", "é", "é", "é"]
+    );
+}
+
+#[test]
+fn index_finds_ingested_chunks_and_round_trips() {
+    let embeddings = embedding_model(16);
+    let mut index = VectorIndex::new("tiny", 16, Pooling::Mean);
+    let config = ChunkConfig {
+        size: 64,
+        overlap: 0,
+    };
+    let stored = index
+        .ingest(&embeddings, "notes.md", TEXT, &config)
+        .unwrap();
+    assert_eq!(stored, chunk_text(TEXT, &config).len());
+    index
+        .ingest(
+            &embeddings,
+            "other.md",
+            "Vulkan runs compute shaders.",
+            &config,
+        )
+        .unwrap();
+
+    // A chunk's own text is its nearest neighbour.
+    let target = &index.chunks()[1];
+    let query = embeddings.embed(&target.text, Pooling::Mean);
+    let hits = index.search(&query, 3);
+    assert_eq!(hits.len(), 3);
+    assert_eq!(hits[0].chunk, target);
+    assert!((hits[0].score - 1.0).abs() < 1e-4);
+    assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notes.aurexri");
+    index.save(&path).unwrap();
+    let loaded = VectorIndex::load(&path).unwrap();
+    assert_eq!(loaded, index);
+    assert_eq!(loaded.search(&query, 1)[0].chunk, target);
+
+    // Ingesting a document again replaces its chunks.
+    let total = index.len();
+    index
+        .ingest(&embeddings, "notes.md", "Only one chunk now.", &config)
+        .unwrap();
+    assert_eq!(index.len(), total - stored + 1);
+    assert_eq!(index.remove_document("other.md"), 1);
+    assert_eq!(index.chunks()[0].text, "Only one chunk now.");
+
+    assert!(index
+        .ingest(&embedding_model(8), "x", "text", &config)
+        .is_err());
+    std::fs::write(&path, b"not an index").unwrap();
+    assert!(VectorIndex::load(&path).is_err());
+}
+
+#[test]
+fn retriever_injects_top_chunks_into_the_prompt() {
+    let embeddings = embedding_model(16);
+    let mut index = VectorIndex::new("tiny", 16, Pooling::Cls);
+    for (document, text) in [
+        ("a.md", "ROCm runs kernels on AMD GPUs."),
+        ("b.md", "Paged attention stores the KV cache in pages."),
+        ("c.md", "The planner encodes tool plans as state machines."),
+    ] {
+        index
+            .ingest(&embeddings, document, text, &ChunkConfig::default())
+            .unwrap();
+    }
+    let retriever = Retriever::new(&index, &embeddings).unwrap().with_top_k(2);
+    let query = "Paged attention stores the KV cache in pages.";
+    let (prompt, hits) = retriever.augment(query);
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].chunk.document, "b.md");
+    assert_eq!(
+        prompt,
+        format!(
+            "Answer the question using the context.\n\nContext:\n\
+             [1] b.md: {}\n[2] {}: {}\n\nQuestion: {query}\nAnswer:",
+            hits[0].chunk.text, hits[1].chunk.document, hits[1].chunk.text
+        )
+    );
+
+    let strict = Retriever::new(&index, &embeddings)
+        .unwrap()
+        .with_min_score(0.9999);
+    assert_eq!(strict.retrieve(query).len(), 1);
+    assert_eq!(augment_prompt("why?", &[]), "Question: why?\nAnswer:");
+    assert!(Retriever::new(&index, &embedding_model(8)).is_err());
+
+    let chunk = Chunk {
+        document: "d.md".into(),
+        start: 0,
+        text: "x".into(),
+    };
+    assert!(index.insert(chunk, vec![1.0; 3]).is_err());
+}
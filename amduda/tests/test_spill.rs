@@ -0,0 +1,111 @@
+use std::sync::{Arc, Mutex};
+
+use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager, MemoryTier};
+use amduda::amduda_core::spill::SpillStore;
+use aurex_utils::profiler::Profiler;
+use serial_test::serial;
+use tempfile::tempdir;
+
+fn cpu_and_nvme() -> DeviceCapabilities {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_HAS_NVME", "1");
+    DeviceCapabilities::detect()
+}
+
+#[test]
+#[serial]
+fn evicted_allocations_round_trip_through_spill_files() {
+    let dir = tempdir().unwrap();
+    let profiler = Arc::new(Mutex::new(Profiler::new()));
+    let store = SpillStore::new(dir.path()).unwrap().with_profiler(profiler.clone());
+    let mut mgr = MemoryManager::new_with_limits(cpu_and_nvme(), 0, 64, 1024)
+        .without_metrics()
+        .with_spill_store(store);
+
+    let a = mgr.allocate_data(vec![1; 48]);
+    let b = mgr.allocate_data(vec![2; 48]);
+    assert_eq!(mgr.tier(a), Some(MemoryTier::Nvme));
+    let spill_file = mgr.spill_store().unwrap().path(a);
+    assert_eq!(std::fs::read(&spill_file).unwrap(), vec![1; 48]);
+    assert_eq!(mgr.read(a).unwrap(), Some(vec![1; 48]));
+
+    // Promoting `a` restores it from disk and spills `b` in its place.
+    assert_eq!(mgr.migrate(a, MemoryTier::Cpu), Some(MemoryTier::Cpu));
+    assert!(!spill_file.exists());
+    assert_eq!(mgr.read(a).unwrap(), Some(vec![1; 48]));
+    assert_eq!(mgr.read(b).unwrap(), Some(vec![2; 48]));
+    assert_eq!(mgr.usage(), (0, 48, 48));
+
+    let stats = mgr.spill_store().unwrap().stats();
+    assert_eq!(stats.bytes_written, 96);
+    assert_eq!(stats.bytes_read, 144);
+    assert_eq!(profiler.lock().unwrap().spill_stats(), stats);
+
+    let b_file = mgr.spill_store().unwrap().path(b);
+    assert!(mgr.free(b));
+    assert!(!b_file.exists());
+}
+
+#[test]
+#[serial]
+fn unreadable_spill_files_keep_the_allocation_on_nvme() {
+    let dir = tempdir().unwrap();
+    let store = SpillStore::new(dir.path()).unwrap();
+    let mut mgr = MemoryManager::new_with_limits(cpu_and_nvme(), 0, 64, 1024)
+        .without_metrics()
+        .with_spill_store(store);
+
+    let a = mgr.allocate_data(vec![1; 48]);
+    let b = mgr.allocate_data(vec![2; 48]);
+    let spill_file = mgr.spill_store().unwrap().path(a);
+    std::fs::write(&spill_file, [1; 10]).unwrap();
+
+    // The short file fails the read: `a` stays on NVMe with its file kept
+    // and `b` is not evicted for it.
+    assert_eq!(mgr.migrate(a, MemoryTier::Cpu), Some(MemoryTier::Nvme));
+    assert!(mgr.spill_store().unwrap().contains(a));
+    assert!(spill_file.exists());
+    assert_eq!(mgr.tier(b), Some(MemoryTier::Cpu));
+    assert_eq!(mgr.usage(), (0, 48, 48));
+
+    std::fs::write(&spill_file, [1; 48]).unwrap();
+    assert_eq!(mgr.migrate(a, MemoryTier::Cpu), Some(MemoryTier::Cpu));
+    assert_eq!(mgr.read(a).unwrap(), Some(vec![1; 48]));
+    assert_eq!(mgr.read(b).unwrap(), Some(vec![2; 48]));
+}
+
+#[test]
+#[serial]
+fn oversized_allocations_are_spilled_immediately() {
+    let dir = tempdir().unwrap();
+    let store = SpillStore::new(dir.path()).unwrap();
+    let mut mgr = MemoryManager::new_with_limits(cpu_and_nvme(), 0, 64, 1024)
+        .without_metrics()
+        .with_spill_store(store);
+
+    let big = mgr.allocate_data((0..200u8).collect());
+    assert_eq!(mgr.tier(big), Some(MemoryTier::Nvme));
+    assert!(mgr.spill_store().unwrap().contains(big));
+    assert_eq!(mgr.read(big).unwrap(), Some((0..200u8).collect()));
+
+    drop(mgr);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+#[serial]
+fn direct_io_writes_unaligned_lengths() {
+    let dir = tempdir().unwrap();
+    let mut store = SpillStore::new(dir.path()).unwrap().with_direct_io(true);
+    let id = {
+        // Any id works; take one from a throwaway manager.
+        let mut mgr = MemoryManager::new_with_limits(cpu_and_nvme(), 0, 64, 0).without_metrics();
+        mgr.allocate(1)
+    };
+    let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    store.write(id, &data).unwrap();
+    assert_eq!(std::fs::metadata(store.path(id)).unwrap().len(), 5000);
+    assert_eq!(store.read(id).unwrap(), data);
+    assert!(store.remove(id));
+    assert!(!store.remove(id));
+}
@@ -1,9 +1,10 @@
-use amduda::amduda_core::tensor_ops::TensorOps;
+use amduda::amduda_core::tensor_ops::{Conv1dShape, TensorOps};
 use amduda::hal_backends::cpu_simd::CpuSimdBackend;
 use amduda::hal_backends::opencl_backend::{DeviceKind, OpenClBackend};
 use amduda::hal_backends::rocm_backend::RocmBackend;
 use amduda::hal_backends::vulkan_backend::VulkanBackend;
 use amduda::hal_backends::riscv_backend::RiscvBackend;
+use amduda::hal_backends::sycl_backend::SyclBackend;
 
 fn run_backend<B: TensorOps>(backend: &B) {
     // MatMul test 2x2 * 2x2
@@ -18,6 +19,17 @@ fn run_backend<B: TensorOps>(backend: &B) {
     let conv = backend.conv2d(&input, &kernel, (3, 3), (2, 2));
     assert_eq!(conv, vec![6.0, 8.0, 12.0, 14.0]);
 
+    // Conv1d test: one channel of 4 samples, 2-tap kernel with bias
+    let shape = Conv1dShape {
+        in_channels: 1,
+        out_channels: 1,
+        kernel: 2,
+        stride: 1,
+        padding: 0,
+    };
+    let conv = backend.conv1d(&[1.0, 2.0, 3.0, 4.0], &[1.0, 1.0], &[0.5], shape);
+    assert_eq!(conv, vec![3.5, 5.5, 7.5]);
+
     // Attention test with dim=2
     let q = vec![1.0, 0.0];
     let k = vec![1.0, 0.0];
@@ -52,9 +64,22 @@ fn vulkan_backend_ops() {
     }
 }
 
+#[test]
+fn sycl_backend_ops() {
+    let backend = SyclBackend::new();
+    // Without a SYCL device the emulated CPU device runs the ops.
+    if !backend.is_native() {
+        assert_eq!(backend.device_name(), "SYCL CPU");
+    }
+    run_backend(&backend);
+}
+
 #[test]
 fn opencl_cpu_backend_ops() {
     let backend = OpenClBackend::new(DeviceKind::Cpu);
+    if !backend.is_native() {
+        assert_eq!(backend.device().name, "OpenCL CPU");
+    }
     run_backend(&backend);
 }
 
@@ -67,8 +92,14 @@ fn opencl_gpu_backend_ops() {
 #[test]
 fn opencl_device_enumeration() {
     let devices = OpenClBackend::enumerate();
-    assert!(devices.iter().any(|d| d.kind == DeviceKind::Cpu));
-    assert!(devices.iter().any(|d| d.kind == DeviceKind::Gpu));
+    assert!(!devices.is_empty());
+    for (id, device) in devices.iter().enumerate() {
+        assert_eq!(device.id, id);
+    }
+    if !OpenClBackend::new(DeviceKind::Gpu).is_native() {
+        assert!(devices.iter().any(|d| d.kind == DeviceKind::Cpu));
+        assert!(devices.iter().any(|d| d.kind == DeviceKind::Gpu));
+    }
 }
 
 #[test]
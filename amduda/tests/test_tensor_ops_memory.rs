@@ -16,10 +16,11 @@ fn tensor_ops_allocation_respects_tiers() {
     let result = ops.matmul(&a, &b, 2, 2, 2);
     let bytes = result.len() * std::mem::size_of::<f32>();
 
-    let tier = mgr.allocate(bytes);
-    assert_eq!(tier, MemoryTier::Gpu);
+    let result_id = mgr.allocate(bytes);
+    assert_eq!(mgr.tier(result_id), Some(MemoryTier::Gpu));
 
-    mgr.mark_cold(MemoryTier::Gpu, bytes);
-    assert_eq!(mgr.allocate(64), MemoryTier::Gpu);
+    let next = mgr.allocate(64);
+    assert_eq!(mgr.tier(next), Some(MemoryTier::Gpu));
+    assert_eq!(mgr.tier(result_id), Some(MemoryTier::Cpu));
     assert_eq!(mgr.usage(), (64, bytes, 0));
 }
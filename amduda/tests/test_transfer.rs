@@ -0,0 +1,43 @@
+use amduda::amduda_core::memory_tiering::MemoryTier;
+use amduda::amduda_core::transfer::{TransferEngine, TransferKind, STAGING_CHUNK};
+use amduda::hal_backends::BackendKind;
+
+#[test]
+fn staged_transfer_round_trips_across_chunks() {
+    let engine = TransferEngine::for_backend(BackendKind::Rocm);
+    let src: Vec<u8> = (0..STAGING_CHUNK + 17).map(|i| (i % 251) as u8).collect();
+    let pending = engine.submit(TransferKind::HostToDevice, src.clone());
+    assert_eq!(pending.bytes(), src.len());
+    assert_eq!(pending.wait(), src);
+
+    let stats = engine.stats();
+    assert_eq!(stats.transfers, 1);
+    assert_eq!(stats.bytes, src.len() as u64);
+    assert_eq!(stats.staged_bytes, src.len() as u64);
+}
+
+#[test]
+fn host_backend_copies_without_staging() {
+    let engine = TransferEngine::for_backend(BackendKind::CpuSimd);
+    let handles: Vec<_> = (0..4u8)
+        .map(|i| engine.submit(TransferKind::NvmeToHost, vec![i; 64]))
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.wait(), vec![i as u8; 64]);
+    }
+    assert_eq!(engine.stats().staged_bytes, 0);
+    assert_eq!(engine.stats().bytes, 256);
+}
+
+#[test]
+fn tier_pairs_map_to_transfer_directions() {
+    assert_eq!(
+        TransferKind::between(MemoryTier::Cpu, MemoryTier::Gpu),
+        Some(TransferKind::HostToDevice)
+    );
+    assert_eq!(
+        TransferKind::between(MemoryTier::Nvme, MemoryTier::Cpu),
+        Some(TransferKind::NvmeToHost)
+    );
+    assert_eq!(TransferKind::between(MemoryTier::Gpu, MemoryTier::Nvme), None);
+}
@@ -0,0 +1,101 @@
+use amduda::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use amduda::hal_backends::verification::{
+    open_backend, verify, verify_all, verify_backend, Tolerance, VerifyConfig, OPS,
+};
+
+/// Reference ops with a layer norm that is off by `offset` and an attention
+/// that panics on large heads.
+struct Skewed {
+    offset: f32,
+}
+
+impl TensorOps for Skewed {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        CpuFallback.matmul(a, b, m, n, k)
+    }
+
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        CpuFallback.conv2d(input, kernel, input_shape, kernel_shape)
+    }
+
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        assert!(dim <= 8, "head too large");
+        CpuFallback.attention(q, k, v, dim)
+    }
+
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        CpuFallback
+            .layer_norm(x, gamma, beta, eps)
+            .into_iter()
+            .map(|v| v + self.offset)
+            .collect()
+    }
+}
+
+#[test]
+fn emulated_backends_conform() {
+    let config = VerifyConfig::new().with_cases(8).with_max_dim(16);
+    for name in ["cpu", "opencl", "sycl", "riscv"] {
+        let report = verify(name, &config).unwrap();
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.backend, name);
+        assert_eq!(report.ops.len(), OPS.len());
+        assert!(report.ops.iter().all(|op| op.cases == 8));
+    }
+    assert!(verify_all(&config).iter().any(|r| r.backend == "cpu"));
+}
+
+#[test]
+fn deviations_and_panics_are_reported() {
+    let config = VerifyConfig::new().with_cases(16).with_max_dim(32);
+    let report = verify_backend("skewed", &Skewed { offset: 1e-2 }, &config);
+    assert!(!report.passed());
+
+    let by_op = |op: &str| report.ops.iter().find(|r| r.op == op).unwrap();
+    assert!(by_op("matmul").passed());
+    assert!(by_op("conv2d").passed());
+
+    let norm = by_op("layer_norm");
+    assert_eq!(norm.failures, 16);
+    assert!((norm.max_abs_error - 1e-2).abs() < 1e-4);
+    assert!(norm.first_failure.as_deref().unwrap().starts_with("len "));
+
+    let attention = by_op("attention");
+    assert!(attention.failures > 0);
+    assert!(attention
+        .first_failure
+        .as_deref()
+        .unwrap()
+        .contains("panicked: head too large"));
+    assert!(report.to_string().starts_with("Backend skewed: FAIL"));
+
+    // A looser tolerance for the op accepts the offset.
+    let loose = config.with_op_tolerance("layer_norm", Tolerance::new(2e-2, 0.0));
+    let report = verify_backend("skewed", &Skewed { offset: 1e-2 }, &loose);
+    assert!(report
+        .ops
+        .iter()
+        .find(|r| r.op == "layer_norm")
+        .unwrap()
+        .passed());
+}
+
+#[test]
+fn runs_are_reproducible() {
+    let config = VerifyConfig::new().with_cases(4).with_seed(7);
+    let first = verify_backend("skewed", &Skewed { offset: 1.0 }, &config);
+    let second = verify_backend("skewed", &Skewed { offset: 1.0 }, &config);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn unknown_backends_are_rejected() {
+    let err = open_backend("tpu").err().unwrap();
+    assert!(err.contains("unknown backend"));
+}
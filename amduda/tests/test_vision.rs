@@ -0,0 +1,197 @@
+use std::io::Cursor;
+use std::sync::Arc;
+
+use amduda::amduda_core::memory_tiering::MemoryTier;
+use amduda::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use amduda::aurex_lm::model_loader::{LoadedModel, ModelConfig, Weights};
+use amduda::aurex_lm::tiny_lm::{LanguageModel, TinyLm};
+use amduda::aurex_lm::vision::{Image, PatchEmbedding, VisionConfig};
+use aurex_backend::dispatch::CpuBackend;
+
+fn encode_png(width: u32, height: u32, color: png::ColorType, pixels: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(pixels).unwrap();
+    writer.finish().unwrap();
+    out
+}
+
+fn config() -> VisionConfig {
+    VisionConfig {
+        image_size: 8,
+        patch_size: 4,
+        mean: [0.5; 3],
+        std: [0.25; 3],
+    }
+}
+
+fn weights(count: usize) -> Vec<f32> {
+    (0..count)
+        .map(|i| ((i * 13 % 29) as f32 - 14.0) / 20.0)
+        .collect()
+}
+
+fn gradient(width: usize, height: usize) -> Image {
+    let pixels: Vec<u8> = (0..width * height * 3)
+        .map(|i| (i * 37 % 256) as u8)
+        .collect();
+    Image::from_u8(width, height, 3, &pixels).unwrap()
+}
+
+#[test]
+fn png_images_decode_to_unit_values() {
+    let rgba = encode_png(
+        2,
+        1,
+        png::ColorType::Rgba,
+        &[255, 0, 51, 128, 0, 255, 0, 255],
+    );
+    let image = Image::decode_png(Cursor::new(rgba)).unwrap();
+    assert_eq!((image.width, image.height, image.channels), (2, 1, 4));
+    let rgb = image.to_rgb();
+    assert_eq!(rgb.data, vec![1.0, 0.0, 0.2, 0.0, 1.0, 0.0]);
+
+    let gray = encode_png(1, 2, png::ColorType::Grayscale, &[0, 255]);
+    let rgb = Image::decode_png(Cursor::new(gray)).unwrap().to_rgb();
+    assert_eq!(rgb.data, vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+
+    assert!(Image::decode_png(Cursor::new(b"not a png".to_vec())).is_err());
+    assert!(Image::from_u8(2, 2, 3, &[0; 5]).is_err());
+}
+
+#[test]
+fn preprocessing_resizes_normalizes_and_patchifies() {
+    // Upscaling interpolates between pixel centres and clamps at the edges.
+    let image = Image::from_u8(2, 1, 1, &[0, 255]).unwrap();
+    let wide = image.resize(4, 1);
+    assert_eq!(wide.data, vec![0.0, 0.25, 0.75, 1.0]);
+    assert_eq!(gradient(5, 3).resize(5, 3), gradient(5, 3));
+
+    let mut image = Image::from_u8(1, 1, 3, &[255, 0, 128]).unwrap();
+    image.normalize(&[0.5; 3], &[0.5; 3]);
+    assert_eq!(image.data[..2], [1.0, -1.0]);
+
+    // A 4x2 image with two channels cut into 2x2 patches.
+    let image = Image {
+        width: 4,
+        height: 2,
+        channels: 2,
+        data: (0..16).map(|v| v as f32).collect(),
+    };
+    let patches = image.patchify(2);
+    assert_eq!(
+        patches,
+        vec![
+            0.0, 2.0, 8.0, 10.0, 1.0, 3.0, 9.0, 11.0, // left patch
+            4.0, 6.0, 12.0, 14.0, 5.0, 7.0, 13.0, 15.0, // right patch
+        ]
+    );
+}
+
+#[test]
+fn patch_embedding_is_a_strided_convolution() {
+    let config = config();
+    let hidden = 3;
+    let weights = weights(config.weight_count(hidden));
+    let embedding = PatchEmbedding::new(config, hidden, &weights, Arc::new(CpuBackend)).unwrap();
+    let image = gradient(11, 6);
+    let out = embedding.forward(&image);
+    assert_eq!(out.len(), config.patches() * hidden);
+
+    // Reference: per channel convolutions sampled every `patch` pixels.
+    let mut input = image.resize(8, 8);
+    input.normalize(&config.mean, &config.std);
+    let patch = config.patch_size;
+    let kernel_len = patch * patch;
+    let bias = &weights[hidden * 3 * kernel_len..hidden * 3 * kernel_len + hidden];
+    let position = &weights[hidden * 3 * kernel_len + hidden..];
+    for h in 0..hidden {
+        let mut sums = [0.0; 25];
+        for c in 0..3 {
+            let plane: Vec<f32> = input.data.iter().skip(c).step_by(3).copied().collect();
+            let start = (h * 3 + c) * kernel_len;
+            let kernel = &weights[start..start + kernel_len];
+            let conv = CpuFallback.conv2d(&plane, kernel, (8, 8), (patch, patch));
+            for (s, v) in sums.iter_mut().zip(conv) {
+                *s += v;
+            }
+        }
+        for (p, (py, px)) in [(0, 0), (0, 4), (4, 0), (4, 4)].into_iter().enumerate() {
+            let expected = sums[py * 5 + px] + bias[h] + position[p * hidden + h];
+            let got = out[p * hidden + h];
+            assert!((got - expected).abs() < 1e-4, "{got} != {expected}");
+        }
+    }
+
+    assert!(PatchEmbedding::new(config, hidden, &weights[1..], Arc::new(CpuBackend)).is_err());
+}
+
+#[test]
+fn image_prefix_stays_in_front_of_the_context() {
+    let (vocab, hidden) = (5, 4);
+    let table = weights(vocab * hidden);
+    let prefix: Vec<f32> = (0..2 * hidden).map(|v| v as f32 / 4.0).collect();
+    let mut plain = TinyLm::new(&table, vocab, hidden, Arc::new(CpuBackend)).unwrap();
+    let mut lm = plain.clone();
+    assert!(lm.set_prefix_embeddings(&prefix[1..]).is_err());
+    lm.set_prefix_embeddings(&prefix).unwrap();
+    assert_eq!((lm.prefix_len(), lm.context_len()), (2, 0));
+
+    let first = lm.forward(1);
+    assert_ne!(first, plain.forward(1));
+    lm.forward(2);
+    assert_eq!(lm.context_len(), 2);
+    lm.truncate(1);
+    assert_eq!(lm.context_len(), 1);
+    lm.reset();
+    assert_eq!((lm.prefix_len(), lm.context_len()), (2, 0));
+    assert_eq!(lm.forward(1), first);
+}
+
+#[test]
+fn models_store_their_patch_embedding_after_the_embeddings() {
+    let config = config();
+    let (vocab, hidden) = (3, 2);
+    let values = weights(vocab * hidden + config.weight_count(hidden));
+    let model = LoadedModel {
+        config: ModelConfig {
+            name: "vlm".into(),
+            vocab_size: Some(vocab),
+            vision: Some(config),
+            ..ModelConfig::default()
+        },
+        weights: Weights::Memory(values.iter().flat_map(|v| v.to_le_bytes()).collect()),
+        tier: MemoryTier::Cpu,
+        scale: None,
+    };
+    assert_eq!(model.config.embedding_shape(values.len()), (vocab, hidden));
+    let names: Vec<String> = model.tensors().into_iter().map(|t| t.name).collect();
+    assert_eq!(
+        names,
+        [
+            "tok_embeddings",
+            "vision.patch_proj",
+            "vision.patch_bias",
+            "vision.pos_embed"
+        ]
+    );
+    let embedding = model.patch_embedding(Arc::new(CpuBackend)).unwrap();
+    let direct = PatchEmbedding::new(
+        config,
+        hidden,
+        &values[vocab * hidden..],
+        Arc::new(CpuBackend),
+    )
+    .unwrap();
+    let image = gradient(4, 4);
+    assert_eq!(embedding.forward(&image), direct.forward(&image));
+
+    let json = r#"{"name": "m", "weight_path": "w.bin", "vision": {"patch_size": 14}}"#;
+    let parsed: ModelConfig = serde_json::from_str(json).unwrap();
+    let vision = parsed.vision.unwrap();
+    assert_eq!((vision.image_size, vision.patch_size), (224, 14));
+    assert_eq!(vision.patches(), 256);
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use amduda::amduda_core::tensor_ops::CpuFallback;
+use amduda::aurex_lm::quantizer::{dequantize_int4, quantize_int4, Quantization};
+use amduda::aurex_lm::sampler::SamplingParams;
+use amduda::aurex_lm::tiny_lm::{LanguageModel, TinyLm};
+use amduda::aurex_lm::tokenizer::BYTE_VOCAB_SIZE;
+use amduda::aurex_lm::web_runtime::{decode_weights, WebModelConfig, WebRuntime};
+use aurex_backend::dispatch::CpuBackend;
+
+fn weights(count: usize) -> Vec<f32> {
+    (0..count)
+        .map(|i| ((i * 31 % 47) as f32 - 23.0) / 25.0)
+        .collect()
+}
+
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn greedy() -> SamplingParams {
+    SamplingParams {
+        temperature: 0.0,
+        ..SamplingParams::default()
+    }
+}
+
+#[test]
+fn logits_match_the_native_model() {
+    let (vocab, hidden) = (11, 6);
+    let table = weights(vocab * hidden);
+    let mut native = TinyLm::new(&table, vocab, hidden, Arc::new(CpuBackend)).unwrap();
+    let mut web = WebRuntime::new(&table, vocab, hidden).unwrap();
+    let mut fallback = web.clone().with_backend(Arc::new(CpuFallback));
+    for token in [3, 7, 7, 0, 10, 42] {
+        let expected = native.forward(token);
+        for got in [web.forward(token), fallback.forward(token)] {
+            assert_eq!(got.len(), vocab);
+            for (g, e) in got.iter().zip(&expected) {
+                assert!((g - e).abs() < 1e-4, "{g} != {e}");
+            }
+        }
+    }
+    assert_eq!(web.context_len(), 6);
+    web.reset();
+    assert_eq!(web.context_len(), 0);
+    assert!(WebRuntime::new(&table[1..], vocab, hidden).is_err());
+}
+
+#[test]
+fn weights_load_from_the_native_configuration() {
+    let hidden = 4;
+    let table = weights(BYTE_VOCAB_SIZE * hidden);
+    let config = r#"{"name": "tiny", "weight_path": "tiny.bin"}"#;
+    let mut runtime = WebRuntime::from_json(config, &f32_bytes(&table)).unwrap();
+    assert_eq!(
+        (runtime.vocab_size(), runtime.hidden_size()),
+        (BYTE_VOCAB_SIZE, hidden)
+    );
+    let mut direct = WebRuntime::new(&table, BYTE_VOCAB_SIZE, hidden).unwrap();
+    assert_eq!(runtime.forward(b'a'.into()), direct.forward(b'a'.into()));
+
+    // Quantized weights are decoded with their scale.
+    let (packed, scale) = quantize_int4(&table);
+    let json = format!(r#"{{"quantization": "int4", "scale": {scale}, "hidden_size": 4}}"#);
+    let mut quantized = WebRuntime::from_json(&json, &packed).unwrap();
+    let decoded = dequantize_int4(&packed, scale, table.len());
+    let mut reference = WebRuntime::new(&decoded, BYTE_VOCAB_SIZE, hidden).unwrap();
+    assert_eq!(quantized.forward(5), reference.forward(5));
+
+    assert!(decode_weights(&packed, Some(Quantization::Int4), None).is_err());
+    assert_eq!(
+        decode_weights(&f32_bytes(&[1.5, -2.0]), None, None).unwrap(),
+        [1.5, -2.0]
+    );
+    let blocked = WebModelConfig {
+        layout: serde_json::from_str(r#"{"blocked": {"cols": 8}}"#).unwrap(),
+        ..WebModelConfig::default()
+    };
+    let err = WebRuntime::from_bytes(&blocked, &f32_bytes(&table)).unwrap_err();
+    assert!(err.to_string().contains("row-major"), "{err}");
+    assert!(WebRuntime::from_json("not json", &[]).is_err());
+}
+
+#[test]
+fn generation_is_deterministic_and_bounded() {
+    let hidden = 8;
+    let table = weights(BYTE_VOCAB_SIZE * hidden);
+    let mut runtime = WebRuntime::new(&table, BYTE_VOCAB_SIZE, hidden).unwrap();
+    let text = runtime.generate("ab", 5, greedy());
+    // The prompt and at most five generated tokens are in the context.
+    assert!(runtime.context_len() <= 2 + 5);
+    assert!(text.chars().count() <= 5);
+
+    runtime.reset();
+    assert_eq!(runtime.generate("ab", 5, greedy()), text);
+    runtime.reset();
+    assert_eq!(runtime.generate("ab", 0, greedy()), "");
+}
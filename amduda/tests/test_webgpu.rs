@@ -0,0 +1,67 @@
+#![cfg(feature = "webgpu")]
+
+use std::sync::Arc;
+
+use amduda::amduda_core::tensor_ops::{CpuFallback, TensorOps};
+use amduda::aurex_lm::web_runtime::WebRuntime;
+use amduda::hal_backends::webgpu_backend::WebGpuBackend;
+
+fn backend() -> Option<WebGpuBackend> {
+    match WebGpuBackend::new() {
+        Ok(backend) => Some(backend),
+        Err(err) => {
+            eprintln!("skipping WebGPU test: {err:#}");
+            None
+        }
+    }
+}
+
+fn values(count: usize, step: usize) -> Vec<f32> {
+    (0..count)
+        .map(|i| ((i * step % 17) as f32 - 8.0) / 8.0)
+        .collect()
+}
+
+fn assert_close(got: &[f32], expected: &[f32]) {
+    assert_eq!(got.len(), expected.len());
+    for (g, e) in got.iter().zip(expected) {
+        assert!((g - e).abs() < 1e-4, "{g} != {e}");
+    }
+}
+
+#[test]
+fn matmul_matches_the_cpu() {
+    let Some(gpu) = backend() else { return };
+    // Sizes that do not fill whole workgroups.
+    let (m, n, k) = (3, 13, 7);
+    let a = values(m * k, 5);
+    let b = values(k * n, 3);
+    let expected = CpuFallback.matmul(&a, &b, m, n, k);
+    let got = pollster::block_on(gpu.matmul_async(&a, &b, m, n, k)).unwrap();
+    assert_close(&got, &expected);
+    assert_close(&gpu.matmul(&a, &b, m, n, k), &expected);
+
+    // Resident weights are reused across calls.
+    let resident = gpu.upload(&b);
+    assert_eq!(resident.len(), k * n);
+    for row in a.chunks_exact(k) {
+        let got = pollster::block_on(gpu.matmul_tensor(row, &resident, 1, n, k)).unwrap();
+        assert_close(&got, &CpuFallback.matmul(row, &b, 1, n, k));
+    }
+    assert!(pollster::block_on(gpu.matmul_tensor(&a, &resident, m, n + 1, k)).is_err());
+    assert!(pollster::block_on(gpu.matmul_async(&a, &b, 0, n, k))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn web_runtime_projects_on_the_gpu() {
+    let Some(gpu) = backend() else { return };
+    let (vocab, hidden) = (19, 8);
+    let table = values(vocab * hidden, 7);
+    let mut cpu = WebRuntime::new(&table, vocab, hidden).unwrap();
+    let mut webgpu = cpu.clone().with_backend(Arc::new(gpu));
+    for token in [1, 4, 18] {
+        assert_close(&webgpu.forward(token), &cpu.forward(token));
+    }
+}
@@ -0,0 +1,74 @@
+//! Multi-turn conversations with an agent.
+//!
+//! [`converse`] runs one turn of a conversation kept in a runtime
+//! [`Session`]: the user input joins the history, the agent perceives the
+//! whole transcript and reasons about it, and its reply is recorded as the
+//! assistant's message before the agent acts on it.  Turns are charged to
+//! the session's token budget by the bytes of the input and the reply, the
+//! token count of the byte-level tokenizer.
+
+use aurex_runtime::{Role, Session, SessionError};
+
+use crate::agent::Agent;
+
+/// Run one turn of `session` with `agent` and return the agent's reply.
+/// Fails without changing the session once its token budget is used up.
+pub async fn converse<A: Agent + ?Sized, K>(
+    agent: &A,
+    session: &mut Session<K>,
+    input: &str,
+) -> Result<String, SessionError> {
+    session.check_budget()?;
+    session.push(Role::User, input);
+    let state = agent.perceive(&session.prompt()).await;
+    let output = agent.reason(&state).await;
+    session.charge(input.len() + output.len());
+    session.push(Role::Assistant, output.clone());
+    agent.act(&output).await;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    /// Agent replying with the number of user turns it has seen.
+    struct Counter;
+
+    #[async_trait]
+    impl Agent for Counter {
+        async fn perceive(&self, input: &str) -> String {
+            input.to_string()
+        }
+
+        async fn reason(&self, state: &str) -> String {
+            state.matches("user: ").count().to_string()
+        }
+
+        async fn act(&self, _output: &str) {}
+    }
+
+    #[tokio::test]
+    async fn turns_see_the_whole_history() {
+        let mut session: Session = Session::new(1, Some("Count.".into()));
+        assert_eq!(converse(&Counter, &mut session, "a").await.unwrap(), "1");
+        assert_eq!(converse(&Counter, &mut session, "b").await.unwrap(), "2");
+        assert_eq!(
+            session.transcript(),
+            "system: Count.\nuser: a\nassistant: 1\nuser: b\nassistant: 2\n"
+        );
+        assert_eq!(session.tokens_used(), 4);
+    }
+
+    #[tokio::test]
+    async fn exhausted_budgets_stop_turns() {
+        let mut session: Session = Session::new(7, None).with_token_limit(3);
+        assert_eq!(converse(&Counter, &mut session, "hi").await.unwrap(), "1");
+        assert_eq!(
+            converse(&Counter, &mut session, "again").await,
+            Err(SessionError::BudgetExhausted { id: 7, limit: 3 })
+        );
+        assert_eq!(session.messages().len(), 2);
+    }
+}
@@ -1,4 +1,11 @@
-//! Agent module exposing the core agent trait and symbolic FSM logic.
+//! Agent module exposing the core agent trait, symbolic FSM logic, tool
+//! calling, planning, multi-turn conversations, sandboxed tool execution
+//! and web access tools.
 
 pub mod agent;
+pub mod conversation;
+pub mod planner;
+pub mod sandbox;
 pub mod symbolic_fsm;
+pub mod tools;
+pub mod web;
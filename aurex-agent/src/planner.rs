@@ -0,0 +1,311 @@
+//! Planning goals into symbolic state machines.
+//!
+//! The [`Planner`] asks an agent's reasoning step for a plan, a sequence of
+//! tool calls in the format understood by [`ToolRouter::parse`], and checks
+//! it against the available tools.  [`Plan::to_state_machine`] encodes the
+//! plan as a [`StateMachine`]: `start` leads to one state per step, each
+//! holding the `tool` and `arguments` to call, and every step moves on with
+//! the `ok` symbol or to `failed` with `error`.  [`execute_plan`] walks the
+//! machine state by state, storing each step's `result` or `error` in its
+//! state.
+
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::agent::Agent;
+use crate::symbolic_fsm::{StateMachine, SymbolicState};
+use crate::tools::{ToolCall, ToolResult, ToolRouter};
+
+/// Initial state of a plan.
+pub const START: &str = "start";
+/// State reached once every step succeeded.
+pub const DONE: &str = "done";
+/// State reached when a step failed.
+pub const FAILED: &str = "failed";
+
+/// Error raised when a plan cannot be built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanError {
+    /// The reasoning output contained no tool calls.
+    NoSteps,
+    /// A step calls a tool that is not available.
+    UnknownTool(String),
+    /// The plan has more steps than the planner allows.
+    TooManySteps(usize),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::NoSteps => write!(f, "plan has no steps"),
+            PlanError::UnknownTool(name) => write!(f, "plan calls unknown tool '{name}'"),
+            PlanError::TooManySteps(steps) => write!(f, "plan has too many steps ({steps})"),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// Ordered tool calls achieving a goal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub goal: String,
+    pub steps: Vec<ToolCall>,
+}
+
+impl Plan {
+    /// Parse the tool calls in `output` as a plan for `goal`, checking that
+    /// every step uses one of `tools`.
+    pub fn parse(goal: &str, output: &str, tools: &ToolRouter) -> Result<Self, PlanError> {
+        let steps = ToolRouter::parse(output);
+        if steps.is_empty() {
+            return Err(PlanError::NoSteps);
+        }
+        if let Some(step) = steps.iter().find(|step| tools.tool(&step.name).is_none()) {
+            return Err(PlanError::UnknownTool(step.name.clone()));
+        }
+        Ok(Self {
+            goal: goal.to_string(),
+            steps,
+        })
+    }
+
+    /// Name of the state running step `index`.
+    pub fn step_state(index: usize) -> String {
+        format!("step-{index}")
+    }
+
+    /// Encode the plan as a state machine, see the [module
+    /// documentation](self).  The machine starts in [`START`]; the `next`
+    /// symbol enters the first step.
+    pub fn to_state_machine(&self) -> StateMachine {
+        let mut start = SymbolicState::new(START);
+        start.set("goal", self.goal.clone());
+        let mut machine = StateMachine::new(start);
+        machine.add_state(SymbolicState::new(DONE));
+        machine.add_state(SymbolicState::new(FAILED));
+
+        let mut previous = (START.to_string(), "next");
+        for (index, step) in self.steps.iter().enumerate() {
+            let name = Self::step_state(index);
+            let mut state = SymbolicState::new(name.clone());
+            state.set("tool", step.name.clone());
+            state.set("arguments", step.arguments.to_string());
+            machine.add_state(state);
+            machine.add_transition(previous.0, previous.1, name.clone());
+            machine.add_transition(name.clone(), "error", FAILED);
+            previous = (name, "ok");
+        }
+        machine.add_transition(previous.0, previous.1, DONE);
+        machine
+    }
+}
+
+/// Turns goals into [`Plan`]s using an agent's reasoning step.
+#[derive(Debug, Clone)]
+pub struct Planner {
+    max_steps: usize,
+}
+
+impl Default for Planner {
+    fn default() -> Self {
+        Self { max_steps: 16 }
+    }
+}
+
+impl Planner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject plans with more than `max_steps` steps.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// JSON schema of a plan using `tools`, for schema-constrained
+    /// generation of the reasoning output.
+    pub fn schema(tools: &ToolRouter) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "steps": { "type": "array", "items": tools.call_schema(), "minItems": 1 },
+            },
+            "required": ["steps"],
+        })
+    }
+
+    /// Prompt asking for a plan achieving `goal` with `tools`.
+    pub fn prompt(&self, goal: &str, tools: &ToolRouter) -> String {
+        format!(
+            "Goal: {goal}\nTools: {}\nAnswer with a plan of at most {} steps as JSON matching {}",
+            tools.describe(),
+            self.max_steps,
+            Self::schema(tools)
+        )
+    }
+
+    /// Ask `agent` to plan `goal` and parse its reasoning output.
+    pub async fn plan<A: Agent + ?Sized>(
+        &self,
+        agent: &A,
+        goal: &str,
+        tools: &ToolRouter,
+    ) -> Result<Plan, PlanError> {
+        let state = agent.perceive(&self.prompt(goal, tools)).await;
+        let output = agent.reason(&state).await;
+        let plan = Plan::parse(goal, &output, tools)?;
+        if plan.steps.len() > self.max_steps {
+            return Err(PlanError::TooManySteps(plan.steps.len()));
+        }
+        Ok(plan)
+    }
+}
+
+/// Run the plan encoded in `machine` from its current state until it reaches
+/// [`DONE`] or [`FAILED`], returning the result of every executed step.
+pub async fn execute_plan(machine: &mut StateMachine, tools: &ToolRouter) -> Vec<ToolResult> {
+    if machine.current_state().name() == START {
+        machine.step("next");
+    }
+    let mut results = Vec::new();
+    loop {
+        let state = machine.current_state();
+        let Some(name) = state.get("tool").cloned() else {
+            break;
+        };
+        let arguments = state
+            .get("arguments")
+            .and_then(|args| serde_json::from_str(args).ok())
+            .unwrap_or(Value::Null);
+        let call = ToolCall { name, arguments };
+        let output = tools.execute(&call).await;
+        let symbol = match &output {
+            Ok(value) => {
+                machine.current_state_mut().set("result", value.to_string());
+                "ok"
+            }
+            Err(err) => {
+                machine.current_state_mut().set("error", err.to_string());
+                "error"
+            }
+        };
+        results.push(ToolResult { call, output });
+        if machine.step(symbol).is_none() {
+            break;
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{Tool, ToolError};
+    use async_trait::async_trait;
+
+    struct Lookup;
+
+    #[async_trait]
+    impl Tool for Lookup {
+        fn name(&self) -> &str {
+            "lookup"
+        }
+
+        fn schema(&self) -> Value {
+            json!({
+                "type": "object",
+                "properties": { "key": { "type": "string" } },
+                "required": ["key"]
+            })
+        }
+
+        async fn invoke(&self, arguments: Value) -> Result<Value, ToolError> {
+            match arguments["key"].as_str() {
+                Some("missing") => Err(ToolError::Failed("no such key".into())),
+                Some(key) => Ok(json!(key.to_uppercase())),
+                None => Err(ToolError::Failed("key must be a string".into())),
+            }
+        }
+    }
+
+    /// Agent whose reasoning always returns a fixed plan.
+    struct Scripted(&'static str);
+
+    #[async_trait]
+    impl Agent for Scripted {
+        async fn perceive(&self, input: &str) -> String {
+            input.to_string()
+        }
+
+        async fn reason(&self, state: &str) -> String {
+            assert!(state.contains("Goal: fetch"));
+            self.0.to_string()
+        }
+
+        async fn act(&self, _output: &str) {}
+    }
+
+    const PLAN: &str = r#"{"steps": [
+        {"name": "lookup", "arguments": {"key": "a"}},
+        {"name": "lookup", "arguments": {"key": "b"}}
+    ]}"#;
+
+    #[tokio::test]
+    async fn plans_run_state_by_state() {
+        let tools = ToolRouter::new().with_tool(Lookup);
+        let plan = Planner::new()
+            .plan(&Scripted(PLAN), "fetch a and b", &tools)
+            .await
+            .unwrap();
+        assert_eq!(plan.steps.len(), 2);
+
+        let mut machine = plan.to_state_machine();
+        assert_eq!(
+            machine.current_state().get("goal").unwrap(),
+            "fetch a and b"
+        );
+        let results = execute_plan(&mut machine, &tools).await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].output, Ok(json!("B")));
+        assert_eq!(machine.current_state().name(), DONE);
+    }
+
+    #[tokio::test]
+    async fn failed_steps_stop_the_plan() {
+        let tools = ToolRouter::new().with_tool(Lookup);
+        let plan = Plan::parse(
+            "fetch",
+            r#"{"name": "lookup", "arguments": {"key": "missing"}}
+               {"name": "lookup", "arguments": {"key": "b"}}"#,
+            &tools,
+        )
+        .unwrap();
+        let mut machine = plan.to_state_machine();
+        let results = execute_plan(&mut machine, &tools).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(machine.current_state().name(), FAILED);
+    }
+
+    #[tokio::test]
+    async fn invalid_plans_are_rejected() {
+        let tools = ToolRouter::new().with_tool(Lookup);
+        let planner = Planner::new().with_max_steps(1);
+        assert_eq!(
+            planner.plan(&Scripted(PLAN), "fetch", &tools).await,
+            Err(PlanError::TooManySteps(2))
+        );
+        assert_eq!(
+            planner.plan(&Scripted("no plan"), "fetch", &tools).await,
+            Err(PlanError::NoSteps)
+        );
+        let unknown = r#"{"name": "delete", "arguments": {}}"#;
+        assert_eq!(
+            planner.plan(&Scripted(unknown), "fetch", &tools).await,
+            Err(PlanError::UnknownTool("delete".into()))
+        );
+        assert_eq!(Planner::schema(&tools)["required"], json!(["steps"]));
+    }
+}
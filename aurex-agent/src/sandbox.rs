@@ -0,0 +1,689 @@
+//! Sandboxed execution of agent tools.
+//!
+//! A [`Sandbox`] runs the programs tool calls ask for as subprocesses
+//! working under a root directory.  Only allow-listed programs run; they get
+//! an environment holding nothing but `PATH` and `HOME` (the root), limits
+//! on address space, CPU time and written file size, and are killed along
+//! with everything they started once the timeout passes.  Their stdout and
+//! stderr are captured up to `max_output` bytes each.
+//!
+//! [`ShellTool`] and [`FileTool`] expose a sandbox to a
+//! [`ToolRouter`](crate::tools::ToolRouter), so that model output can run
+//! commands and read or write files under the root.  File paths, and every
+//! command argument, are checked with [`Sandbox::resolve`]: absolute paths,
+//! `..` and links out of the root are refused, including as the value of an
+//! `--option=value`.  The processes themselves are not confined to the root,
+//! though.  The allow-list checks the program a call names, not what that
+//! program does: a program that finds paths on its own, or a shell or an
+//! interpreter given a script, can read whatever the server's user can, and
+//! run anything within the resource limits.
+//!
+//! Commands run on the calling thread; [`Tool::invoke`] blocks until they
+//! exit or time out.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::tools::{Tool, ToolError};
+
+/// Error raised by a [`Sandbox`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxError {
+    /// The program is not on the allow-list.
+    NotAllowed(String),
+    /// The path leaves the sandbox root.
+    OutsideRoot(String),
+    /// Starting the program or accessing a file failed.
+    Io(String),
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SandboxError::NotAllowed(program) => write!(f, "program '{program}' is not allowed"),
+            SandboxError::OutsideRoot(path) => write!(f, "path '{path}' is outside the sandbox"),
+            SandboxError::Io(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for SandboxError {}
+
+impl From<SandboxError> for ToolError {
+    fn from(err: SandboxError) -> Self {
+        ToolError::Failed(err.to_string())
+    }
+}
+
+/// Resource limits of sandboxed processes; `None` keeps the host's limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Address space in bytes.
+    pub memory_bytes: Option<u64>,
+    /// CPU time in seconds.
+    pub cpu_seconds: Option<u64>,
+    /// Largest file a process may write, in bytes.
+    pub file_size_bytes: Option<u64>,
+    /// Processes of the sandbox's user, including ones outside it.
+    pub processes: Option<u64>,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            memory_bytes: Some(1 << 30),
+            cpu_seconds: Some(10),
+            file_size_bytes: Some(64 << 20),
+            processes: Some(256),
+        }
+    }
+}
+
+/// Captured result of a sandboxed command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecOutput {
+    /// Exit code, `None` when the process was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// The process was killed for exceeding the timeout.
+    pub timed_out: bool,
+    /// Output beyond `max_output` bytes was dropped.
+    pub truncated: bool,
+    pub elapsed: Duration,
+}
+
+impl ExecOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "exit_code": self.exit_code,
+            "stdout": self.stdout,
+            "stderr": self.stderr,
+            "timed_out": self.timed_out,
+            "truncated": self.truncated,
+        })
+    }
+}
+
+/// Confined executor for tool commands, see the
+/// [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+    allowed: BTreeSet<String>,
+    timeout: Duration,
+    max_output: usize,
+    limits: ResourceLimits,
+}
+
+impl Sandbox {
+    /// Sandbox working under `root`, allowing no programs yet.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            allowed: BTreeSet::new(),
+            timeout: Duration::from_secs(10),
+            max_output: 64 << 10,
+            limits: ResourceLimits::default(),
+        }
+    }
+
+    /// Allow running `program`, a name looked up on `PATH` or a path.
+    pub fn with_command(mut self, program: impl Into<String>) -> Self {
+        self.allowed.insert(program.into());
+        self
+    }
+
+    /// Wall-clock time after which commands are killed.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Bytes of stdout and of stderr kept per command.
+    pub fn with_max_output(mut self, bytes: usize) -> Self {
+        self.max_output = bytes;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Allow-listed programs, in sorted order.
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.allowed.iter().map(String::as_str)
+    }
+
+    /// Run `program` with `args` in the root, feeding it `stdin`.  Every
+    /// argument other than a bare option must [`resolve`](Self::resolve)
+    /// inside the root.  A command that exits non-zero or times out is not
+    /// an error; its output says so.
+    pub fn run(
+        &self,
+        program: &str,
+        args: &[String],
+        stdin: Option<&str>,
+    ) -> Result<ExecOutput, SandboxError> {
+        if !self.allowed.contains(program) {
+            return Err(SandboxError::NotAllowed(program.to_string()));
+        }
+        for arg in args {
+            let path = match arg.strip_prefix('-') {
+                Some(option) => match option.split_once('=') {
+                    Some((_, value)) => value,
+                    None => continue,
+                },
+                None => arg.as_str(),
+            };
+            if !path.is_empty() {
+                self.resolve(path).map_err(|err| match err {
+                    SandboxError::OutsideRoot(_) => SandboxError::OutsideRoot(arg.clone()),
+                    err => err,
+                })?;
+            }
+        }
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .current_dir(&self.root)
+            .env_clear()
+            .env("HOME", &self.root)
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        confine(&mut command, self.limits);
+
+        let started = Instant::now();
+        let mut child = command
+            .spawn()
+            .map_err(|err| SandboxError::Io(format!("cannot run '{program}': {err}")))?;
+        if let (Some(mut pipe), Some(input)) = (child.stdin.take(), stdin) {
+            let input = input.to_string();
+            // A child that exits early closes the pipe; that is its business.
+            std::thread::spawn(move || {
+                let _ = pipe.write_all(input.as_bytes());
+            });
+        }
+        let stdout = capture(child.stdout.take(), self.max_output);
+        let stderr = capture(child.stderr.take(), self.max_output);
+
+        let mut timed_out = false;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Some(status),
+                Ok(None) if started.elapsed() >= self.timeout => {
+                    timed_out = true;
+                    kill(&mut child);
+                    break child.wait().ok();
+                }
+                Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+                Err(err) => return Err(SandboxError::Io(err.to_string())),
+            }
+        };
+        // Background processes the command left behind would keep the pipes
+        // open.
+        kill(&mut child);
+        let (stdout, stdout_truncated) = stdout.join().unwrap_or_default();
+        let (stderr, stderr_truncated) = stderr.join().unwrap_or_default();
+        Ok(ExecOutput {
+            exit_code: status.and_then(|status| status.code()),
+            stdout,
+            stderr,
+            timed_out,
+            truncated: stdout_truncated || stderr_truncated,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Path of `path`, relative to the root, on the host.  Absolute paths,
+    /// `..` and symbolic links leading out of the root are rejected, as are
+    /// dangling links, which a write would follow to create their target.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, SandboxError> {
+        let outside = || SandboxError::OutsideRoot(path.to_string());
+        let relative = Path::new(path);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(outside());
+        }
+        let root = self
+            .root
+            .canonicalize()
+            .map_err(|err| SandboxError::Io(format!("sandbox root: {err}")))?;
+        let mut prefix = root.clone();
+        for component in relative.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            prefix.push(name);
+            match prefix.symlink_metadata() {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    let target = prefix.canonicalize().map_err(|_| outside())?;
+                    if !target.starts_with(&root) {
+                        return Err(outside());
+                    }
+                }
+                Ok(_) => {}
+                // Nothing below a missing entry exists either.
+                Err(_) => break,
+            }
+        }
+        Ok(root.join(relative))
+    }
+}
+
+/// Read `pipe` to its end on a thread, keeping the first `max` bytes.
+fn capture<R: Read + Send + 'static>(pipe: Option<R>, max: usize) -> JoinHandle<(String, bool)> {
+    std::thread::spawn(move || {
+        let Some(mut pipe) = pipe else {
+            return (String::new(), false);
+        };
+        let mut kept = Vec::new();
+        let mut truncated = false;
+        let mut buf = [0; 8192];
+        // Keep draining past the limit so the child never blocks on a full
+        // pipe.
+        while let Ok(n) = pipe.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let room = max - kept.len();
+            kept.extend_from_slice(&buf[..n.min(room)]);
+            truncated |= n > room;
+        }
+        (String::from_utf8_lossy(&kept).into_owned(), truncated)
+    })
+}
+
+/// Start the child in a process group of its own under `limits`.
+#[cfg(unix)]
+fn confine(command: &mut Command, limits: ResourceLimits) {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0);
+    let limits = [
+        (libc::RLIMIT_AS, limits.memory_bytes),
+        (libc::RLIMIT_CPU, limits.cpu_seconds),
+        (libc::RLIMIT_FSIZE, limits.file_size_bytes),
+        (libc::RLIMIT_NPROC, limits.processes),
+    ];
+    // SAFETY: setrlimit is async-signal-safe and the closure allocates
+    // nothing.
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, limit) in limits {
+                let Some(limit) = limit else { continue };
+                let rlimit = libc::rlimit {
+                    rlim_cur: limit as libc::rlim_t,
+                    rlim_max: limit as libc::rlim_t,
+                };
+                if libc::setrlimit(resource, &rlimit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Resource limits need setrlimit; elsewhere only the timeout applies.
+#[cfg(not(unix))]
+fn confine(_command: &mut Command, _limits: ResourceLimits) {}
+
+/// Kill the child and, on unix, every process of its group.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: kill has no memory safety requirements; the group was created
+    // for the child by `confine`.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+/// Tool running allow-listed commands in a [`Sandbox`].
+pub struct ShellTool {
+    sandbox: Arc<Sandbox>,
+    description: String,
+}
+
+impl ShellTool {
+    pub fn new(sandbox: Arc<Sandbox>) -> Self {
+        let commands: Vec<&str> = sandbox.commands().collect();
+        let description = format!(
+            "Run a command in the working directory; allowed commands: {}",
+            commands.join(", ")
+        );
+        Self {
+            sandbox,
+            description,
+        }
+    }
+}
+
+#[async_trait]
+impl Tool for ShellTool {
+    fn name(&self) -> &str {
+        "shell"
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn schema(&self) -> Value {
+        let commands: Vec<&str> = self.sandbox.commands().collect();
+        json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string", "enum": commands },
+                "args": { "type": "array", "items": { "type": "string" } },
+                "stdin": { "type": "string" },
+            },
+            "required": ["command"],
+        })
+    }
+
+    async fn invoke(&self, arguments: Value) -> Result<Value, ToolError> {
+        let invalid = |message: &str| ToolError::InvalidArguments {
+            tool: self.name().to_string(),
+            message: message.to_string(),
+        };
+        let command = arguments["command"]
+            .as_str()
+            .ok_or_else(|| invalid("command must be a string"))?;
+        let args = match &arguments["args"] {
+            Value::Null => Vec::new(),
+            Value::Array(args) => args
+                .iter()
+                .map(|arg| arg.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or_else(|| invalid("args must be strings"))?,
+            _ => return Err(invalid("args must be an array")),
+        };
+        let stdin = arguments["stdin"].as_str();
+        Ok(self.sandbox.run(command, &args, stdin)?.to_json())
+    }
+}
+
+/// Tool reading, writing and listing files under a [`Sandbox`]'s root.
+pub struct FileTool {
+    sandbox: Arc<Sandbox>,
+}
+
+impl FileTool {
+    pub fn new(sandbox: Arc<Sandbox>) -> Self {
+        Self { sandbox }
+    }
+}
+
+#[async_trait]
+impl Tool for FileTool {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn description(&self) -> &str {
+        "Read, write or list files relative to the working directory"
+    }
+
+    fn schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "action": { "enum": ["read", "write", "list"] },
+                "path": { "type": "string" },
+                "content": { "type": "string" },
+            },
+            "required": ["action", "path"],
+        })
+    }
+
+    async fn invoke(&self, arguments: Value) -> Result<Value, ToolError> {
+        let invalid = |message: &str| ToolError::InvalidArguments {
+            tool: self.name().to_string(),
+            message: message.to_string(),
+        };
+        let path = arguments["path"]
+            .as_str()
+            .ok_or_else(|| invalid("path must be a string"))?;
+        let resolved = self.sandbox.resolve(path)?;
+        let io = |err: std::io::Error| ToolError::Failed(format!("{path}: {err}"));
+        match arguments["action"].as_str() {
+            Some("read") => {
+                let mut content = Vec::new();
+                let file = std::fs::File::open(&resolved).map_err(io)?;
+                let max = self.sandbox.max_output as u64;
+                file.take(max + 1).read_to_end(&mut content).map_err(io)?;
+                let truncated = content.len() as u64 > max;
+                content.truncate(self.sandbox.max_output);
+                Ok(json!({
+                    "content": String::from_utf8_lossy(&content),
+                    "truncated": truncated,
+                }))
+            }
+            Some("write") => {
+                let content = arguments["content"]
+                    .as_str()
+                    .ok_or_else(|| invalid("content must be a string"))?;
+                if let Some(limit) = self.sandbox.limits.file_size_bytes {
+                    if content.len() as u64 > limit {
+                        return Err(ToolError::Failed(format!(
+                            "{path}: content exceeds the {limit} byte file size limit"
+                        )));
+                    }
+                }
+                if let Some(parent) = resolved.parent() {
+                    std::fs::create_dir_all(parent).map_err(io)?;
+                }
+                std::fs::write(&resolved, content).map_err(io)?;
+                Ok(json!({ "written": content.len() }))
+            }
+            Some("list") => {
+                let mut entries = Vec::new();
+                for entry in std::fs::read_dir(&resolved).map_err(io)? {
+                    let entry = entry.map_err(io)?;
+                    let dir = entry.file_type().map_err(io)?.is_dir();
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    entries.push(json!({ "name": name, "dir": dir }));
+                }
+                entries.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+                Ok(json!({ "entries": entries }))
+            }
+            _ => Err(invalid("action must be read, write or list")),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::tools::{ToolCall, ToolRouter};
+
+    fn sh(script: &str) -> Vec<String> {
+        vec!["-c".into(), script.into()]
+    }
+
+    #[test]
+    fn runs_allow_listed_commands_with_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let sandbox = Sandbox::new(dir.path())
+            .with_command("sh")
+            .with_limits(ResourceLimits {
+                memory_bytes: Some(512 << 20),
+                cpu_seconds: Some(3),
+                ..ResourceLimits::default()
+            });
+
+        let output = sandbox
+            .run("sh", &sh("cat; echo oops >&2; pwd; exit 3"), Some("in\n"))
+            .unwrap();
+        assert_eq!(output.exit_code, Some(3));
+        let root = dir.path().canonicalize().unwrap();
+        assert_eq!(output.stdout, format!("in\n{}\n", root.display()));
+        assert_eq!(output.stderr, "oops\n");
+        assert!(!output.timed_out && !output.truncated);
+
+        // ulimit reports the address space in KiB.
+        let limits = sandbox
+            .run("sh", &sh("ulimit -v; ulimit -t"), None)
+            .unwrap();
+        assert_eq!(limits.stdout, format!("{}\n3\n", 512 << 10));
+        let env = sandbox.run("sh", &sh("env"), None).unwrap();
+        assert!(!env.stdout.contains("CARGO"), "{}", env.stdout);
+
+        assert_eq!(
+            sandbox.run("ls", &[], None),
+            Err(SandboxError::NotAllowed("ls".into()))
+        );
+        assert!(matches!(
+            sandbox.run("/bin/sh", &sh("true"), None),
+            Err(SandboxError::NotAllowed(_))
+        ));
+    }
+
+    #[test]
+    fn kills_commands_at_the_timeout_and_caps_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let sandbox = Sandbox::new(dir.path())
+            .with_command("sh")
+            .with_timeout(Duration::from_millis(200))
+            .with_max_output(1000);
+
+        // The background sleep holds the pipes open; it is killed too.
+        let output = sandbox
+            .run("sh", &sh("sleep 30 & echo started; wait"), None)
+            .unwrap();
+        assert!(output.timed_out);
+        assert_eq!(output.exit_code, None);
+        assert_eq!(output.stdout, "started\n");
+        assert!(output.elapsed < Duration::from_secs(10));
+
+        let output = sandbox
+            .run("sh", &sh("head -c 100000 /dev/zero | tr '\\0' x"), None)
+            .unwrap();
+        assert!(output.success());
+        assert!(output.truncated);
+        assert_eq!(output.stdout, "x".repeat(1000));
+    }
+
+    #[tokio::test]
+    async fn tools_stay_inside_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let sandbox = Arc::new(Sandbox::new(dir.path().join("work")).with_command("cat"));
+        std::fs::create_dir(sandbox.root()).unwrap();
+        std::os::unix::fs::symlink(dir.path(), sandbox.root().join("up")).unwrap();
+        let router = ToolRouter::new()
+            .with_tool(ShellTool::new(sandbox.clone()))
+            .with_tool(FileTool::new(sandbox.clone()));
+        let call = |name: &str, arguments| ToolCall {
+            name: name.into(),
+            arguments,
+        };
+
+        let written = router
+            .execute(&call(
+                "file",
+                json!({ "action": "write", "path": "notes/a.txt", "content": "hello" }),
+            ))
+            .await;
+        assert_eq!(written, Ok(json!({ "written": 5 })));
+        let read = router
+            .execute(&call(
+                "shell",
+                json!({ "command": "cat", "args": ["notes/a.txt"] }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(read["stdout"], "hello");
+        assert_eq!(read["exit_code"], 0);
+        let listed = router
+            .execute(&call("file", json!({ "action": "list", "path": "." })))
+            .await
+            .unwrap();
+        assert_eq!(
+            listed["entries"],
+            json!([{ "name": "notes", "dir": true }, { "name": "up", "dir": false }])
+        );
+
+        std::os::unix::fs::symlink(dir.path().join("new"), sandbox.root().join("dangling"))
+            .unwrap();
+        for path in [
+            "../secret",
+            "/etc/passwd",
+            "up/secret",
+            "notes/../../secret",
+            "dangling",
+            "./dangling",
+        ] {
+            let result = router
+                .execute(&call(
+                    "file",
+                    json!({ "action": "write", "path": path, "content": "x" }),
+                ))
+                .await;
+            assert_eq!(
+                result,
+                Err(SandboxError::OutsideRoot(path.into()).into()),
+                "{path}"
+            );
+        }
+        assert!(!dir.path().join("secret").exists());
+        assert!(!dir.path().join("new").exists());
+        assert_eq!(
+            router
+                .execute(&call(
+                    "file",
+                    json!({ "action": "read", "path": "dangling" })
+                ))
+                .await,
+            Err(SandboxError::OutsideRoot("dangling".into()).into())
+        );
+        std::fs::write(dir.path().join("secret"), "hidden").unwrap();
+        for arg in ["/etc/passwd", "../secret", "up/secret", "--from=../secret"] {
+            let result = router
+                .execute(&call("shell", json!({ "command": "cat", "args": [arg] })))
+                .await;
+            assert_eq!(
+                result,
+                Err(SandboxError::OutsideRoot(arg.into()).into()),
+                "{arg}"
+            );
+        }
+        assert!(matches!(
+            router.execute(&call("shell", json!({ "command": "rm" }))).await,
+            Err(ToolError::Failed(message)) if message.contains("not allowed")
+        ));
+        assert_eq!(
+            ShellTool::new(sandbox).schema()["properties"]["command"]["enum"],
+            json!(["cat"])
+        );
+    }
+}
@@ -1,4 +1,11 @@
 //! Symbolic finite state machine utilities for agents.
+//!
+//! Besides plain symbol-driven transitions, a [`StateMachine`] supports
+//! guards that only allow a transition when the source state's data satisfies
+//! a predicate, entry and exit actions, and nested sub-machines attached to a
+//! state.  While such a state is current, symbols are first offered to its
+//! sub-machine; only symbols the sub-machine cannot handle move the outer
+//! machine.
 
 use std::collections::HashMap;
 
@@ -30,12 +37,27 @@ impl SymbolicState {
     }
 }
 
+/// Predicate over the data of the source state of a transition.
+pub type Guard = Box<dyn Fn(&SymbolicState) -> bool + Send + Sync>;
+
+/// Action run when a state is entered or exited.
+pub type Action = Box<dyn Fn(&mut SymbolicState) + Send + Sync>;
+
+struct Transition {
+    to: String,
+    guard: Option<Guard>,
+}
+
 /// A simple deterministic symbolic finite state machine. Transitions are
 /// triggered by symbolic events represented as strings.
 pub struct StateMachine {
+    initial: String,
     current: String,
     states: HashMap<String, SymbolicState>,
-    transitions: HashMap<(String, String), String>,
+    transitions: HashMap<(String, String), Vec<Transition>>,
+    on_enter: HashMap<String, Vec<Action>>,
+    on_exit: HashMap<String, Vec<Action>>,
+    submachines: HashMap<String, StateMachine>,
 }
 
 impl StateMachine {
@@ -44,7 +66,15 @@ impl StateMachine {
         let current = initial.name.clone();
         let mut states = HashMap::new();
         states.insert(initial.name.clone(), initial);
-        Self { current, states, transitions: HashMap::new() }
+        Self {
+            initial: current.clone(),
+            current,
+            states,
+            transitions: HashMap::new(),
+            on_enter: HashMap::new(),
+            on_exit: HashMap::new(),
+            submachines: HashMap::new(),
+        }
     }
 
     /// Add a state to the machine.
@@ -59,22 +89,131 @@ impl StateMachine {
         symbol: impl Into<String>,
         to: impl Into<String>,
     ) {
-        self.transitions.insert((from.into(), symbol.into()), to.into());
+        self.push_transition(from.into(), symbol.into(), to.into(), None);
+    }
+
+    /// Define a transition that is only taken while `guard` holds for the
+    /// data of the source state. Transitions for the same state and symbol
+    /// are tried in the order they were added.
+    pub fn add_guarded_transition(
+        &mut self,
+        from: impl Into<String>,
+        symbol: impl Into<String>,
+        to: impl Into<String>,
+        guard: impl Fn(&SymbolicState) -> bool + Send + Sync + 'static,
+    ) {
+        self.push_transition(from.into(), symbol.into(), to.into(), Some(Box::new(guard)));
+    }
+
+    fn push_transition(&mut self, from: String, symbol: String, to: String, guard: Option<Guard>) {
+        self.transitions
+            .entry((from, symbol))
+            .or_default()
+            .push(Transition { to, guard });
+    }
+
+    /// Run `action` whenever a transition enters `state`.
+    pub fn on_enter(
+        &mut self,
+        state: impl Into<String>,
+        action: impl Fn(&mut SymbolicState) + Send + Sync + 'static,
+    ) {
+        self.on_enter.entry(state.into()).or_default().push(Box::new(action));
+    }
+
+    /// Run `action` whenever a transition leaves `state`.
+    pub fn on_exit(
+        &mut self,
+        state: impl Into<String>,
+        action: impl Fn(&mut SymbolicState) + Send + Sync + 'static,
+    ) {
+        self.on_exit.entry(state.into()).or_default().push(Box::new(action));
+    }
+
+    /// Nest `machine` inside `state`. The sub-machine restarts from its
+    /// initial state each time `state` is entered and is exited together with
+    /// it.
+    pub fn add_submachine(&mut self, state: impl Into<String>, machine: StateMachine) {
+        self.submachines.insert(state.into(), machine);
     }
 
-    /// Advance the machine using the provided symbol. Returns the new state if
-    /// the transition exists, otherwise returns `None` and the state remains
-    /// unchanged.
+    /// Sub-machine nested in `state`, if any.
+    pub fn submachine(&self, state: &str) -> Option<&StateMachine> {
+        self.submachines.get(state)
+    }
+
+    /// Names of the current state and of the current states of the active
+    /// sub-machines, outermost first.
+    pub fn active_path(&self) -> Vec<&str> {
+        let mut path = vec![self.current.as_str()];
+        if let Some(sub) = self.submachines.get(&self.current) {
+            path.extend(sub.active_path());
+        }
+        path
+    }
+
+    /// Advance the machine using the provided symbol. The active sub-machine
+    /// gets the first chance to handle it. Returns the current state of this
+    /// machine if a transition was taken, otherwise returns `None` and the
+    /// state remains unchanged.
     pub fn step(&mut self, symbol: impl AsRef<str>) -> Option<&SymbolicState> {
-        let key = (self.current.clone(), symbol.as_ref().to_string());
-        if let Some(next) = self.transitions.get(&key).cloned() {
-            self.current = next;
+        if self.handle(symbol.as_ref()) {
             self.states.get(&self.current)
         } else {
             None
         }
     }
 
+    fn handle(&mut self, symbol: &str) -> bool {
+        if let Some(sub) = self.submachines.get_mut(&self.current) {
+            if sub.handle(symbol) {
+                return true;
+            }
+        }
+        let key = (self.current.clone(), symbol.to_string());
+        let source = self.current_state();
+        let next = self.transitions.get(&key).and_then(|transitions| {
+            transitions
+                .iter()
+                .find(|t| t.guard.as_ref().is_none_or(|guard| guard(source)))
+                .map(|t| t.to.clone())
+        });
+        match next {
+            Some(next) => {
+                self.exit();
+                self.current = next;
+                self.enter();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn enter(&mut self) {
+        Self::run_actions(&self.on_enter, &self.current, &mut self.states);
+        if let Some(sub) = self.submachines.get_mut(&self.current) {
+            sub.current = sub.initial.clone();
+            sub.enter();
+        }
+    }
+
+    fn exit(&mut self) {
+        if let Some(sub) = self.submachines.get_mut(&self.current) {
+            sub.exit();
+        }
+        Self::run_actions(&self.on_exit, &self.current, &mut self.states);
+    }
+
+    fn run_actions(
+        actions: &HashMap<String, Vec<Action>>,
+        name: &str,
+        states: &mut HashMap<String, SymbolicState>,
+    ) {
+        if let (Some(actions), Some(state)) = (actions.get(name), states.get_mut(name)) {
+            actions.iter().for_each(|action| action(state));
+        }
+    }
+
     /// Return a reference to the current state.
     pub fn current_state(&self) -> &SymbolicState {
         self.states
@@ -126,5 +265,75 @@ mod tests {
         assert!(sm.step("missing").is_none());
         assert_eq!(sm.current_state().name(), "a");
     }
+
+    #[test]
+    fn guards_choose_between_transitions() {
+        let mut sm = StateMachine::new(SymbolicState::new("review"));
+        sm.add_state(SymbolicState::new("approved"));
+        sm.add_state(SymbolicState::new("rejected"));
+        sm.add_guarded_transition("review", "decide", "approved", |s| {
+            s.get("score").and_then(|v| v.parse::<u32>().ok()) >= Some(7)
+        });
+        sm.add_transition("review", "decide", "rejected");
+
+        sm.current_state_mut().set("score", "9");
+        assert_eq!(sm.step("decide").unwrap().name(), "approved");
+
+        let mut sm = StateMachine::new(SymbolicState::new("locked"));
+        sm.add_state(SymbolicState::new("open"));
+        sm.add_guarded_transition("locked", "push", "open", |s| s.get("key").is_some());
+        assert!(sm.step("push").is_none());
+        sm.current_state_mut().set("key", "k");
+        assert_eq!(sm.step("push").unwrap().name(), "open");
+    }
+
+    #[test]
+    fn entry_and_exit_actions_update_state_data() {
+        let mut sm = StateMachine::new(SymbolicState::new("idle"));
+        sm.add_state(SymbolicState::new("working"));
+        sm.add_transition("idle", "start", "working");
+        sm.add_transition("working", "stop", "idle");
+        sm.on_enter("working", |s| {
+            let n = s.get("entered").map_or(0, |v| v.parse::<u32>().unwrap());
+            s.set("entered", (n + 1).to_string());
+        });
+        sm.on_exit("working", |s| s.set("exited", "yes"));
+
+        sm.step("start");
+        sm.step("stop");
+        sm.step("start");
+        assert_eq!(sm.current_state().get("entered"), Some(&"2".to_string()));
+        assert_eq!(sm.current_state().get("exited"), Some(&"yes".to_string()));
+    }
+
+    #[test]
+    fn submachines_handle_symbols_first() {
+        let mut inner = StateMachine::new(SymbolicState::new("draft"));
+        inner.add_state(SymbolicState::new("edit"));
+        inner.add_transition("draft", "next", "edit");
+        inner.on_exit("edit", |s| s.set("saved", "true"));
+
+        let mut sm = StateMachine::new(SymbolicState::new("idle"));
+        sm.add_state(SymbolicState::new("writing"));
+        sm.add_state(SymbolicState::new("done"));
+        sm.add_transition("idle", "next", "writing");
+        sm.add_transition("writing", "next", "done");
+        sm.add_transition("done", "again", "writing");
+        sm.add_submachine("writing", inner);
+
+        sm.step("next");
+        assert_eq!(sm.active_path(), vec!["writing", "draft"]);
+        sm.step("next").expect("handled by the sub-machine");
+        assert_eq!(sm.active_path(), vec!["writing", "edit"]);
+        // The sub-machine has no further `next`, so the outer machine moves.
+        sm.step("next").expect("handled by the outer machine");
+        assert_eq!(sm.active_path(), vec!["done"]);
+        let inner = sm.submachine("writing").unwrap();
+        assert_eq!(inner.current_state().get("saved"), Some(&"true".to_string()));
+
+        // Re-entering the state restarts the sub-machine.
+        sm.step("again");
+        assert_eq!(sm.active_path(), vec!["writing", "draft"]);
+    }
 }
 
@@ -0,0 +1,404 @@
+//! Tool calling for agents.
+//!
+//! A [`Tool`] exposes an external capability under a name, with a JSON schema
+//! describing its arguments.  The [`ToolRouter`] finds tool calls of the form
+//! `{"name": "search", "arguments": {...}}` in model output, invokes the
+//! matching tools and renders their results as an observation that is fed to
+//! the agent's next [`perceive`](crate::agent::Agent::perceive) step.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::agent::Agent;
+
+/// Error raised while routing or invoking a tool call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolError {
+    /// No tool with this name is registered.
+    UnknownTool(String),
+    /// The arguments do not fit the tool's schema.
+    InvalidArguments { tool: String, message: String },
+    /// The tool ran but failed.
+    Failed(String),
+    /// The agent kept calling tools for more than the allowed rounds.
+    TooManyRounds(usize),
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolError::UnknownTool(name) => write!(f, "unknown tool '{name}'"),
+            ToolError::InvalidArguments { tool, message } => {
+                write!(f, "invalid arguments for tool '{tool}': {message}")
+            }
+            ToolError::Failed(message) => write!(f, "tool failed: {message}"),
+            ToolError::TooManyRounds(rounds) => {
+                write!(f, "agent still calling tools after {rounds} rounds")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+/// Capability an agent can invoke.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    /// Name the model uses to call the tool.
+    fn name(&self) -> &str;
+
+    /// Short description shown to the model.
+    fn description(&self) -> &str {
+        ""
+    }
+
+    /// JSON schema of the arguments object.
+    fn schema(&self) -> Value;
+
+    /// Run the tool with arguments matching [`Tool::schema`].
+    async fn invoke(&self, arguments: Value) -> Result<Value, ToolError>;
+}
+
+/// Tool call parsed from model output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// Outcome of a [`ToolCall`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolResult {
+    pub call: ToolCall,
+    pub output: Result<Value, ToolError>,
+}
+
+impl ToolResult {
+    /// JSON line reporting the result to the model.
+    pub fn to_observation(&self) -> String {
+        match &self.output {
+            Ok(value) => json!({ "tool": self.call.name, "result": value }),
+            Err(err) => json!({ "tool": self.call.name, "error": err.to_string() }),
+        }
+        .to_string()
+    }
+}
+
+/// Registry of tools that executes the calls found in model output.
+#[derive(Default, Clone)]
+pub struct ToolRouter {
+    tools: BTreeMap<String, Arc<dyn Tool>>,
+}
+
+impl ToolRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `tool`, replacing any tool of the same name.
+    pub fn register(&mut self, tool: Arc<dyn Tool>) {
+        self.tools.insert(tool.name().to_string(), tool);
+    }
+
+    pub fn with_tool(mut self, tool: impl Tool + 'static) -> Self {
+        self.register(Arc::new(tool));
+        self
+    }
+
+    pub fn tool(&self, name: &str) -> Option<&Arc<dyn Tool>> {
+        self.tools.get(name)
+    }
+
+    /// Names of the registered tools, in sorted order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tools.keys().map(String::as_str)
+    }
+
+    /// Name, description and argument schema of every tool, for prompts.
+    pub fn describe(&self) -> Value {
+        self.tools
+            .values()
+            .map(|tool| {
+                json!({
+                    "name": tool.name(),
+                    "description": tool.description(),
+                    "parameters": tool.schema(),
+                })
+            })
+            .collect()
+    }
+
+    /// JSON schema matching a single call of any registered tool.  Passing it
+    /// to schema-constrained generation makes every output a valid call.
+    pub fn call_schema(&self) -> Value {
+        let calls: Vec<Value> = self
+            .tools
+            .values()
+            .map(|tool| {
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "arguments": tool.schema(),
+                        "name": { "const": tool.name() },
+                    },
+                    "required": ["arguments", "name"],
+                })
+            })
+            .collect();
+        json!({ "anyOf": calls })
+    }
+
+    /// Extract the tool calls from `output`.  Every JSON object with a string
+    /// `name` and an `arguments` field counts as a call, also when nested in
+    /// other JSON such as `{"steps": [...]}`; `arguments` may also be a
+    /// string holding JSON.  Other text is ignored.
+    pub fn parse(output: &str) -> Vec<ToolCall> {
+        let mut calls = Vec::new();
+        let mut rest = output;
+        while let Some(start) = rest.find('{') {
+            let candidate = &rest[start..];
+            let mut values = serde_json::Deserializer::from_str(candidate).into_iter::<Value>();
+            match values.next() {
+                Some(Ok(value)) => {
+                    let end = values.byte_offset();
+                    Self::collect_calls(value, &mut calls);
+                    rest = &candidate[end..];
+                }
+                _ => rest = &candidate[1..],
+            }
+        }
+        calls
+    }
+
+    fn collect_calls(value: Value, calls: &mut Vec<ToolCall>) {
+        match value {
+            Value::Object(mut obj) => {
+                if let (Some(Value::String(name)), Some(arguments)) =
+                    (obj.get("name").cloned(), obj.remove("arguments"))
+                {
+                    let arguments = match arguments {
+                        Value::String(text) => match serde_json::from_str(&text) {
+                            Ok(arguments) => arguments,
+                            Err(_) => return,
+                        },
+                        arguments => arguments,
+                    };
+                    calls.push(ToolCall { name, arguments });
+                } else {
+                    obj.into_iter()
+                        .for_each(|(_, value)| Self::collect_calls(value, calls));
+                }
+            }
+            Value::Array(values) => values
+                .into_iter()
+                .for_each(|value| Self::collect_calls(value, calls)),
+            _ => {}
+        }
+    }
+
+    /// Invoke the tool named by `call`.  The arguments must be an object
+    /// holding the properties the tool's schema marks as required.
+    pub async fn execute(&self, call: &ToolCall) -> Result<Value, ToolError> {
+        let tool = self
+            .tools
+            .get(&call.name)
+            .ok_or_else(|| ToolError::UnknownTool(call.name.clone()))?;
+        let invalid = |message: String| ToolError::InvalidArguments {
+            tool: call.name.clone(),
+            message,
+        };
+        let arguments = call
+            .arguments
+            .as_object()
+            .ok_or_else(|| invalid("arguments must be an object".into()))?;
+        let schema = tool.schema();
+        let required = schema["required"].as_array().into_iter().flatten();
+        for key in required.filter_map(Value::as_str) {
+            if !arguments.contains_key(key) {
+                return Err(invalid(format!("missing '{key}'")));
+            }
+        }
+        tool.invoke(call.arguments.clone()).await
+    }
+
+    /// Execute every call in `output`, in order.
+    pub async fn route(&self, output: &str) -> Vec<ToolResult> {
+        let mut results = Vec::new();
+        for call in Self::parse(output) {
+            let output = self.execute(&call).await;
+            results.push(ToolResult { call, output });
+        }
+        results
+    }
+
+    /// Drive `agent` through perceive/reason/act rounds starting from
+    /// `input`.  While the reasoning output contains tool calls, they are
+    /// executed and their observations become the input of the next round;
+    /// failed calls are reported to the agent rather than aborting.  The
+    /// first output without calls is passed to `act` and returned.
+    pub async fn run<A: Agent + ?Sized>(
+        &self,
+        agent: &A,
+        input: &str,
+        max_rounds: usize,
+    ) -> Result<String, ToolError> {
+        let mut input = input.to_string();
+        for _ in 0..max_rounds {
+            let state = agent.perceive(&input).await;
+            let output = agent.reason(&state).await;
+            let results = self.route(&output).await;
+            if results.is_empty() {
+                agent.act(&output).await;
+                return Ok(output);
+            }
+            input = results
+                .iter()
+                .map(ToolResult::to_observation)
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+        Err(ToolError::TooManyRounds(max_rounds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Add;
+
+    #[async_trait]
+    impl Tool for Add {
+        fn name(&self) -> &str {
+            "add"
+        }
+
+        fn description(&self) -> &str {
+            "Add two numbers"
+        }
+
+        fn schema(&self) -> Value {
+            json!({
+                "type": "object",
+                "properties": { "a": { "type": "number" }, "b": { "type": "number" } },
+                "required": ["a", "b"]
+            })
+        }
+
+        async fn invoke(&self, arguments: Value) -> Result<Value, ToolError> {
+            match (arguments["a"].as_f64(), arguments["b"].as_f64()) {
+                (Some(a), Some(b)) => Ok(json!(a + b)),
+                _ => Err(ToolError::Failed("a and b must be numbers".into())),
+            }
+        }
+    }
+
+    /// Agent that asks for a sum, then answers with the observation it got.
+    #[derive(Default)]
+    struct Calculator {
+        seen: Mutex<Vec<String>>,
+        acted: Mutex<Option<String>>,
+    }
+
+    #[async_trait]
+    impl Agent for Calculator {
+        async fn perceive(&self, input: &str) -> String {
+            self.seen.lock().unwrap().push(input.to_string());
+            input.to_string()
+        }
+
+        async fn reason(&self, state: &str) -> String {
+            if state.contains("\"result\"") {
+                format!("The answer is in {state}")
+            } else {
+                r#"Let me add. {"name": "add", "arguments": {"a": 2, "b": 3}}"#.to_string()
+            }
+        }
+
+        async fn act(&self, output: &str) {
+            *self.acted.lock().unwrap() = Some(output.to_string());
+        }
+    }
+
+    #[test]
+    fn parse_finds_calls_in_text() {
+        let output = r#"think {not json} {"name": "add", "arguments": {"a": 1, "b": 2}}
+            {"other": 1} {"name": "add", "arguments": "{\"a\": 4, \"b\": 5}"}
+            {"tool_calls": [{"name": "add", "arguments": {"a": 0, "b": 0}}]}"#;
+        let calls = ToolRouter::parse(output);
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].arguments, json!({ "a": 1, "b": 2 }));
+        assert_eq!(calls[1].arguments, json!({ "a": 4, "b": 5 }));
+        assert!(ToolRouter::parse("no calls here").is_empty());
+    }
+
+    #[tokio::test]
+    async fn execute_validates_calls() {
+        let router = ToolRouter::new().with_tool(Add);
+        let call = |name: &str, arguments| ToolCall {
+            name: name.into(),
+            arguments,
+        };
+        assert_eq!(
+            router
+                .execute(&call("add", json!({ "a": 1, "b": 2 })))
+                .await,
+            Ok(json!(3.0))
+        );
+        assert_eq!(
+            router.execute(&call("mul", json!({}))).await,
+            Err(ToolError::UnknownTool("mul".into()))
+        );
+        assert!(matches!(
+            router.execute(&call("add", json!({ "a": 1 }))).await,
+            Err(ToolError::InvalidArguments { .. })
+        ));
+        assert!(matches!(
+            router
+                .execute(&call("add", json!({ "a": "x", "b": 1 })))
+                .await,
+            Err(ToolError::Failed(_))
+        ));
+        assert_eq!(router.describe()[0]["description"], "Add two numbers");
+        assert_eq!(
+            router.call_schema()["anyOf"][0]["properties"]["name"]["const"],
+            "add"
+        );
+    }
+
+    #[tokio::test]
+    async fn run_feeds_results_back_to_the_agent() {
+        let router = ToolRouter::new().with_tool(Add);
+        let agent = Calculator::default();
+        let answer = router.run(&agent, "what is 2 + 3?", 4).await.unwrap();
+        assert!(answer.starts_with("The answer is in"));
+        assert!(answer.contains(r#""result":5.0"#), "{answer}");
+        assert_eq!(agent.seen.lock().unwrap().len(), 2);
+        assert_eq!(
+            agent.acted.lock().unwrap().as_deref(),
+            Some(answer.as_str())
+        );
+
+        let stubborn = ToolRouter::new().with_tool(Add);
+        struct Loop;
+        #[async_trait]
+        impl Agent for Loop {
+            async fn perceive(&self, input: &str) -> String {
+                input.to_string()
+            }
+            async fn reason(&self, _state: &str) -> String {
+                r#"{"name": "add", "arguments": {"a": 1, "b": 1}}"#.to_string()
+            }
+            async fn act(&self, _output: &str) {}
+        }
+        assert_eq!(
+            stubborn.run(&Loop, "go", 3).await,
+            Err(ToolError::TooManyRounds(3))
+        );
+    }
+}
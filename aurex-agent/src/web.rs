@@ -0,0 +1,597 @@
+//! Web access tools for agents.
+//!
+//! [`HttpTool`] lets a model fetch pages and call APIs with `GET` and `POST`
+//! requests, but only on the hosts of its domain allow-list; a domain also
+//! allows its subdomains.  Redirects are not followed, the response reports
+//! them so that the model can request the target, which is checked again.
+//!
+//! [`SearchTool`] answers queries through a pluggable [`SearchProvider`]:
+//! [`CorpusSearch`] ranks documents held in memory and [`SearxSearch`] asks
+//! a SearXNG instance.  Together with a
+//! [`ToolRouter`](crate::tools::ToolRouter) they give an agent the retrieval
+//! half of retrieval-augmented reasoning.  Bodies and snippets are cut to a
+//! bounded size so results fit into the next prompt.
+//!
+//! Requests run on the calling thread, like the commands of a
+//! [`Sandbox`](crate::sandbox::Sandbox).
+
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::tools::{Tool, ToolError};
+
+/// Tool making HTTP requests to allow-listed domains.
+pub struct HttpTool {
+    domains: BTreeSet<String>,
+    agent: ureq::Agent,
+    max_body: usize,
+}
+
+impl HttpTool {
+    /// Tool allowed to reach `domains` and their subdomains.
+    pub fn new<I, S>(domains: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            domains: domains
+                .into_iter()
+                .map(|domain| domain.into().to_ascii_lowercase())
+                .collect(),
+            agent: agent(Duration::from_secs(10)),
+            max_body: 64 << 10,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.agent = agent(timeout);
+        self
+    }
+
+    /// Bytes of a response body passed to the model.
+    pub fn with_max_body(mut self, bytes: usize) -> Self {
+        self.max_body = bytes;
+        self
+    }
+
+    /// Whether `url` is an `http` or `https` URL of an allowed host.
+    pub fn allows(&self, url: &Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        self.domains.iter().any(|domain| {
+            host == *domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+}
+
+fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(timeout)
+        .redirects(0)
+        .build()
+}
+
+#[async_trait]
+impl Tool for HttpTool {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn description(&self) -> &str {
+        "Fetch a URL with GET or send a body to it with POST"
+    }
+
+    fn schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "url": { "type": "string" },
+                "method": { "enum": ["GET", "POST"] },
+                "body": { "type": "string" },
+                "content_type": { "type": "string" },
+            },
+            "required": ["url"],
+        })
+    }
+
+    async fn invoke(&self, arguments: Value) -> Result<Value, ToolError> {
+        let invalid = |message: String| ToolError::InvalidArguments {
+            tool: self.name().to_string(),
+            message,
+        };
+        let url = arguments["url"]
+            .as_str()
+            .ok_or_else(|| invalid("url must be a string".into()))?;
+        let url = Url::parse(url).map_err(|err| invalid(format!("invalid url: {err}")))?;
+        if !self.allows(&url) {
+            return Err(ToolError::Failed(format!("{url} is not on the allow-list")));
+        }
+        let response = match arguments["method"].as_str().unwrap_or("GET") {
+            "GET" => self.agent.request_url("GET", &url).call(),
+            "POST" => {
+                let content_type = arguments["content_type"]
+                    .as_str()
+                    .unwrap_or("application/json");
+                self.agent
+                    .request_url("POST", &url)
+                    .set("Content-Type", content_type)
+                    .send_string(arguments["body"].as_str().unwrap_or_default())
+            }
+            method => return Err(invalid(format!("unsupported method {method}"))),
+        };
+        // Error statuses are for the model to read, not failures of the tool.
+        let response = match response {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => return Err(ToolError::Failed(format!("{url}: {err}"))),
+        };
+        let status = response.status();
+        let content_type = response.content_type().to_string();
+        let location = response.header("Location").map(str::to_string);
+        let (body, truncated) = read_body(response, self.max_body)
+            .map_err(|err| ToolError::Failed(format!("{url}: {err}")))?;
+        let mut result = json!({
+            "status": status,
+            "content_type": content_type,
+            "body": body,
+            "truncated": truncated,
+        });
+        if let Some(location) = location {
+            result["location"] = json!(location);
+        }
+        Ok(result)
+    }
+}
+
+/// The first `max` bytes of the body of `response`, and whether there was
+/// more.
+fn read_body(response: ureq::Response, max: usize) -> std::io::Result<(String, bool)> {
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(max as u64 + 1)
+        .read_to_end(&mut body)?;
+    let truncated = body.len() > max;
+    body.truncate(max);
+    Ok((String::from_utf8_lossy(&body).into_owned(), truncated))
+}
+
+/// Document found by a [`SearchProvider`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    /// Passage of the document matching the query.
+    pub snippet: String,
+}
+
+/// Source of search results for a [`SearchTool`].
+#[async_trait]
+pub trait SearchProvider: Send + Sync {
+    /// Up to `limit` results for `query`, best first.
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, ToolError>;
+}
+
+/// Longest snippet a provider returns, in bytes.
+const SNIPPET_LEN: usize = 240;
+
+/// Search over documents held in memory, ranked by the number of query
+/// words they contain.
+#[derive(Debug, Clone, Default)]
+pub struct CorpusSearch {
+    documents: Vec<(String, String, String)>,
+}
+
+impl CorpusSearch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_document(
+        mut self,
+        title: impl Into<String>,
+        url: impl Into<String>,
+        text: impl Into<String>,
+    ) -> Self {
+        self.documents.push((title.into(), url.into(), text.into()));
+        self
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[async_trait]
+impl SearchProvider for CorpusSearch {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, ToolError> {
+        let query: BTreeSet<String> = words(query).collect();
+        let mut ranked: Vec<(usize, &(String, String, String))> = self
+            .documents
+            .iter()
+            .map(|document| {
+                let (title, _, text) = document;
+                let found: BTreeSet<String> = words(title).chain(words(text)).collect();
+                (query.intersection(&found).count(), document)
+            })
+            .filter(|(score, _)| *score > 0)
+            .collect();
+        // Stable, so equally good documents keep their order.
+        ranked.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        Ok(ranked
+            .into_iter()
+            .take(limit)
+            .map(|(_, (title, url, text))| SearchResult {
+                title: title.clone(),
+                url: url.clone(),
+                snippet: snippet(text, &query),
+            })
+            .collect())
+    }
+}
+
+/// Up to [`SNIPPET_LEN`] bytes of `text` starting at the sentence holding
+/// the first query word.
+fn snippet(text: &str, query: &BTreeSet<String>) -> String {
+    let lower = text.to_lowercase();
+    let first = query
+        .iter()
+        .filter_map(|word| lower.find(word.as_str()))
+        .min()
+        .unwrap_or(0);
+    // Lowercasing may change byte lengths; fall back to the text's start.
+    let first = if text.is_char_boundary(first) {
+        first
+    } else {
+        0
+    };
+    let start = text[..first]
+        .rfind(['.', '!', '?', '\n'])
+        .map_or(0, |end| end + 1);
+    let mut end = (start + SNIPPET_LEN).min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[start..end].trim().to_string()
+}
+
+/// Search through the JSON API of a SearXNG instance.
+pub struct SearxSearch {
+    endpoint: String,
+    agent: ureq::Agent,
+}
+
+impl SearxSearch {
+    /// Provider querying the instance at `endpoint`, e.g.
+    /// `https://searx.example.org/search`.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            agent: agent(Duration::from_secs(10)),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.agent = agent(timeout);
+        self
+    }
+}
+
+#[async_trait]
+impl SearchProvider for SearxSearch {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, ToolError> {
+        let failed = |message: String| ToolError::Failed(format!("search failed: {message}"));
+        let response = self
+            .agent
+            .get(&self.endpoint)
+            .query("q", query)
+            .query("format", "json")
+            .call()
+            .map_err(|err| failed(err.to_string()))?;
+        let body: Value = serde_json::from_reader(response.into_reader())
+            .map_err(|err| failed(err.to_string()))?;
+        let results = body["results"].as_array().into_iter().flatten();
+        Ok(results
+            .take(limit)
+            .map(|result| {
+                let field = |name: &str| result[name].as_str().unwrap_or_default().to_string();
+                let mut snippet = field("content");
+                if snippet.len() > SNIPPET_LEN {
+                    let mut end = SNIPPET_LEN;
+                    while !snippet.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    snippet.truncate(end);
+                }
+                SearchResult {
+                    title: field("title"),
+                    url: field("url"),
+                    snippet,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Tool answering queries with a [`SearchProvider`].
+pub struct SearchTool<P> {
+    provider: P,
+    max_results: usize,
+}
+
+impl<P: SearchProvider> SearchTool<P> {
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            max_results: 5,
+        }
+    }
+
+    /// Most results a single call returns, at least one.
+    pub fn with_max_results(mut self, max_results: usize) -> Self {
+        self.max_results = max_results.max(1);
+        self
+    }
+}
+
+#[async_trait]
+impl<P: SearchProvider> Tool for SearchTool<P> {
+    fn name(&self) -> &str {
+        "search"
+    }
+
+    fn description(&self) -> &str {
+        "Search for documents matching a query"
+    }
+
+    fn schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string" },
+                "limit": { "type": "integer", "minimum": 1, "maximum": self.max_results },
+            },
+            "required": ["query"],
+        })
+    }
+
+    async fn invoke(&self, arguments: Value) -> Result<Value, ToolError> {
+        let query = arguments["query"]
+            .as_str()
+            .ok_or_else(|| ToolError::InvalidArguments {
+                tool: self.name().to_string(),
+                message: "query must be a string".into(),
+            })?;
+        let limit = arguments["limit"]
+            .as_u64()
+            .map_or(self.max_results, |limit| limit as usize)
+            .clamp(1, self.max_results);
+        let results: Vec<Value> = self
+            .provider
+            .search(query, limit)
+            .await?
+            .into_iter()
+            .map(|result| {
+                json!({ "title": result.title, "url": result.url, "snippet": result.snippet })
+            })
+            .collect();
+        Ok(json!({ "results": results }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{ToolCall, ToolRouter};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener};
+
+    /// Serve `count` requests, answering each with `respond(request line,
+    /// body)`.
+    fn serve(count: usize, respond: fn(&str, &str) -> String) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(count) {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let response = respond(request_line.trim(), &String::from_utf8(body).unwrap());
+                (&stream).write_all(response.as_bytes()).unwrap();
+            }
+        });
+        addr
+    }
+
+    fn reply(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\n{headers}Connection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn call(name: &str, arguments: Value) -> ToolCall {
+        ToolCall {
+            name: name.into(),
+            arguments,
+        }
+    }
+
+    #[test]
+    fn allow_list_covers_subdomains_only() {
+        let tool = HttpTool::new(["Example.org", "127.0.0.1"]);
+        let allowed = |url: &str| tool.allows(&Url::parse(url).unwrap());
+        assert!(allowed("https://example.org/a"));
+        assert!(allowed("http://docs.EXAMPLE.org:8080/"));
+        assert!(allowed("http://127.0.0.1:1/"));
+        assert!(!allowed("https://badexample.org/"));
+        assert!(!allowed("https://example.org.evil.com/"));
+        assert!(!allowed("ftp://example.org/"));
+        assert!(!allowed("file:///etc/passwd"));
+    }
+
+    #[tokio::test]
+    async fn http_tool_fetches_allowed_urls() {
+        let addr = serve(4, |request, body| match request {
+            "GET /page HTTP/1.1" => reply("200 OK", "Content-Type: text/html\r\n", "<p>hi</p>"),
+            "POST /echo HTTP/1.1" => reply("201 Created", "", body),
+            "GET /moved HTTP/1.1" => reply("302 Found", "Location: https://elsewhere/\r\n", ""),
+            _ => reply("404 Not Found", "", "missing"),
+        });
+        let router = ToolRouter::new().with_tool(HttpTool::new(["127.0.0.1"]).with_max_body(5));
+        let base = format!("http://{addr}");
+
+        let page = router
+            .execute(&call("http", json!({ "url": format!("{base}/page") })))
+            .await
+            .unwrap();
+        assert_eq!(page["status"], 200);
+        assert_eq!(page["content_type"], "text/html");
+        assert_eq!(page["body"], "<p>hi");
+        assert_eq!(page["truncated"], true);
+
+        let posted = router
+            .execute(&call(
+                "http",
+                json!({ "url": format!("{base}/echo"), "method": "POST", "body": "{}" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            (posted["status"].clone(), posted["body"].clone()),
+            (json!(201), json!("{}"))
+        );
+
+        let moved = router
+            .execute(&call("http", json!({ "url": format!("{base}/moved") })))
+            .await
+            .unwrap();
+        assert_eq!(moved["status"], 302);
+        assert_eq!(moved["location"], "https://elsewhere/");
+        let missing = router
+            .execute(&call("http", json!({ "url": format!("{base}/nothing") })))
+            .await
+            .unwrap();
+        assert_eq!(missing["status"], 404);
+
+        assert!(matches!(
+            router
+                .execute(&call("http", json!({ "url": "https://example.org/" })))
+                .await,
+            Err(ToolError::Failed(message)) if message.contains("allow-list")
+        ));
+        assert!(matches!(
+            router
+                .execute(&call("http", json!({ "url": "/relative" })))
+                .await,
+            Err(ToolError::InvalidArguments { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn search_tool_ranks_corpus_documents() {
+        let corpus = CorpusSearch::new()
+            .with_document(
+                "Paged attention",
+                "docs/paged.md",
+                "KV caches are split into pages. Paged attention reads the KV pages of a sequence.",
+            )
+            .with_document("ROCm", "docs/rocm.md", "ROCm runs kernels on AMD GPUs.")
+            .with_document(
+                "Vulkan",
+                "docs/vulkan.md",
+                "Vulkan compute shaders run kernels.",
+            );
+        let router = ToolRouter::new().with_tool(SearchTool::new(corpus).with_max_results(2));
+
+        let found = router
+            .execute(&call(
+                "search",
+                json!({ "query": "attention over KV pages" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(found["results"].as_array().unwrap().len(), 1);
+        assert_eq!(found["results"][0]["url"], "docs/paged.md");
+        assert_eq!(
+            found["results"][0]["snippet"],
+            "KV caches are split into pages. Paged attention reads the KV pages of a sequence."
+        );
+
+        let kernels = router
+            .execute(&call(
+                "search",
+                json!({ "query": "AMD kernels", "limit": 9 }),
+            ))
+            .await
+            .unwrap();
+        let urls: Vec<&str> = kernels["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["url"].as_str().unwrap())
+            .collect();
+        assert_eq!(urls, ["docs/rocm.md", "docs/vulkan.md"]);
+
+        // A limit of zero still returns the best result.
+        let corpus = CorpusSearch::new().with_document("ROCm", "docs/rocm.md", "ROCm kernels");
+        let router = ToolRouter::new().with_tool(SearchTool::new(corpus).with_max_results(0));
+        let found = router
+            .execute(&call("search", json!({ "query": "kernels", "limit": 3 })))
+            .await
+            .unwrap();
+        assert_eq!(found["results"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn searx_search_reads_json_results() {
+        let addr = serve(1, |request, _| {
+            assert!(request.starts_with("GET /search?q=rocm+setup&format=json "));
+            let body = json!({
+                "results": [
+                    { "title": "ROCm", "url": "https://rocm.docs/", "content": "x".repeat(500) },
+                    { "title": "Other", "url": "https://other/" },
+                ]
+            });
+            reply(
+                "200 OK",
+                "Content-Type: application/json\r\n",
+                &body.to_string(),
+            )
+        });
+        let searx = SearxSearch::new(format!("http://{addr}/search"));
+        let results = searx.search("rocm setup", 1).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "ROCm");
+        assert_eq!(results[0].snippet.len(), SNIPPET_LEN);
+    }
+}
@@ -0,0 +1,137 @@
+//! Capability matrix of the compute backends.
+//!
+//! Every [`TensorOps`] implementation reports what its device can do
+//! through [`TensorOps::capabilities`]: the precisions it executes, its
+//! workgroup and allocation limits, whether it runs asynchronously to the
+//! host and which matrix instructions it has.  The [`Dispatcher`] runs each op
+//! at the precision [negotiated](BackendCapabilities::negotiate) with the
+//! backend executing it and keeps ops whose inputs exceed the backend's
+//! largest allocation on the CPU; `aurex compile` refuses to quantize
+//! weights to a precision the target cannot run.
+//!
+//! Implementations that report nothing get [`BackendCapabilities::default`],
+//! which only promises `f32`.
+//!
+//! [`Dispatcher`]: crate::Dispatcher
+//! [`TensorOps`]: crate::TensorOps
+//! [`TensorOps::capabilities`]: crate::TensorOps::capabilities
+
+use serde::{Deserialize, Serialize};
+
+use crate::dispatch::Precision;
+
+/// Precisions from the least to the most accurate.
+const BY_ACCURACY: [Precision; 4] = [
+    Precision::Int4,
+    Precision::Int8,
+    Precision::Bf16,
+    Precision::F32,
+];
+
+/// What a backend can execute.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendCapabilities {
+    /// Precisions ops can run at.  `f32` is assumed even when not listed.
+    pub precisions: Vec<Precision>,
+    /// Largest compute workgroup, in invocations.
+    pub max_workgroup_size: u32,
+    /// Largest single buffer, in bytes.
+    pub max_allocation: u64,
+    /// Ops are queued on the device without blocking the host.
+    pub async_execution: bool,
+    /// The device has an int8 dot product instruction, e.g. VNNI, Arm
+    /// `dotprod` or DP4a.
+    pub int8_dot: bool,
+    /// Compute kernels can use subgroup arithmetic such as `subgroupAdd`.
+    pub subgroup_ops: bool,
+    /// Kernels can multiply matrix tiles with cooperative matrix
+    /// instructions, e.g. `VK_KHR_cooperative_matrix`.
+    pub cooperative_matrix: bool,
+}
+
+impl Default for BackendCapabilities {
+    fn default() -> Self {
+        Self {
+            precisions: vec![Precision::F32],
+            max_workgroup_size: 1,
+            max_allocation: u64::MAX,
+            async_execution: false,
+            int8_dot: false,
+            subgroup_ops: false,
+            cooperative_matrix: false,
+        }
+    }
+}
+
+impl BackendCapabilities {
+    /// Capabilities of the host CPU, which emulates every precision by
+    /// rounding.
+    pub fn host() -> Self {
+        Self {
+            precisions: BY_ACCURACY.to_vec(),
+            max_workgroup_size: 1,
+            max_allocation: isize::MAX as u64,
+            async_execution: false,
+            int8_dot: host_int8_dot(),
+            subgroup_ops: false,
+            cooperative_matrix: false,
+        }
+    }
+
+    /// Whether ops can run at `precision`.
+    pub fn supports(&self, precision: Precision) -> bool {
+        precision == Precision::F32 || self.precisions.contains(&precision)
+    }
+
+    /// `requested` if supported, else the next more accurate precision
+    /// that is, ending at `f32`.
+    pub fn negotiate(&self, requested: Precision) -> Precision {
+        BY_ACCURACY
+            .iter()
+            .skip_while(|&&p| p != requested)
+            .copied()
+            .find(|&p| self.supports(p))
+            .unwrap_or(Precision::F32)
+    }
+
+    /// Whether a buffer of `bytes` fits a single allocation.
+    pub fn fits(&self, bytes: u64) -> bool {
+        bytes <= self.max_allocation
+    }
+
+    /// Capabilities shared by all of `devices`, e.g. the devices of a
+    /// tensor parallel group.  Empty input yields the default.
+    pub fn intersect<'a>(devices: impl IntoIterator<Item = &'a BackendCapabilities>) -> Self {
+        let mut devices = devices.into_iter();
+        let Some(first) = devices.next() else {
+            return Self::default();
+        };
+        devices.fold(first.clone(), |mut shared, caps| {
+            shared.precisions.retain(|&p| caps.supports(p));
+            shared.max_workgroup_size = shared.max_workgroup_size.min(caps.max_workgroup_size);
+            shared.max_allocation = shared.max_allocation.min(caps.max_allocation);
+            shared.async_execution &= caps.async_execution;
+            shared.int8_dot &= caps.int8_dot;
+            shared.subgroup_ops &= caps.subgroup_ops;
+            shared.cooperative_matrix &= caps.cooperative_matrix;
+            shared
+        })
+    }
+}
+
+/// Whether the host CPU has an int8 dot product instruction.
+fn host_int8_dot() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        std::arch::is_x86_feature_detected!("avx512vnni")
+            || std::arch::is_x86_feature_detected!("avxvnni")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("dotprod")
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
@@ -0,0 +1,221 @@
+//! Coalescing of small matmuls issued concurrently.
+//!
+//! Per-head and per-token matmuls are often too small to amortize the
+//! dispatch overhead of a backend.  With coalescing enabled on a
+//! [`Dispatcher`](crate::Dispatcher), the first small matmul of a shape waits
+//! up to [`CoalesceConfig::window`] for matmuls of the same shape issued by
+//! other threads and runs them all as one
+//! [`TensorOps::batched_matmul`](crate::TensorOps::batched_matmul) call.
+//! Every caller still gets its own product.
+//!
+//! The window trades latency for throughput: a caller issuing a lone matmul
+//! waits the whole window, while longer windows gather larger batches.  A
+//! batch runs as soon as it holds [`CoalesceConfig::max_batch`] matmuls.
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// When and how far small matmuls are coalesced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoalesceConfig {
+    /// Longest the first matmul of a batch waits for others.
+    pub window: Duration,
+    /// Largest number of matmuls run as one batch.
+    pub max_batch: usize,
+    /// Largest matmul, in multiply-adds, that is coalesced; larger ones run
+    /// on their own.
+    pub max_macs: usize,
+}
+
+impl Default for CoalesceConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_micros(200),
+            max_batch: 32,
+            max_macs: 64 * 64 * 64,
+        }
+    }
+}
+
+/// Matmuls and batches run by an [`OpCoalescer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoalesceStats {
+    pub ops: u64,
+    pub batches: u64,
+}
+
+impl CoalesceStats {
+    /// Average matmuls per batch, 0 before the first batch.
+    pub fn mean_batch(&self) -> f64 {
+        if self.batches == 0 {
+            0.0
+        } else {
+            self.ops as f64 / self.batches as f64
+        }
+    }
+}
+
+/// `(m, n, k)` of the matmuls of a batch.
+type Shape = (usize, usize, usize);
+
+/// Matmuls gathered by the caller that opened the batch.
+#[derive(Default)]
+struct Batch {
+    /// Inputs of the callers that joined, copied since they only wait.
+    joined: Vec<(Vec<f32>, Vec<f32>)>,
+    /// Products of the joined callers once the batch ran, `None` entries
+    /// taken.  `Err` when the batch panicked.
+    results: Option<Result<Vec<Option<Vec<f32>>>, ()>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    batch: Mutex<Batch>,
+    changed: Condvar,
+}
+
+/// Groups concurrent small matmuls of the same shape.
+pub struct OpCoalescer {
+    config: CoalesceConfig,
+    open: Mutex<HashMap<Shape, Arc<Slot>>>,
+    stats: Mutex<CoalesceStats>,
+}
+
+impl std::fmt::Debug for OpCoalescer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpCoalescer")
+            .field("config", &self.config)
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+impl OpCoalescer {
+    pub fn new(config: CoalesceConfig) -> Self {
+        Self {
+            config,
+            open: Mutex::new(HashMap::new()),
+            stats: Mutex::new(CoalesceStats::default()),
+        }
+    }
+
+    pub fn config(&self) -> CoalesceConfig {
+        self.config
+    }
+
+    pub fn stats(&self) -> CoalesceStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Whether an `m x k` by `k x n` matmul is small enough to coalesce.
+    pub fn accepts(&self, m: usize, n: usize, k: usize) -> bool {
+        let macs = m.saturating_mul(n).saturating_mul(k);
+        self.config.max_batch > 1 && macs > 0 && macs <= self.config.max_macs
+    }
+
+    /// Multiply `a` by `b`, possibly in a batch with matmuls of the same
+    /// shape from other threads.  `run` executes a batch and returns one
+    /// product per pair, in order; it is called by the thread whose matmul
+    /// opened the batch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the batch holding this matmul panicked.
+    pub fn matmul(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        m: usize,
+        n: usize,
+        k: usize,
+        run: impl FnOnce(&[(&[f32], &[f32])]) -> Vec<Vec<f32>>,
+    ) -> Vec<f32> {
+        let shape = (m, n, k);
+        let slot = {
+            let mut open = self.open.lock().unwrap();
+            match open.get(&shape) {
+                Some(slot) => {
+                    let slot = slot.clone();
+                    let index = {
+                        let mut batch = slot.batch.lock().unwrap();
+                        batch.joined.push((a.to_vec(), b.to_vec()));
+                        batch.joined.len() - 1
+                    };
+                    // The opener and the joined callers make a full batch.
+                    if index + 2 >= self.config.max_batch {
+                        open.remove(&shape);
+                        slot.changed.notify_all();
+                    }
+                    drop(open);
+                    return Self::wait_for(&slot, index);
+                }
+                None => {
+                    let slot = Arc::new(Slot::default());
+                    open.insert(shape, slot.clone());
+                    slot
+                }
+            }
+        };
+
+        // Gather until the window closes or the batch is full.
+        let deadline = Instant::now() + self.config.window;
+        let mut batch = slot.batch.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            if batch.joined.len() + 1 >= self.config.max_batch || now >= deadline {
+                break;
+            }
+            batch = slot.changed.wait_timeout(batch, deadline - now).unwrap().0;
+        }
+        drop(batch);
+        {
+            let mut open = self.open.lock().unwrap();
+            if open.get(&shape).is_some_and(|s| Arc::ptr_eq(s, &slot)) {
+                open.remove(&shape);
+            }
+        }
+        // No caller can join any more.
+        let joined = std::mem::take(&mut slot.batch.lock().unwrap().joined);
+        let pairs: Vec<(&[f32], &[f32])> = std::iter::once((a, b))
+            .chain(joined.iter().map(|(a, b)| (a.as_slice(), b.as_slice())))
+            .collect();
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.ops += pairs.len() as u64;
+            stats.batches += 1;
+        }
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| run(&pairs)));
+        let mut batch = slot.batch.lock().unwrap();
+        match outcome {
+            Ok(products) => {
+                let mut products = products.into_iter();
+                let own = products.next().expect("one product per matmul");
+                batch.results = Some(Ok(products.map(Some).collect()));
+                slot.changed.notify_all();
+                own
+            }
+            Err(payload) => {
+                batch.results = Some(Err(()));
+                slot.changed.notify_all();
+                drop(batch);
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Wait for the product of joined matmul `index` of `slot`.
+    fn wait_for(slot: &Slot, index: usize) -> Vec<f32> {
+        let mut batch = slot.batch.lock().unwrap();
+        loop {
+            match &mut batch.results {
+                Some(Ok(products)) => {
+                    return products[index].take().expect("product taken once");
+                }
+                Some(Err(())) => panic!("coalesced matmul batch panicked"),
+                None => batch = slot.changed.wait(batch).unwrap(),
+            }
+        }
+    }
+}
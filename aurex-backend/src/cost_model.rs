@@ -0,0 +1,211 @@
+//! Cost model for automatic backend selection.
+//!
+//! Every backend is described by a [`BackendProfile`]: its compute
+//! throughput, the bandwidth of host/device transfers and a fixed launch
+//! overhead.  [`CostModel::estimate`] turns the [`OpCost`] of an op into an
+//! expected duration per backend and [`CostModel::select`] picks the fastest
+//! candidate.  Small ops stay on the CPU because transfers and launches
+//! dominate, large ops move to accelerators.
+//!
+//! Profiles start from nominal values and can be replaced by measurements
+//! from [`CostModel::calibrate`], which times a short matmul micro-benchmark
+//! on each backend.  Decisions are cached per op and size class.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use aurex_utils::roofline::OpCost;
+
+use crate::dispatch::{Backend, Dispatcher};
+
+/// Matrix size of the calibration matmul.
+pub const CALIBRATION_SIZE: usize = 64;
+/// Timed repetitions per backend; the fastest run is kept.
+pub const CALIBRATION_RUNS: usize = 3;
+
+/// Performance characteristics of a backend.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackendProfile {
+    /// Sustained compute throughput.
+    pub gflops: f64,
+    /// Host/device transfer bandwidth in GB/s; `None` when the backend works
+    /// on host memory directly.
+    pub transfer_gbs: Option<f64>,
+    /// Fixed cost of launching a kernel.
+    pub launch_overhead: Duration,
+}
+
+impl BackendProfile {
+    /// Nominal profile used before calibration.
+    pub fn nominal(backend: Backend) -> Self {
+        let (gflops, transfer_gbs, launch_us) = match backend {
+            Backend::Cpu => (50.0, None, 0),
+            Backend::Rocm => (20_000.0, Some(25.0), 20),
+            Backend::Sycl => (8_000.0, Some(16.0), 30),
+            Backend::OpenCl => (5_000.0, Some(16.0), 30),
+            Backend::Vulkan => (10_000.0, Some(16.0), 25),
+            Backend::Plugin(_) => (1_000.0, Some(8.0), 50),
+        };
+        Self {
+            gflops,
+            transfer_gbs,
+            launch_overhead: Duration::from_micros(launch_us),
+        }
+    }
+}
+
+type DecisionKey = (&'static str, u32, u32, Vec<Backend>);
+
+/// Estimates op durations per backend and caches the resulting decisions.
+#[derive(Debug)]
+pub struct CostModel {
+    profiles: HashMap<Backend, BackendProfile>,
+    decisions: Mutex<HashMap<DecisionKey, Backend>>,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CostModel {
+    /// Cost model with nominal profiles for every built in backend.  Plugins
+    /// are only considered once they have a profile, set through
+    /// [`CostModel::calibrate`] or [`CostModel::with_profile`].
+    pub fn new() -> Self {
+        let profiles = [
+            Backend::Cpu,
+            Backend::Rocm,
+            Backend::Sycl,
+            Backend::OpenCl,
+            Backend::Vulkan,
+        ]
+        .into_iter()
+        .map(|b| (b, BackendProfile::nominal(b)))
+        .collect();
+        Self {
+            profiles,
+            decisions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Measure the compute throughput of `backends` with a matmul
+    /// micro-benchmark.  Transfer bandwidth and launch overhead keep their
+    /// nominal values since they cannot be timed through [`TensorOps`].
+    ///
+    /// [`TensorOps`]: crate::TensorOps
+    pub fn calibrate(backends: &[Backend]) -> Self {
+        let n = CALIBRATION_SIZE;
+        let a: Vec<f32> = (0..n * n).map(|i| (i % 13) as f32 * 0.25).collect();
+        let b: Vec<f32> = (0..n * n).map(|i| (i % 7) as f32 * 0.5).collect();
+        let flops = OpCost::matmul(n, n, n).flops as f64;
+
+        let mut model = Self::new();
+        for &backend in backends {
+            let ops = Dispatcher::backend_ops(backend);
+            ops.matmul(&a, &b, n, n, n);
+            let best = (0..CALIBRATION_RUNS)
+                .map(|_| {
+                    let started = Instant::now();
+                    std::hint::black_box(ops.matmul(&a, &b, n, n, n));
+                    started.elapsed()
+                })
+                .min()
+                .unwrap_or_default()
+                .max(Duration::from_nanos(1));
+            let gflops = flops / best.as_secs_f64() / 1e9;
+            tracing::debug!(?backend, gflops, "calibrated backend throughput");
+            model
+                .profiles
+                .entry(backend)
+                .or_insert(BackendProfile::nominal(backend))
+                .gflops = gflops;
+        }
+        model
+    }
+
+    /// Replace the profile of `backend`.
+    pub fn with_profile(mut self, backend: Backend, profile: BackendProfile) -> Self {
+        self.set_profile(backend, profile);
+        self
+    }
+
+    /// Replace the profile of `backend`, invalidating cached decisions.
+    pub fn set_profile(&mut self, backend: Backend, profile: BackendProfile) {
+        self.profiles.insert(backend, profile);
+        self.clear_cache();
+    }
+
+    /// Profile currently used for `backend`.
+    pub fn profile(&self, backend: Backend) -> Option<&BackendProfile> {
+        self.profiles.get(&backend)
+    }
+
+    /// Expected duration of an op on `backend` when `transfer_bytes` have to
+    /// move between host and device.
+    pub fn estimate_with_transfer(
+        &self,
+        backend: Backend,
+        cost: OpCost,
+        transfer_bytes: u64,
+    ) -> Option<Duration> {
+        let profile = self.profiles.get(&backend)?;
+        let compute = cost.flops as f64 / (profile.gflops * 1e9);
+        let transfer = match profile.transfer_gbs {
+            Some(gbs) => transfer_bytes as f64 / (gbs * 1e9),
+            None => 0.0,
+        };
+        Some(profile.launch_overhead + Duration::from_secs_f64(compute + transfer))
+    }
+
+    /// Expected duration of an op on `backend`, assuming all of its inputs
+    /// and outputs are transferred.
+    pub fn estimate(&self, backend: Backend, cost: OpCost) -> Option<Duration> {
+        self.estimate_with_transfer(backend, cost, cost.bytes)
+    }
+
+    /// Pick the fastest of `candidates` for `op`.  Decisions are cached by op
+    /// name and power-of-two size class, so similar ops reuse earlier
+    /// results.  Falls back to the CPU when no candidate has a profile.
+    pub fn select(&self, op: &'static str, cost: OpCost, candidates: &[Backend]) -> Backend {
+        let key = (
+            op,
+            size_class(cost.flops),
+            size_class(cost.bytes),
+            candidates.to_vec(),
+        );
+        if let Some(&backend) = self.decisions.lock().unwrap().get(&key) {
+            return backend;
+        }
+        let backend = candidates
+            .iter()
+            .filter_map(|&b| self.estimate(b, cost).map(|t| (b, t)))
+            .min_by_key(|&(_, t)| t)
+            .map_or(Backend::Cpu, |(b, _)| b);
+        tracing::debug!(
+            op,
+            flops = cost.flops,
+            bytes = cost.bytes,
+            ?backend,
+            "cost model decision"
+        );
+        self.decisions.lock().unwrap().insert(key, backend);
+        backend
+    }
+
+    /// Number of cached decisions.
+    pub fn cached_decisions(&self) -> usize {
+        self.decisions.lock().unwrap().len()
+    }
+
+    /// Forget all cached decisions.
+    pub fn clear_cache(&self) {
+        self.decisions.lock().unwrap().clear();
+    }
+}
+
+fn size_class(value: u64) -> u32 {
+    u64::BITS - value.leading_zeros()
+}
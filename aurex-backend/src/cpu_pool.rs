@@ -0,0 +1,325 @@
+//! Worker threads of the parallel CPU backend.
+//!
+//! [`CpuBackend`](crate::dispatch::CpuBackend) splits the output rows of
+//! large matmuls and convolutions across the workers of the global
+//! [`CpuThreadPool`].  A [`ThreadPoolConfig`] sets the number of workers,
+//! whether they are pinned to NUMA nodes or single cores, and which cores of
+//! a hybrid processor they may run on.  By default the pool runs one worker
+//! per performance core, since a row split evenly across big and little cores
+//! waits for the little ones.
+//!
+//! Every worker computes whole rows in the same order as a single thread, so
+//! results do not depend on the number of workers.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::JoinHandle;
+
+use aurex_utils::hybrid::{CoreKind, CoreTypes};
+use aurex_utils::numa::{self, NumaTopology};
+use serde::{Deserialize, Serialize};
+
+use crate::tensor_parallel::CpuAffinity;
+
+/// Cores of a hybrid processor the workers may run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoreSelection {
+    /// Performance cores only; every core of a uniform processor.
+    #[default]
+    Performance,
+    /// Efficiency cores only, leaving the performance cores to other work.
+    Efficiency,
+    /// Every core.
+    All,
+}
+
+impl CoreSelection {
+    /// Whether workers may run on a core of `kind`.  Cores of unknown kind
+    /// count as performance cores.
+    pub fn allows(self, kind: Option<CoreKind>) -> bool {
+        match self {
+            CoreSelection::Performance => kind != Some(CoreKind::Efficiency),
+            CoreSelection::Efficiency => kind == Some(CoreKind::Efficiency),
+            CoreSelection::All => true,
+        }
+    }
+}
+
+impl std::str::FromStr for CoreSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "performance" | "p" => Ok(CoreSelection::Performance),
+            "efficiency" | "e" => Ok(CoreSelection::Efficiency),
+            "all" => Ok(CoreSelection::All),
+            other => Err(format!("unknown core selection '{other}'")),
+        }
+    }
+}
+
+/// Shape of a [`CpuThreadPool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ThreadPoolConfig {
+    /// Number of workers; one per selected core when unset.
+    pub threads: Option<usize>,
+    /// Pinning of worker `r`: to the selected cores of NUMA node
+    /// `r % nodes`, or to the `r`-th selected core.  Unpinned workers may
+    /// still be restricted to the selected cores.
+    pub pinning: CpuAffinity,
+    pub cores: CoreSelection,
+}
+
+impl ThreadPoolConfig {
+    /// Cores of `topology` the workers may run on, grouped by node.  All
+    /// cores when none is of the selected kind.
+    pub fn allowed_cpus(&self, topology: &NumaTopology, types: &CoreTypes) -> Vec<usize> {
+        let allowed: Vec<usize> = topology
+            .cpus()
+            .filter(|&cpu| self.cores.allows(types.kind(cpu)))
+            .collect();
+        if allowed.is_empty() {
+            topology.cpus().collect()
+        } else {
+            allowed
+        }
+    }
+
+    /// Number of workers of a pool on `topology`.
+    pub fn worker_count(&self, topology: &NumaTopology, types: &CoreTypes) -> usize {
+        self.threads
+            .unwrap_or_else(|| self.allowed_cpus(topology, types).len())
+            .max(1)
+    }
+
+    /// CPUs worker `rank` is restricted to, `None` when the OS may schedule
+    /// it on any CPU.
+    pub fn worker_cpus(
+        &self,
+        topology: &NumaTopology,
+        types: &CoreTypes,
+        rank: usize,
+    ) -> Option<Vec<usize>> {
+        let allowed = self.allowed_cpus(topology, types);
+        match self.pinning {
+            CpuAffinity::None => (allowed.len() < topology.cpus().count()).then_some(allowed),
+            CpuAffinity::Node => {
+                let nodes: Vec<Vec<usize>> = topology
+                    .nodes()
+                    .iter()
+                    .map(|node| {
+                        node.cpus
+                            .iter()
+                            .copied()
+                            .filter(|cpu| allowed.contains(cpu))
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|cpus| !cpus.is_empty())
+                    .collect();
+                Some(nodes[rank % nodes.len()].clone())
+            }
+            CpuAffinity::Core => Some(vec![allowed[rank % allowed.len()]]),
+        }
+    }
+}
+
+/// Task broadcast to every worker; `run` keeps it alive until all of them
+/// are done with it.
+type Task = dyn Fn(usize) + Sync;
+
+struct Job {
+    task: *const Task,
+    done: Arc<Latch>,
+}
+
+// SAFETY: the task is `Sync` and outlives the job, see `CpuThreadPool::run`.
+unsafe impl Send for Job {}
+
+/// Counts down the workers of a job and records whether one panicked.
+struct Latch {
+    state: Mutex<(usize, bool)>,
+    finished: Condvar,
+}
+
+impl Latch {
+    fn new(count: usize) -> Self {
+        Self {
+            state: Mutex::new((count, false)),
+            finished: Condvar::new(),
+        }
+    }
+
+    fn count_down(&self, panicked: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.0 -= 1;
+        state.1 |= panicked;
+        if state.0 == 0 {
+            self.finished.notify_all();
+        }
+    }
+
+    /// Wait for every worker; `true` when one panicked.
+    fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while state.0 > 0 {
+            state = self.finished.wait(state).unwrap();
+        }
+        state.1
+    }
+}
+
+thread_local! {
+    static IN_WORKER: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Long-lived, optionally pinned worker threads.
+pub struct CpuThreadPool {
+    config: ThreadPoolConfig,
+    /// CPUs each worker is restricted to.
+    cpus: Vec<Option<Vec<usize>>>,
+    senders: Vec<Sender<Job>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+static GLOBAL: RwLock<Option<Arc<CpuThreadPool>>> = RwLock::new(None);
+
+impl CpuThreadPool {
+    /// Start a pool on the detected NUMA topology and core types.
+    pub fn new(config: ThreadPoolConfig) -> Self {
+        Self::with_topology(config, &NumaTopology::detect(), &CoreTypes::detect())
+    }
+
+    /// Start a pool on `topology` and `types`.  A pool of one worker runs
+    /// tasks on the calling thread and starts none.
+    pub fn with_topology(
+        config: ThreadPoolConfig,
+        topology: &NumaTopology,
+        types: &CoreTypes,
+    ) -> Self {
+        let count = config.worker_count(topology, types);
+        let cpus: Vec<_> = (0..count)
+            .map(|rank| config.worker_cpus(topology, types, rank))
+            .collect();
+        let mut pool = Self {
+            config,
+            cpus,
+            senders: Vec::new(),
+            handles: Vec::new(),
+        };
+        if count == 1 {
+            return pool;
+        }
+        for rank in 0..count {
+            let (sender, receiver) = mpsc::channel::<Job>();
+            let cpus = pool.cpus[rank].clone();
+            let handle = std::thread::Builder::new()
+                .name(format!("aurex-cpu-{rank}"))
+                .spawn(move || {
+                    if let Some(cpus) = cpus {
+                        if let Err(err) = numa::pin_current_thread(&cpus) {
+                            tracing::debug!(rank, %err, "cannot pin cpu worker");
+                        }
+                    }
+                    IN_WORKER.with(|flag| flag.set(true));
+                    for job in receiver {
+                        // SAFETY: `run` waits for the latch before the task
+                        // goes out of scope.
+                        let task = unsafe { &*job.task };
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| task(rank)));
+                        job.done.count_down(result.is_err());
+                    }
+                })
+                .expect("failed to spawn cpu worker");
+            pool.senders.push(sender);
+            pool.handles.push(handle);
+        }
+        pool
+    }
+
+    /// Pool shared by the CPU backend, started with the default
+    /// configuration on first use.
+    pub fn global() -> Arc<CpuThreadPool> {
+        if let Some(pool) = GLOBAL.read().unwrap().as_ref() {
+            return pool.clone();
+        }
+        GLOBAL
+            .write()
+            .unwrap()
+            .get_or_insert_with(|| Arc::new(Self::new(ThreadPoolConfig::default())))
+            .clone()
+    }
+
+    /// Replace the global pool unless it already has `config`.  Ops running
+    /// on the old pool finish there.
+    pub fn configure_global(config: ThreadPoolConfig) {
+        let mut global = GLOBAL.write().unwrap();
+        if global.as_ref().is_some_and(|pool| pool.config == config) {
+            return;
+        }
+        *global = Some(Arc::new(Self::new(config)));
+    }
+
+    pub fn config(&self) -> ThreadPoolConfig {
+        self.config
+    }
+
+    /// Number of workers.
+    pub fn threads(&self) -> usize {
+        self.cpus.len()
+    }
+
+    /// CPUs worker `rank` is restricted to, `None` when it is not.
+    pub fn worker_cpus(&self, rank: usize) -> Option<&[usize]> {
+        self.cpus.get(rank)?.as_deref()
+    }
+
+    /// Call `task` with every worker rank `0..threads()` in parallel and
+    /// return once all calls did.  Called from inside a task, the ranks run
+    /// one after another on the calling worker.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `task` panicked on a worker.
+    pub fn run(&self, task: &(dyn Fn(usize) + Sync)) {
+        if self.senders.is_empty() || IN_WORKER.with(|flag| flag.get()) {
+            (0..self.threads()).for_each(task);
+            return;
+        }
+        // SAFETY: only the lifetime is erased; the latch below outlives
+        // every use of the pointer by the workers.
+        let task: *const Task = unsafe { std::mem::transmute(task) };
+        let done = Arc::new(Latch::new(self.senders.len()));
+        for sender in &self.senders {
+            let job = Job {
+                task,
+                done: done.clone(),
+            };
+            if sender.send(job).is_err() {
+                done.count_down(true);
+            }
+        }
+        if done.wait() {
+            panic!("cpu worker panicked");
+        }
+    }
+}
+
+impl std::fmt::Debug for CpuThreadPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CpuThreadPool")
+            .field("config", &self.config)
+            .field("cpus", &self.cpus)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for CpuThreadPool {
+    fn drop(&mut self) {
+        self.senders.clear();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
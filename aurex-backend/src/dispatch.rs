@@ -3,6 +3,61 @@
 //! The dispatcher exposes a [`TensorOps`] trait implemented by several backend
 //! stubs.  Backends can be enabled or disabled via environment variables and are
 //! chosen based on user preference or workload characteristics.
+//!
+//! A single dispatcher can keep several backends live and route individual
+//! ops to them, either through explicit routes or an attached [`CostModel`].
+//! Inputs are staged through a [`PlacementTracker`] so tensors already
+//! resident on the executing device are not copied again.  Attached
+//! [`AccessObserver`]s see every input buffer an op reads, which is how the
+//! memory manager keeps its eviction policy up to date.
+//!
+//! [`Dispatcher::warmup`] pays the one-off costs of a model's ops before
+//! serving traffic, and [`Dispatcher::capture`] pins ops to the backends
+//! they were warmed up on, see [`crate::warmup`].
+//!
+//! Scratch buffers, such as inputs rounded to a reduced precision, come from
+//! the dispatcher's [`BufferPool`] instead of being allocated per call; an
+//! attached profiler sees the pool's reuse statistics.
+//!
+//! With validation enabled, the default in debug builds, every op checks its
+//! inputs against its dimensions first and panics with a [`ShapeError`]
+//! naming the op and the mismatched sizes instead of failing inside a
+//! backend.  The `try_*` methods always check and return the error.
+//!
+//! An optional [`HealthCheck`] scans a sample of op outputs for NaN and
+//! infinities and panics with a [`RuntimeError`](crate::RuntimeError) naming
+//! the op and the layer set through [`TensorOps::set_layer`], so bad
+//! quantization or a broken kernel is caught where it happens rather than
+//! tokens later.
+//!
+//! With [coalescing](Dispatcher::set_coalescing) enabled, small matmuls of
+//! the same shape issued concurrently from several threads run as one
+//! batched backend call, see [`crate::coalesce`].
+//!
+//! Plugins registered in [`PluginBackends::global`] are selectable as
+//! [`Backend::Plugin`].  Ops a plugin does not declare run on the default
+//! backend instead, or on the CPU when that is such a plugin too.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use aurex_utils::profiler::Profiler;
+use aurex_utils::roofline::OpCost;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::capabilities::BackendCapabilities;
+use crate::coalesce::{CoalesceConfig, CoalesceStats, OpCoalescer};
+use crate::cost_model::CostModel;
+use crate::cpu_pool::CpuThreadPool;
+use crate::health::{self, HealthCheck};
+use crate::memory_pool::{BufferPool, PooledBuffer};
+use crate::placement::{PlacementTracker, TransferStats};
+use crate::plugin_backend::PluginBackends;
+use crate::specialization::KernelShapes;
+use crate::tensor_parallel::shard_range;
+use crate::validation::{self, ShapeError};
+use crate::warmup::{self, ExecutionPlan};
 
 /// Common tensor operations.
 pub trait TensorOps {
@@ -16,24 +71,109 @@ pub trait TensorOps {
     ) -> Vec<f32>;
     fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32>;
     fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32>;
+
+    /// Whether callers must reduce in a fixed order, e.g. with scalar loops
+    /// instead of host dependent SIMD, so results are reproducible.
+    fn deterministic(&self) -> bool {
+        false
+    }
+
+    /// Name the layer issuing the following ops, for diagnostics; `None`
+    /// once outside of any layer.
+    fn set_layer(&self, _layer: Option<&str>) {}
+
+    /// Record the following ops into one submission instead of submitting
+    /// each, e.g. all ops of a token.  Batches nest; the outermost
+    /// [`end_batch`](Self::end_batch) submits.  Results are still returned
+    /// by each op.
+    fn begin_batch(&self) {}
+
+    /// Submit the ops recorded since the matching
+    /// [`begin_batch`](Self::begin_batch).
+    fn end_batch(&self) {}
+
+    /// Multiply every `(a, b)` pair of `batch`, each an `m x k` by a `k x n`
+    /// matrix.  By default the matmuls are recorded as one
+    /// [batch](Self::begin_batch).
+    fn batched_matmul(
+        &self,
+        batch: &[(&[f32], &[f32])],
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Vec<Vec<f32>> {
+        self.begin_batch();
+        let out = batch
+            .iter()
+            .map(|&(a, b)| self.matmul(a, b, m, n, k))
+            .collect();
+        self.end_batch();
+        out
+    }
+
+    /// What the device can execute.  Defaults to `f32` only.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::default()
+    }
 }
 
-/// CPU fallback implementing all tensor operations in software.
+/// CPU fallback implementing all tensor operations in software.  Large
+/// matmuls and convolutions are split by output rows across the workers of
+/// the global [`CpuThreadPool`].
 #[derive(Clone, Copy, Debug)]
 pub struct CpuBackend;
 
+/// Fewest multiply-adds for which [`CpuBackend`] splits an op across the
+/// thread pool; smaller ops do not pay for the handoff.
+const PARALLEL_MIN_MACS: usize = 1 << 16;
+
+/// Call `row(i, out_row)` for every row of `width` values of `out`, on the
+/// workers of the global thread pool when the op costs `macs` multiply-adds
+/// or more.
+fn for_each_row(
+    out: &mut [f32],
+    width: usize,
+    macs: usize,
+    row: impl Fn(usize, &mut [f32]) + Sync,
+) {
+    if width == 0 {
+        return;
+    }
+    let rows = out.len() / width;
+    let pool = (macs >= PARALLEL_MIN_MACS && rows > 1).then(CpuThreadPool::global);
+    let workers = pool.as_ref().map_or(1, |pool| pool.threads().min(rows));
+    let Some(pool) = pool.filter(|_| workers > 1) else {
+        for (i, out_row) in out.chunks_mut(width).enumerate() {
+            row(i, out_row);
+        }
+        return;
+    };
+    let mut parts = Vec::with_capacity(workers);
+    let mut rest = out;
+    for rank in 0..workers {
+        let range = shard_range(rows, workers, rank);
+        let (part, tail) = rest.split_at_mut(range.len() * width);
+        parts.push(Mutex::new((range.start, part)));
+        rest = tail;
+    }
+    pool.run(&|rank| {
+        let Some(part) = parts.get(rank) else {
+            return;
+        };
+        let mut part = part.lock().unwrap();
+        let (start, ref mut rows) = *part;
+        for (i, out_row) in rows.chunks_mut(width).enumerate() {
+            row(start + i, out_row);
+        }
+    });
+}
+
 impl TensorOps for CpuBackend {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
         let mut out = vec![0.0; m * n];
-        for i in 0..m {
-            for j in 0..n {
-                let mut sum = 0.0;
-                for p in 0..k {
-                    sum += a[i * k + p] * b[p * n + j];
-                }
-                out[i * n + j] = sum;
-            }
-        }
+        for_each_row(&mut out, n, m * n * k, |i, row| {
+            matmul_into(&a[i * k..(i + 1) * k], b, n, k, row);
+        });
         out
     }
 
@@ -49,17 +189,17 @@ impl TensorOps for CpuBackend {
         let oh = ih - kh + 1;
         let ow = iw - kw + 1;
         let mut out = vec![0.0; oh * ow];
-        for i in 0..oh {
-            for j in 0..ow {
+        for_each_row(&mut out, ow, oh * ow * kh * kw, |i, row| {
+            for (j, value) in row.iter_mut().enumerate() {
                 let mut sum = 0.0;
                 for ki in 0..kh {
                     for kj in 0..kw {
                         sum += input[(i + ki) * iw + (j + kj)] * kernel[ki * kw + kj];
                     }
                 }
-                out[i * ow + j] = sum;
+                *value = sum;
             }
-        }
+        });
         out
     }
 
@@ -85,6 +225,40 @@ impl TensorOps for CpuBackend {
             .map(|((v, g), b)| ((v - mean) / denom) * g + b)
             .collect()
     }
+
+    /// Spreads the matmuls of a large enough batch over the thread pool.
+    fn batched_matmul(
+        &self,
+        batch: &[(&[f32], &[f32])],
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Vec<Vec<f32>> {
+        let mut out = vec![0.0; batch.len() * m * n];
+        for_each_row(&mut out, m * n, batch.len() * m * n * k, |i, product| {
+            let (a, b) = batch[i];
+            matmul_into(a, b, n, k, product);
+        });
+        out.chunks(m * n).map(<[f32]>::to_vec).collect()
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::host()
+    }
+}
+
+/// Multiply the rows of `a`, each of `k` values, by the `k x n` matrix `b`
+/// into `out`.
+fn matmul_into(a: &[f32], b: &[f32], n: usize, k: usize, out: &mut [f32]) {
+    for (i, row) in out.chunks_mut(n).enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            let mut sum = 0.0;
+            for p in 0..k {
+                sum += a[i * k + p] * b[p * n + j];
+            }
+            *value = sum;
+        }
+    }
 }
 
 use crate::vulkan_backend::VulkanBackend;
@@ -96,6 +270,22 @@ pub struct RocmBackend;
 #[derive(Clone, Copy, Debug)]
 pub struct OpenClBackend;
 
+/// Limits of a typical device behind a placeholder GPU backend.  All
+/// precisions are available as the CPU kernels run the ops.
+fn placeholder_gpu(
+    max_workgroup_size: u32,
+    max_allocation: u64,
+    int8_dot: bool,
+) -> BackendCapabilities {
+    BackendCapabilities {
+        max_workgroup_size,
+        max_allocation,
+        async_execution: true,
+        int8_dot,
+        ..BackendCapabilities::host()
+    }
+}
+
 impl TensorOps for RocmBackend {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
         CpuBackend.matmul(a, b, m, n, k)
@@ -115,6 +305,9 @@ impl TensorOps for RocmBackend {
     fn layer_norm(&self, x: &[f32], g: &[f32], b: &[f32], eps: f32) -> Vec<f32> {
         CpuBackend.layer_norm(x, g, b, eps)
     }
+    fn capabilities(&self) -> BackendCapabilities {
+        placeholder_gpu(1024, 4 << 30, true)
+    }
 }
 
 
@@ -137,16 +330,161 @@ impl TensorOps for OpenClBackend {
     fn layer_norm(&self, x: &[f32], g: &[f32], b: &[f32], eps: f32) -> Vec<f32> {
         CpuBackend.layer_norm(x, g, b, eps)
     }
+    fn capabilities(&self) -> BackendCapabilities {
+        placeholder_gpu(256, 1 << 30, false)
+    }
 }
 
 /// Available compute backends.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Backend {
     Cpu,
     Rocm,
     Sycl,
     OpenCl,
     Vulkan,
+    /// Plugin registered in [`PluginBackends`] under this name.
+    Plugin(&'static str),
+}
+
+impl Backend {
+    /// Parse a backend name as accepted by `AUREX_BACKEND` (case
+    /// insensitive).  Plugins are named `plugin:<name>`, with the plugin
+    /// name kept as written.
+    pub fn from_name(name: &str) -> Option<Backend> {
+        if let Some((prefix, plugin)) = name.split_once(':') {
+            return (prefix.eq_ignore_ascii_case("plugin") && !plugin.is_empty())
+                .then(|| Backend::plugin(plugin));
+        }
+        match name.to_lowercase().as_str() {
+            "cpu" => Some(Backend::Cpu),
+            "rocm" => Some(Backend::Rocm),
+            "sycl" => Some(Backend::Sycl),
+            "opencl" => Some(Backend::OpenCl),
+            "vulkan" => Some(Backend::Vulkan),
+            _ => None,
+        }
+    }
+
+    /// Backend of the plugin called `name`.  Names are interned, so each
+    /// distinct name is allocated once for the life of the process.
+    pub fn plugin(name: &str) -> Backend {
+        static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+        let mut names = NAMES.get_or_init(Default::default).lock().unwrap();
+        let name = match names.get(name) {
+            Some(&interned) => interned,
+            None => {
+                let interned: &'static str = Box::leak(name.into());
+                names.insert(interned);
+                interned
+            }
+        };
+        Backend::Plugin(name)
+    }
+
+    /// Lowercase name as accepted by `AUREX_BACKEND`, or the plugin name
+    /// for plugins.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Cpu => "cpu",
+            Backend::Rocm => "rocm",
+            Backend::Sycl => "sycl",
+            Backend::OpenCl => "opencl",
+            Backend::Vulkan => "vulkan",
+            Backend::Plugin(name) => name,
+        }
+    }
+
+    /// What the backend can execute, reported by a fresh instance of it.
+    pub fn capabilities(self) -> BackendCapabilities {
+        Dispatcher::backend_ops(self).capabilities()
+    }
+}
+
+/// Formats the backend as accepted by [`Backend::from_name`].
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Plugin(name) => write!(f, "plugin:{name}"),
+            backend => f.write_str(backend.name()),
+        }
+    }
+}
+
+impl Serialize for Backend {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Backend {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Backend::from_name(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown backend '{name}'")))
+    }
+}
+
+/// Numeric precision used for model execution.  The dispatcher emulates
+/// reduced precisions by rounding operation inputs before they reach the
+/// selected backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    F32,
+    Bf16,
+    Int8,
+    Int4,
+}
+
+impl Precision {
+    /// Round `data` to the representable values of this precision.  `F32`
+    /// returns the input unchanged; integer formats use per-tensor symmetric
+    /// scaling matching the quantizer in `aurex_lm`.
+    pub fn round(self, data: &[f32]) -> Vec<f32> {
+        let mut out = vec![0.0; data.len()];
+        self.round_into(data, &mut out);
+        out
+    }
+
+    /// Like [`Precision::round`], writing the rounded values to `out`,
+    /// which must be as long as `data`.
+    pub fn round_into(self, data: &[f32], out: &mut [f32]) {
+        match self {
+            Precision::F32 => out.copy_from_slice(data),
+            Precision::Bf16 => {
+                for (o, v) in out.iter_mut().zip(data) {
+                    let bits = v.to_bits();
+                    let lsb = (bits >> 16) & 1;
+                    *o = f32::from_bits(bits.wrapping_add(0x7fff + lsb) & 0xffff_0000);
+                }
+            }
+            Precision::Int8 => fake_quantize(data, 127.0, out),
+            Precision::Int4 => fake_quantize(data, 7.0, out),
+        }
+    }
+}
+
+impl std::str::FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "f32" | "fp32" => Ok(Precision::F32),
+            "bf16" => Ok(Precision::Bf16),
+            "int8" => Ok(Precision::Int8),
+            "int4" => Ok(Precision::Int4),
+            other => Err(format!("unknown precision '{other}'")),
+        }
+    }
+}
+
+fn fake_quantize(data: &[f32], levels: f32, out: &mut [f32]) {
+    let max = data.iter().fold(0.0_f32, |m, &v| m.max(v.abs()));
+    let scale = if max == 0.0 { 1.0 } else { max / levels };
+    for (o, &v) in out.iter_mut().zip(data) {
+        *o = (v / scale).round().clamp(-levels - 1.0, levels) * scale;
+    }
 }
 
 /// Simplified workload descriptor used by the dispatcher.
@@ -156,10 +494,46 @@ pub enum Workload {
     Heavy,
 }
 
-/// Dispatcher wrapping a [`TensorOps`] implementation selected at runtime.
+impl Workload {
+    /// Representative op used when selecting a single backend for the whole
+    /// workload through a [`CostModel`].
+    pub fn typical_cost(self) -> OpCost {
+        match self {
+            Workload::Light => OpCost::matmul(64, 64, 64),
+            Workload::Heavy => OpCost::matmul(2048, 2048, 2048),
+        }
+    }
+}
+
+/// Notified of the buffers read by every op executed through a
+/// [`Dispatcher`].
+pub trait AccessObserver {
+    fn on_access(&mut self, data: &[f32]);
+}
+
+type SharedOps = Arc<dyn TensorOps + Send + Sync>;
+type SharedObserver = Arc<Mutex<dyn AccessObserver + Send>>;
+
+/// Dispatcher wrapping [`TensorOps`] implementations selected at runtime.
 pub struct Dispatcher {
     backend: Backend,
-    ops: Box<dyn TensorOps + Send + Sync>,
+    available: Vec<Backend>,
+    live: Mutex<HashMap<Backend, SharedOps>>,
+    capabilities: Mutex<HashMap<Backend, BackendCapabilities>>,
+    routes: HashMap<String, Backend>,
+    placement: Mutex<PlacementTracker>,
+    precision: Precision,
+    profiler: Option<Arc<Mutex<Profiler>>>,
+    cost_model: Option<Arc<CostModel>>,
+    observers: Vec<SharedObserver>,
+    shapes: KernelShapes,
+    deterministic: bool,
+    plan: Mutex<Option<ExecutionPlan>>,
+    pool: BufferPool,
+    validate: bool,
+    health: Option<HealthCheck>,
+    layer: Mutex<Option<String>>,
+    coalescer: Option<OpCoalescer>,
 }
 
 impl Dispatcher {
@@ -169,13 +543,188 @@ impl Dispatcher {
     /// the requested backend is unavailable it falls back to an automatically
     /// selected implementation for the provided [`Workload`].
     pub fn new(preferred: Option<Backend>, workload: Workload) -> Self {
+        Self::with_disabled(preferred, workload, &[])
+    }
+
+    /// Like [`Dispatcher::new`] but never selects a backend listed in
+    /// `disabled`, in addition to those turned off via `AUREX_DISABLE_*`.
+    pub fn with_disabled(preferred: Option<Backend>, workload: Workload, disabled: &[Backend]) -> Self {
         let preferred = preferred.or_else(Self::backend_from_env);
+        let available = Self::available_backends(disabled);
         let backend = match preferred {
-            Some(b) if Self::is_available(b) => b,
-            _ => Self::select_backend(workload),
+            Some(b) if available.contains(&b) => b,
+            _ => Self::select_backend(workload, |b| available.contains(&b)),
         };
+        Self::with_backend(backend, workload, available, None)
+    }
+
+    /// Create a dispatcher whose backend is chosen by `cost_model` as the
+    /// fastest available backend for the [`Workload::typical_cost`] of
+    /// `workload`.  The model stays attached and routes every op without an
+    /// explicit route to the backend it estimates to be fastest.
+    pub fn from_cost_model(cost_model: Arc<CostModel>, workload: Workload, disabled: &[Backend]) -> Self {
+        let available = Self::available_backends(disabled);
+        let backend = cost_model.select("matmul", workload.typical_cost(), &available);
+        Self::with_backend(backend, workload, available, Some(cost_model))
+    }
+
+    fn with_backend(
+        backend: Backend,
+        workload: Workload,
+        available: Vec<Backend>,
+        cost_model: Option<Arc<CostModel>>,
+    ) -> Self {
         let ops = Self::backend_ops(backend);
-        Self { backend, ops }
+        tracing::debug!(?backend, ?workload, "dispatcher backend selected");
+        Self {
+            backend,
+            available,
+            live: Mutex::new(HashMap::from([(backend, ops)])),
+            capabilities: Mutex::new(HashMap::new()),
+            routes: HashMap::new(),
+            placement: Mutex::new(PlacementTracker::new()),
+            precision: Precision::F32,
+            profiler: None,
+            cost_model,
+            observers: Vec::new(),
+            shapes: KernelShapes::new(),
+            deterministic: false,
+            plan: Mutex::new(None),
+            pool: BufferPool::default(),
+            validate: cfg!(debug_assertions),
+            health: None,
+            layer: Mutex::new(None),
+            coalescer: None,
+        }
+    }
+
+    /// Backends that can currently be used, excluding `disabled` and those
+    /// turned off via `AUREX_DISABLE_*`.  The CPU is always available, and
+    /// plugins are available once registered in [`PluginBackends::global`].
+    /// A dispatcher only selects plugins registered before it was created.
+    pub fn available_backends(disabled: &[Backend]) -> Vec<Backend> {
+        let plugins = PluginBackends::global().names();
+        [Backend::Cpu, Backend::Rocm, Backend::Sycl, Backend::OpenCl, Backend::Vulkan]
+            .into_iter()
+            .chain(plugins.iter().map(|name| Backend::plugin(name)))
+            .filter(|&b| b == Backend::Cpu || (!disabled.contains(&b) && Self::is_available(b)))
+            .collect()
+    }
+
+    /// Cost model attached via [`Dispatcher::from_cost_model`].
+    pub fn cost_model(&self) -> Option<&Arc<CostModel>> {
+        self.cost_model.as_ref()
+    }
+
+    /// Backend the attached cost model considers fastest for an op of the
+    /// given cost, or the dispatcher's own backend without a cost model.
+    pub fn recommend(&self, op: &'static str, cost: OpCost) -> Backend {
+        match &self.cost_model {
+            Some(model) => model.select(op, cost, &self.available),
+            None => self.backend,
+        }
+    }
+
+    /// Execute `op` on `backend` instead of the default backend.  Returns
+    /// `false`, leaving the routing unchanged, if `backend` is unavailable.
+    pub fn route(&mut self, op: &str, backend: Backend) -> bool {
+        if !self.available.contains(&backend) {
+            tracing::warn!(op, ?backend, "cannot route op to unavailable backend");
+            return false;
+        }
+        tracing::debug!(op, ?backend, "op routed");
+        self.routes.insert(op.to_string(), backend);
+        true
+    }
+
+    /// Builder style variant of [`Dispatcher::route`].
+    pub fn with_route(mut self, op: &str, backend: Backend) -> Self {
+        self.route(op, backend);
+        self
+    }
+
+    /// Explicit op routes.
+    pub fn routes(&self) -> &HashMap<String, Backend> {
+        &self.routes
+    }
+
+    /// Backend that executes `op`: its captured backend, else its explicit
+    /// route, else the cost model decision, else the default backend.
+    /// Plugins not implementing `op` are replaced by the default backend, or
+    /// the CPU.
+    pub(crate) fn backend_for(&self, op: &'static str, cost: impl FnOnce() -> OpCost) -> Backend {
+        let captured = self.plan.lock().unwrap().as_ref().and_then(|plan| plan.backend(op));
+        if let Some(backend) = captured {
+            return backend;
+        }
+        let backend = match (self.routes.get(op), &self.cost_model) {
+            (Some(&backend), _) => backend,
+            (None, Some(model)) if !self.deterministic => model.select(op, cost(), &self.available),
+            _ => self.backend,
+        };
+        [backend, self.backend]
+            .into_iter()
+            .find(|&b| Self::implements(b, op))
+            .unwrap_or(Backend::Cpu)
+    }
+
+    /// Whether `backend` can execute `op`.  Built in backends implement
+    /// every op; plugins the ops listed in their manifest.
+    fn implements(backend: Backend, op: &str) -> bool {
+        match backend {
+            Backend::Plugin(name) => PluginBackends::global().supports(name, op),
+            _ => true,
+        }
+    }
+
+    fn ops_for(&self, backend: Backend) -> SharedOps {
+        self.live
+            .lock()
+            .unwrap()
+            .entry(backend)
+            .or_insert_with(|| Self::specialized_ops(backend, &self.shapes))
+            .clone()
+    }
+
+    /// What `backend` can execute, instantiating it if needed.
+    pub fn capabilities(&self, backend: Backend) -> BackendCapabilities {
+        self.with_capabilities(backend, Clone::clone)
+    }
+
+    /// Precision ops run at on `backend`: the dispatcher's precision
+    /// negotiated with the backend's capabilities.
+    pub fn precision_on(&self, backend: Backend) -> Precision {
+        self.with_capabilities(backend, |caps| caps.negotiate(self.precision))
+    }
+
+    fn with_capabilities<T>(
+        &self,
+        backend: Backend,
+        f: impl FnOnce(&BackendCapabilities) -> T,
+    ) -> T {
+        let mut cache = self.capabilities.lock().unwrap();
+        let caps = cache.entry(backend).or_insert_with(|| {
+            let caps = self.ops_for(backend).capabilities();
+            tracing::debug!(?backend, ?caps, "backend capabilities");
+            caps
+        });
+        f(caps)
+    }
+
+    /// Backends instantiated so far.
+    pub fn live_backends(&self) -> Vec<Backend> {
+        self.live.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Bytes copied between backends and bytes reused from earlier
+    /// placements while staging op inputs.
+    pub fn transfer_stats(&self) -> TransferStats {
+        self.placement.lock().unwrap().stats()
+    }
+
+    /// Forget where tensors live, e.g. after buffers were overwritten.
+    pub fn clear_placements(&self) {
+        self.placement.lock().unwrap().clear();
     }
 
     /// Convenience wrapper constructing the dispatcher solely from environment
@@ -189,6 +738,375 @@ impl Dispatcher {
         self.backend
     }
 
+    /// Change the precision used for subsequent operations.  Inputs are
+    /// rounded to the new precision before being handed to the backend, or
+    /// to the closest more accurate one the backend supports, see
+    /// [`Dispatcher::precision_on`].
+    pub fn set_precision(&mut self, precision: Precision) {
+        tracing::debug!(from = ?self.precision, to = ?precision, "dispatcher precision changed");
+        self.precision = precision;
+    }
+
+    /// Return the precision currently applied to operations.
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// Make results reproducible across runs.  The cost model, whose
+    /// decisions depend on measured timings, is no longer consulted: ops run
+    /// on their explicit route or the default backend.  Callers see the mode
+    /// through [`TensorOps::deterministic`].
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        tracing::debug!(deterministic, "dispatcher determinism changed");
+        self.deterministic = deterministic;
+    }
+
+    /// Builder style variant of [`Dispatcher::set_deterministic`].
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.set_deterministic(deterministic);
+        self
+    }
+
+    /// Check the input sizes of every subsequent op, panicking with a
+    /// [`ShapeError`] on a mismatch.  Enabled by default in debug builds.
+    pub fn set_validation(&mut self, validate: bool) {
+        tracing::debug!(validate, "dispatcher shape validation changed");
+        self.validate = validate;
+    }
+
+    /// Builder style variant of [`Dispatcher::set_validation`].
+    pub fn with_validation(mut self, validate: bool) -> Self {
+        self.set_validation(validate);
+        self
+    }
+
+    /// Whether ops check their input sizes.
+    pub fn validates(&self) -> bool {
+        self.validate
+    }
+
+    /// [`TensorOps::matmul`] returning a [`ShapeError`] instead of running
+    /// on mismatched inputs, whether or not validation is enabled.
+    pub fn try_matmul(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Result<Vec<f32>, ShapeError> {
+        validation::matmul(a, b, m, n, k)?;
+        Ok(self.matmul(a, b, m, n, k))
+    }
+
+    /// Checked variant of [`TensorOps::conv2d`], see [`Dispatcher::try_matmul`].
+    pub fn try_conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Result<Vec<f32>, ShapeError> {
+        validation::conv2d(input, kernel, input_shape, kernel_shape)?;
+        Ok(self.conv2d(input, kernel, input_shape, kernel_shape))
+    }
+
+    /// Checked variant of [`TensorOps::attention`], see [`Dispatcher::try_matmul`].
+    pub fn try_attention(
+        &self,
+        q: &[f32],
+        k: &[f32],
+        v: &[f32],
+        dim: usize,
+    ) -> Result<Vec<f32>, ShapeError> {
+        validation::attention(q, k, dim)?;
+        Ok(self.attention(q, k, v, dim))
+    }
+
+    /// Checked variant of [`TensorOps::layer_norm`], see [`Dispatcher::try_matmul`].
+    pub fn try_layer_norm(
+        &self,
+        x: &[f32],
+        gamma: &[f32],
+        beta: &[f32],
+        eps: f32,
+    ) -> Result<Vec<f32>, ShapeError> {
+        validation::layer_norm(x, gamma, beta)?;
+        Ok(self.layer_norm(x, gamma, beta, eps))
+    }
+
+    /// Panic with the error of `check` when validation is enabled.
+    fn validated(&self, check: impl FnOnce() -> Result<(), ShapeError>) {
+        if self.validate {
+            if let Err(err) = check() {
+                panic!("{err}");
+            }
+        }
+    }
+
+    /// Scan the output of one op out of every `sample_every` for NaN and
+    /// infinities, or stop checking with `None`.
+    pub fn set_health_check(&mut self, sample_every: Option<u64>) {
+        tracing::debug!(?sample_every, "dispatcher health check changed");
+        self.health = sample_every.map(HealthCheck::new);
+    }
+
+    /// Builder variant of [`Dispatcher::set_health_check`].
+    pub fn with_health_check(mut self, sample_every: u64) -> Self {
+        self.set_health_check(Some(sample_every));
+        self
+    }
+
+    /// Ops per op whose output is checked, `None` when outputs are not
+    /// checked.
+    pub fn health_check(&self) -> Option<u64> {
+        self.health.as_ref().map(HealthCheck::sample_every)
+    }
+
+    /// Run small matmuls issued concurrently as batches, or stop with
+    /// `None`.  Matmuls wait up to the window of `config` for company, so
+    /// longer windows trade latency for throughput.
+    pub fn set_coalescing(&mut self, config: Option<CoalesceConfig>) {
+        tracing::debug!(?config, "dispatcher coalescing changed");
+        self.coalescer = config.map(OpCoalescer::new);
+    }
+
+    /// Builder variant of [`Dispatcher::set_coalescing`].
+    pub fn with_coalescing(mut self, config: CoalesceConfig) -> Self {
+        self.set_coalescing(Some(config));
+        self
+    }
+
+    /// Coalescing settings, `None` when matmuls run one by one.
+    pub fn coalescing(&self) -> Option<CoalesceConfig> {
+        self.coalescer.as_ref().map(OpCoalescer::config)
+    }
+
+    /// Matmuls coalesced so far and the batches they ran in.
+    pub fn coalesce_stats(&self) -> CoalesceStats {
+        self.coalescer
+            .as_ref()
+            .map(OpCoalescer::stats)
+            .unwrap_or_default()
+    }
+
+    /// Run a batch of `m x k` by `k x n` matmuls as one op.
+    fn matmul_batch(
+        &self,
+        batch: &[(&[f32], &[f32])],
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Vec<Vec<f32>> {
+        let inputs: Vec<&[f32]> = batch.iter().flat_map(|&(a, b)| [a, b]).collect();
+        let cost = || OpCost::matmul(m * batch.len(), n, k);
+        let out = self.run("matmul", &inputs, cost, |ops, p| {
+            let rounded: Vec<_> = batch
+                .iter()
+                .map(|&(a, b)| (self.round(a, p), self.round(b, p)))
+                .collect();
+            let pairs: Vec<(&[f32], &[f32])> = rounded.iter().map(|(a, b)| (&**a, &**b)).collect();
+            ops.batched_matmul(&pairs, m, n, k).concat()
+        });
+        out.chunks(m * n).map(<[f32]>::to_vec).collect()
+    }
+
+    /// Layer last named through [`TensorOps::set_layer`].
+    pub fn layer(&self) -> Option<String> {
+        self.layer.lock().unwrap().clone()
+    }
+
+    /// Panic with a [`RuntimeError`](crate::RuntimeError) when `out` is
+    /// sampled by the health check and holds a non-finite value.
+    fn check_health(&self, op: &'static str, backend: Backend, out: &[f32]) {
+        let Some(health) = &self.health else { return };
+        if !health.sample() {
+            return;
+        }
+        let layer = self.layer.lock().unwrap();
+        if let Err(err) = health::check_output(op, layer.as_deref(), backend, out) {
+            tracing::error!(%err, "non-finite op output");
+            panic!("{err}");
+        }
+    }
+
+    /// Record every subsequent op into `profiler`, including its duration,
+    /// input sizes and the executing backend.
+    pub fn set_profiler(&mut self, profiler: Arc<Mutex<Profiler>>) {
+        self.profiler = Some(profiler);
+    }
+
+    /// Builder style variant of [`Dispatcher::set_profiler`].
+    pub fn with_profiler(mut self, profiler: Arc<Mutex<Profiler>>) -> Self {
+        self.set_profiler(profiler);
+        self
+    }
+
+    /// Stop profiling, returning the previously attached profiler.
+    pub fn take_profiler(&mut self) -> Option<Arc<Mutex<Profiler>>> {
+        self.profiler.take()
+    }
+
+    /// Specialize the kernels of all backends for the fixed dimensions of a
+    /// loaded model.  Backends already instantiated are recreated.
+    pub fn specialize(&mut self, shapes: KernelShapes) {
+        tracing::debug!(?shapes, "specializing dispatcher kernels");
+        self.shapes = shapes;
+        let live = self.live.get_mut().unwrap();
+        for (&backend, ops) in live.iter_mut() {
+            *ops = Self::specialized_ops(backend, &self.shapes);
+        }
+        self.capabilities.get_mut().unwrap().clear();
+    }
+
+    /// Builder style variant of [`Dispatcher::specialize`].
+    pub fn with_shapes(mut self, shapes: KernelShapes) -> Self {
+        self.specialize(shapes);
+        self
+    }
+
+    /// Shapes the kernels are specialized for.
+    pub fn shapes(&self) -> &KernelShapes {
+        &self.shapes
+    }
+
+    /// Run every op of `shapes` twice on zeroed single-token inputs so
+    /// backends are instantiated and kernels compiled before real traffic,
+    /// and return where each op ran and how long it took.  Pass
+    /// [`Dispatcher::shapes`] to warm up the specialized kernels.
+    pub fn warmup(&self, shapes: &KernelShapes) -> ExecutionPlan {
+        let _span = tracing::debug_span!("warmup", ?shapes).entered();
+        let plan = warmup::warmup(self, shapes);
+        tracing::debug!(
+            ops = plan.ops.len(),
+            cold_us = plan.cold_time().as_micros() as u64,
+            warm_us = plan.warm_time().as_micros() as u64,
+            "dispatcher warmed up"
+        );
+        plan
+    }
+
+    /// Pin the ops of `plan` to their planned backends until
+    /// [`Dispatcher::release_plan`].  Ops planned on backends that are no
+    /// longer available keep being dispatched normally.
+    pub fn capture(&self, mut plan: ExecutionPlan) {
+        plan.ops.retain(|planned| self.available.contains(&planned.backend));
+        tracing::debug!(ops = plan.ops.len(), "execution plan captured");
+        *self.plan.lock().unwrap() = Some(plan);
+    }
+
+    /// Plan set by [`Dispatcher::capture`].
+    pub fn captured_plan(&self) -> Option<ExecutionPlan> {
+        self.plan.lock().unwrap().clone()
+    }
+
+    /// Stop pinning ops, returning the captured plan.
+    pub fn release_plan(&self) -> Option<ExecutionPlan> {
+        self.plan.lock().unwrap().take()
+    }
+
+    /// Pool serving the dispatcher's scratch buffers.
+    pub fn pool(&self) -> &BufferPool {
+        &self.pool
+    }
+
+    /// Replace the buffer pool by one keeping up to `max_cached_bytes` of
+    /// idle buffers.
+    pub fn with_pool_capacity(mut self, max_cached_bytes: u64) -> Self {
+        self.pool = BufferPool::new(max_cached_bytes);
+        self
+    }
+
+    /// Zeroed scratch buffer of `len` elements on `backend` from the
+    /// dispatcher's pool, returned to it when dropped.
+    pub fn scratch(&self, backend: Backend, len: usize) -> PooledBuffer<'_> {
+        self.pool.acquire(backend, len)
+    }
+
+    /// Report the inputs of every subsequent op to `observer`.
+    pub fn add_access_observer(&mut self, observer: SharedObserver) {
+        self.observers.push(observer);
+    }
+
+    /// Builder style variant of [`Dispatcher::add_access_observer`].
+    pub fn with_access_observer(mut self, observer: SharedObserver) -> Self {
+        self.add_access_observer(observer);
+        self
+    }
+
+    fn run(
+        &self,
+        op: &'static str,
+        inputs: &[&[f32]],
+        cost: impl Fn() -> OpCost,
+        f: impl FnOnce(&dyn TensorOps, Precision) -> Vec<f32>,
+    ) -> Vec<f32> {
+        let mut backend = self.backend_for(op, &cost);
+        let largest = inputs
+            .iter()
+            .map(|input| std::mem::size_of_val(*input))
+            .max()
+            .unwrap_or(0);
+        if backend != Backend::Cpu
+            && !self.with_capabilities(backend, |caps| caps.fits(largest as u64))
+        {
+            tracing::debug!(
+                op,
+                ?backend,
+                bytes = largest,
+                "op input exceeds the backend allocation limit, running on cpu"
+            );
+            backend = Backend::Cpu;
+        }
+        let precision = self.precision_on(backend);
+        let ops = self.ops_for(backend);
+        let _span = self.span(op, backend, precision).entered();
+        {
+            let mut placement = self.placement.lock().unwrap();
+            for input in inputs {
+                placement.stage(input, backend);
+            }
+        }
+        for observer in &self.observers {
+            let mut observer = observer.lock().unwrap();
+            for input in inputs {
+                observer.on_access(input);
+            }
+        }
+        let out = match &self.profiler {
+            Some(profiler) => {
+                let input_sizes: Vec<usize> = inputs.iter().map(|i| i.len()).collect();
+                let mut profiler = profiler.lock().unwrap();
+                let out = profiler.profile_op(op, Some(backend.name()), &input_sizes, cost(), || f(&*ops, precision));
+                for (backend, stats) in self.pool.all_stats() {
+                    profiler.record_pool_stats(backend.name(), stats);
+                }
+                out
+            }
+            None => f(&*ops, precision),
+        };
+        self.check_health(op, backend, &out);
+        self.placement.lock().unwrap().record_output(&out, backend);
+        out
+    }
+
+    fn span(&self, op: &'static str, backend: Backend, precision: Precision) -> tracing::Span {
+        tracing::debug_span!("dispatch", op, ?backend, ?precision)
+    }
+
+    /// `data` rounded to `precision` in a pooled host buffer, or `data`
+    /// itself at full precision.
+    fn round<'a>(&'a self, data: &'a [f32], precision: Precision) -> Rounded<'a> {
+        match precision {
+            Precision::F32 => Rounded::Borrowed(data),
+            p => {
+                let mut buffer = self.pool.acquire(Backend::Cpu, data.len());
+                p.round_into(data, &mut buffer);
+                Rounded::Pooled(buffer)
+            }
+        }
+    }
+
     /// Determine if a backend is available.  Availability can be overridden via
     /// `AUREX_DISABLE_*` environment variables for testing purposes.
     fn is_available(backend: Backend) -> bool {
@@ -202,14 +1120,15 @@ impl Dispatcher {
             Backend::Vulkan => {
                 std::env::var("AUREX_DISABLE_VULKAN").is_err() && VulkanBackend::is_available()
             }
+            Backend::Plugin(name) => PluginBackends::global().get(name).is_some(),
         }
     }
 
     /// Automatically select the most appropriate backend for the workload.
-    fn select_backend(workload: Workload) -> Backend {
+    fn select_backend(workload: Workload, available: impl Fn(Backend) -> bool) -> Backend {
         if matches!(workload, Workload::Heavy) {
             for candidate in [Backend::Rocm, Backend::OpenCl, Backend::Sycl, Backend::Vulkan] {
-                if Self::is_available(candidate) {
+                if available(candidate) {
                     return candidate;
                 }
             }
@@ -222,30 +1141,64 @@ impl Dispatcher {
     fn backend_from_env() -> Option<Backend> {
         std::env::var("AUREX_BACKEND")
             .ok()
-            .and_then(|v| match v.to_lowercase().as_str() {
-                "cpu" => Some(Backend::Cpu),
-                "rocm" => Some(Backend::Rocm),
-                "sycl" => Some(Backend::Sycl),
-                "opencl" => Some(Backend::OpenCl),
-                "vulkan" => Some(Backend::Vulkan),
-                _ => None,
-            })
+            .and_then(|v| Backend::from_name(&v))
+    }
+
+    pub(crate) fn backend_ops(backend: Backend) -> SharedOps {
+        match backend {
+            Backend::Cpu => Arc::new(CpuBackend),
+            Backend::Rocm => Arc::new(RocmBackend),
+            Backend::Sycl => Arc::new(SyclBackend::new()),
+            Backend::OpenCl => Arc::new(OpenClBackend),
+            Backend::Vulkan => Arc::new(VulkanBackend::new()),
+            Backend::Plugin(name) => match PluginBackends::global().get(name) {
+                Some(plugin) => plugin.tensor_ops().clone(),
+                None => {
+                    tracing::warn!(plugin = name, "plugin backend not registered, running on cpu");
+                    Arc::new(CpuBackend)
+                }
+            },
+        }
     }
 
-    fn backend_ops(backend: Backend) -> Box<dyn TensorOps + Send + Sync> {
+    /// Like [`Dispatcher::backend_ops`] with kernels specialized for
+    /// `shapes` on backends that generate code.
+    fn specialized_ops(backend: Backend, shapes: &KernelShapes) -> SharedOps {
         match backend {
-            Backend::Cpu => Box::new(CpuBackend),
-            Backend::Rocm => Box::new(RocmBackend),
-            Backend::Sycl => Box::new(SyclBackend::new()),
-            Backend::OpenCl => Box::new(OpenClBackend),
-            Backend::Vulkan => Box::new(VulkanBackend::new()),
+            Backend::Vulkan if !shapes.is_empty() => {
+                Arc::new(VulkanBackend::new().with_shapes(shapes))
+            }
+            _ => Self::backend_ops(backend),
+        }
+    }
+}
+
+/// Op input as handed to a backend by [`Dispatcher::round`].
+enum Rounded<'a> {
+    Borrowed(&'a [f32]),
+    Pooled(PooledBuffer<'a>),
+}
+
+impl std::ops::Deref for Rounded<'_> {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        match self {
+            Rounded::Borrowed(data) => data,
+            Rounded::Pooled(buffer) => buffer,
         }
     }
 }
 
 impl TensorOps for Dispatcher {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
-        self.ops.matmul(a, b, m, n, k)
+        self.validated(|| validation::matmul(a, b, m, n, k));
+        if let Some(coalescer) = self.coalescer.as_ref().filter(|c| c.accepts(m, n, k)) {
+            return coalescer.matmul(a, b, m, n, k, |batch| self.matmul_batch(batch, m, n, k));
+        }
+        self.run("matmul", &[a, b], || OpCost::matmul(m, n, k), |ops, p| {
+            ops.matmul(&self.round(a, p), &self.round(b, p), m, n, k)
+        })
     }
 
     fn conv2d(
@@ -255,14 +1208,55 @@ impl TensorOps for Dispatcher {
         input_shape: (usize, usize),
         kernel_shape: (usize, usize),
     ) -> Vec<f32> {
-        self.ops.conv2d(input, kernel, input_shape, kernel_shape)
+        self.validated(|| validation::conv2d(input, kernel, input_shape, kernel_shape));
+        let cost = || OpCost::conv2d(input_shape, kernel_shape);
+        self.run("conv2d", &[input, kernel], cost, |ops, p| {
+            ops.conv2d(&self.round(input, p), &self.round(kernel, p), input_shape, kernel_shape)
+        })
     }
 
     fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
-        self.ops.attention(q, k, v, dim)
+        self.validated(|| validation::attention(q, k, dim));
+        let cost = || OpCost::attention(q.len(), v.len());
+        self.run("attention", &[q, k, v], cost, |ops, p| {
+            ops.attention(&self.round(q, p), &self.round(k, p), &self.round(v, p), dim)
+        })
     }
 
     fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
-        self.ops.layer_norm(x, gamma, beta, eps)
+        self.validated(|| validation::layer_norm(x, gamma, beta));
+        let cost = || OpCost::layer_norm(x.len());
+        self.run("layer_norm", &[x, gamma, beta], cost, |ops, p| {
+            ops.layer_norm(&self.round(x, p), gamma, beta, eps)
+        })
+    }
+
+    fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        Dispatcher::capabilities(self, self.backend)
+    }
+
+    fn set_layer(&self, layer: Option<&str>) {
+        let mut current = self.layer.lock().unwrap();
+        if current.as_deref() != layer {
+            *current = layer.map(str::to_owned);
+        }
+    }
+
+    /// Batches the backends instantiated so far; ops routed to a backend
+    /// first used inside the batch are submitted one by one.
+    fn begin_batch(&self) {
+        for ops in self.live.lock().unwrap().values() {
+            ops.begin_batch();
+        }
+    }
+
+    fn end_batch(&self) {
+        for ops in self.live.lock().unwrap().values() {
+            ops.end_batch();
+        }
     }
 }
@@ -0,0 +1,108 @@
+//! Numerical health checks of op outputs.
+//!
+//! A NaN or infinity produced by one op, e.g. from a badly scaled quantized
+//! weight or a kernel bug, propagates through every later op and only shows
+//! up as garbage tokens.  [`HealthCheck`] scans the outputs of a sample of
+//! ops for non-finite values and reports the first one as a
+//! [`RuntimeError`] naming the op, the layer that issued it and the backend
+//! that ran it.  Scanning every output costs a pass over each result, so the
+//! [`Dispatcher`](crate::Dispatcher) checks one op out of every
+//! [`HealthCheck::sample_every`].
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::dispatch::Backend;
+
+/// Failure of an op detected while it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    /// The output of `op` holds `value`, a NaN or an infinity, at `index`.
+    NonFinite {
+        op: &'static str,
+        /// Layer that issued the op, when the model named it.
+        layer: Option<String>,
+        backend: Backend,
+        index: usize,
+        value: f32,
+    },
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::NonFinite {
+                op,
+                layer,
+                backend,
+                index,
+                value,
+            } => {
+                write!(f, "{op}")?;
+                if let Some(layer) = layer {
+                    write!(f, " in layer {layer}")?;
+                }
+                write!(f, " on {backend} produced {value} at index {index}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Index and value of the first NaN or infinity in `data`.
+pub fn first_non_finite(data: &[f32]) -> Option<(usize, f32)> {
+    data.iter()
+        .position(|v| !v.is_finite())
+        .map(|i| (i, data[i]))
+}
+
+/// Check the output of `op`, run on `backend` for `layer`, for non-finite
+/// values.
+pub fn check_output(
+    op: &'static str,
+    layer: Option<&str>,
+    backend: Backend,
+    out: &[f32],
+) -> Result<(), RuntimeError> {
+    match first_non_finite(out) {
+        None => Ok(()),
+        Some((index, value)) => Err(RuntimeError::NonFinite {
+            op,
+            layer: layer.map(str::to_owned),
+            backend,
+            index,
+            value,
+        }),
+    }
+}
+
+/// Sampler choosing the ops whose outputs are checked.
+#[derive(Debug)]
+pub struct HealthCheck {
+    sample_every: u64,
+    ops: AtomicU64,
+}
+
+impl HealthCheck {
+    /// Check the first op and then one op out of every `sample_every`; `1`
+    /// checks every op.
+    pub fn new(sample_every: u64) -> Self {
+        Self {
+            sample_every: sample_every.max(1),
+            ops: AtomicU64::new(0),
+        }
+    }
+
+    /// Ops per checked op.
+    pub fn sample_every(&self) -> u64 {
+        self.sample_every
+    }
+
+    /// Count an op and tell whether its output is to be checked.
+    pub fn sample(&self) -> bool {
+        self.ops
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample_every)
+    }
+}
@@ -1,9 +1,32 @@
 //! Backend dispatch layer routing operations to device implementations.
 
+pub mod capabilities;
+pub mod coalesce;
+pub mod cost_model;
+pub mod cpu_pool;
 pub mod dispatch;
+pub mod health;
+pub mod memory_pool;
+pub mod placement;
+pub mod plugin_backend;
+pub mod specialization;
 pub mod vulkan_backend;
 pub mod sycl_backend;
+pub mod tensor_parallel;
+pub mod validation;
+pub mod warmup;
 
-pub use dispatch::{Backend, Dispatcher, Workload, TensorOps};
+pub use capabilities::BackendCapabilities;
+pub use coalesce::{CoalesceConfig, CoalesceStats};
+pub use cost_model::CostModel;
+pub use cpu_pool::{CoreSelection, CpuThreadPool, ThreadPoolConfig};
+pub use dispatch::{AccessObserver, Backend, Dispatcher, Precision, Workload, TensorOps};
+pub use health::{HealthCheck, RuntimeError};
+pub use memory_pool::{BufferPool, PooledBuffer};
+pub use plugin_backend::{PluginBackend, PluginBackends};
+pub use specialization::KernelShapes;
 pub use vulkan_backend::VulkanBackend;
 pub use sycl_backend::SyclBackend;
+pub use tensor_parallel::{CpuAffinity, TensorParallelDispatcher};
+pub use validation::ShapeError;
+pub use warmup::{ExecutionPlan, PlannedOp};
@@ -0,0 +1,184 @@
+//! Pooled scratch buffers for host and device memory.
+//!
+//! Ops that need temporary storage, such as inputs rounded to a reduced
+//! precision or staging buffers on a device, would otherwise allocate and
+//! free a buffer on every call.  [`BufferPool`] keeps released buffers in
+//! slabs keyed by backend and size class, a power of two number of elements,
+//! and hands them out again to later requests of the same class.  Host memory
+//! is pooled under [`Backend::Cpu`].
+//!
+//! Idle buffers are bounded by a byte budget: buffers released once the
+//! budget is used up are freed instead of cached.
+
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use aurex_utils::profiler::PoolStats;
+
+use crate::dispatch::Backend;
+
+/// Smallest size class in elements.
+pub const MIN_SIZE_CLASS: usize = 64;
+
+/// Default budget of idle buffers in bytes.
+pub const DEFAULT_POOL_BYTES: u64 = 256 << 20;
+
+/// Size class of a request for `len` elements: the capacity of the buffers
+/// serving it.
+pub fn size_class(len: usize) -> usize {
+    len.next_power_of_two().max(MIN_SIZE_CLASS)
+}
+
+fn bytes(elements: usize) -> u64 {
+    (elements * std::mem::size_of::<f32>()) as u64
+}
+
+#[derive(Debug, Default)]
+struct State {
+    slabs: HashMap<(Backend, usize), Vec<Vec<f32>>>,
+    stats: HashMap<Backend, PoolStats>,
+    cached: u64,
+}
+
+/// Size class slab allocator of `f32` scratch buffers.
+#[derive(Debug)]
+pub struct BufferPool {
+    state: Mutex<State>,
+    max_cached_bytes: u64,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_BYTES)
+    }
+}
+
+impl BufferPool {
+    /// Create a pool keeping up to `max_cached_bytes` of idle buffers.
+    pub fn new(max_cached_bytes: u64) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            max_cached_bytes,
+        }
+    }
+
+    /// Budget of idle buffers in bytes.
+    pub fn max_cached_bytes(&self) -> u64 {
+        self.max_cached_bytes
+    }
+
+    /// Zeroed buffer of `len` elements on `backend`, reusing an idle buffer
+    /// of the same size class when there is one.  The buffer returns to the
+    /// pool when dropped.
+    pub fn acquire(&self, backend: Backend, len: usize) -> PooledBuffer<'_> {
+        let class = size_class(len);
+        let mut state = self.state.lock().unwrap();
+        let reused = state.slabs.get_mut(&(backend, class)).and_then(Vec::pop);
+        let stats = state.stats.entry(backend).or_default();
+        let mut data = match reused {
+            Some(data) => {
+                stats.reuses += 1;
+                stats.bytes_reused += bytes(class);
+                stats.bytes_cached -= bytes(class);
+                state.cached -= bytes(class);
+                data
+            }
+            None => {
+                stats.allocations += 1;
+                stats.bytes_allocated += bytes(class);
+                Vec::with_capacity(class)
+            }
+        };
+        data.resize(len, 0.0);
+        PooledBuffer {
+            pool: self,
+            backend,
+            data,
+        }
+    }
+
+    fn release(&self, backend: Backend, mut data: Vec<f32>) {
+        let class = data.capacity();
+        if class != size_class(class) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.cached + bytes(class) > self.max_cached_bytes {
+            return;
+        }
+        data.clear();
+        state.cached += bytes(class);
+        state.stats.entry(backend).or_default().bytes_cached += bytes(class);
+        state.slabs.entry((backend, class)).or_default().push(data);
+    }
+
+    /// Reuse statistics of the buffers of `backend`.
+    pub fn stats(&self, backend: Backend) -> PoolStats {
+        self.state
+            .lock()
+            .unwrap()
+            .stats
+            .get(&backend)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Reuse statistics of every backend that requested a buffer.
+    pub fn all_stats(&self) -> Vec<(Backend, PoolStats)> {
+        let state = self.state.lock().unwrap();
+        state.stats.iter().map(|(&b, &s)| (b, s)).collect()
+    }
+
+    /// Bytes held by idle buffers of all backends.
+    pub fn cached_bytes(&self) -> u64 {
+        self.state.lock().unwrap().cached
+    }
+
+    /// Free all idle buffers.
+    pub fn trim(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.slabs.clear();
+        state.cached = 0;
+        for stats in state.stats.values_mut() {
+            stats.bytes_cached = 0;
+        }
+    }
+}
+
+/// Buffer handed out by [`BufferPool::acquire`], dereferencing to its
+/// elements.
+#[derive(Debug)]
+pub struct PooledBuffer<'a> {
+    pool: &'a BufferPool,
+    backend: Backend,
+    data: Vec<f32>,
+}
+
+impl PooledBuffer<'_> {
+    /// Backend whose memory holds the buffer.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        &self.data
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut [f32] {
+        &mut self.data
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool
+            .release(self.backend, std::mem::take(&mut self.data));
+    }
+}
@@ -0,0 +1,119 @@
+//! Residency tracking of tensors across backends.
+//!
+//! When a dispatcher routes ops to several devices, every input has to be
+//! present on the device executing the op.  [`PlacementTracker`] remembers on
+//! which backends a buffer already lives so a tensor is only copied the first
+//! time a device needs it, and counts the bytes that were transferred or
+//! reused.  Buffers are identified by address and length; untracked buffers
+//! are assumed to live in host memory only.
+
+use std::collections::HashMap;
+
+use crate::dispatch::Backend;
+
+/// Upper bound on tracked buffers.  The tracker starts over when it is
+/// exceeded, which can only over-count transfers.
+pub const MAX_TRACKED_BUFFERS: usize = 4096;
+
+const HOST: u8 = 1 << 0;
+
+fn bit(backend: Backend) -> u8 {
+    match backend {
+        Backend::Cpu => HOST,
+        Backend::Rocm => 1 << 1,
+        Backend::Sycl => 1 << 2,
+        Backend::OpenCl => 1 << 3,
+        Backend::Vulkan => 1 << 4,
+        // Plugin devices share a bit, so a buffer staged on one plugin counts
+        // as resident on all of them.
+        Backend::Plugin(_) => 1 << 5,
+    }
+}
+
+/// Totals of the copies performed while staging op inputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    /// Number of buffers copied to another backend.
+    pub transfers: u64,
+    pub bytes_transferred: u64,
+    /// Bytes that were already resident on the executing backend.
+    pub bytes_reused: u64,
+}
+
+/// Records which backends hold a copy of each buffer.
+#[derive(Debug, Default)]
+pub struct PlacementTracker {
+    resident: HashMap<(usize, usize), u8>,
+    stats: TransferStats,
+}
+
+impl PlacementTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(data: &[f32]) -> (usize, usize) {
+        (data.as_ptr() as usize, data.len())
+    }
+
+    fn mask(&self, data: &[f32]) -> u8 {
+        self.resident.get(&Self::key(data)).copied().unwrap_or(HOST)
+    }
+
+    /// Make `data` available on `backend`, returning `true` when it had to be
+    /// copied there.
+    pub fn stage(&mut self, data: &[f32], backend: Backend) -> bool {
+        if data.is_empty() {
+            return false;
+        }
+        let bytes = std::mem::size_of_val(data) as u64;
+        let mask = self.mask(data);
+        if mask & bit(backend) != 0 {
+            self.stats.bytes_reused += bytes;
+            return false;
+        }
+        self.stats.transfers += 1;
+        self.stats.bytes_transferred += bytes;
+        self.insert(data, mask | bit(backend));
+        true
+    }
+
+    /// Record that `data` was produced on `backend` and only lives there.
+    pub fn record_output(&mut self, data: &[f32], backend: Backend) {
+        if data.is_empty() {
+            return;
+        }
+        if backend == Backend::Cpu {
+            self.resident.remove(&Self::key(data));
+        } else {
+            self.insert(data, bit(backend));
+        }
+    }
+
+    fn insert(&mut self, data: &[f32], mask: u8) {
+        if self.resident.len() >= MAX_TRACKED_BUFFERS {
+            self.resident.clear();
+        }
+        self.resident.insert(Self::key(data), mask);
+    }
+
+    /// Whether `data` currently has a copy on `backend`.
+    pub fn is_resident(&self, data: &[f32], backend: Backend) -> bool {
+        self.mask(data) & bit(backend) != 0
+    }
+
+    /// Stop tracking `data`, e.g. before its buffer is reused for new values.
+    pub fn forget(&mut self, data: &[f32]) {
+        self.resident.remove(&Self::key(data));
+    }
+
+    /// Forget all placements and reset the statistics.
+    pub fn clear(&mut self) {
+        self.resident.clear();
+        self.stats = TransferStats::default();
+    }
+
+    pub fn stats(&self) -> TransferStats {
+        self.stats
+    }
+}
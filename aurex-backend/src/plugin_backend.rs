@@ -0,0 +1,125 @@
+//! Backends provided by plugins loaded at runtime.
+//!
+//! The runtime loads plugin libraries and registers the tensor ops of each
+//! plugin here under its name, together with the ops and devices listed in
+//! the plugin's manifest.  The registry is process wide so every dispatcher
+//! sees the same plugins.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::dispatch::TensorOps;
+
+/// Tensor ops of a loaded plugin and the capabilities it declares.
+#[derive(Clone)]
+pub struct PluginBackend {
+    name: String,
+    ops: Vec<String>,
+    devices: Vec<String>,
+    tensor_ops: Arc<dyn TensorOps + Send + Sync>,
+}
+
+impl PluginBackend {
+    pub fn new(name: impl Into<String>, tensor_ops: Arc<dyn TensorOps + Send + Sync>) -> Self {
+        Self {
+            name: name.into(),
+            ops: Vec::new(),
+            devices: Vec::new(),
+            tensor_ops,
+        }
+    }
+
+    /// Ops the plugin implements, e.g. `["matmul", "layer_norm"]`.
+    pub fn with_ops<S: Into<String>>(mut self, ops: impl IntoIterator<Item = S>) -> Self {
+        self.ops = ops.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Devices the plugin drives.
+    pub fn with_devices<S: Into<String>>(mut self, devices: impl IntoIterator<Item = S>) -> Self {
+        self.devices = devices.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn ops(&self) -> &[String] {
+        &self.ops
+    }
+
+    pub fn devices(&self) -> &[String] {
+        &self.devices
+    }
+
+    pub fn tensor_ops(&self) -> &Arc<dyn TensorOps + Send + Sync> {
+        &self.tensor_ops
+    }
+
+    /// Whether the plugin implements `op`.  A plugin declaring no ops is
+    /// assumed to implement all of them.
+    pub fn supports(&self, op: &str) -> bool {
+        self.ops.is_empty() || self.ops.iter().any(|o| o == op)
+    }
+}
+
+impl fmt::Debug for PluginBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PluginBackend")
+            .field("name", &self.name)
+            .field("ops", &self.ops)
+            .field("devices", &self.devices)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Plugin backends by name.
+#[derive(Debug, Default)]
+pub struct PluginBackends {
+    backends: RwLock<BTreeMap<String, PluginBackend>>,
+}
+
+impl PluginBackends {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process wide registry the runtime registers loaded plugins with.
+    pub fn global() -> &'static PluginBackends {
+        static GLOBAL: OnceLock<PluginBackends> = OnceLock::new();
+        GLOBAL.get_or_init(PluginBackends::new)
+    }
+
+    /// Register `backend` under its name, returning the backend it replaces.
+    pub fn register(&self, backend: PluginBackend) -> Option<PluginBackend> {
+        tracing::debug!(plugin = backend.name(), ops = ?backend.ops(), "registered plugin backend");
+        self.backends
+            .write()
+            .unwrap()
+            .insert(backend.name.clone(), backend)
+    }
+
+    pub fn remove(&self, name: &str) -> Option<PluginBackend> {
+        self.backends.write().unwrap().remove(name)
+    }
+
+    /// Whether the plugin called `name` is registered and implements `op`.
+    pub fn supports(&self, name: &str, op: &str) -> bool {
+        self.backends
+            .read()
+            .unwrap()
+            .get(name)
+            .is_some_and(|backend| backend.supports(op))
+    }
+
+    pub fn get(&self, name: &str) -> Option<PluginBackend> {
+        self.backends.read().unwrap().get(name).cloned()
+    }
+
+    /// Names of the registered plugins in sorted order.
+    pub fn names(&self) -> Vec<String> {
+        self.backends.read().unwrap().keys().cloned().collect()
+    }
+}
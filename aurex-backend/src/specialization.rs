@@ -0,0 +1,70 @@
+//! Static kernel shapes of a loaded model.
+//!
+//! The hidden, head and vocabulary dimensions of a model are fixed once its
+//! weights are loaded.  Backends that can generate code compile kernels with
+//! these dimensions as constants: the JIT emits loops with constant trip
+//! counts, which LLVM fully unrolls and vectorizes, and the Vulkan backend
+//! sets them as shader specialization constants.  Ops whose shapes are not
+//! listed keep running on the generic kernels.
+
+/// Dimensions a model runs its kernels with.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct KernelShapes {
+    /// `(n, k)` of the matmuls, the dimensions set by the weights; `m` is the
+    /// number of tokens and varies between calls.
+    pub matmuls: Vec<(usize, usize)>,
+    /// Row lengths of layer norms.
+    pub norms: Vec<usize>,
+    /// Head dimensions of attention.
+    pub head_dims: Vec<usize>,
+}
+
+impl KernelShapes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a matmul with a `k×n` right-hand side.
+    pub fn with_matmul(mut self, n: usize, k: usize) -> Self {
+        if !self.matmuls.contains(&(n, k)) {
+            self.matmuls.push((n, k));
+        }
+        self
+    }
+
+    /// Add a layer norm over rows of `len` values.
+    pub fn with_norm(mut self, len: usize) -> Self {
+        if !self.norms.contains(&len) {
+            self.norms.push(len);
+        }
+        self
+    }
+
+    /// Add attention with heads of `dim` values.
+    pub fn with_head_dim(mut self, dim: usize) -> Self {
+        if !self.head_dims.contains(&dim) {
+            self.head_dims.push(dim);
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matmuls.is_empty() && self.norms.is_empty() && self.head_dims.is_empty()
+    }
+
+    /// Values of the specialization constants of the kernel of `op` for each
+    /// listed shape.  Constant `i` of a kernel is bound to the `i`-th value:
+    /// `N, K` for matmuls, `LEN` for layer norms and `DIM` for attention.
+    pub fn spec_constants(&self, op: &str) -> Vec<Vec<u32>> {
+        match op {
+            "matmul" => self
+                .matmuls
+                .iter()
+                .map(|&(n, k)| vec![n as u32, k as u32])
+                .collect(),
+            "layer_norm" => self.norms.iter().map(|&len| vec![len as u32]).collect(),
+            "attention" => self.head_dims.iter().map(|&dim| vec![dim as u32]).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
@@ -7,6 +7,7 @@
 //! implementation.  This allows the dispatch layer to exercise the SYCL code
 //! paths during tests while staying fully portable.
 
+use crate::capabilities::BackendCapabilities;
 use crate::dispatch::{CpuBackend, TensorOps};
 
 /// Representation of a SYCL device.  In the emulated implementation only an id
@@ -190,6 +191,15 @@ impl TensorOps for SyclBackend {
         self.launch(|| {});
         cpu.layer_norm(x, gamma, beta, eps)
     }
+
+    /// The emulated device is the host CPU behind an asynchronous queue.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            max_workgroup_size: 8192,
+            async_execution: true,
+            ..BackendCapabilities::host()
+        }
+    }
 }
 
 /// Initialize the SYCL backend by probing available devices.
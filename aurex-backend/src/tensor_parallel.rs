@@ -0,0 +1,369 @@
+//! Tensor parallel execution across several devices.
+//!
+//! [`TensorParallelDispatcher`] splits the weight matrix `b` of a matmul
+//! across N devices.  Column sharding gives every device a slice of the output
+//! columns which are gathered afterwards; row sharding splits the inner
+//! dimension so every device produces a full-size partial result that is
+//! summed with an all-reduce.  Weights can be sharded once with
+//! [`TensorParallelDispatcher::shard_weights`] and kept on their devices, so a
+//! model only needs `1/N` of its weights per device.
+//!
+//! Shards run on one worker thread each.  On NUMA servers the workers can be
+//! pinned with a [`CpuAffinity`]; weights sharded by a pinned dispatcher are
+//! then copied by the worker owning the shard, so the kernel's first-touch
+//! policy places them on that worker's node.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use aurex_utils::numa::{self, NumaTopology};
+use serde::{Deserialize, Serialize};
+
+use crate::capabilities::BackendCapabilities;
+use crate::dispatch::{Backend, Dispatcher, TensorOps, Workload};
+
+/// How matmul weights are split across devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardStrategy {
+    /// Split the output columns; results are concatenated.
+    #[default]
+    Column,
+    /// Split the inner dimension; partial results are summed.
+    Row,
+}
+
+/// Pinning of the worker threads running shards on the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuAffinity {
+    /// Leave scheduling to the OS.
+    #[default]
+    None,
+    /// Pin worker `r` to the CPUs of NUMA node `r % nodes`.
+    Node,
+    /// Pin worker `r` to a single CPU, filling one node before the next.
+    Core,
+}
+
+impl CpuAffinity {
+    /// CPUs worker `rank` is pinned to, `None` when it is not pinned.
+    pub fn cpus(self, topology: &NumaTopology, rank: usize) -> Option<Vec<usize>> {
+        match self {
+            CpuAffinity::None => None,
+            CpuAffinity::Node => {
+                let nodes = topology.nodes();
+                Some(nodes[rank % nodes.len()].cpus.clone())
+            }
+            CpuAffinity::Core => {
+                let cpus: Vec<usize> = topology.cpus().collect();
+                Some(vec![cpus[rank % cpus.len()]])
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for CpuAffinity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(CpuAffinity::None),
+            "node" => Ok(CpuAffinity::Node),
+            "core" => Ok(CpuAffinity::Core),
+            other => Err(format!("unknown cpu affinity '{other}'")),
+        }
+    }
+}
+
+/// Part of a `k x n` weight matrix held by one device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightShard {
+    /// Index of the device holding the shard.
+    pub rank: usize,
+    pub strategy: ShardStrategy,
+    /// Columns (for [`ShardStrategy::Column`]) or rows
+    /// ([`ShardStrategy::Row`]) of the full matrix covered by the shard.
+    pub range: Range<usize>,
+    /// Row-major shard data.
+    pub data: Vec<f32>,
+}
+
+impl WeightShard {
+    pub fn bytes(&self) -> usize {
+        std::mem::size_of_val(self.data.as_slice())
+    }
+}
+
+/// Even split of `len` items into `parts`, the first `len % parts` ranges
+/// holding one extra item.
+pub fn shard_range(len: usize, parts: usize, rank: usize) -> Range<usize> {
+    let base = len / parts;
+    let extra = len % parts;
+    let start = rank * base + rank.min(extra);
+    start..start + base + usize::from(rank < extra)
+}
+
+/// Sum equally sized partial results element-wise.
+pub fn all_reduce_sum(partials: Vec<Vec<f32>>) -> Vec<f32> {
+    let mut iter = partials.into_iter();
+    let mut total = iter.next().unwrap_or_default();
+    for partial in iter {
+        for (t, p) in total.iter_mut().zip(partial) {
+            *t += p;
+        }
+    }
+    total
+}
+
+/// Concatenate column blocks of `m` rows into a single row-major matrix.
+pub fn all_gather_columns(blocks: Vec<Vec<f32>>, widths: &[usize], m: usize) -> Vec<f32> {
+    let n: usize = widths.iter().sum();
+    let mut out = vec![0.0; m * n];
+    let mut offset = 0;
+    for (block, &w) in blocks.iter().zip(widths) {
+        for row in 0..m {
+            out[row * n + offset..row * n + offset + w]
+                .copy_from_slice(&block[row * w..(row + 1) * w]);
+        }
+        offset += w;
+    }
+    out
+}
+
+/// Dispatcher executing matmuls tensor parallel across several devices.
+/// Other ops are replicated and run on the first device.
+pub struct TensorParallelDispatcher {
+    devices: Vec<Arc<dyn TensorOps + Send + Sync>>,
+    strategy: ShardStrategy,
+    affinity: CpuAffinity,
+    topology: Option<NumaTopology>,
+}
+
+impl TensorParallelDispatcher {
+    /// Use one device per entry of `backends`; a backend may appear several
+    /// times to address multiple GPUs of the same kind.
+    pub fn new(backends: &[Backend]) -> Self {
+        let devices = backends
+            .iter()
+            .map(|&b| {
+                Arc::new(Dispatcher::new(Some(b), Workload::Heavy))
+                    as Arc<dyn TensorOps + Send + Sync>
+            })
+            .collect();
+        Self::from_devices(devices)
+    }
+
+    /// Use the given device implementations.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `devices` is empty.
+    pub fn from_devices(devices: Vec<Arc<dyn TensorOps + Send + Sync>>) -> Self {
+        assert!(
+            !devices.is_empty(),
+            "tensor parallelism needs at least one device"
+        );
+        Self {
+            devices,
+            strategy: ShardStrategy::default(),
+            affinity: CpuAffinity::default(),
+            topology: None,
+        }
+    }
+
+    /// Shard strategy used by [`TensorOps::matmul`].
+    pub fn with_strategy(mut self, strategy: ShardStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn strategy(&self) -> ShardStrategy {
+        self.strategy
+    }
+
+    /// Pin the shard workers to CPUs of the detected NUMA topology.
+    pub fn with_affinity(self, affinity: CpuAffinity) -> Self {
+        self.with_topology(affinity, NumaTopology::detect())
+    }
+
+    /// Pin the shard workers to CPUs of `topology`.
+    pub fn with_topology(mut self, affinity: CpuAffinity, topology: NumaTopology) -> Self {
+        self.affinity = affinity;
+        self.topology = Some(topology);
+        self
+    }
+
+    pub fn affinity(&self) -> CpuAffinity {
+        self.affinity
+    }
+
+    /// Pin the calling worker thread to the CPUs of `rank`.  Failures only
+    /// cost locality, so they are logged and otherwise ignored.
+    fn pin_worker(&self, rank: usize) {
+        let Some(cpus) = self
+            .topology
+            .as_ref()
+            .and_then(|topology| self.affinity.cpus(topology, rank))
+        else {
+            return;
+        };
+        if let Err(err) = numa::pin_current_thread(&cpus) {
+            tracing::debug!(rank, %err, "cannot pin shard worker");
+        }
+    }
+
+    /// Number of devices.
+    pub fn world_size(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Split a row-major `k x n` weight matrix into one shard per device.
+    pub fn shard_weights(
+        &self,
+        b: &[f32],
+        k: usize,
+        n: usize,
+        strategy: ShardStrategy,
+    ) -> Vec<WeightShard> {
+        let shard = |rank| {
+            let (range, data) = match strategy {
+                ShardStrategy::Column => {
+                    let cols = shard_range(n, self.world_size(), rank);
+                    let data = (0..k)
+                        .flat_map(|row| b[row * n + cols.start..row * n + cols.end].iter().copied())
+                        .collect();
+                    (cols, data)
+                }
+                ShardStrategy::Row => {
+                    let rows = shard_range(k, self.world_size(), rank);
+                    (rows.clone(), b[rows.start * n..rows.end * n].to_vec())
+                }
+            };
+            WeightShard {
+                rank,
+                strategy,
+                range,
+                data,
+            }
+        };
+        if self.affinity == CpuAffinity::None {
+            return (0..self.world_size()).map(shard).collect();
+        }
+        // Copy each shard on its pinned worker so its pages are first
+        // touched on the worker's node.
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.world_size())
+                .map(|rank| {
+                    let shard = &shard;
+                    scope.spawn(move || {
+                        self.pin_worker(rank);
+                        shard(rank)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("shard worker panicked"))
+                .collect()
+        })
+    }
+
+    /// Multiply the `m x k` matrix `a` with pre-sharded `k x n` weights.  Every
+    /// shard runs on its device in parallel before the results are combined.
+    pub fn matmul_sharded(
+        &self,
+        a: &[f32],
+        shards: &[WeightShard],
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Vec<f32> {
+        let results: Vec<Vec<f32>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = shards
+                .iter()
+                .map(|shard| {
+                    let device = self.devices[shard.rank % self.devices.len()].clone();
+                    scope.spawn(move || {
+                        self.pin_worker(shard.rank);
+                        match shard.strategy {
+                            ShardStrategy::Column => {
+                                device.matmul(a, &shard.data, m, shard.range.len(), k)
+                            }
+                            ShardStrategy::Row => {
+                                let width = shard.range.len();
+                                let a_cols: Vec<f32> = (0..m)
+                                    .flat_map(|row| {
+                                        a[row * k + shard.range.start..row * k + shard.range.end]
+                                            .iter()
+                                            .copied()
+                                    })
+                                    .collect();
+                                device.matmul(&a_cols, &shard.data, m, n, width)
+                            }
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("shard worker panicked"))
+                .collect()
+        });
+
+        match shards.first().map(|s| s.strategy) {
+            Some(ShardStrategy::Column) => {
+                let widths: Vec<usize> = shards.iter().map(|s| s.range.len()).collect();
+                all_gather_columns(results, &widths, m)
+            }
+            Some(ShardStrategy::Row) => all_reduce_sum(results),
+            None => vec![0.0; m * n],
+        }
+    }
+}
+
+impl TensorOps for TensorParallelDispatcher {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        let shards = self.shard_weights(b, k, n, self.strategy);
+        self.matmul_sharded(a, &shards, m, n, k)
+    }
+
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        self.devices[0].conv2d(input, kernel, input_shape, kernel_shape)
+    }
+
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        self.devices[0].attention(q, k, v, dim)
+    }
+
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        self.devices[0].layer_norm(x, gamma, beta, eps)
+    }
+
+    fn deterministic(&self) -> bool {
+        self.devices.iter().any(|device| device.deterministic())
+    }
+
+    /// What every device of the group can execute.
+    fn capabilities(&self) -> BackendCapabilities {
+        let devices: Vec<_> = self.devices.iter().map(|device| device.capabilities()).collect();
+        BackendCapabilities::intersect(&devices)
+    }
+
+    fn begin_batch(&self) {
+        for device in &self.devices {
+            device.begin_batch();
+        }
+    }
+
+    fn end_batch(&self) {
+        for device in &self.devices {
+            device.end_batch();
+        }
+    }
+}
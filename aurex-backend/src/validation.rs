@@ -0,0 +1,149 @@
+//! Shape checks of op inputs.
+//!
+//! Backends index their inputs assuming the dimensions passed alongside
+//! them are right, so a mismatch surfaces as an out of bounds panic deep in a
+//! kernel, or as silently wrong results.  The checks here compare every input
+//! against the dimensions of its op and report the first mismatch as a
+//! [`ShapeError`] naming the op, the operand and the expected and actual
+//! sizes.  The [`Dispatcher`](crate::Dispatcher) runs them before each op
+//! when validation is enabled.
+
+use std::fmt;
+
+/// Size an operand is required to have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    Exactly(usize),
+    AtLeast(usize),
+    AtMost(usize),
+}
+
+impl Expected {
+    fn admits(self, actual: usize) -> bool {
+        match self {
+            Expected::Exactly(n) => actual == n,
+            Expected::AtLeast(n) => actual >= n,
+            Expected::AtMost(n) => actual <= n,
+        }
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Exactly(n) => write!(f, "{n}"),
+            Expected::AtLeast(n) => write!(f, "at least {n}"),
+            Expected::AtMost(n) => write!(f, "at most {n}"),
+        }
+    }
+}
+
+/// Operand of an op whose size does not match the op's dimensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShapeError {
+    pub op: &'static str,
+    /// Operand or dimension at fault, e.g. `"b"` or `"kernel rows"`.
+    pub operand: &'static str,
+    pub expected: Expected,
+    pub actual: usize,
+    /// Dimensions the op was called with, e.g. `[("m", 2), ("n", 3)]`.
+    pub dims: Vec<(&'static str, usize)>,
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (", self.op)?;
+        for (i, (name, value)) in self.dims.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            write!(f, "{sep}{name}={value}")?;
+        }
+        write!(
+            f,
+            "): {} has size {}, expected {}",
+            self.operand, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+/// Collects the checks of one op and reports the first that fails.
+struct Checks<'a> {
+    op: &'static str,
+    dims: &'a [(&'static str, usize)],
+    failed: Option<(&'static str, Expected, usize)>,
+}
+
+impl<'a> Checks<'a> {
+    fn new(op: &'static str, dims: &'a [(&'static str, usize)]) -> Self {
+        Self {
+            op,
+            dims,
+            failed: None,
+        }
+    }
+
+    fn check(mut self, operand: &'static str, expected: Expected, actual: usize) -> Self {
+        if self.failed.is_none() && !expected.admits(actual) {
+            self.failed = Some((operand, expected, actual));
+        }
+        self
+    }
+
+    fn finish(self) -> Result<(), ShapeError> {
+        match self.failed {
+            None => Ok(()),
+            Some((operand, expected, actual)) => Err(ShapeError {
+                op: self.op,
+                operand,
+                expected,
+                actual,
+                dims: self.dims.to_vec(),
+            }),
+        }
+    }
+}
+
+/// Check the inputs of an `m x k` by `k x n` matmul.
+pub fn matmul(a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Result<(), ShapeError> {
+    Checks::new("matmul", &[("m", m), ("n", n), ("k", k)])
+        .check("a", Expected::Exactly(m * k), a.len())
+        .check("b", Expected::Exactly(k * n), b.len())
+        .finish()
+}
+
+/// Check the inputs of an unpadded convolution of an `ih x iw` input with a
+/// `kh x kw` kernel, which must not be larger than the input.
+pub fn conv2d(
+    input: &[f32],
+    kernel: &[f32],
+    (ih, iw): (usize, usize),
+    (kh, kw): (usize, usize),
+) -> Result<(), ShapeError> {
+    Checks::new("conv2d", &[("ih", ih), ("iw", iw), ("kh", kh), ("kw", kw)])
+        .check("input", Expected::Exactly(ih * iw), input.len())
+        .check("kernel", Expected::Exactly(kh * kw), kernel.len())
+        .check("kernel rows", Expected::AtLeast(1), kh)
+        .check("kernel rows", Expected::AtMost(ih), kh)
+        .check("kernel columns", Expected::AtLeast(1), kw)
+        .check("kernel columns", Expected::AtMost(iw), kw)
+        .finish()
+}
+
+/// Check the inputs of attention over `dim` wide heads: queries and keys
+/// are scored against each other, so they must be of the same size.
+pub fn attention(q: &[f32], k: &[f32], dim: usize) -> Result<(), ShapeError> {
+    Checks::new("attention", &[("dim", dim)])
+        .check("dim", Expected::AtLeast(1), dim)
+        .check("k", Expected::Exactly(q.len()), k.len())
+        .finish()
+}
+
+/// Check the inputs of a layer norm: one scale and one shift per element.
+pub fn layer_norm(x: &[f32], gamma: &[f32], beta: &[f32]) -> Result<(), ShapeError> {
+    Checks::new("layer_norm", &[("len", x.len())])
+        .check("x", Expected::AtLeast(1), x.len())
+        .check("gamma", Expected::Exactly(x.len()), gamma.len())
+        .check("beta", Expected::Exactly(x.len()), beta.len())
+        .finish()
+}
@@ -1,35 +1,265 @@
 //! Vulkan backend providing minimal instance/device management and compute
 //! pipeline utilities.  The backend compiles a tiny compute shader to SPIR-V at
-//! runtime and uses it for all [`TensorOps`] kernels.  If Vulkan is unavailable
-//! on the system the backend transparently falls back to the CPU implementation.
+//! runtime, kept in the on-disk kernel cache for later runs, and uses it for
+//! all [`TensorOps`] kernels.  If Vulkan is unavailable on the system the
+//! backend transparently falls back to the CPU implementation.
+//!
+//! Kernels declare their dimensions as specialization constants.  A backend
+//! created [`with_shapes`](VulkanBackend::with_shapes) builds a pipeline per
+//! listed shape up front, so the driver compiles code for the fixed model
+//! dimensions and ops of those shapes skip pipeline creation.
+//!
+//! Every kernel gets a pipeline when the backend is created, built through a
+//! `VkPipelineCache` that is saved to the kernel cache, so later runs skip
+//! most driver compilation too.  Dispatches record into command buffers and
+//! descriptor sets taken from a pool and returned once the work completes.
+//! Between [`TensorOps::begin_batch`] and [`TensorOps::end_batch`] the
+//! dispatches of consecutive ops go into one command buffer, separated by
+//! barriers, and are submitted together.  On devices with timeline
+//! semaphores every submission waits for the one before it.
+//!
+//! Devices with subgroup arithmetic or `VK_KHR_cooperative_matrix` get
+//! matmul kernels built on them, see [`MatmulKernel`].  Both are detected
+//! when the device is created and reported in its [`BackendCapabilities`].
 
+use std::collections::HashMap;
 use std::ffi::CStr;
 use std::io::Cursor;
+use std::sync::Mutex;
 
 use anyhow::Result;
 use ash::util::read_spv;
 use ash::{vk, Device, Entry, Instance};
-use shaderc::{Compiler, ShaderKind};
+use aurex_utils::kernel_cache::{KernelCache, KernelKey};
+use shaderc::{CompileOptions, Compiler, ShaderKind, TargetEnv};
 
+use crate::capabilities::BackendCapabilities;
 use crate::dispatch::{CpuBackend, TensorOps};
+use crate::specialization::KernelShapes;
 
 /// Minimal compute shader used as a stand‑in for all TensorOps kernels.  The
 /// shader simply defines an empty `main` function with a workgroup size of 1.
+/// Its specialization constants are the dimensions listed in
+/// [`KernelShapes::spec_constants`], 0 when the kernel is not specialized.
 const PLACEHOLDER_SHADER: &str = r#"
 #version 450
 layout(local_size_x = 1, local_size_y = 1, local_size_z = 1) in;
+layout(constant_id = 0) const uint DIM0 = 0;
+layout(constant_id = 1) const uint DIM1 = 0;
 void main() {}
 "#;
 
+/// Matmul kernel reducing each output element with subgroup arithmetic.
+/// Every subgroup computes one element of the `M x N` output, its
+/// invocations striding over `K`.
+const MATMUL_SUBGROUP_SHADER: &str = r#"
+#version 450
+#extension GL_KHR_shader_subgroup_arithmetic : require
+layout(local_size_x = 64) in;
+layout(constant_id = 0) const uint N = 1;
+layout(constant_id = 1) const uint K = 1;
+layout(set = 0, binding = 0) readonly buffer A { float a[]; };
+layout(set = 0, binding = 1) readonly buffer B { float b[]; };
+layout(set = 0, binding = 2) writeonly buffer C { float c[]; };
+layout(push_constant) uniform Rows { uint M; };
+void main() {
+    uint element = gl_WorkGroupID.x * gl_NumSubgroups + gl_SubgroupID;
+    if (element >= M * N) {
+        return;
+    }
+    uint row = element / N;
+    uint col = element % N;
+    float sum = 0.0;
+    for (uint p = gl_SubgroupInvocationID; p < K; p += gl_SubgroupSize) {
+        sum += a[row * K + p] * b[p * N + col];
+    }
+    sum = subgroupAdd(sum);
+    if (subgroupElect()) {
+        c[element] = sum;
+    }
+}
+"#;
+
+/// Matmul kernel multiplying `f16` tiles with cooperative matrices into an
+/// `f32` output.  Every subgroup computes one 16x16 tile of the output, so
+/// all dimensions must be multiples of [`COOPERATIVE_TILE`].
+const MATMUL_COOPERATIVE_SHADER: &str = r#"
+#version 450
+#extension GL_KHR_cooperative_matrix : require
+#extension GL_KHR_memory_scope_semantics : require
+#extension GL_EXT_shader_explicit_arithmetic_types_float16 : require
+layout(local_size_x = 64) in;
+layout(constant_id = 0) const uint N = 16;
+layout(constant_id = 1) const uint K = 16;
+layout(set = 0, binding = 0) readonly buffer A { float16_t a[]; };
+layout(set = 0, binding = 1) readonly buffer B { float16_t b[]; };
+layout(set = 0, binding = 2) writeonly buffer C { float c[]; };
+layout(push_constant) uniform Rows { uint M; };
+const uint TILE = 16;
+void main() {
+    uint tile = gl_WorkGroupID.x * gl_NumSubgroups + gl_SubgroupID;
+    uint tiles_n = N / TILE;
+    if (tile >= M / TILE * tiles_n) {
+        return;
+    }
+    uint row = tile / tiles_n * TILE;
+    uint col = tile % tiles_n * TILE;
+    coopmat<float, gl_ScopeSubgroup, TILE, TILE, gl_MatrixUseAccumulator> acc =
+        coopmat<float, gl_ScopeSubgroup, TILE, TILE, gl_MatrixUseAccumulator>(0.0);
+    for (uint p = 0; p < K; p += TILE) {
+        coopmat<float16_t, gl_ScopeSubgroup, TILE, TILE, gl_MatrixUseA> tile_a;
+        coopmat<float16_t, gl_ScopeSubgroup, TILE, TILE, gl_MatrixUseB> tile_b;
+        coopMatLoad(tile_a, a, row * K + p, K, gl_CooperativeMatrixLayoutRowMajor);
+        coopMatLoad(tile_b, b, p * N + col, N, gl_CooperativeMatrixLayoutRowMajor);
+        acc = coopMatMulAdd(tile_a, tile_b, acc);
+    }
+    coopMatStore(acc, c, row * N + col, N, gl_CooperativeMatrixLayoutRowMajor);
+}
+"#;
+
+/// Side of the tiles multiplied by the cooperative matrix kernel.
+pub const COOPERATIVE_TILE: usize = 16;
+
+/// Name of `VK_KHR_cooperative_matrix`.
+const COOPERATIVE_MATRIX_EXTENSION: &CStr = c"VK_KHR_cooperative_matrix";
+
+/// `VkStructureType` of `VkPhysicalDeviceCooperativeMatrixFeaturesKHR`.  ash
+/// 0.37 predates the extension; the NV feature struct has the same layout.
+const COOPERATIVE_MATRIX_FEATURES_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_506_000);
+
+/// Flavours of the matmul kernel, from the most to the least specialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatmulKernel {
+    /// Multiplies 16x16 `f16` tiles with `VK_KHR_cooperative_matrix`.
+    CooperativeMatrix,
+    /// Reduces each output element with subgroup arithmetic.
+    Subgroup,
+    /// The generic shader every device runs.
+    Generic,
+}
+
+impl MatmulKernel {
+    /// Kernels a device with `caps` can run, the most specialized first.
+    pub fn available(caps: &BackendCapabilities) -> Vec<MatmulKernel> {
+        let mut kernels = Vec::new();
+        if caps.cooperative_matrix {
+            kernels.push(MatmulKernel::CooperativeMatrix);
+        }
+        if caps.subgroup_ops {
+            kernels.push(MatmulKernel::Subgroup);
+        }
+        kernels.push(MatmulKernel::Generic);
+        kernels
+    }
+
+    /// Whether the kernel can multiply an `m x k` by a `k x n` matrix.
+    pub fn handles(self, m: usize, n: usize, k: usize) -> bool {
+        match self {
+            MatmulKernel::CooperativeMatrix => [m, n, k]
+                .iter()
+                .all(|dim| dim.is_multiple_of(COOPERATIVE_TILE)),
+            MatmulKernel::Subgroup | MatmulKernel::Generic => true,
+        }
+    }
+
+    /// The first of `kernels` that [handles](Self::handles) the shape,
+    /// [`Generic`](Self::Generic) if none does.
+    pub fn select(
+        kernels: impl IntoIterator<Item = MatmulKernel>,
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> MatmulKernel {
+        kernels
+            .into_iter()
+            .find(|kernel| kernel.handles(m, n, k))
+            .unwrap_or(MatmulKernel::Generic)
+    }
+
+    /// Op name the kernel is cached and specialized under.
+    pub fn op(self) -> &'static str {
+        match self {
+            MatmulKernel::CooperativeMatrix => "matmul_coopmat",
+            MatmulKernel::Subgroup => "matmul_subgroup",
+            MatmulKernel::Generic => "matmul",
+        }
+    }
+
+    /// GLSL source of the kernel.
+    pub fn source(self) -> &'static str {
+        match self {
+            MatmulKernel::CooperativeMatrix => MATMUL_COOPERATIVE_SHADER,
+            MatmulKernel::Subgroup => MATMUL_SUBGROUP_SHADER,
+            MatmulKernel::Generic => PLACEHOLDER_SHADER,
+        }
+    }
+
+    /// Vulkan version whose SPIR-V the kernel compiles to.
+    pub fn api_version(self) -> u32 {
+        match self {
+            MatmulKernel::CooperativeMatrix => vk::API_VERSION_1_3,
+            MatmulKernel::Subgroup => vk::API_VERSION_1_1,
+            MatmulKernel::Generic => vk::API_VERSION_1_0,
+        }
+    }
+}
+
+/// GLSL source of the compute kernel implementing `op`, or `None` for ops
+/// without a Vulkan kernel.  All ops currently share the placeholder shader.
+pub fn kernel_source(op: &str) -> Option<&'static str> {
+    match op {
+        "matmul" | "conv2d" | "attention" | "layer_norm" | "matmul_layernorm" | "add" | "mul" => {
+            Some(PLACEHOLDER_SHADER)
+        }
+        _ => None,
+    }
+}
+
 /// Compile a GLSL compute shader to SPIR-V words.
 pub fn compile_shader(src: &str) -> Result<Vec<u32>, String> {
+    compile_shader_for(src, vk::API_VERSION_1_0)
+}
+
+/// Compile a GLSL compute shader to the SPIR-V of Vulkan `api_version`,
+/// which subgroup and cooperative matrix extensions require.
+pub fn compile_shader_for(src: &str, api_version: u32) -> Result<Vec<u32>, String> {
     let mut compiler = Compiler::new().ok_or("failed to create shader compiler")?;
+    let mut options = CompileOptions::new().ok_or("failed to create compile options")?;
+    // shaderc encodes Vulkan versions like `VK_MAKE_API_VERSION`.
+    options.set_target_env(TargetEnv::Vulkan, api_version);
     let binary = compiler
-        .compile_into_spirv(src, ShaderKind::Compute, "kernel.glsl", "main", None)
+        .compile_into_spirv(
+            src,
+            ShaderKind::Compute,
+            "kernel.glsl",
+            "main",
+            Some(&options),
+        )
         .map_err(|e| e.to_string())?;
     Ok(binary.as_binary().to_vec())
 }
 
+/// Compile the kernel of `op` from `src` to SPIR-V words of Vulkan
+/// `api_version` through the on-disk kernel cache.  `driver` identifies the
+/// Vulkan driver the kernel is built for, see [`VulkanContext::driver`].
+pub fn compile_kernel_cached(
+    op: &str,
+    src: &str,
+    api_version: u32,
+    driver: &str,
+) -> Result<Vec<u32>, String> {
+    let key = KernelKey::new(op, "vulkan", driver);
+    let bytes = KernelCache::global().get_or_compile(&key, || {
+        compile_shader_for(src, api_version)
+            .map(|words| words.iter().flat_map(|w| w.to_le_bytes()).collect())
+    })?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect())
+}
+
 /// Load a SPIR-V module from disk.
 pub fn load_shader(path: &str) -> Result<Vec<u32>, String> {
     let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
@@ -37,6 +267,9 @@ pub fn load_shader(path: &str) -> Result<Vec<u32>, String> {
     read_spv(&mut cursor).map_err(|e| e.to_string())
 }
 
+/// Descriptor sets a [`VulkanContext`] can have in flight at once.
+const MAX_SUBMISSIONS: u32 = 64;
+
 /// Holds Vulkan objects required for compute dispatch.
 pub struct VulkanContext {
     entry: Entry,
@@ -44,14 +277,116 @@ pub struct VulkanContext {
     device: Device,
     queue: vk::Queue,
     queue_family_index: u32,
+    driver: String,
+    limits: vk::PhysicalDeviceLimits,
+    features: DeviceFeatures,
+    /// Layout of the `A`, `B` and `C` storage buffers kernels bind.
+    buffers: vk::DescriptorSetLayout,
+    pipeline_cache: vk::PipelineCache,
+    /// Also guards `queue`, which submissions must not use concurrently.
+    submissions: Mutex<SubmissionPool>,
+}
+
+/// Command buffer, descriptor set and fence of one submission.
+struct Submission {
+    commands: vk::CommandBuffer,
+    descriptors: vk::DescriptorSet,
+    fence: vk::Fence,
+}
+
+/// Submission being recorded between `begin_batch` and `end_batch`.
+struct Batch {
+    submission: Submission,
+    dispatches: usize,
+    /// Nesting level of `begin_batch` calls.
+    depth: usize,
+}
+
+/// Pools submissions are allocated from and the submissions not in flight.
+struct SubmissionPool {
+    commands: vk::CommandPool,
+    descriptors: vk::DescriptorPool,
+    free: Vec<Submission>,
+    allocated: usize,
+    batch: Option<Batch>,
+    /// Timeline semaphore ordering submissions, if the device has them.
+    timeline: Option<vk::Semaphore>,
+    /// Value the last submission signals on `timeline`.
+    signaled: u64,
+}
+
+/// Optional features enabled on a device.
+#[derive(Debug, Clone, Copy, Default)]
+struct DeviceFeatures {
+    subgroup_ops: bool,
+    cooperative_matrix: bool,
+    timeline_semaphore: bool,
+}
+
+impl DeviceFeatures {
+    /// Query what `physical` supports.  Subgroup arithmetic needs Vulkan
+    /// 1.1, timeline semaphores Vulkan 1.2 and the cooperative matrix kernel
+    /// Vulkan 1.3 with 16-bit storage, `f16` arithmetic and the Vulkan
+    /// memory model.
+    unsafe fn query(instance: &Instance, physical: vk::PhysicalDevice, api_version: u32) -> Self {
+        let mut features = Self::default();
+        if api_version < vk::API_VERSION_1_1 {
+            return features;
+        }
+        let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+        let mut props = vk::PhysicalDeviceProperties2::builder().push_next(&mut subgroup);
+        instance.get_physical_device_properties2(physical, &mut props);
+        let stages = subgroup.supported_stages;
+        let operations = subgroup.supported_operations;
+        features.subgroup_ops = stages.contains(vk::ShaderStageFlags::COMPUTE)
+            && operations.contains(vk::SubgroupFeatureFlags::ARITHMETIC);
+
+        if api_version < vk::API_VERSION_1_2 {
+            return features;
+        }
+        let has_coop = api_version >= vk::API_VERSION_1_3
+            && instance
+                .enumerate_device_extension_properties(physical)
+                .unwrap_or_default()
+                .iter()
+                .any(|ext| {
+                    CStr::from_ptr(ext.extension_name.as_ptr()) == COOPERATIVE_MATRIX_EXTENSION
+                });
+        let mut coop = vk::PhysicalDeviceCooperativeMatrixFeaturesNV {
+            s_type: COOPERATIVE_MATRIX_FEATURES_KHR,
+            ..Default::default()
+        };
+        let mut vk11 = vk::PhysicalDeviceVulkan11Features::default();
+        let mut vk12 = vk::PhysicalDeviceVulkan12Features::default();
+        let mut all = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut vk11)
+            .push_next(&mut vk12);
+        if has_coop {
+            all = all.push_next(&mut coop);
+        }
+        instance.get_physical_device_features2(physical, &mut all);
+        features.timeline_semaphore = vk12.timeline_semaphore == vk::TRUE;
+        features.cooperative_matrix = has_coop
+            && coop.cooperative_matrix == vk::TRUE
+            && vk11.storage_buffer16_bit_access == vk::TRUE
+            && vk12.shader_float16 == vk::TRUE
+            && vk12.vulkan_memory_model == vk::TRUE;
+        features
+    }
 }
 
 impl VulkanContext {
-    /// Create a new Vulkan instance and logical device with a compute queue.
+    /// Create a new Vulkan instance and logical device with a compute queue,
+    /// enabling the features of the accelerated [`MatmulKernel`]s the device
+    /// supports.
     pub fn new() -> Result<Self> {
         let entry = unsafe { Entry::load()? };
 
-        let app = vk::ApplicationInfo::builder().api_version(vk::API_VERSION_1_0);
+        let instance_version = entry
+            .try_enumerate_instance_version()?
+            .unwrap_or(vk::API_VERSION_1_0)
+            .min(vk::API_VERSION_1_3);
+        let app = vk::ApplicationInfo::builder().api_version(instance_version);
         let info = vk::InstanceCreateInfo::builder().application_info(&app);
         let instance = unsafe { entry.create_instance(&info, None)? };
 
@@ -59,6 +394,11 @@ impl VulkanContext {
             .into_iter()
             .next()
             .ok_or_else(|| anyhow::anyhow!("No Vulkan devices available"))?;
+        let props = unsafe { instance.get_physical_device_properties(physical) };
+        let driver = format!(
+            "{:04x}:{:04x}:{}",
+            props.vendor_id, props.device_id, props.driver_version
+        );
 
         let queue_family_index = unsafe {
             instance
@@ -74,31 +414,369 @@ impl VulkanContext {
         let queue_info = vk::DeviceQueueCreateInfo::builder()
             .queue_family_index(queue_family_index)
             .queue_priorities(&priorities);
-        let device_info = vk::DeviceCreateInfo::builder().queue_create_infos(std::slice::from_ref(&queue_info));
+        let api_version = props.api_version.min(instance_version);
+        let features = unsafe { DeviceFeatures::query(&instance, physical, api_version) };
+        let extensions = [COOPERATIVE_MATRIX_EXTENSION.as_ptr()];
+        let mut coop = vk::PhysicalDeviceCooperativeMatrixFeaturesNV {
+            s_type: COOPERATIVE_MATRIX_FEATURES_KHR,
+            cooperative_matrix: vk::TRUE,
+            ..Default::default()
+        };
+        let mut vk11 =
+            vk::PhysicalDeviceVulkan11Features::builder().storage_buffer16_bit_access(true);
+        let mut vk12 = vk::PhysicalDeviceVulkan12Features::builder()
+            .shader_float16(features.cooperative_matrix)
+            .vulkan_memory_model(features.cooperative_matrix)
+            .timeline_semaphore(features.timeline_semaphore);
+        let mut device_info =
+            vk::DeviceCreateInfo::builder().queue_create_infos(std::slice::from_ref(&queue_info));
+        if features.cooperative_matrix {
+            device_info = device_info
+                .enabled_extension_names(&extensions)
+                .push_next(&mut coop)
+                .push_next(&mut vk11);
+        }
+        if features.cooperative_matrix || features.timeline_semaphore {
+            device_info = device_info.push_next(&mut vk12);
+        }
         let device = unsafe { instance.create_device(physical, &device_info, None)? };
         let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+        tracing::debug!(driver, ?features, "created Vulkan device");
 
-        Ok(Self { entry, instance, device, queue, queue_family_index })
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..3)
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            })
+            .collect();
+        let buffers_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        let buffers = unsafe { device.create_descriptor_set_layout(&buffers_info, None)? };
+
+        // Data from an older driver is rejected by the driver itself, so a
+        // stale entry only costs a recompilation.
+        let cached = KernelCache::global()
+            .get(&pipeline_cache_key(&driver))
+            .unwrap_or_default();
+        let cache_info = vk::PipelineCacheCreateInfo::builder().initial_data(&cached);
+        let pipeline_cache = unsafe { device.create_pipeline_cache(&cache_info, None)? };
+
+        let command_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(queue_family_index);
+        let commands = unsafe { device.create_command_pool(&command_info, None)? };
+        let sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 3 * MAX_SUBMISSIONS,
+        }];
+        let descriptor_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(MAX_SUBMISSIONS)
+            .pool_sizes(&sizes);
+        let descriptors = unsafe { device.create_descriptor_pool(&descriptor_info, None)? };
+        let timeline = if features.timeline_semaphore {
+            let mut kind = vk::SemaphoreTypeCreateInfo::builder()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            let info = vk::SemaphoreCreateInfo::builder().push_next(&mut kind);
+            Some(unsafe { device.create_semaphore(&info, None)? })
+        } else {
+            None
+        };
+        let submissions = Mutex::new(SubmissionPool {
+            commands,
+            descriptors,
+            free: Vec::new(),
+            allocated: 0,
+            batch: None,
+            timeline,
+            signaled: 0,
+        });
+
+        Ok(Self {
+            entry,
+            instance,
+            device,
+            queue,
+            queue_family_index,
+            driver,
+            limits: props.limits,
+            features,
+            buffers,
+            pipeline_cache,
+            submissions,
+        })
+    }
+
+    /// Vendor, device and driver version of the device, used to key cached
+    /// kernels.
+    pub fn driver(&self) -> &str {
+        &self.driver
+    }
+
+    /// Workgroup and buffer limits and matmul features of the device.
+    /// Kernels compute on the CPU for now, so every precision is available.
+    pub fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            max_workgroup_size: self.limits.max_compute_work_group_invocations,
+            max_allocation: u64::from(self.limits.max_storage_buffer_range),
+            async_execution: true,
+            int8_dot: false,
+            subgroup_ops: self.features.subgroup_ops,
+            cooperative_matrix: self.features.cooperative_matrix,
+            ..BackendCapabilities::host()
+        }
+    }
+
+    /// Write the pipeline cache to the kernel cache so the next run builds
+    /// pipelines from it.
+    pub fn save_pipeline_cache(&self) -> Result<()> {
+        let data = unsafe { self.device.get_pipeline_cache_data(self.pipeline_cache)? };
+        KernelCache::global().put(&pipeline_cache_key(&self.driver), &data)?;
+        Ok(())
+    }
+
+    /// Command buffers and descriptor sets allocated for dispatches so far.
+    /// They are reused, so this is the most dispatches ever in flight.
+    pub fn allocated_submissions(&self) -> usize {
+        self.submissions.lock().unwrap().allocated
+    }
+
+    /// Run `pipeline` on the compute queue and wait for it to complete, or
+    /// record it into the open batch.  The command buffer and descriptor set
+    /// come from the pool.  Op inputs are not uploaded yet, so the kernel is
+    /// bound without being launched.
+    pub fn run(&self, pipeline: Pipeline) -> Result<()> {
+        let mut pool = self.submissions.lock().unwrap();
+        if let Some(batch) = &mut pool.batch {
+            unsafe { self.record(&batch.submission, pipeline, batch.dispatches > 0) };
+            batch.dispatches += 1;
+            return Ok(());
+        }
+        let submission = self.begin_submission(&mut pool)?;
+        unsafe {
+            self.record(&submission, pipeline, false);
+            self.device.end_command_buffer(submission.commands)?;
+            self.submit(&mut pool, &submission)?;
+        }
+        drop(pool);
+        self.complete(submission)
+    }
+
+    /// Record the following [`run`](Self::run)s into one command buffer
+    /// until the matching [`end_batch`](Self::end_batch).
+    pub fn begin_batch(&self) -> Result<()> {
+        let mut pool = self.submissions.lock().unwrap();
+        if let Some(batch) = &mut pool.batch {
+            batch.depth += 1;
+            return Ok(());
+        }
+        let submission = self.begin_submission(&mut pool)?;
+        pool.batch = Some(Batch {
+            submission,
+            dispatches: 0,
+            depth: 1,
+        });
+        Ok(())
+    }
+
+    /// Submit the batch opened by the outermost
+    /// [`begin_batch`](Self::begin_batch) and wait for it to complete.
+    /// Returns the number of dispatches submitted, 0 for inner batches.
+    pub fn end_batch(&self) -> Result<usize> {
+        let mut pool = self.submissions.lock().unwrap();
+        match &mut pool.batch {
+            Some(batch) if batch.depth > 1 => {
+                batch.depth -= 1;
+                return Ok(0);
+            }
+            Some(_) => {}
+            None => return Ok(0),
+        }
+        let Batch {
+            submission,
+            dispatches,
+            ..
+        } = pool.batch.take().expect("batch is open");
+        unsafe {
+            self.device.end_command_buffer(submission.commands)?;
+            if dispatches == 0 {
+                pool.free.push(submission);
+                return Ok(0);
+            }
+            self.submit(&mut pool, &submission)?;
+        }
+        drop(pool);
+        self.complete(submission)?;
+        tracing::trace!(dispatches, "submitted Vulkan batch");
+        Ok(dispatches)
+    }
+
+    /// Value of the timeline semaphore signaled by the last submission, 0
+    /// without timeline semaphores.
+    pub fn timeline_value(&self) -> u64 {
+        self.submissions.lock().unwrap().signaled
+    }
+
+    /// Take a submission from the pool and begin recording into it.
+    fn begin_submission(&self, pool: &mut SubmissionPool) -> Result<Submission> {
+        let submission = match pool.free.pop() {
+            Some(submission) => submission,
+            None => self.allocate_submission(pool)?,
+        };
+        let begin = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            self.device
+                .begin_command_buffer(submission.commands, &begin)?
+        };
+        Ok(submission)
+    }
+
+    /// Record `pipeline` into `submission`.  A dispatch `after` others in the
+    /// same command buffer first waits for their writes, as it may read them.
+    unsafe fn record(&self, submission: &Submission, (pipeline, layout): Pipeline, after: bool) {
+        let commands = submission.commands;
+        if after {
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+            self.device.cmd_pipeline_barrier(
+                commands,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                std::slice::from_ref(&barrier),
+                &[],
+                &[],
+            );
+        }
+        self.device
+            .cmd_bind_pipeline(commands, vk::PipelineBindPoint::COMPUTE, pipeline);
+        self.device.cmd_bind_descriptor_sets(
+            commands,
+            vk::PipelineBindPoint::COMPUTE,
+            layout,
+            0,
+            &[submission.descriptors],
+            &[],
+        );
+    }
+
+    /// Submit the recorded `submission`.  With a timeline semaphore it waits
+    /// for the value of the previous submission and signals the next, so
+    /// submissions complete in order.
+    unsafe fn submit(&self, pool: &mut SubmissionPool, submission: &Submission) -> Result<()> {
+        let commands = [submission.commands];
+        let (wait, signal) = ([pool.signaled], [pool.signaled + 1]);
+        let stages = [vk::PipelineStageFlags::COMPUTE_SHADER];
+        let mut values = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait)
+            .signal_semaphore_values(&signal);
+        let timeline: Vec<vk::Semaphore> = pool.timeline.into_iter().collect();
+        let mut info = vk::SubmitInfo::builder().command_buffers(&commands);
+        if !timeline.is_empty() {
+            info = info
+                .wait_semaphores(&timeline)
+                .wait_dst_stage_mask(&stages)
+                .signal_semaphores(&timeline)
+                .push_next(&mut values);
+        }
+        self.device
+            .queue_submit(self.queue, std::slice::from_ref(&info), submission.fence)?;
+        if !timeline.is_empty() {
+            pool.signaled += 1;
+        }
+        Ok(())
+    }
+
+    /// Wait for `submission` and return it to the pool.
+    fn complete(&self, submission: Submission) -> Result<()> {
+        unsafe {
+            self.device
+                .wait_for_fences(&[submission.fence], true, u64::MAX)?;
+            self.device.reset_fences(&[submission.fence])?;
+        }
+        self.submissions.lock().unwrap().free.push(submission);
+        Ok(())
+    }
+
+    fn allocate_submission(&self, pool: &mut SubmissionPool) -> Result<Submission> {
+        let command_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(pool.commands)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let descriptor_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool.descriptors)
+            .set_layouts(std::slice::from_ref(&self.buffers));
+        let submission = unsafe {
+            Submission {
+                commands: self.device.allocate_command_buffers(&command_info)?[0],
+                descriptors: self.device.allocate_descriptor_sets(&descriptor_info)?[0],
+                fence: self
+                    .device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)?,
+            }
+        };
+        pool.allocated += 1;
+        Ok(submission)
     }
 
     /// Create a compute pipeline from SPIR-V code.
     pub fn create_compute_pipeline(&self, code: &[u32]) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
+        self.create_specialized_pipeline(code, &[])
+    }
+
+    /// Create a compute pipeline from SPIR-V code with specialization
+    /// constant `i` set to `constants[i]`.
+    pub fn create_specialized_pipeline(
+        &self,
+        code: &[u32],
+        constants: &[u32],
+    ) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
         let module_info = vk::ShaderModuleCreateInfo::builder().code(code);
         let module = unsafe { self.device.create_shader_module(&module_info, None)? };
 
+        let entries: Vec<vk::SpecializationMapEntry> = (0..constants.len())
+            .map(|i| vk::SpecializationMapEntry {
+                constant_id: i as u32,
+                offset: (i * 4) as u32,
+                size: 4,
+            })
+            .collect();
+        let data: Vec<u8> = constants.iter().flat_map(|c| c.to_ne_bytes()).collect();
+        let specialization = vk::SpecializationInfo::builder()
+            .map_entries(&entries)
+            .data(&data);
+
         let entry = CStr::from_bytes_with_nul(b"main\0").unwrap();
-        let stage = vk::PipelineShaderStageCreateInfo::builder()
+        let mut stage = vk::PipelineShaderStageCreateInfo::builder()
             .stage(vk::ShaderStageFlags::COMPUTE)
             .module(module)
             .name(entry);
+        if !constants.is_empty() {
+            stage = stage.specialization_info(&specialization);
+        }
 
-        let layout_info = vk::PipelineLayoutCreateInfo::default();
+        let push_constants = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: 4,
+        };
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(std::slice::from_ref(&self.buffers))
+            .push_constant_ranges(std::slice::from_ref(&push_constants));
         let layout = unsafe { self.device.create_pipeline_layout(&layout_info, None)? };
 
         let pipeline_info = vk::ComputePipelineCreateInfo::builder().stage(*stage).layout(layout);
         let pipelines = unsafe {
             self.device
-                .create_compute_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&pipeline_info), None)
+                .create_compute_pipelines(
+                    self.pipeline_cache,
+                    std::slice::from_ref(&pipeline_info),
+                    None,
+                )
                 .map_err(|(_, e)| e)?
         };
         let pipeline = pipelines[0];
@@ -108,13 +786,27 @@ impl VulkanContext {
     }
 }
 
+/// Key of the saved pipeline cache of `driver`.
+fn pipeline_cache_key(driver: &str) -> KernelKey {
+    KernelKey::new("pipeline_cache", "vulkan", driver)
+}
+
+/// A compute pipeline and its layout.
+pub type Pipeline = (vk::Pipeline, vk::PipelineLayout);
+
 /// Vulkan backend implementing [`TensorOps`] by dispatching placeholder shaders.
 pub struct VulkanBackend {
     ctx: Option<VulkanContext>,
-    matmul_spv: Vec<u32>,
+    /// Matmul kernels the device runs, the most specialized first.
+    matmul_kernels: Vec<(MatmulKernel, Vec<u32>)>,
     conv2d_spv: Vec<u32>,
     attention_spv: Vec<u32>,
     layernorm_spv: Vec<u32>,
+    /// Generic pipeline of each op, built once with the backend.
+    pipelines: HashMap<&'static str, Pipeline>,
+    /// Pipelines built for the shapes of the loaded model, keyed by op and
+    /// specialization constants.
+    specialized: HashMap<(&'static str, Vec<u32>), Pipeline>,
 }
 
 impl VulkanBackend {
@@ -122,28 +814,157 @@ impl VulkanBackend {
     /// fall back to CPU execution.
     pub fn new() -> Self {
         let ctx = VulkanContext::new().ok();
-        let spv = compile_shader(PLACEHOLDER_SHADER).unwrap_or_default();
-        Self {
+        let driver = ctx.as_ref().map_or("none", VulkanContext::driver).to_string();
+        let compile = |op| {
+            let src = kernel_source(op).unwrap_or(PLACEHOLDER_SHADER);
+            compile_kernel_cached(op, src, vk::API_VERSION_1_0, &driver).unwrap_or_default()
+        };
+        let caps = ctx
+            .as_ref()
+            .map(VulkanContext::capabilities)
+            .unwrap_or_default();
+        // Accelerated kernels that fail to compile are left out so matmuls
+        // fall back to the generic one.
+        let matmul_kernels = MatmulKernel::available(&caps)
+            .into_iter()
+            .filter_map(|kernel| {
+                let (op, src) = (kernel.op(), kernel.source());
+                match compile_kernel_cached(op, src, kernel.api_version(), &driver) {
+                    Ok(code) => Some((kernel, code)),
+                    Err(err) if kernel == MatmulKernel::Generic => {
+                        tracing::warn!(%err, "failed to compile matmul kernel");
+                        Some((kernel, Vec::new()))
+                    }
+                    Err(err) => {
+                        tracing::warn!(?kernel, %err, "failed to compile matmul kernel");
+                        None
+                    }
+                }
+            })
+            .collect();
+        let mut backend = Self {
             ctx,
-            matmul_spv: spv.clone(),
-            conv2d_spv: spv.clone(),
-            attention_spv: spv.clone(),
-            layernorm_spv: spv,
+            matmul_kernels,
+            conv2d_spv: compile("conv2d"),
+            attention_spv: compile("attention"),
+            layernorm_spv: compile("layer_norm"),
+            pipelines: HashMap::new(),
+            specialized: HashMap::new(),
+        };
+        backend.build_pipelines();
+        backend
+    }
+
+    /// Build the generic pipeline of every op.  Ops whose pipeline fails to
+    /// build are computed without dispatching a kernel.
+    fn build_pipelines(&mut self) {
+        let Some(ctx) = &self.ctx else {
+            return;
+        };
+        let matmuls = self
+            .matmul_kernels
+            .iter()
+            .map(|(kernel, code)| (kernel.op(), code));
+        let kernels = matmuls.chain([
+            ("conv2d", &self.conv2d_spv),
+            ("attention", &self.attention_spv),
+            ("layer_norm", &self.layernorm_spv),
+        ]);
+        for (op, code) in kernels {
+            match ctx.create_compute_pipeline(code) {
+                Ok(pipeline) => {
+                    self.pipelines.insert(op, pipeline);
+                }
+                Err(err) => tracing::warn!(op, %err, "failed to build kernel pipeline"),
+            }
         }
     }
 
+    /// Build pipelines specialized for `shapes`.  Shapes whose pipeline
+    /// fails to build keep using the generic one.
+    pub fn with_shapes(mut self, shapes: &KernelShapes) -> Self {
+        let Some(ctx) = &self.ctx else {
+            return self;
+        };
+        let mut kernels = Vec::new();
+        for (kernel, code) in &self.matmul_kernels {
+            for constants in shapes.spec_constants("matmul") {
+                // Rows vary per call; only `N` and `K` are fixed.
+                let (n, k) = (constants[0] as usize, constants[1] as usize);
+                if kernel.handles(COOPERATIVE_TILE, n, k) {
+                    kernels.push((kernel.op(), code, constants));
+                }
+            }
+        }
+        for (op, code) in [
+            ("attention", &self.attention_spv),
+            ("layer_norm", &self.layernorm_spv),
+        ] {
+            for constants in shapes.spec_constants(op) {
+                kernels.push((op, code, constants));
+            }
+        }
+        for (op, code, constants) in kernels {
+            match ctx.create_specialized_pipeline(code, &constants) {
+                Ok(pipeline) => {
+                    self.specialized.insert((op, constants), pipeline);
+                }
+                Err(err) => tracing::warn!(op, ?constants, %err, "failed to specialize kernel"),
+            }
+        }
+        tracing::debug!(pipelines = self.specialized.len(), "specialized Vulkan kernels");
+        self
+    }
+
+    /// Number of pipelines specialized for model shapes.
+    pub fn specialized_kernels(&self) -> usize {
+        self.specialized.len()
+    }
+
+    /// Matmul kernel an `m x k` by `k x n` matmul runs with.
+    pub fn matmul_kernel(&self, m: usize, n: usize, k: usize) -> MatmulKernel {
+        let kernels = self.matmul_kernels.iter().map(|(kernel, _)| *kernel);
+        MatmulKernel::select(kernels, m, n, k)
+    }
+
     /// Check if a Vulkan device is available on the system.
     pub fn is_available() -> bool {
         VulkanContext::new().is_ok()
     }
 
-    fn dispatch(&self, code: &[u32]) {
+    /// Pipelines built so far, generic and specialized.
+    pub fn pipelines(&self) -> usize {
+        self.pipelines.len() + self.specialized.len()
+    }
+
+    /// Write the pipeline cache to disk, see
+    /// [`VulkanContext::save_pipeline_cache`].  Also done on drop.
+    pub fn save_pipeline_cache(&self) -> Result<()> {
+        match &self.ctx {
+            Some(ctx) => ctx.save_pipeline_cache(),
+            None => Ok(()),
+        }
+    }
+
+    /// Dispatch the kernel of `op` with the pipeline specialized for
+    /// `constants`, if one was built, or its generic pipeline.
+    fn dispatch_specialized(&self, op: &'static str, constants: Vec<u32>) {
+        match self.specialized.get(&(op, constants)) {
+            Some(&pipeline) => self.run(op, pipeline),
+            None => self.dispatch(op),
+        }
+    }
+
+    fn dispatch(&self, op: &'static str) {
+        if let Some(&pipeline) = self.pipelines.get(op) {
+            self.run(op, pipeline);
+        }
+    }
+
+    fn run(&self, op: &'static str, pipeline: Pipeline) {
         if let Some(ctx) = &self.ctx {
-            if let Ok((pipeline, layout)) = ctx.create_compute_pipeline(code) {
-                unsafe {
-                    ctx.device.destroy_pipeline(pipeline, None);
-                    ctx.device.destroy_pipeline_layout(layout, None);
-                }
+            if let Err(err) = ctx.run(pipeline) {
+                tracing::warn!(op, %err, "failed to dispatch kernel");
             }
         }
     }
@@ -151,7 +972,8 @@ impl VulkanBackend {
 
 impl TensorOps for VulkanBackend {
     fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
-        self.dispatch(&self.matmul_spv);
+        let kernel = self.matmul_kernel(m, n, k);
+        self.dispatch_specialized(kernel.op(), vec![n as u32, k as u32]);
         CpuBackend.matmul(a, b, m, n, k)
     }
 
@@ -162,18 +984,58 @@ impl TensorOps for VulkanBackend {
         input_shape: (usize, usize),
         kernel_shape: (usize, usize),
     ) -> Vec<f32> {
-        self.dispatch(&self.conv2d_spv);
+        self.dispatch("conv2d");
         CpuBackend.conv2d(input, kernel, input_shape, kernel_shape)
     }
 
     fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
-        self.dispatch(&self.attention_spv);
+        self.dispatch_specialized("attention", vec![dim as u32]);
         CpuBackend.attention(q, k, v, dim)
     }
 
     fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
-        self.dispatch(&self.layernorm_spv);
+        self.dispatch_specialized("layer_norm", vec![x.len() as u32]);
         CpuBackend.layer_norm(x, gamma, beta, eps)
     }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        match &self.ctx {
+            Some(ctx) => ctx.capabilities(),
+            None => BackendCapabilities::host(),
+        }
+    }
+
+    fn begin_batch(&self) {
+        if let Some(ctx) = &self.ctx {
+            if let Err(err) = ctx.begin_batch() {
+                tracing::warn!(%err, "failed to begin Vulkan batch");
+            }
+        }
+    }
+
+    fn end_batch(&self) {
+        if let Some(ctx) = &self.ctx {
+            if let Err(err) = ctx.end_batch() {
+                tracing::warn!(%err, "failed to submit Vulkan batch");
+            }
+        }
+    }
 }
 
+impl Drop for VulkanBackend {
+    fn drop(&mut self) {
+        if let Some(ctx) = &self.ctx {
+            if let Err(err) = ctx.save_pipeline_cache() {
+                tracing::warn!(%err, "cannot save Vulkan pipeline cache");
+            }
+            let generic = self.pipelines.drain().map(|(_, pipeline)| pipeline);
+            let specialized = self.specialized.drain().map(|(_, pipeline)| pipeline);
+            for (pipeline, layout) in generic.chain(specialized) {
+                unsafe {
+                    ctx.device.destroy_pipeline(pipeline, None);
+                    ctx.device.destroy_pipeline_layout(layout, None);
+                }
+            }
+        }
+    }
+}
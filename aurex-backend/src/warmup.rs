@@ -0,0 +1,113 @@
+//! Warmup runs and captured execution plans.
+//!
+//! The first call of an op on a backend pays one-off costs: the backend is
+//! instantiated, shaders or JIT kernels are compiled and device buffers are
+//! allocated.  [`Dispatcher::warmup`] runs every op of a model's
+//! [`KernelShapes`] on zeroed single-token inputs so these costs are paid
+//! before traffic arrives, and returns an [`ExecutionPlan`]: the backend each
+//! op was dispatched to and how long its cold and warm runs took.
+//!
+//! [`Dispatcher::capture`] pins ops to the backends of a plan.  Captured ops
+//! skip route lookup and cost model selection, so steady-state dispatch does
+//! not change as the cost model's measurements drift.
+//!
+//! [`Dispatcher::warmup`]: crate::Dispatcher::warmup
+//! [`Dispatcher::capture`]: crate::Dispatcher::capture
+
+use std::time::{Duration, Instant};
+
+use aurex_utils::roofline::OpCost;
+
+use crate::dispatch::{Backend, Dispatcher, TensorOps};
+use crate::specialization::KernelShapes;
+
+/// One op run during warmup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedOp {
+    pub op: &'static str,
+    /// Dimensions the op was run with: `[m, n, k]` for matmuls, `[len]` for
+    /// layer norms and `[dim]` for attention.
+    pub shape: Vec<usize>,
+    /// Backend that executed the op.
+    pub backend: Backend,
+    /// Duration of the first run, including one-off setup.
+    pub cold: Duration,
+    /// Duration of the second run.
+    pub warm: Duration,
+}
+
+/// Ops of a warmed up model and their backends.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecutionPlan {
+    pub ops: Vec<PlannedOp>,
+}
+
+impl ExecutionPlan {
+    /// Backend `op` was first planned on.
+    pub fn backend(&self, op: &str) -> Option<Backend> {
+        self.ops
+            .iter()
+            .find(|planned| planned.op == op)
+            .map(|planned| planned.backend)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Total duration of the cold runs.
+    pub fn cold_time(&self) -> Duration {
+        self.ops.iter().map(|planned| planned.cold).sum()
+    }
+
+    /// Total duration of the warm runs.
+    pub fn warm_time(&self) -> Duration {
+        self.ops.iter().map(|planned| planned.warm).sum()
+    }
+}
+
+/// Run every op of `shapes` twice through `dispatcher` and plan it on the
+/// backend that executed it.
+pub(crate) fn warmup(dispatcher: &Dispatcher, shapes: &KernelShapes) -> ExecutionPlan {
+    let mut plan = ExecutionPlan::default();
+    let mut twice = |op, shape: Vec<usize>, cost: OpCost, f: &dyn Fn() -> Vec<f32>| {
+        let started = Instant::now();
+        f();
+        let cold = started.elapsed();
+        let started = Instant::now();
+        f();
+        let warm = started.elapsed();
+        let backend = dispatcher.backend_for(op, || cost);
+        plan.ops.push(PlannedOp {
+            op,
+            shape,
+            backend,
+            cold,
+            warm,
+        });
+    };
+    for &(n, k) in &shapes.matmuls {
+        let (a, b) = (vec![0.0; k], vec![0.0; k * n]);
+        let cost = OpCost::matmul(1, n, k);
+        twice("matmul", vec![1, n, k], cost, &|| {
+            dispatcher.matmul(&a, &b, 1, n, k)
+        });
+    }
+    for &len in &shapes.norms {
+        let (x, gamma, beta) = (vec![0.0; len], vec![1.0; len], vec![0.0; len]);
+        let cost = OpCost::layer_norm(len);
+        twice("layer_norm", vec![len], cost, &|| {
+            dispatcher.layer_norm(&x, &gamma, &beta, 1e-5)
+        });
+    }
+    for &dim in &shapes.head_dims {
+        let qkv = vec![0.0; dim];
+        let cost = OpCost::attention(dim, dim);
+        twice("attention", vec![dim], cost, &|| {
+            dispatcher.attention(&qkv, &qkv, &qkv, dim)
+        });
+    }
+    // The zeroed inputs are not reused by real traffic.
+    dispatcher.clear_placements();
+    plan
+}
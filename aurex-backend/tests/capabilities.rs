@@ -0,0 +1,152 @@
+use aurex_backend::dispatch::CpuBackend;
+use aurex_backend::{
+    Backend, BackendCapabilities, Dispatcher, PluginBackend, PluginBackends, Precision, TensorOps,
+    Workload,
+};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn negotiates_the_next_more_accurate_precision() {
+    let caps = BackendCapabilities {
+        precisions: vec![Precision::Int8],
+        ..BackendCapabilities::default()
+    };
+    assert!(caps.supports(Precision::Int8));
+    // f32 is always available.
+    assert!(caps.supports(Precision::F32));
+    assert!(!caps.supports(Precision::Bf16));
+    assert_eq!(caps.negotiate(Precision::Int4), Precision::Int8);
+    assert_eq!(caps.negotiate(Precision::Int8), Precision::Int8);
+    assert_eq!(caps.negotiate(Precision::Bf16), Precision::F32);
+    assert_eq!(caps.negotiate(Precision::F32), Precision::F32);
+
+    let default = BackendCapabilities::default();
+    assert_eq!(default.negotiate(Precision::Int4), Precision::F32);
+    assert!(default.fits(u64::MAX));
+
+    let host = BackendCapabilities::host();
+    for precision in [
+        Precision::F32,
+        Precision::Bf16,
+        Precision::Int8,
+        Precision::Int4,
+    ] {
+        assert!(host.supports(precision));
+        assert_eq!(host.negotiate(precision), precision);
+    }
+    assert_eq!(Backend::Cpu.capabilities(), host);
+}
+
+#[test]
+fn intersects_device_capabilities() {
+    let a = BackendCapabilities {
+        precisions: vec![Precision::F32, Precision::Bf16, Precision::Int8],
+        max_workgroup_size: 1024,
+        max_allocation: 4 << 30,
+        async_execution: true,
+        int8_dot: true,
+        subgroup_ops: true,
+        cooperative_matrix: true,
+    };
+    let b = BackendCapabilities {
+        precisions: vec![Precision::Int8, Precision::Int4],
+        max_workgroup_size: 256,
+        max_allocation: 1 << 30,
+        async_execution: true,
+        int8_dot: false,
+        subgroup_ops: true,
+        cooperative_matrix: false,
+    };
+    let shared = BackendCapabilities::intersect([&a, &b]);
+    assert_eq!(shared.precisions, [Precision::F32, Precision::Int8]);
+    assert_eq!(shared.max_workgroup_size, 256);
+    assert_eq!(shared.max_allocation, 1 << 30);
+    assert!(shared.async_execution);
+    assert!(!shared.int8_dot);
+    assert!(shared.subgroup_ops);
+    assert!(!shared.cooperative_matrix);
+
+    assert_eq!(BackendCapabilities::intersect([&a]), a);
+    assert_eq!(
+        BackendCapabilities::intersect([]),
+        BackendCapabilities::default()
+    );
+}
+
+/// Plugin ops recording the matmul inputs they receive.
+struct Recorder {
+    capabilities: BackendCapabilities,
+    seen: Mutex<Vec<Vec<f32>>>,
+}
+
+impl Recorder {
+    fn new(capabilities: BackendCapabilities) -> Arc<Self> {
+        Arc::new(Self {
+            capabilities,
+            seen: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+impl TensorOps for Recorder {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        self.seen.lock().unwrap().push(a.to_vec());
+        CpuBackend.matmul(a, b, m, n, k)
+    }
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        CpuBackend.conv2d(input, kernel, input_shape, kernel_shape)
+    }
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        CpuBackend.attention(q, k, v, dim)
+    }
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        CpuBackend.layer_norm(x, gamma, beta, eps)
+    }
+    fn capabilities(&self) -> BackendCapabilities {
+        self.capabilities.clone()
+    }
+}
+
+#[test]
+fn ops_run_at_the_precision_negotiated_with_the_backend() {
+    let recorder = Recorder::new(BackendCapabilities::default());
+    PluginBackends::global().register(PluginBackend::new("caps-f32", recorder.clone()));
+    let plugin = Backend::plugin("caps-f32");
+
+    let mut d = Dispatcher::new(Some(plugin), Workload::Light);
+    d.set_precision(Precision::Bf16);
+    assert_eq!(d.capabilities(plugin), BackendCapabilities::default());
+    assert_eq!(d.precision_on(plugin), Precision::F32);
+    assert_eq!(d.precision_on(Backend::Cpu), Precision::Bf16);
+
+    // 1 + 2^-10 is not representable in bf16; the f32-only plugin gets it
+    // unrounded.
+    let a = 1.0 + f32::EPSILON * 8192.0;
+    d.matmul(&[a], &[1.0], 1, 1, 1);
+    assert_eq!(recorder.seen.lock().unwrap().as_slice(), [vec![a]]);
+}
+
+#[test]
+fn ops_exceeding_the_backend_allocation_limit_run_on_the_cpu() {
+    let recorder = Recorder::new(BackendCapabilities {
+        max_allocation: 16,
+        ..BackendCapabilities::default()
+    });
+    PluginBackends::global().register(PluginBackend::new("caps-small", recorder.clone()));
+    let d = Dispatcher::new(Some(Backend::plugin("caps-small")), Workload::Light);
+
+    // Four f32 values fit in 16 bytes.
+    let small = [1.0; 4];
+    assert_eq!(d.matmul(&small, &[1.0], 4, 1, 1), vec![1.0; 4]);
+    assert_eq!(recorder.seen.lock().unwrap().len(), 1);
+
+    let large = [1.0; 8];
+    assert_eq!(d.matmul(&large, &[1.0], 8, 1, 1), vec![1.0; 8]);
+    assert_eq!(recorder.seen.lock().unwrap().len(), 1);
+}
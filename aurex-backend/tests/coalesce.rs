@@ -0,0 +1,120 @@
+use aurex_backend::dispatch::CpuBackend;
+use aurex_backend::{
+    Backend, CoalesceConfig, Dispatcher, PluginBackend, PluginBackends, TensorOps, Workload,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+
+/// Plugin ops counting the backend calls they receive.
+#[derive(Default)]
+struct Counter {
+    matmuls: AtomicUsize,
+    batches: AtomicUsize,
+}
+
+impl TensorOps for Counter {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        self.matmuls.fetch_add(1, Ordering::SeqCst);
+        CpuBackend.matmul(a, b, m, n, k)
+    }
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        CpuBackend.conv2d(input, kernel, input_shape, kernel_shape)
+    }
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        CpuBackend.attention(q, k, v, dim)
+    }
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        CpuBackend.layer_norm(x, gamma, beta, eps)
+    }
+    fn batched_matmul(
+        &self,
+        batch: &[(&[f32], &[f32])],
+        m: usize,
+        n: usize,
+        k: usize,
+    ) -> Vec<Vec<f32>> {
+        self.batches.fetch_add(1, Ordering::SeqCst);
+        CpuBackend.batched_matmul(batch, m, n, k)
+    }
+}
+
+fn head(seed: usize, len: usize) -> Vec<f32> {
+    (0..len).map(|i| ((i + seed) % 7) as f32 - 3.0).collect()
+}
+
+#[test]
+fn concurrent_small_matmuls_run_as_one_batch() {
+    let counter = Arc::new(Counter::default());
+    PluginBackends::global().register(PluginBackend::new("coalesce-counter", counter.clone()));
+    let dispatcher = Dispatcher::new(Some(Backend::plugin("coalesce-counter")), Workload::Light)
+        .with_coalescing(CoalesceConfig {
+            window: Duration::from_secs(10),
+            max_batch: 8,
+            ..CoalesceConfig::default()
+        });
+
+    // Eight heads of a 1 x 16 query times a 16 x 4 key block.
+    let (m, n, k) = (1, 4, 16);
+    let start = Barrier::new(8);
+    let products: Vec<(Vec<f32>, Vec<f32>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|h| {
+                let (dispatcher, start) = (&dispatcher, &start);
+                scope.spawn(move || {
+                    let (a, b) = (head(h, m * k), head(h * 3, k * n));
+                    start.wait();
+                    let out = dispatcher.matmul(&a, &b, m, n, k);
+                    (out, CpuBackend.matmul(&a, &b, m, n, k))
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    for (coalesced, expected) in products {
+        assert_eq!(coalesced, expected);
+    }
+    let stats = dispatcher.coalesce_stats();
+    assert_eq!((stats.ops, stats.batches), (8, 1));
+    assert_eq!(stats.mean_batch(), 8.0);
+    assert_eq!(counter.batches.load(Ordering::SeqCst), 1);
+    assert_eq!(counter.matmuls.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn lone_and_large_matmuls_run_on_their_own() {
+    let window = Duration::from_millis(20);
+    let dispatcher =
+        Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_coalescing(CoalesceConfig {
+            window,
+            max_batch: 4,
+            max_macs: 1024,
+        });
+    assert_eq!(dispatcher.coalescing().unwrap().max_batch, 4);
+
+    let (a, b) = (head(1, 8 * 8), head(2, 8 * 8));
+    let begun = Instant::now();
+    assert_eq!(
+        dispatcher.matmul(&a, &b, 8, 8, 8),
+        CpuBackend.matmul(&a, &b, 8, 8, 8)
+    );
+    // A lone matmul waits the window out before running.
+    assert!(begun.elapsed() >= window);
+    assert_eq!(dispatcher.coalesce_stats().batches, 1);
+
+    // 16 x 16 x 16 exceeds `max_macs` and is not delayed.
+    let (a, b) = (head(3, 16 * 16), head(4, 16 * 16));
+    dispatcher.matmul(&a, &b, 16, 16, 16);
+    assert_eq!(dispatcher.coalesce_stats().ops, 1);
+
+    let mut dispatcher = dispatcher;
+    dispatcher.set_coalescing(None);
+    assert_eq!(dispatcher.coalescing(), None);
+    assert_eq!(dispatcher.coalesce_stats().ops, 0);
+}
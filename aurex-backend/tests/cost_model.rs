@@ -0,0 +1,87 @@
+use aurex_backend::cost_model::{BackendProfile, CostModel};
+use aurex_backend::{Backend, Dispatcher, TensorOps, Workload};
+use aurex_utils::roofline::OpCost;
+use std::sync::Arc;
+
+#[test]
+fn small_ops_stay_on_cpu_and_large_ops_offload() {
+    let model = CostModel::new();
+    let candidates = [Backend::Cpu, Backend::Rocm];
+    assert_eq!(
+        model.select("matmul", OpCost::matmul(4, 4, 4), &candidates),
+        Backend::Cpu
+    );
+    assert_eq!(
+        model.select("matmul", OpCost::matmul(1000, 1024, 1024), &candidates),
+        Backend::Rocm
+    );
+    assert_eq!(model.cached_decisions(), 2);
+    // Same size class reuses the cached decision.
+    model.select("matmul", OpCost::matmul(1020, 1024, 1024), &candidates);
+    assert_eq!(model.cached_decisions(), 2);
+}
+
+#[test]
+fn profiles_change_decisions() {
+    let slow_gpu = BackendProfile {
+        gflops: 1.0,
+        ..BackendProfile::nominal(Backend::Rocm)
+    };
+    let model = CostModel::new().with_profile(Backend::Rocm, slow_gpu);
+    let cost = OpCost::matmul(1024, 1024, 1024);
+    assert_eq!(
+        model.select("matmul", cost, &[Backend::Cpu, Backend::Rocm]),
+        Backend::Cpu
+    );
+    assert!(model.estimate(Backend::Rocm, cost) > model.estimate(Backend::Cpu, cost));
+}
+
+#[test]
+fn calibration_measures_throughput() {
+    let model = CostModel::calibrate(&[Backend::Cpu]);
+    let cpu = model.profile(Backend::Cpu).unwrap();
+    assert!(cpu.gflops > 0.0);
+    assert_ne!(cpu.gflops, BackendProfile::nominal(Backend::Cpu).gflops);
+    assert_eq!(
+        model.profile(Backend::Rocm),
+        Some(&BackendProfile::nominal(Backend::Rocm))
+    );
+}
+
+#[test]
+fn dispatcher_uses_cost_model_for_workload() {
+    let model = Arc::new(CostModel::new());
+    let light = Dispatcher::from_cost_model(model.clone(), Workload::Light, &[]);
+    assert_eq!(light.backend(), Backend::Cpu);
+    let heavy = Dispatcher::from_cost_model(
+        model.clone(),
+        Workload::Heavy,
+        &[Backend::Sycl, Backend::OpenCl, Backend::Vulkan],
+    );
+    assert_eq!(heavy.backend(), Backend::Rocm);
+    assert_eq!(
+        heavy.recommend("layer_norm", OpCost::layer_norm(16)),
+        Backend::Cpu
+    );
+}
+
+#[test]
+fn deterministic_dispatcher_ignores_cost_model() {
+    let model = Arc::new(CostModel::new());
+    let disabled = [Backend::Sycl, Backend::OpenCl, Backend::Vulkan];
+    let run_small_norm = |dispatcher: &Dispatcher| {
+        assert_eq!(dispatcher.backend(), Backend::Rocm);
+        dispatcher.layer_norm(&[1.0, 2.0], &[1.0, 1.0], &[0.0, 0.0], 1e-5);
+        dispatcher.live_backends()
+    };
+
+    // The model moves the small op to the CPU.
+    let routed = Dispatcher::from_cost_model(model.clone(), Workload::Heavy, &disabled);
+    assert!(!routed.deterministic());
+    assert!(run_small_norm(&routed).contains(&Backend::Cpu));
+
+    let pinned =
+        Dispatcher::from_cost_model(model, Workload::Heavy, &disabled).with_deterministic(true);
+    assert!(pinned.deterministic());
+    assert_eq!(run_small_norm(&pinned), vec![Backend::Rocm]);
+}
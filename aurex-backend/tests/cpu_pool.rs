@@ -0,0 +1,132 @@
+use aurex_backend::dispatch::CpuBackend;
+use aurex_backend::{CoreSelection, CpuAffinity, CpuThreadPool, TensorOps, ThreadPoolConfig};
+use aurex_utils::hybrid::CoreTypes;
+use aurex_utils::numa::NumaTopology;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Two nodes of four CPUs; CPUs 2, 3, 6 and 7 are efficiency cores.
+fn hybrid_machine() -> (NumaTopology, CoreTypes) {
+    let dir = std::env::temp_dir().join(format!("aurex-cpu-pool-{}", std::process::id()));
+    for (node, cpus) in [("node0", "0-3"), ("node1", "4-7")] {
+        std::fs::create_dir_all(dir.join(node)).unwrap();
+        std::fs::write(dir.join(node).join("cpulist"), cpus).unwrap();
+    }
+    let topology = NumaTopology::detect_in(&dir).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    let dir = std::env::temp_dir().join(format!("aurex-cpu-types-{}", std::process::id()));
+    for (pmu, cpus) in [("cpu_core", "0-1,4-5"), ("cpu_atom", "2-3,6-7")] {
+        std::fs::create_dir_all(dir.join(pmu)).unwrap();
+        std::fs::write(dir.join(pmu).join("cpus"), cpus).unwrap();
+    }
+    let types = CoreTypes::detect_in(&dir).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    (topology, types)
+}
+
+#[test]
+fn workers_run_on_the_selected_cores() {
+    let (topology, types) = hybrid_machine();
+
+    let config = ThreadPoolConfig::default();
+    assert_eq!(config.allowed_cpus(&topology, &types), [0, 1, 4, 5]);
+    assert_eq!(config.worker_count(&topology, &types), 4);
+    // Unpinned workers stay off the efficiency cores.
+    assert_eq!(
+        config.worker_cpus(&topology, &types, 0),
+        Some(vec![0, 1, 4, 5])
+    );
+
+    let all = ThreadPoolConfig {
+        cores: CoreSelection::All,
+        ..config
+    };
+    assert_eq!(all.worker_count(&topology, &types), 8);
+    assert_eq!(all.worker_cpus(&topology, &types, 0), None);
+
+    let node = ThreadPoolConfig {
+        pinning: CpuAffinity::Node,
+        ..config
+    };
+    assert_eq!(node.worker_cpus(&topology, &types, 0), Some(vec![0, 1]));
+    assert_eq!(node.worker_cpus(&topology, &types, 3), Some(vec![4, 5]));
+
+    let core = ThreadPoolConfig {
+        threads: Some(6),
+        pinning: CpuAffinity::Core,
+        cores: CoreSelection::Efficiency,
+    };
+    assert_eq!(core.worker_count(&topology, &types), 6);
+    assert_eq!(core.worker_cpus(&topology, &types, 1), Some(vec![3]));
+    assert_eq!(core.worker_cpus(&topology, &types, 5), Some(vec![3]));
+
+    let pool = CpuThreadPool::with_topology(core, &topology, &types);
+    assert_eq!(pool.threads(), 6);
+    assert_eq!(pool.worker_cpus(2), Some(&[6][..]));
+    assert_eq!(pool.config(), core);
+
+    assert_eq!("P".parse(), Ok(CoreSelection::Performance));
+    assert!("big".parse::<CoreSelection>().is_err());
+}
+
+#[test]
+fn every_worker_runs_each_task_once() {
+    let topology = NumaTopology::single();
+    let types = CoreTypes::uniform(topology.cpus());
+    let config = ThreadPoolConfig {
+        threads: Some(3),
+        ..ThreadPoolConfig::default()
+    };
+    let pool = CpuThreadPool::with_topology(config, &topology, &types);
+    let ranks = Mutex::new(Vec::new());
+    let calls = AtomicUsize::new(0);
+    for _ in 0..4 {
+        pool.run(&|rank| {
+            ranks.lock().unwrap().push(rank);
+            calls.fetch_add(1, Ordering::Relaxed);
+            // Nested runs execute inline on the calling worker.
+            pool.run(&|_| {
+                calls.fetch_add(1, Ordering::Relaxed);
+            });
+        });
+    }
+    let mut ranks = ranks.into_inner().unwrap();
+    ranks.sort_unstable();
+    assert_eq!(ranks, [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2]);
+    assert_eq!(calls.load(Ordering::Relaxed), 12 * 4);
+
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        pool.run(&|rank| assert_ne!(rank, 1));
+    }));
+    assert!(panicked.is_err());
+    // The pool survives a panicking task.
+    pool.run(&|_| {});
+}
+
+#[test]
+fn parallel_cpu_ops_match_a_single_thread() {
+    let (m, n, k) = (64, 48, 32);
+    let a: Vec<f32> = (0..m * k).map(|i| (i % 7) as f32 * 0.5 - 1.0).collect();
+    let b: Vec<f32> = (0..k * n).map(|i| (i % 5) as f32 * 0.25).collect();
+    let input: Vec<f32> = (0..80 * 80).map(|i| (i % 11) as f32).collect();
+    let kernel = [1.0f32; 25];
+
+    CpuThreadPool::configure_global(ThreadPoolConfig {
+        threads: Some(1),
+        ..ThreadPoolConfig::default()
+    });
+    let serial_mm = CpuBackend.matmul(&a, &b, m, n, k);
+    let serial_conv = CpuBackend.conv2d(&input, &kernel, (80, 80), (5, 5));
+
+    let config = ThreadPoolConfig {
+        threads: Some(4),
+        ..ThreadPoolConfig::default()
+    };
+    CpuThreadPool::configure_global(config);
+    assert_eq!(CpuThreadPool::global().threads(), 4);
+    assert_eq!(CpuBackend.matmul(&a, &b, m, n, k), serial_mm);
+    assert_eq!(
+        CpuBackend.conv2d(&input, &kernel, (80, 80), (5, 5)),
+        serial_conv
+    );
+}
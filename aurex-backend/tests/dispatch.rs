@@ -43,6 +43,15 @@ fn heavy_workload_prefers_gpu() {
     assert_ne!(d.backend(), Backend::Cpu);
 }
 
+#[test]
+#[serial]
+fn skips_explicitly_disabled_backends() {
+    reset_env();
+    let d = Dispatcher::with_disabled(Some(Backend::Sycl), Workload::Heavy, &[Backend::Sycl, Backend::Rocm]);
+    assert_ne!(d.backend(), Backend::Sycl);
+    assert_ne!(d.backend(), Backend::Rocm);
+}
+
 #[test]
 #[serial]
 fn heavy_workload_cpu_when_gpu_unavailable() {
@@ -61,3 +70,160 @@ fn light_workload_uses_cpu() {
     let d = Dispatcher::new(None, Workload::Light);
     assert_eq!(d.backend(), Backend::Cpu);
 }
+
+#[test]
+#[serial]
+fn profiler_records_every_op() {
+    use aurex_backend::TensorOps;
+    use aurex_utils::gpu_counters::NoGpu;
+    use aurex_utils::profiler::Profiler;
+    use std::sync::{Arc, Mutex};
+
+    reset_env();
+    let profiler = Arc::new(Mutex::new(Profiler::with_gpu_source(Box::new(NoGpu))));
+    let d = Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_profiler(profiler.clone());
+
+    d.matmul(&[1.0; 6], &[1.0; 6], 2, 2, 3);
+    d.matmul(&[1.0; 4], &[1.0; 4], 2, 2, 2);
+    d.layer_norm(&[1.0, 2.0], &[1.0, 1.0], &[0.0, 0.0], 1e-5);
+
+    let profiler = profiler.lock().unwrap();
+    let records = profiler.records();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].name, "matmul");
+    assert_eq!(records[0].backend, Some("cpu"));
+    assert_eq!(records[0].input_sizes, vec![6, 6]);
+    assert_eq!(records[2].input_sizes, vec![2, 2, 2]);
+
+    let breakdown = profiler.breakdown();
+    let matmul = breakdown.iter().find(|s| s.name == "matmul").unwrap();
+    assert_eq!(matmul.calls, 2);
+}
+
+#[test]
+#[serial]
+fn profiled_ops_feed_roofline_report() {
+    use aurex_backend::TensorOps;
+    use aurex_utils::gpu_counters::NoGpu;
+    use aurex_utils::profiler::Profiler;
+    use aurex_utils::roofline::RooflineAnalyzer;
+    use std::sync::{Arc, Mutex};
+
+    reset_env();
+    let profiler = Arc::new(Mutex::new(Profiler::with_gpu_source(Box::new(NoGpu))));
+    let d = Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_profiler(profiler.clone());
+    d.matmul(&[1.0; 6], &[1.0; 6], 2, 2, 3);
+
+    let profiler = profiler.lock().unwrap();
+    assert_eq!(profiler.records()[0].cost.flops, 24);
+    let report = RooflineAnalyzer::new().analyze(profiler.records());
+    assert_eq!(report.entries[0].op, "matmul");
+    assert_eq!(report.entries[0].backend, Some("cpu"));
+}
+
+#[test]
+#[serial]
+fn routes_ops_to_different_backends() {
+    use aurex_backend::TensorOps;
+    use aurex_utils::gpu_counters::NoGpu;
+    use aurex_utils::profiler::Profiler;
+    use std::sync::{Arc, Mutex};
+
+    reset_env();
+    let profiler = Arc::new(Mutex::new(Profiler::with_gpu_source(Box::new(NoGpu))));
+    let d = Dispatcher::new(Some(Backend::Cpu), Workload::Light)
+        .with_route("matmul", Backend::Rocm)
+        .with_profiler(profiler.clone());
+    assert_eq!(d.routes().get("matmul"), Some(&Backend::Rocm));
+
+    let weights = vec![1.0; 4];
+    let x = vec![1.0, 2.0];
+    let h = d.matmul(&x, &weights, 1, 2, 2);
+    let h2 = d.matmul(&h, &weights, 1, 2, 2);
+    let stats = d.transfer_stats();
+    // x and the weights are copied once; h is already on the device.
+    assert_eq!(stats.transfers, 2);
+    assert_eq!(stats.bytes_reused, 24);
+
+    d.layer_norm(&h2, &[1.0, 1.0], &[0.0, 0.0], 1e-5);
+    // h2 is read back for the CPU layer norm, gamma and beta stay on the host.
+    assert_eq!(d.transfer_stats().transfers, 3);
+
+    let mut live = d.live_backends();
+    live.sort_by_key(|b| b.name());
+    assert_eq!(live, vec![Backend::Cpu, Backend::Rocm]);
+    let backends: Vec<_> = profiler.lock().unwrap().records().iter().map(|r| r.backend).collect();
+    assert_eq!(backends, vec![Some("rocm"), Some("rocm"), Some("cpu")]);
+}
+
+#[test]
+#[serial]
+fn refuses_routes_to_unavailable_backends() {
+    reset_env();
+    std::env::set_var("AUREX_DISABLE_ROCM", "1");
+    let mut d = Dispatcher::new(Some(Backend::Cpu), Workload::Light);
+    assert!(!d.route("matmul", Backend::Rocm));
+    assert!(d.routes().is_empty());
+    reset_env();
+}
+
+#[test]
+#[serial]
+fn specialized_dispatcher_keeps_results() {
+    use aurex_backend::{KernelShapes, TensorOps};
+
+    reset_env();
+    let shapes = KernelShapes::new().with_matmul(2, 2).with_norm(2).with_matmul(2, 2);
+    assert_eq!(shapes.matmuls, vec![(2, 2)]);
+    assert_eq!(shapes.spec_constants("matmul"), vec![vec![2, 2]]);
+    assert_eq!(shapes.spec_constants("layer_norm"), vec![vec![2]]);
+    assert!(shapes.spec_constants("conv2d").is_empty());
+
+    let d = Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_shapes(shapes.clone());
+    assert_eq!(d.shapes(), &shapes);
+    let out = d.matmul(&[1.0, 2.0, 3.0, 4.0], &[1.0, 0.0, 0.0, 1.0], 2, 2, 2);
+    assert_eq!(out, vec![1.0, 2.0, 3.0, 4.0]);
+}
+
+#[test]
+#[serial]
+fn warmup_plans_and_captures_ops() {
+    use aurex_backend::{KernelShapes, TensorOps};
+
+    reset_env();
+    let shapes = KernelShapes::new().with_matmul(4, 2).with_norm(2).with_head_dim(2);
+    let routed = Dispatcher::new(Some(Backend::Cpu), Workload::Light)
+        .with_route("matmul", Backend::Rocm)
+        .with_shapes(shapes.clone());
+    let plan = routed.warmup(routed.shapes());
+    let ops: Vec<_> = plan.ops.iter().map(|p| (p.op, p.shape.clone(), p.backend)).collect();
+    assert_eq!(
+        ops,
+        vec![
+            ("matmul", vec![1, 4, 2], Backend::Rocm),
+            ("layer_norm", vec![2], Backend::Cpu),
+            ("attention", vec![2], Backend::Cpu),
+        ]
+    );
+    assert!(routed.live_backends().contains(&Backend::Rocm));
+    assert_eq!(routed.transfer_stats().transfers, 0);
+    assert_eq!(plan.backend("conv2d"), None);
+
+    // A dispatcher without the route follows the captured plan.
+    let d = Dispatcher::new(Some(Backend::Cpu), Workload::Light);
+    d.capture(plan.clone());
+    assert_eq!(d.captured_plan(), Some(plan.clone()));
+    d.matmul(&[1.0, 2.0], &[1.0, 0.0, 0.0, 1.0], 1, 2, 2);
+    assert!(d.live_backends().contains(&Backend::Rocm));
+    assert_eq!(d.release_plan(), Some(plan));
+    assert_eq!(d.captured_plan(), None);
+
+    // Ops planned on backends that became unavailable are not pinned.
+    std::env::set_var("AUREX_DISABLE_ROCM", "1");
+    let d = Dispatcher::new(Some(Backend::Cpu), Workload::Light);
+    d.capture(routed.warmup(&shapes));
+    let captured = d.captured_plan().unwrap();
+    assert_eq!(captured.backend("matmul"), None);
+    assert_eq!(captured.backend("layer_norm"), Some(Backend::Cpu));
+    reset_env();
+}
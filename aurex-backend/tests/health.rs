@@ -0,0 +1,91 @@
+use aurex_backend::health::{self, HealthCheck};
+use aurex_backend::{Backend, Dispatcher, RuntimeError, TensorOps, Workload};
+
+fn dispatcher() -> Dispatcher {
+    Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_health_check(1)
+}
+
+#[test]
+fn reports_op_layer_and_first_non_finite_value() {
+    let err = health::check_output(
+        "matmul",
+        Some("lm_head"),
+        Backend::Cpu,
+        &[1.0, f32::NAN, f32::INFINITY],
+    )
+    .unwrap_err();
+    match &err {
+        RuntimeError::NonFinite {
+            op,
+            layer,
+            backend,
+            index,
+            value,
+        } => {
+            assert_eq!(
+                (*op, layer.as_deref(), *backend, *index),
+                ("matmul", Some("lm_head"), Backend::Cpu, 1)
+            );
+            assert!(value.is_nan());
+        }
+    }
+    assert_eq!(
+        err.to_string(),
+        "matmul in layer lm_head on cpu produced NaN at index 1"
+    );
+
+    let err =
+        health::check_output("layer_norm", None, Backend::Cpu, &[f32::NEG_INFINITY]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "layer_norm on cpu produced -inf at index 0"
+    );
+    assert!(health::check_output("matmul", None, Backend::Cpu, &[0.0, -1.0, f32::MAX]).is_ok());
+}
+
+#[test]
+fn samples_one_op_out_of_every_n() {
+    let check = HealthCheck::new(3);
+    let sampled: Vec<bool> = (0..7).map(|_| check.sample()).collect();
+    assert_eq!(sampled, [true, false, false, true, false, false, true]);
+    assert_eq!(HealthCheck::new(0).sample_every(), 1);
+}
+
+#[test]
+fn finite_outputs_pass() {
+    let d = dispatcher();
+    assert_eq!(d.health_check(), Some(1));
+    let out = d.matmul(&[1.0, 2.0], &[3.0, 4.0], 1, 1, 2);
+    assert_eq!(out, vec![11.0]);
+}
+
+#[test]
+#[should_panic(expected = "matmul in layer attn.0 on cpu produced NaN at index 0")]
+fn non_finite_output_panics_with_layer() {
+    let d = dispatcher();
+    d.set_layer(Some("attn.0"));
+    d.matmul(&[f32::NAN, 2.0], &[3.0, 4.0], 1, 1, 2);
+}
+
+#[test]
+fn unsampled_ops_are_not_checked() {
+    let d = Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_health_check(2);
+    d.matmul(&[1.0], &[1.0], 1, 1, 1);
+    let out = d.matmul(&[f32::NAN], &[1.0], 1, 1, 1);
+    assert!(out[0].is_nan());
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut d = Dispatcher::new(Some(Backend::Cpu), Workload::Light);
+    assert_eq!(d.health_check(), None);
+    let out = d.matmul(&[f32::INFINITY], &[1.0], 1, 1, 1);
+    assert!(out[0].is_infinite());
+
+    d.set_layer(Some("lm_head"));
+    assert_eq!(d.layer().as_deref(), Some("lm_head"));
+    d.set_layer(None);
+    assert_eq!(d.layer(), None);
+    d.set_health_check(Some(4));
+    assert_eq!(d.health_check(), Some(4));
+}
@@ -0,0 +1,86 @@
+use aurex_backend::memory_pool::{size_class, BufferPool, MIN_SIZE_CLASS};
+use aurex_backend::{Backend, Dispatcher, Precision, TensorOps, Workload};
+
+#[test]
+fn size_classes_are_powers_of_two() {
+    assert_eq!(size_class(0), MIN_SIZE_CLASS);
+    assert_eq!(size_class(1), MIN_SIZE_CLASS);
+    assert_eq!(size_class(100), 128);
+    assert_eq!(size_class(128), 128);
+    assert_eq!(size_class(129), 256);
+}
+
+#[test]
+fn released_buffers_are_reused_within_their_class() {
+    let pool = BufferPool::default();
+    let mut first = pool.acquire(Backend::Cpu, 100);
+    assert_eq!(first.len(), 100);
+    first.fill(3.0);
+    drop(first);
+    assert_eq!(pool.cached_bytes(), 128 * 4);
+
+    // Same class: served from the pool, zeroed.
+    let second = pool.acquire(Backend::Cpu, 120);
+    assert!(second.iter().all(|&v| v == 0.0));
+    // Other class and other device: fresh allocations.
+    let third = pool.acquire(Backend::Cpu, 300);
+    let device = pool.acquire(Backend::Vulkan, 120);
+    assert_eq!(device.backend(), Backend::Vulkan);
+    drop((second, third, device));
+
+    let host = pool.stats(Backend::Cpu);
+    assert_eq!((host.allocations, host.reuses), (2, 1));
+    assert_eq!(host.bytes_reused, 128 * 4);
+    assert_eq!(host.bytes_cached, (128 + 512) * 4);
+    assert_eq!(pool.stats(Backend::Vulkan).allocations, 1);
+    assert_eq!(host.reuse_ratio(), 1.0 / 3.0);
+
+    pool.trim();
+    assert_eq!(pool.cached_bytes(), 0);
+    assert_eq!(pool.stats(Backend::Cpu).bytes_cached, 0);
+}
+
+#[test]
+fn idle_buffers_stay_within_budget() {
+    let pool = BufferPool::new(64 * 4);
+    let a = pool.acquire(Backend::Cpu, 64);
+    let b = pool.acquire(Backend::Cpu, 64);
+    drop((a, b));
+    assert_eq!(pool.cached_bytes(), 64 * 4);
+    drop(pool.acquire(Backend::Cpu, 1000));
+    assert_eq!(pool.cached_bytes(), 64 * 4);
+}
+
+#[test]
+fn dispatcher_pools_rounded_inputs() {
+    use aurex_utils::gpu_counters::NoGpu;
+    use aurex_utils::profiler::Profiler;
+    use std::sync::{Arc, Mutex};
+
+    let profiler = Arc::new(Mutex::new(Profiler::with_gpu_source(Box::new(NoGpu))));
+    let mut d =
+        Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_profiler(profiler.clone());
+    d.set_precision(Precision::Int8);
+    for _ in 0..3 {
+        d.matmul(&[1.0; 6], &[0.5; 6], 2, 2, 3);
+    }
+
+    // Both inputs need a buffer on the first call, later calls reuse them.
+    let stats = d.pool().stats(Backend::Cpu);
+    assert_eq!((stats.allocations, stats.reuses), (2, 4));
+    assert_eq!(
+        profiler.lock().unwrap().pool_stats().get("cpu"),
+        Some(&stats)
+    );
+
+    let scratch = d.scratch(Backend::Cpu, 6);
+    assert_eq!(&scratch[..], &[0.0; 6]);
+}
+
+#[test]
+fn full_precision_needs_no_scratch() {
+    let d = Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_pool_capacity(0);
+    assert_eq!(d.matmul(&[1.0; 4], &[1.0; 4], 2, 2, 2), vec![2.0; 4]);
+    assert!(d.pool().all_stats().is_empty());
+    assert_eq!(d.pool().max_cached_bytes(), 0);
+}
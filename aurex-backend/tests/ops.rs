@@ -52,3 +52,17 @@ fn layer_norm_consistency() {
         assert!((out[1] - 1.0).abs() < 1e-4);
     }
 }
+
+#[test]
+fn dispatcher_rounds_inputs_to_precision() {
+    use aurex_backend::{Backend, Dispatcher, Precision, Workload};
+
+    let mut d = Dispatcher::new(Some(Backend::Cpu), Workload::Light);
+    let a = vec![1.001, 0.0, 0.0, 1.0];
+    let b = vec![1.0, 0.0, 0.0, 1.0];
+    assert_eq!(d.matmul(&a, &b, 2, 2, 2), a);
+
+    d.set_precision(Precision::Bf16);
+    assert_eq!(d.precision(), Precision::Bf16);
+    assert_eq!(d.matmul(&a, &b, 2, 2, 2), vec![1.0, 0.0, 0.0, 1.0]);
+}
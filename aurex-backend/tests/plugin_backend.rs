@@ -0,0 +1,139 @@
+use aurex_backend::dispatch::CpuBackend;
+use aurex_backend::{Backend, Dispatcher, PluginBackend, PluginBackends, TensorOps, Workload};
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn registers_plugin_backends_by_name() {
+    let backends = PluginBackends::new();
+    let npu = PluginBackend::new("npu", Arc::new(CpuBackend))
+        .with_ops(["matmul", "layer_norm"])
+        .with_devices(["npu0"]);
+    assert!(backends.register(npu).is_none());
+    assert!(backends
+        .register(PluginBackend::new("dsp", Arc::new(CpuBackend)))
+        .is_none());
+    assert_eq!(backends.names(), vec!["dsp", "npu"]);
+
+    let npu = backends.get("npu").unwrap();
+    assert!(npu.supports("matmul"));
+    assert!(!npu.supports("attention"));
+    assert_eq!(npu.devices(), ["npu0"]);
+    assert_eq!(npu.tensor_ops().matmul(&[2.0], &[3.0], 1, 1, 1), vec![6.0]);
+    // Plugins declaring no ops accept every op.
+    assert!(backends.get("dsp").unwrap().supports("attention"));
+
+    assert!(backends.remove("dsp").is_some());
+    assert!(backends.get("dsp").is_none());
+}
+
+/// Plugin ops offsetting matmul results so tests can tell where ops ran.
+struct Offset;
+
+impl TensorOps for Offset {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        CpuBackend
+            .matmul(a, b, m, n, k)
+            .iter()
+            .map(|v| v + 100.0)
+            .collect()
+    }
+    fn conv2d(
+        &self,
+        _input: &[f32],
+        _kernel: &[f32],
+        _input_shape: (usize, usize),
+        _kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        unreachable!("conv2d is not declared")
+    }
+    fn attention(&self, _q: &[f32], _k: &[f32], _v: &[f32], _dim: usize) -> Vec<f32> {
+        unreachable!("attention is not declared")
+    }
+    fn layer_norm(&self, _x: &[f32], _gamma: &[f32], _beta: &[f32], _eps: f32) -> Vec<f32> {
+        unreachable!("layer_norm is not declared")
+    }
+}
+
+#[test]
+fn dispatcher_routes_ops_to_plugins() {
+    PluginBackends::global()
+        .register(PluginBackend::new("offset", Arc::new(Offset)).with_ops(["matmul"]));
+    let plugin = Backend::from_name("plugin:offset").unwrap();
+    assert_eq!(plugin, Backend::plugin("offset"));
+    assert_eq!(plugin.to_string(), "plugin:offset");
+    assert_eq!(plugin.name(), "offset");
+    assert!(Dispatcher::available_backends(&[]).contains(&plugin));
+    assert!(!Dispatcher::available_backends(&[plugin]).contains(&plugin));
+
+    let d = Dispatcher::new(Some(plugin), Workload::Light);
+    assert_eq!(d.backend(), plugin);
+    assert_eq!(d.matmul(&[2.0], &[3.0], 1, 1, 1), vec![106.0]);
+    // Ops the plugin does not declare run on the CPU.
+    assert_eq!(
+        d.layer_norm(&[1.0, 3.0], &[1.0, 1.0], &[0.0, 0.0], 0.0),
+        vec![-1.0, 1.0]
+    );
+
+    let mut d = Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_route("matmul", plugin);
+    assert_eq!(d.matmul(&[2.0], &[3.0], 1, 1, 1), vec![106.0]);
+    assert!(d.live_backends().contains(&plugin));
+    assert!(!d.route("matmul", Backend::plugin("absent")));
+}
+
+#[test]
+fn unregistered_plugins_fall_back() {
+    let absent = Backend::plugin("not_loaded");
+    assert!(!Dispatcher::available_backends(&[]).contains(&absent));
+    let d = Dispatcher::new(Some(absent), Workload::Light);
+    assert_eq!(d.backend(), Backend::Cpu);
+    assert_eq!(Backend::from_name("plugin:"), None);
+    assert_eq!(
+        Backend::from_name("PLUGIN:Npu"),
+        Some(Backend::plugin("Npu"))
+    );
+}
+
+/// CPU ops tracking how many batches are open.
+#[derive(Default)]
+struct Batches(AtomicIsize);
+
+impl TensorOps for Batches {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        assert_eq!(self.0.load(Ordering::SeqCst), 1, "matmul outside a batch");
+        CpuBackend.matmul(a, b, m, n, k)
+    }
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        CpuBackend.conv2d(input, kernel, input_shape, kernel_shape)
+    }
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        CpuBackend.attention(q, k, v, dim)
+    }
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        CpuBackend.layer_norm(x, gamma, beta, eps)
+    }
+    fn begin_batch(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+    fn end_batch(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn dispatcher_batches_its_backends() {
+    let batches = Arc::new(Batches::default());
+    PluginBackends::global().register(PluginBackend::new("batches", batches.clone()));
+    let d = Dispatcher::new(Some(Backend::plugin("batches")), Workload::Light);
+
+    d.begin_batch();
+    assert_eq!(d.matmul(&[2.0], &[3.0], 1, 1, 1), vec![6.0]);
+    d.end_batch();
+    assert_eq!(batches.0.load(Ordering::SeqCst), 0);
+}
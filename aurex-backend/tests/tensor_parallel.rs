@@ -0,0 +1,70 @@
+use aurex_backend::dispatch::CpuBackend;
+use aurex_backend::tensor_parallel::{all_reduce_sum, shard_range, ShardStrategy};
+use aurex_backend::{Backend, CpuAffinity, TensorOps, TensorParallelDispatcher};
+use aurex_utils::numa::NumaTopology;
+
+fn matrices(m: usize, n: usize, k: usize) -> (Vec<f32>, Vec<f32>) {
+    let a = (0..m * k).map(|i| (i % 5) as f32 - 2.0).collect();
+    let b = (0..k * n).map(|i| (i % 7) as f32 * 0.5).collect();
+    (a, b)
+}
+
+#[test]
+fn shard_ranges_cover_the_dimension() {
+    let ranges: Vec<_> = (0..3).map(|r| shard_range(10, 3, r)).collect();
+    assert_eq!(ranges, vec![0..4, 4..7, 7..10]);
+    assert_eq!(
+        all_reduce_sum(vec![vec![1.0, 2.0], vec![3.0, 4.0]]),
+        vec![4.0, 6.0]
+    );
+}
+
+#[test]
+fn column_and_row_sharding_match_single_device() {
+    let (m, n, k) = (3, 7, 5);
+    let (a, b) = matrices(m, n, k);
+    let expected = CpuBackend.matmul(&a, &b, m, n, k);
+    for strategy in [ShardStrategy::Column, ShardStrategy::Row] {
+        let tp = TensorParallelDispatcher::new(&[Backend::Cpu, Backend::Cpu, Backend::Cpu])
+            .with_strategy(strategy);
+        assert_eq!(tp.world_size(), 3);
+        assert_eq!(tp.matmul(&a, &b, m, n, k), expected, "{strategy:?}");
+    }
+}
+
+#[test]
+fn pre_sharded_weights_hold_a_fraction_of_the_matrix() {
+    let (m, n, k) = (2, 8, 4);
+    let (a, b) = matrices(m, n, k);
+    let tp = TensorParallelDispatcher::new(&[Backend::Cpu, Backend::Cpu]);
+    let shards = tp.shard_weights(&b, k, n, ShardStrategy::Row);
+    assert!(shards.iter().all(|s| s.data.len() == b.len() / 2));
+    assert_eq!(
+        tp.matmul_sharded(&a, &shards, m, n, k),
+        CpuBackend.matmul(&a, &b, m, n, k)
+    );
+}
+
+#[test]
+fn pinned_workers_match_unpinned_results() {
+    let topology = NumaTopology::single();
+    let cpus = topology.nodes()[0].cpus.len();
+    assert_eq!(CpuAffinity::None.cpus(&topology, 0), None);
+    assert_eq!(CpuAffinity::Node.cpus(&topology, 1).unwrap().len(), cpus);
+    assert_eq!(CpuAffinity::Core.cpus(&topology, cpus), Some(vec![0]));
+    assert_eq!("core".parse::<CpuAffinity>(), Ok(CpuAffinity::Core));
+
+    let (m, n, k) = (2, 6, 4);
+    let (a, b) = matrices(m, n, k);
+    for affinity in [CpuAffinity::Node, CpuAffinity::Core] {
+        let tp = TensorParallelDispatcher::new(&[Backend::Cpu, Backend::Cpu])
+            .with_topology(affinity, topology.clone())
+            .with_strategy(ShardStrategy::Row);
+        let shards = tp.shard_weights(&b, k, n, ShardStrategy::Row);
+        assert_eq!(shards[1].range, 2..4);
+        assert_eq!(
+            tp.matmul_sharded(&a, &shards, m, n, k),
+            CpuBackend.matmul(&a, &b, m, n, k)
+        );
+    }
+}
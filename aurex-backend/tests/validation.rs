@@ -0,0 +1,79 @@
+use aurex_backend::validation::{self, Expected};
+use aurex_backend::{Backend, Dispatcher, ShapeError, TensorOps, Workload};
+
+fn dispatcher() -> Dispatcher {
+    Dispatcher::new(Some(Backend::Cpu), Workload::Light)
+}
+
+#[test]
+fn reports_op_operand_and_sizes() {
+    let err = validation::matmul(&[0.0; 6], &[0.0; 8], 2, 3, 3).unwrap_err();
+    assert_eq!(
+        err,
+        ShapeError {
+            op: "matmul",
+            operand: "b",
+            expected: Expected::Exactly(9),
+            actual: 8,
+            dims: vec![("m", 2), ("n", 3), ("k", 3)],
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "matmul (m=2 n=3 k=3): b has size 8, expected 9"
+    );
+}
+
+#[test]
+fn checks_every_op() {
+    assert!(validation::matmul(&[0.0; 6], &[0.0; 6], 2, 2, 3).is_ok());
+
+    assert!(validation::conv2d(&[0.0; 9], &[0.0; 4], (3, 3), (2, 2)).is_ok());
+    let err = validation::conv2d(&[0.0; 4], &[0.0; 6], (2, 2), (3, 2)).unwrap_err();
+    assert_eq!(
+        (err.operand, err.expected, err.actual),
+        ("kernel rows", Expected::AtMost(2), 3)
+    );
+    let err = validation::conv2d(&[0.0; 4], &[], (2, 2), (0, 2)).unwrap_err();
+    assert_eq!(err.expected, Expected::AtLeast(1));
+
+    let err = validation::attention(&[0.0; 4], &[0.0; 3], 4).unwrap_err();
+    assert_eq!((err.operand, err.actual), ("k", 3));
+    assert_eq!(
+        validation::attention(&[], &[], 0).unwrap_err().operand,
+        "dim"
+    );
+
+    let err = validation::layer_norm(&[0.0; 3], &[1.0; 3], &[0.0; 2]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "layer_norm (len=3): beta has size 2, expected 3"
+    );
+}
+
+#[test]
+fn try_ops_return_errors() {
+    let d = dispatcher().with_validation(false);
+    assert_eq!(
+        d.try_matmul(&[1.0; 4], &[1.0; 4], 2, 2, 2),
+        Ok(vec![2.0; 4])
+    );
+    let err = d
+        .try_layer_norm(&[1.0, 2.0], &[1.0], &[0.0, 0.0], 1e-5)
+        .unwrap_err();
+    assert_eq!(err.operand, "gamma");
+    assert!(d.try_attention(&[1.0; 2], &[1.0; 2], &[1.0; 2], 2).is_ok());
+    assert!(d.try_conv2d(&[1.0; 4], &[1.0; 9], (2, 2), (3, 3)).is_err());
+}
+
+#[test]
+#[should_panic(expected = "matmul (m=2 n=2 k=2): a has size 3, expected 4")]
+fn validating_dispatcher_panics_with_diagnostics() {
+    let d = dispatcher().with_validation(true);
+    d.matmul(&[1.0; 3], &[1.0; 4], 2, 2, 2);
+}
+
+#[test]
+fn validation_defaults_to_debug_builds() {
+    assert_eq!(dispatcher().validates(), cfg!(debug_assertions));
+}
@@ -0,0 +1,102 @@
+use aurex_backend::vulkan_backend::{MatmulKernel, COOPERATIVE_TILE};
+use aurex_backend::{BackendCapabilities, TensorOps, VulkanBackend};
+
+#[test]
+fn accelerated_matmul_kernels_follow_the_capabilities() {
+    let generic = BackendCapabilities::default();
+    assert_eq!(MatmulKernel::available(&generic), [MatmulKernel::Generic]);
+
+    let subgroup = BackendCapabilities {
+        subgroup_ops: true,
+        ..BackendCapabilities::default()
+    };
+    assert_eq!(
+        MatmulKernel::available(&subgroup),
+        [MatmulKernel::Subgroup, MatmulKernel::Generic]
+    );
+
+    let all = BackendCapabilities {
+        subgroup_ops: true,
+        cooperative_matrix: true,
+        ..BackendCapabilities::default()
+    };
+    assert_eq!(
+        MatmulKernel::available(&all),
+        [
+            MatmulKernel::CooperativeMatrix,
+            MatmulKernel::Subgroup,
+            MatmulKernel::Generic
+        ]
+    );
+}
+
+#[test]
+fn cooperative_matrix_kernel_only_takes_whole_tiles() {
+    let tile = COOPERATIVE_TILE;
+    let kernels = [
+        MatmulKernel::CooperativeMatrix,
+        MatmulKernel::Subgroup,
+        MatmulKernel::Generic,
+    ];
+    assert_eq!(
+        MatmulKernel::select(kernels, 2 * tile, 4 * tile, tile),
+        MatmulKernel::CooperativeMatrix
+    );
+    assert_eq!(
+        MatmulKernel::select(kernels, 1, 4 * tile, tile),
+        MatmulKernel::Subgroup
+    );
+    assert_eq!(
+        MatmulKernel::select([MatmulKernel::CooperativeMatrix], 3, 5, 7),
+        MatmulKernel::Generic
+    );
+}
+
+#[test]
+fn kernels_are_cached_apart_and_use_their_extensions() {
+    let kernels = [
+        MatmulKernel::CooperativeMatrix,
+        MatmulKernel::Subgroup,
+        MatmulKernel::Generic,
+    ];
+    let mut ops: Vec<_> = kernels.iter().map(|kernel| kernel.op()).collect();
+    ops.sort_unstable();
+    ops.dedup();
+    assert_eq!(ops.len(), kernels.len());
+    assert!(MatmulKernel::CooperativeMatrix
+        .source()
+        .contains("GL_KHR_cooperative_matrix"));
+    assert!(MatmulKernel::Subgroup
+        .source()
+        .contains("GL_KHR_shader_subgroup_arithmetic"));
+}
+
+#[test]
+fn matmul_falls_back_to_the_generic_kernel() {
+    let backend = VulkanBackend::new();
+    let caps = backend.capabilities();
+    if !caps.subgroup_ops && !caps.cooperative_matrix {
+        assert_eq!(backend.matmul_kernel(16, 16, 16), MatmulKernel::Generic);
+    }
+    assert_ne!(
+        backend.matmul_kernel(3, 5, 7),
+        MatmulKernel::CooperativeMatrix
+    );
+    let out = backend.matmul(&[1.0, 2.0, 3.0, 4.0], &[5.0, 6.0, 7.0, 8.0], 2, 2, 2);
+    assert_eq!(out, vec![19.0, 22.0, 43.0, 50.0]);
+}
+
+#[test]
+fn repeated_dispatches_reuse_pipelines() {
+    let backend = VulkanBackend::new();
+    let built = backend.pipelines();
+    if !VulkanBackend::is_available() {
+        assert_eq!(built, 0);
+    }
+    for _ in 0..4 {
+        backend.matmul(&[1.0, 2.0], &[3.0, 4.0], 1, 1, 2);
+        backend.layer_norm(&[1.0, 3.0], &[1.0, 1.0], &[0.0, 0.0], 0.0);
+    }
+    assert_eq!(backend.pipelines(), built);
+    backend.save_pipeline_cache().unwrap();
+}
@@ -1,4 +1,23 @@
 fn main() {
-    aurex_cli::compile_model("model.onnx", "cpu");
-    aurex_cli::run_model("model.onnx", "cpu");
+    let model = std::env::args().nth(1).unwrap_or_else(|| "model.json".into());
+    let bundle = match aurex_cli::compile_model(&model, "cpu", &aurex_cli::CompileOptions::default()) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            eprintln!("compile failed: {err:#}");
+            return;
+        }
+    };
+    let config = aurex_runtime::AurexConfig::load(None).unwrap_or_default();
+    let options = aurex_cli::RunOptions {
+        prompt: "Hello".into(),
+        max_tokens: 32,
+        ..aurex_cli::RunOptions::default()
+    };
+    let result = aurex_cli::run_model(bundle.to_str().unwrap(), &config, &options, |text| {
+        print!("{text}")
+    });
+    println!();
+    if let Err(err) = result {
+        eprintln!("run failed: {err:#}");
+    }
 }
@@ -1,13 +1,827 @@
 //! Command handlers for the `aurex-cli` binary.
 
-/// Compile a model for the given backend target.
-pub fn compile_model(model: &str, target: &str) {
-    println!("Compiling {model} for {target} backend");
-    // TODO: integrate with actual compilation pipeline
+pub mod request_log;
+pub mod server;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use amduda::aurex_lm::benchmark::{self, BenchConfig, BenchReport};
+use amduda::aurex_lm::bundle::{
+    self, BundleManifest, CompiledBundle, KernelBlob, KernelKind, BUNDLE_EXTENSION, BUNDLE_VERSION,
+};
+use amduda::aurex_lm::embeddings::{EmbeddingModel, Pooling};
+use amduda::aurex_lm::evaluation::{self, Perplexity};
+use amduda::aurex_lm::generation::{GenerationConfig, GenerationEngine, GenerationOutput};
+use amduda::aurex_lm::grammar::Grammar;
+use amduda::aurex_lm::layout::WeightLayout;
+use amduda::amduda_core::memory_tiering::{DeviceCapabilities, MemoryManager};
+use amduda::aurex_lm::model_loader::{
+    load_model, LoadedModel, ModelConfig, Quantization, TensorInfo, Weights,
+};
+use amduda::aurex_lm::retrieval::{ChunkConfig, Retriever, VectorIndex};
+use amduda::aurex_lm::sampler::SamplingParams;
+use amduda::aurex_lm::tiny_lm::TinyLm;
+use amduda::aurex_lm::tokenizer::ByteTokenizer;
+use amduda::aurex_lm::vision::Image;
+use amduda::hal_backends::verification::{self, ConformanceReport, VerifyConfig};
+use anyhow::{anyhow, bail, Context, Result};
+use aurex_backend::{Backend, Dispatcher, Precision, Workload};
+use aurex_kernel::codegen::{self, ShaderLanguage};
+use aurex_kernel::graph::{Graph, UnaryOp};
+use aurex_kernel::memory_plan::MemoryPlan;
+use aurex_kernel::optimizations::{optimize_graph, GraphNode};
+use aurex_kernel::passes::optimize;
+use aurex_runtime::AurexConfig;
+use request_log::{ReplayReport, RequestLog};
+
+/// Ops of a single transformer block before fusion.
+const BLOCK_OPS: [&str; 8] = [
+    "matmul",
+    "attention",
+    "matmul",
+    "layer_norm",
+    "matmul",
+    "mul",
+    "matmul",
+    "layer_norm",
+];
+
+/// Options for [`compile_model`].
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Destination of the bundle; defaults to the model path with an
+    /// `.aurexc` extension.
+    pub output: Option<PathBuf>,
+    /// Quantize `f32` weights to this format.
+    pub quantize: Option<Quantization>,
+}
+
+/// Compile a model for the given backend target into a `.aurexc` bundle and
+/// return the path of the written bundle.
+///
+/// The pipeline loads the model, optionally quantizes its weights, repacks
+/// them into the [`WeightLayout`] of `target`, runs the fusion passes from
+/// `aurex-kernel` over the op graph and pre-compiles the kernels of the fused
+/// graph for `target`.  Vulkan kernels are generated from a transformer
+/// block in the graph IR at the model's hidden size.  Quantizing to a
+/// precision the capabilities of `target` do not include is an error.
+pub fn compile_model(model: &str, target: &str, options: &CompileOptions) -> Result<PathBuf> {
+    let backend =
+        Backend::from_name(target).ok_or_else(|| anyhow!("unknown backend target '{target}'"))?;
+    let mut loaded = load_model(model)?;
+    let bytes = loaded.weights.as_bytes().to_vec();
+    let weight_count = bundle::weight_count(bytes.len(), loaded.config.quantization);
+
+    if let Some(target_quant) = options.quantize {
+        let precision = match target_quant {
+            Quantization::Int4 => Precision::Int4,
+            Quantization::Int8 => Precision::Int8,
+            Quantization::Bf16 => Precision::Bf16,
+        };
+        let capabilities = backend.capabilities();
+        if !capabilities.supports(precision) {
+            bail!(
+                "target '{target}' cannot run {target_quant:?} weights, it supports {:?}",
+                capabilities.precisions
+            );
+        }
+        match loaded.config.quantization {
+            None => {
+                let data: Vec<f32> = bytes
+                    .chunks_exact(4)
+                    .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect();
+                loaded.change_precision(&data, target_quant);
+                tracing::info!(?target_quant, weights = weight_count, "quantized weights");
+            }
+            Some(existing) if existing == target_quant => {}
+            Some(existing) => bail!(
+                "model weights are already quantized as {existing:?}, cannot re-quantize to {target_quant:?}"
+            ),
+        }
+    }
+
+    loaded.repack(WeightLayout::for_backend(backend));
+
+    let layers = loaded.config.layers.unwrap_or(1);
+    let nodes: Vec<GraphNode> = (0..layers)
+        .flat_map(|_| BLOCK_OPS.iter().map(|&name| GraphNode { name }))
+        .collect();
+    let graph: Vec<String> = optimize_graph(&nodes)
+        .into_iter()
+        .map(|n| n.name.to_string())
+        .collect();
+    let kernels = match backend {
+        Backend::Vulkan => {
+            let (_, hidden) = loaded.config.embedding_shape(weight_count);
+            vulkan_kernels(&block_graph(hidden.max(1))?)
+        }
+        #[cfg(feature = "jit")]
+        Backend::Cpu | Backend::Rocm => jit_kernels(&graph),
+        _ => Vec::new(),
+    };
+
+    // The bundle stores the scale and layout of the encoded weights in its
+    // config.
+    loaded.config.scale = loaded.scale;
+    let weights = match loaded.weights {
+        Weights::Memory(data) => data,
+        Weights::Mmap(_) | Weights::Numa(_) => bytes,
+    };
+    let compiled = CompiledBundle {
+        manifest: BundleManifest {
+            version: BUNDLE_VERSION,
+            config: loaded.config,
+            target: backend.to_string(),
+            weight_count,
+            graph,
+            kernels: Vec::new(),
+        },
+        weights,
+        kernels,
+    };
+    let output = options
+        .output
+        .clone()
+        .unwrap_or_else(|| Path::new(model).with_extension(BUNDLE_EXTENSION));
+    compiled.save(&output)?;
+    Ok(output)
+}
+
+/// Transformer block in the graph IR the Vulkan kernels are generated from:
+/// attention over one position and a GELU MLP, each followed by a residual
+/// RMS norm.
+fn block_graph(hidden: usize) -> Result<Graph> {
+    let mut graph = Graph::new();
+    let x = graph.input("x", &[1, hidden]);
+    let [wq, wk, wv, wo] = ["wq", "wk", "wv", "wo"].map(|w| graph.input(w, &[hidden, hidden]));
+    let q = graph.matmul(x, wq)?;
+    let k = graph.matmul(x, wk)?;
+    let v = graph.matmul(x, wv)?;
+    let kt = graph.transpose(k)?;
+    let scores = graph.matmul(q, kt)?;
+    let scale = graph.constant(vec![1.0 / (hidden as f32).sqrt()], &[1])?;
+    let scores = graph.mul(scores, scale)?;
+    let probs = graph.softmax(scores)?;
+    let attn = graph.matmul(probs, v)?;
+    let attn = graph.matmul(attn, wo)?;
+    let attn = graph.add(attn, x)?;
+    let norm = graph.input("attn_norm", &[hidden]);
+    let h = graph.rms_norm(attn, norm, 1e-5)?;
+
+    let w_up = graph.input("w_up", &[hidden, 4 * hidden]);
+    let b_up = graph.input("b_up", &[4 * hidden]);
+    let w_down = graph.input("w_down", &[4 * hidden, hidden]);
+    let up = graph.matmul(h, w_up)?;
+    let up = graph.add(up, b_up)?;
+    let up = graph.unary(UnaryOp::Gelu, up)?;
+    let down = graph.matmul(up, w_down)?;
+    let down = graph.add(down, h)?;
+    let norm = graph.input("mlp_norm", &[hidden]);
+    let out = graph.rms_norm(down, norm, 1e-5)?;
+    graph.output(out)?;
+    optimize(&mut graph);
+    Ok(graph)
+}
+
+/// SPIR-V of the shaders generated for the nodes of `block`.  Shaders that
+/// fail to compile are skipped with a warning.
+fn vulkan_kernels(block: &Graph) -> Vec<KernelBlob> {
+    let mut kernels = Vec::new();
+    for shader in codegen::lower_graph(block, ShaderLanguage::Glsl) {
+        match aurex_backend::vulkan_backend::compile_shader(&shader.source) {
+            Ok(words) => kernels.push(KernelBlob {
+                name: shader.name,
+                kind: KernelKind::SpirV,
+                data: words.iter().flat_map(|w| w.to_le_bytes()).collect(),
+            }),
+            Err(err) => {
+                tracing::warn!(kernel = %shader.name, %err, "failed to compile SPIR-V kernel")
+            }
+        }
+    }
+    kernels
+}
+
+/// Pre-compile the LLVM IR of the kernels used by `graph` for the JIT.
+/// Kernels that fail to compile are skipped with a warning; `run` compiles
+/// them on demand.
+#[cfg(feature = "jit")]
+fn jit_kernels(graph: &[String]) -> Vec<KernelBlob> {
+    let mut ops: Vec<&str> = graph.iter().map(String::as_str).collect();
+    ops.sort_unstable();
+    ops.dedup();
+
+    let mut kernels = Vec::new();
+    for op in ops {
+        let source = match op {
+            "add" => "add_f32",
+            "mul" => "mul_f32",
+            _ => continue,
+        };
+        match amduda::amduda_core::jit_compiler::emit_kernel_ir_f32(source) {
+            Ok(ir) => kernels.push(KernelBlob {
+                name: op.to_string(),
+                kind: KernelKind::LlvmIr,
+                data: ir.into_bytes(),
+            }),
+            Err(err) => tracing::warn!(op, %err, "failed to emit LLVM IR kernel"),
+        }
+    }
+    kernels
+}
+
+/// Options for [`run_model`].
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub prompt: String,
+    pub max_tokens: usize,
+    pub temperature: f32,
+    pub top_p: f32,
+    pub seed: Option<u64>,
+    /// Stop generating at any of these strings.
+    pub stop: Vec<String>,
+    /// GBNF grammar the output must follow.
+    pub grammar: Option<String>,
+    /// PNG image placed in front of the prompt; the model needs a `vision`
+    /// patch embedding.
+    pub image: Option<PathBuf>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        let sampling = SamplingParams::default();
+        Self {
+            prompt: String::new(),
+            max_tokens: 128,
+            temperature: sampling.temperature,
+            top_p: sampling.top_p,
+            seed: None,
+            stop: Vec::new(),
+            grammar: None,
+            image: None,
+        }
+    }
+}
+
+/// Run inference for a model on the backend and precision selected by
+/// `config`, passing generated text to `on_text` as it is produced.  `model`
+/// may be a `.aurexc` bundle or a JSON model configuration.  An image given
+/// in `options` is embedded patch by patch ahead of the prompt.
+pub fn run_model(
+    model: &str,
+    config: &AurexConfig,
+    options: &RunOptions,
+    on_text: impl FnMut(&str),
+) -> Result<GenerationOutput> {
+    let loaded = load_model_or_bundle(model)?;
+    let ops = Arc::new(model_dispatcher(&loaded, config));
+    let mut lm = TinyLm::from_model(&loaded, ops.clone())?;
+    if let Some(path) = &options.image {
+        let image = Image::open(path)?;
+        let patches = loaded.patch_embedding(ops)?.forward(&image);
+        lm.set_prefix_embeddings(&patches)?;
+    }
+
+    let mut sampling = SamplingParams {
+        temperature: options.temperature,
+        top_p: options.top_p,
+        ..SamplingParams::default()
+    };
+    if let Some(seed) = options.seed {
+        sampling.seed = seed;
+    }
+    let generation = GenerationConfig {
+        max_tokens: options.max_tokens,
+        sampling,
+        stop: options.stop.clone(),
+        grammar: options.grammar.as_deref().map(Grammar::parse).transpose()?,
+        ..GenerationConfig::default()
+    };
+    Ok(GenerationEngine::new(lm).generate(&options.prompt, &generation, on_text))
+}
+
+/// Embed every text of `texts` with the model at `model`, a `.aurexc` bundle
+/// or JSON model configuration, on the backend and precision selected by
+/// `config`.
+pub fn embed_texts(
+    model: &str,
+    config: &AurexConfig,
+    texts: &[String],
+    pooling: Pooling,
+) -> Result<Vec<Vec<f32>>> {
+    let loaded = load_model_or_bundle(model)?;
+    let ops = Arc::new(model_dispatcher(&loaded, config));
+    let embeddings = EmbeddingModel::from_model(&loaded, ops)?;
+    Ok(embeddings.embed_batch(texts, pooling))
+}
+
+/// Options for [`rag_ingest`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IngestOptions {
+    pub chunk: ChunkConfig,
+    /// Pooling of a new index; an existing index keeps its own.
+    pub pooling: Pooling,
+}
+
+/// Outcome of [`rag_ingest`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct IngestReport {
+    pub documents: usize,
+    /// Chunks stored for the ingested documents.
+    pub chunks: usize,
+    /// Chunks in the index afterwards.
+    pub total: usize,
+    pub index: PathBuf,
+}
+
+impl fmt::Display for IngestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Ingested {} documents as {} chunks into {} ({} chunks total)",
+            self.documents,
+            self.chunks,
+            self.index.display(),
+            self.total
+        )
+    }
+}
+
+/// Chunk and embed `files` with `model` into the `.aurexri` index at
+/// `index`, creating it if missing.  Files already in the index, keyed by
+/// their path, are replaced.
+pub fn rag_ingest(
+    model: &str,
+    config: &AurexConfig,
+    index: &Path,
+    files: &[PathBuf],
+    options: &IngestOptions,
+) -> Result<IngestReport> {
+    let loaded = load_model_or_bundle(model)?;
+    let ops = Arc::new(model_dispatcher(&loaded, config));
+    let embeddings = EmbeddingModel::from_model(&loaded, ops)?;
+    let mut vectors = if index.exists() {
+        let existing = VectorIndex::load(index)?;
+        if existing.model() != loaded.config.name {
+            bail!(
+                "index {} was built with model {}, not {}",
+                index.display(),
+                existing.model(),
+                loaded.config.name
+            );
+        }
+        existing
+    } else {
+        VectorIndex::new(&loaded.config.name, embeddings.hidden_size(), options.pooling)
+    };
+
+    let mut chunks = 0;
+    for file in files {
+        let text = std::fs::read_to_string(file)
+            .with_context(|| format!("cannot read {}", file.display()))?;
+        let document = file.display().to_string();
+        chunks += vectors.ingest(&embeddings, &document, &text, &options.chunk)?;
+    }
+    vectors.save(index)?;
+    Ok(IngestReport {
+        documents: files.len(),
+        chunks,
+        total: vectors.len(),
+        index: index.to_path_buf(),
+    })
+}
+
+/// Options for [`rag_query`].
+#[derive(Debug, Clone)]
+pub struct RagQueryOptions {
+    /// Chunks injected into the prompt.
+    pub top_k: usize,
+    pub max_tokens: usize,
+    pub temperature: f32,
+    pub top_p: f32,
+    pub seed: Option<u64>,
+    /// Only look up chunks, without generating an answer.
+    pub retrieve_only: bool,
+}
+
+impl Default for RagQueryOptions {
+    fn default() -> Self {
+        let sampling = SamplingParams::default();
+        Self {
+            top_k: 4,
+            max_tokens: 128,
+            temperature: sampling.temperature,
+            top_p: sampling.top_p,
+            seed: None,
+            retrieve_only: false,
+        }
+    }
+}
+
+/// A chunk retrieved by [`rag_query`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetrievedChunk {
+    pub document: String,
+    /// Byte offset of the chunk in its document.
+    pub start: usize,
+    pub score: f32,
+    pub text: String,
+}
+
+/// Result of [`rag_query`].
+#[derive(Debug, Clone)]
+pub struct RagAnswer {
+    /// Retrieved chunks, most similar first.
+    pub hits: Vec<RetrievedChunk>,
+    /// Prompt with the chunks injected.
+    pub prompt: String,
+    /// Generated answer, `None` with `retrieve_only`.
+    pub output: Option<GenerationOutput>,
+}
+
+/// Answer `query` with `model` from the chunks of the index at `index` most
+/// similar to it, passing generated text to `on_text` as it is produced.
+pub fn rag_query(
+    model: &str,
+    config: &AurexConfig,
+    index: &Path,
+    query: &str,
+    options: &RagQueryOptions,
+    on_text: impl FnMut(&str),
+) -> Result<RagAnswer> {
+    let vectors = VectorIndex::load(index)?;
+    let loaded = load_model_or_bundle(model)?;
+    if vectors.model() != loaded.config.name {
+        bail!(
+            "index {} was built with model {}, not {}",
+            index.display(),
+            vectors.model(),
+            loaded.config.name
+        );
+    }
+    let ops = Arc::new(model_dispatcher(&loaded, config));
+    let embeddings = EmbeddingModel::from_model(&loaded, ops.clone())?;
+    let retriever = Retriever::new(&vectors, &embeddings)?.with_top_k(options.top_k);
+    let (prompt, hits) = retriever.augment(query);
+    let hits = hits
+        .into_iter()
+        .map(|hit| RetrievedChunk {
+            document: hit.chunk.document.clone(),
+            start: hit.chunk.start,
+            score: hit.score,
+            text: hit.chunk.text.clone(),
+        })
+        .collect();
+    if options.retrieve_only {
+        return Ok(RagAnswer {
+            hits,
+            prompt,
+            output: None,
+        });
+    }
+
+    let lm = TinyLm::from_model(&loaded, ops)?;
+    let mut sampling = SamplingParams {
+        temperature: options.temperature,
+        top_p: options.top_p,
+        ..SamplingParams::default()
+    };
+    if let Some(seed) = options.seed {
+        sampling.seed = seed;
+    }
+    let generation = GenerationConfig {
+        max_tokens: options.max_tokens,
+        sampling,
+        ..GenerationConfig::default()
+    };
+    let output = GenerationEngine::new(lm).generate(&prompt, &generation, on_text);
+    Ok(RagAnswer {
+        hits,
+        prompt,
+        output: Some(output),
+    })
+}
+
+/// Options for [`eval_model`].
+#[derive(Debug, Clone)]
+pub struct EvalOptions {
+    /// Text file whose perplexity is measured.
+    pub dataset: PathBuf,
+    /// Precisions to evaluate; the weights are quantized to match each.
+    pub precisions: Vec<Precision>,
+    /// Tokens per window; every window starts from an empty context.
+    pub window: usize,
+    /// Evaluate only the leading tokens of the dataset.
+    pub max_tokens: Option<usize>,
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            dataset: PathBuf::new(),
+            precisions: vec![
+                Precision::F32,
+                Precision::Bf16,
+                Precision::Int8,
+                Precision::Int4,
+            ],
+            window: 256,
+            max_tokens: None,
+        }
+    }
+}
+
+/// Perplexity of the model at one precision.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalResult {
+    pub precision: Precision,
+    /// Encoding of the weights, `None` for `f32`.
+    pub quantization: Option<Quantization>,
+    pub perplexity: Perplexity,
+}
+
+/// Perplexities reported by [`eval_model`].
+#[derive(Debug, Clone)]
+pub struct EvalReport {
+    pub model: String,
+    pub dataset: PathBuf,
+    /// Tokens of the dataset evaluated.
+    pub tokens: usize,
+    pub window: usize,
+    /// Encoding of the weights as stored in the model.
+    pub source_quantization: Option<Quantization>,
+    pub results: Vec<EvalResult>,
+}
+
+impl EvalReport {
+    /// Relative change of the perplexity of `result` from the `f32` result,
+    /// when `f32` was evaluated.
+    pub fn delta(&self, result: &EvalResult) -> Option<f64> {
+        let baseline = self
+            .results
+            .iter()
+            .find(|r| r.precision == Precision::F32)?
+            .perplexity
+            .perplexity();
+        Some(result.perplexity.perplexity() / baseline - 1.0)
+    }
+}
+
+/// Measure the perplexity of `model` over the text in `options.dataset` at
+/// every precision of `options`, on the backend selected by `config`.  The
+/// weights are decoded to `f32` once and quantized again for each precision,
+/// so a model stored quantized is evaluated with its quantization error.
+pub fn eval_model(model: &str, config: &AurexConfig, options: &EvalOptions) -> Result<EvalReport> {
+    let text = std::fs::read_to_string(&options.dataset)
+        .map_err(|err| anyhow!("cannot read dataset {}: {err}", options.dataset.display()))?;
+    let mut tokens = ByteTokenizer.encode(&text);
+    if let Some(max) = options.max_tokens {
+        tokens.truncate(max);
+    }
+    if tokens.len() < 2 {
+        bail!(
+            "dataset {} has fewer than 2 tokens",
+            options.dataset.display()
+        );
+    }
+
+    let mut loaded = load_model_or_bundle(model)?;
+    let source_quantization = loaded.config.quantization;
+    let master = loaded.weights_f32()?;
+    let mut results = Vec::with_capacity(options.precisions.len());
+    for &precision in &options.precisions {
+        loaded.set_precision(&master, precision);
+        let mut dispatcher = model_dispatcher(&loaded, config);
+        dispatcher.set_precision(precision);
+        let mut lm = TinyLm::from_model(&loaded, Arc::new(dispatcher))?;
+        let perplexity = evaluation::perplexity(&mut lm, &tokens, options.window);
+        tracing::info!(
+            ?precision,
+            perplexity = perplexity.perplexity(),
+            "evaluated model"
+        );
+        results.push(EvalResult {
+            precision,
+            quantization: loaded.config.quantization,
+            perplexity,
+        });
+    }
+
+    Ok(EvalReport {
+        model: model.to_string(),
+        dataset: options.dataset.clone(),
+        tokens: tokens.len(),
+        window: options.window,
+        source_quantization,
+        results,
+    })
+}
+
+impl fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quant = |q: Option<Quantization>| match q {
+            Some(q) => format!("{q:?}").to_lowercase(),
+            None => "f32".to_string(),
+        };
+        writeln!(f, "Model: {}", self.model)?;
+        writeln!(
+            f,
+            "Dataset: {} ({} tokens, window {})",
+            self.dataset.display(),
+            self.tokens,
+            self.window
+        )?;
+        writeln!(f, "Source weights: {}", quant(self.source_quantization))?;
+        write!(
+            f,
+            "{:<10} {:<8} {:>8} {:>10} {:>12} {:>9}",
+            "precision", "weights", "scored", "nll", "perplexity", "delta"
+        )?;
+        for result in &self.results {
+            let delta = match self.delta(result) {
+                Some(delta) => format!("{:+.2}%", delta * 100.0),
+                None => "-".to_string(),
+            };
+            write!(
+                f,
+                "\n{:<10} {:<8} {:>8} {:>10.4} {:>12.4} {:>9}",
+                format!("{:?}", result.precision).to_lowercase(),
+                quant(result.quantization),
+                result.perplexity.tokens,
+                result.perplexity.mean_nll(),
+                result.perplexity.perplexity(),
+                delta
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare throughput, weight footprint and perplexity of `model` across
+/// the precisions of `bench` on the backend selected by `config`.
+pub fn bench_model(model: &str, config: &AurexConfig, bench: &BenchConfig) -> Result<BenchReport> {
+    let loaded = load_model_or_bundle(model)?;
+    benchmark::run(loaded, config, bench)
+}
+
+/// Run the requests logged at `log` by `aurex serve` again on `model` with
+/// the backend and precision selected by `config`, comparing latency and
+/// outputs with the logged runs.
+pub fn replay_log(
+    model: &str,
+    config: &AurexConfig,
+    log: &Path,
+    pooling: Pooling,
+) -> Result<ReplayReport> {
+    let records = RequestLog::read(log)?;
+    let state = server::ServerState::load(model, config, pooling)?;
+    Ok(request_log::replay(&state, &records))
+}
+
+/// Dispatcher running `loaded` on the backend and precision selected by
+/// `config`.
+pub(crate) fn model_dispatcher(loaded: &LoadedModel, config: &AurexConfig) -> Dispatcher {
+    let dispatcher = config
+        .dispatcher(Workload::Heavy)
+        .with_shapes(loaded.kernel_shapes());
+    tracing::info!(
+        backend = dispatcher.backend().name(),
+        precision = ?dispatcher.precision(),
+        "running model"
+    );
+    dispatcher
+}
+
+/// Load a `.aurexc` bundle or JSON model configuration.
+pub(crate) fn load_model_or_bundle(model: &str) -> Result<LoadedModel> {
+    if bundle::is_bundle(model) {
+        Ok(CompiledBundle::load(model)?.into_model())
+    } else {
+        load_model(model)
+    }
+}
+
+/// Check the tensor ops of the backend called `name` against the CPU
+/// reference, or of every available backend when `name` is `all`.
+pub fn verify_backend(name: &str, config: &VerifyConfig) -> Result<Vec<ConformanceReport>> {
+    if name.eq_ignore_ascii_case("all") {
+        return Ok(verification::verify_all(config));
+    }
+    let report = verification::verify(name, config).map_err(|err| anyhow!(err))?;
+    Ok(vec![report])
+}
+
+/// Metadata reported by [`inspect_model`].
+#[derive(Debug, Clone)]
+pub struct InspectReport {
+    pub config: ModelConfig,
+    /// Manifest of the bundle when inspecting a `.aurexc` file.
+    pub bundle: Option<BundleManifest>,
+    pub tensors: Vec<TensorInfo>,
+    pub caps: DeviceCapabilities,
+    /// Simulated `(gpu, cpu, nvme)` bytes after placing all tensors.
+    pub tiers: (usize, usize, usize),
+    /// Whether all tensors can be placed without dropping data.
+    pub fits: bool,
+    /// Activation buffers of one transformer block at the model's hidden
+    /// size.
+    pub activations: MemoryPlan,
+}
+
+/// Describe a model configuration or bundle: its config, tensors, the
+/// memory tiers the weights would occupy on this device, with the `[memory]`
+/// section of `config` overriding the probed capabilities, and the planned
+/// activation memory of a forward pass.
+pub fn inspect_model(model: &str, config: &AurexConfig) -> Result<InspectReport> {
+    let (loaded, manifest) = if bundle::is_bundle(model) {
+        let compiled = CompiledBundle::load(model)?;
+        let manifest = compiled.manifest.clone();
+        (compiled.into_model(), Some(manifest))
+    } else {
+        (load_model(model)?, None)
+    };
+    let tensors = loaded.tensors();
+    let (_, hidden) = loaded.config.embedding_shape(loaded.weight_count());
+    let activations = MemoryPlan::new(&block_graph(hidden.max(1))?);
+
+    let caps = DeviceCapabilities::probe(&config.memory);
+    let mut mgr = MemoryManager::new(caps).without_metrics();
+    for tensor in &tensors {
+        mgr.allocate(tensor.bytes);
+    }
+    let tiers = mgr.usage();
+    let total: usize = tensors.iter().map(|t| t.bytes).sum();
+    let placed = tiers.0 + tiers.1 + tiers.2;
+    let fits = placed == total && (tiers.2 == 0 || (caps.has_nvme && tiers.2 <= caps.nvme_mem));
+
+    Ok(InspectReport {
+        config: loaded.config,
+        bundle: manifest,
+        tensors,
+        caps,
+        tiers,
+        fits,
+        activations,
+    })
 }
 
-/// Run inference for a compiled model on the selected backend.
-pub fn run_model(model: &str, target: &str) {
-    println!("Running {model} on {target} backend");
-    // TODO: integrate with runtime execution
+impl fmt::Display for InspectReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        writeln!(f, "Model: {}", self.config.name)?;
+        match &self.bundle {
+            Some(m) => writeln!(f, "Source: bundle v{} (target {})", m.version, m.target)?,
+            None => writeln!(f, "Source: config ({})", self.config.weight_path)?,
+        }
+        match (self.config.quantization, self.config.scale) {
+            (Some(q), Some(s)) => writeln!(f, "Quantization: {q:?} (scale {s})")?,
+            (Some(q), None) => writeln!(f, "Quantization: {q:?}")?,
+            (None, _) => writeln!(f, "Quantization: none (f32)")?,
+        }
+        writeln!(f, "Layers: {}", self.config.layers.unwrap_or(1))?;
+        writeln!(f, "Layout: {}", self.config.layout)?;
+        if let Some(m) = &self.bundle {
+            writeln!(f, "Graph: {}", m.graph.join(" -> "))?;
+            writeln!(f, "Kernels:")?;
+            if m.kernels.is_empty() {
+                writeln!(f, "  (none, compiled on demand)")?;
+            }
+            for k in &m.kernels {
+                writeln!(f, "  {:<20} {:?} {} B", k.name, k.kind, k.len)?;
+            }
+        }
+        writeln!(f, "Tensors:")?;
+        for t in &self.tensors {
+            writeln!(
+                f,
+                "  {:<20} {:<16} {:<5} {} B",
+                t.name,
+                format!("{:?}", t.shape),
+                t.dtype,
+                t.bytes
+            )?;
+        }
+        writeln!(f, "Memory footprint:")?;
+        writeln!(f, "  gpu   {} B", self.tiers.0)?;
+        writeln!(f, "  cpu   {} B", self.tiers.1)?;
+        writeln!(f, "  nvme  {} B", self.tiers.2)?;
+        writeln!(
+            f,
+            "Activations per block: {} B planned peak ({} B unplanned)",
+            self.activations.peak_bytes(),
+            self.activations.unplanned_bytes()
+        )?;
+        writeln!(
+            f,
+            "Device: gpu={} ({} B) cpu={} B nvme={}",
+            yes_no(self.caps.has_gpu),
+            self.caps.gpu_mem,
+            self.caps.cpu_mem,
+            yes_no(self.caps.has_nvme)
+        )?;
+        write!(f, "Fits current device: {}", yes_no(self.fits))
+    }
 }
@@ -1,11 +1,25 @@
 use clap::{Parser, Subcommand};
+use std::io::Write;
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(author, version, about = "AUREX command line interface")]
 struct Cli {
-    /// Backend target to use (e.g., cpu, rocm, vulkan)
-    #[arg(long, default_value = "cpu")]
-    target: String,
+    /// Configuration file (defaults to AUREX_CONFIG or ./aurex.toml)
+    #[arg(long)]
+    config: Option<std::path::PathBuf>,
+
+    /// Backend target to use (e.g., cpu, rocm, vulkan); overrides the config
+    #[arg(long)]
+    target: Option<String>,
+
+    /// Log output format (text or json); defaults to AUREX_LOG_FORMAT
+    #[arg(long)]
+    log_format: Option<aurex_runtime::telemetry::LogFormat>,
+
+    /// Expose Prometheus metrics on this address (e.g. 0.0.0.0:9090)
+    #[arg(long)]
+    metrics_addr: Option<String>,
 
     #[command(subcommand)]
     command: Commands,
@@ -13,21 +27,643 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Compile a model for the selected backend
-    Compile { model: String },
-    /// Run inference using a compiled model
-    Run { model: String },
+    /// Compile a model for the selected backend into a .aurexc bundle
+    Compile {
+        model: String,
+        /// Output path of the bundle (defaults to <model>.aurexc)
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+        /// Quantize f32 weights (int8, int4 or bf16)
+        #[arg(long)]
+        quantize: Option<amduda::aurex_lm::model_loader::Quantization>,
+    },
+    /// Show config, tensors and memory footprint of a model or bundle
+    Inspect { model: String },
+    /// Run inference using a compiled bundle or model configuration
+    Run {
+        model: String,
+        /// Prompt to continue
+        #[arg(long, default_value = "")]
+        prompt: String,
+        /// Maximum number of tokens to generate
+        #[arg(long, default_value_t = 128)]
+        max_tokens: usize,
+        /// Sampling temperature (0 for greedy decoding)
+        #[arg(long, default_value_t = 0.8)]
+        temperature: f32,
+        /// Nucleus sampling threshold
+        #[arg(long, default_value_t = 0.95)]
+        top_p: f32,
+        /// Seed for reproducible sampling
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Numeric precision (f32, bf16, int8, int4); overrides the config
+        #[arg(long)]
+        precision: Option<aurex_backend::Precision>,
+        /// Backend to run on; overrides --target
+        #[arg(long)]
+        backend: Option<String>,
+        /// Reproduce identical outputs across runs
+        #[arg(long)]
+        deterministic: bool,
+        /// Stop generating at this string (repeatable)
+        #[arg(long)]
+        stop: Vec<String>,
+        /// GBNF grammar file the output must follow
+        #[arg(long)]
+        grammar: Option<std::path::PathBuf>,
+        /// PNG image to show the model before the prompt
+        #[arg(long)]
+        image: Option<std::path::PathBuf>,
+    },
+    /// Print sentence embeddings of texts as JSON arrays, one per line
+    Embed {
+        model: String,
+        /// Texts to embed
+        #[arg(required = true)]
+        texts: Vec<String>,
+        /// Pooling of the token states (mean or cls)
+        #[arg(long, default_value = "mean")]
+        pooling: amduda::aurex_lm::embeddings::Pooling,
+    },
+    /// Serve a model over HTTP (POST /v1/embeddings, /v1/completions,
+    /// /v1/chat/completions, /v1/sessions, /admin/reload; GET /v1/models,
+    /// /healthz, /readyz)
+    Serve {
+        /// Model serving requests that name none
+        model: String,
+        /// Further model requests can name, loaded on first use (repeatable)
+        #[arg(long = "model", value_name = "NAME=PATH")]
+        models: Vec<String>,
+        /// Seconds after which further models unload without requests
+        #[arg(long)]
+        model_idle_ttl: Option<u64>,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+        /// Pooling of requests that do not choose one (mean or cls)
+        #[arg(long, default_value = "mean")]
+        pooling: amduda::aurex_lm::embeddings::Pooling,
+        /// Accept traffic without warming up the model first
+        #[arg(long)]
+        no_warmup: bool,
+        /// Append every embedding, completion and chat request to this JSON
+        /// lines file for `aurex replay`
+        #[arg(long)]
+        request_log: Option<std::path::PathBuf>,
+        /// Bearer token required by /admin endpoints; without one only
+        /// loopback clients may use them
+        #[arg(long)]
+        admin_token: Option<String>,
+    },
+    /// Run requests logged by `aurex serve --request-log` again and compare
+    /// latency and outputs
+    Replay {
+        model: String,
+        /// Request log written by `aurex serve`
+        log: std::path::PathBuf,
+        /// Pooling of requests that do not choose one (mean or cls)
+        #[arg(long, default_value = "mean")]
+        pooling: amduda::aurex_lm::embeddings::Pooling,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check a backend's tensor ops against the CPU reference on random shapes
+    VerifyBackend {
+        /// Backend to verify (cpu, rocm, vulkan, opencl, sycl, riscv, jit) or
+        /// `all` for every available backend
+        name: String,
+        /// Random cases per op
+        #[arg(long, default_value_t = 32)]
+        cases: usize,
+        /// Largest generated dimension
+        #[arg(long, default_value_t = 64)]
+        max_dim: usize,
+        /// Seed of the generated shapes and values
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Absolute tolerance per element
+        #[arg(long)]
+        atol: Option<f32>,
+        /// Tolerance relative to the reference value
+        #[arg(long)]
+        rtol: Option<f32>,
+    },
+    /// Measure the perplexity of a model over a text file at each precision
+    Eval {
+        model: String,
+        /// Text file to evaluate on
+        #[arg(long)]
+        dataset: std::path::PathBuf,
+        /// Precision to evaluate (f32, bf16, int8, int4; repeatable);
+        /// defaults to all of them
+        #[arg(long)]
+        precision: Vec<aurex_backend::Precision>,
+        /// Tokens per evaluation window
+        #[arg(long, default_value_t = 256)]
+        window: usize,
+        /// Evaluate only the first tokens of the dataset
+        #[arg(long)]
+        max_tokens: Option<usize>,
+    },
+    /// Compare tokens/sec, weight size and perplexity across precisions
+    Bench {
+        model: String,
+        /// Text file the perplexity is measured on (defaults to a built-in
+        /// sample)
+        #[arg(long)]
+        dataset: Option<std::path::PathBuf>,
+        /// Precision to benchmark (f32, bf16, int8, int4; repeatable);
+        /// defaults to all of them
+        #[arg(long)]
+        precision: Vec<aurex_backend::Precision>,
+        /// Tokens generated per timed run
+        #[arg(long, default_value_t = 64)]
+        tokens: usize,
+        /// Timed runs per precision
+        #[arg(long, default_value_t = 3)]
+        repeats: usize,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Manage the on-disk cache of compiled kernels
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Answer questions from documents with retrieval-augmented generation
+    Rag {
+        #[command(subcommand)]
+        action: RagAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Show the location, number and size of cached kernels
+    Stats,
+    /// Remove all cached kernels
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum RagAction {
+    /// Chunk and embed text files into a .aurexri index
+    Ingest {
+        model: String,
+        /// Index to create or add the files to
+        #[arg(long)]
+        index: std::path::PathBuf,
+        /// Text files to ingest; files already in the index are replaced
+        #[arg(required = true)]
+        files: Vec<std::path::PathBuf>,
+        /// Longest chunk in bytes
+        #[arg(long, default_value_t = 512)]
+        chunk_size: usize,
+        /// Bytes consecutive chunks share
+        #[arg(long, default_value_t = 64)]
+        chunk_overlap: usize,
+        /// Pooling of the token states for a new index (mean or cls)
+        #[arg(long, default_value = "mean")]
+        pooling: amduda::aurex_lm::embeddings::Pooling,
+    },
+    /// Answer a question from the chunks of an index closest to it
+    Query {
+        model: String,
+        #[arg(long)]
+        index: std::path::PathBuf,
+        query: String,
+        /// Chunks to put into the prompt
+        #[arg(long, default_value_t = 4)]
+        top_k: usize,
+        #[arg(long, default_value_t = 128)]
+        max_tokens: usize,
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Print the retrieved chunks without generating an answer
+        #[arg(long)]
+        retrieve_only: bool,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
+    aurex_runtime::telemetry::init(
+        cli.log_format
+            .unwrap_or_else(aurex_runtime::telemetry::LogFormat::from_env),
+    );
+    if let Some(addr) = &cli.metrics_addr {
+        let metrics = aurex_utils::metrics::Metrics::global();
+        if let Err(err) = aurex_utils::metrics::serve(metrics, addr.as_str()) {
+            eprintln!("failed to start metrics exporter on {addr}: {err}");
+        }
+    }
+
+    let mut config = match aurex_runtime::AurexConfig::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error: {err}");
+            std::process::exit(1);
+        }
+    };
 
     match cli.command {
-        Commands::Compile { model } => {
-            aurex_cli::compile_model(&model, &cli.target);
+        Commands::Compile {
+            model,
+            output,
+            quantize,
+        } => {
+            let target = cli.target.unwrap_or_else(|| {
+                config
+                    .backend
+                    .preferred
+                    .map_or("cpu", aurex_backend::Backend::name)
+                    .to_string()
+            });
+            let options = aurex_cli::CompileOptions { output, quantize };
+            match aurex_cli::compile_model(&model, &target, &options) {
+                Ok(path) => println!("Compiled {model} for {target} backend: {}", path.display()),
+                Err(err) => {
+                    eprintln!("error: {err:#}");
+                    std::process::exit(1);
+                }
+            }
         }
-        Commands::Run { model } => {
-            aurex_cli::run_model(&model, &cli.target);
+        Commands::Inspect { model } => match aurex_cli::inspect_model(&model, &config) {
+            Ok(report) => println!("{report}"),
+            Err(err) => {
+                eprintln!("error: {err:#}");
+                std::process::exit(1);
+            }
+        },
+        Commands::Run {
+            model,
+            prompt,
+            max_tokens,
+            temperature,
+            top_p,
+            seed,
+            precision,
+            backend,
+            deterministic,
+            stop,
+            grammar,
+            image,
+        } => {
+            let grammar = match grammar.map(std::fs::read_to_string).transpose() {
+                Ok(grammar) => grammar,
+                Err(err) => {
+                    eprintln!("error: cannot read grammar: {err}");
+                    std::process::exit(1);
+                }
+            };
+            let options = aurex_cli::RunOptions {
+                prompt,
+                max_tokens,
+                temperature,
+                top_p,
+                seed,
+                stop,
+                grammar,
+                image,
+            };
+            select_backend(&mut config, backend.or(cli.target));
+            if precision.is_some() {
+                config.backend.precision = precision;
+            }
+            if deterministic {
+                config.backend.deterministic = Some(true);
+            }
+            let mut stdout = std::io::stdout();
+            let result = aurex_cli::run_model(&model, &config, &options, |text| {
+                let _ = stdout.write_all(text.as_bytes());
+                let _ = stdout.flush();
+            });
+            println!();
+            if let Err(err) = result {
+                eprintln!("error: {err:#}");
+                std::process::exit(1);
+            }
+        }
+        Commands::Embed {
+            model,
+            texts,
+            pooling,
+        } => {
+            select_backend(&mut config, cli.target);
+            match aurex_cli::embed_texts(&model, &config, &texts, pooling) {
+                Ok(embeddings) => {
+                    for embedding in embeddings {
+                        println!("{}", serde_json::Value::from(embedding));
+                    }
+                }
+                Err(err) => {
+                    eprintln!("error: {err:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Serve {
+            model,
+            models,
+            model_idle_ttl,
+            addr,
+            pooling,
+            no_warmup,
+            request_log,
+            admin_token,
+        } => {
+            select_backend(&mut config, cli.target);
+            // Reloaded models are warmed up like the first one.
+            if no_warmup {
+                config.scheduler.warmup = Some(false);
+            }
+            let warmup = config.scheduler.warmup != Some(false);
+            let served = aurex_cli::server::ServerState::load(&model, &config, pooling)
+                .and_then(|mut state| {
+                    for spec in &models {
+                        let (name, path) = spec.split_once('=').ok_or_else(|| {
+                            anyhow::anyhow!("--model takes NAME=PATH, got {spec}")
+                        })?;
+                        state = state.with_model(name, path);
+                    }
+                    if let Some(secs) = model_idle_ttl {
+                        state = state.with_idle_ttl(std::time::Duration::from_secs(secs));
+                    }
+                    if let Some(path) = request_log {
+                        let log = aurex_cli::request_log::RequestLog::create(path)?;
+                        state = state.with_request_log(log);
+                    }
+                    if let Some(token) = admin_token {
+                        state = state.with_admin_token(token);
+                    }
+                    let state = Arc::new(state);
+                    let (local, handle) = aurex_cli::server::serve(state.clone(), addr.as_str())?;
+                    Ok((state, local, handle))
+                });
+            match served {
+                Ok((state, local, handle)) => {
+                    println!("Serving {model} on http://{local}");
+                    // /readyz fails until the model is warm.
+                    if warmup {
+                        let report =
+                            aurex_runtime::Runtime::from_config(&config).warmup(&*state.model());
+                        println!(
+                            "Warmed up {} ops in {} ms",
+                            report.plan.ops.len(),
+                            report.elapsed.as_millis()
+                        );
+                    }
+                    state.set_ready(true);
+                    let _ = handle.join();
+                }
+                Err(err) => {
+                    eprintln!("error: {err:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Replay {
+            model,
+            log,
+            pooling,
+            json,
+        } => {
+            select_backend(&mut config, cli.target);
+            match aurex_cli::replay_log(&model, &config, &log, pooling) {
+                Ok(report) => {
+                    if json {
+                        match serde_json::to_string_pretty(&report) {
+                            Ok(json) => println!("{json}"),
+                            Err(err) => {
+                                eprintln!("error: {err}");
+                                std::process::exit(1);
+                            }
+                        }
+                    } else {
+                        println!("{report}");
+                    }
+                    if report.mismatches() > 0 {
+                        std::process::exit(1);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("error: {err:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::VerifyBackend {
+            name,
+            cases,
+            max_dim,
+            seed,
+            atol,
+            rtol,
+        } => {
+            use amduda::hal_backends::verification::{Tolerance, VerifyConfig};
+            let mut verify = VerifyConfig::new().with_cases(cases).with_max_dim(max_dim);
+            if let Some(seed) = seed {
+                verify = verify.with_seed(seed);
+            }
+            let default = Tolerance::default();
+            verify = verify.with_tolerance(Tolerance::new(
+                atol.unwrap_or(default.abs),
+                rtol.unwrap_or(default.rel),
+            ));
+            match aurex_cli::verify_backend(&name, &verify) {
+                Ok(reports) => {
+                    for report in &reports {
+                        print!("{report}");
+                    }
+                    if !reports.iter().all(|r| r.passed()) {
+                        std::process::exit(1);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("error: {err:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Eval {
+            model,
+            dataset,
+            precision,
+            window,
+            max_tokens,
+        } => {
+            select_backend(&mut config, cli.target);
+            let mut options = aurex_cli::EvalOptions {
+                dataset,
+                window,
+                max_tokens,
+                ..aurex_cli::EvalOptions::default()
+            };
+            if !precision.is_empty() {
+                options.precisions = precision;
+            }
+            match aurex_cli::eval_model(&model, &config, &options) {
+                Ok(report) => println!("{report}"),
+                Err(err) => {
+                    eprintln!("error: {err:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Bench {
+            model,
+            dataset,
+            precision,
+            tokens,
+            repeats,
+            json,
+        } => {
+            select_backend(&mut config, cli.target);
+            let mut bench = amduda::aurex_lm::benchmark::BenchConfig {
+                tokens,
+                repeats,
+                ..Default::default()
+            };
+            if !precision.is_empty() {
+                bench.precisions = precision;
+            }
+            if let Some(dataset) = dataset {
+                match std::fs::read_to_string(&dataset) {
+                    Ok(text) => bench.text = text,
+                    Err(err) => {
+                        eprintln!("error: cannot read dataset {}: {err}", dataset.display());
+                        std::process::exit(1);
+                    }
+                }
+            }
+            match aurex_cli::bench_model(&model, &config, &bench) {
+                Ok(report) if json => match serde_json::to_string_pretty(&report) {
+                    Ok(json) => println!("{json}"),
+                    Err(err) => {
+                        eprintln!("error: {err}");
+                        std::process::exit(1);
+                    }
+                },
+                Ok(report) => println!("{report}"),
+                Err(err) => {
+                    eprintln!("error: {err:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Cache { action } => {
+            let cache = aurex_utils::kernel_cache::KernelCache::global();
+            let Some(dir) = cache.dir() else {
+                println!("Kernel cache disabled");
+                return;
+            };
+            match action {
+                CacheAction::Stats => {
+                    let stats = cache.stats();
+                    println!(
+                        "{}: {} kernels, {} bytes",
+                        dir.display(),
+                        stats.entries,
+                        stats.bytes
+                    );
+                }
+                CacheAction::Clear => match cache.clear() {
+                    Ok(removed) => println!("Removed {removed} kernels from {}", dir.display()),
+                    Err(err) => {
+                        eprintln!("error: {err}");
+                        std::process::exit(1);
+                    }
+                },
+            }
+        }
+        Commands::Rag { action } => {
+            select_backend(&mut config, cli.target);
+            match action {
+                RagAction::Ingest {
+                    model,
+                    index,
+                    files,
+                    chunk_size,
+                    chunk_overlap,
+                    pooling,
+                } => {
+                    let options = aurex_cli::IngestOptions {
+                        chunk: amduda::aurex_lm::retrieval::ChunkConfig {
+                            size: chunk_size,
+                            overlap: chunk_overlap,
+                        },
+                        pooling,
+                    };
+                    match aurex_cli::rag_ingest(&model, &config, &index, &files, &options) {
+                        Ok(report) => println!("{report}"),
+                        Err(err) => {
+                            eprintln!("error: {err:#}");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                RagAction::Query {
+                    model,
+                    index,
+                    query,
+                    top_k,
+                    max_tokens,
+                    seed,
+                    retrieve_only,
+                } => {
+                    let options = aurex_cli::RagQueryOptions {
+                        top_k,
+                        max_tokens,
+                        seed,
+                        retrieve_only,
+                        ..aurex_cli::RagQueryOptions::default()
+                    };
+                    let mut stdout = std::io::stdout();
+                    let result =
+                        aurex_cli::rag_query(&model, &config, &index, &query, &options, |text| {
+                            let _ = stdout.write_all(text.as_bytes());
+                            let _ = stdout.flush();
+                        });
+                    match result {
+                        Ok(answer) => {
+                            if answer.output.is_some() {
+                                println!();
+                            }
+                            println!("Sources:");
+                            for hit in &answer.hits {
+                                println!(
+                                    "  [{:.3}] {}@{}: {}",
+                                    hit.score,
+                                    hit.document,
+                                    hit.start,
+                                    hit.text.replace('\n', " ")
+                                );
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("error: {err:#}");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Prefer the backend named `target`, exiting on unknown names.
+fn select_backend(config: &mut aurex_runtime::AurexConfig, target: Option<String>) {
+    let Some(target) = target else {
+        return;
+    };
+    match aurex_backend::Backend::from_name(&target) {
+        Some(b) => config.backend.preferred = Some(b),
+        None => {
+            eprintln!("error: unknown backend target '{target}'");
+            std::process::exit(1);
         }
     }
 }
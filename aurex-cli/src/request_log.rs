@@ -0,0 +1,272 @@
+//! Request logging and offline replay for `aurex serve`.
+//!
+//! With a [`RequestLog`] attached to the [`ServerState`], every embedding,
+//! completion and chat request is appended to a JSON lines file as a
+//! [`RequestRecord`]: the request body, a hash of its prompt, the
+//! generation options it asked for, the backend that served it, its
+//! latency and token timings, and a hash of the output.
+//!
+//! `aurex replay` runs the logged requests again against a new build with
+//! [`replay`] and reports how latency and outputs changed, so a kernel change
+//! can be checked against real traffic before it ships.  Chat requests
+//! continuing a session are skipped since the session is gone.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use amduda::aurex_lm::kv_snapshot::{fnv1a, FNV_OFFSET};
+use anyhow::{Context, Result};
+use aurex_runtime::GenerationOptions;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::server::{self, ServerState};
+
+/// Token timings of a generation request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenTimings {
+    /// Milliseconds from the start of the request to its first token.
+    pub first_token_ms: Option<f64>,
+    /// Tokens generated.
+    pub tokens: usize,
+}
+
+/// A served request as logged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestRecord {
+    /// Milliseconds since the Unix epoch when the request arrived.
+    pub timestamp_ms: u64,
+    pub path: String,
+    /// Hash of the prompt, messages or input of the request.
+    pub prompt_hash: String,
+    /// Options of completion and chat requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<GenerationOptions>,
+    pub backend: String,
+    pub status: String,
+    pub latency_ms: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<TokenTimings>,
+    /// Hash of the generated text or embeddings.
+    pub output_hash: String,
+    /// Request body, kept for replay.
+    pub body: Value,
+}
+
+impl RequestRecord {
+    /// Record of a request to `path` with `body` answered with `status` and
+    /// `response` after `latency`.
+    pub(crate) fn new(
+        state: &ServerState,
+        path: &str,
+        body: &[u8],
+        status: &str,
+        response: &Value,
+        latency: Duration,
+        timings: Option<TokenTimings>,
+    ) -> Self {
+        let body: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+        Self {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            path: path.to_string(),
+            prompt_hash: prompt_hash(&body),
+            options: server::generation_options(path, &body),
+            backend: state.model().dispatcher.backend().name().to_string(),
+            status: status.to_string(),
+            latency_ms: latency.as_secs_f64() * 1e3,
+            timings,
+            output_hash: output_hash(response),
+            body,
+        }
+    }
+
+    /// Whether replaying the request is meaningful.
+    pub fn replayable(&self) -> bool {
+        self.body.get("session").is_none_or(Value::is_null)
+    }
+}
+
+fn hash(value: Option<&Value>) -> String {
+    let text = value.map(Value::to_string).unwrap_or_default();
+    format!("{:016x}", fnv1a(FNV_OFFSET, text.as_bytes()))
+}
+
+fn prompt_hash(body: &Value) -> String {
+    hash(
+        ["prompt", "messages", "input"]
+            .iter()
+            .find_map(|k| body.get(k)),
+    )
+}
+
+fn output_hash(response: &Value) -> String {
+    let choices = response.get("choices").map(|choices| {
+        // Chat replies and completions differ in where the text is.
+        let texts: Vec<Value> = choices
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|c| c.get("text").or_else(|| c.pointer("/message/content")))
+            .cloned()
+            .collect();
+        Value::from(texts)
+    });
+    hash(choices.as_ref().or_else(|| response.get("data")))
+}
+
+/// Append-only log of served requests, one JSON record per line.
+pub struct RequestLog {
+    file: Mutex<File>,
+}
+
+impl RequestLog {
+    /// Append to the log at `path`, creating it if needed.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("cannot open request log {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append `record`.  Failures are logged and otherwise ignored so that
+    /// serving is never held up by the log.
+    pub fn record(&self, record: &RequestRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(err) => return tracing::warn!(%err, "cannot serialize request record"),
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|p| p.into_inner());
+        if let Err(err) = file.write_all(&line) {
+            tracing::warn!(%err, "cannot write request log");
+        }
+    }
+
+    /// Read the records logged at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<RequestRecord>> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("cannot open request log {}", path.display()))?;
+        let mut records = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line)
+                .with_context(|| format!("{}:{}: invalid record", path.display(), i + 1))?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+/// A logged request run again.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReplayResult {
+    pub path: String,
+    pub prompt_hash: String,
+    pub logged_ms: f64,
+    pub replayed_ms: f64,
+    /// Whether the status and output match the logged ones.
+    pub matched: bool,
+}
+
+/// Outcome of [`replay`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReplayReport {
+    pub backend: String,
+    pub results: Vec<ReplayResult>,
+    /// Requests that could not be replayed.
+    pub skipped: usize,
+}
+
+impl ReplayReport {
+    /// Replayed requests whose status or output differ.
+    pub fn mismatches(&self) -> usize {
+        self.results.iter().filter(|r| !r.matched).count()
+    }
+
+    /// Total logged and replayed latency in milliseconds.
+    pub fn latency_ms(&self) -> (f64, f64) {
+        self.results
+            .iter()
+            .fold((0.0, 0.0), |(logged, replayed), r| {
+                (logged + r.logged_ms, replayed + r.replayed_ms)
+            })
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<24} {:<16} {:>10} {:>10}  output",
+            "path", "prompt", "logged ms", "replay ms"
+        )?;
+        for r in &self.results {
+            writeln!(
+                f,
+                "{:<24} {:<16} {:>10.2} {:>10.2}  {}",
+                r.path,
+                r.prompt_hash,
+                r.logged_ms,
+                r.replayed_ms,
+                if r.matched { "same" } else { "DIFFERS" }
+            )?;
+        }
+        let (logged, replayed) = self.latency_ms();
+        write!(
+            f,
+            "Replayed {} requests on {} ({} skipped): {} differ, latency {:.2} ms -> {:.2} ms",
+            self.results.len(),
+            self.backend,
+            self.skipped,
+            self.mismatches(),
+            logged,
+            replayed
+        )?;
+        if logged > 0.0 && replayed > 0.0 {
+            write!(f, " ({:.2}x)", logged / replayed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `records` again on `state` and compare them with the logged runs.
+pub fn replay(state: &ServerState, records: &[RequestRecord]) -> ReplayReport {
+    let mut report = ReplayReport {
+        backend: state.model().dispatcher.backend().name().to_string(),
+        ..ReplayReport::default()
+    };
+    for record in records {
+        if !record.replayable() {
+            report.skipped += 1;
+            continue;
+        }
+        let body = record.body.to_string();
+        let started = Instant::now();
+        let response = server::route(state, None, "POST", &record.path, body.as_bytes());
+        let replayed = started.elapsed();
+        report.results.push(ReplayResult {
+            path: record.path.clone(),
+            prompt_hash: record.prompt_hash.clone(),
+            logged_ms: record.latency_ms,
+            replayed_ms: replayed.as_secs_f64() * 1e3,
+            matched: response.status == record.status
+                && output_hash(&response.body) == record.output_hash,
+        });
+    }
+    report
+}
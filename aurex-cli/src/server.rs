@@ -0,0 +1,1499 @@
+//! HTTP server behind `aurex serve`.
+//!
+//! The server answers one request per connection, each connection on a
+//! thread of its own, and turns connections beyond [`MAX_CONNECTIONS`] away.
+//! Clients get [`IO_TIMEOUT`] to send a request and to read the response,
+//! and request lines and headers beyond 8 KiB get `431 Request Header
+//! Fields Too Large`.
+//! `POST /v1/embeddings` accepts the OpenAI request shape (`input` as a
+//! string or a list of strings, optional `model`) plus an optional
+//! `pooling` of `mean` or `cls`, and returns one embedding per input.
+//!
+//! `POST /v1/completions` generates a continuation of `prompt` with the
+//! usual `max_tokens`, `temperature`, `top_p`, `seed` and `stop` fields.
+//! `response_format` constrains the text to JSON: `{"type": "json_object"}`
+//! for any object, `{"type": "json_schema", "json_schema": {"schema": ...}}`
+//! for documents matching a [`JsonSchema`].
+//!
+//! Multi-turn conversations live in [`Session`]s.  `POST /v1/sessions`
+//! starts one with an optional `system_prompt` and a `max_tokens` budget,
+//! `POST /v1/sessions/{id}/fork` copies one and `DELETE /v1/sessions/{id}`
+//! ends one; sessions idle for [`SESSION_TTL`] expire.  A session belongs to
+//! the API key that started it and is not found with other keys.  `POST
+//! /v1/chat/completions` appends `messages` to the conversation of
+//! `session` and generates the assistant's reply, reusing the session's KV
+//! cache for the earlier turns.  Without `session` the messages form a
+//! one-off conversation.  With a `context_window` in `[sessions]`, a
+//! [`ContextCompressor`] has the model summarize the older turns of a
+//! conversation whose next turn would come close to filling the window.
+//!
+//! `aurex serve` warms the model up with
+//! [`Runtime::warmup`](aurex_runtime::Runtime::warmup) once it listens;
+//! [`ServedModel`] runs an embedding as its representative input.  `GET
+//! /healthz` answers as soon as the server listens, `GET /readyz` only once
+//! the warm-up is done.
+//!
+//! `POST /admin/reload` loads the model again, or the one at `model` in the
+//! request body, with a dispatcher and buffers of its own while the current
+//! model keeps serving, switches new requests over and then waits up to
+//! [`DRAIN_TIMEOUT`] for requests still running on the previous model
+//! before releasing it.  The reload runs in the background; `GET
+//! /admin/reload` reports its progress.  Admin endpoints require the
+//! server's admin token as a bearer token, or a loopback client when no
+//! token is set.  Sessions drop KV caches computed by a previous model.
+//!
+//! A server can hold several models, each with a dispatcher of its own.
+//! Requests pick one with their `model` field and fall back to the default
+//! model without one; `GET /v1/models` lists them.  Models added with
+//! [`ServerState::with_model`] load on their first request.  Their weights
+//! are accounted for in a [`MemoryManager`], and a model that would not fit
+//! into GPU and CPU memory next to the loaded ones unloads the least
+//! recently used idle models first.  Models other than the default also
+//! unload after [`MODEL_IDLE_TTL`] without requests.
+//!
+//! With `--request-log` every embedding, completion and chat request is
+//! appended to a [`RequestLog`] that `aurex replay` can run again later.
+//!
+//! With `[response_cache]` limits set, completion and chat requests without
+//! a session repeating an earlier request's model, prompt and options are
+//! answered from a [`ResponseCache`] without passing admission or running
+//! the model.  Such answers still charge the tokens their `usage` reports,
+//! but no GPU time.
+//!
+//! When the `[auth]` section configures API keys, `/v1` requests must
+//! present one as a bearer token and get `401 Unauthorized` otherwise.  Keys
+//! making requests faster than their rate limit get `429 Too Many Requests`
+//! with a `Retry-After` header.  Health and admin endpoints need no key.
+//!
+//! The prompt tokens, generated tokens and GPU-seconds of every embedding,
+//! completion and chat request are charged to its API key and session in a
+//! [`UsageTracker`] and exported to the metrics.  `GET /admin/usage` reports
+//! them; keys that used up their `token_quota` get `429 Too Many Requests`
+//! until the server restarts.
+//!
+//! Embedding, completion and chat requests pass the [`AdmissionController`]
+//! configured by `[admission]` first.  Up to `max_in_flight` of them run at
+//! once and `max_queued` more wait for a slot on their connection's thread.
+//! Requests needing more tokens than `max_request_tokens` (the bytes of their
+//! input plus `max_tokens`) get `413 Payload Too Large`; while the server is
+//! overloaded requests get `503 Service Unavailable` with a `Retry-After`
+//! header and the reason.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use amduda::amduda_core::memory_tiering::{AllocationId, DeviceCapabilities, MemoryManager};
+use amduda::aurex_lm::context_compression::{Compression, CompressionPolicy, ContextCompressor};
+use amduda::aurex_lm::embeddings::{EmbeddingModel, Pooling};
+use amduda::aurex_lm::generation::{
+    FinishReason, GenerationConfig, GenerationEngine, GenerationOutput, KvCache, StreamEvent,
+};
+use amduda::aurex_lm::json_schema::JsonSchema;
+use amduda::aurex_lm::tiny_lm::TinyLm;
+use anyhow::Context;
+use aurex_backend::Dispatcher;
+use aurex_runtime::config::{ApiKeyConfig, AuthConfig};
+use aurex_runtime::{
+    AdmissionController, AdmissionError, AdmissionPermit, AurexConfig, AuthError, Authenticator,
+    CacheKey, GenerationOptions, Message, QuotaExceeded, ResponseCache, Role, Runtime,
+    SamplerOptions, Session, SessionError, SessionId, SessionStore, Usage, UsageTracker, Warmup,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::request_log::{RequestLog, RequestRecord, TokenTimings};
+
+/// Largest accepted request body.
+const MAX_BODY: usize = 16 << 20;
+
+/// Largest accepted request line and headers together.
+const MAX_HEAD: usize = 8 << 10;
+
+/// Connections served at once; further ones get `503 Service Unavailable`.
+pub const MAX_CONNECTIONS: usize = 256;
+
+/// Longest a read from or write to a client may block.
+pub const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Idle time after which sessions expire.
+pub const SESSION_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Longest a reload waits for requests still running on the previous model.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time after which models other than the default unload when no request
+/// used them.
+pub const MODEL_IDLE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A loaded model with a dispatcher of its own.
+pub struct ServedModel {
+    /// Name reported in responses.
+    pub name: String,
+    /// Bundle or model configuration the model was loaded from.
+    pub source: String,
+    /// Reloads before this model was loaded.
+    pub generation: u64,
+    /// Dispatcher both models run their ops through.
+    pub dispatcher: Arc<Dispatcher>,
+    /// Bytes of the model's weights.
+    pub bytes: usize,
+    pub embeddings: EmbeddingModel,
+    pub generator: Mutex<GenerationEngine<TinyLm>>,
+}
+
+impl ServedModel {
+    /// Load the model at `model`, a `.aurexc` bundle or JSON model
+    /// configuration, on the backend and precision selected by `config`.
+    pub fn load(model: &str, config: &AurexConfig, generation: u64) -> anyhow::Result<Self> {
+        let loaded = crate::load_model_or_bundle(model)?;
+        let ops = Arc::new(crate::model_dispatcher(&loaded, config));
+        let embeddings = EmbeddingModel::from_model(&loaded, ops.clone())?;
+        let generator = GenerationEngine::new(TinyLm::from_model(&loaded, ops.clone())?);
+        let bytes = loaded.tensors().iter().map(|t| t.bytes).sum();
+        Ok(Self {
+            name: loaded.config.name,
+            source: model.to_string(),
+            generation,
+            dispatcher: ops,
+            bytes,
+            embeddings,
+            generator: Mutex::new(generator),
+        })
+    }
+}
+
+impl Warmup for ServedModel {
+    fn dispatcher(&self) -> &Dispatcher {
+        &self.dispatcher
+    }
+
+    fn run_representative(&self) {
+        self.embeddings.embed_batch(&["warmup"], Pooling::Mean);
+    }
+}
+
+/// KV cache of a session's last turn.
+#[derive(Clone)]
+pub struct SessionKv {
+    /// [`ServedModel::name`] and [`ServedModel::generation`] of the model
+    /// that computed the cache.
+    pub model: String,
+    pub generation: u64,
+    pub cache: KvCache<TinyLm>,
+}
+
+/// Progress of model reloads.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadStatus {
+    /// Whether a reload is running.
+    pub reloading: bool,
+    /// Reloads that completed.
+    pub reloads: u64,
+    /// Error of the last reload if it failed.
+    pub last_error: Option<String>,
+}
+
+/// Outcome of [`ServerState::reload`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReloadReport {
+    pub model: String,
+    pub generation: u64,
+    /// Time spent loading and warming up the new model.
+    pub load_time: Duration,
+    /// Time spent waiting for requests on the previous model.
+    pub drain_time: Duration,
+    /// Whether those requests finished within [`DRAIN_TIMEOUT`].
+    pub drained: bool,
+}
+
+/// A model requests can be routed to, loaded on first use.
+struct ModelSlot {
+    source: String,
+    loaded: Option<Arc<ServedModel>>,
+    /// Weights of the loaded model in [`ModelPool::memory`].
+    allocation: Option<AllocationId>,
+    last_used: Instant,
+    /// Held while the model loads so that concurrent requests load it once.
+    loading: Arc<Mutex<()>>,
+}
+
+impl ModelSlot {
+    fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            loaded: None,
+            allocation: None,
+            last_used: Instant::now(),
+            loading: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Whether the model is loaded and no request runs on it.
+    fn idle(&self) -> bool {
+        self.loaded
+            .as_ref()
+            .is_some_and(|model| Arc::strong_count(model) == 1)
+    }
+}
+
+/// Models of a server by name and the memory their weights take.
+struct ModelPool {
+    /// Model of requests that name none; it is never unloaded.
+    default: String,
+    slots: BTreeMap<String, ModelSlot>,
+    memory: MemoryManager,
+    /// Bytes of the GPU and CPU tiers; weights beyond them spill to NVMe.
+    resident: usize,
+    idle_ttl: Duration,
+}
+
+impl ModelPool {
+    fn new(model: Arc<ServedModel>, config: &AurexConfig) -> Self {
+        let caps = DeviceCapabilities::probe(&config.memory);
+        let gpu = if caps.has_gpu { caps.gpu_mem } else { 0 };
+        let mut pool = Self {
+            default: model.name.clone(),
+            slots: BTreeMap::new(),
+            memory: MemoryManager::new(caps),
+            resident: gpu.saturating_add(caps.cpu_mem),
+            idle_ttl: MODEL_IDLE_TTL,
+        };
+        let default = pool.default.clone();
+        pool.insert(&default, model);
+        pool
+    }
+
+    fn default_model(&self) -> Arc<ServedModel> {
+        self.slots[&self.default]
+            .loaded
+            .clone()
+            .expect("the default model stays loaded")
+    }
+
+    /// Make `model` the loaded model of `name`, unloading idle models until
+    /// its weights fit.
+    fn insert(&mut self, name: &str, model: Arc<ServedModel>) {
+        self.make_room(model.bytes, name);
+        let allocation = self.memory.allocate(model.bytes);
+        let slot = self
+            .slots
+            .entry(name.to_string())
+            .or_insert_with(|| ModelSlot::new(model.source.clone()));
+        if let Some(previous) = slot.allocation.replace(allocation) {
+            self.memory.free(previous);
+        }
+        slot.source = model.source.clone();
+        slot.loaded = Some(model);
+        slot.last_used = Instant::now();
+    }
+
+    fn resident_free(&self) -> usize {
+        let (gpu, cpu, _) = self.memory.usage();
+        self.resident.saturating_sub(gpu + cpu)
+    }
+
+    fn make_room(&mut self, bytes: usize, keep: &str) {
+        while self.resident_free() < bytes {
+            let victim = self
+                .slots
+                .iter()
+                .filter(|(name, slot)| **name != self.default && *name != keep && slot.idle())
+                .min_by_key(|(_, slot)| slot.last_used)
+                .map(|(name, _)| name.clone());
+            let Some(victim) = victim else {
+                tracing::warn!(model = keep, bytes, "model weights exceed free memory");
+                return;
+            };
+            self.unload(&victim, "memory");
+        }
+    }
+
+    fn unload(&mut self, name: &str, reason: &str) {
+        let Some(slot) = self.slots.get_mut(name) else {
+            return;
+        };
+        if let Some(allocation) = slot.allocation.take() {
+            self.memory.free(allocation);
+        }
+        if slot.loaded.take().is_some() {
+            tracing::info!(model = name, reason, "unloaded model");
+        }
+    }
+
+    fn unload_idle(&mut self) {
+        let expired: Vec<String> = self
+            .slots
+            .iter()
+            .filter(|(name, slot)| {
+                **name != self.default && slot.idle() && slot.last_used.elapsed() >= self.idle_ttl
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in expired {
+            self.unload(&name, "idle");
+        }
+    }
+}
+
+/// A model [`ServerState`] routes requests to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelStatus {
+    pub name: String,
+    /// Whether requests without `model` go to it.
+    pub default: bool,
+    /// Bytes of its weights while loaded.
+    pub loaded: Option<usize>,
+}
+
+/// Models served by [`serve`].
+pub struct ServerState {
+    models: Mutex<ModelPool>,
+    /// Backend, precision and warm-up settings of reloaded models.
+    config: AurexConfig,
+    /// Pooling of requests that do not choose one.
+    pub pooling: Pooling,
+    /// Conversations with the KV cache of their last turn.
+    pub sessions: SessionStore<SessionKv>,
+    /// Summarizer of conversations outgrowing the context window.
+    pub compressor: Option<ContextCompressor>,
+    /// Gate of embedding, completion and chat requests.
+    pub admission: AdmissionController,
+    /// API keys and rate limits of `/v1` requests.
+    pub auth: Authenticator,
+    /// Outputs of completion and one-off chat requests.
+    pub responses: ResponseCache<GenerationOutput>,
+    /// Tokens and GPU time used per API key and session.
+    pub usage: UsageTracker,
+    /// Log of served requests, see [`request_log`](crate::request_log).
+    pub request_log: Option<RequestLog>,
+    /// Bearer token required by the admin endpoints, checked like API keys
+    /// so that the comparison takes the same time however close a guess is.
+    admin_token: Option<Authenticator>,
+    ready: AtomicBool,
+    reload: Mutex<ReloadStatus>,
+}
+
+impl ServerState {
+    /// Load the model at `model`, a `.aurexc` bundle or JSON model
+    /// configuration, on the backend and precision selected by `config`.
+    /// The state is not ready until [`set_ready`](Self::set_ready).
+    pub fn load(model: &str, config: &AurexConfig, pooling: Pooling) -> anyhow::Result<Self> {
+        let model = Arc::new(ServedModel::load(model, config, 0)?);
+        Ok(Self {
+            models: Mutex::new(ModelPool::new(model, config)),
+            config: config.clone(),
+            pooling,
+            sessions: SessionStore::new().with_ttl(SESSION_TTL),
+            compressor: CompressionPolicy::from_config(&config.sessions)
+                .map(ContextCompressor::new),
+            admission: AdmissionController::from_config(config),
+            auth: Authenticator::from_config(config),
+            responses: ResponseCache::from_config(config),
+            usage: UsageTracker::from_config(config),
+            request_log: None,
+            admin_token: None,
+            ready: AtomicBool::new(false),
+            reload: Mutex::new(ReloadStatus::default()),
+        })
+    }
+
+    /// Log every embedding, completion and chat request to `log`.
+    pub fn with_request_log(mut self, log: RequestLog) -> Self {
+        self.request_log = Some(log);
+        self
+    }
+
+    /// Serve the model at `source` to requests naming `name`, loading it on
+    /// the first of them.
+    pub fn with_model(mut self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        let pool = self
+            .models
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        pool.slots
+            .entry(name.into())
+            .or_insert_with(|| ModelSlot::new(source));
+        self
+    }
+
+    /// Unload models other than the default after `ttl` without requests.
+    pub fn with_idle_ttl(mut self, ttl: Duration) -> Self {
+        self.models
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .idle_ttl = ttl;
+        self
+    }
+
+    /// Require `token` as a bearer token on the admin endpoints.
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        let admin = ApiKeyConfig {
+            name: "admin".into(),
+            key: token.into(),
+            requests_per_second: None,
+            burst: None,
+            token_quota: None,
+        };
+        self.admin_token = Some(Authenticator::new(&AuthConfig {
+            keys: vec![admin],
+            ..AuthConfig::default()
+        }));
+        self
+    }
+
+    /// Model serving new requests that name no model.
+    pub fn model(&self) -> Arc<ServedModel> {
+        self.pool().default_model()
+    }
+
+    /// Model named `name`, or the default one, loading it if needed.
+    /// `None` if no model of that name is served.
+    pub fn resolve(&self, name: Option<&str>) -> anyhow::Result<Option<Arc<ServedModel>>> {
+        let (name, source, loading) = {
+            let mut pool = self.pool();
+            pool.unload_idle();
+            let name = name.map_or_else(|| pool.default.clone(), str::to_string);
+            let Some(slot) = pool.slots.get_mut(&name) else {
+                return Ok(None);
+            };
+            slot.last_used = Instant::now();
+            if let Some(model) = &slot.loaded {
+                return Ok(Some(model.clone()));
+            }
+            (name, slot.source.clone(), slot.loading.clone())
+        };
+        let _loading = loading
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Another request may have loaded it in the meantime.
+        let loaded = self
+            .pool()
+            .slots
+            .get(&name)
+            .and_then(|slot| slot.loaded.clone());
+        if loaded.is_some() {
+            return Ok(loaded);
+        }
+        let started = Instant::now();
+        let mut model = ServedModel::load(&source, &self.config, 0)
+            .with_context(|| format!("cannot load model {name} from {source}"))?;
+        model.name = name.clone();
+        if self.config.scheduler.warmup != Some(false) {
+            Runtime::from_config(&self.config).warmup(&model);
+        }
+        let model = Arc::new(model);
+        self.pool().insert(&name, model.clone());
+        tracing::info!(model = %name, bytes = model.bytes, elapsed = ?started.elapsed(), "loaded model");
+        Ok(Some(model))
+    }
+
+    /// Models requests can name.
+    pub fn models(&self) -> Vec<ModelStatus> {
+        let pool = self.pool();
+        pool.slots
+            .iter()
+            .map(|(name, slot)| ModelStatus {
+                name: name.clone(),
+                default: *name == pool.default,
+                loaded: slot.loaded.as_ref().map(|model| model.bytes),
+            })
+            .collect()
+    }
+
+    fn pool(&self) -> MutexGuard<'_, ModelPool> {
+        self.models
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Report the server ready, or not, on `/readyz`.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn reload_status(&self) -> ReloadStatus {
+        self.reload_lock().clone()
+    }
+
+    fn reload_lock(&self) -> MutexGuard<'_, ReloadStatus> {
+        self.reload
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Replace the served model by the one at `model`, or by a fresh load of
+    /// the current one.  The new model is loaded and warmed up while the
+    /// current one keeps serving; requests still running on the current
+    /// one are waited for up to [`DRAIN_TIMEOUT`].  Fails without touching
+    /// the served model if loading fails or another reload is running.
+    pub fn reload(&self, model: Option<&str>) -> anyhow::Result<ReloadReport> {
+        self.begin_reload()?;
+        self.finish_reload(model)
+    }
+
+    fn begin_reload(&self) -> anyhow::Result<()> {
+        let mut status = self.reload_lock();
+        anyhow::ensure!(!status.reloading, "a reload is already running");
+        status.reloading = true;
+        Ok(())
+    }
+
+    fn finish_reload(&self, model: Option<&str>) -> anyhow::Result<ReloadReport> {
+        let result = self.swap_model(model);
+        let mut status = self.reload_lock();
+        status.reloading = false;
+        match &result {
+            Ok(_) => {
+                status.reloads += 1;
+                status.last_error = None;
+            }
+            Err(err) => status.last_error = Some(format!("{err:#}")),
+        }
+        result
+    }
+
+    fn swap_model(&self, model: Option<&str>) -> anyhow::Result<ReloadReport> {
+        let started = Instant::now();
+        let (source, generation) = {
+            let current = self.model();
+            let source = model.unwrap_or(&current.source).to_string();
+            (source, current.generation + 1)
+        };
+        let next = ServedModel::load(&source, &self.config, generation)
+            .with_context(|| format!("cannot reload {source}"))?;
+        if self.config.scheduler.warmup != Some(false) {
+            Runtime::from_config(&self.config).warmup(&next);
+        }
+        let load_time = started.elapsed();
+        let next = Arc::new(next);
+        let (previous, allocation) = {
+            let mut pool = self.pool();
+            let default = pool.default.clone();
+            let slot = pool
+                .slots
+                .remove(&default)
+                .expect("the default model stays loaded");
+            // The new model takes the place of one of its name.
+            pool.unload(&next.name, "replaced");
+            pool.default = next.name.clone();
+            pool.insert(&next.name, next.clone());
+            (
+                slot.loaded.expect("the default model stays loaded"),
+                slot.allocation,
+            )
+        };
+
+        // Requests hold the model they started on until they finish.
+        let draining = Instant::now();
+        while Arc::strong_count(&previous) > 1 && draining.elapsed() < DRAIN_TIMEOUT {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let drained = Arc::strong_count(&previous) == 1;
+        if !drained {
+            tracing::warn!(model = %previous.name, "previous model still in use after drain timeout");
+        }
+        if let Some(allocation) = allocation {
+            self.pool().memory.free(allocation);
+        }
+        tracing::info!(model = %next.name, generation, ?load_time, "model reloaded");
+        Ok(ReloadReport {
+            model: next.name.clone(),
+            generation,
+            load_time,
+            drain_time: draining.elapsed(),
+            drained,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Input {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct EmbeddingRequest {
+    input: Input,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    pooling: Option<Pooling>,
+}
+
+/// Sampling fields shared by completion and chat requests.
+#[derive(Deserialize)]
+struct SamplingRequest {
+    #[serde(default = "default_max_tokens")]
+    max_tokens: usize,
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default)]
+    top_p: Option<f32>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    stop: Option<Input>,
+}
+
+fn default_max_tokens() -> usize {
+    16
+}
+
+impl SamplingRequest {
+    fn options(&self) -> GenerationOptions {
+        GenerationOptions {
+            max_tokens: Some(self.max_tokens),
+            sampler: SamplerOptions {
+                temperature: self.temperature,
+                top_p: self.top_p,
+                seed: self.seed,
+            },
+            stop: match &self.stop {
+                None => Vec::new(),
+                Some(Input::One(stop)) => vec![stop.clone()],
+                Some(Input::Many(stop)) => stop.clone(),
+            },
+            ..GenerationOptions::default()
+        }
+    }
+}
+
+/// Options of a completion or chat request to `path` with `body`.
+pub(crate) fn generation_options(path: &str, body: &Value) -> Option<GenerationOptions> {
+    match path {
+        "/v1/completions" | "/v1/chat/completions" => SamplingRequest::deserialize(body)
+            .ok()
+            .map(|request| request.options()),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+struct CompletionRequest {
+    #[serde(default)]
+    prompt: String,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(flatten)]
+    sampling: SamplingRequest,
+    #[serde(default)]
+    response_format: Option<ResponseFormat>,
+}
+
+#[derive(Deserialize, Default)]
+struct SessionRequest {
+    #[serde(default)]
+    system_prompt: Option<String>,
+    /// Token budget of the session.
+    #[serde(default)]
+    max_tokens: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+    #[serde(default)]
+    session: Option<SessionId>,
+    #[serde(default)]
+    model: Option<String>,
+    messages: Vec<Message>,
+    #[serde(flatten)]
+    sampling: SamplingRequest,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: SchemaFormat },
+}
+
+#[derive(Deserialize)]
+struct SchemaFormat {
+    schema: Value,
+}
+
+pub(crate) struct Response {
+    pub(crate) status: &'static str,
+    pub(crate) body: Value,
+    /// Sent as the `Retry-After` header.
+    retry_after: Option<Duration>,
+    /// Whether to ask for a bearer token with `WWW-Authenticate`.
+    challenge: bool,
+    /// Token timings of generation requests, for the request log.
+    timings: Option<TokenTimings>,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self {
+            status: "200 OK",
+            body,
+            retry_after: None,
+            challenge: false,
+            timings: None,
+        }
+    }
+
+    fn error(status: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: json!({ "error": { "message": message.into() } }),
+            retry_after: None,
+            challenge: false,
+            timings: None,
+        }
+    }
+
+    fn session_error(err: SessionError) -> Self {
+        let status = match err {
+            SessionError::NotFound(_) | SessionError::Expired(_) => "404 Not Found",
+            SessionError::BudgetExhausted { .. } => "429 Too Many Requests",
+        };
+        Self::error(status, err.to_string())
+    }
+
+    fn admission_error(err: AdmissionError) -> Self {
+        let message = err.to_string();
+        match err {
+            AdmissionError::TooLarge { .. } => Self::error("413 Payload Too Large", message),
+            AdmissionError::Overloaded {
+                reason,
+                retry_after,
+            } => Self {
+                status: "503 Service Unavailable",
+                body: json!({
+                    "error": {
+                        "message": message,
+                        "reason": reason.to_string(),
+                        "retry_after_ms": retry_after.as_millis() as u64,
+                    }
+                }),
+                retry_after: Some(retry_after),
+                challenge: false,
+                timings: None,
+            },
+        }
+    }
+
+    fn auth_error(err: AuthError) -> Self {
+        let message = err.to_string();
+        match err {
+            AuthError::Missing | AuthError::Invalid => Self {
+                challenge: true,
+                ..Self::error("401 Unauthorized", message)
+            },
+            AuthError::RateLimited { retry_after, .. } => Self {
+                status: "429 Too Many Requests",
+                body: json!({
+                    "error": {
+                        "message": message,
+                        "retry_after_ms": retry_after.as_millis() as u64,
+                    }
+                }),
+                retry_after: Some(retry_after),
+                challenge: false,
+                timings: None,
+            },
+        }
+    }
+
+    fn quota_error(err: QuotaExceeded) -> Self {
+        Self {
+            status: "429 Too Many Requests",
+            body: json!({
+                "error": {
+                    "message": err.to_string(),
+                    "used": err.used,
+                    "token_quota": err.quota,
+                }
+            }),
+            ..Self::ok(Value::Null)
+        }
+    }
+}
+
+/// Serve `state` on `addr` from a background thread accepting connections,
+/// each handled on a thread of its own.  The listener is bound before
+/// returning so bind errors are reported to the caller.
+pub fn serve<A: ToSocketAddrs>(
+    state: Arc<ServerState>,
+    addr: A,
+) -> std::io::Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    let handle = std::thread::spawn(move || {
+        // Every connection thread holds a clone until it exits.
+        let open = Arc::new(());
+        for stream in listener.incoming().flatten() {
+            if Arc::strong_count(&open) > MAX_CONNECTIONS {
+                tracing::warn!(open = MAX_CONNECTIONS, "turning a connection away");
+                let response = Response::error("503 Service Unavailable", "too many connections");
+                let _ = write_response(&stream, response);
+                continue;
+            }
+            let (state, open) = (state.clone(), open.clone());
+            std::thread::spawn(move || {
+                if let Err(err) = handle_connection(&state, &stream) {
+                    tracing::debug!(%err, "dropped connection");
+                }
+                drop(open);
+            });
+        }
+    });
+    Ok((local, handle))
+}
+
+fn handle_connection(state: &Arc<ServerState>, stream: &TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut head = BufReader::new(stream).take(MAX_HEAD as u64);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut content_length = 0;
+    let mut bearer = None;
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                bearer = value.trim().strip_prefix("Bearer ").map(str::to_string);
+            }
+        }
+    }
+
+    if head.limit() == 0 {
+        let response = Response::error(
+            "431 Request Header Fields Too Large",
+            "request line and headers too large",
+        );
+        return write_response(stream, response);
+    }
+    let mut reader = head.into_inner();
+
+    let response = if content_length > MAX_BODY {
+        Response::error("413 Payload Too Large", "request body too large")
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        if path.starts_with("/admin/") {
+            let authorized = match &state.admin_token {
+                Some(token) => token.authenticate(bearer.as_deref()).is_ok(),
+                None => stream.peer_addr().is_ok_and(|peer| peer.ip().is_loopback()),
+            };
+            let response = if authorized {
+                admin(state, &method, &path, &body)
+            } else {
+                Response::error("403 Forbidden", "admin endpoints need the admin token")
+            };
+            return write_response(stream, response);
+        }
+        let key = if path.starts_with("/v1/") {
+            match state.auth.authenticate(bearer.as_deref()) {
+                Ok(key) => key,
+                Err(err) => {
+                    tracing::debug!(%err, %path, "rejected request");
+                    return write_response(stream, Response::auth_error(err));
+                }
+            }
+        } else {
+            None
+        };
+        let metered = method == "POST"
+            && matches!(
+                path.as_str(),
+                "/v1/embeddings" | "/v1/completions" | "/v1/chat/completions"
+            );
+        if metered {
+            if let Err(err) = state.usage.check_quota(key) {
+                tracing::debug!(%err, %path, "rejected request");
+                return write_response(stream, Response::quota_error(err));
+            }
+        }
+        let started = Instant::now();
+        let response = route(state, key, &method, &path, &body);
+        if let (Some(log), true) = (&state.request_log, metered) {
+            log.record(&RequestRecord::new(
+                state,
+                &path,
+                &body,
+                response.status,
+                &response.body,
+                started.elapsed(),
+                response.timings,
+            ));
+        }
+        response
+    };
+    write_response(stream, response)
+}
+
+fn write_response(stream: &TcpStream, response: Response) -> std::io::Result<()> {
+    let body = response.body.to_string();
+    // Retry-After is in whole seconds; round up so clients never retry early.
+    let retry_after = match response.retry_after {
+        Some(delay) => format!("Retry-After: {}\r\n", delay.as_millis().div_ceil(1000)),
+        None => String::new(),
+    };
+    let challenge = if response.challenge {
+        "WWW-Authenticate: Bearer\r\n"
+    } else {
+        ""
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{retry_after}{challenge}Connection: close\r\n\r\n{body}",
+        response.status,
+        body.len()
+    )
+}
+
+/// Answer a request made with the API key named `key`.
+pub(crate) fn route(
+    state: &ServerState,
+    key: Option<&str>,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> Response {
+    let expired = state.sessions.expire();
+    if !expired.is_empty() {
+        tracing::debug!(?expired, "expired sessions");
+        state.usage.forget_sessions(&expired);
+    }
+    if let Some(rest) = path.strip_prefix("/v1/sessions/") {
+        let (id, fork) = match rest.strip_suffix("/fork") {
+            Some(id) => (id, true),
+            None => (rest, false),
+        };
+        let Ok(id) = id.parse() else {
+            return Response::error("404 Not Found", format!("no route for {path}"));
+        };
+        return match (method, fork) {
+            ("POST", true) => fork_session(state, key, id),
+            ("DELETE", false) => delete_session(state, key, id),
+            (_, true) => Response::error("405 Method Not Allowed", "use POST"),
+            (_, false) => Response::error("405 Method Not Allowed", "use DELETE"),
+        };
+    }
+    match (method, path) {
+        ("GET", "/healthz") => Response::ok(json!({ "status": "ok" })),
+        ("GET", "/readyz") => readiness(state),
+        ("GET", "/v1/models") => list_models(state),
+        ("POST", "/v1/embeddings") => embeddings(state, key, body),
+        ("POST", "/v1/completions") => completions(state, key, body),
+        ("POST", "/v1/chat/completions") => chat_completions(state, key, body),
+        ("POST", "/v1/sessions") => create_session(state, key, body),
+        (_, "/v1/embeddings" | "/v1/completions" | "/v1/chat/completions" | "/v1/sessions") => {
+            Response::error("405 Method Not Allowed", "use POST")
+        }
+        (_, "/healthz" | "/readyz" | "/v1/models") => {
+            Response::error("405 Method Not Allowed", "use GET")
+        }
+        _ => Response::error("404 Not Found", format!("no route for {path}")),
+    }
+}
+
+fn readiness(state: &ServerState) -> Response {
+    let model = state.model();
+    let body = json!({
+        "status": if state.is_ready() { "ready" } else { "starting" },
+        "model": model.name,
+        "generation": model.generation,
+        "reloading": state.reload_status().reloading,
+    });
+    if state.is_ready() {
+        Response::ok(body)
+    } else {
+        Response {
+            status: "503 Service Unavailable",
+            ..Response::ok(body)
+        }
+    }
+}
+
+fn list_models(state: &ServerState) -> Response {
+    let data: Vec<Value> = state
+        .models()
+        .into_iter()
+        .map(|model| {
+            json!({
+                "id": model.name,
+                "object": "model",
+                "default": model.default,
+                "loaded": model.loaded.is_some(),
+                "bytes": model.loaded,
+            })
+        })
+        .collect();
+    Response::ok(json!({ "object": "list", "data": data }))
+}
+
+/// Model a request named, or the default one.
+fn routed_model(state: &ServerState, name: Option<&str>) -> Result<Arc<ServedModel>, Response> {
+    match state.resolve(name) {
+        Ok(Some(model)) => Ok(model),
+        Ok(None) => Err(Response::error(
+            "404 Not Found",
+            format!("unknown model {}", name.unwrap_or_default()),
+        )),
+        Err(err) => Err(Response::error(
+            "500 Internal Server Error",
+            format!("{err:#}"),
+        )),
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct ReloadRequest {
+    /// Bundle or model configuration to switch to instead of the current one.
+    #[serde(default)]
+    model: Option<String>,
+}
+
+fn admin(state: &Arc<ServerState>, method: &str, path: &str, body: &[u8]) -> Response {
+    match (method, path) {
+        ("GET", "/admin/reload") => {
+            let status = state.reload_status();
+            let model = state.model();
+            Response::ok(json!({
+                "reloading": status.reloading,
+                "reloads": status.reloads,
+                "last_error": status.last_error,
+                "model": model.name,
+                "source": model.source,
+                "generation": model.generation,
+            }))
+        }
+        ("POST", "/admin/reload") => {
+            let request: ReloadRequest = if body.is_empty() {
+                ReloadRequest::default()
+            } else {
+                match serde_json::from_slice(body) {
+                    Ok(request) => request,
+                    Err(err) => return Response::error("400 Bad Request", err.to_string()),
+                }
+            };
+            if let Err(err) = state.begin_reload() {
+                return Response::error("409 Conflict", err.to_string());
+            }
+            let reloading = state.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = reloading.finish_reload(request.model.as_deref()) {
+                    tracing::error!("model reload failed: {err:#}");
+                }
+            });
+            Response {
+                status: "202 Accepted",
+                ..Response::ok(json!({ "status": "reloading" }))
+            }
+        }
+        ("GET", "/admin/usage") => usage_report(state),
+        (_, "/admin/reload") => Response::error("405 Method Not Allowed", "use GET or POST"),
+        (_, "/admin/usage") => Response::error("405 Method Not Allowed", "use GET"),
+        _ => Response::error("404 Not Found", format!("no route for {path}")),
+    }
+}
+
+fn usage_report(state: &ServerState) -> Response {
+    let keys: serde_json::Map<String, Value> = state
+        .usage
+        .keys()
+        .into_iter()
+        .map(|(name, usage)| {
+            let mut report = json!(usage);
+            report["token_quota"] = json!(state.usage.quota(&name));
+            (name, report)
+        })
+        .collect();
+    Response::ok(json!({ "keys": keys, "sessions": state.usage.sessions() }))
+}
+
+fn embeddings(state: &ServerState, key: Option<&str>, body: &[u8]) -> Response {
+    let request: EmbeddingRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(err) => return Response::error("400 Bad Request", err.to_string()),
+    };
+    let texts = match request.input {
+        Input::One(text) => vec![text],
+        Input::Many(texts) => texts,
+    };
+    let tokens = texts.iter().map(String::len).sum();
+    let _permit = match state.admission.admit(tokens) {
+        Ok(permit) => permit,
+        Err(err) => return Response::admission_error(err),
+    };
+    let model = match routed_model(state, request.model.as_deref()) {
+        Ok(model) => model,
+        Err(response) => return response,
+    };
+    let pooling = request.pooling.unwrap_or(state.pooling);
+    let started = Instant::now();
+    let embeddings = model.embeddings.embed_batch(&texts, pooling);
+    let seconds = started.elapsed().as_secs_f64();
+    let data: Vec<Value> = embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(index, embedding)| {
+            json!({ "object": "embedding", "index": index, "embedding": embedding })
+        })
+        .collect();
+    let tokens: usize = texts
+        .iter()
+        .map(|text| model.embeddings.token_count(text))
+        .sum();
+    state
+        .usage
+        .record(key, None, Usage::request(tokens, 0, seconds));
+    Response::ok(json!({
+        "object": "list",
+        "data": data,
+        "model": model.name,
+        "usage": { "prompt_tokens": tokens, "total_tokens": tokens },
+    }))
+}
+
+/// Admit a generation reading `input` bytes of text.
+fn admit<'a>(
+    state: &'a ServerState,
+    input: usize,
+    sampling: &SamplingRequest,
+) -> Result<AdmissionPermit<'a>, Response> {
+    state
+        .admission
+        .admit(input + sampling.max_tokens)
+        .map_err(Response::admission_error)
+}
+
+fn completions(state: &ServerState, key: Option<&str>, body: &[u8]) -> Response {
+    let request: CompletionRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(err) => return Response::error("400 Bad Request", err.to_string()),
+    };
+    let schema = match request.response_format {
+        None | Some(ResponseFormat::Text) => None,
+        Some(ResponseFormat::JsonObject) => Some(json!({ "type": "object" })),
+        Some(ResponseFormat::JsonSchema { json_schema }) => Some(json_schema.schema),
+    };
+    let format = schema.as_ref().map(Value::to_string).unwrap_or_default();
+    let schema = match schema.as_ref().map(JsonSchema::compile).transpose() {
+        Ok(schema) => schema,
+        Err(err) => return Response::error("400 Bad Request", err.to_string()),
+    };
+
+    // Admit the request before it may load a model.
+    let _permit = match admit(state, request.prompt.len(), &request.sampling) {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+    let model = match routed_model(state, request.model.as_deref()) {
+        Ok(model) => model,
+        Err(response) => return response,
+    };
+    let options = request.sampling.options();
+    let cached = cache_key(&model, &request.prompt, &options).with(&format);
+    let (output, timings, seconds) = match state.responses.get(&cached) {
+        Some(output) => (output, None, 0.0),
+        None => {
+            let config = GenerationConfig {
+                schema,
+                ..GenerationConfig::from_options(&options)
+            };
+            let mut timer = TokenTimer::start();
+            let mut generator = model
+                .generator
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let started = Instant::now();
+            let output = generator
+                .generate_streaming(&request.prompt, &config, |event| timer.observe(event));
+            let seconds = started.elapsed().as_secs_f64();
+            state
+                .responses
+                .insert(cached, output.clone(), output.text.len());
+            let timings = timer.timings(&output);
+            (output, Some(timings), seconds)
+        }
+    };
+    state
+        .usage
+        .record(key, None, output_usage(&output, seconds));
+
+    Response {
+        timings,
+        ..Response::ok(json!({
+            "object": "text_completion",
+            "model": model.name,
+            "choices": [{
+                "index": 0,
+                "text": output.text,
+                "finish_reason": finish_reason(output.finish_reason),
+            }],
+            "usage": usage(&output),
+        }))
+    }
+}
+
+fn chat_completions(state: &ServerState, key: Option<&str>, body: &[u8]) -> Response {
+    let request: ChatRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(err) => return Response::error("400 Bad Request", err.to_string()),
+    };
+    if let Some(id) = request.session {
+        if let Err(err) = state.sessions.check_owner(id, key) {
+            return Response::session_error(err);
+        }
+    }
+    let input = request.messages.iter().map(|m| m.content.len()).sum();
+    // Admit the request before it may load a model.
+    let _permit = match admit(state, input, &request.sampling) {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+    let model = match routed_model(state, request.model.as_deref()) {
+        Ok(model) => model,
+        Err(response) => return response,
+    };
+    let options = request.sampling.options();
+    // Only one-off conversations are cached; sessions go on from state of
+    // their own.
+    let cached = request.session.is_none().then(|| {
+        let mut conversation = Session::<SessionKv>::new(0, None);
+        for message in &request.messages {
+            conversation.push(message.role, message.content.as_str());
+        }
+        cache_key(&model, &conversation.prompt(), &options).with("chat")
+    });
+    let hit = cached.as_ref().and_then(|key| state.responses.get(key));
+    let (output, timings, seconds, summary_usage) = match hit {
+        Some(output) => (output, None, 0.0, Usage::default()),
+        None => {
+            let mut config = GenerationConfig::from_options(&options);
+            // Stop before the model starts writing the user's next turn.
+            config.stop.push(format!("\n{}: ", Role::User));
+            let mut timer = TokenTimer::start();
+            let mut seconds = 0.0;
+            let mut summary_usage = Usage::default();
+            let reply = |session: &mut Session<SessionKv>| {
+                session.check_budget()?;
+                for message in request.messages {
+                    session.push(message.role, message.content);
+                }
+                let mut config = config.clone();
+                if let Some(remaining) = session.remaining_tokens() {
+                    config.max_tokens = config.max_tokens.min(remaining);
+                }
+                let mut generator = model
+                    .generator
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                // A cache computed by another model, or one since reloaded, is
+                // of no use.
+                if let Some(kv) = session.take_kv() {
+                    if kv.model == model.name && kv.generation == model.generation {
+                        generator.restore_kv_cache(kv.cache);
+                    }
+                }
+                let started = Instant::now();
+                if let Some(compressor) = &state.compressor {
+                    let max_tokens = config.max_tokens;
+                    if let Some(compression) =
+                        compressor.compress(&mut generator, session, max_tokens)
+                    {
+                        if compression.summarized == 0 {
+                            tracing::warn!(
+                                session = session.id(),
+                                tokens = compression.tokens,
+                                "model wrote an empty summary of the session"
+                            );
+                        } else {
+                            tracing::info!(
+                                session = session.id(),
+                                summarized = compression.summarized,
+                                before = compression.prompt_tokens_before,
+                                after = compression.prompt_tokens_after,
+                                "compressed session context"
+                            );
+                        }
+                        session.charge(compression.tokens);
+                        summary_usage = compression_usage(&compression);
+                    }
+                }
+                let output = generator
+                    .generate_streaming(&session.prompt(), &config, |event| timer.observe(event));
+                seconds = started.elapsed().as_secs_f64();
+                session.set_kv(SessionKv {
+                    model: model.name.clone(),
+                    generation: model.generation,
+                    cache: generator.kv_cache(),
+                });
+                session.charge(output.prompt_tokens - output.cached_tokens + output.tokens.len());
+                session.push(Role::Assistant, output.text.clone());
+                Ok(output)
+            };
+            let output = match request.session {
+                Some(id) => state
+                    .sessions
+                    .with_session(id, reply)
+                    .and_then(|output| output),
+                None => reply(&mut Session::new(0, None)),
+            };
+            let output = match output {
+                Ok(output) => output,
+                Err(err) => return Response::session_error(err),
+            };
+            if let Some(cached) = cached {
+                state
+                    .responses
+                    .insert(cached, output.clone(), output.text.len());
+            }
+            let timings = timer.timings(&output);
+            (output, Some(timings), seconds, summary_usage)
+        }
+    };
+    let mut used = output_usage(&output, seconds);
+    used += summary_usage;
+    state.usage.record(key, request.session, used);
+
+    Response {
+        timings,
+        ..Response::ok(json!({
+            "object": "chat.completion",
+            "model": model.name,
+            "session": request.session,
+            "choices": [{
+                "index": 0,
+                "message": { "role": Role::Assistant, "content": output.text },
+                "finish_reason": finish_reason(output.finish_reason),
+            }],
+            "usage": usage(&output),
+        }))
+    }
+}
+
+/// Response cache key of generating from `prompt` with `options` on
+/// `model`; reloading a model invalidates its responses.
+fn cache_key(model: &ServedModel, prompt: &str, options: &GenerationOptions) -> CacheKey {
+    CacheKey::new(
+        &format!("{}@{}", model.name, model.generation),
+        prompt,
+        options,
+    )
+}
+
+fn create_session(state: &ServerState, key: Option<&str>, body: &[u8]) -> Response {
+    let request: SessionRequest = if body.is_empty() {
+        SessionRequest::default()
+    } else {
+        match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(err) => return Response::error("400 Bad Request", err.to_string()),
+        }
+    };
+    let id = state
+        .sessions
+        .create(key, request.system_prompt, request.max_tokens);
+    Response::ok(json!({ "object": "session", "id": id }))
+}
+
+fn fork_session(state: &ServerState, key: Option<&str>, id: SessionId) -> Response {
+    if let Err(err) = state.sessions.check_owner(id, key) {
+        return Response::session_error(err);
+    }
+    match state.sessions.fork(id) {
+        Ok(fork) => Response::ok(json!({ "object": "session", "id": fork, "parent": id })),
+        Err(err) => Response::session_error(err),
+    }
+}
+
+fn delete_session(state: &ServerState, key: Option<&str>, id: SessionId) -> Response {
+    if let Err(err) = state.sessions.check_owner(id, key) {
+        return Response::session_error(err);
+    }
+    if state.sessions.remove(id) {
+        state.usage.forget_sessions(&[id]);
+        Response::ok(json!({ "object": "session", "id": id, "deleted": true }))
+    } else {
+        Response::session_error(SessionError::NotFound(id))
+    }
+}
+
+/// Measures when the first token of a generation arrives.
+struct TokenTimer {
+    started: Instant,
+    first_token: Option<Duration>,
+}
+
+impl TokenTimer {
+    fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            first_token: None,
+        }
+    }
+
+    fn observe(&mut self, event: &StreamEvent) {
+        if event.token.is_some() && self.first_token.is_none() {
+            self.first_token = Some(self.started.elapsed());
+        }
+    }
+
+    fn timings(&self, output: &GenerationOutput) -> TokenTimings {
+        TokenTimings {
+            first_token_ms: self.first_token.map(|d| d.as_secs_f64() * 1e3),
+            tokens: output.tokens.len(),
+        }
+    }
+}
+
+fn finish_reason(reason: FinishReason) -> &'static str {
+    match reason {
+        FinishReason::Length => "length",
+        FinishReason::Eos | FinishReason::Stop | FinishReason::LowConfidence => "stop",
+    }
+}
+
+/// Usage of a request that generated `output` in `seconds`.
+fn output_usage(output: &GenerationOutput, seconds: f64) -> Usage {
+    Usage::request(output.prompt_tokens, output.tokens.len(), seconds)
+}
+
+/// Usage of summarizing a session's history; its time is counted with
+/// the request that needed the room.
+fn compression_usage(compression: &Compression) -> Usage {
+    Usage {
+        prompt_tokens: (compression.tokens - compression.generated_tokens) as u64,
+        generated_tokens: compression.generated_tokens as u64,
+        ..Usage::default()
+    }
+}
+
+fn usage(output: &GenerationOutput) -> Value {
+    let completion_tokens = output.tokens.len();
+    json!({
+        "prompt_tokens": output.prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": output.prompt_tokens + completion_tokens,
+        "prompt_tokens_details": { "cached_tokens": output.cached_tokens },
+    })
+}
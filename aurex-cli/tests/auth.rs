@@ -0,0 +1,59 @@
+mod common;
+
+use std::sync::Arc;
+
+use amduda::aurex_lm::embeddings::Pooling;
+use aurex_cli::server::{serve, ServerState};
+use aurex_runtime::AurexConfig;
+use tempfile::tempdir;
+
+use common::{exchange, write_model};
+
+const INPUT: &str = r#"{"input": "hi"}"#;
+
+#[test]
+fn api_keys_are_required_and_rate_limited() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let model = model.to_str().unwrap();
+    let config = AurexConfig::from_toml_str(
+        r#"
+        [backend]
+        preferred = "cpu"
+
+        [auth]
+        requests_per_second = 0.01
+        keys = [
+            { name = "ci", key = "sk-ci", burst = 2 },
+            { name = "admin", key = "sk-admin", requests_per_second = 1000.0 },
+        ]
+        "#,
+    )
+    .unwrap();
+    let state = ServerState::load(model, &config, Pooling::Mean).unwrap();
+    let (addr, _handle) = serve(Arc::new(state), "127.0.0.1:0").unwrap();
+
+    let (head, body) = exchange(addr, "POST", "/v1/embeddings", None, INPUT);
+    assert!(head.starts_with("HTTP/1.1 401 Unauthorized"));
+    assert!(head.contains("WWW-Authenticate: Bearer"));
+    assert_eq!(body["error"]["message"], "missing API key");
+    let (head, _) = exchange(addr, "POST", "/v1/embeddings", Some("sk-wrong"), INPUT);
+    assert!(head.starts_with("HTTP/1.1 401 Unauthorized"));
+
+    for _ in 0..2 {
+        let (head, body) = exchange(addr, "POST", "/v1/embeddings", Some("sk-ci"), INPUT);
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+        assert_eq!(body["model"], "tiny");
+    }
+    let (head, body) = exchange(addr, "POST", "/v1/embeddings", Some("sk-ci"), INPUT);
+    assert!(head.starts_with("HTTP/1.1 429 Too Many Requests"));
+    assert!(head.contains("Retry-After: "));
+    assert!(body["error"]["retry_after_ms"].as_u64().unwrap() > 90_000);
+
+    // Other keys have buckets of their own.
+    let (head, _) = exchange(addr, "GET", "/v1/models", Some("sk-admin"), INPUT);
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    // Health checks need no key.
+    let (head, _) = exchange(addr, "GET", "/healthz", None, INPUT);
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+}
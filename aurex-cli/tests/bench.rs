@@ -0,0 +1,75 @@
+mod common;
+
+use amduda::aurex_lm::benchmark::BenchConfig;
+use amduda::aurex_lm::model_loader::Quantization;
+use aurex_backend::{Backend, Precision};
+use aurex_cli::bench_model;
+use aurex_runtime::AurexConfig;
+use tempfile::tempdir;
+
+use common::write_model;
+
+#[test]
+fn compares_every_precision_on_the_selected_backend() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let mut config = AurexConfig::default();
+    config.backend.preferred = Some(Backend::Cpu);
+    let bench = BenchConfig {
+        tokens: 8,
+        repeats: 1,
+        ..BenchConfig::default()
+    };
+
+    let report = bench_model(model.to_str().unwrap(), &config, &bench).unwrap();
+    assert_eq!(report.model, "tiny");
+    assert_eq!(report.backend, Backend::Cpu);
+    let bytes: Vec<(Precision, Option<Quantization>, usize)> = report
+        .results
+        .iter()
+        .map(|r| (r.precision, r.quantization, r.weight_bytes))
+        .collect();
+    let count = 257 * 8;
+    assert_eq!(
+        bytes,
+        [
+            (Precision::F32, None, 4 * count),
+            (Precision::Bf16, Some(Quantization::Bf16), 2 * count),
+            (Precision::Int8, Some(Quantization::Int8), count),
+            (Precision::Int4, Some(Quantization::Int4), count / 2),
+        ]
+    );
+    for result in &report.results {
+        assert!(result.tokens_per_sec > 0.0);
+        assert!(result.perplexity.is_finite() && result.perplexity > 1.0);
+        assert_eq!(result.scored_tokens, bench.text.len() - 1);
+    }
+    assert_eq!(report.perplexity_delta(&report.results[0]), Some(0.0));
+    assert_eq!(report.speedup(&report.results[0]), Some(1.0));
+
+    let text = report.to_string();
+    assert!(text.contains("Backend: cpu"), "{text}");
+    assert!(text.contains("1.00x"), "{text}");
+    let value = serde_json::to_value(&report).unwrap();
+    assert_eq!(value["results"][3]["precision"], "int4");
+    assert_eq!(value["results"][3]["quantization"], "int4");
+}
+
+#[test]
+fn reports_no_delta_without_f32_baseline() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let bench = BenchConfig {
+        precisions: vec![Precision::Int8],
+        tokens: 4,
+        repeats: 1,
+        text: "short text".into(),
+        ..BenchConfig::default()
+    };
+    let report = bench_model(model.to_str().unwrap(), &AurexConfig::default(), &bench).unwrap();
+    assert_eq!(report.results.len(), 1);
+    assert_eq!(report.perplexity_delta(&report.results[0]), None);
+    assert!(report.to_string().lines().last().unwrap().ends_with('-'));
+}
@@ -0,0 +1,77 @@
+//! Models, configurations and HTTP requests shared by the tests.
+
+// Each test binary uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+
+use aurex_backend::Backend;
+use aurex_runtime::AurexConfig;
+use serde_json::{json, Value};
+
+/// Write the tiny model `name` with weights drawn using `stride` and
+/// return the path of its configuration.
+pub fn write_named_model(dir: &Path, name: &str, stride: usize) -> PathBuf {
+    let hidden = 8;
+    let weights: Vec<u8> = (0..257 * hidden)
+        .map(|i| ((i * stride % 97) as f32 / 48.0) - 1.0)
+        .flat_map(|v: f32| v.to_le_bytes())
+        .collect();
+    let weight_path = dir.join(format!("{name}.bin"));
+    std::fs::write(&weight_path, weights).unwrap();
+    let config_path = dir.join(format!("{name}.json"));
+    let cfg = json!({ "name": name, "weight_path": weight_path, "hidden_size": hidden });
+    std::fs::write(&config_path, serde_json::to_vec(&cfg).unwrap()).unwrap();
+    config_path
+}
+
+/// Write the model `tiny` and return the path of its configuration.
+pub fn write_model(dir: &Path) -> PathBuf {
+    write_named_model(dir, "tiny", 31)
+}
+
+/// Configuration running on the CPU.
+pub fn cpu() -> AurexConfig {
+    let mut config = AurexConfig::default();
+    config.backend.preferred = Some(Backend::Cpu);
+    config
+}
+
+/// Send a request, with `key` as bearer token if given, and return the
+/// response's head and body.
+pub fn exchange(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    key: Option<&str>,
+    body: &str,
+) -> (String, Value) {
+    let auth = key.map_or(String::new(), |k| format!("Authorization: Bearer {k}\r\n"));
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\n{auth}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_string(), serde_json::from_str(body).unwrap())
+}
+
+/// Send a request and return the response's status line and body.
+pub fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (String, Value) {
+    let (head, body) = exchange(addr, method, path, None, body);
+    (head.lines().next().unwrap().to_string(), body)
+}
+
+/// Send a POST request, check that it succeeded and return the response's
+/// body.
+pub fn post(addr: SocketAddr, path: &str, body: &Value) -> Value {
+    let (status, body) = request(addr, "POST", path, &body.to_string());
+    assert_eq!(status, "HTTP/1.1 200 OK", "{body}");
+    body
+}
@@ -0,0 +1,172 @@
+use amduda::aurex_lm::bundle::{CompiledBundle, KernelKind};
+use amduda::aurex_lm::layout::WeightLayout;
+use amduda::aurex_lm::model_loader::Quantization;
+use aurex_backend::dispatch::CpuBackend;
+use aurex_backend::{PluginBackend, PluginBackends, TensorOps};
+use aurex_cli::{compile_model, CompileOptions};
+use serde_json::json;
+use std::sync::Arc;
+use tempfile::tempdir;
+
+#[test]
+fn compile_writes_quantized_bundle() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_CPU_MEM", "1048576");
+    let dir = tempdir().unwrap();
+    let weights: Vec<f32> = (0..16).map(|i| i as f32 / 4.0 - 2.0).collect();
+    let weight_path = dir.path().join("weights.bin");
+    std::fs::write(
+        &weight_path,
+        weights.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>(),
+    )
+    .unwrap();
+    let config_path = dir.path().join("model.json");
+    let cfg = json!({ "name": "tiny", "weight_path": weight_path, "layers": 2 });
+    std::fs::write(&config_path, serde_json::to_vec(&cfg).unwrap()).unwrap();
+
+    let options = CompileOptions {
+        output: None,
+        quantize: Some(Quantization::Int8),
+    };
+    let out = compile_model(config_path.to_str().unwrap(), "cpu", &options).unwrap();
+    assert_eq!(out.extension().unwrap(), "aurexc");
+
+    let bundle = CompiledBundle::load(&out).unwrap();
+    assert_eq!(bundle.manifest.target, "cpu");
+    assert_eq!(bundle.manifest.weight_count, 16);
+    assert_eq!(bundle.manifest.config.quantization, Some(Quantization::Int8));
+    assert_eq!(bundle.weights.len(), 16);
+    // Both post-norm projections of each block are fused.
+    let fused = bundle
+        .manifest
+        .graph
+        .iter()
+        .filter(|op| op.as_str() == "matmul_layernorm")
+        .count();
+    assert_eq!(fused, 4);
+
+    let model = bundle.into_model();
+    let restored = model.dequantized_weights(16).unwrap();
+    for (orig, got) in weights.iter().zip(restored) {
+        assert!((orig - got).abs() < 0.02);
+    }
+
+    assert!(compile_model(config_path.to_str().unwrap(), "tpu", &options).is_err());
+}
+
+#[test]
+fn compile_repacks_weights_for_the_target() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_CPU_MEM", "1048576");
+    let dir = tempdir().unwrap();
+    let weights: Vec<f32> = (0..40).map(|i| i as f32).collect();
+    let weight_path = dir.path().join("weights.bin");
+    std::fs::write(
+        &weight_path,
+        weights.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>(),
+    )
+    .unwrap();
+    let config_path = dir.path().join("model.json");
+    let cfg = json!({ "name": "tiny", "weight_path": weight_path, "vocab_size": 4 });
+    std::fs::write(&config_path, serde_json::to_vec(&cfg).unwrap()).unwrap();
+
+    let out = compile_model(
+        config_path.to_str().unwrap(),
+        "cpu",
+        &CompileOptions::default(),
+    )
+    .unwrap();
+    let bundle = CompiledBundle::load(&out).unwrap();
+    assert_eq!(
+        bundle.manifest.config.layout,
+        WeightLayout::Blocked { cols: 8 }
+    );
+    // The first panel holds columns 0..8 of each of the 4 rows.
+    let stored: Vec<f32> = bundle
+        .weights
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    assert_eq!(stored[8..10], [10.0, 11.0]);
+    assert_eq!(bundle.into_model().weights_f32().unwrap(), weights);
+}
+
+#[test]
+fn vulkan_bundles_store_kernels_generated_from_the_graph_ir() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_CPU_MEM", "1048576");
+    let dir = tempdir().unwrap();
+    let weight_path = dir.path().join("weights.bin");
+    std::fs::write(&weight_path, vec![0u8; 64 * 4]).unwrap();
+    let config_path = dir.path().join("model.json");
+    let cfg = json!({ "name": "tiny", "weight_path": weight_path, "vocab_size": 4 });
+    std::fs::write(&config_path, serde_json::to_vec(&cfg).unwrap()).unwrap();
+
+    let out = compile_model(
+        config_path.to_str().unwrap(),
+        "vulkan",
+        &CompileOptions::default(),
+    )
+    .unwrap();
+    let bundle = CompiledBundle::load(&out).unwrap();
+    // Hidden size 16: the q, k and v projections share a kernel and the
+    // attention and the MLP are fused.
+    let names: Vec<&str> = bundle.kernels.iter().map(|k| k.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "matmul_1x16_16x16",
+            "fused_attention_1x16_1x16_1x16",
+            "matmul_residual_rms_norm_1x16_16x16_1x16_16",
+            "matmul_bias_gelu_1x16_16x64_64",
+            "matmul_residual_rms_norm_1x64_64x16_1x16_16",
+        ]
+    );
+    assert!(bundle.kernels.iter().all(|k| k.kind == KernelKind::SpirV));
+}
+
+/// Plugin ops reporting the default, `f32` only, capabilities.
+struct F32Only;
+
+impl TensorOps for F32Only {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        CpuBackend.matmul(a, b, m, n, k)
+    }
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        CpuBackend.conv2d(input, kernel, input_shape, kernel_shape)
+    }
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        CpuBackend.attention(q, k, v, dim)
+    }
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        CpuBackend.layer_norm(x, gamma, beta, eps)
+    }
+}
+
+#[test]
+fn compile_rejects_quantization_the_target_cannot_run() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_CPU_MEM", "1048576");
+    PluginBackends::global().register(PluginBackend::new("f32only", Arc::new(F32Only)));
+    let dir = tempdir().unwrap();
+    let weight_path = dir.path().join("weights.bin");
+    std::fs::write(&weight_path, [0u8; 64]).unwrap();
+    let config_path = dir.path().join("model.json");
+    let cfg = json!({ "name": "tiny", "weight_path": weight_path });
+    std::fs::write(&config_path, serde_json::to_vec(&cfg).unwrap()).unwrap();
+    let model = config_path.to_str().unwrap();
+
+    let options = CompileOptions {
+        output: None,
+        quantize: Some(Quantization::Int4),
+    };
+    let err = compile_model(model, "plugin:f32only", &options).unwrap_err();
+    assert!(err.to_string().contains("cannot run Int4"), "{err}");
+    compile_model(model, "plugin:f32only", &CompileOptions::default()).unwrap();
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use std::sync::Arc;
+
+use amduda::aurex_lm::embeddings::Pooling;
+use aurex_cli::server::{serve, ServerState};
+use aurex_runtime::session::SUMMARY_PREFIX;
+use aurex_runtime::{AurexConfig, Role};
+use serde_json::json;
+use tempfile::tempdir;
+
+use common::{post, write_model};
+
+#[test]
+fn long_sessions_are_summarized_to_fit_the_window() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let model = model.to_str().unwrap();
+    let config = AurexConfig::from_toml_str(
+        r#"
+        [backend]
+        preferred = "cpu"
+
+        [sessions]
+        context_window = 256
+        compress_at = 1.0
+        keep_messages = 2
+        summary_tokens = 8
+        "#,
+    )
+    .unwrap();
+    let state = Arc::new(ServerState::load(model, &config, Pooling::Mean).unwrap());
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let session = post(
+        addr,
+        "/v1/sessions",
+        &json!({ "system_prompt": "Be brief." }),
+    );
+    let id = session["id"].as_u64().unwrap();
+    let system = "system: Be brief.\n".len() as u64;
+    let mut compressed_turns = 0;
+    for turn in 0..8 {
+        let content = format!("Question number {turn} about paging");
+        let body = json!({
+            "session": id,
+            "messages": [{ "role": "user", "content": content }],
+            "max_tokens": 8,
+            "temperature": 0.0,
+        });
+        let reply = post(addr, "/v1/chat/completions", &body);
+        let prompt = reply["usage"]["prompt_tokens"].as_u64().unwrap();
+        assert!(prompt + 8 <= 256, "turn {turn}: {prompt} prompt tokens");
+        // After a summary only the system prompt is left in the KV cache.
+        let cached = reply["usage"]["prompt_tokens_details"]["cached_tokens"]
+            .as_u64()
+            .unwrap();
+        if cached == system {
+            compressed_turns += 1;
+        }
+    }
+    assert!(compressed_turns > 0);
+
+    let (messages, used) = state
+        .sessions
+        .with_session(id, |s| (s.messages().to_vec(), s.tokens_used()))
+        .unwrap();
+    assert_eq!(messages[0].role, Role::System);
+    assert!(messages[0].content.starts_with(SUMMARY_PREFIX));
+    assert_eq!(messages.last().unwrap().role, Role::Assistant);
+    assert!(messages.len() < 16);
+    assert!(used > 0);
+}
@@ -0,0 +1,333 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use amduda::aurex_lm::embeddings::Pooling;
+use aurex_cli::embed_texts;
+use aurex_cli::server::{serve, ServerState};
+use aurex_runtime::Runtime;
+use serde_json::{json, Value};
+use tempfile::tempdir;
+
+use common::{cpu, exchange, request, write_model};
+
+#[test]
+fn embed_returns_one_vector_per_text() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let texts = vec!["hello".to_string(), "world".to_string()];
+    let embeddings = embed_texts(model.to_str().unwrap(), &cpu(), &texts, Pooling::Mean).unwrap();
+    assert_eq!(embeddings.len(), 2);
+    assert!(embeddings.iter().all(|e| e.len() == 8));
+    assert_ne!(embeddings[0], embeddings[1]);
+
+    let missing = dir.path().join("missing.json");
+    assert!(embed_texts(missing.to_str().unwrap(), &cpu(), &texts, Pooling::Cls).is_err());
+}
+
+#[test]
+fn server_answers_embedding_requests() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let state = ServerState::load(model.to_str().unwrap(), &cpu(), Pooling::Mean).unwrap();
+    let expected = state.model().embeddings.embed("hi", Pooling::Cls);
+    let (addr, _handle) = serve(Arc::new(state), "127.0.0.1:0").unwrap();
+
+    let (status, body) = request(
+        addr,
+        "POST",
+        "/v1/embeddings",
+        r#"{"input": ["hi", "there"]}"#,
+    );
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["model"], "tiny");
+    assert_eq!(body["data"].as_array().unwrap().len(), 2);
+    assert_eq!(body["data"][1]["index"], 1);
+    assert_eq!(body["usage"]["prompt_tokens"], 3 + 6);
+
+    let (_, body) = request(
+        addr,
+        "POST",
+        "/v1/embeddings",
+        r#"{"input": "hi", "model": "tiny", "pooling": "cls"}"#,
+    );
+    let embedding: Vec<f32> = serde_json::from_value(body["data"][0]["embedding"].clone()).unwrap();
+    assert_eq!(embedding, expected);
+
+    let (status, _) = request(addr, "POST", "/v1/embeddings", r#"{"text": "hi"}"#);
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    let (status, _) = request(addr, "GET", "/v1/embeddings", "");
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    let (status, _) = request(addr, "POST", "/v1/chat", "{}");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[test]
+fn server_constrains_completions_to_json_schemas() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let state = ServerState::load(model.to_str().unwrap(), &cpu(), Pooling::Mean).unwrap();
+    let (addr, _handle) = serve(Arc::new(state), "127.0.0.1:0").unwrap();
+
+    let request_body = json!({
+        "prompt": "Is it raining?",
+        "max_tokens": 64,
+        "temperature": 1.0,
+        "seed": 5,
+        "response_format": {
+            "type": "json_schema",
+            "json_schema": {
+                "name": "answer",
+                "schema": {
+                    "type": "object",
+                    "properties": { "raining": { "type": "boolean" } },
+                    "required": ["raining"]
+                }
+            }
+        }
+    });
+    let (status, body) = request(addr, "POST", "/v1/completions", &request_body.to_string());
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["object"], "text_completion");
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    let text = body["choices"][0]["text"].as_str().unwrap();
+    let answer: Value = serde_json::from_str(text).unwrap();
+    assert!(answer["raining"].is_boolean(), "{text}");
+    assert_eq!(body["usage"]["prompt_tokens"], 14);
+
+    let (status, body) = request(
+        addr,
+        "POST",
+        "/v1/completions",
+        r#"{"prompt": "hi", "max_tokens": 3}"#,
+    );
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["choices"][0]["finish_reason"], "length");
+    assert_eq!(body["usage"]["completion_tokens"], 3);
+
+    let bad_schema = r#"{"response_format": {"type": "json_schema", "json_schema": {"schema": {"type": "tuple"}}}}"#;
+    let (status, _) = request(addr, "POST", "/v1/completions", bad_schema);
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+}
+
+#[test]
+fn server_keeps_multi_turn_sessions() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let state = ServerState::load(model.to_str().unwrap(), &cpu(), Pooling::Mean).unwrap();
+    let (addr, _handle) = serve(Arc::new(state), "127.0.0.1:0").unwrap();
+
+    let (status, body) = request(
+        addr,
+        "POST",
+        "/v1/sessions",
+        r#"{"system_prompt": "Be brief."}"#,
+    );
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let id = body["id"].as_u64().unwrap();
+
+    let turn = |session: Value, content: &str| {
+        let body = json!({
+            "session": session,
+            "messages": [{ "role": "user", "content": content }],
+            "max_tokens": 4,
+            "temperature": 0.0,
+        });
+        request(addr, "POST", "/v1/chat/completions", &body.to_string())
+    };
+    let (status, first) = turn(json!(id), "Hi");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(first["object"], "chat.completion");
+    assert_eq!(first["session"], id);
+    assert_eq!(first["choices"][0]["message"]["role"], "assistant");
+    let (status, second) = turn(json!(id), "Again");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    // The second turn reuses the KV cache of the first.
+    let cached = second["usage"]["prompt_tokens_details"]["cached_tokens"]
+        .as_u64()
+        .unwrap();
+    assert!(cached >= first["usage"]["prompt_tokens"].as_u64().unwrap());
+
+    let (status, fork) = request(addr, "POST", &format!("/v1/sessions/{id}/fork"), "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(fork["parent"], id);
+    let (status, _) = turn(fork["id"].clone(), "Fork");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+
+    let (status, _) = request(addr, "DELETE", &format!("/v1/sessions/{id}"), "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let (status, _) = turn(json!(id), "Gone?");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    let (status, _) = request(addr, "DELETE", &format!("/v1/sessions/{id}"), "");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+    let (_, body) = request(addr, "POST", "/v1/sessions", r#"{"max_tokens": 8}"#);
+    let limited = body["id"].clone();
+    let (status, _) = turn(limited.clone(), "Hello there");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let (status, _) = turn(limited, "And now?");
+    assert_eq!(status, "HTTP/1.1 429 Too Many Requests");
+
+    let (status, body) = turn(Value::Null, "No session");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(body["session"].is_null());
+}
+
+#[test]
+fn server_rejects_requests_it_cannot_admit() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let mut config = cpu();
+    config.admission.max_request_tokens = Some(64);
+    // No slots: every request small enough is turned away as overload.
+    config.admission.max_in_flight = Some(0);
+    config.admission.retry_after_ms = Some(1500);
+    let state = ServerState::load(model.to_str().unwrap(), &config, Pooling::Mean).unwrap();
+    let (addr, _handle) = serve(Arc::new(state), "127.0.0.1:0").unwrap();
+
+    let long = json!({ "prompt": "x".repeat(100), "max_tokens": 4 });
+    let (status, body) = request(addr, "POST", "/v1/completions", &long.to_string());
+    assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+    assert!(body["error"]["message"].as_str().unwrap().contains("104"));
+
+    let short = r#"{"prompt": "Hi", "max_tokens": 4}"#;
+    let (head, body) = exchange(addr, "POST", "/v1/completions", None, short);
+    assert!(head.starts_with("HTTP/1.1 503 Service Unavailable"));
+    assert!(head.contains("Retry-After: 2\r\n"));
+    assert_eq!(body["error"]["retry_after_ms"], 1500);
+    assert!(body["error"]["reason"]
+        .as_str()
+        .unwrap()
+        .contains("queue is full"));
+
+    let (status, _) = request(addr, "POST", "/v1/embeddings", r#"{"input": "Hi"}"#);
+    assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+    let chat = r#"{"messages": [{"role": "user", "content": "Hi"}]}"#;
+    let (status, _) = request(addr, "POST", "/v1/chat/completions", chat);
+    assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+    // Session management is not gated.
+    let (status, _) = request(addr, "POST", "/v1/sessions", "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+}
+
+#[test]
+fn server_queues_and_rejects_concurrent_requests() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let mut config = cpu();
+    config.admission.max_in_flight = Some(1);
+    config.admission.max_queued = Some(1);
+    let state =
+        Arc::new(ServerState::load(model.to_str().unwrap(), &config, Pooling::Mean).unwrap());
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+    let wait_for = |done: &dyn Fn() -> bool| {
+        let started = Instant::now();
+        while !done() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    };
+
+    // Holding the model's generator keeps the admitted request running.
+    let served = state.model();
+    let generator = served.generator.lock().unwrap();
+    let complete = |prompt: &'static str| {
+        std::thread::spawn(move || {
+            let body = json!({ "prompt": prompt, "max_tokens": 2 }).to_string();
+            request(addr, "POST", "/v1/completions", &body).0
+        })
+    };
+    let running = complete("first");
+    wait_for(&|| state.admission.in_flight() == 1);
+    let queued = complete("second");
+    wait_for(&|| state.admission.queued() == 1);
+
+    let (head, body) = exchange(
+        addr,
+        "POST",
+        "/v1/completions",
+        None,
+        r#"{"prompt": "third"}"#,
+    );
+    assert!(
+        head.starts_with("HTTP/1.1 503 Service Unavailable"),
+        "{head}"
+    );
+    assert!(body["error"]["reason"]
+        .as_str()
+        .unwrap()
+        .contains("queue is full"));
+    let (status, _) = request(addr, "GET", "/healthz", "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+
+    drop(generator);
+    assert_eq!(running.join().unwrap(), "HTTP/1.1 200 OK");
+    assert_eq!(queued.join().unwrap(), "HTTP/1.1 200 OK");
+    assert_eq!(state.admission.in_flight(), 0);
+}
+
+#[test]
+fn server_answers_while_a_client_stalls() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let state = ServerState::load(model.to_str().unwrap(), &cpu(), Pooling::Mean).unwrap();
+    let (addr, _handle) = serve(Arc::new(state), "127.0.0.1:0").unwrap();
+
+    // A client that never sends its body only holds up its own connection.
+    let mut stalled = TcpStream::connect(addr).unwrap();
+    write!(
+        stalled,
+        "POST /v1/embeddings HTTP/1.1\r\nContent-Length: 100\r\n\r\n{{"
+    )
+    .unwrap();
+    let (status, _) = request(addr, "GET", "/healthz", "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let (status, _) = request(addr, "POST", "/v1/embeddings", r#"{"input": "hi"}"#);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+
+    // The server reads at most 8 KiB of request line and headers; sending
+    // exactly that much leaves nothing unread when it answers.
+    let mut oversized = TcpStream::connect(addr).unwrap();
+    let mut head = "GET /healthz HTTP/1.1\r\nX-Padding: ".to_string();
+    head.push_str(&"x".repeat((8 << 10) - head.len()));
+    oversized.write_all(head.as_bytes()).unwrap();
+    let mut response = String::new();
+    oversized.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"),
+        "{response}"
+    );
+}
+
+#[test]
+fn warmed_up_servers_keep_their_answers() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let mut config = cpu();
+    config.scheduler.capture_plan = Some(true);
+    let state = ServerState::load(model.to_str().unwrap(), &config, Pooling::Mean).unwrap();
+    let expected = state.model().embeddings.embed("hi", Pooling::Mean);
+
+    let report = Runtime::from_config(&config).warmup(&*state.model());
+    let ops: Vec<_> = report
+        .plan
+        .ops
+        .iter()
+        .map(|p| (p.op, p.shape.clone()))
+        .collect();
+    assert_eq!(
+        ops,
+        vec![("matmul", vec![1, 257, 8]), ("layer_norm", vec![8])]
+    );
+    assert!(report.captured);
+    assert_eq!(state.model().dispatcher.captured_plan(), Some(report.plan));
+
+    let (addr, _handle) = serve(Arc::new(state), "127.0.0.1:0").unwrap();
+    let (status, body) = request(addr, "POST", "/v1/embeddings", r#"{"input": "hi"}"#);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let embedding: Vec<f32> = serde_json::from_value(body["data"][0]["embedding"].clone()).unwrap();
+    assert_eq!(embedding, expected);
+}
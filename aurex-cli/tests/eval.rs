@@ -0,0 +1,105 @@
+mod common;
+
+use amduda::aurex_lm::model_loader::Quantization;
+use aurex_backend::Precision;
+use aurex_cli::{compile_model, eval_model, CompileOptions, EvalOptions};
+use tempfile::tempdir;
+
+use common::{cpu, write_model};
+
+#[test]
+fn reports_perplexity_per_precision() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let dataset = dir.path().join("text.txt");
+    std::fs::write(&dataset, "the cat sat on the mat. the dog sat on the log.").unwrap();
+    let options = EvalOptions {
+        dataset: dataset.clone(),
+        window: 16,
+        ..EvalOptions::default()
+    };
+
+    let report = eval_model(model.to_str().unwrap(), &cpu(), &options).unwrap();
+    assert_eq!(report.tokens, 47);
+    assert_eq!(report.source_quantization, None);
+    let rows: Vec<(Precision, Option<Quantization>)> = report
+        .results
+        .iter()
+        .map(|r| (r.precision, r.quantization))
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            (Precision::F32, None),
+            (Precision::Bf16, Some(Quantization::Bf16)),
+            (Precision::Int8, Some(Quantization::Int8)),
+            (Precision::Int4, Some(Quantization::Int4)),
+        ]
+    );
+    for result in &report.results {
+        // Windows of 16, 16, 15 tokens score all but their first token.
+        assert_eq!(result.perplexity.tokens, 15 + 15 + 14);
+        assert!(result.perplexity.perplexity().is_finite());
+    }
+    assert_eq!(report.delta(&report.results[0]), Some(0.0));
+
+    let text = report.to_string();
+    assert!(text.contains("Dataset:"), "{text}");
+    assert!(text.contains("Source weights: f32"), "{text}");
+    assert!(text.lines().any(|l| l.starts_with("int4")), "{text}");
+    assert!(text.contains("+0.00%"), "{text}");
+}
+
+#[test]
+fn evaluates_quantized_bundle_and_limits_tokens() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let bundle = compile_model(
+        model.to_str().unwrap(),
+        "cpu",
+        &CompileOptions {
+            quantize: Some(Quantization::Int8),
+            ..CompileOptions::default()
+        },
+    )
+    .unwrap();
+    let dataset = dir.path().join("text.txt");
+    std::fs::write(&dataset, "abcdefghijklmnopqrstuvwxyz").unwrap();
+    let options = EvalOptions {
+        dataset,
+        precisions: vec![Precision::Int4],
+        window: 8,
+        max_tokens: Some(10),
+    };
+
+    let report = eval_model(bundle.to_str().unwrap(), &cpu(), &options).unwrap();
+    assert_eq!(report.tokens, 10);
+    assert_eq!(report.source_quantization, Some(Quantization::Int8));
+    assert_eq!(report.results.len(), 1);
+    assert_eq!(report.results[0].quantization, Some(Quantization::Int4));
+    assert_eq!(report.results[0].perplexity.tokens, 7 + 1);
+    // Without an f32 baseline there is nothing to compare against.
+    assert_eq!(report.delta(&report.results[0]), None);
+}
+
+#[test]
+fn rejects_missing_and_empty_datasets() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let missing = EvalOptions {
+        dataset: dir.path().join("missing.txt"),
+        ..EvalOptions::default()
+    };
+    let err = eval_model(model.to_str().unwrap(), &cpu(), &missing).unwrap_err();
+    assert!(err.to_string().contains("cannot read dataset"), "{err}");
+
+    let empty = dir.path().join("empty.txt");
+    std::fs::write(&empty, "a").unwrap();
+    let options = EvalOptions {
+        dataset: empty,
+        ..EvalOptions::default()
+    };
+    assert!(eval_model(model.to_str().unwrap(), &cpu(), &options).is_err());
+}
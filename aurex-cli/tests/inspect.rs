@@ -0,0 +1,90 @@
+use aurex_cli::{compile_model, inspect_model, CompileOptions};
+use amduda::aurex_lm::model_loader::Quantization;
+use aurex_runtime::config::{AurexConfig, MemoryConfig};
+use serde_json::json;
+use serial_test::serial;
+use tempfile::tempdir;
+
+fn write_model(dir: &std::path::Path, values: usize) -> std::path::PathBuf {
+    let weight_path = dir.join("weights.bin");
+    std::fs::write(&weight_path, vec![0u8; values * 4]).unwrap();
+    let config_path = dir.join("model.json");
+    let cfg = json!({ "name": "tiny", "weight_path": weight_path, "vocab_size": 10, "hidden_size": 4 });
+    std::fs::write(&config_path, serde_json::to_vec(&cfg).unwrap()).unwrap();
+    config_path
+}
+
+fn cpu_only(cpu_mem: usize) -> AurexConfig {
+    AurexConfig {
+        memory: MemoryConfig {
+            has_gpu: Some(false),
+            has_nvme: Some(false),
+            cpu_mem: Some(cpu_mem),
+            ..MemoryConfig::default()
+        },
+        ..AurexConfig::default()
+    }
+}
+
+#[test]
+#[serial]
+fn inspect_reports_tensors_and_footprint() {
+    let caps = cpu_only(1 << 20);
+    let dir = tempdir().unwrap();
+    let config = write_model(dir.path(), 42);
+
+    let report = inspect_model(config.to_str().unwrap(), &caps).unwrap();
+    assert_eq!(report.tensors.len(), 2);
+    assert_eq!(report.tensors[0].name, "tok_embeddings");
+    assert_eq!(report.tensors[0].shape, vec![10, 4]);
+    assert_eq!(report.tensors[0].dtype, "f32");
+    assert_eq!(report.tensors[1].shape, vec![2]);
+    assert_eq!(report.tiers, (0, 168, 0));
+    assert!(report.fits);
+    assert!(report.to_string().contains("Fits current device: yes"));
+    // Block intermediates share buffers instead of each taking its own.
+    assert!(report.activations.peak_bytes() > 0);
+    assert!(report.activations.peak_bytes() < report.activations.unplanned_bytes());
+    assert!(report.to_string().contains("B planned peak"));
+
+    let options = CompileOptions {
+        quantize: Some(Quantization::Int4),
+        ..CompileOptions::default()
+    };
+    let bundle = compile_model(config.to_str().unwrap(), "cpu", &options).unwrap();
+    let report = inspect_model(bundle.to_str().unwrap(), &caps).unwrap();
+    assert_eq!(report.tensors[0].dtype, "int4");
+    assert_eq!(report.tensors[0].bytes, 20);
+    assert_eq!(report.bundle.unwrap().target, "cpu");
+}
+
+#[test]
+#[serial]
+fn inspect_flags_models_exceeding_memory() {
+    let dir = tempdir().unwrap();
+    let config = write_model(dir.path(), 40);
+    let report = inspect_model(config.to_str().unwrap(), &cpu_only(64)).unwrap();
+    assert!(!report.fits);
+}
+
+#[test]
+#[serial]
+fn environment_overrides_config_file() {
+    for var in ["AMDUDA_HAS_GPU", "AMDUDA_HAS_NVME", "AMDUDA_GPU_MEM", "AMDUDA_NVME_MEM"] {
+        std::env::remove_var(var);
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("aurex.toml");
+    std::fs::write(&path, "[memory]\nhas_gpu = false\nhas_nvme = false\ncpu_mem = 64\n").unwrap();
+    std::env::set_var("AMDUDA_CPU_MEM", "1048576");
+    let layered = AurexConfig::load(Some(&path)).unwrap();
+    std::env::remove_var("AMDUDA_CPU_MEM");
+    assert_eq!(layered.memory.cpu_mem, Some(1 << 20));
+    assert_eq!(layered.memory.has_nvme, Some(false));
+
+    let model = write_model(dir.path(), 40);
+    assert!(inspect_model(model.to_str().unwrap(), &layered).unwrap().fits);
+    let file_only = AurexConfig::load(Some(&path)).unwrap();
+    assert!(!inspect_model(model.to_str().unwrap(), &file_only).unwrap().fits);
+    assert!(AurexConfig::load(Some(&dir.path().join("missing.toml"))).is_err());
+}
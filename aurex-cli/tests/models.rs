@@ -0,0 +1,166 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use amduda::aurex_lm::embeddings::Pooling;
+use aurex_cli::server::{serve, ModelStatus, ServedModel, ServerState};
+use serde_json::json;
+use tempfile::tempdir;
+
+use common::{cpu, request, write_named_model};
+
+fn loaded(state: &ServerState) -> Vec<String> {
+    state
+        .models()
+        .into_iter()
+        .filter(|model| model.loaded.is_some())
+        .map(|model| model.name)
+        .collect()
+}
+
+#[test]
+fn requests_are_routed_by_model_and_idle_models_make_room() {
+    let dir = tempdir().unwrap();
+    let first = write_named_model(dir.path(), "first", 31);
+    let second = write_named_model(dir.path(), "second", 17);
+    let third = write_named_model(dir.path(), "third", 13);
+    let bytes = ServedModel::load(first.to_str().unwrap(), &cpu(), 0)
+        .unwrap()
+        .bytes;
+
+    // Room for two models at a time.
+    let mut config = cpu();
+    config.memory.has_gpu = Some(false);
+    config.memory.cpu_mem = Some(2 * bytes + bytes / 2);
+    let state = ServerState::load(first.to_str().unwrap(), &config, Pooling::Mean)
+        .unwrap()
+        .with_model("second", second.to_str().unwrap())
+        .with_model("small", third.to_str().unwrap());
+    let state = Arc::new(state);
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let (status, body) = request(addr, "GET", "/v1/models", "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let ids: Vec<&str> = body["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|model| model["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, ["first", "second", "small"]);
+    assert_eq!(body["data"][0]["default"], true);
+    assert_eq!(body["data"][1]["loaded"], false);
+    assert_eq!(loaded(&state), ["first"]);
+
+    let embed = |model: &str| {
+        let body = json!({ "input": "hi", "model": model }).to_string();
+        request(addr, "POST", "/v1/embeddings", &body)
+    };
+    let (status, body) = embed("second");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["model"], "second");
+    let expected = ServedModel::load(second.to_str().unwrap(), &cpu(), 0)
+        .unwrap()
+        .embeddings
+        .embed("hi", Pooling::Mean);
+    let embedding: Vec<f32> = serde_json::from_value(body["data"][0]["embedding"].clone()).unwrap();
+    assert_eq!(embedding, expected);
+    assert_eq!(loaded(&state), ["first", "second"]);
+
+    // The third model only fits once the idle second one is unloaded; the
+    // default model stays.
+    let (_, body) = embed("small");
+    assert_eq!(body["model"], "small");
+    assert_eq!(loaded(&state), ["first", "small"]);
+
+    let (_, body) = request(addr, "POST", "/v1/embeddings", r#"{"input": "hi"}"#);
+    assert_eq!(body["model"], "first");
+    let (status, _) = embed("missing");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    let completion = json!({ "prompt": "Once", "model": "second", "max_tokens": 2 });
+    let (status, body) = request(addr, "POST", "/v1/completions", &completion.to_string());
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["model"], "second");
+}
+
+#[test]
+fn idle_models_unload_after_their_ttl() {
+    let dir = tempdir().unwrap();
+    let first = write_named_model(dir.path(), "first", 31);
+    let second = write_named_model(dir.path(), "second", 17);
+    let state = ServerState::load(first.to_str().unwrap(), &cpu(), Pooling::Mean)
+        .unwrap()
+        .with_model("second", second.to_str().unwrap())
+        .with_model("broken", dir.path().join("missing.json").to_str().unwrap())
+        .with_idle_ttl(Duration::ZERO);
+
+    let in_use = state.resolve(Some("second")).unwrap().unwrap();
+    state.resolve(None).unwrap();
+    assert_eq!(loaded(&state), ["first", "second"]);
+    drop(in_use);
+    assert_eq!(state.resolve(None).unwrap().unwrap().name, "first");
+    assert_eq!(
+        state.models(),
+        [
+            ModelStatus {
+                name: "broken".into(),
+                default: false,
+                loaded: None,
+            },
+            ModelStatus {
+                name: "first".into(),
+                default: true,
+                loaded: Some(state.model().bytes),
+            },
+            ModelStatus {
+                name: "second".into(),
+                default: false,
+                loaded: None,
+            },
+        ]
+    );
+
+    assert!(state.resolve(Some("unknown")).unwrap().is_none());
+    let err = state.resolve(Some("broken")).err().unwrap();
+    assert!(format!("{err:#}").contains("cannot load model broken"));
+}
+
+#[test]
+fn rejected_requests_do_not_load_models() {
+    let dir = tempdir().unwrap();
+    let first = write_named_model(dir.path(), "first", 31);
+    let second = write_named_model(dir.path(), "second", 17);
+    let mut config = cpu();
+    // No slots: every request is turned away as overload.
+    config.admission.max_in_flight = Some(0);
+    let state = ServerState::load(first.to_str().unwrap(), &config, Pooling::Mean)
+        .unwrap()
+        .with_model("second", second.to_str().unwrap());
+    let state = Arc::new(state);
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let requests = [
+        (
+            "/v1/embeddings",
+            json!({ "input": "hi", "model": "second" }),
+        ),
+        (
+            "/v1/completions",
+            json!({ "prompt": "Once", "model": "second", "max_tokens": 2 }),
+        ),
+        (
+            "/v1/chat/completions",
+            json!({
+                "messages": [{ "role": "user", "content": "hi" }],
+                "model": "second",
+                "max_tokens": 2,
+            }),
+        ),
+    ];
+    for (path, body) in requests {
+        let (status, _) = request(addr, "POST", path, &body.to_string());
+        assert_eq!(status, "HTTP/1.1 503 Service Unavailable", "{path}");
+    }
+    assert_eq!(loaded(&state), ["first"]);
+}
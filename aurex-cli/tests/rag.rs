@@ -0,0 +1,86 @@
+mod common;
+
+use amduda::aurex_lm::retrieval::{ChunkConfig, VectorIndex};
+use aurex_cli::{rag_ingest, rag_query, IngestOptions, RagQueryOptions};
+use tempfile::tempdir;
+
+use common::{cpu, write_named_model};
+
+#[test]
+fn ingested_documents_answer_queries() {
+    let dir = tempdir().unwrap();
+    let model = write_named_model(dir.path(), "tiny", 31);
+    let model = model.to_str().unwrap();
+    let attention = dir.path().join("attention.md");
+    std::fs::write(
+        &attention,
+        "Paged attention stores the KV cache in fixed size pages.\n\n\
+         Pages are shared between sequences with a common prefix.",
+    )
+    .unwrap();
+    let backends = dir.path().join("backends.md");
+    std::fs::write(&backends, "ROCm and Vulkan run kernels on GPUs.").unwrap();
+    let index = dir.path().join("docs.aurexri");
+    let options = IngestOptions {
+        chunk: ChunkConfig {
+            size: 64,
+            overlap: 0,
+        },
+        ..IngestOptions::default()
+    };
+
+    let report = rag_ingest(
+        model,
+        &cpu(),
+        &index,
+        std::slice::from_ref(&attention),
+        &options,
+    )
+    .unwrap();
+    assert_eq!((report.documents, report.chunks, report.total), (1, 2, 2));
+    let report = rag_ingest(model, &cpu(), &index, &[backends, attention], &options).unwrap();
+    // Ingesting a file again replaces its chunks.
+    assert_eq!((report.chunks, report.total), (3, 3));
+    assert_eq!(VectorIndex::load(&index).unwrap().model(), "tiny");
+
+    let query = "Paged attention stores the KV cache in fixed size pages.";
+    let retrieve = RagQueryOptions {
+        top_k: 2,
+        retrieve_only: true,
+        ..RagQueryOptions::default()
+    };
+    let answer = rag_query(model, &cpu(), &index, query, &retrieve, |_| {}).unwrap();
+    assert!(answer.output.is_none());
+    assert_eq!(answer.hits.len(), 2);
+    assert!(answer.hits[0].document.ends_with("attention.md"));
+    assert_eq!(answer.hits[0].start, 0);
+    assert!((answer.hits[0].score - 1.0).abs() < 1e-4);
+    assert!(answer.prompt.contains(&answer.hits[1].text));
+    assert!(answer
+        .prompt
+        .ends_with(&format!("Question: {query}\nAnswer:")));
+
+    let generate = RagQueryOptions {
+        max_tokens: 4,
+        temperature: 0.0,
+        retrieve_only: false,
+        ..retrieve
+    };
+    let mut streamed = String::new();
+    let answer = rag_query(model, &cpu(), &index, query, &generate, |t| {
+        streamed.push_str(t)
+    })
+    .unwrap();
+    let output = answer.output.unwrap();
+    assert!(output.tokens.len() <= 4);
+    assert_eq!(output.prompt_tokens, answer.prompt.len());
+    assert_eq!(streamed, output.text);
+
+    // Indexes belong to the model that built them.
+    let other = write_named_model(dir.path(), "other", 31);
+    let other = other.to_str().unwrap();
+    assert!(rag_query(other, &cpu(), &index, query, &retrieve, |_| {}).is_err());
+    assert!(rag_ingest(other, &cpu(), &index, &[], &options).is_err());
+    let missing = dir.path().join("missing.aurexri");
+    assert!(rag_query(model, &cpu(), &missing, query, &retrieve, |_| {}).is_err());
+}
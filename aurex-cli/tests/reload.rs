@@ -0,0 +1,129 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use amduda::aurex_lm::embeddings::Pooling;
+use aurex_cli::server::{serve, ServedModel, ServerState};
+use serde_json::{json, Value};
+use tempfile::tempdir;
+
+use common::{cpu, exchange, request, write_named_model};
+
+/// Poll the reload status until no reload is running.
+fn wait_for_reload(addr: SocketAddr, token: &str) -> Value {
+    for _ in 0..500 {
+        let (_, status) = exchange(addr, "GET", "/admin/reload", Some(token), "");
+        if status["reloading"] == false {
+            return status;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("reload did not finish");
+}
+
+#[test]
+fn health_and_readiness_follow_the_server_state() {
+    let dir = tempdir().unwrap();
+    let model = write_named_model(dir.path(), "tiny", 31);
+    let state =
+        Arc::new(ServerState::load(model.to_str().unwrap(), &cpu(), Pooling::Mean).unwrap());
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let (status, body) = request(addr, "GET", "/healthz", "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["status"], "ok");
+    let (status, body) = request(addr, "GET", "/readyz", "");
+    assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+    assert_eq!(body["status"], "starting");
+
+    state.set_ready(true);
+    let (status, body) = request(addr, "GET", "/readyz", "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["model"], "tiny");
+    assert_eq!(body["generation"], 0);
+    let (status, _) = request(addr, "POST", "/healthz", "");
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+
+    // Without an admin token only loopback clients may reload.
+    let (status, _) = request(addr, "GET", "/admin/reload", "");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+}
+
+#[test]
+fn reloads_switch_models_without_dropping_requests() {
+    let dir = tempdir().unwrap();
+    let first = write_named_model(dir.path(), "first", 31);
+    let second = write_named_model(dir.path(), "second", 17);
+    let state = ServerState::load(first.to_str().unwrap(), &cpu(), Pooling::Mean)
+        .unwrap()
+        .with_admin_token("secret");
+    let state = Arc::new(state);
+    state.set_ready(true);
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let (_, session) = request(addr, "POST", "/v1/sessions", "");
+    let turn = json!({
+        "session": session["id"],
+        "messages": [{ "role": "user", "content": "hello there" }],
+        "max_tokens": 2,
+    })
+    .to_string();
+    request(addr, "POST", "/v1/chat/completions", &turn);
+
+    let reload = json!({ "model": second }).to_string();
+    let (status, _) = request(addr, "POST", "/admin/reload", &reload);
+    assert_eq!(status, "HTTP/1.1 403 Forbidden");
+    let (head, _) = exchange(addr, "POST", "/admin/reload", Some("secre"), &reload);
+    assert!(head.starts_with("HTTP/1.1 403 Forbidden"), "{head}");
+    let (head, body) = exchange(addr, "POST", "/admin/reload", Some("secret"), &reload);
+    assert!(head.starts_with("HTTP/1.1 202 Accepted"), "{head}");
+    assert_eq!(body["status"], "reloading");
+    let status = wait_for_reload(addr, "secret");
+    assert_eq!(status["model"], "second");
+    assert_eq!(status["generation"], 1);
+    assert_eq!(status["reloads"], 1);
+
+    let expected = ServedModel::load(second.to_str().unwrap(), &cpu(), 0)
+        .unwrap()
+        .embeddings
+        .embed("hi", Pooling::Mean);
+    let (_, body) = request(addr, "POST", "/v1/embeddings", r#"{"input": "hi"}"#);
+    assert_eq!(body["model"], "second");
+    let embedding: Vec<f32> = serde_json::from_value(body["data"][0]["embedding"].clone()).unwrap();
+    assert_eq!(embedding, expected);
+
+    // The session's cache came from the first model and is recomputed.
+    let (status, body) = request(addr, "POST", "/v1/chat/completions", &turn);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body["usage"]["prompt_tokens_details"]["cached_tokens"], 0);
+
+    // A failed reload keeps the served model.
+    let missing = json!({ "model": dir.path().join("missing.json") }).to_string();
+    exchange(addr, "POST", "/admin/reload", Some("secret"), &missing);
+    let status = wait_for_reload(addr, "secret");
+    assert_eq!(status["model"], "second");
+    assert!(status["last_error"].as_str().unwrap().contains("missing"));
+    let (_, body) = request(addr, "GET", "/readyz", "");
+    assert_eq!(body["generation"], 1);
+}
+
+#[test]
+fn reloads_wait_for_requests_on_the_previous_model() {
+    let dir = tempdir().unwrap();
+    let model = write_named_model(dir.path(), "tiny", 31);
+    let state = ServerState::load(model.to_str().unwrap(), &cpu(), Pooling::Mean).unwrap();
+
+    let in_flight = state.model();
+    let request = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        drop(in_flight);
+    });
+    let report = state.reload(None).unwrap();
+    request.join().unwrap();
+    assert!(report.drained);
+    assert_eq!((report.model.as_str(), report.generation), ("tiny", 1));
+    assert_eq!(state.model().generation, 1);
+    assert_eq!(state.reload_status().reloads, 1);
+}
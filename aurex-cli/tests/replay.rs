@@ -0,0 +1,78 @@
+mod common;
+
+use std::sync::Arc;
+
+use amduda::aurex_lm::embeddings::Pooling;
+use aurex_cli::replay_log;
+use aurex_cli::request_log::{replay, RequestLog};
+use aurex_cli::server::{serve, ServerState};
+use serde_json::json;
+use tempfile::tempdir;
+
+use common::{cpu, post, request, write_model};
+
+#[test]
+fn logged_requests_replay_with_the_same_outputs() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let model = model.to_str().unwrap();
+    let log_path = dir.path().join("requests.jsonl");
+    let state = ServerState::load(model, &cpu(), Pooling::Mean)
+        .unwrap()
+        .with_request_log(RequestLog::create(&log_path).unwrap());
+    let (addr, _handle) = serve(Arc::new(state), "127.0.0.1:0").unwrap();
+
+    let completion = json!({ "prompt": "Once upon", "max_tokens": 6, "seed": 3, "stop": "." });
+    post(addr, "/v1/completions", &completion);
+    post(addr, "/v1/embeddings", &json!({ "input": ["hi", "there"] }));
+    let chat = json!({
+        "messages": [{ "role": "user", "content": "hello" }],
+        "max_tokens": 4,
+        "temperature": 0.0,
+    });
+    post(addr, "/v1/chat/completions", &chat);
+    let session = post(addr, "/v1/sessions", &json!({}));
+    let chat_in_session = json!({
+        "session": session["id"],
+        "messages": [{ "role": "user", "content": "hello" }],
+    });
+    post(addr, "/v1/chat/completions", &chat_in_session);
+    let (status, _) = request(addr, "POST", "/v1/completions", r#"{"prompt": 5}"#);
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+    // Session management is not logged.
+    let records = RequestLog::read(&log_path).unwrap();
+    assert_eq!(records.len(), 5);
+    let first = &records[0];
+    assert_eq!(first.path, "/v1/completions");
+    assert_eq!(first.body, completion);
+    assert_eq!(first.backend, "cpu");
+    assert_eq!(first.status, "200 OK");
+    let options = first.options.as_ref().unwrap();
+    assert_eq!(options.max_tokens, Some(6));
+    assert_eq!(options.sampler.seed, Some(3));
+    assert_eq!(options.stop, ["."]);
+    let timings = first.timings.unwrap();
+    assert!(timings.tokens > 0 && timings.tokens <= 6);
+    assert!(timings.first_token_ms.unwrap() <= first.latency_ms);
+    assert_eq!(records[1].options, None);
+    assert_eq!(records[1].timings, None);
+    assert_ne!(records[0].prompt_hash, records[1].prompt_hash);
+    assert_eq!(records[2].prompt_hash, records[3].prompt_hash);
+    assert_eq!(records[4].status, "400 Bad Request");
+
+    let report = replay_log(model, &cpu(), &log_path, Pooling::Mean).unwrap();
+    assert_eq!(report.backend, "cpu");
+    assert_eq!(report.results.len(), 4);
+    assert_eq!(report.skipped, 1);
+    assert_eq!(report.mismatches(), 0, "{report}");
+    assert!(report.to_string().contains("0 differ"));
+
+    // A changed output is reported.
+    let mut records = records;
+    records[0].output_hash = "0".repeat(16);
+    let state = ServerState::load(model, &cpu(), Pooling::Mean).unwrap();
+    let report = replay(&state, &records);
+    assert_eq!(report.mismatches(), 1);
+    assert!(!report.results[0].matched);
+}
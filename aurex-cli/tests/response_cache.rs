@@ -0,0 +1,82 @@
+mod common;
+
+use std::sync::Arc;
+
+use amduda::aurex_lm::embeddings::Pooling;
+use aurex_cli::server::{serve, ServerState};
+use aurex_runtime::{AurexConfig, CacheStats};
+use serde_json::json;
+use tempfile::tempdir;
+
+use common::{post, write_model};
+
+#[test]
+fn repeated_requests_are_answered_from_the_cache() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let model = model.to_str().unwrap();
+    let config = AurexConfig::from_toml_str(
+        r#"
+        [backend]
+        preferred = "cpu"
+
+        [response_cache]
+        max_entries = 8
+        "#,
+    )
+    .unwrap();
+    let state = Arc::new(ServerState::load(model, &config, Pooling::Mean).unwrap());
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let completion = json!({ "prompt": "Once upon", "max_tokens": 6, "seed": 3 });
+    let first = post(addr, "/v1/completions", &completion);
+    let generated = state.usage.key(None);
+    assert_eq!(post(addr, "/v1/completions", &completion), first);
+    assert_eq!(state.responses.stats().hits, 1);
+    // A hit charges the tokens it reports but no GPU time.
+    let repeated = state.usage.key(None);
+    assert_eq!(repeated.requests, 2);
+    assert_eq!(repeated.tokens(), 2 * generated.tokens());
+    assert_eq!(repeated.gpu_seconds, generated.gpu_seconds);
+
+    // Other options or formats are generated anew.
+    post(
+        addr,
+        "/v1/completions",
+        &json!({ "prompt": "Once upon", "max_tokens": 6 }),
+    );
+    let json_object = json!({
+        "prompt": "Once upon",
+        "max_tokens": 6,
+        "seed": 3,
+        "response_format": { "type": "json_object" },
+    });
+    post(addr, "/v1/completions", &json_object);
+    assert_eq!(state.responses.stats().hits, 1);
+
+    let chat = json!({
+        "messages": [{ "role": "user", "content": "hello" }],
+        "max_tokens": 4,
+    });
+    let reply = post(addr, "/v1/chat/completions", &chat);
+    assert_eq!(post(addr, "/v1/chat/completions", &chat), reply);
+
+    // Turns of a session are never cached.
+    let session = post(addr, "/v1/sessions", &json!({}));
+    let turn = json!({
+        "session": session["id"],
+        "messages": [{ "role": "user", "content": "hello" }],
+        "max_tokens": 4,
+    });
+    post(addr, "/v1/chat/completions", &turn);
+    assert_eq!(
+        state.responses.stats(),
+        CacheStats {
+            hits: 2,
+            misses: 4,
+            entries: 4,
+            bytes: state.responses.stats().bytes,
+            evictions: 0,
+        }
+    );
+}
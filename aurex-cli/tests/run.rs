@@ -0,0 +1,135 @@
+mod common;
+
+use aurex_cli::{compile_model, run_model, CompileOptions, RunOptions};
+use aurex_backend::{Backend, Precision};
+use aurex_runtime::AurexConfig;
+use serde_json::json;
+use tempfile::tempdir;
+
+use common::write_model;
+
+#[test]
+fn run_generates_from_config_and_bundle() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    std::env::set_var("AMDUDA_CPU_MEM", "1048576");
+    let dir = tempdir().unwrap();
+    let config = write_model(dir.path());
+    let mut cpu = AurexConfig::default();
+    cpu.backend.preferred = Some(Backend::Cpu);
+    let options = RunOptions {
+        prompt: "Hi".into(),
+        max_tokens: 6,
+        temperature: 0.0,
+        ..RunOptions::default()
+    };
+
+    let mut streamed = String::new();
+    let from_config = run_model(config.to_str().unwrap(), &cpu, &options, |t| {
+        streamed.push_str(t)
+    })
+    .unwrap();
+    assert_eq!(from_config.prompt_tokens, 2);
+    assert!(from_config.tokens.len() <= 6);
+    assert_eq!(streamed, from_config.text);
+
+    let bundle = compile_model(config.to_str().unwrap(), "cpu", &CompileOptions::default()).unwrap();
+    let from_bundle = run_model(bundle.to_str().unwrap(), &cpu, &options, |_| {}).unwrap();
+    assert_eq!(from_bundle.tokens, from_config.tokens);
+
+    let mut int8 = cpu.clone();
+    int8.backend.precision = Some(Precision::Int8);
+    assert!(run_model(bundle.to_str().unwrap(), &int8, &options, |_| {}).is_ok());
+    let missing = dir.path().join("missing.aurexc");
+    assert!(run_model(missing.to_str().unwrap(), &cpu, &options, |_| {}).is_err());
+}
+
+#[test]
+fn run_follows_grammar_and_stop_strings() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let dir = tempdir().unwrap();
+    let config = write_model(dir.path());
+    let mut cpu = AurexConfig::default();
+    cpu.backend.preferred = Some(Backend::Cpu);
+
+    let options = RunOptions {
+        prompt: "Hi".into(),
+        max_tokens: 32,
+        grammar: Some(r#"root ::= "yes" | "no""#.into()),
+        ..RunOptions::default()
+    };
+    let out = run_model(config.to_str().unwrap(), &cpu, &options, |_| {}).unwrap();
+    assert!(out.text == "yes" || out.text == "no", "{}", out.text);
+
+    let options = RunOptions {
+        grammar: Some(r#"root ::= "ab" "c"*"#.into()),
+        stop: vec!["bc".into()],
+        ..options
+    };
+    let out = run_model(config.to_str().unwrap(), &cpu, &options, |_| {}).unwrap();
+    assert!(out.text == "a" || out.text == "ab", "{}", out.text);
+
+    let options = RunOptions {
+        grammar: Some("root ::= missing".into()),
+        ..options
+    };
+    assert!(run_model(config.to_str().unwrap(), &cpu, &options, |_| {}).is_err());
+}
+
+#[test]
+fn run_places_an_image_before_the_prompt() {
+    std::env::set_var("AMDUDA_HAS_GPU", "0");
+    let dir = tempdir().unwrap();
+    let hidden = 4;
+    // 257 x 4 embedding table, then an 8x8 patch embedding with 4x4 patches:
+    // a 4 x 48 kernel, 4 biases and 4 x 4 position embeddings.
+    let count = 257 * hidden + (48 + 1 + 4) * hidden;
+    let weights: Vec<u8> = (0..count)
+        .map(|i| ((i * 31 % 97) as f32 / 48.0) - 1.0)
+        .flat_map(|v: f32| v.to_le_bytes())
+        .collect();
+    let weight_path = dir.path().join("weights.bin");
+    std::fs::write(&weight_path, weights).unwrap();
+    let config_path = dir.path().join("vlm.json");
+    let cfg = json!({
+        "name": "vlm",
+        "weight_path": weight_path,
+        "hidden_size": hidden,
+        "vision": { "image_size": 8, "patch_size": 4 }
+    });
+    std::fs::write(&config_path, serde_json::to_vec(&cfg).unwrap()).unwrap();
+
+    let image_path = dir.path().join("image.png");
+    let file = std::io::BufWriter::new(std::fs::File::create(&image_path).unwrap());
+    let mut encoder = png::Encoder::new(file, 3, 2);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels: Vec<u8> = (0..18).map(|i| i * 14).collect();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&pixels).unwrap();
+    writer.finish().unwrap();
+
+    let mut cpu = AurexConfig::default();
+    cpu.backend.preferred = Some(Backend::Cpu);
+    let options = RunOptions {
+        prompt: "Hi".into(),
+        max_tokens: 4,
+        temperature: 0.0,
+        image: Some(image_path.clone()),
+        ..RunOptions::default()
+    };
+    let model = config_path.to_str().unwrap();
+    let out = run_model(model, &cpu, &options, |_| {}).unwrap();
+    assert_eq!(out.prompt_tokens, 2);
+    let bundle = compile_model(model, "cpu", &CompileOptions::default()).unwrap();
+    let from_bundle = run_model(bundle.to_str().unwrap(), &cpu, &options, |_| {}).unwrap();
+    assert_eq!(from_bundle.tokens, out.tokens);
+
+    // Text-only models and unreadable images are rejected.
+    let text_only = write_model(dir.path());
+    assert!(run_model(text_only.to_str().unwrap(), &cpu, &options, |_| {}).is_err());
+    let options = RunOptions {
+        image: Some(dir.path().join("missing.png")),
+        ..options
+    };
+    assert!(run_model(model, &cpu, &options, |_| {}).is_err());
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use std::sync::Arc;
+
+use amduda::aurex_lm::embeddings::Pooling;
+use aurex_cli::server::{serve, ServerState};
+use aurex_runtime::AurexConfig;
+use serde_json::{json, Value};
+use tempfile::tempdir;
+
+use common::{exchange, write_model};
+
+#[test]
+fn usage_is_reported_per_key_and_session_and_capped() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let model = model.to_str().unwrap();
+    let config = AurexConfig::from_toml_str(
+        r#"
+        [backend]
+        preferred = "cpu"
+
+        [auth]
+        keys = [
+            { name = "ci", key = "sk-ci" },
+            { name = "trial", key = "sk-trial", token_quota = 10 },
+        ]
+        "#,
+    )
+    .unwrap();
+    let state = Arc::new(ServerState::load(model, &config, Pooling::Mean).unwrap());
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let completion = json!({ "prompt": "Once upon", "max_tokens": 4 }).to_string();
+    let (_, completion) = exchange(addr, "POST", "/v1/completions", Some("sk-ci"), &completion);
+    let (_, session) = exchange(addr, "POST", "/v1/sessions", Some("sk-ci"), "{}");
+    let turn = json!({
+        "session": session["id"],
+        "messages": [{ "role": "user", "content": "hello" }],
+        "max_tokens": 3,
+    })
+    .to_string();
+    let (_, reply) = exchange(addr, "POST", "/v1/chat/completions", Some("sk-ci"), &turn);
+
+    let (head, report) = exchange(addr, "GET", "/admin/usage", None, "");
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+    let ci = &report["keys"]["ci"];
+    assert_eq!(ci["requests"], 2);
+    let prompt_tokens = completion["usage"]["prompt_tokens"].as_u64().unwrap()
+        + reply["usage"]["prompt_tokens"].as_u64().unwrap();
+    let generated_tokens = completion["usage"]["completion_tokens"].as_u64().unwrap()
+        + reply["usage"]["completion_tokens"].as_u64().unwrap();
+    assert_eq!(ci["prompt_tokens"], prompt_tokens);
+    assert_eq!(ci["generated_tokens"], generated_tokens);
+    assert!(ci["gpu_seconds"].as_f64().unwrap() > 0.0);
+    assert_eq!(ci["token_quota"], Value::Null);
+    let id = session["id"].to_string();
+    assert_eq!(report["sessions"][&id]["requests"], 1);
+    assert_eq!(
+        report["sessions"][&id]["generated_tokens"],
+        reply["usage"]["completion_tokens"]
+    );
+
+    // The trial key may finish the request crossing its quota, but no more.
+    let long = json!({ "input": "a longer text than ten tokens of quota" }).to_string();
+    let (head, _) = exchange(addr, "POST", "/v1/embeddings", Some("sk-trial"), &long);
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+    let (head, body) = exchange(addr, "POST", "/v1/embeddings", Some("sk-trial"), &long);
+    assert!(head.starts_with("HTTP/1.1 429 Too Many Requests"), "{head}");
+    assert_eq!(body["error"]["token_quota"], 10);
+    assert_eq!(state.usage.key(Some("trial")).requests, 1);
+
+    // Ending a session drops its counters but not the key's.
+    let end = format!("/v1/sessions/{id}");
+    exchange(addr, "DELETE", &end, Some("sk-ci"), "");
+    assert!(state.usage.sessions().is_empty());
+    assert_eq!(state.usage.key(Some("ci")).requests, 2);
+}
+
+#[test]
+fn session_summaries_are_charged_to_the_key() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let model = model.to_str().unwrap();
+    let config = AurexConfig::from_toml_str(
+        r#"
+        [backend]
+        preferred = "cpu"
+
+        [auth]
+        keys = [{ name = "ci", key = "sk-ci" }]
+
+        [sessions]
+        context_window = 256
+        compress_at = 1.0
+        keep_messages = 2
+        summary_tokens = 8
+        "#,
+    )
+    .unwrap();
+    let state = Arc::new(ServerState::load(model, &config, Pooling::Mean).unwrap());
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let (_, session) = exchange(addr, "POST", "/v1/sessions", Some("sk-ci"), "{}");
+    let id = session["id"].as_u64().unwrap();
+    // Tokens of the replies as reported, and as computed.
+    let (mut reported, mut computed) = (0, 0);
+    for turn in 0..8 {
+        let content = format!("Question number {turn} about paging");
+        let body = json!({
+            "session": id,
+            "messages": [{ "role": "user", "content": content }],
+            "max_tokens": 8,
+            "temperature": 0.0,
+        })
+        .to_string();
+        let (head, reply) = exchange(addr, "POST", "/v1/chat/completions", Some("sk-ci"), &body);
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+        let usage = &reply["usage"];
+        let prompt = usage["prompt_tokens"].as_u64().unwrap();
+        let generated = usage["completion_tokens"].as_u64().unwrap();
+        let cached = usage["prompt_tokens_details"]["cached_tokens"]
+            .as_u64()
+            .unwrap();
+        reported += prompt + generated;
+        computed += prompt - cached + generated;
+    }
+
+    // The session is charged for its summaries on top of its replies, and
+    // so is the key.
+    let used = state
+        .sessions
+        .with_session(id, |s| s.tokens_used())
+        .unwrap() as u64;
+    let summaries = used - computed;
+    assert!(summaries > 0);
+    let ci = state.usage.key(Some("ci"));
+    assert_eq!(ci.requests, 8);
+    assert_eq!(ci.tokens(), reported + summaries);
+    assert_eq!(
+        state.usage.session(id).unwrap().tokens(),
+        reported + summaries
+    );
+}
+
+#[test]
+fn sessions_are_only_found_with_the_key_that_started_them() {
+    let dir = tempdir().unwrap();
+    let model = write_model(dir.path());
+    let model = model.to_str().unwrap();
+    let config = AurexConfig::from_toml_str(
+        r#"
+        [backend]
+        preferred = "cpu"
+
+        [auth]
+        keys = [
+            { name = "ci", key = "sk-ci" },
+            { name = "other", key = "sk-other" },
+        ]
+        "#,
+    )
+    .unwrap();
+    let state = Arc::new(ServerState::load(model, &config, Pooling::Mean).unwrap());
+    let (addr, _handle) = serve(state.clone(), "127.0.0.1:0").unwrap();
+
+    let (_, session) = exchange(addr, "POST", "/v1/sessions", Some("sk-ci"), "{}");
+    let id = session["id"].as_u64().unwrap();
+    let turn = json!({
+        "session": id,
+        "messages": [{ "role": "user", "content": "hello" }],
+        "max_tokens": 3,
+    })
+    .to_string();
+    let fork = format!("/v1/sessions/{id}/fork");
+    let end = format!("/v1/sessions/{id}");
+    let (head, _) = exchange(
+        addr,
+        "POST",
+        "/v1/chat/completions",
+        Some("sk-other"),
+        &turn,
+    );
+    assert!(head.starts_with("HTTP/1.1 404 Not Found"), "{head}");
+    let (head, _) = exchange(addr, "POST", &fork, Some("sk-other"), "{}");
+    assert!(head.starts_with("HTTP/1.1 404 Not Found"), "{head}");
+    let (head, _) = exchange(addr, "DELETE", &end, Some("sk-other"), "{}");
+    assert!(head.starts_with("HTTP/1.1 404 Not Found"), "{head}");
+    assert_eq!(state.usage.key(Some("other")).requests, 0);
+
+    let (head, _) = exchange(addr, "POST", "/v1/chat/completions", Some("sk-ci"), &turn);
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+    let (head, _) = exchange(addr, "POST", &fork, Some("sk-ci"), "{}");
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+    let (head, _) = exchange(addr, "DELETE", &end, Some("sk-ci"), "{}");
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+}
@@ -0,0 +1,16 @@
+use amduda::hal_backends::verification::VerifyConfig;
+use aurex_cli::verify_backend;
+
+#[test]
+fn verifies_named_and_all_backends() {
+    let config = VerifyConfig::new().with_cases(4).with_max_dim(8);
+
+    let reports = verify_backend("riscv", &config).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].passed(), "{}", reports[0]);
+    assert!(reports[0].to_string().starts_with("Backend riscv: PASS"));
+
+    let all = verify_backend("all", &config).unwrap();
+    assert!(all.iter().any(|r| r.backend == "cpu"));
+    assert!(verify_backend("tpu", &config).is_err());
+}
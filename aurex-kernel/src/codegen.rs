@@ -0,0 +1,484 @@
+//! Compute shader generation from the graph IR.
+//!
+//! [`lower`] turns a node of a [`Graph`], fused or not, into the source of a
+//! compute shader in GLSL for Vulkan or WGSL for WebGPU.  The dimensions of
+//! the node are baked into the source, so a shader only runs the shapes it
+//! was generated for and its name says which.  Node inputs are bound to
+//! storage buffers `0..` in input order and the output to the binding after
+//! them.  Every invocation computes one output element, or one row for ops
+//! reducing over rows, in workgroups of [`WORKGROUP_SIZE`].
+//!
+//! Inputs and constants are bound by the host and have no shader.
+
+use std::fmt::Write;
+
+use crate::graph::{BinaryOp, ElementwiseStep, Graph, Node, Op, TensorId, UnaryOp};
+
+/// Invocations per workgroup of the generated shaders.
+pub const WORKGROUP_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    /// GLSL 450 for Vulkan, compiled to SPIR-V with shaderc.
+    Glsl,
+    /// WGSL for WebGPU.
+    Wgsl,
+}
+
+/// Generated compute shader of one node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shader {
+    /// Kernel name of the op followed by the shapes of its inputs, e.g.
+    /// `matmul_bias_2x3_3x4_4`.
+    pub name: String,
+    pub language: ShaderLanguage,
+    pub source: String,
+    /// Invocations to dispatch: output elements, or rows for row-wise ops.
+    pub invocations: usize,
+}
+
+impl Shader {
+    /// Workgroups covering all invocations.
+    pub fn workgroups(&self) -> usize {
+        self.invocations.div_ceil(WORKGROUP_SIZE)
+    }
+}
+
+/// Shader computing the node `id` of `graph`, `None` for inputs and
+/// constants.
+pub fn lower(graph: &Graph, id: TensorId, language: ShaderLanguage) -> Option<Shader> {
+    let node = graph.node(id);
+    let shapes: Vec<&[usize]> = node
+        .inputs
+        .iter()
+        .map(|input| graph.node(*input).shape.as_slice())
+        .collect();
+    let mut name = node.op.name();
+    for shape in &shapes {
+        name.push('_');
+        let dims: Vec<String> = shape.iter().map(usize::to_string).collect();
+        name.push_str(&dims.join("x"));
+    }
+
+    let mut w = Writer::new(language);
+    let invocations = body(&mut w, node, &shapes)?;
+    Some(Shader {
+        name,
+        language,
+        source: w.finish(shapes.len(), invocations),
+        invocations,
+    })
+}
+
+/// Shaders of the live nodes of `graph` in execution order, one per
+/// distinct kernel.  Kernels of the same name whose sources differ, e.g. by
+/// their epsilon, get a numeric suffix from the second on.
+pub fn lower_graph(graph: &Graph, language: ShaderLanguage) -> Vec<Shader> {
+    let live = graph.live();
+    let mut shaders = Vec::new();
+    // Name before suffixing and source of every shader so far.
+    let mut seen: Vec<(String, String)> = Vec::new();
+    for (i, _) in live.iter().enumerate().filter(|(_, live)| **live) {
+        let Some(mut shader) = lower(graph, TensorId(i), language) else {
+            continue;
+        };
+        if seen.contains(&(shader.name.clone(), shader.source.clone())) {
+            continue;
+        }
+        let variants = seen.iter().filter(|(name, _)| *name == shader.name).count();
+        seen.push((shader.name.clone(), shader.source.clone()));
+        if variants > 0 {
+            shader.name = format!("{}_{variants}", shader.name);
+        }
+        shaders.push(shader);
+    }
+    shaders
+}
+
+/// Emit the statements computing `node` for invocation `i`, returning the
+/// number of invocations.
+fn body(w: &mut Writer, node: &Node, shapes: &[&[usize]]) -> Option<usize> {
+    let len = node.len();
+    let row = node.shape.last().copied().unwrap_or(1).max(1);
+    let rows = len / row;
+    match &node.op {
+        Op::Input(_) | Op::Constant(_) => None,
+        Op::MatMul | Op::FusedMatMul { .. } => {
+            let (k, n) = (shapes[0][1], shapes[1][1]);
+            w.index("row", &format!("i / {n}u"));
+            w.index("col", &format!("i % {n}u"));
+            w.var("acc", "0.0");
+            w.begin_for("p", k);
+            w.line(&format!(
+                "acc += in0[row * {k}u + p] * in1[p * {n}u + col];"
+            ));
+            w.end();
+            if let Op::FusedMatMul { bias, activation } = &node.op {
+                if *bias {
+                    w.line("acc += in2[col];");
+                }
+                if let Some(act) = activation {
+                    w.line(&format!("acc = {};", unary(*act, "acc")));
+                }
+            }
+            w.line("result[i] = acc;");
+            Some(len)
+        }
+        Op::Unary(op) => {
+            w.line(&format!("result[i] = {};", unary(*op, "in0[i]")));
+            Some(len)
+        }
+        Op::Binary(op) => {
+            let (a, b) = (load(0, shapes, len), load(1, shapes, len));
+            w.line(&format!("result[i] = {a} {} {b};", symbol(*op)));
+            Some(len)
+        }
+        Op::FusedElementwise(steps) => {
+            for (r, step) in steps.iter().enumerate() {
+                let value = match *step {
+                    ElementwiseStep::Load(input) => load(input, shapes, len),
+                    ElementwiseStep::Unary(op, x) => unary(op, &format!("r{x}")),
+                    ElementwiseStep::Binary(op, a, b) => format!("r{a} {} r{b}", symbol(op)),
+                };
+                w.value(&format!("r{r}"), &value);
+            }
+            w.line(&format!("result[i] = r{};", steps.len() - 1));
+            Some(len)
+        }
+        Op::Transpose => {
+            let (m, n) = (shapes[0][0], shapes[0][1]);
+            w.line(&format!("result[i] = in0[(i % {m}u) * {n}u + i / {m}u];"));
+            Some(len)
+        }
+        Op::Attention => {
+            // The backend primitive scales `v` by the dot product of `q`
+            // and `k` over the last dimension.
+            let dim = shapes[0].last().copied().unwrap_or(1);
+            let q_len: usize = shapes[0].iter().product();
+            w.var("score", "0.0");
+            w.begin_for("p", q_len);
+            w.line("score += in0[p] * in1[p];");
+            w.end();
+            w.line(&format!(
+                "result[i] = in2[i] * (score / {});",
+                float(dim as f32)
+            ));
+            Some(len)
+        }
+        Op::Softmax => {
+            w.index("base", &format!("i * {row}u"));
+            w.var("top", "in0[base]");
+            w.begin_for("j", row);
+            w.line("top = max(top, in0[base + j]);");
+            w.end();
+            w.var("total", "0.0");
+            w.begin_for("j", row);
+            w.value("e", "exp(in0[base + j] - top)");
+            w.line("result[base + j] = e;");
+            w.line("total += e;");
+            w.end();
+            w.begin_for("j", row);
+            w.line("result[base + j] = result[base + j] / total;");
+            w.end();
+            Some(rows)
+        }
+        Op::LayerNorm { eps } => {
+            w.index("base", &format!("i * {row}u"));
+            w.var("mean", "0.0");
+            w.begin_for("j", row);
+            w.line("mean += in0[base + j];");
+            w.end();
+            w.line(&format!("mean = mean / {};", float(row as f32)));
+            w.var("variance", "0.0");
+            w.begin_for("j", row);
+            w.value("d", "in0[base + j] - mean");
+            w.line("variance += d * d;");
+            w.end();
+            let denom = format!("sqrt(variance / {} + {})", float(row as f32), float(*eps));
+            w.value("denom", &denom);
+            w.begin_for("j", row);
+            w.line("result[base + j] = (in0[base + j] - mean) / denom * in1[j] + in2[j];");
+            w.end();
+            Some(rows)
+        }
+        Op::RmsNorm { eps } => {
+            w.index("base", &format!("i * {row}u"));
+            w.var("sum", "0.0");
+            w.begin_for("j", row);
+            w.line("sum += in0[base + j] * in0[base + j];");
+            w.end();
+            rms_scale(w, row, *eps, "in0[base + j]", "in1");
+            Some(rows)
+        }
+        Op::MatMulResidualRmsNorm { eps } => {
+            let (k, n) = (shapes[0][1], shapes[1][1]);
+            w.index("base", &format!("i * {n}u"));
+            w.var("sum", "0.0");
+            w.begin_for("j", n);
+            w.var("acc", "in2[base + j]");
+            w.begin_for("p", k);
+            w.line(&format!("acc += in0[i * {k}u + p] * in1[p * {n}u + j];"));
+            w.end();
+            w.line("result[base + j] = acc;");
+            w.line("sum += acc * acc;");
+            w.end();
+            rms_scale(w, n, *eps, "result[base + j]", "in3");
+            Some(rows)
+        }
+        Op::FusedAttention { scale } => {
+            let (sk, d) = (shapes[1][0], shapes[0][1]);
+            let dv = shapes[2][1];
+            // Two passes over the keys, the first finding the largest score
+            // so the exponentials cannot overflow.
+            w.index("q", &format!("i * {d}u"));
+            w.index("base", &format!("i * {dv}u"));
+            w.var("top", &float(f32::MIN));
+            w.begin_for("j", sk);
+            w.var("s", "0.0");
+            w.begin_for("p", d);
+            w.line(&format!("s += in0[q + p] * in1[j * {d}u + p];"));
+            w.end();
+            w.line(&format!("s = s * {};", float(*scale)));
+            w.line("top = max(top, s);");
+            w.end();
+            w.begin_for("t", dv);
+            w.line("result[base + t] = 0.0;");
+            w.end();
+            w.var("total", "0.0");
+            w.begin_for("j", sk);
+            w.var("s", "0.0");
+            w.begin_for("p", d);
+            w.line(&format!("s += in0[q + p] * in1[j * {d}u + p];"));
+            w.end();
+            w.value("e", &format!("exp(s * {} - top)", float(*scale)));
+            w.line("total += e;");
+            w.begin_for("t", dv);
+            w.line(&format!("result[base + t] += e * in2[j * {dv}u + t];"));
+            w.end();
+            w.end();
+            w.begin_for("t", dv);
+            w.line("result[base + t] = result[base + t] / total;");
+            w.end();
+            Some(rows)
+        }
+    }
+}
+
+/// Write the row at `base` of `value` divided by the RMS whose sum of
+/// squares is `sum` and scaled by `gamma`.
+fn rms_scale(w: &mut Writer, row: usize, eps: f32, value: &str, gamma: &str) {
+    let inv = format!("1.0 / sqrt(sum / {} + {})", float(row as f32), float(eps));
+    w.value("inv", &inv);
+    w.begin_for("j", row);
+    w.line(&format!("result[base + j] = {value} * inv * {gamma}[j];"));
+    w.end();
+}
+
+/// Element `i` of input `j`, broadcast over the leading dimensions like
+/// [`Op::Binary`] operands.
+fn load(j: usize, shapes: &[&[usize]], len: usize) -> String {
+    match shapes[j].iter().product::<usize>() {
+        n if n == len => format!("in{j}[i]"),
+        1 => format!("in{j}[0]"),
+        n => format!("in{j}[i % {n}u]"),
+    }
+}
+
+fn unary(op: UnaryOp, x: &str) -> String {
+    match op {
+        UnaryOp::Relu => format!("max({x}, 0.0)"),
+        UnaryOp::Gelu => format!(
+            "0.5 * {x} * (1.0 + tanh({} * ({x} + 0.044715 * {x} * {x} * {x})))",
+            float((2.0 / std::f32::consts::PI).sqrt())
+        ),
+        UnaryOp::Silu => format!("{x} / (1.0 + exp(-{x}))"),
+        UnaryOp::Neg => format!("-{x}"),
+        UnaryOp::Exp => format!("exp({x})"),
+    }
+}
+
+fn symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+    }
+}
+
+/// Float literal valid in GLSL and WGSL, e.g. `2.0` or `1e-5`.
+fn float(value: f32) -> String {
+    format!("{value:?}")
+}
+
+/// Statements of a shader body in the syntax of its language.  GLSL and
+/// WGSL share expressions, assignments and loop conditions, so only
+/// declarations and the entry point differ.
+struct Writer {
+    language: ShaderLanguage,
+    body: String,
+    depth: usize,
+}
+
+impl Writer {
+    fn new(language: ShaderLanguage) -> Self {
+        Self {
+            language,
+            body: String::new(),
+            depth: 1,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.body, "{:1$}{line}", "", self.depth * 4);
+    }
+
+    /// Declare an immutable float.
+    fn value(&mut self, name: &str, init: &str) {
+        match self.language {
+            ShaderLanguage::Glsl => self.line(&format!("float {name} = {init};")),
+            ShaderLanguage::Wgsl => self.line(&format!("let {name}: f32 = {init};")),
+        }
+    }
+
+    /// Declare a mutable float.
+    fn var(&mut self, name: &str, init: &str) {
+        match self.language {
+            ShaderLanguage::Glsl => self.line(&format!("float {name} = {init};")),
+            ShaderLanguage::Wgsl => self.line(&format!("var {name}: f32 = {init};")),
+        }
+    }
+
+    /// Declare an immutable unsigned index.
+    fn index(&mut self, name: &str, init: &str) {
+        match self.language {
+            ShaderLanguage::Glsl => self.line(&format!("uint {name} = {init};")),
+            ShaderLanguage::Wgsl => self.line(&format!("let {name}: u32 = {init};")),
+        }
+    }
+
+    /// Open a loop of `var` over `0..bound`.
+    fn begin_for(&mut self, var: &str, bound: usize) {
+        let init = match self.language {
+            ShaderLanguage::Glsl => format!("uint {var} = 0u"),
+            ShaderLanguage::Wgsl => format!("var {var}: u32 = 0u"),
+        };
+        self.line(&format!("for ({init}; {var} < {bound}u; {var}++) {{"));
+        self.depth += 1;
+    }
+
+    fn end(&mut self) {
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    /// Complete source with `inputs` input buffers, an output buffer and an
+    /// entry point guarding against invocations past `invocations`.
+    fn finish(self, inputs: usize, invocations: usize) -> String {
+        let mut src = String::new();
+        match self.language {
+            ShaderLanguage::Glsl => {
+                src.push_str("#version 450\n");
+                let _ = writeln!(src, "layout(local_size_x = {WORKGROUP_SIZE}) in;");
+                for j in 0..inputs {
+                    let _ = writeln!(
+                        src,
+                        "layout(set = 0, binding = {j}) readonly buffer In{j} {{ float in{j}[]; }};"
+                    );
+                }
+                let _ = writeln!(
+                    src,
+                    "layout(set = 0, binding = {inputs}) buffer Result {{ float result[]; }};"
+                );
+                src.push_str("void main() {\n");
+                src.push_str("    uint i = gl_GlobalInvocationID.x;\n");
+            }
+            ShaderLanguage::Wgsl => {
+                for j in 0..inputs {
+                    let _ = writeln!(
+                        src,
+                        "@group(0) @binding({j}) var<storage, read> in{j}: array<f32>;"
+                    );
+                }
+                let _ = writeln!(
+                    src,
+                    "@group(0) @binding({inputs}) var<storage, read_write> result: array<f32>;"
+                );
+                src.push('\n');
+                let _ = writeln!(src, "@compute @workgroup_size({WORKGROUP_SIZE})");
+                src.push_str("fn main(@builtin(global_invocation_id) id: vec3<u32>) {\n");
+                src.push_str("    let i = id.x;\n");
+            }
+        }
+        let _ = writeln!(src, "    if (i >= {invocations}u) {{");
+        src.push_str("        return;\n    }\n");
+        src.push_str(&self.body);
+        src.push_str("}\n");
+        src
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::passes::optimize;
+
+    #[test]
+    fn lowers_fused_nodes() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[2, 3]);
+        let w = graph.input("w", &[3, 4]);
+        let bias = graph.input("bias", &[4]);
+        let h = graph.matmul(x, w).unwrap();
+        let h = graph.add(h, bias).unwrap();
+        let h = graph.unary(UnaryOp::Relu, h).unwrap();
+        let scale = graph.input("scale", &[1]);
+        let y = graph.mul(h, scale).unwrap();
+        let y = graph.unary(UnaryOp::Neg, y).unwrap();
+        graph.output(y).unwrap();
+        optimize(&mut graph);
+
+        let shaders = lower_graph(&graph, ShaderLanguage::Glsl);
+        let names: Vec<&str> = shaders.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["matmul_bias_relu_2x3_3x4_4", "fused_mul_neg_2x4_1"]);
+        let matmul = &shaders[0].source;
+        assert!(matmul.starts_with("#version 450\n"));
+        assert!(matmul.contains("binding = 2) readonly buffer In2"));
+        assert!(matmul.contains("binding = 3) buffer Result"));
+        assert!(matmul.contains("acc += in2[col];\n    acc = max(acc, 0.0);"));
+        assert!(shaders[1].source.contains("float r1 = in1[0];"));
+        assert_eq!(shaders[0].invocations, 8);
+        assert_eq!(shaders[0].workgroups(), 1);
+
+        let wgsl = lower_graph(&graph, ShaderLanguage::Wgsl);
+        assert_eq!(wgsl.len(), 2);
+        assert_eq!(wgsl[0].name, shaders[0].name);
+        assert!(wgsl[0]
+            .source
+            .contains("@group(0) @binding(3) var<storage, read_write> result: array<f32>;"));
+        assert!(wgsl[0]
+            .source
+            .contains("for (var p: u32 = 0u; p < 3u; p++) {"));
+        assert!(wgsl[1].source.contains("let r2: f32 = r0 * r1;"));
+    }
+
+    #[test]
+    fn identical_kernels_are_generated_once() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[2, 4]);
+        let gamma = graph.input("gamma", &[4]);
+        let a = graph.rms_norm(x, gamma, 1e-5).unwrap();
+        let b = graph.rms_norm(a, gamma, 1e-5).unwrap();
+        let c = graph.rms_norm(b, gamma, 1e-6).unwrap();
+        graph.output(c).unwrap();
+
+        let shaders = lower_graph(&graph, ShaderLanguage::Glsl);
+        let names: Vec<&str> = shaders.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["rms_norm_2x4_4", "rms_norm_2x4_4_1"]);
+        assert!(shaders[0].source.contains("sqrt(sum / 4.0 + 1e-5)"));
+        assert!(shaders[1].source.contains("sqrt(sum / 4.0 + 1e-6)"));
+        // One invocation per row.
+        assert_eq!(shaders[0].invocations, 2);
+        assert!(lower(&graph, x, ShaderLanguage::Glsl).is_none());
+    }
+}
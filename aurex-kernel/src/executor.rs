@@ -0,0 +1,363 @@
+//! Execution of graph IR on a tensor backend.
+//!
+//! Matmuls, layer norms and attention are lowered to [`TensorOps`] calls, so
+//! with a [`Dispatcher`](aurex_backend::Dispatcher) they run on whichever
+//! backend it routes them to.  The epilogue of a fused matmul and fused
+//! elementwise programs run on the host in a single pass over the output, so
+//! fused intermediates are never materialized.  Fused attention runs on the
+//! host with the SIMD kernels of [`crate::optimizations`], or with their
+//! scalar loops when the backend is [deterministic].
+//!
+//! [deterministic]: TensorOps::deterministic
+
+use aurex_backend::TensorOps;
+
+use crate::graph::{ElementwiseStep, Graph, GraphError, Node, Op, TensorId};
+use crate::memory_plan::MemoryPlan;
+use crate::optimizations::{fused_attention, residual_rms_norm, rms_norm};
+use crate::simd;
+
+/// Runs graphs through a [`TensorOps`] implementation.
+pub struct Executor<'a> {
+    ops: &'a dyn TensorOps,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(ops: &'a dyn TensorOps) -> Self {
+        Self { ops }
+    }
+
+    /// Execute `graph` with its inputs bound by name and return the values
+    /// of its outputs.  Intermediate tensors live in the buffers of a
+    /// [`MemoryPlan`], reused once their tensors are no longer read.
+    /// The ops of the graph are submitted to the backend as one
+    /// [batch](TensorOps::begin_batch).
+    pub fn run(
+        &self,
+        graph: &Graph,
+        inputs: &[(&str, &[f32])],
+    ) -> Result<Vec<Vec<f32>>, GraphError> {
+        self.ops.begin_batch();
+        let outputs = if self.ops.deterministic() {
+            simd::scalar(|| self.execute(graph, inputs))
+        } else {
+            self.execute(graph, inputs)
+        };
+        self.ops.end_batch();
+        outputs
+    }
+
+    fn execute(
+        &self,
+        graph: &Graph,
+        inputs: &[(&str, &[f32])],
+    ) -> Result<Vec<Vec<f32>>, GraphError> {
+        let plan = MemoryPlan::new(graph);
+        let mut buffers: Vec<Vec<f32>> = plan
+            .buffers()
+            .iter()
+            .map(|&len| Vec::with_capacity(len))
+            .collect();
+        let mut bound: Vec<Option<&[f32]>> = vec![None; graph.nodes().len()];
+        let live = graph.live();
+        for (i, node) in graph.nodes().iter().enumerate() {
+            if !live[i] {
+                continue;
+            }
+            match &node.op {
+                Op::Input(name) => {
+                    let (_, data) = inputs
+                        .iter()
+                        .find(|(bound, _)| bound == name)
+                        .ok_or_else(|| GraphError::MissingInput(name.clone()))?;
+                    if data.len() != node.len() {
+                        return Err(GraphError::InputLength {
+                            name: name.clone(),
+                            expected: node.len(),
+                            actual: data.len(),
+                        });
+                    }
+                    bound[i] = Some(data);
+                }
+                Op::Constant(_) => {}
+                _ => {
+                    let slot = plan.buffer(TensorId(i)).expect("activation planned");
+                    // The plan never places an operand in the output buffer.
+                    let mut out = std::mem::take(&mut buffers[slot]);
+                    let args: Vec<&[f32]> = node
+                        .inputs
+                        .iter()
+                        .map(|&id| value(graph, &plan, &bound, &buffers, id))
+                        .collect();
+                    let shapes: Vec<&[usize]> = node
+                        .inputs
+                        .iter()
+                        .map(|id| graph.node(*id).shape.as_slice())
+                        .collect();
+                    eval_into(self.ops, node, &args, &shapes, &mut out);
+                    buffers[slot] = out;
+                }
+            }
+        }
+        Ok(graph
+            .outputs()
+            .iter()
+            .map(|&id| value(graph, &plan, &bound, &buffers, id).to_vec())
+            .collect())
+    }
+}
+
+/// Value of `id` during a run: a bound input, a constant or an activation in
+/// its planned buffer.
+fn value<'v>(
+    graph: &'v Graph,
+    plan: &MemoryPlan,
+    bound: &[Option<&'v [f32]>],
+    buffers: &'v [Vec<f32>],
+    id: TensorId,
+) -> &'v [f32] {
+    match (&graph.node(id).op, plan.buffer(id)) {
+        (_, Some(slot)) => &buffers[slot],
+        (Op::Constant(data), None) => data,
+        (_, None) => bound[id.0].expect("input bound"),
+    }
+}
+
+/// Compute `node` from its operand values `args` of the given `shapes`.
+pub(crate) fn eval(
+    ops: &dyn TensorOps,
+    node: &Node,
+    args: &[&[f32]],
+    shapes: &[&[usize]],
+) -> Vec<f32> {
+    let mut out = Vec::with_capacity(node.len());
+    eval_into(ops, node, args, shapes, &mut out);
+    out
+}
+
+/// Compute `node` into `out`, replacing its contents but keeping its
+/// allocation.  Host ops write `out` directly; results of backend calls are
+/// copied into it.
+fn eval_into(
+    ops: &dyn TensorOps,
+    node: &Node,
+    args: &[&[f32]],
+    shapes: &[&[usize]],
+    out: &mut Vec<f32>,
+) {
+    out.clear();
+    match &node.op {
+        Op::Input(_) => unreachable!("inputs are bound by the executor"),
+        Op::Constant(data) => out.extend_from_slice(data),
+        Op::MatMul => {
+            let (m, k, n) = (shapes[0][0], shapes[0][1], shapes[1][1]);
+            out.extend_from_slice(&ops.matmul(args[0], args[1], m, n, k));
+        }
+        Op::FusedMatMul { bias, activation } => {
+            let (m, k, n) = (shapes[0][0], shapes[0][1], shapes[1][1]);
+            out.extend_from_slice(&ops.matmul(args[0], args[1], m, n, k));
+            for row in out.chunks_mut(n.max(1)) {
+                for (j, value) in row.iter_mut().enumerate() {
+                    if *bias {
+                        *value += args[2][j];
+                    }
+                    if let Some(act) = activation {
+                        *value = act.apply(*value);
+                    }
+                }
+            }
+        }
+        Op::Unary(op) => out.extend(args[0].iter().map(|&x| op.apply(x))),
+        Op::Binary(op) => {
+            out.extend((0..node.len()).map(|e| op.apply(args[0][e], broadcast(args[1], e))))
+        }
+        Op::FusedElementwise(steps) => {
+            let mut regs = vec![0.0; steps.len()];
+            out.extend((0..node.len()).map(|e| {
+                for (r, step) in steps.iter().enumerate() {
+                    regs[r] = match *step {
+                        ElementwiseStep::Load(input) => broadcast(args[input], e),
+                        ElementwiseStep::Unary(op, x) => op.apply(regs[x]),
+                        ElementwiseStep::Binary(op, a, b) => op.apply(regs[a], regs[b]),
+                    };
+                }
+                regs[steps.len() - 1]
+            }))
+        }
+        Op::LayerNorm { eps } => {
+            let n = node.shape.last().copied().unwrap_or(1).max(1);
+            for row in args[0].chunks(n) {
+                out.extend_from_slice(&ops.layer_norm(row, args[1], args[2], *eps));
+            }
+        }
+        Op::Attention => {
+            let dim = shapes[0].last().copied().unwrap_or(1);
+            out.extend_from_slice(&ops.attention(args[0], args[1], args[2], dim));
+        }
+        Op::Softmax => {
+            out.extend_from_slice(args[0]);
+            for row in out.chunks_mut(shapes[0][shapes[0].len() - 1]) {
+                let max = row.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let mut total = 0.0;
+                for value in row.iter_mut() {
+                    *value = (*value - max).exp();
+                    total += *value;
+                }
+                for value in row.iter_mut() {
+                    *value /= total;
+                }
+            }
+        }
+        Op::Transpose => {
+            let (m, n) = (shapes[0][0], shapes[0][1]);
+            out.extend((0..n * m).map(|e| args[0][(e % m) * n + e / m]));
+        }
+        Op::RmsNorm { eps } => {
+            out.extend_from_slice(args[0]);
+            rms_norm(out, args[1], *eps);
+        }
+        Op::FusedAttention { scale } => out.extend_from_slice(&fused_attention(
+            args[0],
+            args[1],
+            args[2],
+            shapes[0][1],
+            *scale,
+        )),
+        Op::MatMulResidualRmsNorm { eps } => {
+            let (m, k, n) = (shapes[0][0], shapes[0][1], shapes[1][1]);
+            out.extend_from_slice(&ops.matmul(args[0], args[1], m, n, k));
+            residual_rms_norm(out, args[2], args[3], *eps);
+        }
+    }
+}
+
+/// Element `e` of an operand broadcast over the leading dimensions.
+fn broadcast(data: &[f32], e: usize) -> f32 {
+    data[e % data.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::UnaryOp;
+    use crate::passes::optimize;
+    use aurex_backend::dispatch::CpuBackend;
+    use aurex_backend::{Backend, Dispatcher, Workload};
+
+    #[test]
+    fn optimized_graph_computes_the_same_values() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[2, 3]);
+        let w = graph.input("w", &[3, 2]);
+        let bias = graph.constant(vec![0.5, -4.0], &[2]).unwrap();
+        let h = graph.matmul(x, w).unwrap();
+        let h = graph.add(h, bias).unwrap();
+        let h = graph.unary(UnaryOp::Relu, h).unwrap();
+        let scale = graph.constant(vec![2.0], &[1]).unwrap();
+        let y = graph.mul(h, scale).unwrap();
+        let gamma = graph.constant(vec![1.0, 1.0], &[2]).unwrap();
+        let beta = graph.constant(vec![0.0, 0.0], &[2]).unwrap();
+        let out = graph.layer_norm(y, gamma, beta, 1e-5).unwrap();
+        graph.output(y).unwrap();
+        graph.output(out).unwrap();
+
+        let inputs: [(&str, &[f32]); 2] = [
+            ("x", &[1.0, 2.0, 3.0, -1.0, 0.5, 2.0]),
+            ("w", &[1.0, 0.0, 0.0, 1.0, 1.0, 1.0]),
+        ];
+        let cpu = CpuBackend;
+        let executor = Executor::new(&cpu);
+        let expected = executor.run(&graph, &inputs).unwrap();
+        assert_eq!(expected[0], vec![9.0, 2.0, 3.0, 0.0]);
+
+        let mut optimized = graph.clone();
+        optimize(&mut optimized);
+        assert_eq!(
+            optimized.kernel_names(),
+            vec!["matmul_bias_relu", "mul", "layer_norm"]
+        );
+        assert_eq!(executor.run(&optimized, &inputs).unwrap(), expected);
+
+        assert_eq!(
+            executor.run(&graph, &inputs[..1]),
+            Err(GraphError::MissingInput("w".to_string()))
+        );
+    }
+
+    /// CPU ops logging batch boundaries and matmuls.
+    #[derive(Default)]
+    struct BatchLog(std::sync::Mutex<Vec<&'static str>>);
+
+    impl TensorOps for BatchLog {
+        fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+            self.0.lock().unwrap().push("matmul");
+            CpuBackend.matmul(a, b, m, n, k)
+        }
+        fn conv2d(
+            &self,
+            input: &[f32],
+            kernel: &[f32],
+            input_shape: (usize, usize),
+            kernel_shape: (usize, usize),
+        ) -> Vec<f32> {
+            CpuBackend.conv2d(input, kernel, input_shape, kernel_shape)
+        }
+        fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+            CpuBackend.attention(q, k, v, dim)
+        }
+        fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+            CpuBackend.layer_norm(x, gamma, beta, eps)
+        }
+        fn begin_batch(&self) {
+            self.0.lock().unwrap().push("begin");
+        }
+        fn end_batch(&self) {
+            self.0.lock().unwrap().push("end");
+        }
+    }
+
+    #[test]
+    fn graph_ops_are_submitted_as_one_batch() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[1, 2]);
+        let w = graph.constant(vec![1.0, 0.0, 0.0, 1.0], &[2, 2]).unwrap();
+        let h = graph.matmul(x, w).unwrap();
+        let y = graph.matmul(h, w).unwrap();
+        graph.output(y).unwrap();
+
+        let log = BatchLog::default();
+        let out = Executor::new(&log).run(&graph, &[("x", &[3.0, 4.0])]);
+        assert_eq!(out.unwrap(), vec![vec![3.0, 4.0]]);
+        assert_eq!(*log.0.lock().unwrap(), ["begin", "matmul", "matmul", "end"]);
+
+        // The batch is closed when the graph fails, too.
+        log.0.lock().unwrap().clear();
+        assert!(Executor::new(&log).run(&graph, &[]).is_err());
+        assert_eq!(*log.0.lock().unwrap(), ["begin", "end"]);
+    }
+
+    #[test]
+    fn deterministic_backends_sum_in_index_order() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[2, 19]);
+        let gamma = graph.constant(vec![1.5; 19], &[19]).unwrap();
+        let y = graph.rms_norm(x, gamma, 1e-5).unwrap();
+        graph.output(y).unwrap();
+
+        let x_data: Vec<f32> = (0..38).map(|i| (i as f32 * 0.37).sin()).collect();
+        let expected: Vec<f32> = x_data
+            .chunks(19)
+            .flat_map(|row| {
+                let sum = row.iter().fold(0.0f32, |acc, v| acc + v * v);
+                let scale = 1.0 / (sum / 19.0 + 1e-5).sqrt();
+                row.iter().map(move |v| v * (scale * 1.5))
+            })
+            .collect();
+        let dispatcher =
+            Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_deterministic(true);
+        let out = Executor::new(&dispatcher)
+            .run(&graph, &[("x", &x_data)])
+            .unwrap();
+        assert_eq!(out[0], expected);
+    }
+}
@@ -0,0 +1,494 @@
+//! Typed graph IR for tensor programs.
+//!
+//! A [`Graph`] is a list of [`Node`]s in topological order.  Every node
+//! produces one tensor, named by the [`TensorId`] of the node, whose static
+//! shape is inferred when the node is added.  The builder methods reject
+//! operands of mismatched shapes, so passes and the executor can rely on
+//! them.  Only tensors reachable from the marked outputs are live.
+
+use std::fmt;
+
+/// Tensor produced by the node with the same index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TensorId(pub usize);
+
+/// Elementwise op with one operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Relu,
+    /// GELU with the tanh approximation.
+    Gelu,
+    Silu,
+    Neg,
+    Exp,
+}
+
+impl UnaryOp {
+    pub fn apply(self, x: f32) -> f32 {
+        match self {
+            UnaryOp::Relu => x.max(0.0),
+            UnaryOp::Gelu => {
+                let c = (2.0 / std::f32::consts::PI).sqrt();
+                0.5 * x * (1.0 + (c * (x + 0.044_715 * x * x * x)).tanh())
+            }
+            UnaryOp::Silu => x / (1.0 + (-x).exp()),
+            UnaryOp::Neg => -x,
+            UnaryOp::Exp => x.exp(),
+        }
+    }
+
+    /// Whether the op can be fused into a matmul epilogue.
+    pub fn is_activation(self) -> bool {
+        matches!(self, UnaryOp::Relu | UnaryOp::Gelu | UnaryOp::Silu)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            UnaryOp::Relu => "relu",
+            UnaryOp::Gelu => "gelu",
+            UnaryOp::Silu => "silu",
+            UnaryOp::Neg => "neg",
+            UnaryOp::Exp => "exp",
+        }
+    }
+}
+
+/// Elementwise op with two operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOp {
+    pub fn apply(self, a: f32, b: f32) -> f32 {
+        match self {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+        }
+    }
+}
+
+/// Step of a fused elementwise program.  Each step writes the register with
+/// its own index; registers are referenced by the index of their step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementwiseStep {
+    /// Current element of the node input with this index.
+    Load(usize),
+    Unary(UnaryOp, usize),
+    Binary(BinaryOp, usize, usize),
+}
+
+/// Operation of a node.  Input order is given with each variant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// Tensor bound by name when the graph is executed.
+    Input(String),
+    Constant(Vec<f32>),
+    /// `[a, b]` with `a: [m, k]` and `b: [k, n]`.
+    MatMul,
+    /// `[x]`.
+    Unary(UnaryOp),
+    /// `[a, b]`; `b` may broadcast over the leading dimensions of `a`.
+    Binary(BinaryOp),
+    /// `[x, gamma, beta]`, normalizing each row over the last dimension.
+    LayerNorm {
+        eps: f32,
+    },
+    /// `[q, k, v]`, the attention primitive of the backends.
+    Attention,
+    /// `[x]`, a softmax over the last dimension.
+    Softmax,
+    /// `[x]` with `x: [m, n]`.
+    Transpose,
+    /// `[x, gamma]`, RMS-normalizing each row over the last dimension.
+    RmsNorm {
+        eps: f32,
+    },
+    /// `[q, k, v]` with `q: [sq, d]`, `k: [sk, d]` and `v: [sk, dv]`:
+    /// `softmax(q k^T * scale) v` without materializing the scores.
+    FusedAttention {
+        scale: f32,
+    },
+    /// `[a, b, residual, gamma]`: `rms_norm(a b + residual)`.
+    MatMulResidualRmsNorm {
+        eps: f32,
+    },
+    /// `[a, b]` or `[a, b, bias]`: a matmul whose output rows get `bias`
+    /// added and `activation` applied.
+    FusedMatMul {
+        bias: bool,
+        activation: Option<UnaryOp>,
+    },
+    /// Program evaluated once per output element over the node inputs,
+    /// which broadcast like [`Op::Binary`] operands.  The last step is the
+    /// result.
+    FusedElementwise(Vec<ElementwiseStep>),
+}
+
+impl Op {
+    /// Kernel name of the op, e.g. `matmul_bias_relu` or `fused_mul_add`.
+    pub fn name(&self) -> String {
+        match self {
+            Op::Input(_) => "input".to_string(),
+            Op::Constant(_) => "constant".to_string(),
+            Op::MatMul => "matmul".to_string(),
+            Op::Unary(op) => op.name().to_string(),
+            Op::Binary(op) => op.name().to_string(),
+            Op::LayerNorm { .. } => "layer_norm".to_string(),
+            Op::Attention => "attention".to_string(),
+            Op::Softmax => "softmax".to_string(),
+            Op::Transpose => "transpose".to_string(),
+            Op::RmsNorm { .. } => "rms_norm".to_string(),
+            Op::FusedAttention { .. } => "fused_attention".to_string(),
+            Op::MatMulResidualRmsNorm { .. } => "matmul_residual_rms_norm".to_string(),
+            Op::FusedMatMul { bias, activation } => {
+                let mut name = "matmul".to_string();
+                if *bias {
+                    name.push_str("_bias");
+                }
+                if let Some(act) = activation {
+                    name.push('_');
+                    name.push_str(act.name());
+                }
+                name
+            }
+            Op::FusedElementwise(steps) => {
+                let mut name = "fused".to_string();
+                for step in steps {
+                    let op = match step {
+                        ElementwiseStep::Load(_) => continue,
+                        ElementwiseStep::Unary(op, _) => op.name(),
+                        ElementwiseStep::Binary(op, _, _) => op.name(),
+                    };
+                    name.push('_');
+                    name.push_str(op);
+                }
+                name
+            }
+        }
+    }
+
+    pub fn is_elementwise(&self) -> bool {
+        matches!(self, Op::Unary(_) | Op::Binary(_) | Op::FusedElementwise(_))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub op: Op,
+    pub inputs: Vec<TensorId>,
+    pub shape: Vec<usize>,
+}
+
+impl Node {
+    /// Number of elements of the output tensor.
+    pub fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Error raised while building or executing a graph.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphError {
+    /// Operands an op cannot combine.
+    Shape {
+        op: String,
+        shapes: Vec<Vec<usize>>,
+    },
+    UnknownTensor(TensorId),
+    /// An input of the graph was not bound.
+    MissingInput(String),
+    /// A bound input does not have the length of its declared shape.
+    InputLength {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Shape { op, shapes } => write!(f, "invalid shapes for {op}: {shapes:?}"),
+            GraphError::UnknownTensor(id) => write!(f, "unknown tensor {}", id.0),
+            GraphError::MissingInput(name) => write!(f, "input '{name}' is not bound"),
+            GraphError::InputLength {
+                name,
+                expected,
+                actual,
+            } => write!(f, "input '{name}' has {actual} values, expected {expected}"),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// Whether an operand of `shape` broadcasts to `to`: it matches the trailing
+/// dimensions of `to` or holds a single value.
+pub(crate) fn broadcasts(shape: &[usize], to: &[usize]) -> bool {
+    to.ends_with(shape) || shape.iter().product::<usize>() == 1
+}
+
+/// Graph of tensor ops in topological order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Graph {
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) outputs: Vec<TensorId>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn node(&self, id: TensorId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn outputs(&self) -> &[TensorId] {
+        &self.outputs
+    }
+
+    /// Mark `id` as a result of the graph.
+    pub fn output(&mut self, id: TensorId) -> Result<(), GraphError> {
+        self.check(id)?;
+        self.outputs.push(id);
+        Ok(())
+    }
+
+    /// Kernel names of the ops the graph runs, in execution order.
+    pub fn kernel_names(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter(|node| !matches!(node.op, Op::Input(_) | Op::Constant(_)))
+            .map(|node| node.op.name())
+            .collect()
+    }
+
+    pub fn input(&mut self, name: &str, shape: &[usize]) -> TensorId {
+        self.push(Op::Input(name.to_string()), Vec::new(), shape.to_vec())
+    }
+
+    pub fn constant(&mut self, data: Vec<f32>, shape: &[usize]) -> Result<TensorId, GraphError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(GraphError::Shape {
+                op: "constant".to_string(),
+                shapes: vec![shape.to_vec(), vec![data.len()]],
+            });
+        }
+        Ok(self.push(Op::Constant(data), Vec::new(), shape.to_vec()))
+    }
+
+    pub fn matmul(&mut self, a: TensorId, b: TensorId) -> Result<TensorId, GraphError> {
+        let shape = match (self.shape(a)?, self.shape(b)?) {
+            (&[m, k], &[k2, n]) if k == k2 => vec![m, n],
+            _ => return Err(self.shape_error(&Op::MatMul, &[a, b])),
+        };
+        Ok(self.push(Op::MatMul, vec![a, b], shape))
+    }
+
+    pub fn unary(&mut self, op: UnaryOp, x: TensorId) -> Result<TensorId, GraphError> {
+        let shape = self.shape(x)?.to_vec();
+        Ok(self.push(Op::Unary(op), vec![x], shape))
+    }
+
+    pub fn binary(
+        &mut self,
+        op: BinaryOp,
+        a: TensorId,
+        b: TensorId,
+    ) -> Result<TensorId, GraphError> {
+        let shape = self.shape(a)?.to_vec();
+        if !broadcasts(self.shape(b)?, &shape) {
+            return Err(self.shape_error(&Op::Binary(op), &[a, b]));
+        }
+        Ok(self.push(Op::Binary(op), vec![a, b], shape))
+    }
+
+    pub fn add(&mut self, a: TensorId, b: TensorId) -> Result<TensorId, GraphError> {
+        self.binary(BinaryOp::Add, a, b)
+    }
+
+    pub fn mul(&mut self, a: TensorId, b: TensorId) -> Result<TensorId, GraphError> {
+        self.binary(BinaryOp::Mul, a, b)
+    }
+
+    pub fn layer_norm(
+        &mut self,
+        x: TensorId,
+        gamma: TensorId,
+        beta: TensorId,
+        eps: f32,
+    ) -> Result<TensorId, GraphError> {
+        let op = Op::LayerNorm { eps };
+        let shape = self.shape(x)?.to_vec();
+        let row = &shape[shape.len().saturating_sub(1)..];
+        if row.is_empty() || self.shape(gamma)? != row || self.shape(beta)? != row {
+            return Err(self.shape_error(&op, &[x, gamma, beta]));
+        }
+        Ok(self.push(op, vec![x, gamma, beta], shape))
+    }
+
+    pub fn attention(
+        &mut self,
+        q: TensorId,
+        k: TensorId,
+        v: TensorId,
+    ) -> Result<TensorId, GraphError> {
+        if self.shape(q)? != self.shape(k)? {
+            return Err(self.shape_error(&Op::Attention, &[q, k, v]));
+        }
+        let shape = self.shape(v)?.to_vec();
+        Ok(self.push(Op::Attention, vec![q, k, v], shape))
+    }
+
+    pub fn softmax(&mut self, x: TensorId) -> Result<TensorId, GraphError> {
+        let shape = self.shape(x)?.to_vec();
+        if !matches!(shape.last(), Some(&n) if n > 0) {
+            return Err(self.shape_error(&Op::Softmax, &[x]));
+        }
+        Ok(self.push(Op::Softmax, vec![x], shape))
+    }
+
+    pub fn transpose(&mut self, x: TensorId) -> Result<TensorId, GraphError> {
+        let shape = match self.shape(x)? {
+            &[m, n] => vec![n, m],
+            _ => return Err(self.shape_error(&Op::Transpose, &[x])),
+        };
+        Ok(self.push(Op::Transpose, vec![x], shape))
+    }
+
+    pub fn rms_norm(
+        &mut self,
+        x: TensorId,
+        gamma: TensorId,
+        eps: f32,
+    ) -> Result<TensorId, GraphError> {
+        let op = Op::RmsNorm { eps };
+        let shape = self.shape(x)?.to_vec();
+        let row = &shape[shape.len().saturating_sub(1)..];
+        if row.is_empty() || self.shape(gamma)? != row {
+            return Err(self.shape_error(&op, &[x, gamma]));
+        }
+        Ok(self.push(op, vec![x, gamma], shape))
+    }
+
+    pub(crate) fn push(&mut self, op: Op, inputs: Vec<TensorId>, shape: Vec<usize>) -> TensorId {
+        self.nodes.push(Node { op, inputs, shape });
+        TensorId(self.nodes.len() - 1)
+    }
+
+    /// Which nodes the outputs depend on.  Inputs are always live, so the
+    /// signature of the graph never changes.
+    pub(crate) fn live(&self) -> Vec<bool> {
+        let mut live: Vec<bool> = self
+            .nodes
+            .iter()
+            .map(|node| matches!(node.op, Op::Input(_)))
+            .collect();
+        for id in &self.outputs {
+            live[id.0] = true;
+        }
+        for i in (0..self.nodes.len()).rev() {
+            if live[i] {
+                for input in &self.nodes[i].inputs {
+                    live[input.0] = true;
+                }
+            }
+        }
+        live
+    }
+
+    /// Number of live consumers of every tensor, counting each use as a
+    /// graph output as one more.
+    pub(crate) fn use_counts(&self) -> Vec<usize> {
+        let live = self.live();
+        let mut uses = vec![0; self.nodes.len()];
+        for (node, _) in self.nodes.iter().zip(&live).filter(|(_, live)| **live) {
+            for input in &node.inputs {
+                uses[input.0] += 1;
+            }
+        }
+        for id in &self.outputs {
+            uses[id.0] += 1;
+        }
+        uses
+    }
+
+    fn check(&self, id: TensorId) -> Result<(), GraphError> {
+        if id.0 < self.nodes.len() {
+            Ok(())
+        } else {
+            Err(GraphError::UnknownTensor(id))
+        }
+    }
+
+    fn shape(&self, id: TensorId) -> Result<&[usize], GraphError> {
+        self.check(id)?;
+        Ok(&self.nodes[id.0].shape)
+    }
+
+    fn shape_error(&self, op: &Op, inputs: &[TensorId]) -> GraphError {
+        GraphError::Shape {
+            op: op.name(),
+            shapes: inputs
+                .iter()
+                .map(|&id| self.nodes[id.0].shape.clone())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_and_checks_shapes() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[2, 3]);
+        let w = graph.input("w", &[3, 4]);
+        let b = graph.constant(vec![0.5; 4], &[4]).unwrap();
+        let y = graph.matmul(x, w).unwrap();
+        let z = graph.add(y, b).unwrap();
+        assert_eq!(graph.node(z).shape, vec![2, 4]);
+
+        assert!(matches!(
+            graph.matmul(w, w),
+            Err(GraphError::Shape { ref op, .. }) if op == "matmul"
+        ));
+        assert!(graph.add(b, z).is_err());
+        assert!(graph.constant(vec![1.0], &[2]).is_err());
+        assert_eq!(
+            graph.output(TensorId(42)),
+            Err(GraphError::UnknownTensor(TensorId(42)))
+        );
+        let t = graph.transpose(w).unwrap();
+        assert_eq!(graph.node(t).shape, vec![4, 3]);
+        assert!(graph.transpose(b).is_err());
+        assert!(graph.rms_norm(z, w, 1e-5).is_err());
+        assert_eq!(graph.kernel_names(), vec!["matmul", "add", "transpose"]);
+    }
+}
@@ -1,4 +1,10 @@
 //! Tensor and symbolic kernel implementations.
 
+pub mod codegen;
+pub mod executor;
+pub mod graph;
+pub mod memory_plan;
 pub mod optimizations;
+pub mod passes;
+mod simd;
 pub mod tensor;
@@ -0,0 +1,200 @@
+//! Static memory planning of graph execution.
+//!
+//! A [`MemoryPlan`] assigns every activation of a graph to one of a few
+//! reusable buffers before the graph runs.  A tensor lives from the node
+//! computing it to the last node reading it; once it is dead its buffer is
+//! handed to a later tensor, the smallest free buffer large enough being
+//! preferred.  The [`Executor`](crate::executor::Executor) allocates the
+//! buffers once per run, so the activation memory of a forward pass is the
+//! planned peak rather than the sum of all intermediates.
+//!
+//! Inputs and constants are read in place and take no buffer.  A node never
+//! writes into the buffer of one of its operands.
+
+use crate::graph::{Graph, Op, TensorId};
+
+/// Nodes between which a tensor is live, both inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lifetime {
+    /// Node computing the tensor.
+    pub start: usize,
+    /// Last node reading the tensor; `usize::MAX` for graph outputs.
+    pub end: usize,
+}
+
+/// Lifetimes of the tensors of `graph`, `None` for dead tensors.
+pub fn lifetimes(graph: &Graph) -> Vec<Option<Lifetime>> {
+    let live = graph.live();
+    let mut lifetimes: Vec<Option<Lifetime>> = (0..graph.nodes().len())
+        .map(|i| live[i].then_some(Lifetime { start: i, end: i }))
+        .collect();
+    for (i, node) in graph.nodes().iter().enumerate().filter(|&(i, _)| live[i]) {
+        for input in &node.inputs {
+            if let Some(lifetime) = &mut lifetimes[input.0] {
+                lifetime.end = i;
+            }
+        }
+    }
+    for id in graph.outputs() {
+        if let Some(lifetime) = &mut lifetimes[id.0] {
+            lifetime.end = usize::MAX;
+        }
+    }
+    lifetimes
+}
+
+/// Buffers holding the activations of a graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryPlan {
+    /// Buffer of every tensor, `None` for inputs, constants and dead tensors.
+    assignment: Vec<Option<usize>>,
+    /// Length of every buffer in `f32` elements.
+    buffers: Vec<usize>,
+    /// Elements of all activations together.
+    activations: usize,
+}
+
+impl MemoryPlan {
+    /// Plan the activations of `graph`.
+    pub fn new(graph: &Graph) -> Self {
+        let lifetimes = lifetimes(graph);
+        let mut assignment = vec![None; graph.nodes().len()];
+        let mut buffers: Vec<usize> = Vec::new();
+        let mut free: Vec<usize> = Vec::new();
+        let mut activations = 0;
+        for (i, node) in graph.nodes().iter().enumerate() {
+            if lifetimes[i].is_none() || matches!(node.op, Op::Input(_) | Op::Constant(_)) {
+                continue;
+            }
+            let len = node.len();
+            activations += len;
+            // Best fit among the free buffers, else grow the largest one.
+            let fit = free
+                .iter()
+                .enumerate()
+                .filter(|&(_, &b)| buffers[b] >= len)
+                .min_by_key(|&(_, &b)| buffers[b])
+                .or_else(|| free.iter().enumerate().max_by_key(|&(_, &b)| buffers[b]))
+                .map(|(slot, _)| slot);
+            let buffer = match fit {
+                Some(slot) => free.swap_remove(slot),
+                None => {
+                    buffers.push(0);
+                    buffers.len() - 1
+                }
+            };
+            buffers[buffer] = buffers[buffer].max(len);
+            assignment[i] = Some(buffer);
+
+            // Operands are released only once the output has its buffer.
+            for input in &node.inputs {
+                let released = lifetimes[input.0].is_some_and(|l| l.end == i);
+                if let (true, Some(buffer)) = (released, assignment[input.0]) {
+                    if !free.contains(&buffer) {
+                        free.push(buffer);
+                    }
+                }
+            }
+        }
+        Self {
+            assignment,
+            buffers,
+            activations,
+        }
+    }
+
+    /// Buffer holding `id`, `None` when it is an input, a constant or dead.
+    pub fn buffer(&self, id: TensorId) -> Option<usize> {
+        self.assignment.get(id.0).copied().flatten()
+    }
+
+    /// Length of every buffer in `f32` elements.
+    pub fn buffers(&self) -> &[usize] {
+        &self.buffers
+    }
+
+    /// Bytes of all buffers, the peak activation memory of a run.
+    pub fn peak_bytes(&self) -> usize {
+        self.buffers.iter().sum::<usize>() * std::mem::size_of::<f32>()
+    }
+
+    /// Bytes the activations would take with a buffer each.
+    pub fn unplanned_bytes(&self) -> usize {
+        self.activations * std::mem::size_of::<f32>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::Executor;
+    use crate::graph::UnaryOp;
+    use aurex_backend::dispatch::CpuBackend;
+
+    #[test]
+    fn chained_activations_share_two_buffers() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[4, 8]);
+        let mut h = x;
+        for _ in 0..4 {
+            h = graph.unary(UnaryOp::Silu, h).unwrap();
+            h = graph.unary(UnaryOp::Neg, h).unwrap();
+        }
+        graph.output(h).unwrap();
+
+        let plan = MemoryPlan::new(&graph);
+        assert_eq!(plan.buffer(x), None);
+        assert_eq!(plan.buffers(), &[32, 32]);
+        assert_eq!(plan.peak_bytes(), 2 * 32 * 4);
+        assert_eq!(plan.unplanned_bytes(), 8 * 32 * 4);
+
+        let data: Vec<f32> = (0..32).map(|i| i as f32 - 16.0).collect();
+        let out = Executor::new(&CpuBackend)
+            .run(&graph, &[("x", &data)])
+            .unwrap();
+        let expected: Vec<f32> = data
+            .iter()
+            .map(|&v| (0..4).fold(v, |v, _| -UnaryOp::Silu.apply(v)))
+            .collect();
+        assert_eq!(out[0], expected);
+    }
+
+    #[test]
+    fn live_tensors_never_share_a_buffer() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[2, 4]);
+        let w = graph.input("w", &[4, 16]);
+        let up = graph.matmul(x, w).unwrap();
+        let act = graph.unary(UnaryOp::Gelu, up).unwrap();
+        let wt = graph.transpose(w).unwrap();
+        let down = graph.matmul(act, wt).unwrap();
+        let sum = graph.add(down, x).unwrap();
+        let y = graph.softmax(sum).unwrap();
+        graph.output(act).unwrap();
+        graph.output(y).unwrap();
+
+        let plan = MemoryPlan::new(&graph);
+        let lifetimes = lifetimes(&graph);
+        for a in 0..graph.nodes().len() {
+            for b in a + 1..graph.nodes().len() {
+                let (Some(la), Some(lb)) = (lifetimes[a], lifetimes[b]) else {
+                    continue;
+                };
+                if plan.buffer(TensorId(a)).is_some()
+                    && plan.buffer(TensorId(a)) == plan.buffer(TensorId(b))
+                {
+                    assert!(la.end < lb.start, "{a} and {b} overlap");
+                }
+            }
+        }
+        // The 16 x 4 transpose grows the buffer freed by the matmul rather
+        // than taking a new one, and the sum reuses it in turn.
+        assert_eq!(plan.buffers(), &[64, 32, 8]);
+        assert_eq!(plan.buffer(sum), plan.buffer(wt));
+        assert_eq!(
+            (plan.peak_bytes(), plan.unplanned_bytes()),
+            (104 * 4, 152 * 4)
+        );
+        assert_eq!(lifetimes[act.0].map(|l| l.end), Some(usize::MAX));
+    }
+}
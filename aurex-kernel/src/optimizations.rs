@@ -1,4 +1,6 @@
-//! Kernel fusion and dynamic graph stubs for tensor operations.
+//! Kernel fusion and dynamic graph passes for tensor operations.
+
+use crate::simd;
 
 /// Fuses a matrix multiplication with a subsequent layer normalization.
 pub fn fuse_matmul_layernorm(
@@ -43,15 +45,111 @@ pub fn fuse_matmul_layernorm(
     out
 }
 
+/// Fused scaled dot-product attention `softmax(q k^T * scale) v`.
+///
+/// `q` holds query rows and `k` key rows of width `d`; `v` holds one row per
+/// key.  Scores are computed for one query row at a time, so the full score
+/// matrix is never materialized.
+pub fn fused_attention(q: &[f32], k: &[f32], v: &[f32], d: usize, scale: f32) -> Vec<f32> {
+    if d == 0 || k.len() < d {
+        return Vec::new();
+    }
+    let seq_k = k.len() / d;
+    let dv = v.len() / seq_k;
+    let mut out = vec![0.0; q.len() / d * dv];
+    if dv == 0 {
+        return out;
+    }
+    let mut scores = vec![0.0; seq_k];
+    for (q_row, out_row) in q.chunks_exact(d).zip(out.chunks_exact_mut(dv)) {
+        for (score, k_row) in scores.iter_mut().zip(k.chunks_exact(d)) {
+            *score = simd::dot(q_row, k_row) * scale;
+        }
+        let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mut total = 0.0;
+        for score in &mut scores {
+            *score = (*score - max).exp();
+            total += *score;
+        }
+        for (score, v_row) in scores.iter().zip(v.chunks_exact(dv)) {
+            simd::axpy(out_row, score / total, v_row);
+        }
+    }
+    out
+}
+
+/// RMS-normalizes each row of `x` in place and scales it by `gamma`, whose
+/// length is the row width.
+pub fn rms_norm(x: &mut [f32], gamma: &[f32], eps: f32) {
+    let n = gamma.len();
+    if n == 0 {
+        return;
+    }
+    for row in x.chunks_exact_mut(n) {
+        let rms = (simd::dot(row, row) / n as f32 + eps).sqrt();
+        simd::scale_mul(row, 1.0 / rms, gamma);
+    }
+}
+
+/// Adds `residual` to `x` and RMS-normalizes the sum in the same pass over
+/// each row.
+pub fn residual_rms_norm(x: &mut [f32], residual: &[f32], gamma: &[f32], eps: f32) {
+    let n = gamma.len();
+    if n == 0 {
+        return;
+    }
+    for (row, res) in x.chunks_exact_mut(n).zip(residual.chunks_exact(n)) {
+        simd::add_assign(row, res);
+        let rms = (simd::dot(row, row) / n as f32 + eps).sqrt();
+        simd::scale_mul(row, 1.0 / rms, gamma);
+    }
+}
+
+/// Fuses a matrix multiplication with a residual add and an RMS norm, the
+/// epilogue of the attention and MLP blocks of pre-norm transformers.
+pub fn fuse_matmul_residual_rmsnorm(
+    a: &[f32],
+    b: &[f32],
+    residual: &[f32],
+    gamma: &[f32],
+    m: usize,
+    n: usize,
+    k: usize,
+) -> Vec<f32> {
+    let mut out = vec![0.0; m * n];
+    for (a_row, out_row) in a.chunks_exact(k.max(1)).zip(out.chunks_exact_mut(n.max(1))) {
+        for (p, &a_ip) in a_row.iter().enumerate().take(k) {
+            simd::axpy(out_row, a_ip, &b[p * n..(p + 1) * n]);
+        }
+    }
+    residual_rms_norm(&mut out, residual, gamma, 1e-5);
+    out
+}
+
 /// Simple dynamic graph node used for optimization stubs.
 #[derive(Clone)]
 pub struct GraphNode {
     pub name: &'static str,
 }
 
-/// Runs a dummy optimization pass returning the provided graph unmodified.
+/// Runs the fusion passes over a linear op graph.  A `matmul` directly
+/// followed by a `layer_norm` is replaced by a single `matmul_layernorm` node
+/// implemented by [`fuse_matmul_layernorm`]; other nodes are kept as is.
 pub fn optimize_graph(nodes: &[GraphNode]) -> Vec<GraphNode> {
-    nodes.to_vec()
+    let mut out = Vec::with_capacity(nodes.len());
+    let mut i = 0;
+    while i < nodes.len() {
+        if nodes[i].name == "matmul" && nodes.get(i + 1).map(|n| n.name) == Some("layer_norm") {
+            out.push(GraphNode {
+                name: "matmul_layernorm",
+            });
+            i += 2;
+        } else {
+            out.push(nodes[i].clone());
+            i += 1;
+        }
+    }
+    out
 }
 
 #[cfg(test)]
@@ -69,4 +167,54 @@ mod tests {
         let graph = vec![GraphNode { name: "matmul" }, GraphNode { name: "ln" }];
         assert_eq!(optimize_graph(&graph).len(), 2);
     }
+
+    #[test]
+    fn fused_attention_matches_softmax_of_scores() {
+        let (d, seq_q, seq_k, dv) = (3, 2, 4, 2);
+        let q: Vec<f32> = (0..seq_q * d).map(|i| i as f32 * 0.3 - 0.5).collect();
+        let k: Vec<f32> = (0..seq_k * d).map(|i| (i % 5) as f32 * 0.2).collect();
+        let v: Vec<f32> = (0..seq_k * dv).map(|i| i as f32).collect();
+        let scale = 1.0 / (d as f32).sqrt();
+        let out = fused_attention(&q, &k, &v, d, scale);
+
+        for i in 0..seq_q {
+            let scores: Vec<f32> = (0..seq_k)
+                .map(|j| (0..d).map(|p| q[i * d + p] * k[j * d + p]).sum::<f32>() * scale)
+                .collect();
+            let total: f32 = scores.iter().map(|s| s.exp()).sum();
+            for c in 0..dv {
+                let want: f32 = (0..seq_k)
+                    .map(|j| scores[j].exp() / total * v[j * dv + c])
+                    .sum();
+                assert!((out[i * dv + c] - want).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn fuses_matmul_residual_and_rms_norm() {
+        let a = vec![1.0, 2.0, 3.0, 4.0];
+        let b = vec![1.0, 0.0, 0.0, 1.0];
+        let residual = vec![2.0, 1.0, -3.0, 0.0];
+        let gamma = vec![1.0, 2.0];
+        let out = fuse_matmul_residual_rmsnorm(&a, &b, &residual, &gamma, 2, 2, 2);
+        // Rows after the residual add: [3, 3] and [0, 4].
+        let rms = [(9.0f32 + 1e-5).sqrt(), (8.0f32 + 1e-5).sqrt()];
+        let want = [3.0 / rms[0], 6.0 / rms[0], 0.0, 8.0 / rms[1]];
+        for (got, want) in out.iter().zip(want) {
+            assert!((got - want).abs() < 1e-5, "{got} != {want}");
+        }
+    }
+
+    #[test]
+    fn fuses_matmul_followed_by_layer_norm() {
+        let graph = vec![
+            GraphNode { name: "attention" },
+            GraphNode { name: "matmul" },
+            GraphNode { name: "layer_norm" },
+            GraphNode { name: "layer_norm" },
+        ];
+        let names: Vec<_> = optimize_graph(&graph).iter().map(|n| n.name).collect();
+        assert_eq!(names, vec!["attention", "matmul_layernorm", "layer_norm"]);
+    }
 }
@@ -0,0 +1,418 @@
+//! Optimization passes over the graph IR.
+//!
+//! Fusion passes rewrite the consumer node in place and leave the absorbed
+//! producers without live uses; [`eliminate_dead_nodes`] then drops them and
+//! renumbers the graph.  A producer is only absorbed when the consumer is its
+//! single live use and it is not a graph output, so no value is computed
+//! twice.  Every pass returns the number of nodes it rewrote or removed.
+
+use aurex_backend::dispatch::CpuBackend;
+
+use crate::executor::eval;
+use crate::graph::{BinaryOp, ElementwiseStep, Graph, Op, TensorId};
+
+/// Run all passes: constant folding, attention and residual norm fusion,
+/// matmul epilogue fusion, elementwise fusion and dead-node elimination.
+pub fn optimize(graph: &mut Graph) {
+    let folded = fold_constants(graph);
+    let attention = fuse_attention(graph);
+    let residual_norms = fuse_residual_rms_norm(graph);
+    let epilogues = fuse_matmul_epilogues(graph);
+    let elementwise = fuse_elementwise(graph);
+    let removed = eliminate_dead_nodes(graph);
+    tracing::debug!(
+        folded,
+        attention,
+        residual_norms,
+        epilogues,
+        elementwise,
+        removed,
+        "optimized graph"
+    );
+}
+
+/// Replace nodes whose operands are all constants by their value, computed
+/// on the CPU.
+pub fn fold_constants(graph: &mut Graph) -> usize {
+    let live = graph.live();
+    let mut folded = 0;
+    for (i, live) in live.into_iter().enumerate() {
+        let node = &graph.nodes[i];
+        if !live || matches!(node.op, Op::Input(_) | Op::Constant(_)) {
+            continue;
+        }
+        let args: Option<Vec<&[f32]>> = node
+            .inputs
+            .iter()
+            .map(|id| match &graph.nodes[id.0].op {
+                Op::Constant(data) => Some(data.as_slice()),
+                _ => None,
+            })
+            .collect();
+        let Some(args) = args else {
+            continue;
+        };
+        let shapes: Vec<&[usize]> = node
+            .inputs
+            .iter()
+            .map(|id| graph.nodes[id.0].shape.as_slice())
+            .collect();
+        let value = eval(&CpuBackend, node, &args, &shapes);
+        let node = &mut graph.nodes[i];
+        node.op = Op::Constant(value);
+        node.inputs.clear();
+        folded += 1;
+    }
+    folded
+}
+
+/// Fuse `softmax(q k^T * c) v` into [`Op::FusedAttention`].  The scores may
+/// be scaled by multiplying with or dividing by a scalar constant.
+pub fn fuse_attention(graph: &mut Graph) -> usize {
+    let mut fused = 0;
+    for i in 0..graph.nodes.len() {
+        let uses = graph.use_counts();
+        let nodes = &graph.nodes;
+        let absorbable = |id: TensorId| uses[id.0] == 1;
+        let node = &nodes[i];
+        if node.op != Op::MatMul {
+            continue;
+        }
+        let (probs, v) = (node.inputs[0], node.inputs[1]);
+        if nodes[probs.0].op != Op::Softmax || !absorbable(probs) {
+            continue;
+        }
+        let mut scores = nodes[probs.0].inputs[0];
+        let mut scale = 1.0;
+        if let Op::Binary(op @ (BinaryOp::Mul | BinaryOp::Div)) = nodes[scores.0].op {
+            let (x, c) = (nodes[scores.0].inputs[0], nodes[scores.0].inputs[1]);
+            match &nodes[c.0].op {
+                Op::Constant(data) if data.len() == 1 && absorbable(scores) => {
+                    scale = op.apply(1.0, data[0]);
+                    scores = x;
+                }
+                _ => continue,
+            }
+        }
+        let Op::MatMul = nodes[scores.0].op else {
+            continue;
+        };
+        let (q, kt) = (nodes[scores.0].inputs[0], nodes[scores.0].inputs[1]);
+        if !absorbable(scores) || nodes[kt.0].op != Op::Transpose {
+            continue;
+        }
+        let k = nodes[kt.0].inputs[0];
+        let node = &mut graph.nodes[i];
+        node.op = Op::FusedAttention { scale };
+        node.inputs = vec![q, k, v];
+        fused += 1;
+    }
+    fused
+}
+
+/// Fuse `rms_norm(a b + residual)`, with a residual of the shape of the
+/// matmul output, into [`Op::MatMulResidualRmsNorm`].
+pub fn fuse_residual_rms_norm(graph: &mut Graph) -> usize {
+    let mut fused = 0;
+    for i in 0..graph.nodes.len() {
+        let uses = graph.use_counts();
+        let nodes = &graph.nodes;
+        let Op::RmsNorm { eps } = nodes[i].op else {
+            continue;
+        };
+        let (sum, gamma) = (nodes[i].inputs[0], nodes[i].inputs[1]);
+        if nodes[sum.0].op != Op::Binary(BinaryOp::Add) || uses[sum.0] != 1 {
+            continue;
+        }
+        let operands = &nodes[sum.0].inputs;
+        let matmul = (0..2).find(|&j| {
+            let (x, residual) = (operands[j], operands[1 - j]);
+            nodes[x.0].op == Op::MatMul
+                && uses[x.0] == 1
+                && nodes[residual.0].shape == nodes[x.0].shape
+        });
+        let Some(j) = matmul else {
+            continue;
+        };
+        let mut inputs = nodes[operands[j].0].inputs.clone();
+        inputs.extend([operands[1 - j], gamma]);
+        let node = &mut graph.nodes[i];
+        node.op = Op::MatMulResidualRmsNorm { eps };
+        node.inputs = inputs;
+        fused += 1;
+    }
+    fused
+}
+
+/// Fuse a matmul with a following bias add of one value per output column
+/// and a following activation, in that order, into [`Op::FusedMatMul`].
+pub fn fuse_matmul_epilogues(graph: &mut Graph) -> usize {
+    let mut fused = 0;
+    for i in 0..graph.nodes.len() {
+        let uses = graph.use_counts();
+        let node = &graph.nodes[i];
+        let absorbable = |id: TensorId| uses[id.0] == 1;
+        let rewrite = match node.op {
+            Op::Binary(BinaryOp::Add) => {
+                let (x, bias) = (node.inputs[0], node.inputs[1]);
+                let producer = &graph.nodes[x.0];
+                let column_bias = node.shape.get(1..) == Some(graph.nodes[bias.0].shape.as_slice());
+                match producer.op {
+                    Op::MatMul if absorbable(x) && column_bias => {
+                        let mut inputs = producer.inputs.clone();
+                        inputs.push(bias);
+                        Some((
+                            Op::FusedMatMul {
+                                bias: true,
+                                activation: None,
+                            },
+                            inputs,
+                        ))
+                    }
+                    _ => None,
+                }
+            }
+            Op::Unary(act) if act.is_activation() => {
+                let x = node.inputs[0];
+                let producer = &graph.nodes[x.0];
+                match producer.op {
+                    Op::MatMul if absorbable(x) => Some((
+                        Op::FusedMatMul {
+                            bias: false,
+                            activation: Some(act),
+                        },
+                        producer.inputs.clone(),
+                    )),
+                    Op::FusedMatMul {
+                        bias,
+                        activation: None,
+                    } if absorbable(x) => Some((
+                        Op::FusedMatMul {
+                            bias,
+                            activation: Some(act),
+                        },
+                        producer.inputs.clone(),
+                    )),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some((op, inputs)) = rewrite {
+            let node = &mut graph.nodes[i];
+            node.op = op;
+            node.inputs = inputs;
+            fused += 1;
+        }
+    }
+    fused
+}
+
+/// Merge chains of elementwise ops of the same shape into one
+/// [`Op::FusedElementwise`] program.
+pub fn fuse_elementwise(graph: &mut Graph) -> usize {
+    let mut fused = 0;
+    for i in 0..graph.nodes.len() {
+        let Some(mut steps) = program(&graph.nodes[i].op) else {
+            continue;
+        };
+        let mut inputs = graph.nodes[i].inputs.clone();
+        loop {
+            let uses = graph.use_counts();
+            let shape = &graph.nodes[i].shape;
+            let candidate = inputs.iter().position(|id| {
+                let producer = &graph.nodes[id.0];
+                uses[id.0] == 1 && producer.op.is_elementwise() && &producer.shape == shape
+            });
+            let Some(j) = candidate else {
+                break;
+            };
+            let producer = &graph.nodes[inputs[j].0];
+            let producer_steps = program(&producer.op).expect("elementwise producer");
+            (steps, inputs) = inline(&steps, &inputs, j, &producer_steps, &producer.inputs);
+            let node = &mut graph.nodes[i];
+            node.op = Op::FusedElementwise(steps.clone());
+            node.inputs = inputs.clone();
+            fused += 1;
+        }
+    }
+    fused
+}
+
+/// Remove nodes the outputs do not depend on and renumber the rest.
+pub fn eliminate_dead_nodes(graph: &mut Graph) -> usize {
+    let live = graph.live();
+    let mut ids = Vec::with_capacity(graph.nodes.len());
+    let mut next = 0;
+    for &l in &live {
+        ids.push(TensorId(next));
+        next += usize::from(l);
+    }
+    let removed = graph.nodes.len() - next;
+    let nodes = std::mem::take(&mut graph.nodes);
+    graph.nodes = nodes
+        .into_iter()
+        .zip(&live)
+        .filter(|(_, live)| **live)
+        .map(|(mut node, _)| {
+            for input in &mut node.inputs {
+                *input = ids[input.0];
+            }
+            node
+        })
+        .collect();
+    for output in &mut graph.outputs {
+        *output = ids[output.0];
+    }
+    removed
+}
+
+/// Elementwise program computing `op` from the node inputs.
+fn program(op: &Op) -> Option<Vec<ElementwiseStep>> {
+    match op {
+        Op::Unary(op) => Some(vec![
+            ElementwiseStep::Load(0),
+            ElementwiseStep::Unary(*op, 0),
+        ]),
+        Op::Binary(op) => Some(vec![
+            ElementwiseStep::Load(0),
+            ElementwiseStep::Load(1),
+            ElementwiseStep::Binary(*op, 0, 1),
+        ]),
+        Op::FusedElementwise(steps) => Some(steps.clone()),
+        _ => None,
+    }
+}
+
+/// Substitute the program of the producer of input `j` for the loads of that
+/// input, returning the merged program and inputs.
+fn inline(
+    steps: &[ElementwiseStep],
+    inputs: &[TensorId],
+    j: usize,
+    producer_steps: &[ElementwiseStep],
+    producer_inputs: &[TensorId],
+) -> (Vec<ElementwiseStep>, Vec<TensorId>) {
+    let mut merged: Vec<TensorId> = inputs
+        .iter()
+        .enumerate()
+        .filter(|&(x, _)| x != j)
+        .map(|(_, &id)| id)
+        .collect();
+    let mut slot = |id: TensorId| match merged.iter().position(|&m| m == id) {
+        Some(pos) => pos,
+        None => {
+            merged.push(id);
+            merged.len() - 1
+        }
+    };
+
+    let mut out = Vec::new();
+    // Register of `out` holding each register of `steps`.
+    let mut regs = Vec::with_capacity(steps.len());
+    for step in steps {
+        let step = match *step {
+            ElementwiseStep::Load(x) if x == j => {
+                let base = out.len();
+                for producer_step in producer_steps {
+                    out.push(match *producer_step {
+                        ElementwiseStep::Load(y) => ElementwiseStep::Load(slot(producer_inputs[y])),
+                        ElementwiseStep::Unary(op, a) => ElementwiseStep::Unary(op, base + a),
+                        ElementwiseStep::Binary(op, a, b) => {
+                            ElementwiseStep::Binary(op, base + a, base + b)
+                        }
+                    });
+                }
+                regs.push(out.len() - 1);
+                continue;
+            }
+            ElementwiseStep::Load(x) => ElementwiseStep::Load(slot(inputs[x])),
+            ElementwiseStep::Unary(op, a) => ElementwiseStep::Unary(op, regs[a]),
+            ElementwiseStep::Binary(op, a, b) => ElementwiseStep::Binary(op, regs[a], regs[b]),
+        };
+        out.push(step);
+        regs.push(out.len() - 1);
+    }
+    (out, merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::Executor;
+    use crate::graph::UnaryOp;
+
+    #[test]
+    fn passes_fold_fuse_and_prune() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[2, 2]);
+        let w = graph.input("w", &[2, 2]);
+        let half = graph.constant(vec![0.25, 0.5], &[2]).unwrap();
+        let bias = graph.add(half, half).unwrap();
+        let h = graph.matmul(x, w).unwrap();
+        let h = graph.add(h, bias).unwrap();
+        let h = graph.unary(UnaryOp::Gelu, h).unwrap();
+        // Residual branch: three elementwise ops over x.
+        let r = graph.unary(UnaryOp::Neg, x).unwrap();
+        let r = graph.mul(r, x).unwrap();
+        let y = graph.add(h, r).unwrap();
+        let unused = graph.matmul(w, x).unwrap();
+        graph.unary(UnaryOp::Exp, unused).unwrap();
+        graph.output(y).unwrap();
+
+        let cpu = CpuBackend;
+        let inputs: [(&str, &[f32]); 2] =
+            [("x", &[1.0, -2.0, 0.5, 3.0]), ("w", &[0.5, 1.0, -1.0, 2.0])];
+        let expected = Executor::new(&cpu).run(&graph, &inputs).unwrap();
+
+        assert_eq!(fold_constants(&mut graph), 1);
+        assert_eq!(graph.node(bias).op, Op::Constant(vec![0.5, 1.0]));
+        assert_eq!(fuse_matmul_epilogues(&mut graph), 2);
+        assert_eq!(fuse_elementwise(&mut graph), 2);
+        assert_eq!(eliminate_dead_nodes(&mut graph), 7);
+        assert_eq!(
+            graph.kernel_names(),
+            vec!["matmul_bias_gelu", "fused_neg_mul_add"]
+        );
+        assert_eq!(graph.nodes().len(), 5);
+        assert_eq!(Executor::new(&cpu).run(&graph, &inputs).unwrap(), expected);
+    }
+
+    #[test]
+    fn fuses_attention_and_residual_rms_norm() {
+        let mut graph = Graph::new();
+        let x = graph.input("x", &[3, 4]);
+        let wq = graph.input("wq", &[4, 4]);
+        let wo = graph.input("wo", &[4, 4]);
+        let gamma = graph.constant(vec![1.0, 0.5, 2.0, 1.5], &[4]).unwrap();
+        let q = graph.matmul(x, wq).unwrap();
+        let kt = graph.transpose(x).unwrap();
+        let scores = graph.matmul(q, kt).unwrap();
+        let scale = graph.constant(vec![2.0], &[1]).unwrap();
+        let scores = graph.binary(BinaryOp::Div, scores, scale).unwrap();
+        let probs = graph.softmax(scores).unwrap();
+        let attn = graph.matmul(probs, x).unwrap();
+        let proj = graph.matmul(attn, wo).unwrap();
+        let sum = graph.add(x, proj).unwrap();
+        let y = graph.rms_norm(sum, gamma, 1e-5).unwrap();
+        graph.output(y).unwrap();
+
+        let cpu = CpuBackend;
+        let x_data: Vec<f32> = (0..12).map(|i| (i as f32 * 0.7).sin()).collect();
+        let wq_data: Vec<f32> = (0..16).map(|i| (i as f32 * 0.3).cos()).collect();
+        let wo_data: Vec<f32> = (0..16).map(|i| ((i % 5) as f32 - 2.0) * 0.25).collect();
+        let inputs: [(&str, &[f32]); 3] = [("x", &x_data), ("wq", &wq_data), ("wo", &wo_data)];
+        let expected = Executor::new(&cpu).run(&graph, &inputs).unwrap();
+
+        optimize(&mut graph);
+        assert_eq!(
+            graph.kernel_names(),
+            vec!["matmul", "fused_attention", "matmul_residual_rms_norm"]
+        );
+        assert_eq!(graph.nodes()[5].op, Op::FusedAttention { scale: 0.5 });
+        let actual = Executor::new(&cpu).run(&graph, &inputs).unwrap();
+        for (got, want) in actual[0].iter().zip(&expected[0]) {
+            assert!((got - want).abs() < 1e-5, "{got} != {want}");
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Vector primitives of the fused CPU kernels.
+//!
+//! Each primitive uses AVX when the host supports it and falls back to
+//! scalar loops otherwise.  The AVX paths sum in a different order, so
+//! results may differ from the scalar ones in the last bits.  Code run
+//! through [`scalar`] always takes the scalar loops, whose results do not
+//! depend on the host.
+
+use std::cell::Cell;
+
+thread_local! {
+    static FORCE_SCALAR: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` with the scalar loops on this thread.
+pub(crate) fn scalar<R>(f: impl FnOnce() -> R) -> R {
+    let previous = FORCE_SCALAR.with(|force| force.replace(true));
+    let result = f();
+    FORCE_SCALAR.with(|force| force.set(previous));
+    result
+}
+
+#[cfg(target_arch = "x86_64")]
+fn use_avx() -> bool {
+    !FORCE_SCALAR.with(Cell::get) && is_x86_feature_detected!("avx")
+}
+
+/// Sum of `a[i] * b[i]`.
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    #[cfg(target_arch = "x86_64")]
+    if use_avx() {
+        return unsafe { avx::dot(&a[..len], &b[..len]) };
+    }
+    a[..len].iter().zip(&b[..len]).map(|(x, y)| x * y).sum()
+}
+
+/// `y[i] += alpha * x[i]`.
+pub(crate) fn axpy(y: &mut [f32], alpha: f32, x: &[f32]) {
+    let len = y.len().min(x.len());
+    #[cfg(target_arch = "x86_64")]
+    if use_avx() {
+        return unsafe { avx::axpy(&mut y[..len], alpha, &x[..len]) };
+    }
+    for (y, x) in y[..len].iter_mut().zip(&x[..len]) {
+        *y += alpha * x;
+    }
+}
+
+/// `y[i] += x[i]`.
+pub(crate) fn add_assign(y: &mut [f32], x: &[f32]) {
+    axpy(y, 1.0, x);
+}
+
+/// `y[i] *= scale * gamma[i]`.
+pub(crate) fn scale_mul(y: &mut [f32], scale: f32, gamma: &[f32]) {
+    let len = y.len().min(gamma.len());
+    #[cfg(target_arch = "x86_64")]
+    if use_avx() {
+        return unsafe { avx::scale_mul(&mut y[..len], scale, &gamma[..len]) };
+    }
+    for (y, g) in y[..len].iter_mut().zip(&gamma[..len]) {
+        *y *= scale * g;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx {
+    use std::arch::x86_64::*;
+
+    const LANES: usize = 8;
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let mut acc = _mm256_setzero_ps();
+        let mut i = 0;
+        while i + LANES <= a.len() {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i));
+            acc = _mm256_add_ps(acc, _mm256_mul_ps(x, y));
+            i += LANES;
+        }
+        let mut lanes = [0.0f32; LANES];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), acc);
+        let mut sum: f32 = lanes.iter().sum();
+        for j in i..a.len() {
+            sum += a[j] * b[j];
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn axpy(y: &mut [f32], alpha: f32, x: &[f32]) {
+        let a = _mm256_set1_ps(alpha);
+        let mut i = 0;
+        while i + LANES <= y.len() {
+            let xv = _mm256_loadu_ps(x.as_ptr().add(i));
+            let yv = _mm256_loadu_ps(y.as_ptr().add(i));
+            _mm256_storeu_ps(
+                y.as_mut_ptr().add(i),
+                _mm256_add_ps(yv, _mm256_mul_ps(a, xv)),
+            );
+            i += LANES;
+        }
+        for j in i..y.len() {
+            y[j] += alpha * x[j];
+        }
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn scale_mul(y: &mut [f32], scale: f32, gamma: &[f32]) {
+        let s = _mm256_set1_ps(scale);
+        let mut i = 0;
+        while i + LANES <= y.len() {
+            let g = _mm256_loadu_ps(gamma.as_ptr().add(i));
+            let yv = _mm256_loadu_ps(y.as_ptr().add(i));
+            _mm256_storeu_ps(
+                y.as_mut_ptr().add(i),
+                _mm256_mul_ps(yv, _mm256_mul_ps(s, g)),
+            );
+            i += LANES;
+        }
+        for j in i..y.len() {
+            y[j] *= scale * gamma[j];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitives_handle_remainders() {
+        // 19 values: two full AVX vectors and a scalar tail.
+        let x: Vec<f32> = (0..19).map(|i| i as f32 * 0.5 - 4.0).collect();
+        let g: Vec<f32> = (0..19).map(|i| (i % 3) as f32).collect();
+        let expected: f32 = x.iter().zip(&g).map(|(a, b)| a * b).sum();
+        assert!((dot(&x, &g) - expected).abs() < 1e-4);
+
+        let mut y = g.clone();
+        axpy(&mut y, 2.0, &x);
+        let want: Vec<f32> = g.iter().zip(&x).map(|(g, x)| g + 2.0 * x).collect();
+        assert_eq!(y, want);
+
+        let mut y = x.clone();
+        scale_mul(&mut y, 0.5, &g);
+        let want: Vec<f32> = x.iter().zip(&g).map(|(x, g)| x * (0.5 * g)).collect();
+        assert_eq!(y, want);
+    }
+
+    #[test]
+    fn scalar_sums_in_index_order() {
+        let x: Vec<f32> = (0..37).map(|i| 1.0 / (i as f32 + 1.0)).collect();
+        let sequential = x.iter().fold(0.0f32, |acc, v| acc + v * v);
+        assert_eq!(scalar(|| dot(&x, &x)).to_bits(), sequential.to_bits());
+    }
+}
@@ -0,0 +1,186 @@
+//! Driver interface of FPGA/NPU accelerators.
+//!
+//! A driver enumerates the devices of a machine, maps DMA buffers the host
+//! fills and reads back, and queues commands on a device.  Submission is
+//! asynchronous: [`Driver::submit`] returns a [`JobId`] the caller polls for
+//! completion, or waits on through [`wait`].  [`SimulatorDriver`] implements
+//! the interface in software so the plugin runs without hardware.
+//!
+//! [`SimulatorDriver`]: crate::simulator::SimulatorDriver
+
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+/// Set once a device was discovered and opened by the plugin.
+pub static INITIALIZED: AtomicBool = AtomicBool::new(false);
+/// Set once a job completed on a device.
+pub static EXECUTED: AtomicBool = AtomicBool::new(false);
+
+/// Device reported by [`Driver::discover`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Index passed to [`Driver::alloc`] and [`Driver::submit`].
+    pub index: usize,
+    pub name: String,
+    /// Bytes of DMA memory the device can map.
+    pub memory: usize,
+}
+
+/// DMA buffer mapped on a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferHandle(pub u64);
+
+/// Job queued on a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct JobId(pub u64);
+
+/// Command executed by a device.  Buffers hold little endian values: `f32`
+/// unless noted otherwise, results are always written as `f32`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// `m x k` matrix `a` times `k x n` matrix `b` into `out`.
+    MatMul {
+        a: BufferHandle,
+        b: BufferHandle,
+        out: BufferHandle,
+        m: usize,
+        n: usize,
+        k: usize,
+    },
+    /// Matmul of `i8` matrices accumulated in `i32`, with the result scaled
+    /// by `a_scale * b_scale`.
+    QuantizedMatMul {
+        a: BufferHandle,
+        b: BufferHandle,
+        out: BufferHandle,
+        m: usize,
+        n: usize,
+        k: usize,
+        a_scale: f32,
+        b_scale: f32,
+    },
+    /// Layer norm of `len` values.
+    LayerNorm {
+        x: BufferHandle,
+        gamma: BufferHandle,
+        beta: BufferHandle,
+        out: BufferHandle,
+        len: usize,
+        eps: f32,
+    },
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::MatMul { .. } => "matmul",
+            Command::QuantizedMatMul { .. } => "quantized_matmul",
+            Command::LayerNorm { .. } => "layer_norm",
+        }
+    }
+}
+
+/// Progress of a submitted job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Waiting behind earlier jobs of the device.
+    Queued,
+    Complete,
+    Failed(String),
+}
+
+/// Error raised by a driver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DriverError {
+    /// No device with this index.
+    NoDevice(usize),
+    /// The device cannot map another `requested` bytes.
+    OutOfMemory { requested: usize, available: usize },
+    /// The buffer is not mapped.
+    InvalidBuffer(BufferHandle),
+    /// An access of `len` bytes at `offset` exceeds the buffer.
+    OutOfBounds {
+        buffer: BufferHandle,
+        offset: usize,
+        len: usize,
+    },
+    /// No job with this id was submitted, or its status was already
+    /// collected.
+    UnknownJob(JobId),
+    /// The job did not complete in time.
+    Timeout(JobId),
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::NoDevice(index) => write!(f, "no device {index}"),
+            DriverError::OutOfMemory {
+                requested,
+                available,
+            } => write!(
+                f,
+                "cannot map {requested} bytes, {available} bytes available"
+            ),
+            DriverError::InvalidBuffer(buffer) => write!(f, "buffer {} is not mapped", buffer.0),
+            DriverError::OutOfBounds {
+                buffer,
+                offset,
+                len,
+            } => write!(
+                f,
+                "access of {len} bytes at offset {offset} exceeds buffer {}",
+                buffer.0
+            ),
+            DriverError::UnknownJob(job) => write!(f, "unknown job {}", job.0),
+            DriverError::Timeout(job) => write!(f, "job {} timed out", job.0),
+        }
+    }
+}
+
+impl std::error::Error for DriverError {}
+
+/// Accelerator driver.
+pub trait Driver: Send + Sync {
+    /// Devices present on the machine.
+    fn discover(&self) -> Vec<DeviceInfo>;
+    /// Map a zeroed DMA buffer of `len` bytes on `device`.
+    fn alloc(&self, device: usize, len: usize) -> Result<BufferHandle, DriverError>;
+    /// Release a buffer mapped by [`Driver::alloc`].
+    fn free(&self, buffer: BufferHandle) -> Result<(), DriverError>;
+    /// Copy `data` into `buffer` at `offset` bytes.
+    fn write(&self, buffer: BufferHandle, offset: usize, data: &[u8]) -> Result<(), DriverError>;
+    /// Copy `out.len()` bytes at `offset` of `buffer` into `out`.
+    fn read(&self, buffer: BufferHandle, offset: usize, out: &mut [u8]) -> Result<(), DriverError>;
+    /// Queue `command` on `device`.  Jobs of a device run in submission
+    /// order.
+    fn submit(&self, device: usize, command: Command) -> Result<JobId, DriverError>;
+    /// Status of `job`.  The status of a finished job is returned once.
+    fn poll(&self, job: JobId) -> Result<JobStatus, DriverError>;
+}
+
+/// Poll `job` until it finishes or `timeout` elapses.
+pub fn wait(driver: &dyn Driver, job: JobId, timeout: Duration) -> Result<JobStatus, DriverError> {
+    let started = Instant::now();
+    loop {
+        match driver.poll(job)? {
+            JobStatus::Queued if started.elapsed() < timeout => std::thread::yield_now(),
+            JobStatus::Queued => return Err(DriverError::Timeout(job)),
+            status => return Ok(status),
+        }
+    }
+}
+
+/// Little endian bytes of `values`, as written to DMA buffers.
+pub fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Values of little endian `f32` bytes.
+pub fn bytes_f32(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
@@ -1,70 +1,241 @@
 #![allow(improper_ctypes_definitions)]
 
-use aurex_runtime::BackendPlugin;
-
-/// Low level driver interactions for the FPGA/NPU plugin.  The functions use
-/// simple libc calls to open a device file and issue a dummy ioctl which is
-/// sufficient for tests that validate that real driver entry points are
-/// exercised.
-pub mod driver {
-    use libc::{c_int, c_void, ioctl, open, O_RDWR};
-    use once_cell::sync::OnceCell;
-    use std::sync::atomic::{AtomicBool, Ordering};
-
-    /// File descriptor to the simulated device.
-    static FD: OnceCell<c_int> = OnceCell::new();
-
-    /// Flag indicating that the driver was initialized.
-    pub static INITIALIZED: AtomicBool = AtomicBool::new(false);
-    /// Flag indicating that an execution call was issued.
-    pub static EXECUTED: AtomicBool = AtomicBool::new(false);
-
-    /// Open the device file representing the accelerator.  We use `/dev/null`
-    /// as a stand in which is always present in Unix like environments.
-    pub fn init_driver() {
-        unsafe {
-            let fd = open(b"/dev/null\0".as_ptr() as *const i8, O_RDWR);
-            if fd >= 0 {
-                let _ = FD.set(fd);
-                INITIALIZED.store(true, Ordering::SeqCst);
-            }
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use aurex_runtime::config::PluginConfig;
+use aurex_runtime::{BackendPlugin, PluginError, TensorOpRequest, TensorOpResponse};
+
+use driver::{BufferHandle, Command, DeviceInfo, Driver, DriverError, JobStatus};
+use simulator::SimulatorDriver;
+
+pub mod driver;
+pub mod simulator;
+
+/// Ops the accelerator implements.
+pub const SUPPORTED_OPS: [&str; 2] = ["matmul", "layer_norm"];
+
+/// Time a job may take before the op fails.
+pub const JOB_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// FPGA/NPU backend plugin.  Ops are copied into DMA buffers of the first
+/// device the driver discovers and executed as jobs on its queue; the
+/// default driver is the software [`SimulatorDriver`].
+pub struct FpgaNpuPlugin {
+    driver: Box<dyn Driver>,
+    device: Mutex<Option<DeviceInfo>>,
+    /// Ops enabled by the `ops` option, all of [`SUPPORTED_OPS`] by default.
+    enabled: Mutex<Vec<String>>,
+    /// Run matmuls on `i8` values, set by the `quantize` option.
+    quantize: AtomicBool,
+}
+
+impl FpgaNpuPlugin {
+    pub fn with_driver(driver: Box<dyn Driver>) -> Self {
+        Self {
+            driver,
+            device: Mutex::new(None),
+            enabled: Mutex::new(SUPPORTED_OPS.iter().map(|op| op.to_string()).collect()),
+            quantize: AtomicBool::new(false),
         }
     }
 
-    /// Issue a dummy ioctl against the previously opened file descriptor.  The
-    /// call itself does nothing but models the flow a real driver invocation
-    /// would take.
-    pub fn run_job() {
-        if let Some(&fd) = FD.get() {
-            unsafe {
-                // SAFETY: The file descriptor is valid for the duration of the
-                // program and we pass a null pointer for the unused argument.
-                ioctl(fd, 0, std::ptr::null_mut::<c_void>());
+    /// Device opened by [`BackendPlugin::initialize`].
+    pub fn device(&self) -> Option<DeviceInfo> {
+        self.device.lock().unwrap().clone()
+    }
+
+    /// Copy `inputs` into DMA buffers, run the command built from their
+    /// handles and an output buffer of `outputs` values, and read the output
+    /// back.  Buffers are released whether or not the job succeeds.
+    fn run(
+        &self,
+        inputs: &[&[u8]],
+        outputs: usize,
+        command: impl FnOnce(&[BufferHandle], BufferHandle) -> Command,
+    ) -> Result<Vec<f32>, PluginError> {
+        let device = self
+            .device()
+            .ok_or_else(|| PluginError::Device("no device discovered".to_string()))?
+            .index;
+        let mut buffers = Vec::with_capacity(inputs.len() + 1);
+        let result = (|| {
+            for input in inputs {
+                let buffer = self.driver.alloc(device, input.len())?;
+                buffers.push(buffer);
+                self.driver.write(buffer, 0, input)?;
+            }
+            let out = self.driver.alloc(device, outputs * 4)?;
+            buffers.push(out);
+            let job = self
+                .driver
+                .submit(device, command(&buffers[..inputs.len()], out))?;
+            let status = driver::wait(&*self.driver, job, JOB_TIMEOUT)?;
+            let mut bytes = vec![0; outputs * 4];
+            self.driver.read(out, 0, &mut bytes)?;
+            Ok((status, bytes))
+        })();
+        for buffer in buffers {
+            if let Err(err) = self.driver.free(buffer) {
+                tracing::warn!(%err, "failed to free dma buffer");
             }
-            EXECUTED.store(true, Ordering::SeqCst);
         }
+        match result.map_err(|err: DriverError| PluginError::Device(err.to_string()))? {
+            (JobStatus::Complete, bytes) => {
+                driver::EXECUTED.store(true, Ordering::SeqCst);
+                Ok(driver::bytes_f32(&bytes))
+            }
+            (JobStatus::Failed(reason), _) => Err(PluginError::Device(reason)),
+            (JobStatus::Queued, _) => unreachable!("wait returns finished jobs"),
+        }
+    }
+}
+
+impl Default for FpgaNpuPlugin {
+    fn default() -> Self {
+        Self::with_driver(Box::new(SimulatorDriver::default()))
     }
 }
 
-/// FPGA/NPU backend plugin using a very small simulated driver interface.
-pub struct FpgaNpuPlugin;
+/// Symmetric per-tensor `i8` quantization of `values` and its scale.
+fn quantize_i8(values: &[f32]) -> (Vec<u8>, f32) {
+    let max = values.iter().fold(0.0_f32, |m, v| m.max(v.abs()));
+    let scale = if max == 0.0 { 1.0 } else { max / 127.0 };
+    let quantized = values
+        .iter()
+        .map(|v| (v / scale).round().clamp(-127.0, 127.0) as i8 as u8)
+        .collect();
+    (quantized, scale)
+}
 
 impl BackendPlugin for FpgaNpuPlugin {
     fn name(&self) -> &'static str {
         "fpga_npu"
     }
 
+    /// Opens the first device the driver discovers.
     fn initialize(&self) {
-        driver::init_driver();
+        let device = self.driver.discover().into_iter().next();
+        match &device {
+            Some(info) => {
+                tracing::debug!(device = %info.name, memory = info.memory, "fpga_npu device opened");
+                driver::INITIALIZED.store(true, Ordering::SeqCst);
+            }
+            None => tracing::warn!("no fpga_npu device found"),
+        }
+        *self.device.lock().unwrap() = device;
     }
 
+    /// Reads the `ops` option, the subset of [`SUPPORTED_OPS`] to accept,
+    /// and the `quantize` option.
+    fn configure(&self, config: &PluginConfig) {
+        let Some(options) = config.options(self.name()) else {
+            return;
+        };
+        if let Some(ops) = options.get("ops").and_then(|ops| ops.as_array()) {
+            *self.enabled.lock().unwrap() = ops
+                .iter()
+                .filter_map(|op| op.as_str())
+                .filter(|op| SUPPORTED_OPS.contains(op))
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(quantize) = options.get("quantize").and_then(|q| q.as_bool()) {
+            self.quantize.store(quantize, Ordering::Relaxed);
+        }
+    }
+
+    /// Runs a 1x1 matmul as a device self test.
     fn execute(&self) {
-        driver::run_job();
+        let one = driver::f32_bytes(&[1.0]);
+        let result = self.run(&[&one, &one], 1, |inputs, out| Command::MatMul {
+            a: inputs[0],
+            b: inputs[1],
+            out,
+            m: 1,
+            n: 1,
+            k: 1,
+        });
+        if let Err(err) = result {
+            tracing::warn!(%err, "fpga_npu self test failed");
+        }
+    }
+
+    fn execute_op(&self, op: TensorOpRequest) -> Result<TensorOpResponse, PluginError> {
+        if !self.enabled.lock().unwrap().iter().any(|e| e == op.name()) {
+            return Err(PluginError::Unsupported(op.name()));
+        }
+        let output = match op {
+            TensorOpRequest::MatMul { a, b, m, n, k } => {
+                if a.len() != m * k || b.len() != k * n {
+                    return Err(PluginError::InvalidInput(format!(
+                        "matmul of {m}x{k} and {k}x{n} with {} and {} values",
+                        a.len(),
+                        b.len()
+                    )));
+                }
+                if self.quantize.load(Ordering::Relaxed) {
+                    let (qa, a_scale) = quantize_i8(a);
+                    let (qb, b_scale) = quantize_i8(b);
+                    self.run(&[&qa, &qb], m * n, |inputs, out| Command::QuantizedMatMul {
+                        a: inputs[0],
+                        b: inputs[1],
+                        out,
+                        m,
+                        n,
+                        k,
+                        a_scale,
+                        b_scale,
+                    })?
+                } else {
+                    let (a, b) = (driver::f32_bytes(a), driver::f32_bytes(b));
+                    self.run(&[&a, &b], m * n, |inputs, out| Command::MatMul {
+                        a: inputs[0],
+                        b: inputs[1],
+                        out,
+                        m,
+                        n,
+                        k,
+                    })?
+                }
+            }
+            TensorOpRequest::LayerNorm {
+                x,
+                gamma,
+                beta,
+                eps,
+            } => {
+                if gamma.len() != x.len() || beta.len() != x.len() {
+                    return Err(PluginError::InvalidInput(format!(
+                        "layer norm of {} values with {} gammas and {} betas",
+                        x.len(),
+                        gamma.len(),
+                        beta.len()
+                    )));
+                }
+                let inputs = [x, gamma, beta].map(driver::f32_bytes);
+                self.run(
+                    &[&inputs[0], &inputs[1], &inputs[2]],
+                    x.len(),
+                    |inputs, out| Command::LayerNorm {
+                        x: inputs[0],
+                        gamma: inputs[1],
+                        beta: inputs[2],
+                        out,
+                        len: x.len(),
+                        eps,
+                    },
+                )?
+            }
+            _ => unreachable!("only supported ops are enabled"),
+        };
+        Ok(TensorOpResponse::new(output))
     }
 }
 
 /// Exported constructor called by the runtime to instantiate the plugin.
 #[no_mangle]
 pub extern "C" fn create_plugin() -> *mut dyn BackendPlugin {
-    Box::into_raw(Box::new(FpgaNpuPlugin))
+    Box::into_raw(Box::new(FpgaNpuPlugin::default()))
 }
@@ -0,0 +1,279 @@
+//! Software implementation of the accelerator [`Driver`].
+//!
+//! Each simulated device owns a bounded amount of DMA memory and a FIFO job
+//! queue.  Jobs run when polled: every [`Driver::poll`] executes the oldest
+//! queued job of the polled job's device, so a job submitted behind others
+//! reports [`JobStatus::Queued`] until they have run.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::driver::{
+    bytes_f32, f32_bytes, BufferHandle, Command, DeviceInfo, Driver, DriverError, JobId, JobStatus,
+};
+
+/// Simulated devices and their buffers and queues.
+pub struct SimulatorDriver {
+    devices: Vec<DeviceInfo>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_buffer: u64,
+    next_job: u64,
+    /// Device and contents of every mapped buffer.
+    buffers: HashMap<BufferHandle, (usize, Vec<u8>)>,
+    /// Mapped bytes per device.
+    used: Vec<usize>,
+    queues: Vec<VecDeque<(JobId, Command)>>,
+    finished: HashMap<JobId, JobStatus>,
+}
+
+impl SimulatorDriver {
+    /// DMA memory of each simulated device.
+    pub const DEFAULT_MEMORY: usize = 256 << 20;
+
+    /// Simulator with `devices` devices.
+    pub fn new(devices: usize) -> Self {
+        let devices = (0..devices)
+            .map(|index| DeviceInfo {
+                index,
+                name: format!("aurex-npu-sim{index}"),
+                memory: Self::DEFAULT_MEMORY,
+            })
+            .collect::<Vec<_>>();
+        let state = State {
+            used: vec![0; devices.len()],
+            queues: vec![VecDeque::new(); devices.len()],
+            ..State::default()
+        };
+        Self {
+            devices,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Limit the DMA memory of every device to `bytes`.
+    pub fn with_memory(mut self, bytes: usize) -> Self {
+        for device in &mut self.devices {
+            device.memory = bytes;
+        }
+        self
+    }
+
+    /// Jobs waiting in the queue of `device`.
+    pub fn pending(&self, device: usize) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .queues
+            .get(device)
+            .map_or(0, VecDeque::len)
+    }
+
+    fn device(&self, index: usize) -> Result<&DeviceInfo, DriverError> {
+        self.devices.get(index).ok_or(DriverError::NoDevice(index))
+    }
+}
+
+impl Default for SimulatorDriver {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Driver for SimulatorDriver {
+    fn discover(&self) -> Vec<DeviceInfo> {
+        self.devices.clone()
+    }
+
+    fn alloc(&self, device: usize, len: usize) -> Result<BufferHandle, DriverError> {
+        let memory = self.device(device)?.memory;
+        let mut state = self.state.lock().unwrap();
+        let available = memory - state.used[device];
+        if len > available {
+            return Err(DriverError::OutOfMemory {
+                requested: len,
+                available,
+            });
+        }
+        state.used[device] += len;
+        state.next_buffer += 1;
+        let buffer = BufferHandle(state.next_buffer);
+        state.buffers.insert(buffer, (device, vec![0; len]));
+        Ok(buffer)
+    }
+
+    fn free(&self, buffer: BufferHandle) -> Result<(), DriverError> {
+        let mut state = self.state.lock().unwrap();
+        let (device, data) = state
+            .buffers
+            .remove(&buffer)
+            .ok_or(DriverError::InvalidBuffer(buffer))?;
+        state.used[device] -= data.len();
+        Ok(())
+    }
+
+    fn write(&self, buffer: BufferHandle, offset: usize, data: &[u8]) -> Result<(), DriverError> {
+        let mut state = self.state.lock().unwrap();
+        let (_, contents) = state
+            .buffers
+            .get_mut(&buffer)
+            .ok_or(DriverError::InvalidBuffer(buffer))?;
+        let range = checked_range(buffer, contents.len(), offset, data.len())?;
+        contents[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&self, buffer: BufferHandle, offset: usize, out: &mut [u8]) -> Result<(), DriverError> {
+        let state = self.state.lock().unwrap();
+        let (_, contents) = state
+            .buffers
+            .get(&buffer)
+            .ok_or(DriverError::InvalidBuffer(buffer))?;
+        let range = checked_range(buffer, contents.len(), offset, out.len())?;
+        out.copy_from_slice(&contents[range]);
+        Ok(())
+    }
+
+    fn submit(&self, device: usize, command: Command) -> Result<JobId, DriverError> {
+        self.device(device)?;
+        let mut state = self.state.lock().unwrap();
+        state.next_job += 1;
+        let job = JobId(state.next_job);
+        tracing::trace!(device, job = job.0, command = command.name(), "job queued");
+        state.queues[device].push_back((job, command));
+        Ok(job)
+    }
+
+    fn poll(&self, job: JobId) -> Result<JobStatus, DriverError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(status) = state.finished.remove(&job) {
+            return Ok(status);
+        }
+        let device = state
+            .queues
+            .iter()
+            .position(|queue| queue.iter().any(|&(queued, _)| queued == job))
+            .ok_or(DriverError::UnknownJob(job))?;
+        let (head, command) = state.queues[device]
+            .pop_front()
+            .expect("queue holds the job");
+        let status = match state.execute(device, &command) {
+            Ok(()) => JobStatus::Complete,
+            Err(reason) => JobStatus::Failed(reason),
+        };
+        if head == job {
+            return Ok(status);
+        }
+        state.finished.insert(head, status);
+        Ok(JobStatus::Queued)
+    }
+}
+
+impl State {
+    /// Contents of `buffer`, which must be mapped on `device` and hold at
+    /// least `len` bytes.
+    fn input(&self, device: usize, buffer: BufferHandle, len: usize) -> Result<&[u8], String> {
+        match self.buffers.get(&buffer) {
+            Some((d, data)) if *d == device && data.len() >= len => Ok(&data[..len]),
+            Some((d, _)) if *d != device => {
+                Err(format!("buffer {} is mapped on device {d}", buffer.0))
+            }
+            Some((_, data)) => Err(format!(
+                "buffer {} holds {} bytes, {len} required",
+                buffer.0,
+                data.len()
+            )),
+            None => Err(DriverError::InvalidBuffer(buffer).to_string()),
+        }
+    }
+
+    fn execute(&mut self, device: usize, command: &Command) -> Result<(), String> {
+        let (out, values) = match *command {
+            Command::MatMul { a, b, out, m, n, k } => {
+                let a = bytes_f32(self.input(device, a, m * k * 4)?);
+                let b = bytes_f32(self.input(device, b, k * n * 4)?);
+                let mut c = vec![0.0; m * n];
+                for i in 0..m {
+                    for p in 0..k {
+                        let a_ip = a[i * k + p];
+                        for j in 0..n {
+                            c[i * n + j] += a_ip * b[p * n + j];
+                        }
+                    }
+                }
+                (out, c)
+            }
+            Command::QuantizedMatMul {
+                a,
+                b,
+                out,
+                m,
+                n,
+                k,
+                a_scale,
+                b_scale,
+            } => {
+                let a = self.input(device, a, m * k)?;
+                let b = self.input(device, b, k * n)?;
+                let mut acc = vec![0i32; m * n];
+                for i in 0..m {
+                    for p in 0..k {
+                        let a_ip = a[i * k + p] as i8 as i32;
+                        for j in 0..n {
+                            acc[i * n + j] += a_ip * b[p * n + j] as i8 as i32;
+                        }
+                    }
+                }
+                let scale = a_scale * b_scale;
+                (out, acc.into_iter().map(|v| v as f32 * scale).collect())
+            }
+            Command::LayerNorm {
+                x,
+                gamma,
+                beta,
+                out,
+                len,
+                eps,
+            } => {
+                let x = bytes_f32(self.input(device, x, len * 4)?);
+                let gamma = bytes_f32(self.input(device, gamma, len * 4)?);
+                let beta = bytes_f32(self.input(device, beta, len * 4)?);
+                let count = len.max(1) as f32;
+                let mean = x.iter().sum::<f32>() / count;
+                let var = x.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / count;
+                let denom = (var + eps).sqrt();
+                let y = x
+                    .iter()
+                    .zip(&gamma)
+                    .zip(&beta)
+                    .map(|((v, g), b)| (v - mean) / denom * g + b)
+                    .collect();
+                (out, y)
+            }
+        };
+        let bytes = f32_bytes(&values);
+        self.input(device, out, bytes.len())?;
+        let (_, contents) = self.buffers.get_mut(&out).expect("output buffer is mapped");
+        contents[..bytes.len()].copy_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+fn checked_range(
+    buffer: BufferHandle,
+    size: usize,
+    offset: usize,
+    len: usize,
+) -> Result<std::ops::Range<usize>, DriverError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= size => Ok(offset..end),
+        _ => Err(DriverError::OutOfBounds {
+            buffer,
+            offset,
+            len,
+        }),
+    }
+}
@@ -1,5 +1,5 @@
-use aurex_runtime::BackendPlugin;
-use fpga_npu::{create_plugin, driver};
+use aurex_runtime::{AurexConfig, BackendPlugin, PluginError, TensorOpRequest};
+use fpga_npu::{create_plugin, driver, FpgaNpuPlugin};
 use std::sync::atomic::Ordering;
 
 #[test]
@@ -11,3 +11,86 @@ fn plugin_initializes_and_executes_driver() {
     plugin.execute();
     assert!(driver::EXECUTED.load(Ordering::SeqCst));
 }
+
+#[test]
+fn plugin_executes_enabled_tensor_ops() {
+    let plugin: Box<dyn BackendPlugin> = unsafe { Box::from_raw(create_plugin()) };
+    plugin.initialize();
+    let matmul = TensorOpRequest::MatMul {
+        a: &[1.0, 2.0, 3.0, 4.0],
+        b: &[5.0, 6.0, 7.0, 8.0],
+        m: 2,
+        n: 2,
+        k: 2,
+    };
+    let response = plugin.execute_op(matmul).unwrap();
+    assert_eq!(response.output, vec![19.0, 22.0, 43.0, 50.0]);
+    let bad = TensorOpRequest::MatMul {
+        a: &[1.0],
+        b: &[1.0],
+        m: 2,
+        n: 1,
+        k: 1,
+    };
+    assert!(matches!(
+        plugin.execute_op(bad),
+        Err(PluginError::InvalidInput(_))
+    ));
+
+    let config = AurexConfig::from_toml_str(
+        "[plugins]\noptions = { fpga_npu = { ops = [\"layer_norm\"] } }",
+    )
+    .unwrap();
+    plugin.configure(&config.plugins);
+    assert_eq!(
+        plugin.execute_op(matmul),
+        Err(PluginError::Unsupported("matmul"))
+    );
+    let norm = TensorOpRequest::LayerNorm {
+        x: &[1.0, 3.0],
+        gamma: &[1.0, 1.0],
+        beta: &[0.0, 0.0],
+        eps: 0.0,
+    };
+    assert_eq!(plugin.execute_op(norm).unwrap().output, vec![-1.0, 1.0]);
+}
+
+#[test]
+fn quantized_matmul_runs_on_the_simulator() {
+    let plugin = FpgaNpuPlugin::default();
+    assert!(matches!(
+        plugin.execute_op(TensorOpRequest::MatMul {
+            a: &[1.0],
+            b: &[1.0],
+            m: 1,
+            n: 1,
+            k: 1,
+        }),
+        Err(PluginError::Device(_))
+    ));
+    plugin.initialize();
+    assert_eq!(plugin.device().unwrap().name, "aurex-npu-sim0");
+
+    let config =
+        AurexConfig::from_toml_str("[plugins]\noptions = { fpga_npu = { quantize = true } }")
+            .unwrap();
+    plugin.configure(&config.plugins);
+    let a: Vec<f32> = (0..12).map(|i| i as f32 * 0.3 - 1.5).collect();
+    let b: Vec<f32> = (0..20).map(|i| (i % 7) as f32 * 0.2 - 0.4).collect();
+    let output = plugin
+        .execute_op(TensorOpRequest::MatMul {
+            a: &a,
+            b: &b,
+            m: 3,
+            n: 5,
+            k: 4,
+        })
+        .unwrap()
+        .output;
+    for i in 0..3 {
+        for j in 0..5 {
+            let want: f32 = (0..4).map(|p| a[i * 4 + p] * b[p * 5 + j]).sum();
+            assert!((output[i * 5 + j] - want).abs() < 0.05, "{i},{j}");
+        }
+    }
+}
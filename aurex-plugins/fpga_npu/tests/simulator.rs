@@ -0,0 +1,109 @@
+use fpga_npu::driver::{self, f32_bytes, Command, Driver, DriverError, JobStatus};
+use fpga_npu::simulator::SimulatorDriver;
+use std::time::Duration;
+
+#[test]
+fn jobs_run_in_submission_order() {
+    let sim = SimulatorDriver::new(2);
+    let devices = sim.discover();
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[1].index, 1);
+
+    let a = sim.alloc(0, 16).unwrap();
+    let b = sim.alloc(0, 16).unwrap();
+    let out = sim.alloc(0, 16).unwrap();
+    sim.write(a, 0, &f32_bytes(&[1.0, 2.0, 3.0, 4.0])).unwrap();
+    sim.write(b, 0, &f32_bytes(&[5.0, 6.0, 7.0, 8.0])).unwrap();
+    let matmul = Command::MatMul {
+        a,
+        b,
+        out,
+        m: 2,
+        n: 2,
+        k: 2,
+    };
+    let first = sim.submit(0, matmul.clone()).unwrap();
+    let second = sim.submit(0, matmul).unwrap();
+    assert_eq!(sim.pending(0), 2);
+
+    // Polling the second job runs the first one.
+    assert_eq!(sim.poll(second), Ok(JobStatus::Queued));
+    assert_eq!(sim.poll(second), Ok(JobStatus::Complete));
+    assert_eq!(sim.poll(first), Ok(JobStatus::Complete));
+    assert_eq!(sim.poll(first), Err(DriverError::UnknownJob(first)));
+    assert_eq!(sim.pending(0), 0);
+
+    let mut bytes = [0; 16];
+    sim.read(out, 0, &mut bytes).unwrap();
+    assert_eq!(driver::bytes_f32(&bytes), vec![19.0, 22.0, 43.0, 50.0]);
+}
+
+#[test]
+fn quantized_matmul_accumulates_in_i32() {
+    let sim = SimulatorDriver::default();
+    let a = sim.alloc(0, 4).unwrap();
+    let b = sim.alloc(0, 4).unwrap();
+    let out = sim.alloc(0, 4).unwrap();
+    sim.write(a, 0, &[127, 127, 127, 127]).unwrap();
+    sim.write(b, 0, &[(-127i8) as u8, 127, 127, 127]).unwrap();
+    let job = sim
+        .submit(
+            0,
+            Command::QuantizedMatMul {
+                a,
+                b,
+                out,
+                m: 1,
+                n: 1,
+                k: 4,
+                a_scale: 0.5,
+                b_scale: 0.25,
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        driver::wait(&sim, job, Duration::from_secs(1)),
+        Ok(JobStatus::Complete)
+    );
+    let mut bytes = [0; 4];
+    sim.read(out, 0, &mut bytes).unwrap();
+    assert_eq!(driver::bytes_f32(&bytes), vec![2.0 * 127.0 * 127.0 * 0.125]);
+}
+
+#[test]
+fn dma_memory_is_bounded_and_checked() {
+    let sim = SimulatorDriver::new(1).with_memory(64);
+    let buffer = sim.alloc(0, 48).unwrap();
+    assert_eq!(
+        sim.alloc(0, 32),
+        Err(DriverError::OutOfMemory {
+            requested: 32,
+            available: 16
+        })
+    );
+    assert!(matches!(
+        sim.write(buffer, 40, &[0; 16]),
+        Err(DriverError::OutOfBounds { offset: 40, .. })
+    ));
+    assert_eq!(sim.alloc(3, 4), Err(DriverError::NoDevice(3)));
+
+    // Jobs reading past their buffers fail instead of panicking.
+    let job = sim
+        .submit(
+            0,
+            Command::LayerNorm {
+                x: buffer,
+                gamma: buffer,
+                beta: buffer,
+                out: buffer,
+                len: 64,
+                eps: 1e-5,
+            },
+        )
+        .unwrap();
+    assert!(matches!(sim.poll(job), Ok(JobStatus::Failed(_))));
+
+    sim.free(buffer).unwrap();
+    assert_eq!(sim.free(buffer), Err(DriverError::InvalidBuffer(buffer)));
+    assert!(sim.alloc(0, 64).is_ok());
+}
@@ -0,0 +1,112 @@
+//! Interface of NPU delegates.
+//!
+//! A delegate runs [`Graph`]s on the accelerators of a machine in three
+//! steps, like Android NNAPI and vendor NPU SDKs: [`Delegate::compile`]
+//! prepares a graph for a device and returns a [`ModelHandle`],
+//! [`Delegate::execute`] runs the compiled model on input buffers any number
+//! of times, and [`Delegate::release`] frees it.  [`ReferenceDelegate`]
+//! implements the interface in software and [`NnapiDelegate`] on the devices
+//! NNAPI reports.
+//!
+//! [`Graph`]: crate::graph::Graph
+//! [`ReferenceDelegate`]: crate::reference::ReferenceDelegate
+//! [`NnapiDelegate`]: crate::nnapi::NnapiDelegate
+
+use std::fmt;
+
+use crate::graph::Graph;
+
+/// Kind of a device, as reported by NNAPI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Cpu,
+    Gpu,
+    /// Dedicated accelerator, e.g. an NPU or DSP.
+    Accelerator,
+    Other,
+}
+
+/// Device reported by [`Delegate::devices`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Index passed to [`Delegate::compile`].
+    pub index: usize,
+    pub name: String,
+    pub kind: DeviceKind,
+}
+
+/// Graph compiled by [`Delegate::compile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModelHandle(pub u64);
+
+/// Error raised by a delegate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DelegateError {
+    /// No device with this index.
+    NoDevice(usize),
+    /// The graph is malformed, see [`Graph::validate`].
+    InvalidGraph(String),
+    /// The model was not compiled or was already released.
+    UnknownModel(ModelHandle),
+    /// Execution buffers do not match the graph's inputs or outputs.
+    InvalidBuffers(String),
+    /// The delegate's runtime reported an error.
+    Runtime(String),
+}
+
+impl fmt::Display for DelegateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DelegateError::NoDevice(index) => write!(f, "no device {index}"),
+            DelegateError::InvalidGraph(reason) => write!(f, "invalid graph: {reason}"),
+            DelegateError::UnknownModel(model) => write!(f, "unknown model {}", model.0),
+            DelegateError::InvalidBuffers(reason) => write!(f, "invalid buffers: {reason}"),
+            DelegateError::Runtime(reason) => write!(f, "runtime error: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for DelegateError {}
+
+/// NPU delegate.
+pub trait Delegate: Send + Sync {
+    /// Devices graphs can be compiled for.
+    fn devices(&self) -> Vec<DeviceInfo>;
+    /// Compile `graph` for `device`.
+    fn compile(&self, device: usize, graph: &Graph) -> Result<ModelHandle, DelegateError>;
+    /// Run `model` on one buffer per graph input and fill one buffer per
+    /// graph output, each holding the operand's bytes.
+    fn execute(
+        &self,
+        model: ModelHandle,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<(), DelegateError>;
+    /// Free a model returned by [`Delegate::compile`].
+    fn release(&self, model: ModelHandle) -> Result<(), DelegateError>;
+}
+
+/// Check that `inputs` and `outputs` hold the bytes of the graph's input and
+/// output operands.
+pub fn check_buffers(
+    graph: &Graph,
+    inputs: &[&[u8]],
+    outputs: &[&mut [u8]],
+) -> Result<(), DelegateError> {
+    let lengths = |operands: &[usize]| -> Vec<usize> {
+        operands
+            .iter()
+            .map(|&index| graph.operands[index].byte_len())
+            .collect()
+    };
+    let given_inputs: Vec<usize> = inputs.iter().map(|b| b.len()).collect();
+    let given_outputs: Vec<usize> = outputs.iter().map(|b| b.len()).collect();
+    let (inputs, outputs) = (lengths(&graph.inputs), lengths(&graph.outputs));
+    if given_inputs != inputs || given_outputs != outputs {
+        return Err(DelegateError::InvalidBuffers(format!(
+            "got {given_inputs:?} input and {given_outputs:?} output bytes, \
+             the graph takes {inputs:?} and {outputs:?}"
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+//! Quantized operation graphs handed to a [`Delegate`].
+//!
+//! The graph model follows Android NNAPI: operands are typed tensors,
+//! optionally with a constant value, operations connect operands by index and
+//! the graph lists which operands the caller provides and reads back.
+//! Quantized tensors hold `u8` values `q` standing for `(q - zero_point) *
+//! scale`; 32-bit biases have a zero point of 0.  Tensors are little endian
+//! and row-major, images are NHWC.
+//!
+//! [`Delegate`]: crate::delegate::Delegate
+
+use crate::delegate::DelegateError;
+
+/// Zero point of the symmetric quantization used by the plugin.
+pub const ZERO_POINT: i32 = 128;
+
+/// Element type of an operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandType {
+    Float32,
+    /// `i32` values, e.g. the bias of a quantized operation.
+    Int32 {
+        scale: f32,
+    },
+    /// Asymmetric `u8` quantization.
+    Quant8 {
+        scale: f32,
+        zero_point: i32,
+    },
+}
+
+impl OperandType {
+    /// Bytes of one element.
+    pub fn size(&self) -> usize {
+        match self {
+            OperandType::Float32 | OperandType::Int32 { .. } => 4,
+            OperandType::Quant8 { .. } => 1,
+        }
+    }
+}
+
+/// Tensor of a graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operand {
+    pub ty: OperandType,
+    pub dims: Vec<usize>,
+    /// Value of a constant operand.
+    pub value: Option<Vec<u8>>,
+}
+
+impl Operand {
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.dims.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of the tensor in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.len() * self.ty.size()
+    }
+}
+
+/// Operation of a graph; fields are operand indices.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// `output = input x weights^T + bias` for a `batch x k` input and
+    /// `units x k` weights, as NNAPI `FULLY_CONNECTED`.
+    FullyConnected {
+        input: usize,
+        weights: usize,
+        bias: usize,
+        output: usize,
+    },
+    /// Unpadded convolution with stride 1 of a `1 x h x w x c` input with
+    /// `out_c x kh x kw x c` filters, as NNAPI `CONV_2D`.
+    Conv2d {
+        input: usize,
+        filter: usize,
+        bias: usize,
+        output: usize,
+    },
+    /// Convert quantized values to `f32`, as NNAPI `DEQUANTIZE`.
+    Dequantize { input: usize, output: usize },
+}
+
+/// Operands and operations of a model.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Graph {
+    pub operands: Vec<Operand>,
+    pub operations: Vec<Operation>,
+    /// Operands the caller writes, in execution input order.
+    pub inputs: Vec<usize>,
+    /// Operands the caller reads, in execution output order.
+    pub outputs: Vec<usize>,
+}
+
+impl Graph {
+    /// Add an operand without a value and return its index.
+    pub fn add_operand(&mut self, ty: OperandType, dims: &[usize]) -> usize {
+        self.operands.push(Operand {
+            ty,
+            dims: dims.to_vec(),
+            value: None,
+        });
+        self.operands.len() - 1
+    }
+
+    /// Add a constant operand holding `value`.
+    pub fn add_constant(&mut self, ty: OperandType, dims: &[usize], value: Vec<u8>) -> usize {
+        let index = self.add_operand(ty, dims);
+        self.operands[index].value = Some(value);
+        index
+    }
+
+    /// Quantized matmul of an `m x k` input with `n x k` weights, both
+    /// inputs of the graph, producing `m x n` `f32` values.  The
+    /// accumulators are requantized with `out_scale` before they are
+    /// converted, as NNAPI has no `i32` outputs.
+    pub fn quantized_matmul(
+        (m, n, k): (usize, usize, usize),
+        a_scale: f32,
+        b_scale: f32,
+        out_scale: f32,
+    ) -> Self {
+        let mut graph = Self::default();
+        let input = graph.add_operand(quant8(a_scale), &[m, k]);
+        let weights = graph.add_operand(quant8(b_scale), &[n, k]);
+        let bias = graph.add_constant(
+            OperandType::Int32 {
+                scale: a_scale * b_scale,
+            },
+            &[n],
+            vec![0; n * 4],
+        );
+        let product = graph.add_operand(quant8(out_scale), &[m, n]);
+        let output = graph.add_operand(OperandType::Float32, &[m, n]);
+        graph.operations.push(Operation::FullyConnected {
+            input,
+            weights,
+            bias,
+            output: product,
+        });
+        graph.operations.push(Operation::Dequantize {
+            input: product,
+            output,
+        });
+        graph.inputs = vec![input, weights];
+        graph.outputs = vec![output];
+        graph
+    }
+
+    /// Quantized single channel convolution of an `ih x iw` input with a
+    /// `kh x kw` kernel, both inputs of the graph, producing the
+    /// `(ih - kh + 1) x (iw - kw + 1)` `f32` output.
+    pub fn quantized_conv2d(
+        (ih, iw): (usize, usize),
+        (kh, kw): (usize, usize),
+        input_scale: f32,
+        kernel_scale: f32,
+        out_scale: f32,
+    ) -> Self {
+        let (oh, ow) = (ih + 1 - kh, iw + 1 - kw);
+        let mut graph = Self::default();
+        let input = graph.add_operand(quant8(input_scale), &[1, ih, iw, 1]);
+        let filter = graph.add_operand(quant8(kernel_scale), &[1, kh, kw, 1]);
+        let bias = graph.add_constant(
+            OperandType::Int32 {
+                scale: input_scale * kernel_scale,
+            },
+            &[1],
+            vec![0; 4],
+        );
+        let product = graph.add_operand(quant8(out_scale), &[1, oh, ow, 1]);
+        let output = graph.add_operand(OperandType::Float32, &[1, oh, ow, 1]);
+        graph.operations.push(Operation::Conv2d {
+            input,
+            filter,
+            bias,
+            output: product,
+        });
+        graph.operations.push(Operation::Dequantize {
+            input: product,
+            output,
+        });
+        graph.inputs = vec![input, filter];
+        graph.outputs = vec![output];
+        graph
+    }
+
+    /// Check that operations reference existing operands of matching types
+    /// and shapes, and that inputs and outputs are not constants.
+    pub fn validate(&self) -> Result<(), DelegateError> {
+        let invalid = |reason: String| Err(DelegateError::InvalidGraph(reason));
+        let operand = |index: usize| {
+            self.operands
+                .get(index)
+                .ok_or_else(|| DelegateError::InvalidGraph(format!("no operand {index}")))
+        };
+        for &index in self.inputs.iter().chain(&self.outputs) {
+            if operand(index)?.value.is_some() {
+                return invalid(format!("operand {index} is constant"));
+            }
+        }
+        for operation in &self.operations {
+            match *operation {
+                Operation::FullyConnected {
+                    input,
+                    weights,
+                    bias,
+                    output,
+                } => {
+                    let (input, weights) = (operand(input)?, operand(weights)?);
+                    let (bias, output) = (operand(bias)?, operand(output)?);
+                    let [batch, k] = input.dims[..] else {
+                        return invalid(format!("fully connected input of shape {:?}", input.dims));
+                    };
+                    if weights.dims.len() != 2 || weights.dims[1] != k {
+                        return invalid(format!(
+                            "fully connected weights of shape {:?} for {k} inputs",
+                            weights.dims
+                        ));
+                    }
+                    let units = weights.dims[0];
+                    if bias.dims != [units] || output.dims != [batch, units] {
+                        return invalid(format!(
+                            "fully connected of {batch} x {units} with bias {:?} and output {:?}",
+                            bias.dims, output.dims
+                        ));
+                    }
+                    check_quantized(&[input, weights, output], bias)?;
+                }
+                Operation::Conv2d {
+                    input,
+                    filter,
+                    bias,
+                    output,
+                } => {
+                    let (input, filter) = (operand(input)?, operand(filter)?);
+                    let (bias, output) = (operand(bias)?, operand(output)?);
+                    let ([1, ih, iw, c], [out_c, kh, kw, fc]) = (&input.dims[..], &filter.dims[..])
+                    else {
+                        return invalid(format!(
+                            "convolution of {:?} with filter {:?}",
+                            input.dims, filter.dims
+                        ));
+                    };
+                    if fc != c || kh > ih || kw > iw {
+                        return invalid(format!(
+                            "convolution of {:?} with filter {:?}",
+                            input.dims, filter.dims
+                        ));
+                    }
+                    let expected = [1, ih - kh + 1, iw - kw + 1, *out_c];
+                    if bias.dims != [*out_c] || output.dims != expected {
+                        return invalid(format!(
+                            "convolution output {:?} and bias {:?}, expected {expected:?}",
+                            output.dims, bias.dims
+                        ));
+                    }
+                    check_quantized(&[input, filter, output], bias)?;
+                }
+                Operation::Dequantize { input, output } => {
+                    let (input, output) = (operand(input)?, operand(output)?);
+                    if !matches!(input.ty, OperandType::Quant8 { .. })
+                        || output.ty != OperandType::Float32
+                        || input.dims != output.dims
+                    {
+                        return invalid(format!(
+                            "dequantize of {:?} {:?} into {:?} {:?}",
+                            input.ty, input.dims, output.ty, output.dims
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn quant8(scale: f32) -> OperandType {
+    OperandType::Quant8 {
+        scale,
+        zero_point: ZERO_POINT,
+    }
+}
+
+/// Check the operand types of a quantized operation: `u8` tensors and an
+/// `i32` bias scaled by the product of the input scales.
+fn check_quantized(tensors: &[&Operand], bias: &Operand) -> Result<(), DelegateError> {
+    let scales: Vec<f32> = tensors
+        .iter()
+        .filter_map(|operand| match operand.ty {
+            OperandType::Quant8 { scale, .. } => Some(scale),
+            _ => None,
+        })
+        .collect();
+    let [input, weights, _] = scales[..] else {
+        return Err(DelegateError::InvalidGraph(
+            "quantized operation on unquantized tensors".to_string(),
+        ));
+    };
+    match bias.ty {
+        OperandType::Int32 { scale } if (scale - input * weights).abs() <= 1e-6 * scale.abs() => {
+            Ok(())
+        }
+        ty => Err(DelegateError::InvalidGraph(format!(
+            "bias of type {ty:?} for input scales {input} and {weights}"
+        ))),
+    }
+}
+
+/// Symmetric quantization of `values` around [`ZERO_POINT`] and its scale.
+pub fn quantize(values: &[f32]) -> (Vec<u8>, f32) {
+    let max = values.iter().fold(0.0_f32, |m, v| m.max(v.abs()));
+    let scale = if max == 0.0 { 1.0 } else { max / 127.0 };
+    let quantized = values
+        .iter()
+        .map(|v| ((v / scale).round().clamp(-127.0, 127.0) as i32 + ZERO_POINT) as u8)
+        .collect();
+    (quantized, scale)
+}
@@ -0,0 +1,232 @@
+#![allow(improper_ctypes_definitions)]
+
+use std::sync::Mutex;
+
+use aurex_runtime::config::PluginConfig;
+use aurex_runtime::{BackendPlugin, PluginError, TensorOpRequest, TensorOpResponse};
+
+use delegate::{Delegate, DelegateError, DeviceInfo, DeviceKind};
+use graph::Graph;
+use nnapi::NnapiDelegate;
+use reference::ReferenceDelegate;
+
+pub mod delegate;
+pub mod graph;
+pub mod nnapi;
+pub mod reference;
+
+/// Ops the plugin runs as quantized graphs.
+pub const SUPPORTED_OPS: [&str; 2] = ["matmul", "conv2d"];
+
+/// Android NNAPI / vendor NPU backend plugin.  Every op is quantized to
+/// `u8`, compiled into a [`Graph`] for the selected device of the delegate,
+/// executed once and released, since the quantization scales are part of the
+/// graph.  The default delegate is NNAPI where `libneuralnetworks.so` loads
+/// and the software [`ReferenceDelegate`] elsewhere.
+///
+/// Quantized outputs need a scale before the result is known, so it is
+/// derived from a bound on the accumulators: results lose precision the
+/// further they stay below that bound.
+pub struct NnapiPlugin {
+    delegate: Box<dyn Delegate>,
+    device: Mutex<Option<DeviceInfo>>,
+    /// Ops enabled by the `ops` option, all of [`SUPPORTED_OPS`] by default.
+    enabled: Mutex<Vec<String>>,
+    /// Device name set by the `device` option.
+    preferred: Mutex<Option<String>>,
+}
+
+impl NnapiPlugin {
+    pub fn with_delegate(delegate: Box<dyn Delegate>) -> Self {
+        Self {
+            delegate,
+            device: Mutex::new(None),
+            enabled: Mutex::new(SUPPORTED_OPS.iter().map(|op| op.to_string()).collect()),
+            preferred: Mutex::new(None),
+        }
+    }
+
+    /// Device ops are compiled for, selected by [`BackendPlugin::initialize`]
+    /// and the `device` option.
+    pub fn device(&self) -> Option<DeviceInfo> {
+        self.device.lock().unwrap().clone()
+    }
+
+    /// Select the device named by the `device` option, or else the first
+    /// accelerator, or else the first device.
+    fn select_device(&self) {
+        let devices = self.delegate.devices();
+        let preferred = self.preferred.lock().unwrap().clone();
+        let device = match &preferred {
+            Some(name) => devices.iter().find(|d| d.name == *name),
+            None => devices
+                .iter()
+                .find(|d| d.kind == DeviceKind::Accelerator)
+                .or(devices.first()),
+        };
+        match device {
+            Some(info) => {
+                tracing::debug!(device = %info.name, kind = ?info.kind, "nnapi_npu device selected")
+            }
+            None => tracing::warn!(?preferred, "no nnapi_npu device found"),
+        }
+        *self.device.lock().unwrap() = device.cloned();
+    }
+
+    /// Compile `graph`, run it on `inputs` and return its `outputs` `f32`
+    /// values.  The model is released whether or not execution succeeds.
+    fn run(
+        &self,
+        graph: &Graph,
+        inputs: &[&[u8]],
+        outputs: usize,
+    ) -> Result<Vec<f32>, PluginError> {
+        let device = self
+            .device()
+            .ok_or_else(|| PluginError::Device("no device selected".to_string()))?
+            .index;
+        let device_error = |err: DelegateError| PluginError::Device(err.to_string());
+        let model = self.delegate.compile(device, graph).map_err(device_error)?;
+        let mut bytes = vec![0; outputs * 4];
+        let result = self.delegate.execute(model, inputs, &mut [&mut bytes]);
+        if let Err(err) = self.delegate.release(model) {
+            tracing::warn!(%err, "failed to release nnapi_npu model");
+        }
+        result.map_err(device_error)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect())
+    }
+}
+
+impl Default for NnapiPlugin {
+    fn default() -> Self {
+        match NnapiDelegate::load() {
+            Ok(nnapi) => Self::with_delegate(Box::new(nnapi)),
+            Err(err) => {
+                tracing::debug!(%err, "NNAPI unavailable, using the reference delegate");
+                Self::with_delegate(Box::new(ReferenceDelegate::default()))
+            }
+        }
+    }
+}
+
+/// Scale of `u8` outputs holding accumulators of magnitude up to `bound`,
+/// in units of the product of the input scales.
+fn output_scale(bound: f32, a_scale: f32, b_scale: f32) -> f32 {
+    if bound == 0.0 {
+        1.0
+    } else {
+        a_scale * b_scale * bound / 127.0
+    }
+}
+
+/// L2 norms of the rows of `len` quantized values in `quantized`.
+fn norms(quantized: &[u8], len: usize) -> impl Iterator<Item = f32> + '_ {
+    quantized.chunks_exact(len.max(1)).map(|row| {
+        row.iter()
+            .map(|&q| (q as f32 - graph::ZERO_POINT as f32).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    })
+}
+
+impl BackendPlugin for NnapiPlugin {
+    fn name(&self) -> &'static str {
+        "nnapi_npu"
+    }
+
+    fn initialize(&self) {
+        self.select_device();
+    }
+
+    /// Reads the `ops` option, the subset of [`SUPPORTED_OPS`] to accept,
+    /// and the `device` option, the name of the device to compile for.
+    fn configure(&self, config: &PluginConfig) {
+        let Some(options) = config.options(self.name()) else {
+            return;
+        };
+        if let Some(ops) = options.get("ops").and_then(|ops| ops.as_array()) {
+            *self.enabled.lock().unwrap() = ops
+                .iter()
+                .filter_map(|op| op.as_str())
+                .filter(|op| SUPPORTED_OPS.contains(op))
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(device) = options.get("device").and_then(|d| d.as_str()) {
+            *self.preferred.lock().unwrap() = Some(device.to_string());
+            self.select_device();
+        }
+    }
+
+    fn execute_op(&self, op: TensorOpRequest) -> Result<TensorOpResponse, PluginError> {
+        if !self.enabled.lock().unwrap().iter().any(|e| e == op.name()) {
+            return Err(PluginError::Unsupported(op.name()));
+        }
+        let output = match op {
+            TensorOpRequest::MatMul { a, b, m, n, k } => {
+                if a.len() != m * k || b.len() != k * n {
+                    return Err(PluginError::InvalidInput(format!(
+                        "matmul of {m}x{k} and {k}x{n} with {} and {} values",
+                        a.len(),
+                        b.len()
+                    )));
+                }
+                // Fully connected weights are `n x k`.
+                let bt: Vec<f32> = (0..n * k).map(|i| b[i % k * n + i / k]).collect();
+                let (qa, a_scale) = graph::quantize(a);
+                let (qb, b_scale) = graph::quantize(&bt);
+                // Cauchy-Schwarz bound of every row-column dot product.
+                let bound = norms(&qa, k).fold(0.0, f32::max) * norms(&qb, k).fold(0.0, f32::max);
+                let graph = Graph::quantized_matmul(
+                    (m, n, k),
+                    a_scale,
+                    b_scale,
+                    output_scale(bound, a_scale, b_scale),
+                );
+                self.run(&graph, &[&qa, &qb], m * n)?
+            }
+            TensorOpRequest::Conv2d {
+                input,
+                kernel,
+                input_shape: (ih, iw),
+                kernel_shape: (kh, kw),
+            } => {
+                if input.len() != ih * iw
+                    || kernel.len() != kh * kw
+                    || !(1..=ih).contains(&kh)
+                    || !(1..=iw).contains(&kw)
+                {
+                    return Err(PluginError::InvalidInput(format!(
+                        "conv2d of {ih}x{iw} with {kh}x{kw} on {} and {} values",
+                        input.len(),
+                        kernel.len()
+                    )));
+                }
+                let (qi, input_scale) = graph::quantize(input);
+                let (qk, kernel_scale) = graph::quantize(kernel);
+                // Every window holds `kh * kw` values of magnitude up to 127.
+                let window = 127.0 * ((kh * kw) as f32).sqrt();
+                let bound = norms(&qk, qk.len()).fold(0.0, f32::max) * window;
+                let graph = Graph::quantized_conv2d(
+                    (ih, iw),
+                    (kh, kw),
+                    input_scale,
+                    kernel_scale,
+                    output_scale(bound, input_scale, kernel_scale),
+                );
+                self.run(&graph, &[&qi, &qk], (ih - kh + 1) * (iw - kw + 1))?
+            }
+            _ => unreachable!("only supported ops are enabled"),
+        };
+        Ok(TensorOpResponse::new(output))
+    }
+}
+
+/// Exported constructor called by the runtime to instantiate the plugin.
+#[no_mangle]
+pub extern "C" fn create_plugin() -> *mut dyn BackendPlugin {
+    Box::into_raw(Box::new(NnapiPlugin::default()))
+}
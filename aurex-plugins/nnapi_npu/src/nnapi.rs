@@ -0,0 +1,498 @@
+//! [`Delegate`] running graphs through Android NNAPI.
+//!
+//! `libneuralnetworks.so` is loaded at runtime, so the plugin builds for any
+//! target and falls back to the [`ReferenceDelegate`] where the library is
+//! missing.  The device API of NNAPI feature level 3 (Android 10) is
+//! required: every device NNAPI reports, typically the CPU, GPU and the
+//! vendor's NPU or DSP drivers, is listed and graphs are compiled for one
+//! device explicitly instead of letting NNAPI partition them.
+//!
+//! [`ReferenceDelegate`]: crate::reference::ReferenceDelegate
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::ptr;
+use std::sync::Mutex;
+
+use libloading::Library;
+
+use crate::delegate::{
+    check_buffers, Delegate, DelegateError, DeviceInfo, DeviceKind, ModelHandle,
+};
+use crate::graph::{Graph, OperandType, Operation};
+
+/// Library providing the NNAPI runtime.
+pub const LIBRARY: &str = "libneuralnetworks.so";
+
+// Operand types.
+const INT32: i32 = 1;
+const TENSOR_FLOAT32: i32 = 3;
+const TENSOR_INT32: i32 = 4;
+const TENSOR_QUANT8_ASYMM: i32 = 5;
+// Operation types.
+const CONV_2D: i32 = 3;
+const DEQUANTIZE: i32 = 6;
+const FULLY_CONNECTED: i32 = 9;
+// Scalar arguments.
+const FUSED_NONE: i32 = 0;
+const PREFER_SUSTAINED_SPEED: i32 = 2;
+// Device types.
+const DEVICE_CPU: i32 = 2;
+const DEVICE_GPU: i32 = 3;
+const DEVICE_ACCELERATOR: i32 = 4;
+
+const NO_ERROR: c_int = 0;
+
+/// `ANeuralNetworksOperandType`.
+#[repr(C)]
+struct OperandTypeC {
+    ty: i32,
+    dimension_count: u32,
+    dimensions: *const u32,
+    scale: f32,
+    zero_point: i32,
+}
+
+/// Entry points of the NNAPI runtime.
+struct Api {
+    model_create: unsafe extern "C" fn(*mut *mut c_void) -> c_int,
+    model_free: unsafe extern "C" fn(*mut c_void),
+    model_add_operand: unsafe extern "C" fn(*mut c_void, *const OperandTypeC) -> c_int,
+    model_set_operand_value: unsafe extern "C" fn(*mut c_void, i32, *const c_void, usize) -> c_int,
+    model_add_operation:
+        unsafe extern "C" fn(*mut c_void, i32, u32, *const u32, u32, *const u32) -> c_int,
+    model_identify_inputs_and_outputs:
+        unsafe extern "C" fn(*mut c_void, u32, *const u32, u32, *const u32) -> c_int,
+    model_finish: unsafe extern "C" fn(*mut c_void) -> c_int,
+    get_device_count: unsafe extern "C" fn(*mut u32) -> c_int,
+    get_device: unsafe extern "C" fn(u32, *mut *mut c_void) -> c_int,
+    device_get_name: unsafe extern "C" fn(*const c_void, *mut *const c_char) -> c_int,
+    device_get_type: unsafe extern "C" fn(*const c_void, *mut i32) -> c_int,
+    compilation_create_for_devices:
+        unsafe extern "C" fn(*mut c_void, *const *const c_void, u32, *mut *mut c_void) -> c_int,
+    compilation_set_preference: unsafe extern "C" fn(*mut c_void, i32) -> c_int,
+    compilation_finish: unsafe extern "C" fn(*mut c_void) -> c_int,
+    compilation_free: unsafe extern "C" fn(*mut c_void),
+    execution_create: unsafe extern "C" fn(*mut c_void, *mut *mut c_void) -> c_int,
+    execution_set_input:
+        unsafe extern "C" fn(*mut c_void, i32, *const OperandTypeC, *const c_void, usize) -> c_int,
+    execution_set_output:
+        unsafe extern "C" fn(*mut c_void, i32, *const OperandTypeC, *mut c_void, usize) -> c_int,
+    execution_compute: unsafe extern "C" fn(*mut c_void) -> c_int,
+    execution_free: unsafe extern "C" fn(*mut c_void),
+    // Keeps the entry points above valid.
+    _library: Library,
+}
+
+impl Api {
+    /// Load [`LIBRARY`] and resolve the entry points.
+    ///
+    /// # Safety
+    ///
+    /// The library must be the NNAPI runtime, whose symbols have the
+    /// declared signatures.
+    unsafe fn load() -> Result<Self, libloading::Error> {
+        let library = Library::new(LIBRARY)?;
+        macro_rules! symbol {
+            ($name:literal) => {
+                *library.get(concat!($name, "\0").as_bytes())?
+            };
+        }
+        Ok(Self {
+            model_create: symbol!("ANeuralNetworksModel_create"),
+            model_free: symbol!("ANeuralNetworksModel_free"),
+            model_add_operand: symbol!("ANeuralNetworksModel_addOperand"),
+            model_set_operand_value: symbol!("ANeuralNetworksModel_setOperandValue"),
+            model_add_operation: symbol!("ANeuralNetworksModel_addOperation"),
+            model_identify_inputs_and_outputs: symbol!(
+                "ANeuralNetworksModel_identifyInputsAndOutputs"
+            ),
+            model_finish: symbol!("ANeuralNetworksModel_finish"),
+            get_device_count: symbol!("ANeuralNetworks_getDeviceCount"),
+            get_device: symbol!("ANeuralNetworks_getDevice"),
+            device_get_name: symbol!("ANeuralNetworksDevice_getName"),
+            device_get_type: symbol!("ANeuralNetworksDevice_getType"),
+            compilation_create_for_devices: symbol!("ANeuralNetworksCompilation_createForDevices"),
+            compilation_set_preference: symbol!("ANeuralNetworksCompilation_setPreference"),
+            compilation_finish: symbol!("ANeuralNetworksCompilation_finish"),
+            compilation_free: symbol!("ANeuralNetworksCompilation_free"),
+            execution_create: symbol!("ANeuralNetworksExecution_create"),
+            execution_set_input: symbol!("ANeuralNetworksExecution_setInput"),
+            execution_set_output: symbol!("ANeuralNetworksExecution_setOutput"),
+            execution_compute: symbol!("ANeuralNetworksExecution_compute"),
+            execution_free: symbol!("ANeuralNetworksExecution_free"),
+            _library: library,
+        })
+    }
+}
+
+/// Turn an NNAPI result code into an error naming the failed `call`.
+fn check(code: c_int, call: &str) -> Result<(), DelegateError> {
+    if code == NO_ERROR {
+        Ok(())
+    } else {
+        Err(DelegateError::Runtime(format!(
+            "{call} failed with code {code}"
+        )))
+    }
+}
+
+/// `ANeuralNetworksDevice` pointer, valid for the life of the process.
+struct Device(*const c_void);
+
+/// Model and compilation of a compiled graph.  The graph owns the values of
+/// its constants, which NNAPI references instead of copying when they are
+/// larger than 128 bytes.
+struct Compiled {
+    model: *mut c_void,
+    compilation: *mut c_void,
+    graph: Graph,
+}
+
+// NNAPI objects may be used from any thread; the delegate serializes access
+// to compiled models through its mutex.
+unsafe impl Send for Device {}
+unsafe impl Sync for Device {}
+unsafe impl Send for Compiled {}
+
+/// Delegate compiling graphs for the devices NNAPI reports.
+pub struct NnapiDelegate {
+    api: Api,
+    devices: Vec<(DeviceInfo, Device)>,
+    models: Mutex<(u64, HashMap<ModelHandle, Compiled>)>,
+}
+
+impl NnapiDelegate {
+    /// Load the NNAPI runtime and list its devices.  Fails where
+    /// [`LIBRARY`] is missing, i.e. off Android, or predates the device API.
+    pub fn load() -> Result<Self, DelegateError> {
+        // SAFETY: `LIBRARY` is the system's NNAPI runtime.
+        let api = unsafe { Api::load() }
+            .map_err(|err| DelegateError::Runtime(format!("cannot load {LIBRARY}: {err}")))?;
+        let mut count = 0;
+        check(
+            unsafe { (api.get_device_count)(&mut count) },
+            "ANeuralNetworks_getDeviceCount",
+        )?;
+        let mut devices = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let mut device = ptr::null_mut();
+            let mut name = ptr::null();
+            let mut ty = 0;
+            unsafe {
+                check(
+                    (api.get_device)(index, &mut device),
+                    "ANeuralNetworks_getDevice",
+                )?;
+                check(
+                    (api.device_get_name)(device, &mut name),
+                    "ANeuralNetworksDevice_getName",
+                )?;
+                check(
+                    (api.device_get_type)(device, &mut ty),
+                    "ANeuralNetworksDevice_getType",
+                )?;
+            }
+            let kind = match ty {
+                DEVICE_CPU => DeviceKind::Cpu,
+                DEVICE_GPU => DeviceKind::Gpu,
+                DEVICE_ACCELERATOR => DeviceKind::Accelerator,
+                _ => DeviceKind::Other,
+            };
+            let info = DeviceInfo {
+                index: index as usize,
+                // SAFETY: NNAPI returns a NUL-terminated name owned by the
+                // device.
+                name: unsafe { CStr::from_ptr(name) }
+                    .to_string_lossy()
+                    .into_owned(),
+                kind,
+            };
+            devices.push((info, Device(device)));
+        }
+        Ok(Self {
+            api,
+            devices,
+            models: Mutex::new((0, HashMap::new())),
+        })
+    }
+
+    /// Build and finish the NNAPI model of `graph`.
+    ///
+    /// # Safety
+    ///
+    /// The constants of `graph` must outlive the returned model.
+    unsafe fn build(&self, graph: &Graph) -> Result<*mut c_void, DelegateError> {
+        let api = &self.api;
+        let mut model = ptr::null_mut();
+        check(
+            (api.model_create)(&mut model),
+            "ANeuralNetworksModel_create",
+        )?;
+        let result = (|| {
+            for (index, operand) in graph.operands.iter().enumerate() {
+                let dims: Vec<u32> = operand.dims.iter().map(|&d| d as u32).collect();
+                let (ty, scale, zero_point) = match operand.ty {
+                    OperandType::Float32 => (TENSOR_FLOAT32, 0.0, 0),
+                    OperandType::Int32 { scale } => (TENSOR_INT32, scale, 0),
+                    OperandType::Quant8 { scale, zero_point } => {
+                        (TENSOR_QUANT8_ASYMM, scale, zero_point)
+                    }
+                };
+                let ty = OperandTypeC {
+                    ty,
+                    dimension_count: dims.len() as u32,
+                    dimensions: dims.as_ptr(),
+                    scale,
+                    zero_point,
+                };
+                check(
+                    (api.model_add_operand)(model, &ty),
+                    "ANeuralNetworksModel_addOperand",
+                )?;
+                if let Some(value) = &operand.value {
+                    check(
+                        (api.model_set_operand_value)(
+                            model,
+                            index as i32,
+                            value.as_ptr().cast(),
+                            value.len(),
+                        ),
+                        "ANeuralNetworksModel_setOperandValue",
+                    )?;
+                }
+            }
+
+            // Scalar arguments are appended after the graph's operands.
+            let mut next = graph.operands.len() as u32;
+            let mut scalar = |value: i32| -> Result<u32, DelegateError> {
+                let ty = OperandTypeC {
+                    ty: INT32,
+                    dimension_count: 0,
+                    dimensions: ptr::null(),
+                    scale: 0.0,
+                    zero_point: 0,
+                };
+                check(
+                    (api.model_add_operand)(model, &ty),
+                    "ANeuralNetworksModel_addOperand",
+                )?;
+                // Values of up to 128 bytes are copied.
+                check(
+                    (api.model_set_operand_value)(
+                        model,
+                        next as i32,
+                        (&value as *const i32).cast(),
+                        4,
+                    ),
+                    "ANeuralNetworksModel_setOperandValue",
+                )?;
+                next += 1;
+                Ok(next - 1)
+            };
+            for operation in &graph.operations {
+                let (ty, inputs, output) = match *operation {
+                    Operation::FullyConnected {
+                        input,
+                        weights,
+                        bias,
+                        output,
+                    } => {
+                        let operands = [input, weights, bias].map(|i| i as u32);
+                        let mut inputs = operands.to_vec();
+                        inputs.push(scalar(FUSED_NONE)?);
+                        (FULLY_CONNECTED, inputs, output)
+                    }
+                    Operation::Conv2d {
+                        input,
+                        filter,
+                        bias,
+                        output,
+                    } => {
+                        let operands = [input, filter, bias].map(|i| i as u32);
+                        let mut inputs = operands.to_vec();
+                        // Explicit padding of 0 on every side, strides of 1.
+                        for value in [0, 0, 0, 0, 1, 1, FUSED_NONE] {
+                            inputs.push(scalar(value)?);
+                        }
+                        (CONV_2D, inputs, output)
+                    }
+                    Operation::Dequantize { input, output } => {
+                        (DEQUANTIZE, vec![input as u32], output)
+                    }
+                };
+                let output = output as u32;
+                check(
+                    (api.model_add_operation)(
+                        model,
+                        ty,
+                        inputs.len() as u32,
+                        inputs.as_ptr(),
+                        1,
+                        &output,
+                    ),
+                    "ANeuralNetworksModel_addOperation",
+                )?;
+            }
+
+            let inputs: Vec<u32> = graph.inputs.iter().map(|&i| i as u32).collect();
+            let outputs: Vec<u32> = graph.outputs.iter().map(|&i| i as u32).collect();
+            check(
+                (api.model_identify_inputs_and_outputs)(
+                    model,
+                    inputs.len() as u32,
+                    inputs.as_ptr(),
+                    outputs.len() as u32,
+                    outputs.as_ptr(),
+                ),
+                "ANeuralNetworksModel_identifyInputsAndOutputs",
+            )?;
+            check((api.model_finish)(model), "ANeuralNetworksModel_finish")
+        })();
+        match result {
+            Ok(()) => Ok(model),
+            Err(err) => {
+                (api.model_free)(model);
+                Err(err)
+            }
+        }
+    }
+
+    /// Free the NNAPI objects of `compiled`.
+    fn free(&self, compiled: Compiled) {
+        unsafe {
+            (self.api.compilation_free)(compiled.compilation);
+            (self.api.model_free)(compiled.model);
+        }
+    }
+}
+
+impl Delegate for NnapiDelegate {
+    fn devices(&self) -> Vec<DeviceInfo> {
+        self.devices.iter().map(|(info, _)| info.clone()).collect()
+    }
+
+    fn compile(&self, device: usize, graph: &Graph) -> Result<ModelHandle, DelegateError> {
+        let (_, handle) = self
+            .devices
+            .get(device)
+            .ok_or(DelegateError::NoDevice(device))?;
+        graph.validate()?;
+        let graph = graph.clone();
+        let api = &self.api;
+        let compiled = unsafe {
+            // SAFETY: `graph` moves into the `Compiled` entry with the model,
+            // and moving it does not move the constants' heap buffers.
+            let model = self.build(&graph)?;
+            let mut compilation = ptr::null_mut();
+            let result = check(
+                (api.compilation_create_for_devices)(model, &handle.0, 1, &mut compilation),
+                "ANeuralNetworksCompilation_createForDevices",
+            )
+            .and_then(|()| {
+                check(
+                    (api.compilation_set_preference)(compilation, PREFER_SUSTAINED_SPEED),
+                    "ANeuralNetworksCompilation_setPreference",
+                )
+            })
+            .and_then(|()| {
+                check(
+                    (api.compilation_finish)(compilation),
+                    "ANeuralNetworksCompilation_finish",
+                )
+            });
+            if let Err(err) = result {
+                if !compilation.is_null() {
+                    (api.compilation_free)(compilation);
+                }
+                (api.model_free)(model);
+                return Err(err);
+            }
+            Compiled {
+                model,
+                compilation,
+                graph,
+            }
+        };
+        let mut models = self.models.lock().unwrap();
+        let model = ModelHandle(models.0);
+        models.0 += 1;
+        models.1.insert(model, compiled);
+        Ok(model)
+    }
+
+    fn execute(
+        &self,
+        model: ModelHandle,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<(), DelegateError> {
+        let models = self.models.lock().unwrap();
+        let compiled = models
+            .1
+            .get(&model)
+            .ok_or(DelegateError::UnknownModel(model))?;
+        check_buffers(&compiled.graph, inputs, outputs)?;
+        let api = &self.api;
+        unsafe {
+            let mut execution = ptr::null_mut();
+            check(
+                (api.execution_create)(compiled.compilation, &mut execution),
+                "ANeuralNetworksExecution_create",
+            )?;
+            let result = (|| {
+                for (index, input) in inputs.iter().enumerate() {
+                    check(
+                        (api.execution_set_input)(
+                            execution,
+                            index as i32,
+                            ptr::null(),
+                            input.as_ptr().cast(),
+                            input.len(),
+                        ),
+                        "ANeuralNetworksExecution_setInput",
+                    )?;
+                }
+                for (index, output) in outputs.iter_mut().enumerate() {
+                    check(
+                        (api.execution_set_output)(
+                            execution,
+                            index as i32,
+                            ptr::null(),
+                            output.as_mut_ptr().cast(),
+                            output.len(),
+                        ),
+                        "ANeuralNetworksExecution_setOutput",
+                    )?;
+                }
+                check(
+                    (api.execution_compute)(execution),
+                    "ANeuralNetworksExecution_compute",
+                )
+            })();
+            (api.execution_free)(execution);
+            result
+        }
+    }
+
+    fn release(&self, model: ModelHandle) -> Result<(), DelegateError> {
+        let compiled = self
+            .models
+            .lock()
+            .unwrap()
+            .1
+            .remove(&model)
+            .ok_or(DelegateError::UnknownModel(model))?;
+        self.free(compiled);
+        Ok(())
+    }
+}
+
+impl Drop for NnapiDelegate {
+    fn drop(&mut self) {
+        let models = std::mem::take(&mut self.models.get_mut().unwrap().1);
+        for (_, compiled) in models {
+            self.free(compiled);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Software implementation of the [`Delegate`] interface.
+//!
+//! Operations run on the CPU with the integer arithmetic NNAPI prescribes:
+//! quantized products are accumulated in `i32` together with the bias and
+//! requantized to the output's scale and zero point, saturating to `u8`.
+//! Results therefore match what an NPU computes, rounding included.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::delegate::{
+    check_buffers, Delegate, DelegateError, DeviceInfo, DeviceKind, ModelHandle,
+};
+use crate::graph::{Graph, Operand, OperandType, Operation};
+
+/// Reference delegate with a single CPU device.
+#[derive(Default)]
+pub struct ReferenceDelegate {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_model: u64,
+    models: HashMap<ModelHandle, Graph>,
+}
+
+impl ReferenceDelegate {
+    /// Models compiled and not yet released.
+    pub fn models(&self) -> usize {
+        self.state.lock().unwrap().models.len()
+    }
+}
+
+impl Delegate for ReferenceDelegate {
+    fn devices(&self) -> Vec<DeviceInfo> {
+        vec![DeviceInfo {
+            index: 0,
+            name: "nnapi-reference".to_string(),
+            kind: DeviceKind::Cpu,
+        }]
+    }
+
+    fn compile(&self, device: usize, graph: &Graph) -> Result<ModelHandle, DelegateError> {
+        if device != 0 {
+            return Err(DelegateError::NoDevice(device));
+        }
+        graph.validate()?;
+        let mut state = self.state.lock().unwrap();
+        let model = ModelHandle(state.next_model);
+        state.next_model += 1;
+        state.models.insert(model, graph.clone());
+        Ok(model)
+    }
+
+    fn execute(
+        &self,
+        model: ModelHandle,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<(), DelegateError> {
+        let graph = self
+            .state
+            .lock()
+            .unwrap()
+            .models
+            .get(&model)
+            .cloned()
+            .ok_or(DelegateError::UnknownModel(model))?;
+        check_buffers(&graph, inputs, outputs)?;
+
+        let mut values: HashMap<usize, Vec<u8>> = graph
+            .operands
+            .iter()
+            .enumerate()
+            .filter_map(|(index, operand)| Some((index, operand.value.clone()?)))
+            .collect();
+        for (&index, input) in graph.inputs.iter().zip(inputs) {
+            values.insert(index, input.to_vec());
+        }
+        for operation in &graph.operations {
+            let (output, bytes) = run(&graph, &values, operation)?;
+            values.insert(output, bytes);
+        }
+        for (&index, output) in graph.outputs.iter().zip(outputs.iter_mut()) {
+            let bytes = values.get(&index).ok_or_else(|| {
+                DelegateError::InvalidGraph(format!("output {index} is never written"))
+            })?;
+            output.copy_from_slice(bytes);
+        }
+        Ok(())
+    }
+
+    fn release(&self, model: ModelHandle) -> Result<(), DelegateError> {
+        self.state
+            .lock()
+            .unwrap()
+            .models
+            .remove(&model)
+            .map(|_| ())
+            .ok_or(DelegateError::UnknownModel(model))
+    }
+}
+
+/// Run `operation` and return its output operand and bytes.
+fn run(
+    graph: &Graph,
+    values: &HashMap<usize, Vec<u8>>,
+    operation: &Operation,
+) -> Result<(usize, Vec<u8>), DelegateError> {
+    let value = |index: usize| {
+        values
+            .get(&index)
+            .map(Vec::as_slice)
+            .ok_or_else(|| DelegateError::InvalidGraph(format!("operand {index} is never written")))
+    };
+    let operand = |index: usize| &graph.operands[index];
+    Ok(match *operation {
+        Operation::FullyConnected {
+            input,
+            weights,
+            bias,
+            output,
+        } => {
+            let [batch, k] = operand(input).dims[..] else {
+                unreachable!("validated graph")
+            };
+            let units = operand(weights).dims[0];
+            let (x, w, b) = (value(input)?, value(weights)?, i32_values(value(bias)?));
+            let (xz, wz) = (zero_point(operand(input)), zero_point(operand(weights)));
+            let mut acc = Vec::with_capacity(batch * units);
+            for row in x.chunks_exact(k.max(1)).take(batch) {
+                for (u, filter) in w.chunks_exact(k.max(1)).take(units).enumerate() {
+                    let dot: i32 = row
+                        .iter()
+                        .zip(filter)
+                        .map(|(&a, &f)| (a as i32 - xz) * (f as i32 - wz))
+                        .sum();
+                    acc.push(b[u] + dot);
+                }
+            }
+            (output, requantize(&acc, graph, input, weights, output))
+        }
+        Operation::Conv2d {
+            input,
+            filter,
+            bias,
+            output,
+        } => {
+            let [_, ih, iw, c] = operand(input).dims[..] else {
+                unreachable!("validated graph")
+            };
+            let [out_c, kh, kw, _] = operand(filter).dims[..] else {
+                unreachable!("validated graph")
+            };
+            let (x, f, b) = (value(input)?, value(filter)?, i32_values(value(bias)?));
+            let (xz, fz) = (zero_point(operand(input)), zero_point(operand(filter)));
+            let (oh, ow) = (ih - kh + 1, iw - kw + 1);
+            let mut acc = Vec::with_capacity(oh * ow * out_c);
+            for y in 0..oh {
+                for x0 in 0..ow {
+                    for o in 0..out_c {
+                        let mut sum = b[o];
+                        for ky in 0..kh {
+                            for kx in 0..kw {
+                                for ch in 0..c {
+                                    let xv = x[((y + ky) * iw + x0 + kx) * c + ch] as i32 - xz;
+                                    let fv = f[((o * kh + ky) * kw + kx) * c + ch] as i32 - fz;
+                                    sum += xv * fv;
+                                }
+                            }
+                        }
+                        acc.push(sum);
+                    }
+                }
+            }
+            (output, requantize(&acc, graph, input, filter, output))
+        }
+        Operation::Dequantize { input, output } => {
+            let OperandType::Quant8 { scale, zero_point } = operand(input).ty else {
+                unreachable!("validated graph")
+            };
+            let bytes = value(input)?
+                .iter()
+                .flat_map(|&q| ((q as i32 - zero_point) as f32 * scale).to_le_bytes())
+                .collect();
+            (output, bytes)
+        }
+    })
+}
+
+fn zero_point(operand: &Operand) -> i32 {
+    match operand.ty {
+        OperandType::Quant8 { zero_point, .. } => zero_point,
+        _ => 0,
+    }
+}
+
+fn scale(operand: &Operand) -> f32 {
+    match operand.ty {
+        OperandType::Quant8 { scale, .. } | OperandType::Int32 { scale } => scale,
+        OperandType::Float32 => 1.0,
+    }
+}
+
+fn i32_values(bytes: &[u8]) -> Vec<i32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Requantize the accumulators of a product of operands `a` and `b` to the
+/// `u8` values of `output`.
+fn requantize(acc: &[i32], graph: &Graph, a: usize, b: usize, output: usize) -> Vec<u8> {
+    let (a, b, output) = (
+        &graph.operands[a],
+        &graph.operands[b],
+        &graph.operands[output],
+    );
+    let multiplier = scale(a) * scale(b) / scale(output);
+    let zero = zero_point(output);
+    acc.iter()
+        .map(|&v| ((v as f32 * multiplier).round() as i32 + zero).clamp(0, 255) as u8)
+        .collect()
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use aurex_backend::TensorOps;
+use aurex_runtime::{AurexConfig, BackendPlugin, PluginError, PluginOps, TensorOpRequest};
+use nnapi_npu::delegate::DeviceKind;
+use nnapi_npu::reference::ReferenceDelegate;
+use nnapi_npu::{create_plugin, NnapiPlugin};
+
+fn reference_plugin() -> NnapiPlugin {
+    let plugin = NnapiPlugin::with_delegate(Box::new(ReferenceDelegate::default()));
+    plugin.initialize();
+    plugin
+}
+
+fn assert_close(got: &[f32], expected: &[f32], tolerance: f32) {
+    assert_eq!(got.len(), expected.len());
+    for (g, e) in got.iter().zip(expected) {
+        assert!((g - e).abs() <= tolerance, "{g} != {e}");
+    }
+}
+
+#[test]
+fn plugin_loads_with_a_device() {
+    let plugin: Box<dyn BackendPlugin> = unsafe { Box::from_raw(create_plugin()) };
+    assert_eq!(plugin.name(), "nnapi_npu");
+    plugin.initialize();
+
+    let plugin = reference_plugin();
+    let device = plugin.device().unwrap();
+    assert_eq!(
+        (device.name.as_str(), device.kind),
+        ("nnapi-reference", DeviceKind::Cpu)
+    );
+
+    let config =
+        AurexConfig::from_toml_str("[plugins]\noptions = { nnapi_npu = { device = \"npu0\" } }")
+            .unwrap();
+    plugin.configure(&config.plugins);
+    assert!(plugin.device().is_none());
+    let one = TensorOpRequest::MatMul {
+        a: &[1.0],
+        b: &[1.0],
+        m: 1,
+        n: 1,
+        k: 1,
+    };
+    assert!(matches!(
+        plugin.execute_op(one),
+        Err(PluginError::Device(_))
+    ));
+}
+
+#[test]
+fn quantized_matmul_matches_f32() {
+    let plugin = reference_plugin();
+    let (m, n, k) = (3, 5, 4);
+    let a: Vec<f32> = (0..m * k).map(|i| i as f32 * 0.3 - 1.5).collect();
+    let b: Vec<f32> = (0..k * n).map(|i| (i % 7) as f32 * 0.2 - 0.4).collect();
+    let output = plugin
+        .execute_op(TensorOpRequest::MatMul {
+            a: &a,
+            b: &b,
+            m,
+            n,
+            k,
+        })
+        .unwrap()
+        .output;
+    let expected: Vec<f32> = (0..m * n)
+        .map(|e| (0..k).map(|p| a[e / n * k + p] * b[p * n + e % n]).sum())
+        .collect();
+    assert_close(&output, &expected, 0.05);
+
+    let bad = TensorOpRequest::MatMul {
+        a: &[1.0],
+        b: &[1.0],
+        m: 2,
+        n: 1,
+        k: 1,
+    };
+    assert!(matches!(
+        plugin.execute_op(bad),
+        Err(PluginError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn quantized_conv2d_matches_f32() {
+    let plugin = reference_plugin();
+    let input: Vec<f32> = (0..20).map(|i| ((i * 7) % 11) as f32 * 0.1 - 0.5).collect();
+    let kernel = [0.5, -1.0, 0.25, 1.0, 0.0, -0.5];
+    let output = plugin
+        .execute_op(TensorOpRequest::Conv2d {
+            input: &input,
+            kernel: &kernel,
+            input_shape: (4, 5),
+            kernel_shape: (2, 3),
+        })
+        .unwrap()
+        .output;
+    let mut expected = Vec::new();
+    for i in 0..3 {
+        for j in 0..3 {
+            let mut sum = 0.0;
+            for ki in 0..2 {
+                for kj in 0..3 {
+                    sum += input[(i + ki) * 5 + j + kj] * kernel[ki * 3 + kj];
+                }
+            }
+            expected.push(sum);
+        }
+    }
+    assert_close(&output, &expected, 0.05);
+
+    let oversized = TensorOpRequest::Conv2d {
+        input: &[1.0; 4],
+        kernel: &[1.0; 6],
+        input_shape: (2, 2),
+        kernel_shape: (2, 3),
+    };
+    assert!(matches!(
+        plugin.execute_op(oversized),
+        Err(PluginError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn configured_ops_dispatch_through_plugin_ops() {
+    let plugin = reference_plugin();
+    let config =
+        AurexConfig::from_toml_str("[plugins]\noptions = { nnapi_npu = { ops = [\"conv2d\"] } }")
+            .unwrap();
+    plugin.configure(&config.plugins);
+    let a = [1.0, 2.0, 3.0, 4.0];
+    let b = [5.0, 6.0, 7.0, 8.0];
+    assert_eq!(
+        plugin.execute_op(TensorOpRequest::MatMul {
+            a: &a,
+            b: &b,
+            m: 2,
+            n: 2,
+            k: 2,
+        }),
+        Err(PluginError::Unsupported("matmul"))
+    );
+
+    // Rejected ops run on the CPU, enabled ones on the delegate.
+    let ops = PluginOps::new(Arc::new(plugin));
+    assert_eq!(ops.matmul(&a, &b, 2, 2, 2), vec![19.0, 22.0, 43.0, 50.0]);
+    let conv = ops.conv2d(&[1.0, 2.0, 3.0, 4.0], &[1.0, 1.0], (2, 2), (1, 2));
+    assert_close(&conv, &[3.0, 7.0], 0.05);
+}
@@ -0,0 +1,84 @@
+use nnapi_npu::delegate::{Delegate, DelegateError, ModelHandle};
+use nnapi_npu::graph::{self, Graph, Operation};
+use nnapi_npu::nnapi::NnapiDelegate;
+use nnapi_npu::reference::ReferenceDelegate;
+
+fn f32_values(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+#[test]
+fn fully_connected_requantizes_its_accumulators() {
+    let delegate = ReferenceDelegate::default();
+    // 1x2 input times 2x2 weights, all values exactly representable.
+    let graph = Graph::quantized_matmul((1, 2, 2), 0.5, 0.25, 0.125);
+    let model = delegate.compile(0, &graph).unwrap();
+    let zero = graph::ZERO_POINT as u8;
+    let input = [zero + 2, zero - 4];
+    let weights = [zero + 1, zero + 3, zero - 2, zero];
+    let mut output = [0; 8];
+    delegate
+        .execute(model, &[&input, &weights], &mut [&mut output])
+        .unwrap();
+    // 1.0 * 0.25 + -2.0 * 0.75 = -1.25 and 1.0 * -0.5 + -2.0 * 0.0 = -0.5.
+    assert_eq!(f32_values(&output), vec![-1.25, -0.5]);
+
+    // Accumulators beyond the output range saturate.
+    let saturating = Graph::quantized_matmul((1, 1, 1), 1.0, 1.0, 0.5);
+    let model = delegate.compile(0, &saturating).unwrap();
+    delegate
+        .execute(model, &[&[255], &[255]], &mut [&mut output[..4]])
+        .unwrap();
+    assert_eq!(f32_values(&output[..4]), vec![127.0 * 0.5]);
+
+    assert_eq!(delegate.models(), 2);
+    delegate.release(model).unwrap();
+    assert_eq!(
+        delegate.release(model),
+        Err(DelegateError::UnknownModel(model))
+    );
+    assert_eq!(delegate.models(), 1);
+}
+
+#[test]
+fn buffers_and_graphs_are_validated() {
+    let delegate = ReferenceDelegate::default();
+    let graph = Graph::quantized_conv2d((3, 3), (2, 2), 1.0, 1.0, 1.0);
+    assert_eq!(graph.operands[graph.outputs[0]].dims, vec![1, 2, 2, 1]);
+    assert_eq!(delegate.compile(1, &graph), Err(DelegateError::NoDevice(1)));
+    let model = delegate.compile(0, &graph).unwrap();
+    let mut output = [0; 16];
+    let err = delegate
+        .execute(model, &[&[0; 9]], &mut [&mut output])
+        .unwrap_err();
+    assert!(matches!(err, DelegateError::InvalidBuffers(_)), "{err}");
+    assert!(matches!(
+        delegate.execute(ModelHandle(42), &[], &mut []),
+        Err(DelegateError::UnknownModel(_))
+    ));
+
+    let mut broken = graph.clone();
+    let Operation::Conv2d { filter, .. } = broken.operations[0] else {
+        panic!("convolution graph")
+    };
+    broken.operands[filter].dims = vec![1, 4, 4, 1];
+    assert!(matches!(
+        delegate.compile(0, &broken),
+        Err(DelegateError::InvalidGraph(_))
+    ));
+    let mut constant_input = graph;
+    constant_input.operands[constant_input.inputs[0]].value = Some(vec![0; 9]);
+    assert!(constant_input.validate().is_err());
+}
+
+#[test]
+fn nnapi_requires_the_android_runtime() {
+    if cfg!(target_os = "android") {
+        return;
+    }
+    let err = NnapiDelegate::load().err().unwrap();
+    assert!(err.to_string().contains("libneuralnetworks.so"), "{err}");
+}
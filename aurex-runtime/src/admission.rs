@@ -0,0 +1,416 @@
+//! Backpressure-aware admission control.
+//!
+//! An [`AdmissionController`] decides whether a new request may start.  It
+//! rejects requests while a watched memory tier is above its high watermark
+//! or the p99 step latency exceeds the latency SLO, so that an overloaded
+//! server answers some clients with a retry hint instead of slowing down
+//! all of them.  At most `max_in_flight` requests run at once; up to
+//! `max_queued` more wait for a free slot and any beyond are rejected.
+//!
+//! Rejections are [`AdmissionError::Overloaded`] errors carrying the
+//! [`Overload`] reason and a `retry_after` delay.  Memory usage and latency
+//! are read from the [`Metrics`] registry the memory manager and generation
+//! engine record into.
+
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use aurex_utils::metrics::Metrics;
+
+use crate::config::{AdmissionConfig, AurexConfig, Watermarks};
+
+/// Retry delay suggested when the configuration sets none.
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Why a request was turned away.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Overload {
+    /// All slots are taken and the queue is full.
+    QueueFull { queued: usize },
+    /// The request waited in the queue for too long.
+    QueueTimeout { waited: Duration },
+    /// A memory tier is above its high watermark.
+    MemoryPressure { tier: String, usage: f64, high: f64 },
+    /// The p99 step latency exceeds the SLO.
+    LatencySlo { p99: Duration, slo: Duration },
+}
+
+impl fmt::Display for Overload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Overload::QueueFull { queued } => {
+                write!(f, "request queue is full ({queued} waiting)")
+            }
+            Overload::QueueTimeout { waited } => {
+                write!(f, "request queued for {} ms", waited.as_millis())
+            }
+            Overload::MemoryPressure { tier, usage, high } => write!(
+                f,
+                "{tier} memory at {:.0}% is above its {:.0}% watermark",
+                usage * 100.0,
+                high * 100.0
+            ),
+            Overload::LatencySlo { p99, slo } => write!(
+                f,
+                "p99 step latency {} ms exceeds the {} ms SLO",
+                p99.as_millis(),
+                slo.as_millis()
+            ),
+        }
+    }
+}
+
+/// Error returned by [`AdmissionController::admit`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdmissionError {
+    /// The server is overloaded; the request may be retried after
+    /// `retry_after`.
+    Overloaded {
+        reason: Overload,
+        retry_after: Duration,
+    },
+    /// The request needs more tokens than any request may use.
+    TooLarge { tokens: usize, limit: usize },
+}
+
+impl AdmissionError {
+    /// Delay after which a retry may succeed, `None` when retrying the same
+    /// request is pointless.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AdmissionError::Overloaded { retry_after, .. } => Some(*retry_after),
+            AdmissionError::TooLarge { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for AdmissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdmissionError::Overloaded {
+                reason,
+                retry_after,
+            } => write!(
+                f,
+                "overloaded: {reason}; retry after {} ms",
+                retry_after.as_millis()
+            ),
+            AdmissionError::TooLarge { tokens, limit } => {
+                write!(f, "request needs {tokens} tokens, the limit is {limit}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AdmissionError {}
+
+/// Memory tier whose usage gates admission.
+#[derive(Debug, Clone, PartialEq)]
+struct WatchedTier {
+    name: String,
+    capacity: u64,
+    marks: Watermarks,
+}
+
+#[derive(Debug, Default)]
+struct Slots {
+    in_flight: usize,
+    queued: usize,
+}
+
+/// Admission policy, see the [module documentation](self).
+#[derive(Default)]
+pub struct AdmissionController {
+    config: AdmissionConfig,
+    tiers: Vec<WatchedTier>,
+    /// Registry read for tier usage and latency, the global one when unset.
+    metrics: Option<Arc<Metrics>>,
+    slots: Mutex<Slots>,
+    freed: Condvar,
+}
+
+impl AdmissionController {
+    /// Create a controller enforcing the limits of `config`.
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Create a controller from the `[admission]` limits of `config`,
+    /// watching every tier that has both watermarks and a configured size.
+    pub fn from_config(config: &AurexConfig) -> Self {
+        let memory = &config.memory;
+        let mut controller = Self::new(config.admission.clone());
+        for (tier, &marks) in &memory.watermarks {
+            let capacity = match tier.as_str() {
+                "gpu" => memory.gpu_mem,
+                "cpu" => memory.cpu_mem,
+                "nvme" => memory.nvme_mem,
+                _ => None,
+            };
+            match capacity {
+                Some(capacity) => controller = controller.with_tier(tier, capacity as u64, marks),
+                None => tracing::debug!(%tier, "not gating admission on tier without a size"),
+            }
+        }
+        controller
+    }
+
+    /// Reject requests while `tier`, holding `capacity` bytes, is above the
+    /// high watermark of `marks`.
+    pub fn with_tier(mut self, tier: &str, capacity: u64, marks: Watermarks) -> Self {
+        self.tiers.push(WatchedTier {
+            name: tier.to_string(),
+            capacity,
+            marks,
+        });
+        self
+    }
+
+    /// Read tier usage and latency from `metrics` instead of
+    /// [`Metrics::global`].
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Requests currently admitted.
+    pub fn in_flight(&self) -> usize {
+        self.lock().in_flight
+    }
+
+    /// Requests waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.lock().queued
+    }
+
+    /// Admit a request using up to `tokens` tokens, waiting in the queue
+    /// when all slots are taken.  The slot is held until the returned permit
+    /// is dropped.
+    pub fn admit(&self, tokens: usize) -> Result<AdmissionPermit<'_>, AdmissionError> {
+        if let Some(limit) = self.config.max_request_tokens {
+            if tokens > limit {
+                return Err(AdmissionError::TooLarge { tokens, limit });
+            }
+        }
+        if let Some(reason) = self.load_overload() {
+            return Err(self.overloaded(reason));
+        }
+
+        let mut slots = self.lock();
+        let Some(max_in_flight) = self.config.max_in_flight else {
+            slots.in_flight += 1;
+            return Ok(AdmissionPermit { controller: self });
+        };
+        if slots.in_flight >= max_in_flight {
+            let max_queued = self.config.max_queued.unwrap_or(0);
+            if slots.queued >= max_queued {
+                let queued = slots.queued;
+                return Err(self.overloaded(Overload::QueueFull { queued }));
+            }
+            slots.queued += 1;
+            let started = Instant::now();
+            let full = |slots: &mut Slots| slots.in_flight >= max_in_flight;
+            slots = match self.config.queue_timeout_ms {
+                Some(ms) => {
+                    let timeout = Duration::from_millis(ms);
+                    self.freed
+                        .wait_timeout_while(slots, timeout, full)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .freed
+                    .wait_while(slots, full)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+            slots.queued -= 1;
+            if slots.in_flight >= max_in_flight {
+                let waited = started.elapsed();
+                return Err(self.overloaded(Overload::QueueTimeout { waited }));
+            }
+        }
+        slots.in_flight += 1;
+        Ok(AdmissionPermit { controller: self })
+    }
+
+    /// First tier above its high watermark, or a latency SLO violation.
+    fn load_overload(&self) -> Option<Overload> {
+        let metrics = self.metrics.as_deref().unwrap_or_else(|| Metrics::global());
+        for tier in &self.tiers {
+            let Some(used) = metrics.tier_usage(&tier.name) else {
+                continue;
+            };
+            let usage = used as f64 / tier.capacity.max(1) as f64;
+            if usage >= tier.marks.high {
+                return Some(Overload::MemoryPressure {
+                    tier: tier.name.clone(),
+                    usage,
+                    high: tier.marks.high,
+                });
+            }
+        }
+        let slo = Duration::from_millis(self.config.latency_slo_ms?);
+        let p99 = metrics.latency_quantile(0.99)?;
+        (p99 > slo).then_some(Overload::LatencySlo { p99, slo })
+    }
+
+    fn overloaded(&self, reason: Overload) -> AdmissionError {
+        tracing::debug!(%reason, "request rejected");
+        AdmissionError::Overloaded {
+            reason,
+            retry_after: self
+                .config
+                .retry_after_ms
+                .map_or(DEFAULT_RETRY_AFTER, Duration::from_millis),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Slots> {
+        self.slots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Slot of an admitted request, released on drop.
+pub struct AdmissionPermit<'a> {
+    controller: &'a AdmissionController,
+}
+
+impl fmt::Debug for AdmissionPermit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("admission permit")
+    }
+}
+
+impl Drop for AdmissionPermit<'_> {
+    fn drop(&mut self) {
+        self.controller.lock().in_flight -= 1;
+        self.controller.freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(config: AdmissionConfig) -> (AdmissionController, Arc<Metrics>) {
+        let metrics = Arc::new(Metrics::new());
+        let controller = AdmissionController::new(config).with_metrics(metrics.clone());
+        (controller, metrics)
+    }
+
+    #[test]
+    fn slots_queue_and_reject() {
+        let (controller, _) = controller(AdmissionConfig {
+            max_in_flight: Some(1),
+            max_queued: Some(1),
+            queue_timeout_ms: Some(200),
+            retry_after_ms: Some(250),
+            ..AdmissionConfig::default()
+        });
+        let permit = controller.admit(1).unwrap();
+        assert_eq!(controller.in_flight(), 1);
+
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| controller.admit(1).map(drop));
+            while controller.queued() == 0 {
+                std::thread::yield_now();
+            }
+            // The queue holds one request; the next one is turned away.
+            let err = controller.admit(1).unwrap_err();
+            assert_eq!(
+                err,
+                AdmissionError::Overloaded {
+                    reason: Overload::QueueFull { queued: 1 },
+                    retry_after: Duration::from_millis(250),
+                }
+            );
+            let Err(AdmissionError::Overloaded { reason, .. }) = waiter.join().unwrap() else {
+                panic!("queued request should time out");
+            };
+            assert!(matches!(reason, Overload::QueueTimeout { .. }));
+        });
+
+        drop(permit);
+        assert_eq!(controller.in_flight(), 0);
+        assert!(controller.admit(1).is_ok());
+    }
+
+    #[test]
+    fn queued_requests_take_freed_slots() {
+        let (controller, _) = controller(AdmissionConfig {
+            max_in_flight: Some(1),
+            max_queued: Some(4),
+            ..AdmissionConfig::default()
+        });
+        let permit = controller.admit(1).unwrap();
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| controller.admit(1).map(|_| ()));
+            while controller.queued() == 0 {
+                std::thread::yield_now();
+            }
+            drop(permit);
+            assert_eq!(waiter.join().unwrap(), Ok(()));
+        });
+        assert_eq!(controller.in_flight(), 0);
+    }
+
+    #[test]
+    fn memory_pressure_latency_and_size_reject() {
+        let (controller, metrics) = controller(AdmissionConfig {
+            max_request_tokens: Some(64),
+            latency_slo_ms: Some(10),
+            ..AdmissionConfig::default()
+        });
+        let controller = controller.with_tier(
+            "gpu",
+            1000,
+            Watermarks {
+                high: 0.9,
+                low: 0.75,
+            },
+        );
+        assert_eq!(
+            controller.admit(65).unwrap_err(),
+            AdmissionError::TooLarge {
+                tokens: 65,
+                limit: 64
+            }
+        );
+        assert!(controller.admit(65).unwrap_err().retry_after().is_none());
+
+        metrics.set_tier_usage("gpu", 950);
+        let err = controller.admit(1).unwrap_err();
+        assert_eq!(err.retry_after(), Some(DEFAULT_RETRY_AFTER));
+        assert!(err.to_string().contains("gpu memory at 95%"));
+        metrics.set_tier_usage("gpu", 500);
+        assert!(controller.admit(1).is_ok());
+
+        metrics.observe_latency(Duration::from_millis(40));
+        let Err(AdmissionError::Overloaded { reason, .. }) = controller.admit(1) else {
+            panic!("latency above the SLO should reject");
+        };
+        assert!(matches!(reason, Overload::LatencySlo { .. }));
+    }
+
+    #[test]
+    fn tiers_come_from_the_memory_config() {
+        let config = AurexConfig::from_toml_str(
+            r#"
+            [memory]
+            cpu_mem = 100
+            watermarks = { cpu = { high = 0.5, low = 0.25 }, gpu = { high = 0.5, low = 0.25 } }
+            "#,
+        )
+        .unwrap();
+        let controller = AdmissionController::from_config(&config);
+        assert_eq!(controller.tiers.len(), 1);
+        assert_eq!(controller.tiers[0].name, "cpu");
+        assert_eq!(controller.tiers[0].capacity, 100);
+    }
+}
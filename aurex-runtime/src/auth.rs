@@ -0,0 +1,278 @@
+//! API key authentication and per-key rate limits.
+//!
+//! An [`Authenticator`] checks the key a request presents against the keys
+//! of the `[auth]` section and charges the request to that key's token
+//! bucket.  A bucket holds up to `burst` requests and refills at
+//! `requests_per_second`, so a key may make short bursts but not exceed its
+//! rate for long.  Rejections are [`AuthError`]s; a rate limited key learns
+//! how long to wait before its next request.
+//!
+//! Presented keys are found by comparing digests of a keyed hash, so the
+//! time a lookup takes does not tell how much of a key a guess got right.
+//!
+//! Without configured keys the authenticator accepts every request.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::config::{AurexConfig, AuthConfig};
+
+/// Why a request was not authenticated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    /// The request carries no API key.
+    Missing,
+    /// The request's API key is not configured.
+    Invalid,
+    /// The key exceeded its rate; the request may be retried after
+    /// `retry_after`.
+    RateLimited { key: String, retry_after: Duration },
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "missing API key"),
+            AuthError::Invalid => write!(f, "invalid API key"),
+            AuthError::RateLimited { key, retry_after } => write!(
+                f,
+                "rate limit of key {key} exceeded; retry after {} ms",
+                retry_after.as_millis()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Requests a key may make, refilled at a constant rate.
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    /// Requests per second.
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: Option<u32>, now: Instant) -> Self {
+        let capacity = burst.map_or(rate.ceil(), f64::from).max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled: now,
+        }
+    }
+
+    /// Take a request at `now`, or tell how long until one is available.
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// A configured key and its digest.
+#[derive(Debug)]
+struct Key {
+    name: String,
+    key: String,
+    digest: u64,
+}
+
+/// Checks API keys and their rate limits, see the
+/// [module documentation](self).
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    keys: Vec<Key>,
+    /// Hasher of the digests, seeded randomly per authenticator.
+    hasher: RandomState,
+    /// Buckets of the rate limited keys by index into `keys`.
+    buckets: Mutex<HashMap<usize, TokenBucket>>,
+}
+
+impl Authenticator {
+    /// Create an authenticator accepting the keys of `config`.
+    pub fn new(config: &AuthConfig) -> Self {
+        let now = Instant::now();
+        let hasher = RandomState::new();
+        let mut keys = Vec::new();
+        let mut buckets = HashMap::new();
+        for (id, key) in config.keys.iter().enumerate() {
+            keys.push(Key {
+                name: key.name.clone(),
+                key: key.key.clone(),
+                digest: hasher.hash_one(&key.key),
+            });
+            let rate = key.requests_per_second.or(config.requests_per_second);
+            match rate {
+                Some(rate) if rate > 0.0 => {
+                    let burst = key.burst.or(config.burst);
+                    buckets.insert(id, TokenBucket::new(rate, burst, now));
+                }
+                Some(rate) => {
+                    tracing::warn!(key = %key.name, rate, "ignoring non-positive rate limit");
+                }
+                None => {}
+            }
+        }
+        Self {
+            keys,
+            hasher,
+            buckets: Mutex::new(buckets),
+        }
+    }
+
+    /// Create an authenticator for the `[auth]` section of `config`.
+    pub fn from_config(config: &AurexConfig) -> Self {
+        Self::new(&config.auth)
+    }
+
+    /// Whether requests need an API key.
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Authenticate a request presenting `key` and charge it to the key's
+    /// rate limit.  Returns the name of the key, `None` when no keys are
+    /// configured.
+    pub fn authenticate(&self, key: Option<&str>) -> Result<Option<&str>, AuthError> {
+        self.authenticate_at(key, Instant::now())
+    }
+
+    fn authenticate_at(&self, key: Option<&str>, now: Instant) -> Result<Option<&str>, AuthError> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        let key = key.ok_or(AuthError::Missing)?;
+        let id = self.find(key).ok_or(AuthError::Invalid)?;
+        let name = &self.keys[id].name;
+        if let Some(bucket) = self.lock().get_mut(&id) {
+            bucket
+                .take(now)
+                .map_err(|retry_after| AuthError::RateLimited {
+                    key: name.clone(),
+                    retry_after,
+                })?;
+        }
+        Ok(Some(name))
+    }
+
+    /// Index of `key` among the configured keys.  Every key is checked, and
+    /// only one whose digest matches is compared byte by byte.
+    fn find(&self, key: &str) -> Option<usize> {
+        let digest = self.hasher.hash_one(key);
+        let mut found = None;
+        for (id, candidate) in self.keys.iter().enumerate() {
+            if candidate.digest == digest && candidate.key == key && found.is_none() {
+                found = Some(id);
+            }
+        }
+        found
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<usize, TokenBucket>> {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKeyConfig;
+
+    fn key(name: &str, rate: Option<f64>, burst: Option<u32>) -> ApiKeyConfig {
+        ApiKeyConfig {
+            name: name.into(),
+            key: format!("sk-{name}"),
+            requests_per_second: rate,
+            burst,
+            token_quota: None,
+        }
+    }
+
+    #[test]
+    fn keys_are_required_once_configured() {
+        let open = Authenticator::new(&AuthConfig::default());
+        assert!(!open.is_enabled());
+        assert_eq!(open.authenticate(None), Ok(None));
+
+        let auth = Authenticator::new(&AuthConfig {
+            keys: vec![key("ci", None, None)],
+            ..AuthConfig::default()
+        });
+        assert_eq!(auth.authenticate(None), Err(AuthError::Missing));
+        assert_eq!(auth.authenticate(Some("sk-other")), Err(AuthError::Invalid));
+        for _ in 0..100 {
+            assert_eq!(auth.authenticate(Some("sk-ci")), Ok(Some("ci")));
+        }
+    }
+
+    #[test]
+    fn keys_match_whole_and_are_limited_by_key() {
+        let mut other = key("ci", None, None);
+        other.key = "sk-ci-2".into();
+        let auth = Authenticator::new(&AuthConfig {
+            keys: vec![key("ci", None, None), other],
+            requests_per_second: Some(1.0),
+            burst: None,
+        });
+        for guess in ["sk-c", "sk-ci-", "sk-ci-22", ""] {
+            assert_eq!(auth.authenticate(Some(guess)), Err(AuthError::Invalid));
+        }
+        // Even under one name, each key takes from its own bucket.
+        let now = Instant::now();
+        assert!(auth.authenticate_at(Some("sk-ci"), now).is_ok());
+        assert!(auth.authenticate_at(Some("sk-ci-2"), now).is_ok());
+        assert!(auth.authenticate_at(Some("sk-ci"), now).is_err());
+    }
+
+    #[test]
+    fn buckets_allow_bursts_and_refill_at_the_rate() {
+        let auth = Authenticator::new(&AuthConfig {
+            keys: vec![key("ci", None, Some(3)), key("batch", Some(0.5), None)],
+            requests_per_second: Some(2.0),
+            burst: None,
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(auth.authenticate_at(Some("sk-ci"), start).is_ok());
+        }
+        let err = auth.authenticate_at(Some("sk-ci"), start).unwrap_err();
+        assert_eq!(
+            err,
+            AuthError::RateLimited {
+                key: "ci".into(),
+                retry_after: Duration::from_millis(500),
+            }
+        );
+        // Half a second refills one request at two per second.
+        let later = start + Duration::from_millis(500);
+        assert!(auth.authenticate_at(Some("sk-ci"), later).is_ok());
+        assert!(auth.authenticate_at(Some("sk-ci"), later).is_err());
+
+        // Keys are limited separately; a burst of one at half a request per
+        // second.
+        assert!(auth.authenticate_at(Some("sk-batch"), later).is_ok());
+        let Err(AuthError::RateLimited { retry_after, .. }) =
+            auth.authenticate_at(Some("sk-batch"), later)
+        else {
+            panic!("expected a rate limit");
+        };
+        assert_eq!(retry_after, Duration::from_secs(2));
+    }
+}
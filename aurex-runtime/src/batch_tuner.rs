@@ -0,0 +1,439 @@
+//! Batch size auto-tuning against a latency SLO.
+//!
+//! Larger batches raise throughput until the step latency they cause
+//! exceeds what clients tolerate.  A [`BatchTuner`] finds the largest batch
+//! size within the SLO by measurement: it probes batch sizes 1, 2, 4, ... up
+//! to `max_batch`, timing `probe_steps` steps of each, stops at the first
+//! size whose slowest step misses the SLO and settles on the largest size
+//! that met it.
+//!
+//! Probing runs at startup, either up front with [`BatchTuner::tune`] or
+//! during the first steps of a caller that sizes every step with
+//! [`BatchTuner::batch_size`] and reports its latency to
+//! [`BatchTuner::observe`].  Under load a settled tuner probes again after
+//! `probe_steps` consecutive steps over the SLO and, if configured, every
+//! `retune_every` steps so batches can grow again once load eases.  While
+//! probing, steps of other sizes than the probed one are not counted.
+//!
+//! Every decision is kept with its probes in [`BatchTuner::decisions`] and
+//! reported as the `aurex_batch_probe_*` gauges, the `aurex_batch_size`
+//! gauge and the `aurex_batch_decisions_total` counter of [`Metrics`], so
+//! operators can audit why a batch size was chosen.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use aurex_utils::metrics::Metrics;
+
+use crate::AurexConfig;
+
+/// Steps timed per batch size when the configuration sets none.
+pub const DEFAULT_PROBE_STEPS: usize = 4;
+
+/// Decisions kept by [`BatchTuner::decisions`].
+pub const DECISION_HISTORY: usize = 64;
+
+/// Why batch sizes were probed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TuneReason {
+    /// First tuning after the tuner was created or [`BatchTuner::tune`].
+    Startup,
+    /// `probe_steps` consecutive steps missed the SLO.
+    SloMissed,
+    /// `retune_every` steps passed since the last decision.
+    Scheduled,
+}
+
+impl TuneReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            TuneReason::Startup => "startup",
+            TuneReason::SloMissed => "slo_missed",
+            TuneReason::Scheduled => "scheduled",
+        }
+    }
+}
+
+/// Measurements of one probed batch size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchProbe {
+    pub batch: usize,
+    /// Slowest of the probed steps.
+    pub latency: Duration,
+    /// Items per second over the probed steps.
+    pub throughput: f64,
+    pub meets_slo: bool,
+}
+
+/// Batch size chosen after probing, with the probes it was chosen from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchDecision {
+    pub batch: usize,
+    pub reason: TuneReason,
+    pub probes: Vec<BatchProbe>,
+}
+
+#[derive(Debug)]
+struct Probing {
+    reason: TuneReason,
+    candidate: usize,
+    samples: Vec<Duration>,
+    probes: Vec<BatchProbe>,
+}
+
+impl Probing {
+    fn new(reason: TuneReason) -> Self {
+        Self {
+            reason,
+            candidate: 1,
+            samples: Vec::new(),
+            probes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    batch: usize,
+    probing: Option<Probing>,
+    /// Consecutive settled steps over the SLO.
+    over_slo: usize,
+    /// Settled steps since the last decision.
+    settled_steps: u64,
+    decisions: VecDeque<BatchDecision>,
+}
+
+/// Latency SLO driven batch sizing, see the [module documentation](self).
+pub struct BatchTuner {
+    max_batch: usize,
+    slo: Duration,
+    probe_steps: usize,
+    retune_every: Option<u64>,
+    metrics: Option<Arc<Metrics>>,
+    state: Mutex<State>,
+}
+
+impl BatchTuner {
+    /// Tune batch sizes up to `max_batch` against a step latency of `slo`.
+    pub fn new(max_batch: usize, slo: Duration) -> Self {
+        Self {
+            max_batch: max_batch.max(1),
+            slo,
+            probe_steps: DEFAULT_PROBE_STEPS,
+            retune_every: None,
+            metrics: None,
+            state: Mutex::new(State {
+                batch: 1,
+                probing: Some(Probing::new(TuneReason::Startup)),
+                over_slo: 0,
+                settled_steps: 0,
+                decisions: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Tuner for the `[scheduler]` batch settings of `config`.  `None`
+    /// without `max_batch` or a latency SLO.
+    pub fn from_config(config: &AurexConfig) -> Option<Self> {
+        let scheduler = &config.scheduler;
+        let max_batch = scheduler.max_batch?;
+        let Some(slo_ms) = scheduler
+            .batch_latency_slo_ms
+            .or(config.admission.latency_slo_ms)
+        else {
+            tracing::warn!("max_batch configured without a latency SLO, batches are not tuned");
+            return None;
+        };
+        let mut tuner = Self::new(max_batch, Duration::from_millis(slo_ms));
+        if let Some(steps) = scheduler.batch_probe_steps {
+            tuner = tuner.with_probe_steps(steps);
+        }
+        if let Some(every) = scheduler.batch_retune_every {
+            tuner = tuner.with_retune_every(every);
+        }
+        Some(tuner)
+    }
+
+    /// Time `steps` steps of every probed batch size.
+    pub fn with_probe_steps(mut self, steps: usize) -> Self {
+        self.probe_steps = steps.max(1);
+        self
+    }
+
+    /// Probe again after `steps` settled steps.
+    pub fn with_retune_every(mut self, steps: u64) -> Self {
+        self.retune_every = Some(steps.max(1));
+        self
+    }
+
+    /// Report probes and decisions to `metrics` instead of
+    /// [`Metrics::global`].
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn max_batch(&self) -> usize {
+        self.max_batch
+    }
+
+    pub fn slo(&self) -> Duration {
+        self.slo
+    }
+
+    /// Whether batch sizes are being probed.
+    pub fn is_probing(&self) -> bool {
+        self.lock().probing.is_some()
+    }
+
+    /// Number of items to run in the next step: the probed size while
+    /// probing, the chosen one otherwise.
+    pub fn batch_size(&self) -> usize {
+        let state = self.lock();
+        match &state.probing {
+            Some(probing) => probing.candidate,
+            None => state.batch,
+        }
+    }
+
+    /// Past decisions, oldest first.
+    pub fn decisions(&self) -> Vec<BatchDecision> {
+        self.lock().decisions.iter().cloned().collect()
+    }
+
+    /// Probe every batch size by calling `step` with it, which runs one
+    /// step of that many items and returns its latency, and return the
+    /// decision.
+    pub fn tune(&self, mut step: impl FnMut(usize) -> Duration) -> BatchDecision {
+        self.lock().probing = Some(Probing::new(TuneReason::Startup));
+        loop {
+            let batch = self.batch_size();
+            if let Some(decision) = self.observe(batch, step(batch)) {
+                return decision;
+            }
+        }
+    }
+
+    /// Record that a step of `batch` items took `latency`.  Returns the
+    /// decision when this step completed probing.
+    pub fn observe(&self, batch: usize, latency: Duration) -> Option<BatchDecision> {
+        let mut state = self.lock();
+        let Some(probing) = &mut state.probing else {
+            state.settled_steps += 1;
+            state.over_slo = if latency > self.slo {
+                state.over_slo + 1
+            } else {
+                0
+            };
+            let reason = if state.over_slo >= self.probe_steps {
+                TuneReason::SloMissed
+            } else if self
+                .retune_every
+                .is_some_and(|every| state.settled_steps >= every)
+            {
+                TuneReason::Scheduled
+            } else {
+                return None;
+            };
+            tracing::info!(
+                batch = state.batch,
+                reason = reason.name(),
+                "probing batch sizes"
+            );
+            state.probing = Some(Probing::new(reason));
+            return None;
+        };
+        if batch != probing.candidate {
+            return None;
+        }
+        probing.samples.push(latency);
+        if probing.samples.len() < self.probe_steps {
+            return None;
+        }
+
+        let slowest = probing.samples.iter().copied().max().unwrap_or_default();
+        let total: Duration = probing.samples.iter().sum();
+        let items = batch * probing.samples.len();
+        let probe = BatchProbe {
+            batch,
+            latency: slowest,
+            throughput: items as f64 / total.as_secs_f64().max(f64::MIN_POSITIVE),
+            meets_slo: slowest <= self.slo,
+        };
+        self.metrics()
+            .record_batch_probe(batch, probe.latency, probe.throughput);
+        probing.samples.clear();
+        probing.probes.push(probe);
+        if probe.meets_slo && batch < self.max_batch {
+            probing.candidate = (batch * 2).min(self.max_batch);
+            return None;
+        }
+
+        let probing = state.probing.take().expect("probing");
+        let decision = BatchDecision {
+            batch: probing
+                .probes
+                .iter()
+                .filter(|probe| probe.meets_slo)
+                .map(|probe| probe.batch)
+                .max()
+                .unwrap_or(1),
+            reason: probing.reason,
+            probes: probing.probes,
+        };
+        self.metrics()
+            .record_batch_decision(decision.batch, decision.reason.name());
+        tracing::info!(
+            batch = decision.batch,
+            previous = state.batch,
+            reason = decision.reason.name(),
+            slo_ms = self.slo.as_millis() as u64,
+            "batch size chosen"
+        );
+        state.batch = decision.batch;
+        state.over_slo = 0;
+        state.settled_steps = 0;
+        if state.decisions.len() == DECISION_HISTORY {
+            state.decisions.pop_front();
+        }
+        state.decisions.push_back(decision.clone());
+        Some(decision)
+    }
+
+    fn metrics(&self) -> &Metrics {
+        self.metrics.as_deref().unwrap_or_else(|| Metrics::global())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl std::fmt::Debug for BatchTuner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BatchTuner")
+            .field("max_batch", &self.max_batch)
+            .field("slo", &self.slo)
+            .field("probe_steps", &self.probe_steps)
+            .field("retune_every", &self.retune_every)
+            .field("batch", &self.batch_size())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn startup_picks_the_largest_batch_within_the_slo() {
+        let metrics = Arc::new(Metrics::new());
+        let tuner = BatchTuner::new(16, ms(45))
+            .with_probe_steps(2)
+            .with_metrics(metrics.clone());
+        let mut probed = Vec::new();
+        let decision = tuner.tune(|batch| {
+            probed.push(batch);
+            ms(10 * batch as u64)
+        });
+        // 8 items take 80 ms and end probing.
+        assert_eq!(probed, [1, 1, 2, 2, 4, 4, 8, 8]);
+        assert_eq!(decision.batch, 4);
+        assert_eq!(decision.reason, TuneReason::Startup);
+        let meets: Vec<bool> = decision.probes.iter().map(|p| p.meets_slo).collect();
+        assert_eq!(meets, [true, true, true, false]);
+        assert!((decision.probes[2].throughput - 100.0).abs() < 1e-6);
+        assert_eq!(tuner.batch_size(), 4);
+        assert!(!tuner.is_probing());
+
+        assert_eq!(metrics.batch_size(), Some(4));
+        assert_eq!(metrics.batch_decisions("startup"), 1);
+        assert_eq!(metrics.batch_probe(8).map(|p| p.0), Some(ms(80)));
+        assert_eq!(metrics.batch_probe(16), None);
+
+        // Every size within the SLO ends at `max_batch`.
+        let tuner = BatchTuner::new(6, ms(100)).with_metrics(metrics);
+        assert_eq!(tuner.tune(|_| ms(1)).batch, 6);
+    }
+
+    #[test]
+    fn load_reprobes_after_slo_misses_and_on_schedule() {
+        let metrics = Arc::new(Metrics::new());
+        let tuner = BatchTuner::new(8, ms(50))
+            .with_probe_steps(2)
+            .with_retune_every(10)
+            .with_metrics(metrics.clone());
+        // Probed during the first steps of the caller.
+        let mut decided = None;
+        while decided.is_none() {
+            let batch = tuner.batch_size();
+            decided = tuner.observe(batch, ms(5 * batch as u64));
+        }
+        assert_eq!(decided.unwrap().batch, 8);
+
+        // A slow step alone keeps the batch, two in a row start probing.
+        assert_eq!(tuner.observe(8, ms(90)), None);
+        assert_eq!(tuner.observe(8, ms(20)), None);
+        assert_eq!(tuner.observe(8, ms(90)), None);
+        assert!(!tuner.is_probing());
+        assert_eq!(tuner.observe(8, ms(90)), None);
+        assert!(tuner.is_probing());
+        assert_eq!(tuner.batch_size(), 1);
+
+        // The device is now twice as slow per item.
+        let slower = |tuner: &BatchTuner| loop {
+            let batch = tuner.batch_size();
+            // A step of another size does not count while probing.
+            assert_eq!(tuner.observe(batch + 1, ms(1)), None);
+            if let Some(decision) = tuner.observe(batch, ms(20 * batch as u64)) {
+                return decision;
+            }
+        };
+        let decision = slower(&tuner);
+        assert_eq!(
+            (decision.batch, decision.reason),
+            (2, TuneReason::SloMissed)
+        );
+
+        for _ in 0..9 {
+            assert_eq!(tuner.observe(2, ms(40)), None);
+        }
+        assert!(!tuner.is_probing());
+        tuner.observe(2, ms(40));
+        assert!(tuner.is_probing());
+        let decision = slower(&tuner);
+        assert_eq!(decision.reason, TuneReason::Scheduled);
+
+        let reasons: Vec<TuneReason> = tuner.decisions().iter().map(|d| d.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                TuneReason::Startup,
+                TuneReason::SloMissed,
+                TuneReason::Scheduled
+            ]
+        );
+        assert_eq!(metrics.batch_decisions("slo_missed"), 1);
+        assert_eq!(metrics.batch_size(), Some(2));
+    }
+
+    #[test]
+    fn config_needs_a_max_batch_and_an_slo() {
+        let mut config = AurexConfig::default();
+        assert!(BatchTuner::from_config(&config).is_none());
+        config.scheduler.max_batch = Some(32);
+        assert!(BatchTuner::from_config(&config).is_none());
+        config.admission.latency_slo_ms = Some(40);
+        let tuner = BatchTuner::from_config(&config).unwrap();
+        assert_eq!((tuner.max_batch(), tuner.slo()), (32, ms(40)));
+        config.scheduler.batch_latency_slo_ms = Some(25);
+        assert_eq!(BatchTuner::from_config(&config).unwrap().slo(), ms(25));
+    }
+}
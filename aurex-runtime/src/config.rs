@@ -0,0 +1,899 @@
+//! Layered runtime configuration.
+//!
+//! Settings are resolved from an `aurex.toml` file, then overridden by
+//! environment variables and finally by command line flags applied by the
+//! caller.  The resulting [`AurexConfig`] is consumed by the [`Runtime`], the
+//! backend [`Dispatcher`] and the memory manager so those components no longer
+//! read environment variables on their own.
+//!
+//! ```toml
+//! [backend]
+//! preferred = "rocm"
+//! precision = "bf16"
+//! disabled = ["opencl"]
+//! routes = { layer_norm = "cpu" }
+//! cpu_affinity = "node"
+//! deterministic = false
+//! validate_shapes = true
+//! health_check_every = 64
+//! coalesce_window_us = 200
+//! coalesce_max_batch = 32
+//!
+//! [cpu]
+//! threads = 16
+//! pinning = "core"
+//! cores = "performance"
+//!
+//! [memory]
+//! has_gpu = true
+//! gpu_mem = 8589934592
+//! eviction = "lfu"
+//! spill_dir = "/mnt/nvme/aurex-spill"
+//! compression = "zstd"
+//!
+//! [scheduler]
+//! max_steps = 4096
+//! max_wall_time_ms = 30000
+//! capture_plan = true
+//! prefill_chunk = 512
+//! max_batch = 32
+//! batch_retune_every = 10000
+//!
+//! [admission]
+//! max_in_flight = 8
+//! max_queued = 32
+//! latency_slo_ms = 50
+//!
+//! [auth]
+//! requests_per_second = 5.0
+//! keys = [{ name = "ci", key = "sk-ci-4f1d", burst = 20 }]
+//!
+//! [response_cache]
+//! max_entries = 4096
+//! ttl_ms = 600000
+//!
+//! [sessions]
+//! context_window = 4096
+//! keep_messages = 4
+//!
+//! [power]
+//! max_watts = 15.0
+//! max_joules_per_token = 0.5
+//! low_precision = "int8"
+//!
+//! [thermal]
+//! max_celsius = 85.0
+//! critical_celsius = 95.0
+//!
+//! [plugins]
+//! paths = ["/opt/aurex/plugins/libfpga_npu.so"]
+//! options = { fpga_npu = { ops = ["matmul"] } }
+//!
+//! [distributed]
+//! coordinator = "10.0.0.1:29500"
+//! rank = 0
+//! world_size = 4
+//! ```
+//!
+//! [`Runtime`]: crate::Runtime
+//! [`Dispatcher`]: aurex_backend::Dispatcher
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use aurex_backend::{
+    Backend, CoalesceConfig, CoreSelection, CpuAffinity, Dispatcher, Precision,
+    TensorParallelDispatcher, ThreadPoolConfig, Workload,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::EffortCaps;
+
+/// File name looked up in the working directory when no path is given.
+pub const DEFAULT_CONFIG_FILE: &str = "aurex.toml";
+
+/// Complete AUREX configuration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AurexConfig {
+    pub backend: BackendConfig,
+    pub cpu: CpuConfig,
+    pub memory: MemoryConfig,
+    pub scheduler: SchedulerConfig,
+    pub admission: AdmissionConfig,
+    pub auth: AuthConfig,
+    pub response_cache: ResponseCacheConfig,
+    pub sessions: SessionConfig,
+    pub power: PowerConfig,
+    pub thermal: ThermalConfig,
+    pub plugins: PluginConfig,
+    pub distributed: DistributedConfig,
+}
+
+/// Backend selection and numeric precision.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    /// Preferred backend; the dispatcher picks one by workload when unset.
+    pub preferred: Option<Backend>,
+    /// Precision applied to model execution, `f32` when unset.
+    pub precision: Option<Precision>,
+    /// Backends the dispatcher must never select.
+    pub disabled: Vec<Backend>,
+    /// Per-op backend overrides, e.g. `{ matmul = "rocm", layer_norm = "cpu" }`.
+    pub routes: BTreeMap<String, Backend>,
+    /// Pinning of tensor parallel CPU workers to NUMA nodes or cores; the OS
+    /// schedules them when unset.
+    pub cpu_affinity: Option<CpuAffinity>,
+    /// Reproduce identical outputs across runs: ops ignore the cost model
+    /// and reduce in a fixed order, and pipelines run on one thread.
+    pub deterministic: Option<bool>,
+    /// Check the input sizes of every op before it runs; on by default in
+    /// debug builds only.
+    pub validate_shapes: Option<bool>,
+    /// Scan the output of one op out of this many for NaN and infinities;
+    /// outputs are not checked when unset or `0`.
+    pub health_check_every: Option<u64>,
+    /// Longest a small matmul waits for matmuls of the same shape from other
+    /// threads to run with, in microseconds.  Longer windows give larger
+    /// batches at the cost of latency; matmuls are not coalesced when unset
+    /// or `0`.
+    pub coalesce_window_us: Option<u64>,
+    /// Largest number of matmuls coalesced into one batch.
+    pub coalesce_max_batch: Option<usize>,
+}
+
+/// Worker threads of the CPU backend, see
+/// [`CpuThreadPool`](aurex_backend::CpuThreadPool).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuConfig {
+    /// Number of workers, one per selected core when unset.
+    pub threads: Option<usize>,
+    /// Pinning of the workers to NUMA nodes or single cores; unpinned when
+    /// unset.
+    pub pinning: Option<CpuAffinity>,
+    /// Cores of a hybrid processor the workers run on, the performance
+    /// cores when unset.
+    pub cores: Option<CoreSelection>,
+}
+
+/// Memory tier capabilities and limits in bytes.  Unset values fall back to
+/// the memory manager's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    pub has_gpu: Option<bool>,
+    pub has_nvme: Option<bool>,
+    pub gpu_mem: Option<usize>,
+    pub cpu_mem: Option<usize>,
+    pub nvme_mem: Option<usize>,
+    /// Eviction policy of the memory manager, LRU when unset.
+    pub eviction: Option<Eviction>,
+    /// Directory for the spill files backing the NVMe tier; the tier is only
+    /// simulated when unset.
+    pub spill_dir: Option<PathBuf>,
+    /// Write spill files with `O_DIRECT`, bypassing the page cache.
+    pub spill_direct: Option<bool>,
+    /// Codec compressing allocations demoted to the CPU or NVMe tier;
+    /// nothing is compressed when unset.
+    pub compression: Option<Compression>,
+    /// Codec specific level, e.g. 1 to 22 for zstd.
+    pub compression_level: Option<i32>,
+    /// NUMA node large CPU-tier weight buffers are pinned to; they are
+    /// spread over all nodes when unset.
+    pub numa_node: Option<usize>,
+    /// Pressure watermarks per tier (`gpu`, `cpu` or `nvme`), e.g.
+    /// `{ gpu = { high = 0.9, low = 0.75 } }`.
+    pub watermarks: BTreeMap<String, Watermarks>,
+}
+
+/// Fractions of a tier's capacity between which memory pressure handlers
+/// run: crossing `high` asks them to release memory until usage is back at
+/// `low`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Watermarks {
+    pub high: f64,
+    pub low: f64,
+}
+
+/// Eviction policies selectable through `[memory] eviction`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eviction {
+    Lru,
+    Lfu,
+    Clock,
+}
+
+impl std::str::FromStr for Eviction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lru" => Ok(Eviction::Lru),
+            "lfu" => Ok(Eviction::Lfu),
+            "clock" => Ok(Eviction::Clock),
+            other => Err(format!("unknown eviction policy '{other}'")),
+        }
+    }
+}
+
+/// Codecs selectable through `[memory] compression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+    Lz4,
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            other => Err(format!("unknown compression codec '{other}'")),
+        }
+    }
+}
+
+/// Effort caps, pipeline, batching and warmup settings of the runtime
+/// scheduler.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    pub max_steps: Option<u64>,
+    pub max_flops: Option<u64>,
+    pub max_wall_time_ms: Option<u64>,
+    /// Energy cap in joules.
+    pub max_energy: Option<f64>,
+    /// Candidate branches explored at once by branching generation.
+    pub max_branches: Option<usize>,
+    /// Micro-batches buffered between pipeline stages.
+    pub pipeline_queue_depth: Option<usize>,
+    /// Warm up models before serving, on unless `false`.
+    pub warmup: Option<bool>,
+    /// Pin ops to the backends they were warmed up on, see
+    /// [`Runtime::warmup`](crate::Runtime::warmup).
+    pub capture_plan: Option<bool>,
+    /// Prompt tokens prefilled per batching iteration, between the decode
+    /// steps of other requests.
+    pub prefill_chunk: Option<usize>,
+    /// Largest batch size probed by the
+    /// [`BatchTuner`](crate::batch_tuner::BatchTuner); batch sizes are not
+    /// tuned when unset.
+    pub max_batch: Option<usize>,
+    /// Step latency the tuned batch size must meet, the admission
+    /// `latency_slo_ms` when unset.
+    pub batch_latency_slo_ms: Option<u64>,
+    /// Steps timed per probed batch size.
+    pub batch_probe_steps: Option<usize>,
+    /// Steps after which a tuned batch size is probed again even though it
+    /// meets the SLO, so batches can grow once load eases.
+    pub batch_retune_every: Option<u64>,
+}
+
+/// Limits beyond which new requests are queued or rejected, see
+/// [`AdmissionController`](crate::admission::AdmissionController).  Unset
+/// limits are not enforced.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionConfig {
+    /// Requests served at once.
+    pub max_in_flight: Option<usize>,
+    /// Requests waiting for a free slot; further requests are rejected.
+    pub max_queued: Option<usize>,
+    /// Longest a request waits in the queue.
+    pub queue_timeout_ms: Option<u64>,
+    /// Largest number of tokens, prompt and completion, of one request.
+    pub max_request_tokens: Option<usize>,
+    /// Target p99 latency of a generation step; requests are rejected
+    /// while it is exceeded.
+    pub latency_slo_ms: Option<u64>,
+    /// Retry delay suggested to rejected clients, one second when unset.
+    pub retry_after_ms: Option<u64>,
+}
+
+/// API keys accepted by `aurex serve` and their rate limits, see
+/// [`Authenticator`](crate::auth::Authenticator).  Without keys requests
+/// need no key and are not limited.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Keys with distinct names and keys.
+    #[serde(deserialize_with = "unique_keys")]
+    pub keys: Vec<ApiKeyConfig>,
+    /// Requests per second of keys that set no rate of their own;
+    /// unlimited when unset.
+    pub requests_per_second: Option<f64>,
+    /// Requests a key may make in a row after being idle, the rate rounded
+    /// up when unset.
+    pub burst: Option<u32>,
+}
+
+/// Read API keys, rejecting a name or key used twice, whose rate limits and
+/// usage would otherwise be mixed up.
+fn unique_keys<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<ApiKeyConfig>, D::Error> {
+    let keys = Vec::<ApiKeyConfig>::deserialize(deserializer)?;
+    let mut names = HashSet::new();
+    let mut secrets = HashSet::new();
+    for key in &keys {
+        if !names.insert(key.name.as_str()) {
+            return Err(de::Error::custom(format!(
+                "API key name '{}' is used twice",
+                key.name
+            )));
+        }
+        if !secrets.insert(key.key.as_str()) {
+            return Err(de::Error::custom(format!(
+                "the key of '{}' is used by another API key",
+                key.name
+            )));
+        }
+    }
+    Ok(keys)
+}
+
+/// An API key of [`AuthConfig`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// Owner of the key, as shown in logs.
+    pub name: String,
+    pub key: String,
+    #[serde(default)]
+    pub requests_per_second: Option<f64>,
+    #[serde(default)]
+    pub burst: Option<u32>,
+    /// Prompt and generated tokens the key may use in total, see
+    /// [`UsageTracker`](crate::usage::UsageTracker).
+    #[serde(default)]
+    pub token_quota: Option<u64>,
+}
+
+/// Limits of the exact-match cache of generated responses, see
+/// [`ResponseCache`](crate::response_cache::ResponseCache).  The cache is
+/// off unless `max_entries` or `max_bytes` is set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResponseCacheConfig {
+    /// Responses kept.
+    pub max_entries: Option<usize>,
+    /// Bytes of generated text kept.
+    pub max_bytes: Option<usize>,
+    /// Age after which a response is generated again; responses stay until
+    /// evicted when unset.
+    pub ttl_ms: Option<u64>,
+}
+
+/// Context compression of conversation sessions: once the prompt of a
+/// session's next turn and its `max_tokens` would fill `compress_at` of the
+/// `context_window`, the model summarizes the older turns.  Sessions are not
+/// compressed unless `context_window` is set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Tokens the model attends to.
+    pub context_window: Option<usize>,
+    /// Fraction of the window at which to compress, 0.75 when unset.
+    pub compress_at: Option<f64>,
+    /// Most recent messages kept verbatim, 4 when unset.
+    pub keep_messages: Option<usize>,
+    /// Longest summary in tokens, 128 when unset.
+    pub summary_tokens: Option<usize>,
+}
+
+/// Power budget of edge deployments, enforced by a
+/// [`PowerGovernor`](crate::power::PowerGovernor).  Without a budget no
+/// governor is started.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerConfig {
+    /// Average power in watts.
+    pub max_watts: Option<f64>,
+    /// Energy per generated token in joules.
+    pub max_joules_per_token: Option<f64>,
+    /// Energy readings the averages are taken over.
+    pub window: Option<usize>,
+    /// Precision used while throttled, `bf16` when unset.
+    pub low_precision: Option<Precision>,
+    /// Cap device clocks while throttled, on unless `false`.
+    pub cap_clocks: Option<bool>,
+}
+
+/// Temperature limits of sustained load, enforced by a
+/// [`ThermalMonitor`](crate::thermal::ThermalMonitor).  Without
+/// `max_celsius` no monitor is started.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThermalConfig {
+    /// Temperature above which batches shrink.
+    pub max_celsius: Option<f64>,
+    /// Temperature above which idle slots are inserted, 10 degrees above
+    /// `max_celsius` when unset.
+    pub critical_celsius: Option<f64>,
+    /// Degrees below `max_celsius` the temperature must fall to before
+    /// batches grow again, 5 when unset.
+    pub hysteresis: Option<f64>,
+    /// Length of an idle slot, 100 ms when unset.
+    pub idle_ms: Option<u64>,
+    /// Smallest batch, one micro-batch when unset.
+    pub min_batch: Option<usize>,
+}
+
+/// Dynamic plugin libraries to load at startup and their settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginConfig {
+    pub paths: Vec<PathBuf>,
+    /// Settings of each plugin, keyed by plugin name.
+    pub options: BTreeMap<String, toml::Table>,
+}
+
+impl PluginConfig {
+    /// Settings of the plugin called `name`.
+    pub fn options(&self, name: &str) -> Option<&toml::Table> {
+        self.options.get(name)
+    }
+}
+
+/// Multi-node job layout.  A job without `world_size` runs on one process.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DistributedConfig {
+    /// `host:port` rank 0 listens on and the other ranks connect to.
+    pub coordinator: Option<String>,
+    pub rank: Option<usize>,
+    pub world_size: Option<usize>,
+}
+
+/// Error raised while reading a configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "failed to read config {}: {source}", path.display())
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "invalid config {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+        }
+    }
+}
+
+impl AurexConfig {
+    /// Parse a configuration from TOML text.
+    pub fn from_toml_str(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Read a configuration file without applying environment overrides.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_toml_str(&text).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Resolve the configuration from file and environment.
+    ///
+    /// The file is `path` if given, otherwise `AUREX_CONFIG` or
+    /// `./aurex.toml` when present.  An explicitly requested file must exist.
+    /// Environment variables are applied on top of the file.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let explicit = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("AUREX_CONFIG").map(PathBuf::from));
+        let mut config = match explicit {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+                Self::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            None => Self::default(),
+        };
+        config.apply_env();
+        Ok(config)
+    }
+
+    /// Override settings with the `AUREX_*` and `AMDUDA_*` environment
+    /// variables.  Unparsable values are ignored.
+    pub fn apply_env(&mut self) {
+        self.backend.apply_env();
+        self.cpu.apply_env();
+        self.memory.apply_env();
+        self.distributed.apply_env();
+        if let Some(paths) = std::env::var_os("AUREX_PLUGIN_PATH") {
+            self.plugins.paths = std::env::split_paths(&paths).collect();
+        }
+    }
+
+    /// Effort caps derived from the scheduler settings.
+    pub fn effort_caps(&self) -> EffortCaps {
+        EffortCaps {
+            max_steps: self.scheduler.max_steps,
+            max_flops: self.scheduler.max_flops,
+            max_wall_time: self.scheduler.max_wall_time_ms.map(Duration::from_millis),
+            max_energy: self.scheduler.max_energy,
+            max_branches: self.scheduler.max_branches,
+        }
+    }
+
+    /// Build a dispatcher honouring the backend preference, disabled
+    /// backends, op routes, precision, deterministic mode, shape
+    /// validation, numerical health check and matmul coalescing.
+    pub fn dispatcher(&self, workload: Workload) -> Dispatcher {
+        let mut dispatcher =
+            Dispatcher::with_disabled(self.backend.preferred, workload, &self.backend.disabled);
+        dispatcher.set_precision(self.backend.precision.unwrap_or(Precision::F32));
+        dispatcher.set_deterministic(self.backend.deterministic == Some(true));
+        if let Some(validate) = self.backend.validate_shapes {
+            dispatcher.set_validation(validate);
+        }
+        dispatcher.set_health_check(self.backend.health_check_every.filter(|&n| n > 0));
+        dispatcher.set_coalescing(self.coalescing());
+        for (op, &backend) in &self.backend.routes {
+            dispatcher.route(op, backend);
+        }
+        dispatcher
+    }
+
+    /// Matmul coalescing of dispatchers, `None` when disabled.
+    pub fn coalescing(&self) -> Option<CoalesceConfig> {
+        let window = self.backend.coalesce_window_us.filter(|&us| us > 0)?;
+        let defaults = CoalesceConfig::default();
+        Some(CoalesceConfig {
+            window: Duration::from_micros(window),
+            max_batch: self
+                .backend
+                .coalesce_max_batch
+                .unwrap_or(defaults.max_batch),
+            ..defaults
+        })
+    }
+
+    /// Shape of the CPU backend's thread pool.
+    pub fn thread_pool(&self) -> ThreadPoolConfig {
+        ThreadPoolConfig {
+            threads: self.cpu.threads.filter(|&n| n > 0),
+            pinning: self.cpu.pinning.unwrap_or_default(),
+            cores: self.cpu.cores.unwrap_or_default(),
+        }
+    }
+
+    /// Build a tensor parallel dispatcher over `backends` whose workers are
+    /// pinned according to `cpu_affinity`.
+    pub fn tensor_parallel(&self, backends: &[Backend]) -> TensorParallelDispatcher {
+        let tp = TensorParallelDispatcher::new(backends);
+        match self.backend.cpu_affinity {
+            Some(affinity) if affinity != CpuAffinity::None => tp.with_affinity(affinity),
+            _ => tp,
+        }
+    }
+}
+
+impl BackendConfig {
+    const DISABLE_VARS: [(Backend, &'static str); 4] = [
+        (Backend::Rocm, "AUREX_DISABLE_ROCM"),
+        (Backend::Sycl, "AUREX_DISABLE_SYCL"),
+        (Backend::OpenCl, "AUREX_DISABLE_OPENCL"),
+        (Backend::Vulkan, "AUREX_DISABLE_VULKAN"),
+    ];
+
+    fn apply_env(&mut self) {
+        if let Some(backend) = std::env::var("AUREX_BACKEND")
+            .ok()
+            .and_then(|v| Backend::from_name(&v))
+        {
+            self.preferred = Some(backend);
+        }
+        if let Some(precision) = std::env::var("AUREX_PRECISION")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            self.precision = Some(precision);
+        }
+        if let Some(affinity) = std::env::var("AUREX_CPU_AFFINITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            self.cpu_affinity = Some(affinity);
+        }
+        if let Ok(value) = std::env::var("AUREX_DETERMINISTIC") {
+            self.deterministic = Some(value == "1");
+        }
+        if let Ok(value) = std::env::var("AUREX_VALIDATE_SHAPES") {
+            self.validate_shapes = Some(value == "1");
+        }
+        if let Some(every) = std::env::var("AUREX_HEALTH_CHECK_EVERY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            self.health_check_every = Some(every);
+        }
+        if let Some(window) = std::env::var("AUREX_COALESCE_WINDOW_US")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            self.coalesce_window_us = Some(window);
+        }
+        for (backend, var) in Self::DISABLE_VARS {
+            if std::env::var_os(var).is_some() && !self.disabled.contains(&backend) {
+                self.disabled.push(backend);
+            }
+        }
+    }
+}
+
+impl CpuConfig {
+    fn apply_env(&mut self) {
+        let parsed = |var| std::env::var(var).ok().filter(|v| !v.is_empty());
+        self.threads = parsed("AUREX_CPU_THREADS")
+            .and_then(|v| v.parse().ok())
+            .or(self.threads);
+        self.pinning = parsed("AUREX_CPU_PINNING")
+            .and_then(|v| v.parse().ok())
+            .or(self.pinning);
+        self.cores = parsed("AUREX_CPU_CORES")
+            .and_then(|v| v.parse().ok())
+            .or(self.cores);
+    }
+}
+
+impl MemoryConfig {
+    /// Memory settings taken from the `AMDUDA_*` environment variables only.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        config.apply_env();
+        config
+    }
+
+    fn apply_env(&mut self) {
+        let flag = |var| std::env::var(var).ok().map(|v| v == "1");
+        let bytes = |var| {
+            std::env::var(var)
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+        };
+        self.has_gpu = flag("AMDUDA_HAS_GPU").or(self.has_gpu);
+        self.has_nvme = flag("AMDUDA_HAS_NVME").or(self.has_nvme);
+        self.gpu_mem = bytes("AMDUDA_GPU_MEM").or(self.gpu_mem);
+        self.cpu_mem = bytes("AMDUDA_CPU_MEM").or(self.cpu_mem);
+        self.nvme_mem = bytes("AMDUDA_NVME_MEM").or(self.nvme_mem);
+        self.eviction = std::env::var("AMDUDA_EVICTION")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .or(self.eviction);
+        if let Ok(dir) = std::env::var("AMDUDA_SPILL_DIR") {
+            self.spill_dir = Some(PathBuf::from(dir));
+        }
+        self.spill_direct = flag("AMDUDA_SPILL_DIRECT").or(self.spill_direct);
+        self.compression = std::env::var("AMDUDA_COMPRESSION")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .or(self.compression);
+        self.compression_level = std::env::var("AMDUDA_COMPRESSION_LEVEL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .or(self.compression_level);
+        self.numa_node = std::env::var("AMDUDA_NUMA_NODE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .or(self.numa_node);
+    }
+}
+
+impl DistributedConfig {
+    fn apply_env(&mut self) {
+        let index = |var| {
+            std::env::var(var)
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+        };
+        if let Ok(addr) = std::env::var("AUREX_COORDINATOR") {
+            self.coordinator = Some(addr);
+        }
+        self.rank = index("AUREX_RANK").or(self.rank);
+        self.world_size = index("AUREX_WORLD_SIZE").or(self.world_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_all_sections() {
+        let config = AurexConfig::from_toml_str(
+            r#"
+            [backend]
+            preferred = "vulkan"
+            precision = "bf16"
+            disabled = ["opencl"]
+            routes = { layer_norm = "cpu" }
+            cpu_affinity = "node"
+            deterministic = true
+            validate_shapes = false
+            health_check_every = 16
+            coalesce_window_us = 500
+            coalesce_max_batch = 4
+
+            [cpu]
+            threads = 6
+            pinning = "core"
+            cores = "all"
+
+            [memory]
+            has_gpu = true
+            gpu_mem = 4096
+            eviction = "clock"
+            spill_dir = "/var/tmp/spill"
+            compression = "lz4"
+            numa_node = 1
+            watermarks = { gpu = { high = 0.9, low = 0.75 } }
+
+            [scheduler]
+            max_steps = 8
+            max_wall_time_ms = 250
+            capture_plan = true
+            prefill_chunk = 64
+            max_batch = 16
+            batch_probe_steps = 2
+
+            [admission]
+            max_in_flight = 4
+            latency_slo_ms = 20
+
+            [auth]
+            requests_per_second = 2.0
+            keys = [{ name = "ci", key = "sk-ci", burst = 5, token_quota = 100000 }]
+
+            [response_cache]
+            max_bytes = 65536
+
+            [sessions]
+            context_window = 2048
+            summary_tokens = 64
+
+            [power]
+            max_joules_per_token = 0.25
+            low_precision = "int8"
+
+            [thermal]
+            max_celsius = 80.0
+            idle_ms = 50
+
+            [plugins]
+            paths = ["libfpga_npu.so"]
+            options = { fpga_npu = { ops = ["matmul"] } }
+
+            [distributed]
+            coordinator = "127.0.0.1:29500"
+            rank = 1
+            world_size = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.backend.preferred, Some(Backend::Vulkan));
+        assert_eq!(config.backend.precision, Some(Precision::Bf16));
+        assert_eq!(config.backend.disabled, vec![Backend::OpenCl]);
+        assert_eq!(config.backend.routes.get("layer_norm"), Some(&Backend::Cpu));
+        assert_eq!(config.backend.cpu_affinity, Some(CpuAffinity::Node));
+        assert_eq!(config.backend.deterministic, Some(true));
+        assert_eq!(config.backend.validate_shapes, Some(false));
+        assert!(!config.dispatcher(Workload::Light).validates());
+        assert_eq!(config.backend.health_check_every, Some(16));
+        assert_eq!(config.dispatcher(Workload::Light).health_check(), Some(16));
+        let coalescing = config.dispatcher(Workload::Light).coalescing().unwrap();
+        assert_eq!(coalescing.window, Duration::from_micros(500));
+        assert_eq!(coalescing.max_batch, 4);
+        assert_eq!(AurexConfig::default().coalescing(), None);
+        assert_eq!(
+            config.thread_pool(),
+            ThreadPoolConfig {
+                threads: Some(6),
+                pinning: CpuAffinity::Core,
+                cores: CoreSelection::All,
+            }
+        );
+        assert_eq!(
+            AurexConfig::default().thread_pool(),
+            ThreadPoolConfig::default()
+        );
+        assert_eq!(config.memory.has_gpu, Some(true));
+        assert_eq!(config.memory.gpu_mem, Some(4096));
+        assert_eq!(config.memory.cpu_mem, None);
+        assert_eq!(config.memory.eviction, Some(Eviction::Clock));
+        assert_eq!(config.memory.spill_dir, Some(PathBuf::from("/var/tmp/spill")));
+        assert_eq!(config.memory.compression, Some(Compression::Lz4));
+        assert_eq!(config.memory.numa_node, Some(1));
+        assert_eq!(
+            config.memory.watermarks.get("gpu"),
+            Some(&Watermarks {
+                high: 0.9,
+                low: 0.75
+            })
+        );
+        let caps = config.effort_caps();
+        assert_eq!(caps.max_steps, Some(8));
+        assert_eq!(caps.max_wall_time, Some(Duration::from_millis(250)));
+        assert_eq!(config.scheduler.capture_plan, Some(true));
+        assert_eq!(config.scheduler.warmup, None);
+        assert_eq!(config.scheduler.prefill_chunk, Some(64));
+        assert_eq!(config.scheduler.max_batch, Some(16));
+        assert_eq!(config.scheduler.batch_probe_steps, Some(2));
+        assert_eq!(config.admission.max_in_flight, Some(4));
+        assert_eq!(config.admission.latency_slo_ms, Some(20));
+        assert_eq!(config.admission.max_queued, None);
+        assert_eq!(config.auth.requests_per_second, Some(2.0));
+        assert_eq!(config.auth.keys[0].name, "ci");
+        assert_eq!(config.auth.keys[0].burst, Some(5));
+        assert_eq!(config.auth.keys[0].token_quota, Some(100000));
+        assert_eq!(config.response_cache.max_bytes, Some(65536));
+        assert_eq!(config.response_cache.max_entries, None);
+        assert_eq!(config.sessions.context_window, Some(2048));
+        assert_eq!(config.sessions.summary_tokens, Some(64));
+        assert_eq!(config.sessions.keep_messages, None);
+        assert_eq!(config.power.max_joules_per_token, Some(0.25));
+        assert_eq!(config.power.low_precision, Some(Precision::Int8));
+        assert_eq!(config.thermal.max_celsius, Some(80.0));
+        assert_eq!(config.thermal.idle_ms, Some(50));
+        assert_eq!(config.thermal.critical_celsius, None);
+        assert_eq!(config.plugins.paths, vec![PathBuf::from("libfpga_npu.so")]);
+        let options = config.plugins.options("fpga_npu").unwrap();
+        assert_eq!(options["ops"].as_array().unwrap().len(), 1);
+        assert!(config.plugins.options("gpu").is_none());
+        assert_eq!(config.distributed.rank, Some(1));
+        assert_eq!(config.distributed.world_size, Some(2));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(AurexConfig::from_toml_str("[backend]\nprefered = \"cpu\"").is_err());
+        assert!(AurexConfig::from_toml_str("[backend]\npreferred = \"tpu\"").is_err());
+    }
+
+    #[test]
+    fn rejects_api_keys_sharing_a_name_or_key() {
+        let keys = |a: &str, b: &str| {
+            AurexConfig::from_toml_str(&format!("[auth]\nkeys = [{a}, {b}]"))
+                .map_err(|err| err.to_string())
+        };
+        let ci = r#"{ name = "ci", key = "sk-ci" }"#;
+        assert!(keys(ci, r#"{ name = "batch", key = "sk-batch" }"#).is_ok());
+        let err = keys(ci, r#"{ name = "ci", key = "sk-other" }"#).unwrap_err();
+        assert!(err.contains("'ci' is used twice"), "{err}");
+        let err = keys(ci, r#"{ name = "batch", key = "sk-ci" }"#).unwrap_err();
+        assert!(err.contains("key of 'batch'"), "{err}");
+    }
+}
@@ -0,0 +1,677 @@
+//! Multi-node execution.
+//!
+//! Workers on different machines exchange buffers through a [`Transport`].
+//! [`TcpTransport`] is the built-in implementation: rank 0 acts as the
+//! coordinator and every other rank connects to it during rendezvous,
+//! announcing its rank, the expected world size and the protocol version.
+//! The coordinator rejects duplicate ranks and mismatching world sizes before
+//! any data is exchanged, and drops connections that do not open with a
+//! well-formed announcement.
+//!
+//! [`Communicator`] builds the collective ops used by data and tensor parallel
+//! inference on top of a transport.  Collectives are routed through rank 0,
+//! so a transport only needs links between rank 0 and every other rank.  All
+//! ranks must issue the same collectives in the same order.
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), aurex_runtime::distributed::DistributedError> {
+//! use aurex_runtime::distributed::{Communicator, TcpTransport};
+//!
+//! let transport = TcpTransport::connect("10.0.0.1:29500", 1, 2).await?;
+//! let comm = Communicator::new(transport);
+//! let mut grads = vec![0.5f32; 1024];
+//! comm.all_reduce_sum(&mut grads).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::Mutex;
+
+use crate::config::DistributedConfig;
+
+/// Version of the rendezvous and framing protocol.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long a worker keeps retrying to reach the coordinator.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest frame accepted from a peer.
+pub const MAX_FRAME_BYTES: u64 = 1 << 32;
+
+const MAGIC: [u8; 4] = *b"AURX";
+const HELLO_BYTES: usize = 16;
+const CONNECT_RETRY: Duration = Duration::from_millis(100);
+/// How long the coordinator waits for a new connection to announce itself.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error raised while setting up or using a transport.
+#[derive(Debug)]
+pub enum DistributedError {
+    Io(std::io::Error),
+    /// Rendezvous failed, e.g. a duplicate rank or a version mismatch.
+    Handshake(String),
+    /// A peer sent a malformed or unexpected message.
+    Protocol(String),
+}
+
+impl fmt::Display for DistributedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DistributedError::Io(err) => write!(f, "transport I/O error: {err}"),
+            DistributedError::Handshake(reason) => write!(f, "rendezvous failed: {reason}"),
+            DistributedError::Protocol(reason) => write!(f, "protocol error: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for DistributedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DistributedError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DistributedError {
+    fn from(err: std::io::Error) -> Self {
+        DistributedError::Io(err)
+    }
+}
+
+/// Point-to-point byte transport between the ranks of a job.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Rank of this process, `0..world_size`.
+    fn rank(&self) -> usize;
+
+    /// Number of processes in the job.
+    fn world_size(&self) -> usize;
+
+    /// Send one message to `peer`.
+    async fn send(&self, peer: usize, payload: &[u8]) -> Result<(), DistributedError>;
+
+    /// Receive the next message sent by `peer`.
+    async fn recv(&self, peer: usize) -> Result<Vec<u8>, DistributedError>;
+}
+
+/// Connection to one peer.
+struct Link {
+    reader: Mutex<OwnedReadHalf>,
+    writer: Mutex<OwnedWriteHalf>,
+}
+
+impl Link {
+    fn new(reader: OwnedReadHalf, writer: OwnedWriteHalf) -> Self {
+        Self {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+/// Length-prefixed messages over TCP in a star around rank 0.
+pub struct TcpTransport {
+    rank: usize,
+    world_size: usize,
+    links: Vec<Option<Link>>,
+}
+
+impl TcpTransport {
+    /// Transport of a job with a single process.
+    pub fn single() -> Self {
+        Self {
+            rank: 0,
+            world_size: 1,
+            links: vec![None],
+        }
+    }
+
+    /// Join the job coordinated at `addr` as `rank`.  Connection attempts are
+    /// retried for [`CONNECT_TIMEOUT`] so workers may start before rank 0.
+    pub async fn connect(
+        addr: &str,
+        rank: usize,
+        world_size: usize,
+    ) -> Result<Self, DistributedError> {
+        if rank == 0 || rank >= world_size {
+            return Err(DistributedError::Handshake(format!(
+                "worker rank {rank} outside 1..{world_size}"
+            )));
+        }
+        let deadline = tokio::time::Instant::now() + CONNECT_TIMEOUT;
+        let stream = loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(err) if tokio::time::Instant::now() < deadline => {
+                    tracing::trace!(addr, %err, "coordinator not reachable yet");
+                    tokio::time::sleep(CONNECT_RETRY).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        };
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            rank: rank as u32,
+            world_size: world_size as u32,
+        };
+        write_frame(&mut writer, &hello.encode()).await?;
+        let reply = read_frame(&mut reader).await?;
+        match reply.split_first() {
+            Some((0, _)) => {}
+            Some((_, reason)) => {
+                return Err(DistributedError::Handshake(
+                    String::from_utf8_lossy(reason).into_owned(),
+                ))
+            }
+            None => return Err(DistributedError::Protocol("empty handshake reply".into())),
+        }
+        tracing::info!(addr, rank, world_size, "joined distributed job");
+
+        let mut links: Vec<Option<Link>> = (0..world_size).map(|_| None).collect();
+        links[0] = Some(Link::new(reader, writer));
+        Ok(Self {
+            rank,
+            world_size,
+            links,
+        })
+    }
+
+    fn link(&self, peer: usize) -> Result<&Link, DistributedError> {
+        self.links
+            .get(peer)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| {
+                DistributedError::Protocol(format!("rank {} has no link to rank {peer}", self.rank))
+            })
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    fn rank(&self) -> usize {
+        self.rank
+    }
+
+    fn world_size(&self) -> usize {
+        self.world_size
+    }
+
+    async fn send(&self, peer: usize, payload: &[u8]) -> Result<(), DistributedError> {
+        let mut writer = self.link(peer)?.writer.lock().await;
+        write_frame(&mut *writer, payload).await
+    }
+
+    async fn recv(&self, peer: usize) -> Result<Vec<u8>, DistributedError> {
+        let mut reader = self.link(peer)?.reader.lock().await;
+        read_frame(&mut *reader).await
+    }
+}
+
+/// Rank 0 side of the TCP rendezvous.
+pub struct Coordinator {
+    listener: TcpListener,
+    world_size: usize,
+}
+
+impl Coordinator {
+    /// Listen on `addr` for the workers of a job of `world_size` ranks.
+    pub async fn bind(
+        addr: impl ToSocketAddrs,
+        world_size: usize,
+    ) -> Result<Self, DistributedError> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            world_size: world_size.max(1),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, DistributedError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Wait until every worker has joined.  Workers announcing a duplicate or
+    /// out of range rank, another world size or another protocol version are
+    /// told why and fail the rendezvous.  Connections that send no valid
+    /// announcement within a few seconds are dropped with a warning and do
+    /// not count towards the job.
+    pub async fn accept(self) -> Result<TcpTransport, DistributedError> {
+        let mut links: Vec<Option<Link>> = (0..self.world_size).map(|_| None).collect();
+        let mut joined = 1;
+        while joined < self.world_size {
+            let (stream, peer) = self.listener.accept().await?;
+            stream.set_nodelay(true)?;
+            let (mut reader, mut writer) = stream.into_split();
+            let hello = match tokio::time::timeout(HELLO_TIMEOUT, read_hello(&mut reader)).await {
+                Ok(Ok(hello)) => hello,
+                Ok(Err(err)) => {
+                    tracing::warn!(%peer, %err, "dropped connection without a valid hello");
+                    continue;
+                }
+                Err(_) => {
+                    tracing::warn!(%peer, "dropped connection that sent no hello");
+                    continue;
+                }
+            };
+            let rank = hello.rank as usize;
+            let rejection = if hello.version != PROTOCOL_VERSION {
+                Some(format!(
+                    "protocol version {} does not match {PROTOCOL_VERSION}",
+                    hello.version
+                ))
+            } else if hello.world_size as usize != self.world_size {
+                Some(format!(
+                    "world size {} does not match {}",
+                    hello.world_size, self.world_size
+                ))
+            } else if rank == 0 || rank >= self.world_size {
+                Some(format!("rank {rank} outside 1..{}", self.world_size))
+            } else if links[rank].is_some() {
+                Some(format!("rank {rank} joined twice"))
+            } else {
+                None
+            };
+            if let Some(reason) = rejection {
+                tracing::warn!(%peer, rank, %reason, "rejected worker");
+                let mut reply = vec![1];
+                reply.extend_from_slice(reason.as_bytes());
+                write_frame(&mut writer, &reply).await?;
+                return Err(DistributedError::Handshake(reason));
+            }
+            write_frame(&mut writer, &[0]).await?;
+            tracing::debug!(%peer, rank, "worker joined");
+            links[rank] = Some(Link::new(reader, writer));
+            joined += 1;
+        }
+        tracing::info!(world_size = self.world_size, "all workers joined");
+        Ok(TcpTransport {
+            rank: 0,
+            world_size: self.world_size,
+            links,
+        })
+    }
+}
+
+/// Rendezvous message sent by a worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Hello {
+    version: u32,
+    rank: u32,
+    world_size: u32,
+}
+
+impl Hello {
+    fn encode(self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        for field in [self.version, self.rank, self.world_size] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        out
+    }
+
+    fn decode(bytes: &[u8; HELLO_BYTES]) -> Result<Self, DistributedError> {
+        if bytes[..4] != MAGIC {
+            return Err(DistributedError::Handshake(
+                "peer is not an aurex worker".into(),
+            ));
+        }
+        let field = |i: usize| u32::from_le_bytes(bytes[4 + i * 4..8 + i * 4].try_into().unwrap());
+        Ok(Self {
+            version: field(0),
+            rank: field(1),
+            world_size: field(2),
+        })
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    payload: &[u8],
+) -> Result<(), DistributedError> {
+    writer.write_u64_le(payload.len() as u64).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read the framed hello of a worker into a fixed buffer, so an unknown
+/// peer cannot make the coordinator allocate the length it claims.
+async fn read_hello<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Hello, DistributedError> {
+    let len = reader.read_u64_le().await?;
+    if len != HELLO_BYTES as u64 {
+        return Err(DistributedError::Handshake(
+            "peer is not an aurex worker".into(),
+        ));
+    }
+    let mut bytes = [0; HELLO_BYTES];
+    reader.read_exact(&mut bytes).await?;
+    Hello::decode(&bytes)
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, DistributedError> {
+    let len = reader.read_u64_le().await?;
+    if len > MAX_FRAME_BYTES {
+        return Err(DistributedError::Protocol(format!(
+            "frame of {len} bytes exceeds the limit"
+        )));
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+/// Brain float 16 value stored as its raw bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bf16(pub u16);
+
+impl Bf16 {
+    /// Round to nearest even, matching [`Precision::Bf16`](crate::Precision).
+    pub fn from_f32(value: f32) -> Self {
+        let bits = value.to_bits();
+        let lsb = (bits >> 16) & 1;
+        Self((bits.wrapping_add(0x7fff + lsb) >> 16) as u16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        f32::from_bits(u32::from(self.0) << 16)
+    }
+}
+
+/// Element type that collectives can exchange and reduce.  Reductions are
+/// accumulated in `f32`.
+pub trait Element: Copy + Send + Sync {
+    /// Encoded size in bytes.
+    const BYTES: usize;
+
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+    fn write_le(self, out: &mut Vec<u8>);
+    fn read_le(bytes: &[u8]) -> Self;
+}
+
+impl Element for f32 {
+    const BYTES: usize = 4;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        f32::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+impl Element for Bf16 {
+    const BYTES: usize = 2;
+
+    fn to_f32(self) -> f32 {
+        Bf16::to_f32(self)
+    }
+
+    fn from_f32(value: f32) -> Self {
+        Bf16::from_f32(value)
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        Bf16(u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+fn encode<T: Element>(data: &[T]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() * T::BYTES);
+    for &value in data {
+        value.write_le(&mut out);
+    }
+    out
+}
+
+fn decode<T: Element>(bytes: &[u8]) -> Result<Vec<T>, DistributedError> {
+    if !bytes.len().is_multiple_of(T::BYTES) {
+        return Err(DistributedError::Protocol(format!(
+            "{} bytes is not a whole number of {}-byte elements",
+            bytes.len(),
+            T::BYTES
+        )));
+    }
+    Ok(bytes.chunks_exact(T::BYTES).map(T::read_le).collect())
+}
+
+/// Collective operations over a [`Transport`].
+#[derive(Clone)]
+pub struct Communicator {
+    transport: Arc<dyn Transport>,
+}
+
+impl Communicator {
+    pub fn new(transport: impl Transport + 'static) -> Self {
+        Self {
+            transport: Arc::new(transport),
+        }
+    }
+
+    /// Set up the transport described by the `[distributed]` settings.  Rank
+    /// 0 binds the coordinator address, other ranks connect to it; a job of
+    /// one process needs no network at all.
+    pub async fn from_config(config: &DistributedConfig) -> Result<Self, DistributedError> {
+        let world_size = config.world_size.unwrap_or(1);
+        if world_size <= 1 {
+            return Ok(Self::new(TcpTransport::single()));
+        }
+        let addr = config.coordinator.as_deref().ok_or_else(|| {
+            DistributedError::Handshake("no coordinator address configured".into())
+        })?;
+        let transport = match config.rank.unwrap_or(0) {
+            0 => Coordinator::bind(addr, world_size).await?.accept().await?,
+            rank => TcpTransport::connect(addr, rank, world_size).await?,
+        };
+        Ok(Self::new(transport))
+    }
+
+    pub fn rank(&self) -> usize {
+        self.transport.rank()
+    }
+
+    pub fn world_size(&self) -> usize {
+        self.transport.world_size()
+    }
+
+    pub fn transport(&self) -> &dyn Transport {
+        self.transport.as_ref()
+    }
+
+    /// Replace `data` on every rank with the contents held by `root`.
+    pub async fn broadcast<T: Element>(
+        &self,
+        data: &mut Vec<T>,
+        root: usize,
+    ) -> Result<(), DistributedError> {
+        let (rank, size) = (self.rank(), self.world_size());
+        if root >= size {
+            return Err(DistributedError::Protocol(format!(
+                "broadcast root {root} outside 0..{size}"
+            )));
+        }
+        if root != 0 {
+            if rank == root {
+                self.transport.send(0, &encode(data)).await?;
+            } else if rank == 0 {
+                *data = decode(&self.transport.recv(root).await?)?;
+            }
+        }
+        if rank == 0 {
+            let bytes = encode(data);
+            for peer in (1..size).filter(|&peer| peer != root) {
+                self.transport.send(peer, &bytes).await?;
+            }
+        } else if rank != root {
+            *data = decode(&self.transport.recv(0).await?)?;
+        }
+        Ok(())
+    }
+
+    /// Sum `data` element-wise across all ranks.  Every rank ends up with the
+    /// same result, accumulated in rank order.
+    pub async fn all_reduce_sum<T: Element>(&self, data: &mut [T]) -> Result<(), DistributedError> {
+        let (rank, size) = (self.rank(), self.world_size());
+        if size == 1 {
+            return Ok(());
+        }
+        if rank == 0 {
+            let mut acc: Vec<f32> = data.iter().map(|v| v.to_f32()).collect();
+            for peer in 1..size {
+                let part: Vec<T> = decode(&self.transport.recv(peer).await?)?;
+                check_len(part.len(), acc.len(), peer)?;
+                for (a, p) in acc.iter_mut().zip(part) {
+                    *a += p.to_f32();
+                }
+            }
+            for (d, a) in data.iter_mut().zip(acc) {
+                *d = T::from_f32(a);
+            }
+            let bytes = encode(data);
+            for peer in 1..size {
+                self.transport.send(peer, &bytes).await?;
+            }
+        } else {
+            self.transport.send(0, &encode(data)).await?;
+            let reduced: Vec<T> = decode(&self.transport.recv(0).await?)?;
+            check_len(reduced.len(), data.len(), 0)?;
+            data.copy_from_slice(&reduced);
+        }
+        Ok(())
+    }
+
+    /// Block until every rank has reached the barrier.
+    pub async fn barrier(&self) -> Result<(), DistributedError> {
+        self.all_reduce_sum::<f32>(&mut []).await
+    }
+}
+
+fn check_len(got: usize, expected: usize, peer: usize) -> Result<(), DistributedError> {
+    if got == expected {
+        Ok(())
+    } else {
+        Err(DistributedError::Protocol(format!(
+            "rank {peer} sent {got} elements, expected {expected}"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn spawn_job(world_size: usize) -> Vec<Communicator> {
+        let coordinator = Coordinator::bind("127.0.0.1:0", world_size).await.unwrap();
+        let addr = coordinator.local_addr().unwrap().to_string();
+        let workers: Vec<_> = (1..world_size)
+            .map(|rank| {
+                let addr = addr.clone();
+                tokio::spawn(async move { TcpTransport::connect(&addr, rank, world_size).await })
+            })
+            .collect();
+        let mut comms = vec![Communicator::new(coordinator.accept().await.unwrap())];
+        for worker in workers {
+            comms.push(Communicator::new(worker.await.unwrap().unwrap()));
+        }
+        comms
+    }
+
+    #[tokio::test]
+    async fn broadcast_and_all_reduce_across_ranks() {
+        let comms = spawn_job(3).await;
+        let tasks: Vec<_> = comms
+            .into_iter()
+            .map(|comm| {
+                tokio::spawn(async move {
+                    let rank = comm.rank();
+                    let mut weights = if rank == 2 { vec![1.5f32, -2.0] } else { Vec::new() };
+                    comm.broadcast(&mut weights, 2).await.unwrap();
+
+                    let mut grads = vec![rank as f32, 1.0, 0.25];
+                    comm.all_reduce_sum(&mut grads).await.unwrap();
+
+                    let mut halves = vec![Bf16::from_f32(0.5 * rank as f32); 4];
+                    comm.all_reduce_sum(&mut halves).await.unwrap();
+                    comm.barrier().await.unwrap();
+                    (weights, grads, halves)
+                })
+            })
+            .collect();
+        for task in tasks {
+            let (weights, grads, halves) = task.await.unwrap();
+            assert_eq!(weights, vec![1.5, -2.0]);
+            assert_eq!(grads, vec![3.0, 3.0, 0.75]);
+            assert!(halves.iter().all(|h| h.to_f32() == 1.5));
+        }
+    }
+
+    #[tokio::test]
+    async fn rendezvous_rejects_mismatched_world_size() {
+        let coordinator = Coordinator::bind("127.0.0.1:0", 2).await.unwrap();
+        let addr = coordinator.local_addr().unwrap().to_string();
+        let worker = tokio::spawn(async move { TcpTransport::connect(&addr, 1, 3).await });
+        assert!(matches!(
+            coordinator.accept().await,
+            Err(DistributedError::Handshake(_))
+        ));
+        assert!(matches!(
+            worker.await.unwrap(),
+            Err(DistributedError::Handshake(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rendezvous_drops_connections_without_a_hello() {
+        let coordinator = Coordinator::bind("127.0.0.1:0", 2).await.unwrap();
+        let addr = coordinator.local_addr().unwrap().to_string();
+        let accepted = tokio::spawn(coordinator.accept());
+
+        // A frame claiming 4 GiB and one with the wrong magic are both dropped
+        // without allocating or failing the job.
+        let mut stray = TcpStream::connect(&addr).await.unwrap();
+        stray.write_u64_le(MAX_FRAME_BYTES).await.unwrap();
+        let mut stray = TcpStream::connect(&addr).await.unwrap();
+        stray.write_u64_le(HELLO_BYTES as u64).await.unwrap();
+        stray.write_all(&[0xff; HELLO_BYTES]).await.unwrap();
+        assert_eq!(stray.read(&mut [0; 8]).await.unwrap(), 0);
+
+        let worker = TcpTransport::connect(&addr, 1, 2).await.unwrap();
+        let coordinator = accepted.await.unwrap().unwrap();
+        coordinator.send(1, b"ping").await.unwrap();
+        assert_eq!(worker.recv(0).await.unwrap(), b"ping");
+    }
+
+    #[test]
+    fn bf16_rounds_to_nearest_even() {
+        assert_eq!(Bf16::from_f32(1.0).to_f32(), 1.0);
+        assert_eq!(Bf16::from_f32(1.0 + f32::EPSILON).to_f32(), 1.0);
+        assert_eq!(
+            Bf16::from_f32(1.2345).to_f32(),
+            crate::Precision::Bf16.round(&[1.2345])[0]
+        );
+    }
+}
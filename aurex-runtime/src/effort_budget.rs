@@ -0,0 +1,205 @@
+//! Effort accounting for adaptive compute.
+//!
+//! The runtime records a [`StepCost`] for every step it executes and
+//! accumulates them in an [`EffortBudget`].  Effort evaluators receive the
+//! budget so they can spend more compute on hard tokens while the runtime
+//! enforces the configured [`EffortCaps`].
+
+use std::time::Duration;
+
+/// Energy proxy used when no measured value is available: roughly 10 pJ per
+/// floating point operation, in the range of current accelerators.
+pub const DEFAULT_JOULES_PER_FLOP: f64 = 1e-11;
+
+/// Estimated cost of a single runtime step.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StepCost {
+    /// Floating point operations charged during the step.
+    pub flops: u64,
+    /// Wall-clock time spent in the step.
+    pub wall_time: Duration,
+    /// Energy proxy in joules.
+    pub energy: f64,
+}
+
+impl StepCost {
+    /// Build a cost record deriving the energy proxy from the FLOP count.
+    pub fn estimate(flops: u64, wall_time: Duration) -> Self {
+        Self {
+            flops,
+            wall_time,
+            energy: flops as f64 * DEFAULT_JOULES_PER_FLOP,
+        }
+    }
+}
+
+/// Upper bounds enforced by the runtime.  `None` leaves a dimension
+/// unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EffortCaps {
+    pub max_steps: Option<u64>,
+    pub max_flops: Option<u64>,
+    pub max_wall_time: Option<Duration>,
+    pub max_energy: Option<f64>,
+    /// Candidate branches explored at once by branching generation.
+    pub max_branches: Option<usize>,
+}
+
+/// Running totals of the effort spent so far.
+#[derive(Debug, Clone, Default)]
+pub struct EffortBudget {
+    caps: EffortCaps,
+    spent: StepCost,
+    last: StepCost,
+    steps: u64,
+}
+
+impl EffortBudget {
+    /// Create an empty budget with the given caps.
+    pub fn new(caps: EffortCaps) -> Self {
+        Self {
+            caps,
+            ..Self::default()
+        }
+    }
+
+    /// Caps enforced for this budget.
+    pub fn caps(&self) -> EffortCaps {
+        self.caps
+    }
+
+    /// Replace the caps while keeping the accumulated totals.
+    pub fn set_caps(&mut self, caps: EffortCaps) {
+        self.caps = caps;
+    }
+
+    /// Add the cost of one completed step.
+    pub fn record(&mut self, cost: StepCost) {
+        self.spent.flops += cost.flops;
+        self.spent.wall_time += cost.wall_time;
+        self.spent.energy += cost.energy;
+        self.last = cost;
+        self.steps += 1;
+    }
+
+    /// Totals accumulated over all recorded steps.
+    pub fn spent(&self) -> StepCost {
+        self.spent
+    }
+
+    /// Cost of the most recently recorded step.
+    pub fn last_step(&self) -> StepCost {
+        self.last
+    }
+
+    /// Number of recorded steps.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Largest fraction of any capped dimension consumed so far, in `[0, ∞)`.
+    /// Returns `0.0` when no caps are configured.
+    pub fn fraction_used(&self) -> f64 {
+        let mut used: f64 = 0.0;
+        if let Some(max) = self.caps.max_steps {
+            used = used.max(ratio(self.steps as f64, max as f64));
+        }
+        if let Some(max) = self.caps.max_flops {
+            used = used.max(ratio(self.spent.flops as f64, max as f64));
+        }
+        if let Some(max) = self.caps.max_wall_time {
+            used = used.max(ratio(
+                self.spent.wall_time.as_secs_f64(),
+                max.as_secs_f64(),
+            ));
+        }
+        if let Some(max) = self.caps.max_energy {
+            used = used.max(ratio(self.spent.energy, max));
+        }
+        used
+    }
+
+    /// Number of branches that may be explored when `requested` are wanted:
+    /// at most [`EffortCaps::max_branches`], shrinking with the fraction of
+    /// the budget already used, and a single branch once it is exhausted.
+    pub fn branch_limit(&self, requested: usize) -> usize {
+        let capped = self
+            .caps
+            .max_branches
+            .map_or(requested, |max| requested.min(max));
+        let remaining = (1.0 - self.fraction_used()).max(0.0);
+        ((capped as f64 * remaining).ceil() as usize).clamp(1, capped.max(1))
+    }
+
+    /// Whether any cap has been reached.
+    pub fn is_exhausted(&self) -> bool {
+        self.fraction_used() >= 1.0
+    }
+
+    /// Clear the accumulated totals, keeping the caps.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.caps);
+    }
+}
+
+fn ratio(used: f64, max: f64) -> f64 {
+    if max <= 0.0 {
+        f64::INFINITY
+    } else {
+        used / max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_limit_follows_caps_and_spent_effort() {
+        let mut budget = EffortBudget::new(EffortCaps {
+            max_steps: Some(4),
+            max_branches: Some(6),
+            ..EffortCaps::default()
+        });
+        assert_eq!(budget.branch_limit(8), 6);
+        assert_eq!(budget.branch_limit(3), 3);
+        budget.record(StepCost::default());
+        budget.record(StepCost::default());
+        assert_eq!(budget.branch_limit(8), 3);
+        budget.record(StepCost::default());
+        budget.record(StepCost::default());
+        assert_eq!(budget.branch_limit(8), 1);
+        assert_eq!(EffortBudget::default().branch_limit(5), 5);
+    }
+
+    #[test]
+    fn accumulates_and_detects_exhaustion() {
+        let mut budget = EffortBudget::new(EffortCaps {
+            max_flops: Some(1_000),
+            ..EffortCaps::default()
+        });
+        budget.record(StepCost::estimate(400, Duration::from_millis(1)));
+        budget.record(StepCost::estimate(300, Duration::from_millis(2)));
+        assert_eq!(budget.steps(), 2);
+        assert_eq!(budget.spent().flops, 700);
+        assert_eq!(budget.spent().wall_time, Duration::from_millis(3));
+        assert_eq!(budget.last_step().flops, 300);
+        assert!((budget.fraction_used() - 0.7).abs() < 1e-9);
+        assert!(!budget.is_exhausted());
+
+        budget.record(StepCost::estimate(300, Duration::ZERO));
+        assert!(budget.is_exhausted());
+
+        budget.reset();
+        assert_eq!(budget.steps(), 0);
+        assert!(!budget.is_exhausted());
+    }
+
+    #[test]
+    fn uncapped_budget_never_exhausts() {
+        let mut budget = EffortBudget::default();
+        budget.record(StepCost::estimate(u32::MAX as u64, Duration::from_secs(5)));
+        assert_eq!(budget.fraction_used(), 0.0);
+        assert!(!budget.is_exhausted());
+    }
+}
@@ -1,8 +1,45 @@
 //! AUREX runtime orchestrates agent execution and dispatches operations to the appropriate backend.
 use async_trait::async_trait;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::Instrument;
 
+pub mod admission;
+pub mod auth;
+pub mod batch_tuner;
+pub mod config;
+pub mod distributed;
+pub mod effort_budget;
+pub mod options;
 pub mod plugin;
-pub use plugin::{BackendPlugin, PluginRegistry};
+pub mod power;
+pub mod response_cache;
+pub mod scheduler;
+pub mod session;
+pub mod telemetry;
+pub mod thermal;
+pub mod usage;
+pub mod warmup;
+pub use aurex_backend::Precision;
+pub use admission::{AdmissionController, AdmissionError, AdmissionPermit, Overload};
+pub use auth::{AuthError, Authenticator};
+pub use batch_tuner::{BatchDecision, BatchTuner};
+pub use config::AurexConfig;
+pub use distributed::{Communicator, Transport};
+pub use effort_budget::{EffortBudget, EffortCaps, StepCost};
+pub use options::{GenerationOptions, Priority, SamplerOptions};
+pub use plugin::{
+    BackendPlugin, PluginError, PluginManifest, PluginOps, PluginRegistry, TensorOpRequest,
+    TensorOpResponse,
+};
+pub use power::{PowerGovernor, Throttle};
+pub use response_cache::{CacheKey, CacheStats, ResponseCache};
+pub use scheduler::Scheduler;
+pub use session::{Message, Role, Session, SessionError, SessionId, SessionStore};
+pub use thermal::{ThermalMonitor, ThermalState};
+pub use usage::{QuotaExceeded, Usage, UsageTracker};
+pub use warmup::{Warmup, WarmupReport};
 
 /// Events emitted by the runtime to drive higher level state machines.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -23,31 +60,71 @@ pub enum RuntimeEvent {
     Error,
 }
 
-/// Numeric precision used by the runtime for model execution.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Precision {
-    F32,
-    Bf16,
-    Int8,
-    Int4,
+/// Component notified whenever the runtime switches numeric precision, e.g.
+/// the backend dispatcher or the loaded model weights.
+pub trait PrecisionObserver: Send + Sync {
+    fn on_precision_change(&self, precision: Precision);
+}
+
+impl PrecisionObserver for Mutex<aurex_backend::Dispatcher> {
+    fn on_precision_change(&self, precision: Precision) {
+        self.lock().unwrap().set_precision(precision);
+    }
 }
 
 pub struct Runtime {
-    precision: Precision,
+    precision: Mutex<Precision>,
+    precision_observers: Vec<Arc<dyn PrecisionObserver>>,
+    budget: Mutex<EffortBudget>,
+    pending_flops: AtomicU64,
+    capture_plan: bool,
+    power: Option<Arc<PowerGovernor>>,
+    /// Precision to return to once the power governor stops throttling.
+    power_restore: Mutex<Option<Precision>>,
 }
 
 impl Default for Runtime {
     fn default() -> Self {
         Self {
-            precision: Precision::F32,
+            precision: Mutex::new(Precision::F32),
+            precision_observers: Vec::new(),
+            budget: Mutex::new(EffortBudget::default()),
+            pending_flops: AtomicU64::new(0),
+            capture_plan: false,
+            power: None,
+            power_restore: Mutex::new(None),
         }
     }
 }
 
 impl Runtime {
+    /// Create a runtime using the precision, effort caps, plan capture
+    /// setting and power budget of `config`.  The CPU backend's thread pool
+    /// is restarted when `config` shapes it differently.
+    pub fn from_config(config: &AurexConfig) -> Self {
+        if config.cpu != config::CpuConfig::default() {
+            aurex_backend::CpuThreadPool::configure_global(config.thread_pool());
+        }
+        Self {
+            precision: Mutex::new(config.backend.precision.unwrap_or(Precision::F32)),
+            budget: Mutex::new(EffortBudget::new(config.effort_caps())),
+            capture_plan: config.scheduler.capture_plan == Some(true),
+            power: PowerGovernor::from_config(&config.power).map(Arc::new),
+            ..Self::default()
+        }
+    }
+
     /// Perform a single runtime step, invoking the evaluation, regulation,
     /// reflexion, and hypothesis components in sequence. If the effort
     /// evaluator rejects the step, an [`RuntimeEvent::Error`] is returned.
+    /// Precision changes requested by the regulator are applied before the
+    /// remaining components run.
+    ///
+    /// The evaluator sees the current [`EffortBudget`]; once any of its caps
+    /// is reached the step is refused with [`RuntimeEvent::Error`].  Completed
+    /// steps are charged with their wall time and the FLOPs reported through
+    /// [`Runtime::charge_flops`], and with the energy measured by the power
+    /// governor if one is set.
     pub async fn step<Ev, Cf, Rx, Hy>(
         &self,
         event: RuntimeEvent,
@@ -62,15 +139,102 @@ impl Runtime {
         Rx: ReflexionLoop + Send + Sync,
         Hy: HypothesisManager + Send + Sync,
     {
-        println!("runtime step: {:?}", event);
-        if !evaluator.evaluate(&event).await {
+        self.step_with_options(
+            event,
+            &GenerationOptions::default(),
+            evaluator,
+            regulator,
+            reflexion,
+            hypothesis,
+        )
+        .await
+    }
+
+    /// Perform a [`step`](Runtime::step) on behalf of a request made with
+    /// `options`.  The evaluator and regulator see the options, and the
+    /// request's precision is switched to before regulating unless the power
+    /// governor is throttling.
+    pub async fn step_with_options<Ev, Cf, Rx, Hy>(
+        &self,
+        event: RuntimeEvent,
+        options: &GenerationOptions,
+        evaluator: &Ev,
+        regulator: &Cf,
+        reflexion: &Rx,
+        hypothesis: &Hy,
+    ) -> RuntimeEvent
+    where
+        Ev: EffortEvaluator + Send + Sync,
+        Cf: ConfidenceRegulator + Send + Sync,
+        Rx: ReflexionLoop + Send + Sync,
+        Hy: HypothesisManager + Send + Sync,
+    {
+        let span = tracing::info_span!(
+            "runtime_step",
+            ?event,
+            priority = ?options.priority,
+            next = tracing::field::Empty
+        );
+        self.step_inner(event, options, evaluator, regulator, reflexion, hypothesis)
+            .instrument(span)
+            .await
+    }
+
+    async fn step_inner<Ev, Cf, Rx, Hy>(
+        &self,
+        event: RuntimeEvent,
+        options: &GenerationOptions,
+        evaluator: &Ev,
+        regulator: &Cf,
+        reflexion: &Rx,
+        hypothesis: &Hy,
+    ) -> RuntimeEvent
+    where
+        Ev: EffortEvaluator + Send + Sync,
+        Cf: ConfidenceRegulator + Send + Sync,
+        Rx: ReflexionLoop + Send + Sync,
+        Hy: HypothesisManager + Send + Sync,
+    {
+        let started = Instant::now();
+        let budget = self.effort_budget();
+        if budget.is_exhausted() {
+            tracing::warn!(fraction_used = budget.fraction_used(), "effort budget exhausted");
+            return RuntimeEvent::Error;
+        }
+        let accepted = evaluator
+            .evaluate_with_options(&event, &budget, options)
+            .instrument(tracing::debug_span!("evaluate"))
+            .await;
+        if !accepted {
+            tracing::debug!("step rejected by effort evaluator");
             return RuntimeEvent::Error;
         }
 
-        let event = regulator.regulate(&event).await;
-        let event = reflexion.reflect(&event).await;
-        let event = hypothesis.manage(&event).await;
-        match event {
+        let event = async {
+            let throttled = self.power.as_ref().is_some_and(|g| g.precision().is_some());
+            if let (Some(precision), false) = (options.precision, throttled) {
+                self.change_precision(precision);
+            }
+            let event = regulator.regulate_with_options(&event, options).await;
+            if let Some(precision) = regulator
+                .requested_precision_with_options(&event, options)
+                .await
+            {
+                self.change_precision(precision);
+            }
+            event
+        }
+        .instrument(tracing::debug_span!("regulate"))
+        .await;
+        let event = reflexion
+            .reflect(&event)
+            .instrument(tracing::debug_span!("reflect"))
+            .await;
+        let event = hypothesis
+            .manage(&event)
+            .instrument(tracing::debug_span!("manage"))
+            .await;
+        let next = match event {
             RuntimeEvent::TokenFetched { cache_hit } => {
                 if cache_hit {
                     RuntimeEvent::AttentionComputed
@@ -83,18 +247,149 @@ impl Runtime {
             RuntimeEvent::TokenEmitted => RuntimeEvent::TokenFetched { cache_hit: false },
             RuntimeEvent::Rollback => RuntimeEvent::TokenFetched { cache_hit: false },
             RuntimeEvent::Error => RuntimeEvent::Error,
+        };
+
+        let metrics = aurex_utils::metrics::Metrics::global();
+        if let RuntimeEvent::TokenFetched { cache_hit } = event {
+            metrics.record_cache_lookup(cache_hit);
+        }
+        if next == RuntimeEvent::TokenEmitted {
+            metrics.record_tokens(1);
+        }
+        metrics.observe_latency(started.elapsed());
+
+        let flops = self.pending_flops.swap(0, Ordering::SeqCst);
+        let mut cost = StepCost::estimate(flops, started.elapsed());
+        if let Some(governor) = &self.power {
+            let sample = governor.observe(u64::from(next == RuntimeEvent::TokenEmitted));
+            if let Some(joules) = sample.joules {
+                cost.energy = joules;
+            }
+            if sample.changed {
+                self.follow_power_precision(governor.precision());
+            }
+        }
+        self.budget.lock().unwrap().record(cost);
+        tracing::Span::current().record("next", tracing::field::debug(&next));
+        tracing::trace!(flops, elapsed_us = started.elapsed().as_micros() as u64, "step completed");
+        next
+    }
+
+    /// Charge `flops` to the step currently in progress.  Kernels and model
+    /// code call this so effort policies see realistic per-token costs.
+    pub fn charge_flops(&self, flops: u64) {
+        self.pending_flops.fetch_add(flops, Ordering::SeqCst);
+    }
+
+    /// Snapshot of the effort spent so far.
+    pub fn effort_budget(&self) -> EffortBudget {
+        self.budget.lock().unwrap().clone()
+    }
+
+    /// Configure the caps enforced on the effort budget.
+    pub fn set_effort_caps(&mut self, caps: EffortCaps) {
+        self.budget.lock().unwrap().set_caps(caps);
+    }
+
+    /// Clear the accumulated effort, e.g. at the start of a new request.
+    pub fn reset_effort(&self) {
+        self.budget.lock().unwrap().reset();
+    }
+
+    /// Warm up `model` before it serves traffic: run its kernel shapes
+    /// through its dispatcher, then the model itself on a representative
+    /// input, and capture the resulting execution plan if enabled with
+    /// [`Runtime::set_plan_capture`].  See [`warmup`].
+    pub fn warmup<M: Warmup + ?Sized>(&self, model: &M) -> WarmupReport {
+        let span = tracing::info_span!("warmup", captured = self.capture_plan);
+        let _entered = span.enter();
+        let started = Instant::now();
+        let dispatcher = model.dispatcher();
+        let plan = dispatcher.warmup(&model.kernel_shapes());
+        let model_started = Instant::now();
+        model.run_representative();
+        let model_time = model_started.elapsed();
+        if self.capture_plan {
+            dispatcher.capture(plan.clone());
+        }
+        let report = WarmupReport {
+            plan,
+            captured: self.capture_plan,
+            model_time,
+            elapsed: started.elapsed(),
+        };
+        tracing::info!(
+            ops = report.plan.ops.len(),
+            cold_us = report.plan.cold_time().as_micros() as u64,
+            warm_us = report.plan.warm_time().as_micros() as u64,
+            elapsed_ms = report.elapsed.as_millis() as u64,
+            "warmup finished"
+        );
+        report
+    }
+
+    /// Whether [`Runtime::warmup`] pins ops to the backends they were warmed
+    /// up on.
+    pub fn set_plan_capture(&mut self, capture: bool) {
+        self.capture_plan = capture;
+    }
+
+    /// Enforce a power budget with `governor`, see [`power`].
+    pub fn set_power_governor(&mut self, governor: Arc<PowerGovernor>) {
+        self.power = Some(governor);
+    }
+
+    pub fn power_governor(&self) -> Option<&Arc<PowerGovernor>> {
+        self.power.as_ref()
+    }
+
+    /// Switch to the precision requested by the power governor, or back to
+    /// the precision in use before it started throttling.
+    fn follow_power_precision(&self, precision: Option<Precision>) {
+        let mut restore = self.power_restore.lock().unwrap();
+        match precision {
+            Some(precision) => {
+                restore.get_or_insert(self.precision());
+                self.change_precision(precision);
+            }
+            None => {
+                if let Some(previous) = restore.take() {
+                    self.change_precision(previous);
+                }
+            }
         }
     }
 
     /// Update the runtime's numeric precision. This allows dynamic precision
     /// scaling based on model or system requirements.
     pub fn set_precision(&mut self, precision: Precision) {
-        self.precision = precision;
+        self.change_precision(precision);
     }
 
     /// Return the currently configured numeric precision.
     pub fn precision(&self) -> Precision {
-        self.precision
+        *self.precision.lock().unwrap()
+    }
+
+    /// Register a component that must follow precision changes.
+    pub fn add_precision_observer(&mut self, observer: Arc<dyn PrecisionObserver>) {
+        self.precision_observers.push(observer);
+    }
+
+    /// Switch to `precision` and notify observers if it differs from the
+    /// current setting.
+    fn change_precision(&self, precision: Precision) {
+        {
+            let mut current = self.precision.lock().unwrap();
+            if *current == precision {
+                return;
+            }
+            tracing::info!(from = ?*current, to = ?precision, "precision changed");
+            *current = precision;
+        }
+        for observer in &self.precision_observers {
+            observer.on_precision_change(precision);
+        }
     }
 }
 
@@ -104,22 +399,69 @@ pub trait Executor {
 }
 
 pub mod effort_evaluator {
-    use super::RuntimeEvent;
+    use super::{EffortBudget, GenerationOptions, RuntimeEvent};
     use async_trait::async_trait;
 
     #[async_trait]
     pub trait EffortEvaluator {
         async fn evaluate(&self, event: &RuntimeEvent) -> bool;
+
+        /// Budget-aware evaluation used by the runtime.  The default ignores
+        /// the budget; policies override it to spend more effort on hard
+        /// tokens while staying under the caps.
+        async fn evaluate_with_budget(&self, event: &RuntimeEvent, _budget: &EffortBudget) -> bool {
+            self.evaluate(event).await
+        }
+
+        /// Evaluation of a step of a request made with `options`, e.g. to
+        /// spend more effort on high priority requests.  The default ignores
+        /// the options.
+        async fn evaluate_with_options(
+            &self,
+            event: &RuntimeEvent,
+            budget: &EffortBudget,
+            _options: &GenerationOptions,
+        ) -> bool {
+            self.evaluate_with_budget(event, budget).await
+        }
     }
 }
 
 pub mod confidence_regulator {
-    use super::RuntimeEvent;
+    use super::{GenerationOptions, Precision, RuntimeEvent};
     use async_trait::async_trait;
 
     #[async_trait]
     pub trait ConfidenceRegulator {
         async fn regulate(&self, event: &RuntimeEvent) -> RuntimeEvent;
+
+        /// Precision the runtime should switch to after regulating `event`,
+        /// e.g. back to `F32` when confidence drops.  `None` keeps the
+        /// current precision.
+        async fn requested_precision(&self, _event: &RuntimeEvent) -> Option<Precision> {
+            None
+        }
+
+        /// [`regulate`](Self::regulate) for a step of a request made with
+        /// `options`.
+        async fn regulate_with_options(
+            &self,
+            event: &RuntimeEvent,
+            _options: &GenerationOptions,
+        ) -> RuntimeEvent {
+            self.regulate(event).await
+        }
+
+        /// Precision to switch to after regulating a step of a request made
+        /// with `options`, which has already been switched to the request's
+        /// own precision.
+        async fn requested_precision_with_options(
+            &self,
+            event: &RuntimeEvent,
+            _options: &GenerationOptions,
+        ) -> Option<Precision> {
+            self.requested_precision(event).await
+        }
     }
 }
 
@@ -209,6 +551,17 @@ mod tests {
         }
     }
 
+    struct UpgradeRegulator;
+    #[async_trait]
+    impl ConfidenceRegulator for UpgradeRegulator {
+        async fn regulate(&self, event: &RuntimeEvent) -> RuntimeEvent {
+            event.clone()
+        }
+        async fn requested_precision(&self, _event: &RuntimeEvent) -> Option<Precision> {
+            Some(Precision::F32)
+        }
+    }
+
     struct FetchManager;
     #[async_trait]
     impl HypothesisManager for FetchManager {
@@ -258,4 +611,191 @@ mod tests {
             .await;
         assert_eq!(result, RuntimeEvent::AttentionComputed);
     }
+
+    #[tokio::test]
+    async fn regulator_precision_request_propagates() {
+        let mut runtime = Runtime::default();
+        let dispatcher = Arc::new(Mutex::new(aurex_backend::Dispatcher::new(
+            Some(aurex_backend::Backend::Cpu),
+            aurex_backend::Workload::Light,
+        )));
+        runtime.add_precision_observer(dispatcher.clone());
+        runtime.set_precision(Precision::Int8);
+        assert_eq!(dispatcher.lock().unwrap().precision(), Precision::Int8);
+
+        let event = RuntimeEvent::CacheUpdated;
+        runtime
+            .step(event, &AcceptEvaluator, &UpgradeRegulator, &Reflector, &Manager)
+            .await;
+        assert_eq!(runtime.precision(), Precision::F32);
+        assert_eq!(dispatcher.lock().unwrap().precision(), Precision::F32);
+    }
+
+    struct PriorityEvaluator;
+    #[async_trait]
+    impl EffortEvaluator for PriorityEvaluator {
+        async fn evaluate(&self, _event: &RuntimeEvent) -> bool {
+            true
+        }
+        async fn evaluate_with_options(
+            &self,
+            _event: &RuntimeEvent,
+            budget: &EffortBudget,
+            options: &GenerationOptions,
+        ) -> bool {
+            options.priority > Priority::Low || budget.spent().flops < 100
+        }
+    }
+
+    #[tokio::test]
+    async fn steps_follow_the_options_of_their_request() {
+        let runtime = Runtime::default();
+        let event = RuntimeEvent::CacheUpdated;
+        let bf16 = GenerationOptions::default().with_precision(Precision::Bf16);
+        let next = runtime
+            .step_with_options(
+                event.clone(),
+                &bf16,
+                &AcceptEvaluator,
+                &EchoRegulator,
+                &Reflector,
+                &Manager,
+            )
+            .await;
+        assert_eq!(next, RuntimeEvent::AttentionComputed);
+        assert_eq!(runtime.precision(), Precision::Bf16);
+
+        // The regulator overrides the request's precision.
+        runtime
+            .step_with_options(
+                event.clone(),
+                &bf16,
+                &AcceptEvaluator,
+                &UpgradeRegulator,
+                &Reflector,
+                &Manager,
+            )
+            .await;
+        assert_eq!(runtime.precision(), Precision::F32);
+
+        runtime.charge_flops(100);
+        runtime
+            .step(event.clone(), &AcceptEvaluator, &EchoRegulator, &Reflector, &Manager)
+            .await;
+        let low = GenerationOptions::default().with_priority(Priority::Low);
+        let next = runtime
+            .step_with_options(
+                event.clone(),
+                &low,
+                &PriorityEvaluator,
+                &EchoRegulator,
+                &Reflector,
+                &Manager,
+            )
+            .await;
+        assert_eq!(next, RuntimeEvent::Error);
+        let next = runtime
+            .step(event, &PriorityEvaluator, &EchoRegulator, &Reflector, &Manager)
+            .await;
+        assert_eq!(next, RuntimeEvent::AttentionComputed);
+    }
+
+    struct BudgetEvaluator;
+    #[async_trait]
+    impl EffortEvaluator for BudgetEvaluator {
+        async fn evaluate(&self, _event: &RuntimeEvent) -> bool {
+            true
+        }
+        async fn evaluate_with_budget(&self, _event: &RuntimeEvent, budget: &EffortBudget) -> bool {
+            budget.spent().flops < 150
+        }
+    }
+
+    #[tokio::test]
+    async fn effort_budget_is_charged_and_enforced() {
+        let mut runtime = Runtime::default();
+        runtime.set_effort_caps(EffortCaps {
+            max_steps: Some(3),
+            ..EffortCaps::default()
+        });
+
+        runtime.charge_flops(100);
+        let event = RuntimeEvent::CacheUpdated;
+        let next = runtime
+            .step(event.clone(), &BudgetEvaluator, &EchoRegulator, &Reflector, &Manager)
+            .await;
+        assert_eq!(next, RuntimeEvent::AttentionComputed);
+        assert_eq!(runtime.effort_budget().spent().flops, 100);
+
+        // The evaluator refuses once its own FLOP policy is exceeded.
+        runtime.charge_flops(100);
+        runtime
+            .step(event.clone(), &BudgetEvaluator, &EchoRegulator, &Reflector, &Manager)
+            .await;
+        let next = runtime
+            .step(event.clone(), &BudgetEvaluator, &EchoRegulator, &Reflector, &Manager)
+            .await;
+        assert_eq!(next, RuntimeEvent::Error);
+
+        // The runtime enforces the step cap regardless of the evaluator.
+        runtime.reset_effort();
+        for _ in 0..3 {
+            runtime
+                .step(event.clone(), &AcceptEvaluator, &EchoRegulator, &Reflector, &Manager)
+                .await;
+        }
+        let next = runtime
+            .step(event, &AcceptEvaluator, &EchoRegulator, &Reflector, &Manager)
+            .await;
+        assert_eq!(next, RuntimeEvent::Error);
+    }
+    /// Meter consuming the joules in its cell on every reading.
+    struct StepMeter(Arc<Mutex<(f64, f64)>>);
+
+    impl aurex_utils::power::EnergyMeter for StepMeter {
+        fn name(&self) -> &'static str {
+            "step"
+        }
+
+        fn energy(&self) -> Option<f64> {
+            let mut meter = self.0.lock().unwrap();
+            meter.0 += meter.1;
+            Some(meter.0)
+        }
+    }
+
+    async fn emit(runtime: &Runtime) -> RuntimeEvent {
+        let event = RuntimeEvent::AttentionComputed;
+        runtime
+            .step(event, &AcceptEvaluator, &EchoRegulator, &Reflector, &Manager)
+            .await
+    }
+
+    #[tokio::test]
+    async fn power_governor_throttles_precision_and_charges_energy() {
+        let meter = Arc::new(Mutex::new((0.0, 2.0)));
+        let config = config::PowerConfig {
+            max_joules_per_token: Some(1.0),
+            window: Some(1),
+            low_precision: Some(Precision::Int8),
+            ..config::PowerConfig::default()
+        };
+        let governor = PowerGovernor::new(config, Box::new(StepMeter(meter.clone())));
+        let mut runtime = Runtime::default();
+        runtime.set_power_governor(Arc::new(governor));
+        runtime.set_precision(Precision::Bf16);
+
+        emit(&runtime).await;
+        assert_eq!(runtime.precision(), Precision::Bf16);
+        emit(&runtime).await;
+        assert_eq!(runtime.power_governor().unwrap().throttle(), Throttle::LowPrecision);
+        assert_eq!(runtime.precision(), Precision::Int8);
+        assert_eq!(runtime.effort_budget().last_step().energy, 2.0);
+
+        meter.lock().unwrap().1 = 0.1;
+        emit(&runtime).await;
+        assert_eq!(runtime.power_governor().unwrap().throttle(), Throttle::None);
+        assert_eq!(runtime.precision(), Precision::Bf16);
+        assert_eq!(runtime.power_governor().unwrap().tokens(), 2);
+    }
 }
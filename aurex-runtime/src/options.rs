@@ -0,0 +1,110 @@
+//! Per-request generation options.
+//!
+//! [`GenerationOptions`] hold what a single request asks for: how many
+//! tokens to generate, how to sample them, where to stop, the precision to
+//! run at and how urgent it is.  They travel with the request through the
+//! batch scheduler and into every [`Runtime::step_with_options`] taken on
+//! its behalf, where the [`EffortEvaluator`] and [`ConfidenceRegulator`] see
+//! them.  Fields left unset fall back to the runtime's own settings, so two
+//! concurrent requests no longer have to share one global configuration.
+//!
+//! [`Runtime::step_with_options`]: crate::Runtime::step_with_options
+//! [`EffortEvaluator`]: crate::EffortEvaluator
+//! [`ConfidenceRegulator`]: crate::ConfidenceRegulator
+
+use serde::{Deserialize, Serialize};
+
+use crate::Precision;
+
+/// How urgently a request should be served.  Schedulers start higher
+/// priorities first; requests of equal priority keep their order.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Sampling overrides of a request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplerOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub seed: Option<u64>,
+}
+
+/// Options of a single generation request.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationOptions {
+    /// Most tokens to generate.
+    pub max_tokens: Option<usize>,
+    pub sampler: SamplerOptions,
+    /// Strings ending the generation.
+    pub stop: Vec<String>,
+    /// Precision to run the request's steps at; the regulator may still
+    /// raise it and a throttling power governor takes precedence.
+    pub precision: Option<Precision>,
+    pub priority: Priority,
+}
+
+impl GenerationOptions {
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_deserialize_with_defaults() {
+        let options: GenerationOptions = toml::from_str(
+            r#"
+            max_tokens = 32
+            precision = "bf16"
+            priority = "high"
+
+            [sampler]
+            seed = 7
+            "#,
+        )
+        .unwrap();
+        let expected = GenerationOptions {
+            sampler: SamplerOptions {
+                seed: Some(7),
+                ..SamplerOptions::default()
+            },
+            ..GenerationOptions::default()
+        };
+        assert_eq!(
+            options,
+            expected
+                .with_max_tokens(32)
+                .with_precision(Precision::Bf16)
+                .with_priority(Priority::High)
+        );
+        assert!(Priority::High > Priority::Normal && Priority::Normal > Priority::Low);
+        assert_eq!(
+            toml::from_str::<GenerationOptions>("").unwrap(),
+            GenerationOptions::default()
+        );
+    }
+}
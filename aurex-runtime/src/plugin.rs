@@ -1,7 +1,105 @@
 #![allow(improper_ctypes_definitions)]
 
+use aurex_backend::dispatch::CpuBackend;
+use aurex_backend::{BackendCapabilities, PluginBackend, PluginBackends, TensorOps};
 use libloading::{Library, Symbol};
+use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::PluginConfig;
+
+/// Tensor op handed to a plugin, with the arguments of the matching
+/// [`TensorOps`](aurex_backend::TensorOps) method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TensorOpRequest<'a> {
+    /// `m x k` matrix `a` times `k x n` matrix `b`.
+    MatMul {
+        a: &'a [f32],
+        b: &'a [f32],
+        m: usize,
+        n: usize,
+        k: usize,
+    },
+    Conv2d {
+        input: &'a [f32],
+        kernel: &'a [f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    },
+    Attention {
+        q: &'a [f32],
+        k: &'a [f32],
+        v: &'a [f32],
+        dim: usize,
+    },
+    LayerNorm {
+        x: &'a [f32],
+        gamma: &'a [f32],
+        beta: &'a [f32],
+        eps: f32,
+    },
+}
+
+impl TensorOpRequest<'_> {
+    /// Op name as used for dispatcher routes.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TensorOpRequest::MatMul { .. } => "matmul",
+            TensorOpRequest::Conv2d { .. } => "conv2d",
+            TensorOpRequest::Attention { .. } => "attention",
+            TensorOpRequest::LayerNorm { .. } => "layer_norm",
+        }
+    }
+}
+
+/// Result of a tensor op executed by a plugin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorOpResponse {
+    pub output: Vec<f32>,
+}
+
+impl TensorOpResponse {
+    pub fn new(output: Vec<f32>) -> Self {
+        Self { output }
+    }
+}
+
+/// Error raised by a plugin or the registry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PluginError {
+    /// No plugin of this name is loaded.
+    NotFound(String),
+    /// The plugin does not implement the op.
+    Unsupported(&'static str),
+    /// The arguments do not match the op, e.g. a wrong buffer length.
+    InvalidInput(String),
+    /// The device or its driver failed.
+    Device(String),
+    /// A plugin manifest could not be read or does not match its library.
+    Manifest(String),
+    /// The plugin library could not be loaded.
+    Load(String),
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::NotFound(name) => write!(f, "plugin '{name}' not found"),
+            PluginError::Unsupported(op) => write!(f, "op '{op}' is not supported"),
+            PluginError::InvalidInput(reason) => write!(f, "invalid input: {reason}"),
+            PluginError::Device(reason) => write!(f, "device error: {reason}"),
+            PluginError::Manifest(reason) => write!(f, "invalid manifest: {reason}"),
+            PluginError::Load(reason) => write!(f, "failed to load plugin: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
 
 /// Trait implemented by backend plugins.
 pub trait BackendPlugin: Send + Sync {
@@ -9,8 +107,135 @@ pub trait BackendPlugin: Send + Sync {
     fn name(&self) -> &'static str;
     /// Called once when the plugin is loaded.
     fn initialize(&self);
+    /// Apply the `[plugins]` configuration, whose `options` table holds the
+    /// settings of each plugin under its name.  Called after
+    /// [`BackendPlugin::initialize`] by [`PluginRegistry::load_configured`].
+    fn configure(&self, _config: &PluginConfig) {}
     /// Execute the plugin's main functionality.
-    fn execute(&self);
+    fn execute(&self) {}
+    /// Execute a tensor op on the plugin's device.  Plugins answer ops they
+    /// do not implement with [`PluginError::Unsupported`].
+    fn execute_op(&self, op: TensorOpRequest) -> Result<TensorOpResponse, PluginError> {
+        Err(PluginError::Unsupported(op.name()))
+    }
+    /// What the plugin's device can execute; `f32` only unless the plugin
+    /// says otherwise.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::default()
+    }
+}
+
+/// Manifest describing a plugin in a plugins directory, e.g.
+/// `plugins/fpga_npu.toml`:
+///
+/// ```toml
+/// name = "fpga_npu"
+/// library = "libfpga_npu.so"
+/// ops = ["matmul", "layer_norm"]
+/// devices = ["npu0"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginManifest {
+    /// Name the library's plugin registers under.
+    pub name: String,
+    /// Dynamic library, relative to the manifest's directory.
+    pub library: PathBuf,
+    /// Ops the plugin implements; all of them when empty.
+    #[serde(default)]
+    pub ops: Vec<String>,
+    /// Devices the plugin drives.
+    #[serde(default)]
+    pub devices: Vec<String>,
+}
+
+impl PluginManifest {
+    /// Parse a manifest from a TOML string, leaving `library` as written.
+    pub fn from_toml_str(s: &str) -> Result<Self, PluginError> {
+        toml::from_str(s).map_err(|e| PluginError::Manifest(e.to_string()))
+    }
+
+    /// Read the manifest at `path`, resolving `library` against its directory.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, PluginError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| PluginError::Manifest(format!("{}: {e}", path.display())))?;
+        let mut manifest = Self::from_toml_str(&text)
+            .map_err(|e| PluginError::Manifest(format!("{}: {e}", path.display())))?;
+        if let Some(dir) = path.parent() {
+            manifest.library = dir.join(&manifest.library);
+        }
+        Ok(manifest)
+    }
+}
+
+/// [`TensorOps`] backed by a plugin's [`BackendPlugin::execute_op`].  Ops the
+/// plugin rejects or fails run on the CPU instead.
+pub struct PluginOps {
+    plugin: Arc<dyn BackendPlugin>,
+}
+
+impl PluginOps {
+    pub fn new(plugin: Arc<dyn BackendPlugin>) -> Self {
+        Self { plugin }
+    }
+
+    fn run(&self, op: TensorOpRequest, fallback: impl FnOnce() -> Vec<f32>) -> Vec<f32> {
+        match self.plugin.execute_op(op) {
+            Ok(response) => response.output,
+            Err(err) => {
+                tracing::warn!(plugin = self.plugin.name(), op = op.name(), %err, "plugin op failed, running on cpu");
+                fallback()
+            }
+        }
+    }
+}
+
+impl TensorOps for PluginOps {
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, n: usize, k: usize) -> Vec<f32> {
+        self.run(TensorOpRequest::MatMul { a, b, m, n, k }, || {
+            CpuBackend.matmul(a, b, m, n, k)
+        })
+    }
+
+    fn conv2d(
+        &self,
+        input: &[f32],
+        kernel: &[f32],
+        input_shape: (usize, usize),
+        kernel_shape: (usize, usize),
+    ) -> Vec<f32> {
+        let op = TensorOpRequest::Conv2d {
+            input,
+            kernel,
+            input_shape,
+            kernel_shape,
+        };
+        self.run(op, || {
+            CpuBackend.conv2d(input, kernel, input_shape, kernel_shape)
+        })
+    }
+
+    fn attention(&self, q: &[f32], k: &[f32], v: &[f32], dim: usize) -> Vec<f32> {
+        self.run(TensorOpRequest::Attention { q, k, v, dim }, || {
+            CpuBackend.attention(q, k, v, dim)
+        })
+    }
+
+    fn layer_norm(&self, x: &[f32], gamma: &[f32], beta: &[f32], eps: f32) -> Vec<f32> {
+        self.run(
+            TensorOpRequest::LayerNorm {
+                x,
+                gamma,
+                beta,
+                eps,
+            },
+            || CpuBackend.layer_norm(x, gamma, beta, eps),
+        )
+    }
+    fn capabilities(&self) -> BackendCapabilities {
+        self.plugin.capabilities()
+    }
 }
 
 // Signature of the plugin constructor function exported by dynamic libraries.
@@ -18,7 +243,7 @@ type PluginCreate = unsafe extern "C" fn() -> *mut dyn BackendPlugin;
 
 /// Registry that loads backend plugins dynamically and stores them by name.
 pub struct PluginRegistry {
-    plugins: HashMap<String, Box<dyn BackendPlugin>>,
+    plugins: HashMap<String, Arc<dyn BackendPlugin>>,
     // Hold libraries to ensure they remain loaded for the lifetime of the registry.
     libs: Vec<Library>,
 }
@@ -39,25 +264,148 @@ impl PluginRegistry {
     /// Loading arbitrary dynamic libraries is inherently unsafe. The caller must
     /// ensure the library is trusted and follows the expected ABI.
     pub unsafe fn load(&mut self, path: &str) -> Result<(), libloading::Error> {
-        let lib = Library::new(path)?;
+        self.register(Library::new(path)?)
+    }
+
+    unsafe fn register(&mut self, lib: Library) -> Result<(), libloading::Error> {
+        let plugin = Self::instantiate(&lib)?;
+        self.plugins.insert(plugin.name().to_string(), plugin);
+        self.libs.push(lib);
+        Ok(())
+    }
+
+    unsafe fn instantiate(lib: &Library) -> Result<Arc<dyn BackendPlugin>, libloading::Error> {
         let constructor: Symbol<PluginCreate> = lib.get(b"create_plugin")?;
         let plugin = Box::<dyn BackendPlugin>::from_raw(constructor());
         plugin.initialize();
-        let name = plugin.name().to_string();
-        self.plugins.insert(name, plugin);
-        self.libs.push(lib);
+        Ok(plugin.into())
+    }
+
+    /// Load the plugin of every `*.toml` manifest in `dir` and register each
+    /// one as a dispatchable backend in [`PluginBackends::global`], returning
+    /// the manifests that failed together with their errors.  Libraries
+    /// loaded this way stay loaded for the life of the process since their
+    /// backends are shared process wide.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as [`PluginRegistry::load`] for every listed library.
+    pub unsafe fn load_dir(
+        &mut self,
+        dir: impl AsRef<Path>,
+    ) -> io::Result<Vec<(PathBuf, PluginError)>> {
+        let mut manifests = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "toml") {
+                manifests.push(path);
+            }
+        }
+        manifests.sort();
+
+        let mut failed = Vec::new();
+        for path in manifests {
+            let result = PluginManifest::from_file(&path).and_then(|m| self.load_manifest(&m));
+            if let Err(err) = result {
+                tracing::warn!(path = %path.display(), %err, "failed to load plugin");
+                failed.push((path, err));
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Load the library of `manifest` and register its plugin as a backend.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as [`PluginRegistry::load`] for `manifest.library`.
+    pub unsafe fn load_manifest(&mut self, manifest: &PluginManifest) -> Result<(), PluginError> {
+        let load_error = |e: libloading::Error| {
+            PluginError::Load(format!("{}: {e}", manifest.library.display()))
+        };
+        let lib = Library::new(&manifest.library).map_err(load_error)?;
+        let plugin = Self::instantiate(&lib).map_err(load_error)?;
+        if plugin.name() != manifest.name {
+            return Err(PluginError::Manifest(format!(
+                "'{}' names plugin '{}' but the library provides '{}'",
+                manifest.library.display(),
+                manifest.name,
+                plugin.name()
+            )));
+        }
+        std::mem::forget(lib);
+        self.plugins.insert(manifest.name.clone(), plugin);
+        self.register_backend(manifest)
+    }
+
+    /// Register the loaded plugin named by `manifest` in
+    /// [`PluginBackends::global`] with the ops and devices it declares.  Its
+    /// library must stay loaded for the life of the process.
+    fn register_backend(&self, manifest: &PluginManifest) -> Result<(), PluginError> {
+        let plugin = self
+            .plugins
+            .get(&manifest.name)
+            .ok_or_else(|| PluginError::NotFound(manifest.name.clone()))?;
+        let backend = PluginBackend::new(&manifest.name, Arc::new(PluginOps::new(plugin.clone())))
+            .with_ops(manifest.ops.iter().cloned())
+            .with_devices(manifest.devices.iter().cloned());
+        PluginBackends::global().register(backend);
         Ok(())
     }
 
+    /// Load every library listed in the plugin configuration and configure
+    /// the plugins, returning the paths that failed together with their
+    /// errors.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as [`PluginRegistry::load`] for every configured path.
+    pub unsafe fn load_configured(
+        &mut self,
+        config: &PluginConfig,
+    ) -> Vec<(PathBuf, libloading::Error)> {
+        let mut failed = Vec::new();
+        for path in &config.paths {
+            let result = Library::new(path).and_then(|lib| self.register(lib));
+            if let Err(err) = result {
+                tracing::warn!(path = %path.display(), %err, "failed to load plugin");
+                failed.push((path.clone(), err));
+            }
+        }
+        self.configure(config);
+        failed
+    }
+
+    /// Pass `config` to every loaded plugin.
+    pub fn configure(&self, config: &PluginConfig) {
+        for plugin in self.plugins.values() {
+            plugin.configure(config);
+        }
+    }
+
     /// Execute a previously loaded plugin by name.
     pub fn execute(&self, name: &str) {
         if let Some(plugin) = self.plugins.get(name) {
             plugin.execute();
         } else {
-            eprintln!("Plugin '{}' not found", name);
+            tracing::warn!(plugin = name, "plugin not found");
         }
     }
 
+    /// Execute a tensor op on the plugin called `name`.
+    pub fn execute_op(
+        &self,
+        name: &str,
+        op: TensorOpRequest,
+    ) -> Result<TensorOpResponse, PluginError> {
+        let plugin = self
+            .plugins
+            .get(name)
+            .ok_or_else(|| PluginError::NotFound(name.to_string()))?;
+        let _span = tracing::debug_span!("plugin_op", plugin = name, op = op.name()).entered();
+        plugin.execute_op(op)
+    }
+
     /// List the names of all loaded plugins.
     pub fn list(&self) -> Vec<&str> {
         self.plugins.keys().map(|k| k.as_str()).collect()
@@ -93,10 +441,159 @@ mod tests {
         let mut registry = PluginRegistry::new();
         registry
             .plugins
-            .insert(plugin.name().to_string(), Box::new(plugin));
+            .insert(plugin.name().to_string(), Arc::new(plugin));
 
         assert_eq!(registry.list(), vec!["test"]);
         registry.execute("test");
         assert!(*executed.lock().unwrap());
     }
+
+    /// Plugin scaling matmul results by its configured `scale`.
+    struct ScalingPlugin {
+        scale: Mutex<f32>,
+    }
+
+    impl BackendPlugin for ScalingPlugin {
+        fn name(&self) -> &'static str {
+            "scaling"
+        }
+        fn initialize(&self) {}
+        fn configure(&self, config: &PluginConfig) {
+            if let Some(scale) = config
+                .options(self.name())
+                .and_then(|o| o.get("scale"))
+                .and_then(|v| v.as_float())
+            {
+                *self.scale.lock().unwrap() = scale as f32;
+            }
+        }
+        fn execute_op(&self, op: TensorOpRequest) -> Result<TensorOpResponse, PluginError> {
+            let TensorOpRequest::MatMul { a, b, m, n, k } = op else {
+                return Err(PluginError::Unsupported(op.name()));
+            };
+            let scale = *self.scale.lock().unwrap();
+            let output = (0..m * n)
+                .map(|e| {
+                    (0..k)
+                        .map(|p| a[e / n * k + p] * b[p * n + e % n])
+                        .sum::<f32>()
+                        * scale
+                })
+                .collect();
+            Ok(TensorOpResponse::new(output))
+        }
+    }
+
+    #[test]
+    fn configured_plugins_execute_tensor_ops() {
+        let mut registry = PluginRegistry::new();
+        let plugin = ScalingPlugin {
+            scale: Mutex::new(1.0),
+        };
+        registry
+            .plugins
+            .insert(plugin.name().to_string(), Arc::new(plugin));
+        let config =
+            crate::AurexConfig::from_toml_str("[plugins]\noptions = { scaling = { scale = 2.0 } }")
+                .unwrap();
+        registry.configure(&config.plugins);
+
+        let matmul = TensorOpRequest::MatMul {
+            a: &[1.0, 2.0],
+            b: &[3.0, 4.0],
+            m: 1,
+            n: 1,
+            k: 2,
+        };
+        let response = registry.execute_op("scaling", matmul).unwrap();
+        assert_eq!(response.output, vec![22.0]);
+
+        let norm = TensorOpRequest::LayerNorm {
+            x: &[1.0],
+            gamma: &[1.0],
+            beta: &[0.0],
+            eps: 1e-5,
+        };
+        assert_eq!(
+            registry.execute_op("scaling", norm),
+            Err(PluginError::Unsupported("layer_norm"))
+        );
+        assert_eq!(
+            registry.execute_op("missing", matmul),
+            Err(PluginError::NotFound("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn plugins_register_as_backends() {
+        let mut registry = PluginRegistry::new();
+        let plugin = ScalingPlugin {
+            scale: Mutex::new(3.0),
+        };
+        registry
+            .plugins
+            .insert(plugin.name().to_string(), Arc::new(plugin));
+        let manifest = PluginManifest::from_toml_str(
+            "name = \"scaling\"\nlibrary = \"libscaling.so\"\nops = [\"matmul\"]",
+        )
+        .unwrap();
+        registry.register_backend(&manifest).unwrap();
+
+        let backend = PluginBackends::global().get("scaling").unwrap();
+        assert!(backend.supports("matmul") && !backend.supports("layer_norm"));
+        let ops = backend.tensor_ops();
+        assert_eq!(ops.matmul(&[1.0, 2.0], &[3.0, 4.0], 1, 1, 2), vec![33.0]);
+        // Ops the plugin rejects run on the CPU.
+        assert_eq!(
+            ops.layer_norm(&[1.0, 3.0], &[1.0, 1.0], &[0.0, 0.0], 0.0),
+            vec![-1.0, 1.0]
+        );
+
+        // Configured routes send ops to the plugin through the dispatcher.
+        let config = crate::AurexConfig::from_toml_str(
+            "[backend]\npreferred = \"cpu\"\nroutes = { matmul = \"plugin:scaling\" }",
+        )
+        .unwrap();
+        let dispatcher = config.dispatcher(aurex_backend::Workload::Light);
+        assert_eq!(
+            dispatcher.matmul(&[1.0, 2.0], &[3.0, 4.0], 1, 1, 2),
+            vec![33.0]
+        );
+
+        let missing = PluginManifest {
+            name: "missing".to_string(),
+            ..manifest
+        };
+        assert_eq!(
+            registry.register_backend(&missing),
+            Err(PluginError::NotFound("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn load_dir_reports_broken_manifests() {
+        let dir = std::env::temp_dir().join(format!("aurex-plugins-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("absent.toml"),
+            "name = \"absent\"\nlibrary = \"libabsent.so\"",
+        )
+        .unwrap();
+        fs::write(dir.join("broken.toml"), "name = \"broken\"\nops = 3").unwrap();
+        fs::write(dir.join("README"), "not a manifest").unwrap();
+
+        let manifest = PluginManifest::from_file(dir.join("absent.toml")).unwrap();
+        assert_eq!(manifest.library, dir.join("libabsent.so"));
+        assert!(manifest.ops.is_empty() && manifest.devices.is_empty());
+
+        let mut registry = PluginRegistry::new();
+        let failed = unsafe { registry.load_dir(&dir) }.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].0, dir.join("absent.toml"));
+        assert!(matches!(failed[0].1, PluginError::Load(_)));
+        assert!(matches!(failed[1].1, PluginError::Manifest(_)));
+        assert!(registry.list().is_empty());
+        assert!(unsafe { registry.load_dir(&dir) }.is_err());
+    }
 }
@@ -0,0 +1,380 @@
+//! Power-aware execution for edge devices.
+//!
+//! A [`PowerGovernor`] reads an [`EnergyMeter`] after every runtime step and
+//! keeps the average power and the energy per generated token over a window
+//! of readings.  While either exceeds the configured [`PowerConfig`] budget
+//! it throttles one [`Throttle`] stage per window: first it caps the device
+//! clocks, then it asks the runtime for the low precision, then it halves
+//! batch sizes.  Once consumption has fallen below [`RELAX_FRACTION`] of the
+//! budget for a whole window it relaxes one stage again.
+//!
+//! The [`Runtime`](crate::Runtime) charges the measured energy of each step
+//! to its [`EffortBudget`](crate::EffortBudget) instead of the FLOP based
+//! proxy and follows the governor's precision.  Batching callers size their
+//! batches with [`PowerGovernor::batch_size`].
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use aurex_backend::Precision;
+use aurex_utils::power::{ClockControl, EnergyMeter};
+
+use crate::config::PowerConfig;
+
+/// Readings averaged when the configuration sets no window.
+pub const DEFAULT_WINDOW: usize = 16;
+
+/// Fraction of the budget consumption must stay below for the governor to
+/// relax.
+pub const RELAX_FRACTION: f64 = 0.8;
+
+/// Measures taken while over budget, each including the previous ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Throttle {
+    #[default]
+    None,
+    /// Device clocks are capped.
+    ClocksCapped,
+    /// Execution runs at the low precision.
+    LowPrecision,
+    /// Batches are halved.
+    SmallBatch,
+}
+
+/// Result of [`PowerGovernor::observe`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerSample {
+    /// Energy consumed since the previous reading, `None` if the meter could
+    /// not be read.
+    pub joules: Option<f64>,
+    pub throttle: Throttle,
+    /// Whether this reading changed the throttle.
+    pub changed: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reading {
+    elapsed: Duration,
+    joules: f64,
+    tokens: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    last: Option<(Instant, f64)>,
+    window: VecDeque<Reading>,
+    /// Tokens observed since the last reading.
+    pending_tokens: u64,
+    joules: f64,
+    tokens: u64,
+    throttle: Throttle,
+}
+
+/// Power budget enforcement, see the [module documentation](self).
+pub struct PowerGovernor {
+    config: PowerConfig,
+    meter: Box<dyn EnergyMeter>,
+    clocks: Option<Box<dyn ClockControl>>,
+    state: Mutex<State>,
+}
+
+impl PowerGovernor {
+    /// Enforce the budget of `config` with energy readings from `meter`.
+    pub fn new(config: PowerConfig, meter: Box<dyn EnergyMeter>) -> Self {
+        Self {
+            config,
+            meter,
+            clocks: None,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Governor for the budget of `config` on the detected RAPL or hwmon
+    /// meter and, unless disabled, clock control.  `None` without a budget
+    /// or an energy meter.
+    pub fn from_config(config: &PowerConfig) -> Option<Self> {
+        if config.max_watts.is_none() && config.max_joules_per_token.is_none() {
+            return None;
+        }
+        let Some(meter) = aurex_utils::power::detect_meter() else {
+            tracing::warn!("power budget configured but no energy meter found");
+            return None;
+        };
+        tracing::info!(meter = meter.name(), "power governor started");
+        let governor = Self::new(config.clone(), meter);
+        match aurex_utils::power::detect_clocks() {
+            Some(clocks) if config.cap_clocks != Some(false) => {
+                Some(governor.with_clock_control(clocks))
+            }
+            _ => Some(governor),
+        }
+    }
+
+    /// Cap clocks through `clocks` while throttled.
+    pub fn with_clock_control(mut self, clocks: Box<dyn ClockControl>) -> Self {
+        self.clocks = Some(clocks);
+        self
+    }
+
+    pub fn config(&self) -> &PowerConfig {
+        &self.config
+    }
+
+    pub fn throttle(&self) -> Throttle {
+        self.lock().throttle
+    }
+
+    /// Energy measured so far in joules.
+    pub fn joules(&self) -> f64 {
+        self.lock().joules
+    }
+
+    /// Tokens observed so far.
+    pub fn tokens(&self) -> u64 {
+        self.lock().tokens
+    }
+
+    /// Energy per token over the whole run, `None` before the first token.
+    pub fn joules_per_token(&self) -> Option<f64> {
+        let state = self.lock();
+        (state.tokens > 0).then(|| state.joules / state.tokens as f64)
+    }
+
+    /// Average power over the current window, `None` before two readings.
+    pub fn watts(&self) -> Option<f64> {
+        Self::window_watts(&self.lock().window)
+    }
+
+    /// Precision the runtime should run at, `None` while not throttled to
+    /// the low precision.
+    pub fn precision(&self) -> Option<Precision> {
+        (self.throttle() >= Throttle::LowPrecision)
+            .then(|| self.config.low_precision.unwrap_or(Precision::Bf16))
+    }
+
+    /// Batch size to use instead of `requested`.
+    pub fn batch_size(&self, requested: usize) -> usize {
+        if self.throttle() >= Throttle::SmallBatch {
+            (requested / 2).max(1)
+        } else {
+            requested
+        }
+    }
+
+    /// Record that `tokens` were generated since the previous call, read the
+    /// meter and adjust the throttle.
+    pub fn observe(&self, tokens: u64) -> PowerSample {
+        let mut state = self.lock();
+        state.pending_tokens += tokens;
+        let now = Instant::now();
+        let Some(energy) = self.meter.energy() else {
+            return PowerSample {
+                joules: None,
+                throttle: state.throttle,
+                changed: false,
+            };
+        };
+        let Some((last_time, last_energy)) = state.last.replace((now, energy)) else {
+            state.pending_tokens = 0;
+            return PowerSample {
+                joules: Some(0.0),
+                throttle: state.throttle,
+                changed: false,
+            };
+        };
+
+        let reading = Reading {
+            elapsed: now - last_time,
+            joules: (energy - last_energy).max(0.0),
+            tokens: std::mem::take(&mut state.pending_tokens),
+        };
+        state.joules += reading.joules;
+        state.tokens += reading.tokens;
+        state.window.push_back(reading);
+        let window = self.config.window.unwrap_or(DEFAULT_WINDOW).max(1);
+        while state.window.len() > window {
+            state.window.pop_front();
+        }
+
+        let previous = state.throttle;
+        if state.window.len() == window {
+            let load = self.load(&state.window);
+            if load > 1.0 {
+                state.throttle = self.tighter(previous);
+            } else if load < RELAX_FRACTION {
+                state.throttle = self.looser(previous);
+            }
+        }
+        let changed = state.throttle != previous;
+        if changed {
+            // Judge the new stage on its own readings.
+            state.window.clear();
+            tracing::info!(from = ?previous, to = ?state.throttle, "power throttle changed");
+            if let Some(clocks) = &self.clocks {
+                let capped = state.throttle >= Throttle::ClocksCapped;
+                if let Err(err) = clocks.set_capped(capped) {
+                    tracing::warn!(clocks = clocks.name(), %err, "cannot change clocks");
+                }
+            }
+        }
+        PowerSample {
+            joules: Some(reading.joules),
+            throttle: state.throttle,
+            changed,
+        }
+    }
+
+    /// Consumption over `window` as a fraction of the budget, the larger of
+    /// power and energy per token.
+    fn load(&self, window: &VecDeque<Reading>) -> f64 {
+        let mut load: f64 = 0.0;
+        if let (Some(max), Some(watts)) = (self.config.max_watts, Self::window_watts(window)) {
+            load = load.max(watts / max);
+        }
+        let tokens: u64 = window.iter().map(|r| r.tokens).sum();
+        if let (Some(max), true) = (self.config.max_joules_per_token, tokens > 0) {
+            let joules: f64 = window.iter().map(|r| r.joules).sum();
+            load = load.max(joules / tokens as f64 / max);
+        }
+        load
+    }
+
+    fn window_watts(window: &VecDeque<Reading>) -> Option<f64> {
+        let secs: f64 = window.iter().map(|r| r.elapsed.as_secs_f64()).sum();
+        let joules: f64 = window.iter().map(|r| r.joules).sum();
+        (secs > 0.0).then(|| joules / secs)
+    }
+
+    /// Next stage when over budget.  Clock capping is skipped without a
+    /// clock control.
+    fn tighter(&self, throttle: Throttle) -> Throttle {
+        match throttle {
+            Throttle::None if self.clocks.is_some() => Throttle::ClocksCapped,
+            Throttle::None | Throttle::ClocksCapped => Throttle::LowPrecision,
+            Throttle::LowPrecision | Throttle::SmallBatch => Throttle::SmallBatch,
+        }
+    }
+
+    /// Previous stage when well under budget.
+    fn looser(&self, throttle: Throttle) -> Throttle {
+        match throttle {
+            Throttle::SmallBatch => Throttle::LowPrecision,
+            Throttle::LowPrecision if self.clocks.is_some() => Throttle::ClocksCapped,
+            Throttle::None | Throttle::ClocksCapped | Throttle::LowPrecision => Throttle::None,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::Arc;
+
+    /// Meter whose reading the test advances.
+    #[derive(Clone, Default)]
+    struct Meter(Arc<Mutex<f64>>);
+
+    impl Meter {
+        fn consume(&self, joules: f64) {
+            *self.0.lock().unwrap() += joules;
+        }
+    }
+
+    impl EnergyMeter for Meter {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn energy(&self) -> Option<f64> {
+            Some(*self.0.lock().unwrap())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Clocks(Arc<Mutex<Vec<bool>>>);
+
+    impl ClockControl for Clocks {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn set_capped(&self, capped: bool) -> io::Result<()> {
+            self.0.lock().unwrap().push(capped);
+            Ok(())
+        }
+    }
+
+    fn governor(meter: &Meter) -> PowerGovernor {
+        let config = PowerConfig {
+            max_joules_per_token: Some(1.0),
+            window: Some(2),
+            low_precision: Some(Precision::Int8),
+            ..PowerConfig::default()
+        };
+        PowerGovernor::new(config, Box::new(meter.clone()))
+    }
+
+    /// Generate one token per reading at `joules` per token.
+    fn run(governor: &PowerGovernor, meter: &Meter, joules: f64, readings: usize) {
+        for _ in 0..readings {
+            meter.consume(joules);
+            governor.observe(1);
+        }
+    }
+
+    #[test]
+    fn over_budget_escalates_one_stage_per_window() {
+        let meter = Meter::default();
+        let clocks = Clocks::default();
+        let governor = governor(&meter).with_clock_control(Box::new(clocks.clone()));
+        assert_eq!(governor.observe(0).joules, Some(0.0));
+
+        run(&governor, &meter, 2.0, 2);
+        assert_eq!(governor.throttle(), Throttle::ClocksCapped);
+        assert_eq!(governor.precision(), None);
+        run(&governor, &meter, 2.0, 2);
+        assert_eq!(governor.throttle(), Throttle::LowPrecision);
+        assert_eq!(governor.precision(), Some(Precision::Int8));
+        assert_eq!(governor.batch_size(8), 8);
+        run(&governor, &meter, 2.0, 2);
+        assert_eq!(governor.throttle(), Throttle::SmallBatch);
+        assert_eq!(governor.batch_size(8), 4);
+        assert_eq!(governor.batch_size(1), 1);
+        assert_eq!(governor.joules_per_token(), Some(2.0));
+        assert_eq!(governor.tokens(), 6);
+
+        // Within budget but above the relax fraction: hold.
+        run(&governor, &meter, 0.9, 4);
+        assert_eq!(governor.throttle(), Throttle::SmallBatch);
+        run(&governor, &meter, 0.1, 6);
+        assert_eq!(governor.throttle(), Throttle::None);
+        assert_eq!(
+            *clocks.0.lock().unwrap(),
+            vec![true, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn clock_stage_is_skipped_without_control() {
+        let meter = Meter::default();
+        let governor = governor(&meter);
+        governor.observe(0);
+        let sample = {
+            meter.consume(3.0);
+            governor.observe(1);
+            meter.consume(3.0);
+            governor.observe(1)
+        };
+        assert_eq!(sample.joules, Some(3.0));
+        assert!(sample.changed);
+        assert_eq!(sample.throttle, Throttle::LowPrecision);
+    }
+}
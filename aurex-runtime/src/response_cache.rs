@@ -0,0 +1,331 @@
+//! Exact-match cache of generated responses.
+//!
+//! Sampling is seeded, so a request repeated with the same prompt and
+//! [`GenerationOptions`] on the same model generates the same text.  A
+//! [`ResponseCache`] keeps such outputs under a [`CacheKey`] holding all
+//! three and hands them to repeats, such as eval suites or agent retries,
+//! without running the model again.  Keys are bucketed by a hash but matched
+//! on their full contents, so colliding hashes never share a response.  Entries expire `ttl_ms` after they were
+//! generated; beyond `max_entries` or `max_bytes` the least recently used
+//! ones are dropped.
+//!
+//! The cache is off unless the `[response_cache]` section sets a size
+//! limit.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem::Discriminant;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::config::{AurexConfig, ResponseCacheConfig};
+use crate::{GenerationOptions, Precision};
+
+/// Everything a cached response depends on.  Keys hash to a digest
+/// computed once and compare equal only if all their inputs match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    digest: u64,
+    inputs: KeyInputs,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct KeyInputs {
+    model: String,
+    prompt: String,
+    max_tokens: Option<usize>,
+    /// Bits of the sampler's floats, which are not `Eq`.
+    temperature: Option<u32>,
+    top_p: Option<u32>,
+    seed: Option<u64>,
+    stop: Vec<String>,
+    precision: Option<Discriminant<Precision>>,
+    /// Inputs added with [`CacheKey::with`].
+    extra: Vec<String>,
+}
+
+impl CacheKey {
+    /// Key of generating from `prompt` with `options` on `model`.
+    pub fn new(model: &str, prompt: &str, options: &GenerationOptions) -> Self {
+        let sampler = options.sampler;
+        // The priority only orders requests; it does not change outputs.
+        Self::from_inputs(KeyInputs {
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            max_tokens: options.max_tokens,
+            temperature: sampler.temperature.map(f32::to_bits),
+            top_p: sampler.top_p.map(f32::to_bits),
+            seed: sampler.seed,
+            stop: options.stop.clone(),
+            precision: options.precision.as_ref().map(std::mem::discriminant),
+            extra: Vec::new(),
+        })
+    }
+
+    /// Key that also depends on `extra`, e.g. an output format.
+    pub fn with(mut self, extra: &str) -> Self {
+        self.inputs.extra.push(extra.to_string());
+        Self::from_inputs(self.inputs)
+    }
+
+    fn from_inputs(inputs: KeyInputs) -> Self {
+        let mut hasher = DefaultHasher::new();
+        inputs.hash(&mut hasher);
+        Self {
+            digest: hasher.finish(),
+            inputs,
+        }
+    }
+}
+
+impl Hash for CacheKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.digest.hash(state);
+    }
+}
+
+/// Counters of a [`ResponseCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped for the size limits or their age.
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+struct Entry<V> {
+    value: V,
+    bytes: usize,
+    inserted: Instant,
+    /// Lookup counter value of the last use, for LRU eviction.
+    used: u64,
+}
+
+struct Entries<V> {
+    map: HashMap<CacheKey, Entry<V>>,
+    bytes: usize,
+    uses: u64,
+    stats: CacheStats,
+}
+
+impl<V> Entries<V> {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.map.remove(key) {
+            self.bytes -= entry.bytes;
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+/// Responses by [`CacheKey`], see the [module documentation](self).
+pub struct ResponseCache<V> {
+    config: ResponseCacheConfig,
+    entries: Mutex<Entries<V>>,
+}
+
+impl<V: Clone> ResponseCache<V> {
+    /// Create a cache with the limits of `config`.
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                bytes: 0,
+                uses: 0,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// Create a cache with the `[response_cache]` limits of `config`.
+    pub fn from_config(config: &AurexConfig) -> Self {
+        Self::new(config.response_cache.clone())
+    }
+
+    /// Whether responses are cached at all.
+    pub fn is_enabled(&self) -> bool {
+        self.config.max_entries.is_some() || self.config.max_bytes.is_some()
+    }
+
+    /// The response cached under `key` unless it expired.
+    pub fn get(&self, key: &CacheKey) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    fn get_at(&self, key: &CacheKey, now: Instant) -> Option<V> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let ttl = self.config.ttl_ms.map(Duration::from_millis);
+        let mut entries = self.lock();
+        let expired = entries.map.get(key).is_some_and(|entry| {
+            ttl.is_some_and(|ttl| now.saturating_duration_since(entry.inserted) >= ttl)
+        });
+        if expired {
+            entries.remove(key);
+        }
+        entries.uses += 1;
+        let uses = entries.uses;
+        let value = entries.map.get_mut(key).map(|entry| {
+            entry.used = uses;
+            entry.value.clone()
+        });
+        match value {
+            Some(_) => entries.stats.hits += 1,
+            None => entries.stats.misses += 1,
+        }
+        value
+    }
+
+    /// Cache `value`, taking `bytes` of the size limit, under `key`.
+    /// Responses larger than the whole limit are not cached.
+    pub fn insert(&self, key: CacheKey, value: V, bytes: usize) {
+        self.insert_at(key, value, bytes, Instant::now());
+    }
+
+    fn insert_at(&self, key: CacheKey, value: V, bytes: usize, now: Instant) {
+        let max_entries = self.config.max_entries.unwrap_or(usize::MAX);
+        let max_bytes = self.config.max_bytes.unwrap_or(usize::MAX);
+        if !self.is_enabled() || max_entries == 0 || bytes > max_bytes {
+            return;
+        }
+        let mut entries = self.lock();
+        if let Some(previous) = entries.map.remove(&key) {
+            entries.bytes -= previous.bytes;
+        }
+        while entries.map.len() >= max_entries || entries.bytes + bytes > max_bytes {
+            let Some(oldest) = entries
+                .map
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.uses += 1;
+        let used = entries.uses;
+        entries.bytes += bytes;
+        entries.map.insert(
+            key,
+            Entry {
+                value,
+                bytes,
+                inserted: now,
+                used,
+            },
+        );
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.lock();
+        CacheStats {
+            entries: entries.map.len(),
+            bytes: entries.bytes,
+            ..entries.stats
+        }
+    }
+
+    /// Drop every cached response.
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.map.clear();
+        entries.bytes = 0;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries<V>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(prompt: &str) -> CacheKey {
+        CacheKey::new("tiny", prompt, &GenerationOptions::default())
+    }
+
+    #[test]
+    fn keys_cover_model_prompt_and_options() {
+        let options = GenerationOptions::default().with_max_tokens(8);
+        let base = CacheKey::new("tiny", "hi", &options);
+        assert_eq!(base, CacheKey::new("tiny", "hi", &options));
+        assert_ne!(base, CacheKey::new("other", "hi", &options));
+        assert_ne!(base, CacheKey::new("tiny", "hello", &options));
+        assert_ne!(
+            base,
+            CacheKey::new("tiny", "hi", &options.clone().with_max_tokens(9))
+        );
+        assert_ne!(
+            base,
+            CacheKey::new(
+                "tiny",
+                "hi",
+                &options.clone().with_precision(Precision::Int8)
+            )
+        );
+        assert_ne!(base, base.clone().with("json"));
+        let urgent = options.clone().with_priority(crate::Priority::High);
+        assert_eq!(base, CacheKey::new("tiny", "hi", &urgent));
+    }
+
+    #[test]
+    fn colliding_digests_do_not_share_responses() {
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            max_entries: Some(4),
+            ..ResponseCacheConfig::default()
+        });
+        let mut other = key("b");
+        other.digest = key("a").digest;
+        cache.insert(key("a"), "A", 1);
+        assert_eq!(cache.get(&other), None);
+        cache.insert(other.clone(), "B", 1);
+        assert_eq!(cache.get(&key("a")), Some("A"));
+        assert_eq!(cache.get(&other), Some("B"));
+    }
+
+    #[test]
+    fn responses_expire_and_evict_least_recently_used() {
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            max_entries: Some(2),
+            max_bytes: Some(10),
+            ttl_ms: Some(1000),
+        });
+        let start = Instant::now();
+        cache.insert_at(key("a"), "A", 4, start);
+        cache.insert_at(key("b"), "B", 4, start);
+        assert_eq!(cache.get_at(&key("a"), start), Some("A"));
+        // "b" is least recently used.
+        cache.insert_at(key("c"), "C", 4, start);
+        assert_eq!(cache.get_at(&key("b"), start), None);
+        assert_eq!(cache.get_at(&key("a"), start), Some("A"));
+        // Too large for the byte limit next to "a" and "c".
+        cache.insert_at(key("d"), "D", 11, start);
+        assert_eq!(cache.get_at(&key("d"), start), None);
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(cache.get_at(&key("c"), later), None);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 3,
+                evictions: 2,
+                entries: 1,
+                bytes: 4,
+            }
+        );
+
+        let off = ResponseCache::new(ResponseCacheConfig::default());
+        off.insert(key("a"), "A", 1);
+        assert_eq!(off.get(&key("a")), None);
+        assert!(!off.is_enabled());
+    }
+}
@@ -0,0 +1,435 @@
+//! Pipeline-parallel scheduling of model layers.
+//!
+//! The [`Scheduler`] partitions a model's layers into contiguous stages, one
+//! per device, and streams micro-batches through them.  Every stage runs on
+//! its own thread and hands its output to the next stage through a bounded
+//! queue, so stage `i` works on micro-batch `b` while stage `i + 1` works on
+//! micro-batch `b - 1`.  When a profiler is attached, the busy time, idle
+//! time (the pipeline bubble) and peak queue length of every stage are
+//! recorded.
+//!
+//! In deterministic mode the stages run one after another on the calling
+//! thread instead, so layers sharing state are called in the same order on
+//! every run.
+//!
+//! With a [`ThermalMonitor`] attached, micro-batches are pushed through in
+//! rounds: before each round the temperature is read, the round is sized
+//! with [`ThermalMonitor::batch_size`] and, when the device runs critically
+//! hot, the scheduler idles before starting it.  With a [`BatchTuner`]
+//! attached, rounds are also capped at [`BatchTuner::batch_size`] and the
+//! latency of every round is reported to the tuner.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use aurex_backend::{Dispatcher, TensorOps};
+use aurex_utils::profiler::{Profiler, StageRecord};
+
+use crate::batch_tuner::BatchTuner;
+use crate::thermal::ThermalMonitor;
+use crate::AurexConfig;
+
+/// A single model layer executed with the ops of its stage's device.
+pub type Layer = Arc<dyn Fn(&dyn TensorOps, Vec<f32>) -> Vec<f32> + Send + Sync>;
+
+/// Default number of micro-batches buffered between two stages.
+pub const DEFAULT_QUEUE_DEPTH: usize = 2;
+
+/// Consecutive layers assigned to one device.
+pub struct PipelineStage {
+    pub device: Arc<Dispatcher>,
+    pub layers: Vec<Layer>,
+}
+
+/// Runs micro-batches through a pipeline of stages.
+pub struct Scheduler {
+    stages: Vec<PipelineStage>,
+    queue_depth: usize,
+    profiler: Option<Arc<Mutex<Profiler>>>,
+    deterministic: bool,
+    thermal: Option<Arc<ThermalMonitor>>,
+    tuner: Option<Arc<BatchTuner>>,
+}
+
+impl Scheduler {
+    /// Partition `layers` into contiguous, evenly sized stages over
+    /// `devices`.  Devices left without layers are not used.
+    pub fn pipeline(layers: Vec<Layer>, devices: Vec<Arc<Dispatcher>>) -> Self {
+        let parts = devices.len().max(1);
+        let (base, extra) = (layers.len() / parts, layers.len() % parts);
+        let mut layers = layers.into_iter();
+        let stages = devices
+            .into_iter()
+            .enumerate()
+            .map(|(i, device)| PipelineStage {
+                device,
+                layers: layers
+                    .by_ref()
+                    .take(base + usize::from(i < extra))
+                    .collect(),
+            })
+            .filter(|stage| !stage.layers.is_empty())
+            .collect();
+        Self {
+            stages,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            profiler: None,
+            deterministic: false,
+            thermal: None,
+            tuner: None,
+        }
+    }
+
+    /// Apply the `[scheduler]` settings of `config`, including batch size
+    /// tuning, its deterministic mode and its `[thermal]` limits.
+    pub fn with_config(self, config: &AurexConfig) -> Self {
+        let mut scheduler = self.with_deterministic(config.backend.deterministic == Some(true));
+        if let Some(depth) = config.scheduler.pipeline_queue_depth {
+            scheduler = scheduler.with_queue_depth(depth);
+        }
+        if let Some(tuner) = BatchTuner::from_config(config) {
+            scheduler = scheduler.with_batch_tuner(Arc::new(tuner));
+        }
+        match ThermalMonitor::from_config(&config.thermal) {
+            Some(monitor) => scheduler.with_thermal(Arc::new(monitor)),
+            None => scheduler,
+        }
+    }
+
+    /// Run the stages sequentially on the calling thread.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Number of micro-batches buffered between two stages.
+    pub fn with_queue_depth(mut self, depth: usize) -> Self {
+        self.queue_depth = depth.max(1);
+        self
+    }
+
+    /// Record per-stage utilisation into `profiler` after every run.
+    pub fn with_profiler(mut self, profiler: Arc<Mutex<Profiler>>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Size rounds of micro-batches and insert idle slots by the readings
+    /// of `monitor`.
+    pub fn with_thermal(mut self, monitor: Arc<ThermalMonitor>) -> Self {
+        self.thermal = Some(monitor);
+        self
+    }
+
+    /// Cap rounds of micro-batches at the batch size chosen by `tuner` and
+    /// report their latency to it.
+    pub fn with_batch_tuner(mut self, tuner: Arc<BatchTuner>) -> Self {
+        self.tuner = Some(tuner);
+        self
+    }
+
+    pub fn stages(&self) -> &[PipelineStage] {
+        &self.stages
+    }
+
+    pub fn thermal(&self) -> Option<&ThermalMonitor> {
+        self.thermal.as_deref()
+    }
+
+    pub fn batch_tuner(&self) -> Option<&BatchTuner> {
+        self.tuner.as_deref()
+    }
+
+    /// Push `micro_batches` through the pipeline and return their outputs in
+    /// input order.
+    pub fn run(&self, micro_batches: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+        if self.stages.is_empty() {
+            return micro_batches;
+        }
+        if self.thermal.is_none() && self.tuner.is_none() {
+            return self.run_round(micro_batches);
+        }
+        self.run_rounds(micro_batches)
+    }
+
+    /// Run `micro_batches` in rounds sized by the thermal monitor and the
+    /// batch tuner, idling before a round while the device is critically
+    /// hot.
+    fn run_rounds(&self, micro_batches: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+        let requested = micro_batches.len();
+        let mut outputs = Vec::with_capacity(requested);
+        let mut pending = micro_batches.into_iter();
+        while pending.len() > 0 {
+            let mut size = requested;
+            if let Some(thermal) = &self.thermal {
+                let sample = thermal.observe();
+                if !sample.idle.is_zero() {
+                    tracing::debug!(idle = ?sample.idle, celsius = sample.celsius, "thermal idle slot");
+                    std::thread::sleep(sample.idle);
+                }
+                size = thermal.batch_size(requested);
+            }
+            if let Some(tuner) = &self.tuner {
+                size = size.min(tuner.batch_size());
+            }
+            let round: Vec<Vec<f32>> = pending.by_ref().take(size).collect();
+            let (len, started) = (round.len(), Instant::now());
+            outputs.extend(self.run_round(round));
+            if let Some(tuner) = &self.tuner {
+                tuner.observe(len, started.elapsed());
+            }
+        }
+        outputs
+    }
+
+    fn run_round(&self, micro_batches: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+        if self.deterministic {
+            self.run_sequential(micro_batches)
+        } else {
+            self.run_pipelined(micro_batches)
+        }
+    }
+
+    /// Stream `micro_batches` through the stage threads.
+    fn run_pipelined(&self, micro_batches: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+        let started = Instant::now();
+        let queued: Vec<AtomicUsize> = self.stages.iter().map(|_| AtomicUsize::new(0)).collect();
+        let peak: Vec<AtomicUsize> = self.stages.iter().map(|_| AtomicUsize::new(0)).collect();
+        let enqueue = |stage: usize| {
+            let len = queued[stage].fetch_add(1, Ordering::SeqCst) + 1;
+            peak[stage].fetch_max(len, Ordering::SeqCst);
+        };
+
+        let (outputs, usage) = std::thread::scope(|scope| {
+            let (feed, mut input) = sync_channel::<Vec<f32>>(self.queue_depth);
+            let mut workers = Vec::new();
+            for (i, stage) in self.stages.iter().enumerate() {
+                let (tx, next) = sync_channel(self.queue_depth);
+                let rx = std::mem::replace(&mut input, next);
+                let (queued, enqueue) = (&queued, &enqueue);
+                workers.push(scope.spawn(move || {
+                    let _span = tracing::debug_span!("pipeline_stage", stage = i).entered();
+                    let (mut busy, mut count) = (Duration::ZERO, 0);
+                    for mut x in rx {
+                        queued[i].fetch_sub(1, Ordering::SeqCst);
+                        let t = Instant::now();
+                        for layer in &stage.layers {
+                            x = layer(stage.device.as_ref(), x);
+                        }
+                        busy += t.elapsed();
+                        count += 1;
+                        if i + 1 < queued.len() {
+                            enqueue(i + 1);
+                        }
+                        if tx.send(x).is_err() {
+                            break;
+                        }
+                    }
+                    (busy, count)
+                }));
+            }
+            let enqueue = &enqueue;
+            scope.spawn(move || {
+                for batch in micro_batches {
+                    enqueue(0);
+                    if feed.send(batch).is_err() {
+                        break;
+                    }
+                }
+            });
+            let outputs: Vec<Vec<f32>> = input.iter().collect();
+            let usage: Vec<(Duration, usize)> = workers
+                .into_iter()
+                .map(|w| w.join().expect("pipeline stage panicked"))
+                .collect();
+            (outputs, usage)
+        });
+
+        let peak: Vec<usize> = peak.iter().map(|p| p.load(Ordering::SeqCst)).collect();
+        self.record(&usage, &peak, started.elapsed());
+        outputs
+    }
+
+    /// Pass every micro-batch through all stages before starting the next.
+    /// Nothing is queued, so the recorded queue lengths are zero.
+    fn run_sequential(&self, micro_batches: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+        let started = Instant::now();
+        let mut usage = vec![(Duration::ZERO, 0); self.stages.len()];
+        let outputs: Vec<Vec<f32>> = micro_batches
+            .into_iter()
+            .map(|mut x| {
+                for (stage, (busy, count)) in self.stages.iter().zip(&mut usage) {
+                    let t = Instant::now();
+                    for layer in &stage.layers {
+                        x = layer(stage.device.as_ref(), x);
+                    }
+                    *busy += t.elapsed();
+                    *count += 1;
+                }
+                x
+            })
+            .collect();
+        self.record(&usage, &vec![0; self.stages.len()], started.elapsed());
+        outputs
+    }
+
+    /// Log a run and report the `(busy, micro_batches)` usage and peak queue
+    /// length of every stage to the profiler.
+    fn record(&self, usage: &[(Duration, usize)], peak: &[usize], wall: Duration) {
+        tracing::debug!(
+            stages = self.stages.len(),
+            micro_batches = usage.first().map_or(0, |u| u.1),
+            deterministic = self.deterministic,
+            ?wall,
+            "pipeline run"
+        );
+        if let Some(profiler) = &self.profiler {
+            let mut profiler = profiler.lock().unwrap();
+            for (i, &(busy, micro_batches)) in usage.iter().enumerate() {
+                profiler.record_stage(StageRecord {
+                    stage: i,
+                    device: Some(self.stages[i].device.backend().name()),
+                    micro_batches,
+                    busy,
+                    wall,
+                    max_queue_len: peak[i],
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ThermalConfig;
+    use aurex_backend::{Backend, Workload};
+    use aurex_utils::gpu_counters::NoGpu;
+    use aurex_utils::metrics::Metrics;
+    use aurex_utils::thermal::TemperatureSensor;
+
+    fn doubling_layer() -> Layer {
+        Arc::new(|ops: &dyn TensorOps, x: Vec<f32>| ops.matmul(&x, &[2.0], x.len(), 1, 1))
+    }
+
+    #[test]
+    fn pipelines_micro_batches_across_stages() {
+        let devices = (0..3)
+            .map(|_| Arc::new(Dispatcher::new(Some(Backend::Cpu), Workload::Light)))
+            .collect();
+        let profiler = Arc::new(Mutex::new(Profiler::with_gpu_source(Box::new(NoGpu))));
+        let scheduler = Scheduler::pipeline((0..4).map(|_| doubling_layer()).collect(), devices)
+            .with_queue_depth(1)
+            .with_profiler(profiler.clone());
+        let sizes: Vec<usize> = scheduler.stages().iter().map(|s| s.layers.len()).collect();
+        assert_eq!(sizes, vec![2, 1, 1]);
+
+        let batches: Vec<Vec<f32>> = (0..6).map(|i| vec![i as f32, 1.0]).collect();
+        let outputs = scheduler.run(batches);
+        let expected: Vec<Vec<f32>> = (0..6).map(|i| vec![i as f32 * 16.0, 16.0]).collect();
+        assert_eq!(outputs, expected);
+
+        let profiler = profiler.lock().unwrap();
+        let stages = profiler.stage_records();
+        assert_eq!(stages.len(), 3);
+        assert!(stages
+            .iter()
+            .all(|s| s.micro_batches == 6 && s.device == Some("cpu")));
+        assert!(stages.iter().all(|s| s.max_queue_len >= 1));
+        let bubble = profiler.pipeline_bubble_fraction();
+        assert!((0.0..=1.0).contains(&bubble));
+    }
+
+    #[test]
+    fn deterministic_pipeline_runs_on_the_calling_thread() {
+        let caller = std::thread::current().id();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let layers: Vec<Layer> = (0..3)
+            .map(|i| {
+                let order = order.clone();
+                Arc::new(move |_: &dyn TensorOps, x: Vec<f32>| {
+                    assert_eq!(std::thread::current().id(), caller);
+                    order.lock().unwrap().push((x[0] as usize, i));
+                    x
+                }) as Layer
+            })
+            .collect();
+        let devices = (0..3)
+            .map(|_| Arc::new(Dispatcher::new(Some(Backend::Cpu), Workload::Light)))
+            .collect();
+        let config = AurexConfig::from_toml_str("[backend]\ndeterministic = true").unwrap();
+        let scheduler = Scheduler::pipeline(layers, devices).with_config(&config);
+
+        let outputs = scheduler.run((0..2).map(|b| vec![b as f32]).collect());
+        assert_eq!(outputs, vec![vec![0.0], vec![1.0]]);
+        let expected: Vec<(usize, usize)> =
+            (0..2).flat_map(|b| (0..3).map(move |i| (b, i))).collect();
+        assert_eq!(*order.lock().unwrap(), expected);
+    }
+
+    struct Hot(Arc<AtomicUsize>);
+
+    impl TemperatureSensor for Hot {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn temperature(&self) -> Option<f64> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Some(99.0)
+        }
+    }
+
+    #[test]
+    fn hot_devices_run_smaller_rounds() {
+        let readings = Arc::new(AtomicUsize::new(0));
+        let config = ThermalConfig {
+            max_celsius: Some(80.0),
+            idle_ms: Some(1),
+            ..ThermalConfig::default()
+        };
+        let metrics = Arc::new(Metrics::new());
+        let monitor = ThermalMonitor::new(config, Box::new(Hot(readings.clone())))
+            .with_metrics(metrics.clone());
+        let devices = (0..2)
+            .map(|_| Arc::new(Dispatcher::new(Some(Backend::Cpu), Workload::Light)))
+            .collect();
+        let scheduler = Scheduler::pipeline((0..2).map(|_| doubling_layer()).collect(), devices)
+            .with_thermal(Arc::new(monitor));
+
+        let outputs = scheduler.run((0..8).map(|i| vec![i as f32]).collect());
+        let expected: Vec<Vec<f32>> = (0..8).map(|i| vec![i as f32 * 4.0]).collect();
+        assert_eq!(outputs, expected);
+        // Rounds of 4, 2, 1 and 1 micro-batches.
+        assert_eq!(readings.load(Ordering::SeqCst), 4);
+        assert_eq!(metrics.thermal_events("shrink_batch"), 4);
+        assert_eq!(metrics.thermal_events("idle"), 4);
+        assert_eq!(scheduler.thermal().unwrap().batch_size(8), 1);
+    }
+
+    #[test]
+    fn tuned_rounds_grow_to_the_chosen_batch() {
+        let devices = vec![Arc::new(Dispatcher::new(
+            Some(Backend::Cpu),
+            Workload::Light,
+        ))];
+        let tuner = BatchTuner::new(4, Duration::from_secs(60))
+            .with_probe_steps(1)
+            .with_metrics(Arc::new(Metrics::new()));
+        let scheduler =
+            Scheduler::pipeline(vec![doubling_layer()], devices).with_batch_tuner(Arc::new(tuner));
+
+        let outputs = scheduler.run((0..9).map(|i| vec![i as f32]).collect());
+        let expected: Vec<Vec<f32>> = (0..9).map(|i| vec![i as f32 * 2.0]).collect();
+        assert_eq!(outputs, expected);
+        // Rounds of 1, 2 and 4 micro-batches probe, the last 2 run settled.
+        let tuner = scheduler.batch_tuner().unwrap();
+        let decisions = tuner.decisions();
+        assert_eq!(decisions.len(), 1);
+        let probed: Vec<usize> = decisions[0].probes.iter().map(|p| p.batch).collect();
+        assert_eq!(probed, [1, 2, 4]);
+        assert_eq!(tuner.batch_size(), 4);
+    }
+}
@@ -0,0 +1,509 @@
+//! Multi-turn conversation sessions.
+//!
+//! A [`Session`] holds the system prompt and chat history of one
+//! conversation, an optional handle to the model's KV cache for that
+//! conversation so the next turn only computes the new tokens, and a token
+//! budget.  A [`SessionStore`] creates sessions, forks them into independent
+//! copies sharing their history and KV cache handle, and expires sessions
+//! that have been idle longer than its time to live.
+//!
+//! Sessions created for an API key belong to it; [`SessionStore::check_owner`]
+//! hides them from requests made with other keys.
+//!
+//! Long conversations can be compressed: [`Session::summarize`] replaces the
+//! oldest messages with a system message carrying a summary of them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Start of the system message [`Session::summarize`] puts in place of the
+/// summarized messages.
+pub const SUMMARY_PREFIX: &str = "Summary of the earlier conversation: ";
+
+/// Identifier of a session in a [`SessionStore`].
+pub type SessionId = u64;
+
+/// Author of a chat message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        })
+    }
+}
+
+/// One message of a conversation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+/// Error raised by session operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionError {
+    /// No session has this id.
+    NotFound(SessionId),
+    /// The session was idle for longer than the store's time to live.
+    Expired(SessionId),
+    /// The session has used up its token budget.
+    BudgetExhausted { id: SessionId, limit: usize },
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::NotFound(id) => write!(f, "no session {id}"),
+            SessionError::Expired(id) => write!(f, "session {id} expired"),
+            SessionError::BudgetExhausted { id, limit } => {
+                write!(f, "session {id} used its budget of {limit} tokens")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+/// State of one conversation.  `K` is the KV cache handle of the model
+/// serving it.
+#[derive(Debug, Clone)]
+pub struct Session<K = ()> {
+    id: SessionId,
+    parent: Option<SessionId>,
+    owner: Option<String>,
+    system_prompt: Option<String>,
+    messages: Vec<Message>,
+    kv: Option<K>,
+    token_limit: Option<usize>,
+    tokens_used: usize,
+    /// Length of the history when the model last failed to summarize it.
+    failed_summary: Option<usize>,
+    created: Instant,
+    last_used: Instant,
+}
+
+impl<K> Session<K> {
+    /// Create a session with an optional system prompt and no token limit.
+    pub fn new(id: SessionId, system_prompt: Option<String>) -> Self {
+        let now = Instant::now();
+        Self {
+            id,
+            parent: None,
+            owner: None,
+            system_prompt,
+            messages: Vec::new(),
+            kv: None,
+            token_limit: None,
+            tokens_used: 0,
+            failed_summary: None,
+            created: now,
+            last_used: now,
+        }
+    }
+
+    /// Stop serving the session once it has used `limit` tokens.
+    pub fn with_token_limit(mut self, limit: usize) -> Self {
+        self.token_limit = Some(limit);
+        self
+    }
+
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    /// Session this one was forked from.
+    pub fn parent(&self) -> Option<SessionId> {
+        self.parent
+    }
+
+    /// Name of the API key the session was created with.
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    /// Chat history, oldest first, excluding the system prompt.
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Append a message to the history.
+    pub fn push(&mut self, role: Role, content: impl Into<String>) {
+        self.messages.push(Message {
+            role,
+            content: content.into(),
+        });
+    }
+
+    /// Replace the oldest `count` messages with a system message holding
+    /// `summary`, returning the replaced messages.
+    pub fn summarize(&mut self, count: usize, summary: &str) -> Vec<Message> {
+        let count = count.min(self.messages.len());
+        let summary = Message {
+            role: Role::System,
+            content: format!("{SUMMARY_PREFIX}{summary}"),
+        };
+        self.failed_summary = None;
+        self.messages.splice(..count, [summary]).collect()
+    }
+
+    /// Record that the model wrote an empty summary of the history.
+    pub fn summary_failed(&mut self) {
+        self.failed_summary = Some(self.messages.len());
+    }
+
+    /// Messages added since the model last wrote an empty summary, `None`
+    /// if it has not since the history was last summarized.
+    pub fn messages_since_failed_summary(&self) -> Option<usize> {
+        self.failed_summary
+            .map(|len| self.messages.len().saturating_sub(len))
+    }
+
+    /// The conversation so far, one `role: content` line per message.
+    pub fn transcript(&self) -> String {
+        self.transcript_prefix(self.messages.len())
+    }
+
+    /// The transcript of the system prompt and the first `count` messages.
+    pub fn transcript_prefix(&self, count: usize) -> String {
+        let mut text = String::new();
+        if let Some(system) = &self.system_prompt {
+            text.push_str(&format!("{}: {system}\n", Role::System));
+        }
+        for message in self.messages.iter().take(count) {
+            text.push_str(&format!("{}: {}\n", message.role, message.content));
+        }
+        text
+    }
+
+    /// Prompt asking the model for the assistant's next reply: the
+    /// transcript followed by `assistant: `.
+    pub fn prompt(&self) -> String {
+        format!("{}{}: ", self.transcript(), Role::Assistant)
+    }
+
+    /// KV cache of the conversation, if one has been stored.
+    pub fn kv(&self) -> Option<&K> {
+        self.kv.as_ref()
+    }
+
+    /// Store the KV cache of the conversation.
+    pub fn set_kv(&mut self, kv: K) {
+        self.kv = Some(kv);
+    }
+
+    /// Remove and return the stored KV cache.
+    pub fn take_kv(&mut self) -> Option<K> {
+        self.kv.take()
+    }
+
+    pub fn token_limit(&self) -> Option<usize> {
+        self.token_limit
+    }
+
+    /// Tokens computed for the session so far.
+    pub fn tokens_used(&self) -> usize {
+        self.tokens_used
+    }
+
+    /// Tokens left before the limit, `None` without a limit.
+    pub fn remaining_tokens(&self) -> Option<usize> {
+        self.token_limit
+            .map(|limit| limit.saturating_sub(self.tokens_used))
+    }
+
+    /// Fail with [`SessionError::BudgetExhausted`] once no tokens are left.
+    pub fn check_budget(&self) -> Result<(), SessionError> {
+        match self.token_limit {
+            Some(limit) if self.tokens_used >= limit => {
+                Err(SessionError::BudgetExhausted { id: self.id, limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record `tokens` computed for the session.
+    pub fn charge(&mut self, tokens: usize) {
+        self.tokens_used += tokens;
+    }
+
+    /// Time since the session was created.
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    /// Time since the session was last used through its store.
+    pub fn idle(&self) -> Duration {
+        self.last_used.elapsed()
+    }
+}
+
+/// Sessions shared between requests, see the [module documentation](self).
+#[derive(Debug)]
+pub struct SessionStore<K = ()> {
+    sessions: Mutex<HashMap<SessionId, Session<K>>>,
+    next_id: AtomicU64,
+    ttl: Option<Duration>,
+    token_limit: Option<usize>,
+}
+
+impl<K> Default for SessionStore<K> {
+    fn default() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            ttl: None,
+            token_limit: None,
+        }
+    }
+}
+
+impl<K> SessionStore<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expire sessions idle for `ttl` or longer.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Token limit of sessions created without one.
+    pub fn with_token_limit(mut self, limit: usize) -> Self {
+        self.token_limit = Some(limit);
+        self
+    }
+
+    /// Start a new session owned by the API key named `owner` and return its
+    /// id.  `token_limit` overrides the store's default limit.
+    pub fn create(
+        &self,
+        owner: Option<&str>,
+        system_prompt: Option<String>,
+        token_limit: Option<usize>,
+    ) -> SessionId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut session = Session::new(id, system_prompt);
+        session.owner = owner.map(str::to_owned);
+        session.token_limit = token_limit.or(self.token_limit);
+        self.lock().insert(id, session);
+        id
+    }
+
+    /// Run `f` on session `id`, marking it as used.
+    pub fn with_session<R>(
+        &self,
+        id: SessionId,
+        f: impl FnOnce(&mut Session<K>) -> R,
+    ) -> Result<R, SessionError> {
+        let mut sessions = self.lock();
+        let session = sessions.get_mut(&id).ok_or(SessionError::NotFound(id))?;
+        if self.is_expired(session) {
+            sessions.remove(&id);
+            return Err(SessionError::Expired(id));
+        }
+        let result = f(session);
+        session.last_used = Instant::now();
+        Ok(result)
+    }
+
+    /// Fail with [`SessionError::NotFound`] unless session `id` exists and
+    /// belongs to the API key named `owner`, so that other keys cannot tell
+    /// it from a missing one.  Ids are never reused, so the check holds for
+    /// operations on `id` that follow it.
+    pub fn check_owner(&self, id: SessionId, owner: Option<&str>) -> Result<(), SessionError> {
+        match self.lock().get(&id) {
+            Some(session) if session.owner.as_deref() == owner => Ok(()),
+            _ => Err(SessionError::NotFound(id)),
+        }
+    }
+
+    /// End session `id`, returning whether it existed.
+    pub fn remove(&self, id: SessionId) -> bool {
+        self.lock().remove(&id).is_some()
+    }
+
+    /// Remove every expired session and return their ids.
+    pub fn expire(&self) -> Vec<SessionId> {
+        let mut sessions = self.lock();
+        let mut expired: Vec<SessionId> = sessions
+            .values()
+            .filter(|session| self.is_expired(session))
+            .map(Session::id)
+            .collect();
+        expired.sort_unstable();
+        for id in &expired {
+            sessions.remove(id);
+        }
+        expired
+    }
+
+    /// Number of sessions, including expired ones not yet removed.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn is_expired(&self, session: &Session<K>) -> bool {
+        self.ttl.is_some_and(|ttl| session.idle() >= ttl)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SessionId, Session<K>>> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<K: Clone> SessionStore<K> {
+    /// Copy session `id` into a new session with the same owner, history, KV
+    /// cache handle and token usage, and return the new id.  Later turns of either
+    /// session do not affect the other.
+    pub fn fork(&self, id: SessionId) -> Result<SessionId, SessionError> {
+        let mut child = self.with_session(id, |session| session.clone())?;
+        let now = Instant::now();
+        child.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        child.parent = Some(id);
+        child.created = now;
+        child.last_used = now;
+        let child_id = child.id;
+        self.lock().insert(child_id, child);
+        Ok(child_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcripts_render_the_history() {
+        let mut session: Session = Session::new(1, Some("Be brief.".into()));
+        session.push(Role::User, "Hi");
+        session.push(Role::Assistant, "Hello");
+        session.push(Role::User, "Bye");
+        assert_eq!(
+            session.prompt(),
+            "system: Be brief.\nuser: Hi\nassistant: Hello\nuser: Bye\nassistant: "
+        );
+        assert!(session.prompt().starts_with(&session.transcript()));
+        assert_eq!(
+            session.transcript_prefix(1),
+            "system: Be brief.\nuser: Hi\n"
+        );
+
+        let replaced = session.summarize(2, "They greeted each other.");
+        assert_eq!(replaced.len(), 2);
+        assert_eq!(replaced[1].content, "Hello");
+        assert_eq!(
+            session.transcript(),
+            "system: Be brief.\nsystem: Summary of the earlier conversation: \
+             They greeted each other.\nuser: Bye\n"
+        );
+    }
+
+    #[test]
+    fn forks_are_independent() {
+        let store: SessionStore<Vec<u32>> = SessionStore::new();
+        let id = store.create(None, None, None);
+        store
+            .with_session(id, |session| {
+                session.push(Role::User, "a");
+                session.set_kv(vec![1, 2]);
+                session.charge(2);
+            })
+            .unwrap();
+
+        let fork = store.fork(id).unwrap();
+        assert_ne!(fork, id);
+        store
+            .with_session(fork, |session| {
+                assert_eq!(session.parent(), Some(id));
+                assert_eq!(session.kv(), Some(&vec![1, 2]));
+                assert_eq!(session.tokens_used(), 2);
+                session.push(Role::User, "b");
+            })
+            .unwrap();
+        let messages = store.with_session(id, |s| s.messages().len()).unwrap();
+        assert_eq!(messages, 1);
+        assert_eq!(store.fork(99), Err(SessionError::NotFound(99)));
+    }
+
+    #[test]
+    fn sessions_belong_to_their_key() {
+        let store: SessionStore = SessionStore::new();
+        let id = store.create(Some("ci"), None, None);
+        let open = store.create(None, None, None);
+        assert_eq!(store.check_owner(id, Some("ci")), Ok(()));
+        assert_eq!(
+            store.check_owner(id, Some("batch")),
+            Err(SessionError::NotFound(id))
+        );
+        assert_eq!(store.check_owner(id, None), Err(SessionError::NotFound(id)));
+        assert_eq!(store.check_owner(open, None), Ok(()));
+
+        let fork = store.fork(id).unwrap();
+        let owner = store.with_session(fork, |s| s.owner().map(str::to_owned));
+        assert_eq!(owner, Ok(Some("ci".into())));
+        assert!(store.remove(id));
+        assert_eq!(
+            store.check_owner(id, Some("ci")),
+            Err(SessionError::NotFound(id))
+        );
+    }
+
+    #[test]
+    fn budgets_and_expiry_end_sessions() {
+        let store: SessionStore = SessionStore::new().with_token_limit(4);
+        let id = store.create(None, None, None);
+        let unlimited = store.create(None, None, Some(usize::MAX));
+        store
+            .with_session(id, |session| {
+                assert_eq!(session.check_budget(), Ok(()));
+                session.charge(5);
+                assert_eq!(session.remaining_tokens(), Some(0));
+                assert_eq!(
+                    session.check_budget(),
+                    Err(SessionError::BudgetExhausted { id, limit: 4 })
+                );
+            })
+            .unwrap();
+        let remaining = store.with_session(unlimited, |s| s.remaining_tokens());
+        assert_eq!(remaining, Ok(Some(usize::MAX)));
+
+        let store: SessionStore = SessionStore::new().with_ttl(Duration::ZERO);
+        let first = store.create(None, None, None);
+        let second = store.create(None, None, None);
+        assert_eq!(
+            store.with_session(first, |_| ()),
+            Err(SessionError::Expired(first))
+        );
+        assert_eq!(store.expire(), vec![second]);
+        assert!(store.is_empty());
+        assert!(!store.remove(second));
+    }
+}
@@ -0,0 +1,70 @@
+//! Logging setup for binaries embedding the runtime.
+//!
+//! The runtime, dispatcher and memory manager emit `tracing` spans and
+//! events.  Nothing is printed until a subscriber is installed; binaries call
+//! [`init`] once at startup.  Human readable output is the default, JSON lines
+//! can be requested for production log pipelines.
+
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/// Output format of the installed subscriber.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "pretty" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format '{other}'")),
+        }
+    }
+}
+
+impl LogFormat {
+    /// Read the format from `AUREX_LOG_FORMAT`, defaulting to text.
+    pub fn from_env() -> Self {
+        std::env::var("AUREX_LOG_FORMAT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Install a global subscriber.  The level filter is taken from `AUREX_LOG`
+/// (e.g. `info` or `aurex_runtime=debug`) and defaults to `warn`.  Returns
+/// `false` if a subscriber was already installed.
+pub fn init(format: LogFormat) -> bool {
+    let filter = EnvFilter::try_from_env("AUREX_LOG").unwrap_or_else(|_| EnvFilter::new("warn"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.try_init().is_ok(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init()
+            .is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_log_formats() {
+        assert_eq!("JSON".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!("text".parse::<LogFormat>(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! Thermal throttling detection.
+//!
+//! Under sustained load CPUs and GPUs throttle their clocks once they run
+//! hot, and step latency spikes.  A [`ThermalMonitor`] reads a
+//! [`TemperatureSensor`] before every round of work and backs off before the
+//! hardware does:
+//!
+//! * above `max_celsius` every reading halves the batch size, down to
+//!   `min_batch`;
+//! * above `critical_celsius` every reading also inserts an idle slot of
+//!   `idle_ms`;
+//! * once the temperature has fallen `hysteresis` degrees below
+//!   `max_celsius`, every reading doubles the batch size again.
+//!
+//! The [`Scheduler`](crate::Scheduler) sizes its rounds of micro-batches with
+//! [`ThermalMonitor::batch_size`] and sleeps through idle slots; other
+//! batching callers do the same with [`ThermalMonitor::observe`].  Readings
+//! and events are reported as the `aurex_temperature_celsius` gauge and the
+//! `aurex_thermal_events_total` counter of [`Metrics`].
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use aurex_utils::metrics::Metrics;
+use aurex_utils::thermal::TemperatureSensor;
+
+use crate::config::ThermalConfig;
+
+/// Degrees above `max_celsius` at which idle slots start when the
+/// configuration sets no critical temperature.
+pub const CRITICAL_MARGIN: f64 = 10.0;
+
+/// Degrees below `max_celsius` batches grow again at when the configuration
+/// sets no hysteresis.
+pub const DEFAULT_HYSTERESIS: f64 = 5.0;
+
+/// Idle slot length when the configuration sets none.
+pub const DEFAULT_IDLE: Duration = Duration::from_millis(100);
+
+/// Most times batches are halved.
+pub const MAX_HALVINGS: u32 = 8;
+
+/// Temperature band of the last reading.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThermalState {
+    #[default]
+    Nominal,
+    /// Above `max_celsius`: batches shrink.
+    Hot,
+    /// Above `critical_celsius`: batches shrink and idle slots are inserted.
+    Critical,
+}
+
+/// Result of [`ThermalMonitor::observe`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermalSample {
+    /// Temperature read in degrees Celsius, `None` if the sensor could not
+    /// be read.
+    pub celsius: Option<f64>,
+    pub state: ThermalState,
+    /// Time to stay idle before the next round of work.
+    pub idle: Duration,
+    /// Whether this reading changed the batch size.
+    pub changed: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    celsius: Option<f64>,
+    state: ThermalState,
+    halvings: u32,
+}
+
+/// Temperature limit enforcement, see the [module documentation](self).
+pub struct ThermalMonitor {
+    config: ThermalConfig,
+    sensor: Box<dyn TemperatureSensor>,
+    metrics: Option<Arc<Metrics>>,
+    state: Mutex<State>,
+}
+
+impl ThermalMonitor {
+    /// Enforce the limits of `config` with readings from `sensor`.
+    pub fn new(config: ThermalConfig, sensor: Box<dyn TemperatureSensor>) -> Self {
+        Self {
+            config,
+            sensor,
+            metrics: None,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Monitor for the limits of `config` on the detected hwmon or `sysinfo`
+    /// sensors.  `None` without `max_celsius` or a sensor.
+    pub fn from_config(config: &ThermalConfig) -> Option<Self> {
+        config.max_celsius?;
+        let Some(sensor) = aurex_utils::thermal::detect_sensor() else {
+            tracing::warn!("thermal limit configured but no temperature sensor found");
+            return None;
+        };
+        tracing::info!(sensor = sensor.name(), "thermal monitor started");
+        Some(Self::new(config.clone(), sensor))
+    }
+
+    /// Report readings and events to `metrics` instead of
+    /// [`Metrics::global`].
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn config(&self) -> &ThermalConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> ThermalState {
+        self.lock().state
+    }
+
+    /// Temperature of the last successful reading.
+    pub fn celsius(&self) -> Option<f64> {
+        self.lock().celsius
+    }
+
+    /// Batch size to use instead of `requested`.
+    pub fn batch_size(&self, requested: usize) -> usize {
+        let shrunk = requested >> self.lock().halvings;
+        shrunk
+            .max(self.config.min_batch.unwrap_or(1))
+            .clamp(1, requested.max(1))
+    }
+
+    /// Read the sensor and adjust the batch size.
+    pub fn observe(&self) -> ThermalSample {
+        let mut state = self.lock();
+        let Some(celsius) = self.sensor.temperature() else {
+            return ThermalSample {
+                celsius: None,
+                state: state.state,
+                idle: Duration::ZERO,
+                changed: false,
+            };
+        };
+        let metrics = self.metrics.as_deref().unwrap_or_else(|| Metrics::global());
+        metrics.set_temperature(self.sensor.name(), celsius);
+
+        let max = self.config.max_celsius.unwrap_or(f64::INFINITY);
+        let critical = self
+            .config
+            .critical_celsius
+            .unwrap_or(max + CRITICAL_MARGIN);
+        let cool = max - self.config.hysteresis.unwrap_or(DEFAULT_HYSTERESIS);
+        let previous = state.halvings;
+        state.celsius = Some(celsius);
+        state.state = if celsius >= critical {
+            ThermalState::Critical
+        } else if celsius >= max {
+            ThermalState::Hot
+        } else {
+            ThermalState::Nominal
+        };
+        if state.state > ThermalState::Nominal {
+            state.halvings = (state.halvings + 1).min(MAX_HALVINGS);
+        } else if celsius < cool {
+            state.halvings = state.halvings.saturating_sub(1);
+        }
+
+        let changed = state.halvings != previous;
+        if changed {
+            let event = if state.halvings > previous {
+                "shrink_batch"
+            } else {
+                "grow_batch"
+            };
+            metrics.record_thermal_event(event);
+            tracing::info!(
+                celsius,
+                halvings = state.halvings,
+                event,
+                "thermal throttle changed"
+            );
+        }
+        let idle = if state.state == ThermalState::Critical {
+            metrics.record_thermal_event("idle");
+            self.config
+                .idle_ms
+                .map_or(DEFAULT_IDLE, Duration::from_millis)
+        } else {
+            Duration::ZERO
+        };
+        ThermalSample {
+            celsius: Some(celsius),
+            state: state.state,
+            idle,
+            changed,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sensor replaying a fixed temperature set by the test.
+    #[derive(Clone, Default)]
+    struct Thermometer(Arc<Mutex<Option<f64>>>);
+
+    impl Thermometer {
+        fn set(&self, celsius: f64) {
+            *self.0.lock().unwrap() = Some(celsius);
+        }
+    }
+
+    impl TemperatureSensor for Thermometer {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn temperature(&self) -> Option<f64> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn monitor(thermometer: &Thermometer) -> (ThermalMonitor, Arc<Metrics>) {
+        let metrics = Arc::new(Metrics::new());
+        let config = ThermalConfig {
+            max_celsius: Some(80.0),
+            critical_celsius: Some(90.0),
+            idle_ms: Some(20),
+            min_batch: Some(2),
+            ..ThermalConfig::default()
+        };
+        let monitor = ThermalMonitor::new(config, Box::new(thermometer.clone()))
+            .with_metrics(metrics.clone());
+        (monitor, metrics)
+    }
+
+    #[test]
+    fn hot_readings_shrink_batches_and_critical_ones_idle() {
+        let thermometer = Thermometer::default();
+        let (monitor, metrics) = monitor(&thermometer);
+        let sample = monitor.observe();
+        assert_eq!(sample.celsius, None);
+        assert_eq!(monitor.batch_size(16), 16);
+
+        thermometer.set(70.0);
+        assert!(!monitor.observe().changed);
+        assert_eq!(monitor.state(), ThermalState::Nominal);
+
+        thermometer.set(84.0);
+        let sample = monitor.observe();
+        assert!(sample.changed);
+        assert_eq!(sample.state, ThermalState::Hot);
+        assert_eq!(sample.idle, Duration::ZERO);
+        assert_eq!(monitor.batch_size(16), 8);
+
+        thermometer.set(93.0);
+        let sample = monitor.observe();
+        assert_eq!(sample.state, ThermalState::Critical);
+        assert_eq!(sample.idle, Duration::from_millis(20));
+        assert_eq!(monitor.batch_size(16), 4);
+        monitor.observe();
+        monitor.observe();
+        assert_eq!(monitor.batch_size(16), 2);
+        assert_eq!(monitor.batch_size(1), 1);
+        assert_eq!(metrics.temperature("test"), Some(93.0));
+        assert_eq!(metrics.thermal_events("shrink_batch"), 4);
+        assert_eq!(metrics.thermal_events("idle"), 3);
+    }
+
+    #[test]
+    fn batches_grow_back_below_the_hysteresis() {
+        let thermometer = Thermometer::default();
+        let (monitor, metrics) = monitor(&thermometer);
+        thermometer.set(85.0);
+        monitor.observe();
+        monitor.observe();
+        assert_eq!(monitor.batch_size(16), 4);
+
+        // Within the hysteresis band the batch size holds.
+        thermometer.set(77.0);
+        assert!(!monitor.observe().changed);
+        assert_eq!(monitor.state(), ThermalState::Nominal);
+        assert_eq!(monitor.batch_size(16), 4);
+
+        thermometer.set(60.0);
+        assert!(monitor.observe().changed);
+        assert_eq!(monitor.batch_size(16), 8);
+        monitor.observe();
+        monitor.observe();
+        assert_eq!(monitor.batch_size(16), 16);
+        assert_eq!(metrics.thermal_events("grow_batch"), 2);
+    }
+}
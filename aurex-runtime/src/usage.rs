@@ -0,0 +1,247 @@
+//! Usage accounting and token quotas.
+//!
+//! A [`UsageTracker`] sums the prompt tokens, generated tokens and
+//! GPU-seconds of served requests per API key and per session so that
+//! multi-tenant deployments can bill their tenants or cap them.  The usage
+//! of each key is also exported to the [`Metrics`] registry; requests made
+//! without a key are charged to [`ANONYMOUS`].
+//!
+//! Keys with a `token_quota` in the `[auth]` section are refused with
+//! [`QuotaExceeded`] once their prompt and generated tokens reach it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::AddAssign;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use aurex_utils::metrics::Metrics;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AurexConfig, AuthConfig};
+use crate::SessionId;
+
+/// Key name usage of requests without an API key is charged to.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Resources used by requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub generated_tokens: u64,
+    /// Seconds the model's backend worked on the requests, whether it is
+    /// a GPU or not.
+    pub gpu_seconds: f64,
+}
+
+impl Usage {
+    /// Usage of one request.
+    pub fn request(prompt_tokens: usize, generated_tokens: usize, gpu_seconds: f64) -> Self {
+        Self {
+            requests: 1,
+            prompt_tokens: prompt_tokens as u64,
+            generated_tokens: generated_tokens as u64,
+            gpu_seconds,
+        }
+    }
+
+    /// Prompt and generated tokens, as counted against quotas.
+    pub fn tokens(&self) -> u64 {
+        self.prompt_tokens + self.generated_tokens
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.generated_tokens += other.generated_tokens;
+        self.gpu_seconds += other.gpu_seconds;
+    }
+}
+
+/// Error returned by [`UsageTracker::check_quota`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub key: String,
+    pub used: u64,
+    pub quota: u64,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key {} used {} of its {} token quota",
+            self.key, self.used, self.quota
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Usage per API key and session, see the [module documentation](self).
+#[derive(Default)]
+pub struct UsageTracker {
+    /// Token quotas by key name.
+    quotas: HashMap<String, u64>,
+    keys: Mutex<BTreeMap<String, Usage>>,
+    sessions: Mutex<BTreeMap<SessionId, Usage>>,
+    /// Registry usage is exported to, the global one when unset.
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl UsageTracker {
+    /// Create a tracker enforcing the token quotas of the keys of `config`.
+    pub fn new(config: &AuthConfig) -> Self {
+        let quotas = config
+            .keys
+            .iter()
+            .filter_map(|key| Some((key.name.clone(), key.token_quota?)))
+            .collect();
+        Self {
+            quotas,
+            ..Self::default()
+        }
+    }
+
+    /// Create a tracker for the `[auth]` section of `config`.
+    pub fn from_config(config: &AurexConfig) -> Self {
+        Self::new(&config.auth)
+    }
+
+    /// Export usage to `metrics` instead of [`Metrics::global`].
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Fail if the key named `key` has used up its token quota.
+    pub fn check_quota(&self, key: Option<&str>) -> Result<(), QuotaExceeded> {
+        let key = key.unwrap_or(ANONYMOUS);
+        let Some(&quota) = self.quotas.get(key) else {
+            return Ok(());
+        };
+        let used = self.key(Some(key)).tokens();
+        if used >= quota {
+            return Err(QuotaExceeded {
+                key: key.to_string(),
+                used,
+                quota,
+            });
+        }
+        Ok(())
+    }
+
+    /// Charge `usage` to the key named `key` and to `session`.
+    pub fn record(&self, key: Option<&str>, session: Option<SessionId>, usage: Usage) {
+        let key = key.unwrap_or(ANONYMOUS);
+        *lock(&self.keys).entry(key.to_string()).or_default() += usage;
+        if let Some(session) = session {
+            *lock(&self.sessions).entry(session).or_default() += usage;
+        }
+        self.metrics
+            .as_deref()
+            .unwrap_or_else(|| Metrics::global())
+            .record_usage(
+                key,
+                usage.prompt_tokens,
+                usage.generated_tokens,
+                usage.gpu_seconds,
+            );
+    }
+
+    /// Usage charged to the key named `key`.
+    pub fn key(&self, key: Option<&str>) -> Usage {
+        let key = key.unwrap_or(ANONYMOUS);
+        lock(&self.keys).get(key).copied().unwrap_or_default()
+    }
+
+    /// Usage of every key that made requests.
+    pub fn keys(&self) -> BTreeMap<String, Usage> {
+        lock(&self.keys).clone()
+    }
+
+    /// Usage of every session that made requests and was not forgotten.
+    pub fn sessions(&self) -> BTreeMap<SessionId, Usage> {
+        lock(&self.sessions).clone()
+    }
+
+    pub fn session(&self, id: SessionId) -> Option<Usage> {
+        lock(&self.sessions).get(&id).copied()
+    }
+
+    /// Token quota of the key named `key`.
+    pub fn quota(&self, key: &str) -> Option<u64> {
+        self.quotas.get(key).copied()
+    }
+
+    /// Drop the usage of sessions that ended.
+    pub fn forget_sessions(&self, ids: &[SessionId]) {
+        let mut sessions = lock(&self.sessions);
+        for id in ids {
+            sessions.remove(id);
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKeyConfig;
+
+    #[test]
+    fn usage_is_charged_to_keys_and_sessions() {
+        let metrics = Arc::new(Metrics::new());
+        let tracker = UsageTracker::new(&AuthConfig {
+            keys: vec![ApiKeyConfig {
+                name: "ci".into(),
+                key: "sk-ci".into(),
+                requests_per_second: None,
+                burst: None,
+                token_quota: Some(20),
+            }],
+            ..AuthConfig::default()
+        })
+        .with_metrics(metrics.clone());
+
+        tracker.record(Some("ci"), Some(7), Usage::request(8, 4, 0.5));
+        tracker.record(Some("ci"), None, Usage::request(2, 2, 0.25));
+        tracker.record(None, Some(9), Usage::request(1, 0, 0.0));
+        assert_eq!(
+            tracker.key(Some("ci")),
+            Usage {
+                requests: 2,
+                prompt_tokens: 10,
+                generated_tokens: 6,
+                gpu_seconds: 0.75,
+            }
+        );
+        assert_eq!(tracker.key(None).requests, 1);
+        assert_eq!(tracker.session(7).unwrap().tokens(), 12);
+        assert_eq!(metrics.usage("ci"), (10, 6, 0.75));
+        assert_eq!(metrics.usage(ANONYMOUS), (1, 0, 0.0));
+
+        // 16 of 20 tokens used; the next request may still start.
+        assert_eq!(tracker.check_quota(Some("ci")), Ok(()));
+        tracker.record(Some("ci"), None, Usage::request(3, 1, 0.1));
+        assert_eq!(
+            tracker.check_quota(Some("ci")),
+            Err(QuotaExceeded {
+                key: "ci".into(),
+                used: 20,
+                quota: 20,
+            })
+        );
+        assert_eq!(tracker.check_quota(None), Ok(()));
+
+        tracker.forget_sessions(&[7]);
+        assert_eq!(tracker.sessions().keys().collect::<Vec<_>>(), [&9]);
+    }
+}
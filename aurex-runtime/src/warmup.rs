@@ -0,0 +1,92 @@
+//! Warming up models before they serve traffic.
+//!
+//! The first requests a model serves pay for instantiating backends,
+//! compiling kernels and allocating buffers.  [`Runtime::warmup`] pays these
+//! costs up front: it runs the kernel shapes of a [`Warmup`] model through
+//! its dispatcher, then the model itself on a representative input, and,
+//! when `capture_plan` is set in the scheduler configuration, pins every op
+//! to the backend it was warmed up on so steady-state dispatch stays stable.
+//!
+//! [`Runtime::warmup`]: crate::Runtime::warmup
+
+use std::time::Duration;
+
+use aurex_backend::{Dispatcher, ExecutionPlan, KernelShapes};
+
+/// A model that can be warmed up by [`Runtime::warmup`](crate::Runtime::warmup).
+pub trait Warmup {
+    /// Dispatcher the model runs its ops through.
+    fn dispatcher(&self) -> &Dispatcher;
+
+    /// Shapes of the model's kernels, by default those the dispatcher is
+    /// specialized for.
+    fn kernel_shapes(&self) -> KernelShapes {
+        self.dispatcher().shapes().clone()
+    }
+
+    /// Run the model once on a representative input so buffers and ops
+    /// outside the kernel shapes are warm too.  Does nothing by default.
+    fn run_representative(&self) {}
+}
+
+/// Outcome of [`Runtime::warmup`](crate::Runtime::warmup).
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarmupReport {
+    /// Where each kernel shape ran and how long its runs took.
+    pub plan: ExecutionPlan,
+    /// Whether the plan was captured by the dispatcher.
+    pub captured: bool,
+    /// Duration of the representative run of the model.
+    pub model_time: Duration,
+    /// Duration of the whole warmup.
+    pub elapsed: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use aurex_backend::{Backend, Workload};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Model {
+        dispatcher: Dispatcher,
+        runs: AtomicUsize,
+    }
+
+    impl Warmup for Model {
+        fn dispatcher(&self) -> &Dispatcher {
+            &self.dispatcher
+        }
+
+        fn run_representative(&self) {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn warmup_runs_shapes_and_captures_plans() {
+        let shapes = KernelShapes::new().with_matmul(3, 2).with_norm(2);
+        let model = Model {
+            dispatcher: Dispatcher::new(Some(Backend::Cpu), Workload::Light).with_shapes(shapes),
+            runs: AtomicUsize::new(0),
+        };
+
+        let mut runtime = Runtime::default();
+        let report = runtime.warmup(&model);
+        let ops: Vec<_> = report.plan.ops.iter().map(|p| (p.op, p.backend)).collect();
+        assert_eq!(
+            ops,
+            vec![("matmul", Backend::Cpu), ("layer_norm", Backend::Cpu)]
+        );
+        assert!(!report.captured);
+        assert!(report.elapsed >= report.model_time);
+        assert_eq!(model.runs.load(Ordering::SeqCst), 1);
+        assert_eq!(model.dispatcher.captured_plan(), None);
+
+        runtime.set_plan_capture(true);
+        let report = runtime.warmup(&model);
+        assert!(report.captured);
+        assert_eq!(model.dispatcher.captured_plan(), Some(report.plan));
+    }
+}
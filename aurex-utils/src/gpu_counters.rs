@@ -0,0 +1,394 @@
+//! GPU counter collection for the profiler.
+//!
+//! Each vendor interface is wrapped in a [`GpuCounterSource`] returning a flat
+//! map of counter name to value.  Gauges (utilization, memory, power) are
+//! reported as sampled at the end of an op; keys ending in `_total` are
+//! monotonic counters and are reported as the delta over the op.
+//!
+//! * [`RocmSmiSource`] reads the amdgpu sysfs files also used by `rocm-smi`.
+//! * [`NvmlSource`] queries NVIDIA's management library (feature `nvml`).
+//! * [`VulkanSource`] reports device memory via `VK_EXT_memory_budget` and the
+//!   availability of `VK_KHR_performance_query` counters (feature `vulkan`).
+//!
+//! [`for_backend`] picks a source matching the active backend.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Utilization of the GPU in percent.
+pub const GPU_UTILIZATION: &str = "gpu_utilization_pct";
+/// Device memory in use, in bytes.
+pub const GPU_MEMORY_USED: &str = "gpu_memory_used_bytes";
+/// Instantaneous board power, in milliwatts.
+pub const GPU_POWER: &str = "gpu_power_mw";
+/// Energy consumed since driver load, in millijoules.
+pub const GPU_ENERGY_TOTAL: &str = "gpu_energy_mj_total";
+
+/// A source of GPU counters.
+pub trait GpuCounterSource: Send + Sync {
+    /// Short name of the interface, e.g. `"rocm-smi"`.
+    fn name(&self) -> &'static str;
+    /// Sample the current counter values.  Unavailable counters are omitted.
+    fn sample(&self) -> HashMap<String, u64>;
+}
+
+/// Source used when no GPU interface is available.
+pub struct NoGpu;
+
+impl GpuCounterSource for NoGpu {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn sample(&self) -> HashMap<String, u64> {
+        HashMap::new()
+    }
+}
+
+/// Select a counter source for the named backend (`rocm`, `vulkan`, ...).
+/// Backends without a dedicated interface probe NVML and then rocm-smi.
+/// Falls back to [`NoGpu`] when nothing is available.
+pub fn for_backend(backend: &str) -> Box<dyn GpuCounterSource> {
+    match backend.to_lowercase().as_str() {
+        "cpu" => Box::new(NoGpu),
+        "rocm" => RocmSmiSource::detect()
+            .map(|s| Box::new(s) as Box<dyn GpuCounterSource>)
+            .unwrap_or_else(|| Box::new(NoGpu)),
+        "vulkan" => vulkan_source()
+            .or_else(any_vendor_source)
+            .unwrap_or_else(|| Box::new(NoGpu)),
+        _ => any_vendor_source().unwrap_or_else(|| Box::new(NoGpu)),
+    }
+}
+
+/// Select a counter source for the backend named by `AUREX_BACKEND`.
+pub fn from_env() -> Box<dyn GpuCounterSource> {
+    for_backend(&std::env::var("AUREX_BACKEND").unwrap_or_else(|_| "cpu".into()))
+}
+
+fn any_vendor_source() -> Option<Box<dyn GpuCounterSource>> {
+    nvml_source().or_else(|| {
+        RocmSmiSource::detect().map(|s| Box::new(s) as Box<dyn GpuCounterSource>)
+    })
+}
+
+/// Counters read from the amdgpu sysfs interface
+/// (`/sys/class/drm/cardN/device`).
+pub struct RocmSmiSource {
+    device: PathBuf,
+}
+
+impl RocmSmiSource {
+    /// Use the device directory at `device`, e.g. `/sys/class/drm/card0/device`.
+    pub fn new(device: impl Into<PathBuf>) -> Self {
+        Self {
+            device: device.into(),
+        }
+    }
+
+    /// Find the first amdgpu device exposing `gpu_busy_percent`.
+    pub fn detect() -> Option<Self> {
+        Self::detect_in(Path::new("/sys/class/drm"))
+    }
+
+    fn detect_in(drm: &Path) -> Option<Self> {
+        let mut cards: Vec<PathBuf> = fs::read_dir(drm)
+            .ok()?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.starts_with("card") && !n.contains('-'))
+                    .unwrap_or(false)
+            })
+            .collect();
+        cards.sort();
+        cards
+            .into_iter()
+            .map(|card| card.join("device"))
+            .find(|dev| dev.join("gpu_busy_percent").exists())
+            .map(Self::new)
+    }
+
+    fn read(path: &Path) -> Option<u64> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    fn hwmon_power(&self) -> Option<u64> {
+        let hwmon = fs::read_dir(self.device.join("hwmon")).ok()?;
+        hwmon
+            .flatten()
+            .find_map(|dir| Self::read(&dir.path().join("power1_average")))
+            // sysfs reports microwatts
+            .map(|uw| uw / 1000)
+    }
+}
+
+impl GpuCounterSource for RocmSmiSource {
+    fn name(&self) -> &'static str {
+        "rocm-smi"
+    }
+
+    fn sample(&self) -> HashMap<String, u64> {
+        let mut out = HashMap::new();
+        if let Some(v) = Self::read(&self.device.join("gpu_busy_percent")) {
+            out.insert(GPU_UTILIZATION.to_string(), v);
+        }
+        if let Some(v) = Self::read(&self.device.join("mem_info_vram_used")) {
+            out.insert(GPU_MEMORY_USED.to_string(), v);
+        }
+        if let Some(v) = self.hwmon_power() {
+            out.insert(GPU_POWER.to_string(), v);
+        }
+        out
+    }
+}
+
+#[cfg(feature = "nvml")]
+pub use nvml::NvmlSource;
+
+#[cfg(feature = "nvml")]
+mod nvml {
+    use super::*;
+    use nvml_wrapper::Nvml;
+
+    /// Counters of the first NVIDIA device reported by NVML.
+    pub struct NvmlSource {
+        nvml: Nvml,
+    }
+
+    impl NvmlSource {
+        /// Initialise NVML, returning `None` if the library or a device is
+        /// unavailable.
+        pub fn detect() -> Option<Self> {
+            let nvml = Nvml::init().ok()?;
+            nvml.device_by_index(0).ok()?;
+            Some(Self { nvml })
+        }
+    }
+
+    impl GpuCounterSource for NvmlSource {
+        fn name(&self) -> &'static str {
+            "nvml"
+        }
+
+        fn sample(&self) -> HashMap<String, u64> {
+            let mut out = HashMap::new();
+            let Ok(device) = self.nvml.device_by_index(0) else {
+                return out;
+            };
+            if let Ok(util) = device.utilization_rates() {
+                out.insert(GPU_UTILIZATION.to_string(), util.gpu as u64);
+            }
+            if let Ok(mem) = device.memory_info() {
+                out.insert(GPU_MEMORY_USED.to_string(), mem.used);
+            }
+            if let Ok(power) = device.power_usage() {
+                out.insert(GPU_POWER.to_string(), power as u64);
+            }
+            if let Ok(energy) = device.total_energy_consumption() {
+                out.insert(GPU_ENERGY_TOTAL.to_string(), energy);
+            }
+            out
+        }
+    }
+}
+
+#[cfg(feature = "nvml")]
+fn nvml_source() -> Option<Box<dyn GpuCounterSource>> {
+    NvmlSource::detect().map(|s| Box::new(s) as Box<dyn GpuCounterSource>)
+}
+
+#[cfg(not(feature = "nvml"))]
+fn nvml_source() -> Option<Box<dyn GpuCounterSource>> {
+    None
+}
+
+#[cfg(feature = "vulkan")]
+pub use vulkan::VulkanSource;
+
+#[cfg(feature = "vulkan")]
+mod vulkan {
+    use super::*;
+    use ash::{vk, Entry, Instance};
+    use std::ffi::CStr;
+
+    /// Number of performance counters the device exposes.
+    pub const VK_PERF_COUNTERS: &str = "vk_perf_query_counters";
+
+    /// Device memory usage from `VK_EXT_memory_budget`.  The number of
+    /// `VK_KHR_performance_query` counters available on the first queue
+    /// family is reported so tools can tell whether per-op queries recorded
+    /// by the Vulkan backend will produce data.
+    pub struct VulkanSource {
+        _entry: Entry,
+        instance: Instance,
+        device: vk::PhysicalDevice,
+        has_budget: bool,
+        perf_counters: u64,
+    }
+
+    // SAFETY: the instance handle is only used for read-only physical device
+    // queries, which Vulkan allows from any thread.
+    unsafe impl Send for VulkanSource {}
+    unsafe impl Sync for VulkanSource {}
+
+    impl VulkanSource {
+        /// Create a Vulkan 1.1 instance and pick the first physical device.
+        pub fn detect() -> Option<Self> {
+            unsafe {
+                let entry = Entry::load().ok()?;
+                let app = vk::ApplicationInfo::builder().api_version(vk::API_VERSION_1_1);
+                let info = vk::InstanceCreateInfo::builder().application_info(&app);
+                let instance = entry.create_instance(&info, None).ok()?;
+                let Some(device) = instance
+                    .enumerate_physical_devices()
+                    .ok()
+                    .and_then(|d| d.into_iter().next())
+                else {
+                    instance.destroy_instance(None);
+                    return None;
+                };
+                let extensions = instance
+                    .enumerate_device_extension_properties(device)
+                    .unwrap_or_default();
+                let has = |name: &CStr| {
+                    extensions
+                        .iter()
+                        .any(|e| CStr::from_ptr(e.extension_name.as_ptr()) == name)
+                };
+                let has_budget = has(vk::ExtMemoryBudgetFn::name());
+                let perf_counters = if has(vk::KhrPerformanceQueryFn::name()) {
+                    let perf = vk::KhrPerformanceQueryFn::load(|name| {
+                        std::mem::transmute(
+                            entry.get_instance_proc_addr(instance.handle(), name.as_ptr()),
+                        )
+                    });
+                    let mut count = 0u32;
+                    let result = (perf.enumerate_physical_device_queue_family_performance_query_counters_khr)(
+                        device,
+                        0,
+                        &mut count,
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    );
+                    if result == vk::Result::SUCCESS {
+                        count as u64
+                    } else {
+                        0
+                    }
+                } else {
+                    0
+                };
+                Some(Self {
+                    _entry: entry,
+                    instance,
+                    device,
+                    has_budget,
+                    perf_counters,
+                })
+            }
+        }
+    }
+
+    impl Drop for VulkanSource {
+        fn drop(&mut self) {
+            unsafe { self.instance.destroy_instance(None) };
+        }
+    }
+
+    impl GpuCounterSource for VulkanSource {
+        fn name(&self) -> &'static str {
+            "vulkan"
+        }
+
+        fn sample(&self) -> HashMap<String, u64> {
+            let mut out = HashMap::new();
+            out.insert(VK_PERF_COUNTERS.to_string(), self.perf_counters);
+            if !self.has_budget {
+                return out;
+            }
+            let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+            let mut props = vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget);
+            unsafe {
+                self.instance
+                    .get_physical_device_memory_properties2(self.device, &mut props);
+            }
+            let heaps = props.memory_properties.memory_heaps;
+            let count = props.memory_properties.memory_heap_count as usize;
+            let used: u64 = (0..count)
+                .filter(|&i| heaps[i].flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+                .map(|i| budget.heap_usage[i])
+                .sum();
+            out.insert(GPU_MEMORY_USED.to_string(), used);
+            out
+        }
+    }
+}
+
+#[cfg(feature = "vulkan")]
+fn vulkan_source() -> Option<Box<dyn GpuCounterSource>> {
+    VulkanSource::detect().map(|s| Box::new(s) as Box<dyn GpuCounterSource>)
+}
+
+#[cfg(not(feature = "vulkan"))]
+fn vulkan_source() -> Option<Box<dyn GpuCounterSource>> {
+    None
+}
+
+/// Combine samples taken before and after an op: gauges keep their end
+/// value, `_total` counters are reported as the delta.
+pub fn diff_counters(
+    start: &HashMap<String, u64>,
+    end: &HashMap<String, u64>,
+) -> HashMap<String, u64> {
+    end.iter()
+        .map(|(k, &v_end)| {
+            let v = if k.ends_with("_total") {
+                v_end.saturating_sub(start.get(k).copied().unwrap_or(0))
+            } else {
+                v_end
+            };
+            (k.clone(), v)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rocm_smi_reads_sysfs_layout() {
+        let dir = std::env::temp_dir().join(format!("aurex-drm-{}", std::process::id()));
+        let device = dir.join("card0").join("device");
+        fs::create_dir_all(device.join("hwmon").join("hwmon3")).unwrap();
+        fs::write(device.join("gpu_busy_percent"), "42\n").unwrap();
+        fs::write(device.join("mem_info_vram_used"), "1048576\n").unwrap();
+        fs::write(device.join("hwmon/hwmon3/power1_average"), "35000000\n").unwrap();
+
+        let source = RocmSmiSource::detect_in(&dir).expect("card detected");
+        let sample = source.sample();
+        assert_eq!(sample[GPU_UTILIZATION], 42);
+        assert_eq!(sample[GPU_MEMORY_USED], 1_048_576);
+        assert_eq!(sample[GPU_POWER], 35_000);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gauges_keep_end_value_and_totals_diff() {
+        let start = HashMap::from([
+            (GPU_UTILIZATION.to_string(), 90),
+            (GPU_ENERGY_TOTAL.to_string(), 1_000),
+        ]);
+        let end = HashMap::from([
+            (GPU_UTILIZATION.to_string(), 40),
+            (GPU_ENERGY_TOTAL.to_string(), 1_250),
+        ]);
+        let diff = diff_counters(&start, &end);
+        assert_eq!(diff[GPU_UTILIZATION], 40);
+        assert_eq!(diff[GPU_ENERGY_TOTAL], 250);
+    }
+}
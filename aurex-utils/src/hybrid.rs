@@ -0,0 +1,140 @@
+//! Performance and efficiency cores of hybrid CPUs.
+//!
+//! [`CoreTypes::detect`] tells the big cores of an Arm big.LITTLE or Intel
+//! hybrid processor from the small ones.  Intel parts list their cores in
+//! `/sys/devices/cpu_core/cpus` and `/sys/devices/cpu_atom/cpus`; Arm parts
+//! report a relative `cpu_capacity` per CPU, the largest belonging to the
+//! performance cores.  CPUs of uniform processors are all performance cores.
+
+use std::fs;
+use std::path::Path;
+
+use crate::numa::parse_cpulist;
+
+/// Kind of a core of a hybrid CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreKind {
+    Performance,
+    Efficiency,
+}
+
+/// Performance and efficiency CPUs of the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreTypes {
+    performance: Vec<usize>,
+    efficiency: Vec<usize>,
+}
+
+impl CoreTypes {
+    /// Read the core types of this machine.
+    pub fn detect() -> Self {
+        Self::detect_in(Path::new("/sys/devices")).unwrap_or_else(|| {
+            let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+            Self::uniform(0..cpus)
+        })
+    }
+
+    /// Read the core types below the sysfs `devices` directory `dir`.
+    /// Returns `None` when they are not reported.
+    pub fn detect_in(dir: &Path) -> Option<Self> {
+        let list = |name: &str| {
+            fs::read_to_string(dir.join(name).join("cpus"))
+                .ok()
+                .and_then(|list| parse_cpulist(&list))
+        };
+        if let (Some(performance), Some(efficiency)) = (list("cpu_core"), list("cpu_atom")) {
+            return Some(Self {
+                performance,
+                efficiency,
+            });
+        }
+
+        let mut capacities: Vec<(usize, u64)> = fs::read_dir(dir.join("system/cpu"))
+            .ok()?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let cpu = name.to_str()?.strip_prefix("cpu")?.parse().ok()?;
+                let capacity = fs::read_to_string(entry.path().join("cpu_capacity")).ok()?;
+                Some((cpu, capacity.trim().parse().ok()?))
+            })
+            .collect();
+        capacities.sort_unstable();
+        let max = capacities.iter().map(|&(_, capacity)| capacity).max()?;
+        let (performance, efficiency) = capacities
+            .into_iter()
+            .partition::<Vec<_>, _>(|&(_, capacity)| capacity == max);
+        Some(Self {
+            performance: performance.into_iter().map(|(cpu, _)| cpu).collect(),
+            efficiency: efficiency.into_iter().map(|(cpu, _)| cpu).collect(),
+        })
+    }
+
+    /// `cpus` as the performance cores of a uniform processor.
+    pub fn uniform(cpus: impl IntoIterator<Item = usize>) -> Self {
+        Self {
+            performance: cpus.into_iter().collect(),
+            efficiency: Vec::new(),
+        }
+    }
+
+    /// Whether the processor has both kinds of cores.
+    pub fn is_hybrid(&self) -> bool {
+        !self.performance.is_empty() && !self.efficiency.is_empty()
+    }
+
+    /// Performance CPUs in ascending order.
+    pub fn performance(&self) -> &[usize] {
+        &self.performance
+    }
+
+    /// Efficiency CPUs in ascending order.
+    pub fn efficiency(&self) -> &[usize] {
+        &self.efficiency
+    }
+
+    /// Kind of `cpu`, `None` when it is not listed.
+    pub fn kind(&self, cpu: usize) -> Option<CoreKind> {
+        if self.performance.contains(&cpu) {
+            Some(CoreKind::Performance)
+        } else if self.efficiency.contains(&cpu) {
+            Some(CoreKind::Efficiency)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_intel_and_arm_core_types() {
+        let dir = std::env::temp_dir().join(format!("aurex-hybrid-{}", std::process::id()));
+        let intel = dir.join("intel");
+        for (pmu, cpus) in [("cpu_core", "0-7\n"), ("cpu_atom", "8-15\n")] {
+            fs::create_dir_all(intel.join(pmu)).unwrap();
+            fs::write(intel.join(pmu).join("cpus"), cpus).unwrap();
+        }
+        let types = CoreTypes::detect_in(&intel).expect("core types detected");
+        assert!(types.is_hybrid());
+        assert_eq!(types.performance(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(types.kind(12), Some(CoreKind::Efficiency));
+        assert_eq!(types.kind(16), None);
+
+        let arm = dir.join("arm");
+        for (cpu, capacity) in [(0, 446), (1, 446), (2, 1024), (3, 1024)] {
+            let cpu_dir = arm.join(format!("system/cpu/cpu{cpu}"));
+            fs::create_dir_all(&cpu_dir).unwrap();
+            fs::write(cpu_dir.join("cpu_capacity"), format!("{capacity}\n")).unwrap();
+        }
+        let types = CoreTypes::detect_in(&arm).expect("capacities detected");
+        assert_eq!(types.performance(), &[2, 3]);
+        assert_eq!(types.efficiency(), &[0, 1]);
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(!CoreTypes::uniform(0..4).is_hybrid());
+        assert_eq!(CoreTypes::detect_in(Path::new("/nonexistent")), None);
+    }
+}
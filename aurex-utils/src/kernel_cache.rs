@@ -0,0 +1,277 @@
+//! Persistent on-disk cache of compiled kernels.
+//!
+//! Compiled LLVM objects and SPIR-V binaries are stored in one file per
+//! [`KernelKey`] so repeated runs skip compilation.  The cache lives in
+//! `AUREX_KERNEL_CACHE` when set (`off` disables it), else in
+//! `$XDG_CACHE_HOME/aurex/kernels` or `~/.cache/aurex/kernels`.  Each file
+//! starts with the full key, so a hash collision reads as a miss rather than
+//! loading the wrong kernel.  Cache errors are never fatal: a kernel that
+//! cannot be read or stored is simply compiled again.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+const EXTENSION: &str = "kernel";
+
+/// Everything a compiled kernel depends on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KernelKey {
+    pub op: String,
+    /// Static shapes baked into the kernel, empty for shape-generic kernels.
+    pub shapes: Vec<usize>,
+    pub precision: String,
+    pub backend: String,
+    /// Version of the compiler or driver the kernel was built for.
+    pub driver: String,
+}
+
+impl KernelKey {
+    /// Key of a shape-generic `f32` kernel.
+    pub fn new(op: &str, backend: &str, driver: &str) -> Self {
+        Self {
+            op: op.to_string(),
+            shapes: Vec::new(),
+            precision: "f32".to_string(),
+            backend: backend.to_string(),
+            driver: driver.to_string(),
+        }
+    }
+
+    pub fn with_shapes(mut self, shapes: &[usize]) -> Self {
+        self.shapes = shapes.to_vec();
+        self
+    }
+
+    pub fn with_precision(mut self, precision: &str) -> Self {
+        self.precision = precision.to_string();
+        self
+    }
+
+    /// File name of the entry: the op for readability plus a hash of the key.
+    fn file_name(&self) -> String {
+        let op: String = self
+            .op
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!(
+            "{op}-{:016x}.{EXTENSION}",
+            fnv1a(self.to_string().as_bytes())
+        )
+    }
+}
+
+impl fmt::Display for KernelKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?} {} {} {}",
+            self.op, self.shapes, self.precision, self.backend, self.driver
+        )
+    }
+}
+
+/// FNV-1a, stable across Rust releases unlike the std hashers.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Lookups of a [`KernelCache`] in this process and the size of its directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KernelCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub stores: u64,
+    /// Entries currently in the cache directory.
+    pub entries: usize,
+    /// Bytes of those entries.
+    pub bytes: u64,
+}
+
+/// Directory of compiled kernels.
+#[derive(Debug, Default)]
+pub struct KernelCache {
+    dir: Option<PathBuf>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stores: AtomicU64,
+}
+
+impl KernelCache {
+    /// Cache storing kernels in `dir`, created on the first store.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Cache that never stores anything.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Cache in the directory selected by the environment.
+    pub fn from_env() -> Self {
+        match std::env::var_os("AUREX_KERNEL_CACHE") {
+            Some(dir) if dir == "off" => Self::disabled(),
+            Some(dir) if !dir.is_empty() => Self::new(dir),
+            _ => default_dir().map_or_else(Self::disabled, Self::new),
+        }
+    }
+
+    /// Process wide cache, configured from the environment on first use.
+    pub fn global() -> &'static KernelCache {
+        static GLOBAL: OnceLock<KernelCache> = OnceLock::new();
+        GLOBAL.get_or_init(Self::from_env)
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Compiled kernel stored for `key`.
+    pub fn get(&self, key: &KernelKey) -> Option<Vec<u8>> {
+        let data = self.dir.as_ref().and_then(|dir| {
+            let bytes = fs::read(dir.join(key.file_name())).ok()?;
+            let header = format!("{key}\n");
+            bytes.strip_prefix(header.as_bytes()).map(<[u8]>::to_vec)
+        });
+        let counter = if data.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        data
+    }
+
+    /// Store the compiled kernel `data` for `key`.  The entry is written to a
+    /// temporary file first, so concurrent runs never read a partial kernel.
+    pub fn put(&self, key: &KernelKey, data: &[u8]) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)?;
+        let path = dir.join(key.file_name());
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        let mut bytes = format!("{key}\n").into_bytes();
+        bytes.extend_from_slice(data);
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, &path)?;
+        self.stores.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Return the kernel cached for `key` or compile and store it.  Failing
+    /// to store is logged and otherwise ignored.
+    pub fn get_or_compile<E>(
+        &self,
+        key: &KernelKey,
+        compile: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<u8>, E> {
+        if let Some(data) = self.get(key) {
+            return Ok(data);
+        }
+        let data = compile()?;
+        if let Err(err) = self.put(key, &data) {
+            tracing::warn!(%key, %err, "cannot store compiled kernel");
+        }
+        Ok(data)
+    }
+
+    /// Remove every cached kernel and return how many there were.
+    pub fn clear(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for path in self.entries()? {
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    pub fn stats(&self) -> KernelCacheStats {
+        let entries = self.entries().unwrap_or_default();
+        KernelCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            stores: self.stores.load(Ordering::Relaxed),
+            bytes: entries
+                .iter()
+                .filter_map(|path| fs::metadata(path).ok())
+                .map(|meta| meta.len())
+                .sum(),
+            entries: entries.len(),
+        }
+    }
+
+    fn entries(&self) -> io::Result<Vec<PathBuf>> {
+        let Some(dir) = &self.dir else {
+            return Ok(Vec::new());
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        Ok(entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+            .collect())
+    }
+}
+
+fn default_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("aurex").join("kernels"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_and_clears_kernels() {
+        let dir = std::env::temp_dir().join(format!("aurex-kernels-{}", std::process::id()));
+        let cache = KernelCache::new(&dir);
+        let key = KernelKey::new("matmul", "cpu", "llvm-15").with_shapes(&[4, 4]);
+        assert_eq!(cache.get(&key), None);
+
+        let compiled = cache.get_or_compile(&key, || Ok::<_, String>(vec![1, 2, 3]));
+        assert_eq!(compiled.unwrap(), vec![1, 2, 3]);
+        let cached = cache.get_or_compile(&key, || Err("compiled twice".to_string()));
+        assert_eq!(cached.unwrap(), vec![1, 2, 3]);
+
+        // Any part of the key selects a different kernel.
+        assert_eq!(cache.get(&key.clone().with_precision("bf16")), None);
+        let other_driver = KernelKey::new("matmul", "cpu", "llvm-16").with_shapes(&[4, 4]);
+        assert_eq!(cache.get(&other_driver), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.stores), (1, 4, 1));
+        assert_eq!(
+            (stats.entries, stats.bytes),
+            (1, key.to_string().len() as u64 + 4)
+        );
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.get(&key), None);
+        fs::remove_dir_all(dir).unwrap();
+
+        let disabled = KernelCache::disabled();
+        disabled.put(&key, &[1]).unwrap();
+        assert_eq!(disabled.get(&key), None);
+        assert_eq!(disabled.clear().unwrap(), 0);
+    }
+}
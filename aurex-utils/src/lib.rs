@@ -1,3 +1,11 @@
-//! Utility functions and profiler stubs.
+//! Utility functions, metrics and profiler stubs.
 
+pub mod gpu_counters;
+pub mod hybrid;
+pub mod kernel_cache;
+pub mod metrics;
+pub mod numa;
+pub mod power;
 pub mod profiler;
+pub mod roofline;
+pub mod thermal;
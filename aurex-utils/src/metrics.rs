@@ -0,0 +1,625 @@
+//! Runtime metrics in Prometheus text format.
+//!
+//! A [`Metrics`] registry collects token throughput, step latency, memory tier
+//! usage and events, KV cache hit rate, backend errors, device temperatures,
+//! thermal throttling events, the probes and decisions of batch size
+//! tuning and the usage of each API key.  Components record into the
+//! process wide [`Metrics::global`] instance; [`Metrics::render`] produces the
+//! exposition format and [`serve`] exposes it on `/metrics` for Prometheus.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Number of latency samples kept for percentile estimation.
+pub const LATENCY_WINDOW: usize = 1024;
+
+/// Quantiles reported for the latency summary.
+pub const LATENCY_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Registry of runtime metrics.  All methods take `&self` so the registry can
+/// be shared freely between threads.
+pub struct Metrics {
+    started: Instant,
+    tokens: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    latency: Mutex<LatencyWindow>,
+    tier_usage: Mutex<BTreeMap<String, u64>>,
+    /// Count and bytes of memory events by kind and tier.
+    memory_events: Mutex<BTreeMap<(String, String), (u64, u64)>>,
+    backend_errors: Mutex<BTreeMap<String, u64>>,
+    temperatures: Mutex<BTreeMap<String, f64>>,
+    thermal_events: Mutex<BTreeMap<String, u64>>,
+    /// Slowest step latency in seconds and throughput of the last probe of
+    /// each batch size.
+    batch_probes: Mutex<BTreeMap<usize, (f64, f64)>>,
+    batch_size: AtomicU64,
+    batch_decisions: Mutex<BTreeMap<String, u64>>,
+    /// Prompt tokens, generated tokens and GPU-seconds by API key.
+    usage: Mutex<BTreeMap<String, (u64, u64, f64)>>,
+}
+
+#[derive(Default)]
+struct LatencyWindow {
+    samples: VecDeque<f64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            tokens: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            latency: Mutex::new(LatencyWindow::default()),
+            tier_usage: Mutex::new(BTreeMap::new()),
+            memory_events: Mutex::new(BTreeMap::new()),
+            backend_errors: Mutex::new(BTreeMap::new()),
+            temperatures: Mutex::new(BTreeMap::new()),
+            thermal_events: Mutex::new(BTreeMap::new()),
+            batch_probes: Mutex::new(BTreeMap::new()),
+            batch_size: AtomicU64::new(0),
+            batch_decisions: Mutex::new(BTreeMap::new()),
+            usage: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Process wide registry used by the runtime and backends.
+    pub fn global() -> &'static Metrics {
+        static GLOBAL: OnceLock<Metrics> = OnceLock::new();
+        GLOBAL.get_or_init(Metrics::new)
+    }
+
+    /// Count generated tokens.
+    pub fn record_tokens(&self, count: u64) {
+        self.tokens.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record the latency of one runtime step.
+    pub fn observe_latency(&self, latency: Duration) {
+        let secs = latency.as_secs_f64();
+        let mut window = self.latency.lock().unwrap();
+        if window.samples.len() == LATENCY_WINDOW {
+            window.samples.pop_front();
+        }
+        window.samples.push_back(secs);
+        window.count += 1;
+        window.sum += secs;
+    }
+
+    /// Record a KV cache lookup.
+    pub fn record_cache_lookup(&self, hit: bool) {
+        if hit {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Set the number of bytes currently resident in a memory tier.
+    pub fn set_tier_usage(&self, tier: &str, bytes: u64) {
+        self.tier_usage
+            .lock()
+            .unwrap()
+            .insert(tier.to_string(), bytes);
+    }
+
+    /// Bytes last reported resident in a memory tier.
+    pub fn tier_usage(&self, tier: &str) -> Option<u64> {
+        self.tier_usage.lock().unwrap().get(tier).copied()
+    }
+
+    /// Count a memory manager event of `kind`, e.g. `"evict"`, moving
+    /// `bytes` into or out of `tier`.
+    pub fn record_memory_event(&self, kind: &str, tier: &str, bytes: u64) {
+        let mut events = self.memory_events.lock().unwrap();
+        let entry = events
+            .entry((kind.to_string(), tier.to_string()))
+            .or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += bytes;
+    }
+
+    /// Number of memory events of `kind` on `tier` recorded so far.
+    pub fn memory_events(&self, kind: &str, tier: &str) -> u64 {
+        self.memory_events
+            .lock()
+            .unwrap()
+            .get(&(kind.to_string(), tier.to_string()))
+            .map_or(0, |&(count, _)| count)
+    }
+
+    /// Count an error reported by a backend.
+    pub fn record_backend_error(&self, backend: &str) {
+        *self
+            .backend_errors
+            .lock()
+            .unwrap()
+            .entry(backend.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// Set the temperature last read from a sensor, in degrees Celsius.
+    pub fn set_temperature(&self, sensor: &str, celsius: f64) {
+        self.temperatures
+            .lock()
+            .unwrap()
+            .insert(sensor.to_string(), celsius);
+    }
+
+    /// Temperature last reported by a sensor.
+    pub fn temperature(&self, sensor: &str) -> Option<f64> {
+        self.temperatures.lock().unwrap().get(sensor).copied()
+    }
+
+    /// Count a thermal throttling event, e.g. `"shrink_batch"` or `"idle"`.
+    pub fn record_thermal_event(&self, event: &str) {
+        *self
+            .thermal_events
+            .lock()
+            .unwrap()
+            .entry(event.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// Number of thermal events of a kind recorded so far.
+    pub fn thermal_events(&self, event: &str) -> u64 {
+        self.thermal_events
+            .lock()
+            .unwrap()
+            .get(event)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Record a probe of `batch` items per step: its slowest step and its
+    /// throughput in items per second.
+    pub fn record_batch_probe(&self, batch: usize, latency: Duration, throughput: f64) {
+        self.batch_probes
+            .lock()
+            .unwrap()
+            .insert(batch, (latency.as_secs_f64(), throughput));
+    }
+
+    /// Slowest step and throughput of the last probe of `batch`.
+    pub fn batch_probe(&self, batch: usize) -> Option<(Duration, f64)> {
+        self.batch_probes
+            .lock()
+            .unwrap()
+            .get(&batch)
+            .map(|&(latency, throughput)| (Duration::from_secs_f64(latency), throughput))
+    }
+
+    /// Record that batch size tuning chose `batch`, e.g. for `"startup"`.
+    pub fn record_batch_decision(&self, batch: usize, reason: &str) {
+        self.batch_size.store(batch as u64, Ordering::Relaxed);
+        *self
+            .batch_decisions
+            .lock()
+            .unwrap()
+            .entry(reason.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// Batch size last chosen by batch size tuning.
+    pub fn batch_size(&self) -> Option<usize> {
+        match self.batch_size.load(Ordering::Relaxed) {
+            0 => None,
+            batch => Some(batch as usize),
+        }
+    }
+
+    /// Number of batch size decisions made for `reason` so far.
+    pub fn batch_decisions(&self, reason: &str) -> u64 {
+        self.batch_decisions
+            .lock()
+            .unwrap()
+            .get(reason)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Charge a request made with the API key `key`: its prompt and
+    /// generated tokens and the seconds the device spent on it.
+    pub fn record_usage(
+        &self,
+        key: &str,
+        prompt_tokens: u64,
+        generated_tokens: u64,
+        gpu_seconds: f64,
+    ) {
+        let mut usage = self.usage.lock().unwrap();
+        let charged = usage.entry(key.to_string()).or_insert((0, 0, 0.0));
+        charged.0 += prompt_tokens;
+        charged.1 += generated_tokens;
+        charged.2 += gpu_seconds;
+    }
+
+    /// Prompt tokens, generated tokens and GPU-seconds charged to `key`.
+    pub fn usage(&self, key: &str) -> (u64, u64, f64) {
+        self.usage
+            .lock()
+            .unwrap()
+            .get(key)
+            .copied()
+            .unwrap_or((0, 0, 0.0))
+    }
+
+    /// Total number of generated tokens.
+    pub fn tokens(&self) -> u64 {
+        self.tokens.load(Ordering::Relaxed)
+    }
+
+    /// Average tokens per second since the registry was created.
+    pub fn throughput(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.tokens() as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Fraction of cache lookups that hit, or `0.0` without lookups.
+    pub fn cache_hit_rate(&self) -> f64 {
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let total = hits + self.cache_misses.load(Ordering::Relaxed);
+        if total == 0 {
+            0.0
+        } else {
+            hits as f64 / total as f64
+        }
+    }
+
+    /// Latency quantile `q` in `[0, 1]` over the recent sample window.
+    pub fn latency_quantile(&self, q: f64) -> Option<Duration> {
+        let window = self.latency.lock().unwrap();
+        if window.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f64> = window.samples.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let idx = ((sorted.len() - 1) as f64 * q.clamp(0.0, 1.0)).round() as usize;
+        Some(Duration::from_secs_f64(sorted[idx]))
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        metric(&mut out, "aurex_tokens_total", "counter", "Tokens generated.");
+        out.push_str(&format!("aurex_tokens_total {}\n", self.tokens()));
+        metric(
+            &mut out,
+            "aurex_tokens_per_second",
+            "gauge",
+            "Average token throughput since start.",
+        );
+        out.push_str(&format!("aurex_tokens_per_second {}\n", self.throughput()));
+
+        metric(
+            &mut out,
+            "aurex_step_latency_seconds",
+            "summary",
+            "Runtime step latency.",
+        );
+        for q in LATENCY_QUANTILES {
+            if let Some(latency) = self.latency_quantile(q) {
+                out.push_str(&format!(
+                    "aurex_step_latency_seconds{{quantile=\"{q}\"}} {}\n",
+                    latency.as_secs_f64()
+                ));
+            }
+        }
+        {
+            let window = self.latency.lock().unwrap();
+            out.push_str(&format!("aurex_step_latency_seconds_sum {}\n", window.sum));
+            out.push_str(&format!("aurex_step_latency_seconds_count {}\n", window.count));
+        }
+
+        metric(
+            &mut out,
+            "aurex_memory_tier_bytes",
+            "gauge",
+            "Bytes resident in each memory tier.",
+        );
+        for (tier, bytes) in self.tier_usage.lock().unwrap().iter() {
+            out.push_str(&format!("aurex_memory_tier_bytes{{tier=\"{tier}\"}} {bytes}\n"));
+        }
+
+        {
+            let events = self.memory_events.lock().unwrap();
+            metric(
+                &mut out,
+                "aurex_memory_events_total",
+                "counter",
+                "Memory manager allocations, migrations and evictions by tier.",
+            );
+            for ((kind, tier), (count, _)) in events.iter() {
+                out.push_str(&format!(
+                    "aurex_memory_events_total{{event=\"{kind}\",tier=\"{tier}\"}} {count}\n"
+                ));
+            }
+            metric(
+                &mut out,
+                "aurex_memory_event_bytes_total",
+                "counter",
+                "Bytes moved by memory manager events by tier.",
+            );
+            for ((kind, tier), (_, bytes)) in events.iter() {
+                out.push_str(&format!(
+                    "aurex_memory_event_bytes_total{{event=\"{kind}\",tier=\"{tier}\"}} {bytes}\n"
+                ));
+            }
+        }
+
+        metric(
+            &mut out,
+            "aurex_cache_lookups_total",
+            "counter",
+            "KV cache lookups by result.",
+        );
+        out.push_str(&format!(
+            "aurex_cache_lookups_total{{result=\"hit\"}} {}\n",
+            self.cache_hits.load(Ordering::Relaxed)
+        ));
+        out.push_str(&format!(
+            "aurex_cache_lookups_total{{result=\"miss\"}} {}\n",
+            self.cache_misses.load(Ordering::Relaxed)
+        ));
+        metric(&mut out, "aurex_cache_hit_rate", "gauge", "KV cache hit rate.");
+        out.push_str(&format!("aurex_cache_hit_rate {}\n", self.cache_hit_rate()));
+
+        metric(
+            &mut out,
+            "aurex_backend_errors_total",
+            "counter",
+            "Errors reported by backends.",
+        );
+        for (backend, count) in self.backend_errors.lock().unwrap().iter() {
+            out.push_str(&format!(
+                "aurex_backend_errors_total{{backend=\"{backend}\"}} {count}\n"
+            ));
+        }
+
+        metric(
+            &mut out,
+            "aurex_temperature_celsius",
+            "gauge",
+            "Hottest temperature read from each sensor.",
+        );
+        for (sensor, celsius) in self.temperatures.lock().unwrap().iter() {
+            out.push_str(&format!(
+                "aurex_temperature_celsius{{sensor=\"{sensor}\"}} {celsius}\n"
+            ));
+        }
+        metric(
+            &mut out,
+            "aurex_thermal_events_total",
+            "counter",
+            "Thermal throttling events by kind.",
+        );
+        for (event, count) in self.thermal_events.lock().unwrap().iter() {
+            out.push_str(&format!(
+                "aurex_thermal_events_total{{event=\"{event}\"}} {count}\n"
+            ));
+        }
+
+        {
+            let probes = self.batch_probes.lock().unwrap();
+            metric(
+                &mut out,
+                "aurex_batch_probe_latency_seconds",
+                "gauge",
+                "Slowest step of the last probe of each batch size.",
+            );
+            for (batch, (latency, _)) in probes.iter() {
+                out.push_str(&format!(
+                    "aurex_batch_probe_latency_seconds{{batch=\"{batch}\"}} {latency}\n"
+                ));
+            }
+            metric(
+                &mut out,
+                "aurex_batch_probe_items_per_second",
+                "gauge",
+                "Throughput of the last probe of each batch size.",
+            );
+            for (batch, (_, throughput)) in probes.iter() {
+                out.push_str(&format!(
+                    "aurex_batch_probe_items_per_second{{batch=\"{batch}\"}} {throughput}\n"
+                ));
+            }
+        }
+        if let Some(batch) = self.batch_size() {
+            metric(
+                &mut out,
+                "aurex_batch_size",
+                "gauge",
+                "Batch size chosen by batch size tuning.",
+            );
+            out.push_str(&format!("aurex_batch_size {batch}\n"));
+        }
+        metric(
+            &mut out,
+            "aurex_batch_decisions_total",
+            "counter",
+            "Batch size tuning decisions by reason.",
+        );
+        for (reason, count) in self.batch_decisions.lock().unwrap().iter() {
+            out.push_str(&format!(
+                "aurex_batch_decisions_total{{reason=\"{reason}\"}} {count}\n"
+            ));
+        }
+        let usage = self.usage.lock().unwrap();
+        metric(
+            &mut out,
+            "aurex_usage_prompt_tokens_total",
+            "counter",
+            "Prompt tokens by API key.",
+        );
+        for (key, (prompt, _, _)) in usage.iter() {
+            out.push_str(&format!(
+                "aurex_usage_prompt_tokens_total{{key=\"{key}\"}} {prompt}\n"
+            ));
+        }
+        metric(
+            &mut out,
+            "aurex_usage_generated_tokens_total",
+            "counter",
+            "Generated tokens by API key.",
+        );
+        for (key, (_, generated, _)) in usage.iter() {
+            out.push_str(&format!(
+                "aurex_usage_generated_tokens_total{{key=\"{key}\"}} {generated}\n"
+            ));
+        }
+        metric(
+            &mut out,
+            "aurex_usage_gpu_seconds_total",
+            "counter",
+            "Device time spent on requests by API key.",
+        );
+        for (key, (_, _, seconds)) in usage.iter() {
+            out.push_str(&format!(
+                "aurex_usage_gpu_seconds_total{{key=\"{key}\"}} {seconds}\n"
+            ));
+        }
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+}
+
+/// Serve `metrics` on `GET /metrics` from a background thread.  Any other
+/// path returns 404.  The listener is bound before returning so bind errors
+/// are reported to the caller.  Accepts either [`Metrics::global`] or an
+/// `Arc<Metrics>`.
+pub fn serve<M, A>(
+    metrics: M,
+    addr: A,
+) -> std::io::Result<(std::net::SocketAddr, JoinHandle<()>)>
+where
+    M: Deref<Target = Metrics> + Send + 'static,
+    A: ToSocketAddrs,
+{
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    let handle = std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut request_line = String::new();
+            let mut reader = BufReader::new(&stream);
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            let path = request_line.split_whitespace().nth(1).unwrap_or("");
+            let (status, body) = if path == "/metrics" {
+                ("200 OK", metrics.render())
+            } else {
+                ("404 Not Found", String::from("not found\n"))
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = (&stream).write_all(response.as_bytes());
+        }
+    });
+    Ok((local, handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+
+    #[test]
+    fn renders_recorded_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_tokens(3);
+        metrics.record_cache_lookup(true);
+        metrics.record_cache_lookup(false);
+        metrics.set_tier_usage("gpu", 512);
+        metrics.record_memory_event("evict", "cpu", 256);
+        metrics.record_memory_event("evict", "cpu", 128);
+        metrics.record_backend_error("rocm");
+        metrics.set_temperature("hwmon", 82.5);
+        metrics.record_thermal_event("idle");
+        metrics.record_batch_probe(8, Duration::from_millis(250), 32.0);
+        metrics.record_batch_decision(4, "startup");
+        metrics.record_usage("ci", 10, 4, 0.5);
+        metrics.record_usage("ci", 2, 1, 0.25);
+        for ms in 1..=100 {
+            metrics.observe_latency(Duration::from_millis(ms));
+        }
+
+        assert_eq!(metrics.cache_hit_rate(), 0.5);
+        assert_eq!(metrics.tier_usage("gpu"), Some(512));
+        assert_eq!(metrics.tier_usage("nvme"), None);
+        assert_eq!(metrics.memory_events("evict", "cpu"), 2);
+        assert_eq!(metrics.memory_events("evict", "gpu"), 0);
+        assert_eq!(metrics.temperature("hwmon"), Some(82.5));
+        assert_eq!(metrics.thermal_events("idle"), 1);
+        assert_eq!(metrics.thermal_events("shrink_batch"), 0);
+        assert_eq!(
+            metrics.batch_probe(8),
+            Some((Duration::from_millis(250), 32.0))
+        );
+        assert_eq!(metrics.batch_size(), Some(4));
+        assert_eq!(metrics.batch_decisions("startup"), 1);
+        assert_eq!(metrics.usage("ci"), (12, 5, 0.75));
+        assert_eq!(metrics.usage("other"), (0, 0, 0.0));
+        let p50 = metrics.latency_quantile(0.5).unwrap();
+        assert!((p50.as_secs_f64() - 0.05).abs() < 0.002);
+
+        let text = metrics.render();
+        assert!(text.contains("aurex_tokens_total 3\n"));
+        assert!(text.contains("aurex_memory_tier_bytes{tier=\"gpu\"} 512\n"));
+        assert!(text.contains("aurex_memory_events_total{event=\"evict\",tier=\"cpu\"} 2\n"));
+        assert!(text.contains("aurex_memory_event_bytes_total{event=\"evict\",tier=\"cpu\"} 384\n"));
+        assert!(text.contains("aurex_backend_errors_total{backend=\"rocm\"} 1\n"));
+        assert!(text.contains("aurex_step_latency_seconds_count 100\n"));
+        assert!(text.contains("aurex_temperature_celsius{sensor=\"hwmon\"} 82.5\n"));
+        assert!(text.contains("aurex_thermal_events_total{event=\"idle\"} 1\n"));
+        assert!(text.contains("aurex_batch_probe_latency_seconds{batch=\"8\"} 0.25\n"));
+        assert!(text.contains("aurex_batch_probe_items_per_second{batch=\"8\"} 32\n"));
+        assert!(text.contains("aurex_batch_size 4\n"));
+        assert!(text.contains("aurex_batch_decisions_total{reason=\"startup\"} 1\n"));
+        assert!(text.contains("aurex_usage_prompt_tokens_total{key=\"ci\"} 12\n"));
+        assert!(text.contains("aurex_usage_generated_tokens_total{key=\"ci\"} 5\n"));
+        assert!(text.contains("aurex_usage_gpu_seconds_total{key=\"ci\"} 0.75\n"));
+    }
+
+    #[test]
+    fn exporter_serves_metrics() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_tokens(7);
+        let (addr, _handle) = serve(metrics, "127.0.0.1:0").unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("aurex_tokens_total 7"));
+    }
+}
@@ -0,0 +1,184 @@
+//! NUMA topology detection and thread pinning.
+//!
+//! [`NumaTopology::detect`] reads the nodes, their CPUs and their memory from
+//! `/sys/devices/system/node`.  Machines without that interface (or with
+//! NUMA disabled) are described as a single node holding every CPU, so callers
+//! never need to special-case them.  [`pin_current_thread`] restricts the
+//! calling thread to a set of CPUs; memory it touches first is then allocated
+//! on their node by the kernel's first-touch policy.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// One NUMA node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    pub id: usize,
+    /// CPUs of the node in ascending order.
+    pub cpus: Vec<usize>,
+    /// Memory attached to the node in bytes, 0 when unknown.
+    pub mem_total: u64,
+}
+
+/// NUMA nodes of the machine, ordered by id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaTopology {
+    nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// Read the topology of this machine.
+    pub fn detect() -> Self {
+        Self::detect_in(Path::new("/sys/devices/system/node")).unwrap_or_else(Self::single)
+    }
+
+    /// Read the `nodeN` directories below `dir`.  Returns `None` when there
+    /// are none or none lists a CPU.
+    pub fn detect_in(dir: &Path) -> Option<Self> {
+        let mut nodes: Vec<NumaNode> = fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let id = name.to_str()?.strip_prefix("node")?.parse().ok()?;
+                let cpus = fs::read_to_string(entry.path().join("cpulist"))
+                    .ok()
+                    .and_then(|list| parse_cpulist(&list))
+                    .unwrap_or_default();
+                let mem_total = fs::read_to_string(entry.path().join("meminfo"))
+                    .ok()
+                    .and_then(|info| parse_mem_total(&info))
+                    .unwrap_or(0);
+                Some(NumaNode {
+                    id,
+                    cpus,
+                    mem_total,
+                })
+            })
+            .collect();
+        // Memory-only nodes (e.g. CXL expanders) cannot run workers.
+        nodes.retain(|node| !node.cpus.is_empty());
+        if nodes.is_empty() {
+            return None;
+        }
+        nodes.sort_by_key(|node| node.id);
+        Some(Self { nodes })
+    }
+
+    /// A single node holding all CPUs of the machine.
+    pub fn single() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self {
+            nodes: vec![NumaNode {
+                id: 0,
+                cpus: (0..cpus).collect(),
+                mem_total: 0,
+            }],
+        }
+    }
+
+    pub fn nodes(&self) -> &[NumaNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, id: usize) -> Option<&NumaNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    /// Whether memory placement matters, i.e. there is more than one node.
+    pub fn is_numa(&self) -> bool {
+        self.nodes.len() > 1
+    }
+
+    /// All CPUs, grouped by node.
+    pub fn cpus(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes.iter().flat_map(|node| node.cpus.iter().copied())
+    }
+
+    /// Node owning `cpu`.
+    pub fn node_of_cpu(&self, cpu: usize) -> Option<&NumaNode> {
+        self.nodes.iter().find(|node| node.cpus.contains(&cpu))
+    }
+}
+
+/// Parse a kernel CPU list such as `0-3,8,10-11`.
+pub fn parse_cpulist(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end): (usize, usize) = (start.parse().ok()?, end.parse().ok()?);
+                cpus.extend(start..=end);
+            }
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// `MemTotal` of a node's `meminfo`, reported there in KiB.
+fn parse_mem_total(info: &str) -> Option<u64> {
+    let line = info.lines().find(|l| l.contains("MemTotal:"))?;
+    let kib: u64 = line.split_whitespace().rev().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Restrict the calling thread to `cpus`.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+    let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+    for &cpu in cpus {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cpu {cpu} exceeds the affinity mask"),
+            ));
+        }
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    if unsafe { libc::sched_setaffinity(0, size, &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thread affinity is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_sysfs_node_layout() {
+        let dir = std::env::temp_dir().join(format!("aurex-numa-{}", std::process::id()));
+        for (node, cpus, kib) in [("node0", "0-3,8-11\n", 1024), ("node1", "4-7\n", 2048)] {
+            fs::create_dir_all(dir.join(node)).unwrap();
+            fs::write(dir.join(node).join("cpulist"), cpus).unwrap();
+            let meminfo = format!("Node 0 MemTotal:       {kib} kB\nNode 0 MemFree: 1 kB\n");
+            fs::write(dir.join(node).join("meminfo"), meminfo).unwrap();
+        }
+        // A memory-only node is ignored.
+        fs::create_dir_all(dir.join("node2")).unwrap();
+        fs::write(dir.join("node2").join("cpulist"), "\n").unwrap();
+
+        let topo = NumaTopology::detect_in(&dir).expect("nodes detected");
+        assert!(topo.is_numa());
+        assert_eq!(topo.nodes().len(), 2);
+        assert_eq!(topo.nodes()[0].cpus, vec![0, 1, 2, 3, 8, 9, 10, 11]);
+        assert_eq!(topo.node(1).unwrap().mem_total, 2048 * 1024);
+        assert_eq!(topo.node_of_cpu(9).unwrap().id, 0);
+        assert_eq!(topo.cpus().count(), 12);
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(parse_cpulist("0-1,x"), None);
+        assert!(!NumaTopology::single().is_numa());
+    }
+}
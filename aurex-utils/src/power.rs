@@ -0,0 +1,311 @@
+//! Energy meters and clock controls for power-aware execution.
+//!
+//! An [`EnergyMeter`] reports the energy consumed since an arbitrary origin
+//! in joules; callers take the difference of two readings.
+//!
+//! * [`RaplMeter`] sums the package domains of the Linux powercap RAPL
+//!   interface (`/sys/class/powercap/intel-rapl:N`), unwrapping counters that
+//!   roll over at `max_energy_range_uj`.
+//! * [`HwmonMeter`] sums the `energyN_input` sensors of `/sys/class/hwmon`,
+//!   as exposed by e.g. the `amd_energy` driver and many ARM boards.
+//!
+//! A [`ClockControl`] lowers device clocks while a power budget is exceeded:
+//! [`AmdGpuClocks`] forces the lowest amdgpu DPM level and [`CpuFreqClocks`]
+//! halves the cpufreq frequency range.  Both write sysfs files and usually
+//! need root.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A source of cumulative energy readings.
+pub trait EnergyMeter: Send + Sync {
+    /// Short name of the interface, e.g. `"rapl"`.
+    fn name(&self) -> &'static str;
+    /// Energy consumed so far in joules, `None` if the counters cannot be
+    /// read.
+    fn energy(&self) -> Option<f64>;
+}
+
+/// Find an energy meter, preferring RAPL over hwmon.
+pub fn detect_meter() -> Option<Box<dyn EnergyMeter>> {
+    RaplMeter::detect()
+        .map(|m| Box::new(m) as Box<dyn EnergyMeter>)
+        .or_else(|| HwmonMeter::detect().map(|m| Box::new(m) as Box<dyn EnergyMeter>))
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn sorted_entries(dir: &Path, keep: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(&keep))
+        .collect();
+    entries.sort();
+    entries
+}
+
+#[derive(Debug)]
+struct RaplDomain {
+    energy: PathBuf,
+    /// Counter value at which the domain wraps to zero, in microjoules.
+    range: u64,
+    /// Last raw reading.
+    last: Option<u64>,
+    /// Microjoules consumed since the first reading.
+    total: u64,
+}
+
+/// Package energy from the powercap RAPL interface.
+#[derive(Debug)]
+pub struct RaplMeter {
+    domains: Mutex<Vec<RaplDomain>>,
+}
+
+impl RaplMeter {
+    /// Find the package domains below `/sys/class/powercap`.
+    pub fn detect() -> Option<Self> {
+        Self::detect_in(Path::new("/sys/class/powercap"))
+    }
+
+    fn detect_in(powercap: &Path) -> Option<Self> {
+        // Packages are `intel-rapl:N`; their subzones `intel-rapl:N:M` are
+        // already included in the package counter.
+        let domains: Vec<RaplDomain> = sorted_entries(powercap, |n| {
+            n.starts_with("intel-rapl:") && n.matches(':').count() == 1
+        })
+        .into_iter()
+        .filter(|zone| zone.join("energy_uj").exists())
+        .map(|zone| RaplDomain {
+            energy: zone.join("energy_uj"),
+            range: read_u64(&zone.join("max_energy_range_uj")).unwrap_or(u64::MAX),
+            last: None,
+            total: 0,
+        })
+        .collect();
+        (!domains.is_empty()).then(|| Self {
+            domains: Mutex::new(domains),
+        })
+    }
+}
+
+impl EnergyMeter for RaplMeter {
+    fn name(&self) -> &'static str {
+        "rapl"
+    }
+
+    fn energy(&self) -> Option<f64> {
+        let mut domains = self.domains.lock().unwrap();
+        let mut total = 0;
+        for domain in domains.iter_mut() {
+            let raw = read_u64(&domain.energy)?;
+            if let Some(last) = domain.last {
+                domain.total += if raw >= last {
+                    raw - last
+                } else {
+                    domain.range.saturating_sub(last) + raw
+                };
+            }
+            domain.last = Some(raw);
+            total += domain.total;
+        }
+        Some(total as f64 * 1e-6)
+    }
+}
+
+/// Energy sensors of the hwmon interface.
+#[derive(Debug)]
+pub struct HwmonMeter {
+    sensors: Vec<PathBuf>,
+}
+
+impl HwmonMeter {
+    /// Find the `energyN_input` sensors below `/sys/class/hwmon`.
+    pub fn detect() -> Option<Self> {
+        Self::detect_in(Path::new("/sys/class/hwmon"))
+    }
+
+    fn detect_in(hwmon: &Path) -> Option<Self> {
+        let sensors: Vec<PathBuf> = sorted_entries(hwmon, |n| n.starts_with("hwmon"))
+            .iter()
+            .flat_map(|dir| {
+                sorted_entries(dir, |n| n.starts_with("energy") && n.ends_with("_input"))
+            })
+            .collect();
+        (!sensors.is_empty()).then_some(Self { sensors })
+    }
+}
+
+impl EnergyMeter for HwmonMeter {
+    fn name(&self) -> &'static str {
+        "hwmon"
+    }
+
+    fn energy(&self) -> Option<f64> {
+        // hwmon reports microjoules.
+        let micro: Option<u64> = self.sensors.iter().map(|s| read_u64(s)).sum();
+        micro.map(|uj| uj as f64 * 1e-6)
+    }
+}
+
+/// A knob lowering device clocks to save power.
+pub trait ClockControl: Send + Sync {
+    /// Short name of the interface, e.g. `"cpufreq"`.
+    fn name(&self) -> &'static str;
+    /// Cap the clocks, or restore them when `capped` is false.
+    fn set_capped(&self, capped: bool) -> io::Result<()>;
+}
+
+/// amdgpu clocks through `power_dpm_force_performance_level`.
+#[derive(Debug)]
+pub struct AmdGpuClocks {
+    device: PathBuf,
+}
+
+impl AmdGpuClocks {
+    /// Control the device at `device`, e.g. `/sys/class/drm/card0/device`.
+    pub fn new(device: impl Into<PathBuf>) -> Self {
+        Self {
+            device: device.into(),
+        }
+    }
+
+    /// Find the first amdgpu device with a DPM performance level.
+    pub fn detect() -> Option<Self> {
+        sorted_entries(Path::new("/sys/class/drm"), |n| {
+            n.starts_with("card") && !n.contains('-')
+        })
+        .into_iter()
+        .map(|card| card.join("device"))
+        .find(|dev| dev.join("power_dpm_force_performance_level").exists())
+        .map(Self::new)
+    }
+}
+
+impl ClockControl for AmdGpuClocks {
+    fn name(&self) -> &'static str {
+        "amdgpu"
+    }
+
+    fn set_capped(&self, capped: bool) -> io::Result<()> {
+        let level = if capped { "low" } else { "auto" };
+        fs::write(self.device.join("power_dpm_force_performance_level"), level)
+    }
+}
+
+/// CPU clocks through the cpufreq `scaling_max_freq` of every policy.
+#[derive(Debug)]
+pub struct CpuFreqClocks {
+    policies: Vec<PathBuf>,
+}
+
+impl CpuFreqClocks {
+    /// Control the policies below `cpufreq`, e.g.
+    /// `/sys/devices/system/cpu/cpufreq`.
+    pub fn new(cpufreq: impl AsRef<Path>) -> Self {
+        Self {
+            policies: sorted_entries(cpufreq.as_ref(), |n| n.starts_with("policy")),
+        }
+    }
+
+    /// Control the system's cpufreq policies, if there are any.
+    pub fn detect() -> Option<Self> {
+        let clocks = Self::new("/sys/devices/system/cpu/cpufreq");
+        (!clocks.policies.is_empty()).then_some(clocks)
+    }
+}
+
+impl ClockControl for CpuFreqClocks {
+    fn name(&self) -> &'static str {
+        "cpufreq"
+    }
+
+    fn set_capped(&self, capped: bool) -> io::Result<()> {
+        for policy in &self.policies {
+            let missing = || io::Error::new(io::ErrorKind::NotFound, "missing cpuinfo frequency");
+            let max = read_u64(&policy.join("cpuinfo_max_freq")).ok_or_else(missing)?;
+            let min = read_u64(&policy.join("cpuinfo_min_freq")).ok_or_else(missing)?;
+            let freq = if capped { min + (max - min) / 2 } else { max };
+            fs::write(policy.join("scaling_max_freq"), freq.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Find a clock control, preferring the GPU over the CPU.
+pub fn detect_clocks() -> Option<Box<dyn ClockControl>> {
+    AmdGpuClocks::detect()
+        .map(|c| Box::new(c) as Box<dyn ClockControl>)
+        .or_else(|| CpuFreqClocks::detect().map(|c| Box::new(c) as Box<dyn ClockControl>))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurex-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn rapl_sums_packages_and_unwraps_counters() {
+        let dir = scratch("rapl");
+        for zone in ["intel-rapl:0", "intel-rapl:1", "intel-rapl:0:0"] {
+            fs::create_dir_all(dir.join(zone)).unwrap();
+            fs::write(dir.join(zone).join("energy_uj"), "900000\n").unwrap();
+            fs::write(dir.join(zone).join("max_energy_range_uj"), "1000000\n").unwrap();
+        }
+        let meter = RaplMeter::detect_in(&dir).expect("rapl detected");
+        assert_eq!(meter.energy(), Some(0.0));
+
+        fs::write(dir.join("intel-rapl:0/energy_uj"), "950000\n").unwrap();
+        // Wrapped: 100000 to the range, then 200000 more.
+        fs::write(dir.join("intel-rapl:1/energy_uj"), "200000\n").unwrap();
+        let joules = meter.energy().unwrap();
+        assert!((joules - 0.35).abs() < 1e-9, "{joules}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hwmon_reads_energy_sensors() {
+        let dir = scratch("hwmon");
+        fs::create_dir_all(dir.join("hwmon0")).unwrap();
+        fs::create_dir_all(dir.join("hwmon1")).unwrap();
+        fs::write(dir.join("hwmon0/energy1_input"), "1500000\n").unwrap();
+        fs::write(dir.join("hwmon1/energy2_input"), "500000\n").unwrap();
+        fs::write(dir.join("hwmon1/power1_input"), "7000000\n").unwrap();
+        let meter = HwmonMeter::detect_in(&dir).expect("sensors detected");
+        assert_eq!(meter.energy(), Some(2.0));
+        assert!(HwmonMeter::detect_in(&dir.join("hwmon0")).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cpufreq_caps_to_the_middle_of_the_range() {
+        let dir = scratch("cpufreq");
+        let policy = dir.join("policy0");
+        fs::create_dir_all(&policy).unwrap();
+        fs::write(policy.join("cpuinfo_max_freq"), "3000000\n").unwrap();
+        fs::write(policy.join("cpuinfo_min_freq"), "1000000\n").unwrap();
+        let clocks = CpuFreqClocks::new(&dir);
+        clocks.set_capped(true).unwrap();
+        assert_eq!(
+            fs::read_to_string(policy.join("scaling_max_freq")).unwrap(),
+            "2000000"
+        );
+        clocks.set_capped(false).unwrap();
+        assert_eq!(
+            fs::read_to_string(policy.join("scaling_max_freq")).unwrap(),
+            "3000000"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
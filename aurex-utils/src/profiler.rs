@@ -5,6 +5,9 @@ use std::time::{Duration, Instant};
 
 use sysinfo::System;
 
+use crate::gpu_counters::{self, GpuCounterSource};
+use crate::roofline::OpCost;
+
 /// Collected metrics for a single operation.
 #[derive(Debug, Clone)]
 pub struct OpRecord {
@@ -12,40 +15,213 @@ pub struct OpRecord {
     pub duration: Duration,
     pub memory_bytes: u64,
     pub gpu_counters: HashMap<String, u64>,
+    /// Backend that executed the op, when recorded by the dispatcher.
+    pub backend: Option<&'static str>,
+    /// Element counts of the op inputs, when known.
+    pub input_sizes: Vec<usize>,
+    /// Estimated work and memory traffic, zero when unknown.
+    pub cost: OpCost,
+}
+
+/// Aggregated timings for one op on one backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpSummary {
+    pub name: &'static str,
+    pub backend: Option<&'static str>,
+    pub calls: usize,
+    pub total: Duration,
+}
+
+/// Utilisation of one stage during a pipeline-parallel run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageRecord {
+    pub stage: usize,
+    /// Backend the stage ran on.
+    pub device: Option<&'static str>,
+    pub micro_batches: usize,
+    /// Time spent computing.
+    pub busy: Duration,
+    /// Wall time of the whole run.
+    pub wall: Duration,
+    /// Largest number of micro-batches waiting in the stage's input queue.
+    pub max_queue_len: usize,
+}
+
+impl StageRecord {
+    /// Time the stage sat idle waiting for input or for the next stage.
+    pub fn bubble(&self) -> Duration {
+        self.wall.saturating_sub(self.busy)
+    }
+
+    /// Idle share of the wall time in `[0, 1]`.
+    pub fn bubble_fraction(&self) -> f64 {
+        if self.wall.is_zero() {
+            return 0.0;
+        }
+        self.bubble().as_secs_f64() / self.wall.as_secs_f64()
+    }
+}
+
+/// Traffic to and from the spill files backing the NVMe memory tier.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SpillStats {
+    pub bytes_written: u64,
+    pub bytes_read: u64,
+    pub write_time: Duration,
+    pub read_time: Duration,
+}
+
+impl SpillStats {
+    /// Average write throughput in bytes per second.
+    pub fn write_throughput(&self) -> f64 {
+        throughput(self.bytes_written, self.write_time)
+    }
+
+    /// Average read throughput in bytes per second.
+    pub fn read_throughput(&self) -> f64 {
+        throughput(self.bytes_read, self.read_time)
+    }
+}
+
+fn throughput(bytes: u64, time: Duration) -> f64 {
+    if time.is_zero() {
+        return 0.0;
+    }
+    bytes as f64 / time.as_secs_f64()
+}
+
+/// Reuse of the scratch buffers pooled for one device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers that had to be freshly allocated.
+    pub allocations: u64,
+    /// Buffers served from the pool.
+    pub reuses: u64,
+    pub bytes_allocated: u64,
+    pub bytes_reused: u64,
+    /// Bytes held by idle buffers waiting to be reused.
+    pub bytes_cached: u64,
+}
+
+impl PoolStats {
+    /// Share of requests served from the pool in `[0, 1]`.
+    pub fn reuse_ratio(&self) -> f64 {
+        let requests = self.allocations + self.reuses;
+        if requests == 0 {
+            return 0.0;
+        }
+        self.reuses as f64 / requests as f64
+    }
+}
+
+/// Allocation, migration, eviction or release performed by a memory
+/// manager.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryRecord {
+    /// Kind of event, e.g. `"allocate"`, `"migrate"` or `"evict"`.
+    pub kind: &'static str,
+    /// Tier the data left, `None` for allocations.
+    pub from: Option<&'static str>,
+    /// Tier the data moved to, `None` when it was released.
+    pub to: Option<&'static str>,
+    pub bytes: u64,
+    /// Why the manager acted, e.g. `"request"` or `"pressure"`.
+    pub reason: &'static str,
+    pub latency: Duration,
+}
+
+/// Confidence of a language model in one generated token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceRecord {
+    /// Index of the token in the generated output.
+    pub position: usize,
+    /// Log-probability of the sampled token.
+    pub logprob: f32,
+    /// Entropy in nats of the distribution the token was sampled from.
+    pub entropy: f32,
+    /// Perplexity over the recent tokens, including this one.
+    pub perplexity: f32,
 }
 
 /// Profiler holding per-operation records.
-#[derive(Default)]
 pub struct Profiler {
     records: Vec<OpRecord>,
+    stages: Vec<StageRecord>,
+    spill: SpillStats,
+    confidence: Vec<ConfidenceRecord>,
+    pools: HashMap<&'static str, PoolStats>,
+    memory: Vec<MemoryRecord>,
+    gpu: Box<dyn GpuCounterSource>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Profiler {
-    /// Create a new empty profiler.
+    /// Create a new empty profiler.  GPU counters are collected from the
+    /// interface matching the backend named by `AUREX_BACKEND`.
     pub fn new() -> Self {
+        Self::with_gpu_source(gpu_counters::from_env())
+    }
+
+    /// Create a profiler collecting GPU counters from `gpu`.
+    pub fn with_gpu_source(gpu: Box<dyn GpuCounterSource>) -> Self {
         Self {
             records: Vec::new(),
+            stages: Vec::new(),
+            spill: SpillStats::default(),
+            confidence: Vec::new(),
+            pools: HashMap::new(),
+            memory: Vec::new(),
+            gpu,
         }
     }
 
+    /// Name of the GPU counter interface in use.
+    pub fn gpu_source(&self) -> &'static str {
+        self.gpu.name()
+    }
+
     /// Profile a named operation by executing `f` and recording metrics.
     pub fn profile<F, R>(&mut self, name: &'static str, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        self.profile_op(name, None, &[], OpCost::default(), f)
+    }
+
+    /// Profile an op, additionally recording the executing backend, the
+    /// sizes of its inputs and its estimated cost for roofline analysis.
+    pub fn profile_op<F, R>(
+        &mut self,
+        name: &'static str,
+        backend: Option<&'static str>,
+        input_sizes: &[usize],
+        cost: OpCost,
+        f: F,
+    ) -> R
     where
         F: FnOnce() -> R,
     {
         let start_time = Instant::now();
         let start_mem = current_mem();
-        let start_gpu = gpu_counters();
+        let start_gpu = self.gpu.sample();
         let result = f();
         let end_time = Instant::now();
         let end_mem = current_mem();
-        let end_gpu = gpu_counters();
+        let end_gpu = self.gpu.sample();
 
         self.records.push(OpRecord {
             name,
             duration: end_time - start_time,
             memory_bytes: end_mem.saturating_sub(start_mem),
-            gpu_counters: diff_counters(&start_gpu, &end_gpu),
+            gpu_counters: gpu_counters::diff_counters(&start_gpu, &end_gpu),
+            backend,
+            input_sizes: input_sizes.to_vec(),
+            cost,
         });
 
         result
@@ -55,6 +231,104 @@ impl Profiler {
     pub fn records(&self) -> &[OpRecord] {
         &self.records
     }
+
+    /// Per-op and per-backend totals, slowest first.
+    pub fn breakdown(&self) -> Vec<OpSummary> {
+        let mut totals: HashMap<(&'static str, Option<&'static str>), OpSummary> = HashMap::new();
+        for record in &self.records {
+            let entry = totals
+                .entry((record.name, record.backend))
+                .or_insert_with(|| OpSummary {
+                    name: record.name,
+                    backend: record.backend,
+                    calls: 0,
+                    total: Duration::ZERO,
+                });
+            entry.calls += 1;
+            entry.total += record.duration;
+        }
+        let mut summary: Vec<OpSummary> = totals.into_values().collect();
+        summary.sort_by(|a, b| b.total.cmp(&a.total).then(a.name.cmp(b.name)));
+        summary
+    }
+
+    /// Record the utilisation of a pipeline stage.
+    pub fn record_stage(&mut self, record: StageRecord) {
+        self.stages.push(record);
+    }
+
+    /// Access collected pipeline stage records.
+    pub fn stage_records(&self) -> &[StageRecord] {
+        &self.stages
+    }
+
+    /// Idle share across all recorded pipeline stages.
+    pub fn pipeline_bubble_fraction(&self) -> f64 {
+        let wall: f64 = self.stages.iter().map(|s| s.wall.as_secs_f64()).sum();
+        if wall == 0.0 {
+            return 0.0;
+        }
+        let bubble: f64 = self.stages.iter().map(|s| s.bubble().as_secs_f64()).sum();
+        bubble / wall
+    }
+
+    /// Record `bytes` written to spill storage in `duration`.
+    pub fn record_spill_write(&mut self, bytes: u64, duration: Duration) {
+        self.spill.bytes_written += bytes;
+        self.spill.write_time += duration;
+    }
+
+    /// Record `bytes` read back from spill storage in `duration`.
+    pub fn record_spill_read(&mut self, bytes: u64, duration: Duration) {
+        self.spill.bytes_read += bytes;
+        self.spill.read_time += duration;
+    }
+
+    /// Spill traffic recorded so far.
+    pub fn spill_stats(&self) -> SpillStats {
+        self.spill
+    }
+
+    /// Record the confidence of a generated token.
+    pub fn record_confidence(&mut self, record: ConfidenceRecord) {
+        self.confidence.push(record);
+    }
+
+    /// Confidence of the generated tokens in the order they were recorded.
+    pub fn confidence_trace(&self) -> &[ConfidenceRecord] {
+        &self.confidence
+    }
+
+    /// Record the latest buffer pool statistics of `device`, replacing
+    /// earlier ones.
+    pub fn record_pool_stats(&mut self, device: &'static str, stats: PoolStats) {
+        self.pools.insert(device, stats);
+    }
+
+    /// Buffer pool statistics per device.
+    pub fn pool_stats(&self) -> &HashMap<&'static str, PoolStats> {
+        &self.pools
+    }
+
+    /// Record an event of a memory manager.
+    pub fn record_memory_event(&mut self, record: MemoryRecord) {
+        self.memory.push(record);
+    }
+
+    /// Memory manager events in the order they were recorded.
+    pub fn memory_events(&self) -> &[MemoryRecord] {
+        &self.memory
+    }
+
+    /// Drop all collected records.
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.stages.clear();
+        self.spill = SpillStats::default();
+        self.confidence.clear();
+        self.pools.clear();
+        self.memory.clear();
+    }
 }
 
 /// Macro to profile an individual `TensorOps` call.
@@ -92,16 +366,3 @@ fn current_mem() -> u64 {
     sys.process(pid).map(|p| p.memory() * 1024).unwrap_or(0)
 }
 
-fn gpu_counters() -> HashMap<String, u64> {
-    // Placeholder for real GPU counter collection.
-    HashMap::new()
-}
-
-fn diff_counters(start: &HashMap<String, u64>, end: &HashMap<String, u64>) -> HashMap<String, u64> {
-    let mut diff = HashMap::new();
-    for (k, v_end) in end {
-        let v_start = start.get(k).copied().unwrap_or(0);
-        diff.insert(k.clone(), v_end.saturating_sub(v_start));
-    }
-    diff
-}
@@ -0,0 +1,293 @@
+//! Roofline analysis of profiled ops.
+//!
+//! Each [`OpRecord`] carries an estimate of the floating point work and memory
+//! traffic of the op.  Combined with the measured duration this yields the
+//! achieved GFLOP/s and GB/s per op and backend.  When the peak compute and
+//! bandwidth of a backend are known, the report also classifies each op as
+//! compute or memory bound and shows how close it gets to the roofline.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::profiler::OpRecord;
+
+/// Work and memory traffic of a single op.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct OpCost {
+    pub flops: u64,
+    pub bytes: u64,
+}
+
+const F32_BYTES: u64 = 4;
+
+impl OpCost {
+    /// `m x k` by `k x n` matrix multiply.
+    pub fn matmul(m: usize, n: usize, k: usize) -> Self {
+        let (m, n, k) = (m as u64, n as u64, k as u64);
+        Self {
+            flops: 2 * m * n * k,
+            bytes: F32_BYTES * (m * k + k * n + m * n),
+        }
+    }
+
+    /// Valid 2D convolution of an `input` image with a `kernel`.
+    pub fn conv2d(input: (usize, usize), kernel: (usize, usize)) -> Self {
+        let out_h = input.0.saturating_sub(kernel.0) + 1;
+        let out_w = input.1.saturating_sub(kernel.1) + 1;
+        let out = (out_h * out_w) as u64;
+        let taps = (kernel.0 * kernel.1) as u64;
+        Self {
+            flops: 2 * out * taps,
+            bytes: F32_BYTES * ((input.0 * input.1) as u64 + taps + out),
+        }
+    }
+
+    /// Attention over query/key vectors of length `qk` and values of length `v`.
+    pub fn attention(qk: usize, v: usize) -> Self {
+        let (qk, v) = (qk as u64, v as u64);
+        Self {
+            flops: 2 * qk + v,
+            bytes: F32_BYTES * (2 * qk + 2 * v),
+        }
+    }
+
+    /// Layer normalisation over `n` elements.
+    pub fn layer_norm(n: usize) -> Self {
+        let n = n as u64;
+        Self {
+            flops: 8 * n,
+            bytes: F32_BYTES * 4 * n,
+        }
+    }
+}
+
+/// Peak capabilities of a backend.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Peak {
+    pub gflops: f64,
+    pub bandwidth_gbs: f64,
+}
+
+impl Peak {
+    /// Arithmetic intensity (FLOP/byte) where the roofline turns from memory
+    /// bound to compute bound.
+    pub fn ridge_point(&self) -> f64 {
+        self.gflops / self.bandwidth_gbs
+    }
+
+    /// Attainable GFLOP/s at arithmetic intensity `intensity`.
+    pub fn attainable(&self, intensity: f64) -> f64 {
+        self.gflops.min(intensity * self.bandwidth_gbs)
+    }
+}
+
+/// Which roof limits an op.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bound {
+    Compute,
+    Memory,
+}
+
+/// Aggregated measurements of one op on one backend.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RooflineEntry {
+    pub op: &'static str,
+    pub backend: Option<&'static str>,
+    pub calls: usize,
+    pub total_seconds: f64,
+    pub flops: u64,
+    pub bytes: u64,
+    /// FLOP per byte moved.
+    pub intensity: f64,
+    pub achieved_gflops: f64,
+    pub achieved_gbs: f64,
+    /// Present when a [`Peak`] is known for the backend.
+    pub bound: Option<Bound>,
+    /// Achieved over attainable GFLOP/s, in `[0, 1]` for well behaved data.
+    pub efficiency: Option<f64>,
+}
+
+/// Roofline report over a set of profiled ops.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RooflineReport {
+    pub entries: Vec<RooflineEntry>,
+}
+
+/// Builds [`RooflineReport`]s, optionally using per-backend peaks.
+#[derive(Debug, Clone, Default)]
+pub struct RooflineAnalyzer {
+    peaks: HashMap<String, Peak>,
+}
+
+impl RooflineAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the peak capabilities of `backend` (e.g. `"rocm"`).
+    pub fn with_peak(mut self, backend: &str, peak: Peak) -> Self {
+        self.peaks.insert(backend.to_string(), peak);
+        self
+    }
+
+    /// Aggregate `records` per op and backend.  Records without a cost
+    /// estimate are skipped.  Entries are ordered by total time, slowest first.
+    pub fn analyze(&self, records: &[OpRecord]) -> RooflineReport {
+        let mut groups: HashMap<(&'static str, Option<&'static str>), (usize, Duration, OpCost)> =
+            HashMap::new();
+        for record in records.iter().filter(|r| r.cost.flops > 0 || r.cost.bytes > 0) {
+            let group = groups
+                .entry((record.name, record.backend))
+                .or_insert((0, Duration::ZERO, OpCost::default()));
+            group.0 += 1;
+            group.1 += record.duration;
+            group.2.flops += record.cost.flops;
+            group.2.bytes += record.cost.bytes;
+        }
+
+        let mut entries: Vec<RooflineEntry> = groups
+            .into_iter()
+            .map(|((op, backend), (calls, total, cost))| {
+                let secs = total.as_secs_f64();
+                let per_sec = |v: u64| if secs > 0.0 { v as f64 / secs / 1e9 } else { 0.0 };
+                let achieved_gflops = per_sec(cost.flops);
+                let achieved_gbs = per_sec(cost.bytes);
+                let intensity = if cost.bytes > 0 {
+                    cost.flops as f64 / cost.bytes as f64
+                } else {
+                    f64::INFINITY
+                };
+                let peak = backend.and_then(|b| self.peaks.get(b));
+                let bound = peak.map(|p| {
+                    if intensity >= p.ridge_point() {
+                        Bound::Compute
+                    } else {
+                        Bound::Memory
+                    }
+                });
+                let efficiency = peak.map(|p| achieved_gflops / p.attainable(intensity));
+                RooflineEntry {
+                    op,
+                    backend,
+                    calls,
+                    total_seconds: secs,
+                    flops: cost.flops,
+                    bytes: cost.bytes,
+                    intensity,
+                    achieved_gflops,
+                    achieved_gbs,
+                    bound,
+                    efficiency,
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.total_seconds
+                .total_cmp(&a.total_seconds)
+                .then(a.op.cmp(b.op))
+        });
+        RooflineReport { entries }
+    }
+}
+
+impl RooflineReport {
+    /// Render the report as a markdown table.
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::from(
+            "| op | backend | calls | time (ms) | GFLOP/s | GB/s | FLOP/B | bound | efficiency |\n\
+             |----|---------|------:|----------:|--------:|-----:|-------:|-------|-----------:|\n",
+        );
+        for e in &self.entries {
+            let bound = match e.bound {
+                Some(Bound::Compute) => "compute",
+                Some(Bound::Memory) => "memory",
+                None => "-",
+            };
+            let efficiency = e
+                .efficiency
+                .map(|v| format!("{:.1}%", v * 100.0))
+                .unwrap_or_else(|| "-".into());
+            out.push_str(&format!(
+                "| {} | {} | {} | {:.3} | {:.2} | {:.2} | {:.2} | {} | {} |\n",
+                e.op,
+                e.backend.unwrap_or("-"),
+                e.calls,
+                e.total_seconds * 1e3,
+                e.achieved_gflops,
+                e.achieved_gbs,
+                e.intensity,
+                bound,
+                efficiency
+            ));
+        }
+        out
+    }
+
+    /// Render the report as pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report is serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &'static str, ms: u64, cost: OpCost) -> OpRecord {
+        OpRecord {
+            name,
+            duration: Duration::from_millis(ms),
+            memory_bytes: 0,
+            gpu_counters: HashMap::new(),
+            backend: Some("cpu"),
+            input_sizes: Vec::new(),
+            cost,
+        }
+    }
+
+    #[test]
+    fn classifies_ops_against_the_roofline() {
+        // 100 GFLOP/s, 10 GB/s: ridge point at 10 FLOP/byte.
+        let analyzer = RooflineAnalyzer::new().with_peak(
+            "cpu",
+            Peak {
+                gflops: 100.0,
+                bandwidth_gbs: 10.0,
+            },
+        );
+        let records = vec![
+            // 2 GFLOP over 20 ms -> 100 GFLOP/s, intensity 20 -> compute bound.
+            record("matmul", 10, OpCost { flops: 1_000_000_000, bytes: 50_000_000 }),
+            record("matmul", 10, OpCost { flops: 1_000_000_000, bytes: 50_000_000 }),
+            // 1 GB over 200 ms -> 5 GB/s, intensity 0.5 -> memory bound at 50%.
+            record("layer_norm", 200, OpCost { flops: 500_000_000, bytes: 1_000_000_000 }),
+            record("unknown", 5, OpCost::default()),
+        ];
+
+        let report = analyzer.analyze(&records);
+        assert_eq!(report.entries.len(), 2);
+        let norm = &report.entries[0];
+        assert_eq!(norm.op, "layer_norm");
+        assert_eq!(norm.bound, Some(Bound::Memory));
+        assert!((norm.achieved_gbs - 5.0).abs() < 1e-9);
+        assert!((norm.efficiency.unwrap() - 0.5).abs() < 1e-9);
+
+        let matmul = &report.entries[1];
+        assert_eq!(matmul.calls, 2);
+        assert_eq!(matmul.bound, Some(Bound::Compute));
+        assert!((matmul.achieved_gflops - 100.0).abs() < 1e-9);
+
+        assert!(report.to_markdown().contains("| matmul | cpu | 2 |"));
+        assert!(report.to_json().contains("\"bound\": \"memory\""));
+    }
+
+    #[test]
+    fn matmul_cost_counts_multiply_adds() {
+        let cost = OpCost::matmul(2, 3, 4);
+        assert_eq!(cost.flops, 48);
+        assert_eq!(cost.bytes, 4 * (8 + 12 + 6));
+    }
+}
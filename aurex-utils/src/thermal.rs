@@ -0,0 +1,135 @@
+//! Temperature sensors for thermal throttling detection.
+//!
+//! A [`TemperatureSensor`] reports the hottest temperature among the sensors
+//! it watches, in degrees Celsius.
+//!
+//! * [`HwmonSensor`] reads the `tempN_input` files of `/sys/class/hwmon`,
+//!   which cover CPU packages (`coretemp`, `k10temp`), GPUs (`amdgpu`) and
+//!   most ARM boards.
+//! * [`ComponentSensor`] reads the components reported by `sysinfo`, for
+//!   platforms without hwmon.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use sysinfo::Components;
+
+/// A source of temperature readings.
+pub trait TemperatureSensor: Send + Sync {
+    /// Short name of the interface, e.g. `"hwmon"`.
+    fn name(&self) -> &'static str;
+    /// Hottest current temperature in degrees Celsius, `None` if no sensor
+    /// can be read.
+    fn temperature(&self) -> Option<f64>;
+}
+
+/// Find a temperature sensor, preferring hwmon over `sysinfo`.
+pub fn detect_sensor() -> Option<Box<dyn TemperatureSensor>> {
+    HwmonSensor::detect()
+        .map(|s| Box::new(s) as Box<dyn TemperatureSensor>)
+        .or_else(|| ComponentSensor::detect().map(|s| Box::new(s) as Box<dyn TemperatureSensor>))
+}
+
+fn sorted_entries(dir: &Path, keep: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(&keep))
+        .collect();
+    entries.sort();
+    entries
+}
+
+/// Temperature sensors of the hwmon interface.
+#[derive(Debug)]
+pub struct HwmonSensor {
+    sensors: Vec<PathBuf>,
+}
+
+impl HwmonSensor {
+    /// Find the `tempN_input` sensors below `/sys/class/hwmon`.
+    pub fn detect() -> Option<Self> {
+        Self::detect_in(Path::new("/sys/class/hwmon"))
+    }
+
+    fn detect_in(hwmon: &Path) -> Option<Self> {
+        let sensors: Vec<PathBuf> = sorted_entries(hwmon, |n| n.starts_with("hwmon"))
+            .iter()
+            .flat_map(|dir| sorted_entries(dir, |n| n.starts_with("temp") && n.ends_with("_input")))
+            .collect();
+        (!sensors.is_empty()).then_some(Self { sensors })
+    }
+}
+
+impl TemperatureSensor for HwmonSensor {
+    fn name(&self) -> &'static str {
+        "hwmon"
+    }
+
+    fn temperature(&self) -> Option<f64> {
+        // hwmon reports millidegrees; unreadable sensors are skipped.
+        self.sensors
+            .iter()
+            .filter_map(|s| fs::read_to_string(s).ok()?.trim().parse::<i64>().ok())
+            .max()
+            .map(|milli| milli as f64 / 1000.0)
+    }
+}
+
+/// Temperatures of the components reported by `sysinfo`.
+pub struct ComponentSensor {
+    components: Mutex<Components>,
+}
+
+impl ComponentSensor {
+    /// Watch the system's components, if there are any.
+    pub fn detect() -> Option<Self> {
+        let components = Components::new_with_refreshed_list();
+        (!components.list().is_empty()).then(|| Self {
+            components: Mutex::new(components),
+        })
+    }
+}
+
+impl TemperatureSensor for ComponentSensor {
+    fn name(&self) -> &'static str {
+        "sysinfo"
+    }
+
+    fn temperature(&self) -> Option<f64> {
+        let mut components = self.components.lock().unwrap();
+        components.refresh();
+        components
+            .list()
+            .iter()
+            .map(|c| c.temperature() as f64)
+            .filter(|t| t.is_finite())
+            .reduce(f64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hwmon_reports_the_hottest_sensor() {
+        let dir = std::env::temp_dir().join(format!("aurex-temp-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("hwmon0")).unwrap();
+        fs::create_dir_all(dir.join("hwmon1")).unwrap();
+        fs::write(dir.join("hwmon0/temp1_input"), "45000\n").unwrap();
+        fs::write(dir.join("hwmon1/temp2_input"), "81500\n").unwrap();
+        fs::write(dir.join("hwmon1/temp2_crit"), "100000\n").unwrap();
+        fs::write(dir.join("hwmon1/energy1_input"), "900000000\n").unwrap();
+        let sensor = HwmonSensor::detect_in(&dir).expect("sensors detected");
+        assert_eq!(sensor.temperature(), Some(81.5));
+
+        fs::remove_file(dir.join("hwmon1/temp2_input")).unwrap();
+        assert_eq!(sensor.temperature(), Some(45.0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 use amduda::amduda_core::tensor_ops::TensorOps;
 use amduda::hal_backends::{self, BackendKind};
-use aurex_runtime::BackendPlugin;
+use aurex_runtime::config::PowerConfig;
+use aurex_runtime::{BackendPlugin, PowerGovernor, TensorOpRequest};
 
 fn main() {
     // Select backend based on environment variable, defaulting to CPU.
@@ -18,8 +19,50 @@ fn main() {
         }
     }
 
+    // Keep within the device's power budget: the governor measures joules
+    // per token through RAPL or hwmon and caps clocks, lowers precision and
+    // shrinks batches while the budget is exceeded.
+    let power = PowerConfig {
+        max_watts: Some(10.0),
+        max_joules_per_token: Some(0.5),
+        ..PowerConfig::default()
+    };
+    match PowerGovernor::from_config(&power) {
+        Some(governor) => {
+            let backend = amduda::hal_backends::cpu_simd::CpuSimdBackend;
+            governor.observe(0);
+            for _ in 0..32 {
+                let batch = governor.batch_size(8);
+                backend.matmul(&vec![1.0; batch * 64], &[1.0; 64 * 64], batch, 64, 64);
+                governor.observe(batch as u64);
+            }
+            println!(
+                "{:?} J/token, throttle {:?}",
+                governor.joules_per_token(),
+                governor.throttle()
+            );
+        }
+        None => println!("No energy meter available, running unthrottled"),
+    }
+
     // Demonstrate usage of the FPGA/NPU plugin in an edge scenario.
     let plugin: Box<dyn BackendPlugin> = unsafe { Box::from_raw(fpga_npu::create_plugin()) };
     plugin.initialize();
     plugin.execute();
+
+    // On Android the NNAPI plugin runs quantized matmuls on the NPU, or on
+    // its software reference delegate elsewhere.
+    let npu: Box<dyn BackendPlugin> = unsafe { Box::from_raw(nnapi_npu::create_plugin()) };
+    npu.initialize();
+    let request = TensorOpRequest::MatMul {
+        a: &[1.0, 2.0],
+        b: &[3.0, 4.0],
+        m: 1,
+        n: 1,
+        k: 2,
+    };
+    match npu.execute_op(request) {
+        Ok(response) => println!("NNAPI plugin result: {:?}", response.output),
+        Err(err) => println!("NNAPI plugin unavailable: {err}"),
+    }
 }
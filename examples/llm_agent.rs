@@ -1,8 +1,13 @@
 //! Minimal example of an LLM-based agent using the AUREX runtime.
 //! This example can be executed with `cargo run --example llm_agent --target=cpu`.
+//!
+//! The agent answers a question by searching a small corpus with the
+//! built-in search tool first, then replying from the retrieved passage.
 
 use async_trait::async_trait;
 use aurex_agent::agent::Agent;
+use aurex_agent::tools::ToolRouter;
+use aurex_agent::web::{CorpusSearch, HttpTool, SearchTool};
 use aurex_runtime::confidence_regulator::ConfidenceRegulator;
 use aurex_runtime::effort_evaluator::EffortEvaluator;
 use aurex_runtime::hypothesis_manager::HypothesisManager;
@@ -93,7 +98,20 @@ impl Agent for LlmAgent {
     }
 
     async fn reason(&self, state: &str) -> String {
-        state.to_string()
+        // Stand-in for generation: look the question up, then answer from
+        // the best passage the search observation holds.
+        match serde_json::from_str::<serde_json::Value>(state) {
+            Ok(observation) if observation["tool"] == "search" => {
+                let top = &observation["result"]["results"][0];
+                format!(
+                    "{} (source: {})",
+                    top["snippet"].as_str().unwrap_or("Nothing found."),
+                    top["url"].as_str().unwrap_or("-")
+                )
+            }
+            _ => serde_json::json!({ "name": "search", "arguments": { "query": state } })
+                .to_string(),
+        }
     }
 
     async fn act(&self, output: &str) {
@@ -101,6 +119,28 @@ impl Agent for LlmAgent {
     }
 }
 
+/// Tools of the agent: search over a few documentation passages and HTTP
+/// access to the Rust documentation.  Swap [`CorpusSearch`] for
+/// `SearxSearch` to search the web.
+fn tools() -> ToolRouter {
+    let corpus = CorpusSearch::new()
+        .with_document(
+            "Paged attention",
+            "amduda/src/aurex_lm/paged_attention.rs",
+            "Paged attention splits the KV cache into fixed-size pages, so sequences \
+             grow without copying and share the pages of a common prefix.",
+        )
+        .with_document(
+            "Memory tiering",
+            "amduda/src/amduda_core/memory_tiering.rs",
+            "Tensors move between GPU memory, CPU memory and disk as the memory \
+             manager evicts the least recently used ones.",
+        );
+    ToolRouter::new()
+        .with_tool(SearchTool::new(corpus))
+        .with_tool(HttpTool::new(["docs.rs"]))
+}
+
 #[tokio::main]
 async fn main() {
     let backend_kind = hal_backends::select_backend();
@@ -142,4 +182,12 @@ async fn main() {
     let runtime = Runtime::default();
     let agent = LlmAgent::new(runtime, backend, model);
     agent.run("Hello world").await;
+
+    match tools()
+        .run(&agent, "How does paged attention store the KV cache?", 4)
+        .await
+    {
+        Ok(answer) => println!("Answer: {answer}"),
+        Err(err) => eprintln!("Agent failed: {err}"),
+    }
 }